@@ -183,12 +183,25 @@ pub enum Response<T> {
 #[serde(tag = "error")]
 #[serde(rename_all = "camelCase")]
 pub enum ManagementApiError {
-    FieldAlreadyExists { field: String, value: String },
-    FieldMissing { field: String },
-    NotFound { item: String },
-    Unsupported { details: String },
+    FieldAlreadyExists {
+        field: String,
+        value: String,
+        #[serde(default)]
+        holder: Option<String>,
+    },
+    FieldMissing {
+        field: String,
+    },
+    NotFound {
+        item: String,
+    },
+    Unsupported {
+        details: String,
+    },
     AssertFailed,
-    Other { details: String },
+    Other {
+        details: String,
+    },
 }
 
 impl Client {
@@ -293,7 +306,18 @@ impl Client {
 impl Display for ManagementApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ManagementApiError::FieldAlreadyExists { field, value } => {
+            ManagementApiError::FieldAlreadyExists {
+                field,
+                value,
+                holder: Some(holder),
+            } => {
+                write!(
+                    f,
+                    "Field {} already exists with value {} on {}.",
+                    field, value, holder
+                )
+            }
+            ManagementApiError::FieldAlreadyExists { field, value, .. } => {
                 write!(f, "Field {} already exists with value {}.", field, value)
             }
             ManagementApiError::FieldMissing { field } => {
@@ -7,6 +7,7 @@
 use super::{AccessToken, ResourceToken, TenantInfo, roles::RolePermissions};
 use crate::{
     Server,
+    config::overrides::TenantProtocol,
     ipc::BroadcastEvent,
     listener::limiter::{ConcurrencyLimiter, LimiterResult},
 };
@@ -16,18 +17,22 @@ use directory::{
     backend::internal::{
         lookup::DirectoryStore,
         manage::{ChangedPrincipals, ManageDirectory},
+        schedule::apply_schedule,
     },
 };
 use std::{
     hash::{DefaultHasher, Hash, Hasher},
     sync::Arc,
 };
-use store::{query::acl::AclQuery, rand};
+use store::{query::acl::AclQuery, rand, write::now};
 use trc::AddContext;
 use types::{acl::Acl, collection::Collection};
-use utils::map::{
-    bitmap::{Bitmap, BitmapItem},
-    vec_map::VecMap,
+use utils::{
+    config::{Rate, utils::ParseValue},
+    map::{
+        bitmap::{Bitmap, BitmapItem},
+        vec_map::VecMap,
+    },
 };
 
 pub enum PrincipalOrId {
@@ -38,11 +43,16 @@ pub enum PrincipalOrId {
 impl Server {
     async fn build_access_token_from_principal(
         &self,
-        principal: Principal,
+        mut principal: Principal,
         revision: u64,
     ) -> trc::Result<AccessToken> {
         let mut role_permissions = RolePermissions::default();
 
+        // Apply scheduled events that are due but were not yet applied
+        let now = now();
+        apply_schedule(&mut principal, now);
+        let schedule_change = principal.schedule().next_change(now);
+
         // Extract data
         let mut object_quota = self.core.jmap.max_objects;
         let mut description = None;
@@ -51,6 +61,10 @@ impl Server {
         let mut locale = None;
         let mut member_of = Vec::new();
         let mut emails = Vec::new();
+        let mut primary_email = None;
+        let mut allowed_protocols = 0;
+        let mut sender_address = None;
+        let mut send_rate = None;
         for data in principal.data {
             match data {
                 PrincipalData::Tenant(v) => tenant_id = Some(v),
@@ -74,6 +88,7 @@ impl Server {
                 }
                 PrincipalData::Description(v) => description = Some(v),
                 PrincipalData::PrimaryEmail(v) => {
+                    primary_email = Some(v.clone());
                     if emails.is_empty() {
                         emails.push(v);
                     } else {
@@ -84,6 +99,13 @@ impl Server {
                     emails.push(v);
                 }
                 PrincipalData::Locale(v) => locale = Some(v),
+                PrincipalData::AllowedProtocol(v) => {
+                    if let Some(protocol) = TenantProtocol::parse(&v) {
+                        allowed_protocols |= protocol.bit();
+                    }
+                }
+                PrincipalData::SenderAddress(v) => sender_address = Some(v),
+                PrincipalData::SendRate(v) => send_rate = Rate::parse_value(&v).ok(),
                 _ => (),
             }
         }
@@ -91,6 +113,7 @@ impl Server {
         // Apply principal permissions
         let mut permissions = role_permissions.finalize();
         let mut tenant = None;
+        let mut rate_limit = None;
 
         // SPDX-SnippetBegin
         // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
@@ -105,22 +128,27 @@ impl Server {
                     // Limit tenant permissions
                     permissions.intersection(&self.get_role_permissions(tenant_id).await?.enabled);
 
-                    // Obtain tenant quota
+                    // Obtain tenant quota and plan limits
+                    let tenant_principal = self
+                        .store()
+                        .query(QueryParams::id(tenant_id).with_return_member_of(false))
+                        .await
+                        .caused_by(trc::location!())?
+                        .ok_or_else(|| {
+                            trc::SecurityEvent::Unauthorized
+                                .into_err()
+                                .details("Tenant not found")
+                                .id(tenant_id)
+                                .caused_by(trc::location!())
+                        })?;
+                    rate_limit = self
+                        .principal_plan(&tenant_principal)
+                        .and_then(|plan| plan.rate_limit.clone());
                     tenant = Some(TenantInfo {
                         id: tenant_id,
                         quota: self
-                            .store()
-                            .query(QueryParams::id(tenant_id).with_return_member_of(false))
-                            .await
-                            .caused_by(trc::location!())?
-                            .ok_or_else(|| {
-                                trc::SecurityEvent::Unauthorized
-                                    .into_err()
-                                    .details("Tenant not found")
-                                    .id(tenant_id)
-                                    .caused_by(trc::location!())
-                            })?
-                            .quota()
+                            .tenant_limits(&tenant_principal)
+                            .quota
                             .unwrap_or_default(),
                     });
                 } else {
@@ -151,6 +179,11 @@ impl Server {
             member_of,
             access_to: VecMap::new(),
             tenant,
+            domains: VecMap::new(),
+            allowed_protocols,
+            rate_limit,
+            sender_address,
+            send_rate,
             name: principal.name,
             description,
             emails,
@@ -171,6 +204,7 @@ impl Server {
                 .map(ConcurrencyLimiter::new),
             obj_size: 0,
             revision,
+            schedule_change,
         };
 
         for grant_account_id in [access_token.primary_id]
@@ -214,6 +248,26 @@ impl Server {
             }
         }
 
+        // Data written to shared accounts counts towards their own domain
+        for account_id in [access_token.primary_id]
+            .into_iter()
+            .chain(access_token.member_of.iter().copied())
+            .chain(access_token.access_to.keys().copied())
+            .collect::<AHashSet<_>>()
+        {
+            let domain_id = if account_id == access_token.primary_id {
+                match primary_email.as_deref() {
+                    Some(email) => self.store().get_domain_id(email).await?,
+                    None => None,
+                }
+            } else {
+                self.account_domain_id(account_id).await?
+            };
+            if let Some(domain_id) = domain_id {
+                access_token.domains.append(account_id, domain_id);
+            }
+        }
+
         Ok(access_token.update_size())
     }
 
@@ -253,6 +307,13 @@ impl Server {
         // Obtain current revision
         let principal_id = principal.id();
 
+        // Rebuild the token once a scheduled disable or enable is due
+        if let Some(token) = self.inner.cache.access_tokens.get(&principal_id)
+            && token.schedule_change.is_some_and(|at| at <= now())
+        {
+            self.inner.cache.access_tokens.remove(&principal_id);
+        }
+
         match self
             .inner
             .cache
@@ -280,6 +341,11 @@ impl Server {
     }
 
     pub async fn invalidate_principal_caches(&self, changed_principals: ChangedPrincipals) {
+        self.inner.data.directory_changes.publish(
+            changed_principals.changes(),
+            self.core.jmap.directory_changes_window,
+        );
+
         let mut nested_principals = Vec::new();
         let mut changed_ids = AHashSet::new();
         let mut changed_names = Vec::new();
@@ -362,7 +428,18 @@ impl Server {
         }
 
         // Invalidate access tokens in cluster
-        if !changed_ids.is_empty() {
+        let threshold = self.core.jmap.directory_invalidation_threshold;
+        if threshold > 0 && changed_ids.len() > threshold {
+            // Bulk changes invalidate the whole tenant rather than listing every principal
+            for id in changed_ids {
+                self.inner.cache.permissions.remove(&id);
+                self.inner.cache.access_tokens.remove(&id);
+            }
+            self.cluster_broadcast(BroadcastEvent::InvalidateTenantAccessTokens(
+                changed_principals.tenant_id(),
+            ))
+            .await;
+        } else if !changed_ids.is_empty() {
             let mut ids = Vec::with_capacity(changed_ids.len());
             for id in changed_ids {
                 self.inner.cache.permissions.remove(&id);
@@ -424,6 +501,10 @@ impl AccessToken {
         self
     }
 
+    pub fn domain_id(&self, account_id: u32) -> Option<u32> {
+        self.domains.get(&account_id).copied()
+    }
+
     pub fn state(&self) -> u32 {
         // Hash state
         let mut s = DefaultHasher::new();
@@ -432,6 +513,38 @@ impl AccessToken {
         s.finish() as u32
     }
 
+    pub fn is_protocol_allowed(&self, protocol: TenantProtocol) -> bool {
+        self.allowed_protocols == 0 || self.allowed_protocols & protocol.bit() != 0
+    }
+
+    /// Fails when the account is restricted to other protocols. Only called
+    /// once the credentials were verified, so that the error does not reveal
+    /// whether a password is valid.
+    pub fn assert_protocol_allowed(&self, protocol: &str) -> trc::Result<()> {
+        if self.allowed_protocols == 0
+            || TenantProtocol::parse(protocol)
+                .is_some_and(|protocol| self.is_protocol_allowed(protocol))
+        {
+            Ok(())
+        } else {
+            Err(trc::AuthEvent::ProtocolNotAllowed
+                .into_err()
+                .details("Protocol not permitted for this account")
+                .account_id(self.primary_id)
+                .ctx(trc::Key::Type, protocol.to_string()))
+        }
+    }
+
+    /// Whether the account may submit messages from the address, which is
+    /// always the case unless it is a send-only credential.
+    pub fn is_sender_allowed(&self, address: &str) -> bool {
+        match &self.sender_address {
+            Some(pattern) if pattern.starts_with('@') => address.ends_with(pattern.as_str()),
+            Some(pattern) => address == pattern,
+            None => true,
+        }
+    }
+
     #[inline(always)]
     pub fn primary_id(&self) -> u32 {
         self.primary_id
@@ -558,6 +671,7 @@ impl AccessToken {
             account_id: self.primary_id,
             quota: self.quota,
             tenant: self.tenant,
+            domain_id: self.domain_id(self.primary_id),
         }
     }
 
@@ -583,9 +697,11 @@ impl AccessToken {
         self.obj_size = (std::mem::size_of::<AccessToken>()
             + (self.member_of.len() * std::mem::size_of::<u32>())
             + (self.access_to.len() * (std::mem::size_of::<u32>() + std::mem::size_of::<u64>()))
+            + (self.domains.len() * std::mem::size_of::<u32>() * 2)
             + self.name.len()
             + self.description.as_ref().map_or(0, |v| v.len())
             + self.locale.as_ref().map_or(0, |v| v.len())
+            + self.sender_address.as_ref().map_or(0, |v| v.len())
             + self.emails.iter().map(|v| v.len()).sum::<usize>()) as u64;
         self
     }
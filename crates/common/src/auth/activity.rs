@@ -0,0 +1,71 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::Server;
+use ahash::AHashMap;
+use directory::{
+    FALLBACK_ADMIN_ID,
+    backend::internal::activity::{ActivityType, PrincipalActivity},
+};
+use parking_lot::Mutex;
+use store::write::now;
+
+/// Last activity written for each principal on this node, so that busy
+/// accounts only update their timestamps once per interval.
+#[derive(Debug, Default)]
+pub struct ActivityTimes {
+    written: Mutex<AHashMap<(u32, ActivityType), u64>>,
+}
+
+impl ActivityTimes {
+    /// Returns whether the activity is due to be written.
+    pub fn update(&self, id: u32, typ: ActivityType, now: u64, interval: u64) -> bool {
+        let mut written = self.written.lock();
+        match written.get(&(id, typ)) {
+            Some(last) if now < last + interval => false,
+            _ => {
+                written.insert((id, typ), now);
+                true
+            }
+        }
+    }
+}
+
+impl Server {
+    pub async fn record_activity(&self, account_id: u32, typ: ActivityType) {
+        let now = now();
+        if account_id != FALLBACK_ADMIN_ID
+            && self.inner.data.activity_times.update(
+                account_id,
+                typ,
+                now,
+                self.core.jmap.activity_interval,
+            )
+            && let Err(err) = self.store().set_last_activity(account_id, typ, now).await
+        {
+            trc::error!(
+                err.account_id(account_id)
+                    .details("Failed to record account activity")
+                    .caused_by(trc::location!())
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn activity_times() {
+        let times = ActivityTimes::default();
+        assert!(times.update(1, ActivityType::Imap, 1000, 60));
+        assert!(!times.update(1, ActivityType::Imap, 1059, 60));
+        assert!(times.update(1, ActivityType::Smtp, 1059, 60));
+        assert!(times.update(2, ActivityType::Imap, 1059, 60));
+        assert!(times.update(1, ActivityType::Imap, 1060, 60));
+    }
+}
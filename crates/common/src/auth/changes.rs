@@ -0,0 +1,256 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::VecDeque, sync::Arc};
+
+use directory::{
+    Type,
+    backend::internal::{
+        PrincipalField,
+        manage::{PrincipalChange, PrincipalChangeAction},
+    },
+};
+use parking_lot::Mutex;
+use store::{rand, write::now};
+use tokio::sync::broadcast;
+
+const BROADCAST_CAPACITY: usize = 1024;
+const INSTANCE_SHIFT: u32 = 40;
+
+/// In-memory log of the most recent directory changes on this node, used to
+/// feed the directory change stream and to let consumers resume after a
+/// disconnect.
+///
+/// The log is scoped to a single node: only changes written through this node
+/// are published and the change log is not shared over the cluster broadcast.
+/// Sequences carry a random instance id in their upper bits, so resuming from
+/// a sequence issued by another node (or before a restart) always results in
+/// a resync request instead of a replay from an unrelated sequence space.
+#[derive(Debug)]
+pub struct DirectoryChanges {
+    inner: Mutex<ChangeLog>,
+    tx: broadcast::Sender<Arc<DirectoryChange>>,
+}
+
+#[derive(Debug)]
+struct ChangeLog {
+    entries: VecDeque<Arc<DirectoryChange>>,
+    // Sequence assigned to the next change
+    next_seq: u64,
+    // Oldest sequence that can still be resumed from
+    first_seq: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryChange {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub action: DirectoryChangeAction,
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub typ: Type,
+    pub tenant_id: Option<u32>,
+    pub fields: Vec<PrincipalField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DirectoryChangeAction {
+    Created,
+    Updated,
+    Deleted,
+}
+
+pub struct DirectoryChangeSubscription {
+    /// Set when the requested sequence is no longer (or not yet) available
+    /// and the consumer has to perform a full resynchronization.
+    pub resync: bool,
+    /// Sequence of the most recent change at subscription time.
+    pub last_seq: u64,
+    pub replay: Vec<Arc<DirectoryChange>>,
+    pub rx: broadcast::Receiver<Arc<DirectoryChange>>,
+}
+
+impl DirectoryChanges {
+    pub fn new() -> Self {
+        Self::with_sequence(Self::instance_sequence(rand::random()))
+    }
+
+    fn instance_sequence(instance_id: u32) -> u64 {
+        // Keep the instance id within 23 bits so sequences stay below 2^63
+        ((instance_id & 0x7f_ffff) as u64) << INSTANCE_SHIFT
+    }
+
+    fn with_sequence(seq: u64) -> Self {
+        Self {
+            inner: Mutex::new(ChangeLog {
+                entries: VecDeque::new(),
+                next_seq: seq + 1,
+                first_seq: seq + 1,
+            }),
+            tx: broadcast::channel(BROADCAST_CAPACITY).0,
+        }
+    }
+
+    pub fn publish(&self, changes: &[PrincipalChange], max_entries: usize) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let timestamp = now();
+        let mut log = self.inner.lock();
+        for change in changes {
+            let change = Arc::new(DirectoryChange {
+                sequence: log.next_seq,
+                timestamp,
+                action: change.action.into(),
+                id: change.id,
+                typ: change.typ,
+                tenant_id: change.tenant_id,
+                fields: change.fields.clone(),
+            });
+            log.next_seq += 1;
+
+            while log.entries.len() >= max_entries.max(1) {
+                if let Some(evicted) = log.entries.pop_front() {
+                    log.first_seq = evicted.sequence + 1;
+                }
+            }
+            log.entries.push_back(change.clone());
+
+            // Sending fails only when there are no subscribers
+            let _ = self.tx.send(change);
+        }
+    }
+
+    /// Subscribes to new changes, replaying those that followed `since`.
+    pub fn subscribe(&self, since: Option<u64>) -> DirectoryChangeSubscription {
+        // Hold the lock so no change is published between replay and subscription
+        let log = self.inner.lock();
+        let last_seq = log.next_seq - 1;
+        let rx = self.tx.subscribe();
+
+        match since {
+            Some(since)
+                if !log.is_local(since) || since < log.first_seq - 1 || since > last_seq =>
+            {
+                DirectoryChangeSubscription {
+                    resync: true,
+                    last_seq,
+                    replay: vec![],
+                    rx,
+                }
+            }
+            Some(since) => DirectoryChangeSubscription {
+                resync: false,
+                last_seq,
+                replay: log
+                    .entries
+                    .iter()
+                    .filter(|change| change.sequence > since)
+                    .cloned()
+                    .collect(),
+                rx,
+            },
+            None => DirectoryChangeSubscription {
+                resync: false,
+                last_seq,
+                replay: vec![],
+                rx,
+            },
+        }
+    }
+}
+
+impl ChangeLog {
+    /// Whether a sequence was issued by this instance of the change log.
+    fn is_local(&self, seq: u64) -> bool {
+        seq >> INSTANCE_SHIFT == self.first_seq >> INSTANCE_SHIFT
+    }
+}
+
+impl Default for DirectoryChanges {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<PrincipalChangeAction> for DirectoryChangeAction {
+    fn from(action: PrincipalChangeAction) -> Self {
+        match action {
+            PrincipalChangeAction::Created => DirectoryChangeAction::Created,
+            PrincipalChangeAction::Updated => DirectoryChangeAction::Updated,
+            PrincipalChangeAction::Deleted => DirectoryChangeAction::Deleted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use directory::{
+        Type,
+        backend::internal::{
+            PrincipalField,
+            manage::{PrincipalChange, PrincipalChangeAction},
+        },
+    };
+
+    use super::DirectoryChanges;
+
+    fn change(id: u32) -> PrincipalChange {
+        PrincipalChange {
+            action: PrincipalChangeAction::Updated,
+            id,
+            typ: Type::Individual,
+            tenant_id: None,
+            fields: vec![PrincipalField::Secrets],
+        }
+    }
+
+    #[test]
+    fn directory_change_window() {
+        let changes = DirectoryChanges::with_sequence(100);
+
+        // Nothing has happened yet
+        let sub = changes.subscribe(Some(100));
+        assert!(!sub.resync);
+        assert!(sub.replay.is_empty());
+        assert_eq!(sub.last_seq, 100);
+        assert!(changes.subscribe(Some(99)).resync);
+        assert!(changes.subscribe(Some(101)).resync);
+
+        // Live changes reach subscribers
+        let mut live = changes.subscribe(None);
+        changes.publish(&(0..5).map(change).collect::<Vec<_>>(), 3);
+        assert_eq!(live.rx.try_recv().unwrap().sequence, 101);
+        assert_eq!(live.rx.try_recv().unwrap().id, 1);
+
+        // Only the last three changes (103..=105) can be replayed
+        let sub = changes.subscribe(Some(103));
+        assert!(!sub.resync);
+        assert_eq!(sub.last_seq, 105);
+        assert_eq!(
+            sub.replay.iter().map(|c| c.sequence).collect::<Vec<_>>(),
+            vec![104, 105]
+        );
+        assert_eq!(changes.subscribe(Some(102)).replay.len(), 3);
+        assert!(changes.subscribe(Some(101)).resync);
+        assert!(changes.subscribe(Some(105)).replay.is_empty());
+        assert!(changes.subscribe(Some(106)).resync);
+
+        // Sequences issued by another node always require a resync
+        let other = DirectoryChanges::with_sequence(DirectoryChanges::instance_sequence(7) + 100);
+        other.publish(&(0..5).map(change).collect::<Vec<_>>(), 3);
+        assert!(!other.subscribe(Some(other.subscribe(None).last_seq)).resync);
+        assert!(
+            changes
+                .subscribe(Some(other.subscribe(None).last_seq))
+                .resync
+        );
+        assert!(other.subscribe(Some(104)).resync);
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::Type;
+use parking_lot::Mutex;
+use store::write::now;
+
+use crate::jobs::{Job, JobRegistry};
+
+/// Directory import jobs started on this node, kept so that their report
+/// can be downloaded.
+pub type ImportJobs = JobRegistry<ImportJob>;
+
+#[derive(Debug)]
+pub struct ImportJob {
+    pub id: u64,
+    pub tenant_id: u32,
+    pub dry_run: bool,
+    state: Mutex<ImportJobState>,
+}
+
+#[derive(Debug)]
+struct ImportJobState {
+    status: ImportJobStatus,
+    started: u64,
+    finished: Option<u64>,
+    total: usize,
+    error: Option<String>,
+    results: Vec<ImportEntryResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEntryResult {
+    pub dn: String,
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub typ: Type,
+    pub result: ImportEntryOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    /// Set for users whose password hash uses an unsupported scheme.
+    pub password_not_imported: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportEntryOutcome {
+    Created,
+    WouldCreate,
+    Exists,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportJobSummary {
+    pub id: String,
+    pub status: ImportJobStatus,
+    pub dry_run: bool,
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub total: usize,
+    pub processed: usize,
+    pub created: usize,
+    pub exists: usize,
+    pub failed: usize,
+    pub passwords_not_imported: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ImportJobs {
+    /// Registers a new job, unless the tenant already has one running.
+    pub fn start(&self, id: u64, tenant_id: u32, dry_run: bool) -> Option<Arc<ImportJob>> {
+        self.register(
+            ImportJob {
+                id,
+                tenant_id,
+                dry_run,
+                state: Mutex::new(ImportJobState {
+                    status: ImportJobStatus::Running,
+                    started: now(),
+                    finished: None,
+                    total: 0,
+                    error: None,
+                    results: Vec::new(),
+                }),
+            },
+            |job| job.tenant_id == tenant_id,
+        )
+    }
+}
+
+impl Job for ImportJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == ImportJobStatus::Running
+    }
+}
+
+impl ImportJob {
+    pub fn set_total(&self, total: usize) {
+        self.state.lock().total = total;
+    }
+
+    pub fn push_result(&self, result: ImportEntryResult) {
+        self.state.lock().results.push(result);
+    }
+
+    pub fn complete(&self) {
+        let mut state = self.state.lock();
+        state.status = ImportJobStatus::Completed;
+        state.finished = Some(now());
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = ImportJobStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn results(&self) -> Vec<ImportEntryResult> {
+        self.state.lock().results.clone()
+    }
+
+    pub fn summary(&self) -> ImportJobSummary {
+        let state = self.state.lock();
+        let count = |outcome: ImportEntryOutcome| {
+            state
+                .results
+                .iter()
+                .filter(|result| result.result == outcome)
+                .count()
+        };
+
+        ImportJobSummary {
+            id: self.id.to_string(),
+            status: state.status,
+            dry_run: self.dry_run,
+            started: state.started,
+            finished: state.finished,
+            total: state.total,
+            processed: state.results.len(),
+            created: count(if self.dry_run {
+                ImportEntryOutcome::WouldCreate
+            } else {
+                ImportEntryOutcome::Created
+            }),
+            exists: count(ImportEntryOutcome::Exists),
+            failed: count(ImportEntryOutcome::Failed),
+            passwords_not_imported: state
+                .results
+                .iter()
+                .filter(|result| result.password_not_imported)
+                .count(),
+            error: state.error.clone(),
+        }
+    }
+}
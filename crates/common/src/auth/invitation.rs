@@ -0,0 +1,253 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::hmac;
+use store::{InMemoryStore, dispatch::lookup::KeyValue, write::now};
+
+use crate::{
+    KV_LOCK_INVITATION, KV_ORGANIZATION_INVITATION, Server, config::groupware::TenantCollections,
+};
+
+/// Invitation to provision an organization within pre-approved limits.
+/// Invitations are single-use and can be revoked, which is enforced when
+/// they are redeemed using the record stored under their ID.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationInvitation {
+    pub id: u64,
+    /// Principal that created the invitation, organizations are provisioned
+    /// with its permissions.
+    pub invited_by: u32,
+    /// Either a domain name, `*.` followed by a parent domain, or `*`.
+    pub domain_pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_users: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collections: Option<TenantCollections>,
+    pub expires: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InvitationStatus {
+    Pending,
+    Redeemed,
+    Revoked,
+}
+
+/// Stored state of an invitation, kept in the data store until the
+/// invitation expires so that redemptions and revocations survive restarts.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationRecord {
+    #[serde(flatten)]
+    pub invitation: OrganizationInvitation,
+    /// Tenant of the principal that created the invitation.
+    #[serde(default)]
+    pub tenant_id: Option<u32>,
+    pub status: InvitationStatus,
+}
+
+impl Server {
+    pub async fn add_invitation(&self, record: &InvitationRecord) -> trc::Result<()> {
+        self.invitation_store()
+            .key_set(
+                KeyValue::with_prefix(
+                    KV_ORGANIZATION_INVITATION,
+                    record.invitation.id.to_be_bytes(),
+                    serde_json::to_vec(record).unwrap_or_default(),
+                )
+                .expires(record.invitation.expires.saturating_sub(now()) + 1),
+            )
+            .await
+    }
+
+    pub async fn get_invitation(&self, id: u64) -> trc::Result<Option<InvitationRecord>> {
+        self.invitation_store()
+            .key_get::<String>(KeyValue::<()>::build_key(
+                KV_ORGANIZATION_INVITATION,
+                id.to_be_bytes(),
+            ))
+            .await?
+            .map(|value| {
+                serde_json::from_str(&value).map_err(|err| {
+                    trc::StoreEvent::DataCorruption
+                        .reason(err)
+                        .details("Failed to deserialize organization invitation")
+                        .caused_by(trc::location!())
+                })
+            })
+            .transpose()
+    }
+
+    /// Claims a pending invitation before redeeming or revoking it, only one
+    /// concurrent caller succeeds. The claim is held in the data store along
+    /// with the invitation until it expires, or until `release_invitation`
+    /// returns it after a failed redemption.
+    pub async fn claim_invitation(&self, record: &InvitationRecord) -> trc::Result<bool> {
+        Ok(record.status == InvitationStatus::Pending
+            && self
+                .invitation_store()
+                .try_lock(
+                    KV_LOCK_INVITATION,
+                    &record.invitation.id.to_be_bytes(),
+                    record.invitation.expires.saturating_sub(now()) + 1,
+                )
+                .await?)
+    }
+
+    pub async fn release_invitation(&self, id: u64) -> trc::Result<()> {
+        self.invitation_store()
+            .remove_lock(KV_LOCK_INVITATION, &id.to_be_bytes())
+            .await
+    }
+
+    /// Records the outcome of a claimed invitation.
+    pub async fn set_invitation_status(
+        &self,
+        mut record: InvitationRecord,
+        status: InvitationStatus,
+    ) -> trc::Result<()> {
+        record.status = status;
+        self.add_invitation(&record).await
+    }
+
+    // Invitations are kept in the data store rather than the in-memory store,
+    // which may not be persistent
+    fn invitation_store(&self) -> InMemoryStore {
+        InMemoryStore::Store(self.store().clone())
+    }
+}
+
+impl OrganizationInvitation {
+    pub fn sign(&self, key: &str) -> String {
+        let data = self.signed_data();
+        let signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            data.as_bytes(),
+        );
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(data.as_bytes()),
+            URL_SAFE_NO_PAD.encode(signature.as_ref())
+        )
+    }
+
+    /// Returns the invitation if its signature is valid, expiration and
+    /// redemption are checked by the caller.
+    pub fn verify(key: &str, token: &str) -> Option<Self> {
+        let (data, signature) = token.split_once('.')?;
+        let data = String::from_utf8(URL_SAFE_NO_PAD.decode(data).ok()?).ok()?;
+        hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            data.as_bytes(),
+            &URL_SAFE_NO_PAD.decode(signature).ok()?,
+        )
+        .ok()?;
+
+        serde_json::from_str(data.strip_prefix("org-invitation:")?).ok()
+    }
+
+    pub fn is_valid_pattern(pattern: &str) -> bool {
+        let domain = pattern.strip_prefix("*.").unwrap_or(pattern);
+        pattern == "*"
+            || (domain.contains('.')
+                && domain
+                    .split('.')
+                    .all(|label| !label.is_empty() && label.len() <= 63)
+                && domain
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '.')))
+    }
+
+    pub fn allows_domain(&self, domain: &str) -> bool {
+        let domain = domain.to_lowercase();
+        let pattern = self.domain_pattern.to_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(parent) => domain
+                .strip_suffix(parent)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => pattern == "*" || pattern == domain,
+        }
+    }
+
+    fn signed_data(&self) -> String {
+        format!(
+            "org-invitation:{}",
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn organization_invitation() {
+        let invitation = OrganizationInvitation {
+            id: 42,
+            invited_by: 7,
+            domain_pattern: "*.example.com".to_string(),
+            max_users: Some(10),
+            quota: None,
+            plan: Some("starter".to_string()),
+            collections: None,
+            expires: 1000,
+        };
+        let signed = invitation.sign("secret");
+
+        assert_eq!(
+            OrganizationInvitation::verify("secret", &signed),
+            Some(invitation.clone())
+        );
+        assert_eq!(OrganizationInvitation::verify("other", &signed), None);
+        let (data, signature) = signed.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(
+            String::from_utf8(URL_SAFE_NO_PAD.decode(data).unwrap())
+                .unwrap()
+                .replace("\"maxUsers\":10", "\"maxUsers\":1000"),
+        );
+        assert_eq!(
+            OrganizationInvitation::verify("secret", &format!("{forged}.{signature}")),
+            None
+        );
+
+        // Wildcards only match subdomains
+        assert!(invitation.allows_domain("acme.example.com"));
+        assert!(invitation.allows_domain("Mail.ACME.example.com"));
+        assert!(!invitation.allows_domain("example.com"));
+        assert!(!invitation.allows_domain("acmeexample.com"));
+        let exact = OrganizationInvitation {
+            domain_pattern: "acme.org".to_string(),
+            ..invitation.clone()
+        };
+        assert!(exact.allows_domain("ACME.org"));
+        assert!(!exact.allows_domain("sub.acme.org"));
+
+        for (pattern, is_valid) in [
+            ("*", true),
+            ("acme.org", true),
+            ("*.example.com", true),
+            ("*.com*", false),
+            ("example", false),
+            ("*example.com", false),
+            ("exa mple.com", false),
+            ("", false),
+        ] {
+            assert_eq!(
+                OrganizationInvitation::is_valid_pattern(pattern),
+                is_valid,
+                "{pattern}"
+            );
+        }
+    }
+}
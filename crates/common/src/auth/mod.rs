@@ -4,25 +4,42 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use crate::{Server, listener::limiter::ConcurrencyLimiter};
+use crate::{
+    Server, config::overrides::TenantProtocol, ipc::HousekeeperEvent,
+    listener::limiter::ConcurrencyLimiter, telemetry::metrics::tenant::TenantMetric,
+};
 use directory::{
-    Directory, FALLBACK_ADMIN_ID, Permission, Permissions, Principal, QueryParams, Type,
-    backend::internal::lookup::DirectoryStore, core::secret::verify_secret_hash,
+    AuthProtocol, Directory, FALLBACK_ADMIN_ID, Permission, Permissions, Principal, QueryParams,
+    Type, backend::internal::lookup::DirectoryStore, core::secret::verify_secret_hash,
 };
 use mail_send::Credentials;
 use oauth::GrantType;
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 use types::collection::Collection;
 use utils::{
     cache::CacheItemWeight,
+    config::Rate,
     map::{bitmap::Bitmap, vec_map::VecMap},
 };
 
 pub mod access_token;
+pub mod activity;
+pub mod changes;
+pub mod import;
+pub mod invitation;
+pub mod notice;
 pub mod oauth;
 pub mod rate_limit;
 pub mod roles;
 pub mod sasl;
+pub mod verification;
+pub mod webauthn;
 
 #[derive(Debug, Default)]
 pub struct AccessToken {
@@ -37,11 +54,24 @@ pub struct AccessToken {
     pub object_quota: [u32; Collection::MAX],
     pub permissions: Permissions,
     pub tenant: Option<TenantInfo>,
+    /// Domain of the primary address of each account the token writes to,
+    /// which storage usage is rolled up into.
+    pub domains: VecMap<u32, u32>,
+    /// Protocols the account may log in with as a bitmask of
+    /// `TenantProtocol` bits, zero when none is restricted.
+    pub allowed_protocols: u64,
+    /// Request rate of the tenant's plan, replacing the global one.
+    pub rate_limit: Option<Rate>,
+    /// Address or domain pattern a send-only credential is locked to, and
+    /// the rate at which it may submit messages.
+    pub sender_address: Option<String>,
+    pub send_rate: Option<Rate>,
     pub concurrent_http_requests: Option<ConcurrencyLimiter>,
     pub concurrent_imap_requests: Option<ConcurrencyLimiter>,
     pub concurrent_uploads: Option<ConcurrencyLimiter>,
     pub revision: u64,
     pub obj_size: u64,
+    pub schedule_change: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
@@ -55,6 +85,7 @@ pub struct ResourceToken {
     pub account_id: u32,
     pub quota: u64,
     pub tenant: Option<TenantInfo>,
+    pub domain_id: Option<u32>,
 }
 
 pub struct AuthRequest<'x> {
@@ -63,7 +94,11 @@ pub struct AuthRequest<'x> {
     remote_ip: IpAddr,
     return_member_of: bool,
     allow_api_access: bool,
+    protocol: Option<AuthProtocol>,
+    tenant_protocol: Option<TenantProtocol>,
+    allow_expired_password: bool,
     directory: Option<&'x Directory>,
+    impersonated: AtomicBool,
 }
 
 impl Server {
@@ -71,14 +106,30 @@ impl Server {
         // Resolve directory
         let directory = req.directory.unwrap_or(&self.core.storage.directory);
 
+        // Refuse disabled protocols before verifying the password
+        let protocol = req
+            .tenant_protocol
+            .or_else(|| req.protocol.and_then(TenantProtocol::from_auth));
+        if let Some(protocol) = protocol
+            && let Some(login) = req.credentials.login()
+            && self.has_protocol_flags()
+        {
+            self.assert_protocol_enabled(self.tenant_id_for_name(login).await, protocol)
+                .await?;
+        }
+
         // Validate credentials
-        match &req.credentials {
+        let result = match &req.credentials {
             Credentials::OAuthBearer { token } if !directory.has_bearer_token_support() => {
                 match self
                     .validate_access_token(GrantType::AccessToken.into(), token)
                     .await
                 {
-                    Ok(token_into) => self.get_access_token(token_into.account_id).await,
+                    Ok(token_into) => {
+                        req.impersonated
+                            .store(token_into.grant.impersonated, Ordering::Relaxed);
+                        self.get_access_token(token_into.account_id).await
+                    }
                     Err(err) => Err(err),
                 }
             }
@@ -90,8 +141,52 @@ impl Server {
         .and_then(|token| {
             token
                 .assert_has_permission(Permission::Authenticate)
+                .and_then(|_| self.assert_tenant_available(token.tenant.map(|t| t.id)))
                 .map(|_| token)
-        })
+        });
+        let result = match (result, protocol) {
+            (Ok(token), Some(protocol)) => self
+                .assert_protocol_enabled(token.tenant.map(|t| t.id), protocol)
+                .await
+                .and_then(|_| token.assert_protocol_allowed(protocol.as_str()))
+                .map(|_| token),
+            // ManageSieve can't be listed, so restricted accounts can't use it
+            (Ok(token), None) if req.protocol == Some(AuthProtocol::ManageSieve) => token
+                .assert_protocol_allowed(AuthProtocol::ManageSieve.as_str())
+                .map(|_| token),
+            (result, _) => result,
+        };
+
+        // Record the login
+        if let (Ok(token), Some(protocol)) = (&result, req.protocol) {
+            self.record_activity(token.primary_id(), protocol.into())
+                .await;
+        }
+
+        // Update tenant metrics
+        if self.core.metrics.tenants {
+            match &result {
+                Ok(token) => {
+                    self.record_tenant_metric(
+                        token.tenant.map(|tenant| tenant.id),
+                        TenantMetric::AuthSuccess,
+                        1,
+                    );
+                }
+                Err(err) if err.matches(trc::EventType::Auth(trc::AuthEvent::Failed)) => {
+                    if let Some(login) = req.credentials.login() {
+                        self.record_tenant_metric(
+                            self.tenant_id_for_name(login).await,
+                            TenantMetric::AuthFailure,
+                            1,
+                        );
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+
+        result
     }
 
     async fn authenticate_credentials(
@@ -99,28 +194,89 @@ impl Server {
         req: &AuthRequest<'_>,
         directory: &Directory,
     ) -> trc::Result<Principal> {
+        // Expired passwords and app passwords are checked against the policy
+        // of the tenant the login belongs to
+        let password_policy = match req.credentials.login() {
+            Some(login) if self.has_password_restrictions() => {
+                let mut policy = self
+                    .password_policy(self.tenant_id_for_name(login).await)
+                    .into_owned();
+                if req.allow_expired_password {
+                    policy.max_age_days = 0;
+                }
+                Some(policy)
+            }
+            _ => None,
+        };
+
         // First try to authenticate the user against the default directory
+        let secret_cipher = self.secret_cipher();
+        let recovery_code_used = AtomicBool::new(false);
         let result = match directory
             .query(
                 QueryParams::credentials(&req.credentials)
-                    .with_return_member_of(req.return_member_of),
+                    .with_return_member_of(req.return_member_of)
+                    .with_protocol(req.protocol)
+                    .with_password_policy(password_policy.as_ref())
+                    .with_secret_cipher(Some(&secret_cipher))
+                    .with_recovery_code_used(Some(&recovery_code_used)),
             )
             .await
         {
             Ok(Some(principal)) => {
+                if recovery_code_used.load(Ordering::Relaxed) {
+                    let remaining = principal.recovery_code_count();
+                    trc::event!(
+                        Auth(trc::AuthEvent::RecoveryCodeUsed),
+                        AccountName = principal.name().to_string(),
+                        AccountId = principal.id(),
+                        Total = remaining,
+                        SpanId = req.session_id,
+                    );
+                    self.notify_recovery_code_used(principal.id(), remaining)
+                        .await;
+                }
+
                 trc::event!(
                     Auth(trc::AuthEvent::Success),
                     AccountName = principal.name().to_string(),
                     AccountId = principal.id(),
                     SpanId = req.session_id,
                 );
+                self.check_login_country(principal.id(), principal.tenant(), req.remote_ip);
 
                 return Ok(principal);
             }
             Ok(None) => Ok(()),
             Err(err) => {
-                if err.matches(trc::EventType::Auth(trc::AuthEvent::MissingTotp)) {
+                if err.matches(trc::EventType::Directory(
+                    trc::DirectoryEvent::AccountLocked,
+                )) {
+                    // The account was locked by this attempt
+                    let account_id = err.value_as_uint(trc::Key::AccountId).unwrap_or_default();
+                    let locked_until = err.value_as_uint(trc::Key::Expires);
+                    trc::event!(
+                        Directory(trc::DirectoryEvent::AccountLocked),
+                        AccountName = req.credentials.login().map(|s| s.to_string()),
+                        AccountId = account_id,
+                        RemoteIp = req.remote_ip,
+                        Expires = locked_until.map(trc::Value::Timestamp),
+                        SpanId = req.session_id,
+                    );
+                    self.notify_account_lockout(account_id as u32, locked_until)
+                        .await;
+
+                    return Err(trc::AuthEvent::AccountLocked
+                        .into_err()
+                        .account_id(account_id)
+                        .ctx_opt(trc::Key::Expires, locked_until.map(trc::Value::Timestamp)));
+                } else if err.matches(trc::EventType::Auth(trc::AuthEvent::MissingTotp))
+                    || err.matches(trc::EventType::Auth(trc::AuthEvent::TooManyAttempts))
+                    || err.matches(trc::EventType::Auth(trc::AuthEvent::PasswordExpired))
+                {
                     return Err(err);
+                } else if err.matches(trc::EventType::Auth(trc::AuthEvent::AccountLocked)) {
+                    return Err(err.ctx(trc::Key::RemoteIp, req.remote_ip));
                 } else {
                     Err(err)
                 }
@@ -161,6 +317,7 @@ impl Server {
                                     AccountId = principal.id(),
                                     Type = principal.typ().description(),
                                 );
+                                req.impersonated.store(true, Ordering::Relaxed);
 
                                 return Ok(principal);
                             }
@@ -173,7 +330,8 @@ impl Server {
                                 .store()
                                 .query(
                                     QueryParams::credentials(&req.credentials)
-                                        .with_return_member_of(req.return_member_of),
+                                        .with_return_member_of(req.return_member_of)
+                                        .with_protocol(req.protocol),
                                 )
                                 .await
                             && principal.typ == Type::ApiKey
@@ -247,6 +405,50 @@ impl Server {
                 ))
         }
     }
+
+    /// Hands the use of a recovery code over to the housekeeper, which
+    /// notifies the owner of the account.
+    pub async fn notify_recovery_code_used(&self, account_id: u32, remaining: usize) {
+        if let Err(err) = self
+            .inner
+            .ipc
+            .housekeeper_tx
+            .send(HousekeeperEvent::RecoveryCodeUsed {
+                account_id,
+                remaining,
+            })
+            .await
+        {
+            trc::event!(
+                Server(trc::ServerEvent::ThreadError),
+                Details = "Failed to send event to Housekeeper",
+                CausedBy = trc::location!(),
+                Reason = err.to_string(),
+            );
+        }
+    }
+
+    /// Hands a lockout change over to the housekeeper, which notifies the
+    /// owner of the account when the policy of its tenant requests it.
+    pub async fn notify_account_lockout(&self, account_id: u32, locked_until: Option<u64>) {
+        if let Err(err) = self
+            .inner
+            .ipc
+            .housekeeper_tx
+            .send(HousekeeperEvent::AccountLockout {
+                account_id,
+                locked_until,
+            })
+            .await
+        {
+            trc::event!(
+                Server(trc::ServerEvent::ThreadError),
+                Details = "Failed to send event to Housekeeper",
+                CausedBy = trc::location!(),
+                Reason = err.to_string(),
+            );
+        }
+    }
 }
 
 impl<'x> AuthRequest<'x> {
@@ -262,6 +464,10 @@ impl<'x> AuthRequest<'x> {
             return_member_of: true,
             directory: None,
             allow_api_access: false,
+            protocol: None,
+            tenant_protocol: None,
+            allow_expired_password: false,
+            impersonated: AtomicBool::new(false),
         }
     }
 
@@ -295,6 +501,31 @@ impl<'x> AuthRequest<'x> {
         self.allow_api_access = allow_api_access;
         self
     }
+
+    pub fn with_protocol(mut self, protocol: AuthProtocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Protocol checked against the tenant's flags, HTTP logins set it from
+    /// the endpoint being accessed.
+    pub fn with_tenant_protocol(mut self, protocol: Option<TenantProtocol>) -> Self {
+        self.tenant_protocol = protocol;
+        self
+    }
+
+    /// Accepts passwords older than the maximum password age, so that they
+    /// can be used to set a new password.
+    pub fn with_expired_password(mut self, allow_expired_password: bool) -> Self {
+        self.allow_expired_password = allow_expired_password;
+        self
+    }
+
+    /// Whether the request was authenticated by a master user logging in as
+    /// the account, either directly or through a token issued to one.
+    pub fn is_impersonated(&self) -> bool {
+        self.impersonated.load(Ordering::Relaxed)
+    }
 }
 
 impl CacheItemWeight for AccessToken {
@@ -0,0 +1,326 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{
+    Server,
+    config::{network::AsnGeoLookupConfig, overrides::TenantSetting},
+    ipc::HousekeeperEvent,
+};
+use directory::{
+    Principal,
+    backend::internal::{
+        PrincipalAction, PrincipalField, PrincipalUpdate, SpecialSecrets, security::SecurityNotices,
+    },
+};
+use std::net::IpAddr;
+
+/// Classes of sensitive account events that are notified by email when
+/// enabled for the tenant of the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SecurityNotice {
+    AdminCreated,
+    PasswordChanged,
+    MfaChanged,
+    AppPasswordCreated,
+    NewCountry,
+}
+
+/// Who is notified of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoticeRecipient {
+    /// The owner of the account, at its secondary addresses or else at its
+    /// primary address.
+    Account,
+    /// The technical contact of the tenant of the account.
+    TechnicalContact,
+}
+
+impl SecurityNotice {
+    pub const ALL: [SecurityNotice; 5] = [
+        SecurityNotice::AdminCreated,
+        SecurityNotice::PasswordChanged,
+        SecurityNotice::MfaChanged,
+        SecurityNotice::AppPasswordCreated,
+        SecurityNotice::NewCountry,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        SecurityNotice::ALL
+            .into_iter()
+            .find(|notice| notice.as_str() == value)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityNotice::AdminCreated => "admin-created",
+            SecurityNotice::PasswordChanged => "password-changed",
+            SecurityNotice::MfaChanged => "mfa-changed",
+            SecurityNotice::AppPasswordCreated => "app-password-created",
+            SecurityNotice::NewCountry => "new-country",
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Maps an audit event to the notice it triggers, if any.
+    pub fn from_event(event: trc::EventType) -> Option<Self> {
+        match event {
+            trc::EventType::Directory(trc::DirectoryEvent::PrincipalCreated) => {
+                Some(SecurityNotice::AdminCreated)
+            }
+            trc::EventType::Directory(trc::DirectoryEvent::PasswordChanged) => {
+                Some(SecurityNotice::PasswordChanged)
+            }
+            trc::EventType::Directory(
+                trc::DirectoryEvent::MfaFactorAdded | trc::DirectoryEvent::MfaFactorRemoved,
+            ) => Some(SecurityNotice::MfaChanged),
+            trc::EventType::Directory(trc::DirectoryEvent::AppPasswordCreated) => {
+                Some(SecurityNotice::AppPasswordCreated)
+            }
+            trc::EventType::Auth(trc::AuthEvent::NewCountry) => Some(SecurityNotice::NewCountry),
+            _ => None,
+        }
+    }
+
+    pub fn recipient(&self) -> NoticeRecipient {
+        match self {
+            SecurityNotice::AdminCreated => NoticeRecipient::TechnicalContact,
+            SecurityNotice::PasswordChanged
+            | SecurityNotice::MfaChanged
+            | SecurityNotice::AppPasswordCreated
+            | SecurityNotice::NewCountry => NoticeRecipient::Account,
+        }
+    }
+
+    /// Addresses the notice is sent to, empty when nobody can be notified.
+    pub fn recipients(&self, account: &Principal, tenant: Option<&Principal>) -> Vec<String> {
+        match self.recipient() {
+            NoticeRecipient::Account => {
+                let recipients = account
+                    .secondary_emails()
+                    .map(|email| email.to_string())
+                    .collect::<Vec<_>>();
+                if recipients.is_empty() {
+                    account
+                        .primary_email()
+                        .map(|email| vec![email.to_string()])
+                        .unwrap_or_default()
+                } else {
+                    recipients
+                }
+            }
+            NoticeRecipient::TechnicalContact => tenant
+                .and_then(|tenant| tenant.alert_recipient())
+                .map(|email| vec![email.to_string()])
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn subject(&self) -> &'static str {
+        match self {
+            SecurityNotice::AdminCreated => "A new administrator was added",
+            SecurityNotice::PasswordChanged => "Your password was changed",
+            SecurityNotice::MfaChanged => "Your two-factor authentication settings changed",
+            SecurityNotice::AppPasswordCreated => "A new app password was created",
+            SecurityNotice::NewCountry => "New login from an unfamiliar country",
+        }
+    }
+
+    pub fn body(&self, account: &str, details: Option<&str>) -> String {
+        let (summary, advice) = match self {
+            SecurityNotice::AdminCreated => (
+                "The administrator account {} was created in your organization.",
+                "If this account was not expected, review the administrators of your organization.",
+            ),
+            SecurityNotice::PasswordChanged => (
+                "The password of the account {} was changed.",
+                "If you did not make this change, contact your administrator immediately.",
+            ),
+            SecurityNotice::MfaChanged => (
+                "The two-factor authentication settings of the account {} were changed.",
+                "If you did not make this change, change your password and review your second factors.",
+            ),
+            SecurityNotice::AppPasswordCreated => (
+                "An app password was created for the account {}.",
+                "If you did not create it, remove it and change your password.",
+            ),
+            SecurityNotice::NewCountry => (
+                "The account {} was logged in to from a country it was not used from before.",
+                "If this login was not made by you, change your password.",
+            ),
+        };
+        let mut body = summary.replace("{}", account);
+        if let Some(details) = details {
+            body.push_str("\r\n\r\n");
+            body.push_str(details);
+        }
+        body.push_str("\r\n\r\n");
+        body.push_str(advice);
+        body.push_str("\r\n");
+        body
+    }
+}
+
+/// Returns whether the updates change the password of an account, app
+/// passwords and TOTP secrets are not passwords.
+pub fn is_password_change(changes: &[PrincipalUpdate]) -> bool {
+    changes.iter().any(|change| {
+        change.field == PrincipalField::Secrets
+            && matches!(
+                change.action,
+                PrincipalAction::Set | PrincipalAction::AddItem
+            )
+            && change.value.iter_str().any(|secret| {
+                !secret.is_empty() && !secret.is_app_secret() && !secret.is_otp_secret()
+            })
+    })
+}
+
+impl Server {
+    /// Hands an audit event over to the housekeeper, which sends the notice
+    /// it triggers when enabled for the tenant of the account.
+    pub async fn notify_security_event(
+        &self,
+        event: trc::EventType,
+        account_id: u32,
+        details: Option<String>,
+    ) {
+        let Some(notice) = SecurityNotice::from_event(event) else {
+            return;
+        };
+        if let Err(err) = self
+            .inner
+            .ipc
+            .housekeeper_tx
+            .send(HousekeeperEvent::SecurityNotice {
+                notice,
+                account_id,
+                details,
+            })
+            .await
+        {
+            trc::event!(
+                Server(trc::ServerEvent::ThreadError),
+                Details = "Failed to send event to Housekeeper",
+                CausedBy = trc::location!(),
+                Reason = err.to_string(),
+            );
+        }
+    }
+
+    /// Records the country of a successful login and notifies the account
+    /// when it was never used from there. The lookup may have to query the
+    /// network so it does not delay the login.
+    pub fn check_login_country(&self, account_id: u32, tenant_id: Option<u32>, ip: IpAddr) {
+        if matches!(
+            self.core.network.asn_geo_lookup,
+            AsnGeoLookupConfig::Disabled
+        ) || ip.is_loopback()
+        {
+            return;
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            if server
+                .tenant_setting(
+                    tenant_id,
+                    TenantSetting::SecurityNotice(SecurityNotice::NewCountry),
+                )
+                .await
+                == 0
+            {
+                return;
+            }
+            let Some(country) = server.lookup_asn_country(ip).await.country else {
+                return;
+            };
+
+            match server
+                .store()
+                .record_login_country(account_id, &country)
+                .await
+            {
+                Ok(true) => {
+                    trc::event!(
+                        Auth(trc::AuthEvent::NewCountry),
+                        AccountId = account_id,
+                        RemoteIp = ip,
+                        Details = country.to_string(),
+                    );
+                    server
+                        .notify_security_event(
+                            trc::EventType::Auth(trc::AuthEvent::NewCountry),
+                            account_id,
+                            format!("Country: {}\r\nIP address: {ip}", country.to_uppercase())
+                                .into(),
+                        )
+                        .await;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    trc::error!(
+                        err.account_id(account_id)
+                            .details("Failed to record login country")
+                            .caused_by(trc::location!())
+                    );
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecurityNotice;
+    use directory::{Principal, PrincipalData, Type};
+
+    #[test]
+    fn notice_recipients() {
+        let mut account = Principal::new(1, Type::Individual);
+        account
+            .data
+            .push(PrincipalData::PrimaryEmail("john@example.org".to_string()));
+        let mut tenant = Principal::new(2, Type::Tenant);
+        tenant.data.push(PrincipalData::TechnicalContact(
+            "it@example.org".to_string(),
+        ));
+
+        // Owners are notified at their primary address without secondary ones
+        for notice in [
+            SecurityNotice::PasswordChanged,
+            SecurityNotice::MfaChanged,
+            SecurityNotice::AppPasswordCreated,
+            SecurityNotice::NewCountry,
+        ] {
+            assert_eq!(
+                notice.recipients(&account, Some(&tenant)),
+                vec!["john@example.org".to_string()],
+                "{notice:?}"
+            );
+        }
+        assert_eq!(
+            SecurityNotice::AdminCreated.recipients(&account, Some(&tenant)),
+            vec!["it@example.org".to_string()]
+        );
+        assert!(
+            SecurityNotice::AdminCreated
+                .recipients(&account, None)
+                .is_empty()
+        );
+
+        // Verified secondary addresses take precedence
+        account
+            .data
+            .push(PrincipalData::SecondaryEmail("john@remote.org".to_string()));
+        assert_eq!(
+            SecurityNotice::PasswordChanged.recipients(&account, Some(&tenant)),
+            vec!["john@remote.org".to_string()]
+        );
+    }
+}
@@ -4,6 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use ahash::AHashSet;
 use std::time::Duration;
 
 use biscuit::{
@@ -22,6 +23,7 @@ use utils::config::Config;
 use x509_parser::num_bigint::BigUint;
 
 use crate::{
+    auth::notice::SecurityNotice,
     config::{build_ecdsa_pem, build_rsa_keypair},
     manager::webadmin::Resource,
 };
@@ -36,6 +38,14 @@ pub struct OAuthConfig {
     pub oauth_expiry_refresh_token_renew: u64,
     pub oauth_max_auth_attempts: u32,
 
+    pub session_max_lifetime: u64,
+    pub session_idle_timeout: u64,
+
+    pub webauthn_passwordless: bool,
+    pub recovery_code_notify: bool,
+    pub security_notices: AHashSet<SecurityNotice>,
+    pub security_notice_burst: u64,
+
     pub allow_anonymous_client_registration: bool,
     pub require_client_authentication: bool,
 
@@ -178,6 +188,32 @@ impl OAuthConfig {
             oauth_max_auth_attempts: config
                 .property_or_default("oauth.auth.max-attempts", "3")
                 .unwrap_or(10),
+            session_max_lifetime: config
+                .property::<Duration>("authentication.session.max-lifetime")
+                .map_or(0, |duration| duration.as_secs()),
+            session_idle_timeout: config
+                .property::<Duration>("authentication.session.idle-timeout")
+                .map_or(0, |duration| duration.as_secs()),
+            webauthn_passwordless: config
+                .property_or_default("authentication.webauthn.passwordless", "false")
+                .unwrap_or(false),
+            recovery_code_notify: config
+                .property_or_default("authentication.recovery-codes.notify", "true")
+                .unwrap_or(true),
+            security_notices: SecurityNotice::ALL
+                .into_iter()
+                .filter(|notice| {
+                    config
+                        .property_or_default::<bool>(
+                            ("authentication.security-notice", notice.as_str()),
+                            "false",
+                        )
+                        .unwrap_or(false)
+                })
+                .collect(),
+            security_notice_burst: config
+                .property_or_default::<Duration>("authentication.security-notice.burst", "15m")
+                .map_or(15 * 60, |duration| duration.as_secs()),
             oidc_expiry_id_token: config
                 .property_or_default::<Duration>("oauth.oidc.expiry.id-token", "15m")
                 .unwrap_or_else(|| Duration::from_secs(15 * 60))
@@ -205,6 +241,12 @@ impl Default for OAuthConfig {
             oauth_expiry_refresh_token: Default::default(),
             oauth_expiry_refresh_token_renew: Default::default(),
             oauth_max_auth_attempts: Default::default(),
+            session_max_lifetime: Default::default(),
+            session_idle_timeout: Default::default(),
+            webauthn_passwordless: Default::default(),
+            recovery_code_notify: Default::default(),
+            security_notices: Default::default(),
+            security_notice_burst: Default::default(),
             oidc_expiry_id_token: Default::default(),
             allow_anonymous_client_registration: Default::default(),
             require_client_authentication: Default::default(),
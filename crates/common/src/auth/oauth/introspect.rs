@@ -4,11 +4,12 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use super::GrantType;
+use crate::{Server, auth::AccessToken};
+use directory::{Permission, backend::internal::manage::ManageDirectory};
 use serde::{Deserialize, Serialize};
 use trc::{AddContext, AuthEvent, EventType};
 
-use crate::{Server, auth::AccessToken};
-
 #[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct OAuthIntrospect {
     #[serde(default)]
@@ -45,42 +46,97 @@ pub struct OAuthIntrospect {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sub: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+
+    /// Whether the token was issued to a master user logged in as the account.
+    #[serde(default)]
+    pub impersonated: bool,
 }
 
 impl Server {
+    /// Introspects an access or refresh token. Accounts may introspect their
+    /// own tokens, while those with the token introspection permission may
+    /// introspect any token issued within their tenant. Tokens the caller is
+    /// not allowed to see are reported as inactive, like invalid ones.
     pub async fn introspect_access_token(
         &self,
         token: &str,
         access_token: &AccessToken,
     ) -> trc::Result<OAuthIntrospect> {
-        match self.validate_access_token(None, token).await {
-            Ok(token_info) => Ok(OAuthIntrospect {
-                active: true,
-                client_id: Some(token_info.client_id),
-                username: if access_token.primary_id() == token_info.account_id {
-                    access_token.name.clone()
-                } else {
-                    self.get_access_token(token_info.account_id)
-                        .await
-                        .caused_by(trc::location!())?
-                        .name
-                        .clone()
-                }
-                .into(),
-                token_type: Some("bearer".into()),
-                exp: Some(token_info.expiry as i64),
-                iat: Some(token_info.issued_at as i64),
-                ..Default::default()
-            }),
+        let token_info = match self.validate_access_token(None, token).await {
+            Ok(token_info)
+                if matches!(
+                    token_info.grant_type,
+                    GrantType::AccessToken | GrantType::RefreshToken
+                ) =>
+            {
+                token_info
+            }
+            Ok(_) => return Ok(OAuthIntrospect::default()),
             Err(err)
                 if matches!(
                     err.event_type(),
                     EventType::Auth(AuthEvent::Error) | EventType::Auth(AuthEvent::TokenExpired)
                 ) =>
             {
-                Ok(OAuthIntrospect::default())
+                return Ok(OAuthIntrospect::default());
             }
-            Err(err) => Err(err),
-        }
+            Err(err) => return Err(err),
+        };
+
+        let owner_token;
+        let owner = if access_token.primary_id() == token_info.account_id {
+            access_token
+        } else if access_token.has_permission(Permission::OauthTokenIntrospect) {
+            owner_token = self
+                .get_access_token(token_info.account_id)
+                .await
+                .caused_by(trc::location!())?;
+            if access_token.tenant.is_some_and(|tenant| {
+                owner_token
+                    .tenant
+                    .is_none_or(|owner_tenant| owner_tenant.id != tenant.id)
+            }) {
+                return Ok(OAuthIntrospect::default());
+            }
+            owner_token.as_ref()
+        } else {
+            return Ok(OAuthIntrospect::default());
+        };
+
+        let tenant = if let Some(tenant) = owner.tenant {
+            self.store()
+                .get_principal_name(tenant.id)
+                .await
+                .caused_by(trc::location!())?
+        } else {
+            None
+        };
+
+        Ok(OAuthIntrospect {
+            active: true,
+            client_id: Some(token_info.client_id),
+            username: Some(owner.name.clone()),
+            token_type: Some("bearer".into()),
+            exp: Some(token_info.expiry as i64),
+            iat: Some(token_info.issued_at as i64),
+            nbf: Some(token_info.issued_at as i64),
+            sub: Some(token_info.account_id.to_string()),
+            tenant,
+            permissions: owner
+                .permissions()
+                .into_iter()
+                .map(|permission| permission.name().to_string())
+                .collect(),
+            impersonated: token_info.grant.impersonated,
+            ..Default::default()
+        })
     }
 }
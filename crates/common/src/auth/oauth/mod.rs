@@ -9,6 +9,7 @@ pub mod crypto;
 pub mod introspect;
 pub mod oidc;
 pub mod registration;
+pub mod revoke;
 pub mod token;
 
 pub const DEVICE_CODE_LEN: usize = 40;
@@ -0,0 +1,118 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    GrantType, RANDOM_CODE_LEN,
+    crypto::SymmetricEncrypt,
+    token::{TokenGrant, TokenInfo, token_grant_id},
+};
+use crate::{KV_OAUTH_REVOKED, Server, ipc::BroadcastEvent};
+use mail_parser::decoders::base64::base64_decode;
+use store::{blake3, dispatch::lookup::KeyValue};
+use trc::AddContext;
+
+impl Server {
+    /// Revokes a token until it expires. Revoking a refresh token also revokes
+    /// the tokens issued under the same grant. Cached access tokens of the
+    /// account are invalidated in the cluster, so sessions that were
+    /// authenticated with the token validate it again on their next request.
+    pub async fn revoke_token(&self, token: &str, token_info: &TokenInfo) -> trc::Result<()> {
+        let key = base64_decode(token.as_bytes())
+            .and_then(|bytes| revoked_token_key(&bytes))
+            .ok_or_else(|| {
+                trc::AuthEvent::Error
+                    .into_err()
+                    .ctx(trc::Key::Reason, "Failed to decode token")
+                    .caused_by(trc::location!())
+            })?;
+        self.in_memory_store()
+            .key_set(
+                KeyValue::with_prefix(KV_OAUTH_REVOKED, key, vec![]).expires(token_info.expires_in),
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        // Refresh tokens are renewed under the same grant, so the grant stays
+        // revoked for as long as any of them may be valid
+        if token_info.grant_type == GrantType::RefreshToken
+            && let Some(grant_id) = token_info.grant.id
+        {
+            self.in_memory_store()
+                .key_set(
+                    KeyValue::with_prefix(KV_OAUTH_REVOKED, revoked_grant_key(grant_id), vec![])
+                        .expires(
+                            token_info
+                                .expires_in
+                                .max(self.core.oauth.oauth_expiry_refresh_token),
+                        ),
+                )
+                .await
+                .caused_by(trc::location!())?;
+            self.inner
+                .cache
+                .http_auth
+                .retain(|token, _| token_grant_id(token) != Some(grant_id));
+        }
+
+        let account_id = token_info.account_id;
+        self.inner.cache.http_auth.remove(token);
+        self.inner.cache.permissions.remove(&account_id);
+        self.inner.cache.access_tokens.remove(&account_id);
+        self.cluster_broadcast(BroadcastEvent::InvalidateAccessTokens(vec![account_id]))
+            .await;
+
+        trc::event!(
+            Auth(trc::AuthEvent::TokenRevoked),
+            AccountId = account_id,
+            Id = token_info.client_id.clone(),
+            Type = token_info.grant_type.as_str(),
+        );
+
+        Ok(())
+    }
+
+    /// Checks a decoded token against the revocation list.
+    pub async fn is_token_revoked(&self, token: &[u8]) -> trc::Result<bool> {
+        if let Some(key) = revoked_token_key(token) {
+            self.in_memory_store()
+                .key_exists(KeyValue::<()>::build_key(KV_OAUTH_REVOKED, key))
+                .await
+                .caused_by(trc::location!())
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Checks whether the grant a token was issued under was revoked.
+    pub async fn is_grant_revoked(&self, grant: TokenGrant) -> trc::Result<bool> {
+        if let Some(grant_id) = grant.id {
+            self.in_memory_store()
+                .key_exists(KeyValue::<()>::build_key(
+                    KV_OAUTH_REVOKED,
+                    revoked_grant_key(grant_id),
+                ))
+                .await
+                .caused_by(trc::location!())
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+// Revocations are keyed by the encrypted part of the token, which cannot be
+// altered without failing validation, rather than by its encoding
+fn revoked_token_key(token: &[u8]) -> Option<[u8; 32]> {
+    token
+        .get(..RANDOM_CODE_LEN + SymmetricEncrypt::ENCRYPT_TAG_LEN)
+        .map(|bytes| *blake3::hash(bytes).as_bytes())
+}
+
+// Prefixed so that grant keys are never the same length as token hashes
+fn revoked_grant_key(grant_id: u64) -> [u8; 9] {
+    let mut key = [b'g'; 9];
+    key[1..].copy_from_slice(&grant_id.to_be_bytes());
+    key
+}
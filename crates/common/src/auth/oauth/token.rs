@@ -9,7 +9,7 @@ use crate::Server;
 use directory::{PrincipalData, QueryParams};
 use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
-use std::time::SystemTime;
+use std::{fmt::Write, time::SystemTime};
 use store::{
     blake3,
     rand::{Rng, rng},
@@ -24,10 +24,46 @@ pub struct TokenInfo {
     pub expiry: u64,
     pub issued_at: u64,
     pub expires_in: u64,
+    pub grant: TokenGrant,
+}
+
+/// Authorization a token was issued under, shared by the access and refresh
+/// tokens obtained from the same code and by those refreshed from them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TokenGrant {
+    /// Revoking a refresh token revokes every token with the same id.
+    pub id: Option<u64>,
+    /// Whether the grant was authorized by a master user logged in as the
+    /// account.
+    pub impersonated: bool,
 }
 
 const OAUTH_EPOCH: u64 = 946684800; // Jan 1, 2000
 
+// Flags stored next to the grant type id
+const GRANT_HAS_ID: u8 = 0x40;
+const GRANT_IMPERSONATED: u8 = 0x80;
+
+impl TokenGrant {
+    pub fn new(impersonated: bool) -> Self {
+        TokenGrant {
+            id: Some(rng().random()),
+            impersonated,
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.id.is_some() {
+            flags |= GRANT_HAS_ID;
+        }
+        if self.impersonated {
+            flags |= GRANT_IMPERSONATED;
+        }
+        flags
+    }
+}
+
 impl Server {
     pub async fn encode_access_token(
         &self,
@@ -35,6 +71,24 @@ impl Server {
         account_id: u32,
         client_id: &str,
         expiry_in: u64,
+    ) -> trc::Result<String> {
+        self.encode_granted_token(
+            grant_type,
+            account_id,
+            client_id,
+            expiry_in,
+            TokenGrant::default(),
+        )
+        .await
+    }
+
+    pub async fn encode_granted_token(
+        &self,
+        grant_type: GrantType,
+        account_id: u32,
+        client_id: &str,
+        expiry_in: u64,
+        grant: TokenGrant,
     ) -> trc::Result<String> {
         // Build context
         let mut password_hash = String::new();
@@ -56,13 +110,7 @@ impl Server {
         }
 
         let key = &self.core.oauth.oauth_key;
-        let context = format!(
-            "{} {} {} {}",
-            grant_type.as_str(),
-            client_id,
-            account_id,
-            password_hash
-        );
+        let context = token_context(grant_type, client_id, account_id, &password_hash, grant);
 
         // Set expiration time
         let issued_at = SystemTime::now()
@@ -97,9 +145,12 @@ impl Server {
                     .caused_by(trc::location!())
             })?;
         token.push_leb128(account_id);
-        token.push(grant_type.id());
+        token.push(grant_type.id() | grant.flags());
         token.push_leb128(issued_at);
         token.push_leb128(expiry);
+        if let Some(grant_id) = grant.id {
+            token.push_leb128(grant_id);
+        }
         token.extend_from_slice(client_id.as_bytes());
 
         Ok(String::from_utf8(base64_encode(&token).unwrap_or_default()).unwrap())
@@ -118,20 +169,8 @@ impl Server {
                 .caused_by(trc::location!())
                 .details(token_.to_string())
         })?;
-        let (account_id, grant_type, issued_at, expiry, client_id) = token
-            .get((RANDOM_CODE_LEN + SymmetricEncrypt::ENCRYPT_TAG_LEN)..)
-            .and_then(|bytes| {
-                let mut bytes = bytes.iter();
-                (
-                    bytes.next_leb128()?,
-                    GrantType::from_id(bytes.next().copied()?)?,
-                    bytes.next_leb128::<u64>()?,
-                    bytes.next_leb128::<u64>()?,
-                    bytes.copied().map(char::from).collect::<String>(),
-                )
-                    .into()
-            })
-            .ok_or_else(|| {
+        let (account_id, grant_type, issued_at, expiry, grant, client_id) =
+            decode_token_fields(&token).ok_or_else(|| {
                 trc::AuthEvent::Error
                     .into_err()
                     .ctx(trc::Key::Reason, "Failed to decode token")
@@ -166,13 +205,7 @@ impl Server {
 
         // Build context
         let key = self.core.oauth.oauth_key.clone();
-        let context = format!(
-            "{} {} {} {}",
-            grant_type.as_str(),
-            client_id,
-            account_id,
-            password_hash
-        );
+        let context = token_context(grant_type, &client_id, account_id, &password_hash, grant);
 
         // Calculate nonce
         let mut hasher = blake3::Hasher::new();
@@ -204,6 +237,13 @@ impl Server {
                     .reason(err)
             })?;
 
+        // Validate revocation
+        if self.is_token_revoked(&token).await? || self.is_grant_revoked(grant).await? {
+            return Err(trc::AuthEvent::Error
+                .into_err()
+                .details("Token was revoked"));
+        }
+
         // Success
         Ok(TokenInfo {
             grant_type,
@@ -212,6 +252,7 @@ impl Server {
             expiry: expiry + OAUTH_EPOCH,
             issued_at: issued_at + OAUTH_EPOCH,
             expires_in: expiry - now,
+            grant,
         })
     }
 
@@ -254,3 +295,56 @@ impl Server {
         }
     }
 }
+
+/// Reads the fields stored in clear after the encrypted part of a token.
+fn decode_token_fields(token: &[u8]) -> Option<(u32, GrantType, u64, u64, TokenGrant, String)> {
+    let mut bytes = token
+        .get((RANDOM_CODE_LEN + SymmetricEncrypt::ENCRYPT_TAG_LEN)..)?
+        .iter();
+    let account_id = bytes.next_leb128()?;
+    let flags = bytes.next().copied()?;
+    let grant_type = GrantType::from_id(flags & !(GRANT_HAS_ID | GRANT_IMPERSONATED))?;
+    let issued_at = bytes.next_leb128::<u64>()?;
+    let expiry = bytes.next_leb128::<u64>()?;
+    let grant = TokenGrant {
+        id: if flags & GRANT_HAS_ID != 0 {
+            Some(bytes.next_leb128::<u64>()?)
+        } else {
+            None
+        },
+        impersonated: flags & GRANT_IMPERSONATED != 0,
+    };
+    let client_id = bytes.copied().map(char::from).collect::<String>();
+
+    Some((account_id, grant_type, issued_at, expiry, grant, client_id))
+}
+
+/// Returns the grant id of an encoded token without validating it.
+pub fn token_grant_id(token: &str) -> Option<u64> {
+    decode_token_fields(&base64_decode(token.as_bytes())?)?.4.id
+}
+
+// The grant is only part of the context when present, so that tokens issued
+// before grants were recorded remain valid
+fn token_context(
+    grant_type: GrantType,
+    client_id: &str,
+    account_id: u32,
+    password_hash: &str,
+    grant: TokenGrant,
+) -> String {
+    let mut context = format!(
+        "{} {} {} {}",
+        grant_type.as_str(),
+        client_id,
+        account_id,
+        password_hash
+    );
+    if let Some(grant_id) = grant.id {
+        let _ = write!(context, " {grant_id}");
+    }
+    if grant.impersonated {
+        context.push_str(" impersonated");
+    }
+    context
+}
@@ -20,7 +20,12 @@ impl Server {
         &self,
         access_token: &AccessToken,
     ) -> trc::Result<Option<InFlight>> {
-        let is_rate_allowed = if let Some(rate) = &self.core.jmap.rate_authenticated {
+        // The rate of the tenant's plan replaces the global rate
+        let rate = access_token
+            .rate_limit
+            .as_ref()
+            .or(self.core.jmap.rate_authenticated.as_ref());
+        let is_rate_allowed = if let Some(rate) = rate {
             self.core
                 .storage
                 .lookup
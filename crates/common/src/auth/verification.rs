@@ -0,0 +1,179 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::hmac;
+
+/// Confirmation token sent to a secondary address of an account. The nonce
+/// must match the one stored with the pending address, which is replaced on
+/// every resend and removed once the address is verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailVerificationToken {
+    pub account_id: u32,
+    pub address: String,
+    pub nonce: u64,
+    pub expires: u64,
+}
+
+impl EmailVerificationToken {
+    pub fn sign(&self, key: &str) -> String {
+        let data = self.signed_data();
+        let signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            data.as_bytes(),
+        );
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(data.as_bytes()),
+            URL_SAFE_NO_PAD.encode(signature.as_ref())
+        )
+    }
+
+    /// Returns the token if its signature is valid, expiration is checked by
+    /// the caller.
+    pub fn verify(key: &str, token: &str) -> Option<Self> {
+        let (data, signature) = token.split_once('.')?;
+        let data = String::from_utf8(URL_SAFE_NO_PAD.decode(data).ok()?).ok()?;
+        hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            data.as_bytes(),
+            &URL_SAFE_NO_PAD.decode(signature).ok()?,
+        )
+        .ok()?;
+
+        let mut parts = data.strip_prefix("verify-email:")?.splitn(4, ':');
+        Some(EmailVerificationToken {
+            account_id: parts.next()?.parse().ok()?,
+            nonce: parts.next()?.parse().ok()?,
+            expires: parts.next()?.parse().ok()?,
+            address: parts.next()?.to_string(),
+        })
+    }
+
+    fn signed_data(&self) -> String {
+        format!(
+            "verify-email:{}:{}:{}:{}",
+            self.account_id, self.nonce, self.expires, self.address
+        )
+    }
+}
+
+/// Activation token sent to the contact of an organization provisioned with
+/// admin email verification. The nonce must match the one stored with the
+/// pending activation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrganizationActivationToken {
+    pub tenant_id: u32,
+    pub nonce: u64,
+    pub expires: u64,
+}
+
+impl OrganizationActivationToken {
+    pub fn sign(&self, key: &str) -> String {
+        let data = self.signed_data();
+        let signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            data.as_bytes(),
+        );
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(data.as_bytes()),
+            URL_SAFE_NO_PAD.encode(signature.as_ref())
+        )
+    }
+
+    /// Returns the token if its signature is valid, expiration is checked by
+    /// the caller.
+    pub fn verify(key: &str, token: &str) -> Option<Self> {
+        let (data, signature) = token.split_once('.')?;
+        let data = String::from_utf8(URL_SAFE_NO_PAD.decode(data).ok()?).ok()?;
+        hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            data.as_bytes(),
+            &URL_SAFE_NO_PAD.decode(signature).ok()?,
+        )
+        .ok()?;
+
+        let mut parts = data.strip_prefix("activate-organization:")?.splitn(3, ':');
+        Some(OrganizationActivationToken {
+            tenant_id: parts.next()?.parse().ok()?,
+            nonce: parts.next()?.parse().ok()?,
+            expires: parts.next()?.parse().ok()?,
+        })
+    }
+
+    fn signed_data(&self) -> String {
+        format!(
+            "activate-organization:{}:{}:{}",
+            self.tenant_id, self.nonce, self.expires
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_verification_token() {
+        let token = EmailVerificationToken {
+            account_id: 7,
+            address: "jane:doe@remote.org".to_string(),
+            nonce: 1234,
+            expires: 1000,
+        };
+        let signed = token.sign("secret");
+
+        assert_eq!(
+            EmailVerificationToken::verify("secret", &signed),
+            Some(token.clone())
+        );
+        assert_eq!(EmailVerificationToken::verify("other", &signed), None);
+        assert_eq!(
+            EmailVerificationToken::verify("secret", &signed.replace('.', "")),
+            None
+        );
+        let (data, signature) = signed.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(
+            String::from_utf8(URL_SAFE_NO_PAD.decode(data).unwrap())
+                .unwrap()
+                .replace(":7:", ":8:"),
+        );
+        assert_eq!(
+            EmailVerificationToken::verify("secret", &format!("{forged}.{signature}")),
+            None
+        );
+    }
+
+    #[test]
+    fn organization_activation_token() {
+        let token = OrganizationActivationToken {
+            tenant_id: 7,
+            nonce: 1234,
+            expires: 1000,
+        };
+        let signed = token.sign("secret");
+
+        assert_eq!(
+            OrganizationActivationToken::verify("secret", &signed),
+            Some(token.clone())
+        );
+        assert_eq!(OrganizationActivationToken::verify("other", &signed), None);
+
+        // Email verification tokens are not accepted as activation tokens
+        let email_token = EmailVerificationToken {
+            account_id: 7,
+            address: "1000".to_string(),
+            nonce: 1234,
+            expires: 1000,
+        }
+        .sign("secret");
+        assert_eq!(
+            OrganizationActivationToken::verify("secret", &email_token),
+            None
+        );
+    }
+}
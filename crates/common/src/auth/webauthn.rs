@@ -0,0 +1,750 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::signature::{
+    ECDSA_P256_SHA256_ASN1, ED25519, RSA_PKCS1_2048_8192_SHA256, RsaPublicKeyComponents,
+    UnparsedPublicKey,
+};
+use sha2::{Digest, Sha256};
+use store::{
+    dispatch::lookup::KeyValue,
+    rand::{Rng, distr::Alphanumeric, rng},
+};
+
+use crate::{KV_WEBAUTHN_CHALLENGE, KV_WEBAUTHN_CHALLENGE_USED, Server};
+
+pub const WEBAUTHN_CHALLENGE_LEN: usize = 32;
+
+/// Seconds a registration or login ceremony can be completed for.
+pub const WEBAUTHN_TIMEOUT: u64 = 300;
+
+/// COSE algorithms accepted for credentials, in order of preference.
+pub const COSE_ES256: i64 = -7;
+pub const COSE_EDDSA: i64 = -8;
+pub const COSE_RS256: i64 = -257;
+pub const COSE_ALGORITHMS: [i64; 3] = [COSE_ES256, COSE_EDDSA, COSE_RS256];
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED: u8 = 0x40;
+
+const MAX_CBOR_DEPTH: usize = 8;
+
+/// Relying party a ceremony is performed for. Credentials are bound to the
+/// host the web interface is served from, so passkeys registered on a
+/// tenant hostname are only offered on that hostname.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelyingParty {
+    pub id: String,
+    pub tenant_id: Option<u32>,
+}
+
+/// Ceremony waiting for the response of an authenticator. Challenges are
+/// single use and expire after `WEBAUTHN_TIMEOUT` seconds.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WebAuthnChallenge {
+    pub challenge: Vec<u8>,
+    pub rp_id: String,
+    pub tenant_id: Option<u32>,
+    /// Account registering a credential, or the account a login was
+    /// started for when a username was provided.
+    pub account_id: Option<u32>,
+    #[serde(default)]
+    pub nickname: Option<String>,
+    #[serde(default)]
+    pub resident: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredCredential {
+    pub id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+impl Server {
+    /// Returns the relying party of a host, which has to be the server
+    /// hostname, a verified tenant hostname or a host of a local domain.
+    pub async fn webauthn_relying_party(&self, host: &str) -> trc::Result<Option<RelyingParty>> {
+        let host = host.trim().trim_end_matches('.').to_lowercase();
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|ch| ch.is_ascii_digit()) => name,
+            _ => host.as_str(),
+        };
+
+        if host.is_empty() {
+            Ok(None)
+        } else if let Some(mapping) = self.verified_tenant_hostname(host) {
+            Ok(Some(RelyingParty {
+                id: host.to_string(),
+                tenant_id: Some(mapping.tenant_id),
+            }))
+        } else if host == self.core.network.server_name {
+            Ok(Some(RelyingParty {
+                id: host.to_string(),
+                tenant_id: None,
+            }))
+        } else {
+            Ok(self
+                .branding_domain(host)
+                .await?
+                .map(|(_, tenant_id)| RelyingParty {
+                    id: host.to_string(),
+                    tenant_id,
+                }))
+        }
+    }
+
+    /// Stores a new challenge for the relying party and returns its id.
+    pub async fn create_webauthn_challenge(
+        &self,
+        rp: &RelyingParty,
+        account_id: Option<u32>,
+        nickname: Option<String>,
+        resident: bool,
+    ) -> trc::Result<(String, WebAuthnChallenge)> {
+        let id = rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>();
+        let mut challenge = vec![0u8; WEBAUTHN_CHALLENGE_LEN];
+        rng().fill(&mut challenge[..]);
+        let challenge = WebAuthnChallenge {
+            challenge,
+            rp_id: rp.id.clone(),
+            tenant_id: rp.tenant_id,
+            account_id,
+            nickname,
+            resident,
+        };
+
+        self.core
+            .storage
+            .lookup
+            .key_set(
+                KeyValue::with_prefix(
+                    KV_WEBAUTHN_CHALLENGE,
+                    id.as_bytes(),
+                    serde_json::to_vec(&challenge).unwrap_or_default(),
+                )
+                .expires(WEBAUTHN_TIMEOUT),
+            )
+            .await?;
+
+        Ok((id, challenge))
+    }
+
+    /// Removes a challenge and returns it if it did not expire yet. Each
+    /// challenge can only be taken once, concurrent requests presenting the
+    /// same challenge id race for the lock and all but one get `None`.
+    pub async fn take_webauthn_challenge(
+        &self,
+        id: &str,
+    ) -> trc::Result<Option<WebAuthnChallenge>> {
+        if !self
+            .core
+            .storage
+            .lookup
+            .try_lock(KV_WEBAUTHN_CHALLENGE_USED, id.as_bytes(), WEBAUTHN_TIMEOUT)
+            .await?
+        {
+            return Ok(None);
+        }
+
+        let key = KeyValue::<()>::build_key(KV_WEBAUTHN_CHALLENGE, id.as_bytes());
+        let challenge = self
+            .core
+            .storage
+            .lookup
+            .key_get::<String>(key.clone())
+            .await?;
+        if challenge.is_some() {
+            self.core.storage.lookup.key_delete(key).await?;
+        }
+
+        Ok(challenge.and_then(|challenge| serde_json::from_str(&challenge).ok()))
+    }
+}
+
+/// Verifies the response of an authenticator to a registration ceremony.
+/// Attestation is not requested, so attestation statements are ignored.
+pub fn verify_registration(
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+    challenge: &[u8],
+    rp_id: &str,
+) -> Result<RegisteredCredential, String> {
+    verify_client_data(client_data_json, "webauthn.create", challenge, rp_id)?;
+
+    let attestation = CborReader::new(attestation_object).read_all()?;
+    let auth_data = attestation
+        .get_text("authData")
+        .and_then(|value| value.as_bytes())
+        .ok_or("Missing authenticator data")?;
+    let auth_data = AuthenticatorData::parse(auth_data)?;
+    auth_data.verify(rp_id)?;
+    let credential = auth_data
+        .credential
+        .ok_or("Missing attested credential data")?;
+    let key = CoseKey::parse(credential.public_key)?;
+
+    Ok(RegisteredCredential {
+        id: credential.id.to_vec(),
+        public_key: credential.public_key.to_vec(),
+        algorithm: key.algorithm,
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+/// Verifies the response of an authenticator to a login ceremony against
+/// the COSE public key of the credential.
+pub fn verify_assertion(
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    challenge: &[u8],
+    rp_id: &str,
+    public_key: &[u8],
+) -> Result<VerifiedAssertion, String> {
+    verify_client_data(client_data_json, "webauthn.get", challenge, rp_id)?;
+
+    let auth_data = AuthenticatorData::parse(authenticator_data)?;
+    auth_data.verify(rp_id)?;
+
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(&Sha256::digest(client_data_json));
+    CoseKey::parse(public_key)?.verify(&message, signature)?;
+
+    Ok(VerifiedAssertion {
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    typ: String,
+    challenge: String,
+    origin: String,
+    #[serde(default, rename = "crossOrigin")]
+    cross_origin: bool,
+}
+
+fn verify_client_data(
+    client_data_json: &[u8],
+    typ: &str,
+    challenge: &[u8],
+    rp_id: &str,
+) -> Result<(), String> {
+    let client_data = serde_json::from_slice::<ClientData>(client_data_json)
+        .map_err(|err| format!("Invalid client data: {err}"))?;
+    let origin_host = client_data
+        .origin
+        .strip_prefix("https://")
+        .map(|authority| match authority.rsplit_once(':') {
+            Some((host, port)) if port.bytes().all(|ch| ch.is_ascii_digit()) => host,
+            _ => authority,
+        });
+
+    if client_data.typ != typ {
+        Err(format!("Unexpected client data type {:?}", client_data.typ))
+    } else if decode_base64url(&client_data.challenge).as_deref() != Some(challenge) {
+        Err("Challenge mismatch".to_string())
+    } else if client_data.cross_origin {
+        Err("Cross-origin requests are not allowed".to_string())
+    } else if !origin_host.is_some_and(|host| host.eq_ignore_ascii_case(rp_id)) {
+        Err(format!("Unexpected origin {:?}", client_data.origin))
+    } else {
+        Ok(())
+    }
+}
+
+/// Decodes base64url with or without padding, as sent by browsers.
+pub fn decode_base64url(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+pub fn encode_base64url(value: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+#[derive(Debug)]
+struct AuthenticatorData<'x> {
+    rp_id_hash: &'x [u8],
+    flags: u8,
+    sign_count: u32,
+    credential: Option<AttestedCredential<'x>>,
+}
+
+#[derive(Debug)]
+struct AttestedCredential<'x> {
+    id: &'x [u8],
+    public_key: &'x [u8],
+}
+
+impl<'x> AuthenticatorData<'x> {
+    fn parse(bytes: &'x [u8]) -> Result<Self, String> {
+        if bytes.len() < 37 {
+            return Err("Authenticator data is too short".to_string());
+        }
+        let flags = bytes[32];
+        let sign_count = u32::from_be_bytes(bytes[33..37].try_into().unwrap());
+
+        let credential = if flags & FLAG_ATTESTED != 0 {
+            // AAGUID, credential id length and credential id
+            let data = &bytes[37..];
+            let id_len = data
+                .get(16..18)
+                .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                .ok_or("Attested credential data is too short")?;
+            let id = data
+                .get(18..18 + id_len)
+                .ok_or("Attested credential data is too short")?;
+            // The COSE key is followed by the extensions, if any
+            let key = &data[18 + id_len..];
+            let mut reader = CborReader::new(key);
+            reader.read(0)?;
+            Some(AttestedCredential {
+                id,
+                public_key: &key[..reader.pos],
+            })
+        } else {
+            None
+        };
+
+        Ok(AuthenticatorData {
+            rp_id_hash: &bytes[..32],
+            flags,
+            sign_count,
+            credential,
+        })
+    }
+
+    fn verify(&self, rp_id: &str) -> Result<(), String> {
+        if self.rp_id_hash != Sha256::digest(rp_id.as_bytes()).as_slice() {
+            Err("Relying party mismatch".to_string())
+        } else if self.flags & FLAG_USER_PRESENT == 0 {
+            Err("User presence is required".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CoseKey {
+    algorithm: i64,
+    public_key: CosePublicKey,
+}
+
+#[derive(Debug)]
+enum CosePublicKey {
+    Ec2(Vec<u8>),
+    Okp(Vec<u8>),
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl CoseKey {
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let key = CborReader::new(bytes).read_all()?;
+        let int = |label: i128| key.get_int(label).and_then(|value| value.as_int());
+        let bytes = |label: i128| {
+            key.get_int(label)
+                .and_then(|value| value.as_bytes())
+                .map(|value| value.to_vec())
+                .ok_or_else(|| format!("Missing COSE key parameter {label}"))
+        };
+
+        let algorithm = int(3).ok_or("Missing COSE algorithm")? as i64;
+        let public_key = match (algorithm, int(1), int(-1)) {
+            // EC2 key on P-256
+            (COSE_ES256, Some(2), Some(1)) => {
+                let (x, y) = (bytes(-2)?, bytes(-3)?);
+                if x.len() != 32 || y.len() != 32 {
+                    return Err("Invalid P-256 public key".to_string());
+                }
+                let mut point = Vec::with_capacity(65);
+                point.push(0x04);
+                point.extend_from_slice(&x);
+                point.extend_from_slice(&y);
+                CosePublicKey::Ec2(point)
+            }
+            // OKP key on Ed25519
+            (COSE_EDDSA, Some(1), Some(6)) => {
+                let x = bytes(-2)?;
+                if x.len() != 32 {
+                    return Err("Invalid Ed25519 public key".to_string());
+                }
+                CosePublicKey::Okp(x)
+            }
+            (COSE_RS256, Some(3), _) => CosePublicKey::Rsa {
+                n: bytes(-1)?,
+                e: bytes(-2)?,
+            },
+            _ => return Err(format!("Unsupported COSE algorithm {algorithm}")),
+        };
+
+        Ok(CoseKey {
+            algorithm,
+            public_key,
+        })
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), String> {
+        match &self.public_key {
+            CosePublicKey::Ec2(point) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+            }
+            CosePublicKey::Okp(key) => {
+                UnparsedPublicKey::new(&ED25519, key).verify(message, signature)
+            }
+            CosePublicKey::Rsa { n, e } => RsaPublicKeyComponents { n, e }.verify(
+                &RSA_PKCS1_2048_8192_SHA256,
+                message,
+                signature,
+            ),
+        }
+        .map_err(|_| "Invalid signature".to_string())
+    }
+}
+
+/// Subset of CBOR used by WebAuthn, indefinite lengths are not supported.
+#[derive(Debug, Clone, PartialEq)]
+enum Cbor<'x> {
+    Int(i128),
+    Bytes(&'x [u8]),
+    Text(&'x str),
+    Array(Vec<Cbor<'x>>),
+    Map(Vec<(Cbor<'x>, Cbor<'x>)>),
+    Simple,
+}
+
+impl<'x> Cbor<'x> {
+    fn get_text(&self, key: &str) -> Option<&Cbor<'x>> {
+        self.get(|k| matches!(k, Cbor::Text(text) if *text == key))
+    }
+
+    fn get_int(&self, key: i128) -> Option<&Cbor<'x>> {
+        self.get(|k| matches!(k, Cbor::Int(int) if *int == key))
+    }
+
+    fn get(&self, f: impl Fn(&Cbor<'x>) -> bool) -> Option<&Cbor<'x>> {
+        match self {
+            Cbor::Map(entries) => entries.iter().find(|(k, _)| f(k)).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_bytes(&self) -> Option<&'x [u8]> {
+        match self {
+            Cbor::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn as_int(&self) -> Option<i128> {
+        match self {
+            Cbor::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+}
+
+struct CborReader<'x> {
+    bytes: &'x [u8],
+    pos: usize,
+}
+
+impl<'x> CborReader<'x> {
+    fn new(bytes: &'x [u8]) -> Self {
+        CborReader { bytes, pos: 0 }
+    }
+
+    fn read_all(mut self) -> Result<Cbor<'x>, String> {
+        let value = self.read(0)?;
+        if self.pos == self.bytes.len() {
+            Ok(value)
+        } else {
+            Err("Trailing CBOR data".to_string())
+        }
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'x [u8], String> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Truncated CBOR data")?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read(&mut self, depth: usize) -> Result<Cbor<'x>, String> {
+        if depth > MAX_CBOR_DEPTH {
+            return Err("CBOR data is nested too deeply".to_string());
+        }
+
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let arg = match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            _ => return Err("Unsupported CBOR length".to_string()),
+        };
+
+        // Every item takes at least one byte, which bounds allocations
+        let remaining = (self.bytes.len() - self.pos) as u64;
+        match major {
+            0 => Ok(Cbor::Int(arg as i128)),
+            1 => Ok(Cbor::Int(-1 - arg as i128)),
+            2 => self.take(arg).map(Cbor::Bytes),
+            3 => std::str::from_utf8(self.take(arg)?)
+                .map(Cbor::Text)
+                .map_err(|_| "Invalid CBOR text".to_string()),
+            4 if arg <= remaining => (0..arg)
+                .map(|_| self.read(depth + 1))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Cbor::Array),
+            5 if arg <= remaining / 2 => (0..arg)
+                .map(|_| Ok((self.read(depth + 1)?, self.read(depth + 1)?)))
+                .collect::<Result<Vec<_>, String>>()
+                .map(Cbor::Map),
+            // Tags are ignored
+            6 => self.read(depth + 1),
+            7 => Ok(Cbor::Simple),
+            _ => Err("Truncated CBOR data".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+
+    fn cbor_head(major: u8, len: usize, out: &mut Vec<u8>) {
+        if len < 24 {
+            out.push((major << 5) | len as u8);
+        } else if len < 256 {
+            out.extend([(major << 5) | 24, len as u8]);
+        } else {
+            out.push((major << 5) | 25);
+            out.extend((len as u16).to_be_bytes());
+        }
+    }
+
+    fn cbor_int(value: i64, out: &mut Vec<u8>) {
+        if value >= 0 {
+            cbor_head(0, value as usize, out);
+        } else {
+            cbor_head(1, (-1 - value) as usize, out);
+        }
+    }
+
+    fn cbor_bytes(value: &[u8], out: &mut Vec<u8>) {
+        cbor_head(2, value.len(), out);
+        out.extend_from_slice(value);
+    }
+
+    fn cbor_text(value: &str, out: &mut Vec<u8>) {
+        cbor_head(3, value.len(), out);
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    fn cose_key(point: &[u8]) -> Vec<u8> {
+        let mut key = Vec::new();
+        cbor_head(5, 5, &mut key);
+        for (label, value) in [(1, 2), (3, COSE_ES256), (-1, 1)] {
+            cbor_int(label, &mut key);
+            cbor_int(value, &mut key);
+        }
+        cbor_int(-2, &mut key);
+        cbor_bytes(&point[1..33], &mut key);
+        cbor_int(-3, &mut key);
+        cbor_bytes(&point[33..65], &mut key);
+        key
+    }
+
+    fn auth_data(rp_id: &str, flags: u8, sign_count: u32, credential: Option<&[u8]>) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend(sign_count.to_be_bytes());
+        if let Some(key) = credential {
+            data.extend([0u8; 16]);
+            data.extend(4u16.to_be_bytes());
+            data.extend(b"cred");
+            data.extend_from_slice(key);
+        }
+        data
+    }
+
+    fn client_data(typ: &str, challenge: &[u8], origin: &str) -> Vec<u8> {
+        serde_json::json!({
+            "type": typ,
+            "challenge": encode_base64url(challenge),
+            "origin": origin,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn webauthn_ceremonies() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        let public_key = cose_key(key_pair.public_key().as_ref());
+        let challenge = [7u8; WEBAUTHN_CHALLENGE_LEN];
+
+        // Registration with a "none" attestation
+        let mut attestation = Vec::new();
+        cbor_head(5, 3, &mut attestation);
+        cbor_text("fmt", &mut attestation);
+        cbor_text("none", &mut attestation);
+        cbor_text("attStmt", &mut attestation);
+        cbor_head(5, 0, &mut attestation);
+        cbor_text("authData", &mut attestation);
+        cbor_bytes(
+            &auth_data(
+                "mail.example.org",
+                FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED,
+                0,
+                Some(&public_key),
+            ),
+            &mut attestation,
+        );
+        let client_data_json = client_data(
+            "webauthn.create",
+            &challenge,
+            "https://mail.example.org:8443",
+        );
+        let credential = verify_registration(
+            &client_data_json,
+            &attestation,
+            &challenge,
+            "mail.example.org",
+        )
+        .unwrap();
+        assert_eq!(credential.id, b"cred");
+        assert_eq!(credential.public_key, public_key);
+        assert_eq!(credential.algorithm, COSE_ES256);
+        assert!(credential.user_verified);
+        let mut trailing = attestation.clone();
+        trailing.push(0);
+        assert!(
+            verify_registration(&client_data_json, &trailing, &challenge, "mail.example.org")
+                .is_err()
+        );
+
+        // Ceremonies are bound to the challenge, origin and relying party
+        for (client_data_json, rp_id) in [
+            (
+                client_data("webauthn.create", &[0u8; 32], "https://mail.example.org"),
+                "mail.example.org",
+            ),
+            (
+                client_data("webauthn.create", &challenge, "https://evil.example.org"),
+                "mail.example.org",
+            ),
+            (
+                client_data("webauthn.create", &challenge, "http://mail.example.org"),
+                "mail.example.org",
+            ),
+            (
+                client_data("webauthn.get", &challenge, "https://mail.example.org"),
+                "mail.example.org",
+            ),
+            (
+                client_data("webauthn.create", &challenge, "https://example.org"),
+                "example.org",
+            ),
+        ] {
+            assert!(
+                verify_registration(&client_data_json, &attestation, &challenge, rp_id).is_err(),
+                "{}",
+                String::from_utf8_lossy(&client_data_json)
+            );
+        }
+
+        // Assertions are signed over the authenticator data and client data hash
+        let authenticator_data = auth_data("mail.example.org", FLAG_USER_PRESENT, 5, None);
+        let client_data_json = client_data("webauthn.get", &challenge, "https://mail.example.org");
+        let mut message = authenticator_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data_json));
+        let signature = key_pair.sign(&rng, &message).unwrap();
+        assert_eq!(
+            verify_assertion(
+                &client_data_json,
+                &authenticator_data,
+                signature.as_ref(),
+                &challenge,
+                "mail.example.org",
+                &public_key,
+            ),
+            Ok(VerifiedAssertion {
+                sign_count: 5,
+                user_verified: false,
+            })
+        );
+        let mut tampered = authenticator_data.clone();
+        tampered[36] = 6;
+        assert!(
+            verify_assertion(
+                &client_data_json,
+                &tampered,
+                signature.as_ref(),
+                &challenge,
+                "mail.example.org",
+                &public_key,
+            )
+            .is_err()
+        );
+        let no_presence = auth_data("mail.example.org", 0, 5, None);
+        assert!(
+            verify_assertion(
+                &client_data_json,
+                &no_presence,
+                signature.as_ref(),
+                &challenge,
+                "mail.example.org",
+                &public_key,
+            )
+            .is_err()
+        );
+
+        // Malformed CBOR is rejected
+        for invalid in [
+            &[0x5f][..],
+            &[0xa1, 0x01][..],
+            &[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff][..],
+            &[0x81; 32][..],
+        ] {
+            assert!(CborReader::new(invalid).read_all().is_err(), "{invalid:?}");
+            assert!(CoseKey::parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+}
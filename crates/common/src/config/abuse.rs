@@ -0,0 +1,198 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{str::FromStr, time::Duration};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hyper::{
+    HeaderMap,
+    header::{AUTHORIZATION, CONTENT_TYPE, HeaderName, HeaderValue},
+};
+use utils::config::{Config, utils::ParseValue};
+
+use crate::expr::{if_block::IfBlock, tokenizer::TokenMap};
+
+pub const V_ABUSE_SOURCE: u32 = 0;
+pub const V_ABUSE_REMOTE_IP: u32 = 1;
+pub const V_ABUSE_DOMAIN: u32 = 2;
+pub const V_ABUSE_TENANT_NAME: u32 = 3;
+pub const V_ABUSE_ADMIN_EMAIL: u32 = 4;
+
+pub const ABUSE_CHECK_VARS: &[(&str, u32)] = &[
+    ("source", V_ABUSE_SOURCE),
+    ("remote_ip", V_ABUSE_REMOTE_IP),
+    ("domain", V_ABUSE_DOMAIN),
+    ("tenant_name", V_ABUSE_TENANT_NAME),
+    ("admin_email", V_ABUSE_ADMIN_EMAIL),
+];
+
+/// Check run on organizations requested through the public signup and
+/// invitation endpoints before anything is provisioned.
+#[derive(Debug, Clone)]
+pub struct ProvisioningCheck {
+    pub method: ProvisioningCheckMethod,
+    /// Verdict used when the check fails or does not return a verdict.
+    pub default: ProvisioningVerdict,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProvisioningCheckMethod {
+    Http(ProvisioningHook),
+    Expression(IfBlock),
+}
+
+#[derive(Debug, Clone)]
+pub struct ProvisioningHook {
+    pub url: String,
+    pub headers: HeaderMap,
+    pub timeout: Duration,
+    pub tls_allow_invalid_certs: bool,
+    pub max_response_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProvisioningVerdict {
+    Allow,
+    Review,
+    Deny,
+}
+
+impl ProvisioningCheck {
+    pub fn parse(config: &mut Config) -> Option<Self> {
+        let method = if let Some(url) = config.value("organization.abuse-check.url") {
+            let url = url.to_string();
+            let mut headers = HeaderMap::new();
+
+            for (header, value) in config
+                .values("organization.abuse-check.headers")
+                .map(|(_, v)| {
+                    v.split_once(':')
+                        .and_then(|(k, v)| {
+                            Some((
+                                HeaderName::from_str(k.trim()).ok()?,
+                                HeaderValue::from_str(v.trim()).ok()?,
+                            ))
+                        })
+                        .ok_or_else(|| {
+                            format!(
+                                "Invalid header found in property \"organization.abuse-check.headers\": {v}",
+                            )
+                        })
+                })
+                .collect::<Result<Vec<(HeaderName, HeaderValue)>, String>>()
+                .map_err(|e| config.new_parse_error("organization.abuse-check.headers", e))
+                .unwrap_or_default()
+            {
+                headers.insert(header, value);
+            }
+
+            headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            if let (Some(name), Some(secret)) = (
+                config.value("organization.abuse-check.auth.username"),
+                config.value("organization.abuse-check.auth.secret"),
+            ) {
+                headers.insert(
+                    AUTHORIZATION,
+                    format!("Basic {}", STANDARD.encode(format!("{}:{}", name, secret)))
+                        .parse()
+                        .unwrap(),
+                );
+            }
+
+            ProvisioningCheckMethod::Http(ProvisioningHook {
+                url,
+                headers,
+                timeout: config
+                    .property_or_default("organization.abuse-check.timeout", "5s")
+                    .unwrap_or_else(|| Duration::from_secs(5)),
+                tls_allow_invalid_certs: config
+                    .property_or_default("organization.abuse-check.allow-invalid-certs", "false")
+                    .unwrap_or_default(),
+                max_response_size: config
+                    .property_or_default("organization.abuse-check.max-response-size", "65536")
+                    .unwrap_or(65536),
+            })
+        } else {
+            ProvisioningCheckMethod::Expression(IfBlock::try_parse(
+                config,
+                "organization.abuse-check.expr",
+                &TokenMap::default().with_variables_map(ABUSE_CHECK_VARS.iter().copied()),
+            )?)
+        };
+
+        Some(ProvisioningCheck {
+            method,
+            default: config
+                .property_or_default("organization.abuse-check.default", "review")
+                .unwrap_or(ProvisioningVerdict::Review),
+        })
+    }
+}
+
+impl ProvisioningVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProvisioningVerdict::Allow => "allow",
+            ProvisioningVerdict::Review => "review",
+            ProvisioningVerdict::Deny => "deny",
+        }
+    }
+}
+
+impl FromStr for ProvisioningVerdict {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "allow" => Ok(ProvisioningVerdict::Allow),
+            "review" | "require-manual-review" => Ok(ProvisioningVerdict::Review),
+            "deny" => Ok(ProvisioningVerdict::Deny),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ParseValue for ProvisioningVerdict {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        ProvisioningVerdict::from_str(value).map_err(|_| format!("Invalid verdict {value:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProvisioningCheck, ProvisioningCheckMethod, ProvisioningVerdict};
+    use std::{str::FromStr, time::Duration};
+    use utils::config::Config;
+
+    #[test]
+    fn provisioning_check() {
+        let mut config = Config::new(
+            r#"
+[organization.abuse-check]
+url = "https://127.0.0.1/check"
+headers = ["X-Key: secret"]
+timeout = "2s"
+default = "deny"
+"#,
+        )
+        .unwrap();
+        let check = ProvisioningCheck::parse(&mut config).unwrap();
+        assert_eq!(check.default, ProvisioningVerdict::Deny);
+        let ProvisioningCheckMethod::Http(hook) = &check.method else {
+            panic!("Expected HTTP check");
+        };
+        assert_eq!(hook.timeout, Duration::from_secs(2));
+        assert_eq!(hook.headers.get("x-key").unwrap(), "secret");
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+        assert!(ProvisioningCheck::parse(&mut Config::default()).is_none());
+        assert_eq!(
+            ProvisioningVerdict::from_str("Require-Manual-Review"),
+            Ok(ProvisioningVerdict::Review)
+        );
+        assert!(ProvisioningVerdict::from_str("maybe").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+pub const TENANT_ACTIVATION_KEY: &str = "tenant.activation";
+
+/// Activation pending on a tenant provisioned with admin email
+/// verification. Until its contact confirms it the tenant's users can't log
+/// in and mail for its domains is deferred, and once it expires the tenant
+/// is purged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantActivation {
+    pub requested_at: u64,
+    pub expires_at: u64,
+    pub contact: String,
+    /// Must match the nonce of the activation token, a new request
+    /// invalidates the tokens sent before it.
+    #[serde(skip)]
+    pub nonce: u64,
+}
+
+impl TenantActivation {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantActivation>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_ACTIVATION_KEY, ".requested-at") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_ACTIVATION_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_ACTIVATION_KEY}.{tenant_id}");
+            let (Some(requested_at), Some(expires_at), Some(nonce)) = (
+                config.property_require::<u64>((prefix.as_str(), "requested-at")),
+                config.property_require::<u64>((prefix.as_str(), "expires-at")),
+                config.property_require::<u64>((prefix.as_str(), "nonce")),
+            ) else {
+                continue;
+            };
+            tenants.insert(
+                tenant_id,
+                Arc::new(TenantActivation {
+                    requested_at,
+                    expires_at,
+                    contact: config
+                        .value((prefix.as_str(), "contact"))
+                        .unwrap_or_default()
+                        .to_string(),
+                    nonce,
+                }),
+            );
+        }
+
+        tenants
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_ACTIVATION_KEY}.{tenant_id}");
+        vec![
+            ConfigKey {
+                key: format!("{prefix}.requested-at"),
+                value: self.requested_at.to_string(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.expires-at"),
+                value: self.expires_at.to_string(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.contact"),
+                value: self.contact.clone(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.nonce"),
+                value: self.nonce.to_string(),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TenantActivation;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_activation() {
+        let activation = TenantActivation {
+            requested_at: 1000,
+            expires_at: 2000,
+            contact: "jerry@remote.org".to_string(),
+            nonce: 1234,
+        };
+        let mut config = Config {
+            keys: activation
+                .config_keys(3)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .chain([(
+                    "tenant.activation.4.requested-at".to_string(),
+                    "1500".to_string(),
+                )])
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantActivation::parse_all(&mut config);
+        assert_eq!(tenants[&3].as_ref(), &activation);
+        assert!(!tenants.contains_key(&4));
+        assert!(!config.errors.is_empty());
+
+        assert!(!tenants[&3].is_expired(1999));
+        assert!(tenants[&3].is_expired(2000));
+    }
+}
@@ -0,0 +1,610 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{str::FromStr, sync::LazyLock};
+
+use directory::Principal;
+use utils::template::{Template, Variables};
+
+use super::login_page::LoginPage;
+
+pub const DEFAULT_PRIMARY_COLOR: &str = "#1d4ed8";
+pub const DEFAULT_ACCENT_COLOR: &str = "#64748b";
+pub const DEFAULT_BACKGROUND_COLOR: &str = "#f1f5f9";
+pub const DEFAULT_TEXT_COLOR: &str = "#0f172a";
+pub const DEFAULT_FONT_FAMILY: &str = "Helvetica, Arial, sans-serif";
+pub const LIGHT_SURFACE_COLOR: &str = "#ffffff";
+pub const DARK_SURFACE_COLOR: &str = "#1e293b";
+pub const MAX_FONT_FAMILY_LEN: usize = 128;
+pub const MAX_LOGO_URL_LEN: usize = 2048;
+
+static LOGIN_TEMPLATE: LazyLock<Template<BrandTemplateVariable>> = LazyLock::new(|| {
+    Template::parse(include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../resources/html-templates/brand-login.html"
+    )))
+    .expect("Failed to parse login template")
+});
+
+static EMAIL_TEMPLATE: LazyLock<Template<BrandTemplateVariable>> = LazyLock::new(|| {
+    Template::parse(include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../resources/html-templates/brand-email.html"
+    )))
+    .expect("Failed to parse notification template")
+});
+
+/// Colors and fonts of a tenant, stored as JSON in its `brandTheme` field.
+/// Keys that the server does not use when rendering are ignored.
+///
+/// The colors at the top level are those of single palette themes and are
+/// used as the light variant, the `light` and `dark` palettes replace them
+/// for each variant. Without a dark palette one is derived from the light
+/// colors.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrandTheme {
+    #[serde(flatten)]
+    pub palette: BrandPalette,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_family: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<BrandPalette>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark: Option<BrandPalette>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrandPalette {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accent_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_color: Option<String>,
+}
+
+/// Branding of a tenant as displayed on the login page and in the
+/// notifications sent on its behalf.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Branding {
+    pub name: String,
+    pub logo_url: Option<String>,
+    /// Logo displayed on dark backgrounds, the primary logo is used when
+    /// there is none.
+    pub logo_dark_url: Option<String>,
+    pub theme: BrandTheme,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrandComponent {
+    Login,
+    Email,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BrandVariant {
+    #[default]
+    Light,
+    Dark,
+}
+
+/// Text of a notification rendered with the branded email template.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrandNotification<'x> {
+    pub title: &'x str,
+    pub message: &'x str,
+    pub action: Option<(&'x str, &'x str)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BrandTemplateVariable {
+    PageTitle,
+    BrandName,
+    LogoUrl,
+    Heading,
+    Message,
+    ActionUrl,
+    ActionName,
+    ColorScheme,
+    PrimaryColor,
+    AccentColor,
+    BackgroundColor,
+    SurfaceColor,
+    TextColor,
+    FontFamily,
+    Subtitle,
+    SupportUrl,
+    TermsUrl,
+    PrivacyUrl,
+    CustomCss,
+}
+
+impl BrandTheme {
+    /// Parses a stored theme, values that are not safe to render are
+    /// dropped so the defaults are used instead.
+    pub fn parse(value: &str) -> Self {
+        let mut theme = serde_json::from_str::<BrandTheme>(value).unwrap_or_default();
+        for palette in theme.palettes_mut() {
+            for color in palette.colors_mut() {
+                if color.as_deref().is_some_and(|value| !is_valid_color(value)) {
+                    *color = None;
+                }
+            }
+        }
+        if theme
+            .font_family
+            .as_deref()
+            .is_some_and(|value| !is_valid_font_family(value))
+        {
+            theme.font_family = None;
+        }
+        theme
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (palette, prefix) in [
+            (Some(&self.palette), ""),
+            (self.light.as_ref(), "light."),
+            (self.dark.as_ref(), "dark."),
+        ] {
+            if let Some(palette) = palette {
+                palette.validate(prefix)?;
+            }
+        }
+        if let Some(font_family) = &self.font_family
+            && !is_valid_font_family(font_family)
+        {
+            return Err(format!(
+                "Invalid fontFamily {font_family:?}, only letters, digits, spaces, commas and hyphens are allowed"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the values that are set in `other`.
+    pub fn merge(&mut self, other: BrandTheme) {
+        self.palette.merge(other.palette);
+        for (palette, other) in [(&mut self.light, other.light), (&mut self.dark, other.dark)] {
+            if let Some(other) = other {
+                palette.get_or_insert_default().merge(other);
+            }
+        }
+        if other.font_family.is_some() {
+            self.font_family = other.font_family;
+        }
+    }
+
+    /// Colors of a variant with the defaults filled in.
+    pub fn resolve(&self, variant: BrandVariant) -> BrandPalette {
+        let mut light = BrandPalette {
+            primary_color: Some(DEFAULT_PRIMARY_COLOR.into()),
+            accent_color: Some(DEFAULT_ACCENT_COLOR.into()),
+            background_color: Some(DEFAULT_BACKGROUND_COLOR.into()),
+            text_color: Some(DEFAULT_TEXT_COLOR.into()),
+        };
+        light.merge(self.palette.clone());
+        if let Some(palette) = &self.light {
+            light.merge(palette.clone());
+        }
+
+        match variant {
+            BrandVariant::Light => light,
+            BrandVariant::Dark => {
+                // Brand colors are kept while the background and text
+                // colors of the light variant are swapped
+                let mut dark = BrandPalette {
+                    background_color: light.text_color.take(),
+                    text_color: light.background_color.take(),
+                    ..light
+                };
+                if let Some(palette) = &self.dark {
+                    dark.merge(palette.clone());
+                }
+                dark
+            }
+        }
+    }
+
+    fn palettes_mut(&mut self) -> impl Iterator<Item = &mut BrandPalette> {
+        [
+            Some(&mut self.palette),
+            self.light.as_mut(),
+            self.dark.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+impl BrandPalette {
+    fn validate(&self, prefix: &str) -> Result<(), String> {
+        for (color, name) in [
+            (&self.primary_color, "primaryColor"),
+            (&self.accent_color, "accentColor"),
+            (&self.background_color, "backgroundColor"),
+            (&self.text_color, "textColor"),
+        ] {
+            if let Some(color) = color
+                && !is_valid_color(color)
+            {
+                return Err(format!(
+                    "Invalid {prefix}{name} {color:?}, expected a hexadecimal color such as #1d4ed8"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge(&mut self, other: BrandPalette) {
+        for (value, other) in self.colors_mut().into_iter().zip([
+            other.primary_color,
+            other.accent_color,
+            other.background_color,
+            other.text_color,
+        ]) {
+            if other.is_some() {
+                *value = other;
+            }
+        }
+    }
+
+    fn colors_mut(&mut self) -> [&mut Option<String>; 4] {
+        [
+            &mut self.primary_color,
+            &mut self.accent_color,
+            &mut self.background_color,
+            &mut self.text_color,
+        ]
+    }
+}
+
+impl Branding {
+    /// Branding of a tenant, which uses its name when no brand name is set.
+    pub fn from_principal(tenant: &Principal) -> Self {
+        Branding {
+            name: tenant.brand_name().unwrap_or(tenant.name()).to_string(),
+            logo_url: tenant.brand_logo_url().map(|url| url.to_string()),
+            logo_dark_url: tenant.brand_logo_dark_url().map(|url| url.to_string()),
+            theme: tenant
+                .brand_theme()
+                .map(BrandTheme::parse)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn logo(&self, variant: BrandVariant) -> Option<&str> {
+        match variant {
+            BrandVariant::Light => self.logo_url.as_deref(),
+            BrandVariant::Dark => self.logo_dark_url.as_deref().or(self.logo_url.as_deref()),
+        }
+        .filter(|url| is_valid_logo_url(url))
+    }
+
+    /// Renders the login page with the branding and the texts and links of
+    /// a custom login page, the page is self-contained and does not submit
+    /// anywhere. Custom styles must have been sanitized.
+    pub fn render_login(&self, variant: BrandVariant, page: Option<&LoginPage>) -> String {
+        let palette = self.theme.resolve(variant);
+        let mut variables = self.variables(&palette, variant);
+        let title = page
+            .and_then(|page| page.title.as_deref())
+            .unwrap_or(self.name.as_str());
+        variables.insert_single(BrandTemplateVariable::PageTitle, title);
+        variables.insert_single(BrandTemplateVariable::Heading, title);
+        if let Some(page) = page {
+            for (variable, value) in [
+                (BrandTemplateVariable::Subtitle, &page.subtitle),
+                (BrandTemplateVariable::SupportUrl, &page.support_url),
+                (BrandTemplateVariable::TermsUrl, &page.terms_url),
+                (BrandTemplateVariable::PrivacyUrl, &page.privacy_url),
+                (BrandTemplateVariable::CustomCss, &page.css),
+            ] {
+                if let Some(value) = value {
+                    variables.insert_single(variable, value.as_str());
+                }
+            }
+        }
+        LOGIN_TEMPLATE.eval(&variables)
+    }
+
+    pub fn render_email(
+        &self,
+        variant: BrandVariant,
+        notification: &BrandNotification<'_>,
+    ) -> String {
+        let palette = self.theme.resolve(variant);
+        let mut variables = self.variables(&palette, variant);
+        variables.insert_single(BrandTemplateVariable::PageTitle, notification.title);
+        variables.insert_single(BrandTemplateVariable::Heading, notification.title);
+        variables.insert_single(BrandTemplateVariable::Message, notification.message);
+        if let Some((name, url)) = notification.action {
+            variables.insert_single(BrandTemplateVariable::ActionName, name);
+            variables.insert_single(BrandTemplateVariable::ActionUrl, url);
+        }
+        EMAIL_TEMPLATE.eval(&variables)
+    }
+
+    /// Text, URLs and the brand name are escaped by the template, colors and
+    /// fonts are written into style rules and must have been validated.
+    fn variables<'x>(
+        &'x self,
+        palette: &'x BrandPalette,
+        variant: BrandVariant,
+    ) -> Variables<BrandTemplateVariable, &'x str> {
+        let mut variables = Variables::new();
+        variables.insert_single(BrandTemplateVariable::BrandName, self.name.as_str());
+        if let Some(logo_url) = self.logo(variant) {
+            variables.insert_single(BrandTemplateVariable::LogoUrl, logo_url);
+        }
+        variables.insert_single(BrandTemplateVariable::ColorScheme, variant.as_str());
+        variables.insert_single(
+            BrandTemplateVariable::SurfaceColor,
+            match variant {
+                BrandVariant::Light => LIGHT_SURFACE_COLOR,
+                BrandVariant::Dark => DARK_SURFACE_COLOR,
+            },
+        );
+        for (variable, value, default) in [
+            (
+                BrandTemplateVariable::PrimaryColor,
+                &palette.primary_color,
+                DEFAULT_PRIMARY_COLOR,
+            ),
+            (
+                BrandTemplateVariable::AccentColor,
+                &palette.accent_color,
+                DEFAULT_ACCENT_COLOR,
+            ),
+            (
+                BrandTemplateVariable::BackgroundColor,
+                &palette.background_color,
+                DEFAULT_BACKGROUND_COLOR,
+            ),
+            (
+                BrandTemplateVariable::TextColor,
+                &palette.text_color,
+                DEFAULT_TEXT_COLOR,
+            ),
+        ] {
+            variables.insert_single(
+                variable,
+                value
+                    .as_deref()
+                    .filter(|value| is_valid_color(value))
+                    .unwrap_or(default),
+            );
+        }
+        variables.insert_single(
+            BrandTemplateVariable::FontFamily,
+            self.theme
+                .font_family
+                .as_deref()
+                .filter(|value| is_valid_font_family(value))
+                .unwrap_or(DEFAULT_FONT_FAMILY),
+        );
+        variables
+    }
+}
+
+impl BrandComponent {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "login" => Some(BrandComponent::Login),
+            "email" => Some(BrandComponent::Email),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BrandComponent::Login => "login",
+            BrandComponent::Email => "email",
+        }
+    }
+}
+
+impl BrandVariant {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().trim_matches('"') {
+            "light" => Some(BrandVariant::Light),
+            "dark" => Some(BrandVariant::Dark),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BrandVariant::Light => "light",
+            BrandVariant::Dark => "dark",
+        }
+    }
+}
+
+impl FromStr for BrandTemplateVariable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "page_title" => Ok(BrandTemplateVariable::PageTitle),
+            "brand_name" => Ok(BrandTemplateVariable::BrandName),
+            "logo_url" => Ok(BrandTemplateVariable::LogoUrl),
+            "heading" => Ok(BrandTemplateVariable::Heading),
+            "message" => Ok(BrandTemplateVariable::Message),
+            "action_url" => Ok(BrandTemplateVariable::ActionUrl),
+            "action_name" => Ok(BrandTemplateVariable::ActionName),
+            "color_scheme" => Ok(BrandTemplateVariable::ColorScheme),
+            "primary_color" => Ok(BrandTemplateVariable::PrimaryColor),
+            "accent_color" => Ok(BrandTemplateVariable::AccentColor),
+            "background_color" => Ok(BrandTemplateVariable::BackgroundColor),
+            "surface_color" => Ok(BrandTemplateVariable::SurfaceColor),
+            "text_color" => Ok(BrandTemplateVariable::TextColor),
+            "font_family" => Ok(BrandTemplateVariable::FontFamily),
+            "subtitle" => Ok(BrandTemplateVariable::Subtitle),
+            "support_url" => Ok(BrandTemplateVariable::SupportUrl),
+            "terms_url" => Ok(BrandTemplateVariable::TermsUrl),
+            "privacy_url" => Ok(BrandTemplateVariable::PrivacyUrl),
+            "custom_css" => Ok(BrandTemplateVariable::CustomCss),
+            _ => Err(format!("Unknown brand template variable: {}", s)),
+        }
+    }
+}
+
+/// Logos are fetched by the browser, only HTTP and HTTPS URLs are accepted.
+pub fn is_valid_logo_url(value: &str) -> bool {
+    value.len() <= MAX_LOGO_URL_LEN
+        && (value.starts_with("https://") || value.starts_with("http://"))
+        && !value
+            .chars()
+            .any(|ch| ch.is_whitespace() || ch.is_control())
+}
+
+fn is_valid_color(value: &str) -> bool {
+    value.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 6 | 8) && hex.bytes().all(|ch| ch.is_ascii_hexdigit())
+    })
+}
+
+fn is_valid_font_family(value: &str) -> bool {
+    !value.trim().is_empty()
+        && value.len() <= MAX_FONT_FAMILY_LEN
+        && value
+            .bytes()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b' ' | b',' | b'-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BrandNotification, BrandPalette, BrandTheme, BrandVariant, Branding,
+        DEFAULT_BACKGROUND_COLOR, DEFAULT_PRIMARY_COLOR, DEFAULT_TEXT_COLOR,
+    };
+
+    #[test]
+    fn brand_preview() {
+        // Unsafe values of a stored theme fall back to the defaults
+        let theme = BrandTheme::parse(
+            r##"{"primaryColor": "red;}</style><script>", "accentColor": "#ABC", "fontFamily": "Inter, sans-serif", "logo": "x"}"##,
+        );
+        assert_eq!(theme.palette.primary_color, None);
+        assert_eq!(theme.palette.accent_color.as_deref(), Some("#ABC"));
+        assert_eq!(theme.font_family.as_deref(), Some("Inter, sans-serif"));
+        assert!(
+            BrandTheme {
+                font_family: Some("x;}body{".into()),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+
+        // Candidate values replace the current ones
+        let mut merged = theme.clone();
+        merged.merge(BrandTheme {
+            palette: BrandPalette {
+                accent_color: Some("#00ff00".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(merged.palette.accent_color.as_deref(), Some("#00ff00"));
+        assert_eq!(merged.font_family, theme.font_family);
+
+        // User provided text is escaped
+        let branding = Branding {
+            name: "Acme <b>& Sons</b>".into(),
+            logo_url: Some("javascript:alert(1)".into()),
+            logo_dark_url: None,
+            theme: merged,
+        };
+        let login = branding.render_login(BrandVariant::Light, None);
+        assert!(
+            login.contains("Acme &lt;b&gt;&amp; Sons&lt;/b&gt;"),
+            "{login}"
+        );
+        assert!(login.contains(DEFAULT_PRIMARY_COLOR), "{login}");
+        assert!(login.contains("color: #00ff00"), "{login}");
+        assert!(!login.contains("javascript:"), "{login}");
+        let email = Branding {
+            logo_url: Some("https://acme.org/logo.png?a=1&b=\"2\"".into()),
+            ..branding
+        }
+        .render_email(
+            BrandVariant::Light,
+            &BrandNotification {
+                title: "Welcome",
+                message: "<script>",
+                action: Some(("Open", "https://acme.org/")),
+            },
+        );
+        assert!(email.contains("&lt;script&gt;"), "{email}");
+        assert!(
+            email.contains("https://acme.org/logo.png?a=1&amp;b=&quot;2&quot;"),
+            "{email}"
+        );
+        assert!(email.contains(">Open</a>"), "{email}");
+        assert!(email.contains("font-family:Inter, sans-serif"), "{email}");
+    }
+
+    #[test]
+    fn brand_variants() {
+        // Single palette themes are the light variant, the dark one is derived
+        let theme = BrandTheme::parse(r##"{"primaryColor": "#ff5733", "textColor": "#111111"}"##);
+        let light = theme.resolve(BrandVariant::Light);
+        assert_eq!(light.primary_color.as_deref(), Some("#ff5733"));
+        assert_eq!(light.text_color.as_deref(), Some("#111111"));
+        let dark = theme.resolve(BrandVariant::Dark);
+        assert_eq!(dark.primary_color.as_deref(), Some("#ff5733"));
+        assert_eq!(dark.background_color.as_deref(), Some("#111111"));
+        assert_eq!(dark.text_color.as_deref(), Some(DEFAULT_BACKGROUND_COLOR));
+
+        // Explicit palettes take precedence
+        let theme = BrandTheme::parse(
+            r##"{"primaryColor": "#ff5733",
+                 "light": {"backgroundColor": "#fafafa"},
+                 "dark": {"primaryColor": "#ffaa88", "backgroundColor": "bad"}}"##,
+        );
+        let light = theme.resolve(BrandVariant::Light);
+        assert_eq!(light.background_color.as_deref(), Some("#fafafa"));
+        assert_eq!(light.text_color.as_deref(), Some(DEFAULT_TEXT_COLOR));
+        let dark = theme.resolve(BrandVariant::Dark);
+        assert_eq!(dark.primary_color.as_deref(), Some("#ffaa88"));
+        assert_eq!(dark.background_color.as_deref(), Some(DEFAULT_TEXT_COLOR));
+        assert!(
+            BrandTheme {
+                dark: Some(BrandPalette {
+                    text_color: Some("white".into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+
+        // The dark logo falls back to the primary one
+        let mut branding = Branding {
+            name: "Acme".into(),
+            logo_url: Some("https://acme.org/logo.png".into()),
+            logo_dark_url: None,
+            theme,
+        };
+        assert_eq!(
+            branding.logo(BrandVariant::Dark),
+            Some("https://acme.org/logo.png")
+        );
+        branding.logo_dark_url = Some("https://acme.org/logo-dark.png".into());
+        let login = branding.render_login(BrandVariant::Dark, None);
+        assert!(login.contains("logo-dark.png"), "{login}");
+        assert!(login.contains("color-scheme: dark"), "{login}");
+        assert!(login.contains("#ffaa88"), "{login}");
+    }
+}
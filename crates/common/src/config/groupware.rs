@@ -4,9 +4,16 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
-use utils::{config::Config, template::Template};
+use ahash::AHashMap;
+use utils::{
+    config::{Config, ConfigKey, utils::ParseValue},
+    template::Template,
+};
+
+pub const RESOURCE_KEY: &str = "calendar.resource";
+pub const TENANT_COLLECTIONS_KEY: &str = "groupware.tenant";
 
 #[derive(Debug, Clone, Default)]
 pub struct GroupwareConfig {
@@ -226,3 +233,368 @@ impl FromStr for CalendarTemplateVariable {
         }
     }
 }
+
+/// Room or piece of equipment of a tenant that can be invited to events.
+/// Its calendar answers scheduling requests on its own, following the
+/// booking policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookableResource {
+    pub tenant_id: u32,
+    pub kind: ResourceKind,
+    pub capacity: Option<u32>,
+    pub location: Option<String>,
+    pub policy: BookingPolicy,
+    /// Account that accepts or declines bookings on behalf of the resource.
+    pub approver_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResourceKind {
+    Room,
+    Equipment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BookingPolicy {
+    /// Bookings are accepted when the slot is free.
+    AutoAccept,
+    /// Bookings are sent to the approver, conflicts are still declined.
+    RequireApproval,
+}
+
+impl BookableResource {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<BookableResource>> {
+        let mut resources = AHashMap::new();
+
+        for id in config.sub_keys(RESOURCE_KEY, ".tenant") {
+            let Ok(account_id) = id.parse::<u32>() else {
+                config.new_parse_error((RESOURCE_KEY, id.as_str()), "Invalid account id");
+                continue;
+            };
+            let (Some(tenant_id), Some(kind), Some(policy)) = (
+                config.property_require((RESOURCE_KEY, id.as_str(), "tenant")),
+                config.property_require((RESOURCE_KEY, id.as_str(), "kind")),
+                config.property_require((RESOURCE_KEY, id.as_str(), "booking-policy")),
+            ) else {
+                continue;
+            };
+
+            resources.insert(
+                account_id,
+                Arc::new(BookableResource {
+                    tenant_id,
+                    kind,
+                    capacity: config.property((RESOURCE_KEY, id.as_str(), "capacity")),
+                    location: config
+                        .value((RESOURCE_KEY, id.as_str(), "location"))
+                        .map(|location| location.to_string()),
+                    policy,
+                    approver_id: config.property((RESOURCE_KEY, id.as_str(), "approver")),
+                }),
+            );
+        }
+
+        resources
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.policy == BookingPolicy::RequireApproval && self.approver_id.is_none() {
+            Err("Resources that require approval need an approver".to_string())
+        } else if self.capacity == Some(0) {
+            Err("Capacity must be greater than zero".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn config_keys(&self, account_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{RESOURCE_KEY}.{account_id}");
+        let mut keys = vec![
+            ConfigKey {
+                key: format!("{prefix}.tenant"),
+                value: self.tenant_id.to_string(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.kind"),
+                value: self.kind.as_str().to_string(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.booking-policy"),
+                value: self.policy.as_str().to_string(),
+            },
+        ];
+        if let Some(capacity) = self.capacity {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.capacity"),
+                value: capacity.to_string(),
+            });
+        }
+        if let Some(location) = &self.location {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.location"),
+                value: location.clone(),
+            });
+        }
+        if let Some(approver_id) = self.approver_id {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.approver"),
+                value: approver_id.to_string(),
+            });
+        }
+
+        keys
+    }
+}
+
+/// Calendars and address books created for the accounts of a tenant as
+/// soon as they are provisioned, in addition to the default ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantCollections {
+    #[serde(default)]
+    pub calendars: Vec<ProvisionCollection>,
+    #[serde(default)]
+    pub address_books: Vec<ProvisionCollection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ProvisionCollection {
+    /// Path segment of the collection.
+    pub name: String,
+    /// Name shown to users, the path segment when missing.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Whether the collection belongs to the tenant, which shares it
+    /// read-only with each of its accounts.
+    #[serde(default)]
+    pub shared: bool,
+}
+
+impl TenantCollections {
+    pub fn parse(config: &mut Config, tenant_id: u32) -> Option<Self> {
+        let prefix = format!("{TENANT_COLLECTIONS_KEY}.{tenant_id}");
+        let collections = TenantCollections {
+            calendars: ProvisionCollection::parse_list(config, &format!("{prefix}.calendar")),
+            address_books: ProvisionCollection::parse_list(
+                config,
+                &format!("{prefix}.address-book"),
+            ),
+        };
+
+        (!collections.calendars.is_empty() || !collections.address_books.is_empty())
+            .then_some(collections)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for collections in [&self.calendars, &self.address_books] {
+            let mut names = Vec::with_capacity(collections.len());
+            for collection in collections {
+                let name = collection.name.as_str();
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+                {
+                    return Err(format!("Invalid collection name {name:?}"));
+                } else if names.contains(&name) {
+                    return Err(format!("Duplicate collection name {name:?}"));
+                }
+                names.push(name);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let mut keys = Vec::new();
+
+        for (kind, collections) in [
+            ("calendar", &self.calendars),
+            ("address-book", &self.address_books),
+        ] {
+            let prefix = format!("{TENANT_COLLECTIONS_KEY}.{tenant_id}.{kind}");
+            for (idx, collection) in collections.iter().enumerate() {
+                for (key, value) in [
+                    ("name", Some(collection.name.clone())),
+                    ("display-name", collection.display_name.clone()),
+                    ("shared", Some(collection.shared.to_string())),
+                ] {
+                    if let Some(value) = value {
+                        keys.push(ConfigKey {
+                            key: format!("{prefix}.{idx:03}.{key}"),
+                            value,
+                        });
+                    }
+                }
+            }
+        }
+
+        keys
+    }
+}
+
+impl ProvisionCollection {
+    fn parse_list(config: &mut Config, prefix: &str) -> Vec<Self> {
+        let mut collections = Vec::new();
+
+        for id in config.sub_keys(prefix, ".name") {
+            if let Some(name) = config
+                .value_require((prefix, id.as_str(), "name"))
+                .map(|name| name.trim().to_string())
+            {
+                collections.push(ProvisionCollection {
+                    name,
+                    display_name: config
+                        .value((prefix, id.as_str(), "display-name"))
+                        .map(|name| name.trim().to_string()),
+                    shared: config
+                        .property_or_default((prefix, id.as_str(), "shared"), "false")
+                        .unwrap_or(false),
+                });
+            }
+        }
+
+        collections
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl ResourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResourceKind::Room => "room",
+            ResourceKind::Equipment => "equipment",
+        }
+    }
+}
+
+impl BookingPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BookingPolicy::AutoAccept => "auto-accept",
+            BookingPolicy::RequireApproval => "require-approval",
+        }
+    }
+}
+
+impl ParseValue for ResourceKind {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        match value {
+            "room" => Ok(ResourceKind::Room),
+            "equipment" => Ok(ResourceKind::Equipment),
+            _ => Err(format!("Invalid resource kind {:?}", value)),
+        }
+    }
+}
+
+impl ParseValue for BookingPolicy {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        match value {
+            "auto-accept" => Ok(BookingPolicy::AutoAccept),
+            "require-approval" => Ok(BookingPolicy::RequireApproval),
+            _ => Err(format!("Invalid booking policy {:?}", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BookableResource, BookingPolicy, ProvisionCollection, ResourceKind, TenantCollections,
+    };
+    use utils::config::Config;
+
+    #[test]
+    fn bookable_resource_config() {
+        let mut resource = BookableResource {
+            tenant_id: 3,
+            kind: ResourceKind::Room,
+            capacity: Some(12),
+            location: Some("Building A, 2nd floor".to_string()),
+            policy: BookingPolicy::RequireApproval,
+            approver_id: Some(9),
+        };
+        assert_eq!(resource.validate(), Ok(()));
+
+        let mut config = Config {
+            keys: resource
+                .config_keys(7)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let resources = BookableResource::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(resources.get(&7).unwrap().as_ref(), &resource);
+
+        // Approval requires someone to approve
+        resource.approver_id = None;
+        assert!(resource.validate().is_err());
+        resource.policy = BookingPolicy::AutoAccept;
+        assert_eq!(resource.validate(), Ok(()));
+        resource.capacity = Some(0);
+        assert!(resource.validate().is_err());
+    }
+
+    #[test]
+    fn tenant_collections() {
+        let collections = TenantCollections {
+            calendars: vec![
+                ProvisionCollection {
+                    name: "work".to_string(),
+                    display_name: Some("Work".to_string()),
+                    shared: false,
+                },
+                ProvisionCollection {
+                    name: "holidays".to_string(),
+                    display_name: Some("Company holidays".to_string()),
+                    shared: true,
+                },
+            ],
+            address_books: vec![ProvisionCollection {
+                name: "staff".to_string(),
+                display_name: None,
+                shared: true,
+            }],
+        };
+        assert_eq!(collections.validate(), Ok(()));
+
+        // Collections are stored as config keys and parsed back in order
+        let mut config = Config {
+            keys: collections
+                .config_keys(3)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            TenantCollections::parse(&mut config, 3),
+            Some(collections.clone())
+        );
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(TenantCollections::parse(&mut config, 4), None);
+        assert_eq!(collections.address_books[0].display_name(), "staff");
+
+        // Names are path segments, unique within each kind
+        for name in ["", "Work/Personal", "holidays"] {
+            let mut invalid = collections.clone();
+            invalid.calendars[0].name = name.to_string();
+            assert!(invalid.validate().is_err(), "{name:?}");
+        }
+        let mut valid = collections;
+        valid.address_books[0].name = "holidays".to_string();
+        assert_eq!(valid.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+pub const TENANT_HOSTNAME_KEY: &str = "tenant.hostname";
+
+/// Prefix of the TXT record proving the ownership of a hostname.
+pub const HOSTNAME_CHALLENGE_PREFIX: &str = "_stalwart-challenge";
+
+/// Hostname such as `mail.customer.com` serving the web interface with the
+/// branding of a tenant. Requests are only routed to the tenant once the
+/// ownership of the hostname was verified through DNS.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantHostname {
+    pub hostname: String,
+    pub tenant_id: u32,
+    /// Domain users of the tenant log in with by default.
+    pub default_domain: String,
+    #[serde(skip)]
+    pub token: String,
+    pub created_at: u64,
+    pub verified_at: Option<u64>,
+}
+
+impl TenantHostname {
+    pub fn parse_all(config: &mut Config) -> AHashMap<String, Arc<TenantHostname>> {
+        let mut hostnames = AHashMap::new();
+
+        for hostname in config.sub_keys(TENANT_HOSTNAME_KEY, ".tenant") {
+            let prefix = format!("{TENANT_HOSTNAME_KEY}.{hostname}");
+            let (Some(tenant_id), Some(created_at), Some(token), Some(default_domain)) = (
+                config.property_require::<u32>((prefix.as_str(), "tenant")),
+                config.property_require::<u64>((prefix.as_str(), "created-at")),
+                config
+                    .value_require((prefix.as_str(), "token"))
+                    .map(|token| token.to_string()),
+                config
+                    .value_require((prefix.as_str(), "default-domain"))
+                    .map(|domain| domain.to_string()),
+            ) else {
+                continue;
+            };
+            let hostname = hostname.to_lowercase();
+            hostnames.insert(
+                hostname.clone(),
+                Arc::new(TenantHostname {
+                    hostname,
+                    tenant_id,
+                    default_domain,
+                    token,
+                    created_at,
+                    verified_at: config.property::<u64>((prefix.as_str(), "verified-at")),
+                }),
+            );
+        }
+
+        hostnames
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified_at.is_some()
+    }
+
+    /// Name of the TXT record that must contain `challenge_value`.
+    pub fn challenge_name(&self) -> String {
+        format!("{HOSTNAME_CHALLENGE_PREFIX}.{}.", self.hostname)
+    }
+
+    pub fn challenge_value(&self) -> String {
+        format!("stalwart-verification={}", self.token)
+    }
+
+    pub fn config_keys(&self) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_HOSTNAME_KEY}.{}", self.hostname);
+        let mut keys = vec![
+            ConfigKey {
+                key: format!("{prefix}.tenant"),
+                value: self.tenant_id.to_string(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.default-domain"),
+                value: self.default_domain.clone(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.token"),
+                value: self.token.clone(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.created-at"),
+                value: self.created_at.to_string(),
+            },
+        ];
+        if let Some(verified_at) = self.verified_at {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.verified-at"),
+                value: verified_at.to_string(),
+            });
+        }
+
+        keys
+    }
+}
+
+/// Fully qualified names only, IP addresses and single labels are rejected.
+pub fn is_valid_hostname(hostname: &str) -> bool {
+    hostname.len() <= 253
+        && hostname.split('.').count() >= 2
+        && hostname.parse::<std::net::IpAddr>().is_err()
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == b'-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TenantHostname, is_valid_hostname};
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_hostname() {
+        let hostname = TenantHostname {
+            hostname: "mail.customer.com".to_string(),
+            tenant_id: 3,
+            default_domain: "customer.com".to_string(),
+            token: "abc123".to_string(),
+            created_at: 1000,
+            verified_at: None,
+        };
+        let mut config = Config {
+            keys: hostname
+                .config_keys()
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .chain([(
+                    "tenant.hostname.webmail.other.org.tenant".to_string(),
+                    "4".to_string(),
+                )])
+                .collect(),
+            ..Default::default()
+        };
+        let hostnames = TenantHostname::parse_all(&mut config);
+        assert_eq!(hostnames["mail.customer.com"].as_ref(), &hostname);
+        assert!(!hostnames.contains_key("webmail.other.org"));
+        assert!(!config.errors.is_empty());
+        assert!(!hostname.is_verified());
+        assert_eq!(
+            hostname.challenge_name(),
+            "_stalwart-challenge.mail.customer.com."
+        );
+        assert_eq!(hostname.challenge_value(), "stalwart-verification=abc123");
+
+        for valid in ["mail.customer.com", "a-b.c0.io"] {
+            assert!(is_valid_hostname(valid), "{valid}");
+        }
+        for invalid in [
+            "localhost",
+            "192.168.1.1",
+            "-mail.customer.com",
+            "mail..customer.com",
+            "Mail.customer.com",
+            "mail.customer.com:8080",
+            "*.customer.com",
+        ] {
+            assert!(!is_valid_hostname(invalid), "{invalid}");
+        }
+    }
+}
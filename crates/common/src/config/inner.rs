@@ -6,12 +6,28 @@
 
 use super::server::tls::{build_self_signed_cert, parse_certificates};
 use crate::{
-    CacheSwap, Caches, Data, DavResource, DavResources, MailboxCache, MessageStoreCache,
+    CacheSwap, Caches, Core, Data, DavResource, DavResources, MailboxCache, MessageStoreCache,
     MessageUidCache, TlsConnectors,
     auth::{AccessToken, roles::RolePermissions},
     config::{
-        smtp::resolver::{Policy, Tlsa},
-        spamfilter::SpamClassifier,
+        activation::TenantActivation,
+        groupware::BookableResource,
+        hostname::TenantHostname,
+        login_page::LoginPage,
+        maintenance::TenantMaintenance,
+        overrides::TenantOverrides,
+        password_policy::TenantPasswordPolicy,
+        scripts::{ForwardingRule, TenantSieveScript},
+        smtp::{
+            auth::{TenantDkimPolicy, parse_tenant_arc_sealers},
+            bimi::DomainBimi,
+            disclaimer::{DomainDisclaimer, TenantDisclaimer},
+            journal::TenantJournaling,
+            queue::{DomainRoute, TenantRouting},
+            resolver::{Policy, Tlsa},
+        },
+        spamfilter::{SpamClassifier, TenantSenderLists, TenantSpamSettings},
+        storage::{parse_tenant_blob_stores, parse_tenant_encryption},
     },
     listener::blocked::BlockedIps,
     manager::webadmin::WebAdminManager,
@@ -32,7 +48,7 @@ use utils::{
 };
 
 impl Data {
-    pub fn parse(config: &mut Config) -> Self {
+    pub fn parse(config: &mut Config, core: &Core) -> Self {
         // Parse certificates
         let mut certificates = AHashMap::new();
         let mut subject_names = AHashSet::new();
@@ -52,6 +68,33 @@ impl Data {
 
         Data {
             spam_classifier: ArcSwap::from_pointee(SpamClassifier::default()),
+            tenant_spam_settings: ArcSwap::from_pointee(TenantSpamSettings::parse_all(config)),
+            tenant_sender_lists: ArcSwap::from_pointee(TenantSenderLists::parse_all(config)),
+            tenant_sieve_scripts: ArcSwap::from_pointee(TenantSieveScript::parse_all(
+                config,
+                &core.sieve.untrusted_compiler,
+            )),
+            forwarding_rules: ArcSwap::from_pointee(ForwardingRule::parse_all(config)),
+            bookable_resources: ArcSwap::from_pointee(BookableResource::parse_all(config)),
+            tenant_routing: ArcSwap::from_pointee(TenantRouting::parse_all(config)),
+            domain_routes: ArcSwap::from_pointee(DomainRoute::parse_all(config)),
+            tenant_dkim_policies: ArcSwap::from_pointee(TenantDkimPolicy::parse_all(config)),
+            tenant_arc_sealers: ArcSwap::from_pointee(parse_tenant_arc_sealers(config)),
+            tenant_blob_stores: ArcSwap::from_pointee(parse_tenant_blob_stores(config)),
+            tenant_encryption: ArcSwap::from_pointee(parse_tenant_encryption(config)),
+            tenant_overrides: ArcSwap::from_pointee(TenantOverrides::parse_all(config)),
+            tenant_maintenance: ArcSwap::from_pointee(TenantMaintenance::parse_all(config)),
+            tenant_activation: ArcSwap::from_pointee(TenantActivation::parse_all(config)),
+            tenant_disclaimers: ArcSwap::from_pointee(TenantDisclaimer::parse_all(config)),
+            domain_disclaimers: ArcSwap::from_pointee(DomainDisclaimer::parse_all(config)),
+            tenant_login_pages: ArcSwap::from_pointee(LoginPage::parse_all_tenants(config)),
+            domain_login_pages: ArcSwap::from_pointee(LoginPage::parse_all_domains(config)),
+            tenant_hostnames: ArcSwap::from_pointee(TenantHostname::parse_all(config)),
+            tenant_password_policies: ArcSwap::from_pointee(TenantPasswordPolicy::parse_all(
+                config,
+            )),
+            tenant_journaling: ArcSwap::from_pointee(TenantJournaling::parse_all(config)),
+            domain_bimi: ArcSwap::from_pointee(DomainBimi::parse_all(config)),
             tls_certificates: ArcSwap::from_pointee(certificates),
             tls_self_signed_cert: build_self_signed_cert(
                 subject_names.into_iter().collect::<Vec<_>>(),
@@ -74,6 +117,23 @@ impl Data {
             logos: Default::default(),
             smtp_connectors: TlsConnectors::default(),
             asn_geo_data: Default::default(),
+            tenant_metrics: Default::default(),
+            slow_requests: Default::default(),
+            directory_changes: Default::default(),
+            import_jobs: Default::default(),
+            message_import_jobs: Default::default(),
+            imap_import_jobs: Default::default(),
+            reindex_jobs: Default::default(),
+            blob_migrations: Default::default(),
+            backup_jobs: Default::default(),
+            erasure_jobs: Default::default(),
+            deletion_reports: Default::default(),
+            export_jobs: Default::default(),
+            data_keys: Default::default(),
+            quota_steps: Default::default(),
+            activity_times: Default::default(),
+            domain_certificates: Default::default(),
+            dns_checks: Default::default(),
         }
     }
 }
@@ -147,6 +207,12 @@ impl Caches {
                 MB_5,
                 (std::mem::size_of::<Txt>() + 255) as u64,
             ),
+            dns_txt_records: CacheWithTtl::from_config(
+                config,
+                "dns.txt-records",
+                MB_1,
+                (std::mem::size_of::<String>() + 255) as u64,
+            ),
             dns_mx: CacheWithTtl::from_config(
                 config,
                 "dns.mx",
@@ -221,6 +287,28 @@ impl Default for Data {
     fn default() -> Self {
         Self {
             spam_classifier: Default::default(),
+            tenant_spam_settings: Default::default(),
+            tenant_sender_lists: Default::default(),
+            tenant_sieve_scripts: Default::default(),
+            forwarding_rules: Default::default(),
+            bookable_resources: Default::default(),
+            tenant_routing: Default::default(),
+            domain_routes: Default::default(),
+            tenant_dkim_policies: Default::default(),
+            tenant_arc_sealers: Default::default(),
+            tenant_blob_stores: Default::default(),
+            tenant_encryption: Default::default(),
+            tenant_overrides: Default::default(),
+            tenant_maintenance: Default::default(),
+            tenant_activation: Default::default(),
+            tenant_disclaimers: Default::default(),
+            domain_disclaimers: Default::default(),
+            tenant_login_pages: Default::default(),
+            domain_login_pages: Default::default(),
+            tenant_hostnames: Default::default(),
+            tenant_password_policies: Default::default(),
+            tenant_journaling: Default::default(),
+            domain_bimi: Default::default(),
             tls_certificates: Default::default(),
             tls_self_signed_cert: Default::default(),
             blocked_ips: Default::default(),
@@ -232,6 +320,23 @@ impl Default for Data {
             logos: Default::default(),
             smtp_connectors: Default::default(),
             asn_geo_data: Default::default(),
+            tenant_metrics: Default::default(),
+            slow_requests: Default::default(),
+            directory_changes: Default::default(),
+            import_jobs: Default::default(),
+            message_import_jobs: Default::default(),
+            imap_import_jobs: Default::default(),
+            reindex_jobs: Default::default(),
+            blob_migrations: Default::default(),
+            backup_jobs: Default::default(),
+            erasure_jobs: Default::default(),
+            deletion_reports: Default::default(),
+            export_jobs: Default::default(),
+            data_keys: Default::default(),
+            quota_steps: Default::default(),
+            activity_times: Default::default(),
+            domain_certificates: Default::default(),
+            dns_checks: Default::default(),
         }
     }
 }
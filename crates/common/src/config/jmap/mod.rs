@@ -5,4 +5,6 @@
  */
 
 pub mod capabilities;
+pub mod retention;
 pub mod settings;
+pub mod shared;
@@ -0,0 +1,342 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use types::special_use::SpecialUse;
+use utils::config::{Config, ConfigKey, utils::ParseValue};
+
+pub const TENANT_RETENTION_KEY: &str = "email.retention.tenant";
+pub const MAX_RETENTION_POLICIES: usize = 100;
+
+/// Message retention policies applied to every account of a tenant.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantRetention {
+    #[serde(default)]
+    pub policies: Vec<RetentionPolicy>,
+    /// Principals whose messages are never expired.
+    #[serde(default)]
+    pub legal_hold: Vec<String>,
+    /// Only report the messages that would be affected.
+    #[serde(default = "default_dry_run")]
+    pub dry_run: bool,
+}
+
+#[derive(
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    rkyv::Archive,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Special-use role of the folders the policy applies to.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Folder path the policy applies to, ending in `/*` to include
+    /// subfolders, or `*` for all folders.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Number of days after which messages expire.
+    pub days: u64,
+    pub action: RetentionAction,
+}
+
+#[derive(
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    rkyv::Archive,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    Delete,
+    Archive,
+}
+
+fn default_dry_run() -> bool {
+    true
+}
+
+impl Default for TenantRetention {
+    fn default() -> Self {
+        TenantRetention {
+            policies: Vec::new(),
+            legal_hold: Vec::new(),
+            dry_run: true,
+        }
+    }
+}
+
+impl TenantRetention {
+    pub fn parse(config: &mut Config, tenant_id: u32) -> Option<Self> {
+        let prefix = format!("{TENANT_RETENTION_KEY}.{tenant_id}");
+        let policy_prefix = format!("{prefix}.policy");
+        let mut policies = Vec::new();
+
+        for id in config.sub_keys_with_suffixes(policy_prefix.as_str(), &[".days"]) {
+            let policy = RetentionPolicy {
+                role: config
+                    .value((policy_prefix.as_str(), id.as_str(), "role"))
+                    .map(|role| role.to_string()),
+                folder: config
+                    .value((policy_prefix.as_str(), id.as_str(), "folder"))
+                    .map(|folder| folder.to_string()),
+                days: config
+                    .property_require((policy_prefix.as_str(), id.as_str(), "days"))
+                    .unwrap_or_default(),
+                action: config
+                    .property_require((policy_prefix.as_str(), id.as_str(), "action"))
+                    .unwrap_or(RetentionAction::Delete),
+            };
+
+            if let Err(err) = policy.validate() {
+                config.new_parse_error((policy_prefix.as_str(), id.as_str()), err);
+            } else {
+                policies.push(policy);
+            }
+        }
+
+        let legal_hold = config
+            .values((prefix.as_str(), "legal-hold"))
+            .map(|(_, name)| name.to_string())
+            .collect::<Vec<_>>();
+
+        if !policies.is_empty() || !legal_hold.is_empty() {
+            Some(TenantRetention {
+                policies,
+                legal_hold,
+                dry_run: config
+                    .property_or_default((prefix.as_str(), "dry-run"), "true")
+                    .unwrap_or(true),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.policies.len() > MAX_RETENTION_POLICIES {
+            return Err(format!(
+                "At most {MAX_RETENTION_POLICIES} retention policies can be defined"
+            ));
+        }
+
+        for policy in &self.policies {
+            policy.validate()?;
+        }
+
+        for name in &self.legal_hold {
+            if name.is_empty() {
+                return Err("Legal hold principal names cannot be empty".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_RETENTION_KEY}.{tenant_id}");
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.dry-run"),
+            value: self.dry_run.to_string(),
+        }];
+
+        for (idx, policy) in self.policies.iter().enumerate() {
+            for (key, value) in [
+                ("role", policy.role.clone()),
+                ("folder", policy.folder.clone()),
+                ("days", Some(policy.days.to_string())),
+                ("action", Some(policy.action.as_str().to_string())),
+            ] {
+                if let Some(value) = value {
+                    keys.push(ConfigKey {
+                        key: format!("{prefix}.policy.{idx:03}.{key}"),
+                        value,
+                    });
+                }
+            }
+        }
+
+        for (idx, name) in self.legal_hold.iter().enumerate() {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.legal-hold.{idx:03}"),
+                value: name.clone(),
+            });
+        }
+
+        keys
+    }
+}
+
+impl RetentionPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.days == 0 {
+            return Err("Retention period must be at least one day".to_string());
+        }
+
+        match (&self.role, &self.folder) {
+            (Some(role), None) => match SpecialUse::parse_value(role)? {
+                SpecialUse::Archive if self.action == RetentionAction::Archive => {
+                    Err("Messages in the archive folder cannot be archived again".to_string())
+                }
+                SpecialUse::Shared => {
+                    Err("Shared folders cannot have a retention policy".to_string())
+                }
+                _ => Ok(()),
+            },
+            (None, Some(folder)) => {
+                if folder.is_empty() || folder.starts_with('/') || folder.ends_with('/') {
+                    Err(format!("Invalid folder {folder:?}"))
+                } else {
+                    Ok(())
+                }
+            }
+            (Some(_), Some(_)) => {
+                Err("Policies can match either a folder role or a folder path".to_string())
+            }
+            (None, None) => Err("Policies require a folder role or a folder path".to_string()),
+        }
+    }
+
+    /// Returns whether the policy applies to the folder with the given path and role.
+    pub fn matches(&self, path: &str, role: SpecialUse) -> bool {
+        if let Some(policy_role) = &self.role {
+            SpecialUse::parse(policy_role).is_some_and(|policy_role| policy_role == role)
+        } else if let Some(folder) = &self.folder {
+            if folder == "*" {
+                true
+            } else if let Some(parent) = folder.strip_suffix("/*") {
+                path.len() >= parent.len()
+                    && path.is_char_boundary(parent.len())
+                    && path[..parent.len()].eq_ignore_ascii_case(parent)
+                    && (path.len() == parent.len() || path.as_bytes()[parent.len()] == b'/')
+            } else {
+                path.eq_ignore_ascii_case(folder)
+            }
+        } else {
+            false
+        }
+    }
+}
+
+impl RetentionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetentionAction::Delete => "delete",
+            RetentionAction::Archive => "archive",
+        }
+    }
+}
+
+impl ParseValue for RetentionAction {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        match value {
+            "delete" => Ok(RetentionAction::Delete),
+            "archive" => Ok(RetentionAction::Archive),
+            _ => Err(format!("Invalid retention action {:?}", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RetentionAction, RetentionPolicy, TenantRetention};
+    use types::special_use::SpecialUse;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_retention() {
+        let retention = TenantRetention {
+            policies: vec![
+                RetentionPolicy {
+                    role: Some("trash".to_string()),
+                    folder: None,
+                    days: 30,
+                    action: RetentionAction::Delete,
+                },
+                RetentionPolicy {
+                    role: None,
+                    folder: Some("Projects/*".to_string()),
+                    days: 365,
+                    action: RetentionAction::Archive,
+                },
+                RetentionPolicy {
+                    role: None,
+                    folder: Some("*".to_string()),
+                    days: 7 * 365,
+                    action: RetentionAction::Delete,
+                },
+            ],
+            legal_hold: vec!["jane@acme.org".to_string()],
+            dry_run: false,
+        };
+        assert!(retention.validate().is_ok());
+
+        // Policies are stored as config keys and parsed back in order
+        let mut config = Config {
+            keys: retention
+                .config_keys(5)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            TenantRetention::parse(&mut config, 5),
+            Some(retention.clone())
+        );
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(TenantRetention::parse(&mut config, 6), None);
+
+        // Folder matching
+        for (policy, path, role, expected) in [
+            (0, "Deleted Items", SpecialUse::Trash, true),
+            (0, "Trash", SpecialUse::None, false),
+            (1, "Projects", SpecialUse::None, true),
+            (1, "projects/2024/Q1", SpecialUse::None, true),
+            (1, "Projects 2024", SpecialUse::None, false),
+            (2, "Inbox", SpecialUse::Inbox, true),
+        ] {
+            assert_eq!(
+                retention.policies[policy].matches(path, role),
+                expected,
+                "{policy} {path}"
+            );
+        }
+
+        for invalid in [
+            (Some("archive"), None, 30, RetentionAction::Archive),
+            (Some("unknown"), None, 30, RetentionAction::Delete),
+            (Some("trash"), Some("Trash"), 30, RetentionAction::Delete),
+            (None, None, 30, RetentionAction::Delete),
+            (None, Some("Projects/"), 30, RetentionAction::Delete),
+            (Some("junk"), None, 0, RetentionAction::Delete),
+        ] {
+            let policy = RetentionPolicy {
+                role: invalid.0.map(|role| role.to_string()),
+                folder: invalid.1.map(|folder| folder.to_string()),
+                days: invalid.2,
+                action: invalid.3,
+            };
+            assert!(policy.validate().is_err(), "{invalid:?}");
+        }
+    }
+}
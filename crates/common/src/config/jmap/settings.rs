@@ -4,15 +4,19 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use crate::config::groupware::GroupwareConfig;
+use crate::config::{
+    abuse::ProvisioningCheck, groupware::GroupwareConfig, password_policy::parse_password_policy,
+    plan::TenantPlan,
+};
 use ahash::{AHashMap, AHashSet};
+use directory::backend::internal::{DEFAULT_RESERVED_NAMES, NamePolicy, password::PasswordPolicy};
 use jmap_proto::request::capability::BaseCapabilities;
 use nlp::language::Language;
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc, time::Duration};
 use store::{search::SearchField, write::SearchIndex};
 use types::{collection::Collection, special_use::SpecialUse};
 use utils::{
-    config::{Config, Rate, cron::SimpleCron, utils::ParseValue},
+    config::{Config, ConfigKey, Rate, cron::SimpleCron, utils::ParseValue},
     map::bitmap::Bitmap,
 };
 
@@ -56,6 +60,7 @@ pub struct JmapConfig {
 
     pub rate_authenticated: Option<Rate>,
     pub rate_anonymous: Option<Rate>,
+    pub rate_webhook_test: Option<Rate>,
 
     pub event_source_throttle: Duration,
     pub push_attempt_interval: Duration,
@@ -74,9 +79,18 @@ pub struct JmapConfig {
 
     pub default_folders: Vec<DefaultFolder>,
     pub shared_folder: String,
+    pub provision_folders: Vec<ProvisionFolder>,
 
     pub http_headers: Vec<(hyper::header::HeaderName, hyper::header::HeaderValue)>,
     pub http_use_forwarded: bool,
+    pub http_body_limits: HttpBodyLimits,
+    pub http_compression: HttpCompression,
+    pub http_cors: HttpCors,
+    pub http_slow_requests: HttpSlowRequests,
+    pub directory_changes_window: usize,
+    pub directory_invalidation_threshold: usize,
+    pub directory_names: NamePolicy,
+    pub password_policy: PasswordPolicy,
 
     pub encrypt: bool,
     pub encrypt_append: bool,
@@ -86,6 +100,49 @@ pub struct JmapConfig {
 
     pub capabilities: BaseCapabilities,
     pub account_purge_frequency: SimpleCron,
+    pub retention_frequency: SimpleCron,
+
+    pub export_allow_self: bool,
+    pub export_allow_admin: bool,
+    pub export_max_size: u64,
+    pub export_max_concurrent: usize,
+    pub export_expiry: u64,
+
+    pub email_verification_expiry: u64,
+    pub email_verification_from: String,
+
+    pub activity_interval: u64,
+    pub dormant_after: u64,
+
+    pub schedule_interval: Duration,
+    pub schedule_notify_before: u64,
+
+    pub avatar_max_size: usize,
+    pub avatar_max_dimension: u32,
+
+    pub dns_check_ttl: u64,
+    pub dns_check_interval: Duration,
+
+    pub signup_enabled: bool,
+    pub signup_expiry: u64,
+    pub signup_from: String,
+
+    pub activation_expiry: u64,
+    pub activation_from: String,
+    pub activation_url: String,
+    pub abuse_check: Option<ProvisioningCheck>,
+
+    pub trial_notify_before: Vec<u64>,
+
+    pub plans: AHashMap<String, Arc<TenantPlan>>,
+
+    /// Referral codes accepted at provisioning, in lowercase. Any code is
+    /// accepted when empty.
+    pub referral_codes: AHashSet<String>,
+
+    /// Blob stores tenants can write journal envelopes to, tenants cannot
+    /// journal to a bucket when empty.
+    pub journal_blob_stores: AHashSet<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -97,6 +154,87 @@ pub struct DefaultFolder {
     pub create: bool,
 }
 
+pub const TENANT_FOLDERS_KEY: &str = "email.tenant";
+pub const PROVISION_FOLDERS_KEY: &str = "email.provision";
+
+/// Special-use folders with a translated name in every locale.
+pub const LOCALIZED_FOLDERS: [SpecialUse; 6] = [
+    SpecialUse::Inbox,
+    SpecialUse::Sent,
+    SpecialUse::Drafts,
+    SpecialUse::Trash,
+    SpecialUse::Junk,
+    SpecialUse::Archive,
+];
+
+/// Mailboxes created for the accounts of a tenant as soon as they are
+/// provisioned, instead of on first access.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantFolders {
+    #[serde(default)]
+    pub folders: Vec<ProvisionFolder>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ProvisionFolder {
+    /// Special-use role, or none for custom folders.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Folder name, localized from the role when missing.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default = "default_subscribe")]
+    pub subscribe: bool,
+}
+
+fn default_subscribe() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpBodyLimits {
+    pub default: usize,
+    pub decompressed: usize,
+    pub routes: Vec<(String, usize)>,
+    pub streaming: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpCompression {
+    pub enable: bool,
+    pub min_size: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpSlowRequests {
+    pub threshold: Option<Duration>,
+    pub max_entries: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpCors {
+    pub enable: bool,
+    pub origins: Vec<CorsOrigin>,
+    pub methods: Vec<hyper::Method>,
+    pub headers: Vec<hyper::header::HeaderName>,
+    pub allow_credentials: bool,
+    pub max_age: Option<Duration>,
+    pub listeners: AHashSet<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorsOrigin {
+    Any,
+    Exact(String),
+    Subdomain { scheme: String, domain: String },
+}
+
 impl JmapConfig {
     pub fn parse(config: &mut Config, groupware_config: &GroupwareConfig) -> Self {
         // Parse HTTP headers
@@ -297,6 +435,9 @@ impl JmapConfig {
             rate_anonymous: config
                 .property_or_default::<Option<Rate>>("http.rate-limit.anonymous", "100/1m")
                 .unwrap_or_default(),
+            rate_webhook_test: config
+                .property_or_default::<Option<Rate>>("http.rate-limit.webhook-test", "5/1m")
+                .unwrap_or_default(),
             event_source_throttle: config
                 .property_or_default("jmap.event-source.throttle", "1s")
                 .unwrap_or_else(|| Duration::from_secs(1)),
@@ -317,6 +458,54 @@ impl JmapConfig {
                 .unwrap_or(false),
             http_use_forwarded: config.property("http.use-x-forwarded").unwrap_or(false),
             http_headers,
+            http_body_limits: HttpBodyLimits::parse(config),
+            http_cors: HttpCors::parse(config),
+            http_slow_requests: HttpSlowRequests {
+                threshold: config
+                    .property_or_default::<Option<Duration>>("http.slow-request.threshold", "2s")
+                    .unwrap_or_default(),
+                max_entries: config
+                    .property_or_default("http.slow-request.max-entries", "100")
+                    .unwrap_or(100),
+            },
+            directory_changes_window: config
+                .property_or_default("directory.changes.window", "1000")
+                .unwrap_or(1000),
+            directory_invalidation_threshold: config
+                .property_or_default("directory.invalidation.threshold", "100")
+                .unwrap_or(100),
+            directory_names: NamePolicy {
+                scope: config
+                    .property_or_default("directory.names.scope", "global")
+                    .unwrap_or_default(),
+                reserved: {
+                    let reserved = config
+                        .values("directory.names.reserved")
+                        .map(|(_, name)| name.trim().to_lowercase())
+                        .filter(|name| !name.is_empty())
+                        .collect::<AHashSet<_>>();
+                    if !reserved.is_empty() {
+                        reserved
+                    } else {
+                        DEFAULT_RESERVED_NAMES
+                            .iter()
+                            .map(|name| name.to_string())
+                            .collect()
+                    }
+                },
+                case_sensitive_local_part: config
+                    .property_or_default("directory.email.case-sensitive-local-part", "false")
+                    .unwrap_or(false),
+            },
+            password_policy: parse_password_policy(config),
+            http_compression: HttpCompression {
+                enable: config
+                    .property_or_default("http.compression.enable", "true")
+                    .unwrap_or(true),
+                min_size: config
+                    .property_or_default("http.compression.min-size", "1024")
+                    .unwrap_or(1024),
+            },
             push_attempt_interval: config
                 .property_or_default("jmap.push.attempts.interval", "1m")
                 .unwrap_or_else(|| Duration::from_secs(60)),
@@ -338,6 +527,115 @@ impl JmapConfig {
             account_purge_frequency: config
                 .property_or_default::<SimpleCron>("account.purge.frequency", "0 0 *")
                 .unwrap_or_else(|| SimpleCron::parse_value("0 0 *").unwrap()),
+            retention_frequency: config
+                .property_or_default::<SimpleCron>("email.retention.frequency", "0 2 *")
+                .unwrap_or_else(|| SimpleCron::parse_value("0 2 *").unwrap()),
+            export_allow_self: config
+                .property_or_default("account.export.allow-self", "true")
+                .unwrap_or(true),
+            export_allow_admin: config
+                .property_or_default("account.export.allow-admin", "true")
+                .unwrap_or(true),
+            export_max_size: config
+                .property_or_default("account.export.max-size", "5368709120")
+                .unwrap_or(5368709120),
+            export_max_concurrent: config
+                .property_or_default("account.export.max-concurrent", "2")
+                .unwrap_or(2),
+            export_expiry: config
+                .property_or_default::<Duration>("account.export.expiry", "7d")
+                .unwrap_or_else(|| Duration::from_secs(7 * 86400))
+                .as_secs(),
+            email_verification_expiry: config
+                .property_or_default::<Duration>("account.email-verification.expiry", "1d")
+                .unwrap_or_else(|| Duration::from_secs(86400))
+                .as_secs(),
+            email_verification_from: config
+                .value("account.email-verification.from")
+                .unwrap_or("postmaster@localhost")
+                .to_string(),
+            activity_interval: config
+                .property_or_default::<Duration>("account.activity.interval", "1h")
+                .unwrap_or_else(|| Duration::from_secs(3600))
+                .as_secs(),
+            dormant_after: config
+                .property_or_default::<Duration>("account.activity.dormant-after", "90d")
+                .unwrap_or_else(|| Duration::from_secs(90 * 86400))
+                .as_secs(),
+            schedule_interval: config
+                .property_or_default::<Duration>("account.schedule.interval", "5m")
+                .unwrap_or_else(|| Duration::from_secs(300)),
+            schedule_notify_before: config
+                .property_or_default::<Duration>("account.schedule.notify-before", "1h")
+                .unwrap_or_else(|| Duration::from_secs(3600))
+                .as_secs(),
+            avatar_max_size: config
+                .property_or_default("account.avatar.max-size", "1048576")
+                .unwrap_or(1048576),
+            avatar_max_dimension: config
+                .property_or_default("account.avatar.max-dimension", "1024")
+                .unwrap_or(1024),
+            dns_check_ttl: config
+                .property_or_default::<Duration>("domain.dns-check.ttl", "15m")
+                .unwrap_or_else(|| Duration::from_secs(15 * 60))
+                .as_secs(),
+            dns_check_interval: config
+                .property_or_default::<Duration>("domain.dns-check.interval", "6h")
+                .unwrap_or_else(|| Duration::from_secs(6 * 3600)),
+            signup_enabled: config
+                .property_or_default("organization.signup.enable", "false")
+                .unwrap_or(false),
+            signup_expiry: config
+                .property_or_default::<Duration>("organization.signup.expiry", "14d")
+                .unwrap_or_else(|| Duration::from_secs(14 * 86400))
+                .as_secs(),
+            signup_from: config
+                .value("organization.signup.from")
+                .unwrap_or("postmaster@localhost")
+                .to_string(),
+            activation_expiry: config
+                .property_or_default::<Duration>("organization.activation.expiry", "3d")
+                .unwrap_or_else(|| Duration::from_secs(3 * 86400))
+                .as_secs(),
+            activation_from: config
+                .value("organization.activation.from")
+                .unwrap_or("postmaster@localhost")
+                .to_string(),
+            activation_url: config
+                .value("organization.activation.url")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| {
+                    format!(
+                        "https://{}",
+                        config.value("server.hostname").unwrap_or("localhost")
+                    )
+                }),
+            abuse_check: ProvisioningCheck::parse(config),
+            trial_notify_before: {
+                let mut notify = config
+                    .properties::<Duration>("organization.trial.notify-before")
+                    .into_iter()
+                    .map(|(_, d)| d.as_secs())
+                    .filter(|d| *d > 0)
+                    .collect::<Vec<_>>();
+                if notify.is_empty() {
+                    notify = vec![7 * 86400, 86400];
+                }
+                notify.sort_unstable();
+                notify.dedup();
+                notify
+            },
+            plans: TenantPlan::parse_all(config),
+            referral_codes: config
+                .values("organization.referral.codes")
+                .map(|(_, code)| code.trim().to_lowercase())
+                .filter(|code| !code.is_empty())
+                .collect(),
+            journal_blob_stores: config
+                .values("organization.journal.stores")
+                .map(|(_, store)| store.trim().to_string())
+                .filter(|store| !store.is_empty())
+                .collect(),
             fallback_admin: config
                 .value("authentication.fallback-admin.user")
                 .and_then(|u| {
@@ -362,6 +660,8 @@ impl JmapConfig {
             index_fields: AHashMap::new(),
             default_folders,
             shared_folder,
+            provision_folders: ProvisionFolder::parse_list(config, PROVISION_FOLDERS_KEY)
+                .unwrap_or_else(ProvisionFolder::defaults),
         };
 
         // Parse index fields
@@ -429,3 +729,567 @@ impl JmapConfig {
         jmap
     }
 }
+
+impl HttpBodyLimits {
+    pub fn parse(config: &mut Config) -> Self {
+        let mut routes = Vec::new();
+        // Bodies on these routes are read by the handler as they arrive,
+        // everything else is buffered up to its route limit before dispatch.
+        // The organization import endpoints (`organization/*/import/ldap` and
+        // `organization/*/import/imap`) buffer their bodies, which only hold
+        // the job description and are bounded by the default limit.
+        let mut streaming = vec!["principal/*/import/messages".to_string()];
+        for id in config.sub_keys("http.limits.body.route", ".path") {
+            let path = config
+                .value_require(("http.limits.body.route", id.as_str(), "path"))
+                .map(|path| path.trim_matches('/').to_string());
+            let max_size = config.property_require::<usize>((
+                "http.limits.body.route",
+                id.as_str(),
+                "max-size",
+            ));
+            if let (Some(path), Some(max_size)) = (path, max_size) {
+                // Only routes whose handler reads the body itself can stream
+                if config
+                    .property_or_default::<bool>(
+                        ("http.limits.body.route", id.as_str(), "streaming"),
+                        "false",
+                    )
+                    .unwrap_or(false)
+                    && !streaming.contains(&path)
+                {
+                    streaming.push(path.clone());
+                }
+                routes.push((path, max_size));
+            }
+        }
+
+        // Built-in routes, unless overridden
+        for (path, max_size) in [
+            ("organization/provision", 64 * 1024),
+            ("organization/signup", 64 * 1024),
+            ("organization/*/activate", 64 * 1024),
+            ("spam-filter/upload", 25 * 1024 * 1024),
+            ("principal/*/import/messages", 1024 * 1024 * 1024),
+        ] {
+            if !routes.iter().any(|(p, _)| p == path) {
+                routes.push((path.to_string(), max_size));
+            }
+        }
+
+        // Most specific route first: literal segments outrank wildcards,
+        // then deeper routes outrank shallower ones
+        routes.sort_by_key(|(path, _)| {
+            let segments = path.split('/');
+            std::cmp::Reverse((
+                segments.clone().filter(|segment| *segment != "*").count(),
+                segments.count(),
+            ))
+        });
+
+        HttpBodyLimits {
+            default: config
+                .property_or_default("http.limits.body.default", "1048576")
+                .unwrap_or(1024 * 1024),
+            decompressed: config
+                .property_or_default("http.limits.body.decompressed", "52428800")
+                .unwrap_or(50 * 1024 * 1024),
+            routes,
+            streaming,
+        }
+    }
+
+    /// Returns the maximum body size for a management API path
+    /// relative to `/api/`. A `*` route segment matches any single
+    /// path segment.
+    pub fn limit(&self, path: &str) -> usize {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| route_matches(prefix, path))
+            .map_or(self.default, |(_, max_size)| *max_size)
+    }
+
+    /// Returns whether the handler for a management API path reads the
+    /// request body itself rather than receiving it buffered.
+    pub fn is_streaming(&self, path: &str) -> bool {
+        self.streaming
+            .iter()
+            .any(|prefix| route_matches(prefix, path))
+    }
+}
+
+fn route_matches(prefix: &str, path: &str) -> bool {
+    let mut segments = path.trim_matches('/').split('/');
+    prefix.split('/').all(|prefix| {
+        segments
+            .next()
+            .is_some_and(|segment| prefix == "*" || prefix == segment)
+    })
+}
+
+impl HttpCors {
+    pub fn parse(config: &mut Config) -> Self {
+        let origins = config
+            .values("http.cors.origins")
+            .map(|(_, origin)| origin.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|origin| match CorsOrigin::parse(&origin) {
+                Some(origin) => Some(origin),
+                None => {
+                    config.new_parse_error(
+                        "http.cors.origins",
+                        format!("Invalid CORS origin {origin:?}"),
+                    );
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut methods = config
+            .values("http.cors.methods")
+            .map(|(_, method)| method.trim().to_ascii_uppercase())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|method| {
+                hyper::Method::from_str(&method)
+                    .map_err(|_| {
+                        config.new_parse_error(
+                            "http.cors.methods",
+                            format!("Invalid HTTP method {method:?}"),
+                        )
+                    })
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        if methods.is_empty() {
+            methods = vec![
+                hyper::Method::GET,
+                hyper::Method::POST,
+                hyper::Method::PUT,
+                hyper::Method::PATCH,
+                hyper::Method::DELETE,
+            ];
+        }
+        let mut headers = config
+            .values("http.cors.headers")
+            .map(|(_, header)| header.trim().to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|header| {
+                hyper::header::HeaderName::from_str(&header)
+                    .map_err(|_| {
+                        config.new_parse_error(
+                            "http.cors.headers",
+                            format!("Invalid HTTP header {header:?}"),
+                        )
+                    })
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        if headers.is_empty() {
+            headers = vec![
+                hyper::header::AUTHORIZATION,
+                hyper::header::CONTENT_TYPE,
+                hyper::header::ACCEPT,
+                hyper::header::HeaderName::from_static("x-requested-with"),
+            ];
+        }
+
+        // Browsers reject credentialed responses for a wildcard origin, and
+        // reflecting any origin instead would expose sessions to every site
+        let mut allow_credentials = config
+            .property_or_default("http.cors.allow-credentials", "false")
+            .unwrap_or(false);
+        if allow_credentials && origins.contains(&CorsOrigin::Any) {
+            config.new_parse_error(
+                "http.cors.allow-credentials",
+                "Credentials cannot be allowed for any origin",
+            );
+            allow_credentials = false;
+        }
+
+        HttpCors {
+            enable: !origins.is_empty(),
+            origins,
+            methods,
+            headers,
+            allow_credentials,
+            max_age: config
+                .property_or_default::<Option<Duration>>("http.cors.max-age", "1h")
+                .unwrap_or_default(),
+            listeners: config
+                .values("http.cors.listeners")
+                .map(|(_, id)| id.to_string())
+                .collect(),
+        }
+    }
+
+    /// Returns whether CORS handling is active for the given listener.
+    pub fn is_enabled_for(&self, listener_id: &str) -> bool {
+        self.enable && (self.listeners.is_empty() || self.listeners.contains(listener_id))
+    }
+
+    pub fn is_allowed_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    pub fn is_allowed_method(&self, method: &str) -> bool {
+        self.methods
+            .iter()
+            .any(|allowed| allowed.as_str().eq_ignore_ascii_case(method.trim()))
+    }
+
+    pub fn is_allowed_header(&self, header: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|allowed| allowed.as_str().eq_ignore_ascii_case(header.trim()))
+    }
+}
+
+impl CorsOrigin {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().trim_end_matches('/').to_ascii_lowercase();
+        if value == "*" {
+            return Some(CorsOrigin::Any);
+        }
+
+        let (scheme, host) = value.split_once("://")?;
+        if scheme.is_empty() || host.is_empty() || host.contains('/') {
+            return None;
+        }
+
+        if let Some(domain) = host.strip_prefix("*.") {
+            if domain.is_empty() || domain.contains('*') {
+                None
+            } else {
+                Some(CorsOrigin::Subdomain {
+                    scheme: scheme.to_string(),
+                    domain: domain.to_string(),
+                })
+            }
+        } else if !host.contains('*') {
+            Some(CorsOrigin::Exact(value))
+        } else {
+            None
+        }
+    }
+
+    /// Matches a request `Origin` header value. Wildcard entries only match
+    /// subdomains, not the parent domain itself.
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            CorsOrigin::Any => true,
+            CorsOrigin::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            CorsOrigin::Subdomain { scheme, domain } => origin
+                .split_once("://")
+                .filter(|(origin_scheme, _)| origin_scheme.eq_ignore_ascii_case(scheme))
+                .and_then(|(_, host)| {
+                    host.len()
+                        .checked_sub(domain.len() + 1)
+                        .filter(|&pos| pos > 0)
+                        .and_then(|pos| host.get(pos..))
+                })
+                .is_some_and(|suffix| {
+                    suffix.starts_with('.') && suffix[1..].eq_ignore_ascii_case(domain)
+                }),
+        }
+    }
+}
+
+impl TenantFolders {
+    pub fn parse(config: &mut Config, tenant_id: u32) -> Option<Self> {
+        ProvisionFolder::parse_list(config, &format!("{TENANT_FOLDERS_KEY}.{tenant_id}"))
+            .map(|folders| TenantFolders { folders })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut roles = Vec::with_capacity(self.folders.len());
+        let mut names = Vec::with_capacity(self.folders.len());
+
+        for folder in &self.folders {
+            let special_use = folder.special_use()?;
+            if folder.name.is_none() && !LOCALIZED_FOLDERS.contains(&special_use) {
+                return Err(format!(
+                    "Folder name is required for role {:?}",
+                    folder.role.as_deref().unwrap_or("none")
+                ));
+            }
+
+            match special_use {
+                SpecialUse::None => (),
+                SpecialUse::Shared => {
+                    return Err("The shared folder cannot be provisioned".to_string());
+                }
+                special_use => {
+                    if roles.contains(&special_use) {
+                        return Err(format!(
+                            "Duplicate folder role {:?}",
+                            folder.role.as_deref().unwrap_or_default()
+                        ));
+                    }
+                    roles.push(special_use);
+                }
+            }
+
+            if let Some(name) = &folder.name {
+                let name_lcase = name.trim().to_lowercase();
+                if name_lcase.is_empty() || name_lcase.contains('/') {
+                    return Err(format!("Invalid folder name {name:?}"));
+                } else if names.contains(&name_lcase) {
+                    return Err(format!("Duplicate folder name {name:?}"));
+                }
+                names.push(name_lcase);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_FOLDERS_KEY}.{tenant_id}.folder");
+        let mut keys = Vec::new();
+
+        for (idx, folder) in self.folders.iter().enumerate() {
+            for (key, value) in [
+                ("role", folder.role.clone()),
+                ("name", folder.name.clone()),
+                ("subscribe", Some(folder.subscribe.to_string())),
+            ] {
+                if let Some(value) = value {
+                    keys.push(ConfigKey {
+                        key: format!("{prefix}.{idx:03}.{key}"),
+                        value,
+                    });
+                }
+            }
+        }
+
+        keys
+    }
+}
+
+impl ProvisionFolder {
+    fn parse_list(config: &mut Config, prefix: &str) -> Option<Vec<Self>> {
+        let prefix = format!("{prefix}.folder");
+        let mut folders = Vec::new();
+
+        for id in config.sub_keys_with_suffixes(prefix.as_str(), &[".role", ".name"]) {
+            let folder = ProvisionFolder {
+                role: config
+                    .value((prefix.as_str(), id.as_str(), "role"))
+                    .map(|role| role.trim().to_lowercase()),
+                name: config
+                    .value((prefix.as_str(), id.as_str(), "name"))
+                    .map(|name| name.trim().to_string()),
+                subscribe: config
+                    .property_or_default((prefix.as_str(), id.as_str(), "subscribe"), "true")
+                    .unwrap_or(true),
+            };
+
+            match folder.special_use() {
+                Ok(SpecialUse::Shared) => {
+                    config.new_parse_error(
+                        (prefix.as_str(), id.as_str()),
+                        "The shared folder cannot be provisioned",
+                    );
+                }
+                Ok(_) => {
+                    folders.push(folder);
+                }
+                Err(err) => {
+                    config.new_parse_error((prefix.as_str(), id.as_str()), err);
+                }
+            }
+        }
+
+        (!folders.is_empty()).then_some(folders)
+    }
+
+    pub fn defaults() -> Vec<Self> {
+        LOCALIZED_FOLDERS
+            .into_iter()
+            .map(|special_use| ProvisionFolder {
+                role: special_use.as_str().map(|role| role.to_string()),
+                name: None,
+                subscribe: true,
+            })
+            .collect()
+    }
+
+    pub fn special_use(&self) -> Result<SpecialUse, String> {
+        self.role
+            .as_deref()
+            .map_or(Ok(SpecialUse::None), SpecialUse::parse_value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CorsOrigin, HttpBodyLimits, HttpCors, PROVISION_FOLDERS_KEY, ProvisionFolder, TenantFolders,
+    };
+    use types::special_use::SpecialUse;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_folders() {
+        let folders = TenantFolders {
+            folders: (0..12)
+                .map(|idx| ProvisionFolder {
+                    role: (idx == 0).then(|| "sent".to_string()),
+                    name: Some(format!("Folder {idx}")),
+                    subscribe: idx % 2 == 0,
+                })
+                .collect(),
+        };
+        assert!(folders.validate().is_ok());
+
+        // Folders are stored as config keys and parsed back in order
+        let mut config = Config {
+            keys: folders
+                .config_keys(3)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(TenantFolders::parse(&mut config, 3), Some(folders));
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(TenantFolders::parse(&mut config, 4), None);
+
+        // The shared folder is reported and skipped when parsing
+        let mut config = Config::new(
+            r#"
+[email.provision.folder.a]
+role = "shared"
+name = "Shared"
+
+[email.provision.folder.b]
+role = "sent"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ProvisionFolder::parse_list(&mut config, PROVISION_FOLDERS_KEY),
+            Some(vec![ProvisionFolder {
+                role: Some("sent".to_string()),
+                name: None,
+                subscribe: true,
+            }])
+        );
+        assert!(
+            config
+                .errors
+                .contains_key(&format!("{PROVISION_FOLDERS_KEY}.folder.a")),
+            "{:?}",
+            config.errors
+        );
+
+        // Defaults only contain folders with localized names
+        assert!(
+            TenantFolders {
+                folders: ProvisionFolder::defaults(),
+            }
+            .validate()
+            .is_ok()
+        );
+
+        for invalid in [
+            vec![("inbox", None), ("inbox", Some("Inbox 2"))],
+            vec![("snoozed", None)],
+            vec![("shared", Some("Shared"))],
+            vec![("unknown", Some("Unknown"))],
+            vec![("drafts", Some("Work/Drafts"))],
+            vec![("sent", Some("Sent")), ("drafts", Some("sent"))],
+        ] {
+            let folders = TenantFolders {
+                folders: invalid
+                    .iter()
+                    .map(|(role, name)| ProvisionFolder {
+                        role: Some(role.to_string()),
+                        name: name.map(|name| name.to_string()),
+                        subscribe: true,
+                    })
+                    .collect(),
+            };
+            assert!(folders.validate().is_err(), "{invalid:?}");
+        }
+        assert_eq!(
+            ProvisionFolder {
+                role: None,
+                name: Some("Projects".to_string()),
+                subscribe: true,
+            }
+            .special_use(),
+            Ok(SpecialUse::None)
+        );
+    }
+
+    #[test]
+    fn body_limits() {
+        let mut config = Config::new(
+            r#"
+[http.limits.body.route.a]
+path = "/organization/*/import/"
+max-size = 100
+
+[http.limits.body.route.b]
+path = "organization/x/import"
+max-size = 200
+
+[http.limits.body.route.c]
+path = "organization/*/uploads"
+max-size = 300
+streaming = true
+"#,
+        )
+        .unwrap();
+        let limits = HttpBodyLimits::parse(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+        // Literal segments take precedence over wildcards at the same depth
+        assert_eq!(limits.limit("/organization/x/import/imap"), 200);
+        assert_eq!(limits.limit("/organization/y/import/imap"), 100);
+        assert_eq!(limits.limit("/organization/provision"), 64 * 1024);
+        assert_eq!(limits.limit("/organization/x/activate"), 64 * 1024);
+        assert_eq!(limits.limit("/organization/x/uploads"), 300);
+        assert_eq!(
+            limits.limit("/principal/john/import/messages"),
+            1024 * 1024 * 1024
+        );
+        assert_eq!(limits.limit("/principal/john"), limits.default);
+
+        // Streaming is opt-in per route
+        assert!(limits.is_streaming("/principal/john/import/messages"));
+        assert!(limits.is_streaming("/organization/x/uploads"));
+        assert!(!limits.is_streaming("/organization/x/import/imap"));
+    }
+
+    #[test]
+    fn cors_credentials() {
+        let mut config = Config::new(
+            r#"
+[http.cors]
+origins = ["*"]
+allow-credentials = true
+"#,
+        )
+        .unwrap();
+        let cors = HttpCors::parse(&mut config);
+        assert!(config.errors.contains_key("http.cors.allow-credentials"));
+        assert!(!cors.allow_credentials);
+        assert_eq!(cors.origins, vec![CorsOrigin::Any]);
+
+        let mut config = Config::new(
+            r#"
+[http.cors]
+origins = ["https://*.example.org"]
+allow-credentials = true
+"#,
+        )
+        .unwrap();
+        let cors = HttpCors::parse(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert!(cors.allow_credentials);
+    }
+}
@@ -0,0 +1,190 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use types::acl::{Acl, AclGrant};
+use utils::{
+    config::{Config, ConfigKey, utils::ParseValue},
+    map::bitmap::Bitmap,
+};
+
+pub const TENANT_SHARED_MAILBOX_KEY: &str = "email.shared.tenant";
+
+/// Account of a tenant whose mailboxes are opened by several Individuals.
+/// Shared mailboxes have no password of their own, their folders are
+/// shared with every Individual in the access list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SharedMailbox {
+    pub account_id: u32,
+    pub access: Vec<SharedMailboxAccess>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharedMailboxAccess {
+    pub account_id: u32,
+    pub rights: SharedMailboxRights,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SharedMailboxRights {
+    /// Read messages, change their flags, add and remove messages.
+    ReadWrite,
+    /// Also manage the folders of the mailbox.
+    Full,
+}
+
+impl SharedMailbox {
+    pub fn parse_all(config: &mut Config, tenant_id: u32) -> Vec<Self> {
+        let prefix = format!("{TENANT_SHARED_MAILBOX_KEY}.{tenant_id}");
+        let mut mailboxes = Vec::new();
+
+        for id in config.sub_keys(prefix.as_str(), ".enable") {
+            let Ok(account_id) = id.parse::<u32>() else {
+                config.new_parse_error((prefix.as_str(), id.as_str()), "Invalid account id");
+                continue;
+            };
+            let access_prefix = format!("{prefix}.{id}.access");
+            let mut access = Vec::new();
+            for member_id in config.sub_keys(access_prefix.as_str(), "") {
+                let Ok(member_account_id) = member_id.parse::<u32>() else {
+                    config.new_parse_error(
+                        (access_prefix.as_str(), member_id.as_str()),
+                        "Invalid account id",
+                    );
+                    continue;
+                };
+                if let Some(rights) =
+                    config.property_require((access_prefix.as_str(), member_id.as_str()))
+                {
+                    access.push(SharedMailboxAccess {
+                        account_id: member_account_id,
+                        rights,
+                    });
+                }
+            }
+
+            mailboxes.push(SharedMailbox { account_id, access });
+        }
+
+        mailboxes
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!(
+            "{TENANT_SHARED_MAILBOX_KEY}.{tenant_id}.{}",
+            self.account_id
+        );
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.enable"),
+            value: "true".to_string(),
+        }];
+
+        for access in &self.access {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.access.{}", access.account_id),
+                value: access.rights.as_str().to_string(),
+            });
+        }
+
+        keys
+    }
+
+    /// Returns the grants to apply to every folder of the mailbox.
+    pub fn acl_grants(&self) -> Vec<AclGrant> {
+        self.access
+            .iter()
+            .map(|access| AclGrant {
+                account_id: access.account_id,
+                grants: access.rights.grants(),
+            })
+            .collect()
+    }
+}
+
+impl SharedMailboxRights {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SharedMailboxRights::ReadWrite => "read-write",
+            SharedMailboxRights::Full => "full",
+        }
+    }
+
+    pub fn grants(&self) -> Bitmap<Acl> {
+        let mut grants = Bitmap::from_iter([
+            Acl::Read,
+            Acl::ReadItems,
+            Acl::AddItems,
+            Acl::ModifyItems,
+            Acl::RemoveItems,
+            Acl::Submit,
+        ]);
+        if *self == SharedMailboxRights::Full {
+            grants.union(&Bitmap::from_iter([
+                Acl::Modify,
+                Acl::Delete,
+                Acl::CreateChild,
+                Acl::Share,
+            ]));
+        }
+        grants
+    }
+}
+
+impl ParseValue for SharedMailboxRights {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        match value {
+            "read-write" => Ok(SharedMailboxRights::ReadWrite),
+            "full" => Ok(SharedMailboxRights::Full),
+            _ => Err(format!("Invalid shared mailbox rights {:?}", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SharedMailbox, SharedMailboxAccess, SharedMailboxRights};
+    use types::acl::Acl;
+    use utils::config::Config;
+
+    #[test]
+    fn shared_mailbox_config() {
+        let mailboxes = vec![
+            SharedMailbox {
+                account_id: 10,
+                access: vec![
+                    SharedMailboxAccess {
+                        account_id: 20,
+                        rights: SharedMailboxRights::ReadWrite,
+                    },
+                    SharedMailboxAccess {
+                        account_id: 30,
+                        rights: SharedMailboxRights::Full,
+                    },
+                ],
+            },
+            SharedMailbox {
+                account_id: 11,
+                access: vec![],
+            },
+        ];
+
+        let mut config = Config::default();
+        for mailbox in &mailboxes {
+            for key in mailbox.config_keys(1) {
+                config.keys.insert(key.key, key.value);
+            }
+        }
+        assert_eq!(SharedMailbox::parse_all(&mut config, 1), mailboxes);
+        assert!(SharedMailbox::parse_all(&mut config, 2).is_empty());
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+        // Only full access allows managing folders
+        let grants = mailboxes[0].acl_grants();
+        assert!(grants[0].grants.contains(Acl::ModifyItems));
+        assert!(!grants[0].grants.contains(Acl::CreateChild));
+        assert!(grants[1].grants.contains(Acl::CreateChild));
+    }
+}
@@ -0,0 +1,444 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+use super::branding::is_valid_logo_url;
+
+pub const LOGIN_PAGE_KEY: &str = "http.login-page";
+pub const TENANT_LOGIN_PAGE_KEY: &str = "http.login-page.tenant";
+pub const DOMAIN_LOGIN_PAGE_KEY: &str = "http.login-page.domain";
+
+pub const MAX_TITLE_LEN: usize = 128;
+pub const MAX_SUBTITLE_LEN: usize = 512;
+pub const MAX_CSS_LEN: usize = 8192;
+
+const LOGIN_PAGE_SUFFIXES: [&str; 6] = [
+    ".title",
+    ".subtitle",
+    ".support-url",
+    ".terms-url",
+    ".privacy-url",
+    ".css",
+];
+
+/// Properties that custom login page styles may set, anything able to load
+/// resources or run code is left out.
+const CSS_PROPERTIES: &[&str] = &[
+    "background-color",
+    "border",
+    "border-bottom",
+    "border-color",
+    "border-left",
+    "border-radius",
+    "border-right",
+    "border-style",
+    "border-top",
+    "border-width",
+    "box-shadow",
+    "color",
+    "display",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "height",
+    "letter-spacing",
+    "line-height",
+    "margin",
+    "margin-bottom",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "max-height",
+    "max-width",
+    "min-height",
+    "min-width",
+    "opacity",
+    "padding",
+    "padding-bottom",
+    "padding-left",
+    "padding-right",
+    "padding-top",
+    "text-align",
+    "text-decoration",
+    "text-transform",
+    "width",
+];
+
+/// Texts, links and styles of the login page served for a domain or for
+/// the domains of a tenant. Fields that are not set use the defaults of the
+/// tenant branding.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct LoginPage {
+    /// Replaces the brand name as the page title and heading.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    /// Help link, either a web page or a `mailto:` address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub support_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy_url: Option<String>,
+    /// Style rules appended to the page, restricted to plain selectors and
+    /// the properties in `CSS_PROPERTIES`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub css: Option<String>,
+}
+
+impl LoginPage {
+    pub fn parse_all_tenants(config: &mut Config) -> AHashMap<u32, Arc<LoginPage>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys_with_suffixes(TENANT_LOGIN_PAGE_KEY, &LOGIN_PAGE_SUFFIXES) {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_LOGIN_PAGE_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_LOGIN_PAGE_KEY}.{tenant_id}");
+            let mut page = LoginPage::parse(config, &prefix);
+
+            match page.sanitize() {
+                Ok(()) => {
+                    tenants.insert(tenant_id, Arc::new(page));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    pub fn parse_all_domains(config: &mut Config) -> AHashMap<String, Arc<LoginPage>> {
+        let mut domains = AHashMap::new();
+
+        for domain in config.sub_keys_with_suffixes(DOMAIN_LOGIN_PAGE_KEY, &LOGIN_PAGE_SUFFIXES) {
+            let prefix = format!("{DOMAIN_LOGIN_PAGE_KEY}.{domain}");
+            let mut page = LoginPage::parse(config, &prefix);
+
+            match page.sanitize() {
+                Ok(()) => {
+                    domains.insert(domain.to_lowercase(), Arc::new(page));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        domains
+    }
+
+    fn parse(config: &mut Config, prefix: &str) -> Self {
+        let mut page = LoginPage::default();
+        for (value, key) in page.fields_mut() {
+            *value = config.value((prefix, key)).map(|value| value.to_string());
+        }
+        page
+    }
+
+    /// Validates the texts and links and rewrites the custom styles keeping
+    /// only the allowed rules, which are rejected when anything else is
+    /// found.
+    pub fn sanitize(&mut self) -> Result<(), String> {
+        for (value, field, max_len) in [
+            (&mut self.title, "title", MAX_TITLE_LEN),
+            (&mut self.subtitle, "subtitle", MAX_SUBTITLE_LEN),
+        ] {
+            if let Some(text) = value {
+                let trimmed = text.trim();
+                if trimmed.len() > max_len {
+                    return Err(format!("The {field} exceeds {max_len} characters"));
+                } else if trimmed.chars().any(|ch| ch.is_control() && ch != '\n') {
+                    return Err(format!("The {field} contains control characters"));
+                } else if trimmed.is_empty() {
+                    *value = None;
+                } else if trimmed.len() != text.len() {
+                    *text = trimmed.to_string();
+                }
+            }
+        }
+        for (value, field) in [
+            (&self.support_url, "supportUrl"),
+            (&self.terms_url, "termsUrl"),
+            (&self.privacy_url, "privacyUrl"),
+        ] {
+            if let Some(url) = value
+                && !is_valid_logo_url(url)
+                && !(field == "supportUrl" && is_valid_mailto(url))
+            {
+                return Err(format!(
+                    "Invalid {field} {url:?}, expected an HTTP or HTTPS URL"
+                ));
+            }
+        }
+        if let Some(css) = &self.css {
+            let css = sanitize_css(css)?;
+            self.css = (!css.is_empty()).then_some(css);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the values that are set in `other`.
+    pub fn merge(&mut self, other: LoginPage) {
+        for ((value, _), (other, _)) in self.fields_mut().into_iter().zip(other.into_fields()) {
+            if other.is_some() {
+                *value = other;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &LoginPage::default()
+    }
+
+    pub fn tenant_config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        self.prefix_config_keys(&format!("{TENANT_LOGIN_PAGE_KEY}.{tenant_id}"))
+    }
+
+    pub fn domain_config_keys(&self, domain: &str) -> Vec<ConfigKey> {
+        self.prefix_config_keys(&format!("{DOMAIN_LOGIN_PAGE_KEY}.{domain}"))
+    }
+
+    fn prefix_config_keys(&self, prefix: &str) -> Vec<ConfigKey> {
+        self.clone()
+            .into_fields()
+            .into_iter()
+            .filter_map(|(value, key)| {
+                value.map(|value| ConfigKey {
+                    key: format!("{prefix}.{key}"),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn fields_mut(&mut self) -> [(&mut Option<String>, &'static str); 6] {
+        [
+            (&mut self.title, "title"),
+            (&mut self.subtitle, "subtitle"),
+            (&mut self.support_url, "support-url"),
+            (&mut self.terms_url, "terms-url"),
+            (&mut self.privacy_url, "privacy-url"),
+            (&mut self.css, "css"),
+        ]
+    }
+
+    fn into_fields(self) -> [(Option<String>, &'static str); 6] {
+        [
+            (self.title, "title"),
+            (self.subtitle, "subtitle"),
+            (self.support_url, "support-url"),
+            (self.terms_url, "terms-url"),
+            (self.privacy_url, "privacy-url"),
+            (self.css, "css"),
+        ]
+    }
+}
+
+/// Parses custom styles into `selector { property: value; }` rules. At-rules,
+/// escapes, markup and any value able to load a resource or run code are
+/// rejected, so the result can be written into a `<style>` element as is.
+pub fn sanitize_css(css: &str) -> Result<String, String> {
+    if css.len() > MAX_CSS_LEN {
+        return Err(format!("Custom CSS exceeds {MAX_CSS_LEN} bytes"));
+    }
+
+    // Comments are dropped
+    let mut source = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some((before, after)) = rest.split_once("/*") {
+        source.push_str(before);
+        rest = after
+            .split_once("*/")
+            .ok_or_else(|| "Unterminated comment in custom CSS".to_string())?
+            .1;
+    }
+    source.push_str(rest);
+
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source.as_str();
+    loop {
+        let Some((selector, after)) = rest.split_once('{') else {
+            if !rest.trim().is_empty() {
+                return Err(format!(
+                    "Unexpected {:?} in custom CSS, expected a rule",
+                    rest.trim()
+                ));
+            }
+            break;
+        };
+        let (block, after) = after
+            .split_once('}')
+            .ok_or_else(|| "Unterminated rule in custom CSS".to_string())?;
+        rest = after;
+
+        let selector = selector.split_whitespace().collect::<Vec<_>>().join(" ");
+        if selector.is_empty()
+            || !selector
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || b" .#-_:,>*+~".contains(&ch))
+        {
+            return Err(format!("Unsupported selector {selector:?} in custom CSS"));
+        }
+        if block.contains('{') {
+            return Err(format!("Nested rules are not supported in {selector:?}"));
+        }
+
+        let mut declarations = Vec::new();
+        for declaration in block.split(';') {
+            if declaration.trim().is_empty() {
+                continue;
+            }
+            let (property, value) = declaration.split_once(':').ok_or_else(|| {
+                format!("Invalid declaration {:?} in custom CSS", declaration.trim())
+            })?;
+            let property = property.trim().to_ascii_lowercase();
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !CSS_PROPERTIES.contains(&property.as_str()) {
+                return Err(format!(
+                    "Property {property:?} is not allowed in custom CSS"
+                ));
+            }
+            let lower_value = value.to_ascii_lowercase();
+            if value.is_empty()
+                || !value
+                    .bytes()
+                    .all(|ch| ch.is_ascii_alphanumeric() || b" #%.,()-+/!'\"".contains(&ch))
+                || ["url(", "expression(", "javascript:", "image-set("]
+                    .iter()
+                    .any(|token| lower_value.contains(token))
+            {
+                return Err(format!(
+                    "Invalid value {value:?} for {property:?} in custom CSS"
+                ));
+            }
+            declarations.push(format!("{property}: {value};"));
+        }
+
+        if !declarations.is_empty() {
+            result.push_str(&selector);
+            result.push_str(" { ");
+            result.push_str(&declarations.join(" "));
+            result.push_str(" }\n");
+        }
+    }
+
+    Ok(result)
+}
+
+fn is_valid_mailto(value: &str) -> bool {
+    value.strip_prefix("mailto:").is_some_and(|address| {
+        address.contains('@')
+            && value.len() <= 320
+            && !address
+                .chars()
+                .any(|ch| ch.is_whitespace() || ch.is_control() || matches!(ch, '<' | '>' | '"'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoginPage, sanitize_css};
+    use crate::config::branding::{BrandVariant, Branding};
+
+    #[test]
+    fn login_page_css() {
+        assert_eq!(
+            sanitize_css(
+                "/* brand */ h1 {\n  color: #ff0000;\n  FONT-SIZE: 28px }\n.card, .footer > a { border-radius: 0 }"
+            )
+            .unwrap(),
+            "h1 { color: #ff0000; font-size: 28px; }\n.card, .footer > a { border-radius: 0; }\n"
+        );
+        for css in [
+            "body { background: url(https://evil.org/x.png) }",
+            "body { background-image: none }",
+            "@import 'https://evil.org/x.css';",
+            "h1 { color: red } </style><script>alert(1)</script>",
+            "h1 { width: expression(alert(1)) }",
+            "h1 { color: \\72 ed }",
+            "h1 { color: red",
+            "h1[title] { color: red }",
+            "h1 { color: red; } }",
+        ] {
+            assert!(sanitize_css(css).is_err(), "{css}");
+        }
+
+        let mut page = LoginPage {
+            title: Some("  Acme Webmail ".into()),
+            subtitle: Some("".into()),
+            support_url: Some("mailto:help@acme.org".into()),
+            css: Some("/* empty */".into()),
+            ..Default::default()
+        };
+        page.sanitize().unwrap();
+        assert_eq!(
+            page,
+            LoginPage {
+                title: Some("Acme Webmail".into()),
+                support_url: Some("mailto:help@acme.org".into()),
+                ..Default::default()
+            }
+        );
+        for page in [
+            LoginPage {
+                terms_url: Some("javascript:alert(1)".into()),
+                ..Default::default()
+            },
+            LoginPage {
+                privacy_url: Some("mailto:help@acme.org".into()),
+                ..Default::default()
+            },
+            LoginPage {
+                title: Some("x".repeat(200)),
+                ..Default::default()
+            },
+        ] {
+            assert!(page.clone().sanitize().is_err(), "{page:?}");
+        }
+
+        // Texts and links are escaped, the title replaces the brand name
+        let mut page = LoginPage {
+            title: Some("Acme <Mail>".into()),
+            subtitle: Some("Sign in with your \"work\" account".into()),
+            terms_url: Some("https://acme.org/terms?a=1&b=2".into()),
+            css: Some("h1 { color: #ff0000 }".into()),
+            ..Default::default()
+        };
+        page.sanitize().unwrap();
+        let branding = Branding {
+            name: "Acme".into(),
+            ..Default::default()
+        };
+        let login = branding.render_login(BrandVariant::Light, Some(&page));
+        assert!(
+            login.contains("<title>Acme &lt;Mail&gt;</title>"),
+            "{login}"
+        );
+        assert!(login.contains("&quot;work&quot; account"), "{login}");
+        assert!(
+            login.contains("https://acme.org/terms?a=1&amp;b=2"),
+            "{login}"
+        );
+        assert!(login.contains("h1 { color: #ff0000; }"), "{login}");
+        assert!(!login.contains("Privacy policy"), "{login}");
+        assert!(!login.contains("Need help?"), "{login}");
+        let login = branding.render_login(BrandVariant::Light, None);
+        assert!(login.contains("<h1>Acme</h1>"), "{login}");
+        assert!(!login.contains("class=\"subtitle\""), "{login}");
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+pub const TENANT_MAINTENANCE_KEY: &str = "tenant.maintenance";
+
+/// Maintenance window of a tenant. While it is active the tenant's users
+/// can't log in and mail for its domains is deferred.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantMaintenance {
+    pub started_at: u64,
+    pub ends_at: Option<u64>,
+    pub message: Option<String>,
+    /// Set when the tenant was suspended because its trial lapsed, the
+    /// suspension is lifted when the trial is extended or converted.
+    pub trial_expired: bool,
+}
+
+impl TenantMaintenance {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantMaintenance>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_MAINTENANCE_KEY, ".started-at") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_MAINTENANCE_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_MAINTENANCE_KEY}.{tenant_id}");
+            let Some(started_at) = config.property::<u64>((prefix.as_str(), "started-at")) else {
+                continue;
+            };
+            tenants.insert(
+                tenant_id,
+                Arc::new(TenantMaintenance {
+                    started_at,
+                    ends_at: config.property::<u64>((prefix.as_str(), "ends-at")),
+                    message: config
+                        .value((prefix.as_str(), "message"))
+                        .map(|message| message.to_string()),
+                    trial_expired: config
+                        .property::<bool>((prefix.as_str(), "trial-expired"))
+                        .unwrap_or_default(),
+                }),
+            );
+        }
+
+        tenants
+    }
+
+    /// Maintenance ends on its own once the expected end time is reached.
+    pub fn is_active(&self, now: u64) -> bool {
+        self.ends_at.is_none_or(|ends_at| ends_at > now)
+    }
+
+    /// Text returned to clients that are turned away.
+    pub fn reason(&self) -> String {
+        if self.trial_expired {
+            return "Trial period ended, contact your provider.".to_string();
+        }
+        match &self.message {
+            Some(message) => format!("Temporarily unavailable, try later: {message}"),
+            None => "Temporarily unavailable, try later.".to_string(),
+        }
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_MAINTENANCE_KEY}.{tenant_id}");
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.started-at"),
+            value: self.started_at.to_string(),
+        }];
+        if let Some(ends_at) = self.ends_at {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.ends-at"),
+                value: ends_at.to_string(),
+            });
+        }
+        if let Some(message) = &self.message {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.message"),
+                value: message.clone(),
+            });
+        }
+        if self.trial_expired {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.trial-expired"),
+                value: "true".to_string(),
+            });
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TenantMaintenance;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_maintenance() {
+        let maintenance = TenantMaintenance {
+            started_at: 1000,
+            ends_at: Some(2000),
+            message: Some("Mailbox migration".to_string()),
+            trial_expired: false,
+        };
+        let mut config = Config {
+            keys: maintenance
+                .config_keys(3)
+                .into_iter()
+                .chain(
+                    TenantMaintenance {
+                        started_at: 1500,
+                        ..Default::default()
+                    }
+                    .config_keys(4),
+                )
+                .chain(
+                    TenantMaintenance {
+                        started_at: 1500,
+                        trial_expired: true,
+                        ..Default::default()
+                    }
+                    .config_keys(5),
+                )
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantMaintenance::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(tenants[&3].as_ref(), &maintenance);
+        assert_eq!(tenants[&4].message, None);
+
+        // Maintenance clears itself at the end time
+        assert!(tenants[&3].is_active(1999));
+        assert!(!tenants[&3].is_active(2000));
+        assert!(tenants[&4].is_active(u64::MAX));
+        assert_eq!(
+            tenants[&3].reason(),
+            "Temporarily unavailable, try later: Mailbox migration"
+        );
+        assert!(tenants[&5].trial_expired);
+        assert!(!tenants[&4].trial_expired);
+        assert_eq!(
+            tenants[&5].reason(),
+            "Trial period ended, contact your provider."
+        );
+    }
+}
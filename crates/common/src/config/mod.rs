@@ -20,14 +20,23 @@ use ring::signature::{EcdsaKeyPair, RsaKeyPair};
 use spamfilter::SpamFilterConfig;
 use std::sync::Arc;
 use store::{BlobBackend, BlobStore, InMemoryStore, SearchStore, Store, Stores};
-use telemetry::Metrics;
+use telemetry::{AuditLog, Metering, Metrics};
 use utils::config::{Config, utils::AsKey};
 
+pub mod abuse;
+pub mod activation;
+pub mod branding;
 pub mod groupware;
+pub mod hostname;
 pub mod imap;
 pub mod inner;
 pub mod jmap;
+pub mod login_page;
+pub mod maintenance;
 pub mod network;
+pub mod overrides;
+pub mod password_policy;
+pub mod plan;
 pub mod scripts;
 pub mod server;
 pub mod smtp;
@@ -132,6 +141,18 @@ impl Core {
                 }
             })
             .unwrap_or_default();
+        let backup = config
+            .value("storage.backup")
+            .map(|id| id.to_string())
+            .and_then(|id| {
+                if let Some(store) = stores.blob_stores.get(&id) {
+                    store.clone().into()
+                } else {
+                    config
+                        .new_parse_error("storage.backup", format!("Blob store {id:?} not found"));
+                    None
+                }
+            });
         let pubsub = config
             .value("cluster.coordinator")
             .map(|id| id.to_string())
@@ -185,6 +206,8 @@ impl Core {
         }
 
         let groupware = GroupwareConfig::parse(config);
+        let audit = AuditLog::parse(config, &stores);
+        let metering = Metering::parse(config, &stores);
         Self {
             // SPDX-SnippetBegin
             // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
@@ -212,6 +235,9 @@ impl Core {
                 directories: directories.directories,
                 purge_schedules: stores.purge_schedules,
                 config: config_manager,
+                audit,
+                metering,
+                backup,
                 stores: stores.stores,
                 lookups: stores.in_memory_stores,
                 blobs: stores.blob_stores,
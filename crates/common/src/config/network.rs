@@ -45,6 +45,8 @@ pub struct ClusterRoles {
     pub spam_training: ClusterRole,
     pub imip_processing: ClusterRole,
     pub merge_threads: ClusterRole,
+    pub journaling: ClusterRole,
+    pub spf_monitoring: ClusterRole,
     pub calendar_alerts: ClusterRole,
     pub renew_acme: ClusterRole,
     pub calculate_metrics: ClusterRole,
@@ -261,6 +263,11 @@ impl Network {
                 &mut network.roles.merge_threads,
                 "cluster.roles.merge-threads",
             ),
+            (&mut network.roles.journaling, "cluster.roles.journaling"),
+            (
+                &mut network.roles.spf_monitoring,
+                "cluster.roles.spf-monitoring",
+            ),
         ] {
             let shards = config
                 .properties::<NodeList>(key)
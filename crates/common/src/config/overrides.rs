@@ -0,0 +1,623 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::auth::notice::SecurityNotice;
+use ahash::AHashMap;
+use directory::AuthProtocol;
+use std::{sync::Arc, time::Duration};
+use utils::config::{Config, ConfigKey, utils::ParseValue};
+
+pub const TENANT_OVERRIDES_KEY: &str = "tenant.override";
+
+/// Server settings that a tenant may override. Any other setting can only
+/// be changed globally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TenantSetting {
+    OAuthTokenExpiry,
+    OAuthRefreshTokenExpiry,
+    UploadMaxSize,
+    EmailMaxSize,
+    AttachmentMaxSize,
+    SessionMaxLifetime,
+    SessionIdleTimeout,
+    ImapIdleTimeout,
+    WebAuthnPasswordless,
+    SecurityNotice(SecurityNotice),
+    Protocol(TenantProtocol),
+}
+
+/// Protocols that can be disabled for the users of a tenant, all of them are
+/// enabled unless a tenant or its plan disables them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TenantProtocol {
+    Imap,
+    Pop3,
+    Jmap,
+    Dav,
+    Submission,
+    Webmail,
+}
+
+/// Values overridden by a tenant, durations in seconds and sizes in bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantOverrides {
+    pub values: AHashMap<TenantSetting, u64>,
+}
+
+/// Where the effective value of a setting comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingSource {
+    Tenant,
+    Plan,
+    Global,
+}
+
+/// Problems found when validating overrides. Errors prevent the overrides
+/// from being saved, warnings flag values that are likely unintended.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SettingsValidation {
+    pub errors: Vec<SettingIssue>,
+    pub warnings: Vec<SettingIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SettingIssue {
+    pub key: String,
+    pub message: String,
+}
+
+/// Limits of an authenticated session, resolved from the tenant of the
+/// account when the session is created. Durations are in seconds and zero
+/// disables a limit.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionLimits {
+    pub max_lifetime: u64,
+    pub idle_timeout: u64,
+    pub imap_idle_timeout: u64,
+    #[serde(skip)]
+    pub tenant_id: Option<u32>,
+    /// Overrides of the tenant the limits were resolved from, used to detect
+    /// changes to the settings of existing sessions.
+    #[serde(skip)]
+    pub overrides: Option<Arc<TenantOverrides>>,
+}
+
+impl AsRef<str> for TenantSetting {
+    fn as_ref(&self) -> &str {
+        self.key()
+    }
+}
+
+impl TenantSetting {
+    pub const ALL: [TenantSetting; 20] = [
+        TenantSetting::OAuthTokenExpiry,
+        TenantSetting::OAuthRefreshTokenExpiry,
+        TenantSetting::UploadMaxSize,
+        TenantSetting::EmailMaxSize,
+        TenantSetting::AttachmentMaxSize,
+        TenantSetting::SessionMaxLifetime,
+        TenantSetting::SessionIdleTimeout,
+        TenantSetting::ImapIdleTimeout,
+        TenantSetting::WebAuthnPasswordless,
+        TenantSetting::SecurityNotice(SecurityNotice::AdminCreated),
+        TenantSetting::SecurityNotice(SecurityNotice::PasswordChanged),
+        TenantSetting::SecurityNotice(SecurityNotice::MfaChanged),
+        TenantSetting::SecurityNotice(SecurityNotice::AppPasswordCreated),
+        TenantSetting::SecurityNotice(SecurityNotice::NewCountry),
+        TenantSetting::Protocol(TenantProtocol::Imap),
+        TenantSetting::Protocol(TenantProtocol::Pop3),
+        TenantSetting::Protocol(TenantProtocol::Jmap),
+        TenantSetting::Protocol(TenantProtocol::Dav),
+        TenantSetting::Protocol(TenantProtocol::Submission),
+        TenantSetting::Protocol(TenantProtocol::Webmail),
+    ];
+
+    pub fn parse(key: &str) -> Option<Self> {
+        TenantSetting::ALL
+            .into_iter()
+            .find(|setting| setting.key() == key)
+    }
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            TenantSetting::OAuthTokenExpiry => "oauth.expiry.token",
+            TenantSetting::OAuthRefreshTokenExpiry => "oauth.expiry.refresh-token",
+            TenantSetting::UploadMaxSize => "jmap.protocol.upload.max-size",
+            TenantSetting::EmailMaxSize => "jmap.email.max-size",
+            TenantSetting::AttachmentMaxSize => "jmap.email.max-attachment-size",
+            TenantSetting::SessionMaxLifetime => "authentication.session.max-lifetime",
+            TenantSetting::SessionIdleTimeout => "authentication.session.idle-timeout",
+            TenantSetting::ImapIdleTimeout => "imap.timeout.idle",
+            TenantSetting::WebAuthnPasswordless => "authentication.webauthn.passwordless",
+            TenantSetting::SecurityNotice(SecurityNotice::AdminCreated) => {
+                "authentication.security-notice.admin-created"
+            }
+            TenantSetting::SecurityNotice(SecurityNotice::PasswordChanged) => {
+                "authentication.security-notice.password-changed"
+            }
+            TenantSetting::SecurityNotice(SecurityNotice::MfaChanged) => {
+                "authentication.security-notice.mfa-changed"
+            }
+            TenantSetting::SecurityNotice(SecurityNotice::AppPasswordCreated) => {
+                "authentication.security-notice.app-password-created"
+            }
+            TenantSetting::SecurityNotice(SecurityNotice::NewCountry) => {
+                "authentication.security-notice.new-country"
+            }
+            TenantSetting::Protocol(TenantProtocol::Imap) => "protocol.imap.enable",
+            TenantSetting::Protocol(TenantProtocol::Pop3) => "protocol.pop3.enable",
+            TenantSetting::Protocol(TenantProtocol::Jmap) => "protocol.jmap.enable",
+            TenantSetting::Protocol(TenantProtocol::Dav) => "protocol.dav.enable",
+            TenantSetting::Protocol(TenantProtocol::Submission) => "protocol.submission.enable",
+            TenantSetting::Protocol(TenantProtocol::Webmail) => "protocol.webmail.enable",
+        }
+    }
+
+    fn is_duration(&self) -> bool {
+        matches!(
+            self,
+            TenantSetting::OAuthTokenExpiry
+                | TenantSetting::OAuthRefreshTokenExpiry
+                | TenantSetting::SessionMaxLifetime
+                | TenantSetting::SessionIdleTimeout
+                | TenantSetting::ImapIdleTimeout
+        )
+    }
+
+    /// Flags are stored as 1 when enabled and 0 when disabled.
+    pub fn is_flag(&self) -> bool {
+        matches!(
+            self,
+            TenantSetting::Protocol(_)
+                | TenantSetting::WebAuthnPasswordless
+                | TenantSetting::SecurityNotice(_)
+        )
+    }
+
+    /// Parses a value using the same syntax as the global setting.
+    pub fn parse_value(&self, value: &str) -> Result<u64, String> {
+        if self.is_duration() {
+            Duration::parse_value(value).map(|duration| duration.as_secs())
+        } else if self.is_flag() {
+            bool::parse_value(value).map(u64::from)
+        } else {
+            u64::parse_value(value)
+        }
+    }
+
+    pub fn config_value(&self, value: u64) -> String {
+        if self.is_duration() {
+            format!("{value}s")
+        } else if self.is_flag() {
+            (value != 0).to_string()
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+impl TenantProtocol {
+    pub const ALL: [TenantProtocol; 6] = [
+        TenantProtocol::Imap,
+        TenantProtocol::Pop3,
+        TenantProtocol::Jmap,
+        TenantProtocol::Dav,
+        TenantProtocol::Submission,
+        TenantProtocol::Webmail,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        TenantProtocol::ALL
+            .into_iter()
+            .find(|protocol| protocol.as_str() == value)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TenantProtocol::Imap => "imap",
+            TenantProtocol::Pop3 => "pop3",
+            TenantProtocol::Jmap => "jmap",
+            TenantProtocol::Dav => "dav",
+            TenantProtocol::Submission => "submission",
+            TenantProtocol::Webmail => "webmail",
+        }
+    }
+
+    pub fn bit(&self) -> u64 {
+        1 << (*self as u64)
+    }
+
+    /// Protocol restricted by the flag when authenticating over `protocol`,
+    /// HTTP requests are checked once the endpoint is known.
+    pub fn from_auth(protocol: AuthProtocol) -> Option<Self> {
+        match protocol {
+            AuthProtocol::Imap => Some(TenantProtocol::Imap),
+            AuthProtocol::Pop3 => Some(TenantProtocol::Pop3),
+            AuthProtocol::Smtp => Some(TenantProtocol::Submission),
+            AuthProtocol::ManageSieve | AuthProtocol::Http | AuthProtocol::Management => None,
+        }
+    }
+}
+
+impl TenantOverrides {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantOverrides>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_OVERRIDES_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_OVERRIDES_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let overrides = TenantOverrides::parse(config, tenant_id);
+            if !overrides.is_empty() {
+                tenants.insert(tenant_id, Arc::new(overrides));
+            }
+        }
+
+        tenants
+    }
+
+    pub fn parse(config: &mut Config, tenant_id: u32) -> Self {
+        Self::parse_prefix(config, &format!("{TENANT_OVERRIDES_KEY}.{tenant_id}"))
+    }
+
+    /// Parses the settings found under a prefix, also used by billing plans.
+    pub fn parse_prefix(config: &mut Config, prefix: &str) -> Self {
+        let mut values = AHashMap::new();
+
+        for setting in TenantSetting::ALL {
+            let key = format!("{prefix}.{}", setting.key());
+            if let Some(value) = config.value(key.as_str()) {
+                match setting.parse_value(value) {
+                    Ok(value) => {
+                        values.insert(setting, value);
+                    }
+                    Err(err) => {
+                        config.new_parse_error(key, err);
+                    }
+                }
+            }
+        }
+
+        TenantOverrides { values }
+    }
+
+    pub fn get(&self, setting: TenantSetting) -> Option<u64> {
+        self.values.get(&setting).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn has_protocol_flags(&self) -> bool {
+        self.values
+            .keys()
+            .any(|setting| matches!(setting, TenantSetting::Protocol(_)))
+    }
+
+    /// Checks the overrides against each other and against the global values
+    /// of the settings that are not overridden.
+    pub fn validate(&self, global: impl Fn(TenantSetting) -> u64) -> SettingsValidation {
+        let mut result = SettingsValidation::default();
+        let effective = |setting| self.get(setting).unwrap_or_else(|| global(setting));
+
+        for (&setting, &value) in &self.values {
+            match setting {
+                TenantSetting::OAuthTokenExpiry | TenantSetting::OAuthRefreshTokenExpiry
+                    if value == 0 =>
+                {
+                    result.error(setting, "Token lifetimes must be at least one second");
+                }
+                TenantSetting::UploadMaxSize | TenantSetting::EmailMaxSize if value == 0 => {
+                    result.error(setting, "Size limits must be greater than zero");
+                }
+                TenantSetting::AttachmentMaxSize if value == 0 => {
+                    result.warning(setting, "A zero limit allows attachments of any size");
+                }
+                TenantSetting::ImapIdleTimeout if value == 0 => {
+                    result.error(setting, "Timeouts must be at least one second");
+                }
+                _ => {}
+            }
+            // Enabling a protocol is meaningful when the plan disables it
+            if value == global(setting) && !setting.is_flag() {
+                result.warning(setting, "Value is the same as the global setting");
+            }
+        }
+
+        if self.get(TenantSetting::OAuthRefreshTokenExpiry).is_some()
+            || self.get(TenantSetting::OAuthTokenExpiry).is_some()
+        {
+            let token = effective(TenantSetting::OAuthTokenExpiry);
+            let refresh_token = effective(TenantSetting::OAuthRefreshTokenExpiry);
+            if refresh_token < token {
+                result.error(
+                    TenantSetting::OAuthRefreshTokenExpiry,
+                    format!(
+                        "Refresh tokens must not expire before access tokens ({refresh_token}s < {token}s)"
+                    ),
+                );
+            }
+        }
+
+        if self.get(TenantSetting::AttachmentMaxSize).is_some()
+            || self.get(TenantSetting::EmailMaxSize).is_some()
+        {
+            let attachments = effective(TenantSetting::AttachmentMaxSize);
+            let message = effective(TenantSetting::EmailMaxSize);
+            if attachments > message {
+                result.warning(
+                    TenantSetting::AttachmentMaxSize,
+                    format!(
+                        "Attachments are limited by the maximum message size ({attachments} > {message} bytes)"
+                    ),
+                );
+            }
+        }
+
+        if self.get(TenantSetting::SessionIdleTimeout).is_some()
+            || self.get(TenantSetting::SessionMaxLifetime).is_some()
+        {
+            let idle_timeout = effective(TenantSetting::SessionIdleTimeout);
+            let max_lifetime = effective(TenantSetting::SessionMaxLifetime);
+            if idle_timeout > 0 && max_lifetime > 0 && idle_timeout >= max_lifetime {
+                result.warning(
+                    TenantSetting::SessionIdleTimeout,
+                    format!(
+                        "Sessions reach their maximum lifetime before the idle timeout ({idle_timeout}s >= {max_lifetime}s)"
+                    ),
+                );
+            }
+        }
+
+        result
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        self.values
+            .iter()
+            .map(|(setting, value)| ConfigKey {
+                key: format!("{TENANT_OVERRIDES_KEY}.{tenant_id}.{}", setting.key()),
+                value: setting.config_value(*value),
+            })
+            .collect()
+    }
+}
+
+impl SessionLimits {
+    /// Whether a session created and last used at the given times, in
+    /// seconds since the epoch, has ended.
+    pub fn is_expired(&self, created_at: u64, last_used: u64, now: u64) -> bool {
+        self.expires_at(created_at, last_used)
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Time at which a session ends unless it is used again.
+    pub fn expires_at(&self, created_at: u64, last_used: u64) -> Option<u64> {
+        [
+            (self.max_lifetime > 0).then(|| created_at + self.max_lifetime),
+            (self.idle_timeout > 0).then(|| last_used + self.idle_timeout),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+}
+
+impl SettingsValidation {
+    pub fn error(&mut self, key: impl AsRef<str>, message: impl Into<String>) {
+        self.errors.push(SettingIssue {
+            key: key.as_ref().to_string(),
+            message: message.into(),
+        });
+    }
+
+    pub fn warning(&mut self, key: impl AsRef<str>, message: impl Into<String>) {
+        self.warnings.push(SettingIssue {
+            key: key.as_ref().to_string(),
+            message: message.into(),
+        });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionLimits, TenantOverrides, TenantProtocol, TenantSetting};
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_overrides() {
+        let mut overrides = TenantOverrides::default();
+        overrides
+            .values
+            .insert(TenantSetting::OAuthTokenExpiry, 2 * 3600);
+        overrides
+            .values
+            .insert(TenantSetting::AttachmentMaxSize, 10_000_000);
+
+        // Overrides are stored as config keys and parsed back
+        let mut config = Config {
+            keys: overrides
+                .config_keys(7)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(TenantOverrides::parse(&mut config, 7), overrides);
+        assert!(TenantOverrides::parse(&mut config, 8).is_empty());
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+        // Keys outside the whitelist are ignored
+        config.keys.insert(
+            "tenant.override.7.http.use-x-forwarded".to_string(),
+            "true".to_string(),
+        );
+        assert_eq!(
+            TenantOverrides::parse_all(&mut config)[&7].as_ref(),
+            &overrides
+        );
+        assert_eq!(TenantSetting::parse("http.use-x-forwarded"), None);
+
+        // Values use the syntax of the global setting
+        assert_eq!(
+            TenantSetting::OAuthRefreshTokenExpiry.parse_value("30d"),
+            Ok(30 * 86400)
+        );
+        assert_eq!(TenantSetting::EmailMaxSize.parse_value("1000"), Ok(1000));
+        assert!(TenantSetting::EmailMaxSize.parse_value("1mb").is_err());
+
+        // Overrides are checked against each other and the global values
+        let global = |setting| match setting {
+            TenantSetting::OAuthTokenExpiry => 3600,
+            TenantSetting::OAuthRefreshTokenExpiry => 30 * 86400,
+            _ => 50_000_000,
+        };
+        let validation = overrides.validate(global);
+        assert!(!validation.has_errors(), "{validation:?}");
+        assert!(validation.warnings.is_empty(), "{validation:?}");
+
+        let mut overrides = TenantOverrides::default();
+        overrides
+            .values
+            .insert(TenantSetting::OAuthRefreshTokenExpiry, 60);
+        overrides.values.insert(TenantSetting::EmailMaxSize, 0);
+        overrides
+            .values
+            .insert(TenantSetting::UploadMaxSize, 50_000_000);
+        overrides
+            .values
+            .insert(TenantSetting::AttachmentMaxSize, 60_000_000);
+        let validation = overrides.validate(global);
+        let mut errors = validation
+            .errors
+            .iter()
+            .map(|issue| issue.key.as_str())
+            .collect::<Vec<_>>();
+        errors.sort_unstable();
+        assert_eq!(
+            errors,
+            ["jmap.email.max-size", "oauth.expiry.refresh-token"]
+        );
+        let mut warnings = validation
+            .warnings
+            .iter()
+            .map(|issue| issue.key.as_str())
+            .collect::<Vec<_>>();
+        warnings.sort_unstable();
+        assert_eq!(
+            warnings,
+            [
+                "jmap.email.max-attachment-size",
+                "jmap.protocol.upload.max-size"
+            ]
+        );
+    }
+
+    #[test]
+    fn session_limits() {
+        let limits = SessionLimits {
+            max_lifetime: 8 * 3600,
+            idle_timeout: 15 * 60,
+            ..Default::default()
+        };
+        assert_eq!(limits.expires_at(1000, 2000), Some(2000 + 15 * 60));
+        assert_eq!(limits.expires_at(1000, 30000), Some(1000 + 8 * 3600));
+        assert!(!limits.is_expired(1000, 2000, 2000 + 15 * 60 - 1));
+        assert!(limits.is_expired(1000, 2000, 2000 + 15 * 60));
+        assert!(limits.is_expired(1000, 30000, 30001));
+        assert_eq!(SessionLimits::default().expires_at(0, 0), None);
+        assert!(!SessionLimits::default().is_expired(0, 0, u64::MAX));
+
+        // Idle timeouts longer than the maximum lifetime are flagged
+        let mut overrides = TenantOverrides::default();
+        overrides
+            .values
+            .insert(TenantSetting::SessionIdleTimeout, 2 * 3600);
+        overrides.values.insert(TenantSetting::ImapIdleTimeout, 0);
+        let validation = overrides.validate(|setting| match setting {
+            TenantSetting::SessionMaxLifetime => 3600,
+            _ => 0,
+        });
+        assert_eq!(
+            validation
+                .errors
+                .iter()
+                .map(|issue| issue.key.as_str())
+                .collect::<Vec<_>>(),
+            ["imap.timeout.idle"]
+        );
+        assert_eq!(
+            validation
+                .warnings
+                .iter()
+                .map(|issue| issue.key.as_str())
+                .collect::<Vec<_>>(),
+            ["authentication.session.idle-timeout"]
+        );
+        assert_eq!(
+            TenantSetting::parse("authentication.session.max-lifetime")
+                .unwrap()
+                .parse_value("15m"),
+            Ok(15 * 60)
+        );
+    }
+
+    #[test]
+    fn protocol_flags() {
+        let mut config = Config::new(
+            r#"
+[tenant.override.3.protocol]
+pop3.enable = false
+imap.enable = true
+dav.enable = "maybe"
+"#,
+        )
+        .unwrap();
+        let overrides = TenantOverrides::parse(&mut config, 3);
+        assert_eq!(
+            overrides.get(TenantSetting::Protocol(TenantProtocol::Pop3)),
+            Some(0)
+        );
+        assert_eq!(
+            overrides.get(TenantSetting::Protocol(TenantProtocol::Imap)),
+            Some(1)
+        );
+        assert_eq!(
+            overrides.get(TenantSetting::Protocol(TenantProtocol::Dav)),
+            None
+        );
+        assert_eq!(config.errors.len(), 1, "{:?}", config.errors);
+        assert!(overrides.has_protocol_flags());
+
+        // Flags are stored as booleans
+        let setting = TenantSetting::parse("protocol.submission.enable").unwrap();
+        assert_eq!(setting, TenantSetting::Protocol(TenantProtocol::Submission));
+        assert_eq!(setting.config_value(0), "false");
+        assert_eq!(setting.parse_value("true"), Ok(1));
+
+        // Enabling a protocol is not reported as redundant
+        let validation = overrides.validate(|_| 1);
+        assert!(!validation.has_errors(), "{validation:?}");
+        assert!(validation.warnings.is_empty(), "{validation:?}");
+
+        assert_eq!(
+            TenantProtocol::from_auth(directory::AuthProtocol::Smtp),
+            Some(TenantProtocol::Submission)
+        );
+        assert_eq!(
+            TenantProtocol::from_auth(directory::AuthProtocol::Http),
+            None
+        );
+    }
+}
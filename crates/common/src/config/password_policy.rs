@@ -0,0 +1,344 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use directory::backend::internal::password::{PasswordClass, PasswordPolicy};
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+pub const TENANT_PASSWORD_POLICY_KEY: &str = "tenant.password-policy";
+
+pub const MAX_PASSWORD_MIN_LENGTH: u32 = 128;
+pub const MAX_PASSWORD_AGE_DAYS: u32 = 3650;
+pub const MAX_DENY_LIST_ENTRIES: usize = 1000;
+
+/// Password policy of a tenant, the settings that are not provided are taken
+/// from the global policy. Denied passwords are added to the global list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenantPasswordPolicy {
+    #[serde(default)]
+    pub min_length: Option<u32>,
+    #[serde(default)]
+    pub required_classes: Option<Vec<PasswordClass>>,
+    #[serde(default)]
+    pub deny_list: Vec<String>,
+    #[serde(default)]
+    pub max_age_days: Option<u32>,
+    #[serde(default)]
+    pub allow_app_passwords: Option<bool>,
+}
+
+/// Parses the global password policy, which only applies to passwords
+/// provided in plain text.
+pub fn parse_password_policy(config: &mut Config) -> PasswordPolicy {
+    let mut required_classes = Vec::new();
+    for (key, value) in config
+        .values("directory.password.require")
+        .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+        .collect::<Vec<_>>()
+    {
+        match PasswordClass::parse(&value) {
+            Some(class) => required_classes.push(class),
+            None => {
+                config.new_parse_error(key, format!("Invalid character class {value:?}"));
+            }
+        }
+    }
+    required_classes.sort_unstable();
+    required_classes.dedup();
+
+    PasswordPolicy {
+        min_length: config
+            .property_or_default::<u32>("directory.password.min-length", "0")
+            .unwrap_or_default()
+            .min(MAX_PASSWORD_MIN_LENGTH),
+        required_classes,
+        deny_list: config
+            .values("directory.password.deny-list")
+            .map(|(_, password)| password.trim().to_lowercase())
+            .filter(|password| !password.is_empty())
+            .collect(),
+        max_age_days: config
+            .property_or_default::<u32>("directory.password.max-age-days", "0")
+            .unwrap_or_default()
+            .min(MAX_PASSWORD_AGE_DAYS),
+        allow_app_passwords: config
+            .property_or_default("directory.password.app-passwords", "true")
+            .unwrap_or(true),
+    }
+}
+
+impl TenantPasswordPolicy {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantPasswordPolicy>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_PASSWORD_POLICY_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error(
+                    (TENANT_PASSWORD_POLICY_KEY, id.as_str()),
+                    "Invalid tenant id",
+                );
+                continue;
+            };
+            let prefix = format!("{TENANT_PASSWORD_POLICY_KEY}.{tenant_id}");
+            let mut required_classes = None;
+            for class in config
+                .set_values((prefix.as_str(), "require"))
+                .map(|class| PasswordClass::parse(class).ok_or_else(|| class.to_string()))
+                .collect::<Vec<_>>()
+            {
+                match class {
+                    Ok(class) => required_classes.get_or_insert_with(Vec::new).push(class),
+                    Err(class) => {
+                        config.new_parse_error(
+                            (prefix.as_str(), "require"),
+                            format!("Invalid character class {class:?}"),
+                        );
+                    }
+                }
+            }
+            if config
+                .value((prefix.as_str(), "require"))
+                .is_some_and(|value| value.is_empty())
+            {
+                required_classes.get_or_insert_with(Vec::new);
+            }
+            let mut policy = TenantPasswordPolicy {
+                min_length: config.property((prefix.as_str(), "min-length")),
+                required_classes,
+                deny_list: config
+                    .values((prefix.as_str(), "deny-list"))
+                    .map(|(_, password)| password.to_string())
+                    .collect(),
+                max_age_days: config.property((prefix.as_str(), "max-age-days")),
+                allow_app_passwords: config.property((prefix.as_str(), "app-passwords")),
+            };
+
+            match policy.normalize() {
+                Ok(()) if !policy.is_empty() => {
+                    tenants.insert(tenant_id, Arc::new(policy));
+                }
+                Ok(()) => {}
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    /// Validates the policy, denied passwords are lowercased and sorted.
+    pub fn normalize(&mut self) -> Result<(), String> {
+        if self
+            .min_length
+            .is_some_and(|length| length > MAX_PASSWORD_MIN_LENGTH)
+        {
+            return Err(format!(
+                "The minimum length cannot exceed {MAX_PASSWORD_MIN_LENGTH} characters"
+            ));
+        } else if self
+            .max_age_days
+            .is_some_and(|days| days > MAX_PASSWORD_AGE_DAYS)
+        {
+            return Err(format!(
+                "The maximum age cannot exceed {MAX_PASSWORD_AGE_DAYS} days"
+            ));
+        } else if self.deny_list.len() > MAX_DENY_LIST_ENTRIES {
+            return Err(format!(
+                "The deny list cannot contain more than {MAX_DENY_LIST_ENTRIES} passwords"
+            ));
+        }
+
+        for password in &mut self.deny_list {
+            *password = password.trim().to_lowercase();
+            if password.is_empty() || password.len() > MAX_PASSWORD_MIN_LENGTH as usize {
+                return Err("Denied passwords must be between 1 and 128 bytes long".to_string());
+            }
+        }
+        self.deny_list.sort_unstable();
+        self.deny_list.dedup();
+        if let Some(classes) = &mut self.required_classes {
+            classes.sort_unstable();
+            classes.dedup();
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &TenantPasswordPolicy::default()
+    }
+
+    /// Applies the overrides of the tenant to the global policy, which a
+    /// tenant may make either stricter or looser.
+    pub fn resolve(&self, global: &PasswordPolicy) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: self.min_length.unwrap_or(global.min_length),
+            required_classes: self
+                .required_classes
+                .clone()
+                .unwrap_or_else(|| global.required_classes.clone()),
+            deny_list: global
+                .deny_list
+                .iter()
+                .chain(self.deny_list.iter())
+                .cloned()
+                .collect(),
+            max_age_days: self.max_age_days.unwrap_or(global.max_age_days),
+            allow_app_passwords: self
+                .allow_app_passwords
+                .unwrap_or(global.allow_app_passwords),
+        }
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_PASSWORD_POLICY_KEY}.{tenant_id}");
+        let mut keys = Vec::new();
+
+        for (key, value) in [
+            ("min-length", self.min_length.map(|v| v.to_string())),
+            ("max-age-days", self.max_age_days.map(|v| v.to_string())),
+            (
+                "app-passwords",
+                self.allow_app_passwords.map(|v| v.to_string()),
+            ),
+        ] {
+            if let Some(value) = value {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.{key}"),
+                    value,
+                });
+            }
+        }
+        match self.required_classes.as_deref() {
+            // An empty list removes the requirements of the global policy
+            Some([]) => {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.require"),
+                    value: String::new(),
+                });
+            }
+            Some(classes) => {
+                for class in classes {
+                    keys.push(ConfigKey {
+                        key: format!("{prefix}.require.{}", class.as_str()),
+                        value: String::new(),
+                    });
+                }
+            }
+            None => {}
+        }
+        for (idx, password) in self.deny_list.iter().enumerate() {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.deny-list.{idx:03}"),
+                value: password.clone(),
+            });
+        }
+
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TenantPasswordPolicy, parse_password_policy};
+    use directory::backend::internal::password::{PasswordClass, PasswordPolicy};
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_password_policy() {
+        let mut config = Config::new(
+            r#"
+[directory.password]
+min-length = 8
+require = ["digit", "uppercase"]
+deny-list = ["Password1"]
+max-age-days = 90
+"#,
+        )
+        .unwrap();
+        let global = parse_password_policy(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(
+            global.required_classes,
+            vec![PasswordClass::Uppercase, PasswordClass::Digit]
+        );
+        assert!(global.check("Secure123").is_ok());
+        assert!(global.check("password1").is_err());
+        assert_eq!(
+            global.check("short").unwrap_err(),
+            "The password must contain at least 8 characters, an uppercase letter, a digit"
+        );
+
+        // Stricter tenant policy, denied passwords are added to the global list
+        let mut stricter = TenantPasswordPolicy {
+            min_length: Some(12),
+            required_classes: Some(vec![PasswordClass::Symbol, PasswordClass::Digit]),
+            deny_list: vec![" AcmeRocks!2024 ".to_string()],
+            max_age_days: Some(30),
+            allow_app_passwords: Some(false),
+        };
+        assert_eq!(stricter.normalize(), Ok(()));
+        let policy = stricter.resolve(&global);
+        assert_eq!(policy.min_length, 12);
+        assert_eq!(policy.max_age_days, 30);
+        assert!(!policy.allow_app_passwords);
+        assert!(policy.check("Secure123").is_err());
+        assert!(policy.check("acmerocks!2024").is_err());
+        assert!(policy.check("password1").is_err());
+        assert!(policy.check("lowercase-only-1").is_ok());
+
+        // Looser tenant policy without character class requirements
+        let mut looser = TenantPasswordPolicy {
+            min_length: Some(4),
+            required_classes: Some(vec![]),
+            ..Default::default()
+        };
+        assert_eq!(looser.normalize(), Ok(()));
+        let policy = looser.resolve(&global);
+        assert!(policy.check("abcd").is_ok());
+        assert!(policy.check("abc").is_err());
+        assert!(policy.check("password1").is_err());
+        assert_eq!(policy.max_age_days, 90);
+        assert!(policy.is_expired(0, 90 * 86400));
+        assert!(!policy.is_expired(0, 90 * 86400 - 1));
+        assert!(!PasswordPolicy::default().is_expired(0, u64::MAX / 2));
+
+        // Policies are stored as config keys and parsed back
+        let mut config = Config {
+            keys: stricter
+                .config_keys(7)
+                .into_iter()
+                .chain(looser.config_keys(8))
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantPasswordPolicy::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(tenants.get(&7).map(|p| p.as_ref()), Some(&stricter));
+        assert_eq!(tenants.get(&8).map(|p| p.as_ref()), Some(&looser));
+
+        for mut invalid in [
+            TenantPasswordPolicy {
+                min_length: Some(1000),
+                ..Default::default()
+            },
+            TenantPasswordPolicy {
+                max_age_days: Some(100_000),
+                ..Default::default()
+            },
+            TenantPasswordPolicy {
+                deny_list: vec!["  ".to_string()],
+                ..Default::default()
+            },
+        ] {
+            assert!(invalid.normalize().is_err(), "{invalid:?}");
+        }
+    }
+}
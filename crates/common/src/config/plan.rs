@@ -0,0 +1,110 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use std::sync::Arc;
+use utils::config::{Config, Rate};
+
+use super::overrides::TenantOverrides;
+
+pub const PLAN_KEY: &str = "organization.plan";
+
+/// Limits and features shared by the tenants subscribed to a billing plan.
+/// Limits set on a tenant itself take precedence over those of its plan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantPlan {
+    pub name: String,
+    /// Storage quota in bytes.
+    pub quota: Option<u64>,
+    pub max_users: Option<u32>,
+    pub max_domains: Option<u32>,
+    /// Overridable settings, such as the maximum message size, using the
+    /// same keys and syntax as the tenant overrides.
+    pub settings: TenantOverrides,
+    /// Rate of authenticated HTTP requests of each account.
+    pub rate_limit: Option<Rate>,
+    pub features: Vec<String>,
+}
+
+impl TenantPlan {
+    pub fn parse_all(config: &mut Config) -> AHashMap<String, Arc<TenantPlan>> {
+        let mut plans = AHashMap::new();
+
+        for name in config.sub_keys(PLAN_KEY, "") {
+            let prefix = format!("{PLAN_KEY}.{name}");
+            let plan = TenantPlan {
+                quota: config.property::<u64>((prefix.as_str(), "quota")),
+                max_users: config.property::<u32>((prefix.as_str(), "max-users")),
+                max_domains: config.property::<u32>((prefix.as_str(), "max-domains")),
+                settings: TenantOverrides::parse_prefix(config, &prefix),
+                rate_limit: config.property::<Rate>((prefix.as_str(), "rate-limit")),
+                features: config
+                    .values((prefix.as_str(), "features"))
+                    .map(|(_, feature)| feature.trim().to_lowercase())
+                    .filter(|feature| !feature.is_empty())
+                    .collect(),
+                name: name.clone(),
+            };
+            plans.insert(name, Arc::new(plan));
+        }
+
+        plans
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TenantPlan;
+    use crate::config::overrides::TenantSetting;
+    use std::time::Duration;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_plans() {
+        let mut config = Config::new(
+            r#"
+[organization.plan.starter]
+max-users = 5
+max-domains = 1
+quota = 1073741824
+rate-limit = "100/1m"
+features = ["Imap-Import"]
+jmap.email.max-size = 10000000
+
+[organization.plan.enterprise]
+max-users = 1000
+"#,
+        )
+        .unwrap();
+        let plans = TenantPlan::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(plans.len(), 2);
+
+        let starter = &plans["starter"];
+        assert_eq!(starter.name, "starter");
+        assert_eq!(starter.max_users, Some(5));
+        assert_eq!(starter.max_domains, Some(1));
+        assert_eq!(starter.quota, Some(1073741824));
+        assert_eq!(
+            starter.rate_limit.as_ref().map(|rate| rate.period),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            starter.settings.get(TenantSetting::EmailMaxSize),
+            Some(10000000)
+        );
+        assert!(starter.has_feature("imap-import"));
+
+        let enterprise = &plans["enterprise"];
+        assert_eq!(enterprise.max_domains, None);
+        assert!(enterprise.settings.is_empty());
+        assert!(!enterprise.has_feature("imap-import"));
+    }
+}
@@ -9,10 +9,14 @@ use std::{sync::Arc, time::Duration};
 use ahash::AHashMap;
 use sieve::{Compiler, Runtime, Sieve, compiler::grammar::Capability};
 use store::Stores;
-use utils::config::Config;
+use utils::{
+    config::{Config, ConfigKey},
+    sanitize_email,
+};
 
 use crate::{
     VERSION_PUBLIC,
+    auth::AccessToken,
     scripts::{
         functions::{register_functions_trusted, register_functions_untrusted},
         plugins::RegisterSievePlugins,
@@ -24,6 +28,7 @@ use super::{if_block::IfBlock, smtp::SMTP_RCPT_TO_VARS, tokenizer::TokenMap};
 pub struct Scripting {
     pub untrusted_compiler: Compiler,
     pub untrusted_runtime: Runtime,
+    pub untrusted_max_redirects: usize,
     pub trusted_runtime: Runtime,
     pub from_addr: IfBlock,
     pub from_name: IfBlock,
@@ -35,6 +40,10 @@ pub struct Scripting {
 
 impl Scripting {
     pub async fn parse(config: &mut Config, stores: &Stores) -> Self {
+        let untrusted_max_redirects = config
+            .property("sieve.untrusted.limits.redirects")
+            .unwrap_or(1);
+
         // Parse untrusted compiler
         let mut fnc_map_untrusted = register_functions_untrusted().register_plugins_untrusted();
         let untrusted_compiler = Compiler::new()
@@ -108,11 +117,7 @@ impl Scripting {
                     .property("sieve.untrusted.limits.variable-size")
                     .unwrap_or(4096),
             )
-            .with_max_redirects(
-                config
-                    .property("sieve.untrusted.limits.redirects")
-                    .unwrap_or(1),
-            )
+            .with_max_redirects(untrusted_max_redirects)
             .with_max_received_headers(
                 config
                     .property("sieve.untrusted.limits.received-headers")
@@ -316,6 +321,7 @@ impl Scripting {
         Scripting {
             untrusted_compiler,
             untrusted_runtime,
+            untrusted_max_redirects,
             trusted_runtime,
             from_addr: IfBlock::try_parse(config, "sieve.trusted.from-addr", &token_map)
                 .unwrap_or_else(|| {
@@ -354,6 +360,7 @@ impl Default for Scripting {
         Scripting {
             untrusted_compiler: Compiler::new(),
             untrusted_runtime: Runtime::new(),
+            untrusted_max_redirects: 1,
             trusted_runtime: Runtime::new(),
             from_addr: IfBlock::new::<()>(
                 "sieve.trusted.from-addr",
@@ -381,6 +388,7 @@ impl Clone for Scripting {
         Self {
             untrusted_compiler: self.untrusted_compiler.clone(),
             untrusted_runtime: self.untrusted_runtime.clone(),
+            untrusted_max_redirects: self.untrusted_max_redirects,
             trusted_runtime: self.trusted_runtime.clone(),
             from_addr: self.from_addr.clone(),
             from_name: self.from_name.clone(),
@@ -391,3 +399,710 @@ impl Clone for Scripting {
         }
     }
 }
+
+pub const TENANT_SIEVE_KEY: &str = "sieve.tenant";
+
+#[derive(Debug, Clone)]
+pub struct TenantSieveScript {
+    pub name: String,
+    pub contents: String,
+    pub script: Arc<Sieve>,
+    pub assignment: SieveAssignment,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SieveAssignment {
+    #[serde(default)]
+    pub all: bool,
+    #[serde(default)]
+    pub groups: Vec<u32>,
+    #[serde(default)]
+    pub accounts: Vec<u32>,
+}
+
+impl TenantSieveScript {
+    pub fn parse_all(
+        config: &mut Config,
+        compiler: &Compiler,
+    ) -> AHashMap<u32, Vec<Arc<TenantSieveScript>>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_SIEVE_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_SIEVE_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let mut scripts = Vec::new();
+
+            for name in config.sub_keys((TENANT_SIEVE_KEY, id.as_str()), ".contents") {
+                let key = (TENANT_SIEVE_KEY, id.as_str(), name.as_str());
+                if let Err(err) = Self::validate_name(&name) {
+                    config.new_parse_error(key, err);
+                    continue;
+                }
+                let contents = config
+                    .value((TENANT_SIEVE_KEY, id.as_str(), name.as_str(), "contents"))
+                    .unwrap()
+                    .to_string();
+                let script = match compiler.compile(contents.as_bytes()) {
+                    Ok(script) => Arc::new(script),
+                    Err(err) => {
+                        config.new_build_error(
+                            (TENANT_SIEVE_KEY, id.as_str(), name.as_str(), "contents"),
+                            format!("Failed to compile tenant Sieve script: {err}"),
+                        );
+                        continue;
+                    }
+                };
+                let assignment = SieveAssignment {
+                    all: config
+                        .property((TENANT_SIEVE_KEY, id.as_str(), name.as_str(), "assign.all"))
+                        .unwrap_or(false),
+                    groups: config
+                        .set_values((TENANT_SIEVE_KEY, id.as_str(), name.as_str(), "assign.group"))
+                        .filter_map(|id| id.parse().ok())
+                        .collect(),
+                    accounts: config
+                        .set_values((
+                            TENANT_SIEVE_KEY,
+                            id.as_str(),
+                            name.as_str(),
+                            "assign.account",
+                        ))
+                        .filter_map(|id| id.parse().ok())
+                        .collect(),
+                };
+
+                scripts.push(Arc::new(TenantSieveScript {
+                    name,
+                    contents,
+                    script,
+                    assignment,
+                }));
+            }
+
+            if !scripts.is_empty() {
+                scripts.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+                tenants.insert(tenant_id, scripts);
+            }
+        }
+
+        tenants
+    }
+
+    /// Script names are used as configuration keys, so only lowercase
+    /// alphanumeric characters, dashes and underscores are allowed.
+    pub fn validate_name(name: &str) -> Result<(), String> {
+        if !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' || ch == '_')
+        {
+            Ok(())
+        } else {
+            Err(format!("Invalid script name {name:?}"))
+        }
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_SIEVE_KEY}.{tenant_id}.{}", self.name);
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.contents"),
+            value: self.contents.clone(),
+        }];
+
+        if self.assignment.all {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.assign.all"),
+                value: "true".to_string(),
+            });
+        }
+        for group_id in &self.assignment.groups {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.assign.group.{group_id}"),
+                value: String::new(),
+            });
+        }
+        for account_id in &self.assignment.accounts {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.assign.account.{account_id}"),
+                value: String::new(),
+            });
+        }
+
+        keys
+    }
+}
+
+impl SieveAssignment {
+    pub fn matches(&self, access_token: &AccessToken) -> bool {
+        self.all
+            || self.accounts.contains(&access_token.primary_id)
+            || access_token
+                .member_of
+                .iter()
+                .any(|group_id| self.groups.contains(group_id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.groups.is_empty() && self.accounts.is_empty()
+    }
+}
+
+pub const TENANT_VACATION_KEY: &str = "sieve.vacation";
+
+/// Placeholders that can be used in vacation templates.
+const VACATION_VARIABLES: [&str; 3] = ["name", "from", "to"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct VacationTemplate {
+    /// Subject used when the account's vacation response does not set one.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Body of the auto-reply, which may contain the `{{name}}`, `{{from}}`
+    /// and `{{to}}` placeholders.
+    #[serde(default)]
+    pub body: String,
+    /// Whether the body is appended to custom vacation texts.
+    #[serde(default)]
+    pub mandatory_footer: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VacationVariables<'x> {
+    pub name: &'x str,
+    pub from: Option<&'x str>,
+    pub to: Option<&'x str>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VacationReply {
+    pub subject: Option<String>,
+    pub text_body: Option<String>,
+    pub html_body: Option<String>,
+}
+
+impl VacationTemplate {
+    pub fn parse(config: &mut Config, tenant_id: u32) -> Option<Self> {
+        let id = tenant_id.to_string();
+        let body = config
+            .value((TENANT_VACATION_KEY, id.as_str(), "body"))?
+            .to_string();
+
+        Some(VacationTemplate {
+            subject: config
+                .value((TENANT_VACATION_KEY, id.as_str(), "subject"))
+                .map(|subject| subject.to_string()),
+            body,
+            mandatory_footer: config
+                .property((TENANT_VACATION_KEY, id.as_str(), "mandatory-footer"))
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.body.trim().is_empty() {
+            return Err("Template body is required".to_string());
+        } else if self.body.len() >= 2048 {
+            return Err("Template body is too long".to_string());
+        }
+        if let Some(subject) = &self.subject {
+            if subject.len() >= 512 {
+                return Err("Template subject is too long".to_string());
+            }
+            substitute(subject, &VacationVariables::default())?;
+        }
+        substitute(&self.body, &VacationVariables::default()).map(|_| ())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_VACATION_KEY}.{tenant_id}");
+        let mut keys = vec![
+            ConfigKey {
+                key: format!("{prefix}.body"),
+                value: self.body.clone(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.mandatory-footer"),
+                value: self.mandatory_footer.to_string(),
+            },
+        ];
+        if let Some(subject) = &self.subject {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.subject"),
+                value: subject.clone(),
+            });
+        }
+
+        keys
+    }
+
+    /// Builds the auto-reply of an account from its vacation response. The
+    /// template replaces responses without custom text and, when the footer
+    /// is mandatory, is appended to the custom text.
+    pub fn apply(
+        &self,
+        subject: Option<&str>,
+        text_body: Option<&str>,
+        html_body: Option<&str>,
+        variables: &VacationVariables<'_>,
+    ) -> VacationReply {
+        let body = substitute(&self.body, variables).unwrap_or_else(|_| self.body.clone());
+        let subject = subject.map(|subject| subject.to_string()).or_else(|| {
+            self.subject
+                .as_ref()
+                .map(|subject| substitute(subject, variables).unwrap_or_else(|_| subject.clone()))
+        });
+        let text_body = text_body.filter(|text| !text.trim().is_empty());
+        let html_body = html_body.filter(|html| !html.trim().is_empty());
+
+        if text_body.is_none() && html_body.is_none() {
+            VacationReply {
+                subject,
+                text_body: body.into(),
+                html_body: None,
+            }
+        } else if self.mandatory_footer {
+            VacationReply {
+                subject,
+                text_body: text_body.map(|text| format!("{text}\n\n{body}")),
+                html_body: html_body.map(|html| {
+                    let mut html = html.to_string();
+                    html.push_str("<p>");
+                    for ch in body.chars() {
+                        match ch {
+                            '&' => html.push_str("&amp;"),
+                            '<' => html.push_str("&lt;"),
+                            '>' => html.push_str("&gt;"),
+                            '"' => html.push_str("&quot;"),
+                            '\n' => html.push_str("<br>"),
+                            '\r' => (),
+                            _ => html.push(ch),
+                        }
+                    }
+                    html.push_str("</p>");
+                    html
+                }),
+            }
+        } else {
+            VacationReply {
+                subject,
+                text_body: text_body.map(|text| text.to_string()),
+                html_body: html_body.map(|html| html.to_string()),
+            }
+        }
+    }
+}
+
+fn substitute(text: &str, variables: &VacationVariables<'_>) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut text = text;
+
+    while let Some((start, end)) = text.split_once("{{") {
+        let (name, rest) = end.split_once("}}").ok_or("Unmatched {{")?;
+        result.push_str(start);
+        match name.trim() {
+            "name" => result.push_str(variables.name),
+            "from" => result.push_str(variables.from.unwrap_or_default()),
+            "to" => result.push_str(variables.to.unwrap_or_default()),
+            name => {
+                return Err(format!(
+                    "Unknown placeholder {name:?}, expected one of {}",
+                    VACATION_VARIABLES.join(", ")
+                ));
+            }
+        }
+        text = rest;
+    }
+    result.push_str(text);
+
+    Ok(result)
+}
+
+pub const FORWARDING_KEY: &str = "sieve.forward";
+pub const TENANT_FORWARDING_KEY: &str = "sieve.forward-policy";
+
+/// Forwarding rule set up by an administrator on behalf of an account. It
+/// runs before any other script of the account, so it can't be removed or
+/// bypassed by the account owner.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ForwardingRule {
+    pub addresses: Vec<String>,
+    /// Whether messages are also delivered to the account.
+    #[serde(default)]
+    pub keep_copy: bool,
+    /// Time after which the rule is no longer applied and gets removed.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ForwardingPolicy {
+    /// Whether messages can be forwarded to domains outside the tenant.
+    #[serde(default = "default_allow_external")]
+    pub allow_external: bool,
+}
+
+impl ForwardingRule {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<ForwardingRule>> {
+        let mut rules = AHashMap::new();
+
+        for id in config.sub_keys(FORWARDING_KEY, "") {
+            let Ok(account_id) = id.parse::<u32>() else {
+                config.new_parse_error((FORWARDING_KEY, id.as_str()), "Invalid account id");
+                continue;
+            };
+            let addresses = config
+                .set_values((FORWARDING_KEY, id.as_str(), "to"))
+                .map(|address| address.to_string())
+                .collect::<Vec<_>>();
+            if addresses.is_empty() {
+                continue;
+            }
+
+            rules.insert(
+                account_id,
+                Arc::new(ForwardingRule {
+                    addresses,
+                    keep_copy: config
+                        .property((FORWARDING_KEY, id.as_str(), "keep-copy"))
+                        .unwrap_or(false),
+                    expires: config.property((FORWARDING_KEY, id.as_str(), "expires")),
+                }),
+            );
+        }
+
+        rules
+    }
+
+    /// Normalizes the addresses of the rule and checks that it can be
+    /// applied. Each address is a redirect, so the number of addresses is
+    /// bounded by the redirect limit of untrusted scripts.
+    pub fn validate(&mut self, now: u64, max_addresses: usize) -> Result<(), String> {
+        let mut addresses = Vec::with_capacity(self.addresses.len());
+        for address in &self.addresses {
+            let address = sanitize_email(address)
+                .ok_or_else(|| format!("Invalid forwarding address {address:?}"))?;
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+
+        if addresses.is_empty() {
+            Err("At least one forwarding address is required".to_string())
+        } else if addresses.len() > max_addresses {
+            Err(format!(
+                "Messages cannot be forwarded to more than {max_addresses} addresses"
+            ))
+        } else if self.is_expired(now) {
+            Err("Expiry date must be in the future".to_string())
+        } else {
+            self.addresses = addresses;
+            Ok(())
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    pub fn config_keys(&self, account_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{FORWARDING_KEY}.{account_id}");
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.keep-copy"),
+            value: self.keep_copy.to_string(),
+        }];
+        for address in &self.addresses {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.to.{address}"),
+                value: String::new(),
+            });
+        }
+        if let Some(expires) = self.expires {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.expires"),
+                value: expires.to_string(),
+            });
+        }
+
+        keys
+    }
+
+    /// Builds the script that redirects messages to the given addresses.
+    /// Redirecting cancels the implicit keep, so a copy is kept explicitly
+    /// when requested and otherwise no further scripts are run.
+    pub fn build_script(&self, addresses: &[&str]) -> String {
+        let mut script = String::new();
+        for address in addresses {
+            script.push_str("redirect \"");
+            for ch in address.chars() {
+                if matches!(ch, '"' | '\\') {
+                    script.push('\\');
+                }
+                script.push(ch);
+            }
+            script.push_str("\";\n");
+        }
+        if self.keep_copy {
+            script.push_str("keep;\n");
+        } else {
+            script.push_str("stop;\n");
+        }
+
+        script
+    }
+}
+
+impl ForwardingPolicy {
+    pub fn parse(config: &mut Config, tenant_id: u32) -> Self {
+        ForwardingPolicy {
+            allow_external: config
+                .property((
+                    TENANT_FORWARDING_KEY,
+                    tenant_id.to_string().as_str(),
+                    "allow-external",
+                ))
+                .unwrap_or_else(default_allow_external),
+        }
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        vec![ConfigKey {
+            key: format!("{TENANT_FORWARDING_KEY}.{tenant_id}.allow-external"),
+            value: self.allow_external.to_string(),
+        }]
+    }
+}
+
+impl Default for ForwardingPolicy {
+    fn default() -> Self {
+        ForwardingPolicy {
+            allow_external: default_allow_external(),
+        }
+    }
+}
+
+fn default_allow_external() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ForwardingPolicy, ForwardingRule, SieveAssignment, TenantSieveScript, VacationReply,
+        VacationTemplate, VacationVariables,
+    };
+    use sieve::Compiler;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_sieve_scripts() {
+        let compiler = Compiler::new();
+        let contents = "require \"fileinto\"; fileinto \"Compliance\";";
+        let script = TenantSieveScript {
+            name: "compliance".to_string(),
+            contents: contents.to_string(),
+            script: compiler.compile(contents.as_bytes()).unwrap().into(),
+            assignment: SieveAssignment {
+                all: false,
+                groups: vec![5],
+                accounts: vec![7, 9],
+            },
+        };
+
+        // Scripts are stored as config keys and parsed back
+        let mut config = Config {
+            keys: script
+                .config_keys(3)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantSieveScript::parse_all(&mut config, &compiler);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        let parsed = &tenants.get(&3).unwrap()[0];
+        assert_eq!(parsed.name, script.name);
+        assert_eq!(parsed.contents, script.contents);
+        assert_eq!(parsed.assignment, script.assignment);
+
+        // Scripts that no longer compile are reported
+        config.keys.insert(
+            "sieve.tenant.3.compliance.contents".to_string(),
+            "fileinto \"Compliance\";".to_string(),
+        );
+        assert!(TenantSieveScript::parse_all(&mut config, &compiler).is_empty());
+        assert!(!config.errors.is_empty());
+
+        assert!(TenantSieveScript::validate_name("footer_v2").is_ok());
+        for invalid in ["", "Footer", "footer.sieve", "tenant/footer"] {
+            assert!(
+                TenantSieveScript::validate_name(invalid).is_err(),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn vacation_template() {
+        let template = VacationTemplate {
+            subject: Some("Out of office: {{name}}".to_string()),
+            body: "{{ name }} is away from {{from}} until {{to}}.".to_string(),
+            mandatory_footer: false,
+        };
+        assert_eq!(template.validate(), Ok(()));
+
+        // Templates are stored as config keys and parsed back
+        let mut config = Config {
+            keys: template
+                .config_keys(3)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            VacationTemplate::parse(&mut config, 3),
+            Some(template.clone())
+        );
+        assert_eq!(VacationTemplate::parse(&mut config, 4), None);
+
+        // Placeholders are replaced in responses without custom text
+        let variables = VacationVariables {
+            name: "Jane Smith",
+            from: Some("2026-08-01"),
+            to: None,
+        };
+        assert_eq!(
+            template.apply(None, None, Some(" "), &variables),
+            VacationReply {
+                subject: Some("Out of office: Jane Smith".to_string()),
+                text_body: Some("Jane Smith is away from 2026-08-01 until .".to_string()),
+                html_body: None,
+            }
+        );
+        assert_eq!(
+            template.apply(Some("Gone fishing"), Some("Back soon"), None, &variables),
+            VacationReply {
+                subject: Some("Gone fishing".to_string()),
+                text_body: Some("Back soon".to_string()),
+                html_body: None,
+            }
+        );
+
+        // Mandatory footers are appended to custom text
+        let template = VacationTemplate {
+            subject: None,
+            body: "Urgent matters: <help@acme.org>\n{{name}}".to_string(),
+            mandatory_footer: true,
+        };
+        assert_eq!(
+            template.apply(
+                Some("Gone fishing"),
+                Some("Back soon"),
+                Some("<b>Back soon</b>"),
+                &variables
+            ),
+            VacationReply {
+                subject: Some("Gone fishing".to_string()),
+                text_body: Some(
+                    "Back soon\n\nUrgent matters: <help@acme.org>\nJane Smith".to_string()
+                ),
+                html_body: Some(
+                    "<b>Back soon</b><p>Urgent matters: &lt;help@acme.org&gt;<br>Jane Smith</p>"
+                        .to_string()
+                ),
+            }
+        );
+
+        // Invalid templates are rejected
+        for (subject, body) in [
+            (None, ""),
+            (None, "Away until {{return_date}}"),
+            (None, "Away until {{to"),
+            (Some("Away {{date}}"), "Away"),
+        ] {
+            let template = VacationTemplate {
+                subject: subject.map(|s: &str| s.to_string()),
+                body: body.to_string(),
+                mandatory_footer: false,
+            };
+            assert!(template.validate().is_err(), "{template:?}");
+        }
+    }
+
+    #[test]
+    fn forwarding_rule() {
+        let mut rule = ForwardingRule {
+            addresses: vec![
+                " Jane@Example.org ".to_string(),
+                "jane@example.org".to_string(),
+                "john@acme.org".to_string(),
+            ],
+            keep_copy: true,
+            expires: Some(2000),
+        };
+        assert_eq!(rule.validate(1000, 2), Ok(()));
+        assert_eq!(rule.addresses, ["jane@example.org", "john@acme.org"]);
+
+        // Rules are stored as config keys and parsed back
+        let mut config = Config {
+            keys: rule
+                .config_keys(7)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let rules = ForwardingRule::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(rules.get(&7).unwrap().as_ref(), &rule);
+
+        // A copy is kept explicitly, otherwise no other script runs
+        assert_eq!(
+            rule.build_script(&["jane@example.org"]),
+            "redirect \"jane@example.org\";\nkeep;\n"
+        );
+        rule.keep_copy = false;
+        assert_eq!(
+            rule.build_script(&["jane@example.org", "john@acme.org"]),
+            "redirect \"jane@example.org\";\nredirect \"john@acme.org\";\nstop;\n"
+        );
+
+        // Expired rules and invalid addresses are rejected
+        assert!(rule.is_expired(2000));
+        assert!(rule.validate(2000, 2).is_err());
+        assert!(rule.validate(1000, 1).is_err());
+        for addresses in [vec![], vec!["jane"], vec!["jane@example.org\""]] {
+            let mut rule = ForwardingRule {
+                addresses: addresses.into_iter().map(|a| a.to_string()).collect(),
+                ..Default::default()
+            };
+            assert!(rule.validate(1000, 2).is_err(), "{rule:?}");
+        }
+
+        // External forwarding is allowed unless disabled by the tenant
+        assert!(ForwardingPolicy::parse(&mut Config::default(), 3).allow_external);
+        let mut config = Config {
+            keys: ForwardingPolicy {
+                allow_external: false,
+            }
+            .config_keys(3)
+            .into_iter()
+            .map(|key| (key.key, key.value))
+            .collect(),
+            ..Default::default()
+        };
+        assert!(!ForwardingPolicy::parse(&mut config, 3).allow_external);
+    }
+}
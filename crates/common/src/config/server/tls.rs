@@ -36,6 +36,7 @@ use x509_parser::{
 use crate::listener::{
     acme::{
         AcmeProvider, ChallengeSettings, EabSettings, directory::LETS_ENCRYPT_PRODUCTION_DIRECTORY,
+        domain::DomainCertificates,
     },
     tls::AcmeProviders,
 };
@@ -46,6 +47,7 @@ pub static TLS12_VERSION: &[&SupportedProtocolVersion] = &[&TLS12];
 impl AcmeProviders {
     pub fn parse(config: &mut Config) -> Self {
         let mut providers = AHashMap::new();
+        let mut domain_certificates = None;
 
         // Parse ACME providers
         'outer: for acme_id in config.sub_keys("acme", ".directory") {
@@ -159,7 +161,37 @@ impl AcmeProviders {
                 .property::<bool>(("acme", acme_id, "default"))
                 .unwrap_or_default();
 
-            if !domains.is_empty() {
+            // Hostname templates issued for every domain in the directory
+            let hostnames = config
+                .values(("acme", acme_id, "domain-hostnames"))
+                .map(|(_, s)| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>();
+            if hostnames.iter().any(|h| !h.contains("{domain}")) {
+                config.new_parse_error(
+                    ("acme", acme_id, "domain-hostnames"),
+                    "Hostnames must contain the {domain} placeholder",
+                );
+                continue 'outer;
+            }
+            if !matches!(challenge, ChallengeSettings::Dns01 { .. })
+                && hostnames.iter().any(|d| d.starts_with("*."))
+            {
+                config.new_parse_error(
+                    ("acme", acme_id, "domain-hostnames"),
+                    "Wildcard domains are only supported with DNS-01 challenge",
+                );
+                continue 'outer;
+            }
+            if !hostnames.is_empty() && domain_certificates.is_some() {
+                config.new_parse_error(
+                    ("acme", acme_id, "domain-hostnames"),
+                    "Domain hostnames can only be issued by one ACME provider",
+                );
+                continue 'outer;
+            }
+
+            if !domains.is_empty() || !hostnames.is_empty() {
                 match AcmeProvider::new(
                     acme_id.to_string(),
                     directory,
@@ -171,7 +203,15 @@ impl AcmeProviders {
                     default,
                 ) {
                     Ok(acme_provider) => {
-                        providers.insert(acme_id.to_string(), acme_provider);
+                        if !hostnames.is_empty() {
+                            domain_certificates = Some(DomainCertificates {
+                                provider: acme_provider.clone(),
+                                hostnames,
+                            });
+                        }
+                        if !acme_provider.domains.is_empty() {
+                            providers.insert(acme_id.to_string(), acme_provider);
+                        }
                     }
                     Err(err) => {
                         config.new_build_error(format!("acme.{acme_id}"), err.to_string());
@@ -180,7 +220,10 @@ impl AcmeProviders {
             }
         }
 
-        AcmeProviders { providers }
+        AcmeProviders {
+            providers,
+            domains: domain_certificates,
+        }
     }
 }
 
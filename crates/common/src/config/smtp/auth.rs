@@ -4,7 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{sync::Arc, time::Duration};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use ahash::AHashMap;
 use mail_auth::{
@@ -12,8 +12,9 @@ use mail_auth::{
     dkim::{Canonicalization, Done},
 };
 use mail_parser::decoders::base64::base64_decode;
+use parking_lot::Mutex;
 use utils::config::{
-    Config,
+    Config, ConfigKey,
     utils::{AsKey, ParseValue},
 };
 
@@ -24,6 +25,9 @@ use crate::{
 
 use super::*;
 
+pub const TENANT_DKIM_KEY: &str = "auth.dkim.tenant";
+pub const TENANT_ARC_KEY: &str = "auth.arc.tenant";
+
 #[derive(Clone)]
 pub struct MailAuthConfig {
     pub dkim: DkimAuthConfig,
@@ -45,6 +49,7 @@ pub enum LazySignature {
 pub struct ResolvedSignature {
     pub signer: Arc<DkimSigner>,
     pub sealer: Arc<ArcSealer>,
+    pub config: Arc<Config>,
 }
 
 #[derive(Clone)]
@@ -100,6 +105,55 @@ pub enum ArcSealer {
     Ed25519Sha256(mail_auth::arc::ArcSealer<Ed25519Key, Done>),
 }
 
+/// DKIM signing policy of a tenant, applied to the signatures of messages
+/// submitted by its users.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantDkimPolicy {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<DkimSigningPolicy>,
+    #[serde(default)]
+    pub domains: BTreeMap<String, DkimSigningPolicy>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct DkimSigningPolicy {
+    pub headers: Vec<String>,
+    #[serde(default)]
+    pub oversign: bool,
+    #[serde(default)]
+    pub header_canonicalization: DkimPolicyCanonicalization,
+    #[serde(default)]
+    pub body_canonicalization: DkimPolicyCanonicalization,
+    #[serde(default)]
+    pub body_length: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimPolicyCanonicalization {
+    #[default]
+    Relaxed,
+    Simple,
+}
+
+/// Parsed DKIM policy of a tenant along with the signers derived from it,
+/// keyed by signature id.
+#[derive(Default)]
+pub struct TenantDkimSigning {
+    pub settings: TenantDkimPolicy,
+    signers: Mutex<AHashMap<String, DerivedSigner>>,
+}
+
+struct DerivedSigner {
+    base: Arc<DkimSigner>,
+    signer: Arc<DkimSigner>,
+}
+
 impl Default for MailAuthConfig {
     fn default() -> Self {
         Self {
@@ -422,6 +476,249 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
     Some((signer, sealer))
 }
 
+impl TenantDkimPolicy {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantDkimSigning>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_DKIM_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_DKIM_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_DKIM_KEY}.{id}");
+            let mut settings = TenantDkimPolicy {
+                policy: DkimSigningPolicy::parse(config, &format!("{prefix}.policy")),
+                domains: BTreeMap::new(),
+            };
+            for domain in config.sub_keys((prefix.as_str(), "domain"), ".headers") {
+                if let Some(policy) =
+                    DkimSigningPolicy::parse(config, &format!("{prefix}.domain.{domain}"))
+                {
+                    settings.domains.insert(domain, policy);
+                }
+            }
+
+            match settings.validate() {
+                Ok(()) => {
+                    tenants.insert(tenant_id, Arc::new(TenantDkimSigning::from(settings)));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(domain) = self.domains.keys().find(|domain| {
+            domain.is_empty()
+                || domain.starts_with('.')
+                || domain.contains(|ch: char| ch.is_whitespace() || ch == '@' || ch.is_uppercase())
+        }) {
+            return Err(format!("Invalid signing domain {domain:?}"));
+        }
+
+        for policy in self.policy.iter().chain(self.domains.values()) {
+            if let Some(header) = policy.headers.iter().find(|header| {
+                header.is_empty() || !header.bytes().all(|ch| ch.is_ascii_graphic() && ch != b':')
+            }) {
+                return Err(format!("Invalid header name {header:?}"));
+            }
+            if !policy
+                .headers
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case("From"))
+            {
+                return Err("The From header must be signed".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_DKIM_KEY}.{tenant_id}");
+        let mut keys = Vec::new();
+
+        if let Some(policy) = &self.policy {
+            policy.config_keys(&format!("{prefix}.policy"), &mut keys);
+        }
+        for (domain, policy) in &self.domains {
+            policy.config_keys(&format!("{prefix}.domain.{domain}"), &mut keys);
+        }
+
+        keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.policy.is_none() && self.domains.is_empty()
+    }
+}
+
+/// Parses the signatures used by tenants to ARC seal the messages forwarded
+/// by their accounts.
+pub fn parse_tenant_arc_sealers(config: &mut Config) -> AHashMap<u32, String> {
+    let mut tenants = AHashMap::new();
+
+    for id in config.sub_keys(TENANT_ARC_KEY, ".seal") {
+        let Ok(tenant_id) = id.parse::<u32>() else {
+            config.new_parse_error((TENANT_ARC_KEY, id.as_str()), "Invalid tenant id");
+            continue;
+        };
+        if let Some(signature) = config
+            .value((TENANT_ARC_KEY, id.as_str(), "seal"))
+            .filter(|signature| !signature.is_empty())
+        {
+            tenants.insert(tenant_id, signature.to_string());
+        }
+    }
+
+    tenants
+}
+
+impl DkimSigningPolicy {
+    fn parse(config: &mut Config, prefix: &str) -> Option<Self> {
+        Some(DkimSigningPolicy {
+            headers: config
+                .value((prefix, "headers"))?
+                .split(':')
+                .map(|header| header.trim().to_string())
+                .collect(),
+            oversign: config.property((prefix, "oversign")).unwrap_or(false),
+            header_canonicalization: DkimPolicyCanonicalization::parse(
+                config.value((prefix, "canonicalization.header")),
+            ),
+            body_canonicalization: DkimPolicyCanonicalization::parse(
+                config.value((prefix, "canonicalization.body")),
+            ),
+            body_length: config.property((prefix, "body-length")).unwrap_or(false),
+        })
+    }
+
+    fn config_keys(&self, prefix: &str, keys: &mut Vec<ConfigKey>) {
+        for (key, value) in [
+            ("headers", self.headers.join(":")),
+            ("oversign", self.oversign.to_string()),
+            (
+                "canonicalization.header",
+                self.header_canonicalization.as_str().to_string(),
+            ),
+            (
+                "canonicalization.body",
+                self.body_canonicalization.as_str().to_string(),
+            ),
+            ("body-length", self.body_length.to_string()),
+        ] {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.{key}"),
+                value,
+            });
+        }
+    }
+}
+
+impl DkimPolicyCanonicalization {
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("simple") => DkimPolicyCanonicalization::Simple,
+            _ => DkimPolicyCanonicalization::Relaxed,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DkimPolicyCanonicalization::Relaxed => "relaxed",
+            DkimPolicyCanonicalization::Simple => "simple",
+        }
+    }
+}
+
+impl From<DkimPolicyCanonicalization> for Canonicalization {
+    fn from(value: DkimPolicyCanonicalization) -> Self {
+        match value {
+            DkimPolicyCanonicalization::Relaxed => Canonicalization::Relaxed,
+            DkimPolicyCanonicalization::Simple => Canonicalization::Simple,
+        }
+    }
+}
+
+impl From<TenantDkimPolicy> for TenantDkimSigning {
+    fn from(settings: TenantDkimPolicy) -> Self {
+        TenantDkimSigning {
+            settings,
+            signers: Mutex::new(AHashMap::new()),
+        }
+    }
+}
+
+impl TenantDkimSigning {
+    /// Returns the policy of the tenant for a signing domain.
+    pub fn policy(&self, domain: &str) -> Option<&DkimSigningPolicy> {
+        self.settings
+            .domains
+            .get(domain)
+            .or(self.settings.policy.as_ref())
+    }
+
+    /// Returns the signer previously derived from `base`, or derives a new one
+    /// using `build`. Derived signers are discarded when the base signer changes.
+    pub fn signer(
+        &self,
+        name: &str,
+        base: &Arc<DkimSigner>,
+        build: impl FnOnce() -> Option<DkimSigner>,
+    ) -> Option<Arc<DkimSigner>> {
+        if let Some(derived) = self.signers.lock().get(name)
+            && Arc::ptr_eq(&derived.base, base)
+        {
+            return Some(derived.signer.clone());
+        }
+
+        let signer = Arc::new(build()?);
+        self.signers.lock().insert(
+            name.to_string(),
+            DerivedSigner {
+                base: base.clone(),
+                signer: signer.clone(),
+            },
+        );
+        Some(signer)
+    }
+}
+
+impl DkimSigner {
+    pub fn domain(&self) -> &str {
+        match self {
+            DkimSigner::RsaSha256(signer) => &signer.template.d,
+            DkimSigner::Ed25519Sha256(signer) => &signer.template.d,
+        }
+    }
+
+    /// Applies a tenant signing policy, overriding the signed headers,
+    /// canonicalization and body length of the signature.
+    pub fn with_policy(mut self, policy: &DkimSigningPolicy) -> Self {
+        let template = match &mut self {
+            DkimSigner::RsaSha256(signer) => &mut signer.template,
+            DkimSigner::Ed25519Sha256(signer) => &mut signer.template,
+        };
+        // Listing a header twice signs its absence, preventing additional
+        // instances from being added in transit.
+        template.h = policy
+            .headers
+            .iter()
+            .flat_map(|header| {
+                std::iter::repeat_n(header.clone(), if policy.oversign { 2 } else { 1 })
+            })
+            .collect();
+        template.ch = policy.header_canonicalization.into();
+        template.cb = policy.body_canonicalization.into();
+        template.l = u64::from(policy.body_length);
+        self
+    }
+}
+
 impl<'x> TryFrom<expr::Variable<'x>> for VerifyStrategy {
     type Error = ();
 
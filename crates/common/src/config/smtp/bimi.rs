@@ -0,0 +1,182 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+pub const DOMAIN_BIMI_KEY: &str = "session.bimi.domain";
+
+pub const BIMI_LOGO: &str = "logo.svg";
+pub const BIMI_VMC: &str = "vmc.pem";
+
+/// Brand Indicators for Message Identification of a domain. The logo and
+/// the optional Verified Mark Certificate are kept in the blob store and
+/// served over HTTPS, only their hashes are stored in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainBimi {
+    /// Hash of the SVG Tiny PS logo.
+    pub logo: String,
+    /// Hash of the PEM encoded Verified Mark Certificate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vmc: Option<String>,
+}
+
+impl DomainBimi {
+    pub fn parse_all(config: &mut Config) -> AHashMap<String, Arc<DomainBimi>> {
+        let mut domains = AHashMap::new();
+
+        for domain in config.sub_keys_with_suffixes(DOMAIN_BIMI_KEY, &[".logo"]) {
+            let prefix = format!("{DOMAIN_BIMI_KEY}.{domain}");
+            let bimi = DomainBimi {
+                logo: config
+                    .value((prefix.as_str(), "logo"))
+                    .unwrap_or_default()
+                    .to_string(),
+                vmc: config
+                    .value((prefix.as_str(), "vmc"))
+                    .map(|vmc| vmc.to_string()),
+            };
+
+            if !bimi.logo.is_empty() {
+                domains.insert(domain.to_lowercase(), Arc::new(bimi));
+            } else {
+                config.new_parse_error(prefix, "Missing BIMI logo hash");
+            }
+        }
+
+        domains
+    }
+
+    pub fn config_keys(&self, domain: &str) -> Vec<ConfigKey> {
+        let prefix = format!("{DOMAIN_BIMI_KEY}.{domain}");
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.logo"),
+            value: self.logo.clone(),
+        }];
+        if let Some(vmc) = &self.vmc {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.vmc"),
+                value: vmc.clone(),
+            });
+        }
+
+        keys
+    }
+
+    pub fn logo_url(hostname: &str, domain: &str) -> String {
+        format!("https://{hostname}/bimi/{domain}/{BIMI_LOGO}")
+    }
+
+    pub fn vmc_url(&self, hostname: &str, domain: &str) -> Option<String> {
+        self.vmc
+            .as_ref()
+            .map(|_| format!("https://{hostname}/bimi/{domain}/{BIMI_VMC}"))
+    }
+
+    /// Returns the contents of the `default._bimi` TXT record of the domain.
+    pub fn record(&self, hostname: &str, domain: &str) -> String {
+        format!(
+            "v=BIMI1; l={}; a={}",
+            Self::logo_url(hostname, domain),
+            self.vmc_url(hostname, domain).unwrap_or_default()
+        )
+    }
+
+    /// Whether a published record points to the logo and certificate served
+    /// by this server. Tags are compared regardless of their order.
+    pub fn matches_record(&self, record: &str, hostname: &str, domain: &str) -> bool {
+        let mut version = None;
+        let mut logo = None;
+        let mut vmc = None;
+        for tag in record.split(';') {
+            match tag.trim().split_once('=') {
+                Some(("v", value)) => version = Some(value.trim()),
+                Some(("l", value)) => logo = Some(value.trim()),
+                Some(("a", value)) => vmc = Some(value.trim()),
+                _ => (),
+            }
+        }
+
+        version == Some("BIMI1")
+            && logo == Some(Self::logo_url(hostname, domain).as_str())
+            && vmc.filter(|vmc| !vmc.is_empty()) == self.vmc_url(hostname, domain).as_deref()
+    }
+}
+
+/// Key of a BIMI file of a domain in the blob store.
+pub fn bimi_blob_key(domain: &str, file: &str) -> Vec<u8> {
+    format!("bimi/{domain}/{file}").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DomainBimi;
+    use utils::config::Config;
+
+    #[test]
+    fn domain_bimi() {
+        let bimi = DomainBimi {
+            logo: "abc".to_string(),
+            vmc: Some("def".to_string()),
+        };
+        let no_vmc = DomainBimi {
+            logo: "abc".to_string(),
+            vmc: None,
+        };
+
+        // Configuration round trip
+        let mut config = Config {
+            keys: bimi
+                .config_keys("example.org")
+                .into_iter()
+                .chain(no_vmc.config_keys("example.net"))
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let domains = DomainBimi::parse_all(&mut config);
+        assert_eq!(domains["example.org"].as_ref(), &bimi);
+        assert_eq!(domains["example.net"].as_ref(), &no_vmc);
+        assert!(config.errors.is_empty());
+
+        // Published records are matched regardless of tag order and spacing
+        let record = bimi.record("mx.example.org", "example.org");
+        assert_eq!(
+            record,
+            concat!(
+                "v=BIMI1; l=https://mx.example.org/bimi/example.org/logo.svg; ",
+                "a=https://mx.example.org/bimi/example.org/vmc.pem"
+            )
+        );
+        assert!(bimi.matches_record(&record, "mx.example.org", "example.org"));
+        assert!(bimi.matches_record(
+            concat!(
+                "a=https://mx.example.org/bimi/example.org/vmc.pem;",
+                "l=https://mx.example.org/bimi/example.org/logo.svg;v=BIMI1"
+            ),
+            "mx.example.org",
+            "example.org"
+        ));
+        assert!(!no_vmc.matches_record(&record, "mx.example.org", "example.org"));
+        assert!(no_vmc.matches_record(
+            &no_vmc.record("mx.example.org", "example.net"),
+            "mx.example.org",
+            "example.net"
+        ));
+        assert!(no_vmc.matches_record(
+            "v=BIMI1; l=https://mx.example.org/bimi/example.net/logo.svg",
+            "mx.example.org",
+            "example.net"
+        ));
+        assert!(!bimi.matches_record(
+            "v=BIMI1; l=https://cdn.example.org/logo.svg",
+            "mx.example.org",
+            "example.org"
+        ));
+    }
+}
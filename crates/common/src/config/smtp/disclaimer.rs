@@ -0,0 +1,543 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use mail_builder::encoders::base64::base64_encode_mime;
+use mail_parser::{HeaderName, MessageParser, PartType};
+use std::sync::Arc;
+use utils::config::{Config, ConfigKey};
+
+pub const DISCLAIMER_KEY: &str = "session.disclaimer";
+pub const TENANT_DISCLAIMER_KEY: &str = "session.disclaimer.tenant";
+pub const DOMAIN_DISCLAIMER_KEY: &str = "session.disclaimer.domain";
+
+/// Placeholders that can be used in disclaimers.
+const DISCLAIMER_VARIABLES: [&str; 3] = ["name", "email", "brand"];
+
+/// Disclaimer appended to the messages submitted by the users of a tenant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantDisclaimer {
+    /// Appended to plain text bodies, it may contain the `{{name}}`,
+    /// `{{email}}` and `{{brand}}` placeholders.
+    pub text: String,
+    /// Appended to HTML bodies, the plain text version is used when missing.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+    /// Skips replies, recognized by their In-Reply-To or References header.
+    #[serde(default)]
+    pub exclude_replies: bool,
+    /// Skips messages addressed only to the tenant's own domains.
+    #[serde(default)]
+    pub exclude_internal: bool,
+}
+
+/// Disclaimer of a sending domain, which replaces the disclaimer of the
+/// domain's tenant.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum DomainDisclaimer {
+    Custom(TenantDisclaimer),
+    /// No disclaimer is added to the messages sent from the domain.
+    Disabled(NoDisclaimer),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoDisclaimer {
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DisclaimerVariables<'x> {
+    pub name: &'x str,
+    pub email: &'x str,
+    pub brand: &'x str,
+}
+
+impl TenantDisclaimer {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantDisclaimer>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_DISCLAIMER_KEY, ".text") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_DISCLAIMER_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_DISCLAIMER_KEY}.{tenant_id}");
+            let disclaimer = TenantDisclaimer::parse(config, &prefix);
+
+            match disclaimer.validate() {
+                Ok(()) => {
+                    tenants.insert(tenant_id, Arc::new(disclaimer));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    fn parse(config: &mut Config, prefix: &str) -> Self {
+        TenantDisclaimer {
+            text: config
+                .value((prefix, "text"))
+                .unwrap_or_default()
+                .to_string(),
+            html: config.value((prefix, "html")).map(|html| html.to_string()),
+            exclude_replies: config
+                .property((prefix, "exclude-replies"))
+                .unwrap_or(false),
+            exclude_internal: config
+                .property((prefix, "exclude-internal"))
+                .unwrap_or(false),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.text.trim().is_empty() {
+            return Err("Disclaimer text is required".to_string());
+        }
+        for text in [Some(&self.text), self.html.as_ref()].into_iter().flatten() {
+            if text.len() >= 4096 {
+                return Err("Disclaimer is too long".to_string());
+            }
+            substitute(text, &DisclaimerVariables::default(), false)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        self.prefix_config_keys(&format!("{TENANT_DISCLAIMER_KEY}.{tenant_id}"))
+    }
+
+    fn prefix_config_keys(&self, prefix: &str) -> Vec<ConfigKey> {
+        let mut keys = vec![
+            ConfigKey {
+                key: format!("{prefix}.text"),
+                value: self.text.clone(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.exclude-replies"),
+                value: self.exclude_replies.to_string(),
+            },
+            ConfigKey {
+                key: format!("{prefix}.exclude-internal"),
+                value: self.exclude_internal.to_string(),
+            },
+        ];
+        if let Some(html) = &self.html {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.html"),
+                value: html.clone(),
+            });
+        }
+
+        keys
+    }
+
+    /// Appends the disclaimer to the last plain text and HTML bodies of a
+    /// message, returning `None` when the message is left unchanged. The
+    /// modified parts are re-encoded as UTF-8 while the rest of the MIME
+    /// structure is copied as is.
+    pub fn apply(
+        &self,
+        raw_message: &[u8],
+        variables: &DisclaimerVariables<'_>,
+    ) -> Option<Vec<u8>> {
+        let message = MessageParser::new().parse(raw_message)?;
+        if self.exclude_replies
+            && message
+                .root_part()
+                .headers()
+                .iter()
+                .any(|header| matches!(header.name, HeaderName::InReplyTo | HeaderName::References))
+        {
+            return None;
+        }
+
+        let mut part_ids = message
+            .text_body
+            .last()
+            .into_iter()
+            .chain(message.html_body.last())
+            .copied()
+            .collect::<Vec<_>>();
+        part_ids.sort_unstable_by_key(|part_id| {
+            message
+                .part(*part_id)
+                .map_or(0, |part| part.raw_header_offset())
+        });
+        part_ids.dedup();
+
+        let text = substitute(&self.text, variables, false).unwrap_or_else(|_| self.text.clone());
+        let html = match &self.html {
+            Some(html) => substitute(html, variables, true).unwrap_or_else(|_| html.clone()),
+            None => {
+                let mut html = String::with_capacity(text.len() + 16);
+                html.push_str("<p>");
+                escape_html(&text, &mut html);
+                html.push_str("</p>");
+                html
+            }
+        };
+
+        let mut result = Vec::with_capacity(raw_message.len() + text.len() + html.len() + 256);
+        let mut offset = 0;
+        for part_id in part_ids {
+            let part = message.part(part_id)?;
+            let (content_type, body) = match &part.body {
+                PartType::Text(body) => {
+                    let mut body = body.to_string();
+                    if !body.is_empty() && !body.ends_with('\n') {
+                        body.push_str("\r\n");
+                    }
+                    body.push_str("\r\n");
+                    body.push_str(&text);
+                    body.push_str("\r\n");
+                    ("text/plain", body)
+                }
+                PartType::Html(body) => {
+                    // Insert before the closing body tag, if there is one
+                    let mut body = body.to_string();
+                    let position = body
+                        .to_ascii_lowercase()
+                        .rfind("</body>")
+                        .unwrap_or(body.len());
+                    body.insert_str(position, &html);
+                    ("text/html", body)
+                }
+                _ => continue,
+            };
+
+            result.extend_from_slice(&raw_message[offset..part.raw_header_offset() as usize]);
+            if part_id == 0
+                && !part
+                    .headers()
+                    .iter()
+                    .any(|header| header.name == HeaderName::MimeVersion)
+            {
+                result.extend_from_slice(b"MIME-Version: 1.0\r\n");
+            }
+            for header in part.headers() {
+                if !matches!(
+                    header.name,
+                    HeaderName::ContentType | HeaderName::ContentTransferEncoding
+                ) {
+                    result.extend_from_slice(
+                        &raw_message[header.offset_field as usize..header.offset_end as usize],
+                    );
+                }
+            }
+            result.extend_from_slice(b"Content-Type: ");
+            result.extend_from_slice(content_type.as_bytes());
+            result
+                .extend_from_slice(b"; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n");
+            base64_encode_mime(body.as_bytes(), &mut result, false).ok()?;
+            offset = part.raw_end_offset() as usize;
+        }
+
+        if offset > 0 {
+            result.extend_from_slice(&raw_message[offset..]);
+            Some(result)
+        } else {
+            None
+        }
+    }
+}
+
+impl DomainDisclaimer {
+    pub fn parse_all(config: &mut Config) -> AHashMap<String, Arc<DomainDisclaimer>> {
+        let mut domains = AHashMap::new();
+
+        for domain in config.sub_keys_with_suffixes(DOMAIN_DISCLAIMER_KEY, &[".text", ".disabled"])
+        {
+            let prefix = format!("{DOMAIN_DISCLAIMER_KEY}.{domain}");
+            let disclaimer = if config
+                .property::<bool>((prefix.as_str(), "disabled"))
+                .unwrap_or(false)
+            {
+                DomainDisclaimer::Disabled(NoDisclaimer { disabled: true })
+            } else {
+                DomainDisclaimer::Custom(TenantDisclaimer::parse(config, &prefix))
+            };
+
+            match disclaimer.validate() {
+                Ok(()) => {
+                    domains.insert(domain.to_lowercase(), Arc::new(disclaimer));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        domains
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            DomainDisclaimer::Custom(disclaimer) => disclaimer.validate(),
+            DomainDisclaimer::Disabled(NoDisclaimer { disabled: true }) => Ok(()),
+            DomainDisclaimer::Disabled(_) => {
+                Err("Remove the domain disclaimer to use the organization one".to_string())
+            }
+        }
+    }
+
+    pub fn config_keys(&self, domain: &str) -> Vec<ConfigKey> {
+        let prefix = format!("{DOMAIN_DISCLAIMER_KEY}.{domain}");
+        match self {
+            DomainDisclaimer::Custom(disclaimer) => disclaimer.prefix_config_keys(&prefix),
+            DomainDisclaimer::Disabled(_) => vec![ConfigKey {
+                key: format!("{prefix}.disabled"),
+                value: "true".to_string(),
+            }],
+        }
+    }
+
+    /// Returns the disclaimer to add to a message, the disclaimer of the
+    /// sending domain takes precedence over the one of its tenant.
+    pub fn resolve<'x>(
+        domain: Option<&'x DomainDisclaimer>,
+        tenant: Option<&'x TenantDisclaimer>,
+    ) -> Option<&'x TenantDisclaimer> {
+        match domain {
+            Some(DomainDisclaimer::Custom(disclaimer)) => Some(disclaimer),
+            Some(DomainDisclaimer::Disabled(_)) => None,
+            None => tenant,
+        }
+    }
+}
+
+fn substitute(
+    text: &str,
+    variables: &DisclaimerVariables<'_>,
+    is_html: bool,
+) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut text = text;
+
+    while let Some((start, end)) = text.split_once("{{") {
+        let (name, rest) = end.split_once("}}").ok_or("Unmatched {{")?;
+        result.push_str(start);
+        let value = match name.trim() {
+            "name" => variables.name,
+            "email" => variables.email,
+            "brand" => variables.brand,
+            name => {
+                return Err(format!(
+                    "Unknown placeholder {name:?}, expected one of {}",
+                    DISCLAIMER_VARIABLES.join(", ")
+                ));
+            }
+        };
+        if is_html {
+            escape_html(value, &mut result);
+        } else {
+            result.push_str(value);
+        }
+        text = rest;
+    }
+    result.push_str(text);
+
+    Ok(result)
+}
+
+fn escape_html(text: &str, html: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\n' => html.push_str("<br>"),
+            '\r' => (),
+            _ => html.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisclaimerVariables, DomainDisclaimer, NoDisclaimer, TenantDisclaimer};
+    use mail_parser::MessageParser;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_disclaimer() {
+        let disclaimer = TenantDisclaimer {
+            text: "Sent by {{name}} on behalf of {{brand}}.".to_string(),
+            html: None,
+            exclude_replies: true,
+            exclude_internal: false,
+        };
+        let variables = DisclaimerVariables {
+            name: "John <Doe>",
+            email: "john@example.org",
+            brand: "Acme",
+        };
+
+        // Configuration round trip
+        let mut config = Config {
+            keys: disclaimer
+                .config_keys(1)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .chain([(
+                    "session.disclaimer.tenant.2.text".to_string(),
+                    "Hello {{nobody}}".to_string(),
+                )])
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantDisclaimer::parse_all(&mut config);
+        assert_eq!(tenants[&1].as_ref(), &disclaimer);
+        assert!(!tenants.contains_key(&2));
+        assert!(!config.errors.is_empty());
+
+        // Single part messages are re-encoded
+        let message = disclaimer
+            .apply(
+                b"From: john@example.org\r\nSubject: Hi\r\n\r\nHello there.",
+                &variables,
+            )
+            .unwrap();
+        let parsed = MessageParser::new().parse(&message).unwrap();
+        assert_eq!(parsed.subject(), Some("Hi"));
+        assert_eq!(
+            parsed.body_text(0).unwrap(),
+            "Hello there.\r\n\r\nSent by John <Doe> on behalf of Acme.\r\n"
+        );
+
+        // Both alternatives are updated, attachments are left as is
+        let raw_message = concat!(
+            "From: john@example.org\r\n",
+            "Content-Type: multipart/mixed; boundary=\"a\"\r\n\r\n",
+            "--a\r\n",
+            "Content-Type: multipart/alternative; boundary=\"b\"\r\n\r\n",
+            "--b\r\n",
+            "Content-Type: text/plain; charset=iso-8859-1\r\n",
+            "Content-Transfer-Encoding: quoted-printable\r\n\r\n",
+            "Caf=E9\r\n",
+            "--b\r\n",
+            "Content-Type: text/html\r\n\r\n",
+            "<html><body><p>Caf&eacute;</p></BODY></html>\r\n",
+            "--b--\r\n",
+            "--a\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Disposition: attachment; filename=\"notes.txt\"\r\n\r\n",
+            "Notes\r\n",
+            "--a--\r\n",
+        );
+        let message = disclaimer
+            .apply(raw_message.as_bytes(), &variables)
+            .unwrap();
+        let parsed = MessageParser::new().parse(&message).unwrap();
+        assert_eq!(
+            parsed.body_text(0).unwrap(),
+            "Café\r\n\r\nSent by John <Doe> on behalf of Acme.\r\n"
+        );
+        assert_eq!(
+            parsed.body_html(0).unwrap(),
+            concat!(
+                "<html><body><p>Caf&eacute;</p>",
+                "<p>Sent by John &lt;Doe&gt; on behalf of Acme.</p></BODY></html>"
+            )
+        );
+        assert_eq!(parsed.attachments.len(), 1);
+        assert_eq!(parsed.attachment(0).unwrap().text_contents(), Some("Notes"));
+
+        // Replies are excluded
+        assert_eq!(
+            disclaimer.apply(
+                b"From: john@example.org\r\nIn-Reply-To: <1@example.org>\r\n\r\nThanks.",
+                &variables,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn domain_disclaimer() {
+        let tenant = TenantDisclaimer {
+            text: "Sent on behalf of {{brand}}.".to_string(),
+            ..Default::default()
+        };
+        let marketing = DomainDisclaimer::Custom(TenantDisclaimer {
+            text: "Unsubscribe at any time.".to_string(),
+            html: Some("<p>Unsubscribe at any time.</p>".to_string()),
+            ..Default::default()
+        });
+        let corp = DomainDisclaimer::Disabled(NoDisclaimer { disabled: true });
+
+        // Configuration round trip
+        let mut config = Config {
+            keys: marketing
+                .config_keys("marketing.example.com")
+                .into_iter()
+                .chain(corp.config_keys("corp.example.com"))
+                .map(|key| (key.key, key.value))
+                .chain([(
+                    "session.disclaimer.domain.other.example.com.disabled".to_string(),
+                    "false".to_string(),
+                )])
+                .collect(),
+            ..Default::default()
+        };
+        let domains = DomainDisclaimer::parse_all(&mut config);
+        assert_eq!(domains["marketing.example.com"].as_ref(), &marketing);
+        assert_eq!(domains["corp.example.com"].as_ref(), &corp);
+        assert_eq!(domains.len(), 2);
+
+        // Domain disclaimers take precedence over the tenant disclaimer
+        assert_eq!(
+            DomainDisclaimer::resolve(Some(&marketing), Some(&tenant))
+                .map(|disclaimer| disclaimer.text.as_str()),
+            Some("Unsubscribe at any time.")
+        );
+        assert_eq!(
+            DomainDisclaimer::resolve(Some(&marketing), None)
+                .map(|disclaimer| disclaimer.text.as_str()),
+            Some("Unsubscribe at any time.")
+        );
+        assert_eq!(DomainDisclaimer::resolve(Some(&corp), Some(&tenant)), None);
+        assert_eq!(
+            DomainDisclaimer::resolve(None, Some(&tenant))
+                .map(|disclaimer| disclaimer.text.as_str()),
+            Some("Sent on behalf of {{brand}}.")
+        );
+        assert_eq!(DomainDisclaimer::resolve(None, None), None);
+
+        // Either a disclaimer or the disabled flag is accepted
+        assert_eq!(
+            serde_json::from_str::<DomainDisclaimer>(r#"{"disabled": true}"#).unwrap(),
+            corp
+        );
+        assert_eq!(
+            serde_json::from_str::<DomainDisclaimer>(
+                r#"{"text": "Unsubscribe at any time.", "html": "<p>Unsubscribe at any time.</p>"}"#
+            )
+            .unwrap(),
+            marketing
+        );
+        assert!(
+            serde_json::from_str::<DomainDisclaimer>(r#"{"disabled": true, "text": "Hi"}"#)
+                .is_err()
+        );
+        assert!(
+            serde_json::from_str::<DomainDisclaimer>(r#"{"disabled": false}"#)
+                .unwrap()
+                .validate()
+                .is_err()
+        );
+    }
+}
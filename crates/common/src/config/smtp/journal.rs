@@ -0,0 +1,388 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::AHashMap;
+use mail_builder::{
+    MessageBuilder,
+    headers::{HeaderType, content_type::ContentType},
+    mime::{BodyPart, MimePart, make_boundary},
+};
+use mail_parser::DateTime;
+use std::{fmt::Write, sync::Arc};
+use utils::config::{Config, ConfigKey};
+
+pub const TENANT_JOURNAL_KEY: &str = "session.journal.tenant";
+
+const MAX_KEY_PREFIX_LEN: usize = 255;
+
+/// Journaling of the messages sent and received by the users of a tenant.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantJournaling {
+    pub target: JournalTarget,
+    /// Messages received from outside the tenant.
+    #[serde(default = "default_direction")]
+    pub inbound: bool,
+    /// Messages sent by the tenant's users to outside the tenant.
+    #[serde(default = "default_direction")]
+    pub outbound: bool,
+    /// Messages exchanged between the tenant's users.
+    #[serde(default = "default_direction")]
+    pub internal: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JournalTarget {
+    /// Journal envelopes are sent to an external mailbox.
+    Smtp { address: String },
+    /// Journal envelopes are written to a blob store allowed by the
+    /// administrator, such as an S3-compatible bucket. Keys are always
+    /// placed under `journal/{tenant_id}/`, followed by the prefix.
+    #[serde(rename_all = "camelCase")]
+    Bucket {
+        store: String,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key_prefix: Option<String>,
+    },
+}
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    serde::Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum JournalDirection {
+    Inbound,
+    Outbound,
+    Internal,
+}
+
+/// Copy of a message waiting to be delivered to a tenant's journal target.
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub direction: JournalDirection,
+    pub return_path: String,
+    pub recipients: Vec<String>,
+    pub received_at: u64,
+    pub message: Vec<u8>,
+}
+
+impl TenantJournaling {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantJournaling>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_JOURNAL_KEY, ".target.type") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_JOURNAL_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_JOURNAL_KEY}.{tenant_id}");
+            let target = match config.value((prefix.as_str(), "target.type")) {
+                Some("smtp") => JournalTarget::Smtp {
+                    address: config
+                        .value((prefix.as_str(), "target.address"))
+                        .unwrap_or_default()
+                        .to_string(),
+                },
+                Some("bucket") => JournalTarget::Bucket {
+                    store: config
+                        .value((prefix.as_str(), "target.store"))
+                        .unwrap_or_default()
+                        .to_string(),
+                    key_prefix: config
+                        .value((prefix.as_str(), "target.key-prefix"))
+                        .map(|prefix| prefix.to_string()),
+                },
+                typ => {
+                    let err = format!("Invalid journal target type {typ:?}");
+                    config.new_parse_error((prefix.as_str(), "target.type"), err);
+                    continue;
+                }
+            };
+            let journaling = TenantJournaling {
+                target,
+                inbound: config
+                    .property((prefix.as_str(), "inbound"))
+                    .unwrap_or(true),
+                outbound: config
+                    .property((prefix.as_str(), "outbound"))
+                    .unwrap_or(true),
+                internal: config
+                    .property((prefix.as_str(), "internal"))
+                    .unwrap_or(true),
+            };
+
+            match journaling.validate() {
+                Ok(()) => {
+                    tenants.insert(tenant_id, Arc::new(journaling));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.target {
+            JournalTarget::Smtp { address } => {
+                if !address
+                    .rsplit_once('@')
+                    .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+                {
+                    return Err(format!("Invalid journal address {address:?}"));
+                }
+            }
+            JournalTarget::Bucket { store, key_prefix } => {
+                if store.trim().is_empty() {
+                    return Err("Journal blob store is required".to_string());
+                }
+                if key_prefix.as_ref().is_some_and(|prefix| {
+                    prefix.len() > MAX_KEY_PREFIX_LEN
+                        || prefix.split('/').any(|segment| {
+                            segment.is_empty()
+                                || segment.starts_with('.')
+                                || !segment.chars().all(|ch| {
+                                    ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.')
+                                })
+                        })
+                }) {
+                    return Err("Invalid journal key prefix".to_string());
+                }
+            }
+        }
+        if !self.inbound && !self.outbound && !self.internal {
+            return Err("At least one direction must be journaled".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_JOURNAL_KEY}.{tenant_id}");
+        let mut keys = Vec::with_capacity(6);
+        match &self.target {
+            JournalTarget::Smtp { address } => {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.target.type"),
+                    value: "smtp".to_string(),
+                });
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.target.address"),
+                    value: address.clone(),
+                });
+            }
+            JournalTarget::Bucket { store, key_prefix } => {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.target.type"),
+                    value: "bucket".to_string(),
+                });
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.target.store"),
+                    value: store.clone(),
+                });
+                if let Some(key_prefix) = key_prefix {
+                    keys.push(ConfigKey {
+                        key: format!("{prefix}.target.key-prefix"),
+                        value: key_prefix.clone(),
+                    });
+                }
+            }
+        }
+        for (name, value) in [
+            ("inbound", self.inbound),
+            ("outbound", self.outbound),
+            ("internal", self.internal),
+        ] {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.{name}"),
+                value: value.to_string(),
+            });
+        }
+
+        keys
+    }
+
+    pub fn journals(&self, direction: JournalDirection) -> bool {
+        match direction {
+            JournalDirection::Inbound => self.inbound,
+            JournalDirection::Outbound => self.outbound,
+            JournalDirection::Internal => self.internal,
+        }
+    }
+}
+
+impl JournalDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalDirection::Inbound => "inbound",
+            JournalDirection::Outbound => "outbound",
+            JournalDirection::Internal => "internal",
+        }
+    }
+}
+
+impl JournalEntry {
+    /// Wraps the message in a journal envelope, a text part recording the
+    /// SMTP envelope followed by the unmodified message as an attachment.
+    pub fn envelope(&self, from: &str, to: &str, hostname: &str, journaled_at: u64) -> Vec<u8> {
+        let mut report = String::with_capacity(256);
+        let _ = write!(
+            report,
+            "Sender: <{}>\r\nDirection: {}\r\nReceived: {}\r\nJournaled: {}\r\n",
+            self.return_path,
+            self.direction.as_str(),
+            DateTime::from_timestamp(self.received_at as i64).to_rfc822(),
+            DateTime::from_timestamp(journaled_at as i64).to_rfc822(),
+        );
+        for rcpt in &self.recipients {
+            let _ = write!(report, "Recipient: <{rcpt}>\r\n");
+        }
+
+        MessageBuilder::new()
+            .from(from)
+            .to(to)
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .header(
+                "X-Journal-Direction",
+                HeaderType::Text(self.direction.as_str().into()),
+            )
+            .message_id(format!("<{}@{}>", make_boundary("."), hostname))
+            .subject(format!(
+                "Journal report: {} message from <{}>",
+                self.direction.as_str(),
+                self.return_path
+            ))
+            .body(MimePart::new(
+                ContentType::new("multipart/mixed"),
+                BodyPart::Multipart(vec![
+                    MimePart::new(
+                        ContentType::new("text/plain"),
+                        BodyPart::Text(report.into()),
+                    ),
+                    MimePart::new(
+                        ContentType::new("message/rfc822"),
+                        BodyPart::Binary(self.message.as_slice().into()),
+                    ),
+                ]),
+            ))
+            .write_to_vec()
+            .unwrap_or_default()
+    }
+}
+
+fn default_direction() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JournalDirection, JournalEntry, JournalTarget, TenantJournaling};
+    use mail_parser::{MessageParser, MimeHeaders};
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_journaling() {
+        let smtp = TenantJournaling {
+            target: JournalTarget::Smtp {
+                address: "journal@archive.example.com".to_string(),
+            },
+            inbound: true,
+            outbound: true,
+            internal: false,
+        };
+        let bucket = TenantJournaling {
+            target: JournalTarget::Bucket {
+                store: "s3".to_string(),
+                key_prefix: Some("acme/".to_string()),
+            },
+            inbound: false,
+            outbound: true,
+            internal: true,
+        };
+
+        // Configuration round trip
+        let mut config = Config {
+            keys: smtp
+                .config_keys(1)
+                .into_iter()
+                .chain(bucket.config_keys(2))
+                .map(|key| (key.key, key.value))
+                .chain([
+                    (
+                        "session.journal.tenant.3.target.type".to_string(),
+                        "smtp".to_string(),
+                    ),
+                    (
+                        "session.journal.tenant.3.target.address".to_string(),
+                        "journal".to_string(),
+                    ),
+                ])
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantJournaling::parse_all(&mut config);
+        assert_eq!(tenants[&1].as_ref(), &smtp);
+        assert_eq!(tenants[&2].as_ref(), &bucket);
+        assert!(!tenants.contains_key(&3));
+        assert!(!config.errors.is_empty());
+
+        // All directions are journaled by default
+        let journaling = serde_json::from_str::<TenantJournaling>(
+            r#"{"target": {"type": "bucket", "store": "s3", "keyPrefix": "acme/"}}"#,
+        )
+        .unwrap();
+        assert!(journaling.inbound && journaling.outbound && journaling.internal);
+        assert!(
+            serde_json::from_str::<TenantJournaling>(
+                r#"{"target": {"type": "smtp", "address": "journal@archive.example.com"},
+                    "inbound": false, "outbound": false, "internal": false}"#,
+            )
+            .unwrap()
+            .validate()
+            .is_err()
+        );
+
+        // The original message is attached unmodified
+        let entry = JournalEntry {
+            direction: JournalDirection::Outbound,
+            return_path: "john@example.org".to_string(),
+            recipients: vec![
+                "jane@example.com".to_string(),
+                "bill@example.net".to_string(),
+            ],
+            received_at: 1700000000,
+            message: b"From: john@example.org\r\nSubject: Hi\r\n\r\nHello there.\r\n".to_vec(),
+        };
+        let envelope = entry.envelope(
+            "postmaster@mx.example.org",
+            "journal@archive.example.com",
+            "mx.example.org",
+            1700000060,
+        );
+        let parsed = MessageParser::new().parse(&envelope).unwrap();
+        let report = parsed.body_text(0).unwrap();
+        assert!(report.contains("Sender: <john@example.org>"), "{report}");
+        assert!(report.contains("Direction: outbound"), "{report}");
+        assert!(report.contains("Recipient: <jane@example.com>"), "{report}");
+        assert!(report.contains("Recipient: <bill@example.net>"), "{report}");
+        let attachment = parsed.attachment(0).unwrap();
+        assert!(attachment.is_content_type("message", "rfc822"));
+        assert_eq!(attachment.message().unwrap().subject(), Some("Hi"));
+    }
+}
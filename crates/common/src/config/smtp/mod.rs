@@ -7,6 +7,9 @@
 use utils::config::{Config, Rate};
 
 pub mod auth;
+pub mod bimi;
+pub mod disclaimer;
+pub mod journal;
 pub mod queue;
 pub mod report;
 pub mod resolver;
@@ -13,14 +13,20 @@ use crate::{
 use ahash::AHashMap;
 use mail_auth::IpLookupStrategy;
 use mail_send::Credentials;
+use regex::Regex;
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     hash::{Hash, Hasher},
     net::IpAddr,
+    sync::Arc,
     time::Duration,
 };
 use throttle::parse_queue_rate_limiter_key;
-use utils::config::{Config, utils::ParseValue};
+use utils::config::{Config, ConfigKey, utils::ParseValue};
+
+pub const TENANT_ROUTING_KEY: &str = "queue.tenant";
+pub const DOMAIN_ROUTES_KEY: &str = "session.rcpt.route";
 
 #[derive(
     Debug,
@@ -62,6 +68,18 @@ pub struct QueueConfig {
     pub routing_strategy: AHashMap<String, RoutingStrategy>,
     pub tls_strategy: AHashMap<String, TlsStrategy>,
     pub virtual_queues: AHashMap<QueueName, VirtualQueue>,
+
+    // Delivery log
+    pub delivery_log: Option<DeliveryLogConfig>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeliveryLogConfig {
+    pub retention: Option<Duration>,
+    /// DNSBL zones the sending IPs are checked against in deliverability reports.
+    pub dnsbl_zones: Vec<String>,
+    /// Sending IPs to check, defaults to the source IPs of all connection strategies.
+    pub sending_ips: Vec<IpAddr>,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
@@ -176,6 +194,107 @@ pub struct RelayConfig {
     pub auth: Option<Credentials<String>>,
     pub tls_implicit: bool,
     pub tls_allow_invalid_certs: bool,
+    /// Set for relays configured by tenants, which may only be reached at
+    /// publicly routable addresses.
+    pub public_only: bool,
+}
+
+/// Outbound relays of a tenant, used for messages submitted by its users
+/// instead of MX delivery.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantRouting {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<TenantRelay>,
+    #[serde(default)]
+    pub domains: BTreeMap<String, TenantRelay>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantRelay {
+    pub host: String,
+    #[serde(default = "default_relay_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: TenantRelayTls,
+    #[serde(default)]
+    pub allow_invalid_certs: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Never returned to clients.
+    #[serde(default)]
+    #[serde(skip_serializing)]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TenantRelayTls {
+    #[default]
+    Implicit,
+    StartTls,
+}
+
+/// Parsed routes of a tenant, keyed by destination domain.
+#[derive(Debug, Clone, Default)]
+pub struct TenantRoutes {
+    pub settings: TenantRouting,
+    pub default: Option<RoutingStrategy>,
+    pub domains: AHashMap<String, RoutingStrategy>,
+}
+
+/// Inbound routing rule of a local domain, evaluated before the recipient
+/// is resolved against the directory.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct DomainRoute {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(rename = "match")]
+    pub matcher: LocalPartMatch,
+    pub action: DomainRouteAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum LocalPartMatch {
+    Exact { value: String },
+    Prefix { value: String },
+    Regex { value: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum DomainRouteAction {
+    Local,
+    Forward { address: String },
+    Relay(TenantRelay),
+    Reject { message: String },
+}
+
+/// Routing rules of a domain sorted by priority, along with their compiled
+/// matchers and relay routes.
+#[derive(Debug, Clone, Default)]
+pub struct DomainRoutes {
+    pub rules: Vec<DomainRoute>,
+    compiled: Vec<(LocalPartMatcher, Option<RoutingStrategy>)>,
+}
+
+#[derive(Debug, Clone)]
+enum LocalPartMatcher {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -239,6 +358,11 @@ impl Default for QueueConfig {
             quota: QueueQuotas::default(),
             queue_strategy: Default::default(),
             virtual_queues: Default::default(),
+            delivery_log: Some(DeliveryLogConfig {
+                retention: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+                dnsbl_zones: Vec::new(),
+                sending_ips: Vec::new(),
+            }),
             connection_strategy: Default::default(),
             routing_strategy: Default::default(),
             tls_strategy: Default::default(),
@@ -286,6 +410,30 @@ impl QueueConfig {
         queue.inbound_limiters = parse_inbound_rate_limiters(config);
         queue.outbound_limiters = parse_outbound_rate_limiters(config);
         queue.quota = parse_queue_quota(config);
+
+        // Parse delivery log
+        queue.delivery_log = if config
+            .property_or_default("queue.delivery-log.enable", "true")
+            .unwrap_or(true)
+        {
+            Some(DeliveryLogConfig {
+                retention: config
+                    .property_or_default::<Option<Duration>>("queue.delivery-log.retention", "30d")
+                    .unwrap_or(Some(Duration::from_secs(30 * 24 * 60 * 60))),
+                dnsbl_zones: config
+                    .values("queue.delivery-log.dnsbl.zones")
+                    .map(|(_, zone)| zone.trim().trim_end_matches('.').to_lowercase())
+                    .filter(|zone| !zone.is_empty())
+                    .collect(),
+                sending_ips: config
+                    .properties::<IpAddr>("queue.delivery-log.dnsbl.sending-ips")
+                    .into_iter()
+                    .map(|(_, ip)| ip)
+                    .collect(),
+            })
+        } else {
+            None
+        };
         queue
     }
 }
@@ -429,6 +577,7 @@ fn parse_route(config: &mut Config, id: &str) -> Option<RoutingStrategy> {
             tls_allow_invalid_certs: config
                 .property(("queue.route", id, "tls.allow-invalid-certs"))
                 .unwrap_or(false),
+            public_only: false,
         })
         .into(),
         "local" => RoutingStrategy::Local.into(),
@@ -453,6 +602,409 @@ fn parse_route(config: &mut Config, id: &str) -> Option<RoutingStrategy> {
     }
 }
 
+fn default_relay_port() -> u16 {
+    465
+}
+
+impl TenantRouting {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantRoutes>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_ROUTING_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_ROUTING_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let prefix = format!("{TENANT_ROUTING_KEY}.{id}");
+            let mut settings = TenantRouting {
+                relay: TenantRelay::parse(config, &format!("{prefix}.relay")),
+                domains: BTreeMap::new(),
+            };
+            for domain in config.sub_keys((prefix.as_str(), "domain"), ".host") {
+                if let Some(relay) =
+                    TenantRelay::parse(config, &format!("{prefix}.domain.{domain}"))
+                {
+                    settings.domains.insert(domain, relay);
+                }
+            }
+
+            match settings.validate() {
+                Ok(()) => {
+                    tenants.insert(tenant_id, Arc::new(TenantRoutes::from(settings)));
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(domain) = self.domains.keys().find(|domain| {
+            domain.is_empty()
+                || domain.starts_with('.')
+                || domain.contains(|ch: char| ch.is_whitespace() || ch == '@' || ch.is_uppercase())
+        }) {
+            return Err(format!("Invalid destination domain {domain:?}"));
+        }
+
+        self.relay
+            .iter()
+            .chain(self.domains.values())
+            .try_for_each(TenantRelay::validate)
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_ROUTING_KEY}.{tenant_id}");
+        let mut keys = Vec::new();
+
+        if let Some(relay) = &self.relay {
+            relay.config_keys(&format!("{prefix}.relay"), &mut keys);
+        }
+        for (domain, relay) in &self.domains {
+            relay.config_keys(&format!("{prefix}.domain.{domain}"), &mut keys);
+        }
+
+        keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relay.is_none() && self.domains.is_empty()
+    }
+}
+
+impl TenantRelay {
+    /// Relays are set by tenants, so hosts given as addresses have to be
+    /// publicly routable.
+    pub fn validate(&self) -> Result<(), String> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err(format!("Invalid relay host {:?}", self.host));
+        }
+        if host.eq_ignore_ascii_case("localhost")
+            || host.to_ascii_lowercase().ends_with(".localhost")
+            || host
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| !utils::is_public_ip(ip))
+        {
+            return Err(format!(
+                "Relay host {:?} is not a public address",
+                self.host
+            ));
+        }
+        if self.port == 0 {
+            return Err(format!("Invalid port for relay {:?}", self.host));
+        }
+        if self.username.is_some() != self.secret.is_some() {
+            return Err(format!(
+                "Both username and secret are required to authenticate with relay {:?}",
+                self.host
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn parse(config: &mut Config, prefix: &str) -> Option<Self> {
+        Some(TenantRelay {
+            host: config.value((prefix, "host"))?.to_string(),
+            port: config
+                .property((prefix, "port"))
+                .unwrap_or_else(default_relay_port),
+            tls: match config.value((prefix, "tls")) {
+                Some("starttls") => TenantRelayTls::StartTls,
+                _ => TenantRelayTls::Implicit,
+            },
+            allow_invalid_certs: config
+                .property((prefix, "allow-invalid-certs"))
+                .unwrap_or(false),
+            username: config
+                .value((prefix, "auth.username"))
+                .map(|v| v.to_string()),
+            secret: config.value((prefix, "auth.secret")).map(|v| v.to_string()),
+        })
+    }
+
+    fn config_keys(&self, prefix: &str, keys: &mut Vec<ConfigKey>) {
+        for (key, value) in [
+            ("host", Some(self.host.clone())),
+            ("port", Some(self.port.to_string())),
+            (
+                "tls",
+                Some(
+                    match self.tls {
+                        TenantRelayTls::Implicit => "implicit",
+                        TenantRelayTls::StartTls => "starttls",
+                    }
+                    .to_string(),
+                ),
+            ),
+            (
+                "allow-invalid-certs",
+                Some(self.allow_invalid_certs.to_string()),
+            ),
+            ("auth.username", self.username.clone()),
+            ("auth.secret", self.secret.clone()),
+        ] {
+            if let Some(value) = value {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.{key}"),
+                    value,
+                });
+            }
+        }
+    }
+
+    pub fn to_route(&self) -> RoutingStrategy {
+        RoutingStrategy::Relay(RelayConfig {
+            address: self.host.clone(),
+            port: self.port,
+            protocol: ServerProtocol::Smtp,
+            auth: if let (Some(username), Some(secret)) = (&self.username, &self.secret) {
+                Credentials::new(username.clone(), secret.clone()).into()
+            } else {
+                None
+            },
+            tls_implicit: self.tls == TenantRelayTls::Implicit,
+            tls_allow_invalid_certs: self.allow_invalid_certs,
+            public_only: true,
+        })
+    }
+}
+
+impl From<TenantRouting> for TenantRoutes {
+    fn from(settings: TenantRouting) -> Self {
+        TenantRoutes {
+            default: settings.relay.as_ref().map(TenantRelay::to_route),
+            domains: settings
+                .domains
+                .iter()
+                .map(|(domain, relay)| (domain.clone(), relay.to_route()))
+                .collect(),
+            settings,
+        }
+    }
+}
+
+impl TenantRoutes {
+    /// Returns the relay of the tenant for a destination domain.
+    pub fn route(&self, domain: &str) -> Option<&RoutingStrategy> {
+        self.domains.get(domain).or(self.default.as_ref())
+    }
+}
+
+impl DomainRoute {
+    /// Parses the routing rules of all domains, keyed by domain name.
+    pub fn parse_all(config: &mut Config) -> AHashMap<String, Arc<DomainRoutes>> {
+        let mut domains: AHashMap<String, Vec<DomainRoute>> = AHashMap::new();
+
+        for id in config.sub_keys(DOMAIN_ROUTES_KEY, ".domain") {
+            let prefix = format!("{DOMAIN_ROUTES_KEY}.{id}");
+            let Some(domain) = config
+                .value((prefix.as_str(), "domain"))
+                .map(|d| d.to_string())
+            else {
+                continue;
+            };
+            let Some(route) = DomainRoute::parse(config, &prefix, id) else {
+                config.new_parse_error(prefix, "Invalid routing rule");
+                continue;
+            };
+
+            match route.validate() {
+                Ok(()) => {
+                    domains.entry(domain).or_default().push(route);
+                }
+                Err(err) => {
+                    config.new_parse_error(prefix, err);
+                }
+            }
+        }
+
+        domains
+            .into_iter()
+            .map(|(domain, routes)| (domain, Arc::new(DomainRoutes::new(routes))))
+            .collect()
+    }
+
+    fn parse(config: &mut Config, prefix: &str, id: String) -> Option<Self> {
+        let value = config.value((prefix, "match.value"))?.to_string();
+        Some(DomainRoute {
+            priority: config.property((prefix, "priority")).unwrap_or_default(),
+            matcher: match config.value((prefix, "match.type"))? {
+                "exact" => LocalPartMatch::Exact { value },
+                "prefix" => LocalPartMatch::Prefix { value },
+                "regex" => LocalPartMatch::Regex { value },
+                _ => return None,
+            },
+            action: match config.value((prefix, "action.type"))? {
+                "local" => DomainRouteAction::Local,
+                "forward" => DomainRouteAction::Forward {
+                    address: config.value((prefix, "action.address"))?.to_string(),
+                },
+                "relay" => DomainRouteAction::Relay(TenantRelay::parse(
+                    config,
+                    &format!("{prefix}.action"),
+                )?),
+                "reject" => DomainRouteAction::Reject {
+                    message: config.value((prefix, "action.message"))?.to_string(),
+                },
+                _ => return None,
+            },
+            id,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let value = self.matcher.value();
+        if value.is_empty() || value.contains(|ch: char| ch.is_whitespace() || ch == '@') {
+            return Err(format!("Invalid local part {value:?}"));
+        }
+        self.matcher.compile()?;
+
+        match &self.action {
+            DomainRouteAction::Local => {}
+            DomainRouteAction::Forward { address } => {
+                if utils::sanitize_email(address).as_deref() != Some(address.as_str()) {
+                    return Err(format!("Invalid forward address {address:?}"));
+                }
+            }
+            DomainRouteAction::Relay(relay) => relay.validate()?,
+            DomainRouteAction::Reject { message } => {
+                if message.is_empty()
+                    || message.len() > 255
+                    || message.contains(|ch: char| ch.is_control())
+                {
+                    return Err(format!("Invalid reject message {message:?}"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, domain: &str) -> Vec<ConfigKey> {
+        let prefix = format!("{DOMAIN_ROUTES_KEY}.{}", self.id);
+        let mut keys = Vec::new();
+
+        for (key, value) in [
+            ("domain", Some(domain.to_string())),
+            ("priority", Some(self.priority.to_string())),
+            ("match.type", Some(self.matcher.as_str().to_string())),
+            ("match.value", Some(self.matcher.value().to_string())),
+            ("action.type", Some(self.action.as_str().to_string())),
+            (
+                "action.address",
+                match &self.action {
+                    DomainRouteAction::Forward { address } => Some(address.clone()),
+                    _ => None,
+                },
+            ),
+            (
+                "action.message",
+                match &self.action {
+                    DomainRouteAction::Reject { message } => Some(message.clone()),
+                    _ => None,
+                },
+            ),
+        ] {
+            if let Some(value) = value {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.{key}"),
+                    value,
+                });
+            }
+        }
+        if let DomainRouteAction::Relay(relay) = &self.action {
+            relay.config_keys(&format!("{prefix}.action"), &mut keys);
+        }
+
+        keys
+    }
+}
+
+impl LocalPartMatch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LocalPartMatch::Exact { .. } => "exact",
+            LocalPartMatch::Prefix { .. } => "prefix",
+            LocalPartMatch::Regex { .. } => "regex",
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            LocalPartMatch::Exact { value }
+            | LocalPartMatch::Prefix { value }
+            | LocalPartMatch::Regex { value } => value,
+        }
+    }
+
+    fn compile(&self) -> Result<LocalPartMatcher, String> {
+        match self {
+            LocalPartMatch::Exact { value } => Ok(LocalPartMatcher::Exact(value.to_lowercase())),
+            LocalPartMatch::Prefix { value } => Ok(LocalPartMatcher::Prefix(value.to_lowercase())),
+            // Expressions have to match the whole local part
+            LocalPartMatch::Regex { value } => Regex::new(&format!("^(?i:{value})$"))
+                .map(LocalPartMatcher::Regex)
+                .map_err(|err| format!("Invalid regular expression {value:?}: {err}")),
+        }
+    }
+}
+
+impl DomainRouteAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainRouteAction::Local => "local",
+            DomainRouteAction::Forward { .. } => "forward",
+            DomainRouteAction::Relay(_) => "relay",
+            DomainRouteAction::Reject { .. } => "reject",
+        }
+    }
+}
+
+impl DomainRoutes {
+    pub fn new(mut rules: Vec<DomainRoute>) -> Self {
+        rules.sort_by(|a, b| a.priority.cmp(&b.priority).then_with(|| a.id.cmp(&b.id)));
+        let mut compiled = Vec::with_capacity(rules.len());
+        rules.retain(|rule| {
+            if let Ok(matcher) = rule.matcher.compile() {
+                let route = match &rule.action {
+                    DomainRouteAction::Relay(relay) => Some(relay.to_route()),
+                    _ => None,
+                };
+                compiled.push((matcher, route));
+                true
+            } else {
+                false
+            }
+        });
+        DomainRoutes { rules, compiled }
+    }
+
+    /// Returns the first rule, in priority order, matching a local part.
+    pub fn resolve(&self, local_part: &str) -> Option<&DomainRoute> {
+        self.position(local_part).map(|idx| &self.rules[idx])
+    }
+
+    /// Returns the relay of the rule matching a local part, if any.
+    pub fn relay(&self, local_part: &str) -> Option<&RoutingStrategy> {
+        self.position(local_part)
+            .and_then(|idx| self.compiled[idx].1.as_ref())
+    }
+
+    fn position(&self, local_part: &str) -> Option<usize> {
+        let local_part = local_part.to_lowercase();
+        self.compiled.iter().position(|(matcher, _)| match matcher {
+            LocalPartMatcher::Exact(value) => &local_part == value,
+            LocalPartMatcher::Prefix(value) => local_part.starts_with(value.as_str()),
+            LocalPartMatcher::Regex(regex) => regex.is_match(&local_part),
+        })
+    }
+}
+
 fn parse_tls_strategies(config: &mut Config) -> AHashMap<String, TlsStrategy> {
     let mut entries = AHashMap::new();
     for key in config.sub_keys_with_suffixes(
@@ -846,6 +1398,7 @@ impl std::fmt::Debug for RelayConfig {
             .field("protocol", &self.protocol)
             .field("tls_implicit", &self.tls_implicit)
             .field("tls_allow_invalid_certs", &self.tls_allow_invalid_certs)
+            .field("public_only", &self.public_only)
             .finish()
     }
 }
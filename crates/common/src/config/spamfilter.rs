@@ -5,17 +5,21 @@
  */
 
 use super::{Variable, functions::ResolveVariable, if_block::IfBlock, tokenizer::TokenMap};
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use mail_auth::common::resolver::ToReverseName;
-use nlp::classifier::model::{CcfhClassifier, FhClassifier};
+use nlp::{
+    classifier::model::{CcfhClassifier, FhClassifier},
+    language::Language,
+};
 use std::{
     net::{IpAddr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 use tokio::net::lookup_host;
 use utils::{
     cache::CacheItemWeight,
-    config::{Config, utils::ParseValue},
+    config::{Config, ConfigKey, ipmask::IpAddrMask, utils::ParseValue},
     glob::GlobMap,
 };
 
@@ -55,6 +59,85 @@ pub struct SpamFilterScoreConfig {
     pub spam_threshold: f32,
 }
 
+pub const TENANT_SPAM_KEY: &str = "spam-filter.tenant";
+
+/// Spam filter settings of a tenant that take precedence over the global
+/// ones when classifying messages addressed to its domains.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TenantSpamSettings {
+    #[serde(default)]
+    pub spam_threshold: Option<f32>,
+    #[serde(default)]
+    pub reject_threshold: Option<f32>,
+    #[serde(default)]
+    pub add_headers: Option<bool>,
+    #[serde(default)]
+    pub trusted_domains: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default)]
+    pub language_score: Option<f32>,
+    #[serde(default)]
+    pub blocked_sender_action: Option<BlockedSenderAction>,
+    #[serde(default)]
+    pub greylist: Option<bool>,
+    #[serde(default)]
+    pub greylist_delay: Option<u64>,
+    #[serde(default)]
+    pub greylist_whitelist_after: Option<u32>,
+}
+
+/// Action taken on messages from senders in the block list of a tenant.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockedSenderAction {
+    #[default]
+    Reject,
+    Quarantine,
+}
+
+pub const TENANT_SENDERS_KEY: &str = "spam-filter.sender-list";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderListType {
+    Allow,
+    Block,
+}
+
+/// Sender allow and block lists of a tenant, consulted before the global
+/// settings when classifying messages addressed to its domains.
+#[derive(Debug, Default)]
+pub struct TenantSenderLists {
+    pub allow: SenderList,
+    pub block: SenderList,
+}
+
+/// Entries of a sender list along with a matcher compiled from them.
+#[derive(Debug, Default)]
+pub struct SenderList {
+    pub entries: Vec<SenderListEntry>,
+    addresses: AHashMap<String, u64>,
+    domains: AHashMap<String, u64>,
+    networks: Vec<(IpAddrMask, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SenderListEntry {
+    #[serde(default)]
+    pub id: String,
+    pub value: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct DnsBlConfig {
     pub max_ip_checks: usize,
@@ -576,6 +659,389 @@ impl SpamFilterScoreConfig {
     }
 }
 
+impl TenantSpamSettings {
+    pub const DEFAULT_LANGUAGE_SCORE: f32 = 3.0;
+    pub const DEFAULT_GREYLIST_EXPIRY: u64 = 30 * 86400;
+    pub const MAX_GREYLIST_DELAY: u64 = 86400;
+
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantSpamSettings>> {
+        let mut tenants = AHashMap::new();
+
+        for id in config.sub_keys(TENANT_SPAM_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_SPAM_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let settings = TenantSpamSettings {
+                spam_threshold: config.property((TENANT_SPAM_KEY, id.as_str(), "score.spam")),
+                reject_threshold: config.property((TENANT_SPAM_KEY, id.as_str(), "score.reject")),
+                add_headers: config.property((TENANT_SPAM_KEY, id.as_str(), "add-headers")),
+                trusted_domains: config
+                    .set_values((TENANT_SPAM_KEY, id.as_str(), "trusted-domain"))
+                    .map(|domain| domain.to_string())
+                    .collect(),
+                languages: config
+                    .set_values((TENANT_SPAM_KEY, id.as_str(), "language"))
+                    .map(|language| language.to_string())
+                    .collect(),
+                language_score: config.property((TENANT_SPAM_KEY, id.as_str(), "score.language")),
+                blocked_sender_action: config.property((
+                    TENANT_SPAM_KEY,
+                    id.as_str(),
+                    "blocked-sender-action",
+                )),
+                greylist: config.property((TENANT_SPAM_KEY, id.as_str(), "greylist.enable")),
+                greylist_delay: config.property((TENANT_SPAM_KEY, id.as_str(), "greylist.delay")),
+                greylist_whitelist_after: config.property((
+                    TENANT_SPAM_KEY,
+                    id.as_str(),
+                    "greylist.whitelist-after",
+                )),
+            };
+
+            match settings.validate() {
+                Ok(()) => {
+                    tenants.insert(tenant_id, Arc::new(settings));
+                }
+                Err(err) => {
+                    config.new_parse_error((TENANT_SPAM_KEY, id.as_str()), err);
+                }
+            }
+        }
+
+        tenants
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, score) in [
+            ("spamThreshold", self.spam_threshold),
+            ("rejectThreshold", self.reject_threshold),
+            ("languageScore", self.language_score),
+        ] {
+            if score.is_some_and(|score| !score.is_finite()) {
+                return Err(format!("Invalid {name} score"));
+            }
+        }
+        if let Some(domain) = self.trusted_domains.iter().find(|domain| {
+            domain.is_empty()
+                || domain.starts_with('.')
+                || domain.contains(|ch: char| ch.is_whitespace() || ch == '@' || ch.is_uppercase())
+        }) {
+            return Err(format!("Invalid trusted domain {domain:?}"));
+        }
+        if let Some(language) = self
+            .languages
+            .iter()
+            .find(|language| Language::from_iso_639(language).is_none())
+        {
+            return Err(format!("Invalid language code {language:?}"));
+        }
+        if self
+            .greylist_delay
+            .is_some_and(|delay| delay > Self::MAX_GREYLIST_DELAY)
+        {
+            return Err(format!(
+                "Greylist delay cannot exceed {} seconds",
+                Self::MAX_GREYLIST_DELAY
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn config_keys(&self, tenant_id: u32) -> Vec<ConfigKey> {
+        let prefix = format!("{TENANT_SPAM_KEY}.{tenant_id}");
+        let mut keys = Vec::new();
+
+        for (key, value) in [
+            ("score.spam", self.spam_threshold.map(|v| v.to_string())),
+            ("score.reject", self.reject_threshold.map(|v| v.to_string())),
+            ("score.language", self.language_score.map(|v| v.to_string())),
+            ("add-headers", self.add_headers.map(|v| v.to_string())),
+            (
+                "blocked-sender-action",
+                self.blocked_sender_action.map(|v| v.as_str().to_string()),
+            ),
+            ("greylist.enable", self.greylist.map(|v| v.to_string())),
+            ("greylist.delay", self.greylist_delay.map(|v| v.to_string())),
+            (
+                "greylist.whitelist-after",
+                self.greylist_whitelist_after.map(|v| v.to_string()),
+            ),
+        ] {
+            if let Some(value) = value {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.{key}"),
+                    value,
+                });
+            }
+        }
+        for domain in &self.trusted_domains {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.trusted-domain.{domain}"),
+                value: String::new(),
+            });
+        }
+        for language in &self.languages {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.language.{language}"),
+                value: String::new(),
+            });
+        }
+
+        keys
+    }
+
+    /// Whether a message in the given language is outside the preferences
+    /// of the tenant.
+    pub fn is_foreign_language(&self, language: Language) -> bool {
+        !self.languages.is_empty()
+            && !self
+                .languages
+                .iter()
+                .any(|code| Language::from_iso_639(code) == Some(language))
+    }
+
+    pub fn is_trusted_domain(&self, domain: &str) -> bool {
+        self.trusted_domains.iter().any(|trusted| {
+            domain == trusted
+                || domain
+                    .strip_suffix(trusted.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+}
+
+impl BlockedSenderAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockedSenderAction::Reject => "reject",
+            BlockedSenderAction::Quarantine => "quarantine",
+        }
+    }
+}
+
+impl ParseValue for BlockedSenderAction {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "reject" => Ok(BlockedSenderAction::Reject),
+            "quarantine" => Ok(BlockedSenderAction::Quarantine),
+            other => Err(format!("Invalid blocked sender action {other:?}.",)),
+        }
+    }
+}
+
+impl SenderListType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "allow" => Some(SenderListType::Allow),
+            "block" => Some(SenderListType::Block),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SenderListType::Allow => "allow",
+            SenderListType::Block => "block",
+        }
+    }
+}
+
+impl TenantSenderLists {
+    pub fn parse_all(config: &mut Config) -> AHashMap<u32, Arc<TenantSenderLists>> {
+        let mut tenants = AHashMap::new();
+        let now = store::write::now();
+
+        for id in config.sub_keys(TENANT_SENDERS_KEY, "") {
+            let Ok(tenant_id) = id.parse::<u32>() else {
+                config.new_parse_error((TENANT_SENDERS_KEY, id.as_str()), "Invalid tenant id");
+                continue;
+            };
+            let mut lists = TenantSenderLists::default();
+            for list_type in [SenderListType::Allow, SenderListType::Block] {
+                let prefix = format!("{TENANT_SENDERS_KEY}.{id}.{}", list_type.as_str());
+                let mut entries = Vec::new();
+                for entry_id in config.sub_keys(prefix.as_str(), ".value") {
+                    let mut entry = SenderListEntry {
+                        value: config
+                            .value((prefix.as_str(), entry_id.as_str(), "value"))
+                            .unwrap_or_default()
+                            .to_string(),
+                        expires: config.property((prefix.as_str(), entry_id.as_str(), "expires")),
+                        comment: config
+                            .value((prefix.as_str(), entry_id.as_str(), "comment"))
+                            .map(|comment| comment.to_string()),
+                        id: entry_id,
+                    };
+                    if entry.is_expired(now) {
+                        continue;
+                    }
+                    match entry.normalize() {
+                        Ok(()) => entries.push(entry),
+                        Err(err) => {
+                            config.new_parse_error((prefix.as_str(), entry.id.as_str()), err);
+                        }
+                    }
+                }
+                *lists.list_mut(list_type) = SenderList::new(entries);
+            }
+
+            if !lists.is_empty() {
+                tenants.insert(tenant_id, Arc::new(lists));
+            }
+        }
+
+        tenants
+    }
+
+    pub fn list(&self, list_type: SenderListType) -> &SenderList {
+        match list_type {
+            SenderListType::Allow => &self.allow,
+            SenderListType::Block => &self.block,
+        }
+    }
+
+    pub fn list_mut(&mut self, list_type: SenderListType) -> &mut SenderList {
+        match list_type {
+            SenderListType::Allow => &mut self.allow,
+            SenderListType::Block => &mut self.block,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.entries.is_empty() && self.block.entries.is_empty()
+    }
+}
+
+impl SenderList {
+    pub fn new(entries: Vec<SenderListEntry>) -> Self {
+        let mut list = SenderList {
+            addresses: AHashMap::new(),
+            domains: AHashMap::new(),
+            networks: Vec::new(),
+            entries: Vec::new(),
+        };
+        for entry in &entries {
+            let expires = entry.expires.unwrap_or(u64::MAX);
+            if let Ok(network) = IpAddrMask::parse_value(&entry.value) {
+                list.networks.push((network, expires));
+            } else if entry.value.contains('@') {
+                list.addresses.insert(entry.value.clone(), expires);
+            } else {
+                list.domains.insert(entry.value.clone(), expires);
+            }
+        }
+        list.entries = entries;
+        list
+    }
+
+    /// Whether any of the sender addresses or the remote IP address are listed,
+    /// domain entries also match their subdomains.
+    pub fn matches<'x>(
+        &self,
+        addresses: impl IntoIterator<Item = &'x str>,
+        remote_ip: Option<IpAddr>,
+        now: u64,
+    ) -> bool {
+        for address in addresses {
+            if self
+                .addresses
+                .get(address)
+                .is_some_and(|expires| *expires > now)
+            {
+                return true;
+            }
+
+            let mut domain = address
+                .rsplit_once('@')
+                .map_or(address, |(_, domain)| domain);
+            loop {
+                if self
+                    .domains
+                    .get(domain)
+                    .is_some_and(|expires| *expires > now)
+                {
+                    return true;
+                }
+                match domain.split_once('.') {
+                    Some((_, parent)) if parent.contains('.') => domain = parent,
+                    _ => break,
+                }
+            }
+        }
+
+        remote_ip.is_some_and(|ip| {
+            self.networks
+                .iter()
+                .any(|(network, expires)| *expires > now && network.matches(&ip))
+        })
+    }
+}
+
+impl SenderListEntry {
+    /// Normalizes the value of the entry, which may be an e-mail address,
+    /// a domain name or an IP network.
+    pub fn normalize(&mut self) -> Result<(), String> {
+        self.value = self.value.trim().to_lowercase();
+        if let Some(comment) = &mut self.comment {
+            *comment = comment.trim().to_string();
+            if comment.is_empty() {
+                self.comment = None;
+            } else if comment.len() > 255 || comment.contains(['\r', '\n']) {
+                return Err("Invalid comment".to_string());
+            }
+        }
+
+        if IpAddrMask::parse_value(&self.value).is_ok() {
+            return Ok(());
+        }
+        let (local, domain) = self.value.rsplit_once('@').unwrap_or(("", &self.value));
+        if (self.value.contains('@') && local.is_empty())
+            || domain.is_empty()
+            || domain.starts_with('.')
+            || domain.ends_with('.')
+            || !domain.contains('.')
+            || self
+                .value
+                .contains(|ch: char| ch.is_whitespace() || ch == '/' || ch == '*')
+        {
+            return Err(format!("Invalid sender {:?}", self.value));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    pub fn config_keys(&self, tenant_id: u32, list_type: SenderListType) -> Vec<ConfigKey> {
+        let prefix = format!(
+            "{TENANT_SENDERS_KEY}.{tenant_id}.{}.{}",
+            list_type.as_str(),
+            self.id
+        );
+        let mut keys = vec![ConfigKey {
+            key: format!("{prefix}.value"),
+            value: self.value.clone(),
+        }];
+        if let Some(expires) = self.expires {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.expires"),
+                value: expires.to_string(),
+            });
+        }
+        if let Some(comment) = &self.comment {
+            keys.push(ConfigKey {
+                key: format!("{prefix}.comment"),
+                value: comment.clone(),
+            });
+        }
+        keys
+    }
+}
+
 impl ParseValue for Element {
     fn parse_value(value: &str) -> utils::config::Result<Self> {
         match value {
@@ -836,6 +1302,10 @@ impl IpResolver {
             ip,
         }
     }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
 }
 
 impl CacheItemWeight for IpResolver {
@@ -852,3 +1322,136 @@ impl<T> SpamFilterAction<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BlockedSenderAction, SenderListEntry, SenderListType, TenantSenderLists, TenantSpamSettings,
+    };
+    use nlp::language::Language;
+    use utils::config::Config;
+
+    #[test]
+    fn tenant_spam_settings() {
+        let settings = TenantSpamSettings {
+            spam_threshold: Some(7.5),
+            reject_threshold: None,
+            add_headers: Some(false),
+            trusted_domains: vec!["example.org".to_string()],
+            languages: vec!["de".to_string(), "en".to_string()],
+            language_score: Some(1.0),
+            blocked_sender_action: Some(BlockedSenderAction::Quarantine),
+            greylist: Some(true),
+            greylist_delay: Some(60),
+            greylist_whitelist_after: Some(3),
+        };
+        assert_eq!(settings.validate(), Ok(()));
+
+        // Settings are stored as config keys and parsed back
+        let mut config = Config {
+            keys: settings
+                .config_keys(12)
+                .into_iter()
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantSpamSettings::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(tenants.get(&12).map(|s| s.as_ref()), Some(&settings));
+
+        assert!(settings.is_trusted_domain("example.org"));
+        assert!(settings.is_trusted_domain("news.example.org"));
+        assert!(!settings.is_trusted_domain("badexample.org"));
+        assert!(!settings.is_foreign_language(Language::German));
+        assert!(settings.is_foreign_language(Language::Spanish));
+        assert!(!TenantSpamSettings::default().is_foreign_language(Language::Spanish));
+
+        for invalid in [
+            TenantSpamSettings {
+                languages: vec!["klingon".to_string()],
+                ..Default::default()
+            },
+            TenantSpamSettings {
+                trusted_domains: vec!["user@example.org".to_string()],
+                ..Default::default()
+            },
+            TenantSpamSettings {
+                spam_threshold: Some(f32::NAN),
+                ..Default::default()
+            },
+            TenantSpamSettings {
+                greylist_delay: Some(TenantSpamSettings::MAX_GREYLIST_DELAY + 1),
+                ..Default::default()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn tenant_sender_lists() {
+        let now = store::write::now();
+        let entry = |id: &str, value: &str, expires: Option<u64>| {
+            let mut entry = SenderListEntry {
+                id: id.to_string(),
+                value: value.to_string(),
+                expires,
+                comment: Some(" Partner ".to_string()),
+            };
+            assert_eq!(entry.normalize(), Ok(()), "{value}");
+            entry
+        };
+        let allow = [
+            entry("1", "Boss@Example.org", None),
+            entry("2", "partner.net", Some(now + 3600)),
+            entry("3", "expired.net", Some(now - 1)),
+        ];
+        let block = [
+            entry("4", "192.168.1.0/24", None),
+            entry("5", "spam.org", None),
+        ];
+        assert_eq!(allow[0].value, "boss@example.org");
+        assert_eq!(allow[0].comment.as_deref(), Some("Partner"));
+
+        // Entries are stored as config keys, expired ones are skipped
+        let mut config = Config {
+            keys: allow
+                .iter()
+                .flat_map(|entry| entry.config_keys(3, SenderListType::Allow))
+                .chain(
+                    block
+                        .iter()
+                        .flat_map(|entry| entry.config_keys(3, SenderListType::Block)),
+                )
+                .map(|key| (key.key, key.value))
+                .collect(),
+            ..Default::default()
+        };
+        let tenants = TenantSenderLists::parse_all(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        let lists = tenants.get(&3).unwrap();
+        assert_eq!(lists.allow.entries, &allow[..2]);
+        assert_eq!(lists.block.entries, block);
+
+        let ip = Some("192.168.1.20".parse().unwrap());
+        assert!(lists.allow.matches(["boss@example.org"], None, now));
+        assert!(lists.allow.matches(["sales@eu.partner.net"], None, now));
+        assert!(!lists.allow.matches(["other@example.org"], None, now));
+        assert!(!lists.allow.matches(["user@expired.net"], None, now));
+        assert!(!lists.allow.matches(["sales@partner.net"], None, now + 7200));
+        assert!(lists.block.matches(["user@spam.org"], None, now));
+        assert!(lists.block.matches(["user@example.org"], ip, now));
+        assert!(!lists.block.matches(["user@notspam.org"], None, now));
+
+        for invalid in ["", "user@", "@example.org", "example", "*.example.org"] {
+            let mut entry = SenderListEntry {
+                id: String::new(),
+                value: invalid.to_string(),
+                expires: None,
+                comment: None,
+            };
+            assert!(entry.normalize().is_err(), "{invalid}");
+        }
+    }
+}
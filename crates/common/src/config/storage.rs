@@ -8,9 +8,15 @@ use std::sync::Arc;
 
 use ahash::AHashMap;
 use directory::Directory;
-use store::{BlobStore, SearchStore, InMemoryStore, PubSubStore, PurgeSchedule, Store};
+use store::{BlobStore, InMemoryStore, PubSubStore, PurgeSchedule, SearchStore, Store};
+use utils::config::Config;
 
-use crate::manager::config::ConfigManager;
+use crate::{
+    config::telemetry::{AuditLog, Metering},
+    manager::config::ConfigManager,
+};
+
+pub const TENANT_BLOB_KEY: &str = "storage.tenant";
 
 #[derive(Default, Clone)]
 pub struct Storage {
@@ -23,9 +29,91 @@ pub struct Storage {
     pub directories: AHashMap<String, Arc<Directory>>,
     pub purge_schedules: Vec<PurgeSchedule>,
     pub config: ConfigManager,
+    pub audit: Option<AuditLog>,
+    pub metering: Option<Metering>,
+    /// Blob store tenant backups are written to.
+    pub backup: Option<BlobStore>,
 
     pub stores: AHashMap<String, Store>,
     pub blobs: AHashMap<String, BlobStore>,
     pub lookups: AHashMap<String, InMemoryStore>,
     pub ftss: AHashMap<String, SearchStore>,
 }
+
+/// Parses the blob stores assigned to tenants, which new blobs written
+/// by the tenant's accounts are stored in.
+pub fn parse_tenant_blob_stores(config: &mut Config) -> AHashMap<u32, String> {
+    let mut tenants = AHashMap::new();
+
+    for id in config.sub_keys(TENANT_BLOB_KEY, ".blob") {
+        let Ok(tenant_id) = id.parse::<u32>() else {
+            config.new_parse_error((TENANT_BLOB_KEY, id.as_str()), "Invalid tenant id");
+            continue;
+        };
+        if let Some(store_id) = config
+            .value((TENANT_BLOB_KEY, id.as_str(), "blob"))
+            .filter(|store_id| !store_id.is_empty())
+        {
+            tenants.insert(tenant_id, store_id.to_string());
+        }
+    }
+
+    tenants
+}
+
+/// Encryption settings of a tenant, the tenant's data key is stored wrapped
+/// by a key held outside of the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantEncryption {
+    /// Whether new blobs are encrypted, existing blobs remain readable
+    /// once encryption is disabled.
+    pub enabled: bool,
+    /// Reference to the key that unwraps the data key, either `env:<name>`
+    /// or `file:<path>`.
+    pub key: String,
+    /// RSA public key the data key is wrapped with, the referenced key is
+    /// then the matching private key.
+    pub public_key: Option<String>,
+    /// Base64 encoded wrapped data key.
+    pub data_key: String,
+}
+
+/// Parses the encryption settings of all tenants.
+pub fn parse_tenant_encryption(config: &mut Config) -> AHashMap<u32, Arc<TenantEncryption>> {
+    let mut tenants = AHashMap::new();
+
+    for id in config.sub_keys(TENANT_BLOB_KEY, ".encryption.data-key") {
+        let Ok(tenant_id) = id.parse::<u32>() else {
+            config.new_parse_error((TENANT_BLOB_KEY, id.as_str()), "Invalid tenant id");
+            continue;
+        };
+        let Some(key) = config
+            .value_require((TENANT_BLOB_KEY, id.as_str(), "encryption.key"))
+            .map(|key| key.to_string())
+        else {
+            continue;
+        };
+
+        tenants.insert(
+            tenant_id,
+            Arc::new(TenantEncryption {
+                enabled: config
+                    .property_or_default(
+                        (TENANT_BLOB_KEY, id.as_str(), "encryption.enable"),
+                        "true",
+                    )
+                    .unwrap_or(true),
+                key,
+                public_key: config
+                    .value((TENANT_BLOB_KEY, id.as_str(), "encryption.public-key"))
+                    .map(|key| key.to_string()),
+                data_key: config
+                    .value((TENANT_BLOB_KEY, id.as_str(), "encryption.data-key"))
+                    .unwrap_or_default()
+                    .to_string(),
+            }),
+        );
+    }
+
+    tenants
+}
@@ -39,6 +39,7 @@ pub enum TelemetrySubscriberType {
     Webhook(WebhookTracer),
     #[cfg(unix)]
     JournalTracer(crate::telemetry::tracers::journald::Subscriber),
+    AuditTracer(AuditTracer),
     // SPDX-SnippetBegin
     // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
     // SPDX-License-Identifier: LicenseRef-SEL
@@ -82,6 +83,7 @@ pub struct LogTracer {
 
 #[derive(Debug)]
 pub struct WebhookTracer {
+    pub id: String,
     pub url: String,
     pub key: String,
     pub timeout: Duration,
@@ -91,6 +93,25 @@ pub struct WebhookTracer {
     pub headers: HeaderMap,
 }
 
+#[derive(Debug)]
+pub struct AuditTracer {
+    pub store: store::Store,
+    pub queue_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    pub store: store::Store,
+    pub retention: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metering {
+    pub store: store::Store,
+    pub interval: Duration,
+    pub retention: Option<Duration>,
+}
+
 // SPDX-SnippetBegin
 // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
 // SPDX-License-Identifier: LicenseRef-SEL
@@ -127,6 +148,7 @@ pub struct Metrics {
     pub prometheus: Option<PrometheusMetrics>,
     pub otel: Option<Arc<OtelMetrics>>,
     pub log_path: Option<String>,
+    pub tenants: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -482,6 +504,7 @@ impl Tracers {
                 TelemetrySubscriberType::JournalTracer(_) => {
                     EventType::Telemetry(TelemetryEvent::JournalError).into()
                 }
+                TelemetrySubscriberType::AuditTracer(_) => None,
                 // SPDX-SnippetBegin
                 // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
                 // SPDX-License-Identifier: LicenseRef-SEL
@@ -554,6 +577,40 @@ impl Tracers {
         }
         // SPDX-SnippetEnd
 
+        // Parse audit log
+        if let Some(audit_log) = AuditLog::parse(config, stores) {
+            let mut tracer = TelemetrySubscriber {
+                id: "audit".to_string(),
+                interests: Default::default(),
+                lossy: false,
+                typ: TelemetrySubscriberType::AuditTracer(AuditTracer {
+                    store: audit_log.store,
+                    queue_size: config
+                        .property_or_default("audit.queue-size", "1024")
+                        .unwrap_or(1024),
+                }),
+            };
+
+            let events = config
+                .properties::<EventOrMany>("audit.events")
+                .into_iter()
+                .map(|(_, e)| e)
+                .collect::<Vec<_>>();
+            if events.is_empty() {
+                for event_type in AuditTracer::default_events() {
+                    tracer.interests.set(event_type);
+                    global_interests.set(event_type);
+                }
+            } else {
+                apply_events(events, true, |event_type| {
+                    tracer.interests.set(event_type);
+                    global_interests.set(event_type);
+                });
+            }
+
+            tracers.push(tracer);
+        }
+
         // Parse webhooks
         for id in config.sub_keys("webhook", ".url") {
             if let Some(webhook) = parse_webhook(config, &id, &mut global_interests) {
@@ -594,12 +651,79 @@ impl Tracers {
     }
 }
 
+impl AuditLog {
+    pub fn parse(config: &mut Config, stores: &Stores) -> Option<Self> {
+        if !config
+            .property_or_default("audit.enable", "false")
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let store_id = config
+            .value("audit.store")
+            .or_else(|| config.value("storage.data"))?
+            .to_string();
+        if let Some(store) = stores.stores.get(&store_id) {
+            AuditLog {
+                store: store.clone(),
+                retention: config
+                    .property_or_default::<Option<Duration>>("audit.retention", "90d")
+                    .unwrap_or(Some(Duration::from_secs(90 * 24 * 60 * 60))),
+            }
+            .into()
+        } else {
+            config.new_build_error("audit.store", format!("Store {store_id} not found"));
+            None
+        }
+    }
+}
+
+impl Metering {
+    pub fn parse(config: &mut Config, stores: &Stores) -> Option<Self> {
+        if !config
+            .property_or_default("metering.enable", "false")
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let store_id = config
+            .value("metering.store")
+            .or_else(|| config.value("storage.data"))?
+            .to_string();
+        if let Some(store) = stores.stores.get(&store_id) {
+            Metering {
+                store: store.clone(),
+                interval: config
+                    .property_or_default::<Duration>("metering.interval", "1h")
+                    .unwrap_or(Duration::from_secs(60 * 60))
+                    .max(Duration::from_secs(60)),
+                retention: config
+                    .property_or_default::<Option<Duration>>("metering.retention", "400d")
+                    .unwrap_or(Some(Duration::from_secs(400 * 24 * 60 * 60))),
+            }
+            .into()
+        } else {
+            config.new_build_error("metering.store", format!("Store {store_id} not found"));
+            None
+        }
+    }
+}
+
 impl Metrics {
     pub fn parse(config: &mut Config) -> Self {
         let mut metrics = Metrics {
             prometheus: None,
             otel: None,
             log_path: None,
+            // Metering samples are taken from the per-tenant counters
+            tenants: config
+                .property_or_default("metrics.tenant.enable", "false")
+                .unwrap_or(false)
+                || config
+                    .property_or_default("metering.enable", "false")
+                    .unwrap_or(false),
         };
 
         // Obtain log path
@@ -747,9 +871,6 @@ fn parse_webhook(
     id: &str,
     global_interests: &mut Interests,
 ) -> Option<TelemetrySubscriber> {
-    let mut headers = parse_http_headers(config, ("webhook", id));
-    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
-
     // Build tracer
     let mut tracer = TelemetrySubscriber {
         id: format!("w_{id}"),
@@ -757,26 +878,7 @@ fn parse_webhook(
         lossy: config
             .property_or_default(("webhook", id, "lossy"), "false")
             .unwrap_or(false),
-        typ: TelemetrySubscriberType::Webhook(WebhookTracer {
-            url: config.value_require(("webhook", id, "url"))?.to_string(),
-            timeout: config
-                .property_or_default(("webhook", id, "timeout"), "30s")
-                .unwrap_or_else(|| Duration::from_secs(30)),
-            tls_allow_invalid_certs: config
-                .property_or_default(("webhook", id, "allow-invalid-certs"), "false")
-                .unwrap_or_default(),
-            headers,
-            key: config
-                .value(("webhook", id, "signature-key"))
-                .unwrap_or_default()
-                .to_string(),
-            throttle: config
-                .property_or_default(("webhook", id, "throttle"), "1s")
-                .unwrap_or_else(|| Duration::from_secs(1)),
-            discard_after: config
-                .property_or_default(("webhook", id, "discard-after"), "5m")
-                .unwrap_or_else(|| Duration::from_secs(300)),
-        }),
+        typ: TelemetrySubscriberType::Webhook(WebhookTracer::parse(config, id)?),
     };
 
     // Parse webhook events
@@ -802,6 +904,35 @@ fn parse_webhook(
     }
 }
 
+impl WebhookTracer {
+    pub fn parse(config: &mut Config, id: &str) -> Option<Self> {
+        let mut headers = parse_http_headers(config, ("webhook", id));
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+
+        Some(WebhookTracer {
+            id: id.to_string(),
+            url: config.value_require(("webhook", id, "url"))?.to_string(),
+            timeout: config
+                .property_or_default(("webhook", id, "timeout"), "30s")
+                .unwrap_or_else(|| Duration::from_secs(30)),
+            tls_allow_invalid_certs: config
+                .property_or_default(("webhook", id, "allow-invalid-certs"), "false")
+                .unwrap_or_default(),
+            headers,
+            key: config
+                .value(("webhook", id, "signature-key"))
+                .unwrap_or_default()
+                .to_string(),
+            throttle: config
+                .property_or_default(("webhook", id, "throttle"), "1s")
+                .unwrap_or_else(|| Duration::from_secs(1)),
+            discard_after: config
+                .property_or_default(("webhook", id, "discard-after"), "5m")
+                .unwrap_or_else(|| Duration::from_secs(300)),
+        })
+    }
+}
+
 enum EventOrMany {
     Event(EventType),
     StartsWith(String),
@@ -147,16 +147,51 @@ impl Server {
         })
     }
 
+    /// Returns the DKIM signer for a message submitted by a user of a tenant,
+    /// with the tenant signing policy applied when one covers the signing domain.
+    pub fn get_tenant_dkim_signer(
+        &self,
+        name: &str,
+        tenant_id: Option<u32>,
+        session_id: u64,
+    ) -> Option<Arc<DkimSigner>> {
+        let Some(tenant) = tenant_id.and_then(|tenant_id| self.tenant_dkim_policy(tenant_id))
+        else {
+            return self.get_dkim_signer(name, session_id);
+        };
+        let Some(resolved) = self.resolve_signature(name) else {
+            return self.get_dkim_signer(name, session_id);
+        };
+        let Some(policy) = tenant.policy(resolved.signer.domain()) else {
+            return Some(resolved.signer);
+        };
+
+        tenant
+            .signer(name, &resolved.signer, || {
+                let mut config = resolved.config.as_ref().clone();
+                build_signature(&mut config, name).map(|(signer, _)| signer.with_policy(policy))
+            })
+            .or(Some(resolved.signer))
+    }
+
+    /// Returns the domain a signature signs messages for.
+    pub fn get_signature_domain(&self, name: &str) -> Option<String> {
+        self.resolve_signature(name)
+            .map(|resolved| resolved.signer.domain().to_string())
+    }
+
     fn resolve_signature(&self, name: &str) -> Option<ResolvedSignature> {
         let lazy_resolver_ = self.core.smtp.mail_auth.signatures.get(name)?;
         match lazy_resolver_.load().as_ref() {
             LazySignature::Resolved(resolved_signature) => Some(resolved_signature.clone()),
             LazySignature::Pending(config) => {
                 let mut config = config.clone();
+                let source = Arc::new(config.clone());
                 if let Some((signer, sealer)) = build_signature(&mut config, name) {
                     let resolved = ResolvedSignature {
                         signer: Arc::new(signer),
                         sealer: Arc::new(sealer),
+                        config: source,
                     };
                     lazy_resolver_.store(Arc::new(LazySignature::Resolved(resolved.clone())));
                     Some(resolved)
@@ -396,6 +431,7 @@ impl Server {
                 account_id,
                 quota: access_token.quota,
                 tenant: access_token.tenant,
+                domain_id: access_token.domain_id(account_id),
             }
         } else {
             let mut quotas = ResourceToken {
@@ -412,6 +448,13 @@ impl Server {
                 .add_context(|err| err.caused_by(trc::location!()).account_id(account_id))?
             {
                 quotas.quota = principal.quota().unwrap_or_default();
+                if let Some(email) = principal.primary_email() {
+                    quotas.domain_id = self
+                        .store()
+                        .get_domain_id(email)
+                        .await
+                        .caused_by(trc::location!())?;
+                }
 
                 // SPDX-SnippetBegin
                 // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
@@ -432,7 +475,7 @@ impl Server {
                             .add_context(|err| {
                                 err.caused_by(trc::location!()).account_id(tenant_id)
                             })?
-                            .and_then(|tenant| tenant.quota())
+                            .and_then(|tenant| self.tenant_limits(&tenant).quota)
                             .unwrap_or_default(),
                     }
                     .into();
@@ -692,6 +735,7 @@ impl Server {
 
     pub async fn commit_batch(&self, mut builder: BatchBuilder) -> trc::Result<AssignedIds> {
         let mut assigned_ids = AssignedIds::default();
+        let changed_quotas = builder.changed_quotas().collect::<Vec<_>>();
         let mut commit_points = builder.commit_points();
 
         for commit_point in commit_points.iter() {
@@ -728,6 +772,11 @@ impl Server {
                     }))
                     .await;
                 }
+                if changed_quotas.contains(&account_id)
+                    && let Err(err) = self.notify_quota_changes(account_id).await
+                {
+                    trc::error!(err.caused_by(trc::location!()));
+                }
             }
         }
 
@@ -903,7 +952,10 @@ impl Server {
     #[allow(clippy::blocks_in_conditions)]
     pub async fn put_jmap_blob(&self, account_id: u32, data: &[u8]) -> trc::Result<BlobId> {
         // First reserve the hash
-        let hash = BlobHash::generate(data);
+        let hash = self
+            .account_blob_hash(account_id, data)
+            .await
+            .caused_by(trc::location!())?;
         let mut batch = BatchBuilder::new();
         let until = now() + self.core.jmap.upload_tmp_ttl;
 
@@ -931,32 +983,10 @@ impl Server {
             .await
             .caused_by(trc::location!())?;
 
-        if !self
-            .core
-            .storage
-            .data
-            .blob_exists(&hash)
+        // Upload blob to store
+        self.put_account_blob(account_id, &hash, data)
             .await
-            .caused_by(trc::location!())?
-        {
-            // Upload blob to store
-            self.core
-                .storage
-                .blob
-                .put_blob(hash.as_ref(), data)
-                .await
-                .caused_by(trc::location!())?;
-
-            // Commit blob
-            let mut batch = BatchBuilder::new();
-            batch.set(BlobOp::Commit { hash: hash.clone() }, Vec::new());
-            self.core
-                .storage
-                .data
-                .write(batch.build_all())
-                .await
-                .caused_by(trc::location!())?;
-        }
+            .caused_by(trc::location!())?;
 
         Ok(BlobId {
             hash,
@@ -975,7 +1005,10 @@ impl Server {
         hold_for: u64,
     ) -> trc::Result<(BlobHash, BlobOp)> {
         // First reserve the hash
-        let hash = BlobHash::generate(data);
+        let hash = self
+            .account_blob_hash(account_id, data)
+            .await
+            .caused_by(trc::location!())?;
         let mut batch = BatchBuilder::new();
         let until = now() + hold_for;
 
@@ -994,32 +1027,10 @@ impl Server {
             .await
             .caused_by(trc::location!())?;
 
-        if !self
-            .core
-            .storage
-            .data
-            .blob_exists(&hash)
+        // Upload blob to store
+        self.put_account_blob(account_id, &hash, data)
             .await
-            .caused_by(trc::location!())?
-        {
-            // Upload blob to store
-            self.core
-                .storage
-                .blob
-                .put_blob(hash.as_ref(), data)
-                .await
-                .caused_by(trc::location!())?;
-
-            // Commit blob
-            let mut batch = BatchBuilder::new();
-            batch.set(BlobOp::Commit { hash: hash.clone() }, Vec::new());
-            self.core
-                .storage
-                .data
-                .write(batch.build_all())
-                .await
-                .caused_by(trc::location!())?;
-        }
+            .caused_by(trc::location!())?;
 
         Ok((
             hash.clone(),
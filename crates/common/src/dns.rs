@@ -6,9 +6,145 @@
 
 use std::net::IpAddr;
 
-use mail_auth::{Error, IpLookupStrategy};
+use ahash::AHashMap;
+use mail_auth::{
+    Error, IpLookupStrategy,
+    dmarc::{Dmarc, Policy},
+    mta_sts::TlsRpt,
+    spf::Spf,
+};
+use parking_lot::Mutex;
+use store::write::now;
 
-use crate::Server;
+use crate::{Server, manager::spf::SpfMonitor};
+
+/// Records checked for every local domain, BIMI is only checked for the
+/// domains that publish a logo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DnsCheckType {
+    Mx,
+    Spf,
+    Dmarc,
+    TlsRpt,
+    Bimi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DnsCheckStatus {
+    Ok,
+    /// The record does not exist.
+    Missing,
+    /// The record exists but could not be parsed or does not point to this server.
+    Invalid,
+    /// The lookup failed, the record may or may not exist.
+    Failed,
+    /// The record is missing or incomplete, but only optional features are affected.
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsCheck {
+    #[serde(rename = "type")]
+    pub typ: DnsCheckType,
+    pub name: String,
+    pub status: DnsCheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+    pub checked_at: u64,
+}
+
+/// Latest result of each DNS check, keyed by domain and record type.
+#[derive(Debug, Default)]
+pub struct DnsChecks {
+    checks: Mutex<AHashMap<(String, DnsCheckType), DnsCheck>>,
+}
+
+impl DnsCheckType {
+    pub const ALL: [DnsCheckType; 5] = [
+        DnsCheckType::Mx,
+        DnsCheckType::Spf,
+        DnsCheckType::Dmarc,
+        DnsCheckType::TlsRpt,
+        DnsCheckType::Bimi,
+    ];
+
+    pub fn record_name(&self, domain: &str) -> String {
+        match self {
+            DnsCheckType::Mx | DnsCheckType::Spf => format!("{domain}."),
+            DnsCheckType::Dmarc => format!("_dmarc.{domain}."),
+            DnsCheckType::TlsRpt => format!("_smtp._tls.{domain}."),
+            DnsCheckType::Bimi => format!("default._bimi.{domain}."),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DnsCheckType::Mx => "mx",
+            DnsCheckType::Spf => "spf",
+            DnsCheckType::Dmarc => "dmarc",
+            DnsCheckType::TlsRpt => "tlsRpt",
+            DnsCheckType::Bimi => "bimi",
+        }
+    }
+}
+
+impl DnsCheck {
+    /// Seconds elapsed since the check was run.
+    pub fn age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.checked_at)
+    }
+
+    /// Whether the record needs the attention of an administrator. Lookup
+    /// failures and warnings are not reported and TLS reporting is optional.
+    pub fn is_healthy(&self) -> bool {
+        match self.status {
+            DnsCheckStatus::Ok | DnsCheckStatus::Failed | DnsCheckStatus::Warning => true,
+            DnsCheckStatus::Missing => self.typ == DnsCheckType::TlsRpt,
+            DnsCheckStatus::Invalid => false,
+        }
+    }
+}
+
+impl DnsChecks {
+    /// Returns the cached result of a check unless it is older than `ttl` seconds.
+    pub fn get(&self, domain: &str, typ: DnsCheckType, ttl: u64) -> Option<DnsCheck> {
+        let now = now();
+        self.checks
+            .lock()
+            .get(&(domain.to_string(), typ))
+            .filter(|check| check.age(now) < ttl)
+            .cloned()
+    }
+
+    /// Returns every cached result of a domain regardless of its age.
+    pub fn get_all(&self, domain: &str) -> Vec<DnsCheck> {
+        let checks = self.checks.lock();
+        DnsCheckType::ALL
+            .into_iter()
+            .filter_map(|typ| checks.get(&(domain.to_string(), typ)).cloned())
+            .collect()
+    }
+
+    /// Stores the result of a check, returning the one it replaces.
+    pub fn insert(&self, domain: &str, check: DnsCheck) -> Option<DnsCheck> {
+        self.checks
+            .lock()
+            .insert((domain.to_string(), check.typ), check)
+    }
+
+    pub fn remove(&self, domain: &str, typ: DnsCheckType) {
+        self.checks.lock().remove(&(domain.to_string(), typ));
+    }
+
+    pub fn retain(&self, f: impl Fn(&str) -> bool) {
+        self.checks.lock().retain(|(domain, _), _| f(domain));
+    }
+}
 
 impl Server {
     pub async fn dns_exists_mx(&self, entry: &str) -> trc::Result<bool> {
@@ -95,4 +231,254 @@ impl Server {
             Err(err) => Err(err.into()),
         }
     }
+
+    /// Returns the DNS checks of a domain, running again those that are not
+    /// cached or that are older than the configured TTL.
+    pub async fn domain_dns_checks(&self, domain: &str, fresh: bool) -> Vec<DnsCheck> {
+        let mut checks = Vec::with_capacity(DnsCheckType::ALL.len());
+        for typ in DnsCheckType::ALL {
+            if typ == DnsCheckType::Bimi && self.domain_bimi(domain).is_none() {
+                continue;
+            }
+            let cached = if !fresh {
+                self.inner
+                    .data
+                    .dns_checks
+                    .get(domain, typ, self.core.jmap.dns_check_ttl)
+            } else {
+                None
+            };
+            checks.push(match cached {
+                Some(check) => check,
+                None => self.run_dns_check(domain, typ).await,
+            });
+        }
+        checks
+    }
+
+    /// Looks up a record and caches the result, reporting records that were
+    /// found before and are now missing. Lookups still honor the TTL of the
+    /// DNS records themselves.
+    pub async fn run_dns_check(&self, domain: &str, typ: DnsCheckType) -> DnsCheck {
+        let name = typ.record_name(domain);
+        let resolver = &self.core.smtp.resolvers.dns;
+        let mut remediation = None;
+        let result =
+            match typ {
+                DnsCheckType::Mx => resolver
+                    .mx_lookup(name.as_str(), Some(&self.inner.cache.dns_mx))
+                    .await
+                    .map(|mxs| {
+                        let server_name = self.core.network.server_name.as_str();
+                        if mxs.iter().flat_map(|mx| mx.exchanges.iter()).any(|host| {
+                            host.trim_end_matches('.').eq_ignore_ascii_case(server_name)
+                        }) {
+                            None
+                        } else {
+                            Some(format!("No MX record points to {server_name}"))
+                        }
+                    }),
+                DnsCheckType::Spf => match resolver
+                    .txt_lookup::<Spf>(name.as_str(), Some(&self.inner.cache.dns_txt))
+                    .await
+                {
+                    Ok(_) => Ok(self.check_spf_record(domain, &mut remediation).await),
+                    Err(err) => Err(err),
+                },
+                DnsCheckType::Dmarc => resolver
+                    .txt_lookup::<Dmarc>(name.as_str(), Some(&self.inner.cache.dns_txt))
+                    .await
+                    .map(|_| None),
+                DnsCheckType::TlsRpt => resolver
+                    .txt_lookup::<TlsRpt>(name.as_str(), Some(&self.inner.cache.dns_txt))
+                    .await
+                    .map(|_| None),
+                DnsCheckType::Bimi => self.check_bimi_record(domain, &name).await,
+            };
+        let (status, reason) = match result {
+            Ok(None) => (DnsCheckStatus::Ok, None),
+            Ok(Some(reason)) => (DnsCheckStatus::Invalid, Some(reason)),
+            Err(Error::DnsRecordNotFound(_)) => (DnsCheckStatus::Missing, None),
+            Err(Error::DnsError(err)) => (DnsCheckStatus::Failed, Some(err)),
+            Err(err) => (DnsCheckStatus::Invalid, Some(err.to_string())),
+        };
+
+        // Logos are optional, issues with them are only reported as warnings
+        let (status, reason) = match (typ, status) {
+            (DnsCheckType::Bimi, DnsCheckStatus::Missing) => (
+                DnsCheckStatus::Warning,
+                Some("No BIMI record is published".to_string()),
+            ),
+            (DnsCheckType::Bimi, DnsCheckStatus::Invalid) => (DnsCheckStatus::Warning, reason),
+            (DnsCheckType::Spf, DnsCheckStatus::Missing) => {
+                remediation = Some(format!(
+                    "Publish a TXT record at {domain} with \"v=spf1 mx ra=postmaster -all\""
+                ));
+                (status, reason)
+            }
+            _ => (status, reason),
+        };
+        let check = DnsCheck {
+            typ,
+            name,
+            status,
+            reason,
+            remediation,
+            checked_at: now(),
+        };
+
+        if self
+            .inner
+            .data
+            .dns_checks
+            .insert(domain, check.clone())
+            .is_some_and(|previous| {
+                previous.status == DnsCheckStatus::Ok && check.status == DnsCheckStatus::Missing
+            })
+        {
+            trc::event!(
+                Directory(trc::DirectoryEvent::DomainDnsMissing),
+                Domain = domain.to_string(),
+                Type = typ.as_str(),
+                Hostname = check.name.clone(),
+            );
+        }
+
+        check
+    }
+
+    /// Reports SPF records that receivers reject, either because they are
+    /// invalid or need too many lookups, and flattened records whose
+    /// includes changed since they were published.
+    async fn check_spf_record(
+        &self,
+        domain: &str,
+        remediation: &mut Option<String>,
+    ) -> Option<String> {
+        if let Ok(Some(SpfMonitor {
+            changed_at: Some(_),
+            ..
+        })) = self.spf_monitor(domain).await
+        {
+            *remediation =
+                Some("Flatten the SPF record again and publish the new record".to_string());
+            return Some(
+                "The networks authorized by the includes of the flattened SPF record changed"
+                    .to_string(),
+            );
+        }
+
+        // Lookup failures are already reported by the record check
+        let analysis = self.analyze_spf(domain).await.ok()?;
+        if analysis.record.is_some() && !analysis.errors.is_empty() {
+            *remediation = analysis.remediation;
+            Some(analysis.errors.join("; "))
+        } else {
+            None
+        }
+    }
+
+    /// Verifies that the published BIMI record points to the files served by
+    /// this server. Mailbox providers only display the logos of domains with
+    /// a DMARC policy at enforcement, so this is checked first.
+    async fn check_bimi_record(&self, domain: &str, name: &str) -> Result<Option<String>, Error> {
+        let Some(bimi) = self.domain_bimi(domain) else {
+            return Ok(Some("BIMI is not configured for this domain".to_string()));
+        };
+        let resolver = &self.core.smtp.resolvers.dns;
+        let dmarc_name = DnsCheckType::Dmarc.record_name(domain);
+
+        match resolver
+            .txt_lookup::<Dmarc>(dmarc_name.as_str(), Some(&self.inner.cache.dns_txt))
+            .await
+        {
+            Ok(dmarc) => {
+                if !matches!(dmarc.p, Policy::Quarantine | Policy::Reject) || dmarc.pct != 100 {
+                    return Ok(Some(
+                        "DMARC policy must be quarantine or reject at 100% for BIMI".to_string(),
+                    ));
+                }
+            }
+            Err(Error::DnsRecordNotFound(_)) => {
+                return Ok(Some(
+                    "No DMARC record is published, BIMI requires one".to_string(),
+                ));
+            }
+            Err(err) => return Err(err),
+        }
+
+        let record = resolver.txt_raw_lookup(name).await?;
+        let hostname = self.core.network.server_name.as_str();
+        if bimi.matches_record(
+            std::str::from_utf8(&record).unwrap_or_default(),
+            hostname,
+            domain,
+        ) {
+            Ok(None)
+        } else {
+            Ok(Some(format!(
+                "BIMI record does not match {:?}",
+                bimi.record(hostname, domain)
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DnsCheck, DnsCheckStatus, DnsCheckType, DnsChecks};
+    use store::write::now;
+
+    #[test]
+    fn dns_check_cache() {
+        let checks = DnsChecks::default();
+        let check = |typ, status, checked_at| DnsCheck {
+            typ,
+            name: typ.record_name("example.org"),
+            status,
+            reason: None,
+            remediation: None,
+            checked_at,
+        };
+        let now = now();
+
+        assert_eq!(checks.get("example.org", DnsCheckType::Mx, 60), None);
+        assert_eq!(
+            checks.insert(
+                "example.org",
+                check(DnsCheckType::Mx, DnsCheckStatus::Ok, now - 30)
+            ),
+            None
+        );
+        checks.insert(
+            "example.org",
+            check(DnsCheckType::Spf, DnsCheckStatus::Missing, now - 120),
+        );
+
+        // Entries older than the TTL are not returned, but are kept for reporting
+        assert_eq!(
+            checks
+                .get("example.org", DnsCheckType::Mx, 60)
+                .map(|check| check.age(now)),
+            Some(30)
+        );
+        assert_eq!(checks.get("example.org", DnsCheckType::Spf, 60), None);
+        assert_eq!(checks.get("example.net", DnsCheckType::Mx, 60), None);
+        assert_eq!(checks.get_all("example.org").len(), 2);
+
+        // Replacing an entry returns the previous result
+        assert_eq!(
+            checks
+                .insert(
+                    "example.org",
+                    check(DnsCheckType::Mx, DnsCheckStatus::Missing, now)
+                )
+                .map(|check| check.status),
+            Some(DnsCheckStatus::Ok)
+        );
+        assert!(!checks.get_all("example.org")[0].is_healthy());
+
+        checks.retain(|domain| domain != "example.org");
+        assert!(checks.get_all("example.org").is_empty());
+    }
 }
@@ -4,10 +4,13 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use crate::config::smtp::{
-    queue::QueueName,
-    report::AggregateFrequency,
-    resolver::{Policy, Tlsa},
+use crate::{
+    auth::notice::SecurityNotice,
+    config::smtp::{
+        queue::QueueName,
+        report::AggregateFrequency,
+        resolver::{Policy, Tlsa},
+    },
 };
 use ahash::RandomState;
 use mail_auth::{
@@ -32,7 +35,24 @@ pub enum HousekeeperEvent {
         provider_id: String,
         renew_at: Instant,
     },
+    DomainCertificates {
+        domain: String,
+        due: Instant,
+    },
     Purge(PurgeType),
+    AccountLockout {
+        account_id: u32,
+        locked_until: Option<u64>,
+    },
+    RecoveryCodeUsed {
+        account_id: u32,
+        remaining: usize,
+    },
+    SecurityNotice {
+        notice: SecurityNotice,
+        account_id: u32,
+        details: Option<String>,
+    },
     ReloadSettings,
     Exit,
 }
@@ -51,6 +71,8 @@ pub enum PurgeType {
         account_id: Option<u32>,
         use_roles: bool,
     },
+    Retention,
+    PrincipalCounts,
 }
 
 #[derive(Debug)]
@@ -102,11 +124,31 @@ pub struct CalendarAlert {
 pub enum BroadcastEvent {
     PushNotification(PushNotification),
     InvalidateAccessTokens(Vec<u32>),
+    InvalidateTenantAccessTokens(Option<u32>),
     InvalidateGroupwareCache(Vec<u32>),
     ReloadPushServers(u32),
     ReloadSettings,
     ReloadBlockedIps,
     ReloadSpamFilter,
+    ReloadTenantSpamSettings,
+    ReloadTenantSieveScripts,
+    ReloadTenantRouting,
+    ReloadTenantBlobStores,
+    ReloadTenantDkimPolicies,
+    ReloadTenantArcSealers,
+    ReloadTenantSenderLists,
+    ReloadDomainRoutes,
+    ReloadForwardingRules,
+    ReloadBookableResources,
+    ReloadTenantOverrides,
+    ReloadTenantMaintenance,
+    ReloadTenantActivation,
+    ReloadDisclaimers,
+    ReloadLoginPages,
+    ReloadTenantHostnames,
+    ReloadPasswordPolicies,
+    ReloadTenantJournaling,
+    ReloadDomainBimi,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,160 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use parking_lot::Mutex;
+
+const MAX_JOBS: usize = 32;
+
+/// Background jobs started on this node. Finished jobs are kept so that
+/// their outcome can be retrieved, up to `MAX_JOBS` per registry.
+#[derive(Debug)]
+pub struct JobRegistry<T> {
+    jobs: Mutex<AHashMap<u64, Arc<T>>>,
+}
+
+pub trait Job {
+    fn id(&self) -> u64;
+
+    fn is_running(&self) -> bool;
+}
+
+impl<T> Default for JobRegistry<T> {
+    fn default() -> Self {
+        JobRegistry {
+            jobs: Mutex::new(AHashMap::new()),
+        }
+    }
+}
+
+impl<T: Job> JobRegistry<T> {
+    /// Registers a job, unless it conflicts with a job that is still running.
+    pub fn register(&self, job: T, conflicts: impl Fn(&T) -> bool) -> Option<Arc<T>> {
+        self.try_register(job, |running| {
+            if running.iter().any(|&job| conflicts(job)) {
+                Err(())
+            } else {
+                Ok(())
+            }
+        })
+        .ok()
+    }
+
+    /// Registers a job, unless `check` rejects it given the jobs that are
+    /// still running.
+    pub fn try_register<E>(
+        &self,
+        job: T,
+        check: impl FnOnce(&[&T]) -> Result<(), E>,
+    ) -> Result<Arc<T>, E> {
+        let mut jobs = self.jobs.lock();
+        check(
+            &jobs
+                .values()
+                .map(AsRef::as_ref)
+                .filter(|job| job.is_running())
+                .collect::<Vec<_>>(),
+        )?;
+
+        // Evict the oldest finished jobs
+        while jobs.len() >= MAX_JOBS {
+            let Some(oldest) = jobs
+                .values()
+                .filter(|job| !job.is_running())
+                .min_by_key(|job| job.id())
+                .map(|job| job.id())
+            else {
+                break;
+            };
+            jobs.remove(&oldest);
+        }
+
+        let job = Arc::new(job);
+        jobs.insert(job.id(), job.clone());
+        Ok(job)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<T>> {
+        self.jobs.lock().get(&id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::{Job, JobRegistry, MAX_JOBS};
+
+    struct TestJob {
+        id: u64,
+        owner_id: u32,
+        running: AtomicBool,
+    }
+
+    impl TestJob {
+        fn new(id: u64, owner_id: u32) -> Self {
+            TestJob {
+                id,
+                owner_id,
+                running: AtomicBool::new(true),
+            }
+        }
+    }
+
+    impl Job for TestJob {
+        fn id(&self) -> u64 {
+            self.id
+        }
+
+        fn is_running(&self) -> bool {
+            self.running.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn job_registry() {
+        let registry = JobRegistry::<TestJob>::default();
+
+        // Only one running job per owner
+        let job = registry
+            .register(TestJob::new(1, 10), |job| job.owner_id == 10)
+            .unwrap();
+        assert!(
+            registry
+                .register(TestJob::new(2, 10), |job| job.owner_id == 10)
+                .is_none()
+        );
+        job.running.store(false, Ordering::Relaxed);
+        assert!(
+            registry
+                .register(TestJob::new(3, 10), |job| job.owner_id == 10)
+                .is_some()
+        );
+
+        // The oldest finished jobs are evicted, running jobs are kept
+        for id in 4..=MAX_JOBS as u64 + 2 {
+            registry
+                .register(TestJob::new(id, id as u32), |_| false)
+                .unwrap()
+                .running
+                .store(false, Ordering::Relaxed);
+        }
+        assert!(registry.get(1).is_none());
+        assert!(registry.get(3).is_some());
+        assert!(registry.get(4).is_some());
+
+        // Rejected jobs are not registered
+        assert_eq!(
+            registry
+                .try_register(TestJob::new(100, 100), |running| Err(running.len()))
+                .err(),
+            Some(1)
+        );
+        assert!(registry.get(100).is_none());
+    }
+}
@@ -8,24 +8,42 @@
 
 use ahash::{AHashMap, AHashSet};
 use arc_swap::ArcSwap;
-use auth::{AccessToken, oauth::config::OAuthConfig, roles::RolePermissions};
+use auth::{
+    AccessToken, activity::ActivityTimes, changes::DirectoryChanges, import::ImportJobs,
+    oauth::config::OAuthConfig, roles::RolePermissions,
+};
 use calcard::common::timezone::Tz;
 use config::{
-    groupware::GroupwareConfig,
+    activation::TenantActivation,
+    groupware::{BookableResource, GroupwareConfig},
+    hostname::TenantHostname,
     imap::ImapConfig,
     jmap::settings::JmapConfig,
+    login_page::LoginPage,
+    maintenance::TenantMaintenance,
     network::Network,
-    scripts::Scripting,
+    overrides::{SessionLimits, TenantOverrides},
+    password_policy::TenantPasswordPolicy,
+    scripts::{ForwardingRule, Scripting, TenantSieveScript},
     smtp::{
         SmtpConfig,
+        auth::TenantDkimSigning,
+        bimi::DomainBimi,
+        disclaimer::{DomainDisclaimer, TenantDisclaimer},
+        journal::TenantJournaling,
+        queue::{DomainRoutes, TenantRoutes},
         resolver::{Policy, Tlsa},
     },
-    spamfilter::{IpResolver, SpamFilterConfig},
-    storage::Storage,
+    spamfilter::{IpResolver, SpamFilterConfig, TenantSenderLists, TenantSpamSettings},
+    storage::{Storage, TenantEncryption},
     telemetry::Metrics,
 };
+use dns::DnsChecks;
 use ipc::{BroadcastEvent, HousekeeperEvent, PushEvent, QueueEvent, ReportingEvent};
-use listener::{asn::AsnGeoLookupData, blocked::Security, tls::AcmeProviders};
+use listener::{
+    acme::domain::DomainCertificateStates, asn::AsnGeoLookupData, blocked::Security,
+    tls::AcmeProviders,
+};
 use mail_auth::{MX, Txt};
 use manager::webadmin::{Resource, WebAdminManager};
 use parking_lot::{Mutex, RwLock};
@@ -36,7 +54,19 @@ use std::{
     sync::{Arc, atomic::AtomicBool},
     time::{Duration, Instant},
 };
+use storage::{
+    backup::TenantBackupJobs,
+    blob_migration::BlobMigrations,
+    deletion::DeletionReports,
+    encryption::DataKeys,
+    erasure::ErasureJobs,
+    export::AccountExportJobs,
+    import::{ImapImportJobs, MessageImportJobs},
+    quota::QuotaSteps,
+    reindex::ReindexJobs,
+};
 use store::rand::{Rng, distr::Alphanumeric};
+use telemetry::metrics::{management::SlowRequests, tenant::TenantMetrics};
 use tinyvec::TinyVec;
 use tokio::sync::{Notify, Semaphore, mpsc};
 use tokio_rustls::TlsConnector;
@@ -54,6 +84,7 @@ pub mod dns;
 pub mod expr;
 pub mod i18n;
 pub mod ipc;
+pub mod jobs;
 pub mod listener;
 pub mod manager;
 pub mod scripts;
@@ -111,7 +142,12 @@ pub const KV_RATE_LIMIT_CONTACT: u8 = 7;
 pub const KV_RATE_LIMIT_HTTP_AUTHENTICATED: u8 = 8;
 pub const KV_RATE_LIMIT_HTTP_ANONYMOUS: u8 = 9;
 pub const KV_RATE_LIMIT_IMAP: u8 = 10;
+pub const KV_RATE_LIMIT_WEBHOOK_TEST: u8 = 11;
+pub const KV_ORGANIZATION_INVITATION: u8 = 12;
 pub const KV_GREYLIST: u8 = 16;
+pub const KV_GREYLIST_TENANT: u8 = 17;
+pub const KV_WEBAUTHN_CHALLENGE: u8 = 18;
+pub const KV_OAUTH_REVOKED: u8 = 19;
 pub const KV_LOCK_PURGE_ACCOUNT: u8 = 20;
 pub const KV_LOCK_QUEUE_MESSAGE: u8 = 21;
 pub const KV_LOCK_QUEUE_REPORT: u8 = 22;
@@ -119,6 +155,12 @@ pub const KV_LOCK_TASK: u8 = 23;
 pub const KV_LOCK_HOUSEKEEPER: u8 = 24;
 pub const KV_LOCK_DAV: u8 = 25;
 pub const KV_SIEVE_ID: u8 = 26;
+pub const KV_LOCK_PROVISION: u8 = 37;
+pub const KV_RATE_LIMIT_SENDER: u8 = 38;
+pub const KV_JOURNAL_STATUS: u8 = 39;
+pub const KV_WEBAUTHN_CHALLENGE_USED: u8 = 40;
+pub const KV_LOCK_INVITATION: u8 = 41;
+pub use directory::backend::internal::app_password::KV_APP_PASSWORD_USED;
 
 #[derive(Clone)]
 pub struct Server {
@@ -135,6 +177,28 @@ pub struct Inner {
 
 pub struct Data {
     pub spam_classifier: ArcSwap<SpamClassifier>,
+    pub tenant_spam_settings: ArcSwap<AHashMap<u32, Arc<TenantSpamSettings>>>,
+    pub tenant_sender_lists: ArcSwap<AHashMap<u32, Arc<TenantSenderLists>>>,
+    pub tenant_sieve_scripts: ArcSwap<AHashMap<u32, Vec<Arc<TenantSieveScript>>>>,
+    pub forwarding_rules: ArcSwap<AHashMap<u32, Arc<ForwardingRule>>>,
+    pub bookable_resources: ArcSwap<AHashMap<u32, Arc<BookableResource>>>,
+    pub tenant_routing: ArcSwap<AHashMap<u32, Arc<TenantRoutes>>>,
+    pub domain_routes: ArcSwap<AHashMap<String, Arc<DomainRoutes>>>,
+    pub tenant_dkim_policies: ArcSwap<AHashMap<u32, Arc<TenantDkimSigning>>>,
+    pub tenant_arc_sealers: ArcSwap<AHashMap<u32, String>>,
+    pub tenant_blob_stores: ArcSwap<AHashMap<u32, String>>,
+    pub tenant_encryption: ArcSwap<AHashMap<u32, Arc<TenantEncryption>>>,
+    pub tenant_overrides: ArcSwap<AHashMap<u32, Arc<TenantOverrides>>>,
+    pub tenant_maintenance: ArcSwap<AHashMap<u32, Arc<TenantMaintenance>>>,
+    pub tenant_activation: ArcSwap<AHashMap<u32, Arc<TenantActivation>>>,
+    pub tenant_disclaimers: ArcSwap<AHashMap<u32, Arc<TenantDisclaimer>>>,
+    pub domain_disclaimers: ArcSwap<AHashMap<String, Arc<DomainDisclaimer>>>,
+    pub tenant_login_pages: ArcSwap<AHashMap<u32, Arc<LoginPage>>>,
+    pub domain_login_pages: ArcSwap<AHashMap<String, Arc<LoginPage>>>,
+    pub tenant_hostnames: ArcSwap<AHashMap<String, Arc<TenantHostname>>>,
+    pub tenant_password_policies: ArcSwap<AHashMap<u32, Arc<TenantPasswordPolicy>>>,
+    pub tenant_journaling: ArcSwap<AHashMap<u32, Arc<TenantJournaling>>>,
+    pub domain_bimi: ArcSwap<AHashMap<String, Arc<DomainBimi>>>,
 
     pub tls_certificates: ArcSwap<AHashMap<String, Arc<CertifiedKey>>>,
     pub tls_self_signed_cert: Option<Arc<CertifiedKey>>,
@@ -152,6 +216,23 @@ pub struct Data {
     pub logos: Mutex<AHashMap<String, Option<Resource<Vec<u8>>>>>,
 
     pub smtp_connectors: TlsConnectors,
+    pub tenant_metrics: TenantMetrics,
+    pub slow_requests: SlowRequests,
+    pub directory_changes: DirectoryChanges,
+    pub import_jobs: ImportJobs,
+    pub message_import_jobs: MessageImportJobs,
+    pub imap_import_jobs: ImapImportJobs,
+    pub reindex_jobs: ReindexJobs,
+    pub blob_migrations: BlobMigrations,
+    pub backup_jobs: TenantBackupJobs,
+    pub erasure_jobs: ErasureJobs,
+    pub deletion_reports: DeletionReports,
+    pub export_jobs: AccountExportJobs,
+    pub data_keys: DataKeys,
+    pub quota_steps: QuotaSteps,
+    pub activity_times: ActivityTimes,
+    pub domain_certificates: DomainCertificateStates,
+    pub dns_checks: DnsChecks,
 }
 
 pub struct Caches {
@@ -166,6 +247,7 @@ pub struct Caches {
     pub scheduling: Cache<u32, CacheSwap<DavResources>>,
 
     pub dns_txt: CacheWithTtl<String, Txt>,
+    pub dns_txt_records: CacheWithTtl<String, Arc<Vec<String>>>,
     pub dns_mx: CacheWithTtl<String, Arc<Vec<MX>>>,
     pub dns_ptr: CacheWithTtl<IpAddr, Arc<Vec<String>>>,
     pub dns_ipv4: CacheWithTtl<String, Arc<Vec<Ipv4Addr>>>,
@@ -238,6 +320,21 @@ pub struct HttpAuthCache {
     pub account_id: u32,
     pub revision: u64,
     pub expires: Instant,
+    pub allow_api_access: bool,
+    pub session: HttpSession,
+}
+
+/// Session started by the first request using a set of credentials, times
+/// are in seconds since the epoch.
+#[derive(Debug, Clone)]
+pub struct HttpSession {
+    pub created_at: u64,
+    pub last_used: u64,
+    pub limits: SessionLimits,
+    /// Bearer tokens of ended sessions are rejected until they expire.
+    pub ended: bool,
+    /// Started by a master user logged in as the account.
+    pub impersonated: bool,
 }
 
 pub struct Ipc {
@@ -486,6 +583,7 @@ impl Default for Caches {
             scheduling: Cache::new(1024, 10 * 1024 * 1024),
             dns_rbl: CacheWithTtl::new(1024, 10 * 1024 * 1024),
             dns_txt: CacheWithTtl::new(1024, 10 * 1024 * 1024),
+            dns_txt_records: CacheWithTtl::new(1024, 10 * 1024 * 1024),
             dns_mx: CacheWithTtl::new(1024, 10 * 1024 * 1024),
             dns_ptr: CacheWithTtl::new(1024, 10 * 1024 * 1024),
             dns_ipv4: CacheWithTtl::new(1024, 10 * 1024 * 1024),
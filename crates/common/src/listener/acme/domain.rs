@@ -0,0 +1,418 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use arc_swap::ArcSwap;
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use parking_lot::Mutex;
+use store::write::now;
+use trc::AddContext;
+
+use crate::{
+    Server,
+    ipc::{BroadcastEvent, HousekeeperEvent},
+};
+
+use super::{AcmeProvider, order::parse_cert};
+
+const RETRY_MIN: u64 = 5 * 60;
+const RETRY_MAX: u64 = 24 * 60 * 60;
+
+/// Certificates issued for hostnames derived from the directory domains,
+/// such as `mail.<domain>` or `mta-sts.<domain>`, and for the verified
+/// hostnames mapped to tenants.
+#[derive(Clone)]
+pub struct DomainCertificates {
+    pub provider: AcmeProvider,
+    pub hostnames: Vec<String>,
+}
+
+/// Issuance status of every domain hostname known to this node.
+#[derive(Debug, Default)]
+pub struct DomainCertificateStates {
+    hostnames: Mutex<AHashMap<String, HostnameCertificate>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostnameCertificate {
+    pub hostname: String,
+    pub status: CertificateStatus,
+    pub expires: Option<u64>,
+    pub attempts: u32,
+    pub next_attempt: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CertificateStatus {
+    Pending,
+    WaitingForDns,
+    Issued,
+    Failed,
+}
+
+impl DomainCertificates {
+    pub fn hostnames(&self, domain: &str) -> Vec<String> {
+        self.hostnames
+            .iter()
+            .map(|template| template.replace("{domain}", domain))
+            .collect()
+    }
+
+    fn provider(&self, hostname: &str) -> AcmeProvider {
+        AcmeProvider {
+            id: format!("{}.{hostname}", self.provider.id),
+            domains: vec![hostname.to_string()],
+            account_key: ArcSwap::new(self.provider.account_key.load_full()),
+            default: false,
+            ..self.provider.clone()
+        }
+    }
+}
+
+impl DomainCertificateStates {
+    pub fn get(&self, hostname: &str) -> HostnameCertificate {
+        self.hostnames
+            .lock()
+            .get(hostname)
+            .cloned()
+            .unwrap_or_else(|| HostnameCertificate::new(hostname))
+    }
+
+    fn update(&self, hostname: &str, f: impl FnOnce(&mut HostnameCertificate)) -> u64 {
+        let mut hostnames = self.hostnames.lock();
+        let state = hostnames
+            .entry(hostname.to_string())
+            .or_insert_with(|| HostnameCertificate::new(hostname));
+        f(state);
+        state.next_attempt
+    }
+
+    pub(crate) fn remove(&self, hostname: &str) {
+        self.hostnames.lock().remove(hostname);
+    }
+}
+
+impl HostnameCertificate {
+    fn new(hostname: &str) -> Self {
+        HostnameCertificate {
+            hostname: hostname.to_string(),
+            status: CertificateStatus::Pending,
+            expires: None,
+            attempts: 0,
+            next_attempt: now(),
+            last_error: None,
+        }
+    }
+
+    fn failed(&mut self, status: CertificateStatus, error: String) {
+        self.status = status;
+        self.attempts += 1;
+        self.next_attempt = now() + retry_delay(self.attempts);
+        self.last_error = Some(error);
+    }
+
+    /// Whether the hostname needs the attention of an administrator.
+    pub fn is_healthy(&self) -> bool {
+        match self.status {
+            CertificateStatus::Pending => true,
+            CertificateStatus::Issued => self.expires.is_some_and(|expires| expires > now()),
+            CertificateStatus::WaitingForDns | CertificateStatus::Failed => false,
+        }
+    }
+}
+
+impl Server {
+    /// Loads the certificates already issued for the hostnames of a domain,
+    /// returning when the domain is next due for issuance or renewal.
+    pub async fn init_domain_certificates(&self, domain: &str) -> trc::Result<Option<Duration>> {
+        let Some(config) = &self.core.acme.domains else {
+            return Ok(None);
+        };
+
+        if config.provider.account_key.load().is_empty() {
+            self.init_acme_account(&config.provider).await?;
+        }
+
+        let mut next_attempt = u64::MAX;
+        for hostname in self.certificate_hostnames(config, domain) {
+            let provider = config.provider(&hostname);
+            let due = if let Some(pem) = self.load_cert(&provider).await? {
+                let renew_at = self.process_cert(&provider, pem.clone(), true).await?;
+                let expires = parse_cert(&pem)?.1[1].timestamp() as u64;
+                self.inner
+                    .data
+                    .domain_certificates
+                    .update(&hostname, |state| {
+                        // Pending retries and forced renewals are kept until they run
+                        if state.expires != Some(expires) {
+                            state.status = CertificateStatus::Issued;
+                            state.expires = Some(expires);
+                            state.next_attempt = now() + renew_at.as_secs();
+                        }
+                    })
+            } else {
+                self.inner
+                    .data
+                    .domain_certificates
+                    .update(&hostname, |_| {})
+            };
+            next_attempt = next_attempt.min(due);
+        }
+
+        Ok((next_attempt != u64::MAX)
+            .then(|| Duration::from_secs(next_attempt.saturating_sub(now()))))
+    }
+
+    /// Issues or renews the certificates of the domain hostnames that are due,
+    /// returning when the domain is next due. Hostnames that do not resolve yet
+    /// or fail to be issued are retried with an exponential backoff.
+    pub async fn renew_domain_certificates(&self, domain: &str) -> trc::Result<Option<Duration>> {
+        let Some(config) = &self.core.acme.domains else {
+            return Ok(None);
+        };
+        let states = &self.inner.data.domain_certificates;
+        let hostnames = self.certificate_hostnames(config, domain);
+
+        // Stop tracking domains that were removed from the directory
+        if self.verified_tenant_hostname(domain).is_none()
+            && self
+                .store()
+                .get_principal_info(domain)
+                .await
+                .caused_by(trc::location!())?
+                .is_none_or(|principal| principal.typ != Type::Domain)
+        {
+            for hostname in hostnames {
+                states.remove(&hostname);
+            }
+            return Ok(None);
+        }
+
+        let mut next_attempt = u64::MAX;
+        let mut issued = false;
+        for hostname in hostnames {
+            let state = states.get(&hostname);
+            if state.next_attempt > now() {
+                next_attempt = next_attempt.min(state.next_attempt);
+                continue;
+            }
+
+            let due = match self.dns_exists_ip(&hostname).await {
+                Ok(true) => {
+                    let provider = config.provider(&hostname);
+                    trc::event!(
+                        Acme(trc::AcmeEvent::OrderStart),
+                        Hostname = hostname.clone(),
+                        Domain = domain.to_string(),
+                    );
+
+                    match self.renew(&provider).await {
+                        Ok(renew_at) => {
+                            let expires = now() + renew_at.as_secs() + provider.renew_before();
+                            trc::event!(
+                                Acme(trc::AcmeEvent::OrderCompleted),
+                                Domain = hostname.clone(),
+                                Expires = trc::Value::Timestamp(expires),
+                            );
+
+                            issued = true;
+                            states.update(&hostname, |state| {
+                                state.status = CertificateStatus::Issued;
+                                state.expires = Some(expires);
+                                state.attempts = 0;
+                                state.next_attempt = now() + renew_at.as_secs();
+                                state.last_error = None;
+                            })
+                        }
+                        Err(err) => {
+                            let details = error_details(&err);
+                            trc::error!(
+                                err.details("Failed to issue domain certificate.")
+                                    .ctx_unique(trc::Key::Domain, domain.to_string())
+                            );
+
+                            states.update(&hostname, |state| {
+                                state.failed(CertificateStatus::Failed, details)
+                            })
+                        }
+                    }
+                }
+                Ok(false) => states.update(&hostname, |state| {
+                    state.failed(
+                        CertificateStatus::WaitingForDns,
+                        format!("Hostname {hostname} does not resolve to an address"),
+                    )
+                }),
+                Err(err) => {
+                    let details = error_details(&err);
+                    states.update(&hostname, |state| {
+                        state.failed(CertificateStatus::WaitingForDns, details)
+                    })
+                }
+            };
+            next_attempt = next_attempt.min(due);
+        }
+
+        // Other nodes load the new certificates on reload
+        if issued {
+            self.cluster_broadcast(BroadcastEvent::ReloadSettings).await;
+        }
+
+        Ok((next_attempt != u64::MAX)
+            .then(|| Duration::from_secs(next_attempt.saturating_sub(now()))))
+    }
+
+    /// Loads the certificates of all the domains in the directory and of the
+    /// verified tenant hostnames, returning when each one is next due.
+    pub async fn init_all_domain_certificates(&self) -> trc::Result<Vec<(String, Duration)>> {
+        if self.core.acme.domains.is_none() {
+            return Ok(vec![]);
+        }
+
+        let hostnames = self
+            .inner
+            .data
+            .tenant_hostnames
+            .load()
+            .values()
+            .filter(|hostname| hostname.is_verified())
+            .map(|hostname| hostname.hostname.clone())
+            .collect::<Vec<_>>();
+        let mut domains = Vec::new();
+        for domain in self
+            .store()
+            .list_principals(None, None, &[Type::Domain], false, 0, 0)
+            .await
+            .caused_by(trc::location!())?
+            .items
+            .into_iter()
+            .map(|principal| principal.name().to_string())
+            .chain(hostnames)
+        {
+            match self.init_domain_certificates(&domain).await {
+                Ok(Some(due)) => domains.push((domain, due)),
+                Ok(None) => (),
+                Err(err) => {
+                    trc::error!(
+                        err.details("Failed to load domain certificates.")
+                            .ctx_unique(trc::Key::Domain, domain)
+                    );
+                }
+            }
+        }
+
+        Ok(domains)
+    }
+
+    /// Schedules the certificates of a domain for issuance, forcing the
+    /// renewal of those already issued when `force` is set.
+    pub async fn request_domain_certificates(&self, domain: &str, force: bool) -> trc::Result<()> {
+        let Some(config) = &self.core.acme.domains else {
+            return Ok(());
+        };
+
+        if force {
+            for hostname in self.certificate_hostnames(config, domain) {
+                self.inner
+                    .data
+                    .domain_certificates
+                    .update(&hostname, |state| {
+                        state.attempts = 0;
+                        state.next_attempt = now();
+                    });
+            }
+        }
+        self.init_domain_certificates(domain)
+            .await
+            .caused_by(trc::location!())?;
+
+        self.inner
+            .ipc
+            .housekeeper_tx
+            .send(HousekeeperEvent::DomainCertificates {
+                domain: domain.to_string(),
+                due: Instant::now(),
+            })
+            .await
+            .map_err(|err| {
+                trc::EventType::Server(trc::ServerEvent::ThreadError)
+                    .reason(err)
+                    .caused_by(trc::location!())
+                    .details("Failed to send event to Housekeeper")
+            })
+    }
+
+    pub fn domain_certificates(&self, domain: &str) -> Vec<HostnameCertificate> {
+        self.core
+            .acme
+            .domains
+            .as_ref()
+            .map(|config| {
+                self.certificate_hostnames(config, domain)
+                    .iter()
+                    .map(|hostname| self.inner.data.domain_certificates.get(hostname))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// A verified tenant hostname is issued a certificate of its own, the
+    /// hostnames of a directory domain are derived from the templates.
+    fn certificate_hostnames(&self, config: &DomainCertificates, name: &str) -> Vec<String> {
+        if self.verified_tenant_hostname(name).is_some() {
+            vec![name.to_string()]
+        } else {
+            config.hostnames(name)
+        }
+    }
+}
+
+impl AcmeProvider {
+    fn renew_before(&self) -> u64 {
+        self.renew_before.num_seconds().max(0) as u64
+    }
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    RETRY_MIN
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(RETRY_MAX)
+}
+
+fn error_details(err: &trc::Error) -> String {
+    err.value_as_str(trc::Key::Reason)
+        .or_else(|| err.value_as_str(trc::Key::Details))
+        .map(|details| details.to_string())
+        .unwrap_or_else(|| err.event_type().description().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CertificateStatus, HostnameCertificate, RETRY_MAX, RETRY_MIN, retry_delay};
+
+    #[test]
+    fn domain_certificate_retries() {
+        assert_eq!(retry_delay(1), RETRY_MIN);
+        assert_eq!(retry_delay(2), RETRY_MIN * 2);
+        assert_eq!(retry_delay(3), RETRY_MIN * 4);
+        assert_eq!(retry_delay(100), RETRY_MAX);
+
+        let mut state = HostnameCertificate::new("mail.example.org");
+        assert!(state.is_healthy());
+        state.failed(CertificateStatus::WaitingForDns, "no address".to_string());
+        assert_eq!(state.attempts, 1);
+        assert!(!state.is_healthy());
+        state.failed(CertificateStatus::Failed, "order invalid".to_string());
+        assert_eq!(state.attempts, 2);
+        assert_eq!(state.last_error.as_deref(), Some("order invalid"));
+    }
+}
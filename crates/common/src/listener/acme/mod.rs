@@ -6,6 +6,7 @@
 
 pub mod cache;
 pub mod directory;
+pub mod domain;
 pub mod jose;
 pub mod order;
 pub mod resolver;
@@ -92,6 +93,17 @@ impl AcmeProvider {
 
 impl Server {
     pub async fn init_acme(&self, provider: &AcmeProvider) -> trc::Result<Duration> {
+        self.init_acme_account(provider).await?;
+
+        // Load certificate from cache or request a new one
+        Ok(if let Some(pem) = self.load_cert(provider).await? {
+            self.process_cert(provider, pem, true).await?
+        } else {
+            Duration::from_millis(1000)
+        })
+    }
+
+    pub(crate) async fn init_acme_account(&self, provider: &AcmeProvider) -> trc::Result<()> {
         // Load account key from cache or generate a new one
         if let Some(account_key) = self.load_account(provider).await? {
             provider.account_key.store(Arc::new(account_key));
@@ -101,12 +113,7 @@ impl Server {
             provider.account_key.store(Arc::new(account_key));
         }
 
-        // Load certificate from cache or request a new one
-        Ok(if let Some(pem) = self.load_cert(provider).await? {
-            self.process_cert(provider, pem, true).await?
-        } else {
-            Duration::from_millis(1000)
-        })
+        Ok(())
     }
 
     pub fn has_acme_tls_providers(&self) -> bool {
@@ -114,6 +121,7 @@ impl Server {
             .acme
             .providers
             .values()
+            .chain(self.core.acme.domains.as_ref().map(|d| &d.provider))
             .any(|p| matches!(p.challenge, ChallengeSettings::TlsAlpn01))
     }
 
@@ -122,6 +130,7 @@ impl Server {
             .acme
             .providers
             .values()
+            .chain(self.core.acme.domains.as_ref().map(|d| &d.provider))
             .any(|p| matches!(p.challenge, ChallengeSettings::Http01))
     }
 }
@@ -392,7 +392,7 @@ impl Server {
     }
 }
 
-fn parse_cert(pem: &[u8]) -> trc::Result<(CertifiedKey, [DateTime<Utc>; 2])> {
+pub(super) fn parse_cert(pem: &[u8]) -> trc::Result<(CertifiedKey, [DateTime<Utc>; 2])> {
     let mut pems = pem::parse_many(pem).map_err(|err| {
         EventType::Acme(AcmeEvent::Error)
             .reason(err)
@@ -26,6 +26,7 @@ use super::{
     ServerInstance, SessionStream, TcpAcceptor, TcpAcceptorResult,
     acme::{
         AcmeProvider,
+        domain::DomainCertificates,
         resolver::{IsTlsAlpnChallenge, build_acme_static_resolver},
     },
 };
@@ -36,6 +37,7 @@ pub static TLS12_VERSION: &[&SupportedProtocolVersion] = &[&TLS12];
 #[derive(Default, Clone)]
 pub struct AcmeProviders {
     pub providers: AHashMap<String, AcmeProvider>,
+    pub domains: Option<DomainCertificates>,
 }
 
 #[derive(Clone)]
@@ -0,0 +1,154 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{net::IpAddr, str::FromStr, time::Instant};
+
+use compact_str::CompactString;
+use utils::HttpLimitResponse;
+
+use crate::{
+    Server,
+    config::abuse::{
+        ProvisioningCheckMethod, ProvisioningHook, ProvisioningVerdict, V_ABUSE_ADMIN_EMAIL,
+        V_ABUSE_DOMAIN, V_ABUSE_REMOTE_IP, V_ABUSE_SOURCE, V_ABUSE_TENANT_NAME,
+    },
+    expr::{Variable, functions::ResolveVariable},
+};
+
+/// Metadata of a public provisioning request, sent to the abuse check.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisioningRequestInfo<'x> {
+    /// Endpoint the request was received on, either "signup" or "invitation".
+    pub source: &'static str,
+    pub remote_ip: IpAddr,
+    pub domain: &'x str,
+    pub tenant_name: &'x str,
+    pub admin_email: &'x str,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProvisioningCheckResponse {
+    verdict: String,
+}
+
+impl Server {
+    /// Runs the configured abuse check on a public provisioning request and
+    /// records its verdict. Requests are allowed when no check is configured.
+    pub async fn check_provisioning(
+        &self,
+        request: &ProvisioningRequestInfo<'_>,
+    ) -> ProvisioningVerdict {
+        let Some(check) = &self.core.jmap.abuse_check else {
+            return ProvisioningVerdict::Allow;
+        };
+        let time = Instant::now();
+
+        let (method, result) = match &check.method {
+            ProvisioningCheckMethod::Http(hook) => {
+                ("http", send_provisioning_check(hook, request).await)
+            }
+            ProvisioningCheckMethod::Expression(if_block) => (
+                "expression",
+                self.eval_if::<String, _>(if_block, request, 0)
+                    .await
+                    .ok_or_else(|| "Expression returned no verdict".to_string())
+                    .and_then(|verdict| parse_verdict(&verdict)),
+            ),
+        };
+        let elapsed = time.elapsed();
+        let (verdict, details) = match result {
+            Ok(verdict) => (
+                verdict,
+                format!(
+                    "{} by {method} in {}ms",
+                    verdict.as_str(),
+                    elapsed.as_millis()
+                ),
+            ),
+            Err(reason) => (
+                check.default,
+                format!(
+                    "{} by default in {}ms: {reason}",
+                    check.default.as_str(),
+                    elapsed.as_millis()
+                ),
+            ),
+        };
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::ProvisioningChecked),
+            AccountName = request.admin_email.to_string(),
+            Id = request.domain.to_string(),
+            Details = details,
+            Type = request.source,
+            RemoteIp = request.remote_ip,
+            Elapsed = elapsed,
+        );
+
+        verdict
+    }
+}
+
+async fn send_provisioning_check(
+    hook: &ProvisioningHook,
+    request: &ProvisioningRequestInfo<'_>,
+) -> Result<ProvisioningVerdict, String> {
+    let response = reqwest::Client::builder()
+        .timeout(hook.timeout)
+        .danger_accept_invalid_certs(hook.tls_allow_invalid_certs)
+        .build()
+        .map_err(|err| format!("Failed to create HTTP client: {}", err))?
+        .post(&hook.url)
+        .headers(hook.headers.clone())
+        .body(
+            serde_json::to_string(request)
+                .map_err(|err| format!("Failed to serialize abuse check request: {}", err))?,
+        )
+        .send()
+        .await
+        .map_err(|err| format!("Abuse check request failed: {err}"))?;
+
+    if response.status().is_success() {
+        serde_json::from_slice::<ProvisioningCheckResponse>(
+            response
+                .bytes_with_limit(hook.max_response_size)
+                .await
+                .map_err(|err| format!("Failed to read abuse check response: {}", err))?
+                .ok_or_else(|| "Abuse check response too large".to_string())?
+                .as_ref(),
+        )
+        .map_err(|err| format!("Failed to parse abuse check response: {}", err))
+        .and_then(|response| parse_verdict(&response.verdict))
+    } else {
+        Err(format!(
+            "Abuse check request failed with code {}: {}",
+            response.status().as_u16(),
+            response.status().canonical_reason().unwrap_or("Unknown")
+        ))
+    }
+}
+
+fn parse_verdict(verdict: &str) -> Result<ProvisioningVerdict, String> {
+    ProvisioningVerdict::from_str(verdict).map_err(|_| format!("Unknown verdict {verdict:?}"))
+}
+
+impl ResolveVariable for ProvisioningRequestInfo<'_> {
+    fn resolve_variable(&self, variable: u32) -> Variable<'_> {
+        match variable {
+            V_ABUSE_SOURCE => self.source.into(),
+            V_ABUSE_REMOTE_IP => CompactString::from(self.remote_ip.to_string()).into(),
+            V_ABUSE_DOMAIN => self.domain.into(),
+            V_ABUSE_TENANT_NAME => self.tenant_name.into(),
+            V_ABUSE_ADMIN_EMAIL => self.admin_email.into(),
+            _ => Variable::Integer(0),
+        }
+    }
+
+    fn resolve_global(&self, _: &str) -> Variable<'_> {
+        Variable::Integer(0)
+    }
+}
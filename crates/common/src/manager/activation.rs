@@ -0,0 +1,82 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::activation::{TENANT_ACTIVATION_KEY, TenantActivation},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    /// Returns the pending activation of a tenant, if it has not been
+    /// activated yet.
+    pub fn tenant_activation(&self, tenant_id: u32) -> Option<Arc<TenantActivation>> {
+        self.inner
+            .data
+            .tenant_activation
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Requests or completes the activation of a tenant on every node.
+    pub async fn update_tenant_activation(
+        &self,
+        tenant_id: u32,
+        activation: Option<TenantActivation>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_ACTIVATION_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(activation) = &activation {
+            config
+                .set(activation.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_activation.load().as_ref().clone();
+        if let Some(activation) = activation {
+            tenants.insert(tenant_id, Arc::new(activation));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_activation.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantActivation)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns whether a local domain belongs to a tenant pending
+    /// activation, used to defer incoming mail.
+    pub async fn domain_pending_activation(&self, domain: &str) -> bool {
+        if self.inner.data.tenant_activation.load().is_empty() {
+            return false;
+        }
+
+        match self.domain_tenant(domain).await {
+            Ok(tenant_id) => tenant_id.is_some_and(|tenant_id| {
+                self.inner
+                    .data
+                    .tenant_activation
+                    .load()
+                    .contains_key(&tenant_id)
+            }),
+            Err(err) => {
+                trc::error!(err.details("Failed to resolve domain tenant"));
+                false
+            }
+        }
+    }
+}
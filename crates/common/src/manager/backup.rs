@@ -102,6 +102,7 @@ impl Core {
     fn backup_blobs(&self, dest: &Path, subspace: u8, schema_version: u32) -> TaskHandle {
         let store = self.storage.data.clone();
         let blob_store = self.storage.blob.clone();
+        let blob_stores = self.storage.blobs.clone();
         let (handle, writer) = spawn_writer(
             dest.join(format!("subspace_{}", char::from(subspace))),
             subspace,
@@ -144,7 +145,14 @@ impl Core {
                     .failed("Failed to iterate over data store");
 
                 for hash in blobs {
-                    if let Some(blob) = blob_store
+                    // Blobs are read from the store they were written to
+                    let location = store
+                        .blob_location(&hash)
+                        .await
+                        .failed("Failed to get blob location");
+                    if let Some(blob) = location
+                        .and_then(|location| blob_stores.get(&location.store_id))
+                        .unwrap_or(&blob_store)
                         .get_blob(hash.as_slice(), 0..usize::MAX)
                         .await
                         .failed("Failed to get blob")
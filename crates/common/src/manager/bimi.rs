@@ -0,0 +1,122 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+use types::blob_hash::BlobHash;
+
+use crate::{
+    Server,
+    config::smtp::bimi::{BIMI_LOGO, BIMI_VMC, DOMAIN_BIMI_KEY, DomainBimi, bimi_blob_key},
+    dns::DnsCheckType,
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn domain_bimi(&self, domain: &str) -> Option<Arc<DomainBimi>> {
+        self.inner.data.domain_bimi.load().get(domain).cloned()
+    }
+
+    /// Stores the logo and the optional Verified Mark Certificate of a
+    /// domain, replacing any previous ones. Both are expected to have been
+    /// validated by the caller.
+    pub async fn update_domain_bimi(
+        &self,
+        domain: &str,
+        logo: &[u8],
+        vmc: Option<&[u8]>,
+    ) -> trc::Result<DomainBimi> {
+        let bimi = DomainBimi {
+            logo: BlobHash::generate(logo).to_hex(),
+            vmc: vmc.map(|vmc| BlobHash::generate(vmc).to_hex()),
+        };
+
+        let blob_store = self.blob_store();
+        blob_store
+            .put_blob(&bimi_blob_key(domain, BIMI_LOGO), logo)
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(vmc) = vmc {
+            blob_store
+                .put_blob(&bimi_blob_key(domain, BIMI_VMC), vmc)
+                .await
+                .caused_by(trc::location!())?;
+        } else {
+            blob_store
+                .delete_blob(&bimi_blob_key(domain, BIMI_VMC))
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        self.set_domain_bimi(domain, Some(bimi.clone())).await?;
+
+        Ok(bimi)
+    }
+
+    /// Removes the BIMI settings of a domain, returns whether it had any.
+    pub async fn delete_domain_bimi(&self, domain: &str) -> trc::Result<bool> {
+        if self.domain_bimi(domain).is_none() {
+            return Ok(false);
+        }
+
+        self.set_domain_bimi(domain, None).await?;
+        let blob_store = self.blob_store();
+        for file in [BIMI_LOGO, BIMI_VMC] {
+            blob_store
+                .delete_blob(&bimi_blob_key(domain, file))
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(true)
+    }
+
+    /// Returns a BIMI file of a domain, as served to mailbox providers.
+    pub async fn domain_bimi_file(&self, domain: &str, file: &str) -> trc::Result<Option<Vec<u8>>> {
+        match (self.domain_bimi(domain), file) {
+            (Some(_), BIMI_LOGO) | (Some(DomainBimi { vmc: Some(_), .. }), BIMI_VMC) => self
+                .blob_store()
+                .get_blob(&bimi_blob_key(domain, file), 0..usize::MAX)
+                .await
+                .caused_by(trc::location!()),
+            _ => Ok(None),
+        }
+    }
+
+    async fn set_domain_bimi(&self, domain: &str, bimi: Option<DomainBimi>) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{DOMAIN_BIMI_KEY}.{domain}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(bimi) = &bimi {
+            config
+                .set(bimi.config_keys(domain), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut domains = self.inner.data.domain_bimi.load().as_ref().clone();
+        if let Some(bimi) = bimi {
+            domains.insert(domain.to_string(), Arc::new(bimi));
+        } else {
+            domains.remove(domain);
+        }
+        self.inner.data.domain_bimi.store(domains.into());
+
+        // The published record is verified again on the next health check
+        self.inner
+            .data
+            .dns_checks
+            .remove(domain, DnsCheckType::Bimi);
+
+        self.cluster_broadcast(BroadcastEvent::ReloadDomainBimi)
+            .await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::storage::{TENANT_BLOB_KEY, TenantEncryption},
+    ipc::BroadcastEvent,
+    storage::encryption::{generate_data_key, wrap_data_key},
+};
+
+impl Server {
+    /// Returns the id of the blob store assigned to a tenant, if any.
+    pub fn tenant_blob_store(&self, tenant_id: u32) -> Option<String> {
+        self.inner
+            .data
+            .tenant_blob_stores
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Assigns a blob store to a tenant, or restores the default blob store
+    /// when no store id is provided. Only newly written blobs are affected.
+    pub async fn update_tenant_blob_store(
+        &self,
+        tenant_id: u32,
+        store_id: Option<String>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        let key = format!("{TENANT_BLOB_KEY}.{tenant_id}.blob");
+        if let Some(store_id) = &store_id {
+            config
+                .set([(key, store_id.as_str())], true)
+                .await
+                .caused_by(trc::location!())?;
+        } else {
+            config.clear(key).await.caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_blob_stores.load().as_ref().clone();
+        if let Some(store_id) = store_id {
+            tenants.insert(tenant_id, store_id);
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_blob_stores.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantBlobStores)
+            .await;
+
+        Ok(())
+    }
+
+    /// Enables encryption of new blobs written for a tenant's accounts. If the
+    /// tenant already has a data key it is re-wrapped with the new key, so key
+    /// rotation does not re-encrypt existing blobs.
+    pub async fn update_tenant_encryption(
+        &self,
+        tenant_id: u32,
+        key_reference: String,
+        key: &[u8],
+        public_key: Option<String>,
+    ) -> trc::Result<()> {
+        let data_key = if self.tenant_encryption(tenant_id).is_some() {
+            self.tenant_data_key(tenant_id).await?
+        } else {
+            generate_data_key()
+        };
+        let settings = TenantEncryption {
+            enabled: true,
+            data_key: wrap_data_key(&data_key, key, public_key.as_deref()).map_err(|reason| {
+                trc::StoreEvent::CryptoError
+                    .into_err()
+                    .ctx(trc::Key::TenantId, tenant_id)
+                    .reason(reason)
+                    .caused_by(trc::location!())
+            })?,
+            key: key_reference,
+            public_key,
+        };
+
+        let config = &self.core.storage.config;
+        let prefix = format!("{TENANT_BLOB_KEY}.{tenant_id}.encryption");
+        config
+            .set(
+                [
+                    (format!("{prefix}.enable"), "true"),
+                    (format!("{prefix}.key"), settings.key.as_str()),
+                    (format!("{prefix}.data-key"), settings.data_key.as_str()),
+                ],
+                true,
+            )
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(public_key) = &settings.public_key {
+            config
+                .set(
+                    [(format!("{prefix}.public-key"), public_key.as_str())],
+                    true,
+                )
+                .await
+                .caused_by(trc::location!())?;
+        } else {
+            config
+                .clear(format!("{prefix}.public-key"))
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        self.store_tenant_encryption(tenant_id, settings).await;
+
+        Ok(())
+    }
+
+    /// Stops encrypting new blobs written for a tenant's accounts. The key
+    /// settings are kept so that existing blobs remain readable. Returns
+    /// `false` if encryption was not enabled.
+    pub async fn disable_tenant_encryption(&self, tenant_id: u32) -> trc::Result<bool> {
+        let Some(settings) = self
+            .tenant_encryption(tenant_id)
+            .filter(|settings| settings.enabled)
+        else {
+            return Ok(false);
+        };
+
+        self.core
+            .storage
+            .config
+            .set(
+                [(
+                    format!("{TENANT_BLOB_KEY}.{tenant_id}.encryption.enable"),
+                    "false",
+                )],
+                true,
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        self.store_tenant_encryption(
+            tenant_id,
+            TenantEncryption {
+                enabled: false,
+                ..settings.as_ref().clone()
+            },
+        )
+        .await;
+
+        Ok(true)
+    }
+
+    async fn store_tenant_encryption(&self, tenant_id: u32, settings: TenantEncryption) {
+        let mut tenants = self.inner.data.tenant_encryption.load().as_ref().clone();
+        tenants.insert(tenant_id, Arc::new(settings));
+        self.inner.data.tenant_encryption.store(tenants.into());
+        self.inner.data.data_keys.remove(tenant_id);
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantBlobStores)
+            .await;
+    }
+}
@@ -424,7 +424,7 @@ impl BootManager {
                 let core = Box::pin(Core::parse(&mut config, stores, manager)).await;
 
                 // Parse data
-                let data = Data::parse(&mut config);
+                let data = Data::parse(&mut config, &core);
 
                 // Parse caches
                 let cache = Caches::parse(&mut config);
@@ -0,0 +1,153 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::smtp::disclaimer::{
+        DOMAIN_DISCLAIMER_KEY, DomainDisclaimer, TENANT_DISCLAIMER_KEY, TenantDisclaimer,
+    },
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_disclaimer(&self, tenant_id: u32) -> Option<Arc<TenantDisclaimer>> {
+        self.inner
+            .data
+            .tenant_disclaimers
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the disclaimer of a tenant, or removes it when no disclaimer
+    /// is provided.
+    pub async fn update_tenant_disclaimer(
+        &self,
+        tenant_id: u32,
+        disclaimer: Option<TenantDisclaimer>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_DISCLAIMER_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(disclaimer) = &disclaimer {
+            config
+                .set(disclaimer.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_disclaimers.load().as_ref().clone();
+        if let Some(disclaimer) = disclaimer {
+            tenants.insert(tenant_id, Arc::new(disclaimer));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_disclaimers.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadDisclaimers)
+            .await;
+
+        Ok(())
+    }
+
+    pub fn domain_disclaimer(&self, domain: &str) -> Option<Arc<DomainDisclaimer>> {
+        self.inner
+            .data
+            .domain_disclaimers
+            .load()
+            .get(domain)
+            .cloned()
+    }
+
+    /// Replaces the disclaimer of a domain, or removes it when no disclaimer
+    /// is provided so the tenant disclaimer is used again.
+    pub async fn update_domain_disclaimer(
+        &self,
+        domain: &str,
+        disclaimer: Option<DomainDisclaimer>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{DOMAIN_DISCLAIMER_KEY}.{domain}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(disclaimer) = &disclaimer {
+            config
+                .set(disclaimer.config_keys(domain), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut domains = self.inner.data.domain_disclaimers.load().as_ref().clone();
+        if let Some(disclaimer) = disclaimer {
+            domains.insert(domain.to_string(), Arc::new(disclaimer));
+        } else {
+            domains.remove(domain);
+        }
+        self.inner.data.domain_disclaimers.store(domains.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadDisclaimers)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the disclaimer of the domain a message is sent from, which
+    /// only applies to the senders of the tenant the domain belongs to.
+    pub async fn sender_domain_disclaimer(
+        &self,
+        domain: &str,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<Arc<DomainDisclaimer>>> {
+        match self.domain_disclaimer(&domain.to_lowercase()) {
+            Some(disclaimer) if self.is_tenant_domain(domain, tenant_id).await? => {
+                Ok(Some(disclaimer))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether a local domain belongs to a tenant, or to no tenant when none
+    /// is provided.
+    pub async fn is_tenant_domain(
+        &self,
+        domain: &str,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<bool> {
+        self.store()
+            .get_principal_info(&domain.to_lowercase())
+            .await
+            .caused_by(trc::location!())
+            .map(|info| {
+                info.is_some_and(|info| info.typ == Type::Domain && info.tenant == tenant_id)
+            })
+    }
+
+    /// Returns the brand name of a tenant as used in disclaimers, which is
+    /// the tenant name when no brand is set.
+    pub async fn tenant_brand_name(&self, tenant_id: u32) -> trc::Result<String> {
+        Ok(self
+            .store()
+            .get_principal(tenant_id)
+            .await
+            .caused_by(trc::location!())?
+            .map(|tenant| {
+                tenant
+                    .brand_name()
+                    .filter(|brand| !brand.is_empty())
+                    .unwrap_or(tenant.name())
+                    .to_string()
+            })
+            .unwrap_or_default())
+    }
+}
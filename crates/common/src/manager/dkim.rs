@@ -0,0 +1,99 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::smtp::auth::{TENANT_ARC_KEY, TENANT_DKIM_KEY, TenantDkimPolicy, TenantDkimSigning},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_dkim_policy(&self, tenant_id: u32) -> Option<Arc<TenantDkimSigning>> {
+        self.inner
+            .data
+            .tenant_dkim_policies
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the DKIM signing policy of a tenant, removing it when
+    /// neither a tenant nor a domain policy is configured.
+    pub async fn update_tenant_dkim_policy(
+        &self,
+        tenant_id: u32,
+        policy: TenantDkimPolicy,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_DKIM_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(policy.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())?;
+
+        let mut tenants = self.inner.data.tenant_dkim_policies.load().as_ref().clone();
+        if !policy.is_empty() {
+            tenants.insert(tenant_id, Arc::new(policy.into()));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_dkim_policies.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantDkimPolicies)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the name of the signature a tenant seals forwarded messages with.
+    pub fn tenant_arc_sealer(&self, tenant_id: u32) -> Option<String> {
+        self.inner
+            .data
+            .tenant_arc_sealers
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Enables ARC sealing of the messages forwarded by a tenant's accounts
+    /// using the provided signature, or disables it when none is provided.
+    pub async fn update_tenant_arc_sealer(
+        &self,
+        tenant_id: u32,
+        signature: Option<String>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        let key = format!("{TENANT_ARC_KEY}.{tenant_id}.seal");
+        if let Some(signature) = &signature {
+            config
+                .set([(key, signature.as_str())], true)
+                .await
+                .caused_by(trc::location!())?;
+        } else {
+            config.clear(key).await.caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_arc_sealers.load().as_ref().clone();
+        if let Some(signature) = signature {
+            tenants.insert(tenant_id, signature);
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_arc_sealers.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantArcSealers)
+            .await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::{
+        groupware::{TENANT_COLLECTIONS_KEY, TenantCollections},
+        jmap::settings::{TENANT_FOLDERS_KEY, TenantFolders},
+    },
+};
+
+impl Server {
+    /// Returns the folders provisioned for the accounts of a tenant, if the
+    /// tenant overrides the server defaults.
+    pub async fn tenant_folders(&self, tenant_id: u32) -> trc::Result<Option<TenantFolders>> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(&format!("{TENANT_FOLDERS_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(TenantFolders::parse(&mut config, tenant_id))
+    }
+
+    /// Replaces the folders provisioned for the accounts of a tenant,
+    /// reverting to the server defaults when the list is empty.
+    pub async fn update_tenant_folders(
+        &self,
+        tenant_id: u32,
+        folders: TenantFolders,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_FOLDERS_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(folders.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())
+    }
+
+    /// Returns the calendars and address books provisioned for the accounts
+    /// of a tenant, if any.
+    pub async fn tenant_collections(
+        &self,
+        tenant_id: u32,
+    ) -> trc::Result<Option<TenantCollections>> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(&format!("{TENANT_COLLECTIONS_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(TenantCollections::parse(&mut config, tenant_id))
+    }
+
+    /// Replaces the calendars and address books provisioned for the accounts
+    /// of a tenant.
+    pub async fn update_tenant_collections(
+        &self,
+        tenant_id: u32,
+        collections: TenantCollections,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_COLLECTIONS_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(collections.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())
+    }
+}
@@ -0,0 +1,103 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::hostname::{TENANT_HOSTNAME_KEY, TenantHostname},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_hostname(&self, hostname: &str) -> Option<Arc<TenantHostname>> {
+        self.inner
+            .data
+            .tenant_hostnames
+            .load()
+            .get(hostname)
+            .cloned()
+    }
+
+    /// Hostnames mapped to a tenant, sorted by name.
+    pub fn tenant_hostnames(&self, tenant_id: u32) -> Vec<Arc<TenantHostname>> {
+        let mut hostnames = self
+            .inner
+            .data
+            .tenant_hostnames
+            .load()
+            .values()
+            .filter(|hostname| hostname.tenant_id == tenant_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        hostnames.sort_unstable_by(|a, b| a.hostname.cmp(&b.hostname));
+        hostnames
+    }
+
+    /// Returns the tenant a request is routed to when it arrives on a
+    /// hostname, only verified hostnames are routed.
+    pub fn verified_tenant_hostname(&self, hostname: &str) -> Option<Arc<TenantHostname>> {
+        self.tenant_hostname(hostname)
+            .filter(|hostname| hostname.is_verified())
+    }
+
+    /// Adds or replaces the mapping of a hostname, or removes it when no
+    /// mapping is provided. Removed hostnames stop serving the tenant's
+    /// branding on every node right away.
+    pub async fn update_tenant_hostname(
+        &self,
+        hostname: &str,
+        mapping: Option<TenantHostname>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_HOSTNAME_KEY}.{hostname}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(mapping) = &mapping {
+            config
+                .set(mapping.config_keys(), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut hostnames = self.inner.data.tenant_hostnames.load().as_ref().clone();
+        if let Some(mapping) = mapping {
+            hostnames.insert(hostname.to_string(), Arc::new(mapping));
+        } else {
+            hostnames.remove(hostname);
+            self.inner.data.domain_certificates.remove(hostname);
+        }
+        self.inner.data.tenant_hostnames.store(hostnames.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantHostnames)
+            .await;
+
+        Ok(())
+    }
+
+    /// Whether the TXT challenge record of a hostname contains its token.
+    /// Lookup failures are reported as errors so they can be told apart from
+    /// a missing record.
+    pub async fn has_hostname_challenge(&self, mapping: &TenantHostname) -> trc::Result<bool> {
+        match self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .txt_raw_lookup(mapping.challenge_name())
+            .await
+        {
+            Ok(record) => Ok(std::str::from_utf8(&record)
+                .unwrap_or_default()
+                .contains(&mapping.challenge_value())),
+            Err(mail_auth::Error::DnsRecordNotFound(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use store::{
+    IterateParams, U32_LEN, U64_LEN, ValueKey,
+    dispatch::lookup::KeyValue,
+    rand,
+    write::{
+        Archiver, BatchBuilder, TaskEpoch, TaskQueueClass, ValueClass, key::DeserializeBigEndian,
+        now,
+    },
+};
+use trc::AddContext;
+
+use crate::{
+    KV_JOURNAL_STATUS, Server,
+    config::smtp::journal::{JournalEntry, TENANT_JOURNAL_KEY, TenantJournaling},
+    ipc::BroadcastEvent,
+};
+
+/// Journal entries waiting to be delivered for a tenant.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct JournalBacklog {
+    pub messages: u64,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JournalFailure {
+    pub at: u64,
+    pub reason: String,
+}
+
+const STATUS_DELIVERED: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+
+impl Server {
+    pub fn tenant_journaling(&self, tenant_id: u32) -> Option<Arc<TenantJournaling>> {
+        self.inner
+            .data
+            .tenant_journaling
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the journaling settings of a tenant, or disables journaling
+    /// when no settings are provided. Entries already queued are delivered
+    /// using the settings in effect at the time of delivery, and discarded
+    /// once journaling is disabled.
+    pub async fn update_tenant_journaling(
+        &self,
+        tenant_id: u32,
+        journaling: Option<TenantJournaling>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_JOURNAL_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(journaling) = &journaling {
+            config
+                .set(journaling.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_journaling.load().as_ref().clone();
+        if let Some(journaling) = journaling {
+            tenants.insert(tenant_id, Arc::new(journaling));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_journaling.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantJournaling)
+            .await;
+
+        Ok(())
+    }
+
+    /// Adds a copy of a message to the journal queue of a tenant, it is
+    /// delivered to the journal target by the task manager.
+    pub async fn schedule_journal_entry(
+        &self,
+        tenant_id: u32,
+        entry: JournalEntry,
+    ) -> trc::Result<()> {
+        let due = TaskEpoch::now().with_random_sequence_id();
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(tenant_id)
+            .with_document(rand::random::<u32>())
+            .set(
+                ValueClass::TaskQueue(TaskQueueClass::Journal {
+                    due,
+                    is_payload: false,
+                }),
+                vec![],
+            )
+            .set(
+                ValueClass::TaskQueue(TaskQueueClass::Journal {
+                    due,
+                    is_payload: true,
+                }),
+                Archiver::new(entry)
+                    .serialize()
+                    .caused_by(trc::location!())?,
+            );
+        self.store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())?;
+        self.notify_task_queue();
+
+        Ok(())
+    }
+
+    pub async fn journal_backlog(&self, tenant_id: u32) -> trc::Result<JournalBacklog> {
+        let from_key = ValueKey {
+            account_id: tenant_id,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::TaskQueue(TaskQueueClass::Journal {
+                due: TaskEpoch::from_inner(0),
+                is_payload: true,
+            }),
+        };
+        let to_key = ValueKey {
+            account_id: tenant_id,
+            collection: 0,
+            document_id: u32::MAX,
+            class: ValueClass::TaskQueue(TaskQueueClass::Journal {
+                due: TaskEpoch::from_inner(u64::MAX),
+                is_payload: true,
+            }),
+        };
+
+        let mut backlog = JournalBacklog::default();
+        self.store()
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |key, value| {
+                    let due = TaskEpoch::from_inner(
+                        key.deserialize_be_u64(U64_LEN + U32_LEN + 1 + U32_LEN)?,
+                    )
+                    .due();
+                    backlog.messages += 1;
+                    backlog.size += value.len() as u64;
+                    backlog.oldest = Some(backlog.oldest.map_or(due, |oldest| oldest.min(due)));
+
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(backlog)
+    }
+
+    pub async fn set_journal_delivered(&self, tenant_id: u32) {
+        if let Err(err) = self
+            .in_memory_store()
+            .key_set(KeyValue::with_prefix(
+                KV_JOURNAL_STATUS,
+                journal_status_key(tenant_id, STATUS_DELIVERED),
+                now().to_string().into_bytes(),
+            ))
+            .await
+        {
+            trc::error!(
+                err.details("Failed to update journal status")
+                    .caused_by(trc::location!())
+            );
+        }
+    }
+
+    pub async fn set_journal_failure(&self, tenant_id: u32, reason: String) {
+        let failure = JournalFailure { at: now(), reason };
+        if let Err(err) = self
+            .in_memory_store()
+            .key_set(KeyValue::with_prefix(
+                KV_JOURNAL_STATUS,
+                journal_status_key(tenant_id, STATUS_FAILED),
+                serde_json::to_vec(&failure).unwrap_or_default(),
+            ))
+            .await
+        {
+            trc::error!(
+                err.details("Failed to update journal status")
+                    .caused_by(trc::location!())
+            );
+        }
+    }
+
+    /// Returns when an entry was last delivered to the journal target of a
+    /// tenant, and the last delivery failure.
+    pub async fn journal_status(
+        &self,
+        tenant_id: u32,
+    ) -> trc::Result<(Option<u64>, Option<JournalFailure>)> {
+        let store = self.in_memory_store();
+        let delivered = store
+            .key_get::<String>(KeyValue::<()>::build_key(
+                KV_JOURNAL_STATUS,
+                journal_status_key(tenant_id, STATUS_DELIVERED),
+            ))
+            .await
+            .caused_by(trc::location!())?
+            .and_then(|timestamp| timestamp.parse().ok());
+        let failure = store
+            .key_get::<String>(KeyValue::<()>::build_key(
+                KV_JOURNAL_STATUS,
+                journal_status_key(tenant_id, STATUS_FAILED),
+            ))
+            .await
+            .caused_by(trc::location!())?
+            .and_then(|failure| serde_json::from_str(&failure).ok());
+
+        Ok((delivered, failure))
+    }
+}
+
+fn journal_status_key(tenant_id: u32, status: u8) -> [u8; U32_LEN + 1] {
+    let mut key = [0u8; U32_LEN + 1];
+    key[..U32_LEN].copy_from_slice(&tenant_id.to_be_bytes());
+    key[U32_LEN] = status;
+    key
+}
@@ -0,0 +1,149 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::login_page::{DOMAIN_LOGIN_PAGE_KEY, LoginPage, TENANT_LOGIN_PAGE_KEY},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_login_page(&self, tenant_id: u32) -> Option<Arc<LoginPage>> {
+        self.inner
+            .data
+            .tenant_login_pages
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the login page of a tenant, or removes it when no page is
+    /// provided.
+    pub async fn update_tenant_login_page(
+        &self,
+        tenant_id: u32,
+        page: Option<LoginPage>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_LOGIN_PAGE_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(page) = &page {
+            config
+                .set(page.tenant_config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_login_pages.load().as_ref().clone();
+        if let Some(page) = page.filter(|page| !page.is_empty()) {
+            tenants.insert(tenant_id, Arc::new(page));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_login_pages.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadLoginPages)
+            .await;
+
+        Ok(())
+    }
+
+    pub fn domain_login_page(&self, domain: &str) -> Option<Arc<LoginPage>> {
+        self.inner
+            .data
+            .domain_login_pages
+            .load()
+            .get(domain)
+            .cloned()
+    }
+
+    /// Replaces the login page of a domain, or removes it when no page is
+    /// provided so the tenant page is used again.
+    pub async fn update_domain_login_page(
+        &self,
+        domain: &str,
+        page: Option<LoginPage>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{DOMAIN_LOGIN_PAGE_KEY}.{domain}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(page) = &page {
+            config
+                .set(page.domain_config_keys(domain), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut domains = self.inner.data.domain_login_pages.load().as_ref().clone();
+        if let Some(page) = page.filter(|page| !page.is_empty()) {
+            domains.insert(domain.to_string(), Arc::new(page));
+        } else {
+            domains.remove(domain);
+        }
+        self.inner.data.domain_login_pages.store(domains.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadLoginPages)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the login page served for a domain, the page of the domain
+    /// takes precedence over the one of its tenant.
+    pub fn login_page(
+        &self,
+        domain: Option<&str>,
+        tenant_id: Option<u32>,
+    ) -> Option<Arc<LoginPage>> {
+        domain
+            .and_then(|domain| self.domain_login_page(domain))
+            .or_else(|| tenant_id.and_then(|tenant_id| self.tenant_login_page(tenant_id)))
+    }
+
+    /// Maps the host a page was requested for to a local domain and its
+    /// tenant. Verified tenant hostnames map to the tenant's default login
+    /// domain, other hosts such as `mail.acme.org` are matched to their
+    /// registrable domain when they are not a local domain themselves.
+    pub async fn branding_domain(&self, host: &str) -> trc::Result<Option<(String, Option<u32>)>> {
+        let host = host.trim().trim_end_matches('.').to_lowercase();
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|ch| ch.is_ascii_digit()) => name,
+            _ => host.as_str(),
+        };
+        if host.is_empty() {
+            return Ok(None);
+        } else if let Some(mapping) = self.verified_tenant_hostname(host) {
+            return Ok(Some((mapping.default_domain.clone(), Some(mapping.tenant_id))));
+        }
+
+        let mut candidates = vec![host];
+        if let Some(domain) = psl::domain_str(host).filter(|domain| *domain != host) {
+            candidates.push(domain);
+        }
+        for domain in candidates {
+            if let Some(info) = self
+                .store()
+                .get_principal_info(domain)
+                .await
+                .caused_by(trc::location!())?
+                .filter(|info| info.typ == Type::Domain)
+            {
+                return Ok(Some((domain.to_string(), info.tenant)));
+            }
+        }
+
+        Ok(None)
+    }
+}
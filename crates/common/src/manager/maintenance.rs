@@ -0,0 +1,98 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use store::write::now;
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::maintenance::{TENANT_MAINTENANCE_KEY, TenantMaintenance},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    /// Returns the maintenance window of a tenant if it is still active.
+    pub fn tenant_maintenance(&self, tenant_id: u32) -> Option<Arc<TenantMaintenance>> {
+        self.inner
+            .data
+            .tenant_maintenance
+            .load()
+            .get(&tenant_id)
+            .filter(|maintenance| maintenance.is_active(now()))
+            .cloned()
+    }
+
+    /// Starts or ends the maintenance of a tenant on every node.
+    pub async fn update_tenant_maintenance(
+        &self,
+        tenant_id: u32,
+        maintenance: Option<TenantMaintenance>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_MAINTENANCE_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(maintenance) = &maintenance {
+            config
+                .set(maintenance.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_maintenance.load().as_ref().clone();
+        if let Some(maintenance) = maintenance {
+            tenants.insert(tenant_id, Arc::new(maintenance));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_maintenance.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantMaintenance)
+            .await;
+
+        Ok(())
+    }
+
+    /// Rejects logins of the principals of a tenant under maintenance or
+    /// pending activation.
+    pub fn assert_tenant_available(&self, tenant_id: Option<u32>) -> trc::Result<()> {
+        if tenant_id.is_some_and(|tenant_id| self.tenant_activation(tenant_id).is_some()) {
+            return Err(trc::AuthEvent::PendingActivation
+                .into_err()
+                .details("Organization pending activation"));
+        }
+
+        match tenant_id.and_then(|tenant_id| self.tenant_maintenance(tenant_id)) {
+            Some(maintenance) => Err(trc::AuthEvent::Maintenance
+                .into_err()
+                .details(maintenance.reason())
+                .ctx_opt(
+                    trc::Key::Expires,
+                    maintenance.ends_at.map(trc::Value::Timestamp),
+                )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the maintenance window of the tenant owning a local domain,
+    /// used to defer incoming mail.
+    pub async fn domain_maintenance(&self, domain: &str) -> Option<Arc<TenantMaintenance>> {
+        if self.inner.data.tenant_maintenance.load().is_empty() {
+            return None;
+        }
+
+        match self.domain_tenant(domain).await {
+            Ok(tenant_id) => tenant_id.and_then(|tenant_id| self.tenant_maintenance(tenant_id)),
+            Err(err) => {
+                trc::error!(err.details("Failed to resolve domain tenant"));
+                None
+            }
+        }
+    }
+}
@@ -10,12 +10,33 @@ use hyper::HeaderMap;
 use std::time::Duration;
 use utils::HttpLimitResponse;
 
+pub mod abuse;
+pub mod activation;
 pub mod backup;
+pub mod bimi;
+pub mod blob;
 pub mod boot;
 pub mod config;
 pub mod console;
+pub mod disclaimer;
+pub mod dkim;
+pub mod folders;
+pub mod hostname;
+pub mod journal;
+pub mod login_page;
+pub mod maintenance;
+pub mod overrides;
+pub mod password_policy;
+pub mod plan;
 pub mod reload;
+pub mod resource;
 pub mod restore;
+pub mod retention;
+pub mod routing;
+pub mod shared;
+pub mod sieve;
+pub mod spam;
+pub mod spf;
 pub mod webadmin;
 
 const DEFAULT_SPAMFILTER_URL: &str =
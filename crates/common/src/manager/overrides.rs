@@ -0,0 +1,204 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::overrides::{
+        SessionLimits, SettingSource, TENANT_OVERRIDES_KEY, TenantOverrides, TenantProtocol,
+        TenantSetting,
+    },
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_overrides(&self, tenant_id: u32) -> Option<Arc<TenantOverrides>> {
+        self.inner
+            .data
+            .tenant_overrides
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the setting overrides of a tenant, the new values are used by
+    /// every node without a restart.
+    pub async fn update_tenant_overrides(
+        &self,
+        tenant_id: u32,
+        overrides: TenantOverrides,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_OVERRIDES_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(overrides.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())?;
+
+        let mut tenants = self.inner.data.tenant_overrides.load().as_ref().clone();
+        if !overrides.is_empty() {
+            tenants.insert(tenant_id, Arc::new(overrides));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_overrides.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantOverrides)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the value of a setting for a tenant along with where it comes
+    /// from. Tenant overrides take precedence over the tenant's plan, which
+    /// takes precedence over the global value.
+    pub async fn effective_setting(
+        &self,
+        tenant_id: Option<u32>,
+        setting: TenantSetting,
+    ) -> (u64, SettingSource) {
+        if let Some(tenant_id) = tenant_id {
+            if let Some(value) = self
+                .tenant_overrides(tenant_id)
+                .and_then(|overrides| overrides.get(setting))
+            {
+                return (value, SettingSource::Tenant);
+            }
+            if let Some(value) = self
+                .tenant_plan(tenant_id)
+                .await
+                .and_then(|plan| plan.settings.get(setting))
+            {
+                return (value, SettingSource::Plan);
+            }
+        }
+
+        (self.global_setting(setting), SettingSource::Global)
+    }
+
+    pub async fn tenant_setting(&self, tenant_id: Option<u32>, setting: TenantSetting) -> u64 {
+        self.effective_setting(tenant_id, setting).await.0
+    }
+
+    pub fn global_setting(&self, setting: TenantSetting) -> u64 {
+        match setting {
+            TenantSetting::OAuthTokenExpiry => self.core.oauth.oauth_expiry_token,
+            TenantSetting::OAuthRefreshTokenExpiry => self.core.oauth.oauth_expiry_refresh_token,
+            TenantSetting::UploadMaxSize => self.core.jmap.upload_max_size as u64,
+            TenantSetting::EmailMaxSize => self.core.jmap.mail_max_size as u64,
+            TenantSetting::AttachmentMaxSize => self.core.jmap.mail_attachments_max_size as u64,
+            TenantSetting::SessionMaxLifetime => self.core.oauth.session_max_lifetime,
+            TenantSetting::SessionIdleTimeout => self.core.oauth.session_idle_timeout,
+            TenantSetting::ImapIdleTimeout => self.core.imap.timeout_idle.as_secs(),
+            TenantSetting::WebAuthnPasswordless => u64::from(self.core.oauth.webauthn_passwordless),
+            TenantSetting::SecurityNotice(notice) => {
+                u64::from(self.core.oauth.security_notices.contains(&notice))
+            }
+            TenantSetting::Protocol(_) => 1,
+        }
+    }
+
+    /// Resolves the limits of a new session of an account of the tenant.
+    pub async fn session_limits(&self, tenant_id: Option<u32>) -> SessionLimits {
+        SessionLimits {
+            max_lifetime: self
+                .tenant_setting(tenant_id, TenantSetting::SessionMaxLifetime)
+                .await,
+            idle_timeout: self
+                .tenant_setting(tenant_id, TenantSetting::SessionIdleTimeout)
+                .await,
+            imap_idle_timeout: self
+                .tenant_setting(tenant_id, TenantSetting::ImapIdleTimeout)
+                .await,
+            tenant_id,
+            overrides: tenant_id.and_then(|tenant_id| self.tenant_overrides(tenant_id)),
+        }
+    }
+
+    /// Resolves the limits of an existing session again when the overrides of
+    /// its tenant changed since they were cached on the session.
+    pub async fn refresh_session_limits(&self, limits: &mut SessionLimits) {
+        if let Some(tenant_id) = limits.tenant_id {
+            let overrides = self.tenant_overrides(tenant_id);
+            let is_current = match (&overrides, &limits.overrides) {
+                (Some(current), Some(cached)) => Arc::ptr_eq(current, cached),
+                (None, None) => true,
+                _ => false,
+            };
+            if !is_current {
+                *limits = self.session_limits(Some(tenant_id)).await;
+            }
+        }
+    }
+
+    /// Whether any tenant or plan restricts protocols, which allows skipping
+    /// the tenant lookup on every login when none does.
+    pub fn has_protocol_flags(&self) -> bool {
+        self.inner
+            .data
+            .tenant_overrides
+            .load()
+            .values()
+            .any(|overrides| overrides.has_protocol_flags())
+            || self
+                .core
+                .jmap
+                .plans
+                .values()
+                .any(|plan| plan.settings.has_protocol_flags())
+    }
+
+    pub async fn is_protocol_enabled(
+        &self,
+        tenant_id: Option<u32>,
+        protocol: TenantProtocol,
+    ) -> bool {
+        tenant_id.is_none()
+            || !self.has_protocol_flags()
+            || self
+                .tenant_setting(tenant_id, TenantSetting::Protocol(protocol))
+                .await
+                != 0
+    }
+
+    /// Fails when the tenant, or its plan, disabled the protocol. The check
+    /// runs before credentials are verified whenever the tenant is known.
+    pub async fn assert_protocol_enabled(
+        &self,
+        tenant_id: Option<u32>,
+        protocol: TenantProtocol,
+    ) -> trc::Result<()> {
+        if self.is_protocol_enabled(tenant_id, protocol).await {
+            Ok(())
+        } else {
+            Err(trc::AuthEvent::ProtocolDisabled
+                .into_err()
+                .details("Protocol not enabled for your organization")
+                .ctx(trc::Key::Type, protocol.as_str())
+                .ctx_opt(trc::Key::TenantId, tenant_id))
+        }
+    }
+
+    /// Returns the tenant of a local domain, used to apply the overrides of
+    /// the recipient's tenant when there is no authenticated principal.
+    pub async fn domain_tenant(&self, domain: &str) -> trc::Result<Option<u32>> {
+        self.store()
+            .get_principal_info(&domain.to_lowercase())
+            .await
+            .caused_by(trc::location!())
+            .map(|info| {
+                info.filter(|info| info.typ == Type::Domain)
+                    .and_then(|info| info.tenant)
+            })
+    }
+}
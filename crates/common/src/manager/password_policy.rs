@@ -0,0 +1,153 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{borrow::Cow, sync::Arc};
+
+use directory::{
+    backend::internal::{
+        PrincipalAction, PrincipalField, PrincipalUpdate, SpecialSecrets, manage,
+        password::PasswordPolicy,
+    },
+    core::secret::is_supported_secret_hash,
+};
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::password_policy::{TENANT_PASSWORD_POLICY_KEY, TenantPasswordPolicy},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_password_policy(&self, tenant_id: u32) -> Option<Arc<TenantPasswordPolicy>> {
+        self.inner
+            .data
+            .tenant_password_policies
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the password policy of a tenant, or removes it when no policy
+    /// is provided so the global policy applies again.
+    pub async fn update_tenant_password_policy(
+        &self,
+        tenant_id: u32,
+        policy: Option<TenantPasswordPolicy>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_PASSWORD_POLICY_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(policy) = &policy {
+            config
+                .set(policy.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self
+            .inner
+            .data
+            .tenant_password_policies
+            .load()
+            .as_ref()
+            .clone();
+        if let Some(policy) = policy.filter(|policy| !policy.is_empty()) {
+            tenants.insert(tenant_id, Arc::new(policy));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner
+            .data
+            .tenant_password_policies
+            .store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadPasswordPolicies)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the policy of the accounts of a tenant, which is the global
+    /// policy with the overrides of the tenant applied.
+    pub fn password_policy(&self, tenant_id: Option<u32>) -> Cow<'_, PasswordPolicy> {
+        let global = &self.core.jmap.password_policy;
+        match tenant_id.and_then(|tenant_id| self.tenant_password_policy(tenant_id)) {
+            Some(policy) => Cow::Owned(policy.resolve(global)),
+            None => Cow::Borrowed(global),
+        }
+    }
+
+    /// Whether logins have to be checked for expired passwords or for app
+    /// passwords that are no longer allowed.
+    pub fn has_password_restrictions(&self) -> bool {
+        let global = &self.core.jmap.password_policy;
+        global.max_age_days > 0
+            || !global.allow_app_passwords
+            || self
+                .inner
+                .data
+                .tenant_password_policies
+                .load()
+                .values()
+                .any(|policy| {
+                    policy.max_age_days.is_some_and(|days| days > 0)
+                        || policy.allow_app_passwords == Some(false)
+                })
+    }
+
+    /// Checks new secrets of an account against the policy of its tenant.
+    /// Hashed passwords can't be checked and are accepted as is, new app
+    /// passwords are rejected when the policy does not allow them.
+    pub fn assert_password_policy<'x>(
+        &self,
+        tenant_id: Option<u32>,
+        secrets: impl IntoIterator<Item = &'x str>,
+    ) -> trc::Result<()> {
+        let policy = self.password_policy(tenant_id);
+        for secret in secrets {
+            if secret.is_app_secret() {
+                if !policy.allow_app_passwords {
+                    return Err(manage::error(
+                        "App passwords not allowed",
+                        "The password policy does not allow app passwords".into(),
+                    ));
+                }
+            } else if !secret.is_otp_secret()
+                && !(secret.starts_with(['$', '{']) && is_supported_secret_hash(secret))
+                && let Err(err) = policy.check(secret)
+            {
+                return Err(manage::error("Invalid password", err.into()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the secrets set or added by a list of principal changes.
+    pub fn assert_password_changes(
+        &self,
+        tenant_id: Option<u32>,
+        changes: &[PrincipalUpdate],
+    ) -> trc::Result<()> {
+        self.assert_password_policy(
+            tenant_id,
+            changes
+                .iter()
+                .filter(|change| {
+                    change.field == PrincipalField::Secrets
+                        && matches!(
+                            change.action,
+                            PrincipalAction::Set | PrincipalAction::AddItem
+                        )
+                })
+                .flat_map(|change| change.value.iter_str())
+                .map(|secret| secret.as_str()),
+        )
+    }
+}
@@ -0,0 +1,162 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::{
+    Principal, QueryParams, Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
+use trc::AddContext;
+
+use crate::{Server, config::plan::TenantPlan};
+
+/// Limits in effect for a tenant, either set on the tenant itself or
+/// derived from its plan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantLimits {
+    pub quota: Option<u64>,
+    pub max_users: Option<u32>,
+    pub max_domains: Option<u32>,
+}
+
+impl Server {
+    /// Returns a plan of the registry, failing when it does not exist.
+    pub fn assert_plan_exists(&self, name: &str) -> trc::Result<Arc<TenantPlan>> {
+        self.core.jmap.plans.get(name).cloned().ok_or_else(|| {
+            manage::error(
+                "Invalid plan",
+                format!("Plan {name:?} does not exist").into(),
+            )
+        })
+    }
+
+    /// Returns the plan of a tenant, plans that were removed from the
+    /// registry are ignored.
+    pub fn principal_plan(&self, tenant: &Principal) -> Option<Arc<TenantPlan>> {
+        tenant
+            .plan()
+            .and_then(|name| self.core.jmap.plans.get(name))
+            .cloned()
+    }
+
+    /// Looks up the plan of a tenant by id, used where only the id is known.
+    pub async fn tenant_plan(&self, tenant_id: u32) -> Option<Arc<TenantPlan>> {
+        if self.core.jmap.plans.is_empty() {
+            return None;
+        }
+
+        match self
+            .store()
+            .query(QueryParams::id(tenant_id).with_return_member_of(false))
+            .await
+        {
+            Ok(tenant) => tenant.and_then(|tenant| self.principal_plan(&tenant)),
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to obtain tenant plan")
+                        .ctx(trc::Key::TenantId, tenant_id)
+                        .caused_by(trc::location!())
+                );
+                None
+            }
+        }
+    }
+
+    pub fn tenant_limits(&self, tenant: &Principal) -> TenantLimits {
+        let plan = self.principal_plan(tenant);
+        let plan = plan.as_deref();
+        TenantLimits {
+            quota: tenant.quota().or(plan.and_then(|plan| plan.quota)),
+            max_users: tenant
+                .directory_quota(&Type::Individual)
+                .filter(|quota| *quota > 0)
+                .or(plan.and_then(|plan| plan.max_users)),
+            max_domains: tenant
+                .directory_quota(&Type::Domain)
+                .filter(|quota| *quota > 0)
+                .or(plan.and_then(|plan| plan.max_domains)),
+        }
+    }
+
+    /// Enforces the principal limits of a tenant's plan. Limits set on the
+    /// tenant itself are enforced by the directory when the principal is
+    /// created.
+    pub async fn assert_plan_limit(&self, tenant_id: u32, typ: Type) -> trc::Result<()> {
+        if !matches!(typ, Type::Individual | Type::Domain) || self.core.jmap.plans.is_empty() {
+            return Ok(());
+        }
+        let Some(tenant) = self
+            .store()
+            .query(QueryParams::id(tenant_id).with_return_member_of(false))
+            .await
+            .caused_by(trc::location!())?
+        else {
+            return Ok(());
+        };
+        if tenant.directory_quota(&typ).is_some_and(|quota| quota > 0) {
+            return Ok(());
+        }
+        let limits = self.tenant_limits(&tenant);
+        let Some(limit) = (if typ == Type::Individual {
+            limits.max_users
+        } else {
+            limits.max_domains
+        }) else {
+            return Ok(());
+        };
+
+        let total = self
+            .store()
+            .count_tenant_principals(tenant_id, typ)
+            .await
+            .caused_by(trc::location!())? as u32;
+        if total >= limit {
+            Err(trc::LimitEvent::TenantQuota
+                .into_err()
+                .details("Tenant principal quota exceeded")
+                .ctx(trc::Key::Details, typ.description())
+                .ctx(trc::Key::Limit, limit)
+                .ctx(trc::Key::Total, total))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the limits a tenant exceeds, which happens when it is moved
+    /// to a smaller plan. Existing data is kept, only new principals and
+    /// messages are refused.
+    pub async fn tenant_exceeded_limits(
+        &self,
+        tenant: &Principal,
+    ) -> trc::Result<Vec<&'static str>> {
+        let limits = self.tenant_limits(tenant);
+        let mut exceeded = Vec::new();
+
+        for (name, typ, limit) in [
+            ("users", Type::Individual, limits.max_users),
+            ("domains", Type::Domain, limits.max_domains),
+        ] {
+            if let Some(limit) = limit
+                && self
+                    .store()
+                    .count_tenant_principals(tenant.id(), typ)
+                    .await
+                    .caused_by(trc::location!())?
+                    > limit as u64
+            {
+                exceeded.push(name);
+            }
+        }
+        if let Some(quota) = limits.quota
+            && self.get_used_quota(tenant.id()).await?.max(0) as u64 > quota
+        {
+            exceeded.push("quota");
+        }
+
+        Ok(exceeded)
+    }
+}
@@ -12,7 +12,24 @@ use utils::config::Config;
 use crate::{
     Core, Server,
     config::{
+        activation::{TENANT_ACTIVATION_KEY, TenantActivation},
+        groupware::{BookableResource, RESOURCE_KEY},
+        hostname::{TENANT_HOSTNAME_KEY, TenantHostname},
+        login_page::{LOGIN_PAGE_KEY, LoginPage},
+        maintenance::{TENANT_MAINTENANCE_KEY, TenantMaintenance},
+        overrides::{TENANT_OVERRIDES_KEY, TenantOverrides},
+        password_policy::{TENANT_PASSWORD_POLICY_KEY, TenantPasswordPolicy},
+        scripts::{FORWARDING_KEY, ForwardingRule, TENANT_SIEVE_KEY, TenantSieveScript},
         server::{Listeners, tls::parse_certificates},
+        smtp::{
+            auth::{TENANT_ARC_KEY, TENANT_DKIM_KEY, TenantDkimPolicy, parse_tenant_arc_sealers},
+            bimi::{DOMAIN_BIMI_KEY, DomainBimi},
+            disclaimer::{DISCLAIMER_KEY, DomainDisclaimer, TenantDisclaimer},
+            journal::{TENANT_JOURNAL_KEY, TenantJournaling},
+            queue::{DOMAIN_ROUTES_KEY, DomainRoute, TENANT_ROUTING_KEY, TenantRouting},
+        },
+        spamfilter::{TENANT_SENDERS_KEY, TENANT_SPAM_KEY, TenantSenderLists, TenantSpamSettings},
+        storage::{TENANT_BLOB_KEY, parse_tenant_blob_stores, parse_tenant_encryption},
         telemetry::Telemetry,
     },
     listener::blocked::{BLOCKED_IP_KEY, BlockedIps},
@@ -39,6 +56,297 @@ impl Server {
         Ok(config.into())
     }
 
+    pub async fn reload_tenant_spam_settings(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_SPAM_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_spam_settings
+            .store(TenantSpamSettings::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_sender_lists(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_SENDERS_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_sender_lists
+            .store(TenantSenderLists::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_sieve_scripts(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_SIEVE_KEY)
+            .await?;
+        self.inner.data.tenant_sieve_scripts.store(
+            TenantSieveScript::parse_all(&mut config, &self.core.sieve.untrusted_compiler).into(),
+        );
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_forwarding_rules(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(FORWARDING_KEY)
+            .await?;
+        self.inner
+            .data
+            .forwarding_rules
+            .store(ForwardingRule::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_bookable_resources(&self) -> trc::Result<ReloadResult> {
+        let mut config = self.core.storage.config.build_config(RESOURCE_KEY).await?;
+        self.inner
+            .data
+            .bookable_resources
+            .store(BookableResource::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_routing(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_ROUTING_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_routing
+            .store(TenantRouting::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_overrides(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_OVERRIDES_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_overrides
+            .store(TenantOverrides::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_maintenance(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_MAINTENANCE_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_maintenance
+            .store(TenantMaintenance::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_activation(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_ACTIVATION_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_activation
+            .store(TenantActivation::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_disclaimers(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(DISCLAIMER_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_disclaimers
+            .store(TenantDisclaimer::parse_all(&mut config).into());
+        self.inner
+            .data
+            .domain_disclaimers
+            .store(DomainDisclaimer::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_login_pages(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(LOGIN_PAGE_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_login_pages
+            .store(LoginPage::parse_all_tenants(&mut config).into());
+        self.inner
+            .data
+            .domain_login_pages
+            .store(LoginPage::parse_all_domains(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_hostnames(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_HOSTNAME_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_hostnames
+            .store(TenantHostname::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_password_policies(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_PASSWORD_POLICY_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_password_policies
+            .store(TenantPasswordPolicy::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_journaling(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_JOURNAL_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_journaling
+            .store(TenantJournaling::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_domain_bimi(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(DOMAIN_BIMI_KEY)
+            .await?;
+        self.inner
+            .data
+            .domain_bimi
+            .store(DomainBimi::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_domain_routes(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(DOMAIN_ROUTES_KEY)
+            .await?;
+        self.inner
+            .data
+            .domain_routes
+            .store(DomainRoute::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_dkim_policies(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_DKIM_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_dkim_policies
+            .store(TenantDkimPolicy::parse_all(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_arc_sealers(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_ARC_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_arc_sealers
+            .store(parse_tenant_arc_sealers(&mut config).into());
+
+        Ok(config.into())
+    }
+
+    pub async fn reload_tenant_blob_stores(&self) -> trc::Result<ReloadResult> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(TENANT_BLOB_KEY)
+            .await?;
+        self.inner
+            .data
+            .tenant_blob_stores
+            .store(parse_tenant_blob_stores(&mut config).into());
+        self.inner
+            .data
+            .tenant_encryption
+            .store(parse_tenant_encryption(&mut config).into());
+
+        Ok(config.into())
+    }
+
     pub async fn reload_certificates(&self) -> trc::Result<ReloadResult> {
         let mut config = self.core.storage.config.build_config("certificate").await?;
         let mut certificates = self.inner.data.tls_certificates.load().as_ref().clone();
@@ -124,6 +432,107 @@ impl Server {
         // Update blocked IPs
         *self.inner.data.blocked_ips.write() = BlockedIps::parse(&mut config).blocked_ip_addresses;
 
+        // Update tenant spam filter settings
+        self.inner
+            .data
+            .tenant_spam_settings
+            .store(TenantSpamSettings::parse_all(&mut config).into());
+        self.inner
+            .data
+            .tenant_sender_lists
+            .store(TenantSenderLists::parse_all(&mut config).into());
+
+        // Update tenant Sieve scripts
+        self.inner.data.tenant_sieve_scripts.store(
+            TenantSieveScript::parse_all(&mut config, &core.sieve.untrusted_compiler).into(),
+        );
+        self.inner
+            .data
+            .forwarding_rules
+            .store(ForwardingRule::parse_all(&mut config).into());
+
+        // Update bookable resources
+        self.inner
+            .data
+            .bookable_resources
+            .store(BookableResource::parse_all(&mut config).into());
+
+        // Update tenant outbound routing
+        self.inner
+            .data
+            .tenant_routing
+            .store(TenantRouting::parse_all(&mut config).into());
+
+        // Update domain inbound routing
+        self.inner
+            .data
+            .domain_routes
+            .store(DomainRoute::parse_all(&mut config).into());
+
+        // Update tenant DKIM signing policies
+        self.inner
+            .data
+            .tenant_dkim_policies
+            .store(TenantDkimPolicy::parse_all(&mut config).into());
+        self.inner
+            .data
+            .tenant_arc_sealers
+            .store(parse_tenant_arc_sealers(&mut config).into());
+
+        // Update tenant and domain disclaimers
+        self.inner
+            .data
+            .tenant_disclaimers
+            .store(TenantDisclaimer::parse_all(&mut config).into());
+        self.inner
+            .data
+            .domain_disclaimers
+            .store(DomainDisclaimer::parse_all(&mut config).into());
+
+        // Update tenant and domain login pages
+        self.inner
+            .data
+            .tenant_login_pages
+            .store(LoginPage::parse_all_tenants(&mut config).into());
+        self.inner
+            .data
+            .domain_login_pages
+            .store(LoginPage::parse_all_domains(&mut config).into());
+
+        // Update tenant hostnames
+        self.inner
+            .data
+            .tenant_hostnames
+            .store(TenantHostname::parse_all(&mut config).into());
+
+        // Update tenant password policies
+        self.inner
+            .data
+            .tenant_password_policies
+            .store(TenantPasswordPolicy::parse_all(&mut config).into());
+
+        // Update tenant journaling
+        self.inner
+            .data
+            .tenant_journaling
+            .store(TenantJournaling::parse_all(&mut config).into());
+
+        // Update domain BIMI settings
+        self.inner
+            .data
+            .domain_bimi
+            .store(DomainBimi::parse_all(&mut config).into());
+
+        // Update tenant blob stores and encryption settings
+        self.inner
+            .data
+            .tenant_blob_stores
+            .store(parse_tenant_blob_stores(&mut config).into());
+        self.inner
+            .data
+            .tenant_encryption
+            .store(parse_tenant_encryption(&mut config).into());
+
         // Parser servers
         let mut servers = Listeners::parse(&mut config);
         servers.parse_tcp_acceptors(&mut config, self.inner.clone());
@@ -0,0 +1,114 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    auth::AccessToken,
+    config::groupware::{BookableResource, RESOURCE_KEY},
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn bookable_resource(&self, account_id: u32) -> Option<Arc<BookableResource>> {
+        self.inner
+            .data
+            .bookable_resources
+            .load()
+            .get(&account_id)
+            .cloned()
+    }
+
+    /// Returns whether an account is a bookable resource of the tenant of the
+    /// access token, whose free/busy time is visible to all its members.
+    pub fn is_tenant_resource(&self, access_token: &AccessToken, account_id: u32) -> bool {
+        access_token.tenant.is_some_and(|tenant| {
+            self.bookable_resource(account_id)
+                .is_some_and(|resource| resource.tenant_id == tenant.id)
+        })
+    }
+
+    /// Returns the bookable resources of a tenant, sorted by account id.
+    pub fn tenant_bookable_resources(&self, tenant_id: u32) -> Vec<(u32, Arc<BookableResource>)> {
+        let mut resources = self
+            .inner
+            .data
+            .bookable_resources
+            .load()
+            .iter()
+            .filter(|(_, resource)| resource.tenant_id == tenant_id)
+            .map(|(account_id, resource)| (*account_id, resource.clone()))
+            .collect::<Vec<_>>();
+        resources.sort_unstable_by_key(|(account_id, _)| *account_id);
+        resources
+    }
+
+    /// Registers an account as a bookable resource or replaces its settings,
+    /// or removes the registration when no resource is provided.
+    pub async fn update_bookable_resource(
+        &self,
+        account_id: u32,
+        resource: Option<BookableResource>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{RESOURCE_KEY}.{account_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(resource) = &resource {
+            config
+                .set(resource.config_keys(account_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut resources = self.inner.data.bookable_resources.load().as_ref().clone();
+        if let Some(resource) = resource {
+            resources.insert(account_id, Arc::new(resource));
+        } else {
+            resources.remove(&account_id);
+        }
+        self.inner.data.bookable_resources.store(resources.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadBookableResources)
+            .await;
+
+        Ok(())
+    }
+
+    /// Removes a deleted account from the bookable resources, either as a
+    /// resource or as the approver of one. Bookings of resources left
+    /// without an approver stay pending until an administrator answers them.
+    pub async fn remove_bookable_resource_account(&self, account_id: u32) -> trc::Result<()> {
+        let resources = self.inner.data.bookable_resources.load_full();
+
+        if resources.contains_key(&account_id) {
+            self.update_bookable_resource(account_id, None)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        for (resource_id, resource) in resources.iter() {
+            if resource.approver_id == Some(account_id) && *resource_id != account_id {
+                self.update_bookable_resource(
+                    *resource_id,
+                    BookableResource {
+                        approver_id: None,
+                        ..resource.as_ref().clone()
+                    }
+                    .into(),
+                )
+                .await
+                .caused_by(trc::location!())?;
+            }
+        }
+
+        Ok(())
+    }
+}
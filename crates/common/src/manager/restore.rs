@@ -13,10 +13,11 @@ use std::{
     path::{Path, PathBuf},
 };
 use store::{
-    BlobStore, SUBSPACE_BLOBS, SUBSPACE_COUNTER, SUBSPACE_INDEXES, SUBSPACE_QUOTA, Store, U32_LEN,
-    write::{AnyClass, BatchBuilder, ValueClass, key::DeserializeBigEndian},
+    BlobStore, Deserialize, SUBSPACE_BLOB_LINK, SUBSPACE_BLOBS, SUBSPACE_COUNTER, SUBSPACE_INDEXES,
+    SUBSPACE_QUOTA, Store, U32_LEN,
+    write::{AnyClass, BatchBuilder, ValueClass, blob::BlobLocation, key::DeserializeBigEndian},
 };
-use types::{collection::Collection, field::Field};
+use types::{blob_hash::BLOB_HASH_LEN, collection::Collection, field::Field};
 use utils::{UnwrapFailure, failed};
 
 impl Core {
@@ -116,7 +117,18 @@ async fn restore_file(store: Store, blob_store: BlobStore, path: &Path) {
             }
         }
         _ => {
-            while let Some((key, value)) = reader.next() {
+            while let Some((key, mut value)) = reader.next() {
+                // Blobs are restored to the default blob store, encrypted blobs
+                // are restored as-is and keep their data key
+                if reader.subspace == SUBSPACE_BLOB_LINK && key.len() == BLOB_HASH_LEN {
+                    value = BlobLocation {
+                        store_id: String::new(),
+                        encryption_key: BlobLocation::deserialize(&value)
+                            .failed("Failed to deserialize blob location")
+                            .encryption_key,
+                    }
+                    .serialize();
+                }
                 batch.set(
                     ValueClass::Any(AnyClass {
                         subspace: reader.subspace,
@@ -0,0 +1,44 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::jmap::retention::{TENANT_RETENTION_KEY, TenantRetention},
+};
+
+impl Server {
+    /// Returns the message retention policies of a tenant, if any.
+    pub async fn tenant_retention(&self, tenant_id: u32) -> trc::Result<Option<TenantRetention>> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(&format!("{TENANT_RETENTION_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(TenantRetention::parse(&mut config, tenant_id))
+    }
+
+    /// Replaces the message retention policies of a tenant.
+    pub async fn update_tenant_retention(
+        &self,
+        tenant_id: u32,
+        retention: TenantRetention,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_RETENTION_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(retention.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())
+    }
+}
@@ -0,0 +1,105 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::smtp::queue::{
+        DOMAIN_ROUTES_KEY, DomainRoute, DomainRoutes, TENANT_ROUTING_KEY, TenantRoutes,
+        TenantRouting,
+    },
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_routing(&self, tenant_id: u32) -> Option<Arc<TenantRoutes>> {
+        self.inner
+            .data
+            .tenant_routing
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the outbound routing of a tenant, removing it when no
+    /// relay is configured.
+    pub async fn update_tenant_routing(
+        &self,
+        tenant_id: u32,
+        routing: TenantRouting,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_ROUTING_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(routing.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())?;
+
+        let mut tenants = self.inner.data.tenant_routing.load().as_ref().clone();
+        if !routing.is_empty() {
+            tenants.insert(tenant_id, Arc::new(routing.into()));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_routing.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantRouting)
+            .await;
+
+        Ok(())
+    }
+
+    pub fn domain_routes(&self, domain: &str) -> Option<Arc<DomainRoutes>> {
+        self.inner.data.domain_routes.load().get(domain).cloned()
+    }
+
+    /// Replaces the inbound routing rules of a domain, the compiled rules
+    /// are swapped in right away.
+    pub async fn update_domain_routes(
+        &self,
+        domain: &str,
+        routes: Vec<DomainRoute>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        if let Some(current) = self.domain_routes(domain) {
+            for route in &current.rules {
+                config
+                    .clear_prefix(format!("{DOMAIN_ROUTES_KEY}.{}.", route.id))
+                    .await
+                    .caused_by(trc::location!())?;
+            }
+        }
+        config
+            .set(
+                routes
+                    .iter()
+                    .flat_map(|route| route.config_keys(domain))
+                    .collect::<Vec<_>>(),
+                true,
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        let mut domains = self.inner.data.domain_routes.load().as_ref().clone();
+        if !routes.is_empty() {
+            domains.insert(domain.to_string(), Arc::new(DomainRoutes::new(routes)));
+        } else {
+            domains.remove(domain);
+        }
+        self.inner.data.domain_routes.store(domains.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadDomainRoutes)
+            .await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use trc::AddContext;
+
+use crate::{
+    Server,
+    config::jmap::shared::{SharedMailbox, TENANT_SHARED_MAILBOX_KEY},
+};
+
+impl Server {
+    /// Returns the shared mailboxes of a tenant.
+    pub async fn tenant_shared_mailboxes(&self, tenant_id: u32) -> trc::Result<Vec<SharedMailbox>> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(&format!("{TENANT_SHARED_MAILBOX_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(SharedMailbox::parse_all(&mut config, tenant_id))
+    }
+
+    /// Returns the shared mailbox of a tenant with the given account id, if any.
+    pub async fn shared_mailbox(
+        &self,
+        tenant_id: u32,
+        account_id: u32,
+    ) -> trc::Result<Option<SharedMailbox>> {
+        self.tenant_shared_mailboxes(tenant_id)
+            .await
+            .map(|mailboxes| {
+                mailboxes
+                    .into_iter()
+                    .find(|mailbox| mailbox.account_id == account_id)
+            })
+    }
+
+    /// Registers a shared mailbox or replaces its access list.
+    pub async fn update_shared_mailbox(
+        &self,
+        tenant_id: u32,
+        mailbox: &SharedMailbox,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!(
+                "{TENANT_SHARED_MAILBOX_KEY}.{tenant_id}.{}.",
+                mailbox.account_id
+            ))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(mailbox.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())
+    }
+
+    /// Removes a deleted account from the shared mailboxes of every tenant,
+    /// either as a shared mailbox or as a member of an access list.
+    pub async fn remove_shared_mailbox_account(&self, account_id: u32) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        let account_id = account_id.to_string();
+        for key in config
+            .list(&format!("{TENANT_SHARED_MAILBOX_KEY}."), true)
+            .await
+            .caused_by(trc::location!())?
+            .into_keys()
+        {
+            let mut parts = key.split('.');
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(tenant_id), Some(mailbox_id), Some("enable"), None)
+                    if mailbox_id == account_id =>
+                {
+                    config
+                        .clear_prefix(format!(
+                            "{TENANT_SHARED_MAILBOX_KEY}.{tenant_id}.{mailbox_id}."
+                        ))
+                        .await
+                        .caused_by(trc::location!())?;
+                }
+                (Some(_), Some(_), Some("access"), Some(member_id)) if member_id == account_id => {
+                    config
+                        .clear(format!("{TENANT_SHARED_MAILBOX_KEY}.{key}"))
+                        .await
+                        .caused_by(trc::location!())?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
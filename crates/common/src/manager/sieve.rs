@@ -0,0 +1,255 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use store::write::now;
+use trc::AddContext;
+
+use crate::{
+    Server,
+    auth::AccessToken,
+    config::scripts::{
+        FORWARDING_KEY, ForwardingPolicy, ForwardingRule, TENANT_FORWARDING_KEY, TENANT_SIEVE_KEY,
+        TENANT_VACATION_KEY, TenantSieveScript, VacationTemplate,
+    },
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_sieve_scripts(&self, tenant_id: u32) -> Vec<Arc<TenantSieveScript>> {
+        self.inner
+            .data
+            .tenant_sieve_scripts
+            .load()
+            .get(&tenant_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the scripts of the account's tenant that have been assigned
+    /// to the account, either directly or through one of its groups.
+    pub fn assigned_sieve_scripts(
+        &self,
+        access_token: &AccessToken,
+    ) -> Vec<Arc<TenantSieveScript>> {
+        let Some(tenant) = &access_token.tenant else {
+            return Vec::new();
+        };
+
+        self.inner
+            .data
+            .tenant_sieve_scripts
+            .load()
+            .get(&tenant.id)
+            .map(|scripts| {
+                scripts
+                    .iter()
+                    .filter(|script| script.assignment.matches(access_token))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Creates or replaces a script of the tenant's library, or removes it
+    /// when no script is provided.
+    pub async fn update_tenant_sieve_script(
+        &self,
+        tenant_id: u32,
+        name: &str,
+        script: Option<TenantSieveScript>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_SIEVE_KEY}.{tenant_id}.{name}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(script) = &script {
+            config
+                .set(script.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut tenants = self.inner.data.tenant_sieve_scripts.load().as_ref().clone();
+        let scripts = tenants.entry(tenant_id).or_default();
+        scripts.retain(|script| script.name != name);
+        if let Some(script) = script {
+            scripts.push(Arc::new(script));
+            scripts.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        } else if scripts.is_empty() {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_sieve_scripts.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantSieveScripts)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the vacation template of a tenant. It is read from the
+    /// settings store each time so that auto-replies always reflect the
+    /// latest version.
+    pub async fn tenant_vacation_template(
+        &self,
+        tenant_id: u32,
+    ) -> trc::Result<Option<VacationTemplate>> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(&format!("{TENANT_VACATION_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(VacationTemplate::parse(&mut config, tenant_id))
+    }
+
+    /// Replaces the vacation template of a tenant, or removes it when no
+    /// template is provided.
+    pub async fn update_tenant_vacation_template(
+        &self,
+        tenant_id: u32,
+        template: Option<VacationTemplate>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_VACATION_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(template) = template {
+            config
+                .set(template.config_keys(tenant_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn forwarding_rule(&self, account_id: u32) -> Option<Arc<ForwardingRule>> {
+        self.inner
+            .data
+            .forwarding_rules
+            .load()
+            .get(&account_id)
+            .cloned()
+    }
+
+    /// Replaces the forwarding rule of an account, or removes it when no
+    /// rule is provided.
+    pub async fn update_forwarding_rule(
+        &self,
+        account_id: u32,
+        rule: Option<ForwardingRule>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{FORWARDING_KEY}.{account_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(rule) = &rule {
+            config
+                .set(rule.config_keys(account_id), true)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let mut rules = self.inner.data.forwarding_rules.load().as_ref().clone();
+        if let Some(rule) = rule {
+            rules.insert(account_id, Arc::new(rule));
+        } else {
+            rules.remove(&account_id);
+        }
+        self.inner.data.forwarding_rules.store(rules.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadForwardingRules)
+            .await;
+
+        Ok(())
+    }
+
+    /// Removes the forwarding rules that are past their expiry date.
+    pub async fn purge_expired_forwarding_rules(&self) -> trc::Result<()> {
+        let now = now();
+        let expired = self
+            .inner
+            .data
+            .forwarding_rules
+            .load()
+            .iter()
+            .filter(|(_, rule)| rule.is_expired(now))
+            .map(|(account_id, _)| *account_id)
+            .collect::<Vec<_>>();
+
+        for account_id in expired {
+            self.update_forwarding_rule(account_id, None)
+                .await
+                .caused_by(trc::location!())?;
+
+            trc::event!(
+                Directory(trc::DirectoryEvent::ForwardingRemoved),
+                Id = self
+                    .store()
+                    .get_principal_name(account_id)
+                    .await
+                    .caused_by(trc::location!())?
+                    .unwrap_or_else(|| account_id.to_string()),
+                Reason = "Forwarding rule expired",
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the forwarding policy of a tenant. It is read from the
+    /// settings store so that changes apply to the next delivery.
+    pub async fn tenant_forwarding_policy(&self, tenant_id: u32) -> trc::Result<ForwardingPolicy> {
+        let mut config = self
+            .core
+            .storage
+            .config
+            .build_config(&format!("{TENANT_FORWARDING_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(ForwardingPolicy::parse(&mut config, tenant_id))
+    }
+
+    pub async fn update_tenant_forwarding_policy(
+        &self,
+        tenant_id: u32,
+        policy: &ForwardingPolicy,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_FORWARDING_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(policy.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())
+    }
+
+    /// Returns whether an address belongs to a domain outside the tenant.
+    pub async fn is_external_address(&self, tenant_id: u32, address: &str) -> trc::Result<bool> {
+        let domain = address
+            .rsplit_once('@')
+            .map_or(address, |(_, domain)| domain);
+
+        self.store()
+            .get_principal_info(domain)
+            .await
+            .caused_by(trc::location!())
+            .map(|info| {
+                !info.is_some_and(|info| info.typ == Type::Domain && info.tenant == Some(tenant_id))
+            })
+    }
+}
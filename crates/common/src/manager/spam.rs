@@ -0,0 +1,180 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use trc::AddContext;
+
+use crate::{
+    KV_GREYLIST_TENANT, Server,
+    config::spamfilter::{
+        SenderList, SenderListEntry, SenderListType, TENANT_SENDERS_KEY, TENANT_SPAM_KEY,
+        TenantSenderLists, TenantSpamSettings,
+    },
+    ipc::BroadcastEvent,
+};
+
+impl Server {
+    pub fn tenant_spam_settings(&self, tenant_id: u32) -> Option<Arc<TenantSpamSettings>> {
+        self.inner
+            .data
+            .tenant_spam_settings
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the spam filter settings of a tenant, removing them when
+    /// no setting is overridden.
+    pub async fn update_tenant_spam_settings(
+        &self,
+        tenant_id: u32,
+        settings: TenantSpamSettings,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!("{TENANT_SPAM_KEY}.{tenant_id}."))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(settings.config_keys(tenant_id), true)
+            .await
+            .caused_by(trc::location!())?;
+
+        // Toggling greylisting starts over with the trackers of this tenant only
+        let mut tenants = self.inner.data.tenant_spam_settings.load().as_ref().clone();
+        if tenants.get(&tenant_id).and_then(|current| current.greylist) != settings.greylist
+            && let Err(err) = self
+                .in_memory_store()
+                .key_delete_prefix(&greylist_tenant_prefix(tenant_id))
+                .await
+        {
+            trc::error!(
+                err.caused_by(trc::location!())
+                    .details("Failed to purge tenant greylist.")
+            );
+        }
+        if settings != TenantSpamSettings::default() {
+            tenants.insert(tenant_id, Arc::new(settings));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_spam_settings.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantSpamSettings)
+            .await;
+
+        Ok(())
+    }
+
+    pub fn tenant_sender_lists(&self, tenant_id: u32) -> Option<Arc<TenantSenderLists>> {
+        self.inner
+            .data
+            .tenant_sender_lists
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Replaces the entries of a sender list of a tenant. The matcher of the
+    /// tenant is rebuilt and expired entries are discarded.
+    pub async fn update_tenant_sender_list(
+        &self,
+        tenant_id: u32,
+        list_type: SenderListType,
+        mut entries: Vec<SenderListEntry>,
+    ) -> trc::Result<()> {
+        let now = store::write::now();
+        entries.retain(|entry| !entry.is_expired(now));
+
+        let config = &self.core.storage.config;
+        config
+            .clear_prefix(format!(
+                "{TENANT_SENDERS_KEY}.{tenant_id}.{}.",
+                list_type.as_str()
+            ))
+            .await
+            .caused_by(trc::location!())?;
+        config
+            .set(
+                entries
+                    .iter()
+                    .flat_map(|entry| entry.config_keys(tenant_id, list_type))
+                    .collect::<Vec<_>>(),
+                true,
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        let mut tenants = self.inner.data.tenant_sender_lists.load().as_ref().clone();
+        let mut lists = TenantSenderLists::default();
+        if let Some(current) = tenants.get(&tenant_id) {
+            for other in [SenderListType::Allow, SenderListType::Block] {
+                if other != list_type {
+                    *lists.list_mut(other) = SenderList::new(current.list(other).entries.clone());
+                }
+            }
+        }
+        *lists.list_mut(list_type) = SenderList::new(entries);
+        if !lists.is_empty() {
+            tenants.insert(tenant_id, Arc::new(lists));
+        } else {
+            tenants.remove(&tenant_id);
+        }
+        self.inner.data.tenant_sender_lists.store(tenants.into());
+
+        self.cluster_broadcast(BroadcastEvent::ReloadTenantSenderLists)
+            .await;
+
+        Ok(())
+    }
+
+    /// Returns the tenant that all the recipients belong to. Messages
+    /// addressed to several tenants or to remote domains are classified
+    /// with the global settings.
+    pub async fn recipients_tenant(&self, recipients: &[&str]) -> Option<u32> {
+        if self.inner.data.tenant_spam_settings.load().is_empty()
+            && self.inner.data.tenant_sender_lists.load().is_empty()
+        {
+            return None;
+        }
+
+        let mut tenant_id = None;
+        let mut domains: Vec<&str> = Vec::with_capacity(1);
+        for recipient in recipients {
+            let (_, domain) = recipient.rsplit_once('@')?;
+            if domains.contains(&domain) {
+                continue;
+            }
+            domains.push(domain);
+
+            let info = match self.store().get_principal_info(domain).await {
+                Ok(Some(info)) if info.typ == Type::Domain => info,
+                Ok(_) => return None,
+                Err(err) => {
+                    trc::error!(err.details("Failed to resolve recipient tenant"));
+                    return None;
+                }
+            };
+            if tenant_id.is_some_and(|tenant_id| Some(tenant_id) != info.tenant) {
+                return None;
+            }
+            tenant_id = Some(info.tenant?);
+        }
+
+        tenant_id
+    }
+}
+
+/// Prefix of the greylist trackers of a tenant, kept apart from the global
+/// ones so that they can be purged without affecting other tenants.
+pub fn greylist_tenant_prefix(tenant_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_GREYLIST_TENANT);
+    key.extend_from_slice(&tenant_id.to_be_bytes());
+    key
+}
@@ -0,0 +1,1284 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use ahash::{AHashMap, AHashSet};
+use directory::backend::internal::manage;
+use mail_auth::{Error, common::resolver::IntoFqdn, hickory_resolver::Name};
+use store::write::now;
+use trc::AddContext;
+use utils::config::ConfigKey;
+
+use crate::{Server, dns::DnsCheckType};
+
+pub const SPF_MONITOR_KEY: &str = "spf.monitor";
+
+/// Maximum number of DNS lookups receivers perform while evaluating a
+/// record (RFC 7208, section 4.6.4).
+pub const SPF_LOOKUP_LIMIT: usize = 10;
+
+/// Includes nested deeper or beyond this number of records are not fetched.
+const MAX_DEPTH: usize = 10;
+const MAX_RECORDS: usize = 50;
+
+/// Longest string of a TXT record, and the size above which a record may
+/// no longer fit in a UDP response.
+const MAX_TXT_STRING: usize = 255;
+const MAX_RECORD_SIZE: usize = 450;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpfQualifier {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SpfTerm {
+    All(SpfQualifier),
+    Include(SpfQualifier, String),
+    A {
+        qualifier: SpfQualifier,
+        domain: Option<String>,
+        cidr4: u8,
+        cidr6: u8,
+    },
+    Mx {
+        qualifier: SpfQualifier,
+        domain: Option<String>,
+        cidr4: u8,
+        cidr6: u8,
+    },
+    Ptr(SpfQualifier, Option<String>),
+    Ip4(SpfQualifier, Ipv4Addr, u8),
+    Ip6(SpfQualifier, Ipv6Addr, u8),
+    Exists(SpfQualifier, String),
+    Redirect(String),
+    Exp(String),
+    Modifier(String, String),
+}
+
+/// Terms of an SPF record, invalid terms are reported and left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpfRecord {
+    pub terms: Vec<SpfTerm>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpfAnalysis {
+    pub domain: String,
+    pub record: Option<String>,
+    pub lookups: usize,
+    pub lookup_limit: usize,
+    pub over_limit: bool,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub includes: Vec<SpfInclude>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_record: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+}
+
+/// A record reached through an include or redirect.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpfInclude {
+    pub domain: String,
+    pub depth: usize,
+    pub record: Option<String>,
+    /// Lookups performed by the terms of this record alone.
+    pub lookups: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpfFlattened {
+    pub domain: String,
+    /// The record before flattening.
+    pub source: String,
+    pub record: String,
+    /// The record split in strings that fit in a TXT record.
+    pub strings: Vec<String>,
+    pub lookups: usize,
+    pub networks: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// A flattened record whose includes are resolved again periodically, so
+/// that administrators learn when their providers add or remove networks.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpfMonitor {
+    /// The record before flattening.
+    pub source: String,
+    pub networks: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_at: Option<u64>,
+}
+
+/// SPF records reachable from a domain through includes and redirects.
+#[derive(Debug, Default)]
+struct SpfTree {
+    records: AHashMap<String, Result<(String, SpfRecord), String>>,
+    order: Vec<(String, usize)>,
+}
+
+impl SpfQualifier {
+    fn as_prefix(&self) -> &'static str {
+        match self {
+            SpfQualifier::Pass => "",
+            SpfQualifier::Fail => "-",
+            SpfQualifier::SoftFail => "~",
+            SpfQualifier::Neutral => "?",
+        }
+    }
+}
+
+impl SpfTerm {
+    /// Whether receivers perform a DNS lookup to evaluate this term.
+    pub fn is_lookup(&self) -> bool {
+        matches!(
+            self,
+            SpfTerm::Include(..)
+                | SpfTerm::A { .. }
+                | SpfTerm::Mx { .. }
+                | SpfTerm::Ptr(..)
+                | SpfTerm::Exists(..)
+                | SpfTerm::Redirect(_)
+        )
+    }
+
+    pub fn is_mechanism(&self) -> bool {
+        !matches!(
+            self,
+            SpfTerm::Redirect(_) | SpfTerm::Exp(_) | SpfTerm::Modifier(..)
+        )
+    }
+
+    fn ip4(addr: Ipv4Addr, len: u8) -> Self {
+        let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+        SpfTerm::Ip4(
+            SpfQualifier::Pass,
+            Ipv4Addr::from(u32::from(addr) & mask),
+            len,
+        )
+    }
+
+    fn ip6(addr: Ipv6Addr, len: u8) -> Self {
+        let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+        SpfTerm::Ip6(
+            SpfQualifier::Pass,
+            Ipv6Addr::from(u128::from(addr) & mask),
+            len,
+        )
+    }
+
+    fn with_qualifier(&self, qualifier: SpfQualifier) -> Self {
+        match self {
+            SpfTerm::Ip4(_, addr, len) => SpfTerm::Ip4(qualifier, *addr, *len),
+            SpfTerm::Ip6(_, addr, len) => SpfTerm::Ip6(qualifier, *addr, *len),
+            term => term.clone(),
+        }
+    }
+}
+
+impl SpfRecord {
+    /// Parses a record, returns `None` if it is not an SPF record.
+    pub fn parse(record: &str) -> Option<Self> {
+        let mut tokens = record.split_ascii_whitespace();
+        if !tokens
+            .next()
+            .is_some_and(|version| version.eq_ignore_ascii_case("v=spf1"))
+        {
+            return None;
+        }
+
+        let mut spf = SpfRecord::default();
+        for token in tokens {
+            match parse_term(token) {
+                Ok(term @ (SpfTerm::Redirect(_) | SpfTerm::Exp(_)))
+                    if spf
+                        .terms
+                        .iter()
+                        .any(|t| std::mem::discriminant(t) == std::mem::discriminant(&term)) =>
+                {
+                    spf.errors.push(format!("Duplicate modifier {token:?}"));
+                }
+                Ok(term) => spf.terms.push(term),
+                Err(err) => spf.errors.push(format!("{err} in term {token:?}")),
+            }
+        }
+
+        Some(spf)
+    }
+
+    pub fn all(&self) -> Option<SpfQualifier> {
+        self.terms.iter().find_map(|term| match term {
+            SpfTerm::All(qualifier) => Some(*qualifier),
+            _ => None,
+        })
+    }
+
+    /// The redirect modifier is ignored when the record has an all mechanism.
+    pub fn redirect(&self) -> Option<&str> {
+        if self.all().is_none() {
+            self.terms.iter().find_map(|term| match term {
+                SpfTerm::Redirect(domain) => Some(domain.as_str()),
+                _ => None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Lookups performed by the terms of this record, not counting those of
+    /// the records it includes.
+    pub fn lookups(&self) -> usize {
+        let has_all = self.all().is_some();
+        self.terms
+            .iter()
+            .filter(|term| term.is_lookup() && (!has_all || !matches!(term, SpfTerm::Redirect(_))))
+            .count()
+    }
+
+    /// Domains whose records are evaluated as part of this one.
+    pub fn targets(&self) -> impl Iterator<Item = &str> {
+        self.terms
+            .iter()
+            .filter_map(|term| match term {
+                SpfTerm::Include(_, domain) => Some(domain.as_str()),
+                _ => None,
+            })
+            .chain(self.redirect())
+    }
+
+    /// Returns a copy without invalid, duplicate, deprecated or unreachable
+    /// terms, ending with an all mechanism unless it redirects.
+    pub fn corrected(&self, warnings: &mut Vec<String>) -> SpfRecord {
+        let has_all = self.all().is_some();
+        let mut mechanisms: Vec<SpfTerm> = Vec::with_capacity(self.terms.len());
+        let mut modifiers: Vec<SpfTerm> = Vec::new();
+        let mut all = None;
+
+        for term in &self.terms {
+            match term {
+                SpfTerm::Ptr(..) => {
+                    warnings.push(format!("Removed {term}, the ptr mechanism is deprecated"));
+                }
+                SpfTerm::Redirect(_) if has_all => {
+                    warnings.push(format!(
+                        "Removed {term}, it is ignored when the record has an all mechanism"
+                    ));
+                }
+                term if mechanisms.contains(term) || modifiers.contains(term) => {
+                    warnings.push(format!("Removed duplicate term {term}"));
+                }
+                term if !term.is_mechanism() => {
+                    modifiers.push(term.clone());
+                }
+                _ if all.is_some() => {
+                    warnings.push(format!(
+                        "Removed {term}, terms after the all mechanism are never evaluated"
+                    ));
+                }
+                SpfTerm::All(_) => {
+                    all = Some(term.clone());
+                }
+                term => {
+                    mechanisms.push(term.clone());
+                }
+            }
+        }
+
+        if all.is_none() && self.redirect().is_none() {
+            warnings.push("Added ~all, the record did not end with an all mechanism".to_string());
+            all = Some(SpfTerm::All(SpfQualifier::SoftFail));
+        }
+        if all == Some(SpfTerm::All(SpfQualifier::Pass)) {
+            warnings.push("The record authorizes any host to send mail".to_string());
+        }
+
+        SpfRecord {
+            terms: mechanisms.into_iter().chain(all).chain(modifiers).collect(),
+            errors: vec![],
+        }
+    }
+
+    /// Splits the record in strings of at most 255 bytes at term boundaries,
+    /// receivers join them without a separator.
+    pub fn split(record: &str) -> Vec<String> {
+        let mut strings = Vec::new();
+        let mut current = String::new();
+        for (pos, term) in record.split(' ').enumerate() {
+            let term_len = term.len() + usize::from(pos > 0);
+            if !current.is_empty() && current.len() + term_len > MAX_TXT_STRING {
+                strings.push(std::mem::take(&mut current));
+            }
+            if pos > 0 {
+                current.push(' ');
+            }
+            current.push_str(term);
+        }
+        if !current.is_empty() {
+            strings.push(current);
+        }
+        strings
+    }
+}
+
+impl SpfTree {
+    fn record(&self, domain: &str) -> Option<&SpfRecord> {
+        match self.records.get(domain) {
+            Some(Ok((_, record))) => Some(record),
+            _ => None,
+        }
+    }
+
+    /// Counts the lookups needed to evaluate a record, including those of
+    /// the records it reaches, and reports include loops.
+    fn lookups(
+        &self,
+        domain: &str,
+        record: &SpfRecord,
+        stack: &mut Vec<String>,
+        errors: &mut Vec<String>,
+    ) -> usize {
+        let mut lookups = record.lookups();
+        stack.push(domain.to_string());
+        for target in record.targets() {
+            if stack.iter().any(|domain| domain == target) {
+                let error = format!("Include loop, {domain} includes {target}");
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            } else if let Some(included) = self.record(target) {
+                lookups += self.lookups(target, included, stack, errors);
+            }
+        }
+        stack.pop();
+        lookups
+    }
+}
+
+impl Display for SpfTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfTerm::All(qualifier) => write!(f, "{}all", qualifier.as_prefix()),
+            SpfTerm::Include(qualifier, domain) => {
+                write!(f, "{}include:{domain}", qualifier.as_prefix())
+            }
+            SpfTerm::A {
+                qualifier,
+                domain,
+                cidr4,
+                cidr6,
+            } => {
+                write!(f, "{}a", qualifier.as_prefix())?;
+                fmt_domain_cidr(f, domain, *cidr4, *cidr6)
+            }
+            SpfTerm::Mx {
+                qualifier,
+                domain,
+                cidr4,
+                cidr6,
+            } => {
+                write!(f, "{}mx", qualifier.as_prefix())?;
+                fmt_domain_cidr(f, domain, *cidr4, *cidr6)
+            }
+            SpfTerm::Ptr(qualifier, domain) => {
+                write!(f, "{}ptr", qualifier.as_prefix())?;
+                fmt_domain_cidr(f, domain, 32, 128)
+            }
+            SpfTerm::Ip4(qualifier, addr, 32) => write!(f, "{}ip4:{addr}", qualifier.as_prefix()),
+            SpfTerm::Ip4(qualifier, addr, len) => {
+                write!(f, "{}ip4:{addr}/{len}", qualifier.as_prefix())
+            }
+            SpfTerm::Ip6(qualifier, addr, 128) => write!(f, "{}ip6:{addr}", qualifier.as_prefix()),
+            SpfTerm::Ip6(qualifier, addr, len) => {
+                write!(f, "{}ip6:{addr}/{len}", qualifier.as_prefix())
+            }
+            SpfTerm::Exists(qualifier, domain) => {
+                write!(f, "{}exists:{domain}", qualifier.as_prefix())
+            }
+            SpfTerm::Redirect(domain) => write!(f, "redirect={domain}"),
+            SpfTerm::Exp(domain) => write!(f, "exp={domain}"),
+            SpfTerm::Modifier(name, value) => write!(f, "{name}={value}"),
+        }
+    }
+}
+
+impl Display for SpfRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("v=spf1")?;
+        for term in &self.terms {
+            write!(f, " {term}")?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt_domain_cidr(
+    f: &mut fmt::Formatter<'_>,
+    domain: &Option<String>,
+    cidr4: u8,
+    cidr6: u8,
+) -> fmt::Result {
+    if let Some(domain) = domain {
+        write!(f, ":{domain}")?;
+    }
+    if cidr4 != 32 {
+        write!(f, "/{cidr4}")?;
+    }
+    if cidr6 != 128 {
+        write!(f, "//{cidr6}")?;
+    }
+    Ok(())
+}
+
+fn parse_term(token: &str) -> Result<SpfTerm, &'static str> {
+    let (qualifier, term) = match token.as_bytes().first() {
+        Some(b'+') => (Some(SpfQualifier::Pass), &token[1..]),
+        Some(b'-') => (Some(SpfQualifier::Fail), &token[1..]),
+        Some(b'~') => (Some(SpfQualifier::SoftFail), &token[1..]),
+        Some(b'?') => (Some(SpfQualifier::Neutral), &token[1..]),
+        _ => (None, token),
+    };
+    let (name, rest) = term.split_at(term.find([':', '/', '=']).unwrap_or(term.len()));
+    let name = name.to_ascii_lowercase();
+
+    if let Some(value) = rest.strip_prefix('=') {
+        if qualifier.is_some() {
+            return Err("Qualifier not allowed");
+        } else if !name.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err("Invalid modifier name");
+        }
+        return match name.as_str() {
+            "redirect" => parse_domain(value).map(SpfTerm::Redirect),
+            "exp" => parse_domain(value).map(SpfTerm::Exp),
+            _ => Ok(SpfTerm::Modifier(name, value.to_string())),
+        };
+    }
+
+    let qualifier = qualifier.unwrap_or(SpfQualifier::Pass);
+    match name.as_str() {
+        "all" if rest.is_empty() => Ok(SpfTerm::All(qualifier)),
+        "include" | "exists" => {
+            let domain = parse_domain(rest.strip_prefix(':').ok_or("Missing domain")?)?;
+            Ok(if name == "include" {
+                SpfTerm::Include(qualifier, domain)
+            } else {
+                SpfTerm::Exists(qualifier, domain)
+            })
+        }
+        "a" | "mx" => {
+            let (domain, cidr) = match rest.strip_prefix(':') {
+                Some(rest) => {
+                    let (domain, cidr) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                    (Some(parse_domain(domain)?), cidr)
+                }
+                None => (None, rest),
+            };
+            let (cidr4, cidr6) = parse_dual_cidr(cidr)?;
+            Ok(if name == "a" {
+                SpfTerm::A {
+                    qualifier,
+                    domain,
+                    cidr4,
+                    cidr6,
+                }
+            } else {
+                SpfTerm::Mx {
+                    qualifier,
+                    domain,
+                    cidr4,
+                    cidr6,
+                }
+            })
+        }
+        "ptr" => match rest.strip_prefix(':') {
+            Some(domain) => Ok(SpfTerm::Ptr(qualifier, Some(parse_domain(domain)?))),
+            None if rest.is_empty() => Ok(SpfTerm::Ptr(qualifier, None)),
+            None => Err("Invalid domain"),
+        },
+        "ip4" => {
+            let value = rest.strip_prefix(':').ok_or("Missing address")?;
+            let (addr, len) = match value.split_once('/') {
+                Some((addr, len)) => (addr, Some(len)),
+                None => (value, None),
+            };
+            Ok(SpfTerm::Ip4(
+                qualifier,
+                addr.parse().map_err(|_| "Invalid IPv4 address")?,
+                parse_cidr(len, 32)?,
+            ))
+        }
+        "ip6" => {
+            let value = rest.strip_prefix(':').ok_or("Missing address")?;
+            let (addr, len) = match value.split_once('/') {
+                Some((addr, len)) => (addr, Some(len)),
+                None => (value, None),
+            };
+            Ok(SpfTerm::Ip6(
+                qualifier,
+                addr.parse().map_err(|_| "Invalid IPv6 address")?,
+                parse_cidr(len, 128)?,
+            ))
+        }
+        _ => Err("Unknown mechanism"),
+    }
+}
+
+fn parse_cidr(len: Option<&str>, max: u8) -> Result<u8, &'static str> {
+    match len {
+        Some(len) => len
+            .parse::<u8>()
+            .ok()
+            .filter(|len| *len <= max)
+            .ok_or("Invalid prefix length"),
+        None => Ok(max),
+    }
+}
+
+fn parse_dual_cidr(cidr: &str) -> Result<(u8, u8), &'static str> {
+    if cidr.is_empty() {
+        return Ok((32, 128));
+    }
+    let (cidr4, cidr6) = match cidr.strip_prefix("//") {
+        Some(cidr6) => (None, Some(cidr6)),
+        None => {
+            let cidr = cidr.strip_prefix('/').ok_or("Invalid prefix length")?;
+            match cidr.split_once("//") {
+                Some((cidr4, cidr6)) => (Some(cidr4), Some(cidr6)),
+                None => (Some(cidr), None),
+            }
+        }
+    };
+    Ok((parse_cidr(cidr4, 32)?, parse_cidr(cidr6, 128)?))
+}
+
+fn parse_domain(domain: &str) -> Result<String, &'static str> {
+    if domain.is_empty()
+        || domain.len() > 253
+        || !domain
+            .bytes()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, b'/' | b'=' | b':'))
+    {
+        Err("Invalid domain")
+    } else if has_macro(domain) {
+        Ok(domain.to_string())
+    } else if domain.contains('.') {
+        Ok(domain.trim_end_matches('.').to_ascii_lowercase())
+    } else {
+        Err("Invalid domain")
+    }
+}
+
+fn has_macro(domain: &str) -> bool {
+    domain.contains('%')
+}
+
+fn is_spf_record(record: &str) -> bool {
+    record
+        .get(..6)
+        .is_some_and(|version| version.eq_ignore_ascii_case("v=spf1"))
+        && record.as_bytes().get(6).is_none_or(|c| *c == b' ')
+}
+
+impl Server {
+    /// Returns the TXT records of a name, with the strings of each record
+    /// joined together.
+    pub async fn dns_txt_records<'x>(
+        &self,
+        name: impl IntoFqdn<'x>,
+    ) -> mail_auth::Result<Arc<Vec<String>>> {
+        let name = name.into_fqdn();
+        if let Some(records) = self.inner.cache.dns_txt_records.get(name.as_ref()) {
+            return Ok(records);
+        }
+
+        #[cfg(any(test, feature = "test_mode"))]
+        if true {
+            return mail_auth::common::resolver::mock_resolve(name.as_ref());
+        }
+
+        let lookup = self
+            .core
+            .smtp
+            .resolvers
+            .dnssec
+            .resolver
+            .txt_lookup(Name::from_str_relaxed(name.as_ref())?)
+            .await?;
+        let records = Arc::new(
+            lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>(),
+        );
+        self.inner.cache.dns_txt_records.insert_with_expiry(
+            name.into_owned(),
+            records.clone(),
+            lookup.valid_until(),
+        );
+
+        Ok(records)
+    }
+
+    /// Returns the SPF records published by a domain, receivers reject
+    /// domains with more than one.
+    pub async fn spf_records(&self, domain: &str) -> mail_auth::Result<Vec<String>> {
+        match self.dns_txt_records(domain).await {
+            Ok(records) => Ok(records
+                .iter()
+                .filter(|record| is_spf_record(record))
+                .cloned()
+                .collect()),
+            Err(Error::DnsRecordNotFound(_)) => Ok(vec![]),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Fetches the current SPF record of a domain and the records it
+    /// includes, counting the lookups receivers perform to evaluate it.
+    pub async fn analyze_spf(&self, domain: &str) -> trc::Result<SpfAnalysis> {
+        let records = self
+            .spf_records(domain)
+            .await
+            .map_err(trc::Error::from)
+            .caused_by(trc::location!())?;
+        let mut analysis = SpfAnalysis {
+            domain: domain.to_string(),
+            record: records.first().cloned(),
+            lookups: 0,
+            lookup_limit: SPF_LOOKUP_LIMIT,
+            over_limit: false,
+            errors: vec![],
+            warnings: vec![],
+            includes: vec![],
+            suggested_record: None,
+            remediation: None,
+        };
+        let Some(source) = records.first() else {
+            analysis
+                .errors
+                .push("No SPF record is published".to_string());
+            analysis.suggested_record = Some("v=spf1 mx ra=postmaster -all".to_string());
+            analysis.remediation = Some(format!(
+                "Publish a TXT record at {domain} with the suggested record"
+            ));
+            return Ok(analysis);
+        };
+        let mut remediation = Vec::new();
+        if records.len() > 1 {
+            analysis.errors.push(format!(
+                "{} SPF records are published, receivers treat this as an error",
+                records.len()
+            ));
+            remediation.push("Remove all SPF records except one");
+        }
+
+        let tree = self.fetch_spf_tree(domain, source).await;
+        let record = tree.record(domain).cloned().unwrap_or_default();
+        analysis.errors.extend(record.errors.iter().cloned());
+        for (include, depth) in tree.order.iter().skip(1) {
+            match &tree.records[include] {
+                Ok((source, included)) => {
+                    analysis.errors.extend(
+                        included
+                            .errors
+                            .iter()
+                            .map(|error| format!("{error} (in {include})")),
+                    );
+                    analysis.includes.push(SpfInclude {
+                        domain: include.clone(),
+                        depth: *depth,
+                        record: Some(source.clone()),
+                        lookups: included.lookups(),
+                    });
+                }
+                Err(error) => {
+                    analysis.errors.push(error.clone());
+                    analysis.includes.push(SpfInclude {
+                        domain: include.clone(),
+                        depth: *depth,
+                        record: None,
+                        lookups: 0,
+                    });
+                }
+            }
+        }
+        analysis.lookups = tree.lookups(domain, &record, &mut vec![], &mut analysis.errors);
+        analysis.over_limit = analysis.lookups > SPF_LOOKUP_LIMIT;
+        if !analysis.errors.is_empty() && remediation.is_empty() {
+            remediation.push("Fix the errors of the record, or replace it with the suggested one");
+        }
+
+        let corrected = record.corrected(&mut analysis.warnings);
+        let corrected_lookups = tree.lookups(domain, &corrected, &mut vec![], &mut vec![]);
+        if corrected.terms != record.terms || !record.errors.is_empty() {
+            analysis.suggested_record = Some(corrected.to_string());
+        }
+        if analysis.over_limit {
+            analysis.errors.push(format!(
+                "The record requires {} DNS lookups, receivers stop at {SPF_LOOKUP_LIMIT}",
+                analysis.lookups
+            ));
+            if corrected_lookups > SPF_LOOKUP_LIMIT {
+                remediation.push(concat!(
+                    "Remove the includes of services no longer in use, or replace ",
+                    "them with their networks by flattening the record"
+                ));
+            }
+        }
+        if !remediation.is_empty() {
+            analysis.remediation = Some(remediation.join(". "));
+        }
+
+        Ok(analysis)
+    }
+
+    /// Replaces the includes of an SPF record with the networks they
+    /// authorize. When no record is provided, the published one is used.
+    pub async fn flatten_spf(
+        &self,
+        domain: &str,
+        source: Option<&str>,
+    ) -> trc::Result<SpfFlattened> {
+        let source = match source {
+            Some(source) => source.trim().to_string(),
+            None => {
+                let mut records = self
+                    .spf_records(domain)
+                    .await
+                    .map_err(trc::Error::from)
+                    .caused_by(trc::location!())?;
+                match records.len() {
+                    1 => records.pop().unwrap(),
+                    0 => return Err(manage::error("No SPF record is published", None::<String>)),
+                    _ => {
+                        return Err(manage::error(
+                            "Multiple SPF records are published",
+                            None::<String>,
+                        ));
+                    }
+                }
+            }
+        };
+        let tree = self.fetch_spf_tree(domain, &source).await;
+        let record = match tree.records.get(domain) {
+            Some(Ok((_, record))) if record.errors.is_empty() => record,
+            Some(Ok((_, record))) => {
+                return Err(manage::error(
+                    "Invalid SPF record",
+                    Some(record.errors.join(", ")),
+                ));
+            }
+            Some(Err(error)) => {
+                return Err(manage::error("Invalid SPF record", Some(error.clone())));
+            }
+            None => unreachable!(),
+        };
+
+        let mut terms: Vec<SpfTerm> = Vec::with_capacity(record.terms.len());
+        let mut warnings = Vec::new();
+        let redirect = record.redirect();
+        for term in &record.terms {
+            let (qualifier, target) = match term {
+                SpfTerm::Include(qualifier, target) => (*qualifier, target.as_str()),
+                SpfTerm::Redirect(target) if redirect.is_some() => {
+                    (SpfQualifier::Pass, target.as_str())
+                }
+                SpfTerm::Redirect(_) => continue,
+                term => {
+                    if !terms.contains(term) {
+                        terms.push(term.clone());
+                    }
+                    continue;
+                }
+            };
+
+            match self.spf_networks(&tree, target).await {
+                Ok(networks) => {
+                    for network in networks {
+                        let network = network.with_qualifier(qualifier);
+                        if !terms.contains(&network) {
+                            terms.push(network);
+                        }
+                    }
+                    if matches!(term, SpfTerm::Redirect(_)) {
+                        // The redirected record decides the result of other hosts
+                        terms.push(SpfTerm::All(
+                            tree.record(target)
+                                .and_then(|record| record.all())
+                                .unwrap_or(SpfQualifier::Neutral),
+                        ));
+                    }
+                }
+                Err(reason) => {
+                    warnings.push(format!("{term} was not flattened: {reason}"));
+                    terms.push(term.clone());
+                }
+            }
+        }
+
+        // Mechanisms must precede modifiers
+        let (mechanisms, modifiers): (Vec<_>, Vec<_>) =
+            terms.into_iter().partition(SpfTerm::is_mechanism);
+        let flattened = SpfRecord {
+            terms: mechanisms.into_iter().chain(modifiers).collect(),
+            errors: vec![],
+        };
+        let lookups = tree.lookups(domain, &flattened, &mut vec![], &mut vec![]);
+        let result = flattened.to_string();
+
+        warnings.push(
+            concat!(
+                "Flattened records do not follow the changes made by the providers of the ",
+                "included domains and must be refreshed periodically"
+            )
+            .to_string(),
+        );
+        if lookups > SPF_LOOKUP_LIMIT {
+            warnings.push(format!(
+                "The flattened record still requires {lookups} DNS lookups, receivers stop at {SPF_LOOKUP_LIMIT}"
+            ));
+        }
+        if result.len() > MAX_RECORD_SIZE {
+            warnings.push(format!(
+                "The flattened record is {} bytes long and may not fit in a UDP response, consider moving some networks to an included record",
+                result.len()
+            ));
+        }
+
+        Ok(SpfFlattened {
+            domain: domain.to_string(),
+            source,
+            strings: SpfRecord::split(&result),
+            record: result,
+            lookups,
+            networks: networks_of(&flattened),
+            warnings,
+        })
+    }
+
+    pub async fn spf_monitor(&self, domain: &str) -> trc::Result<Option<SpfMonitor>> {
+        let config = &self.core.storage.config;
+        let prefix = format!("{SPF_MONITOR_KEY}.{domain}");
+        let Some(source) = config.get(format!("{prefix}.source")).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(SpfMonitor {
+            source,
+            networks: config
+                .get(format!("{prefix}.networks"))
+                .await?
+                .map(|networks| {
+                    networks
+                        .split_ascii_whitespace()
+                        .map(|network| network.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            changed_at: config
+                .get(format!("{prefix}.changed-at"))
+                .await?
+                .and_then(|changed_at| changed_at.parse().ok()),
+        }))
+    }
+
+    /// Returns the domains whose flattened records are monitored.
+    pub async fn spf_monitored_domains(&self) -> trc::Result<Vec<String>> {
+        Ok(self
+            .core
+            .storage
+            .config
+            .group(&format!("{SPF_MONITOR_KEY}."), ".source")
+            .await?
+            .into_keys()
+            .collect())
+    }
+
+    pub async fn set_spf_monitor(
+        &self,
+        domain: &str,
+        monitor: Option<&SpfMonitor>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        let prefix = format!("{SPF_MONITOR_KEY}.{domain}");
+        config
+            .clear_prefix(format!("{prefix}."))
+            .await
+            .caused_by(trc::location!())?;
+        if let Some(monitor) = monitor {
+            let mut keys = vec![
+                ConfigKey {
+                    key: format!("{prefix}.source"),
+                    value: monitor.source.clone(),
+                },
+                ConfigKey {
+                    key: format!("{prefix}.networks"),
+                    value: monitor.networks.join(" "),
+                },
+            ];
+            if let Some(changed_at) = monitor.changed_at {
+                keys.push(ConfigKey {
+                    key: format!("{prefix}.changed-at"),
+                    value: changed_at.to_string(),
+                });
+            }
+            config.set(keys, true).await.caused_by(trc::location!())?;
+        }
+
+        // Changes are reported by the next health check
+        self.inner.data.dns_checks.remove(domain, DnsCheckType::Spf);
+
+        Ok(())
+    }
+
+    /// Flattens a monitored record again, raising an event and flagging the
+    /// domain when the networks of its includes changed. Returns whether
+    /// they did.
+    pub async fn refresh_spf_monitor(&self, domain: &str) -> trc::Result<bool> {
+        let Some(mut monitor) = self.spf_monitor(domain).await? else {
+            return Ok(false);
+        };
+        let flattened = self.flatten_spf(domain, Some(&monitor.source)).await?;
+        if flattened.networks == monitor.networks {
+            return Ok(false);
+        }
+
+        let previous = monitor.networks.iter().collect::<AHashSet<_>>();
+        let current = flattened.networks.iter().collect::<AHashSet<_>>();
+        let changes = flattened
+            .networks
+            .iter()
+            .filter(|network| !previous.contains(network))
+            .map(|network| format!("+{network}"))
+            .chain(
+                monitor
+                    .networks
+                    .iter()
+                    .filter(|network| !current.contains(network))
+                    .map(|network| format!("-{network}")),
+            )
+            .collect::<Vec<_>>()
+            .join(" ");
+        trc::event!(
+            Directory(trc::DirectoryEvent::DomainSpfChanged),
+            Domain = domain.to_string(),
+            Details = changes,
+        );
+
+        monitor.networks = flattened.networks;
+        monitor.changed_at = Some(now());
+        self.set_spf_monitor(domain, Some(&monitor)).await?;
+
+        Ok(true)
+    }
+
+    async fn fetch_spf_tree(&self, domain: &str, source: &str) -> SpfTree {
+        let mut tree = SpfTree::default();
+        let mut queue = VecDeque::from([(domain.to_string(), 0)]);
+
+        while let Some((name, depth)) = queue.pop_front() {
+            if tree.records.contains_key(&name) {
+                continue;
+            }
+            let record = if depth == 0 {
+                SpfRecord::parse(source)
+                    .map(|record| (source.to_string(), record))
+                    .ok_or_else(|| "The record is not an SPF record".to_string())
+            } else {
+                match self.spf_records(&name).await {
+                    Ok(records) if records.len() == 1 => {
+                        let source = records.into_iter().next().unwrap();
+                        let record = SpfRecord::parse(&source).unwrap_or_default();
+                        Ok((source, record))
+                    }
+                    Ok(records) if records.is_empty() => {
+                        Err(format!("No SPF record is published by {name}"))
+                    }
+                    Ok(_) => Err(format!("Multiple SPF records are published by {name}")),
+                    Err(err) => Err(format!("Failed to fetch the SPF record of {name}: {err}")),
+                }
+            };
+
+            if let Ok((_, record)) = &record {
+                for target in record.targets() {
+                    if depth < MAX_DEPTH
+                        && !has_macro(target)
+                        && !tree.records.contains_key(target)
+                        && tree.records.len() + queue.len() < MAX_RECORDS
+                    {
+                        queue.push_back((target.to_string(), depth + 1));
+                    }
+                }
+            }
+            tree.order.push((name.clone(), depth));
+            tree.records.insert(name, record);
+        }
+
+        tree
+    }
+
+    /// Returns the networks an included domain authorizes, or the reason
+    /// they can not be listed.
+    async fn spf_networks(&self, tree: &SpfTree, domain: &str) -> Result<Vec<SpfTerm>, String> {
+        let mut networks = Vec::new();
+        let mut visited = AHashSet::new();
+        let mut pending = vec![domain.to_string()];
+
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if has_macro(&name) {
+                return Err(format!("{name} uses macros"));
+            }
+            let record = match tree.records.get(&name) {
+                Some(Ok((_, record))) if record.errors.is_empty() => record,
+                Some(Ok(_)) => return Err(format!("The SPF record of {name} is invalid")),
+                Some(Err(error)) => return Err(error.clone()),
+                None => return Err(format!("{name} is nested too deeply")),
+            };
+            let redirect = record.redirect();
+
+            for term in &record.terms {
+                match term {
+                    SpfTerm::Ip4(SpfQualifier::Pass, addr, len) => {
+                        networks.push(SpfTerm::ip4(*addr, *len))
+                    }
+                    SpfTerm::Ip6(SpfQualifier::Pass, addr, len) => {
+                        networks.push(SpfTerm::ip6(*addr, *len))
+                    }
+                    SpfTerm::Include(SpfQualifier::Pass, target) => {
+                        pending.push(target.clone());
+                    }
+                    SpfTerm::Redirect(target) if redirect.is_some() => {
+                        pending.push(target.clone());
+                    }
+                    SpfTerm::A {
+                        qualifier: SpfQualifier::Pass,
+                        domain,
+                        cidr4,
+                        cidr6,
+                    } => {
+                        let host = domain.as_deref().unwrap_or(&name);
+                        self.spf_host_networks(host, *cidr4, *cidr6, &mut networks)
+                            .await?;
+                    }
+                    SpfTerm::Mx {
+                        qualifier: SpfQualifier::Pass,
+                        domain,
+                        cidr4,
+                        cidr6,
+                    } => {
+                        let host = domain.as_deref().unwrap_or(&name);
+                        if has_macro(host) {
+                            return Err(format!("{name} uses macros"));
+                        }
+                        match self
+                            .core
+                            .smtp
+                            .resolvers
+                            .dns
+                            .mx_lookup(host, Some(&self.inner.cache.dns_mx))
+                            .await
+                        {
+                            Ok(mxs) => {
+                                for exchange in mxs.iter().flat_map(|mx| mx.exchanges.iter()) {
+                                    self.spf_host_networks(
+                                        exchange.as_str(),
+                                        *cidr4,
+                                        *cidr6,
+                                        &mut networks,
+                                    )
+                                    .await?;
+                                }
+                            }
+                            Err(Error::DnsRecordNotFound(_)) => {}
+                            Err(err) => {
+                                return Err(format!("Failed to resolve the MX of {host}: {err}"));
+                            }
+                        }
+                    }
+                    SpfTerm::Ptr(SpfQualifier::Pass, _) => {
+                        return Err(format!("{name} uses the ptr mechanism"));
+                    }
+                    SpfTerm::Exists(SpfQualifier::Pass, _) => {
+                        return Err(format!("{name} uses the exists mechanism"));
+                    }
+                    SpfTerm::All(SpfQualifier::Pass) => {
+                        return Err(format!("{name} authorizes any host"));
+                    }
+                    SpfTerm::All(_) => break,
+                    // Other qualifiers never cause an include to match
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(networks)
+    }
+
+    async fn spf_host_networks(
+        &self,
+        host: &str,
+        cidr4: u8,
+        cidr6: u8,
+        networks: &mut Vec<SpfTerm>,
+    ) -> Result<(), String> {
+        if has_macro(host) {
+            return Err(format!("{host} uses macros"));
+        }
+        let resolver = &self.core.smtp.resolvers.dns;
+        match resolver
+            .ipv4_lookup(host, Some(&self.inner.cache.dns_ipv4))
+            .await
+        {
+            Ok(addrs) => networks.extend(addrs.iter().map(|addr| SpfTerm::ip4(*addr, cidr4))),
+            Err(Error::DnsRecordNotFound(_)) => {}
+            Err(err) => return Err(format!("Failed to resolve {host}: {err}")),
+        }
+        match resolver
+            .ipv6_lookup(host, Some(&self.inner.cache.dns_ipv6))
+            .await
+        {
+            Ok(addrs) => networks.extend(addrs.iter().map(|addr| SpfTerm::ip6(*addr, cidr6))),
+            Err(Error::DnsRecordNotFound(_)) => {}
+            Err(err) => return Err(format!("Failed to resolve {host}: {err}")),
+        }
+        Ok(())
+    }
+}
+
+/// Networks authorized by a record, sorted so that they can be compared.
+fn networks_of(record: &SpfRecord) -> Vec<String> {
+    let mut networks = record
+        .terms
+        .iter()
+        .filter(|term| {
+            matches!(
+                term,
+                SpfTerm::Ip4(SpfQualifier::Pass, ..) | SpfTerm::Ip6(SpfQualifier::Pass, ..)
+            )
+        })
+        .map(|term| term.to_string())
+        .collect::<Vec<_>>();
+    networks.sort_unstable();
+    networks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpfQualifier, SpfRecord, SpfTerm};
+
+    #[test]
+    fn parse_spf_record() {
+        let record = SpfRecord::parse(concat!(
+            "v=spf1 +mx a:mail.example.org/24//64 -ip4:192.0.2.1 ip6:2001:db8::/32 ",
+            "~include:_spf.example.net ?exists:%{i}.example.org ptr redirect=example.com ",
+            "foo=bar include ip4:300.0.0.1 ip4:192.0.2.0/33 +bad:x redirect=example.net"
+        ))
+        .unwrap();
+        assert_eq!(
+            record.terms,
+            vec![
+                SpfTerm::Mx {
+                    qualifier: SpfQualifier::Pass,
+                    domain: None,
+                    cidr4: 32,
+                    cidr6: 128
+                },
+                SpfTerm::A {
+                    qualifier: SpfQualifier::Pass,
+                    domain: Some("mail.example.org".to_string()),
+                    cidr4: 24,
+                    cidr6: 64
+                },
+                SpfTerm::Ip4(SpfQualifier::Fail, "192.0.2.1".parse().unwrap(), 32),
+                SpfTerm::Ip6(SpfQualifier::Pass, "2001:db8::".parse().unwrap(), 32),
+                SpfTerm::Include(SpfQualifier::SoftFail, "_spf.example.net".to_string()),
+                SpfTerm::Exists(SpfQualifier::Neutral, "%{i}.example.org".to_string()),
+                SpfTerm::Ptr(SpfQualifier::Pass, None),
+                SpfTerm::Redirect("example.com".to_string()),
+                SpfTerm::Modifier("foo".to_string(), "bar".to_string()),
+            ]
+        );
+        assert_eq!(record.errors.len(), 5, "{:?}", record.errors);
+        assert_eq!(record.lookups(), 6);
+        assert_eq!(
+            record.targets().collect::<Vec<_>>(),
+            vec!["_spf.example.net", "example.com"]
+        );
+        assert_eq!(SpfRecord::parse("v=spf10 mx"), None);
+        assert_eq!(SpfRecord::parse("v=DMARC1; p=none"), None);
+
+        // Corrected records end with all and drop deprecated or unreachable terms
+        let mut warnings = vec![];
+        let corrected = SpfRecord::parse("v=spf1 ptr mx -all a mx ip4:192.0.2.1 redirect=a.org")
+            .unwrap()
+            .corrected(&mut warnings);
+        assert_eq!(corrected.to_string(), "v=spf1 mx -all");
+        assert_eq!(warnings.len(), 5, "{warnings:?}");
+        warnings.clear();
+        assert_eq!(
+            SpfRecord::parse("v=spf1 mx include:a.org exp=b.org")
+                .unwrap()
+                .corrected(&mut warnings)
+                .to_string(),
+            "v=spf1 mx include:a.org ~all exp=b.org"
+        );
+        assert_eq!(
+            SpfRecord::parse("v=spf1 mx redirect=a.org")
+                .unwrap()
+                .corrected(&mut warnings)
+                .to_string(),
+            "v=spf1 mx redirect=a.org"
+        );
+
+        // Networks are normalized and long records split at term boundaries
+        assert_eq!(
+            SpfTerm::ip4("192.0.2.77".parse().unwrap(), 24).to_string(),
+            "ip4:192.0.2.0/24"
+        );
+        assert_eq!(
+            SpfTerm::ip6("2001:db8::1".parse().unwrap(), 128).to_string(),
+            "ip6:2001:db8::1"
+        );
+        let record = format!(
+            "v=spf1 {} -all",
+            (0..40)
+                .map(|n| format!("ip4:192.0.2.{n}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        let strings = SpfRecord::split(&record);
+        assert!(strings.len() > 1);
+        assert!(strings.iter().all(|string| string.len() <= 255));
+        assert_eq!(strings.concat(), record);
+    }
+}
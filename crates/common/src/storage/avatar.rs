@@ -0,0 +1,124 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::Server;
+use directory::backend::internal::{
+    avatar::{Avatar, PrincipalAvatars, avatar_key},
+    manage,
+};
+use imagesize::ImageType;
+use trc::AddContext;
+use types::blob_hash::BlobHash;
+
+/// Checks that an uploaded avatar is a supported image within the configured
+/// limits and returns its content type.
+pub fn validate_avatar(
+    bytes: &[u8],
+    max_size: usize,
+    max_dimension: u32,
+) -> Result<&'static str, String> {
+    if bytes.is_empty() {
+        return Err("Avatar image is empty".to_string());
+    } else if bytes.len() > max_size {
+        return Err(format!("Avatar images may not exceed {max_size} bytes"));
+    }
+
+    let content_type = match imagesize::image_type(bytes) {
+        Ok(ImageType::Png) => "image/png",
+        Ok(ImageType::Jpeg) => "image/jpeg",
+        Ok(ImageType::Gif) => "image/gif",
+        Ok(ImageType::Webp) => "image/webp",
+        _ => return Err("Avatars must be PNG, JPEG, GIF or WebP images".to_string()),
+    };
+    let size = imagesize::blob_size(bytes)
+        .map_err(|_| "Failed to read the dimensions of the avatar image".to_string())?;
+    if size.width == 0
+        || size.height == 0
+        || size.width > max_dimension as usize
+        || size.height > max_dimension as usize
+    {
+        return Err(format!(
+            "Avatar images may not exceed {max_dimension}x{max_dimension} pixels"
+        ));
+    }
+
+    Ok(content_type)
+}
+
+impl Server {
+    /// Stores an avatar in the blob store and references it from the principal.
+    pub async fn put_avatar(&self, principal_id: u32, bytes: &[u8]) -> trc::Result<Avatar> {
+        let content_type = validate_avatar(
+            bytes,
+            self.core.jmap.avatar_max_size,
+            self.core.jmap.avatar_max_dimension,
+        )
+        .map_err(|reason| manage::error("Invalid avatar", reason.into()))?;
+        let avatar = Avatar {
+            hash: BlobHash::generate(bytes).to_hex(),
+            content_type: content_type.to_string(),
+        };
+
+        self.blob_store()
+            .put_blob(&avatar_key(principal_id), bytes)
+            .await
+            .caused_by(trc::location!())?;
+        self.store()
+            .set_avatar(principal_id, Some(avatar.clone()))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(avatar)
+    }
+
+    pub async fn get_avatar(&self, principal_id: u32) -> trc::Result<Option<Vec<u8>>> {
+        self.blob_store()
+            .get_blob(&avatar_key(principal_id), 0..usize::MAX)
+            .await
+            .caused_by(trc::location!())
+    }
+
+    /// Removes the avatar of a principal, returns whether it had one.
+    pub async fn delete_avatar(&self, principal_id: u32) -> trc::Result<bool> {
+        let previous = self
+            .store()
+            .set_avatar(principal_id, None)
+            .await
+            .caused_by(trc::location!())?;
+        self.blob_store()
+            .delete_blob(&avatar_key(principal_id))
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(previous.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_avatar;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[8, 6, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn avatar_validation() {
+        assert_eq!(validate_avatar(&png(256, 256), 1024, 512), Ok("image/png"));
+        assert_eq!(validate_avatar(&png(512, 128), 1024, 512), Ok("image/png"));
+        assert!(validate_avatar(&png(513, 128), 1024, 512).is_err());
+        assert!(validate_avatar(&png(256, 0), 1024, 512).is_err());
+        assert!(validate_avatar(&png(256, 256), 16, 512).is_err());
+        assert!(
+            validate_avatar(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>", 1024, 512).is_err()
+        );
+        assert!(validate_avatar(b"", 1024, 512).is_err());
+    }
+}
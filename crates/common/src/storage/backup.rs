@@ -0,0 +1,378 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use parking_lot::Mutex;
+use store::{
+    BlobStore, Serialize, ValueKey,
+    write::{AlignedBytes, Archive, Archiver, BatchBuilder, now},
+};
+use trc::AddContext;
+use types::{blob_hash::BlobHash, collection::Collection, field::PrincipalField};
+
+use crate::{
+    Server,
+    config::{
+        groupware::TENANT_COLLECTIONS_KEY,
+        jmap::{
+            retention::TENANT_RETENTION_KEY, settings::TENANT_FOLDERS_KEY,
+            shared::TENANT_SHARED_MAILBOX_KEY,
+        },
+        login_page::TENANT_LOGIN_PAGE_KEY,
+        overrides::TENANT_OVERRIDES_KEY,
+        password_policy::TENANT_PASSWORD_POLICY_KEY,
+        scripts::{TENANT_FORWARDING_KEY, TENANT_SIEVE_KEY, TENANT_VACATION_KEY},
+        smtp::{
+            auth::{TENANT_ARC_KEY, TENANT_DKIM_KEY},
+            disclaimer::TENANT_DISCLAIMER_KEY,
+            journal::TENANT_JOURNAL_KEY,
+            queue::TENANT_ROUTING_KEY,
+        },
+        spamfilter::{TENANT_SENDERS_KEY, TENANT_SPAM_KEY},
+        storage::TENANT_BLOB_KEY,
+    },
+    ipc::BroadcastEvent,
+    jobs::{Job, JobRegistry},
+};
+
+/// Prefixes of the settings stored per tenant, followed by the tenant id.
+pub const TENANT_SETTINGS: &[&str] = &[
+    TENANT_ROUTING_KEY,
+    TENANT_DKIM_KEY,
+    TENANT_ARC_KEY,
+    TENANT_DISCLAIMER_KEY,
+    TENANT_LOGIN_PAGE_KEY,
+    TENANT_SPAM_KEY,
+    TENANT_SENDERS_KEY,
+    TENANT_SIEVE_KEY,
+    TENANT_VACATION_KEY,
+    TENANT_FORWARDING_KEY,
+    TENANT_RETENTION_KEY,
+    TENANT_FOLDERS_KEY,
+    TENANT_COLLECTIONS_KEY,
+    TENANT_SHARED_MAILBOX_KEY,
+    TENANT_BLOB_KEY,
+    TENANT_OVERRIDES_KEY,
+    TENANT_PASSWORD_POLICY_KEY,
+    TENANT_JOURNAL_KEY,
+];
+
+/// Backup and restore jobs started on this node, kept so that their
+/// outcome can be retrieved.
+pub type TenantBackupJobs = JobRegistry<TenantBackupJob>;
+
+#[derive(Debug)]
+pub struct TenantBackupJob {
+    pub id: u64,
+    /// Tenant the backup was taken from.
+    pub tenant_id: u32,
+    pub kind: TenantBackupJobKind,
+    pub backup_id: u64,
+    state: Mutex<TenantBackupJobState>,
+}
+
+#[derive(Debug)]
+struct TenantBackupJobState {
+    status: TenantBackupJobStatus,
+    started: u64,
+    finished: Option<u64>,
+    total_accounts: u32,
+    completed_accounts: u32,
+    principals: u64,
+    skipped: u64,
+    messages: u64,
+    failed: u64,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TenantBackupJobKind {
+    Backup,
+    Restore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TenantBackupJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantBackupSummary {
+    pub id: String,
+    pub kind: TenantBackupJobKind,
+    pub backup_id: String,
+    pub status: TenantBackupJobStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub total_accounts: u32,
+    pub completed_accounts: u32,
+    pub principals: u64,
+    /// Principals left untouched because they already exist.
+    pub skipped: u64,
+    pub messages: u64,
+    pub failed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A completed backup of a tenant.
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TenantBackup {
+    pub id: u64,
+    pub started: u64,
+    pub finished: u64,
+    /// Whether message blobs were copied to the backup store, otherwise
+    /// the backup references the blobs in the tenant's blob store.
+    pub copy_blobs: bool,
+    /// Whether the copied blobs are encrypted with the tenant's data key.
+    pub encrypted: bool,
+    pub principals: u64,
+    pub accounts: u64,
+    pub messages: u64,
+    /// Size of the snapshot and copied blobs, in bytes.
+    pub size: u64,
+}
+
+impl TenantBackupJobs {
+    /// Registers a new job, unless the tenant already has one running.
+    pub fn start(
+        &self,
+        id: u64,
+        tenant_id: u32,
+        kind: TenantBackupJobKind,
+        backup_id: u64,
+    ) -> Option<Arc<TenantBackupJob>> {
+        self.register(
+            TenantBackupJob {
+                id,
+                tenant_id,
+                kind,
+                backup_id,
+                state: Mutex::new(TenantBackupJobState {
+                    status: TenantBackupJobStatus::Running,
+                    started: now(),
+                    finished: None,
+                    total_accounts: 0,
+                    completed_accounts: 0,
+                    principals: 0,
+                    skipped: 0,
+                    messages: 0,
+                    failed: 0,
+                    error: None,
+                }),
+            },
+            |job| job.tenant_id == tenant_id,
+        )
+    }
+}
+
+impl Job for TenantBackupJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == TenantBackupJobStatus::Running
+    }
+}
+
+impl TenantBackupJob {
+    pub fn set_total_accounts(&self, total_accounts: u32) {
+        self.state.lock().total_accounts = total_accounts;
+    }
+
+    pub fn complete_account(&self) {
+        self.state.lock().completed_accounts += 1;
+    }
+
+    pub fn add_principal(&self) {
+        self.state.lock().principals += 1;
+    }
+
+    pub fn skip_principal(&self) {
+        self.state.lock().skipped += 1;
+    }
+
+    pub fn add_message(&self) {
+        self.state.lock().messages += 1;
+    }
+
+    pub fn fail_message(&self) {
+        self.state.lock().failed += 1;
+    }
+
+    pub fn complete(&self) {
+        let mut state = self.state.lock();
+        state.status = TenantBackupJobStatus::Completed;
+        state.finished = Some(now());
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = TenantBackupJobStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn summary(&self) -> TenantBackupSummary {
+        let state = self.state.lock();
+        TenantBackupSummary {
+            id: self.id.to_string(),
+            kind: self.kind,
+            backup_id: self.backup_id.to_string(),
+            status: state.status,
+            started: state.started,
+            finished: state.finished,
+            total_accounts: state.total_accounts,
+            completed_accounts: state.completed_accounts,
+            principals: state.principals,
+            skipped: state.skipped,
+            messages: state.messages,
+            failed: state.failed,
+            error: state.error.clone(),
+        }
+    }
+}
+
+impl Server {
+    /// Returns the blob store tenant backups are written to, if configured.
+    pub fn backup_store(&self) -> Option<&BlobStore> {
+        self.core.storage.backup.as_ref()
+    }
+
+    /// Returns the completed backups of a tenant, most recent first.
+    pub async fn tenant_backups(&self, tenant_id: u32) -> trc::Result<Vec<TenantBackup>> {
+        self.store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::property(
+                tenant_id,
+                Collection::Principal,
+                0,
+                PrincipalField::Backups,
+            ))
+            .await?
+            .map(|archive| archive.deserialize::<Vec<TenantBackup>>())
+            .transpose()
+            .map(|backups| backups.unwrap_or_default())
+    }
+
+    /// Records a completed backup of a tenant.
+    pub async fn add_tenant_backup(&self, tenant_id: u32, backup: TenantBackup) -> trc::Result<()> {
+        let mut backups = self.tenant_backups(tenant_id).await?;
+        backups.insert(0, backup);
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(tenant_id)
+            .with_collection(Collection::Principal)
+            .with_document(0)
+            .set(
+                PrincipalField::Backups,
+                Archiver::new(backups)
+                    .serialize()
+                    .caused_by(trc::location!())?,
+            );
+        self.store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ())
+    }
+
+    /// Returns the settings of a tenant, grouped by prefix and keyed
+    /// relative to the tenant.
+    pub async fn tenant_settings(
+        &self,
+        tenant_id: u32,
+    ) -> trc::Result<BTreeMap<String, BTreeMap<String, String>>> {
+        let mut settings = BTreeMap::new();
+        for prefix in TENANT_SETTINGS {
+            let keys = self
+                .core
+                .storage
+                .config
+                .list(&format!("{prefix}.{tenant_id}."), true)
+                .await
+                .caused_by(trc::location!())?;
+            if !keys.is_empty() {
+                settings.insert(prefix.to_string(), keys);
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Replaces the settings of a tenant under the given prefixes, leaving
+    /// the settings under any other prefix untouched.
+    pub async fn restore_tenant_settings(
+        &self,
+        tenant_id: u32,
+        settings: &BTreeMap<String, BTreeMap<String, String>>,
+    ) -> trc::Result<()> {
+        let config = &self.core.storage.config;
+        for (prefix, keys) in settings {
+            if !TENANT_SETTINGS.contains(&prefix.as_str()) {
+                continue;
+            }
+            config
+                .clear_prefix(format!("{prefix}.{tenant_id}."))
+                .await
+                .caused_by(trc::location!())?;
+            let keys = keys
+                .iter()
+                .map(|(key, value)| (format!("{prefix}.{tenant_id}.{key}"), value.clone()))
+                .collect::<Vec<_>>();
+            config.set(keys, true).await.caused_by(trc::location!())?;
+        }
+
+        // Reload the settings that are cached in memory
+        self.inner.data.data_keys.remove(tenant_id);
+        self.reload_tenant_spam_settings().await?;
+        self.reload_tenant_sender_lists().await?;
+        self.reload_tenant_sieve_scripts().await?;
+        self.reload_tenant_routing().await?;
+        self.reload_tenant_dkim_policies().await?;
+        self.reload_tenant_arc_sealers().await?;
+        self.reload_disclaimers().await?;
+        self.reload_login_pages().await?;
+        self.reload_tenant_blob_stores().await?;
+        self.reload_tenant_overrides().await?;
+        self.reload_password_policies().await?;
+        self.reload_tenant_journaling().await?;
+        for event in [
+            BroadcastEvent::ReloadTenantSpamSettings,
+            BroadcastEvent::ReloadTenantSenderLists,
+            BroadcastEvent::ReloadTenantSieveScripts,
+            BroadcastEvent::ReloadTenantRouting,
+            BroadcastEvent::ReloadTenantDkimPolicies,
+            BroadcastEvent::ReloadTenantArcSealers,
+            BroadcastEvent::ReloadDisclaimers,
+            BroadcastEvent::ReloadLoginPages,
+            BroadcastEvent::ReloadTenantBlobStores,
+            BroadcastEvent::ReloadTenantOverrides,
+            BroadcastEvent::ReloadPasswordPolicies,
+            BroadcastEvent::ReloadTenantJournaling,
+        ] {
+            self.cluster_broadcast(event).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Key of a tenant backup's snapshot in the backup store.
+pub fn backup_key(tenant_id: u32, backup_id: u64) -> Vec<u8> {
+    format!("backup/{tenant_id}/{backup_id}").into_bytes()
+}
+
+/// Key of a blob copied to the backup store.
+pub fn backup_blob_key(tenant_id: u32, backup_id: u64, hash: &BlobHash) -> Vec<u8> {
+    format!("backup/{tenant_id}/{backup_id}/{}", hash.to_hex()).into_bytes()
+}
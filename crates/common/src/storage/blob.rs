@@ -5,22 +5,291 @@
  */
 
 use crate::Server;
+use directory::backend::internal::manage::ManageDirectory;
 use mail_parser::{
     Encoding,
     decoders::{base64::base64_decode, quoted_printable::quoted_printable_decode},
 };
+use std::ops::Range;
+use store::{
+    BlobStore,
+    roaring::RoaringBitmap,
+    write::{BatchBuilder, BlobOp, blob::BlobLocation},
+};
+use trc::AddContext;
 use types::{blob::BlobSection, blob_hash::BlobHash};
 
+/// Outcome of moving a blob to another location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobMove {
+    Moved,
+    NotFound,
+    /// The blob is referenced outside the tenant and was left in place.
+    Shared,
+}
+
 impl Server {
+    /// Returns the blob store with the given id, an empty id refers to the
+    /// default blob store.
+    pub fn blob_store_by_id(&self, store_id: &str) -> trc::Result<&BlobStore> {
+        if store_id.is_empty() {
+            Ok(self.blob_store())
+        } else {
+            self.core.storage.blobs.get(store_id).ok_or_else(|| {
+                trc::StoreEvent::NotConfigured
+                    .into_err()
+                    .details("Blob store not found")
+                    .ctx(trc::Key::Id, store_id.to_string())
+            })
+        }
+    }
+
+    /// Returns the location new blobs of a tenant are written to, which
+    /// depends on its blob store and encryption settings.
+    pub fn tenant_blob_location(&self, tenant_id: u32) -> BlobLocation {
+        BlobLocation {
+            // Stores removed from the configuration fall back to the default store
+            store_id: self
+                .inner
+                .data
+                .tenant_blob_stores
+                .load()
+                .get(&tenant_id)
+                .filter(|store_id| self.core.storage.blobs.contains_key(store_id.as_str()))
+                .cloned()
+                .unwrap_or_default(),
+            encryption_key: self
+                .inner
+                .data
+                .tenant_encryption
+                .load()
+                .get(&tenant_id)
+                .filter(|settings| settings.enabled)
+                .map(|_| tenant_id),
+        }
+    }
+
+    /// Returns the location new blobs of an account are written to.
+    pub async fn account_blob_location(&self, account_id: u32) -> trc::Result<BlobLocation> {
+        if (self.inner.data.tenant_blob_stores.load().is_empty()
+            && self.inner.data.tenant_encryption.load().is_empty())
+            || account_id == u32::MAX
+        {
+            return Ok(BlobLocation::default());
+        }
+
+        Ok(self
+            .get_access_token(account_id)
+            .await
+            .caused_by(trc::location!())?
+            .tenant
+            .map(|tenant| self.tenant_blob_location(tenant.id))
+            .unwrap_or_default())
+    }
+
+    /// Returns the tenant that blobs written by an account are scoped to.
+    pub async fn account_blob_tenant(&self, account_id: u32) -> trc::Result<Option<u32>> {
+        if account_id == u32::MAX {
+            return Ok(None);
+        }
+
+        Ok(self
+            .get_access_token(account_id)
+            .await
+            .caused_by(trc::location!())?
+            .tenant
+            .map(|tenant| tenant.id))
+    }
+
+    /// Returns the hash a blob written by an account is stored under. Each
+    /// tenant can have its own blob store and data key, so blobs are only
+    /// deduplicated between accounts of the same tenant.
+    pub async fn account_blob_hash(&self, account_id: u32, data: &[u8]) -> trc::Result<BlobHash> {
+        Ok(match self.account_blob_tenant(account_id).await? {
+            Some(tenant_id) => BlobHash::generate_scoped(tenant_id, data),
+            None => BlobHash::generate(data),
+        })
+    }
+
+    /// Reads a blob from the store it was written to, decrypting it if needed.
+    pub async fn get_blob(
+        &self,
+        hash: &BlobHash,
+        range: Range<usize>,
+    ) -> trc::Result<Option<Vec<u8>>> {
+        let location = self.store().blob_location(hash).await?.unwrap_or_default();
+        match self.get_blob_at(hash, &location, range.clone()).await? {
+            Some(blob) => Ok(Some(blob)),
+            None => {
+                // The blob might have been moved to another store while reading it
+                let new_location = self.store().blob_location(hash).await?.unwrap_or_default();
+                if new_location != location {
+                    self.get_blob_at(hash, &new_location, range).await
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn get_blob_at(
+        &self,
+        hash: &BlobHash,
+        location: &BlobLocation,
+        range: Range<usize>,
+    ) -> trc::Result<Option<Vec<u8>>> {
+        let blob_store = self.blob_store_by_id(&location.store_id)?;
+        let Some(tenant_id) = location.encryption_key else {
+            return blob_store.get_blob(hash.as_slice(), range).await;
+        };
+
+        // Encrypted blobs are always read in full
+        let Some(blob) = blob_store.get_blob(hash.as_slice(), 0..usize::MAX).await? else {
+            return Ok(None);
+        };
+        let mut blob = self.decrypt_blob(tenant_id, &blob).await?;
+        if range.start != 0 || range.end < blob.len() {
+            blob = blob
+                .get(range.start..range.end.min(blob.len()))
+                .unwrap_or_default()
+                .to_vec();
+        }
+
+        Ok(Some(blob))
+    }
+
+    /// Uploads a blob written by an account to the blob store assigned to
+    /// its tenant, encrypted if the tenant has encryption enabled. The hash
+    /// must come from `account_blob_hash`, so an existing blob was written by
+    /// the same tenant and is only moved to the tenant's current location when
+    /// no account outside the tenant references it.
+    pub async fn put_account_blob(
+        &self,
+        account_id: u32,
+        hash: &BlobHash,
+        data: &[u8],
+    ) -> trc::Result<()> {
+        let to = self.account_blob_location(account_id).await?;
+        match self
+            .store()
+            .blob_location(hash)
+            .await
+            .caused_by(trc::location!())?
+        {
+            None => {
+                let blob = match to.encryption_key {
+                    Some(tenant_id) => self.encrypt_blob(tenant_id, data).await?,
+                    None => data.to_vec(),
+                };
+                self.blob_store_by_id(&to.store_id)?
+                    .put_blob(hash.as_slice(), &blob)
+                    .await
+                    .caused_by(trc::location!())?;
+                self.commit_blob(hash, &to).await
+            }
+            Some(from) if from != to => {
+                // Blobs of accounts without a tenant stay where they are
+                let Some(tenant_id) = self.account_blob_tenant(account_id).await? else {
+                    return Ok(());
+                };
+                let account_ids = self
+                    .store()
+                    .principal_ids(None, Some(tenant_id))
+                    .await
+                    .caused_by(trc::location!())?;
+                self.move_blob(hash, &from, &to, &account_ids, Some(data))
+                    .await
+                    .map(|_| ())
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Moves a blob between locations, re-encrypting it when the data key
+    /// changes. Blobs are only moved when all their links belong to
+    /// `account_ids`, a blob that is also referenced by other accounts or by
+    /// server objects is left in its current location.
+    pub async fn move_blob(
+        &self,
+        hash: &BlobHash,
+        from: &BlobLocation,
+        to: &BlobLocation,
+        account_ids: &RoaringBitmap,
+        data: Option<&[u8]>,
+    ) -> trc::Result<BlobMove> {
+        if self
+            .store()
+            .blob_is_linked_outside(hash, account_ids)
+            .await
+            .caused_by(trc::location!())?
+        {
+            return Ok(BlobMove::Shared);
+        }
+
+        let from_store = self.blob_store_by_id(&from.store_id)?;
+        let to_store = self.blob_store_by_id(&to.store_id)?;
+        let blob = match data {
+            Some(data) => match to.encryption_key {
+                Some(tenant_id) => self.encrypt_blob(tenant_id, data).await?,
+                None => data.to_vec(),
+            },
+            None => {
+                let Some(blob) = from_store
+                    .get_blob(hash.as_slice(), 0..usize::MAX)
+                    .await
+                    .caused_by(trc::location!())?
+                else {
+                    return Ok(BlobMove::NotFound);
+                };
+
+                if from.encryption_key != to.encryption_key {
+                    let data = match from.encryption_key {
+                        Some(tenant_id) => self.decrypt_blob(tenant_id, &blob).await?,
+                        None => blob,
+                    };
+                    match to.encryption_key {
+                        Some(tenant_id) => self.encrypt_blob(tenant_id, &data).await?,
+                        None => data,
+                    }
+                } else {
+                    blob
+                }
+            }
+        };
+
+        to_store
+            .put_blob(hash.as_slice(), &blob)
+            .await
+            .caused_by(trc::location!())?;
+        self.commit_blob(hash, to).await?;
+        if from.store_id != to.store_id {
+            from_store
+                .delete_blob(hash.as_slice())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(BlobMove::Moved)
+    }
+
+    async fn commit_blob(&self, hash: &BlobHash, location: &BlobLocation) -> trc::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch.set(BlobOp::Commit { hash: hash.clone() }, location.serialize());
+        self.store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ())
+    }
+
     pub async fn get_blob_section(
         &self,
         hash: &BlobHash,
         section: &BlobSection,
     ) -> trc::Result<Option<Vec<u8>>> {
         Ok(self
-            .blob_store()
             .get_blob(
-                hash.as_slice(),
+                hash,
                 (section.offset_start)..(section.offset_start.saturating_add(section.size)),
             )
             .await?
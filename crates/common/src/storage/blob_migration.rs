@@ -0,0 +1,295 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use ahash::AHashMap;
+use directory::backend::internal::manage::ManageDirectory;
+use parking_lot::Mutex;
+use store::{
+    Deserialize, IterateParams, U32_LEN, U64_LEN, ValueKey,
+    write::{BlobLink, BlobOp, ValueClass, blob::BlobLocation, now},
+};
+use trc::{AddContext, TaskQueueEvent};
+use types::blob_hash::{BLOB_HASH_LEN, BlobHash};
+
+use crate::{Server, storage::blob::BlobMove};
+
+/// Blob migrations started on this node, the last migration of each
+/// tenant is kept so that its progress can be displayed.
+#[derive(Debug, Default)]
+pub struct BlobMigrations {
+    jobs: Mutex<AHashMap<u32, Arc<BlobMigration>>>,
+}
+
+#[derive(Debug)]
+pub struct BlobMigration {
+    pub tenant_id: u32,
+    /// Destination of the tenant's blobs.
+    pub location: BlobLocation,
+    pub started: u64,
+    pub total: AtomicU64,
+    pub moved: AtomicU64,
+    pub failed: AtomicU64,
+    /// Blobs left in place because accounts outside the tenant use them.
+    pub shared: AtomicU64,
+    state: Mutex<BlobMigrationState>,
+}
+
+#[derive(Debug)]
+struct BlobMigrationState {
+    status: BlobMigrationStatus,
+    finished: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlobMigrationStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobMigrationSummary {
+    pub blob_store: Option<String>,
+    pub encrypted: bool,
+    pub status: BlobMigrationStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub total: u64,
+    pub moved: u64,
+    pub failed: u64,
+    pub shared: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BlobMigrations {
+    pub fn get(&self, tenant_id: u32) -> Option<Arc<BlobMigration>> {
+        self.jobs.lock().get(&tenant_id).cloned()
+    }
+
+    fn start(&self, tenant_id: u32, location: BlobLocation) -> Option<Arc<BlobMigration>> {
+        let mut jobs = self.jobs.lock();
+        if jobs.get(&tenant_id).is_some_and(|job| job.is_running()) {
+            return None;
+        }
+
+        let job = Arc::new(BlobMigration {
+            tenant_id,
+            location,
+            started: now(),
+            total: AtomicU64::new(0),
+            moved: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            shared: AtomicU64::new(0),
+            state: Mutex::new(BlobMigrationState {
+                status: BlobMigrationStatus::Running,
+                finished: None,
+                error: None,
+            }),
+        });
+        jobs.insert(tenant_id, job.clone());
+        Some(job)
+    }
+}
+
+impl BlobMigration {
+    pub fn is_running(&self) -> bool {
+        self.state.lock().status == BlobMigrationStatus::Running
+    }
+
+    fn finish(&self, error: Option<String>) {
+        let mut state = self.state.lock();
+        state.status = if error.is_none() {
+            BlobMigrationStatus::Completed
+        } else {
+            BlobMigrationStatus::Failed
+        };
+        state.finished = Some(now());
+        state.error = error;
+    }
+
+    pub fn summary(&self) -> BlobMigrationSummary {
+        let state = self.state.lock();
+        BlobMigrationSummary {
+            blob_store: Some(self.location.store_id.clone())
+                .filter(|store_id| !store_id.is_empty()),
+            encrypted: self.location.encryption_key.is_some(),
+            status: state.status,
+            started: self.started,
+            finished: state.finished,
+            total: self.total.load(Ordering::Relaxed),
+            moved: self.moved.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            shared: self.shared.load(Ordering::Relaxed),
+            error: state.error.clone(),
+        }
+    }
+}
+
+impl Server {
+    /// Starts moving the blobs linked to a tenant's accounts to the blob store
+    /// currently assigned to the tenant, encrypting or decrypting them according
+    /// to the tenant's encryption settings. Blobs that are also linked outside
+    /// the tenant are left in place. Returns `None` if a migration is already
+    /// running for the tenant.
+    pub fn start_blob_migration(&self, tenant_id: u32) -> Option<Arc<BlobMigration>> {
+        let job = self
+            .inner
+            .data
+            .blob_migrations
+            .start(tenant_id, self.tenant_blob_location(tenant_id))?;
+
+        let server = self.clone();
+        let job_ = job.clone();
+        tokio::spawn(async move {
+            let start_time = Instant::now();
+            match server.migrate_tenant_blobs(&job_).await {
+                Ok(()) => {
+                    job_.finish(None);
+
+                    trc::event!(
+                        TaskQueue(TaskQueueEvent::BlobMigrationCompleted),
+                        TenantId = tenant_id,
+                        Total = job_.moved.load(Ordering::Relaxed),
+                        Details = job_.failed.load(Ordering::Relaxed),
+                        Elapsed = start_time.elapsed(),
+                    );
+                }
+                Err(err) => {
+                    job_.finish(Some(
+                        err.value_as_str(trc::Key::Details)
+                            .unwrap_or_else(|| err.event_type().description())
+                            .to_string(),
+                    ));
+
+                    trc::event!(
+                        TaskQueue(TaskQueueEvent::BlobMigrationFailed),
+                        TenantId = tenant_id,
+                        CausedBy = err,
+                        Elapsed = start_time.elapsed(),
+                    );
+                }
+            }
+        });
+
+        Some(job)
+    }
+
+    async fn migrate_tenant_blobs(&self, job: &BlobMigration) -> trc::Result<()> {
+        const TEMP_LINK: usize = BLOB_HASH_LEN + U32_LEN + U64_LEN;
+        const DOC_LINK: usize = BLOB_HASH_LEN + U64_LEN + 1;
+
+        let account_ids = self
+            .store()
+            .principal_ids(None, Some(job.tenant_id))
+            .await
+            .caused_by(trc::location!())?;
+        if account_ids.is_empty() {
+            return Ok(());
+        }
+
+        for byte in 0..=u8::MAX {
+            // Find the blobs linked to the tenant's accounts, along with their location
+            let mut from_hash = BlobHash::default();
+            let mut to_hash = BlobHash::new_max();
+            from_hash.0[0] = byte;
+            to_hash.0[0] = byte;
+            let mut blobs: Vec<(BlobHash, BlobLocation)> = Vec::new();
+            let mut location = None;
+            self.store()
+                .iterate(
+                    IterateParams::new(
+                        ValueKey {
+                            account_id: 0,
+                            collection: 0,
+                            document_id: 0,
+                            class: ValueClass::Blob(BlobOp::Commit { hash: from_hash }),
+                        },
+                        ValueKey {
+                            account_id: u32::MAX,
+                            collection: u8::MAX,
+                            document_id: u32::MAX,
+                            class: ValueClass::Blob(BlobOp::Link {
+                                hash: to_hash,
+                                to: BlobLink::Document,
+                            }),
+                        },
+                    )
+                    .ascending(),
+                    |key, value| {
+                        match key.len() {
+                            BLOB_HASH_LEN => {
+                                location = Some((
+                                    BlobHash::try_from_hash_slice(key).map_err(|_| {
+                                        trc::Error::corrupted_key(key, None, trc::location!())
+                                    })?,
+                                    BlobLocation::deserialize(value)?,
+                                ));
+                            }
+                            TEMP_LINK | DOC_LINK => {
+                                if let Some((hash, location)) = location.as_ref()
+                                    && key.starts_with(hash.as_slice())
+                                    && *location != job.location
+                                    && account_ids.contains(u32::from_be_bytes(
+                                        key[BLOB_HASH_LEN..BLOB_HASH_LEN + U32_LEN]
+                                            .try_into()
+                                            .unwrap(),
+                                    ))
+                                    && blobs.last().is_none_or(|(last, _)| last != hash)
+                                {
+                                    blobs.push((hash.clone(), location.clone()));
+                                }
+                            }
+                            _ => {}
+                        }
+
+                        Ok(true)
+                    },
+                )
+                .await
+                .caused_by(trc::location!())?;
+
+            job.total.fetch_add(blobs.len() as u64, Ordering::Relaxed);
+            for (hash, location) in blobs {
+                match self
+                    .move_blob(&hash, &location, &job.location, &account_ids, None)
+                    .await
+                {
+                    Ok(BlobMove::Moved) => {
+                        job.moved.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(BlobMove::NotFound) => {
+                        job.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(BlobMove::Shared) => {
+                        job.shared.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        trc::error!(
+                            err.caused_by(trc::location!())
+                                .details("Failed to move blob")
+                                .ctx(trc::Key::BlobId, hash.to_hex())
+                        );
+                        job.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
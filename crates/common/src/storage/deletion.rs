@@ -0,0 +1,306 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::BTreeSet, fmt::Display, sync::Arc};
+
+use parking_lot::Mutex;
+use store::write::now;
+
+use crate::jobs::{Job, JobRegistry};
+
+/// Deletion reports generated on this node, kept so that they can be
+/// downloaded and referenced when deleting the organization.
+pub type DeletionReports = JobRegistry<DeletionReportJob>;
+
+#[derive(Debug)]
+pub struct DeletionReportJob {
+    pub id: u64,
+    pub tenant_id: u32,
+    state: Mutex<DeletionReportState>,
+}
+
+#[derive(Debug)]
+struct DeletionReportState {
+    status: DeletionReportStatus,
+    started: u64,
+    finished: Option<u64>,
+    report: Option<Arc<DeletionReport>>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeletionReportStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionReportSummary {
+    pub id: String,
+    pub status: DeletionReportStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<Arc<DeletionReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Everything that is removed along with an organization, and anything
+/// that prevents it from being deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionReport {
+    pub organization: String,
+    pub generated_at: u64,
+    pub domains: Vec<String>,
+    pub accounts: Vec<AccountContents>,
+    pub groups: Vec<String>,
+    pub lists: Vec<String>,
+    pub roles: Vec<String>,
+    pub resources: Vec<String>,
+    pub api_keys: Vec<String>,
+    pub oauth_clients: Vec<String>,
+    pub sieve_scripts: Vec<SieveScriptRef>,
+    pub blockers: Vec<DeletionBlocker>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountContents {
+    pub name: String,
+    pub messages: u64,
+    pub size: u64,
+}
+
+/// Script of an account, or of the organization's library when there is
+/// no account.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SieveScriptRef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionBlocker {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub name: String,
+    pub reason: String,
+}
+
+impl DeletionReports {
+    /// Registers a new report, unless one is already being generated for
+    /// the tenant.
+    pub fn start(&self, id: u64, tenant_id: u32) -> Option<Arc<DeletionReportJob>> {
+        self.register(
+            DeletionReportJob {
+                id,
+                tenant_id,
+                state: Mutex::new(DeletionReportState {
+                    status: DeletionReportStatus::Running,
+                    started: now(),
+                    finished: None,
+                    report: None,
+                    error: None,
+                }),
+            },
+            |job| job.tenant_id == tenant_id,
+        )
+    }
+}
+
+impl Job for DeletionReportJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == DeletionReportStatus::Running
+    }
+}
+
+impl DeletionReportJob {
+    pub fn complete(&self, report: DeletionReport) {
+        let mut state = self.state.lock();
+        state.status = DeletionReportStatus::Completed;
+        state.finished = Some(now());
+        state.report = Some(Arc::new(report));
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = DeletionReportStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn report(&self) -> Option<Arc<DeletionReport>> {
+        self.state.lock().report.clone()
+    }
+
+    pub fn summary(&self) -> DeletionReportSummary {
+        let state = self.state.lock();
+        DeletionReportSummary {
+            id: self.id.to_string(),
+            status: state.status,
+            started: state.started,
+            finished: state.finished,
+            report: state.report.clone(),
+            error: state.error.clone(),
+        }
+    }
+}
+
+impl DeletionReport {
+    /// Whether the organization holds nothing besides itself.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+            && self.accounts.is_empty()
+            && self.groups.is_empty()
+            && self.lists.is_empty()
+            && self.roles.is_empty()
+            && self.resources.is_empty()
+            && self.api_keys.is_empty()
+            && self.oauth_clients.is_empty()
+            && self.sieve_scripts.is_empty()
+    }
+
+    /// Describes the material differences with a newer report. Mailbox
+    /// contents are not compared, as mail keeps arriving until the
+    /// organization is deleted.
+    pub fn changes(&self, current: &DeletionReport) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        for (typ, before, after) in [
+            ("domains", names(&self.domains), names(&current.domains)),
+            (
+                "accounts",
+                self.accounts.iter().map(|a| a.name.clone()).collect(),
+                current.accounts.iter().map(|a| a.name.clone()).collect(),
+            ),
+            ("groups", names(&self.groups), names(&current.groups)),
+            ("lists", names(&self.lists), names(&current.lists)),
+            ("roles", names(&self.roles), names(&current.roles)),
+            (
+                "resources",
+                names(&self.resources),
+                names(&current.resources),
+            ),
+            ("API keys", names(&self.api_keys), names(&current.api_keys)),
+            (
+                "OAuth clients",
+                names(&self.oauth_clients),
+                names(&current.oauth_clients),
+            ),
+            (
+                "sieve scripts",
+                self.sieve_scripts.iter().map(|s| s.to_string()).collect(),
+                current
+                    .sieve_scripts
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            ),
+            (
+                "blockers",
+                self.blockers.iter().map(|b| b.to_string()).collect(),
+                current.blockers.iter().map(|b| b.to_string()).collect(),
+            ),
+        ] {
+            let added = after.difference(&before).cloned().collect::<Vec<_>>();
+            let removed = before.difference(&after).cloned().collect::<Vec<_>>();
+            if !added.is_empty() {
+                changes.push(format!("{typ} added: {}", added.join(", ")));
+            }
+            if !removed.is_empty() {
+                changes.push(format!("{typ} removed: {}", removed.join(", ")));
+            }
+        }
+
+        changes
+    }
+}
+
+impl Display for SieveScriptRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.account {
+            Some(account) => write!(f, "{account}/{}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl Display for DeletionBlocker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.typ, self.name)
+    }
+}
+
+fn names(values: &[String]) -> BTreeSet<String> {
+    values.iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountContents, DeletionBlocker, DeletionReport, SieveScriptRef};
+
+    #[test]
+    fn deletion_report_changes() {
+        let report = DeletionReport {
+            organization: "acme".to_string(),
+            generated_at: 1000,
+            domains: vec!["acme.org".to_string()],
+            accounts: vec![AccountContents {
+                name: "jane@acme.org".to_string(),
+                messages: 10,
+                size: 1024,
+            }],
+            groups: vec!["sales@acme.org".to_string()],
+            sieve_scripts: vec![SieveScriptRef {
+                account: Some("jane@acme.org".to_string()),
+                name: "vacation".to_string(),
+            }],
+            ..Default::default()
+        };
+
+        // New mail and a newer timestamp are not material changes
+        let mut current = report.clone();
+        current.generated_at = 2000;
+        current.accounts[0].messages = 12;
+        current.accounts[0].size = 4096;
+        assert!(report.changes(&current).is_empty());
+
+        current.accounts.push(AccountContents {
+            name: "john@acme.org".to_string(),
+            messages: 0,
+            size: 0,
+        });
+        current.groups.clear();
+        current.sieve_scripts[0].name = "filters".to_string();
+        current.blockers.push(DeletionBlocker {
+            typ: "legalHold".to_string(),
+            name: "jane@acme.org".to_string(),
+            reason: "Account is on legal hold".to_string(),
+        });
+        assert_eq!(
+            report.changes(&current),
+            [
+                "accounts added: john@acme.org",
+                "groups removed: sales@acme.org",
+                "sieve scripts added: jane@acme.org/filters",
+                "sieve scripts removed: jane@acme.org/vacation",
+                "blockers added: legalHold jane@acme.org",
+            ]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use directory::backend::internal::mfa::{ENCRYPTED_OTP_PREFIX, SecretCipher};
+use parking_lot::Mutex;
+use rsa::{
+    Oaep, RsaPrivateKey, RsaPublicKey,
+    pkcs1::DecodeRsaPrivateKey,
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    rand_core::OsRng,
+};
+use store::rand::{Rng, rng};
+
+use crate::{Server, auth::oauth::crypto::SymmetricEncrypt, config::storage::TenantEncryption};
+
+pub const DATA_KEY_LEN: usize = 32;
+
+// Unwrapped data keys are cached briefly, so revoking the tenant's key
+// takes effect without a restart
+const DATA_KEY_TTL: Duration = Duration::from_secs(60);
+
+const DATA_KEY_CONTEXT: &str = "tenant data key";
+const BLOB_CONTEXT: &str = "tenant blob";
+const TOTP_CONTEXT: &str = "totp secret";
+
+/// Data keys unwrapped on this node.
+#[derive(Debug, Default)]
+pub struct DataKeys {
+    cache: Mutex<AHashMap<u32, CachedDataKey>>,
+}
+
+#[derive(Debug)]
+struct CachedDataKey {
+    settings: Arc<TenantEncryption>,
+    data_key: [u8; DATA_KEY_LEN],
+    expires: Instant,
+}
+
+impl DataKeys {
+    fn get(&self, tenant_id: u32, settings: &Arc<TenantEncryption>) -> Option<[u8; DATA_KEY_LEN]> {
+        self.cache
+            .lock()
+            .get(&tenant_id)
+            .filter(|cached| {
+                cached.expires > Instant::now() && cached.settings.data_key == settings.data_key
+            })
+            .map(|cached| cached.data_key)
+    }
+
+    fn insert(
+        &self,
+        tenant_id: u32,
+        settings: Arc<TenantEncryption>,
+        data_key: [u8; DATA_KEY_LEN],
+    ) {
+        self.cache.lock().insert(
+            tenant_id,
+            CachedDataKey {
+                settings,
+                data_key,
+                expires: Instant::now() + DATA_KEY_TTL,
+            },
+        );
+    }
+
+    pub fn remove(&self, tenant_id: u32) {
+        self.cache.lock().remove(&tenant_id);
+    }
+}
+
+impl Server {
+    /// Returns the encryption settings of a tenant, which are kept after
+    /// encryption is disabled so existing blobs can still be decrypted.
+    pub fn tenant_encryption(&self, tenant_id: u32) -> Option<Arc<TenantEncryption>> {
+        self.inner
+            .data
+            .tenant_encryption
+            .load()
+            .get(&tenant_id)
+            .cloned()
+    }
+
+    /// Returns the unwrapped data key of a tenant.
+    pub async fn tenant_data_key(&self, tenant_id: u32) -> trc::Result<[u8; DATA_KEY_LEN]> {
+        let settings = self.tenant_encryption(tenant_id).ok_or_else(|| {
+            key_unavailable(tenant_id).details("No encryption settings found for tenant")
+        })?;
+        if let Some(data_key) = self.inner.data.data_keys.get(tenant_id, &settings) {
+            return Ok(data_key);
+        }
+
+        let key = resolve_key(&settings.key)
+            .await
+            .map_err(|reason| key_unavailable(tenant_id).reason(reason))?;
+        let data_key = unwrap_data_key(&settings, &key)
+            .map_err(|reason| key_unavailable(tenant_id).reason(reason))?;
+        self.inner
+            .data
+            .data_keys
+            .insert(tenant_id, settings, data_key);
+
+        Ok(data_key)
+    }
+
+    /// Verifies that a tenant's data key can be unwrapped, bypassing the cache.
+    pub async fn verify_tenant_key(&self, tenant_id: u32) -> Result<(), String> {
+        let settings = self
+            .tenant_encryption(tenant_id)
+            .ok_or_else(|| "Encryption is not configured".to_string())?;
+        unwrap_data_key(&settings, &resolve_key(&settings.key).await?).map(|_| ())
+    }
+
+    /// Encrypts blob contents with a tenant's data key.
+    pub async fn encrypt_blob(&self, tenant_id: u32, data: &[u8]) -> trc::Result<Vec<u8>> {
+        let nonce = rng().random::<[u8; SymmetricEncrypt::NONCE_LEN]>();
+        let mut blob = nonce.to_vec();
+        blob.extend(
+            SymmetricEncrypt::new(&self.tenant_data_key(tenant_id).await?, BLOB_CONTEXT)
+                .encrypt(data, &nonce)
+                .map_err(|reason| {
+                    trc::StoreEvent::CryptoError
+                        .into_err()
+                        .ctx(trc::Key::TenantId, tenant_id)
+                        .reason(reason)
+                        .caused_by(trc::location!())
+                })?,
+        );
+
+        Ok(blob)
+    }
+
+    /// Decrypts blob contents encrypted with a tenant's data key.
+    pub async fn decrypt_blob(&self, tenant_id: u32, blob: &[u8]) -> trc::Result<Vec<u8>> {
+        let data_key = self.tenant_data_key(tenant_id).await?;
+        blob.split_at_checked(SymmetricEncrypt::NONCE_LEN)
+            .ok_or_else(|| "Encrypted blob is too short".to_string())
+            .and_then(|(nonce, blob)| {
+                SymmetricEncrypt::new(&data_key, BLOB_CONTEXT).decrypt(blob, nonce)
+            })
+            .map_err(|reason| {
+                trc::StoreEvent::CryptoError
+                    .into_err()
+                    .ctx(trc::Key::TenantId, tenant_id)
+                    .reason(reason)
+                    .caused_by(trc::location!())
+            })
+    }
+}
+
+impl Server {
+    /// Cipher of the TOTP secrets stored in the internal directory, derived
+    /// from the OAuth key.
+    pub fn secret_cipher(&self) -> SymmetricEncrypt {
+        SymmetricEncrypt::new(self.core.oauth.oauth_key.as_bytes(), TOTP_CONTEXT)
+    }
+}
+
+impl SecretCipher for SymmetricEncrypt {
+    fn encrypt_secret(&self, secret: &str) -> trc::Result<String> {
+        let nonce = rng().random::<[u8; SymmetricEncrypt::NONCE_LEN]>();
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(
+            self.encrypt(secret.as_bytes(), &nonce)
+                .map_err(|reason| trc::AuthEvent::Error.into_err().reason(reason))?,
+        );
+
+        Ok(format!(
+            "{ENCRYPTED_OTP_PREFIX}{}",
+            STANDARD.encode(encrypted)
+        ))
+    }
+
+    fn decrypt_secret(&self, secret: &str) -> trc::Result<String> {
+        secret
+            .strip_prefix(ENCRYPTED_OTP_PREFIX)
+            .and_then(|secret| STANDARD.decode(secret).ok())
+            .ok_or_else(|| "Invalid encrypted secret".to_string())
+            .and_then(|encrypted| {
+                encrypted
+                    .split_at_checked(SymmetricEncrypt::NONCE_LEN)
+                    .ok_or_else(|| "Encrypted secret is too short".to_string())
+                    .and_then(|(nonce, encrypted)| self.decrypt(encrypted, nonce))
+            })
+            .and_then(|secret| String::from_utf8(secret).map_err(|err| err.to_string()))
+            .map_err(|reason| {
+                trc::AuthEvent::Error
+                    .into_err()
+                    .reason(reason)
+                    .details("Failed to decrypt TOTP secret")
+            })
+    }
+}
+
+/// Generates a new data key.
+pub fn generate_data_key() -> [u8; DATA_KEY_LEN] {
+    rng().random::<[u8; DATA_KEY_LEN]>()
+}
+
+/// Obtains the key material a key reference points to, either an
+/// environment variable (`env:<name>`) or a file (`file:<path>`).
+pub async fn resolve_key(reference: &str) -> Result<Vec<u8>, String> {
+    let key = match reference.split_once(':') {
+        Some(("env", name)) => std::env::var(name)
+            .map(String::into_bytes)
+            .map_err(|_| format!("Failed to obtain environment variable {name:?}"))?,
+        Some(("file", path)) => {
+            let path = path.strip_prefix("//").unwrap_or(path);
+            tokio::fs::read(path)
+                .await
+                .map_err(|err| format!("Failed to read file {path:?}: {err}"))?
+        }
+        _ => return Err(format!("Invalid key reference {reference:?}")),
+    };
+
+    let key = key.trim_ascii();
+    if !key.is_empty() {
+        Ok(key.to_vec())
+    } else {
+        Err(format!("Key referenced by {reference:?} is empty"))
+    }
+}
+
+/// Wraps a data key with a symmetric key, or with an RSA public key when
+/// one is provided.
+pub fn wrap_data_key(
+    data_key: &[u8; DATA_KEY_LEN],
+    key: &[u8],
+    public_key: Option<&str>,
+) -> Result<String, String> {
+    let wrapped = if let Some(public_key) = public_key {
+        parse_public_key(public_key)?
+            .encrypt(&mut OsRng, Oaep::new::<sha2::Sha256>(), data_key)
+            .map_err(|err| format!("Failed to wrap data key: {err}"))?
+    } else {
+        let nonce = rng().random::<[u8; SymmetricEncrypt::NONCE_LEN]>();
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend(
+            SymmetricEncrypt::new(key, DATA_KEY_CONTEXT)
+                .encrypt(data_key, &nonce)
+                .map_err(|err| format!("Failed to wrap data key: {err}"))?,
+        );
+        wrapped
+    };
+
+    Ok(STANDARD.encode(wrapped))
+}
+
+/// Unwraps a tenant's data key using the key material its key reference
+/// points to.
+pub fn unwrap_data_key(
+    settings: &TenantEncryption,
+    key: &[u8],
+) -> Result<[u8; DATA_KEY_LEN], String> {
+    let wrapped = STANDARD
+        .decode(&settings.data_key)
+        .map_err(|err| format!("Failed to decode data key: {err}"))?;
+    let data_key = if settings.public_key.is_some() {
+        parse_private_key(key)?
+            .decrypt(Oaep::new::<sha2::Sha256>(), &wrapped)
+            .map_err(|_| "Failed to unwrap data key, the key does not match".to_string())?
+    } else {
+        wrapped
+            .split_at_checked(SymmetricEncrypt::NONCE_LEN)
+            .and_then(|(nonce, wrapped)| {
+                SymmetricEncrypt::new(key, DATA_KEY_CONTEXT)
+                    .decrypt(wrapped, nonce)
+                    .ok()
+            })
+            .ok_or_else(|| "Failed to unwrap data key, the key does not match".to_string())?
+    };
+
+    data_key
+        .try_into()
+        .map_err(|_| "Invalid data key length".to_string())
+}
+
+/// Verifies that a key can be used to wrap and unwrap data keys.
+pub fn validate_key(key: &[u8], public_key: Option<&str>) -> Result<(), String> {
+    if let Some(public_key) = public_key {
+        let public_key = parse_public_key(public_key)?;
+        if RsaPublicKey::from(&parse_private_key(key)?) != public_key {
+            return Err("The referenced private key does not match the public key".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_public_key(public_key: &str) -> Result<RsaPublicKey, String> {
+    RsaPublicKey::from_public_key_pem(public_key.trim())
+        .map_err(|err| format!("Failed to parse public key: {err}"))
+}
+
+fn parse_private_key(key: &[u8]) -> Result<RsaPrivateKey, String> {
+    let key = std::str::from_utf8(key).map_err(|_| "Invalid private key".to_string())?;
+    RsaPrivateKey::from_pkcs8_pem(key)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(key))
+        .map_err(|err| format!("Failed to parse private key: {err}"))
+}
+
+fn key_unavailable(tenant_id: u32) -> trc::Error {
+    trc::StoreEvent::EncryptionKeyUnavailable
+        .into_err()
+        .ctx(trc::Key::TenantId, tenant_id)
+        .caused_by(trc::location!())
+}
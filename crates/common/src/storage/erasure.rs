@@ -0,0 +1,172 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use parking_lot::Mutex;
+use ring::hmac;
+use store::write::now;
+
+use crate::jobs::{Job, JobRegistry};
+
+/// Erasure jobs started on this node, kept so that their reports can be
+/// retrieved.
+pub type ErasureJobs = JobRegistry<ErasureJob>;
+
+#[derive(Debug)]
+pub struct ErasureJob {
+    pub id: u64,
+    pub account_id: u32,
+    pub tenant_id: Option<u32>,
+    /// Name of the erased principal, only kept in memory.
+    pub name: String,
+    state: Mutex<ErasureJobState>,
+}
+
+#[derive(Debug)]
+struct ErasureJobState {
+    status: ErasureJobStatus,
+    started: u64,
+    finished: Option<u64>,
+    report: Option<ErasureReport>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErasureJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErasureSummary {
+    pub id: String,
+    pub principal: String,
+    pub status: ErasureJobStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<ErasureReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Record of an erasure, signed with the server's OAuth key.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErasureReport {
+    pub id: String,
+    pub principal: String,
+    /// Identifier that replaces the principal in retained records.
+    pub tombstone: String,
+    pub requested_by: String,
+    pub erased_at: u64,
+    /// Number of removed objects by type.
+    pub removed: BTreeMap<String, u64>,
+    pub retained: Vec<RetainedData>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+/// Data related to the principal that was not removed.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetainedData {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub count: u64,
+    pub reason: String,
+}
+
+impl ErasureJobs {
+    /// Registers a new job, unless the account is already being erased.
+    pub fn start(
+        &self,
+        id: u64,
+        account_id: u32,
+        tenant_id: Option<u32>,
+        name: String,
+    ) -> Option<Arc<ErasureJob>> {
+        self.register(
+            ErasureJob {
+                id,
+                account_id,
+                tenant_id,
+                name,
+                state: Mutex::new(ErasureJobState {
+                    status: ErasureJobStatus::Running,
+                    started: now(),
+                    finished: None,
+                    report: None,
+                    error: None,
+                }),
+            },
+            |job| job.account_id == account_id,
+        )
+    }
+}
+
+impl Job for ErasureJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == ErasureJobStatus::Running
+    }
+}
+
+impl ErasureJob {
+    pub fn complete(&self, report: ErasureReport) {
+        let mut state = self.state.lock();
+        state.status = ErasureJobStatus::Completed;
+        state.finished = Some(now());
+        state.report = Some(report);
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = ErasureJobStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn summary(&self) -> ErasureSummary {
+        let state = self.state.lock();
+        ErasureSummary {
+            id: self.id.to_string(),
+            principal: self.name.clone(),
+            status: state.status,
+            started: state.started,
+            finished: state.finished,
+            report: state.report.clone(),
+            error: state.error.clone(),
+        }
+    }
+}
+
+impl ErasureReport {
+    /// Returns the HMAC-SHA256 signature of the report, excluding any
+    /// existing signature.
+    pub fn signature(&self, key: &str) -> String {
+        let report = serde_json::to_vec(&ErasureReport {
+            signature: String::new(),
+            ..self.clone()
+        })
+        .unwrap_or_default();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        STANDARD.encode(hmac::sign(&key, &report).as_ref())
+    }
+
+    pub fn sign(mut self, key: &str) -> Self {
+        self.signature = self.signature(key);
+        self
+    }
+}
@@ -0,0 +1,222 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use parking_lot::Mutex;
+use ring::hmac;
+use store::write::now;
+use types::blob_hash::BlobHash;
+
+use crate::jobs::{Job, JobRegistry};
+
+/// Account exports started on this node, kept so that their download
+/// links can be retrieved.
+pub type AccountExportJobs = JobRegistry<AccountExportJob>;
+
+#[derive(Debug)]
+pub struct AccountExportJob {
+    pub id: u64,
+    pub account_id: u32,
+    pub tenant_id: Option<u32>,
+    pub name: String,
+    state: Mutex<AccountExportJobState>,
+}
+
+#[derive(Debug)]
+struct AccountExportJobState {
+    status: AccountExportJobStatus,
+    started: u64,
+    finished: Option<u64>,
+    archive: Option<AccountExportArchive>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountExportJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountExportRejection {
+    InProgress,
+    TooManyExports,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExportSummary {
+    pub id: String,
+    pub principal: String,
+    pub status: AccountExportJobStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<AccountExportArchive>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Generated archive, downloadable until it expires.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExportArchive {
+    pub size: u64,
+    pub messages: u64,
+    pub contacts: u64,
+    pub events: u64,
+    pub sieve_scripts: u64,
+    pub download_url: String,
+    pub expires: u64,
+}
+
+impl AccountExportJobs {
+    /// Registers a new job, unless the account is already being exported
+    /// or its tenant has reached the concurrent export limit.
+    pub fn start(
+        &self,
+        id: u64,
+        account_id: u32,
+        tenant_id: Option<u32>,
+        name: String,
+        max_concurrent: usize,
+    ) -> Result<Arc<AccountExportJob>, AccountExportRejection> {
+        self.try_register(
+            AccountExportJob {
+                id,
+                account_id,
+                tenant_id,
+                name,
+                state: Mutex::new(AccountExportJobState {
+                    status: AccountExportJobStatus::Running,
+                    started: now(),
+                    finished: None,
+                    archive: None,
+                    error: None,
+                }),
+            },
+            |running| {
+                if running.iter().any(|job| job.account_id == account_id) {
+                    Err(AccountExportRejection::InProgress)
+                } else if running
+                    .iter()
+                    .filter(|job| job.tenant_id == tenant_id)
+                    .count()
+                    >= max_concurrent
+                {
+                    Err(AccountExportRejection::TooManyExports)
+                } else {
+                    Ok(())
+                }
+            },
+        )
+    }
+}
+
+impl Job for AccountExportJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == AccountExportJobStatus::Running
+    }
+}
+
+impl AccountExportJob {
+    pub fn complete(&self, archive: AccountExportArchive) {
+        let mut state = self.state.lock();
+        state.status = AccountExportJobStatus::Completed;
+        state.finished = Some(now());
+        state.archive = Some(archive);
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = AccountExportJobStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn summary(&self) -> AccountExportSummary {
+        let state = self.state.lock();
+        AccountExportSummary {
+            id: self.id.to_string(),
+            principal: self.name.clone(),
+            status: state.status,
+            started: state.started,
+            finished: state.finished,
+            archive: state.archive.clone(),
+            error: state.error.clone(),
+        }
+    }
+}
+
+/// Returns the path an export archive can be downloaded from without
+/// authentication until it expires.
+pub fn export_download_path(key: &str, account_id: u32, hash: &BlobHash, expires: u64) -> String {
+    let signature = URL_SAFE_NO_PAD.encode(
+        hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            export_signed_data(account_id, hash, expires).as_bytes(),
+        )
+        .as_ref(),
+    );
+    format!(
+        "/export/{account_id}/{}?expires={expires}&signature={signature}",
+        hash.to_hex()
+    )
+}
+
+pub fn verify_export_signature(
+    key: &str,
+    account_id: u32,
+    hash: &BlobHash,
+    expires: u64,
+    signature: &str,
+) -> bool {
+    URL_SAFE_NO_PAD.decode(signature).is_ok_and(|signature| {
+        hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            export_signed_data(account_id, hash, expires).as_bytes(),
+            &signature,
+        )
+        .is_ok()
+    })
+}
+
+fn export_signed_data(account_id: u32, hash: &BlobHash, expires: u64) -> String {
+    format!("export:{account_id}:{}:{expires}", hash.to_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_download_signature() {
+        let hash = BlobHash::generate(b"archive");
+        let path = export_download_path("secret", 7, &hash, 1000);
+        let signature = path.rsplit_once("signature=").unwrap().1;
+
+        assert!(path.starts_with(&format!("/export/7/{}?expires=1000&", hash.to_hex())));
+        assert!(verify_export_signature("secret", 7, &hash, 1000, signature));
+        assert!(!verify_export_signature(
+            "secret", 8, &hash, 1000, signature
+        ));
+        assert!(!verify_export_signature(
+            "secret", 7, &hash, 1001, signature
+        ));
+        assert!(!verify_export_signature("other", 7, &hash, 1000, signature));
+        assert!(!verify_export_signature(
+            "secret", 7, &hash, 1000, "invalid"
+        ));
+    }
+}
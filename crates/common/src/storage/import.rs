@@ -0,0 +1,428 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use store::write::now;
+
+use crate::jobs::{Job, JobRegistry};
+
+const MAX_RESULTS: usize = 10_000;
+
+/// Message import jobs started on this node, kept so that their report
+/// can be downloaded.
+pub type MessageImportJobs = JobRegistry<MessageImportJob>;
+
+#[derive(Debug)]
+pub struct MessageImportJob {
+    pub id: u64,
+    pub account_id: u32,
+    state: Mutex<MessageImportJobState>,
+}
+
+#[derive(Debug)]
+struct MessageImportJobState {
+    status: MessageImportJobStatus,
+    started: u64,
+    finished: Option<u64>,
+    processed: usize,
+    imported: usize,
+    skipped: usize,
+    failed: usize,
+    error: Option<String>,
+    // Only messages that were not imported are reported
+    results: Vec<MessageImportResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageImportJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageImportResult {
+    /// Position of the message in the import, starting at 1.
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    pub result: MessageImportOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageImportOutcome {
+    Imported,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageImportSummary {
+    pub id: String,
+    pub status: MessageImportJobStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub processed: usize,
+    pub imported: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl MessageImportJobs {
+    /// Registers a new job, unless the account already has one running.
+    pub fn start(&self, id: u64, account_id: u32) -> Option<Arc<MessageImportJob>> {
+        self.register(
+            MessageImportJob {
+                id,
+                account_id,
+                state: Mutex::new(MessageImportJobState {
+                    status: MessageImportJobStatus::Running,
+                    started: now(),
+                    finished: None,
+                    processed: 0,
+                    imported: 0,
+                    skipped: 0,
+                    failed: 0,
+                    error: None,
+                    results: Vec::new(),
+                }),
+            },
+            |job| job.account_id == account_id,
+        )
+    }
+}
+
+impl Job for MessageImportJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == MessageImportJobStatus::Running
+    }
+}
+
+impl MessageImportJob {
+    pub fn push_result(&self, result: MessageImportResult) {
+        let mut state = self.state.lock();
+        state.processed += 1;
+        match result.result {
+            MessageImportOutcome::Imported => {
+                state.imported += 1;
+                return;
+            }
+            MessageImportOutcome::Skipped => state.skipped += 1,
+            MessageImportOutcome::Failed => state.failed += 1,
+        }
+        if state.results.len() < MAX_RESULTS {
+            state.results.push(result);
+        }
+    }
+
+    pub fn complete(&self) {
+        let mut state = self.state.lock();
+        state.status = MessageImportJobStatus::Completed;
+        state.finished = Some(now());
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = MessageImportJobStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn results(&self) -> Vec<MessageImportResult> {
+        self.state.lock().results.clone()
+    }
+
+    pub fn summary(&self) -> MessageImportSummary {
+        let state = self.state.lock();
+        MessageImportSummary {
+            id: self.id.to_string(),
+            status: state.status,
+            started: state.started,
+            finished: state.finished,
+            processed: state.processed,
+            imported: state.imported,
+            skipped: state.skipped,
+            failed: state.failed,
+            error: state.error.clone(),
+        }
+    }
+}
+
+/// IMAP imports started on this node, each copying the mailboxes of
+/// several accounts of a tenant. Each account is tracked by its own
+/// `MessageImportJob`.
+pub type ImapImportJobs = JobRegistry<ImapImportJob>;
+
+#[derive(Debug)]
+pub struct ImapImportJob {
+    pub id: u64,
+    pub tenant_id: u32,
+    pub host: String,
+    state: Mutex<ImapImportJobState>,
+}
+
+#[derive(Debug)]
+struct ImapImportJobState {
+    status: MessageImportJobStatus,
+    started: u64,
+    finished: Option<u64>,
+    accounts: Vec<ImapImportAccountState>,
+}
+
+#[derive(Debug)]
+struct ImapImportAccountState {
+    source_user: String,
+    target: String,
+    job: Option<Arc<MessageImportJob>>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImapImportAccountStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImapImportSummary {
+    pub id: String,
+    pub status: MessageImportJobStatus,
+    pub host: String,
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub accounts: Vec<ImapImportAccountSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImapImportAccountSummary {
+    pub source_user: String,
+    pub target_principal: String,
+    pub status: ImapImportAccountStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<MessageImportSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Last UID imported from each folder of the IMAP accounts that were
+/// imported into an account, so that repeated imports only transfer
+/// new messages.
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Default, Debug, Clone, PartialEq, Eq,
+)]
+pub struct ImapImportState {
+    pub sources: Vec<ImapImportSource>,
+}
+
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Default, Debug, Clone, PartialEq, Eq,
+)]
+pub struct ImapImportSource {
+    pub host: String,
+    pub username: String,
+    pub folders: Vec<ImapImportFolder>,
+}
+
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Default, Debug, Clone, PartialEq, Eq,
+)]
+pub struct ImapImportFolder {
+    pub name: String,
+    pub uid_validity: u32,
+    pub last_uid: u32,
+}
+
+impl ImapImportJobs {
+    /// Registers a new job, unless the tenant already has one running.
+    /// Accounts are given as pairs of source user and target principal.
+    pub fn start(
+        &self,
+        id: u64,
+        tenant_id: u32,
+        host: String,
+        accounts: impl IntoIterator<Item = (String, String)>,
+    ) -> Option<Arc<ImapImportJob>> {
+        self.register(
+            ImapImportJob {
+                id,
+                tenant_id,
+                host,
+                state: Mutex::new(ImapImportJobState {
+                    status: MessageImportJobStatus::Running,
+                    started: now(),
+                    finished: None,
+                    accounts: accounts
+                        .into_iter()
+                        .map(|(source_user, target)| ImapImportAccountState {
+                            source_user,
+                            target,
+                            job: None,
+                            error: None,
+                        })
+                        .collect(),
+                }),
+            },
+            |job| job.tenant_id == tenant_id,
+        )
+    }
+}
+
+impl Job for ImapImportJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == MessageImportJobStatus::Running
+    }
+}
+
+impl ImapImportJob {
+    pub fn set_account_job(&self, index: usize, job: Arc<MessageImportJob>) {
+        if let Some(account) = self.state.lock().accounts.get_mut(index) {
+            account.job = Some(job);
+        }
+    }
+
+    /// Marks an account as failed before its import could be started.
+    pub fn fail_account(&self, index: usize, error: String) {
+        if let Some(account) = self.state.lock().accounts.get_mut(index) {
+            account.error = Some(error);
+        }
+    }
+
+    pub fn complete(&self) {
+        let mut state = self.state.lock();
+        state.status = MessageImportJobStatus::Completed;
+        state.finished = Some(now());
+    }
+
+    /// Returns the target principal and the report of each account.
+    pub fn results(&self) -> Vec<(String, Vec<MessageImportResult>)> {
+        self.state
+            .lock()
+            .accounts
+            .iter()
+            .map(|account| {
+                (
+                    account.target.clone(),
+                    account
+                        .job
+                        .as_ref()
+                        .map(|job| job.results())
+                        .unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn summary(&self) -> ImapImportSummary {
+        let state = self.state.lock();
+        let accounts = state
+            .accounts
+            .iter()
+            .map(|account| {
+                let progress = account.job.as_ref().map(|job| job.summary());
+                ImapImportAccountSummary {
+                    source_user: account.source_user.clone(),
+                    target_principal: account.target.clone(),
+                    status: match (&progress, &account.error) {
+                        (_, Some(_)) => ImapImportAccountStatus::Failed,
+                        (Some(progress), None) => match progress.status {
+                            MessageImportJobStatus::Running => ImapImportAccountStatus::Running,
+                            MessageImportJobStatus::Completed => ImapImportAccountStatus::Completed,
+                            MessageImportJobStatus::Failed => ImapImportAccountStatus::Failed,
+                        },
+                        (None, None) => ImapImportAccountStatus::Pending,
+                    },
+                    error: account
+                        .error
+                        .clone()
+                        .or_else(|| progress.as_ref().and_then(|p| p.error.clone())),
+                    progress,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        ImapImportSummary {
+            id: self.id.to_string(),
+            status: state.status,
+            host: self.host.clone(),
+            started: state.started,
+            finished: state.finished,
+            total: accounts.len(),
+            completed: accounts
+                .iter()
+                .filter(|account| account.status == ImapImportAccountStatus::Completed)
+                .count(),
+            failed: accounts
+                .iter()
+                .filter(|account| account.status == ImapImportAccountStatus::Failed)
+                .count(),
+            accounts,
+        }
+    }
+}
+
+impl ImapImportState {
+    pub fn source_mut(&mut self, host: &str, username: &str) -> &mut ImapImportSource {
+        let idx = if let Some(idx) = self
+            .sources
+            .iter()
+            .position(|source| source.host == host && source.username == username)
+        {
+            idx
+        } else {
+            self.sources.push(ImapImportSource {
+                host: host.to_string(),
+                username: username.to_string(),
+                folders: Vec::new(),
+            });
+            self.sources.len() - 1
+        };
+        &mut self.sources[idx]
+    }
+}
+
+impl ImapImportSource {
+    pub fn folder_mut(&mut self, name: &str) -> &mut ImapImportFolder {
+        let idx = if let Some(idx) = self.folders.iter().position(|folder| folder.name == name) {
+            idx
+        } else {
+            self.folders.push(ImapImportFolder {
+                name: name.to_string(),
+                uid_validity: 0,
+                last_uid: 0,
+            });
+            self.folders.len() - 1
+        };
+        &mut self.folders[idx]
+    }
+}
@@ -24,7 +24,11 @@ use types::{
     collection::{Collection, SyncCollection},
     field::Field,
 };
-use utils::{cheeky_hash::CheekyHash, map::bitmap::Bitmap, snowflake::SnowflakeIdGenerator};
+use utils::{
+    cheeky_hash::CheekyHash,
+    map::{bitmap::Bitmap, vec_map::VecMap},
+    snowflake::SnowflakeIdGenerator,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IndexValue<'x> {
@@ -46,6 +50,7 @@ pub enum IndexValue<'x> {
     Quota {
         used: u32,
     },
+    MessageCount,
     LogContainer {
         sync_collection: SyncCollection,
     },
@@ -252,6 +257,8 @@ pub trait IndexableAndSerializableObject:
 pub struct ObjectIndexBuilder<C: IndexableObject, N: IndexableAndSerializableObject> {
     changed_by: u32,
     tenant_id: Option<u32>,
+    domain_id: Option<u32>,
+    domains: VecMap<u32, u32>,
     current: Option<Archive<C>>,
     changes: Option<N>,
 }
@@ -268,6 +275,8 @@ impl<C: IndexableObject, N: IndexableAndSerializableObject> ObjectIndexBuilder<C
             current: None,
             changes: None,
             tenant_id: None,
+            domain_id: None,
+            domains: VecMap::new(),
             changed_by: u32::MAX,
         }
     }
@@ -301,6 +310,7 @@ impl<C: IndexableObject, N: IndexableAndSerializableObject> ObjectIndexBuilder<C
 
     pub fn with_access_token(mut self, access_token: &AccessToken) -> Self {
         self.tenant_id = access_token.tenant.as_ref().map(|t| t.id);
+        self.domains = access_token.domains.clone();
         self.changed_by = access_token.primary_id();
         self
     }
@@ -309,17 +319,35 @@ impl<C: IndexableObject, N: IndexableAndSerializableObject> ObjectIndexBuilder<C
         self.tenant_id = tenant_id;
         self
     }
+
+    pub fn with_domain_id(mut self, domain_id: Option<u32>) -> Self {
+        self.domain_id = domain_id;
+        self
+    }
+
+    // Usage is attributed to the domain of the account that owns the object,
+    // which may not be the account of the access token.
+    fn domain_id(&self, account_id: Option<u32>) -> Option<u32> {
+        account_id
+            .and_then(|account_id| self.domains.get(&account_id).copied())
+            .or(self.domain_id)
+    }
 }
 
 impl<C: IndexableObject, N: IndexableAndSerializableObject> IntoOperations
     for ObjectIndexBuilder<C, N>
 {
     fn build(self, batch: &mut BatchBuilder) -> trc::Result<()> {
+        let owners = QuotaOwners {
+            tenant_id: self.tenant_id,
+            domain_id: self.domain_id(batch.last_account_id()),
+        };
+
         match (self.current, self.changes) {
             (None, Some(changes)) => {
                 // Insertion
                 for item in changes.index_values() {
-                    build_index(batch, item, self.changed_by, self.tenant_id, true);
+                    build_index(batch, item, self.changed_by, owners, true);
                 }
                 if N::is_versioned() {
                     let (offset, bytes) = Archiver::new(changes).serialize_versioned()?;
@@ -347,7 +375,7 @@ impl<C: IndexableObject, N: IndexableAndSerializableObject> IntoOperations
                 batch.assert_value(Field::ARCHIVE, &current);
                 for (current, change) in current.inner.index_values().zip(changes.index_values()) {
                     if current != change {
-                        merge_index(batch, current, change, self.changed_by, self.tenant_id)?;
+                        merge_index(batch, current, change, self.changed_by, owners)?;
                     } else {
                         match current {
                             IndexValue::LogContainer { sync_collection } => {
@@ -388,7 +416,7 @@ impl<C: IndexableObject, N: IndexableAndSerializableObject> IntoOperations
                 // Deletion
                 batch.assert_value(Field::ARCHIVE, &current);
                 for item in current.inner.index_values() {
-                    build_index(batch, item, self.changed_by, self.tenant_id, false);
+                    build_index(batch, item, self.changed_by, owners, false);
                 }
 
                 batch.clear(Field::ARCHIVE);
@@ -400,11 +428,30 @@ impl<C: IndexableObject, N: IndexableAndSerializableObject> IntoOperations
     }
 }
 
+/// Aggregates that storage usage is rolled up into besides the account.
+#[derive(Debug, Clone, Copy)]
+struct QuotaOwners {
+    tenant_id: Option<u32>,
+    domain_id: Option<u32>,
+}
+
+impl QuotaOwners {
+    fn add(&self, batch: &mut BatchBuilder, class: fn(u32) -> DirectoryClass, value: i64) {
+        if let Some(account_id) = batch.last_account_id() {
+            batch.add(class(account_id), value);
+        }
+
+        for id in [self.tenant_id, self.domain_id].into_iter().flatten() {
+            batch.add(class(id), value);
+        }
+    }
+}
+
 fn build_index(
     batch: &mut BatchBuilder,
     item: IndexValue<'_>,
     changed_by: u32,
-    tenant_id: Option<u32>,
+    owners: QuotaOwners,
     set: bool,
 ) {
     match item {
@@ -498,14 +545,14 @@ fn build_index(
         }
         IndexValue::Quota { used } => {
             let value = if set { used as i64 } else { -(used as i64) };
-
-            if let Some(account_id) = batch.last_account_id() {
-                batch.add(DirectoryClass::UsedQuota(account_id), value);
-            }
-
-            if let Some(tenant_id) = tenant_id {
-                batch.add(DirectoryClass::UsedQuota(tenant_id), value);
-            }
+            owners.add(batch, DirectoryClass::UsedQuota, value);
+        }
+        IndexValue::MessageCount => {
+            owners.add(
+                batch,
+                DirectoryClass::MessageCount,
+                if set { 1 } else { -1 },
+            );
         }
         IndexValue::LogItem {
             sync_collection,
@@ -540,7 +587,7 @@ fn merge_index(
     current: IndexValue<'_>,
     change: IndexValue<'_>,
     changed_by: u32,
-    tenant_id: Option<u32>,
+    owners: QuotaOwners,
 ) -> trc::Result<()> {
     match (current, change) {
         (
@@ -721,14 +768,11 @@ fn merge_index(
             }
         }
         (IndexValue::Quota { used: old_used }, IndexValue::Quota { used: new_used }) => {
-            let value = new_used as i64 - old_used as i64;
-            if let Some(account_id) = batch.last_account_id() {
-                batch.add(DirectoryClass::UsedQuota(account_id), value);
-            }
-
-            if let Some(tenant_id) = tenant_id {
-                batch.add(DirectoryClass::UsedQuota(tenant_id), value);
-            }
+            owners.add(
+                batch,
+                DirectoryClass::UsedQuota,
+                new_used as i64 - old_used as i64,
+            );
         }
         (
             IndexValue::LogItem {
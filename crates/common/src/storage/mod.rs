@@ -4,6 +4,16 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+pub mod avatar;
+pub mod backup;
 pub mod blob;
+pub mod blob_migration;
+pub mod deletion;
+pub mod encryption;
+pub mod erasure;
+pub mod export;
+pub mod import;
 pub mod index;
+pub mod quota;
+pub mod reindex;
 pub mod state;
@@ -0,0 +1,316 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use ahash::{AHashMap, AHashSet};
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use parking_lot::Mutex;
+use store::write::DirectoryClass;
+use trc::AddContext;
+use types::type_state::{DataType, StateChange};
+
+use crate::{Server, auth::AccessToken, ipc::PushNotification};
+
+/// Usage is tracked in steps of one percent of the limit, so that quota
+/// state changes are only pushed when usage changes materially.
+const QUOTA_STEPS: u64 = 100;
+
+/// Last usage step notified for each account and tenant on this node.
+#[derive(Debug, Default)]
+pub struct QuotaSteps {
+    steps: Mutex<AHashMap<u32, u64>>,
+}
+
+impl QuotaSteps {
+    /// Records the current step, returning whether it differs from the
+    /// previously recorded one.
+    pub fn update(&self, id: u32, step: u64) -> bool {
+        self.steps
+            .lock()
+            .insert(id, step)
+            .is_some_and(|prev| prev != step)
+    }
+}
+
+/// Storage used by the accounts of a tenant, split between the personal
+/// accounts of its users and the accounts shared among them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantUsage {
+    pub personal: i64,
+    pub shared_mailboxes: i64,
+    pub groups: i64,
+}
+
+impl TenantUsage {
+    pub fn shared(&self) -> i64 {
+        self.shared_mailboxes + self.groups
+    }
+
+    pub fn total(&self) -> i64 {
+        self.personal + self.shared()
+    }
+}
+
+/// Messages and storage attributed to a domain, which covers the accounts
+/// whose primary address is on the domain, including shared mailboxes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DomainUsage {
+    pub messages: i64,
+    pub bytes: i64,
+}
+
+pub fn quota_step(used: i64, limit: u64) -> u64 {
+    (used.max(0) as u64)
+        .saturating_mul(QUOTA_STEPS)
+        .checked_div(limit)
+        .map_or(0, |step| step.min(QUOTA_STEPS))
+}
+
+impl Server {
+    /// Returns the JMAP Quota state of an account, which changes whenever the
+    /// limits or the usage steps of the account or its tenant change.
+    pub async fn quota_state(&self, access_token: &AccessToken) -> trc::Result<u64> {
+        let mut state = [0u64; 4];
+        if access_token.quota > 0 {
+            state[0] = access_token.quota;
+            state[1] = quota_step(
+                self.get_used_quota(access_token.primary_id()).await?,
+                access_token.quota,
+            );
+        }
+        if let Some(tenant) = access_token.tenant.filter(|tenant| tenant.quota > 0) {
+            state[2] = tenant.quota;
+            state[3] = quota_step(self.get_used_quota(tenant.id).await?, tenant.quota);
+        }
+
+        Ok(xxhash_rust::xxh3::xxh3_64(
+            &state
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Adds up the usage counters of the individuals and groups of a tenant,
+    /// attributing shared mailboxes to shared storage.
+    pub async fn tenant_usage(&self, tenant_id: u32) -> trc::Result<TenantUsage> {
+        self.tenants_usage(&AHashSet::from_iter([tenant_id]))
+            .await
+            .map(|mut usage| usage.remove(&tenant_id).unwrap_or_default())
+    }
+
+    /// Adds up the usage of several tenants, finding their accounts with a
+    /// single scan of the directory.
+    pub async fn tenants_usage(
+        &self,
+        tenant_ids: &AHashSet<u32>,
+    ) -> trc::Result<AHashMap<u32, TenantUsage>> {
+        let mut results = AHashMap::with_capacity(tenant_ids.len());
+        for (tenant_id, accounts) in self
+            .store()
+            .tenant_principals(&[Type::Individual, Type::Group], tenant_ids)
+            .await
+            .caused_by(trc::location!())?
+        {
+            let shared_mailboxes = self
+                .tenant_shared_mailboxes(tenant_id)
+                .await?
+                .into_iter()
+                .map(|mailbox| mailbox.account_id)
+                .collect::<AHashSet<_>>();
+            let mut usage = TenantUsage::default();
+
+            for account in accounts {
+                let used = self.get_used_quota(account.id).await?.max(0);
+                if account.typ == Type::Group {
+                    usage.groups += used;
+                } else if shared_mailboxes.contains(&account.id) {
+                    usage.shared_mailboxes += used;
+                } else {
+                    usage.personal += used;
+                }
+            }
+
+            results.insert(tenant_id, usage);
+        }
+
+        Ok(results)
+    }
+
+    pub async fn get_message_count(&self, account_id: u32) -> trc::Result<i64> {
+        self.core
+            .storage
+            .data
+            .get_counter(DirectoryClass::MessageCount(account_id))
+            .await
+            .add_context(|err| err.caused_by(trc::location!()).account_id(account_id))
+    }
+
+    /// Reads the rollup of a domain, which is updated as its accounts write
+    /// data and when an account's primary address moves between domains.
+    pub async fn domain_usage(&self, domain_id: u32) -> trc::Result<DomainUsage> {
+        Ok(DomainUsage {
+            messages: self.get_message_count(domain_id).await?.max(0),
+            bytes: self.get_used_quota(domain_id).await?.max(0),
+        })
+    }
+
+    /// Domain of the primary address of an account, if it is a local domain.
+    pub async fn account_domain_id(&self, account_id: u32) -> trc::Result<Option<u32>> {
+        let store = self.store();
+        match store
+            .get_principal(account_id)
+            .await
+            .caused_by(trc::location!())?
+            .as_ref()
+            .and_then(|principal| principal.primary_email())
+        {
+            Some(email) => store.get_domain_id(email).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Adds up the counters of the accounts whose primary address is on a
+    /// domain, used to rebuild the rollup of the domain.
+    pub async fn recount_domain_usage(&self, domain_id: u32) -> trc::Result<DomainUsage> {
+        let store = self.store();
+        let mut usage = DomainUsage::default();
+        let Some(domain) = store
+            .get_principal(domain_id)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|domain| domain.typ() == Type::Domain)
+        else {
+            return Ok(usage);
+        };
+
+        for account in store
+            .list_principals(
+                None,
+                domain.tenant(),
+                &[Type::Individual, Type::Group],
+                true,
+                0,
+                0,
+            )
+            .await
+            .caused_by(trc::location!())?
+            .items
+        {
+            if account
+                .primary_email()
+                .and_then(|email| email.rsplit_once('@'))
+                .is_some_and(|(_, name)| name.eq_ignore_ascii_case(domain.name()))
+            {
+                usage.messages += self.get_message_count(account.id()).await?.max(0);
+                usage.bytes += self.get_used_quota(account.id()).await?.max(0);
+            }
+        }
+
+        Ok(usage)
+    }
+
+    /// Pushes a Quota state change to the account, or to every account of its
+    /// tenant, when usage crosses a step. Only accounts with an active access
+    /// token are checked, as the usage of other accounts is read on request.
+    /// Notifying the accounts of a tenant runs as a separate task.
+    pub async fn notify_quota_changes(&self, account_id: u32) -> trc::Result<()> {
+        let Some(access_token) = self.inner.cache.access_tokens.get(&account_id) else {
+            return Ok(());
+        };
+        let steps = &self.inner.data.quota_steps;
+
+        if access_token.quota > 0
+            && steps.update(
+                account_id,
+                quota_step(self.get_used_quota(account_id).await?, access_token.quota),
+            )
+        {
+            let change_id = self.quota_state(&access_token).await?;
+            self.broadcast_push_notification(PushNotification::StateChange(
+                StateChange::new(account_id)
+                    .with_change(DataType::Quota)
+                    .with_change_id(change_id),
+            ))
+            .await;
+        }
+
+        if let Some(tenant) = access_token.tenant.filter(|tenant| tenant.quota > 0)
+            && steps.update(
+                tenant.id,
+                quota_step(self.get_used_quota(tenant.id).await?, tenant.quota),
+            )
+        {
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = server.notify_tenant_quota_change(tenant.id).await {
+                    trc::error!(err.caused_by(trc::location!()));
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn notify_tenant_quota_change(&self, tenant_id: u32) -> trc::Result<()> {
+        for member_id in self
+            .store()
+            .principal_ids(Some(Type::Individual), Some(tenant_id))
+            .await
+            .caused_by(trc::location!())?
+        {
+            let access_token = self
+                .get_access_token(member_id)
+                .await
+                .caused_by(trc::location!())?;
+            let change_id = self.quota_state(&access_token).await?;
+            self.broadcast_push_notification(PushNotification::StateChange(
+                StateChange::new(member_id)
+                    .with_change(DataType::Quota)
+                    .with_change_id(change_id),
+            ))
+            .await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaSteps, TenantUsage, quota_step};
+
+    #[test]
+    fn quota_steps() {
+        assert_eq!(quota_step(0, 0), 0);
+        assert_eq!(quota_step(500, 0), 0);
+        assert_eq!(quota_step(-10, 1000), 0);
+        assert_eq!(quota_step(9, 1000), 0);
+        assert_eq!(quota_step(10, 1000), 1);
+        assert_eq!(quota_step(999, 1000), 99);
+        assert_eq!(quota_step(1000, 1000), 100);
+        assert_eq!(quota_step(5000, 1000), 100);
+
+        // The first observation is only recorded
+        let steps = QuotaSteps::default();
+        assert!(!steps.update(1, 10));
+        assert!(!steps.update(1, 10));
+        assert!(steps.update(1, 11));
+        assert!(!steps.update(2, 11));
+        assert!(steps.update(1, 10));
+    }
+
+    #[test]
+    fn tenant_usage() {
+        let usage = TenantUsage {
+            personal: 100,
+            shared_mailboxes: 20,
+            groups: 5,
+        };
+        assert_eq!(usage.shared(), 25);
+        assert_eq!(usage.total(), 125);
+        assert_eq!(TenantUsage::default().total(), 0);
+    }
+}
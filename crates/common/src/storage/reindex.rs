@@ -0,0 +1,170 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use store::write::now;
+
+use crate::jobs::{Job, JobRegistry};
+
+/// Full-text reindex jobs running or finished on this node, kept so that
+/// their final counts can be retrieved.
+pub type ReindexJobs = JobRegistry<ReindexJob>;
+
+#[derive(Debug)]
+pub struct ReindexJob {
+    pub id: u64,
+    /// Account or tenant the job was started for.
+    pub owner_id: u32,
+    state: Mutex<ReindexJobState>,
+}
+
+#[derive(Debug)]
+struct ReindexJobState {
+    status: ReindexJobStatus,
+    finished: Option<u64>,
+    error: Option<String>,
+    checkpoint: ReindexCheckpoint,
+}
+
+/// Progress of a reindex job, persisted after every batch so that
+/// interrupted jobs can be resumed after a restart.
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Default, Debug, Clone, PartialEq, Eq,
+)]
+pub struct ReindexCheckpoint {
+    pub id: u64,
+    pub owner_id: u32,
+    pub started: u64,
+    pub concurrency: u32,
+    pub total_accounts: u32,
+    /// Accounts that have not been fully reindexed yet.
+    pub pending: Vec<ReindexAccount>,
+    pub indexed: u64,
+    pub failed: u64,
+}
+
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Default, Debug, Clone, PartialEq, Eq,
+)]
+pub struct ReindexAccount {
+    pub account_id: u32,
+    pub next_document_id: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReindexJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReindexSummary {
+    pub id: String,
+    pub status: ReindexJobStatus,
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub total_accounts: u32,
+    pub completed_accounts: u32,
+    pub indexed: u64,
+    pub failed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ReindexJobs {
+    /// Registers a new or resumed job, unless the owner already has one running.
+    pub fn start(&self, checkpoint: ReindexCheckpoint) -> Option<Arc<ReindexJob>> {
+        let owner_id = checkpoint.owner_id;
+        self.register(
+            ReindexJob {
+                id: checkpoint.id,
+                owner_id,
+                state: Mutex::new(ReindexJobState {
+                    status: ReindexJobStatus::Running,
+                    finished: None,
+                    error: None,
+                    checkpoint,
+                }),
+            },
+            |job| job.owner_id == owner_id,
+        )
+    }
+}
+
+impl Job for ReindexJob {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().status == ReindexJobStatus::Running
+    }
+}
+
+impl ReindexJob {
+    pub fn checkpoint(&self) -> ReindexCheckpoint {
+        self.state.lock().checkpoint.clone()
+    }
+
+    /// Records a reindexed batch of messages from an account.
+    pub fn record_batch(&self, account_id: u32, next_document_id: u32, indexed: u64, failed: u64) {
+        let mut state = self.state.lock();
+        let checkpoint = &mut state.checkpoint;
+        checkpoint.indexed += indexed;
+        checkpoint.failed += failed;
+        if let Some(account) = checkpoint
+            .pending
+            .iter_mut()
+            .find(|account| account.account_id == account_id)
+        {
+            account.next_document_id = next_document_id;
+        }
+    }
+
+    pub fn complete_account(&self, account_id: u32) {
+        self.state
+            .lock()
+            .checkpoint
+            .pending
+            .retain(|account| account.account_id != account_id);
+    }
+
+    pub fn complete(&self) {
+        let mut state = self.state.lock();
+        state.status = ReindexJobStatus::Completed;
+        state.finished = Some(now());
+    }
+
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.status = ReindexJobStatus::Failed;
+        state.finished = Some(now());
+        state.error = Some(error);
+    }
+
+    pub fn summary(&self) -> ReindexSummary {
+        let state = self.state.lock();
+        let checkpoint = &state.checkpoint;
+        ReindexSummary {
+            id: self.id.to_string(),
+            status: state.status,
+            started: checkpoint.started,
+            finished: state.finished,
+            total_accounts: checkpoint.total_accounts,
+            completed_accounts: checkpoint
+                .total_accounts
+                .saturating_sub(checkpoint.pending.len() as u32),
+            indexed: checkpoint.indexed,
+            failed: checkpoint.failed,
+            error: state.error.clone(),
+        }
+    }
+}
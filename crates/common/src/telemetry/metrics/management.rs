@@ -0,0 +1,247 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::VecDeque, time::Duration};
+
+use parking_lot::Mutex;
+use store::write::now;
+use trc::{MetricType, atomics::histogram::AtomicHistogram};
+
+use crate::{Server, auth::AccessToken};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ManagementRoute {
+    Queue,
+    Settings,
+    Reports,
+    Principal,
+    Organization,
+    Dns,
+    Store,
+    Reload,
+    Dkim,
+    Update,
+    Logs,
+    SpamFilter,
+    Restart,
+    Oauth,
+    Account,
+    Troubleshoot,
+    Telemetry,
+    Events,
+    Directory,
+    Domain,
+    Metering,
+    Other,
+}
+
+static ROUTE_REQUEST_TIME: [AtomicHistogram<12>; ManagementRoute::COUNT] =
+    [const { AtomicHistogram::<12>::new_short_durations(MetricType::HttpRequestTime) };
+        ManagementRoute::COUNT];
+
+/// Ring buffer holding the most recent slow management requests.
+#[derive(Debug, Default)]
+pub struct SlowRequests {
+    entries: Mutex<VecDeque<SlowRequest>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlowRequest {
+    pub timestamp: u64,
+    pub method: String,
+    pub route: &'static str,
+    pub account_name: String,
+    pub tenant_id: Option<u32>,
+    pub elapsed_ms: u64,
+}
+
+impl ManagementRoute {
+    pub const COUNT: usize = 22;
+    pub const ALL: [ManagementRoute; ManagementRoute::COUNT] = [
+        ManagementRoute::Queue,
+        ManagementRoute::Settings,
+        ManagementRoute::Reports,
+        ManagementRoute::Principal,
+        ManagementRoute::Organization,
+        ManagementRoute::Dns,
+        ManagementRoute::Store,
+        ManagementRoute::Reload,
+        ManagementRoute::Dkim,
+        ManagementRoute::Update,
+        ManagementRoute::Logs,
+        ManagementRoute::SpamFilter,
+        ManagementRoute::Restart,
+        ManagementRoute::Oauth,
+        ManagementRoute::Account,
+        ManagementRoute::Troubleshoot,
+        ManagementRoute::Telemetry,
+        ManagementRoute::Events,
+        ManagementRoute::Directory,
+        ManagementRoute::Domain,
+        ManagementRoute::Metering,
+        ManagementRoute::Other,
+    ];
+
+    pub fn parse(segment: &str) -> Self {
+        match segment {
+            "queue" => ManagementRoute::Queue,
+            "settings" => ManagementRoute::Settings,
+            "reports" => ManagementRoute::Reports,
+            "principal" => ManagementRoute::Principal,
+            "organization" => ManagementRoute::Organization,
+            "dns" => ManagementRoute::Dns,
+            "store" => ManagementRoute::Store,
+            "reload" => ManagementRoute::Reload,
+            "dkim" => ManagementRoute::Dkim,
+            "update" => ManagementRoute::Update,
+            "logs" => ManagementRoute::Logs,
+            "spam-filter" => ManagementRoute::SpamFilter,
+            "restart" => ManagementRoute::Restart,
+            "oauth" => ManagementRoute::Oauth,
+            "account" => ManagementRoute::Account,
+            "troubleshoot" => ManagementRoute::Troubleshoot,
+            "telemetry" => ManagementRoute::Telemetry,
+            "events" => ManagementRoute::Events,
+            "directory" => ManagementRoute::Directory,
+            "domain" => ManagementRoute::Domain,
+            "metering" => ManagementRoute::Metering,
+            _ => ManagementRoute::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManagementRoute::Queue => "/api/queue",
+            ManagementRoute::Settings => "/api/settings",
+            ManagementRoute::Reports => "/api/reports",
+            ManagementRoute::Principal => "/api/principal",
+            ManagementRoute::Organization => "/api/organization",
+            ManagementRoute::Dns => "/api/dns",
+            ManagementRoute::Store => "/api/store",
+            ManagementRoute::Reload => "/api/reload",
+            ManagementRoute::Dkim => "/api/dkim",
+            ManagementRoute::Update => "/api/update",
+            ManagementRoute::Logs => "/api/logs",
+            ManagementRoute::SpamFilter => "/api/spam-filter",
+            ManagementRoute::Restart => "/api/restart",
+            ManagementRoute::Oauth => "/api/oauth",
+            ManagementRoute::Account => "/api/account",
+            ManagementRoute::Troubleshoot => "/api/troubleshoot",
+            ManagementRoute::Telemetry => "/api/telemetry",
+            ManagementRoute::Events => "/api/events",
+            ManagementRoute::Directory => "/api/directory",
+            ManagementRoute::Domain => "/api/domain",
+            ManagementRoute::Metering => "/api/metering",
+            ManagementRoute::Other => "/api/*",
+        }
+    }
+
+    pub fn request_time(&self) -> &'static AtomicHistogram<12> {
+        &ROUTE_REQUEST_TIME[*self as usize]
+    }
+}
+
+impl SlowRequests {
+    pub fn push(&self, request: SlowRequest, max_entries: usize) {
+        let mut entries = self.entries.lock();
+        while entries.len() >= max_entries.max(1) {
+            entries.pop_front();
+        }
+        entries.push_back(request);
+    }
+
+    /// Returns up to `limit` records, most recent first.
+    pub fn recent(&self, limit: usize) -> Vec<SlowRequest> {
+        self.entries
+            .lock()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Server {
+    pub fn record_management_request(
+        &self,
+        route: ManagementRoute,
+        method: &hyper::Method,
+        access_token: &AccessToken,
+        elapsed: Duration,
+    ) {
+        let elapsed_ms = elapsed.as_millis() as u64;
+        route.request_time().observe(elapsed_ms);
+
+        let config = &self.core.jmap.http_slow_requests;
+        if config
+            .threshold
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            let tenant_id = access_token.tenant.map(|tenant| tenant.id);
+
+            trc::event!(
+                Http(trc::HttpEvent::SlowRequest),
+                Path = route.as_str(),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                Id = tenant_id,
+                Elapsed = elapsed,
+            );
+
+            self.inner.data.slow_requests.push(
+                SlowRequest {
+                    timestamp: now(),
+                    method: method.as_str().to_string(),
+                    route: route.as_str(),
+                    account_name: access_token.name.clone(),
+                    tenant_id,
+                    elapsed_ms,
+                },
+                config.max_entries,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManagementRoute, SlowRequest, SlowRequests};
+
+    #[test]
+    fn slow_request_ring_buffer() {
+        let slow_requests = SlowRequests::default();
+        for elapsed_ms in 0..5 {
+            slow_requests.push(
+                SlowRequest {
+                    timestamp: elapsed_ms,
+                    method: "GET".to_string(),
+                    route: ManagementRoute::parse("principal").as_str(),
+                    account_name: "admin".to_string(),
+                    tenant_id: None,
+                    elapsed_ms,
+                },
+                3,
+            );
+        }
+
+        assert_eq!(
+            slow_requests
+                .recent(10)
+                .into_iter()
+                .map(|r| r.elapsed_ms)
+                .collect::<Vec<_>>(),
+            vec![4, 3, 2]
+        );
+        assert_eq!(slow_requests.recent(1)[0].route, "/api/principal");
+
+        for (idx, route) in ManagementRoute::ALL.iter().enumerate() {
+            assert_eq!(*route as usize, idx);
+        }
+        assert_eq!(ManagementRoute::parse("unknown"), ManagementRoute::Other);
+    }
+}
@@ -0,0 +1,501 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+
+use ahash::{AHashMap, AHashSet};
+use parking_lot::Mutex;
+use serde::Serialize;
+use store::{
+    IterateParams, Store, U32_LEN, U64_LEN, ValueKey,
+    write::{
+        BatchBuilder, TelemetryClass, ValueClass,
+        key::{DeserializeBigEndian, KeySerializer},
+        now,
+    },
+};
+use trc::AddContext;
+use utils::codec::leb128::Leb128Reader;
+
+const FLAG_ESTIMATED: u64 = 0x01;
+const DAY_SECS: u64 = 86400;
+
+// Longer outages are not gap filled, their usage is reported in the first sample
+const MAX_GAP_SECS: u64 = 31 * DAY_SECS;
+
+/// Usage of a tenant during one sampling interval, as seen by one node.
+/// Counters hold the increase since the previous sample while storage and
+/// active users are point-in-time readings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeteringSample {
+    pub timestamp: u64,
+    pub tenant_id: u32,
+    pub node_id: u64,
+    pub estimated: bool,
+    pub active_users: u64,
+    pub storage_bytes: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteringDay {
+    pub date: u64,
+    pub tenant_id: u32,
+    pub active_users: u64,
+    pub storage_peak: u64,
+    pub storage_average: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bandwidth_in: u64,
+    pub bandwidth_out: u64,
+    pub samples: u64,
+    pub estimated: bool,
+}
+
+/// Current readings of a tenant, taken by the sampler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeteringReading {
+    pub active_users: u64,
+    pub storage_bytes: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Last readings taken on this node, used to turn the in-memory tenant
+/// counters into per-interval increases.
+#[derive(Debug, Default)]
+pub struct MeteringHistory {
+    pub last_sample: Option<u64>,
+    tenants: AHashMap<u32, MeteringReading>,
+}
+
+pub type SharedMeteringHistory = Arc<Mutex<MeteringHistory>>;
+
+pub trait MeteringStore: Sync + Send {
+    fn write_metering_samples(
+        &self,
+        samples: Vec<MeteringSample>,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+    fn query_metering_samples(
+        &self,
+        from_timestamp: u64,
+        to_timestamp: u64,
+    ) -> impl Future<Output = trc::Result<Vec<MeteringSample>>> + Send;
+    fn metering_cursor(
+        &self,
+        node_id: u64,
+    ) -> impl Future<Output = trc::Result<Option<u64>>> + Send;
+    fn purge_metering_samples(
+        &self,
+        period: Duration,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl MeteringStore for Store {
+    async fn write_metering_samples(&self, samples: Vec<MeteringSample>) -> trc::Result<()> {
+        let mut cursors = AHashMap::new();
+        let mut batch = BatchBuilder::new();
+        for sample in samples {
+            let cursor = cursors.entry(sample.node_id).or_insert(sample.timestamp);
+            *cursor = (*cursor).max(sample.timestamp);
+
+            batch.set(
+                ValueClass::Telemetry(TelemetryClass::MeteringSample {
+                    timestamp: sample.timestamp,
+                    tenant_id: sample.tenant_id,
+                    node_id: sample.node_id,
+                }),
+                KeySerializer::new(U64_LEN * 2)
+                    .write_leb128(if sample.estimated { FLAG_ESTIMATED } else { 0 })
+                    .write_leb128(sample.active_users)
+                    .write_leb128(sample.storage_bytes)
+                    .write_leb128(sample.messages_sent)
+                    .write_leb128(sample.messages_received)
+                    .write_leb128(sample.bytes_sent)
+                    .write_leb128(sample.bytes_received)
+                    .finalize(),
+            );
+
+            if batch.is_large_batch() {
+                self.write(batch.build_all())
+                    .await
+                    .caused_by(trc::location!())?;
+                batch = BatchBuilder::new();
+            }
+        }
+
+        // Cursors are written last so an interrupted write is retried as a gap
+        for (node_id, timestamp) in cursors {
+            batch.set(
+                ValueClass::Telemetry(TelemetryClass::MeteringCursor { node_id }),
+                timestamp.to_be_bytes().to_vec(),
+            );
+        }
+        if !batch.is_empty() {
+            self.write(batch.build_all())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(())
+    }
+
+    async fn query_metering_samples(
+        &self,
+        from_timestamp: u64,
+        to_timestamp: u64,
+    ) -> trc::Result<Vec<MeteringSample>> {
+        let mut samples = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Telemetry(TelemetryClass::MeteringSample {
+                    timestamp: from_timestamp,
+                    tenant_id: 0,
+                    node_id: 0,
+                })),
+                ValueKey::from(ValueClass::Telemetry(TelemetryClass::MeteringSample {
+                    timestamp: to_timestamp,
+                    tenant_id: u32::MAX,
+                    node_id: u64::MAX,
+                })),
+            )
+            .ascending(),
+            |key, value| {
+                let mut values = [0u64; 7];
+                let mut bytes = value;
+                for item in values.iter_mut() {
+                    let (read, bytes_read) = bytes.read_leb128::<u64>().ok_or_else(|| {
+                        trc::Error::corrupted_key(key, value.into(), trc::location!())
+                    })?;
+                    *item = read;
+                    bytes = bytes.get(bytes_read..).unwrap_or_default();
+                }
+                let [
+                    flags,
+                    active_users,
+                    storage_bytes,
+                    messages_sent,
+                    messages_received,
+                    bytes_sent,
+                    bytes_received,
+                ] = values;
+
+                samples.push(MeteringSample {
+                    timestamp: key.deserialize_be_u64(U64_LEN + 1)?,
+                    tenant_id: key.deserialize_be_u32(U64_LEN * 2 + 1)?,
+                    node_id: key.deserialize_be_u64(U64_LEN * 2 + U32_LEN + 1)?,
+                    estimated: flags & FLAG_ESTIMATED != 0,
+                    active_users,
+                    storage_bytes,
+                    messages_sent,
+                    messages_received,
+                    bytes_sent,
+                    bytes_received,
+                });
+
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        Ok(samples)
+    }
+
+    async fn metering_cursor(&self, node_id: u64) -> trc::Result<Option<u64>> {
+        self.get_value::<u64>(ValueKey::from(ValueClass::Telemetry(
+            TelemetryClass::MeteringCursor { node_id },
+        )))
+        .await
+        .caused_by(trc::location!())
+    }
+
+    async fn purge_metering_samples(&self, period: Duration) -> trc::Result<()> {
+        self.delete_range(
+            ValueKey::from(ValueClass::Telemetry(TelemetryClass::MeteringSample {
+                timestamp: 0,
+                tenant_id: 0,
+                node_id: 0,
+            })),
+            ValueKey::from(ValueClass::Telemetry(TelemetryClass::MeteringSample {
+                timestamp: now().saturating_sub(period.as_secs()),
+                tenant_id: 0,
+                node_id: 0,
+            })),
+        )
+        .await
+        .caused_by(trc::location!())
+    }
+}
+
+impl MeteringHistory {
+    /// Builds the samples for `timestamp` from the current readings. When
+    /// runs were missed since the last sample, the gap is filled with
+    /// estimated samples: counter increases are spread evenly and storage
+    /// is interpolated between the previous and current readings.
+    pub fn build_samples(
+        &mut self,
+        node_id: u64,
+        interval: u64,
+        timestamp: u64,
+        readings: Vec<(u32, MeteringReading)>,
+    ) -> Vec<MeteringSample> {
+        let interval = interval.max(1);
+        let missing = match self.last_sample {
+            Some(last_sample) if timestamp > last_sample + interval => {
+                ((timestamp - last_sample).min(MAX_GAP_SECS) / interval).saturating_sub(1)
+            }
+            _ => 0,
+        };
+
+        let mut samples = Vec::with_capacity(readings.len() * (missing as usize + 1));
+        let mut tenant_ids = AHashSet::with_capacity(readings.len());
+        for (tenant_id, reading) in readings {
+            tenant_ids.insert(tenant_id);
+            let previous = self.tenants.insert(tenant_id, reading);
+            let increase = |current: u64, previous: Option<u64>| match previous {
+                // Counters start from zero when the server restarts
+                Some(previous) if previous <= current => current - previous,
+                _ => current,
+            };
+            let mut sample = MeteringSample {
+                timestamp,
+                tenant_id,
+                node_id,
+                estimated: missing > 0,
+                active_users: reading.active_users,
+                storage_bytes: reading.storage_bytes,
+                messages_sent: increase(reading.messages_sent, previous.map(|p| p.messages_sent)),
+                messages_received: increase(
+                    reading.messages_received,
+                    previous.map(|p| p.messages_received),
+                ),
+                bytes_sent: increase(reading.bytes_sent, previous.map(|p| p.bytes_sent)),
+                bytes_received: increase(
+                    reading.bytes_received,
+                    previous.map(|p| p.bytes_received),
+                ),
+            };
+
+            if missing > 0 {
+                let parts = missing + 1;
+                let previous = previous.unwrap_or(reading);
+                for part in 1..parts {
+                    let storage_bytes = (previous.storage_bytes as i128
+                        + (reading.storage_bytes as i128 - previous.storage_bytes as i128)
+                            * part as i128
+                            / parts as i128) as u64;
+                    samples.push(MeteringSample {
+                        timestamp: timestamp - (parts - part) * interval,
+                        tenant_id,
+                        node_id,
+                        estimated: true,
+                        active_users: previous.active_users,
+                        storage_bytes,
+                        messages_sent: sample.messages_sent / parts,
+                        messages_received: sample.messages_received / parts,
+                        bytes_sent: sample.bytes_sent / parts,
+                        bytes_received: sample.bytes_received / parts,
+                    });
+                }
+
+                // The current sample keeps the remainder
+                let filled = parts - 1;
+                sample.messages_sent -= sample.messages_sent / parts * filled;
+                sample.messages_received -= sample.messages_received / parts * filled;
+                sample.bytes_sent -= sample.bytes_sent / parts * filled;
+                sample.bytes_received -= sample.bytes_received / parts * filled;
+            }
+
+            samples.push(sample);
+        }
+
+        self.tenants
+            .retain(|tenant_id, _| tenant_ids.contains(tenant_id));
+        self.last_sample = Some(timestamp);
+
+        samples
+    }
+}
+
+/// Combines the samples of all nodes into one row per tenant and UTC day.
+/// Counters are added up across nodes while gauges, which every node reads
+/// from the shared store, use the highest reading.
+pub fn daily_metering(samples: &[MeteringSample]) -> Vec<MeteringDay> {
+    let mut intervals: BTreeMap<(u64, u32, u64), MeteringSample> = BTreeMap::new();
+    for sample in samples {
+        let date = sample.timestamp - sample.timestamp % DAY_SECS;
+        let interval = intervals
+            .entry((date, sample.tenant_id, sample.timestamp))
+            .or_default();
+        interval.estimated |= sample.estimated;
+        interval.active_users = interval.active_users.max(sample.active_users);
+        interval.storage_bytes = interval.storage_bytes.max(sample.storage_bytes);
+        interval.messages_sent += sample.messages_sent;
+        interval.messages_received += sample.messages_received;
+        interval.bytes_sent += sample.bytes_sent;
+        interval.bytes_received += sample.bytes_received;
+    }
+
+    let mut days: Vec<MeteringDay> = Vec::new();
+    let mut storage_total = 0u128;
+    for ((date, tenant_id, _), interval) in intervals {
+        let day = match days.last_mut() {
+            Some(day) if day.date == date && day.tenant_id == tenant_id => day,
+            _ => {
+                storage_total = 0;
+                days.push(MeteringDay {
+                    date,
+                    tenant_id,
+                    ..Default::default()
+                });
+                days.last_mut().unwrap()
+            }
+        };
+        storage_total += interval.storage_bytes as u128;
+        day.samples += 1;
+        day.estimated |= interval.estimated;
+        day.active_users = day.active_users.max(interval.active_users);
+        day.storage_peak = day.storage_peak.max(interval.storage_bytes);
+        day.storage_average = (storage_total / day.samples as u128) as u64;
+        day.messages_sent += interval.messages_sent;
+        day.messages_received += interval.messages_received;
+        day.bandwidth_in += interval.bytes_received;
+        day.bandwidth_out += interval.bytes_sent;
+    }
+
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MeteringDay, MeteringHistory, MeteringReading, MeteringSample, daily_metering};
+
+    const HOUR: u64 = 3600;
+
+    fn reading(storage_bytes: u64, messages_received: u64) -> MeteringReading {
+        MeteringReading {
+            active_users: 2,
+            storage_bytes,
+            messages_received,
+            bytes_received: messages_received * 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fill_gaps() {
+        let mut history = MeteringHistory::default();
+        let samples = history.build_samples(1, HOUR, 10 * HOUR, vec![(7, reading(1000, 4))]);
+        assert_eq!(samples.len(), 1);
+        assert!(!samples[0].estimated);
+        assert_eq!(samples[0].messages_received, 4);
+
+        // Next run only records the increase
+        let samples = history.build_samples(1, HOUR, 11 * HOUR, vec![(7, reading(1000, 6))]);
+        assert_eq!(samples.len(), 1);
+        assert!(!samples[0].estimated);
+        assert_eq!(samples[0].messages_received, 2);
+        assert_eq!(samples[0].bytes_received, 200);
+
+        // Two missed runs are filled with estimated samples
+        let samples = history.build_samples(1, HOUR, 14 * HOUR, vec![(7, reading(4000, 16))]);
+        assert_eq!(
+            samples
+                .iter()
+                .map(|s| (
+                    s.timestamp / HOUR,
+                    s.estimated,
+                    s.storage_bytes,
+                    s.messages_received
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (12, true, 2000, 3),
+                (13, true, 3000, 3),
+                (14, true, 4000, 4)
+            ]
+        );
+
+        // Counters that went backwards were reset by a restart
+        let samples = history.build_samples(1, HOUR, 15 * HOUR, vec![(7, reading(4000, 1))]);
+        assert_eq!(samples[0].messages_received, 1);
+
+        // The next run continues from the last sample
+        assert_eq!(history.last_sample, Some(15 * HOUR));
+    }
+
+    #[test]
+    fn aggregate_days() {
+        let sample =
+            |timestamp: u64, node_id: u64, storage_bytes: u64, messages_sent: u64| MeteringSample {
+                timestamp,
+                tenant_id: 3,
+                node_id,
+                active_users: node_id,
+                storage_bytes,
+                messages_sent,
+                bytes_sent: messages_sent * 10,
+                ..Default::default()
+            };
+        let day = 20000 * 86400;
+        let mut samples = vec![
+            sample(day + HOUR, 1, 100, 1),
+            sample(day + HOUR, 2, 100, 2),
+            sample(day + 2 * HOUR, 1, 300, 4),
+            sample(day + 86400, 1, 50, 1),
+        ];
+        samples[3].estimated = true;
+        samples.push(MeteringSample {
+            tenant_id: 1,
+            ..sample(day + HOUR, 1, 10, 0)
+        });
+
+        assert_eq!(
+            daily_metering(&samples),
+            vec![
+                MeteringDay {
+                    date: day,
+                    tenant_id: 1,
+                    active_users: 1,
+                    storage_peak: 10,
+                    storage_average: 10,
+                    samples: 1,
+                    ..Default::default()
+                },
+                MeteringDay {
+                    date: day,
+                    tenant_id: 3,
+                    active_users: 2,
+                    storage_peak: 300,
+                    storage_average: 200,
+                    messages_sent: 7,
+                    bandwidth_out: 70,
+                    samples: 2,
+                    ..Default::default()
+                },
+                MeteringDay {
+                    date: day + 86400,
+                    tenant_id: 3,
+                    active_users: 1,
+                    storage_peak: 50,
+                    storage_average: 50,
+                    messages_sent: 1,
+                    bandwidth_out: 10,
+                    samples: 1,
+                    estimated: true,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+}
@@ -6,14 +6,20 @@
 
 use prometheus::{
     TextEncoder,
-    proto::{Bucket, Counter, Gauge, Histogram, Metric, MetricFamily, MetricType},
+    proto::{Bucket, Counter, Gauge, Histogram, LabelPair, Metric, MetricFamily, MetricType},
 };
 use trc::{Collector, atomics::histogram::AtomicHistogram};
 
-use crate::Server;
+use crate::{
+    Server,
+    telemetry::{
+        metrics::{management::ManagementRoute, tenant::TenantMetric},
+        tracers::audit::dropped_audit_events,
+    },
+};
 
 impl Server {
-    pub async fn export_prometheus_metrics(&self) -> trc::Result<String> {
+    pub fn export_prometheus_metrics(&self) -> trc::Result<String> {
         let mut metrics = Vec::new();
 
         // SPDX-SnippetBegin
@@ -56,6 +62,68 @@ impl Server {
             metrics.push(metric);
         }
 
+        // Add management API request times by route
+        let route_histograms = ManagementRoute::ALL
+            .iter()
+            .filter(|route| route.request_time().is_active())
+            .map(|route| with_label(new_histogram(route.request_time()), "route", route.as_str()))
+            .collect::<Vec<_>>();
+        if !route_histograms.is_empty() {
+            let mut metric = MetricFamily::default();
+            metric.set_name("http_management_request_time".into());
+            metric.set_help("Management API request duration by route".into());
+            metric.set_field_type(MetricType::HISTOGRAM);
+            metric.set_metric(route_histograms);
+            metrics.push(metric);
+        }
+
+        // Add audit log drop counter
+        if self.core.storage.audit.is_some() {
+            let mut metric = MetricFamily::default();
+            metric.set_name("audit_dropped_events".into());
+            metric.set_help("Audit events discarded because the write queue was full".into());
+            metric.set_field_type(MetricType::COUNTER);
+            metric.set_metric(vec![new_counter(dropped_audit_events())]);
+            metrics.push(metric);
+        }
+
+        // Add per-tenant counters
+        if self.core.metrics.tenants {
+            let tenants = self.inner.data.tenant_metrics.tenants();
+            for tenant_metric in TenantMetric::ALL {
+                let mut metric = MetricFamily::default();
+                metric.set_name(metric_name(tenant_metric.name()));
+                metric.set_help(tenant_metric.description().into());
+                metric.set_field_type(MetricType::COUNTER);
+                metric.set_metric(
+                    tenants
+                        .iter()
+                        .map(|(tenant_id, counters)| {
+                            with_label(
+                                new_counter(counters.get(tenant_metric)),
+                                "tenant",
+                                tenant_id,
+                            )
+                        })
+                        .collect(),
+                );
+                metrics.push(metric);
+            }
+
+            let bytes_stored = tenants
+                .iter()
+                .map(|(tenant_id, counters)| {
+                    with_label(new_gauge(counters.bytes_stored()), "tenant", tenant_id)
+                })
+                .collect();
+            let mut metric = MetricFamily::default();
+            metric.set_name("tenant_bytes_stored".into());
+            metric.set_help("Bytes stored by tenant accounts".into());
+            metric.set_field_type(MetricType::GAUGE);
+            metric.set_metric(bytes_stored);
+            metrics.push(metric);
+        }
+
         TextEncoder::new().encode_to_string(&metrics).map_err(|e| {
             trc::EventType::Telemetry(trc::TelemetryEvent::OtelExporterError).reason(e)
         })
@@ -75,6 +143,14 @@ fn metric_name(id: impl AsRef<str>) -> String {
     name
 }
 
+fn with_label(mut m: Metric, name: &str, value: impl ToString) -> Metric {
+    let mut label = LabelPair::default();
+    label.set_name(name.into());
+    label.set_value(value.to_string());
+    m.set_label(vec![label]);
+    m
+}
+
 fn new_counter(value: u64) -> Metric {
     let mut m = Metric::default();
     let mut counter = Counter::default();
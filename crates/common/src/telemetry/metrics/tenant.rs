@@ -0,0 +1,250 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use ahash::AHashMap;
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use parking_lot::RwLock;
+use trc::AddContext;
+
+use crate::Server;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TenantMetric {
+    MessagesReceived,
+    MessagesDelivered,
+    MessagesBounced,
+    AuthSuccess,
+    AuthFailure,
+    ApiRequests,
+    MessagesSent,
+    BytesReceived,
+    BytesSent,
+}
+
+/// Per-tenant counters, keyed by tenant id so they are not affected by renames.
+#[derive(Debug, Default)]
+pub struct TenantMetrics {
+    tenants: RwLock<AHashMap<u32, Arc<TenantCounters>>>,
+}
+
+#[derive(Debug, Default)]
+pub struct TenantCounters {
+    counters: [AtomicU64; TenantMetric::COUNT],
+    /// Updated periodically by the housekeeper, reading the used quota of
+    /// every tenant on each scrape would be too expensive.
+    bytes_stored: AtomicU64,
+}
+
+impl TenantMetric {
+    pub const COUNT: usize = 9;
+    pub const ALL: [TenantMetric; TenantMetric::COUNT] = [
+        TenantMetric::MessagesReceived,
+        TenantMetric::MessagesDelivered,
+        TenantMetric::MessagesBounced,
+        TenantMetric::AuthSuccess,
+        TenantMetric::AuthFailure,
+        TenantMetric::ApiRequests,
+        TenantMetric::MessagesSent,
+        TenantMetric::BytesReceived,
+        TenantMetric::BytesSent,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TenantMetric::MessagesReceived => "tenant.messages-received",
+            TenantMetric::MessagesDelivered => "tenant.messages-delivered",
+            TenantMetric::MessagesBounced => "tenant.messages-bounced",
+            TenantMetric::AuthSuccess => "tenant.auth-success",
+            TenantMetric::AuthFailure => "tenant.auth-failure",
+            TenantMetric::ApiRequests => "tenant.api-requests",
+            TenantMetric::MessagesSent => "tenant.messages-sent",
+            TenantMetric::BytesReceived => "tenant.bytes-received",
+            TenantMetric::BytesSent => "tenant.bytes-sent",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TenantMetric::MessagesReceived => "Messages received by tenant accounts",
+            TenantMetric::MessagesDelivered => "Recipients delivered for messages sent by tenant",
+            TenantMetric::MessagesBounced => "Recipients bounced for messages sent by tenant",
+            TenantMetric::AuthSuccess => "Successful authentications by tenant accounts",
+            TenantMetric::AuthFailure => "Failed authentications by tenant accounts",
+            TenantMetric::ApiRequests => "Management API requests by tenant accounts",
+            TenantMetric::MessagesSent => "Messages sent by tenant with at least one delivery",
+            TenantMetric::BytesReceived => "Bytes of messages received by tenant accounts",
+            TenantMetric::BytesSent => "Bytes of messages sent by tenant",
+        }
+    }
+}
+
+impl TenantMetrics {
+    pub fn increment(&self, tenant_id: u32, metric: TenantMetric, value: u64) {
+        self.counters(tenant_id).counters[metric as usize].fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn set_bytes_stored(&self, tenant_id: u32, value: u64) {
+        self.counters(tenant_id)
+            .bytes_stored
+            .store(value, Ordering::Relaxed);
+    }
+
+    fn counters(&self, tenant_id: u32) -> Arc<TenantCounters> {
+        let counters = self.tenants.read().get(&tenant_id).cloned();
+        match counters {
+            Some(counters) => counters,
+            None => self.tenants.write().entry(tenant_id).or_default().clone(),
+        }
+    }
+
+    pub fn get(&self, tenant_id: u32) -> Option<Arc<TenantCounters>> {
+        self.tenants.read().get(&tenant_id).cloned()
+    }
+
+    pub fn tenants(&self) -> Vec<(u32, Arc<TenantCounters>)> {
+        let mut tenants = self
+            .tenants
+            .read()
+            .iter()
+            .map(|(tenant_id, counters)| (*tenant_id, counters.clone()))
+            .collect::<Vec<_>>();
+        tenants.sort_unstable_by_key(|(tenant_id, _)| *tenant_id);
+        tenants
+    }
+
+    pub fn remove(&self, tenant_id: u32) {
+        self.tenants.write().remove(&tenant_id);
+    }
+}
+
+impl TenantCounters {
+    pub fn get(&self, metric: TenantMetric) -> u64 {
+        self.counters[metric as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_stored(&self) -> u64 {
+        self.bytes_stored.load(Ordering::Relaxed)
+    }
+}
+
+impl Server {
+    pub fn record_tenant_metric(&self, tenant_id: Option<u32>, metric: TenantMetric, value: u64) {
+        if self.core.metrics.tenants
+            && let Some(tenant_id) = tenant_id
+        {
+            self.inner
+                .data
+                .tenant_metrics
+                .increment(tenant_id, metric, value);
+        }
+    }
+
+    /// Refreshes the bytes stored by each tenant, tenants whose usage cannot
+    /// be read keep their previous value.
+    pub async fn calculate_tenant_bytes_stored(&self) -> trc::Result<()> {
+        for tenant_id in self
+            .store()
+            .principal_ids(Some(Type::Tenant), None)
+            .await
+            .caused_by(trc::location!())?
+        {
+            match self.get_used_quota(tenant_id).await {
+                Ok(used_quota) => {
+                    self.inner
+                        .data
+                        .tenant_metrics
+                        .set_bytes_stored(tenant_id, used_quota.max(0) as u64);
+                }
+                Err(err) => {
+                    trc::error!(
+                        err.details("Failed to obtain tenant usage")
+                            .ctx(trc::Key::TenantId, tenant_id)
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the tenant that owns a principal name or, failing that, the
+    /// domain part of an address.
+    pub async fn tenant_id_for_name(&self, name: &str) -> Option<u32> {
+        if let Some(info) = self
+            .store()
+            .get_principal_info(name)
+            .await
+            .unwrap_or_default()
+        {
+            return info.tenant;
+        }
+
+        let (_, domain) = name.rsplit_once('@')?;
+        self.store()
+            .get_principal_info(&domain.to_lowercase())
+            .await
+            .unwrap_or_default()
+            .and_then(|info| info.tenant)
+    }
+
+    pub async fn tenant_metrics_snapshot(&self, tenant_id: u32) -> trc::Result<serde_json::Value> {
+        let counters = self.inner.data.tenant_metrics.get(tenant_id);
+        let mut snapshot = serde_json::Map::new();
+        for metric in TenantMetric::ALL {
+            snapshot.insert(
+                metric.name().strip_prefix("tenant.").unwrap().to_string(),
+                counters.as_ref().map_or(0, |c| c.get(metric)).into(),
+            );
+        }
+        snapshot.insert(
+            "bytes-stored".to_string(),
+            self.get_used_quota(tenant_id).await?.max(0).into(),
+        );
+
+        Ok(serde_json::Value::Object(snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TenantMetric, TenantMetrics};
+
+    #[test]
+    fn tenant_counters() {
+        let metrics = TenantMetrics::default();
+        assert!(metrics.get(1).is_none());
+
+        metrics.increment(2, TenantMetric::ApiRequests, 1);
+        metrics.increment(1, TenantMetric::MessagesReceived, 3);
+        metrics.increment(1, TenantMetric::MessagesReceived, 2);
+        metrics.increment(1, TenantMetric::AuthFailure, 1);
+        metrics.set_bytes_stored(1, 1024);
+        metrics.set_bytes_stored(1, 512);
+
+        let counters = metrics.get(1).unwrap();
+        assert_eq!(counters.get(TenantMetric::MessagesReceived), 5);
+        assert_eq!(counters.get(TenantMetric::AuthFailure), 1);
+        assert_eq!(counters.get(TenantMetric::ApiRequests), 0);
+        assert_eq!(counters.bytes_stored(), 512);
+        assert_eq!(
+            metrics
+                .tenants()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        metrics.remove(1);
+        assert!(metrics.get(1).is_none());
+        assert_eq!(metrics.tenants().len(), 1);
+    }
+}
@@ -0,0 +1,910 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use directory::Type;
+use store::{
+    Deserialize, IterateParams, Serialize, Store, U64_LEN, ValueKey,
+    write::{
+        AlignedBytes, Archive, Archiver, BatchBuilder, TelemetryClass, ValueClass,
+        key::DeserializeBigEndian,
+    },
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use trc::{
+    AddContext, AuthEvent, Event, EventDetails, EventType, HttpEvent, Key, SecurityEvent, Value,
+    ipc::subscriber::SubscriberBuilder,
+};
+use utils::snowflake::SnowflakeIdGenerator;
+
+use crate::config::telemetry::AuditTracer;
+
+const MAX_BATCH_SIZE: usize = 256;
+// Index entries read at a time when filtering records
+const AUDIT_SCAN_BATCH: usize = 256;
+
+/// Number of audit records discarded because the write queue was full.
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuditField {
+    Tenant = 0,
+    Actor = 1,
+    Type = 2,
+    Target = 3,
+    ActorId = 4,
+    TargetType = 5,
+    // Tenant scoped indexes, their values are prefixed with the tenant id
+    TenantActor = 6,
+    TenantActorId = 7,
+    TenantType = 8,
+    TenantTargetType = 9,
+}
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    serde::Serialize,
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    #[serde(serialize_with = "serialize_id")]
+    pub id: u64,
+    pub timestamp: u64,
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub actor: Option<String>,
+    pub actor_id: Option<u32>,
+    pub tenant_id: Option<u32>,
+    pub target: Option<String>,
+    pub remote_ip: Option<String>,
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditQuery {
+    pub after: Option<u64>,
+    pub before: Option<u64>,
+    pub tenant_id: Option<u32>,
+    pub actor: Option<String>,
+    pub actor_id: Option<u32>,
+    pub typ: Option<EventType>,
+    pub target: Option<String>,
+    pub target_type: Option<String>,
+    pub cursor: Option<u64>,
+    pub limit: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditPage {
+    pub items: Vec<AuditRecord>,
+    pub cursor: Option<u64>,
+    /// Number of records read from the store to produce the page.
+    pub scanned: usize,
+}
+
+pub(crate) fn spawn_audit_tracer(builder: SubscriberBuilder, settings: AuditTracer) {
+    let (_, mut rx) = builder.register();
+    let (record_tx, mut record_rx) = mpsc::channel::<AuditRecord>(settings.queue_size.max(1));
+
+    tokio::spawn(async move {
+        let id_gen = SnowflakeIdGenerator::new();
+
+        while let Some(events) = rx.recv().await {
+            for event in events {
+                let record = AuditRecord::from_event(id_gen.generate(), &event);
+                if let Err(TrySendError::Full(_)) = record_tx.try_send(record) {
+                    DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let store = settings.store;
+        let mut records = Vec::with_capacity(MAX_BATCH_SIZE);
+
+        while record_rx.recv_many(&mut records, MAX_BATCH_SIZE).await > 0 {
+            if let Err(err) = store
+                .write_audit_records(std::mem::take(&mut records))
+                .await
+            {
+                trc::error!(err.caused_by(trc::location!()));
+            }
+        }
+    });
+}
+
+pub fn dropped_audit_events() -> u64 {
+    DROPPED_EVENTS.load(Ordering::Relaxed)
+}
+
+pub trait AuditStore: Sync + Send {
+    fn write_audit_records(
+        &self,
+        records: Vec<AuditRecord>,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+    fn query_audit_events(
+        &self,
+        query: AuditQuery,
+    ) -> impl Future<Output = trc::Result<AuditPage>> + Send;
+    fn purge_audit_events(&self, period: Duration) -> impl Future<Output = trc::Result<()>> + Send;
+    fn scrub_audit_events(
+        &self,
+        identifiers: &[String],
+        account_id: u32,
+        tombstone: &str,
+    ) -> impl Future<Output = trc::Result<u64>> + Send;
+}
+
+impl AuditStore for Store {
+    async fn write_audit_records(&self, records: Vec<AuditRecord>) -> trc::Result<()> {
+        let mut batch = BatchBuilder::new();
+        for record in records {
+            let archiver = Archiver::new(record);
+            match archiver.serialize() {
+                Ok(bytes) => {
+                    let record = archiver.into_inner();
+                    for (field, value) in record.index_values() {
+                        batch.set(
+                            ValueClass::Telemetry(TelemetryClass::AuditIndex {
+                                field: field as u8,
+                                value,
+                                event_id: record.id,
+                            }),
+                            vec![],
+                        );
+                    }
+                    batch.set(
+                        ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                            event_id: record.id,
+                        }),
+                        bytes,
+                    );
+                }
+                Err(err) => {
+                    trc::error!(err.caused_by(trc::location!()));
+                }
+            }
+
+            if batch.is_large_batch() {
+                self.write(batch.build_all())
+                    .await
+                    .caused_by(trc::location!())?;
+                batch = BatchBuilder::new();
+            }
+        }
+        if !batch.is_empty() {
+            self.write(batch.build_all())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(())
+    }
+
+    async fn query_audit_events(&self, query: AuditQuery) -> trc::Result<AuditPage> {
+        let from_id = query
+            .after
+            .and_then(SnowflakeIdGenerator::from_timestamp)
+            .unwrap_or(0);
+        let mut to_id = query
+            .before
+            .and_then(SnowflakeIdGenerator::from_timestamp)
+            .unwrap_or(u64::MAX);
+        if let Some(cursor) = query.cursor {
+            to_id = to_id.min(cursor.saturating_sub(1));
+        }
+        let limit = if query.limit > 0 { query.limit } else { 100 };
+        let mut page = AuditPage::default();
+        if from_id > to_id {
+            return Ok(page);
+        }
+
+        let mut filters = query.index_filters();
+        if !filters.is_empty() {
+            // Candidates are read in batches from the first index and matched
+            // against the others, stopping as soon as the page is full
+            let (field, value) = filters.remove(0);
+            let mut batch_to_id = to_id;
+            'outer: loop {
+                let mut ids = Vec::with_capacity(AUDIT_SCAN_BATCH);
+                self.iterate(
+                    IterateParams::new(
+                        ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditIndex {
+                            field: field as u8,
+                            value: value.clone(),
+                            event_id: from_id,
+                        })),
+                        ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditIndex {
+                            field: field as u8,
+                            value: value.clone(),
+                            event_id: batch_to_id,
+                        })),
+                    )
+                    .descending()
+                    .no_values(),
+                    |key, _| {
+                        ids.push(key.deserialize_be_u64(key.len() - U64_LEN)?);
+                        Ok(ids.len() < AUDIT_SCAN_BATCH)
+                    },
+                )
+                .await
+                .caused_by(trc::location!())?;
+
+                'next: for &event_id in &ids {
+                    // Records that are missing from any index are never read
+                    for (field, value) in &filters {
+                        if self
+                            .get_value::<()>(ValueKey::from(ValueClass::Telemetry(
+                                TelemetryClass::AuditIndex {
+                                    field: *field as u8,
+                                    value: value.clone(),
+                                    event_id,
+                                },
+                            )))
+                            .await
+                            .caused_by(trc::location!())?
+                            .is_none()
+                        {
+                            continue 'next;
+                        }
+                    }
+
+                    if let Some(record) = self
+                        .get_value::<Archive<AlignedBytes>>(ValueKey::from(ValueClass::Telemetry(
+                            TelemetryClass::AuditEvent { event_id },
+                        )))
+                        .await
+                        .caused_by(trc::location!())?
+                    {
+                        let record = record
+                            .deserialize::<AuditRecord>()
+                            .caused_by(trc::location!())?;
+                        page.scanned += 1;
+
+                        // Index values are truncated, so records are checked again
+                        if query.matches(&record) {
+                            page.items.push(record);
+                            if page.items.len() == limit {
+                                break 'outer;
+                            }
+                        }
+                    }
+                }
+
+                match ids.last() {
+                    Some(&last_id) if ids.len() == AUDIT_SCAN_BATCH && last_id > from_id => {
+                        batch_to_id = last_id - 1;
+                    }
+                    _ => break,
+                }
+            }
+        } else {
+            let mut items = Vec::new();
+            self.iterate(
+                IterateParams::new(
+                    ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                        event_id: from_id,
+                    })),
+                    ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                        event_id: to_id,
+                    })),
+                )
+                .descending(),
+                |key, value| {
+                    let record = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
+                        .and_then(|bytes| bytes.deserialize::<AuditRecord>())
+                        .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+                    items.push(record);
+                    Ok(items.len() < limit)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+            page.scanned = items.len();
+            page.items = items;
+        }
+
+        if page.items.len() == limit {
+            page.cursor = page.items.last().map(|record| record.id);
+        }
+
+        Ok(page)
+    }
+
+    async fn purge_audit_events(&self, period: Duration) -> trc::Result<()> {
+        let until_id = SnowflakeIdGenerator::from_duration(period).ok_or_else(|| {
+            trc::StoreEvent::UnexpectedError
+                .caused_by(trc::location!())
+                .ctx(trc::Key::Reason, "Failed to generate reference event id.")
+        })?;
+
+        // Remove index entries
+        let mut records = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                    event_id: 0,
+                })),
+                ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                    event_id: until_id,
+                })),
+            )
+            .ascending(),
+            |key, value| {
+                let record = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
+                    .and_then(|bytes| bytes.deserialize::<AuditRecord>())
+                    .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+                records.push(record);
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        let mut batch = BatchBuilder::new();
+        for record in records {
+            for (field, value) in record.index_values() {
+                batch.clear(ValueClass::Telemetry(TelemetryClass::AuditIndex {
+                    field: field as u8,
+                    value,
+                    event_id: record.id,
+                }));
+            }
+
+            if batch.is_large_batch() {
+                self.write(batch.build_all())
+                    .await
+                    .caused_by(trc::location!())?;
+                batch = BatchBuilder::new();
+            }
+        }
+        if !batch.is_empty() {
+            self.write(batch.build_all())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        // Remove records
+        self.delete_range(
+            ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                event_id: 0,
+            })),
+            ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                event_id: until_id,
+            })),
+        )
+        .await
+        .caused_by(trc::location!())
+    }
+
+    async fn scrub_audit_events(
+        &self,
+        identifiers: &[String],
+        account_id: u32,
+        tombstone: &str,
+    ) -> trc::Result<u64> {
+        // Identifiers can appear anywhere in the target and details,
+        // so every record is inspected
+        let mut records = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                    event_id: 0,
+                })),
+                ValueKey::from(ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                    event_id: u64::MAX,
+                })),
+            )
+            .ascending(),
+            |key, value| {
+                let record = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
+                    .and_then(|bytes| bytes.deserialize::<AuditRecord>())
+                    .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+                let mut scrubbed = record.clone();
+                if scrubbed.scrub(identifiers, account_id, tombstone) {
+                    records.push((record, scrubbed));
+                }
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        let total = records.len() as u64;
+        let mut batch = BatchBuilder::new();
+        for (record, scrubbed) in records {
+            for (field, value) in record.index_values() {
+                batch.clear(ValueClass::Telemetry(TelemetryClass::AuditIndex {
+                    field: field as u8,
+                    value,
+                    event_id: record.id,
+                }));
+            }
+            for (field, value) in scrubbed.index_values() {
+                batch.set(
+                    ValueClass::Telemetry(TelemetryClass::AuditIndex {
+                        field: field as u8,
+                        value,
+                        event_id: scrubbed.id,
+                    }),
+                    vec![],
+                );
+            }
+            batch.set(
+                ValueClass::Telemetry(TelemetryClass::AuditEvent {
+                    event_id: scrubbed.id,
+                }),
+                Archiver::new(scrubbed)
+                    .serialize()
+                    .caused_by(trc::location!())?,
+            );
+
+            if batch.is_large_batch() {
+                self.write(batch.build_all())
+                    .await
+                    .caused_by(trc::location!())?;
+                batch = BatchBuilder::new();
+            }
+        }
+        if !batch.is_empty() {
+            self.write(batch.build_all())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(total)
+    }
+}
+
+impl AuditRecord {
+    /// Replaces the identifying fields that refer to an erased account with
+    /// its tombstone ID, returning whether the record was changed.
+    pub fn scrub(&mut self, identifiers: &[String], account_id: u32, tombstone: &str) -> bool {
+        let mut scrubbed = false;
+
+        // Records of actions taken by the account
+        if self.actor_id == Some(account_id)
+            || self.actor.as_ref().is_some_and(|actor| {
+                identifiers
+                    .iter()
+                    .any(|identifier| actor.eq_ignore_ascii_case(identifier))
+            })
+        {
+            self.actor = Some(tombstone.to_string());
+            self.actor_id = None;
+            self.remote_ip = None;
+            scrubbed = true;
+        }
+
+        // Records that mention the account
+        for value in [&mut self.target, &mut self.details].into_iter().flatten() {
+            for identifier in identifiers {
+                if let Some(replaced) = replace_ignore_case(value, identifier, tombstone) {
+                    *value = replaced;
+                    scrubbed = true;
+                }
+            }
+        }
+
+        scrubbed
+    }
+
+    pub fn from_event(id: u64, event: &Event<EventDetails>) -> Self {
+        let mut record = AuditRecord {
+            id,
+            timestamp: event.inner.timestamp,
+            typ: event.inner.typ.name().to_string(),
+            ..Default::default()
+        };
+
+        for (key, value) in event
+            .keys
+            .iter()
+            .chain(event.inner.span.iter().flat_map(|span| span.keys.iter()))
+        {
+            match (key, value) {
+                (Key::AccountName, Value::String(name)) if record.actor.is_none() => {
+                    record.actor = Some(name.to_string());
+                }
+                (Key::AccountId, Value::UInt(id)) if record.actor_id.is_none() => {
+                    record.actor_id = Some(*id as u32);
+                }
+                (Key::TenantId, Value::UInt(id)) if record.tenant_id.is_none() => {
+                    record.tenant_id = Some(*id as u32);
+                }
+                (Key::Path | Key::Id, Value::String(target)) if record.target.is_none() => {
+                    record.target = Some(target.to_string());
+                }
+                (Key::RemoteIp, Value::Ipv4(ip)) if record.remote_ip.is_none() => {
+                    record.remote_ip = Some(ip.to_string());
+                }
+                (Key::RemoteIp, Value::Ipv6(ip)) if record.remote_ip.is_none() => {
+                    record.remote_ip = Some(ip.to_string());
+                }
+                (Key::Details | Key::Reason | Key::Type, Value::String(details))
+                    if record.details.is_none() =>
+                {
+                    record.details = Some(details.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        record
+    }
+
+    /// Type of the object an event acted on, either the principal type
+    /// for directory events or the API resource for management writes.
+    pub fn target_type(&self) -> Option<&str> {
+        if self.typ == EventType::Http(HttpEvent::ManagementWrite).name() {
+            self.target
+                .as_deref()
+                .and_then(|path| path.strip_prefix("/api/"))
+                .and_then(|path| path.split('/').next())
+                .filter(|resource| !resource.is_empty())
+        } else if self.typ.starts_with("directory.") {
+            self.details
+                .as_deref()
+                .and_then(Type::parse)
+                .map(|typ| typ.as_str())
+        } else {
+            None
+        }
+    }
+
+    fn index_values(&self) -> Vec<(AuditField, Vec<u8>)> {
+        let actor = self
+            .actor
+            .as_ref()
+            .map(|actor| actor.to_lowercase().into_bytes());
+        let actor_id = self.actor_id.map(|id| id.to_be_bytes().to_vec());
+        let typ = self.typ.as_bytes().to_vec();
+        let target_type = self
+            .target_type()
+            .map(|target_type| target_type.to_lowercase().into_bytes());
+
+        let mut values = Vec::with_capacity(10);
+        if let Some(tenant_id) = self.tenant_id {
+            values.push((AuditField::Tenant, tenant_id.to_be_bytes().to_vec()));
+        }
+        if let Some(actor) = &actor {
+            values.push((AuditField::Actor, actor.clone()));
+        }
+        values.push((AuditField::Type, typ.clone()));
+        if let Some(target) = &self.target {
+            values.push((AuditField::Target, target.to_lowercase().into_bytes()));
+        }
+        if let Some(actor_id) = &actor_id {
+            values.push((AuditField::ActorId, actor_id.clone()));
+        }
+        if let Some(target_type) = &target_type {
+            values.push((AuditField::TargetType, target_type.clone()));
+        }
+
+        if let Some(tenant_id) = self.tenant_id {
+            for (field, value) in [
+                (AuditField::TenantActor, actor),
+                (AuditField::TenantActorId, actor_id),
+                (AuditField::TenantType, Some(typ)),
+                (AuditField::TenantTargetType, target_type),
+            ] {
+                if let Some(value) = value {
+                    values.push((field, tenant_value(tenant_id, value)));
+                }
+            }
+        }
+
+        values
+    }
+}
+
+impl AuditQuery {
+    /// Indexes the query is resolved with. Filters are looked up in the
+    /// tenant scoped indexes when a tenant is given, so that they do not
+    /// read through the whole history of the tenant.
+    fn index_filters(&self) -> Vec<(AuditField, Vec<u8>)> {
+        let scoped = |field, tenant_field, value: Vec<u8>| match self.tenant_id {
+            Some(tenant_id) => (tenant_field, tenant_value(tenant_id, value)),
+            None => (field, value),
+        };
+        let mut filters = Vec::new();
+
+        if let Some(target) = &self.target {
+            filters.push((AuditField::Target, target.to_lowercase().into_bytes()));
+        }
+        if let Some(actor) = &self.actor {
+            filters.push(scoped(
+                AuditField::Actor,
+                AuditField::TenantActor,
+                actor.to_lowercase().into_bytes(),
+            ));
+        }
+        if let Some(actor_id) = self.actor_id {
+            filters.push(scoped(
+                AuditField::ActorId,
+                AuditField::TenantActorId,
+                actor_id.to_be_bytes().to_vec(),
+            ));
+        }
+        if let Some(target_type) = &self.target_type {
+            filters.push(scoped(
+                AuditField::TargetType,
+                AuditField::TenantTargetType,
+                target_type.to_lowercase().into_bytes(),
+            ));
+        }
+        if let Some(typ) = self.typ {
+            filters.push(scoped(
+                AuditField::Type,
+                AuditField::TenantType,
+                typ.name().as_bytes().to_vec(),
+            ));
+        }
+        if filters.is_empty()
+            && let Some(tenant_id) = self.tenant_id
+        {
+            filters.push((AuditField::Tenant, tenant_id.to_be_bytes().to_vec()));
+        }
+
+        filters
+    }
+
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.tenant_id.is_none_or(|id| record.tenant_id == Some(id))
+            && self.actor.as_ref().is_none_or(|actor| {
+                record
+                    .actor
+                    .as_ref()
+                    .is_some_and(|a| a.eq_ignore_ascii_case(actor))
+            })
+            && self.actor_id.is_none_or(|id| record.actor_id == Some(id))
+            && self.typ.is_none_or(|typ| record.typ == typ.name())
+            && self.target_type.as_ref().is_none_or(|target_type| {
+                record
+                    .target_type()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(target_type))
+            })
+            && self.target.as_ref().is_none_or(|target| {
+                record
+                    .target
+                    .as_ref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(target))
+            })
+    }
+}
+
+impl AuditTracer {
+    pub fn default_events() -> impl IntoIterator<Item = EventType> {
+        EventType::variants().into_iter().filter(|event| {
+            matches!(
+                event,
+                EventType::Directory(_)
+                    | EventType::Http(HttpEvent::ManagementWrite)
+                    | EventType::Auth(
+                        AuthEvent::Failed | AuthEvent::TooManyAttempts | AuthEvent::AccountLocked
+                    )
+                    | EventType::Security(SecurityEvent::Unauthorized)
+            )
+        })
+    }
+}
+
+fn replace_ignore_case(value: &str, needle: &str, replacement: &str) -> Option<String> {
+    if needle.is_empty() {
+        return None;
+    }
+
+    // ASCII lowercasing keeps byte offsets valid in the original value
+    let haystack = value.to_ascii_lowercase();
+    let needle = needle.to_ascii_lowercase();
+    let mut result = String::with_capacity(value.len());
+    let mut last = 0;
+    for (pos, _) in haystack.match_indices(&needle) {
+        result.push_str(&value[last..pos]);
+        result.push_str(replacement);
+        last = pos + needle.len();
+    }
+
+    if last > 0 {
+        result.push_str(&value[last..]);
+        Some(result)
+    } else {
+        None
+    }
+}
+
+fn tenant_value(tenant_id: u32, value: Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(value.len() + 4);
+    bytes.extend_from_slice(&tenant_id.to_be_bytes());
+    bytes.extend(value);
+    bytes
+}
+
+fn serialize_id<S: serde::Serializer>(id: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use trc::{DirectoryEvent, Event, EventDetails, EventType, Key, Level, Value};
+
+    use super::{AuditField, AuditQuery, AuditRecord};
+
+    #[test]
+    fn audit_record_from_event() {
+        let span = Event {
+            inner: EventDetails {
+                typ: EventType::Http(trc::HttpEvent::ConnectionStart),
+                timestamp: 1,
+                level: Level::Info,
+                span: None,
+            },
+            keys: vec![(Key::RemoteIp, Value::Ipv4("10.0.0.1".parse().unwrap()))],
+        };
+        let event = Event {
+            inner: EventDetails {
+                typ: EventType::Directory(DirectoryEvent::PrincipalDeleted),
+                timestamp: 100,
+                level: Level::Info,
+                span: Some(Arc::new(span)),
+            },
+            keys: vec![
+                (Key::AccountName, Value::String("Admin".into())),
+                (Key::AccountId, Value::UInt(1)),
+                (Key::TenantId, Value::UInt(7)),
+                (Key::Id, Value::String("john@example.org".into())),
+                (Key::Type, Value::String("individual".into())),
+            ],
+        };
+
+        let record = AuditRecord::from_event(42, &event);
+        assert_eq!(
+            record,
+            AuditRecord {
+                id: 42,
+                timestamp: 100,
+                typ: "directory.principal-deleted".to_string(),
+                actor: Some("Admin".to_string()),
+                actor_id: Some(1),
+                tenant_id: Some(7),
+                target: Some("john@example.org".to_string()),
+                remote_ip: Some("10.0.0.1".to_string()),
+                details: Some("individual".to_string()),
+            }
+        );
+        let tenant_value = |value: &[u8]| [&7u32.to_be_bytes()[..], value].concat();
+        assert_eq!(
+            record.index_values(),
+            vec![
+                (AuditField::Tenant, 7u32.to_be_bytes().to_vec()),
+                (AuditField::Actor, b"admin".to_vec()),
+                (AuditField::Type, b"directory.principal-deleted".to_vec()),
+                (AuditField::Target, b"john@example.org".to_vec()),
+                (AuditField::ActorId, 1u32.to_be_bytes().to_vec()),
+                (AuditField::TargetType, b"individual".to_vec()),
+                (AuditField::TenantActor, tenant_value(b"admin")),
+                (AuditField::TenantActorId, tenant_value(&1u32.to_be_bytes())),
+                (
+                    AuditField::TenantType,
+                    tenant_value(b"directory.principal-deleted")
+                ),
+                (AuditField::TenantTargetType, tenant_value(b"individual")),
+            ]
+        );
+
+        // Filters are resolved with the tenant scoped indexes
+        let query = AuditQuery {
+            actor: Some("ADMIN".to_string()),
+            tenant_id: Some(7),
+            typ: Some(EventType::Directory(DirectoryEvent::PrincipalDeleted)),
+            target_type: Some("Individual".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query.index_filters(),
+            vec![
+                (AuditField::TenantActor, tenant_value(b"admin")),
+                (AuditField::TenantTargetType, tenant_value(b"individual")),
+                (
+                    AuditField::TenantType,
+                    tenant_value(b"directory.principal-deleted")
+                ),
+            ]
+        );
+        assert_eq!(
+            AuditQuery {
+                tenant_id: Some(7),
+                ..Default::default()
+            }
+            .index_filters(),
+            vec![(AuditField::Tenant, 7u32.to_be_bytes().to_vec())]
+        );
+        assert!(query.matches(&record));
+        assert!(
+            !AuditQuery {
+                tenant_id: Some(8),
+                ..Default::default()
+            }
+            .matches(&record)
+        );
+        assert!(
+            !AuditQuery {
+                actor_id: Some(2),
+                ..Default::default()
+            }
+            .matches(&record)
+        );
+
+        // Management writes are typed by the API resource they modify
+        let record = AuditRecord {
+            typ: "http.management-write".to_string(),
+            target: Some("/api/organization/acme/branding".to_string()),
+            details: Some("PATCH".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(record.target_type(), Some("organization"));
+    }
+
+    #[test]
+    fn audit_record_scrub() {
+        let identifiers = vec![
+            "john@example.org".to_string(),
+            "jdoe@example.org".to_string(),
+        ];
+        let mut record = AuditRecord {
+            id: 42,
+            timestamp: 100,
+            typ: "http.management-write".to_string(),
+            actor: Some("John@Example.org".to_string()),
+            actor_id: Some(5),
+            tenant_id: Some(7),
+            target: Some("/api/principal/JDOE@example.org".to_string()),
+            remote_ip: Some("10.0.0.1".to_string()),
+            details: Some("PATCH".to_string()),
+        };
+        assert!(record.scrub(&identifiers, 5, "erased-1"));
+        assert_eq!(
+            record,
+            AuditRecord {
+                id: 42,
+                timestamp: 100,
+                typ: "http.management-write".to_string(),
+                actor: Some("erased-1".to_string()),
+                actor_id: None,
+                tenant_id: Some(7),
+                target: Some("/api/principal/erased-1".to_string()),
+                remote_ip: None,
+                details: Some("PATCH".to_string()),
+            }
+        );
+
+        let mut record = AuditRecord {
+            actor: Some("admin".to_string()),
+            actor_id: Some(1),
+            target: Some("jane@example.org".to_string()),
+            remote_ip: Some("10.0.0.1".to_string()),
+            ..Default::default()
+        };
+        let unchanged = record.clone();
+        assert!(!record.scrub(&identifiers, 5, "erased-1"));
+        assert_eq!(record, unchanged);
+    }
+}
@@ -209,6 +209,7 @@ impl StoreTracer {
                             AuthEvent::Success
                                 | AuthEvent::Failed
                                 | AuthEvent::TooManyAttempts
+                                | AuthEvent::AccountLocked
                                 | AuthEvent::Error
                         )
                         | EventType::Sieve(_)
@@ -5,15 +5,20 @@
  */
 
 use std::{
+    collections::VecDeque,
     sync::{
-        Arc,
+        Arc, LazyLock,
         atomic::{AtomicBool, Ordering},
     },
     time::Instant,
 };
 
 use crate::{LONG_1Y_SLUMBER, config::telemetry::WebhookTracer};
+use ahash::AHashMap;
 use base64::{Engine, engine::general_purpose::STANDARD};
+use mail_parser::DateTime;
+use parking_lot::Mutex;
+use reqwest::StatusCode;
 use ring::hmac;
 use serde::Serialize;
 use store::write::now;
@@ -94,6 +99,50 @@ pub(crate) fn spawn_webhook_tracer(builder: SubscriberBuilder, settings: Webhook
     });
 }
 
+const RESPONSE_PREVIEW_LEN: usize = 1024;
+const DELIVERY_HISTORY_LEN: usize = 50;
+
+// Deliveries are made by the tracer tasks, which have no access to the
+// server data, so the history of each node is kept here.
+static WEBHOOK_DELIVERIES: LazyLock<Mutex<AHashMap<String, VecDeque<WebhookDelivery>>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub timestamp: u64,
+    pub test: bool,
+    pub events: usize,
+    pub status: Option<u16>,
+    pub elapsed: Option<u64>,
+    pub error: Option<WebhookError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookResponse {
+    pub status: u16,
+    pub elapsed: u64,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookError {
+    pub kind: WebhookErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookErrorKind {
+    Dns,
+    Tls,
+    Connect,
+    Timeout,
+    Other,
+}
+
 #[derive(Serialize)]
 struct EventWrapper {
     events: JsonEventSerializer<Vec<Arc<Event<EventDetails>>>>,
@@ -107,11 +156,12 @@ fn spawn_webhook_handler(
 ) {
     tokio::spawn(async move {
         in_flight.store(true, Ordering::Relaxed);
+        let num_events = events.len();
         let wrapper = EventWrapper {
             events: JsonEventSerializer::new(events).with_id().with_spans(),
         };
 
-        if let Err(err) = post_webhook_events(&settings, &wrapper).await {
+        if let Err(err) = post_webhook_events(&settings, &wrapper, num_events).await {
             trc::event!(Telemetry(TelemetryEvent::WebhookError), Details = err);
 
             if webhook_tx.send(wrapper.events.into_inner()).await.is_err() {
@@ -130,11 +180,60 @@ fn spawn_webhook_handler(
 async fn post_webhook_events(
     settings: &WebhookTracer,
     events: &EventWrapper,
+    num_events: usize,
 ) -> Result<(), String> {
     // Serialize body
     let body = serde_json::to_string(events)
         .map_err(|err| format!("Failed to serialize events: {}", err))?;
 
+    let result = send_webhook(settings, body).await;
+    record_webhook_delivery(
+        &settings.id,
+        WebhookDelivery::new(&result, num_events, false),
+    );
+
+    match result {
+        Ok(response) if response.is_success() => Ok(()),
+        Ok(response) => Err(format!(
+            "Webhook request to {} failed with code {}: {}",
+            settings.url,
+            response.status,
+            StatusCode::from_u16(response.status)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or("Unknown")
+        )),
+        Err(err) => Err(format!(
+            "Webhook request to {} failed: {}",
+            settings.url, err.message
+        )),
+    }
+}
+
+/// Sends a synthetic event to a webhook, so that administrators can verify
+/// the URL, TLS settings and signature handling without waiting for a real
+/// event. The delivery is recorded in the history flagged as a test.
+pub async fn test_webhook(settings: &WebhookTracer) -> Result<WebhookResponse, WebhookError> {
+    let now = now();
+    let body = serde_json::json!({
+        "events": [{
+            "id": format!("{now}test"),
+            "createdAt": DateTime::from_timestamp(now as i64).to_rfc3339(),
+            "type": "test",
+            "data": {},
+        }],
+    })
+    .to_string();
+
+    let result = send_webhook(settings, body).await;
+    record_webhook_delivery(&settings.id, WebhookDelivery::new(&result, 1, true));
+    result
+}
+
+async fn send_webhook(
+    settings: &WebhookTracer,
+    body: String,
+) -> Result<WebhookResponse, WebhookError> {
     // Add HMAC-SHA256 signature
     let mut headers = settings.headers.clone();
     if !settings.key.is_empty() {
@@ -148,26 +247,113 @@ async fn post_webhook_events(
     }
 
     // Send request
-    let response = reqwest::Client::builder()
+    let start_time = Instant::now();
+    let mut response = reqwest::Client::builder()
         .timeout(settings.timeout)
         .danger_accept_invalid_certs(settings.tls_allow_invalid_certs)
         .build()
-        .map_err(|err| format!("Failed to create HTTP client: {}", err))?
+        .map_err(|err| WebhookError {
+            kind: WebhookErrorKind::Other,
+            message: format!("Failed to create HTTP client: {err}"),
+        })?
         .post(&settings.url)
         .headers(headers)
         .body(body)
         .send()
         .await
-        .map_err(|err| format!("Webhook request to {} failed: {err}", settings.url))?;
+        .map_err(|err| WebhookError::from_reqwest(&err))?;
 
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        Err(format!(
-            "Webhook request to {} failed with code {}: {}",
-            settings.url,
-            response.status().as_u16(),
-            response.status().canonical_reason().unwrap_or("Unknown")
-        ))
+    // Keep the beginning of the response, which usually explains a failure
+    let mut preview = Vec::new();
+    while preview.len() < RESPONSE_PREVIEW_LEN {
+        match response.chunk().await {
+            Ok(Some(chunk)) => preview.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(err) => return Err(WebhookError::from_reqwest(&err)),
+        }
+    }
+    preview.truncate(RESPONSE_PREVIEW_LEN);
+
+    Ok(WebhookResponse {
+        status: response.status().as_u16(),
+        elapsed: start_time.elapsed().as_millis() as u64,
+        body: String::from_utf8_lossy(&preview).into_owned(),
+    })
+}
+
+impl WebhookResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+impl WebhookError {
+    fn from_reqwest(err: &reqwest::Error) -> Self {
+        // Walk the error chain to tell apart the most common setup mistakes
+        let mut causes = Vec::new();
+        let mut source = std::error::Error::source(err);
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        let has_cause = |needles: &[&str]| {
+            causes
+                .iter()
+                .map(|cause| cause.to_lowercase())
+                .any(|cause| needles.iter().any(|needle| cause.contains(needle)))
+        };
+
+        let kind = if err.is_timeout() {
+            WebhookErrorKind::Timeout
+        } else if has_cause(&["dns error", "failed to lookup address", "name or service"]) {
+            WebhookErrorKind::Dns
+        } else if has_cause(&["certificate", "tls", "handshake"]) {
+            WebhookErrorKind::Tls
+        } else if err.is_connect() {
+            WebhookErrorKind::Connect
+        } else {
+            WebhookErrorKind::Other
+        };
+        let message = std::iter::once(err.to_string())
+            .chain(causes.into_iter().last())
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        WebhookError { kind, message }
+    }
+}
+
+impl WebhookDelivery {
+    fn new(result: &Result<WebhookResponse, WebhookError>, events: usize, test: bool) -> Self {
+        let (status, elapsed, error) = match result {
+            Ok(response) => (Some(response.status), Some(response.elapsed), None),
+            Err(err) => (None, None, Some(err.clone())),
+        };
+        WebhookDelivery {
+            timestamp: now(),
+            test,
+            events,
+            status,
+            elapsed,
+            error,
+        }
+    }
+}
+
+/// Returns the most recent deliveries of a webhook, newest first.
+pub fn webhook_deliveries(id: &str) -> Vec<WebhookDelivery> {
+    WEBHOOK_DELIVERIES
+        .lock()
+        .get(id)
+        .map(|deliveries| deliveries.iter().rev().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn record_webhook_delivery(id: &str, delivery: WebhookDelivery) {
+    let mut webhooks = WEBHOOK_DELIVERIES.lock();
+    let deliveries = webhooks.entry(id.to_string()).or_default();
+    if deliveries.len() == DELIVERY_HISTORY_LEN {
+        deliveries.pop_front();
     }
+    deliveries.push_back(delivery);
 }
@@ -101,7 +101,9 @@ impl CalendarFreebusyRequestHandler for Server {
         resource: DavResourcePath<'_>,
     ) -> crate::Result<ICalendar> {
         // Obtain shared ids
-        let shared_ids = if !access_token.is_member(account_id) {
+        let shared_ids = if !access_token.is_member(account_id)
+            && !self.is_tenant_resource(access_token, account_id)
+        {
             resources
                 .shared_containers(
                     access_token,
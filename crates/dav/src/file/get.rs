@@ -25,6 +25,7 @@ use store::{
 use trc::AddContext;
 use types::{
     acl::Acl,
+    blob_hash::BlobHash,
     collection::{Collection, SyncCollection},
 };
 
@@ -78,7 +79,7 @@ impl FileGetRequestHandler for Server {
 
         let (hash, size, content_type) = if let Some(file) = node.file.as_ref() {
             (
-                file.blob_hash.0.as_ref(),
+                BlobHash::from(&file.blob_hash),
                 u32::from(file.size) as usize,
                 file.media_type.as_ref().map(|s| s.as_str()),
             )
@@ -111,8 +112,7 @@ impl FileGetRequestHandler for Server {
 
         if !is_head {
             Ok(response.with_binary_body(
-                self.blob_store()
-                    .get_blob(hash, 0..usize::MAX)
+                self.get_blob(&hash, 0..usize::MAX)
                     .await
                     .caused_by(trc::location!())?
                     .ok_or(DavError::Code(StatusCode::NOT_FOUND))?,
@@ -126,8 +126,7 @@ impl FileUpdateRequestHandler for Server {
                 {
                     let file = node.inner.file.as_ref().unwrap();
                     let contents = self
-                        .blob_store()
-                        .get_blob(file.blob_hash.0.as_slice(), 0..usize::MAX)
+                        .get_blob(&BlobHash::from(&file.blob_hash), 0..usize::MAX)
                         .await
                         .caused_by(trc::location!())?
                         .ok_or(DavError::Code(StatusCode::PRECONDITION_FAILED))?;
@@ -151,7 +150,13 @@ impl FileUpdateRequestHandler for Server {
 
             // Verify that the node is a file
             if let Some(file) = node.inner.file.as_ref() {
-                if BlobHash::generate(&bytes).as_slice() == file.blob_hash.0.as_slice() {
+                if self
+                    .account_blob_hash(account_id, &bytes)
+                    .await
+                    .caused_by(trc::location!())?
+                    .as_slice()
+                    == file.blob_hash.0.as_slice()
+                {
                     return Ok(HttpResponse::new(StatusCode::NO_CONTENT));
                 }
             } else {
@@ -0,0 +1,472 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::borrow::Cow;
+
+use mail_parser::DateTime;
+use mail_send::Credentials;
+use smtp_proto::{AUTH_PLAIN, IntoString};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+use super::{ImapClient, ImapError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImapFolder {
+    /// Name as returned by the server, in modified UTF-7.
+    pub name: String,
+    pub delimiter: Option<char>,
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImapMessage {
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub internal_date: Option<u64>,
+    pub contents: Vec<u8>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> ImapClient<T> {
+    /// Logs in with SASL PLAIN when advertised, or with LOGIN otherwise.
+    pub async fn login(&mut self, username: &str, secret: &str) -> Result<(), ImapError> {
+        if self.mechanisms == 0 {
+            self.mechanisms = self.authentication_mechanisms().await?;
+        }
+
+        if self.mechanisms & AUTH_PLAIN != 0 {
+            let credentials = Credentials::Plain {
+                username: username.to_string(),
+                secret: secret.to_string(),
+            };
+            tokio::time::timeout(self.timeout, self.authenticate(AUTH_PLAIN, &credentials))
+                .await
+                .map_err(|_| ImapError::Timeout)?
+        } else {
+            self.command(
+                "C4",
+                &format!("LOGIN {} {}", quote(username), quote(secret)),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|err| match err {
+                ImapError::CommandFailed(_) => ImapError::AuthenticationFailed,
+                err => err,
+            })
+        }
+    }
+
+    pub async fn list_folders(&mut self) -> Result<Vec<ImapFolder>, ImapError> {
+        let response = self.command("C5", "LIST \"\" \"*\"").await?;
+        let mut tokens = ResponseTokens::new(&response);
+        let mut folders = Vec::new();
+
+        while let Some(token) = tokens.next() {
+            if token != Token::Atom(b"*")
+                || !tokens
+                    .next()
+                    .is_some_and(|token| token.is_atom_ignore_case("LIST"))
+                || tokens.next() != Some(Token::Open)
+            {
+                tokens.skip_line();
+                continue;
+            }
+
+            let mut attributes = Vec::new();
+            while let Some(Token::Atom(attribute)) = tokens.next() {
+                attributes.push(String::from_utf8_lossy(attribute).into_owned());
+            }
+            let delimiter = match tokens.next() {
+                Some(Token::Text(delimiter)) => delimiter.first().map(|&ch| ch as char),
+                _ => None,
+            };
+            let name = match tokens.next() {
+                Some(Token::Text(name)) => name.into_owned().into_string(),
+                Some(Token::Atom(name)) => String::from_utf8_lossy(name).into_owned(),
+                _ => {
+                    return Err(ImapError::InvalidResponse(response.into_string()));
+                }
+            };
+            tokens.skip_line();
+
+            folders.push(ImapFolder {
+                name,
+                delimiter,
+                attributes,
+            });
+        }
+
+        Ok(folders)
+    }
+
+    /// Opens a folder in read-only mode and returns its UIDVALIDITY.
+    pub async fn examine(&mut self, folder: &str) -> Result<u32, ImapError> {
+        let response = self
+            .command("C6", &format!("EXAMINE {}", quote(folder)))
+            .await?;
+
+        response
+            .split(|&ch| ch == b'\n')
+            .find_map(|line| {
+                let line = std::str::from_utf8(line).ok()?;
+                let (_, value) = line.split_once("[UIDVALIDITY ")?;
+                value.split_once(']')?.0.trim().parse().ok()
+            })
+            .ok_or_else(|| ImapError::InvalidResponse(response.into_string()))
+    }
+
+    /// Returns the UIDs in the selected folder starting at `from_uid`.
+    pub async fn uid_search(&mut self, from_uid: u32) -> Result<Vec<u32>, ImapError> {
+        let response = self
+            .command("C7", &format!("UID SEARCH UID {from_uid}:*"))
+            .await?;
+        let mut uids = Vec::new();
+        for line in response.split(|&ch| ch == b'\n') {
+            if let Some(line) = std::str::from_utf8(line)
+                .ok()
+                .and_then(|line| line.strip_prefix("* SEARCH"))
+            {
+                // "n:*" always includes the last message, even below `from_uid`
+                uids.extend(
+                    line.split_ascii_whitespace()
+                        .filter_map(|uid| uid.parse::<u32>().ok())
+                        .filter(|uid| *uid >= from_uid),
+                );
+            }
+        }
+        uids.sort_unstable();
+        uids.dedup();
+
+        Ok(uids)
+    }
+
+    /// Fetches a message from the selected folder without setting the
+    /// `\Seen` flag, returning `None` if it no longer exists.
+    pub async fn uid_fetch(&mut self, uid: u32) -> Result<Option<ImapMessage>, ImapError> {
+        let response = self
+            .command(
+                "C8",
+                &format!("UID FETCH {uid} (UID FLAGS INTERNALDATE BODY.PEEK[])"),
+            )
+            .await?;
+
+        Ok(parse_fetch(&response)
+            .into_iter()
+            .find(|message| message.uid == uid))
+    }
+
+    /// Sends a command and returns the untagged responses that precede
+    /// its tagged OK response.
+    async fn command(&mut self, tag: &str, command: &str) -> Result<Vec<u8>, ImapError> {
+        tokio::time::timeout(self.timeout, async {
+            self.write(format!("{tag} {command}\r\n").as_bytes())
+                .await?;
+
+            let mut response = Vec::with_capacity(1024);
+            let mut buf = vec![0u8; 8192];
+            let mut pos = 0;
+            let mut is_continuation = false;
+
+            loop {
+                while let Some(end) = response[pos..]
+                    .iter()
+                    .position(|&ch| ch == b'\n')
+                    .map(|end| pos + end + 1)
+                {
+                    let line = &response[pos..end];
+                    if let Some(size) = literal_size(line) {
+                        if response.len() < end + size {
+                            // Wait for the rest of the literal
+                            break;
+                        }
+                        pos = end + size;
+                        is_continuation = true;
+                        continue;
+                    } else if !is_continuation
+                        && line.starts_with(tag.as_bytes())
+                        && line.get(tag.len()) == Some(&b' ')
+                    {
+                        let status = line[tag.len() + 1..].to_vec().into_string();
+                        return if status.starts_with("OK") {
+                            response.truncate(pos);
+                            Ok(response)
+                        } else {
+                            Err(ImapError::CommandFailed(status.trim().to_string()))
+                        };
+                    }
+                    pos = end;
+                    is_continuation = false;
+                }
+
+                let br = self.stream.read(&mut buf).await?;
+                if br == 0 {
+                    return Err(ImapError::Disconnected);
+                }
+                response.extend_from_slice(&buf[..br]);
+            }
+        })
+        .await
+        .map_err(|_| ImapError::Timeout)?
+    }
+}
+
+fn parse_fetch(response: &[u8]) -> Vec<ImapMessage> {
+    let mut tokens = ResponseTokens::new(response);
+    let mut messages = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        if token != Token::Atom(b"*")
+            || !matches!(tokens.next(), Some(Token::Atom(_)))
+            || !tokens
+                .next()
+                .is_some_and(|token| token.is_atom_ignore_case("FETCH"))
+            || tokens.next() != Some(Token::Open)
+        {
+            tokens.skip_line();
+            continue;
+        }
+
+        let mut message = ImapMessage {
+            uid: 0,
+            flags: Vec::new(),
+            internal_date: None,
+            contents: Vec::new(),
+        };
+        while let Some(Token::Atom(item)) = tokens.next() {
+            match (item.to_ascii_uppercase().as_slice(), tokens.next()) {
+                (b"UID", Some(Token::Atom(uid))) => {
+                    message.uid = std::str::from_utf8(uid)
+                        .ok()
+                        .and_then(|uid| uid.parse().ok())
+                        .unwrap_or_default();
+                }
+                (b"FLAGS", Some(Token::Open)) => {
+                    while let Some(Token::Atom(flag)) = tokens.next() {
+                        message
+                            .flags
+                            .push(String::from_utf8_lossy(flag).into_owned());
+                    }
+                }
+                (b"INTERNALDATE", Some(Token::Text(date))) => {
+                    message.internal_date = std::str::from_utf8(&date)
+                        .ok()
+                        .and_then(parse_internal_date);
+                }
+                (b"BODY[]", Some(Token::Text(contents))) => {
+                    message.contents = contents.into_owned();
+                }
+                (_, Some(Token::Open)) => {
+                    tokens.skip_list();
+                }
+                _ => {}
+            }
+        }
+        tokens.skip_line();
+        messages.push(message);
+    }
+
+    messages
+}
+
+/// Parses an INTERNALDATE such as `17-Jul-1996 02:44:25 -0700`.
+fn parse_internal_date(value: &str) -> Option<u64> {
+    let (date, time) = value.trim().split_once(' ')?;
+    DateTime::parse_rfc822(&format!("{} {time}", date.replace('-', " ")))
+        .filter(|date| date.is_valid())
+        .map(|date| date.to_timestamp() as u64)
+}
+
+fn literal_size(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\n")?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_suffix(b"}")?;
+    let start = line.iter().rposition(|&ch| ch == b'{')?;
+    std::str::from_utf8(&line[start + 1..])
+        .ok()?
+        .trim_end_matches('+')
+        .parse()
+        .ok()
+}
+
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        if matches!(ch, '\\' | '"') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'x> {
+    Atom(&'x [u8]),
+    Text(Cow<'x, [u8]>),
+    Open,
+    Close,
+    Eol,
+}
+
+impl Token<'_> {
+    fn is_atom_ignore_case(&self, value: &str) -> bool {
+        matches!(self, Token::Atom(atom) if atom.eq_ignore_ascii_case(value.as_bytes()))
+    }
+}
+
+struct ResponseTokens<'x> {
+    data: &'x [u8],
+    pos: usize,
+}
+
+impl<'x> ResponseTokens<'x> {
+    fn new(data: &'x [u8]) -> Self {
+        ResponseTokens { data, pos: 0 }
+    }
+
+    fn skip_line(&mut self) {
+        while !matches!(self.next(), Some(Token::Eol) | None) {}
+    }
+
+    fn skip_list(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Open) => depth += 1,
+                Some(Token::Close) => depth -= 1,
+                Some(Token::Eol) | None => break,
+                _ => {}
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<Token<'x>> {
+        while self.data.get(self.pos) == Some(&b' ') {
+            self.pos += 1;
+        }
+
+        let start = self.pos;
+        match *self.data.get(start)? {
+            b'\r' | b'\n' => {
+                self.pos += if self.data[start] == b'\r' { 2 } else { 1 };
+                Some(Token::Eol)
+            }
+            b'(' => {
+                self.pos += 1;
+                Some(Token::Open)
+            }
+            b')' => {
+                self.pos += 1;
+                Some(Token::Close)
+            }
+            b'"' => {
+                let mut text = Vec::new();
+                let mut is_escaped = false;
+                self.pos += 1;
+                while let Some(&ch) = self.data.get(self.pos) {
+                    self.pos += 1;
+                    match ch {
+                        b'\\' if !is_escaped => {
+                            is_escaped = true;
+                            continue;
+                        }
+                        b'"' if !is_escaped => break,
+                        _ => text.push(ch),
+                    }
+                    is_escaped = false;
+                }
+                Some(Token::Text(text.into()))
+            }
+            b'{' => {
+                let end = start + self.data[start..].iter().position(|&ch| ch == b'\n')? + 1;
+                let size = literal_size(&self.data[start..end])?;
+                let contents = self.data.get(end..end + size)?;
+                self.pos = end + size;
+                Some(Token::Text(contents.into()))
+            }
+            _ => {
+                // Atoms may contain bracketed sections such as BODY[]
+                let mut depth = 0;
+                while let Some(&ch) = self.data.get(self.pos) {
+                    match ch {
+                        b'[' => depth += 1,
+                        b']' => depth -= 1,
+                        b' ' | b'(' | b')' if depth == 0 => break,
+                        b'\r' | b'\n' => break,
+                        _ => {}
+                    }
+                    self.pos += 1;
+                }
+                Some(Token::Atom(&self.data[start..self.pos]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_imap_responses() {
+        let response = concat!(
+            "* 12 FETCH (UID 4827 FLAGS (\\Seen $Forwarded) ",
+            "INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" BODY[] {29}\r\n",
+            "Subject: test\r\n\r\n(body) {1}\r\n",
+            ")\r\n",
+            "* 13 FETCH (FLAGS (\\Deleted) UID 4828)\r\n",
+        );
+        assert_eq!(literal_size(b"BODY[] {29}\r\n"), Some(29));
+        assert_eq!(
+            parse_fetch(response.as_bytes()),
+            vec![
+                ImapMessage {
+                    uid: 4827,
+                    flags: vec!["\\Seen".to_string(), "$Forwarded".to_string()],
+                    internal_date: Some(837596665),
+                    contents: b"Subject: test\r\n\r\n(body) {1}\r\n".to_vec(),
+                },
+                ImapMessage {
+                    uid: 4828,
+                    flags: vec!["\\Deleted".to_string()],
+                    internal_date: None,
+                    contents: vec![],
+                }
+            ]
+        );
+
+        let mut tokens = ResponseTokens::new(
+            b"* LIST (\\HasNoChildren \\Sent) \"/\" \"Sent \\\"Items\\\"\"\r\n* LIST () NIL {5}\r\nINBOX\r\n",
+        );
+        let mut list = Vec::new();
+        while let Some(token) = tokens.next() {
+            list.push(token);
+        }
+        assert_eq!(
+            list,
+            vec![
+                Token::Atom(b"*"),
+                Token::Atom(b"LIST"),
+                Token::Open,
+                Token::Atom(b"\\HasNoChildren"),
+                Token::Atom(b"\\Sent"),
+                Token::Close,
+                Token::Text(b"/".as_slice().into()),
+                Token::Text(b"Sent \"Items\"".as_slice().into()),
+                Token::Eol,
+                Token::Atom(b"*"),
+                Token::Atom(b"LIST"),
+                Token::Open,
+                Token::Close,
+                Token::Atom(b"NIL"),
+                Token::Text(b"INBOX".as_slice().into()),
+                Token::Eol,
+            ]
+        );
+        assert_eq!(quote("a\"b\\c"), "\"a\\\"b\\\\c\"");
+    }
+}
@@ -7,6 +7,7 @@
 pub mod client;
 pub mod config;
 pub mod lookup;
+pub mod mailbox;
 pub mod pool;
 pub mod tls;
 
@@ -44,6 +45,7 @@ pub enum ImapError {
     Timeout,
     InvalidResponse(String),
     InvalidChallenge(String),
+    CommandFailed(String),
     AuthenticationFailed,
     TLSInvalidName,
     Disconnected,
@@ -64,6 +66,7 @@ impl Display for ImapError {
             ImapError::InvalidChallenge(response) => {
                 write!(f, "Invalid auth challenge: {response}")
             }
+            ImapError::CommandFailed(response) => write!(f, "Command failed: {response}"),
             ImapError::TLSInvalidName => f.write_str("Invalid TLS name"),
             ImapError::Disconnected => f.write_str("Connection disconnected by peer"),
             ImapError::AuthenticationFailed => f.write_str("Authentication failed"),
@@ -0,0 +1,223 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{PrincipalInfo, manage::PrincipalList};
+use crate::{AuthProtocol, Principal, Type};
+use ahash::{AHashMap, AHashSet};
+use store::{
+    Deserialize, InMemoryStore, IterateParams, Store, U32_LEN, U64_LEN, ValueKey,
+    dispatch::lookup::KeyValue,
+    write::{DirectoryClass, InMemoryClass, ValueClass},
+};
+use trc::AddContext;
+
+/// In-memory key prefix of principal activity timestamps.
+pub const KV_PRINCIPAL_ACTIVITY: u8 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActivityType {
+    Imap,
+    Pop3,
+    Smtp,
+    ManageSieve,
+    Http,
+    Mailbox,
+}
+
+impl ActivityType {
+    pub const ALL: [ActivityType; 6] = [
+        ActivityType::Imap,
+        ActivityType::Pop3,
+        ActivityType::Smtp,
+        ActivityType::ManageSieve,
+        ActivityType::Http,
+        ActivityType::Mailbox,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActivityType::Imap => "imap",
+            ActivityType::Pop3 => "pop3",
+            ActivityType::Smtp => "smtp",
+            ActivityType::ManageSieve => "managesieve",
+            ActivityType::Http => "http",
+            ActivityType::Mailbox => "mailbox",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        ActivityType::ALL
+            .into_iter()
+            .find(|typ| typ.as_str() == value)
+    }
+}
+
+impl From<AuthProtocol> for ActivityType {
+    fn from(protocol: AuthProtocol) -> Self {
+        match protocol {
+            AuthProtocol::Imap => ActivityType::Imap,
+            AuthProtocol::Pop3 => ActivityType::Pop3,
+            AuthProtocol::Smtp => ActivityType::Smtp,
+            AuthProtocol::ManageSieve => ActivityType::ManageSieve,
+            AuthProtocol::Http | AuthProtocol::Management => ActivityType::Http,
+        }
+    }
+}
+
+/// Activity timestamps are kept outside the principal record so that
+/// recording them does not change its modification time.
+#[allow(async_fn_in_trait)]
+pub trait PrincipalActivity: Sync + Send {
+    async fn set_last_activity(
+        &self,
+        principal_id: u32,
+        typ: ActivityType,
+        timestamp: u64,
+    ) -> trc::Result<()>;
+    /// Returns the last recorded time of each type of activity.
+    async fn last_activity(&self, principal_id: u32) -> trc::Result<Vec<(ActivityType, u64)>>;
+    /// Returns the most recent activity of any type.
+    async fn last_active_at(&self, principal_id: u32) -> trc::Result<Option<u64>> {
+        Ok(self
+            .last_activity(principal_id)
+            .await?
+            .into_iter()
+            .map(|(_, timestamp)| timestamp)
+            .max())
+    }
+
+    /// Counts the individual accounts of each tenant without any activity
+    /// since the given time, including those that were never active.
+    async fn count_inactive_by_tenant(&self, since: u64) -> trc::Result<AHashMap<u32, u64>>;
+}
+
+impl PrincipalActivity for Store {
+    async fn set_last_activity(
+        &self,
+        principal_id: u32,
+        typ: ActivityType,
+        timestamp: u64,
+    ) -> trc::Result<()> {
+        InMemoryStore::Store(self.clone())
+            .key_set(KeyValue::new(
+                activity_key(principal_id, typ),
+                timestamp.to_string().into_bytes(),
+            ))
+            .await
+    }
+
+    async fn last_activity(&self, principal_id: u32) -> trc::Result<Vec<(ActivityType, u64)>> {
+        let kv = InMemoryStore::Store(self.clone());
+        let mut activity = Vec::new();
+        for typ in ActivityType::ALL {
+            if let Some(timestamp) = kv
+                .key_get::<String>(activity_key(principal_id, typ))
+                .await?
+                .and_then(|value| value.parse().ok())
+            {
+                activity.push((typ, timestamp));
+            }
+        }
+
+        Ok(activity)
+    }
+
+    async fn count_inactive_by_tenant(&self, since: u64) -> trc::Result<AHashMap<u32, u64>> {
+        // Find the principals active since then with a single scan
+        let mut active = AHashSet::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::InMemory(InMemoryClass::Key(vec![
+                    KV_PRINCIPAL_ACTIVITY,
+                ]))),
+                ValueKey::from(ValueClass::InMemory(InMemoryClass::Key(vec![
+                    KV_PRINCIPAL_ACTIVITY,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                ]))),
+            ),
+            |key, value| {
+                if let Some(principal_id) = key
+                    .get(1..1 + U32_LEN)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u32::from_be_bytes)
+                    && std::str::from_utf8(value.get(U64_LEN..).unwrap_or_default())
+                        .ok()
+                        .and_then(|timestamp| timestamp.parse::<u64>().ok())
+                        .is_some_and(|timestamp| timestamp >= since)
+                {
+                    active.insert(principal_id);
+                }
+
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        let mut counts = AHashMap::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![0u8]))),
+                ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![
+                    u8::MAX;
+                    10
+                ]))),
+            ),
+            |_, value| {
+                let pt = PrincipalInfo::deserialize(value).caused_by(trc::location!())?;
+                if pt.typ == Type::Individual
+                    && let Some(tenant_id) = pt.tenant
+                    && !active.contains(&pt.id)
+                {
+                    *counts.entry(tenant_id).or_insert(0) += 1;
+                }
+
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())
+        .map(|_| counts)
+    }
+}
+
+impl PrincipalList<Principal> {
+    /// Keeps the principals without any activity since the given time,
+    /// including those that were never active.
+    pub async fn retain_inactive(mut self, store: &Store, since: u64) -> trc::Result<Self> {
+        let mut items = Vec::with_capacity(self.items.len());
+        for principal in self.items {
+            if store
+                .last_active_at(principal.id)
+                .await?
+                .is_none_or(|timestamp| timestamp < since)
+            {
+                items.push(principal);
+            }
+        }
+        self.total = items.len() as u64;
+        self.items = items;
+
+        Ok(self)
+    }
+}
+
+pub(super) fn activity_prefix(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(6);
+    key.push(KV_PRINCIPAL_ACTIVITY);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
+
+fn activity_key(principal_id: u32, typ: ActivityType) -> Vec<u8> {
+    let mut key = activity_prefix(principal_id);
+    key.push(typ as u8);
+    key
+}
@@ -0,0 +1,213 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    PrincipalField,
+    manage::{ManageDirectory, err_exists, error, not_found},
+    secondary::update_principal_data,
+};
+use crate::{AuthProtocol, PrincipalData};
+use pwhash::sha512_crypt;
+use store::{
+    InMemoryStore, Store,
+    dispatch::lookup::KeyValue,
+    rand::{Rng, distr::Alphanumeric, rng},
+    write::now,
+};
+
+/// Maximum number of app passwords per principal.
+pub const MAX_APP_PASSWORDS: usize = 20;
+
+/// In-memory key prefix of app password last-used timestamps.
+pub const KV_APP_PASSWORD_USED: u8 = 27;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppPasswordEntry {
+    pub label: String,
+    pub created_at: Option<u64>,
+    pub last_used: Option<u64>,
+    pub protocols: Vec<AuthProtocol>,
+}
+
+/// App passwords let clients that can't do MFA authenticate over mail
+/// protocols. Only their hash is stored, and they are never accepted by
+/// the management API.
+#[allow(async_fn_in_trait)]
+pub trait AppPasswords: Sync + Send {
+    /// Generates an app password and returns it, it can't be recovered later.
+    async fn add_app_password(
+        &self,
+        principal_id: u32,
+        label: &str,
+        protocols: &[AuthProtocol],
+    ) -> trc::Result<String>;
+    async fn list_app_passwords(&self, principal_id: u32) -> trc::Result<Vec<AppPasswordEntry>>;
+    async fn remove_app_password(&self, principal_id: u32, label: &str) -> trc::Result<bool>;
+    /// Records a successful login with an app password.
+    async fn app_password_used(&self, principal_id: u32, label: &str) -> trc::Result<()>;
+}
+
+impl AppPasswords for Store {
+    async fn add_app_password(
+        &self,
+        principal_id: u32,
+        label: &str,
+        protocols: &[AuthProtocol],
+    ) -> trc::Result<String> {
+        let label = label.trim();
+        if label.is_empty() || label.len() > 255 || label.contains(['$', '/']) {
+            return Err(error(
+                "Invalid app password label",
+                "Labels must be non-empty and may not contain '$' or '/'".into(),
+            ));
+        } else if protocols.contains(&AuthProtocol::Management) {
+            return Err(error(
+                "Invalid app password protocol",
+                "App passwords can't be used with the management API".into(),
+            ));
+        }
+
+        let secret = rng()
+            .sample_iter(Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect::<String>();
+        let hash = sha512_crypt::hash(&secret).map_err(|err| {
+            trc::AuthEvent::Error
+                .reason(err)
+                .details("Failed to hash app password")
+        })?;
+
+        update_principal_data(self, principal_id, |principal| {
+            let labels = principal
+                .data
+                .iter()
+                .filter_map(|item| match item {
+                    PrincipalData::AppPassword(secret) => app_password_label(secret),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if labels.contains(&label) {
+                return Err(err_exists(PrincipalField::Secrets, label.to_string()));
+            } else if labels.len() >= MAX_APP_PASSWORDS {
+                return Err(error(
+                    "Too many app passwords",
+                    format!("A maximum of {MAX_APP_PASSWORDS} app passwords is allowed").into(),
+                ));
+            }
+
+            principal
+                .data
+                .push(PrincipalData::AppPassword(format!("$app${label}${hash}")));
+            principal.data.push(PrincipalData::AppPasswordInfo {
+                label: label.to_string(),
+                created_at: now(),
+                protocols: protocols.iter().fold(0, |bits, p| bits | p.bit()),
+            });
+            Ok(true)
+        })
+        .await?;
+
+        Ok(secret)
+    }
+
+    async fn list_app_passwords(&self, principal_id: u32) -> trc::Result<Vec<AppPasswordEntry>> {
+        let principal = self
+            .get_principal(principal_id)
+            .await?
+            .ok_or_else(|| not_found(principal_id))?;
+        let kv = InMemoryStore::Store(self.clone());
+        let mut entries = Vec::new();
+
+        for item in &principal.data {
+            if let PrincipalData::AppPassword(secret) = item
+                && let Some(label) = app_password_label(secret)
+            {
+                let (created_at, protocols) = principal
+                    .data
+                    .iter()
+                    .find_map(|item| match item {
+                        PrincipalData::AppPasswordInfo {
+                            label: info_label,
+                            created_at,
+                            protocols,
+                        } if info_label == label => Some((
+                            Some(*created_at),
+                            AuthProtocol::from_bits(*protocols).collect(),
+                        )),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                let last_used = kv
+                    .key_get::<String>(last_used_key(principal_id, label))
+                    .await?
+                    .and_then(|value| value.parse().ok());
+
+                entries.push(AppPasswordEntry {
+                    label: label.to_string(),
+                    created_at,
+                    last_used,
+                    protocols,
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn remove_app_password(&self, principal_id: u32, label: &str) -> trc::Result<bool> {
+        let prefix = format!("$app${label}$");
+        let result = update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal.data.retain(|item| match item {
+                PrincipalData::AppPassword(secret) => !secret.starts_with(&prefix),
+                PrincipalData::AppPasswordInfo {
+                    label: info_label, ..
+                } => info_label != label,
+                _ => true,
+            });
+            Ok(principal.data.len() != len)
+        })
+        .await?;
+
+        if result {
+            InMemoryStore::Store(self.clone())
+                .key_delete(last_used_key(principal_id, label))
+                .await?;
+        }
+
+        Ok(result)
+    }
+
+    async fn app_password_used(&self, principal_id: u32, label: &str) -> trc::Result<()> {
+        InMemoryStore::Store(self.clone())
+            .key_set(KeyValue::new(
+                last_used_key(principal_id, label),
+                now().to_string().into_bytes(),
+            ))
+            .await
+    }
+}
+
+fn app_password_label(secret: &str) -> Option<&str> {
+    secret
+        .strip_prefix("$app$")
+        .and_then(|s| s.split_once('$'))
+        .map(|(label, _)| label)
+}
+
+pub(super) fn last_used_prefix(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_APP_PASSWORD_USED);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
+
+fn last_used_key(principal_id: u32, label: &str) -> Vec<u8> {
+    let mut key = last_used_prefix(principal_id);
+    key.extend_from_slice(label.as_bytes());
+    key
+}
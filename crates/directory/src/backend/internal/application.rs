@@ -0,0 +1,209 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use pwhash::sha512_crypt;
+use std::collections::BTreeMap;
+use store::{
+    InMemoryStore, IterateParams, Store, U64_LEN, ValueKey,
+    dispatch::lookup::KeyValue,
+    write::{InMemoryClass, ValueClass, now},
+};
+use trc::AddContext;
+
+/// In-memory key prefix of organization signup applications.
+pub const KV_ORGANIZATION_APPLICATION: u8 = 13;
+
+/// In-memory key prefix of the domains held by pending applications.
+pub const KV_APPLICATION_DOMAIN: u8 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApplicationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Request for an organization submitted through the public signup
+/// endpoint. Nothing is provisioned until a superadmin approves it, and
+/// the record expires along with the hold on its domain.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationApplication {
+    pub id: u64,
+    pub status: ApplicationStatus,
+    pub tenant_name: String,
+    pub domain: String,
+    pub admin_name: String,
+    pub admin_email: String,
+    /// Hash of the administrator's password, the password itself is never
+    /// stored.
+    pub admin_secret: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub brand_name: Option<String>,
+    #[serde(default)]
+    pub brand_logo_url: Option<String>,
+    #[serde(default)]
+    pub brand_logo_dark_url: Option<String>,
+    #[serde(default)]
+    pub brand_theme: Option<String>,
+    pub submitted_at: u64,
+    pub expires_at: u64,
+    #[serde(default)]
+    pub remote_ip: Option<String>,
+    #[serde(default)]
+    pub reviewed_by: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<u32>,
+    /// Verdict of the abuse check run when the request was received.
+    #[serde(default)]
+    pub verdict: Option<String>,
+    /// Token of the invitation the organization was requested with, for
+    /// redemptions held for review by the abuse check.
+    #[serde(default)]
+    pub invitation: Option<String>,
+    #[serde(default)]
+    pub referral_code: Option<String>,
+    #[serde(default)]
+    pub attribution: BTreeMap<String, String>,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait OrganizationApplications: Sync + Send {
+    /// Stores a new application, unless its domain is held by another
+    /// pending application.
+    async fn add_application(&self, application: &OrganizationApplication) -> trc::Result<bool>;
+    async fn get_application(&self, id: u64) -> trc::Result<Option<OrganizationApplication>>;
+    /// Returns the applications that have not expired, oldest first.
+    async fn list_applications(&self) -> trc::Result<Vec<OrganizationApplication>>;
+    /// Saves a reviewed application, releasing the hold on its domain.
+    async fn review_application(&self, application: &OrganizationApplication) -> trc::Result<()>;
+}
+
+impl OrganizationApplications for Store {
+    async fn add_application(&self, application: &OrganizationApplication) -> trc::Result<bool> {
+        let kv = InMemoryStore::Store(self.clone());
+        let ttl = application.expires_at.saturating_sub(now());
+        if !kv
+            .try_lock(
+                KV_APPLICATION_DOMAIN,
+                application.domain.to_lowercase().as_bytes(),
+                ttl,
+            )
+            .await?
+        {
+            return Ok(false);
+        }
+
+        if let Err(err) = kv.key_set(application_kv(application).expires(ttl)).await {
+            kv.remove_lock(
+                KV_APPLICATION_DOMAIN,
+                application.domain.to_lowercase().as_bytes(),
+            )
+            .await?;
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+
+    async fn get_application(&self, id: u64) -> trc::Result<Option<OrganizationApplication>> {
+        InMemoryStore::Store(self.clone())
+            .key_get::<String>(KeyValue::<()>::build_key(
+                KV_ORGANIZATION_APPLICATION,
+                id.to_be_bytes(),
+            ))
+            .await?
+            .map(|value| deserialize_application(value.as_bytes()))
+            .transpose()
+    }
+
+    async fn list_applications(&self) -> trc::Result<Vec<OrganizationApplication>> {
+        let now = now();
+        let mut applications = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::InMemory(InMemoryClass::Key(vec![
+                    KV_ORGANIZATION_APPLICATION,
+                ]))),
+                ValueKey::from(ValueClass::InMemory(InMemoryClass::Key(vec![
+                    KV_ORGANIZATION_APPLICATION,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                    u8::MAX,
+                ]))),
+            )
+            .ascending(),
+            |_, value| {
+                let expires = value
+                    .get(..U64_LEN)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u64::from_be_bytes)
+                    .unwrap_or_default();
+                if expires > now {
+                    applications.push(deserialize_application(
+                        value.get(U64_LEN..).unwrap_or_default(),
+                    )?);
+                }
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        Ok(applications)
+    }
+
+    async fn review_application(&self, application: &OrganizationApplication) -> trc::Result<()> {
+        let kv = InMemoryStore::Store(self.clone());
+        kv.key_set(
+            application_kv(application).expires(application.expires_at.saturating_sub(now())),
+        )
+        .await?;
+        kv.remove_lock(
+            KV_APPLICATION_DOMAIN,
+            application.domain.to_lowercase().as_bytes(),
+        )
+        .await
+    }
+}
+
+impl OrganizationApplication {
+    pub fn set_password(&mut self, password: &str) -> trc::Result<()> {
+        self.admin_secret = sha512_crypt::hash(password).map_err(|err| {
+            trc::AuthEvent::Error
+                .reason(err)
+                .details("Failed to hash password")
+        })?;
+        Ok(())
+    }
+}
+
+fn application_kv(application: &OrganizationApplication) -> KeyValue<Vec<u8>> {
+    KeyValue::with_prefix(
+        KV_ORGANIZATION_APPLICATION,
+        application.id.to_be_bytes(),
+        serde_json::to_vec(application).unwrap_or_default(),
+    )
+}
+
+fn deserialize_application(bytes: &[u8]) -> trc::Result<OrganizationApplication> {
+    serde_json::from_slice(bytes).map_err(|err| {
+        trc::StoreEvent::DataCorruption
+            .reason(err)
+            .details("Failed to deserialize organization application")
+            .caused_by(trc::location!())
+    })
+}
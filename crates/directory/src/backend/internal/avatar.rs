@@ -0,0 +1,74 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::secondary::update_principal_data;
+use crate::{Principal, PrincipalData};
+use store::Store;
+
+/// Profile picture of a principal. The image itself is kept in the blob
+/// store, its hash is used as the ETag and lets clients skip downloading
+/// images they already have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Avatar {
+    pub hash: String,
+    pub content_type: String,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait PrincipalAvatars: Sync + Send {
+    /// Replaces or removes the avatar of a principal, returns the previous one.
+    async fn set_avatar(
+        &self,
+        principal_id: u32,
+        avatar: Option<Avatar>,
+    ) -> trc::Result<Option<Avatar>>;
+}
+
+impl PrincipalAvatars for Store {
+    async fn set_avatar(
+        &self,
+        principal_id: u32,
+        avatar: Option<Avatar>,
+    ) -> trc::Result<Option<Avatar>> {
+        let mut previous = None;
+        update_principal_data(self, principal_id, |principal| {
+            previous = principal.avatar();
+            if previous == avatar {
+                return Ok(false);
+            }
+
+            principal
+                .data
+                .retain(|v| !matches!(v, PrincipalData::Avatar { .. }));
+            if let Some(avatar) = avatar {
+                principal.data.push(PrincipalData::Avatar {
+                    hash: avatar.hash,
+                    content_type: avatar.content_type,
+                });
+            }
+            Ok(true)
+        })
+        .await?;
+
+        Ok(previous)
+    }
+}
+
+/// Key of the avatar of a principal in the blob store.
+pub fn avatar_key(principal_id: u32) -> Vec<u8> {
+    format!("avatar/{principal_id}").into_bytes()
+}
+
+/// Allows or forbids the members of a tenant to manage their own avatar,
+/// which is allowed unless set otherwise.
+pub(super) fn set_avatar_self_service(principal: &mut Principal, value: u64) {
+    principal
+        .data
+        .retain(|v| !matches!(v, PrincipalData::AvatarSelfService(_)));
+    if value == 0 {
+        principal.data.push(PrincipalData::AvatarSelfService(false));
+    }
+}
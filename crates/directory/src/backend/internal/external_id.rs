@@ -0,0 +1,85 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    PrincipalField, PrincipalInfo,
+    manage::{ManageDirectory, err_exists},
+    name_key,
+};
+use crate::Principal;
+use store::{
+    Store, ValueKey,
+    write::{DirectoryClass, ValueClass},
+};
+use trc::AddContext;
+
+/// External IDs are unique within a tenant, or among the principals without
+/// a tenant, so they are keyed the same way as tenant-scoped names.
+pub fn external_id_key(external_id: &str, tenant_id: Option<u32>) -> Vec<u8> {
+    name_key(external_id, tenant_id)
+}
+
+#[allow(async_fn_in_trait)]
+pub trait ExternalIdLookup: Sync + Send {
+    async fn resolve_external_id(
+        &self,
+        external_id: &str,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<PrincipalInfo>>;
+}
+
+impl ExternalIdLookup for Store {
+    async fn resolve_external_id(
+        &self,
+        external_id: &str,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<PrincipalInfo>> {
+        self.get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
+            DirectoryClass::ExternalIdToId(external_id_key(external_id, tenant_id)),
+        )))
+        .await
+        .caused_by(trc::location!())
+    }
+}
+
+/// Fails with a conflict naming the current holder when another principal of
+/// the tenant, including those pending creation, has the external ID.
+pub(super) async fn assert_external_id_available(
+    store: &Store,
+    pending: &[Principal],
+    external_id: &str,
+    tenant_id: Option<u32>,
+    principal_id: Option<u32>,
+) -> trc::Result<()> {
+    let holder = if let Some(principal) = pending
+        .iter()
+        .find(|p| p.external_id() == Some(external_id) && p.tenant() == tenant_id)
+    {
+        Some((principal.id, principal.name.clone()))
+    } else if let Some(info) = store
+        .resolve_external_id(external_id, tenant_id)
+        .await?
+        .filter(|info| Some(info.id) != principal_id)
+    {
+        let name = store
+            .get_principal_name(info.id)
+            .await
+            .caused_by(trc::location!())?
+            .unwrap_or_else(|| info.id.to_string());
+        Some((info.id, name))
+    } else {
+        None
+    };
+
+    match holder {
+        Some((id, name)) if Some(id) != principal_id => Err(err_exists(
+            PrincipalField::ExternalId,
+            external_id.to_string(),
+        )
+        .ctx(trc::Key::AccountName, name)),
+        _ => Ok(()),
+    }
+}
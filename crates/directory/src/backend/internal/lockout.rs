@@ -0,0 +1,215 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{PrincipalField, manage::error};
+use crate::{AuthProtocol, Principal, PrincipalData};
+use std::time::Duration;
+use store::{InMemoryStore, Store, dispatch::lookup::KeyValue, write::now};
+use utils::config::Rate;
+
+/// In-memory key prefix of authentication failure counters.
+pub const KV_AUTH_FAILURES: u8 = 28;
+
+/// In-memory key prefix of locked accounts.
+pub const KV_ACCOUNT_LOCKED: u8 = 29;
+
+/// Maximum number of failures a tenant can allow before locking an account.
+pub const MAX_LOCKOUT_THRESHOLD: u64 = 1000;
+
+/// Maximum failure window and lockout duration, in seconds.
+pub const MAX_LOCKOUT_PERIOD: u64 = 30 * 86400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    pub threshold: u32,
+    pub window: u64,
+    pub duration: u64,
+    pub per_protocol: bool,
+    pub notify: bool,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 0,
+            window: 900,
+            duration: 900,
+            per_protocol: false,
+            notify: false,
+        }
+    }
+}
+
+/// Accounts are locked once the failed logins within the window of the
+/// tenant policy reach its threshold, and unlock when the lock expires or an
+/// administrator removes it. Failures are counted per account rather than
+/// per address so that distributed attempts are also caught.
+#[allow(async_fn_in_trait)]
+pub trait AccountLockout: Sync + Send {
+    /// Returns when the lock of an account expires.
+    async fn account_locked_until(&self, principal_id: u32) -> trc::Result<Option<u64>>;
+    /// Records a failed login and returns when the lock expires if the
+    /// account was locked as a result.
+    async fn record_auth_failure(
+        &self,
+        principal_id: u32,
+        policy: &LockoutPolicy,
+        protocol: Option<AuthProtocol>,
+    ) -> trc::Result<Option<u64>>;
+    /// Clears the failure counter after a successful login.
+    async fn reset_auth_failures(
+        &self,
+        principal_id: u32,
+        policy: &LockoutPolicy,
+        protocol: Option<AuthProtocol>,
+    ) -> trc::Result<()>;
+    /// Removes the lock and failure counters, returns false when the account
+    /// was not locked.
+    async fn unlock_account(&self, principal_id: u32) -> trc::Result<bool>;
+}
+
+impl AccountLockout for Store {
+    async fn account_locked_until(&self, principal_id: u32) -> trc::Result<Option<u64>> {
+        Ok(InMemoryStore::Store(self.clone())
+            .key_get::<String>(lock_key(principal_id))
+            .await?
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|until| *until > now()))
+    }
+
+    async fn record_auth_failure(
+        &self,
+        principal_id: u32,
+        policy: &LockoutPolicy,
+        protocol: Option<AuthProtocol>,
+    ) -> trc::Result<Option<u64>> {
+        if policy.threshold == 0 {
+            return Ok(None);
+        }
+
+        let kv = InMemoryStore::Store(self.clone());
+        if kv
+            .is_rate_allowed(
+                KV_AUTH_FAILURES,
+                &failures_key(principal_id, policy, protocol)[1..],
+                &Rate {
+                    requests: policy.threshold as u64 - 1,
+                    period: Duration::from_secs(policy.window),
+                },
+                false,
+            )
+            .await?
+            .is_some()
+        {
+            let until = now() + policy.duration;
+            kv.key_set(
+                KeyValue::new(lock_key(principal_id), until.to_string().into_bytes())
+                    .expires(policy.duration),
+            )
+            .await?;
+            kv.key_delete_prefix(&failures_prefix(principal_id)).await?;
+            Ok(Some(until))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn reset_auth_failures(
+        &self,
+        principal_id: u32,
+        policy: &LockoutPolicy,
+        protocol: Option<AuthProtocol>,
+    ) -> trc::Result<()> {
+        InMemoryStore::Store(self.clone())
+            .key_delete_prefix(&failures_key(principal_id, policy, protocol))
+            .await
+    }
+
+    async fn unlock_account(&self, principal_id: u32) -> trc::Result<bool> {
+        let was_locked = self.account_locked_until(principal_id).await?.is_some();
+        let kv = InMemoryStore::Store(self.clone());
+        kv.key_delete(lock_key(principal_id)).await?;
+        kv.key_delete_prefix(&failures_prefix(principal_id)).await?;
+        Ok(was_locked)
+    }
+}
+
+/// Sets a lockout policy setting of a tenant, a zero threshold disables it.
+pub(super) fn set_lockout_policy(
+    principal: &mut Principal,
+    field: PrincipalField,
+    value: u64,
+) -> trc::Result<()> {
+    let (min, max) = match field {
+        PrincipalField::LockoutThreshold => (0, MAX_LOCKOUT_THRESHOLD),
+        PrincipalField::LockoutWindow | PrincipalField::LockoutDuration => (1, MAX_LOCKOUT_PERIOD),
+        _ => (0, 1),
+    };
+    if !(min..=max).contains(&value) {
+        return Err(error(
+            "Invalid parameter",
+            format!("{} must be between {min} and {max}", field.as_str()).into(),
+        ));
+    }
+
+    let mut policy = principal.lockout_policy().unwrap_or_default();
+    match field {
+        PrincipalField::LockoutThreshold => policy.threshold = value as u32,
+        PrincipalField::LockoutWindow => policy.window = value,
+        PrincipalField::LockoutDuration => policy.duration = value,
+        PrincipalField::LockoutPerProtocol => policy.per_protocol = value != 0,
+        _ => policy.notify = value != 0,
+    }
+
+    principal
+        .data
+        .retain(|v| !matches!(v, PrincipalData::LockoutPolicy { .. }));
+    if policy != LockoutPolicy::default() {
+        principal.data.push(PrincipalData::LockoutPolicy {
+            threshold: policy.threshold,
+            window: policy.window,
+            duration: policy.duration,
+            per_protocol: policy.per_protocol,
+            notify: policy.notify,
+        });
+    }
+
+    Ok(())
+}
+
+pub(super) fn lockout_prefixes(principal_id: u32) -> [Vec<u8>; 2] {
+    [failures_prefix(principal_id), lock_key(principal_id)]
+}
+
+fn failures_prefix(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_AUTH_FAILURES);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
+
+// Failures are counted in a single bucket unless the policy counts each
+// protocol separately
+fn failures_key(
+    principal_id: u32,
+    policy: &LockoutPolicy,
+    protocol: Option<AuthProtocol>,
+) -> Vec<u8> {
+    let mut key = failures_prefix(principal_id);
+    key.push(
+        protocol
+            .filter(|_| policy.per_protocol)
+            .map_or(0, |protocol| protocol as u8 + 1),
+    );
+    key
+}
+
+fn lock_key(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_ACCOUNT_LOCKED);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
@@ -4,12 +4,20 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use super::{PrincipalInfo, manage::ManageDirectory};
-use crate::{Principal, PrincipalData, QueryBy, QueryParams, Type, backend::RcptType};
+use super::{
+    PrincipalInfo, app_password::AppPasswords, domain_variants, email_variants,
+    lockout::AccountLockout, manage::ManageDirectory, mfa::TotpFactors, recovery::RecoveryCodes,
+};
+use crate::{
+    Principal, PrincipalData, QueryBy, QueryParams, Type,
+    backend::RcptType,
+    core::secret::{SecretMatch, split_recovery_code, split_totp_code},
+};
 use mail_send::Credentials;
+use std::sync::atomic::Ordering;
 use store::{
     Deserialize, IterateParams, Store, ValueKey,
-    write::{DirectoryClass, ValueClass},
+    write::{DirectoryClass, ValueClass, now},
 };
 use trc::AddContext;
 use utils::DomainPart;
@@ -27,19 +35,28 @@ pub trait DirectoryStore: Sync + Send {
 
 impl DirectoryStore for Store {
     async fn query(&self, by: QueryParams<'_>) -> trc::Result<Option<Principal>> {
+        // Names and logins may refer to tenant-scoped principals, see
+        // `ManageDirectory::resolve_principal_info` for the resolution rules
         let (account_id, secret) = match by.by {
-            QueryBy::Name(name) => (self.get_principal_id(name).await?, None),
+            QueryBy::Name(name) => (
+                self.resolve_principal_info(name, None).await?.map(|v| v.id),
+                None,
+            ),
             QueryBy::Id(account_id) => (account_id.into(), None),
             QueryBy::Credentials(credentials) => match credentials {
                 Credentials::Plain { username, secret } => (
-                    self.get_principal_id(username).await?,
+                    self.resolve_principal_info(username, None)
+                        .await?
+                        .map(|v| v.id),
                     secret.as_str().into(),
                 ),
                 Credentials::OAuthBearer { token } => {
                     (self.get_principal_id(token).await?, token.as_str().into())
                 }
                 Credentials::XOauth2 { username, secret } => (
-                    self.get_principal_id(username).await?,
+                    self.resolve_principal_info(username, None)
+                        .await?
+                        .map(|v| v.id),
                     secret.as_str().into(),
                 ),
             },
@@ -48,12 +65,139 @@ impl DirectoryStore for Store {
         if let Some(account_id) = account_id
             && let Some(mut principal) = self.get_principal(account_id).await?
         {
-            if let Some(secret) = secret
-                && !principal
-                    .verify_secret(secret, by.only_app_pass, true)
-                    .await?
-            {
-                return Ok(None);
+            if let Some(secret) = secret {
+                // Locked accounts are rejected before the secret is checked
+                let lockout = if let Some(tenant_id) = principal.tenant() {
+                    self.get_principal(tenant_id)
+                        .await?
+                        .and_then(|tenant| tenant.lockout_policy())
+                        .filter(|policy| policy.threshold > 0)
+                } else {
+                    None
+                };
+                if lockout.is_some()
+                    && let Some(until) = self.account_locked_until(account_id).await?
+                {
+                    return Err(trc::AuthEvent::AccountLocked
+                        .into_err()
+                        .account_id(account_id)
+                        .ctx(trc::Key::Expires, until));
+                }
+
+                // Logins with a TOTP or recovery code count towards the code
+                // attempts of the account
+                let is_recovery_attempt = !by.only_app_pass
+                    && principal.recovery_code_count() > 0
+                    && split_recovery_code(secret).is_some();
+                let is_totp_attempt = is_recovery_attempt
+                    || (!by.only_app_pass
+                        && split_totp_code(secret).is_some()
+                        && principal
+                            .data
+                            .iter()
+                            .any(|item| matches!(item, PrincipalData::OtpAuth(_))));
+                if is_totp_attempt && self.is_totp_limited(account_id).await? {
+                    return Err(trc::AuthEvent::TooManyAttempts
+                        .into_err()
+                        .account_id(account_id));
+                }
+
+                let mut password_expired = false;
+                let mut secret_match = None;
+                if is_recovery_attempt {
+                    secret_match = principal.match_recovery_code(secret).await?;
+                }
+                if secret_match.is_none() {
+                    secret_match = principal
+                        .match_secret(
+                            secret,
+                            by.only_app_pass,
+                            true,
+                            by.protocol,
+                            by.secret_cipher,
+                        )
+                        .await?;
+                }
+                let used_recovery_code = match secret_match {
+                    Some(SecretMatch::RecoveryCode(hash)) => self
+                        .consume_recovery_code(account_id, hash)
+                        .await?
+                        .then(|| hash.to_string()),
+                    _ => None,
+                };
+                match secret_match {
+                    // Codes used by a concurrent login are rejected
+                    Some(SecretMatch::RecoveryCode(_)) if used_recovery_code.is_none() => {
+                        self.record_totp_failure(account_id).await?;
+                        return Ok(None);
+                    }
+                    Some(SecretMatch::Password | SecretMatch::RecoveryCode(_)) => {
+                        if is_totp_attempt {
+                            self.reset_totp_failures(account_id).await?;
+                        }
+
+                        // Accounts without a password change date expire
+                        // based on their creation date
+                        password_expired = by.password_policy.is_some_and(|policy| {
+                            principal
+                                .password_changed_at()
+                                .or_else(|| principal.created_at())
+                                .is_some_and(|changed_at| policy.is_expired(changed_at, now()))
+                        });
+                    }
+                    Some(SecretMatch::AppPassword(_))
+                        if by
+                            .password_policy
+                            .is_some_and(|policy| !policy.allow_app_passwords) =>
+                    {
+                        return Ok(None);
+                    }
+                    Some(SecretMatch::AppPassword(label)) => {
+                        if let Err(err) = self.app_password_used(account_id, label).await {
+                            trc::error!(
+                                err.account_id(account_id)
+                                    .details("Failed to record app password use")
+                                    .caused_by(trc::location!())
+                            );
+                        }
+                    }
+                    None => {
+                        if is_totp_attempt {
+                            self.record_totp_failure(account_id).await?;
+                        }
+                        if let Some(policy) = lockout
+                            && let Some(until) = self
+                                .record_auth_failure(account_id, &policy, by.protocol)
+                                .await?
+                        {
+                            return Err(trc::DirectoryEvent::AccountLocked
+                                .into_err()
+                                .account_id(account_id)
+                                .ctx(trc::Key::Expires, until));
+                        }
+                        return Ok(None);
+                    }
+                }
+
+                if let Some(policy) = lockout {
+                    self.reset_auth_failures(account_id, &policy, by.protocol)
+                        .await?;
+                }
+
+                if let Some(hash) = used_recovery_code {
+                    principal.data.retain(
+                        |item| !matches!(item, PrincipalData::RecoveryCode(code) if *code == hash),
+                    );
+                    if let Some(used) = by.recovery_code_used {
+                        used.store(true, Ordering::Relaxed);
+                    }
+                }
+
+                if password_expired {
+                    return Err(trc::AuthEvent::PasswordExpired
+                        .into_err()
+                        .account_id(account_id));
+                }
             }
 
             if by.return_member_of {
@@ -77,28 +221,29 @@ impl DirectoryStore for Store {
     }
 
     async fn email_to_id(&self, address: &str) -> trc::Result<Option<u32>> {
-        self.get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
-            DirectoryClass::EmailToId(address.as_bytes().to_vec()),
-        )))
-        .await
-        .map(|ptype| ptype.map(|ptype| ptype.id))
+        email_info(self, address)
+            .await
+            .map(|ptype| ptype.map(|ptype| ptype.id))
     }
 
     async fn is_local_domain(&self, domain: &str) -> trc::Result<bool> {
-        self.get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
-            DirectoryClass::NameToId(domain.as_bytes().to_vec()),
-        )))
-        .await
-        .map(|p| p.is_some_and(|p| p.typ == Type::Domain))
+        for domain in domain_variants(domain) {
+            if self
+                .get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
+                    DirectoryClass::NameToId(domain.into_bytes()),
+                )))
+                .await?
+                .is_some_and(|p| p.typ == Type::Domain)
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
     }
 
     async fn rcpt(&self, address: &str) -> trc::Result<RcptType> {
-        if let Some(pinfo) = self
-            .get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
-                DirectoryClass::EmailToId(address.as_bytes().to_vec()),
-            )))
-            .await?
-        {
+        if let Some(pinfo) = email_info(self, address).await? {
             if pinfo.typ != Type::List {
                 Ok(RcptType::Mailbox)
             } else {
@@ -142,10 +287,7 @@ impl DirectoryStore for Store {
     }
 
     async fn expn(&self, address: &str) -> trc::Result<Vec<String>> {
-        if let Some(ptype) = self
-            .get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
-                DirectoryClass::EmailToId(address.as_bytes().to_vec()),
-            )))
+        if let Some(ptype) = email_info(self, address)
             .await?
             .filter(|p| p.typ == Type::List)
         {
@@ -186,3 +328,20 @@ impl DirectoryStore for Store {
         Ok(results)
     }
 }
+
+/// Addresses are stored normalized, but records written before normalization
+/// was introduced may use a Unicode domain, so all variants are tried.
+async fn email_info(store: &Store, address: &str) -> trc::Result<Option<PrincipalInfo>> {
+    for key in email_variants(address) {
+        if let Some(info) = store
+            .get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::EmailToId(key.into_bytes()),
+            )))
+            .await?
+        {
+            return Ok(Some(info));
+        }
+    }
+
+    Ok(None)
+}
@@ -4,9 +4,22 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use super::activity::{PrincipalActivity, activity_prefix};
+use super::avatar::set_avatar_self_service;
+use super::external_id::{assert_external_id_available, external_id_key};
+use super::lockout::{lockout_prefixes, set_lockout_policy};
+use super::mfa::mfa_prefixes;
+use super::password::{rotate_password, set_password_policy};
+use super::schedule::{apply_schedule, notified_key, set_schedule};
+use super::security::{security_prefixes, set_security_notice_opt_out};
+use super::trial::{TrialStatus, set_trial, trial_notified_key};
+use super::webauthn::counter_prefix;
 use super::{
+    MAX_METADATA_ENTRIES, MAX_METADATA_KEY_LEN, MAX_TAG_LEN, MAX_TAGS, NamePolicy, NameScope,
     PrincipalAction, PrincipalField, PrincipalInfo, PrincipalSet, PrincipalUpdate, PrincipalValue,
-    SpecialSecrets, lookup::DirectoryStore,
+    SpecialSecrets, TENANT_CONTACT_FIELDS, app_password::last_used_prefix, domain_variants,
+    email_variants, is_valid_metadata_key, is_valid_tag, lookup::DirectoryStore, metadata_entry,
+    name_from_key, name_key, normalize_email, sanitize_contact, sanitize_sender,
 };
 use crate::{
     ArchivedPrincipalData, FALLBACK_ADMIN_ID, MemberOf, Permission, PermissionGrant, Permissions,
@@ -17,12 +30,13 @@ use ahash::{AHashMap, AHashSet};
 use compact_str::CompactString;
 use nlp::tokenizers::word::WordTokenizer;
 use store::{
-    Deserialize, IterateParams, Serialize, SerializeInfallible, Store, U32_LEN, ValueKey,
+    Deserialize, InMemoryStore, IterateParams, Serialize, SerializeInfallible, Store, U32_LEN,
+    ValueKey,
     backend::MAX_TOKEN_LENGTH,
     roaring::RoaringBitmap,
     write::{
         AlignedBytes, Archive, Archiver, BatchBuilder, DirectoryClass, ValueClass,
-        key::DeserializeBigEndian,
+        key::DeserializeBigEndian, now,
     },
 };
 use trc::AddContext;
@@ -38,17 +52,35 @@ pub struct PrincipalList<T> {
     pub total: u64,
 }
 
+/// Sorting and filters of a principal listing that can only be applied once
+/// the principals have been fetched.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrincipalListOrder {
+    pub sort_by: Option<PrincipalField>,
+    pub descending: bool,
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub metadata: Vec<(String, String)>,
+    pub tags: Vec<String>,
+    pub inactive_since: Option<u64>,
+    pub trial: Option<TrialStatus>,
+}
+
 pub struct UpdatePrincipal<'x> {
     query: QueryBy<'x>,
     allowed_permissions: Option<&'x Permissions>,
     changes: Vec<PrincipalUpdate>,
     tenant_id: Option<u32>,
     create_domains: bool,
+    self_service: bool,
+    name_policy: Option<&'x NamePolicy>,
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
-#[repr(transparent)]
-pub struct ChangedPrincipals(AHashMap<u32, ChangedPrincipal>);
+pub struct ChangedPrincipals {
+    principals: AHashMap<u32, ChangedPrincipal>,
+    changes: Vec<PrincipalChange>,
+}
 
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct ChangedPrincipal {
@@ -57,16 +89,66 @@ pub struct ChangedPrincipal {
     pub member_change: bool,
 }
 
+/// A principal that was created, updated or deleted, as reported to
+/// directory change subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrincipalChange {
+    pub action: PrincipalChangeAction,
+    pub id: u32,
+    pub typ: Type,
+    pub tenant_id: Option<u32>,
+    pub fields: Vec<PrincipalField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrincipalChangeAction {
+    Created,
+    Updated,
+    Deleted,
+}
+
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct CreatedPrincipal {
     pub id: u32,
     pub changed_principals: ChangedPrincipals,
 }
 
+/// A principal created as part of a batch, optionally assigned to a tenant
+/// created by an earlier entry of the same batch.
+#[derive(Debug)]
+pub struct BatchPrincipal {
+    pub principal: PrincipalSet,
+    pub tenant: Option<BatchTenant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchTenant {
+    Existing(u32),
+    /// Position of the tenant in the batch.
+    Created(usize),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CreatedPrincipals {
+    pub ids: Vec<u32>,
+    pub changed_principals: ChangedPrincipals,
+}
+
 #[allow(async_fn_in_trait)]
 pub trait ManageDirectory: Sized {
     async fn get_principal_id(&self, name: &str) -> trc::Result<Option<u32>>;
     async fn get_principal_info(&self, name: &str) -> trc::Result<Option<PrincipalInfo>>;
+    async fn get_domain_id(&self, email: &str) -> trc::Result<Option<u32>>;
+    async fn get_tenant_principal_info(
+        &self,
+        name: &str,
+        tenant_id: u32,
+    ) -> trc::Result<Option<PrincipalInfo>>;
+    async fn resolve_principal_info(
+        &self,
+        name: &str,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<PrincipalInfo>>;
     async fn get_or_create_principal_id(&self, name: &str, typ: Type) -> trc::Result<u32>;
     async fn get_principal(&self, principal_id: u32) -> trc::Result<Option<Principal>>;
     async fn get_principal_name(&self, principal_id: u32) -> trc::Result<Option<String>>;
@@ -78,6 +160,19 @@ pub trait ManageDirectory: Sized {
         tenant_id: Option<u32>,
         allowed_permissions: Option<&Permissions>,
     ) -> trc::Result<CreatedPrincipal>;
+    async fn create_principal_with_policy(
+        &self,
+        principal: PrincipalSet,
+        tenant_id: Option<u32>,
+        allowed_permissions: Option<&Permissions>,
+        name_policy: &NamePolicy,
+    ) -> trc::Result<CreatedPrincipal>;
+    async fn create_principals_batch(
+        &self,
+        principals: Vec<BatchPrincipal>,
+        allowed_permissions: Option<&Permissions>,
+        name_policy: &NamePolicy,
+    ) -> trc::Result<CreatedPrincipals>;
     async fn update_principal(&self, params: UpdatePrincipal<'_>)
     -> trc::Result<ChangedPrincipals>;
     async fn delete_principal(&self, by: QueryBy<'_>) -> trc::Result<ChangedPrincipals>;
@@ -96,11 +191,18 @@ pub trait ManageDirectory: Sized {
         typ: Option<Type>,
         tenant_id: Option<u32>,
     ) -> trc::Result<u64>;
+    async fn count_tenant_principals(&self, tenant_id: u32, typ: Type) -> trc::Result<u64>;
+    async fn repair_principal_counts(&self) -> trc::Result<usize>;
     async fn principal_ids(
         &self,
         typ: Option<Type>,
         tenant_id: Option<u32>,
     ) -> trc::Result<RoaringBitmap>;
+    async fn tenant_principals(
+        &self,
+        types: &[Type],
+        tenant_ids: &AHashSet<u32>,
+    ) -> trc::Result<AHashMap<u32, Vec<PrincipalInfo>>>;
     async fn map_principal(
         &self,
         principal: Principal,
@@ -118,6 +220,85 @@ trait ValidateDirectory: Sized {
     ) -> trc::Result<()>;
 }
 
+impl PrincipalListOrder {
+    /// Whether the listing is returned in name order without filtering.
+    pub fn is_default(&self) -> bool {
+        matches!(self.sort_by, None | Some(PrincipalField::Name))
+            && !self.descending
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+            && self.metadata.is_empty()
+            && self.tags.is_empty()
+            && self.inactive_since.is_none()
+            && self.trial.is_none()
+    }
+}
+
+impl PrincipalList<Principal> {
+    /// Filters and sorts a complete listing, then returns the requested page.
+    /// Principals created before timestamps were recorded have no creation
+    /// time, they sort first and are excluded by creation time filters.
+    pub fn ordered(mut self, order: &PrincipalListOrder, page: usize, limit: usize) -> Self {
+        if order.created_after.is_some() || order.created_before.is_some() {
+            self.items.retain(|principal| {
+                principal.created_at().is_some_and(|created_at| {
+                    order.created_after.is_none_or(|after| created_at >= after)
+                        && order
+                            .created_before
+                            .is_none_or(|before| created_at < before)
+                })
+            });
+        }
+        if !order.metadata.is_empty() {
+            self.items.retain(|principal| {
+                order
+                    .metadata
+                    .iter()
+                    .all(|(key, value)| principal.metadata_value(key) == Some(value.as_str()))
+            });
+        }
+
+        if !order.tags.is_empty() {
+            self.items.retain(|principal| {
+                order
+                    .tags
+                    .iter()
+                    .all(|tag| principal.tags().any(|t| t == tag))
+            });
+        }
+        if let Some(trial) = order.trial {
+            let now = now();
+            self.items
+                .retain(|principal| principal.trial_status(now) == trial);
+        }
+
+        match order.sort_by {
+            Some(PrincipalField::CreatedAt) => self.items.sort_by(|a, b| {
+                a.created_at()
+                    .cmp(&b.created_at())
+                    .then(a.name.cmp(&b.name))
+            }),
+            Some(PrincipalField::ModifiedAt) => self.items.sort_by(|a, b| {
+                a.modified_at()
+                    .cmp(&b.modified_at())
+                    .then(a.name.cmp(&b.name))
+            }),
+            _ => self.items.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+        if order.descending {
+            self.items.reverse();
+        }
+
+        let total = self.items.len() as u64;
+        let offset = page.saturating_sub(1) * limit;
+        let limit = if limit > 0 { limit } else { usize::MAX };
+        PrincipalList {
+            items: self.items.into_iter().skip(offset).take(limit).collect(),
+            total,
+        }
+    }
+}
+
 impl ManageDirectory for Store {
     async fn get_principal(&self, principal_id: u32) -> trc::Result<Option<Principal>> {
         let archive = self
@@ -168,6 +349,83 @@ impl ManageDirectory for Store {
         .caused_by(trc::location!())
     }
 
+    /// Resolves the domain principal of an address, if the domain is local.
+    async fn get_domain_id(&self, email: &str) -> trc::Result<Option<u32>> {
+        match email.try_domain_part() {
+            Some(domain) => self
+                .get_principal_info(&domain.to_lowercase())
+                .await
+                .map(|info| {
+                    info.filter(|info| info.typ == Type::Domain)
+                        .map(|info| info.id)
+                }),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_tenant_principal_info(
+        &self,
+        name: &str,
+        tenant_id: u32,
+    ) -> trc::Result<Option<PrincipalInfo>> {
+        self.get_value::<PrincipalInfo>(ValueKey::from(ValueClass::Directory(
+            DirectoryClass::NameToId(name_key(name, tenant_id.into())),
+        )))
+        .await
+        .caused_by(trc::location!())
+    }
+
+    /// Resolves a name that may refer to a tenant-scoped principal:
+    ///
+    /// - An exact match on a global name always wins, so existing principals
+    ///   resolve as before.
+    /// - Otherwise, when a tenant is provided, the name is looked up within
+    ///   that tenant.
+    /// - Otherwise, `local@domain` resolves `local` within the tenant that
+    ///   `domain` is assigned to.
+    ///
+    /// A bare name with no tenant never matches a tenant-scoped principal, as
+    /// the same name may exist in several tenants.
+    async fn resolve_principal_info(
+        &self,
+        name: &str,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<PrincipalInfo>> {
+        // Logins and domains may be given in a different case or IDNA form
+        let variants = if name.contains('@') {
+            email_variants(name)
+        } else if name.contains('.') {
+            domain_variants(name)
+        } else {
+            vec![name.to_string()]
+        };
+        for variant in &variants {
+            if let Some(info) = self.get_principal_info(variant).await? {
+                return Ok(Some(info));
+            }
+        }
+
+        if let Some(tenant_id) = tenant_id
+            && let Some(info) = self.get_tenant_principal_info(name, tenant_id).await?
+        {
+            return Ok(Some(info));
+        }
+
+        if let Some((local, domain)) = name.rsplit_once('@')
+            && !local.is_empty()
+            && let Some(domain_tenant_id) = self
+                .get_principal_info(domain)
+                .await?
+                .filter(|v| v.typ == Type::Domain)
+                .and_then(|v| v.tenant)
+        {
+            self.get_tenant_principal_info(local, domain_tenant_id)
+                .await
+        } else {
+            Ok(None)
+        }
+    }
+
     // Used by all directories except internal
     async fn get_or_create_principal_id(&self, name: &str, typ: Type) -> trc::Result<u32> {
         let mut try_count = 0;
@@ -204,6 +462,9 @@ impl ManageDirectory for Store {
             // Prepare principal
             let mut principal = Principal::new(principal_id, typ);
             principal.name = name.as_str().into();
+            let created_at = now();
+            principal.data.push(PrincipalData::CreatedAt(created_at));
+            principal.data.push(PrincipalData::ModifiedAt(created_at));
 
             // Write principal ID
             let name_key =
@@ -265,516 +526,219 @@ impl ManageDirectory for Store {
 
     async fn create_principal(
         &self,
-        mut principal_set: PrincipalSet,
-        mut tenant_id: Option<u32>,
+        principal_set: PrincipalSet,
+        tenant_id: Option<u32>,
         allowed_permissions: Option<&Permissions>,
     ) -> trc::Result<CreatedPrincipal> {
-        // Make sure the principal has a name
-        let name = principal_set.name().to_lowercase();
-        if name.is_empty() {
-            return Err(err_missing(PrincipalField::Name));
-        }
-        let mut valid_domains: AHashSet<String> = AHashSet::new();
+        self.create_principal_with_policy(
+            principal_set,
+            tenant_id,
+            allowed_permissions,
+            &NamePolicy::default(),
+        )
+        .await
+    }
 
-        // SPDX-SnippetBegin
-        // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
-        // SPDX-License-Identifier: LicenseRef-SEL
+    async fn create_principal_with_policy(
+        &self,
+        principal_set: PrincipalSet,
+        tenant_id: Option<u32>,
+        allowed_permissions: Option<&Permissions>,
+        name_policy: &NamePolicy,
+    ) -> trc::Result<CreatedPrincipal> {
+        let mut batch = BatchBuilder::new();
+        let mut changed_principals = ChangedPrincipals::default();
+        let principal_id = build_principal(
+            self,
+            principal_set,
+            tenant_id,
+            allowed_permissions,
+            name_policy,
+            &mut Vec::new(),
+            &mut batch,
+            &mut changed_principals,
+        )
+        .await?;
 
-        // Validate tenant
-        #[cfg(feature = "enterprise")]
-        if let Some(tenant_id) = tenant_id {
-            let tenant = self
-                .query(crate::QueryParams::id(tenant_id).with_return_member_of(false))
-                .await?
-                .ok_or_else(|| {
-                    trc::ManageEvent::NotFound
-                        .into_err()
-                        .id(tenant_id)
-                        .details("Tenant not found")
-                        .caused_by(trc::location!())
-                })?;
+        self.write(batch.build_all())
+            .await
+            .map(|_| CreatedPrincipal {
+                id: principal_id,
+                changed_principals,
+            })
+    }
 
-            // Enforce tenant quotas
-            if let Some(limit) = tenant
-                .directory_quota(&principal_set.typ())
-                .filter(|q| *q > 0)
-            {
-                // Obtain number of principals
-                let total = self
-                    .count_principals(None, principal_set.typ().into(), tenant_id.into())
-                    .await
-                    .caused_by(trc::location!())? as u32;
-
-                if total >= limit {
-                    trc::bail!(
-                        trc::LimitEvent::TenantQuota
-                            .into_err()
-                            .details("Tenant principal quota exceeded")
-                            .ctx(trc::Key::Details, principal_set.typ().description())
-                            .ctx(trc::Key::Limit, limit)
-                            .ctx(trc::Key::Total, total)
-                    );
-                }
-            }
+    async fn create_principals_batch(
+        &self,
+        principals: Vec<BatchPrincipal>,
+        allowed_permissions: Option<&Permissions>,
+        name_policy: &NamePolicy,
+    ) -> trc::Result<CreatedPrincipals> {
+        let mut batch = BatchBuilder::new();
+        let mut changed_principals = ChangedPrincipals::default();
+        let mut pending = Vec::with_capacity(principals.len());
+
+        for (idx, item) in principals.into_iter().enumerate() {
+            let tenant_id = match item.tenant {
+                Some(BatchTenant::Existing(tenant_id)) => Some(tenant_id),
+                Some(BatchTenant::Created(pos)) => match pending.get(pos) {
+                    Some(tenant) if tenant.typ == Type::Tenant => Some(tenant.id),
+                    _ => {
+                        return Err(error(
+                            "Invalid tenant",
+                            format!("Entry {idx} does not reference a tenant created before it")
+                                .into(),
+                        ));
+                    }
+                },
+                None => None,
+            };
+            let typ = item.principal.typ();
+
+            build_principal(
+                self,
+                item.principal,
+                tenant_id,
+                allowed_permissions,
+                name_policy,
+                &mut pending,
+                &mut batch,
+                &mut changed_principals,
+            )
+            .await
+            .map_err(|err| err.ctx(trc::Key::Type, typ.as_str()))?;
         }
 
-        // SPDX-SnippetEnd
+        // All principals are written in a single transaction
+        self.write(batch.build_all())
+            .await
+            .map(|_| CreatedPrincipals {
+                ids: pending.iter().map(|principal| principal.id).collect(),
+                changed_principals,
+            })
+    }
+
+    async fn delete_principal(&self, by: QueryBy<'_>) -> trc::Result<ChangedPrincipals> {
+        // Obtain principal
+        let principal_id = match by {
+            QueryBy::Name(name) => {
+                self.resolve_principal_info(name, None)
+                    .await
+                    .caused_by(trc::location!())?
+                    .ok_or_else(|| not_found(name.to_string()))?
+                    .id
+            }
+            QueryBy::Id(principal_id) => principal_id,
+            QueryBy::Credentials(_) => unreachable!(),
+        };
 
-        // Make sure new name is not taken
-        if self
-            .get_principal_id(&name)
+        let principal_ = self
+            .get_value::<Archive<AlignedBytes>>(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::Principal(principal_id),
+            )))
             .await
             .caused_by(trc::location!())?
-            .is_some()
+            .ok_or_else(|| not_found(principal_id.to_string()))?;
+        let principal = principal_
+            .unarchive::<Principal>()
+            .caused_by(trc::location!())?;
+        let typ = Type::from(&principal.typ);
+
+        // Accounts on legal hold cannot be deleted
+        if principal
+            .data
+            .iter()
+            .any(|data| matches!(data, ArchivedPrincipalData::LegalHold { .. }))
         {
-            return Err(err_exists(PrincipalField::Name, name));
+            return Err(error(
+                "Account is on legal hold",
+                "Release the legal hold before deleting this account".into(),
+            ));
         }
 
-        let mut create_principal = Principal::new(0, principal_set.typ());
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal);
+
+        let tenant = principal.data.iter().find_map(|data| {
+            if let ArchivedPrincipalData::Tenant(tenant_id) = data {
+                Some(tenant_id.to_native())
+            } else {
+                None
+            }
+        });
+
+        // Remove the usage of the account from the rollup of its domain
+        if matches!(typ, Type::Individual | Type::Group) {
+            let primary_email = principal.data.iter().find_map(|data| {
+                if let ArchivedPrincipalData::PrimaryEmail(email) = data {
+                    Some(email.as_str())
+                } else {
+                    None
+                }
+            });
+            if let Some(email) = primary_email {
+                let domain_id = self
+                    .get_domain_id(email)
+                    .await
+                    .caused_by(trc::location!())?;
+                move_domain_usage(self, &mut batch, principal_id, domain_id, None).await?;
+            }
+        }
 
         // SPDX-SnippetBegin
         // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
         // SPDX-License-Identifier: LicenseRef-SEL
 
-        // Obtain tenant id, only if no default tenant is provided
-        #[cfg(feature = "enterprise")]
-        if let (Some(tenant_name), None) =
-            (principal_set.take_str(PrincipalField::Tenant), tenant_id)
-        {
-            tenant_id = self
-                .get_principal_info(&tenant_name)
-                .await
-                .caused_by(trc::location!())?
-                .filter(|v| v.typ == Type::Tenant)
-                .ok_or_else(|| not_found(tenant_name.clone()))?
-                .id
-                .into();
-        }
-
-        // Tenants must provide principal names including a valid domain
+        // Make sure tenant has no data
         #[cfg(feature = "enterprise")]
-        if let Some(tenant_id) = tenant_id {
-            if matches!(principal_set.typ, Type::Tenant) {
-                return Err(error(
-                    "Invalid field",
-                    "Tenants cannot contain a tenant field".into(),
-                ));
-            }
-
-            create_principal.data.push(PrincipalData::Tenant(tenant_id));
-
-            if !matches!(create_principal.typ, Type::Tenant | Type::Domain) {
-                if let Some(domain) = name.try_domain_part()
-                    && self
-                        .get_principal_info(domain)
+        match typ {
+            Type::Individual | Type::Group => {
+                // Update tenant quota
+                if let Some(tenant_id) = tenant {
+                    let quota = self
+                        .get_counter(DirectoryClass::UsedQuota(principal_id))
                         .await
-                        .caused_by(trc::location!())?
-                        .filter(|v| v.typ == Type::Domain && v.has_tenant_access(tenant_id.into()))
-                        .is_some()
-                {
-                    valid_domains.insert(domain.into());
-                }
-
-                if valid_domains.is_empty() {
-                    return Err(error(
-                        "Invalid principal name",
-                        "Principal name must include a valid domain assigned to the tenant".into(),
-                    ));
+                        .caused_by(trc::location!())?;
+                    if quota > 0 {
+                        batch.add(DirectoryClass::UsedQuota(tenant_id), -quota);
+                    }
                 }
             }
-        }
-        // SPDX-SnippetEnd
+            Type::Tenant => {
+                let tenant_members = self
+                    .list_principals(
+                        None,
+                        principal_id.into(),
+                        &[
+                            Type::Individual,
+                            Type::Group,
+                            Type::Role,
+                            Type::List,
+                            Type::Resource,
+                            Type::Other,
+                            Type::Location,
+                            Type::Domain,
+                            Type::ApiKey,
+                        ],
+                        false,
+                        0,
+                        0,
+                    )
+                    .await
+                    .caused_by(trc::location!())?;
 
-        // Set fields
-        create_principal.name = name;
-        let mut has_secret = false;
-        for secret in principal_set
-            .take_str_array(PrincipalField::Secrets)
-            .unwrap_or_default()
-        {
-            if secret.is_otp_secret() {
-                create_principal.data.push(PrincipalData::OtpAuth(secret));
-            } else if secret.is_app_secret() {
-                create_principal
-                    .data
-                    .push(PrincipalData::AppPassword(secret));
-            } else if !has_secret {
-                has_secret = true;
-                create_principal.data.push(PrincipalData::Password(secret));
-            }
-        }
+                if tenant_members.total > 0 {
+                    let mut message =
+                        String::from("Tenant must have no members to be deleted: Found: ");
 
-        if let Some(description) = principal_set.take_str(PrincipalField::Description) {
-            create_principal
-                .data
-                .push(PrincipalData::Description(description));
-        }
-
-        if let Some(picture) = principal_set.take_str(PrincipalField::Picture) {
-            create_principal.data.push(PrincipalData::Picture(picture));
-        }
-        if let Some(picture) = principal_set.take_str(PrincipalField::Locale) {
-            create_principal.data.push(PrincipalData::Locale(picture));
-        }
-        if let Some(brand_name) = principal_set.take_str(PrincipalField::BrandName) {
-            create_principal
-                .data
-                .push(PrincipalData::BrandName(brand_name));
-        }
-        if let Some(brand_logo_url) = principal_set.take_str(PrincipalField::BrandLogoUrl) {
-            create_principal
-                .data
-                .push(PrincipalData::BrandLogoUrl(brand_logo_url));
-        }
-        if let Some(brand_theme) = principal_set.take_str(PrincipalField::BrandTheme) {
-            create_principal
-                .data
-                .push(PrincipalData::BrandTheme(brand_theme));
-        }
-        for url in principal_set
-            .take_str_array(PrincipalField::Urls)
-            .unwrap_or_default()
-        {
-            create_principal.data.push(PrincipalData::Url(url));
-        }
-        for member in principal_set
-            .take_str_array(PrincipalField::ExternalMembers)
-            .unwrap_or_default()
-        {
-            create_principal
-                .data
-                .push(PrincipalData::ExternalMember(member));
-        }
-        if let Some(quotas) = principal_set.take_int_array(PrincipalField::Quota) {
-            for (idx, quota) in quotas.into_iter().take(Type::MAX_ID + 2).enumerate() {
-                if quota != 0 {
-                    if idx != 0 {
-                        create_principal.data.push(PrincipalData::DirectoryQuota {
-                            quota: quota as u32,
-                            typ: Type::from_u8((idx - 1) as u8),
-                        });
-                    } else {
-                        create_principal.data.push(PrincipalData::DiskQuota(quota));
-                    }
-                }
-            }
-        }
-
-        // Map member names
-        let mut members = Vec::new();
-        let mut member_of = Vec::new();
-        let mut changed_principals = ChangedPrincipals::default();
-        for (field, expected_type) in [
-            (PrincipalField::Members, None),
-            (PrincipalField::MemberOf, Some(Type::Group)),
-            (PrincipalField::Lists, Some(Type::List)),
-            (PrincipalField::Roles, Some(Type::Role)),
-        ] {
-            if let Some(names) = principal_set.take_str_array(field) {
-                let list = if field == PrincipalField::Members {
-                    &mut members
-                } else {
-                    &mut member_of
-                };
-
-                for name in names {
-                    let item = match (
-                        self.get_principal_info(&name)
-                            .await
-                            .caused_by(trc::location!())?
-                            .filter(|v| {
-                                expected_type.is_none_or(|t| v.typ == t)
-                                    && v.has_tenant_access(tenant_id)
-                            }),
-                        field.map_internal_roles(&name),
-                    ) {
-                        (_, Some(v)) => v,
-                        (Some(v), _) => {
-                            if field == PrincipalField::Members {
-                                // Update principal members
-                                changed_principals.add_change(
-                                    v.id,
-                                    v.typ,
-                                    PrincipalField::MemberOf,
-                                );
-                            }
-                            v
-                        }
-                        _ => {
-                            return Err(not_found(name));
-                        }
-                    };
-
-                    list.push(item);
-                }
-            }
-        }
-
-        // Map permissions
-        let mut permissions = AHashMap::new();
-        for field in [
-            PrincipalField::EnabledPermissions,
-            PrincipalField::DisabledPermissions,
-        ] {
-            let is_disabled = field == PrincipalField::DisabledPermissions;
-            if let Some(names) = principal_set.take_str_array(field) {
-                for name in names {
-                    let permission = Permission::from_name(&name).ok_or_else(|| {
-                        error(
-                            format!("Invalid {} value", field.as_str()),
-                            format!("Permission {name:?} is invalid").into(),
-                        )
-                    })?;
-
-                    if !permissions.contains_key(&permission) {
-                        if allowed_permissions
-                            .as_ref()
-                            .is_none_or(|p| p.get(permission as usize))
-                            || is_disabled
-                        {
-                            permissions.insert(permission, is_disabled);
-                        } else {
-                            return Err(error(
-                                "Invalid permission",
-                                format!("Your account cannot grant the {name:?} permission").into(),
-                            ));
-                        }
-                    }
-                }
-            }
-        }
-        if !permissions.is_empty() {
-            for (permission, v) in permissions {
-                create_principal.data.push(PrincipalData::Permission {
-                    permission_id: permission.id(),
-                    grant: !v,
-                });
-            }
-        }
-
-        // Make sure the e-mail is not taken and validate domain
-        if create_principal.typ != Type::OauthClient {
-            for (idx, email) in principal_set
-                .take_str_array(PrincipalField::Emails)
-                .unwrap_or_default()
-                .into_iter()
-                .enumerate()
-            {
-                let email = email.to_lowercase();
-                if self.rcpt(&email).await.caused_by(trc::location!())? != RcptType::Invalid {
-                    return Err(err_exists(PrincipalField::Emails, email.to_string()));
-                }
-                if let Some(domain) = email.try_domain_part()
-                    && valid_domains.insert(domain.into())
-                {
-                    self.get_principal_info(domain)
-                        .await
-                        .caused_by(trc::location!())?
-                        .filter(|v| v.typ == Type::Domain && v.has_tenant_access(tenant_id))
-                        .ok_or_else(|| not_found(domain.to_string()))?;
-                }
-                if idx == 0 {
-                    create_principal
-                        .data
-                        .push(PrincipalData::PrimaryEmail(email));
-                } else {
-                    create_principal.data.push(PrincipalData::EmailAlias(email));
-                }
-            }
-        }
-
-        // Write principal
-        let principal_id = self
-            .assign_document_ids(u32::MAX, Collection::Principal, 1)
-            .await
-            .caused_by(trc::location!())?;
-        if principal_id == FALLBACK_ADMIN_ID {
-            return Err(trc::StoreEvent::UnexpectedError
-                .into_err()
-                .details("ID assignment failed")
-                .caused_by(trc::location!()));
-        }
-        create_principal.id = principal_id;
-        let mut batch = BatchBuilder::new();
-        let pinfo_name = PrincipalInfo::new(principal_id, create_principal.typ, tenant_id);
-        let pinfo_email = PrincipalInfo::new(principal_id, create_principal.typ, None);
-
-        // Validate object size
-        if create_principal.object_size() > 100_000 {
-            return Err(error(
-                "Invalid parameter",
-                "Principal object size exceeds 100kb safety limit.".into(),
-            ));
-        }
-
-        // Serialize
-        create_principal.sort();
-        let archiver = Archiver::new(create_principal);
-        let principal_bytes = archiver.serialize().caused_by(trc::location!())?;
-        let create_principal = archiver.into_inner();
-
-        batch
-            .with_account_id(u32::MAX)
-            .with_collection(Collection::Principal)
-            .with_document(principal_id)
-            .assert_value(
-                ValueClass::Directory(DirectoryClass::NameToId(
-                    create_principal.name().as_bytes().to_vec(),
-                )),
-                (),
-            );
-        build_search_index(&mut batch, principal_id, None, Some(&create_principal));
-        batch
-            .set(
-                ValueClass::Directory(DirectoryClass::Principal(principal_id)),
-                principal_bytes,
-            )
-            .set(
-                ValueClass::Directory(DirectoryClass::NameToId(
-                    create_principal.name.as_bytes().to_vec(),
-                )),
-                pinfo_name.serialize(),
-            );
-
-        // Write email to id mapping
-        for email in create_principal.email_addresses() {
-            batch.set(
-                ValueClass::Directory(DirectoryClass::EmailToId(email.as_bytes().to_vec())),
-                pinfo_email.serialize(),
-            );
-        }
-
-        // Write membership
-        for member_of in member_of {
-            batch.set(
-                ValueClass::Directory(DirectoryClass::MemberOf {
-                    principal_id,
-                    member_of: member_of.id,
-                }),
-                vec![member_of.typ as u8],
-            );
-            batch.set(
-                ValueClass::Directory(DirectoryClass::Members {
-                    principal_id: member_of.id,
-                    has_member: principal_id,
-                }),
-                vec![],
-            );
-        }
-        for member in members {
-            batch.set(
-                ValueClass::Directory(DirectoryClass::MemberOf {
-                    principal_id: member.id,
-                    member_of: principal_id,
-                }),
-                vec![create_principal.typ as u8],
-            );
-            batch.set(
-                ValueClass::Directory(DirectoryClass::Members {
-                    principal_id,
-                    has_member: member.id,
-                }),
-                vec![],
-            );
-        }
-
-        self.write(batch.build_all())
-            .await
-            .map(|_| CreatedPrincipal {
-                id: principal_id,
-                changed_principals,
-            })
-    }
-
-    async fn delete_principal(&self, by: QueryBy<'_>) -> trc::Result<ChangedPrincipals> {
-        // Obtain principal
-        let principal_id = match by {
-            QueryBy::Name(name) => self
-                .get_principal_id(name)
-                .await
-                .caused_by(trc::location!())?
-                .ok_or_else(|| not_found(name.to_string()))?,
-            QueryBy::Id(principal_id) => principal_id,
-            QueryBy::Credentials(_) => unreachable!(),
-        };
-
-        let principal_ = self
-            .get_value::<Archive<AlignedBytes>>(ValueKey::from(ValueClass::Directory(
-                DirectoryClass::Principal(principal_id),
-            )))
-            .await
-            .caused_by(trc::location!())?
-            .ok_or_else(|| not_found(principal_id.to_string()))?;
-        let principal = principal_
-            .unarchive::<Principal>()
-            .caused_by(trc::location!())?;
-        let typ = Type::from(&principal.typ);
-
-        let mut batch = BatchBuilder::new();
-        batch
-            .with_account_id(u32::MAX)
-            .with_collection(Collection::Principal);
-
-        let tenant = principal.data.iter().find_map(|data| {
-            if let ArchivedPrincipalData::Tenant(tenant_id) = data {
-                Some(tenant_id.to_native())
-            } else {
-                None
-            }
-        });
-
-        // SPDX-SnippetBegin
-        // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
-        // SPDX-License-Identifier: LicenseRef-SEL
-
-        // Make sure tenant has no data
-        #[cfg(feature = "enterprise")]
-        match typ {
-            Type::Individual | Type::Group => {
-                // Update tenant quota
-                if let Some(tenant_id) = tenant {
-                    let quota = self
-                        .get_counter(DirectoryClass::UsedQuota(principal_id))
-                        .await
-                        .caused_by(trc::location!())?;
-                    if quota > 0 {
-                        batch.add(DirectoryClass::UsedQuota(tenant_id), -quota);
-                    }
-                }
-            }
-            Type::Tenant => {
-                let tenant_members = self
-                    .list_principals(
-                        None,
-                        principal_id.into(),
-                        &[
-                            Type::Individual,
-                            Type::Group,
-                            Type::Role,
-                            Type::List,
-                            Type::Resource,
-                            Type::Other,
-                            Type::Location,
-                            Type::Domain,
-                            Type::ApiKey,
-                        ],
-                        false,
-                        0,
-                        0,
-                    )
-                    .await
-                    .caused_by(trc::location!())?;
-
-                if tenant_members.total > 0 {
-                    let mut message =
-                        String::from("Tenant must have no members to be deleted: Found: ");
-
-                    for (num, principal) in tenant_members.items.iter().enumerate() {
-                        if num > 0 {
-                            message.push_str(", ");
-                        }
-                        message.push_str(principal.name());
-                    }
+                    for (num, principal) in tenant_members.items.iter().enumerate() {
+                        if num > 0 {
+                            message.push_str(", ");
+                        }
+                        message.push_str(principal.name());
+                    }
 
                     if tenant_members.total > 5 {
                         message.push_str(" and ");
@@ -860,17 +824,44 @@ impl ManageDirectory for Store {
         }
 
         // Delete principal
+        let name_scope = principal.data.iter().find_map(|data| {
+            if let ArchivedPrincipalData::ScopedName(tenant_id) = data {
+                Some(tenant_id.to_native())
+            } else {
+                None
+            }
+        });
         batch
             .with_document(principal_id)
-            .clear(DirectoryClass::NameToId(principal.name.as_bytes().to_vec()))
+            .clear(DirectoryClass::NameToId(name_key(
+                principal.name.as_str(),
+                name_scope,
+            )))
             .clear(DirectoryClass::Principal(principal_id))
-            .clear(DirectoryClass::UsedQuota(principal_id));
+            .clear(DirectoryClass::UsedQuota(principal_id))
+            .clear(DirectoryClass::MessageCount(principal_id));
+        if let Some(tenant_id) = tenant {
+            batch.add(principal_count(tenant_id, typ), -1);
+        }
+        if typ == Type::Tenant {
+            for typ in TENANT_PRINCIPAL_TYPES {
+                batch.clear(principal_count(principal_id, typ));
+            }
+        }
 
-        for email in principal.data.iter() {
-            if let ArchivedPrincipalData::PrimaryEmail(email)
-            | ArchivedPrincipalData::EmailAlias(email) = email
-            {
-                batch.clear(DirectoryClass::EmailToId(email.as_bytes().to_vec()));
+        for data in principal.data.iter() {
+            match data {
+                ArchivedPrincipalData::PrimaryEmail(email)
+                | ArchivedPrincipalData::EmailAlias(email) => {
+                    batch.clear(DirectoryClass::EmailToId(email.as_bytes().to_vec()));
+                }
+                ArchivedPrincipalData::ExternalId(external_id) => {
+                    batch.clear(DirectoryClass::ExternalIdToId(external_id_key(
+                        external_id.as_str(),
+                        tenant,
+                    )));
+                }
+                _ => (),
             }
         }
 
@@ -934,7 +925,39 @@ impl ManageDirectory for Store {
             .await
             .caused_by(trc::location!())?;
 
+        // Delete app password usage records, activity, lockout, MFA, notice, schedule and trial state
+        if matches!(typ, Type::Individual) {
+            let kv = InMemoryStore::Store(self.clone());
+            for prefix in [
+                last_used_prefix(principal_id),
+                activity_prefix(principal_id),
+                notified_key(principal_id),
+                counter_prefix(principal_id),
+            ]
+            .into_iter()
+            .chain(lockout_prefixes(principal_id))
+            .chain(mfa_prefixes(principal_id))
+            .chain(security_prefixes(principal_id))
+            {
+                kv.key_delete_prefix(&prefix)
+                    .await
+                    .caused_by(trc::location!())?;
+            }
+        } else if matches!(typ, Type::Tenant) {
+            InMemoryStore::Store(self.clone())
+                .key_delete_prefix(&trial_notified_key(principal_id))
+                .await
+                .caused_by(trc::location!())?;
+        }
+
         changed_principals.add_deletion(principal_id, typ);
+        changed_principals.add_principal_change(
+            PrincipalChangeAction::Deleted,
+            principal_id,
+            typ,
+            tenant,
+            vec![],
+        );
 
         Ok(changed_principals)
     }
@@ -944,11 +967,13 @@ impl ManageDirectory for Store {
         params: UpdatePrincipal<'_>,
     ) -> trc::Result<ChangedPrincipals> {
         let principal_id = match params.query {
-            QueryBy::Name(name) => self
-                .get_principal_id(name)
-                .await
-                .caused_by(trc::location!())?
-                .ok_or_else(|| not_found(name.to_string()))?,
+            QueryBy::Name(name) => {
+                self.resolve_principal_info(name, params.tenant_id)
+                    .await
+                    .caused_by(trc::location!())?
+                    .ok_or_else(|| not_found(name.to_string()))?
+                    .id
+            }
             QueryBy::Id(principal_id) => principal_id,
             QueryBy::Credentials(_) => unreachable!(),
         };
@@ -972,6 +997,12 @@ impl ManageDirectory for Store {
         principal.id = principal_id;
         let principal_type = principal.typ;
         let validate_emails = principal_type != Type::OauthClient;
+        let reserved_names = params
+            .name_policy
+            .filter(|_| enforce_reserved_names(principal_type, params.allowed_permissions));
+        let fold_local_part = params
+            .name_policy
+            .is_none_or(|policy| policy.fold_local_part());
 
         // Keep track of changed principals
         let mut changed_principals = ChangedPrincipals::default();
@@ -991,16 +1022,8 @@ impl ManageDirectory for Store {
         let mut pinfo_name =
             PrincipalInfo::new(principal_id, principal_type, principal.tenant()).serialize();
         let pinfo_email = PrincipalInfo::new(principal_id, principal_type, None).serialize();
-        let update_principal = !changes.is_empty()
-            && !changes.iter().all(|c| {
-                matches!(
-                    c.field,
-                    PrincipalField::MemberOf
-                        | PrincipalField::Members
-                        | PrincipalField::Lists
-                        | PrincipalField::Roles
-                )
-            });
+        let update_principal = !changes.is_empty();
+        let prev_domain = principal_domain(&principal);
 
         let mut used_quota: Option<i64> = None;
 
@@ -1047,6 +1070,25 @@ impl ManageDirectory for Store {
             Type::Role => &[Type::Role][..],
         };
         let mut valid_domains = AHashSet::new();
+        let mut changed_fields = changes.iter().map(|c| c.field).collect::<Vec<_>>();
+        changed_fields.sort_unstable();
+        changed_fields.dedup();
+        let prev_password = principal.data.iter().find_map(|v| match v {
+            PrincipalData::Password(secret) => Some(secret.clone()),
+            _ => None,
+        });
+
+        // Scheduled events that are due apply before the requested changes
+        if principal_type == Type::Individual
+            && update_principal
+            && apply_schedule(&mut principal, now())
+        {
+            changed_principals.add_change(
+                principal_id,
+                principal_type,
+                PrincipalField::DisabledPermissions,
+            );
+        }
 
         // Process changes
         for change in changes {
@@ -1055,50 +1097,68 @@ impl ManageDirectory for Store {
                     // Make sure new name is not taken
                     let new_name = new_name.to_lowercase();
                     if principal.name() != new_name {
-                        if tenant_id.is_some()
-                            && !matches!(principal_type, Type::Tenant | Type::Domain)
-                        {
-                            if let Some(domain) = new_name.try_domain_part()
-                                && self
-                                    .get_principal_info(domain)
-                                    .await
-                                    .caused_by(trc::location!())?
-                                    .filter(|v| {
-                                        v.typ == Type::Domain && v.has_tenant_access(tenant_id)
-                                    })
-                                    .is_some()
-                            {
-                                valid_domains.insert(domain.to_string());
-                            }
+                        if reserved_names.is_some_and(|policy| policy.is_reserved(&new_name)) {
+                            return Err(err_reserved(PrincipalField::Name, new_name));
+                        }
 
-                            if valid_domains.is_empty() {
+                        if let Some(scope_id) = principal.name_scope() {
+                            // Tenant-scoped names are unique within their tenant
+                            if new_name.contains('@') {
                                 return Err(error(
                                     "Invalid principal name",
-                                    "Principal name must include a valid domain assigned to the tenant".into(),
+                                    "Tenant-scoped principal names cannot include a domain".into(),
                                 ));
                             }
-                        }
 
-                        if self
-                            .get_principal_id(&new_name)
-                            .await
-                            .caused_by(trc::location!())?
-                            .is_some()
-                        {
-                            return Err(err_exists(PrincipalField::Name, new_name));
-                        }
+                            if tenant_name_taken(self, &[], &new_name, scope_id)
+                                .await
+                                .caused_by(trc::location!())?
+                            {
+                                return Err(err_exists(PrincipalField::Name, new_name));
+                            }
+                        } else {
+                            if tenant_id.is_some()
+                                && !matches!(principal_type, Type::Tenant | Type::Domain)
+                            {
+                                if let Some(domain) = new_name.try_domain_part()
+                                    && self
+                                        .get_principal_info(domain)
+                                        .await
+                                        .caused_by(trc::location!())?
+                                        .filter(|v| {
+                                            v.typ == Type::Domain && v.has_tenant_access(tenant_id)
+                                        })
+                                        .is_some()
+                                {
+                                    valid_domains.insert(domain.to_string());
+                                }
+
+                                if valid_domains.is_empty() {
+                                    return Err(error(
+                                        "Invalid principal name",
+                                        "Principal name must include a valid domain assigned to the tenant".into(),
+                                    ));
+                                }
+                            }
+
+                            if self
+                                .resolve_principal_info(&new_name, None)
+                                .await
+                                .caused_by(trc::location!())?
+                                .is_some()
+                            {
+                                return Err(err_exists(PrincipalField::Name, new_name));
+                            }
+                        }
 
                         batch.clear(ValueClass::Directory(DirectoryClass::NameToId(
-                            principal.name().as_bytes().to_vec(),
+                            principal.name_key(),
                         )));
-
+                        principal.name = new_name;
                         batch.set(
-                            ValueClass::Directory(DirectoryClass::NameToId(
-                                new_name.as_bytes().to_vec(),
-                            )),
+                            ValueClass::Directory(DirectoryClass::NameToId(principal.name_key())),
                             pinfo_name.clone(),
                         );
-                        principal.name = new_name;
 
                         // Name changed, update changed principals
                         changed_principals.add_change(principal_id, principal_type, change.field);
@@ -1114,6 +1174,7 @@ impl ManageDirectory for Store {
                     PrincipalField::Tenant,
                     PrincipalValue::String(tenant_name),
                 ) if tenant_id.is_none() => {
+                    let prev_tenant = principal.tenant();
                     if !tenant_name.is_empty() {
                         let tenant_info = self
                             .get_principal_info(&tenant_name)
@@ -1132,6 +1193,29 @@ impl ManageDirectory for Store {
                             continue;
                         }
 
+                        // Tenant-scoped names move to the scope of the new tenant
+                        if principal.name_scope().is_some() {
+                            if tenant_name_taken(self, &[], principal.name(), tenant_info.id)
+                                .await
+                                .caused_by(trc::location!())?
+                            {
+                                return Err(err_exists(
+                                    PrincipalField::Name,
+                                    principal.name().to_string(),
+                                ));
+                            }
+
+                            batch.clear(ValueClass::Directory(DirectoryClass::NameToId(
+                                principal.name_key(),
+                            )));
+                            principal
+                                .data
+                                .retain(|v| !matches!(v, PrincipalData::ScopedName(_)));
+                            principal
+                                .data
+                                .push(PrincipalData::ScopedName(tenant_info.id));
+                        }
+
                         // Update quota
                         if let Some(used_quota) = used_quota {
                             if let Some(old_tenant_id) = principal.tenant() {
@@ -1140,6 +1224,12 @@ impl ManageDirectory for Store {
                             batch.add(DirectoryClass::UsedQuota(tenant_info.id), used_quota);
                         }
 
+                        // Move the principal to the counters of the new tenant
+                        if let Some(old_tenant_id) = principal.tenant() {
+                            batch.add(principal_count(old_tenant_id, principal_type), -1);
+                        }
+                        batch.add(principal_count(tenant_info.id, principal_type), 1);
+
                         // Tenant changed, update changed principals
                         changed_principals.add_change(principal_id, principal_type, change.field);
 
@@ -1151,10 +1241,19 @@ impl ManageDirectory for Store {
                             PrincipalInfo::new(principal_id, principal_type, tenant_info.id.into())
                                 .serialize();
                     } else if let Some(tenant_id) = principal.tenant() {
+                        if principal.name_scope().is_some() {
+                            return Err(error(
+                                "Invalid tenant",
+                                "Tenant-scoped principals cannot be removed from their tenant"
+                                    .into(),
+                            ));
+                        }
+
                         // Update quota
                         if let Some(used_quota) = used_quota {
                             batch.add(DirectoryClass::UsedQuota(tenant_id), -used_quota);
                         }
+                        batch.add(principal_count(tenant_id, principal_type), -1);
 
                         // Tenant changed, update changed principals
                         changed_principals.add_change(principal_id, principal_type, change.field);
@@ -1169,11 +1268,31 @@ impl ManageDirectory for Store {
                     }
 
                     batch.set(
-                        ValueClass::Directory(DirectoryClass::NameToId(
-                            principal.name().as_bytes().to_vec(),
-                        )),
+                        ValueClass::Directory(DirectoryClass::NameToId(principal.name_key())),
                         pinfo_name.clone(),
                     );
+
+                    // External IDs move to the scope of the new tenant
+                    if let Some(external_id) = principal.external_id() {
+                        assert_external_id_available(
+                            self,
+                            &[],
+                            external_id,
+                            principal.tenant(),
+                            Some(principal_id),
+                        )
+                        .await?;
+                        batch
+                            .clear(ValueClass::Directory(DirectoryClass::ExternalIdToId(
+                                external_id_key(external_id, prev_tenant),
+                            )))
+                            .set(
+                                ValueClass::Directory(DirectoryClass::ExternalIdToId(
+                                    external_id_key(external_id, principal.tenant()),
+                                )),
+                                pinfo_name.clone(),
+                            );
+                    }
                 }
 
                 // SPDX-SnippetEnd
@@ -1189,6 +1308,7 @@ impl ManageDirectory for Store {
                             v,
                             PrincipalData::Password(_)
                                 | PrincipalData::AppPassword(_)
+                                | PrincipalData::AppPasswordInfo { .. }
                                 | PrincipalData::OtpAuth(_)
                         )
                     });
@@ -1242,10 +1362,16 @@ impl ManageDirectory for Store {
                     changed_principals.add_change(principal_id, principal_type, change.field);
 
                     if secret.is_app_secret() || secret.is_otp_secret() {
+                        let label = secret
+                            .strip_prefix("$app$")
+                            .map(|label| label.split_once('$').map_or(label, |(label, _)| label));
                         principal.data.retain(|v| match v {
                             PrincipalData::AppPassword(v) | PrincipalData::OtpAuth(v) => {
                                 *v != secret && !v.starts_with(secret.as_str())
                             }
+                            PrincipalData::AppPasswordInfo {
+                                label: info_label, ..
+                            } => label.is_none_or(|label| !info_label.starts_with(label)),
                             _ => true,
                         });
                     } else if !secret.is_empty() {
@@ -1255,7 +1381,12 @@ impl ManageDirectory for Store {
                         });
                     } else {
                         principal.data.retain(|v| {
-                            !matches!(v, PrincipalData::AppPassword(_) | PrincipalData::OtpAuth(_))
+                            !matches!(
+                                v,
+                                PrincipalData::AppPassword(_)
+                                    | PrincipalData::AppPasswordInfo { .. }
+                                    | PrincipalData::OtpAuth(_)
+                            )
                         });
                     }
                 }
@@ -1311,6 +1442,18 @@ impl ManageDirectory for Store {
                         principal.data.push(PrincipalData::BrandLogoUrl(value));
                     }
                 }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::BrandLogoDarkUrl,
+                    PrincipalValue::String(value),
+                ) => {
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::BrandLogoDarkUrl(_)));
+                    if !value.is_empty() {
+                        principal.data.push(PrincipalData::BrandLogoDarkUrl(value));
+                    }
+                }
                 (
                     PrincipalAction::Set,
                     PrincipalField::BrandTheme,
@@ -1323,6 +1466,160 @@ impl ManageDirectory for Store {
                         principal.data.push(PrincipalData::BrandTheme(value));
                     }
                 }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::ExternalId,
+                    PrincipalValue::String(value),
+                ) => {
+                    if principal.external_id() == Some(value.as_str()) {
+                        continue;
+                    }
+                    let tenant = principal.tenant();
+                    if !value.is_empty() {
+                        assert_external_id_available(self, &[], &value, tenant, Some(principal_id))
+                            .await?;
+                    }
+                    if let Some(external_id) = principal.external_id() {
+                        batch.clear(ValueClass::Directory(DirectoryClass::ExternalIdToId(
+                            external_id_key(external_id, tenant),
+                        )));
+                    }
+
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::ExternalId(_)));
+                    if !value.is_empty() {
+                        batch.set(
+                            ValueClass::Directory(DirectoryClass::ExternalIdToId(external_id_key(
+                                &value, tenant,
+                            ))),
+                            pinfo_name.clone(),
+                        );
+                        principal.data.push(PrincipalData::ExternalId(value));
+                    }
+                }
+                (PrincipalAction::Set, PrincipalField::Tags, PrincipalValue::StringList(tags)) => {
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::Tag(_)));
+                    add_tags(&mut principal, tags)?;
+                }
+                (PrincipalAction::Set, PrincipalField::Tags, PrincipalValue::String(tag)) => {
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::Tag(_)));
+                    if !tag.is_empty() {
+                        add_tags(&mut principal, [tag])?;
+                    }
+                }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::Tags,
+                    PrincipalValue::StringList(tags),
+                ) => {
+                    add_tags(&mut principal, tags)?;
+                }
+                (PrincipalAction::AddItem, PrincipalField::Tags, PrincipalValue::String(tag)) => {
+                    add_tags(&mut principal, [tag])?;
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::Tags,
+                    PrincipalValue::String(tag),
+                ) => {
+                    let tag = tag.trim().to_lowercase();
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::Tag(t) if *t == tag));
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::AllowedProtocols,
+                    PrincipalValue::StringList(protocols),
+                ) => {
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::AllowedProtocol(_)));
+                    add_allowed_protocols(&mut principal, protocols);
+                    changed_principals.add_change(principal_id, principal_type, change.field);
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::AllowedProtocols,
+                    PrincipalValue::String(protocol),
+                ) => {
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::AllowedProtocol(_)));
+                    add_allowed_protocols(&mut principal, [protocol]);
+                    changed_principals.add_change(principal_id, principal_type, change.field);
+                }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::AllowedProtocols,
+                    PrincipalValue::StringList(protocols),
+                ) => {
+                    add_allowed_protocols(&mut principal, protocols);
+                    changed_principals.add_change(principal_id, principal_type, change.field);
+                }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::AllowedProtocols,
+                    PrincipalValue::String(protocol),
+                ) => {
+                    add_allowed_protocols(&mut principal, [protocol]);
+                    changed_principals.add_change(principal_id, principal_type, change.field);
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::AllowedProtocols,
+                    PrincipalValue::String(protocol),
+                ) => {
+                    let protocol = protocol.trim().to_lowercase();
+                    principal.data.retain(
+                        |v| !matches!(v, PrincipalData::AllowedProtocol(p) if *p == protocol),
+                    );
+                    changed_principals.add_change(principal_id, principal_type, change.field);
+                }
+                (
+                    PrincipalAction::Set | PrincipalAction::AddItem,
+                    PrincipalField::Metadata,
+                    PrincipalValue::StringList(entries),
+                ) => {
+                    merge_metadata(&mut principal, entries)?;
+                }
+                (
+                    PrincipalAction::Set | PrincipalAction::AddItem,
+                    PrincipalField::Metadata,
+                    PrincipalValue::String(entry),
+                ) => {
+                    merge_metadata(&mut principal, vec![entry])?;
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::Metadata,
+                    PrincipalValue::String(key),
+                ) => {
+                    principal.data.retain(
+                        |v| !matches!(v, PrincipalData::Metadata { key: k, .. } if *k == key),
+                    );
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::LegalHold,
+                    PrincipalValue::String(set_by),
+                ) if principal_type == Type::Individual => {
+                    if set_by.is_empty() {
+                        principal
+                            .data
+                            .retain(|v| !matches!(v, PrincipalData::LegalHold { .. }));
+                    } else if principal.legal_hold().is_none() {
+                        principal.data.push(PrincipalData::LegalHold {
+                            set_by,
+                            set_at: now(),
+                        });
+                    }
+                }
                 (PrincipalAction::Set, PrincipalField::Quota, PrincipalValue::Integer(quota))
                     if matches!(
                         principal_type,
@@ -1348,6 +1645,107 @@ impl ManageDirectory for Store {
                         .data
                         .retain(|v| !matches!(v, PrincipalData::DiskQuota(_)));
                 }
+                (
+                    PrincipalAction::Set,
+                    field @ (PrincipalField::PasswordHistoryLength
+                    | PrincipalField::MinPasswordAgeDays),
+                    PrincipalValue::Integer(value),
+                ) if principal_type == Type::Tenant => {
+                    set_password_policy(&mut principal, field, value)?;
+                }
+                (
+                    PrincipalAction::Set,
+                    field @ (PrincipalField::LockoutThreshold
+                    | PrincipalField::LockoutWindow
+                    | PrincipalField::LockoutDuration
+                    | PrincipalField::LockoutPerProtocol
+                    | PrincipalField::LockoutNotify),
+                    PrincipalValue::Integer(value),
+                ) if principal_type == Type::Tenant => {
+                    set_lockout_policy(&mut principal, field, value)?;
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::AvatarSelfService,
+                    PrincipalValue::Integer(value),
+                ) if principal_type == Type::Tenant => {
+                    set_avatar_self_service(&mut principal, value);
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::SecurityNoticeOptOut,
+                    PrincipalValue::Integer(value),
+                ) if principal_type == Type::Individual => {
+                    set_security_notice_opt_out(&mut principal, value);
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::TrialEndsAt,
+                    PrincipalValue::Integer(value),
+                ) if principal_type == Type::Tenant => {
+                    set_trial(&mut principal, value);
+                }
+                (
+                    PrincipalAction::Set,
+                    field @ (PrincipalField::BillingEmail
+                    | PrincipalField::TechnicalContact
+                    | PrincipalField::Phone
+                    | PrincipalField::PostalAddress
+                    | PrincipalField::TaxId),
+                    PrincipalValue::String(value),
+                ) if principal_type == Type::Tenant => {
+                    let value = sanitize_contact(field, &value).ok_or_else(|| {
+                        error(
+                            "Invalid contact",
+                            format!("Invalid value {:?} for {}", value, field.as_str()).into(),
+                        )
+                    })?;
+                    principal
+                        .data
+                        .retain(|v| v.as_contact().is_none_or(|(item, _)| item != field));
+                    if let Some(item) = PrincipalData::contact(field, value).filter(|item| {
+                        item.as_contact()
+                            .is_some_and(|(_, value)| !value.is_empty())
+                    }) {
+                        principal.data.push(item);
+                    }
+                }
+                (PrincipalAction::Set, PrincipalField::Plan, PrincipalValue::String(value))
+                    if principal_type == Type::Tenant =>
+                {
+                    // Limits derived from the plan apply to all the tenant's members
+                    changed_principals.add_change(principal_id, principal_type, change.field);
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::Plan(_)));
+                    if !value.is_empty() {
+                        principal.data.push(PrincipalData::Plan(value));
+                    }
+                }
+                (
+                    PrincipalAction::Set,
+                    field @ (PrincipalField::SenderAddress | PrincipalField::SendRate),
+                    PrincipalValue::String(value),
+                ) if principal_type == Type::Individual => {
+                    set_sender(&mut principal, field, &value)?;
+                    validate_sender_domain(self, &[], &principal, tenant_id).await?;
+                    changed_principals.add_change(principal_id, principal_type, field);
+                }
+                (
+                    PrincipalAction::Set,
+                    field @ (PrincipalField::DisableAt | PrincipalField::EnableAt),
+                    PrincipalValue::Integer(value),
+                ) if principal_type == Type::Individual => {
+                    set_schedule(&mut principal, field, value);
+                    apply_schedule(&mut principal, now());
+
+                    // Login state changed, update changed principals
+                    changed_principals.add_change(
+                        principal_id,
+                        principal_type,
+                        PrincipalField::DisabledPermissions,
+                    );
+                }
                 (
                     PrincipalAction::Set,
                     PrincipalField::Quota,
@@ -1391,11 +1789,21 @@ impl ManageDirectory for Store {
                     // Validate unique emails
                     let emails = emails
                         .into_iter()
-                        .map(|v| v.to_lowercase())
+                        .map(|v| normalize_email(&v, fold_local_part))
                         .collect::<Vec<_>>();
                     for email in &emails {
                         if !principal.email_addresses().any(|v| v == email) {
-                            if validate_emails {
+                            // Addresses stored before normalization are rewritten
+                            // without being validated again
+                            let is_stored = principal
+                                .email_addresses()
+                                .any(|v| normalize_email(v, fold_local_part) == *email);
+                            if !is_stored
+                                && reserved_names.is_some_and(|policy| policy.is_reserved(email))
+                            {
+                                return Err(err_reserved(PrincipalField::Emails, email.as_str()));
+                            }
+                            if !is_stored && validate_emails {
                                 self.validate_email(email, tenant_id, params.create_domains)
                                     .await?;
                             }
@@ -1438,12 +1846,16 @@ impl ManageDirectory for Store {
                     PrincipalField::Emails,
                     PrincipalValue::String(email),
                 ) => {
-                    let email = email.to_lowercase();
+                    let email = normalize_email(&email, fold_local_part);
                     let mut emails_iter = principal.email_addresses().peekable();
                     let has_emails = emails_iter.peek().is_some();
-                    let email_exists = emails_iter.any(|v| v == email);
+                    let email_exists =
+                        emails_iter.any(|v| normalize_email(v, fold_local_part) == email);
                     drop(emails_iter);
                     if !email_exists {
+                        if reserved_names.is_some_and(|policy| policy.is_reserved(&email)) {
+                            return Err(err_reserved(PrincipalField::Emails, email));
+                        }
                         if validate_emails {
                             self.validate_email(&email, tenant_id, params.create_domains)
                                 .await?;
@@ -1469,8 +1881,12 @@ impl ManageDirectory for Store {
                     PrincipalField::Emails,
                     PrincipalValue::String(email),
                 ) => {
-                    let email = email.to_lowercase();
-                    if principal.email_addresses().any(|v| v == email) {
+                    let email = normalize_email(&email, fold_local_part);
+                    if let Some(email) = principal
+                        .email_addresses()
+                        .find(|v| normalize_email(v, fold_local_part) == email)
+                        .map(|v| v.to_string())
+                    {
                         let mut deleted_primary = false;
                         principal.data.retain(|v| match v {
                             PrincipalData::EmailAlias(v) => v != &email,
@@ -1511,7 +1927,7 @@ impl ManageDirectory for Store {
                     let mut new_member_of = Vec::new();
                     for member in members {
                         let member_info = match (
-                            self.get_principal_info(&member)
+                            self.resolve_principal_info(&member, tenant_id)
                                 .await
                                 .caused_by(trc::location!())?
                                 .filter(|p| p.has_tenant_access(tenant_id)),
@@ -1589,7 +2005,7 @@ impl ManageDirectory for Store {
                     PrincipalValue::String(member),
                 ) => {
                     let member_info = match (
-                        self.get_principal_info(&member)
+                        self.resolve_principal_info(&member, tenant_id)
                             .await
                             .caused_by(trc::location!())?
                             .filter(|p| p.has_tenant_access(tenant_id)),
@@ -1641,7 +2057,7 @@ impl ManageDirectory for Store {
                     PrincipalValue::String(member),
                 ) => {
                     if let Some(member_info) =
-                        self.get_principal_info(&member)
+                        self.resolve_principal_info(&member, tenant_id)
                             .await
                             .caused_by(trc::location!())?
                             .or_else(|| {
@@ -1690,7 +2106,7 @@ impl ManageDirectory for Store {
 
                     for member in members_ {
                         let member_info = self
-                            .get_principal_info(&member)
+                            .resolve_principal_info(&member, tenant_id)
                             .await
                             .caused_by(trc::location!())?
                             .filter(|p| p.has_tenant_access(tenant_id))
@@ -1775,7 +2191,7 @@ impl ManageDirectory for Store {
                     PrincipalValue::String(member),
                 ) => {
                     let member_info = self
-                        .get_principal_info(&member)
+                        .resolve_principal_info(&member, tenant_id)
                         .await
                         .caused_by(trc::location!())?
                         .filter(|p| p.has_tenant_access(tenant_id))
@@ -1828,7 +2244,7 @@ impl ManageDirectory for Store {
                     PrincipalValue::String(member),
                 ) => {
                     if let Some(member_info) = self
-                        .get_principal_info(&member)
+                        .resolve_principal_info(&member, tenant_id)
                         .await
                         .caused_by(trc::location!())?
                     {
@@ -2075,6 +2491,24 @@ impl ManageDirectory for Store {
             }
         }
 
+        // Apply the password policy of the tenant
+        if principal_type == Type::Individual && changed_fields.contains(&PrincipalField::Secrets) {
+            let policy = if let Some(tenant_id) = principal.tenant() {
+                self.get_principal(tenant_id)
+                    .await
+                    .caused_by(trc::location!())?
+            } else {
+                None
+            };
+            rotate_password(
+                &mut principal,
+                prev_password,
+                policy.as_ref(),
+                params.self_service,
+            )
+            .await?;
+        }
+
         // Validate object size
         if principal.object_size() > 100_000 {
             return Err(error(
@@ -2083,7 +2517,35 @@ impl ManageDirectory for Store {
             ));
         }
 
+        // Usage follows the account to the rollup of its new primary domain
+        if matches!(principal_type, Type::Individual | Type::Group) {
+            let domain = principal_domain(&principal);
+            if domain != prev_domain {
+                let mut domain_ids = [None, None];
+                for (domain_id, domain) in domain_ids.iter_mut().zip([prev_domain, domain]) {
+                    if let Some(domain) = domain {
+                        *domain_id = self
+                            .get_principal_info(&domain)
+                            .await
+                            .caused_by(trc::location!())?
+                            .filter(|info| info.typ == Type::Domain)
+                            .map(|info| info.id);
+                    }
+                }
+                let [from, to] = domain_ids;
+                if from != to {
+                    move_domain_usage(self, &mut batch, principal_id, from, to).await?;
+                }
+            }
+        }
+
+        let principal_tenant = principal.tenant();
         if update_principal {
+            // Membership changes also count as a modification
+            principal
+                .data
+                .retain(|v| !matches!(v, PrincipalData::ModifiedAt(_)));
+            principal.data.push(PrincipalData::ModifiedAt(now()));
             principal.sort();
             build_search_index(
                 &mut batch,
@@ -2109,6 +2571,16 @@ impl ManageDirectory for Store {
             .await
             .caused_by(trc::location!())?;
 
+        if !changed_fields.is_empty() {
+            changed_principals.add_principal_change(
+                PrincipalChangeAction::Updated,
+                principal_id,
+                principal_type,
+                principal_tenant,
+                changed_fields,
+            );
+        }
+
         Ok(changed_principals)
     }
 
@@ -2198,9 +2670,10 @@ impl ManageDirectory for Store {
                     if offset == 0 {
                         if result.items.len() < max_items {
                             let mut principal = Principal::new(pt.id, pt.typ);
-                            principal.name =
-                                String::from_utf8_lossy(key.get(1..).unwrap_or_default())
-                                    .into_owned();
+                            principal.name = String::from_utf8_lossy(name_from_key(
+                                key.get(1..).unwrap_or_default(),
+                            ))
+                            .into_owned();
                             result.items.push(principal);
                         }
                     } else {
@@ -2250,8 +2723,8 @@ impl ManageDirectory for Store {
             IterateParams::new(from_key, to_key).ascending(),
             |key, value| {
                 let pt = PrincipalInfo::deserialize(value).caused_by(trc::location!())?;
-                let name =
-                    std::str::from_utf8(key.get(1..).unwrap_or_default()).unwrap_or_default();
+                let name = std::str::from_utf8(name_from_key(key.get(1..).unwrap_or_default()))
+                    .unwrap_or_default();
 
                 if typ.is_none_or(|t| pt.typ == t)
                     && pt.has_tenant_access(tenant_id)
@@ -2268,6 +2741,88 @@ impl ManageDirectory for Store {
         .map(|_| count)
     }
 
+    async fn count_tenant_principals(&self, tenant_id: u32, typ: Type) -> trc::Result<u64> {
+        self.get_counter(principal_count(tenant_id, typ))
+            .await
+            .caused_by(trc::location!())
+            .map(|count| count.max(0) as u64)
+    }
+
+    async fn repair_principal_counts(&self) -> trc::Result<usize> {
+        // Read the counters before recounting, so that counters updated while
+        // the principals are being scanned are left for the next run
+        let tenant_ids = self
+            .principal_ids(Type::Tenant.into(), None)
+            .await
+            .caused_by(trc::location!())?;
+        let mut counters = AHashMap::new();
+        for tenant_id in &tenant_ids {
+            for typ in TENANT_PRINCIPAL_TYPES {
+                counters.insert(
+                    (tenant_id, typ),
+                    self.get_counter(principal_count(tenant_id, typ))
+                        .await
+                        .caused_by(trc::location!())?,
+                );
+            }
+        }
+
+        let mut counts = AHashMap::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![0u8]))),
+                ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![
+                    u8::MAX;
+                    10
+                ]))),
+            ),
+            |_, value| {
+                let pt = PrincipalInfo::deserialize(value).caused_by(trc::location!())?;
+                if let Some(tenant_id) = pt.tenant {
+                    *counts.entry((tenant_id, pt.typ)).or_insert(0i64) += 1;
+                }
+
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        let mut repaired = 0;
+        for ((tenant_id, typ), counter) in counters {
+            let count = counts.get(&(tenant_id, typ)).copied().unwrap_or_default();
+            if count == counter
+                || self
+                    .get_counter(principal_count(tenant_id, typ))
+                    .await
+                    .caused_by(trc::location!())?
+                    != counter
+            {
+                continue;
+            }
+
+            trc::event!(
+                Directory(trc::DirectoryEvent::CounterDrift),
+                TenantId = tenant_id,
+                Type = typ.as_str(),
+                Total = counter,
+                Value = count,
+            );
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .add(principal_count(tenant_id, typ), count - counter);
+            self.write(batch.build_all())
+                .await
+                .caused_by(trc::location!())?;
+            repaired += 1;
+        }
+
+        Ok(repaired)
+    }
+
     async fn principal_ids(
         &self,
         typ: Option<Type>,
@@ -2296,18 +2851,50 @@ impl ManageDirectory for Store {
         .map(|_| results)
     }
 
-    async fn get_member_of(&self, principal_id: u32) -> trc::Result<Vec<MemberOf>> {
-        let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::MemberOf {
-            principal_id,
-            member_of: 0,
-        }));
-        let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::MemberOf {
-            principal_id,
-            member_of: u32::MAX,
-        }));
-        let mut results = Vec::new();
-        self.iterate(IterateParams::new(from_key, to_key), |key, value| {
-            results.push(MemberOf {
+    /// Groups the principals of several tenants by tenant, with a single
+    /// scan of the directory.
+    async fn tenant_principals(
+        &self,
+        types: &[Type],
+        tenant_ids: &AHashSet<u32>,
+    ) -> trc::Result<AHashMap<u32, Vec<PrincipalInfo>>> {
+        let mut results: AHashMap<u32, Vec<PrincipalInfo>> = AHashMap::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![0u8]))),
+                ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![
+                    u8::MAX;
+                    10
+                ]))),
+            ),
+            |_, value| {
+                let pt = PrincipalInfo::deserialize(value).caused_by(trc::location!())?;
+                if types.contains(&pt.typ)
+                    && let Some(tenant_id) = pt.tenant.filter(|id| tenant_ids.contains(id))
+                {
+                    results.entry(tenant_id).or_default().push(pt);
+                }
+
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())
+        .map(|_| results)
+    }
+
+    async fn get_member_of(&self, principal_id: u32) -> trc::Result<Vec<MemberOf>> {
+        let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::MemberOf {
+            principal_id,
+            member_of: 0,
+        }));
+        let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::MemberOf {
+            principal_id,
+            member_of: u32::MAX,
+        }));
+        let mut results = Vec::new();
+        self.iterate(IterateParams::new(from_key, to_key), |key, value| {
+            results.push(MemberOf {
                 principal_id: key.deserialize_be_u32(key.len() - U32_LEN)?,
                 typ: value
                     .first()
@@ -2350,6 +2937,14 @@ impl ManageDirectory for Store {
     ) -> trc::Result<PrincipalSet> {
         let mut result = PrincipalSet::new(principal.id, principal.typ);
 
+        // Effective login state, including scheduled events not yet applied
+        if principal.typ == Type::Individual
+            && (fields.is_empty() || fields.contains(&PrincipalField::Disabled))
+            && principal.is_disabled_at(now())
+        {
+            result.set(PrincipalField::Disabled, 1u64);
+        }
+
         let has_enabled = fields.is_empty() || fields.contains(&PrincipalField::EnabledPermissions);
         let has_disabled =
             fields.is_empty() || fields.contains(&PrincipalField::DisabledPermissions);
@@ -2480,14 +3075,156 @@ impl ManageDirectory for Store {
                         result.set(PrincipalField::BrandLogoUrl, url);
                     }
                 }
+                PrincipalData::BrandLogoDarkUrl(url) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::BrandLogoDarkUrl) {
+                        result.set(PrincipalField::BrandLogoDarkUrl, url);
+                    }
+                }
                 PrincipalData::BrandTheme(theme) => {
                     if fields.is_empty() || fields.contains(&PrincipalField::BrandTheme) {
                         result.set(PrincipalField::BrandTheme, theme);
                     }
                 }
+                PrincipalData::ExternalId(external_id) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::ExternalId) {
+                        result.set(PrincipalField::ExternalId, external_id);
+                    }
+                }
+                PrincipalData::LegalHold { set_by, set_at } => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::LegalHold) {
+                        result.set(PrincipalField::LegalHold, 1u64);
+                        result.set(PrincipalField::LegalHoldBy, set_by);
+                        result.set(PrincipalField::LegalHoldAt, set_at);
+                    }
+                }
+                PrincipalData::Tag(tag) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::Tags) {
+                        result.append_str(PrincipalField::Tags, tag);
+                    }
+                }
+                PrincipalData::AllowedProtocol(protocol) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::AllowedProtocols) {
+                        result.append_str(PrincipalField::AllowedProtocols, protocol);
+                    }
+                }
+                PrincipalData::SecondaryEmail(email) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::SecondaryEmails) {
+                        result.append_str(PrincipalField::SecondaryEmails, email);
+                    }
+                }
+                PrincipalData::PendingEmail {
+                    address, expires, ..
+                } => {
+                    if expires > now()
+                        && (fields.is_empty() || fields.contains(&PrincipalField::PendingEmails))
+                    {
+                        result.append_str(PrincipalField::PendingEmails, address);
+                    }
+                }
+                PrincipalData::Metadata { key, value } => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::Metadata) {
+                        result.append_str(PrincipalField::Metadata, format!("{key}={value}"));
+                    }
+                }
+                PrincipalData::CreatedAt(created_at) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::CreatedAt) {
+                        result.set(PrincipalField::CreatedAt, created_at);
+                    }
+                }
+                PrincipalData::ModifiedAt(modified_at) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::ModifiedAt) {
+                        result.set(PrincipalField::ModifiedAt, modified_at);
+                    }
+                }
                 PrincipalData::DirectoryQuota { quota, typ } => {
                     directory_quotas.push((typ, quota));
                 }
+                PrincipalData::PasswordHistoryLength(length) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::PasswordHistoryLength)
+                    {
+                        result.set(PrincipalField::PasswordHistoryLength, length as u64);
+                    }
+                }
+                PrincipalData::MinPasswordAgeDays(days) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::MinPasswordAgeDays) {
+                        result.set(PrincipalField::MinPasswordAgeDays, days as u64);
+                    }
+                }
+                PrincipalData::LockoutPolicy {
+                    threshold,
+                    window,
+                    duration,
+                    per_protocol,
+                    notify,
+                } => {
+                    for (field, value) in [
+                        (PrincipalField::LockoutThreshold, threshold as u64),
+                        (PrincipalField::LockoutWindow, window),
+                        (PrincipalField::LockoutDuration, duration),
+                        (PrincipalField::LockoutPerProtocol, per_protocol as u64),
+                        (PrincipalField::LockoutNotify, notify as u64),
+                    ] {
+                        if fields.is_empty() || fields.contains(&field) {
+                            result.set(field, value);
+                        }
+                    }
+                }
+                PrincipalData::DisableAt(at) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::DisableAt) {
+                        result.set(PrincipalField::DisableAt, at);
+                    }
+                }
+                PrincipalData::EnableAt(at) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::EnableAt) {
+                        result.set(PrincipalField::EnableAt, at);
+                    }
+                }
+                PrincipalData::Avatar { hash, .. } => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::Avatar) {
+                        result.set(PrincipalField::Avatar, hash);
+                    }
+                }
+                PrincipalData::AvatarSelfService(allowed) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::AvatarSelfService) {
+                        result.set(PrincipalField::AvatarSelfService, allowed as u64);
+                    }
+                }
+                PrincipalData::SecurityNoticeOptOut(opt_out) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::SecurityNoticeOptOut) {
+                        result.set(PrincipalField::SecurityNoticeOptOut, opt_out as u64);
+                    }
+                }
+                PrincipalData::TrialEndsAt(at) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::TrialEndsAt) {
+                        result.set(PrincipalField::TrialEndsAt, at);
+                    }
+                }
+                PrincipalData::Plan(plan) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::Plan) {
+                        result.set(PrincipalField::Plan, plan);
+                    }
+                }
+                PrincipalData::SenderAddress(address) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::SenderAddress) {
+                        result.set(PrincipalField::SenderAddress, address);
+                    }
+                }
+                PrincipalData::SendRate(rate) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::SendRate) {
+                        result.set(PrincipalField::SendRate, rate);
+                    }
+                }
+                contact @ (PrincipalData::BillingEmail(_)
+                | PrincipalData::TechnicalContact(_)
+                | PrincipalData::Phone(_)
+                | PrincipalData::PostalAddress(_)
+                | PrincipalData::TaxId(_)) => {
+                    if let Some((field, value)) = contact.as_contact()
+                        && (fields.is_empty() || fields.contains(&field))
+                    {
+                        result.set(field, value.to_string());
+                    }
+                }
                 _ => (),
             }
         }
@@ -2609,6 +3346,22 @@ impl ManageDirectory for Store {
             }
         }
 
+        // Obtain last activity
+        if principal.typ == Type::Individual
+            && (fields.is_empty() || fields.contains(&PrincipalField::LastActivity))
+        {
+            for (typ, timestamp) in self
+                .last_activity(principal.id)
+                .await
+                .caused_by(trc::location!())?
+            {
+                result.append_str(
+                    PrincipalField::LastActivity,
+                    format!("{}={timestamp}", typ.as_str()),
+                );
+            }
+        }
+
         Ok(result)
     }
 }
@@ -2624,7 +3377,7 @@ impl ValidateDirectory for Store {
             Err(err_exists(PrincipalField::Emails, email.to_string()))
         } else if let Some(domain) = email.try_domain_part() {
             match self
-                .get_principal_info(domain)
+                .resolve_principal_info(domain, None)
                 .await
                 .caused_by(trc::location!())?
             {
@@ -2670,8 +3423,10 @@ impl<'x> UpdatePrincipal<'x> {
             query: QueryBy::Id(id),
             changes: Vec::new(),
             create_domains: false,
+            self_service: false,
             tenant_id: None,
             allowed_permissions: None,
+            name_policy: None,
         }
     }
 
@@ -2680,8 +3435,10 @@ impl<'x> UpdatePrincipal<'x> {
             query: QueryBy::Name(name),
             changes: Vec::new(),
             create_domains: false,
+            self_service: false,
             tenant_id: None,
             allowed_permissions: None,
+            name_policy: None,
         }
     }
 
@@ -2700,10 +3457,711 @@ impl<'x> UpdatePrincipal<'x> {
         self
     }
 
+    pub fn with_name_policy(mut self, name_policy: &'x NamePolicy) -> Self {
+        self.name_policy = name_policy.into();
+        self
+    }
+
     pub fn create_domains(mut self) -> Self {
         self.create_domains = true;
         self
     }
+
+    /// Marks the update as made by the account owner, which is subject to
+    /// the minimum password age of the tenant.
+    pub fn self_service(mut self) -> Self {
+        self.self_service = true;
+        self
+    }
+}
+
+/// Validates a new principal and adds it to a batch, resolving names against
+/// the principals added earlier to the same batch.
+async fn build_principal(
+    store: &Store,
+    mut principal_set: PrincipalSet,
+    mut tenant_id: Option<u32>,
+    allowed_permissions: Option<&Permissions>,
+    name_policy: &NamePolicy,
+    pending: &mut Vec<Principal>,
+    batch: &mut BatchBuilder,
+    changed_principals: &mut ChangedPrincipals,
+) -> trc::Result<u32> {
+    // Make sure the principal has a name
+    let name = principal_set.name().to_lowercase();
+    if name.is_empty() {
+        return Err(err_missing(PrincipalField::Name));
+    }
+    let enforce_reserved = enforce_reserved_names(principal_set.typ(), allowed_permissions);
+    if enforce_reserved && name_policy.is_reserved(&name) {
+        return Err(err_reserved(PrincipalField::Name, name));
+    }
+    let mut valid_domains: AHashSet<String> = AHashSet::new();
+    let mut changed_fields = principal_set.fields.keys().copied().collect::<Vec<_>>();
+    changed_fields.sort_unstable();
+
+    // SPDX-SnippetBegin
+    // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+    // SPDX-License-Identifier: LicenseRef-SEL
+
+    // Validate tenant
+    #[cfg(feature = "enterprise")]
+    if let Some(tenant_id) = tenant_id {
+        let tenant = if let Some(tenant) = pending.iter().find(|p| p.id == tenant_id) {
+            tenant.clone()
+        } else {
+            store
+                .query(crate::QueryParams::id(tenant_id).with_return_member_of(false))
+                .await?
+                .ok_or_else(|| {
+                    trc::ManageEvent::NotFound
+                        .into_err()
+                        .id(tenant_id)
+                        .details("Tenant not found")
+                        .caused_by(trc::location!())
+                })?
+        };
+
+        // Enforce tenant quotas
+        if let Some(limit) = tenant
+            .directory_quota(&principal_set.typ())
+            .filter(|q| *q > 0)
+        {
+            // Obtain number of principals
+            let total = store
+                .count_tenant_principals(tenant_id, principal_set.typ())
+                .await
+                .caused_by(trc::location!())? as u32
+                + pending
+                    .iter()
+                    .filter(|p| p.typ == principal_set.typ() && p.tenant() == Some(tenant_id))
+                    .count() as u32;
+
+            if total >= limit {
+                trc::bail!(
+                    trc::LimitEvent::TenantQuota
+                        .into_err()
+                        .details("Tenant principal quota exceeded")
+                        .ctx(trc::Key::Details, principal_set.typ().description())
+                        .ctx(trc::Key::Limit, limit)
+                        .ctx(trc::Key::Total, total)
+                );
+            }
+        }
+    }
+
+    // SPDX-SnippetEnd
+
+    // Make sure new name is not taken, tenant-scoped names are checked once
+    // the tenant is known
+    let scoped_name = name_policy.scope == NameScope::Tenant
+        && matches!(principal_set.typ(), Type::Individual | Type::Group)
+        && !name.contains('@');
+    if !scoped_name && global_name_taken(store, pending, &name).await? {
+        return Err(err_exists(PrincipalField::Name, name));
+    }
+
+    let mut create_principal = Principal::new(0, principal_set.typ());
+
+    // SPDX-SnippetBegin
+    // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+    // SPDX-License-Identifier: LicenseRef-SEL
+
+    // Obtain tenant id, only if no default tenant is provided
+    #[cfg(feature = "enterprise")]
+    if let (Some(tenant_name), None) = (principal_set.take_str(PrincipalField::Tenant), tenant_id) {
+        tenant_id = principal_info(store, pending, &tenant_name)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|v| v.typ == Type::Tenant)
+            .ok_or_else(|| not_found(tenant_name.clone()))?
+            .id
+            .into();
+    }
+
+    // Tenants must provide principal names including a valid domain
+    #[cfg(feature = "enterprise")]
+    if let Some(tenant_id) = tenant_id {
+        if matches!(principal_set.typ, Type::Tenant) {
+            return Err(error(
+                "Invalid field",
+                "Tenants cannot contain a tenant field".into(),
+            ));
+        }
+
+        create_principal.data.push(PrincipalData::Tenant(tenant_id));
+
+        if scoped_name {
+            if tenant_name_taken(store, pending, &name, tenant_id)
+                .await
+                .caused_by(trc::location!())?
+            {
+                return Err(err_exists(PrincipalField::Name, name));
+            }
+
+            create_principal
+                .data
+                .push(PrincipalData::ScopedName(tenant_id));
+        } else if !matches!(create_principal.typ, Type::Tenant | Type::Domain) {
+            if let Some(domain) = name.try_domain_part()
+                && principal_info(store, pending, domain)
+                    .await
+                    .caused_by(trc::location!())?
+                    .filter(|v| v.typ == Type::Domain && v.has_tenant_access(tenant_id.into()))
+                    .is_some()
+            {
+                valid_domains.insert(domain.into());
+            }
+
+            if valid_domains.is_empty() {
+                return Err(error(
+                    "Invalid principal name",
+                    "Principal name must include a valid domain assigned to the tenant".into(),
+                ));
+            }
+        }
+    }
+    // SPDX-SnippetEnd
+
+    // Names without a tenant remain global
+    if scoped_name
+        && create_principal.name_scope().is_none()
+        && global_name_taken(store, pending, &name).await?
+    {
+        return Err(err_exists(PrincipalField::Name, name));
+    }
+
+    // Set fields
+    create_principal.name = name;
+    let mut has_secret = false;
+    for secret in principal_set
+        .take_str_array(PrincipalField::Secrets)
+        .unwrap_or_default()
+    {
+        if secret.is_otp_secret() {
+            create_principal.data.push(PrincipalData::OtpAuth(secret));
+        } else if secret.is_app_secret() {
+            create_principal
+                .data
+                .push(PrincipalData::AppPassword(secret));
+        } else if !has_secret {
+            has_secret = true;
+            create_principal.data.push(PrincipalData::Password(secret));
+        }
+    }
+
+    if let Some(description) = principal_set.take_str(PrincipalField::Description) {
+        create_principal
+            .data
+            .push(PrincipalData::Description(description));
+    }
+
+    if let Some(picture) = principal_set.take_str(PrincipalField::Picture) {
+        create_principal.data.push(PrincipalData::Picture(picture));
+    }
+    if let Some(picture) = principal_set.take_str(PrincipalField::Locale) {
+        create_principal.data.push(PrincipalData::Locale(picture));
+    }
+    if let Some(brand_name) = principal_set.take_str(PrincipalField::BrandName) {
+        create_principal
+            .data
+            .push(PrincipalData::BrandName(brand_name));
+    }
+    if let Some(brand_logo_url) = principal_set.take_str(PrincipalField::BrandLogoUrl) {
+        create_principal
+            .data
+            .push(PrincipalData::BrandLogoUrl(brand_logo_url));
+    }
+    if let Some(brand_logo_dark_url) = principal_set.take_str(PrincipalField::BrandLogoDarkUrl) {
+        create_principal
+            .data
+            .push(PrincipalData::BrandLogoDarkUrl(brand_logo_dark_url));
+    }
+    if let Some(brand_theme) = principal_set.take_str(PrincipalField::BrandTheme) {
+        create_principal
+            .data
+            .push(PrincipalData::BrandTheme(brand_theme));
+    }
+    if let Some(external_id) = principal_set
+        .take_str(PrincipalField::ExternalId)
+        .filter(|v| !v.is_empty())
+    {
+        create_principal
+            .data
+            .push(PrincipalData::ExternalId(external_id));
+    }
+    for url in principal_set
+        .take_str_array(PrincipalField::Urls)
+        .unwrap_or_default()
+    {
+        create_principal.data.push(PrincipalData::Url(url));
+    }
+    if let Some(entries) = principal_set.take_str_array(PrincipalField::Metadata) {
+        merge_metadata(&mut create_principal, entries)?;
+    }
+    if let Some(tags) = principal_set.take_str_array(PrincipalField::Tags) {
+        add_tags(&mut create_principal, tags)?;
+    }
+    if let Some(protocols) = principal_set.take_str_array(PrincipalField::AllowedProtocols) {
+        add_allowed_protocols(&mut create_principal, protocols);
+    }
+    for member in principal_set
+        .take_str_array(PrincipalField::ExternalMembers)
+        .unwrap_or_default()
+    {
+        create_principal
+            .data
+            .push(PrincipalData::ExternalMember(member));
+    }
+    for field in [
+        PrincipalField::PasswordHistoryLength,
+        PrincipalField::MinPasswordAgeDays,
+    ] {
+        if let Some(value) = principal_set.take_int(field)
+            && create_principal.typ == Type::Tenant
+        {
+            set_password_policy(&mut create_principal, field, value)?;
+        }
+    }
+    for field in [
+        PrincipalField::LockoutThreshold,
+        PrincipalField::LockoutWindow,
+        PrincipalField::LockoutDuration,
+        PrincipalField::LockoutPerProtocol,
+        PrincipalField::LockoutNotify,
+    ] {
+        if let Some(value) = principal_set.take_int(field)
+            && create_principal.typ == Type::Tenant
+        {
+            set_lockout_policy(&mut create_principal, field, value)?;
+        }
+    }
+    for field in [PrincipalField::DisableAt, PrincipalField::EnableAt] {
+        if let Some(value) = principal_set.take_int(field)
+            && create_principal.typ == Type::Individual
+        {
+            set_schedule(&mut create_principal, field, value);
+        }
+    }
+    apply_schedule(&mut create_principal, now());
+    if let Some(value) = principal_set.take_int(PrincipalField::AvatarSelfService)
+        && create_principal.typ == Type::Tenant
+    {
+        set_avatar_self_service(&mut create_principal, value);
+    }
+    if let Some(value) = principal_set.take_int(PrincipalField::SecurityNoticeOptOut)
+        && create_principal.typ == Type::Individual
+    {
+        set_security_notice_opt_out(&mut create_principal, value);
+    }
+    if let Some(value) = principal_set.take_int(PrincipalField::TrialEndsAt)
+        && create_principal.typ == Type::Tenant
+    {
+        set_trial(&mut create_principal, value);
+    }
+    if create_principal.typ == Type::Tenant {
+        for field in TENANT_CONTACT_FIELDS {
+            if let Some(value) = principal_set.take_str(field) {
+                let value = sanitize_contact(field, &value).ok_or_else(|| {
+                    error(
+                        "Invalid contact",
+                        format!("Invalid value {:?} for {}", value, field.as_str()).into(),
+                    )
+                })?;
+                if let Some(item) = PrincipalData::contact(field, value).filter(|item| {
+                    item.as_contact()
+                        .is_some_and(|(_, value)| !value.is_empty())
+                }) {
+                    create_principal.data.push(item);
+                }
+            }
+        }
+    }
+    if let Some(plan) = principal_set
+        .take_str(PrincipalField::Plan)
+        .filter(|plan| !plan.is_empty())
+        && create_principal.typ == Type::Tenant
+    {
+        create_principal.data.push(PrincipalData::Plan(plan));
+    }
+    for field in [PrincipalField::SenderAddress, PrincipalField::SendRate] {
+        if let Some(value) = principal_set.take_str(field)
+            && create_principal.typ == Type::Individual
+        {
+            set_sender(&mut create_principal, field, &value)?;
+        }
+    }
+    validate_sender_domain(store, pending, &create_principal, tenant_id).await?;
+    if let Some(quotas) = principal_set.take_int_array(PrincipalField::Quota) {
+        for (idx, quota) in quotas.into_iter().take(Type::MAX_ID + 2).enumerate() {
+            if quota != 0 {
+                if idx != 0 {
+                    create_principal.data.push(PrincipalData::DirectoryQuota {
+                        quota: quota as u32,
+                        typ: Type::from_u8((idx - 1) as u8),
+                    });
+                } else {
+                    create_principal.data.push(PrincipalData::DiskQuota(quota));
+                }
+            }
+        }
+    }
+
+    // Map member names
+    let mut members = Vec::new();
+    let mut member_of = Vec::new();
+    for (field, expected_type) in [
+        (PrincipalField::Members, None),
+        (PrincipalField::MemberOf, Some(Type::Group)),
+        (PrincipalField::Lists, Some(Type::List)),
+        (PrincipalField::Roles, Some(Type::Role)),
+    ] {
+        if let Some(names) = principal_set.take_str_array(field) {
+            let list = if field == PrincipalField::Members {
+                &mut members
+            } else {
+                &mut member_of
+            };
+
+            for name in names {
+                let item = match (
+                    principal_info(store, pending, &name)
+                        .await
+                        .caused_by(trc::location!())?
+                        .filter(|v| {
+                            expected_type.is_none_or(|t| v.typ == t)
+                                && v.has_tenant_access(tenant_id)
+                        }),
+                    field.map_internal_roles(&name),
+                ) {
+                    (_, Some(v)) => v,
+                    (Some(v), _) => {
+                        if field == PrincipalField::Members {
+                            // Update principal members
+                            changed_principals.add_change(v.id, v.typ, PrincipalField::MemberOf);
+                        }
+                        v
+                    }
+                    _ => {
+                        return Err(not_found(name));
+                    }
+                };
+
+                list.push(item);
+            }
+        }
+    }
+
+    // Map permissions
+    let mut permissions = AHashMap::new();
+    for field in [
+        PrincipalField::EnabledPermissions,
+        PrincipalField::DisabledPermissions,
+    ] {
+        let is_disabled = field == PrincipalField::DisabledPermissions;
+        if let Some(names) = principal_set.take_str_array(field) {
+            for name in names {
+                let permission = Permission::from_name(&name).ok_or_else(|| {
+                    error(
+                        format!("Invalid {} value", field.as_str()),
+                        format!("Permission {name:?} is invalid").into(),
+                    )
+                })?;
+
+                if !permissions.contains_key(&permission) {
+                    if allowed_permissions
+                        .as_ref()
+                        .is_none_or(|p| p.get(permission as usize))
+                        || is_disabled
+                    {
+                        permissions.insert(permission, is_disabled);
+                    } else {
+                        return Err(error(
+                            "Invalid permission",
+                            format!("Your account cannot grant the {name:?} permission").into(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    if !permissions.is_empty() {
+        for (permission, v) in permissions {
+            create_principal.data.push(PrincipalData::Permission {
+                permission_id: permission.id(),
+                grant: !v,
+            });
+        }
+    }
+
+    // Make sure the e-mail is not taken and validate domain
+    if create_principal.typ != Type::OauthClient {
+        for (idx, email) in principal_set
+            .take_str_array(PrincipalField::Emails)
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+        {
+            let email = name_policy.normalize_email(&email);
+            if enforce_reserved && name_policy.is_reserved(&email) {
+                return Err(err_reserved(PrincipalField::Emails, email));
+            }
+            if pending
+                .iter()
+                .any(|p| p.email_addresses().any(|address| address == email))
+                || store.rcpt(&email).await.caused_by(trc::location!())? != RcptType::Invalid
+            {
+                return Err(err_exists(PrincipalField::Emails, email.to_string()));
+            }
+            if let Some(domain) = email.try_domain_part()
+                && valid_domains.insert(domain.into())
+            {
+                principal_info(store, pending, domain)
+                    .await
+                    .caused_by(trc::location!())?
+                    .filter(|v| v.typ == Type::Domain && v.has_tenant_access(tenant_id))
+                    .ok_or_else(|| not_found(domain.to_string()))?;
+            }
+            if idx == 0 {
+                create_principal
+                    .data
+                    .push(PrincipalData::PrimaryEmail(email));
+            } else {
+                create_principal.data.push(PrincipalData::EmailAlias(email));
+            }
+        }
+    }
+
+    // Write principal
+    let principal_id = store
+        .assign_document_ids(u32::MAX, Collection::Principal, 1)
+        .await
+        .caused_by(trc::location!())?;
+    if principal_id == FALLBACK_ADMIN_ID {
+        return Err(trc::StoreEvent::UnexpectedError
+            .into_err()
+            .details("ID assignment failed")
+            .caused_by(trc::location!()));
+    }
+    create_principal.id = principal_id;
+    let pinfo_name = PrincipalInfo::new(principal_id, create_principal.typ, tenant_id);
+    let pinfo_email = PrincipalInfo::new(principal_id, create_principal.typ, None);
+
+    // Record creation time
+    let created_at = now();
+    create_principal
+        .data
+        .push(PrincipalData::CreatedAt(created_at));
+    create_principal
+        .data
+        .push(PrincipalData::ModifiedAt(created_at));
+
+    // Validate object size
+    if create_principal.object_size() > 100_000 {
+        return Err(error(
+            "Invalid parameter",
+            "Principal object size exceeds 100kb safety limit.".into(),
+        ));
+    }
+
+    // Serialize
+    create_principal.sort();
+    let archiver = Archiver::new(create_principal);
+    let principal_bytes = archiver.serialize().caused_by(trc::location!())?;
+    let create_principal = archiver.into_inner();
+
+    batch
+        .with_account_id(u32::MAX)
+        .with_collection(Collection::Principal)
+        .with_document(principal_id)
+        .assert_value(
+            ValueClass::Directory(DirectoryClass::NameToId(create_principal.name_key())),
+            (),
+        );
+    build_search_index(batch, principal_id, None, Some(&create_principal));
+    batch
+        .set(
+            ValueClass::Directory(DirectoryClass::Principal(principal_id)),
+            principal_bytes,
+        )
+        .set(
+            ValueClass::Directory(DirectoryClass::NameToId(create_principal.name_key())),
+            pinfo_name.serialize(),
+        );
+    if let Some(tenant_id) = tenant_id {
+        batch.add(principal_count(tenant_id, create_principal.typ), 1);
+    }
+
+    // Write external id to id mapping
+    if let Some(external_id) = create_principal.external_id() {
+        assert_external_id_available(store, pending, external_id, create_principal.tenant(), None)
+            .await?;
+        batch.set(
+            ValueClass::Directory(DirectoryClass::ExternalIdToId(external_id_key(
+                external_id,
+                create_principal.tenant(),
+            ))),
+            pinfo_name.serialize(),
+        );
+    }
+
+    // Write email to id mapping
+    for email in create_principal.email_addresses() {
+        batch.set(
+            ValueClass::Directory(DirectoryClass::EmailToId(email.as_bytes().to_vec())),
+            pinfo_email.serialize(),
+        );
+    }
+
+    // Write membership
+    for member_of in member_of {
+        batch.set(
+            ValueClass::Directory(DirectoryClass::MemberOf {
+                principal_id,
+                member_of: member_of.id,
+            }),
+            vec![member_of.typ as u8],
+        );
+        batch.set(
+            ValueClass::Directory(DirectoryClass::Members {
+                principal_id: member_of.id,
+                has_member: principal_id,
+            }),
+            vec![],
+        );
+    }
+    for member in members {
+        batch.set(
+            ValueClass::Directory(DirectoryClass::MemberOf {
+                principal_id: member.id,
+                member_of: principal_id,
+            }),
+            vec![create_principal.typ as u8],
+        );
+        batch.set(
+            ValueClass::Directory(DirectoryClass::Members {
+                principal_id,
+                has_member: member.id,
+            }),
+            vec![],
+        );
+    }
+
+    changed_principals.add_principal_change(
+        PrincipalChangeAction::Created,
+        principal_id,
+        create_principal.typ,
+        tenant_id,
+        changed_fields,
+    );
+
+    pending.push(create_principal);
+
+    Ok(principal_id)
+}
+
+async fn principal_info(
+    store: &Store,
+    pending: &[Principal],
+    name: &str,
+) -> trc::Result<Option<PrincipalInfo>> {
+    if let Some(principal) = pending.iter().find(|p| p.name == name) {
+        Ok(Some(PrincipalInfo::new(
+            principal.id,
+            principal.typ,
+            principal.tenant(),
+        )))
+    } else {
+        store.resolve_principal_info(name, None).await
+    }
+}
+
+/// Send-only credentials can only be locked to domains of their tenant.
+async fn validate_sender_domain(
+    store: &Store,
+    pending: &[Principal],
+    principal: &Principal,
+    tenant_id: Option<u32>,
+) -> trc::Result<()> {
+    if let Some(domain) = principal
+        .sender_address()
+        .and_then(|address| address.try_domain_part())
+    {
+        principal_info(store, pending, domain)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|v| v.typ == Type::Domain && v.has_tenant_access(tenant_id))
+            .ok_or_else(|| not_found(domain.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// A global name is also taken when it would resolve to a tenant-scoped
+/// principal at login.
+async fn global_name_taken(store: &Store, pending: &[Principal], name: &str) -> trc::Result<bool> {
+    if pending
+        .iter()
+        .any(|p| p.name == name && p.name_scope().is_none())
+    {
+        Ok(true)
+    } else {
+        store
+            .resolve_principal_info(name, None)
+            .await
+            .caused_by(trc::location!())
+            .map(|info| info.is_some())
+    }
+}
+
+/// A tenant-scoped name is taken by another principal with the same name in
+/// the tenant, or by a global `name@domain` for any domain of the tenant, as
+/// logins through that domain would otherwise be ambiguous.
+async fn tenant_name_taken(
+    store: &Store,
+    pending: &[Principal],
+    name: &str,
+    tenant_id: u32,
+) -> trc::Result<bool> {
+    if pending
+        .iter()
+        .any(|p| p.name == name && p.name_scope() == Some(tenant_id))
+        || store
+            .get_tenant_principal_info(name, tenant_id)
+            .await
+            .caused_by(trc::location!())?
+            .is_some()
+    {
+        return Ok(true);
+    }
+
+    let domains = store
+        .list_principals(None, Some(tenant_id), &[Type::Domain], false, 0, 0)
+        .await
+        .caused_by(trc::location!())?
+        .items
+        .into_iter()
+        .map(|domain| domain.name)
+        .chain(
+            pending
+                .iter()
+                .filter(|p| p.typ == Type::Domain && p.tenant() == Some(tenant_id))
+                .map(|domain| domain.name.clone()),
+        );
+    for domain in domains {
+        let address = format!("{name}@{domain}");
+        if pending.iter().any(|p| p.name == address)
+            || store
+                .get_principal_id(&address)
+                .await
+                .caused_by(trc::location!())?
+                .is_some()
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
 }
 
 fn validate_member_of(
@@ -2767,7 +4225,10 @@ impl ChangedPrincipals {
                     | PrincipalField::Tenant
                     | PrincipalField::Roles
                     | PrincipalField::EnabledPermissions
-                    | PrincipalField::DisabledPermissions,
+                    | PrincipalField::DisabledPermissions
+                    | PrincipalField::AllowedProtocols
+                    | PrincipalField::SenderAddress
+                    | PrincipalField::SendRate,
             ) | (
                 Type::Tenant | Type::Role | Type::ApiKey | Type::OauthClient,
                 PrincipalField::MemberOf
@@ -2776,11 +4237,12 @@ impl ChangedPrincipals {
                     | PrincipalField::Tenant
                     | PrincipalField::Roles
                     | PrincipalField::EnabledPermissions
-                    | PrincipalField::DisabledPermissions,
+                    | PrincipalField::DisabledPermissions
+                    | PrincipalField::Plan,
             )
         ) && principal_id < ROLE_USER
         {
-            self.0
+            self.principals
                 .entry(principal_id)
                 .or_insert_with(|| ChangedPrincipal::new(principal_type))
                 .update_member_change(matches!(
@@ -2788,7 +4250,7 @@ impl ChangedPrincipals {
                     (
                         PrincipalField::EnabledPermissions | PrincipalField::DisabledPermissions,
                         Type::Role | Type::Tenant
-                    )
+                    ) | (PrincipalField::Plan, Type::Tenant)
                 ))
                 .update_name_change(matches!(field, PrincipalField::Name));
         }
@@ -2803,12 +4265,12 @@ impl ChangedPrincipals {
     ) {
         match (principal_type, member_type) {
             (Type::Group | Type::Role, Type::Individual | Type::ApiKey | Type::OauthClient) => {
-                self.0
+                self.principals
                     .entry(member_id)
                     .or_insert_with(|| ChangedPrincipal::new(member_type));
             }
             (Type::Individual | Type::ApiKey | Type::OauthClient, Type::Group | Type::Role) => {
-                self.0
+                self.principals
                     .entry(principal_id)
                     .or_insert_with(|| ChangedPrincipal::new(principal_type));
             }
@@ -2817,13 +4279,13 @@ impl ChangedPrincipals {
                 Type::Individual | Type::Group | Type::Tenant | Type::Role,
             ) => {
                 if principal_id < ROLE_USER {
-                    self.0
+                    self.principals
                         .entry(principal_id)
                         .or_insert_with(|| ChangedPrincipal::new(principal_type))
                         .update_member_change(matches!(member_type, Type::Role));
                 }
                 if member_id < ROLE_USER {
-                    self.0
+                    self.principals
                         .entry(member_id)
                         .or_insert_with(|| ChangedPrincipal::new(member_type))
                         .update_member_change(matches!(principal_type, Type::Role));
@@ -2843,22 +4305,65 @@ impl ChangedPrincipals {
                 | Type::ApiKey
                 | Type::OauthClient
         ) {
-            self.0
+            self.principals
                 .entry(principal_id)
                 .or_insert_with(|| ChangedPrincipal::new(principal_type));
         }
     }
 
+    pub fn add_principal_change(
+        &mut self,
+        action: PrincipalChangeAction,
+        id: u32,
+        typ: Type,
+        tenant_id: Option<u32>,
+        fields: Vec<PrincipalField>,
+    ) {
+        self.changes.push(PrincipalChange {
+            action,
+            id,
+            typ,
+            tenant_id,
+            fields,
+        });
+    }
+
+    /// Adds the changes of another operation, so that caches are invalidated
+    /// once for all of them.
+    pub fn merge(&mut self, other: ChangedPrincipals) {
+        for (principal_id, changed) in other.principals {
+            self.principals
+                .entry(principal_id)
+                .or_insert_with(|| ChangedPrincipal::new(changed.typ))
+                .update_member_change(changed.member_change)
+                .update_name_change(changed.name_change);
+        }
+        self.changes.extend(other.changes);
+    }
+
+    pub fn changes(&self) -> &[PrincipalChange] {
+        &self.changes
+    }
+
+    /// Returns the tenant all the changed principals belong to, if any.
+    pub fn tenant_id(&self) -> Option<u32> {
+        let tenant_id = self.changes.first()?.tenant_id?;
+        self.changes
+            .iter()
+            .all(|change| change.tenant_id == Some(tenant_id))
+            .then_some(tenant_id)
+    }
+
     pub fn contains(&self, principal_id: u32) -> bool {
-        self.0.contains_key(&principal_id)
+        self.principals.contains_key(&principal_id)
     }
 
     pub fn iter(&'_ self) -> std::collections::hash_map::Iter<'_, u32, ChangedPrincipal> {
-        self.0.iter()
+        self.principals.iter()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.principals.is_empty() && self.changes.is_empty()
     }
 }
 
@@ -2892,6 +4397,16 @@ pub fn err_exists(field: impl Into<trc::Value>, value: impl Into<trc::Value>) ->
         .ctx(trc::Key::Value, value)
 }
 
+pub fn err_reserved(field: impl Into<trc::Value>, value: impl Into<trc::Value>) -> trc::Error {
+    trc::ManageEvent::ReservedName
+        .ctx(trc::Key::Key, field)
+        .ctx(trc::Key::Value, value)
+}
+
+pub fn err_password_reused(history: u32) -> trc::Error {
+    trc::ManageEvent::PasswordReused.ctx(trc::Key::Limit, history)
+}
+
 pub fn not_found(value: impl Into<trc::Value>) -> trc::Error {
     trc::ManageEvent::NotFound.ctx(trc::Key::Key, value)
 }
@@ -2915,3 +4430,164 @@ impl From<PrincipalField> for trc::Value {
         trc::Value::String(CompactString::const_new(value.as_str()))
     }
 }
+
+/// Reserved names apply to Individual and Group principals, unless the change
+/// is made internally or by a holder of the bypass permission.
+fn enforce_reserved_names(typ: Type, allowed_permissions: Option<&Permissions>) -> bool {
+    matches!(typ, Type::Individual | Type::Group)
+        && allowed_permissions.is_some_and(|p| !p.get(Permission::PrincipalReservedName as usize))
+}
+
+/// Principal types that can belong to a tenant, each with its own counter.
+pub const TENANT_PRINCIPAL_TYPES: [Type; 10] = [
+    Type::Individual,
+    Type::Group,
+    Type::Resource,
+    Type::Location,
+    Type::List,
+    Type::Other,
+    Type::Domain,
+    Type::Role,
+    Type::ApiKey,
+    Type::OauthClient,
+];
+
+/// Domain of the primary address of a principal, as stored in the directory.
+fn principal_domain(principal: &Principal) -> Option<String> {
+    principal
+        .primary_email()
+        .and_then(|email| email.try_domain_part())
+        .map(|domain| domain.to_lowercase())
+}
+
+/// Moves the message and storage counters of an account between the rollups
+/// of two domains.
+async fn move_domain_usage(
+    store: &Store,
+    batch: &mut BatchBuilder,
+    principal_id: u32,
+    from: Option<u32>,
+    to: Option<u32>,
+) -> trc::Result<()> {
+    for class in [DirectoryClass::UsedQuota, DirectoryClass::MessageCount] {
+        let value = store
+            .get_counter(class(principal_id))
+            .await
+            .caused_by(trc::location!())?;
+        if value != 0 {
+            if let Some(from) = from {
+                batch.add(class(from), -value);
+            }
+            if let Some(to) = to {
+                batch.add(class(to), value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn principal_count(tenant_id: u32, typ: Type) -> DirectoryClass {
+    DirectoryClass::PrincipalCount {
+        tenant_id,
+        typ: typ as u8,
+    }
+}
+
+/// Merges metadata entries into a principal, entries without a value delete
+/// the key.
+fn merge_metadata(principal: &mut Principal, entries: Vec<String>) -> trc::Result<()> {
+    for entry in entries {
+        let (key, value) = metadata_entry(&entry);
+        if !is_valid_metadata_key(key) {
+            return Err(error(
+                "Invalid metadata key",
+                format!(
+                    "Key {key:?} must be 1 to {MAX_METADATA_KEY_LEN} characters long and contain only letters, digits, '.', '-', '_' or ':'"
+                )
+                .into(),
+            ));
+        }
+        principal
+            .data
+            .retain(|v| !matches!(v, PrincipalData::Metadata { key: k, .. } if k == key));
+        if let Some(value) = value {
+            principal.data.push(PrincipalData::Metadata {
+                key: key.to_string(),
+                value: value.to_string(),
+            });
+        }
+    }
+
+    if principal.metadata().count() > MAX_METADATA_ENTRIES {
+        Err(error(
+            "Too many metadata entries",
+            format!("A principal can have at most {MAX_METADATA_ENTRIES} metadata entries").into(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Sets or clears a restriction of a send-only credential.
+fn set_sender(principal: &mut Principal, field: PrincipalField, value: &str) -> trc::Result<()> {
+    let value = sanitize_sender(field, value).ok_or_else(|| {
+        error(
+            "Invalid sender restriction",
+            format!("Invalid value {:?} for {}", value, field.as_str()).into(),
+        )
+    })?;
+    principal.data.retain(|v| match field {
+        PrincipalField::SenderAddress => !matches!(v, PrincipalData::SenderAddress(_)),
+        _ => !matches!(v, PrincipalData::SendRate(_)),
+    });
+    if !value.is_empty() {
+        principal.data.push(match field {
+            PrincipalField::SenderAddress => PrincipalData::SenderAddress(value),
+            _ => PrincipalData::SendRate(value),
+        });
+    }
+
+    Ok(())
+}
+
+/// Adds tags to a principal, tags are stored in lowercase.
+// Protocol names are validated by the management API, the directory only
+// normalizes them
+fn add_allowed_protocols(principal: &mut Principal, protocols: impl IntoIterator<Item = String>) {
+    for protocol in protocols {
+        let protocol = protocol.trim().to_lowercase();
+        if !protocol.is_empty() && !principal.allowed_protocols().any(|p| p == protocol) {
+            principal
+                .data
+                .push(PrincipalData::AllowedProtocol(protocol));
+        }
+    }
+}
+
+fn add_tags(principal: &mut Principal, tags: impl IntoIterator<Item = String>) -> trc::Result<()> {
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !is_valid_tag(&tag) {
+            return Err(error(
+                "Invalid tag",
+                format!(
+                    "Tag {tag:?} must be 1 to {MAX_TAG_LEN} characters long and contain only letters, digits, '.', '-', '_' or ':'"
+                )
+                .into(),
+            ));
+        }
+        if !principal.tags().any(|t| t == tag) {
+            principal.data.push(PrincipalData::Tag(tag));
+        }
+    }
+
+    if principal.tags().count() > MAX_TAGS {
+        Err(error(
+            "Too many tags",
+            format!("A principal can have at most {MAX_TAGS} tags").into(),
+        ))
+    } else {
+        Ok(())
+    }
+}
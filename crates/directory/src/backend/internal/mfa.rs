@@ -0,0 +1,287 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    manage::{ManageDirectory, error, not_found},
+    secondary::update_principal_data,
+};
+use crate::PrincipalData;
+use std::time::Duration;
+use store::{
+    InMemoryStore, Store,
+    dispatch::lookup::KeyValue,
+    rand::{Rng, rng},
+};
+use totp_rs::{Algorithm, TOTP};
+use utils::config::Rate;
+
+/// In-memory key prefix of TOTP enrollments waiting for confirmation.
+pub const KV_TOTP_ENROLLMENT: u8 = 32;
+
+/// In-memory key prefix of failed TOTP code counters.
+pub const KV_TOTP_FAILURES: u8 = 33;
+
+/// Seconds an enrollment can be confirmed for.
+pub const TOTP_ENROLLMENT_EXPIRY: u64 = 600;
+
+/// Failed codes accepted per account within `TOTP_ATTEMPT_WINDOW`.
+pub const MAX_TOTP_ATTEMPTS: u64 = 5;
+pub const TOTP_ATTEMPT_WINDOW: u64 = 300;
+
+/// Prefix of TOTP secrets that are encrypted at rest.
+pub const ENCRYPTED_OTP_PREFIX: &str = "$otp$";
+
+/// Encrypts and decrypts the TOTP secrets of the internal directory.
+pub trait SecretCipher: Sync + Send {
+    fn encrypt_secret(&self, secret: &str) -> trc::Result<String>;
+    fn decrypt_secret(&self, secret: &str) -> trc::Result<String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpEnrollment {
+    pub uri: String,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpFactor {
+    pub issuer: Option<String>,
+    pub account_name: Option<String>,
+    pub encrypted: bool,
+}
+
+/// TOTP factors are enrolled in two steps: a secret is generated and kept
+/// until the owner proves it was added to an authenticator by sending a
+/// valid code. Code attempts are limited per account, for both enrollments
+/// and logins.
+#[allow(async_fn_in_trait)]
+pub trait TotpFactors: Sync + Send {
+    /// Generates a secret and returns its `otpauth://` URI, replacing any
+    /// enrollment that was not confirmed.
+    async fn start_totp_enrollment(
+        &self,
+        principal_id: u32,
+        issuer: &str,
+        account_name: &str,
+        cipher: &dyn SecretCipher,
+    ) -> trc::Result<TotpEnrollment>;
+    /// Activates the pending factor if the code is valid, an active factor
+    /// is replaced.
+    async fn confirm_totp_enrollment(
+        &self,
+        principal_id: u32,
+        code: &str,
+        cipher: &dyn SecretCipher,
+    ) -> trc::Result<bool>;
+    async fn list_totp_factors(
+        &self,
+        principal_id: u32,
+        cipher: &dyn SecretCipher,
+    ) -> trc::Result<Vec<TotpFactor>>;
+    async fn remove_totp_factor(&self, principal_id: u32) -> trc::Result<bool>;
+    /// Whether the account used up its code attempts.
+    async fn is_totp_limited(&self, principal_id: u32) -> trc::Result<bool>;
+    async fn record_totp_failure(&self, principal_id: u32) -> trc::Result<()>;
+    async fn reset_totp_failures(&self, principal_id: u32) -> trc::Result<()>;
+}
+
+impl TotpFactors for Store {
+    async fn start_totp_enrollment(
+        &self,
+        principal_id: u32,
+        issuer: &str,
+        account_name: &str,
+        cipher: &dyn SecretCipher,
+    ) -> trc::Result<TotpEnrollment> {
+        let mut secret = [0u8; 20];
+        rng().fill(&mut secret[..]);
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            secret.to_vec(),
+            Some(issuer.replace(':', " ")),
+            account_name.replace(':', " "),
+        )
+        .map_err(|err| error("Invalid TOTP parameters", err.to_string().into()))?;
+        let enrollment = TotpEnrollment {
+            uri: totp.get_url(),
+            secret: totp.get_secret_base32(),
+        };
+
+        InMemoryStore::Store(self.clone())
+            .key_set(
+                KeyValue::new(
+                    enrollment_key(principal_id),
+                    cipher.encrypt_secret(&enrollment.uri)?.into_bytes(),
+                )
+                .expires(TOTP_ENROLLMENT_EXPIRY),
+            )
+            .await?;
+
+        Ok(enrollment)
+    }
+
+    async fn confirm_totp_enrollment(
+        &self,
+        principal_id: u32,
+        code: &str,
+        cipher: &dyn SecretCipher,
+    ) -> trc::Result<bool> {
+        let kv = InMemoryStore::Store(self.clone());
+        let secret = kv
+            .key_get::<String>(enrollment_key(principal_id))
+            .await?
+            .ok_or_else(|| {
+                error(
+                    "No pending enrollment",
+                    "Start a TOTP enrollment before confirming it".into(),
+                )
+            })?;
+
+        if self.is_totp_limited(principal_id).await? {
+            return Err(trc::AuthEvent::TooManyAttempts
+                .into_err()
+                .account_id(principal_id));
+        }
+
+        let uri = cipher.decrypt_secret(&secret)?;
+        let is_valid = TOTP::from_url(&uri)
+            .map_err(|err| trc::AuthEvent::Error.reason(err))?
+            .check_current(code.trim())
+            .unwrap_or(false);
+        if !is_valid {
+            self.record_totp_failure(principal_id).await?;
+            return Ok(false);
+        }
+
+        update_principal_data(self, principal_id, |principal| {
+            principal
+                .data
+                .retain(|item| !matches!(item, PrincipalData::OtpAuth(_)));
+            principal.data.push(PrincipalData::OtpAuth(secret));
+            Ok(true)
+        })
+        .await?;
+        kv.key_delete(enrollment_key(principal_id)).await?;
+        self.reset_totp_failures(principal_id).await?;
+
+        Ok(true)
+    }
+
+    async fn list_totp_factors(
+        &self,
+        principal_id: u32,
+        cipher: &dyn SecretCipher,
+    ) -> trc::Result<Vec<TotpFactor>> {
+        let principal = self
+            .get_principal(principal_id)
+            .await?
+            .ok_or_else(|| not_found(principal_id))?;
+
+        Ok(principal
+            .data
+            .iter()
+            .filter_map(|item| match item {
+                PrincipalData::OtpAuth(secret) => {
+                    let encrypted = is_encrypted_otp_secret(secret);
+                    let totp = if encrypted {
+                        cipher
+                            .decrypt_secret(secret)
+                            .ok()
+                            .and_then(|uri| TOTP::from_url(uri).ok())
+                    } else {
+                        TOTP::from_url(secret).ok()
+                    };
+
+                    Some(TotpFactor {
+                        issuer: totp.as_ref().and_then(|totp| totp.issuer.clone()),
+                        account_name: totp.map(|totp| totp.account_name),
+                        encrypted,
+                    })
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn remove_totp_factor(&self, principal_id: u32) -> trc::Result<bool> {
+        let result = update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal
+                .data
+                .retain(|item| !matches!(item, PrincipalData::OtpAuth(_)));
+            Ok(principal.data.len() != len)
+        })
+        .await?;
+
+        if result {
+            self.reset_totp_failures(principal_id).await?;
+        }
+
+        Ok(result)
+    }
+
+    async fn is_totp_limited(&self, principal_id: u32) -> trc::Result<bool> {
+        InMemoryStore::Store(self.clone())
+            .is_rate_allowed(
+                KV_TOTP_FAILURES,
+                &principal_id.to_be_bytes(),
+                &attempt_rate(),
+                true,
+            )
+            .await
+            .map(|result| result.is_some())
+    }
+
+    async fn record_totp_failure(&self, principal_id: u32) -> trc::Result<()> {
+        InMemoryStore::Store(self.clone())
+            .is_rate_allowed(
+                KV_TOTP_FAILURES,
+                &principal_id.to_be_bytes(),
+                &attempt_rate(),
+                false,
+            )
+            .await
+            .map(|_| ())
+    }
+
+    async fn reset_totp_failures(&self, principal_id: u32) -> trc::Result<()> {
+        InMemoryStore::Store(self.clone())
+            .key_delete_prefix(&failures_prefix(principal_id))
+            .await
+    }
+}
+
+pub fn is_encrypted_otp_secret(secret: &str) -> bool {
+    secret.starts_with(ENCRYPTED_OTP_PREFIX)
+}
+
+fn attempt_rate() -> Rate {
+    Rate {
+        requests: MAX_TOTP_ATTEMPTS,
+        period: Duration::from_secs(TOTP_ATTEMPT_WINDOW),
+    }
+}
+
+pub(super) fn mfa_prefixes(principal_id: u32) -> [Vec<u8>; 2] {
+    [failures_prefix(principal_id), enrollment_key(principal_id)]
+}
+
+fn failures_prefix(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_TOTP_FAILURES);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
+
+fn enrollment_key(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_TOTP_ENROLLMENT);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
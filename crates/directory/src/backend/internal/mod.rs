@@ -4,15 +4,30 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+pub mod activity;
+pub mod app_password;
+pub mod application;
+pub mod avatar;
+pub mod external_id;
+pub mod lockout;
 pub mod lookup;
 pub mod manage;
+pub mod mfa;
+pub mod password;
+pub mod recovery;
+pub mod schedule;
+pub mod secondary;
+pub mod security;
+pub mod sender;
+pub mod trial;
+pub mod webauthn;
 
 use crate::Type;
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 
 use std::fmt::Display;
 use store::{Deserialize, SerializeInfallible, U32_LEN, write::key::KeySerializer};
-use utils::codec::leb128::Leb128Iterator;
+use utils::{DomainPart, codec::leb128::Leb128Iterator, config::utils::ParseValue};
 
 pub struct PrincipalInfo {
     pub id: u32,
@@ -111,6 +126,41 @@ pub enum PrincipalField {
     BrandName,
     BrandLogoUrl,
     BrandTheme,
+    ExternalId,
+    LegalHold,
+    LegalHoldBy,
+    LegalHoldAt,
+    CreatedAt,
+    ModifiedAt,
+    Metadata,
+    Tags,
+    SecondaryEmails,
+    PendingEmails,
+    PasswordHistoryLength,
+    MinPasswordAgeDays,
+    LockoutThreshold,
+    LockoutWindow,
+    LockoutDuration,
+    LockoutPerProtocol,
+    LockoutNotify,
+    LastActivity,
+    DisableAt,
+    EnableAt,
+    Disabled,
+    Avatar,
+    AvatarSelfService,
+    TrialEndsAt,
+    Plan,
+    AllowedProtocols,
+    BillingEmail,
+    TechnicalContact,
+    Phone,
+    PostalAddress,
+    TaxId,
+    BrandLogoDarkUrl,
+    SecurityNoticeOptOut,
+    SenderAddress,
+    SendRate,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -202,6 +252,41 @@ impl PrincipalField {
             PrincipalField::BrandName => 18,
             PrincipalField::BrandLogoUrl => 19,
             PrincipalField::BrandTheme => 20,
+            PrincipalField::ExternalId => 21,
+            PrincipalField::LegalHold => 22,
+            PrincipalField::LegalHoldBy => 23,
+            PrincipalField::LegalHoldAt => 24,
+            PrincipalField::CreatedAt => 25,
+            PrincipalField::ModifiedAt => 26,
+            PrincipalField::Metadata => 27,
+            PrincipalField::Tags => 28,
+            PrincipalField::SecondaryEmails => 29,
+            PrincipalField::PendingEmails => 30,
+            PrincipalField::PasswordHistoryLength => 31,
+            PrincipalField::MinPasswordAgeDays => 32,
+            PrincipalField::LockoutThreshold => 33,
+            PrincipalField::LockoutWindow => 34,
+            PrincipalField::LockoutDuration => 35,
+            PrincipalField::LockoutPerProtocol => 36,
+            PrincipalField::LockoutNotify => 37,
+            PrincipalField::LastActivity => 38,
+            PrincipalField::DisableAt => 39,
+            PrincipalField::EnableAt => 40,
+            PrincipalField::Disabled => 41,
+            PrincipalField::Avatar => 42,
+            PrincipalField::AvatarSelfService => 43,
+            PrincipalField::TrialEndsAt => 44,
+            PrincipalField::Plan => 45,
+            PrincipalField::AllowedProtocols => 46,
+            PrincipalField::BillingEmail => 47,
+            PrincipalField::TechnicalContact => 48,
+            PrincipalField::Phone => 49,
+            PrincipalField::PostalAddress => 50,
+            PrincipalField::TaxId => 51,
+            PrincipalField::BrandLogoDarkUrl => 52,
+            PrincipalField::SecurityNoticeOptOut => 53,
+            PrincipalField::SenderAddress => 54,
+            PrincipalField::SendRate => 55,
         }
     }
 
@@ -228,6 +313,41 @@ impl PrincipalField {
             18 => Some(PrincipalField::BrandName),
             19 => Some(PrincipalField::BrandLogoUrl),
             20 => Some(PrincipalField::BrandTheme),
+            21 => Some(PrincipalField::ExternalId),
+            22 => Some(PrincipalField::LegalHold),
+            23 => Some(PrincipalField::LegalHoldBy),
+            24 => Some(PrincipalField::LegalHoldAt),
+            25 => Some(PrincipalField::CreatedAt),
+            26 => Some(PrincipalField::ModifiedAt),
+            27 => Some(PrincipalField::Metadata),
+            28 => Some(PrincipalField::Tags),
+            29 => Some(PrincipalField::SecondaryEmails),
+            30 => Some(PrincipalField::PendingEmails),
+            31 => Some(PrincipalField::PasswordHistoryLength),
+            32 => Some(PrincipalField::MinPasswordAgeDays),
+            33 => Some(PrincipalField::LockoutThreshold),
+            34 => Some(PrincipalField::LockoutWindow),
+            35 => Some(PrincipalField::LockoutDuration),
+            36 => Some(PrincipalField::LockoutPerProtocol),
+            37 => Some(PrincipalField::LockoutNotify),
+            38 => Some(PrincipalField::LastActivity),
+            39 => Some(PrincipalField::DisableAt),
+            40 => Some(PrincipalField::EnableAt),
+            41 => Some(PrincipalField::Disabled),
+            42 => Some(PrincipalField::Avatar),
+            43 => Some(PrincipalField::AvatarSelfService),
+            44 => Some(PrincipalField::TrialEndsAt),
+            45 => Some(PrincipalField::Plan),
+            46 => Some(PrincipalField::AllowedProtocols),
+            47 => Some(PrincipalField::BillingEmail),
+            48 => Some(PrincipalField::TechnicalContact),
+            49 => Some(PrincipalField::Phone),
+            50 => Some(PrincipalField::PostalAddress),
+            51 => Some(PrincipalField::TaxId),
+            52 => Some(PrincipalField::BrandLogoDarkUrl),
+            53 => Some(PrincipalField::SecurityNoticeOptOut),
+            54 => Some(PrincipalField::SenderAddress),
+            55 => Some(PrincipalField::SendRate),
             _ => None,
         }
     }
@@ -255,6 +375,41 @@ impl PrincipalField {
             PrincipalField::BrandName => "brandName",
             PrincipalField::BrandLogoUrl => "brandLogoUrl",
             PrincipalField::BrandTheme => "brandTheme",
+            PrincipalField::ExternalId => "externalId",
+            PrincipalField::LegalHold => "legalHold",
+            PrincipalField::LegalHoldBy => "legalHoldBy",
+            PrincipalField::LegalHoldAt => "legalHoldAt",
+            PrincipalField::CreatedAt => "createdAt",
+            PrincipalField::ModifiedAt => "modifiedAt",
+            PrincipalField::Metadata => "metadata",
+            PrincipalField::Tags => "tags",
+            PrincipalField::SecondaryEmails => "secondaryEmails",
+            PrincipalField::PendingEmails => "pendingEmails",
+            PrincipalField::PasswordHistoryLength => "passwordHistoryLength",
+            PrincipalField::MinPasswordAgeDays => "minPasswordAgeDays",
+            PrincipalField::LockoutThreshold => "lockoutThreshold",
+            PrincipalField::LockoutWindow => "lockoutWindow",
+            PrincipalField::LockoutDuration => "lockoutDuration",
+            PrincipalField::LockoutPerProtocol => "lockoutPerProtocol",
+            PrincipalField::LockoutNotify => "lockoutNotify",
+            PrincipalField::LastActivity => "lastActivity",
+            PrincipalField::DisableAt => "disableAt",
+            PrincipalField::EnableAt => "enableAt",
+            PrincipalField::Disabled => "disabled",
+            PrincipalField::Avatar => "avatar",
+            PrincipalField::AvatarSelfService => "avatarSelfService",
+            PrincipalField::TrialEndsAt => "trialEndsAt",
+            PrincipalField::Plan => "plan",
+            PrincipalField::AllowedProtocols => "allowedProtocols",
+            PrincipalField::BillingEmail => "billingEmail",
+            PrincipalField::TechnicalContact => "technicalContact",
+            PrincipalField::Phone => "phone",
+            PrincipalField::PostalAddress => "postalAddress",
+            PrincipalField::TaxId => "taxId",
+            PrincipalField::BrandLogoDarkUrl => "brandLogoDarkUrl",
+            PrincipalField::SecurityNoticeOptOut => "securityNoticeOptOut",
+            PrincipalField::SenderAddress => "senderAddress",
+            PrincipalField::SendRate => "sendRate",
         }
     }
 
@@ -281,6 +436,41 @@ impl PrincipalField {
             "brandName" => Some(PrincipalField::BrandName),
             "brandLogoUrl" => Some(PrincipalField::BrandLogoUrl),
             "brandTheme" => Some(PrincipalField::BrandTheme),
+            "externalId" => Some(PrincipalField::ExternalId),
+            "legalHold" => Some(PrincipalField::LegalHold),
+            "legalHoldBy" => Some(PrincipalField::LegalHoldBy),
+            "legalHoldAt" => Some(PrincipalField::LegalHoldAt),
+            "createdAt" => Some(PrincipalField::CreatedAt),
+            "modifiedAt" => Some(PrincipalField::ModifiedAt),
+            "metadata" => Some(PrincipalField::Metadata),
+            "tags" => Some(PrincipalField::Tags),
+            "secondaryEmails" => Some(PrincipalField::SecondaryEmails),
+            "pendingEmails" => Some(PrincipalField::PendingEmails),
+            "passwordHistoryLength" => Some(PrincipalField::PasswordHistoryLength),
+            "minPasswordAgeDays" => Some(PrincipalField::MinPasswordAgeDays),
+            "lockoutThreshold" => Some(PrincipalField::LockoutThreshold),
+            "lockoutWindow" => Some(PrincipalField::LockoutWindow),
+            "lockoutDuration" => Some(PrincipalField::LockoutDuration),
+            "lockoutPerProtocol" => Some(PrincipalField::LockoutPerProtocol),
+            "lockoutNotify" => Some(PrincipalField::LockoutNotify),
+            "lastActivity" => Some(PrincipalField::LastActivity),
+            "disableAt" => Some(PrincipalField::DisableAt),
+            "enableAt" => Some(PrincipalField::EnableAt),
+            "disabled" => Some(PrincipalField::Disabled),
+            "avatar" => Some(PrincipalField::Avatar),
+            "avatarSelfService" => Some(PrincipalField::AvatarSelfService),
+            "trialEndsAt" => Some(PrincipalField::TrialEndsAt),
+            "plan" => Some(PrincipalField::Plan),
+            "allowedProtocols" => Some(PrincipalField::AllowedProtocols),
+            "billingEmail" => Some(PrincipalField::BillingEmail),
+            "technicalContact" => Some(PrincipalField::TechnicalContact),
+            "phone" => Some(PrincipalField::Phone),
+            "postalAddress" => Some(PrincipalField::PostalAddress),
+            "taxId" => Some(PrincipalField::TaxId),
+            "brandLogoDarkUrl" => Some(PrincipalField::BrandLogoDarkUrl),
+            "securityNoticeOptOut" => Some(PrincipalField::SecurityNoticeOptOut),
+            "senderAddress" => Some(PrincipalField::SenderAddress),
+            "sendRate" => Some(PrincipalField::SendRate),
             _ => None,
         }
     }
@@ -296,10 +486,282 @@ where
     T: AsRef<str>,
 {
     fn is_otp_secret(&self) -> bool {
-        self.as_ref().starts_with("otpauth://")
+        self.as_ref().starts_with("otpauth://") || mfa::is_encrypted_otp_secret(self.as_ref())
     }
 
     fn is_app_secret(&self) -> bool {
         self.as_ref().starts_with("$app$")
     }
 }
+
+pub const MAX_METADATA_ENTRIES: usize = 64;
+pub const MAX_METADATA_KEY_LEN: usize = 64;
+
+/// Metadata entries are carried in a `PrincipalSet` or `PrincipalUpdate` as
+/// `key=value` strings, a key without a value deletes the entry.
+pub fn metadata_entry(entry: &str) -> (&str, Option<&str>) {
+    match entry.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (entry, None),
+    }
+}
+
+/// Keys are namespaced by convention, such as `billing.customerId`.
+pub fn is_valid_metadata_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_METADATA_KEY_LEN
+        && key
+            .bytes()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b'.' | b'-' | b'_' | b':'))
+}
+
+pub const MAX_TAGS: usize = 32;
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Tags are compared in lowercase.
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && tag.bytes().all(|ch| {
+            ch.is_ascii_lowercase()
+                || ch.is_ascii_digit()
+                || matches!(ch, b'.' | b'-' | b'_' | b':')
+        })
+}
+
+/// Contact details of a tenant, used by billing and to notify its
+/// administrators.
+pub const TENANT_CONTACT_FIELDS: [PrincipalField; 5] = [
+    PrincipalField::BillingEmail,
+    PrincipalField::TechnicalContact,
+    PrincipalField::Phone,
+    PrincipalField::PostalAddress,
+    PrincipalField::TaxId,
+];
+pub const MAX_POSTAL_ADDRESS_LEN: usize = 512;
+
+/// Normalizes a tenant contact detail, returning `None` when it is not valid
+/// for the field. An empty value clears the field.
+pub fn sanitize_contact(field: PrincipalField, value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Some(String::new());
+    }
+
+    match field {
+        PrincipalField::BillingEmail | PrincipalField::TechnicalContact => {
+            utils::sanitize_email(value)
+        }
+        PrincipalField::Phone => {
+            let digits = value.bytes().filter(|ch| ch.is_ascii_digit()).count();
+            (value.len() <= 32
+                && (5..=15).contains(&digits)
+                && value.bytes().enumerate().all(|(pos, ch)| {
+                    ch.is_ascii_digit()
+                        || matches!(ch, b' ' | b'-' | b'.' | b'(' | b')')
+                        || (ch == b'+' && pos == 0)
+                }))
+            .then(|| value.to_string())
+        }
+        PrincipalField::PostalAddress => (value.len() <= MAX_POSTAL_ADDRESS_LEN
+            && !value
+                .chars()
+                .any(|ch| ch.is_control() && ch != '\n' && ch != '\r'))
+        .then(|| value.replace("\r\n", "\n")),
+        PrincipalField::TaxId => (value.len() <= 32
+            && value.bytes().any(|ch| ch.is_ascii_alphanumeric())
+            && value
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b' ' | b'-' | b'.' | b'/')))
+        .then(|| value.to_uppercase()),
+        _ => None,
+    }
+}
+
+/// Normalizes the sender restriction of a send-only credential, returning
+/// `None` when it is not valid for the field. Sender addresses are either a
+/// single address or a domain pattern such as `@example.org`, rates use the
+/// `requests/period` syntax. An empty value clears the field.
+pub fn sanitize_sender(field: PrincipalField, value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Some(String::new());
+    }
+
+    match field {
+        PrincipalField::SenderAddress => {
+            if let Some(domain) = value.strip_prefix('@') {
+                utils::sanitize_email(&format!("postmaster@{domain}"))
+                    .map(|address| format!("@{}", address.domain_part()))
+            } else {
+                utils::sanitize_email(value)
+            }
+        }
+        PrincipalField::SendRate => utils::config::Rate::parse_value(value)
+            .ok()
+            .filter(|rate| rate.requests > 0)
+            .map(|_| value.to_lowercase()),
+        _ => None,
+    }
+}
+
+/// Scope in which Individual and Group names must be unique.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NameScope {
+    #[default]
+    Global,
+    /// Names without a domain part are unique within their tenant, and logins
+    /// are resolved through the tenant the domain of the login is assigned to.
+    Tenant,
+}
+
+impl ParseValue for NameScope {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        match value {
+            "global" => Ok(NameScope::Global),
+            "tenant" => Ok(NameScope::Tenant),
+            _ => Err(format!("Invalid name scope {:?}", value)),
+        }
+    }
+}
+
+/// Rules applied to the names of Individual and Group principals, and to the
+/// local part of their email addresses.
+#[derive(Debug, Default, Clone)]
+pub struct NamePolicy {
+    pub scope: NameScope,
+    pub reserved: AHashSet<String>,
+    /// Keep the case of the local part of email addresses, domains are always
+    /// lowercased.
+    pub case_sensitive_local_part: bool,
+}
+
+/// Role accounts from RFC 2142 and names commonly used by administrators or
+/// the system itself.
+pub const DEFAULT_RESERVED_NAMES: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "daemon",
+    "hostmaster",
+    "info",
+    "mailer-daemon",
+    "marketing",
+    "news",
+    "no-reply",
+    "noc",
+    "nobody",
+    "noreply",
+    "postmaster",
+    "root",
+    "sales",
+    "security",
+    "support",
+    "sysadmin",
+    "system",
+    "usenet",
+    "uucp",
+    "webmaster",
+    "www",
+];
+
+impl NamePolicy {
+    /// Matches are case-insensitive and use the local part of addresses.
+    pub fn is_reserved(&self, name: &str) -> bool {
+        !self.reserved.is_empty()
+            && self
+                .reserved
+                .contains(&name.try_local_part().unwrap_or(name).to_lowercase())
+    }
+
+    pub fn fold_local_part(&self) -> bool {
+        !self.case_sensitive_local_part
+    }
+
+    pub fn normalize_email(&self, email: &str) -> String {
+        normalize_email(email, self.fold_local_part())
+    }
+}
+
+/// Normalizes an email address before it is stored: the domain is lowercased
+/// and converted to its ASCII (IDNA) form, the local part is lowercased only
+/// when `fold_local_part` is set.
+pub fn normalize_email(email: &str, fold_local_part: bool) -> String {
+    let email = email.trim();
+    if let Some((local, domain)) = email.rsplit_once('@') {
+        let domain = idna::domain_to_ascii(domain)
+            .ok()
+            .filter(|domain| !domain.is_empty())
+            .unwrap_or_else(|| domain.to_lowercase());
+        if fold_local_part {
+            format!("{}@{domain}", local.to_lowercase())
+        } else {
+            format!("{local}@{domain}")
+        }
+    } else if fold_local_part {
+        email.to_lowercase()
+    } else {
+        email.to_string()
+    }
+}
+
+/// Forms under which a domain may be stored: lowercased, ASCII (IDNA) and
+/// Unicode, the latter used by domains created before normalization.
+pub fn domain_variants(domain: &str) -> Vec<String> {
+    let mut variants = vec![domain.to_lowercase()];
+    if let Ok(ascii) = idna::domain_to_ascii(domain)
+        && !ascii.is_empty()
+    {
+        let (unicode, _) = idna::domain_to_unicode(&ascii);
+        for variant in [ascii, unicode] {
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+    }
+    variants
+}
+
+/// Keys an address is looked up with, in order: the address as given, its
+/// normalized forms with and without local part folding, and the same forms
+/// with the domain variants of addresses stored before normalization.
+pub fn email_variants(email: &str) -> Vec<String> {
+    let mut variants = vec![email.to_string()];
+    if let Some((local, domain)) = email.rsplit_once('@') {
+        let local_lower = local.to_lowercase();
+        for domain in domain_variants(domain) {
+            for local in [local, local_lower.as_str()] {
+                let variant = format!("{local}@{domain}");
+                if !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+        }
+    } else {
+        let variant = email.to_lowercase();
+        if variant != email {
+            variants.push(variant);
+        }
+    }
+    variants
+}
+
+/// Tenant-scoped names are stored as the name followed by a zero byte and the
+/// tenant id, which cannot clash with a global name.
+pub fn name_key(name: &str, tenant_id: Option<u32>) -> Vec<u8> {
+    if let Some(tenant_id) = tenant_id {
+        KeySerializer::new(name.len() + U32_LEN + 1)
+            .write(name.as_bytes())
+            .write(0u8)
+            .write(tenant_id)
+            .finalize()
+    } else {
+        name.as_bytes().to_vec()
+    }
+}
+
+pub fn name_from_key(key: &[u8]) -> &[u8] {
+    key.iter()
+        .position(|&ch| ch == 0)
+        .map_or(key, |pos| &key[..pos])
+}
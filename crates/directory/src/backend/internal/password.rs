@@ -0,0 +1,234 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    PrincipalField,
+    manage::{err_password_reused, error},
+};
+use crate::{Principal, PrincipalData, core::secret::verify_secret_hash};
+use ahash::AHashSet;
+use store::write::now;
+
+/// Maximum number of passwords, including the current one, a tenant can
+/// prevent the reuse of.
+pub const MAX_PASSWORD_HISTORY: u32 = 24;
+
+/// Maximum minimum password age a tenant can configure.
+pub const MAX_MIN_PASSWORD_AGE_DAYS: u32 = 365;
+
+/// Character classes new passwords can be required to contain.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+/// Requirements of new passwords and how long they remain valid, as
+/// resolved from the global settings and the overrides of a tenant.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordPolicy {
+    pub min_length: u32,
+    pub required_classes: Vec<PasswordClass>,
+    /// Lowercased passwords that are rejected regardless of their strength.
+    #[serde(skip)]
+    pub deny_list: AHashSet<String>,
+    /// Passwords older than this must be changed before logging in again,
+    /// zero disables the expiration.
+    pub max_age_days: u32,
+    pub allow_app_passwords: bool,
+}
+
+impl PasswordClass {
+    pub const ALL: [PasswordClass; 4] = [
+        PasswordClass::Lowercase,
+        PasswordClass::Uppercase,
+        PasswordClass::Digit,
+        PasswordClass::Symbol,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        PasswordClass::ALL
+            .into_iter()
+            .find(|class| class.as_str() == value)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PasswordClass::Lowercase => "lowercase",
+            PasswordClass::Uppercase => "uppercase",
+            PasswordClass::Digit => "digit",
+            PasswordClass::Symbol => "symbol",
+        }
+    }
+
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            PasswordClass::Lowercase => ch.is_lowercase(),
+            PasswordClass::Uppercase => ch.is_uppercase(),
+            PasswordClass::Digit => ch.is_numeric(),
+            PasswordClass::Symbol => !ch.is_alphanumeric() && !ch.is_whitespace(),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            PasswordClass::Lowercase => "a lowercase letter",
+            PasswordClass::Uppercase => "an uppercase letter",
+            PasswordClass::Digit => "a digit",
+            PasswordClass::Symbol => "a symbol",
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 0,
+            required_classes: Vec::new(),
+            deny_list: AHashSet::new(),
+            max_age_days: 0,
+            allow_app_passwords: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Checks a plain text password, the error lists every requirement the
+    /// password fails to meet.
+    pub fn check(&self, password: &str) -> Result<(), String> {
+        if self.deny_list.contains(&password.to_lowercase()) {
+            return Err("The password is too common".to_string());
+        }
+
+        let mut missing = Vec::new();
+        if password.chars().count() < self.min_length as usize {
+            missing.push(format!("at least {} characters", self.min_length));
+        }
+        for class in &self.required_classes {
+            if !password.chars().any(|ch| class.matches(ch)) {
+                missing.push(class.description().to_string());
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("The password must contain {}", missing.join(", ")))
+        }
+    }
+
+    pub fn is_expired(&self, changed_at: u64, now: u64) -> bool {
+        self.max_age_days > 0 && now >= changed_at + (self.max_age_days as u64 * 86400)
+    }
+}
+
+/// Sets a password policy setting of a tenant, zero disables it.
+pub(super) fn set_password_policy(
+    principal: &mut Principal,
+    field: PrincipalField,
+    value: u64,
+) -> trc::Result<()> {
+    let max = match field {
+        PrincipalField::PasswordHistoryLength => MAX_PASSWORD_HISTORY,
+        _ => MAX_MIN_PASSWORD_AGE_DAYS,
+    };
+    if value > max as u64 {
+        return Err(error(
+            "Invalid parameter",
+            format!("{} must be between 0 and {max}", field.as_str()).into(),
+        ));
+    }
+
+    principal.data.retain(|v| match field {
+        PrincipalField::PasswordHistoryLength => {
+            !matches!(v, PrincipalData::PasswordHistoryLength(_))
+        }
+        _ => !matches!(v, PrincipalData::MinPasswordAgeDays(_)),
+    });
+    if value > 0 {
+        principal.data.push(match field {
+            PrincipalField::PasswordHistoryLength => {
+                PrincipalData::PasswordHistoryLength(value as u32)
+            }
+            _ => PrincipalData::MinPasswordAgeDays(value as u32),
+        });
+    }
+
+    Ok(())
+}
+
+/// Applies the password policy of the tenant to a password change. The
+/// previous password is moved to the history, which is searched for the new
+/// password regardless of the scheme each entry was hashed with. The minimum
+/// password age only applies to changes made by the account owner.
+pub(super) async fn rotate_password(
+    principal: &mut Principal,
+    prev_password: Option<String>,
+    policy: Option<&Principal>,
+    self_service: bool,
+) -> trc::Result<()> {
+    let Some(new_password) = principal.data.iter().find_map(|v| match v {
+        PrincipalData::Password(secret) => Some(secret.clone()),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+    if prev_password.as_ref() == Some(&new_password) {
+        return Ok(());
+    }
+    let history_length = policy.map_or(0, |p| p.password_history_length());
+    let min_age_days = policy.map_or(0, |p| p.min_password_age_days());
+    let now = now();
+
+    if self_service
+        && min_age_days > 0
+        && let Some(changed_at) = principal.password_changed_at()
+        && now < changed_at + (min_age_days as u64 * 86400)
+    {
+        return Err(error(
+            "Password changed too recently",
+            format!("Passwords can only be changed once every {min_age_days} days").into(),
+        ));
+    }
+
+    if history_length > 0 {
+        for secret in prev_password.iter().chain(principal.password_history()) {
+            if *secret == new_password
+                || verify_secret_hash(secret, &new_password)
+                    .await
+                    .unwrap_or(false)
+            {
+                return Err(err_password_reused(history_length));
+            }
+        }
+    }
+
+    // Keep the newest entries, the current password takes one of the slots
+    let mut history = prev_password
+        .into_iter()
+        .chain(principal.password_history().iter().cloned())
+        .collect::<Vec<_>>();
+    history.truncate(history_length.saturating_sub(1) as usize);
+
+    principal.data.retain(|v| {
+        !matches!(
+            v,
+            PrincipalData::PasswordHistory(_) | PrincipalData::PasswordChangedAt(_)
+        )
+    });
+    if !history.is_empty() {
+        principal.data.push(PrincipalData::PasswordHistory(history));
+    }
+    principal.data.push(PrincipalData::PasswordChangedAt(now));
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::secondary::update_principal_data;
+use crate::PrincipalData;
+use pwhash::sha512_crypt;
+use store::{
+    Store,
+    rand::{Rng, rng},
+};
+
+/// Number of codes generated at once.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Characters in a code, excluding the separator.
+pub const RECOVERY_CODE_LEN: usize = 10;
+
+// Characters that can't be mistaken for one another when written down
+const RECOVERY_CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// Recovery codes replace a second factor once when the device holding it
+/// is lost. Only their hash is stored and generating a new set replaces
+/// any previous codes.
+#[allow(async_fn_in_trait)]
+pub trait RecoveryCodes: Sync + Send {
+    /// Generates a set of codes and returns them, they can't be recovered
+    /// later.
+    async fn generate_recovery_codes(&self, principal_id: u32) -> trc::Result<Vec<String>>;
+    async fn remove_recovery_codes(&self, principal_id: u32) -> trc::Result<bool>;
+    /// Removes a verified code, returns false when it was already used by a
+    /// concurrent login.
+    async fn consume_recovery_code(&self, principal_id: u32, hash: &str) -> trc::Result<bool>;
+}
+
+impl RecoveryCodes for Store {
+    async fn generate_recovery_codes(&self, principal_id: u32) -> trc::Result<Vec<String>> {
+        let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+        let mut hashes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+        let mut rng = rng();
+        while codes.len() < RECOVERY_CODE_COUNT {
+            let code = (0..RECOVERY_CODE_LEN)
+                .map(|_| {
+                    char::from(
+                        RECOVERY_CODE_ALPHABET[rng.random_range(0..RECOVERY_CODE_ALPHABET.len())],
+                    )
+                })
+                .collect::<String>();
+            if !codes.contains(&code) {
+                hashes.push(sha512_crypt::hash(&code).map_err(|err| {
+                    trc::AuthEvent::Error
+                        .reason(err)
+                        .details("Failed to hash recovery code")
+                })?);
+                codes.push(code);
+            }
+        }
+
+        update_principal_data(self, principal_id, |principal| {
+            principal
+                .data
+                .retain(|item| !matches!(item, PrincipalData::RecoveryCode(_)));
+            principal
+                .data
+                .extend(hashes.into_iter().map(PrincipalData::RecoveryCode));
+            Ok(true)
+        })
+        .await?;
+
+        Ok(codes
+            .into_iter()
+            .map(|code| format!("{}-{}", &code[..5], &code[5..]))
+            .collect())
+    }
+
+    async fn remove_recovery_codes(&self, principal_id: u32) -> trc::Result<bool> {
+        update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal
+                .data
+                .retain(|item| !matches!(item, PrincipalData::RecoveryCode(_)));
+            Ok(principal.data.len() != len)
+        })
+        .await
+    }
+
+    async fn consume_recovery_code(&self, principal_id: u32, hash: &str) -> trc::Result<bool> {
+        // The principal is written with a compare-and-set, so only one of
+        // several logins racing with the same code removes it
+        match update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal
+                .data
+                .retain(|item| !matches!(item, PrincipalData::RecoveryCode(code) if code == hash));
+            Ok(principal.data.len() != len)
+        })
+        .await
+        {
+            Ok(result) => Ok(result),
+            Err(err) if err.matches(trc::EventType::Store(trc::StoreEvent::AssertValueFailed)) => {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Normalizes a code as typed by a user, ignoring case and separators.
+pub fn normalize_recovery_code(code: &str) -> Option<String> {
+    let code = code
+        .chars()
+        .filter(|ch| !matches!(ch, '-' | ' '))
+        .map(|ch| ch.to_ascii_lowercase())
+        .collect::<String>();
+
+    (code.len() == RECOVERY_CODE_LEN && code.bytes().all(|b| RECOVERY_CODE_ALPHABET.contains(&b)))
+        .then_some(code)
+}
@@ -0,0 +1,117 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::PrincipalField;
+use crate::{Permission, Principal, PrincipalData};
+use store::{InMemoryStore, Store, dispatch::lookup::KeyValue};
+
+/// In-memory key prefix of the scheduled disables already notified.
+pub const KV_DISABLE_NOTIFIED: u8 = 31;
+
+/// Scheduled changes to whether an account can log in. Timestamps are UTC
+/// seconds and an event takes effect from the instant it is due. When both
+/// events are due the latest one wins, and a disable wins over an enable
+/// due at the same instant.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccountSchedule {
+    pub disable_at: Option<u64>,
+    pub enable_at: Option<u64>,
+}
+
+impl AccountSchedule {
+    /// Returns whether the due events leave the account disabled, or `None`
+    /// when no event is due yet.
+    pub fn is_disabled_at(&self, now: u64) -> Option<bool> {
+        match (
+            self.disable_at.filter(|at| *at <= now),
+            self.enable_at.filter(|at| *at <= now),
+        ) {
+            (Some(disable_at), Some(enable_at)) => Some(disable_at >= enable_at),
+            (Some(_), None) => Some(true),
+            (None, Some(_)) => Some(false),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns when the next event is due.
+    pub fn next_change(&self, now: u64) -> Option<u64> {
+        [self.disable_at, self.enable_at]
+            .into_iter()
+            .flatten()
+            .filter(|at| *at > now)
+            .min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.disable_at.is_none() && self.enable_at.is_none()
+    }
+}
+
+/// Tracks the scheduled disables that administrators were notified about,
+/// so that each one is only announced once.
+#[allow(async_fn_in_trait)]
+pub trait ScheduleNotification: Sync + Send {
+    /// Returns true if the disable was not notified before.
+    async fn mark_disable_notified(&self, principal_id: u32, disable_at: u64) -> trc::Result<bool>;
+}
+
+impl ScheduleNotification for Store {
+    async fn mark_disable_notified(&self, principal_id: u32, disable_at: u64) -> trc::Result<bool> {
+        let kv = InMemoryStore::Store(self.clone());
+        let key = notified_key(principal_id);
+        let value = disable_at.to_string();
+        if kv.key_get::<String>(key.clone()).await?.as_ref() == Some(&value) {
+            Ok(false)
+        } else {
+            kv.key_set(KeyValue::new(key, value.into_bytes())).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// Sets or clears, with a zero timestamp, a scheduled event of an account.
+pub(super) fn set_schedule(principal: &mut Principal, field: PrincipalField, value: u64) {
+    principal.data.retain(|v| match field {
+        PrincipalField::DisableAt => !matches!(v, PrincipalData::DisableAt(_)),
+        _ => !matches!(v, PrincipalData::EnableAt(_)),
+    });
+    if value > 0 {
+        principal.data.push(match field {
+            PrincipalField::DisableAt => PrincipalData::DisableAt(value),
+            _ => PrincipalData::EnableAt(value),
+        });
+    }
+}
+
+/// Applies the events that are due to the disabled permissions of the
+/// account and removes them, returns true when any event was due. Applying
+/// them makes a disable scheduled in the past equivalent to disabling the
+/// account right away.
+pub fn apply_schedule(principal: &mut Principal, now: u64) -> bool {
+    let schedule = principal.schedule();
+    let Some(disabled) = schedule.is_disabled_at(now) else {
+        return false;
+    };
+
+    if disabled {
+        principal.add_permission(Permission::Authenticate, false);
+    } else {
+        principal.remove_permission(Permission::Authenticate, false);
+    }
+    principal.data.retain(|v| match v {
+        PrincipalData::DisableAt(at) | PrincipalData::EnableAt(at) => *at > now,
+        _ => true,
+    });
+
+    true
+}
+
+pub(super) fn notified_key(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_DISABLE_NOTIFIED);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
@@ -0,0 +1,231 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    PrincipalField,
+    manage::{err_exists, error, not_found},
+};
+use crate::{Principal, PrincipalData};
+use store::{
+    Store, ValueKey,
+    write::{AlignedBytes, Archive, Archiver, BatchBuilder, DirectoryClass, ValueClass, now},
+};
+use trc::AddContext;
+use types::collection::Collection;
+use utils::sanitize_email;
+
+/// Maximum number of secondary addresses, verified or pending, per principal.
+pub const MAX_SECONDARY_EMAILS: usize = 10;
+
+/// Secondary addresses are confirmed by their owner before they are used.
+/// Pending addresses carry the nonce of the last token sent to them, so that
+/// resending a confirmation invalidates the previous token, and a token can
+/// only be used once.
+#[allow(async_fn_in_trait)]
+pub trait SecondaryEmails: Sync + Send {
+    /// Adds an address pending confirmation and returns its sanitized form.
+    async fn add_pending_email(
+        &self,
+        principal_id: u32,
+        address: &str,
+        nonce: u64,
+        expires: u64,
+    ) -> trc::Result<String>;
+    /// Replaces the token of an address pending confirmation.
+    async fn renew_pending_email(
+        &self,
+        principal_id: u32,
+        address: &str,
+        nonce: u64,
+        expires: u64,
+    ) -> trc::Result<()>;
+    /// Marks a pending address as verified, returns false when the address is
+    /// not pending, the token was replaced or it has expired.
+    async fn verify_pending_email(
+        &self,
+        principal_id: u32,
+        address: &str,
+        nonce: u64,
+    ) -> trc::Result<bool>;
+    /// Cancels a pending confirmation or removes a verified address.
+    async fn remove_secondary_email(&self, principal_id: u32, address: &str) -> trc::Result<bool>;
+}
+
+impl SecondaryEmails for Store {
+    async fn add_pending_email(
+        &self,
+        principal_id: u32,
+        address: &str,
+        nonce: u64,
+        expires: u64,
+    ) -> trc::Result<String> {
+        let address = sanitize_email(address).ok_or_else(|| {
+            error(
+                "Invalid email address",
+                format!("Invalid value {address:?} for secondaryEmails").into(),
+            )
+        })?;
+        update_principal_data(self, principal_id, |principal| {
+            if principal
+                .email_addresses()
+                .chain(principal.secondary_emails())
+                .chain(principal.pending_emails().map(|(pending, _, _)| pending))
+                .any(|email| email == address)
+            {
+                return Err(err_exists(PrincipalField::SecondaryEmails, address.clone()));
+            } else if principal.secondary_emails().count() + principal.pending_emails().count()
+                >= MAX_SECONDARY_EMAILS
+            {
+                return Err(error(
+                    "Too many secondary addresses",
+                    format!("A maximum of {MAX_SECONDARY_EMAILS} secondary addresses is allowed")
+                        .into(),
+                ));
+            }
+
+            principal.data.push(PrincipalData::PendingEmail {
+                address: address.clone(),
+                nonce,
+                expires,
+            });
+            Ok(true)
+        })
+        .await?;
+
+        Ok(address)
+    }
+
+    async fn renew_pending_email(
+        &self,
+        principal_id: u32,
+        address: &str,
+        nonce: u64,
+        expires: u64,
+    ) -> trc::Result<()> {
+        update_principal_data(self, principal_id, |principal| {
+            principal
+                .data
+                .iter_mut()
+                .find_map(|item| match item {
+                    PrincipalData::PendingEmail {
+                        address: pending,
+                        nonce: pending_nonce,
+                        expires: pending_expires,
+                    } if pending == address => {
+                        *pending_nonce = nonce;
+                        *pending_expires = expires;
+                        Some(true)
+                    }
+                    _ => None,
+                })
+                .ok_or_else(|| not_found(address.to_string()))
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn verify_pending_email(
+        &self,
+        principal_id: u32,
+        address: &str,
+        nonce: u64,
+    ) -> trc::Result<bool> {
+        update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal.data.retain(|item| {
+                !matches!(item, PrincipalData::PendingEmail {
+                    address: pending,
+                    nonce: pending_nonce,
+                    ..
+                } if pending == address && *pending_nonce == nonce)
+            });
+            if principal.data.len() != len {
+                principal
+                    .data
+                    .push(PrincipalData::SecondaryEmail(address.to_string()));
+                Ok(true)
+            } else {
+                Ok(false)
+            }
+        })
+        .await
+    }
+
+    async fn remove_secondary_email(&self, principal_id: u32, address: &str) -> trc::Result<bool> {
+        update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal.data.retain(|item| match item {
+                PrincipalData::PendingEmail {
+                    address: pending, ..
+                } => pending != address,
+                PrincipalData::SecondaryEmail(secondary) => secondary != address,
+                _ => true,
+            });
+            Ok(principal.data.len() != len)
+        })
+        .await
+    }
+}
+
+/// Applies a change to the data of a principal, which is only written back
+/// when it was modified or had expired confirmations.
+pub(super) async fn update_principal_data(
+    store: &Store,
+    principal_id: u32,
+    f: impl FnOnce(&mut Principal) -> trc::Result<bool>,
+) -> trc::Result<bool> {
+    let principal_ = store
+        .get_value::<Archive<AlignedBytes>>(ValueKey::from(ValueClass::Directory(
+            DirectoryClass::Principal(principal_id),
+        )))
+        .await
+        .caused_by(trc::location!())?
+        .ok_or_else(|| not_found(principal_id))?;
+    let prev_principal = principal_
+        .to_unarchived::<Principal>()
+        .caused_by(trc::location!())?;
+    let mut principal = prev_principal
+        .deserialize::<Principal>()
+        .caused_by(trc::location!())?;
+
+    // Stale confirmations are removed on every change
+    let now = now();
+    let len = principal.data.len();
+    principal.data.retain(
+        |item| !matches!(item, PrincipalData::PendingEmail { expires, .. } if *expires <= now),
+    );
+    let has_expired = principal.data.len() != len;
+
+    let has_changes = f(&mut principal)?;
+    if has_changes || has_expired {
+        principal
+            .data
+            .retain(|v| !matches!(v, PrincipalData::ModifiedAt(_)));
+        principal.data.push(PrincipalData::ModifiedAt(now));
+        principal.sort();
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .assert_value(
+                ValueClass::Directory(DirectoryClass::Principal(principal_id)),
+                prev_principal,
+            )
+            .set(
+                ValueClass::Directory(DirectoryClass::Principal(principal_id)),
+                Archiver::new(principal)
+                    .serialize()
+                    .caused_by(trc::location!())?,
+            );
+        store
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())?;
+    }
+
+    Ok(has_changes)
+}
@@ -0,0 +1,123 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{Principal, PrincipalData};
+use std::time::Duration;
+use store::{InMemoryStore, Store, dispatch::lookup::KeyValue};
+use utils::config::Rate;
+
+/// In-memory key prefix of the countries accounts logged in from.
+pub const KV_LOGIN_COUNTRIES: u8 = 35;
+
+/// In-memory key prefix of the security notices recently sent to accounts.
+pub const KV_SECURITY_NOTICE: u8 = 36;
+
+/// Countries an account did not log in from for this long are forgotten.
+pub const LOGIN_COUNTRY_EXPIRY: u64 = 365 * 86400;
+
+/// State used to decide whether security notices are sent to an account.
+#[allow(async_fn_in_trait)]
+pub trait SecurityNotices: Sync + Send {
+    /// Records the country of a login and returns true when the account
+    /// logged in before, but never from this country.
+    async fn record_login_country(&self, principal_id: u32, country: &str) -> trc::Result<bool>;
+    /// Returns false when the same notice was already sent to the account
+    /// within the period, so that a burst of events results in one message.
+    async fn mark_security_notice(
+        &self,
+        principal_id: u32,
+        notice: u8,
+        period: u64,
+    ) -> trc::Result<bool>;
+}
+
+impl SecurityNotices for Store {
+    async fn record_login_country(&self, principal_id: u32, country: &str) -> trc::Result<bool> {
+        let country = country.to_ascii_lowercase();
+        let kv = InMemoryStore::Store(self.clone());
+        let key = countries_key(principal_id);
+        let countries = kv.key_get::<String>(key.clone()).await?.unwrap_or_default();
+        if countries.split(',').any(|known| known == country) {
+            return Ok(false);
+        }
+
+        let is_new = !countries.is_empty();
+        let countries = if is_new {
+            format!("{countries},{country}")
+        } else {
+            country
+        };
+        kv.key_set(KeyValue::new(key, countries.into_bytes()).expires(LOGIN_COUNTRY_EXPIRY))
+            .await?;
+
+        Ok(is_new)
+    }
+
+    async fn mark_security_notice(
+        &self,
+        principal_id: u32,
+        notice: u8,
+        period: u64,
+    ) -> trc::Result<bool> {
+        if period == 0 {
+            return Ok(true);
+        }
+
+        let mut key = notice_prefix(principal_id);
+        key.push(notice);
+        InMemoryStore::Store(self.clone())
+            .is_rate_allowed(
+                KV_SECURITY_NOTICE,
+                &key[1..],
+                &Rate {
+                    requests: 1,
+                    period: Duration::from_secs(period),
+                },
+                false,
+            )
+            .await
+            .map(|retry_at| retry_at.is_none())
+    }
+}
+
+impl Principal {
+    /// Returns whether the account opted out of security notices, as
+    /// service accounts usually do.
+    pub fn security_notice_opt_out(&self) -> bool {
+        self.data
+            .iter()
+            .any(|item| matches!(item, PrincipalData::SecurityNoticeOptOut(true)))
+    }
+}
+
+pub(super) fn set_security_notice_opt_out(principal: &mut Principal, value: u64) {
+    principal
+        .data
+        .retain(|v| !matches!(v, PrincipalData::SecurityNoticeOptOut(_)));
+    if value != 0 {
+        principal
+            .data
+            .push(PrincipalData::SecurityNoticeOptOut(true));
+    }
+}
+
+pub(super) fn security_prefixes(principal_id: u32) -> [Vec<u8>; 2] {
+    [countries_key(principal_id), notice_prefix(principal_id)]
+}
+
+fn countries_key(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_LOGIN_COUNTRIES);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
+
+fn notice_prefix(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(6);
+    key.push(KV_SECURITY_NOTICE);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
@@ -0,0 +1,57 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::secondary::update_principal_data;
+use crate::PrincipalData;
+use pwhash::sha512_crypt;
+use store::{
+    Store,
+    rand::{Rng, distr::Alphanumeric, rng},
+    write::now,
+};
+
+/// Length of the secrets generated for send-only credentials.
+pub const SENDER_SECRET_LEN: usize = 32;
+
+/// Send-only credentials are Individuals restricted to SMTP submission from
+/// a fixed address or domain. Their secret is generated by the server and
+/// only its hash is stored.
+#[allow(async_fn_in_trait)]
+pub trait SenderCredentials: Sync + Send {
+    /// Replaces the secret of a send-only credential and returns the new one,
+    /// it can't be recovered later.
+    async fn rotate_sender_secret(&self, principal_id: u32) -> trc::Result<String>;
+}
+
+impl SenderCredentials for Store {
+    async fn rotate_sender_secret(&self, principal_id: u32) -> trc::Result<String> {
+        let secret = rng()
+            .sample_iter(Alphanumeric)
+            .take(SENDER_SECRET_LEN)
+            .map(char::from)
+            .collect::<String>();
+        let hash = sha512_crypt::hash(&secret).map_err(|err| {
+            trc::AuthEvent::Error
+                .reason(err)
+                .details("Failed to hash sender secret")
+        })?;
+
+        update_principal_data(self, principal_id, |principal| {
+            principal.data.retain(|item| {
+                !matches!(
+                    item,
+                    PrincipalData::Password(_) | PrincipalData::PasswordChangedAt(_)
+                )
+            });
+            principal.data.push(PrincipalData::Password(hash));
+            principal.data.push(PrincipalData::PasswordChangedAt(now()));
+            Ok(true)
+        })
+        .await?;
+
+        Ok(secret)
+    }
+}
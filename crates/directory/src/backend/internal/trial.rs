@@ -0,0 +1,117 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{Principal, PrincipalData};
+use store::{InMemoryStore, Store, dispatch::lookup::KeyValue};
+
+/// In-memory key prefix of the trial expiry notices already sent.
+pub const KV_TRIAL_NOTIFIED: u8 = 15;
+
+/// State of the trial period of a tenant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrialStatus {
+    /// The tenant was not provisioned as a trial, or it was converted.
+    None,
+    Active,
+    Expired,
+}
+
+impl TrialStatus {
+    pub fn at(ends_at: Option<u64>, now: u64) -> Self {
+        match ends_at {
+            Some(ends_at) if ends_at > now => TrialStatus::Active,
+            Some(_) => TrialStatus::Expired,
+            None => TrialStatus::None,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(TrialStatus::None),
+            "active" => Some(TrialStatus::Active),
+            "expired" => Some(TrialStatus::Expired),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrialStatus::None => "none",
+            TrialStatus::Active => "active",
+            TrialStatus::Expired => "expired",
+        }
+    }
+}
+
+impl Principal {
+    pub fn trial_ends_at(&self) -> Option<u64> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::TrialEndsAt(at) = item {
+                Some(*at)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn trial_status(&self, now: u64) -> TrialStatus {
+        TrialStatus::at(self.trial_ends_at(), now)
+    }
+}
+
+/// Days left until a trial ends, a partial day counts as a full one.
+pub fn trial_days_remaining(ends_at: u64, now: u64) -> u64 {
+    ends_at.saturating_sub(now).div_ceil(86400)
+}
+
+/// Tracks the expiry notices sent for each trial, so that each notice is
+/// only sent once. Extending a trial starts its notices over.
+#[allow(async_fn_in_trait)]
+pub trait TrialNotification: Sync + Send {
+    /// Returns true if the notice was not sent before.
+    async fn mark_trial_notified(
+        &self,
+        tenant_id: u32,
+        ends_at: u64,
+        notice: u64,
+    ) -> trc::Result<bool>;
+}
+
+impl TrialNotification for Store {
+    async fn mark_trial_notified(
+        &self,
+        tenant_id: u32,
+        ends_at: u64,
+        notice: u64,
+    ) -> trc::Result<bool> {
+        let kv = InMemoryStore::Store(self.clone());
+        let key = trial_notified_key(tenant_id);
+        let value = format!("{ends_at}:{notice}");
+        if kv.key_get::<String>(key.clone()).await?.as_ref() == Some(&value) {
+            Ok(false)
+        } else {
+            kv.key_set(KeyValue::new(key, value.into_bytes())).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// Sets or clears, with a zero timestamp, the end of a tenant's trial.
+pub(super) fn set_trial(principal: &mut Principal, value: u64) {
+    principal
+        .data
+        .retain(|v| !matches!(v, PrincipalData::TrialEndsAt(_)));
+    if value > 0 {
+        principal.data.push(PrincipalData::TrialEndsAt(value));
+    }
+}
+
+pub(super) fn trial_notified_key(tenant_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_TRIAL_NOTIFIED);
+    key.extend_from_slice(&tenant_id.to_be_bytes());
+    key
+}
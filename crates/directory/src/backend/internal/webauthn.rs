@@ -0,0 +1,279 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    PrincipalField,
+    manage::{ManageDirectory, err_exists, error, not_found},
+    secondary::update_principal_data,
+};
+use crate::PrincipalData;
+use store::{InMemoryStore, Store, dispatch::lookup::KeyValue, write::now};
+
+/// Maximum number of WebAuthn credentials per principal.
+pub const MAX_WEBAUTHN_CREDENTIALS: usize = 20;
+
+/// In-memory key prefix of WebAuthn signature counters and last use.
+pub const KV_WEBAUTHN_COUNTER: u8 = 34;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebAuthnCredential {
+    pub id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub rp_id: String,
+    pub nickname: String,
+    pub created_at: u64,
+    pub resident: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebAuthnCredentialEntry {
+    pub credential: WebAuthnCredential,
+    pub sign_count: u32,
+    pub last_used: Option<u64>,
+}
+
+/// Passkeys and security keys registered by a principal. Signature counters
+/// are tracked outside of the principal so that logins don't modify it.
+#[allow(async_fn_in_trait)]
+pub trait WebAuthnCredentials: Sync + Send {
+    async fn add_webauthn_credential(
+        &self,
+        principal_id: u32,
+        credential: WebAuthnCredential,
+        sign_count: u32,
+    ) -> trc::Result<()>;
+    async fn list_webauthn_credentials(
+        &self,
+        principal_id: u32,
+    ) -> trc::Result<Vec<WebAuthnCredentialEntry>>;
+    async fn rename_webauthn_credential(
+        &self,
+        principal_id: u32,
+        id: &[u8],
+        nickname: &str,
+    ) -> trc::Result<bool>;
+    async fn remove_webauthn_credential(&self, principal_id: u32, id: &[u8]) -> trc::Result<bool>;
+    /// Records a login with a credential, returns false when the signature
+    /// counter did not increase, which indicates a cloned authenticator.
+    async fn webauthn_credential_used(
+        &self,
+        principal_id: u32,
+        id: &[u8],
+        sign_count: u32,
+    ) -> trc::Result<bool>;
+}
+
+impl WebAuthnCredentials for Store {
+    async fn add_webauthn_credential(
+        &self,
+        principal_id: u32,
+        credential: WebAuthnCredential,
+        sign_count: u32,
+    ) -> trc::Result<()> {
+        let nickname = validate_nickname(&credential.nickname)?;
+
+        update_principal_data(self, principal_id, |principal| {
+            let mut count = 0;
+            for item in &principal.data {
+                match item {
+                    PrincipalData::WebAuthnCredential { id, .. } if *id == credential.id => {
+                        return Err(err_exists(PrincipalField::Secrets, "WebAuthn credential"));
+                    }
+                    PrincipalData::WebAuthnCredential {
+                        nickname: existing, ..
+                    } if existing == nickname => {
+                        return Err(err_exists(PrincipalField::Secrets, nickname.to_string()));
+                    }
+                    PrincipalData::WebAuthnCredential { .. } => {
+                        count += 1;
+                    }
+                    _ => {}
+                }
+            }
+            if count >= MAX_WEBAUTHN_CREDENTIALS {
+                return Err(error(
+                    "Too many WebAuthn credentials",
+                    format!("A maximum of {MAX_WEBAUTHN_CREDENTIALS} credentials is allowed")
+                        .into(),
+                ));
+            }
+
+            principal.data.push(PrincipalData::WebAuthnCredential {
+                id: credential.id.clone(),
+                public_key: credential.public_key,
+                rp_id: credential.rp_id,
+                nickname: nickname.to_string(),
+                created_at: credential.created_at,
+                resident: credential.resident,
+            });
+            Ok(true)
+        })
+        .await?;
+
+        InMemoryStore::Store(self.clone())
+            .key_set(KeyValue::new(
+                counter_key(principal_id, &credential.id),
+                format!("{sign_count}:").into_bytes(),
+            ))
+            .await
+    }
+
+    async fn list_webauthn_credentials(
+        &self,
+        principal_id: u32,
+    ) -> trc::Result<Vec<WebAuthnCredentialEntry>> {
+        let principal = self
+            .get_principal(principal_id)
+            .await?
+            .ok_or_else(|| not_found(principal_id))?;
+        let kv = InMemoryStore::Store(self.clone());
+        let mut entries = Vec::new();
+
+        for item in principal.data {
+            if let PrincipalData::WebAuthnCredential {
+                id,
+                public_key,
+                rp_id,
+                nickname,
+                created_at,
+                resident,
+            } = item
+            {
+                let (sign_count, last_used) = kv
+                    .key_get::<String>(counter_key(principal_id, &id))
+                    .await?
+                    .map(|value| parse_counter(&value))
+                    .unwrap_or_default();
+
+                entries.push(WebAuthnCredentialEntry {
+                    credential: WebAuthnCredential {
+                        id,
+                        public_key,
+                        rp_id,
+                        nickname,
+                        created_at,
+                        resident,
+                    },
+                    sign_count,
+                    last_used,
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn rename_webauthn_credential(
+        &self,
+        principal_id: u32,
+        id: &[u8],
+        nickname: &str,
+    ) -> trc::Result<bool> {
+        let nickname = validate_nickname(nickname)?;
+
+        update_principal_data(self, principal_id, |principal| {
+            if principal.data.iter().any(|item| {
+                matches!(item, PrincipalData::WebAuthnCredential { id: other_id, nickname: other, .. }
+                    if other == nickname && other_id != id)
+            }) {
+                return Err(err_exists(PrincipalField::Secrets, nickname.to_string()));
+            }
+
+            for item in &mut principal.data {
+                if let PrincipalData::WebAuthnCredential {
+                    id: item_id,
+                    nickname: item_nickname,
+                    ..
+                } = item
+                    && item_id == id
+                {
+                    *item_nickname = nickname.to_string();
+                    return Ok(true);
+                }
+            }
+
+            Ok(false)
+        })
+        .await
+    }
+
+    async fn remove_webauthn_credential(&self, principal_id: u32, id: &[u8]) -> trc::Result<bool> {
+        let result = update_principal_data(self, principal_id, |principal| {
+            let len = principal.data.len();
+            principal.data.retain(|item| {
+                !matches!(item, PrincipalData::WebAuthnCredential { id: item_id, .. } if item_id == id)
+            });
+            Ok(principal.data.len() != len)
+        })
+        .await?;
+
+        if result {
+            InMemoryStore::Store(self.clone())
+                .key_delete(counter_key(principal_id, id))
+                .await?;
+        }
+
+        Ok(result)
+    }
+
+    async fn webauthn_credential_used(
+        &self,
+        principal_id: u32,
+        id: &[u8],
+        sign_count: u32,
+    ) -> trc::Result<bool> {
+        let kv = InMemoryStore::Store(self.clone());
+        let key = counter_key(principal_id, id);
+        let (stored_count, _) = kv
+            .key_get::<String>(key.clone())
+            .await?
+            .map(|value| parse_counter(&value))
+            .unwrap_or_default();
+
+        // Authenticators that don't implement a counter always report zero
+        if (sign_count != 0 || stored_count != 0) && sign_count <= stored_count {
+            return Ok(false);
+        }
+
+        kv.key_set(KeyValue::new(
+            key,
+            format!("{sign_count}:{}", now()).into_bytes(),
+        ))
+        .await?;
+
+        Ok(true)
+    }
+}
+
+fn validate_nickname(nickname: &str) -> trc::Result<&str> {
+    let nickname = nickname.trim();
+    if !nickname.is_empty() && nickname.len() <= 255 && !nickname.contains(char::is_control) {
+        Ok(nickname)
+    } else {
+        Err(error(
+            "Invalid nickname",
+            "Nicknames must be between 1 and 255 characters long".into(),
+        ))
+    }
+}
+
+fn parse_counter(value: &str) -> (u32, Option<u64>) {
+    let (count, last_used) = value.split_once(':').unwrap_or((value, ""));
+    (count.parse().unwrap_or_default(), last_used.parse().ok())
+}
+
+pub(super) fn counter_prefix(principal_id: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(5);
+    key.push(KV_WEBAUTHN_COUNTER);
+    key.extend_from_slice(&principal_id.to_be_bytes());
+    key
+}
+
+fn counter_key(principal_id: u32, id: &[u8]) -> Vec<u8> {
+    let mut key = counter_prefix(principal_id);
+    key.extend_from_slice(id);
+    key
+}
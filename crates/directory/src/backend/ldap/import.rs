@@ -0,0 +1,467 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{net::SocketAddr, time::Duration};
+
+use ldap3::{
+    Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, StdStream,
+    adapters::{Adapter, EntriesOnly, PagedResults},
+};
+use tokio::net::TcpStream;
+use utils::is_public_ip;
+
+use crate::{IntoError, Type, backend::internal::manage};
+
+const PAGE_SIZE: i32 = 500;
+const CONN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connection and mapping parameters for a one-off import of the users and
+/// groups of an external LDAP directory.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdapImportSource {
+    pub url: String,
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_secret: Option<String>,
+    pub base_dn: String,
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    #[serde(default = "default_group_filter")]
+    pub group_filter: String,
+    #[serde(default)]
+    pub attributes: LdapImportAttributes,
+    #[serde(default)]
+    pub starttls: bool,
+    #[serde(default)]
+    pub allow_invalid_certs: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LdapImportAttributes {
+    pub name: Vec<String>,
+    pub group_name: Vec<String>,
+    pub description: Vec<String>,
+    pub email: Vec<String>,
+    pub email_alias: Vec<String>,
+    pub secret: Vec<String>,
+    pub quota: Vec<String>,
+    pub members: Vec<String>,
+    pub external_id: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapImportEntry {
+    pub dn: String,
+    pub typ: Type,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub emails: Vec<String>,
+    pub secrets: Vec<String>,
+    pub quota: Option<u64>,
+    pub external_id: Option<String>,
+    /// Member DNs, or user names for `memberUid` style groups.
+    pub members: Vec<String>,
+}
+
+impl LdapImportSource {
+    /// Fetches all users followed by all groups matching the configured filters.
+    ///
+    /// The connection is made to the addresses checked by [`Self::resolve`],
+    /// so a host that changes its records after validation cannot point the
+    /// import to an internal address.
+    pub async fn fetch(&self, allow_internal: bool) -> trc::Result<Vec<LdapImportEntry>> {
+        let addrs = self.resolve(allow_internal).await?;
+        let stream = tokio::time::timeout(CONN_TIMEOUT, TcpStream::connect(addrs.as_slice()))
+            .await
+            .map_err(|_| {
+                trc::StoreEvent::LdapError
+                    .into_err()
+                    .details("Connection timed out")
+            })?
+            .and_then(|stream| stream.into_std())
+            .map_err(|err| {
+                trc::StoreEvent::LdapError
+                    .reason(err)
+                    .caused_by(trc::location!())
+            })?;
+        let (conn, mut ldap) = LdapConnAsync::with_settings(
+            LdapConnSettings::new()
+                .set_conn_timeout(CONN_TIMEOUT)
+                .set_starttls(self.starttls)
+                .set_no_tls_verify(self.allow_invalid_certs)
+                .set_std_stream(StdStream::Tcp(stream)),
+            &self.url,
+        )
+        .await
+        .map_err(|err| err.into_error().caused_by(trc::location!()))?;
+        ldap3::drive!(conn);
+
+        if let Some(bind_dn) = &self.bind_dn {
+            ldap.simple_bind(bind_dn, self.bind_secret.as_deref().unwrap_or_default())
+                .await
+                .and_then(|result| result.success())
+                .map_err(|err| err.into_error().caused_by(trc::location!()))?;
+        }
+
+        let mut entries = Vec::new();
+        for (typ, filter) in [
+            (Type::Individual, &self.user_filter),
+            (Type::Group, &self.group_filter),
+        ] {
+            for entry in self.search(&mut ldap, filter).await? {
+                entries.push(self.attributes.map_entry(entry, typ));
+            }
+        }
+        let _ = ldap.unbind().await;
+
+        Ok(entries)
+    }
+
+    /// Resolves the host of the source URL, rejecting hosts with any address
+    /// that is not publicly routable unless `allow_internal` is set.
+    pub async fn resolve(&self, allow_internal: bool) -> trc::Result<Vec<SocketAddr>> {
+        let (host, port) = self.host_port().ok_or_else(|| {
+            manage::error(
+                "Invalid URL",
+                format!("Expected an ldap:// or ldaps:// URL, found {:?}", self.url).into(),
+            )
+        })?;
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map(|addrs| addrs.collect::<Vec<_>>())
+            .unwrap_or_default();
+        if addrs.is_empty() {
+            return Err(manage::error(
+                "Invalid host",
+                format!("Failed to resolve {host:?}").into(),
+            ));
+        }
+        if !allow_internal && let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+            return Err(manage::error(
+                "Host not allowed",
+                format!("{host:?} resolves to the non-public address {}", addr.ip()).into(),
+            ));
+        }
+
+        Ok(addrs)
+    }
+
+    fn host_port(&self) -> Option<(&str, u16)> {
+        let (scheme, rest) = self.url.split_once("://")?;
+        let default_port = if scheme.eq_ignore_ascii_case("ldap") {
+            389
+        } else if scheme.eq_ignore_ascii_case("ldaps") {
+            636
+        } else {
+            return None;
+        };
+        let authority = rest.split(['/', '?']).next().unwrap_or_default();
+        let (host, port) = if let Some(authority) = authority.strip_prefix('[') {
+            let (host, port) = authority.split_once(']')?;
+            (host, port.strip_prefix(':'))
+        } else {
+            match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => default_port,
+        };
+
+        (!host.is_empty()).then_some((host, port))
+    }
+
+    async fn search(&self, ldap: &mut Ldap, filter: &str) -> trc::Result<Vec<SearchEntry>> {
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(PAGE_SIZE)),
+        ];
+        let mut stream = ldap
+            .streaming_search_with(
+                adapters,
+                &self.base_dn,
+                Scope::Subtree,
+                filter,
+                self.attributes.all(),
+            )
+            .await
+            .map_err(|err| err.into_error().caused_by(trc::location!()))?;
+
+        let mut entries = Vec::new();
+        while let Some(entry) = stream
+            .next()
+            .await
+            .map_err(|err| err.into_error().caused_by(trc::location!()))?
+        {
+            entries.push(SearchEntry::construct(entry));
+        }
+        stream
+            .finish()
+            .await
+            .success()
+            .map_err(|err| err.into_error().caused_by(trc::location!()))?;
+
+        trc::event!(
+            Store(trc::StoreEvent::LdapQuery),
+            Details = filter.to_string(),
+            Total = entries.len(),
+        );
+
+        Ok(entries)
+    }
+}
+
+impl LdapImportAttributes {
+    fn all(&self) -> Vec<String> {
+        let mut attrs = Vec::new();
+        for attr in [
+            &self.name,
+            &self.group_name,
+            &self.description,
+            &self.email,
+            &self.email_alias,
+            &self.secret,
+            &self.quota,
+            &self.members,
+            &self.external_id,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !attrs.contains(attr) {
+                attrs.push(attr.clone());
+            }
+        }
+        attrs
+    }
+
+    /// Maps a search entry, taking the first non-empty value of the first
+    /// attribute present in each list.
+    pub fn map_entry(&self, entry: SearchEntry, typ: Type) -> LdapImportEntry {
+        let values = |attrs: &[String]| -> Vec<String> {
+            attrs
+                .iter()
+                .find_map(|attr| {
+                    entry
+                        .attrs
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(attr))
+                        .map(|(_, values)| values)
+                })
+                .into_iter()
+                .flatten()
+                .filter(|value| !value.is_empty())
+                .cloned()
+                .collect()
+        };
+        let all_values = |attrs: &[String]| -> Vec<String> {
+            entry
+                .attrs
+                .iter()
+                .filter(|(name, _)| attrs.iter().any(|attr| name.eq_ignore_ascii_case(attr)))
+                .flat_map(|(_, values)| values)
+                .filter(|value| !value.is_empty())
+                .cloned()
+                .collect()
+        };
+
+        let name = values(if typ == Type::Group {
+            &self.group_name
+        } else {
+            &self.name
+        })
+        .into_iter()
+        .next();
+        let mut emails = Vec::new();
+        for email in values(&self.email)
+            .into_iter()
+            .chain(all_values(&self.email_alias))
+        {
+            let email = email.to_lowercase();
+            if !emails.contains(&email) {
+                emails.push(email);
+            }
+        }
+
+        LdapImportEntry {
+            typ,
+            name,
+            description: values(&self.description).into_iter().next(),
+            emails,
+            secrets: if typ == Type::Individual {
+                values(&self.secret)
+            } else {
+                vec![]
+            },
+            quota: values(&self.quota)
+                .into_iter()
+                .next()
+                .and_then(|quota| quota.parse().ok())
+                .filter(|quota| *quota > 0),
+            external_id: values(&self.external_id).into_iter().next(),
+            members: if typ == Type::Group {
+                all_values(&self.members)
+            } else {
+                vec![]
+            },
+            dn: entry.dn,
+        }
+    }
+}
+
+impl Default for LdapImportAttributes {
+    fn default() -> Self {
+        Self {
+            name: vec!["uid".into()],
+            group_name: vec!["cn".into()],
+            description: vec!["displayName".into(), "cn".into()],
+            email: vec!["mail".into()],
+            email_alias: vec!["mailAlias".into()],
+            secret: vec!["userPassword".into()],
+            quota: vec!["diskQuota".into()],
+            members: vec!["member".into(), "uniqueMember".into(), "memberUid".into()],
+            external_id: vec!["entryUUID".into()],
+        }
+    }
+}
+
+fn default_user_filter() -> String {
+    "(|(objectClass=inetOrgPerson)(objectClass=posixAccount))".into()
+}
+
+fn default_group_filter() -> String {
+    "(|(objectClass=groupOfNames)(objectClass=groupOfUniqueNames)(objectClass=posixGroup))".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldap3::SearchEntry;
+
+    use super::{LdapImportAttributes, LdapImportEntry, LdapImportSource};
+    use crate::Type;
+
+    fn source(url: &str) -> LdapImportSource {
+        LdapImportSource {
+            url: url.to_string(),
+            bind_dn: None,
+            bind_secret: None,
+            base_dn: "dc=example,dc=org".to_string(),
+            user_filter: String::new(),
+            group_filter: String::new(),
+            attributes: LdapImportAttributes::default(),
+            starttls: false,
+            allow_invalid_certs: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn ldap_import_source_host() {
+        for (url, expected) in [
+            ("ldap://ldap.example.org", Some(("ldap.example.org", 389))),
+            (
+                "LDAPS://ldap.example.org:1636/",
+                Some(("ldap.example.org", 1636)),
+            ),
+            ("ldap://[::1]:3389/dc=example", Some(("::1", 3389))),
+            ("ldap:///", None),
+            ("ldapi://%2Fvar%2Frun%2Fldapi", None),
+            ("ldap://ldap.example.org:port", None),
+        ] {
+            assert_eq!(source(url).host_port(), expected, "{url}");
+        }
+
+        // Internal addresses are only allowed when requested
+        for url in [
+            "ldap://127.0.0.1",
+            "ldap://[::1]",
+            "ldap://169.254.169.254:389",
+        ] {
+            assert!(source(url).resolve(false).await.is_err(), "{url}");
+            assert!(source(url).resolve(true).await.is_ok(), "{url}");
+        }
+    }
+
+    fn entry(dn: &str, attrs: &[(&str, &[&str])]) -> SearchEntry {
+        SearchEntry {
+            dn: dn.to_string(),
+            attrs: attrs
+                .iter()
+                .map(|(name, values)| {
+                    (
+                        name.to_string(),
+                        values.iter().map(|v| v.to_string()).collect(),
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn ldap_import_mapping() {
+        let attributes = LdapImportAttributes::default();
+
+        assert_eq!(
+            attributes.map_entry(
+                entry(
+                    "uid=jane,ou=people,dc=example,dc=org",
+                    &[
+                        ("uid", &["jane"]),
+                        ("cn", &["Jane Doe"]),
+                        ("mail", &["Jane@Example.org"]),
+                        ("mailAlias", &["jdoe@example.org", "jane@example.org"]),
+                        ("userPassword", &["{SSHA}abcdef"]),
+                        ("diskQuota", &["1024"]),
+                        ("entryUUID", &["8a5d2c4e-2b1f-4c6a-9d3e-7f0a1b2c3d4e"]),
+                        ("member", &["uid=john,ou=people,dc=example,dc=org"]),
+                    ],
+                ),
+                Type::Individual,
+            ),
+            LdapImportEntry {
+                dn: "uid=jane,ou=people,dc=example,dc=org".to_string(),
+                typ: Type::Individual,
+                name: Some("jane".to_string()),
+                description: Some("Jane Doe".to_string()),
+                emails: vec![
+                    "jane@example.org".to_string(),
+                    "jdoe@example.org".to_string()
+                ],
+                secrets: vec!["{SSHA}abcdef".to_string()],
+                quota: Some(1024),
+                external_id: Some("8a5d2c4e-2b1f-4c6a-9d3e-7f0a1b2c3d4e".to_string()),
+                members: vec![],
+            }
+        );
+
+        let group = attributes.map_entry(
+            entry(
+                "cn=sales,ou=groups,dc=example,dc=org",
+                &[
+                    ("cn", &["sales"]),
+                    ("member", &["uid=jane,ou=people,dc=example,dc=org"]),
+                    ("memberUid", &["john"]),
+                    ("userPassword", &["secret"]),
+                ],
+            ),
+            Type::Group,
+        );
+        assert_eq!(group.name.as_deref(), Some("sales"));
+        assert_eq!(group.description.as_deref(), Some("sales"));
+        assert!(group.secrets.is_empty());
+        assert_eq!(group.members.len(), 2);
+
+        let unnamed = attributes.map_entry(entry("cn=nobody", &[]), Type::Individual);
+        assert_eq!(unnamed.name, None);
+    }
+}
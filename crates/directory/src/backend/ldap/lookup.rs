@@ -185,7 +185,11 @@ impl LdapDirectory {
                     AuthBind::None => {
                         let filter = self.mappings.filter_name.build(username);
                         if let Some(mut result) = self.find_principal(&mut conn, &filter).await? {
-                            if result.principal.verify_secret(secret, false, false).await? {
+                            if result
+                                .principal
+                                .verify_secret(secret, false, false, by.protocol)
+                                .await?
+                            {
                                 if result.principal.name.is_empty() {
                                     result.principal.name = username.into();
                                 }
@@ -9,6 +9,7 @@ use ldap3::{LdapConnSettings, ldap_escape};
 use store::Store;
 
 pub mod config;
+pub mod import;
 pub mod lookup;
 pub mod pool;
 
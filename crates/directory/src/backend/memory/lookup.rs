@@ -35,7 +35,10 @@ impl MemoryDirectory {
 
                 for principal in &self.principals {
                     if principal.name() == username {
-                        return if principal.verify_secret(secret, false, false).await? {
+                        return if principal
+                            .verify_secret(secret, false, false, by.protocol)
+                            .await?
+                        {
                             Ok(Some(principal.clone()))
                         } else {
                             Ok(None)
@@ -124,7 +124,7 @@ impl SqlDirectory {
                         }
 
                         if principal
-                            .verify_secret(secret, false, false)
+                            .verify_secret(secret, false, false, by.protocol)
                             .await
                             .caused_by(trc::location!())?
                         {
@@ -319,6 +319,18 @@ impl Permission {
             Permission::JmapParticipantIdentityChanges => {
                 "Track participant identity changes via JMAP"
             }
+            Permission::AuditList => "View stored audit events",
+            Permission::ScimProvision => "Provision users and groups through SCIM",
+            Permission::PrincipalLegalHold => "Place or release legal holds on accounts",
+            Permission::PrincipalErase => "Erase accounts and their personal data",
+            Permission::PrincipalExport => "Export the data of other accounts",
+            Permission::PrincipalReservedName => {
+                "Create principals and addresses with reserved names"
+            }
+            Permission::ManageSecondaryEmails => "Manage and verify secondary email addresses",
+            Permission::OauthTokenIntrospect => "Introspect OAuth tokens issued to other accounts",
+            Permission::MeteringExport => "Export per-tenant metering data for billing",
+            Permission::MailboxStats => "View per-mailbox message statistics of accounts",
         }
     }
 }
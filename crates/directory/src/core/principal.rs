@@ -7,7 +7,10 @@
 use crate::{
     ArchivedPrincipal, ArchivedPrincipalData, FALLBACK_ADMIN_ID, Permission, PermissionGrant,
     Principal, PrincipalData, ROLE_ADMIN, Type,
-    backend::internal::{PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue},
+    backend::internal::{
+        PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue, avatar::Avatar,
+        lockout::LockoutPolicy, metadata_entry, name_key, schedule::AccountSchedule,
+    },
 };
 use ahash::AHashSet;
 use nlp::tokenizers::word::WordTokenizer;
@@ -17,7 +20,12 @@ use serde::{
     ser::SerializeMap,
 };
 use serde_json::Value;
-use std::{cmp::Ordering, collections::hash_map::Entry, fmt, str::FromStr};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, hash_map::Entry},
+    fmt,
+    str::FromStr,
+};
 use store::{
     U32_LEN, U64_LEN,
     backend::MAX_TOKEN_LENGTH,
@@ -89,6 +97,20 @@ impl Principal {
         None
     }
 
+    pub fn name_scope(&self) -> Option<u32> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::ScopedName(tenant_id) = item {
+                Some(*tenant_id)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn name_key(&self) -> Vec<u8> {
+        name_key(&self.name, self.name_scope())
+    }
+
     pub fn description(&self) -> Option<&str> {
         self.data.iter().find_map(|item| {
             if let PrincipalData::Description(description) = item {
@@ -103,6 +125,336 @@ impl Principal {
         })
     }
 
+    pub fn locale(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::Locale(locale) = item
+                && !locale.is_empty()
+            {
+                Some(locale.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn brand_name(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::BrandName(brand_name) = item
+                && !brand_name.is_empty()
+            {
+                Some(brand_name.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn brand_logo_url(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::BrandLogoUrl(url) = item {
+                Some(url.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn brand_logo_dark_url(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::BrandLogoDarkUrl(url) = item {
+                Some(url.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn brand_theme(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::BrandTheme(theme) = item {
+                Some(theme.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn external_id(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::ExternalId(external_id) = item {
+                Some(external_id.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn plan(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::Plan(plan) = item {
+                Some(plan.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns a contact detail of a tenant, such as its billing email.
+    pub fn contact(&self, field: PrincipalField) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            item.as_contact()
+                .filter(|(item_field, _)| *item_field == field)
+                .map(|(_, value)| value)
+        })
+    }
+
+    /// Address that notices about a tenant, such as an expiring trial, are
+    /// delivered to by default.
+    pub fn alert_recipient(&self) -> Option<&str> {
+        self.contact(PrincipalField::TechnicalContact)
+    }
+
+    /// Returns the name of the principal that placed the legal hold and when.
+    pub fn legal_hold(&self) -> Option<(&str, u64)> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::LegalHold { set_by, set_at } = item {
+                Some((set_by.as_str(), *set_at))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns when the principal was created, unknown for principals that
+    /// predate the tracking of timestamps.
+    pub fn created_at(&self) -> Option<u64> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::CreatedAt(created_at) = item {
+                Some(*created_at)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn modified_at(&self) -> Option<u64> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::ModifiedAt(modified_at) = item {
+                Some(*modified_at)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.data.iter().filter_map(|item| {
+            if let PrincipalData::Metadata { key, value } = item {
+                Some((key.as_str(), value.as_str()))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn metadata_value(&self, key: &str) -> Option<&str> {
+        self.metadata()
+            .find_map(|(k, value)| if k == key { Some(value) } else { None })
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().filter_map(|item| {
+            if let PrincipalData::Tag(tag) = item {
+                Some(tag.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Protocols the account is restricted to, none means all of them.
+    pub fn allowed_protocols(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().filter_map(|item| {
+            if let PrincipalData::AllowedProtocol(protocol) = item {
+                Some(protocol.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Address or domain pattern of a send-only credential, which is what
+    /// distinguishes it from other accounts.
+    pub fn sender_address(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::SenderAddress(address) = item {
+                Some(address.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn send_rate(&self) -> Option<&str> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::SendRate(rate) = item {
+                Some(rate.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Secondary addresses whose ownership has been confirmed, the only ones
+    /// account recovery messages may be delivered to.
+    pub fn secondary_emails(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().filter_map(|item| {
+            if let PrincipalData::SecondaryEmail(email) = item {
+                Some(email.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Secondary addresses awaiting confirmation, with the nonce of their
+    /// current token and its expiration time.
+    pub fn pending_emails(&self) -> impl Iterator<Item = (&str, u64, u64)> {
+        self.data.iter().filter_map(|item| {
+            if let PrincipalData::PendingEmail {
+                address,
+                nonce,
+                expires,
+            } = item
+            {
+                Some((address.as_str(), *nonce, *expires))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Number of passwords, including the current one, that can't be reused.
+    pub fn password_history_length(&self) -> u32 {
+        self.data
+            .iter()
+            .find_map(|item| {
+                if let PrincipalData::PasswordHistoryLength(length) = item {
+                    Some(*length)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn min_password_age_days(&self) -> u32 {
+        self.data
+            .iter()
+            .find_map(|item| {
+                if let PrincipalData::MinPasswordAgeDays(days) = item {
+                    Some(*days)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn lockout_policy(&self) -> Option<LockoutPolicy> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::LockoutPolicy {
+                threshold,
+                window,
+                duration,
+                per_protocol,
+                notify,
+            } = item
+            {
+                Some(LockoutPolicy {
+                    threshold: *threshold,
+                    window: *window,
+                    duration: *duration,
+                    per_protocol: *per_protocol,
+                    notify: *notify,
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn schedule(&self) -> AccountSchedule {
+        let mut schedule = AccountSchedule::default();
+        for item in &self.data {
+            match item {
+                PrincipalData::DisableAt(at) => schedule.disable_at = Some(*at),
+                PrincipalData::EnableAt(at) => schedule.enable_at = Some(*at),
+                _ => (),
+            }
+        }
+        schedule
+    }
+
+    /// Returns whether the account is disabled at the given time, taking into
+    /// account the events that are due but not yet applied.
+    pub fn is_disabled_at(&self, now: u64) -> bool {
+        self.schedule().is_disabled_at(now).unwrap_or_else(|| {
+            let authenticate = Permission::Authenticate.id();
+            self.data.iter().any(|item| {
+                matches!(item, PrincipalData::Permission { permission_id, grant: false }
+                    if *permission_id == authenticate)
+            })
+        })
+    }
+
+    pub fn avatar(&self) -> Option<Avatar> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::Avatar { hash, content_type } = item {
+                Some(Avatar {
+                    hash: hash.clone(),
+                    content_type: content_type.clone(),
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns whether the members of this tenant can manage their own avatar.
+    pub fn avatar_self_service(&self) -> bool {
+        !self
+            .data
+            .iter()
+            .any(|item| matches!(item, PrincipalData::AvatarSelfService(false)))
+    }
+
+    /// Returns when the password was last changed, unknown for passwords set
+    /// before password history was tracked.
+    pub fn password_changed_at(&self) -> Option<u64> {
+        self.data.iter().find_map(|item| {
+            if let PrincipalData::PasswordChangedAt(changed_at) = item {
+                Some(*changed_at)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Previous passwords, newest first.
+    pub fn password_history(&self) -> &[String] {
+        self.data
+            .iter()
+            .find_map(|item| {
+                if let PrincipalData::PasswordHistory(secrets) = item {
+                    Some(secrets.as_slice())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
     pub fn secret(&self) -> Option<&str> {
         if let Some(PrincipalData::Password(password)) = self.data.first() {
             Some(password.as_str())
@@ -535,6 +887,30 @@ impl Ord for PrincipalData {
 }
 
 impl PrincipalData {
+    pub fn contact(field: PrincipalField, value: String) -> Option<Self> {
+        match field {
+            PrincipalField::BillingEmail => Some(PrincipalData::BillingEmail(value)),
+            PrincipalField::TechnicalContact => Some(PrincipalData::TechnicalContact(value)),
+            PrincipalField::Phone => Some(PrincipalData::Phone(value)),
+            PrincipalField::PostalAddress => Some(PrincipalData::PostalAddress(value)),
+            PrincipalField::TaxId => Some(PrincipalData::TaxId(value)),
+            _ => None,
+        }
+    }
+
+    pub fn as_contact(&self) -> Option<(PrincipalField, &str)> {
+        match self {
+            PrincipalData::BillingEmail(value) => Some((PrincipalField::BillingEmail, value)),
+            PrincipalData::TechnicalContact(value) => {
+                Some((PrincipalField::TechnicalContact, value))
+            }
+            PrincipalData::Phone(value) => Some((PrincipalField::Phone, value)),
+            PrincipalData::PostalAddress(value) => Some((PrincipalField::PostalAddress, value)),
+            PrincipalData::TaxId(value) => Some((PrincipalField::TaxId, value)),
+            _ => None,
+        }
+    }
+
     pub fn object_size(&self) -> usize {
         match self {
             PrincipalData::Password(v)
@@ -549,14 +925,52 @@ impl PrincipalData {
             | PrincipalData::Locale(v)
             | PrincipalData::BrandName(v)
             | PrincipalData::BrandLogoUrl(v)
-            | PrincipalData::BrandTheme(v) => v.len(),
-            PrincipalData::DiskQuota(_) => U64_LEN,
+            | PrincipalData::BrandLogoDarkUrl(v)
+            | PrincipalData::BrandTheme(v)
+            | PrincipalData::ExternalId(v)
+            | PrincipalData::Plan(v)
+            | PrincipalData::AllowedProtocol(v)
+            | PrincipalData::SenderAddress(v)
+            | PrincipalData::SendRate(v)
+            | PrincipalData::BillingEmail(v)
+            | PrincipalData::TechnicalContact(v)
+            | PrincipalData::Phone(v)
+            | PrincipalData::PostalAddress(v)
+            | PrincipalData::TaxId(v)
+            | PrincipalData::Tag(v)
+            | PrincipalData::SecondaryEmail(v)
+            | PrincipalData::RecoveryCode(v) => v.len(),
+            PrincipalData::Avatar { hash, content_type } => hash.len() + content_type.len(),
+            PrincipalData::LegalHold { set_by, .. } => set_by.len() + U64_LEN,
+            PrincipalData::PendingEmail { address, .. } => address.len() + (U64_LEN * 2),
+            PrincipalData::AppPasswordInfo { label, .. } => label.len() + (U64_LEN * 2),
+            PrincipalData::WebAuthnCredential {
+                id,
+                public_key,
+                rp_id,
+                nickname,
+                ..
+            } => id.len() + public_key.len() + rp_id.len() + nickname.len() + U64_LEN + 1,
+            PrincipalData::PasswordHistory(secrets) => secrets.iter().map(|s| s.len()).sum(),
+            PrincipalData::Metadata { key, value } => key.len() + value.len(),
+            PrincipalData::DiskQuota(_)
+            | PrincipalData::CreatedAt(_)
+            | PrincipalData::ModifiedAt(_)
+            | PrincipalData::PasswordChangedAt(_)
+            | PrincipalData::DisableAt(_)
+            | PrincipalData::EnableAt(_)
+            | PrincipalData::TrialEndsAt(_) => U64_LEN,
+            PrincipalData::LockoutPolicy { .. } => U32_LEN + (U64_LEN * 2) + 2,
             PrincipalData::Permission { .. } => U32_LEN + 1,
+            PrincipalData::AvatarSelfService(_) | PrincipalData::SecurityNoticeOptOut(_) => 1,
             PrincipalData::DirectoryQuota { .. } | PrincipalData::ObjectQuota { .. } => U64_LEN + 1,
             PrincipalData::Tenant(_)
             | PrincipalData::MemberOf(_)
             | PrincipalData::Role(_)
-            | PrincipalData::List(_) => U32_LEN,
+            | PrincipalData::List(_)
+            | PrincipalData::ScopedName(_)
+            | PrincipalData::PasswordHistoryLength(_)
+            | PrincipalData::MinPasswordAgeDays(_) => U32_LEN,
         }
     }
 }
@@ -1203,6 +1617,26 @@ impl serde::Serialize for PrincipalSet {
 
         for (key, value) in &self.fields {
             match value {
+                PrincipalValue::StringList(v) if *key == PrincipalField::Metadata => map
+                    .serialize_entry(
+                        key.as_str(),
+                        &v.iter()
+                            .filter_map(|entry| match metadata_entry(entry) {
+                                (key, Some(value)) => Some((key, value)),
+                                _ => None,
+                            })
+                            .collect::<BTreeMap<_, _>>(),
+                    )?,
+                PrincipalValue::StringList(v) if *key == PrincipalField::LastActivity => map
+                    .serialize_entry(
+                        key.as_str(),
+                        &v.iter()
+                            .filter_map(|entry| {
+                                let (typ, timestamp) = entry.split_once('=')?;
+                                Some((typ, timestamp.parse::<u64>().ok()?))
+                            })
+                            .collect::<BTreeMap<_, _>>(),
+                    )?,
                 PrincipalValue::String(v) => map.serialize_entry(key.as_str(), v)?,
                 PrincipalValue::StringList(v) => map.serialize_entry(key.as_str(), v)?,
                 PrincipalValue::Integer(v) => map.serialize_entry(key.as_str(), v)?,
@@ -1251,6 +1685,13 @@ impl<'de> serde::Deserialize<'de> for PrincipalValue {
                 Ok(PrincipalValue::Integer(value))
             }
 
+            fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(PrincipalValue::Integer(value as u64))
+            }
+
             fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
             where
                 E: de::Error,
@@ -1300,6 +1741,27 @@ impl<'de> serde::Deserialize<'de> for PrincipalValue {
                     _ => Err(serde::de::Error::custom("invalid principal value")),
                 }
             }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                // Metadata entries, null values delete the key
+                let mut entries = Vec::new();
+                while let Some((key, value)) = map.next_entry::<String, Option<String>>()? {
+                    if value.as_ref().is_some_and(|v| v.len() > MAX_STRING_LEN) {
+                        return Err(serde::de::Error::custom("string too long"));
+                    } else if key.contains('=') {
+                        return Err(serde::de::Error::custom("invalid metadata key"));
+                    }
+                    entries.push(match value {
+                        Some(value) => format!("{key}={value}"),
+                        None => key,
+                    });
+                }
+
+                Ok(PrincipalValue::StringList(entries))
+            }
         }
 
         deserializer.deserialize_any(PrincipalValueVisitor)
@@ -1354,7 +1816,17 @@ impl<'de> serde::Deserialize<'de> for PrincipalSet {
                         | PrincipalField::Locale
                         | PrincipalField::BrandName
                         | PrincipalField::BrandLogoUrl
-                        | PrincipalField::BrandTheme => {
+                        | PrincipalField::BrandLogoDarkUrl
+                        | PrincipalField::BrandTheme
+                        | PrincipalField::ExternalId
+                        | PrincipalField::Plan
+                        | PrincipalField::BillingEmail
+                        | PrincipalField::TechnicalContact
+                        | PrincipalField::Phone
+                        | PrincipalField::PostalAddress
+                        | PrincipalField::TaxId
+                        | PrincipalField::SenderAddress
+                        | PrincipalField::SendRate => {
                             if let Some(v) = map.next_value::<Option<String>>()? {
                                 if v.len() <= MAX_STRING_LEN {
                                     PrincipalValue::String(v)
@@ -1371,7 +1843,19 @@ impl<'de> serde::Deserialize<'de> for PrincipalSet {
                             })?;
                             continue;
                         }
-                        PrincipalField::Quota => map.next_value::<PrincipalValue>()?,
+                        PrincipalField::Quota
+                        | PrincipalField::PasswordHistoryLength
+                        | PrincipalField::MinPasswordAgeDays
+                        | PrincipalField::LockoutThreshold
+                        | PrincipalField::LockoutWindow
+                        | PrincipalField::LockoutDuration
+                        | PrincipalField::LockoutPerProtocol
+                        | PrincipalField::LockoutNotify
+                        | PrincipalField::DisableAt
+                        | PrincipalField::EnableAt
+                        | PrincipalField::AvatarSelfService
+                        | PrincipalField::SecurityNoticeOptOut
+                        | PrincipalField::TrialEndsAt => map.next_value::<PrincipalValue>()?,
                         PrincipalField::Secrets
                         | PrincipalField::Emails
                         | PrincipalField::MemberOf
@@ -1381,7 +1865,9 @@ impl<'de> serde::Deserialize<'de> for PrincipalSet {
                         | PrincipalField::EnabledPermissions
                         | PrincipalField::DisabledPermissions
                         | PrincipalField::Urls
-                        | PrincipalField::ExternalMembers => match map.next_value::<Value>()? {
+                        | PrincipalField::ExternalMembers
+                        | PrincipalField::Tags
+                        | PrincipalField::AllowedProtocols => match map.next_value::<Value>()? {
                             Value::String(v) => {
                                 if v.len() <= MAX_STRING_LEN {
                                     PrincipalValue::StringList(vec![v])
@@ -1412,7 +1898,23 @@ impl<'de> serde::Deserialize<'de> for PrincipalSet {
                             }
                             _ => continue,
                         },
-                        PrincipalField::UsedQuota => {
+                        PrincipalField::Metadata => match map.next_value::<PrincipalValue>()? {
+                            PrincipalValue::StringList(entries) if !entries.is_empty() => {
+                                PrincipalValue::StringList(entries)
+                            }
+                            _ => continue,
+                        },
+                        PrincipalField::UsedQuota
+                        | PrincipalField::LegalHold
+                        | PrincipalField::LegalHoldBy
+                        | PrincipalField::LegalHoldAt
+                        | PrincipalField::CreatedAt
+                        | PrincipalField::ModifiedAt
+                        | PrincipalField::SecondaryEmails
+                        | PrincipalField::PendingEmails
+                        | PrincipalField::LastActivity
+                        | PrincipalField::Disabled
+                        | PrincipalField::Avatar => {
                             // consume and ignore
                             map.next_value::<IgnoredAny>()?;
                             continue;
@@ -1498,6 +2000,7 @@ impl Permission {
                 | Permission::EmailReceive
                 | Permission::ManageEncryption
                 | Permission::ManagePasswords
+                | Permission::ManageSecondaryEmails
                 | Permission::JmapEmailGet
                 | Permission::JmapMailboxGet
                 | Permission::JmapThreadGet
@@ -1737,6 +2240,13 @@ impl Permission {
                 | Permission::ApiKeyDelete
                 | Permission::SpamFilterTrain
                 | Permission::SpamFilterTest
+                | Permission::AuditList
+                | Permission::ScimProvision
+                | Permission::PrincipalLegalHold
+                | Permission::PrincipalErase
+                | Permission::PrincipalExport
+                | Permission::OauthTokenIntrospect
+                | Permission::MailboxStats
         ) || self.is_user_permission()
     }
 
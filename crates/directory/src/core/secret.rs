@@ -4,8 +4,13 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use crate::AuthProtocol;
 use crate::Principal;
 use crate::PrincipalData;
+use crate::backend::internal::{
+    mfa::{SecretCipher, is_encrypted_otp_secret},
+    recovery::normalize_recovery_code,
+};
 use argon2::Argon2;
 use compact_str::ToCompactString;
 use mail_builder::encoders::base64::base64_encode;
@@ -18,19 +23,44 @@ use sha1::Digest;
 use sha1::Sha1;
 use sha2::Sha256;
 use sha2::Sha512;
+use std::borrow::Cow;
 use tokio::sync::oneshot;
 use totp_rs::TOTP;
 
+/// Secret a set of credentials was verified against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretMatch<'x> {
+    Password,
+    AppPassword(&'x str),
+    /// Password followed by a recovery code, holds the hash of the code.
+    RecoveryCode(&'x str),
+}
+
 impl Principal {
     pub async fn verify_secret(
         &self,
         code: &str,
         only_app_pass: bool,
         is_ordered: bool,
+        protocol: Option<AuthProtocol>,
     ) -> trc::Result<bool> {
+        self.match_secret(code, only_app_pass, is_ordered, protocol, None)
+            .await
+            .map(|result| result.is_some())
+    }
+
+    pub async fn match_secret(
+        &self,
+        code: &str,
+        only_app_pass: bool,
+        is_ordered: bool,
+        protocol: Option<AuthProtocol>,
+        cipher: Option<&dyn SecretCipher>,
+    ) -> trc::Result<Option<SecretMatch<'_>>> {
         let mut seen_password = false;
         let mut password = None;
         let mut otp_auth = None;
+        let mut app_passwords = Vec::new();
 
         for item in &self.data {
             match item {
@@ -47,12 +77,10 @@ impl Principal {
                     seen_password = true;
                 }
                 PrincipalData::AppPassword(secret) => {
-                    // App passwords do not require TOTP
-                    if let Some((_, app_secret)) =
+                    if let Some((label, app_secret)) =
                         secret.strip_prefix("$app$").and_then(|s| s.split_once('$'))
-                        && verify_secret_hash(app_secret, code).await?
                     {
-                        return Ok(true);
+                        app_passwords.push((label, app_secret));
                     }
 
                     seen_password = true;
@@ -66,39 +94,183 @@ impl Principal {
             }
         }
 
-        // Validate TOTP
-        match (otp_auth, password) {
+        // Encrypted TOTP secrets can only be verified with the cipher
+        let otp_auth = match otp_auth {
+            Some(secret) if is_encrypted_otp_secret(secret) => Some(Cow::Owned(
+                cipher
+                    .ok_or_else(|| {
+                        trc::AuthEvent::Error
+                            .into_err()
+                            .details("Missing cipher for encrypted TOTP secret")
+                    })?
+                    .decrypt_secret(secret)?,
+            )),
+            secret => secret.map(|secret| Cow::Borrowed(secret.as_str())),
+        };
+
+        // Validate password and TOTP
+        let mut missing_totp = false;
+        let is_password = match (otp_auth, password) {
             (Some(otp_auth), Some(password)) => {
-                if let Some((code, totp_token)) = code.rsplit_once('$').filter(|(c, t)| {
-                    !c.is_empty()
-                        && (6..=8).contains(&t.len())
-                        && t.as_bytes().iter().all(|b| b.is_ascii_digit())
-                }) {
-                    let result = verify_secret_hash(password, code).await?
-                        && TOTP::from_url(otp_auth)
+                if let Some((code, totp_token)) = split_totp_code(code) {
+                    verify_secret_hash(password, code).await?
+                        && TOTP::from_url(otp_auth.as_ref())
                             .map_err(|err| {
                                 trc::AuthEvent::Error
                                     .reason(err)
                                     .details(otp_auth.to_compact_string())
                             })?
                             .check_current(totp_token)
-                            .unwrap_or(false);
-                    Ok(result)
-                } else if verify_secret_hash(password, code).await? {
+                            .unwrap_or(false)
+                } else {
                     // Only let the client know if the TOTP code is missing
                     // if the password is correct
+                    missing_totp = verify_secret_hash(password, code).await?;
+                    false
+                }
+            }
+            (None, Some(password)) => verify_secret_hash(password, code).await?,
+            _ => false,
+        };
 
-                    Err(trc::AuthEvent::MissingTotp.into_err())
-                } else {
-                    Ok(false)
+        // App passwords are tried after the password, all of them are verified
+        // regardless of the outcome so that response times do not reveal which
+        // secret matched. App passwords do not require TOTP.
+        let mut app_password = None;
+        for (label, app_secret) in app_passwords {
+            if verify_secret_hash(app_secret, code).await?
+                && app_password.is_none()
+                && self.app_password_allows(label, protocol)
+            {
+                app_password = Some(label);
+            }
+        }
+
+        if is_password {
+            Ok(Some(SecretMatch::Password))
+        } else if let Some(label) = app_password {
+            Ok(Some(SecretMatch::AppPassword(label)))
+        } else if missing_totp {
+            Err(trc::AuthEvent::MissingTotp.into_err())
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Verifies a password followed by a recovery code, the code is only
+    /// checked once the password matched.
+    pub async fn match_recovery_code(&self, code: &str) -> trc::Result<Option<SecretMatch<'_>>> {
+        let Some((password, recovery_code)) = split_recovery_code(code) else {
+            return Ok(None);
+        };
+
+        let mut is_password = false;
+        for item in &self.data {
+            if let PrincipalData::Password(secret) = item
+                && verify_secret_hash(secret, password).await?
+            {
+                is_password = true;
+                break;
+            }
+        }
+        if is_password {
+            for item in &self.data {
+                if let PrincipalData::RecoveryCode(hash) = item
+                    && verify_secret_hash(hash, &recovery_code).await?
+                {
+                    return Ok(Some(SecretMatch::RecoveryCode(hash)));
                 }
             }
-            (None, Some(password)) => verify_secret_hash(password, code).await,
-            _ => Ok(false),
+        }
+
+        Ok(None)
+    }
+
+    pub fn recovery_code_count(&self) -> usize {
+        self.data
+            .iter()
+            .filter(|item| matches!(item, PrincipalData::RecoveryCode(_)))
+            .count()
+    }
+
+    /// App passwords without metadata may be used over any protocol other
+    /// than the management API.
+    pub fn app_password_allows(&self, label: &str, protocol: Option<AuthProtocol>) -> bool {
+        match protocol {
+            Some(AuthProtocol::Management) => false,
+            Some(protocol) => self.data.iter().all(|item| match item {
+                PrincipalData::AppPasswordInfo {
+                    label: info_label,
+                    protocols,
+                    ..
+                } if info_label == label => *protocols == 0 || protocols & protocol.bit() != 0,
+                _ => true,
+            }),
+            None => true,
         }
     }
 }
 
+/// Splits a TOTP code of 6 to 8 digits appended to a password with a `$`.
+pub fn split_totp_code(code: &str) -> Option<(&str, &str)> {
+    code.rsplit_once('$').filter(|(c, t)| {
+        !c.is_empty()
+            && (6..=8).contains(&t.len())
+            && t.as_bytes().iter().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// Splits a secret into a password and a normalized recovery code.
+pub fn split_recovery_code(code: &str) -> Option<(&str, String)> {
+    code.rsplit_once('$').and_then(|(password, code)| {
+        if !password.is_empty() {
+            normalize_recovery_code(code).map(|code| (password, code))
+        } else {
+            None
+        }
+    })
+}
+
+impl AuthProtocol {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "imap" => Some(AuthProtocol::Imap),
+            "pop3" => Some(AuthProtocol::Pop3),
+            "smtp" => Some(AuthProtocol::Smtp),
+            "managesieve" => Some(AuthProtocol::ManageSieve),
+            "http" => Some(AuthProtocol::Http),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthProtocol::Imap => "imap",
+            AuthProtocol::Pop3 => "pop3",
+            AuthProtocol::Smtp => "smtp",
+            AuthProtocol::ManageSieve => "managesieve",
+            AuthProtocol::Http => "http",
+            AuthProtocol::Management => "management",
+        }
+    }
+
+    pub fn bit(&self) -> u64 {
+        1 << (*self as u64)
+    }
+
+    pub fn from_bits(bits: u64) -> impl Iterator<Item = AuthProtocol> {
+        [
+            AuthProtocol::Imap,
+            AuthProtocol::Pop3,
+            AuthProtocol::Smtp,
+            AuthProtocol::ManageSieve,
+            AuthProtocol::Http,
+        ]
+        .into_iter()
+        .filter(move |protocol| bits & protocol.bit() != 0)
+    }
+}
+
 async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> trc::Result<bool> {
     if hashed_secret.starts_with("$argon2")
         || hashed_secret.starts_with("$pbkdf2")
@@ -151,6 +323,41 @@ async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> trc::Result<bo
     }
 }
 
+/// Returns whether a hashed secret uses a scheme that `verify_secret_hash`
+/// understands, so that it can be imported from an external directory.
+pub fn is_supported_secret_hash(hashed_secret: &str) -> bool {
+    fn is_supported_prefix(hashed_secret: &str) -> bool {
+        [
+            "$argon2", "$pbkdf2", "$scrypt", "$2", "$6$", "$5$", "$sha1", "$1",
+        ]
+        .iter()
+        .any(|prefix| hashed_secret.starts_with(prefix))
+    }
+
+    if hashed_secret.starts_with('$') {
+        is_supported_prefix(hashed_secret)
+    } else if hashed_secret.starts_with('_') {
+        true
+    } else if let Some(hashed_secret) = hashed_secret.strip_prefix('{') {
+        match hashed_secret.split_once('}') {
+            Some(("ARGON2" | "ARGON2I" | "ARGON2ID" | "PBKDF2", hashed_secret)) => {
+                is_supported_prefix(hashed_secret)
+            }
+            Some(("CRYPT" | "crypt", hashed_secret)) => {
+                !hashed_secret.starts_with('$') || is_supported_prefix(hashed_secret)
+            }
+            Some((
+                "SHA" | "SSHA" | "SHA256" | "SSHA256" | "SHA512" | "SSHA512" | "MD5" | "PLAIN"
+                | "plain" | "CLEAR" | "clear",
+                _,
+            )) => true,
+            _ => false,
+        }
+    } else {
+        !hashed_secret.is_empty()
+    }
+}
+
 pub async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> trc::Result<bool> {
     if hashed_secret.starts_with('$') {
         verify_hash_prefix(hashed_secret, secret).await
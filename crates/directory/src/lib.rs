@@ -9,6 +9,7 @@
 use ahash::AHashMap;
 use backend::{
     imap::{ImapDirectory, ImapError},
+    internal::{mfa::SecretCipher, password::PasswordPolicy},
     ldap::LdapDirectory,
     memory::MemoryDirectory,
     smtp::SmtpDirectory,
@@ -19,7 +20,10 @@ use deadpool::managed::PoolError;
 use ldap3::LdapError;
 use mail_send::Credentials;
 use proc_macros::EnumMethods;
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    sync::{Arc, atomic::AtomicBool},
+};
 use store::Store;
 use trc::ipc::bitset::Bitset;
 use types::collection::Collection;
@@ -51,12 +55,21 @@ pub enum PrincipalData {
     MemberOf(u32),
     Role(u32),
     List(u32),
-    Permission { permission_id: u32, grant: bool },
+    Permission {
+        permission_id: u32,
+        grant: bool,
+    },
 
     // Quotas
     DiskQuota(u64),
-    DirectoryQuota { quota: u32, typ: Type },
-    ObjectQuota { quota: u32, typ: Collection },
+    DirectoryQuota {
+        quota: u32,
+        typ: Type,
+    },
+    ObjectQuota {
+        quota: u32,
+        typ: Collection,
+    },
 
     // Profile data
     Description(String),
@@ -75,6 +88,113 @@ pub enum PrincipalData {
     BrandName(String),
     BrandLogoUrl(String),
     BrandTheme(String),
+
+    // Identifier assigned by an external provisioning system
+    ExternalId(String),
+
+    // Litigation hold
+    LegalHold {
+        set_by: String,
+        set_at: u64,
+    },
+
+    // Record timestamps
+    CreatedAt(u64),
+    ModifiedAt(u64),
+
+    // Integrator-defined attributes
+    Metadata {
+        key: String,
+        value: String,
+    },
+    Tag(String),
+
+    // Tenant the principal name is unique within
+    ScopedName(u32),
+
+    // Secondary addresses, verified or awaiting confirmation
+    SecondaryEmail(String),
+    PendingEmail {
+        address: String,
+        nonce: u64,
+        expires: u64,
+    },
+
+    // App password metadata, protocols is a bitmask of `AuthProtocol`
+    AppPasswordInfo {
+        label: String,
+        created_at: u64,
+        protocols: u64,
+    },
+
+    // Password policy of a tenant
+    PasswordHistoryLength(u32),
+    MinPasswordAgeDays(u32),
+
+    // Previous passwords, newest first, kept to prevent their reuse
+    PasswordHistory(Vec<String>),
+    PasswordChangedAt(u64),
+
+    // Account lockout policy of a tenant
+    LockoutPolicy {
+        threshold: u32,
+        window: u64,
+        duration: u64,
+        per_protocol: bool,
+        notify: bool,
+    },
+
+    // Scheduled changes to whether an account can log in
+    DisableAt(u64),
+    EnableAt(u64),
+
+    // Profile picture kept in the blob store
+    Avatar {
+        hash: String,
+        content_type: String,
+    },
+    // Whether members of a tenant can manage their own avatar
+    AvatarSelfService(bool),
+
+    // End of the trial period of a tenant
+    TrialEndsAt(u64),
+
+    // Billing plan of a tenant
+    Plan(String),
+
+    // Protocols an account may log in with, all of them when there are none
+    AllowedProtocol(String),
+
+    // Contact details of a tenant
+    BillingEmail(String),
+    TechnicalContact(String),
+    Phone(String),
+    PostalAddress(String),
+    TaxId(String),
+
+    // Logo of a tenant displayed on dark backgrounds
+    BrandLogoDarkUrl(String),
+
+    // WebAuthn credential, the public key is a COSE key
+    WebAuthnCredential {
+        id: Vec<u8>,
+        public_key: Vec<u8>,
+        rp_id: String,
+        nickname: String,
+        created_at: u64,
+        resident: bool,
+    },
+
+    // Hash of a single-use MFA recovery code
+    RecoveryCode(String),
+
+    // Whether an account, such as a service account, opted out of security notices
+    SecurityNoticeOptOut(bool),
+
+    // Address or @domain pattern a send-only credential submits from, and its
+    // message rate in the `requests/period` syntax
+    SenderAddress(String),
+    SendRate(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -428,6 +548,17 @@ pub enum Permission {
     JmapParticipantIdentityGet,
     JmapParticipantIdentitySet,
     JmapParticipantIdentityChanges,
+
+    AuditList,
+    ScimProvision,
+    PrincipalLegalHold,
+    PrincipalErase,
+    PrincipalExport,
+    PrincipalReservedName,
+    ManageSecondaryEmails,
+    OauthTokenIntrospect,
+    MeteringExport,
+    MailboxStats,
     // TODO: Reuse _ suffixes for new permissions
     // WARNING: add new ids at the end (TODO: use static ids)
 }
@@ -459,6 +590,22 @@ pub struct QueryParams<'x> {
     pub by: QueryBy<'x>,
     pub return_member_of: bool,
     pub only_app_pass: bool,
+    pub protocol: Option<AuthProtocol>,
+    pub password_policy: Option<&'x PasswordPolicy>,
+    pub secret_cipher: Option<&'x dyn SecretCipher>,
+    pub recovery_code_used: Option<&'x AtomicBool>,
+}
+
+/// Protocol credentials are presented over. App passwords may be restricted
+/// to some of them and are never accepted by the management API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthProtocol {
+    Imap,
+    Pop3,
+    Smtp,
+    ManageSieve,
+    Http,
+    Management,
 }
 
 impl Default for Directory {
@@ -569,6 +716,10 @@ impl<'x> QueryParams<'x> {
             by: QueryBy::Name(name),
             return_member_of: false,
             only_app_pass: false,
+            protocol: None,
+            password_policy: None,
+            secret_cipher: None,
+            recovery_code_used: None,
         }
     }
 
@@ -577,6 +728,10 @@ impl<'x> QueryParams<'x> {
             by: QueryBy::Credentials(credentials),
             return_member_of: false,
             only_app_pass: false,
+            protocol: None,
+            password_policy: None,
+            secret_cipher: None,
+            recovery_code_used: None,
         }
     }
 
@@ -585,6 +740,10 @@ impl<'x> QueryParams<'x> {
             by: QueryBy::Id(id),
             return_member_of: false,
             only_app_pass: false,
+            protocol: None,
+            password_policy: None,
+            secret_cipher: None,
+            recovery_code_used: None,
         }
     }
 
@@ -593,6 +752,10 @@ impl<'x> QueryParams<'x> {
             by,
             return_member_of: false,
             only_app_pass: false,
+            protocol: None,
+            password_policy: None,
+            secret_cipher: None,
+            recovery_code_used: None,
         }
     }
 
@@ -605,4 +768,27 @@ impl<'x> QueryParams<'x> {
         self.only_app_pass = only_app_pass;
         self
     }
+
+    pub fn with_protocol(mut self, protocol: Option<AuthProtocol>) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Rejects expired passwords and, when not allowed, app passwords.
+    pub fn with_password_policy(mut self, password_policy: Option<&'x PasswordPolicy>) -> Self {
+        self.password_policy = password_policy;
+        self
+    }
+
+    /// Decrypts the TOTP secrets of the internal directory.
+    pub fn with_secret_cipher(mut self, secret_cipher: Option<&'x dyn SecretCipher>) -> Self {
+        self.secret_cipher = secret_cipher;
+        self
+    }
+
+    /// Flags logins that used up one of the recovery codes of the account.
+    pub fn with_recovery_code_used(mut self, recovery_code_used: Option<&'x AtomicBool>) -> Self {
+        self.recovery_code_used = recovery_code_used;
+        self
+    }
 }
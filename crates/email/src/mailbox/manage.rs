@@ -6,9 +6,17 @@
 
 use super::*;
 use crate::cache::MessageCacheFetch;
-use common::{Server, storage::index::ObjectIndexBuilder};
+use common::{
+    Server,
+    i18n::{self, Locale},
+    storage::index::ObjectIndexBuilder,
+};
+use directory::{Type, backend::internal::manage::ManageDirectory};
 use std::future::Future;
-use store::write::BatchBuilder;
+use store::{
+    ValueKey,
+    write::{AlignedBytes, Archive, BatchBuilder},
+};
 use trc::AddContext;
 use types::collection::Collection;
 
@@ -18,11 +26,19 @@ pub trait MailboxFnc: Sync + Send {
         account_id: u32,
     ) -> impl Future<Output = trc::Result<()>> + Send;
 
+    fn provision_folders(&self, account_id: u32) -> impl Future<Output = trc::Result<()>> + Send;
+
     fn mailbox_create_path(
         &self,
         account_id: u32,
         path: &str,
     ) -> impl Future<Output = trc::Result<Option<u32>>> + Send;
+
+    fn mailbox_share_all(
+        &self,
+        account_id: u32,
+        acls: Vec<AclGrant>,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
 }
 
 impl MailboxFnc for Server {
@@ -32,54 +48,75 @@ impl MailboxFnc for Server {
             return Ok(());
         }
 
-        let mut batch = BatchBuilder::new();
-        batch
-            .with_account_id(account_id)
-            .with_collection(Collection::Mailbox);
-
-        // Create mailboxes
-        let mut last_document_id = ARCHIVE_ID;
-        for folder in &self.core.jmap.default_folders {
-            let document_id = match folder.special_use {
-                SpecialUse::Inbox => INBOX_ID,
-                SpecialUse::Trash => TRASH_ID,
-                SpecialUse::Junk => JUNK_ID,
-                SpecialUse::Drafts => DRAFTS_ID,
-                SpecialUse::Sent => SENT_ID,
-                SpecialUse::Archive => ARCHIVE_ID,
-                SpecialUse::None
-                | SpecialUse::Important
-                | SpecialUse::Memos
-                | SpecialUse::Scheduled
-                | SpecialUse::Snoozed => {
-                    last_document_id += 1;
-                    last_document_id
-                }
-                SpecialUse::Shared => unreachable!(),
-            };
+        create_folders(
+            self,
+            account_id,
+            self.core
+                .jmap
+                .default_folders
+                .iter()
+                .map(|folder| (folder.special_use, folder.name.clone(), folder.subscribe)),
+        )
+        .await
+    }
 
-            let mut object = Mailbox::new(folder.name.clone()).with_role(folder.special_use);
-            if folder.subscribe {
-                object.add_subscriber(account_id);
-            }
-            batch
-                .with_document(document_id)
-                .custom(ObjectIndexBuilder::<(), _>::new().with_changes(object))
-                .caused_by(trc::location!())?;
-        }
-        self.store()
-            .assign_document_ids(account_id, Collection::Mailbox, (ARCHIVE_ID + 1) as u64)
+    async fn provision_folders(&self, account_id: u32) -> trc::Result<()> {
+        // Only the accounts of tenants are provisioned eagerly
+        let Some(tenant_id) = self
+            .store()
+            .get_principal(account_id)
             .await
-            .caused_by(trc::location!())?;
+            .caused_by(trc::location!())?
+            .filter(|principal| principal.typ() == Type::Individual)
+            .and_then(|principal| principal.tenant())
+        else {
+            return Ok(());
+        };
 
-        self.core
-            .storage
-            .data
-            .write(batch.build_all())
+        let folders = self
+            .tenant_folders(tenant_id)
             .await
-            .caused_by(trc::location!())?;
+            .caused_by(trc::location!())?
+            .map(|settings| settings.folders)
+            .unwrap_or_else(|| self.core.jmap.provision_folders.clone());
 
-        Ok(())
+        // Folder names are localized to the tenant's locale
+        let locale = i18n::locale_or_default(
+            self.store()
+                .get_principal(tenant_id)
+                .await
+                .caused_by(trc::location!())?
+                .as_ref()
+                .and_then(|tenant| tenant.locale())
+                .unwrap_or("en"),
+        );
+        let mut mailboxes = Vec::with_capacity(folders.len() + 3);
+        for folder in folders {
+            // The shared folder is virtual and never provisioned
+            let special_use = match folder.special_use() {
+                Ok(SpecialUse::Shared) | Err(_) => continue,
+                Ok(special_use) => special_use,
+            };
+            let name = match (folder.name, special_use) {
+                (Some(name), _) => name,
+                (None, special_use) => match localized_name(locale, special_use) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                },
+            };
+            mailboxes.push((special_use, name, folder.subscribe));
+        }
+
+        // The inbox, trash and junk folders are always created
+        for special_use in [SpecialUse::Inbox, SpecialUse::Trash, SpecialUse::Junk] {
+            if !mailboxes.iter().any(|(role, _, _)| *role == special_use)
+                && let Some(name) = localized_name(locale, special_use)
+            {
+                mailboxes.push((special_use, name.to_string(), true));
+            }
+        }
+
+        create_folders(self, account_id, mailboxes.into_iter()).await
     }
 
     async fn mailbox_create_path(&self, account_id: u32, path: &str) -> trc::Result<Option<u32>> {
@@ -155,4 +192,126 @@ impl MailboxFnc for Server {
 
         Ok(Some(next_parent_id - 1))
     }
+
+    async fn mailbox_share_all(&self, account_id: u32, acls: Vec<AclGrant>) -> trc::Result<()> {
+        let cache = self
+            .get_cached_messages(account_id)
+            .await
+            .caused_by(trc::location!())?;
+
+        // The grants replace any other grant on the account's folders
+        for document_id in cache.mailboxes.items.iter().map(|item| item.document_id) {
+            let Some(current) = self
+                .store()
+                .get_value::<Archive<AlignedBytes>>(ValueKey::archive(
+                    account_id,
+                    Collection::Mailbox,
+                    document_id,
+                ))
+                .await
+                .caused_by(trc::location!())?
+            else {
+                continue;
+            };
+            let current = current
+                .into_deserialized::<Mailbox>()
+                .caused_by(trc::location!())?;
+            if current.inner.acls == acls {
+                continue;
+            }
+
+            let mut mailbox = current.inner.clone();
+            mailbox.acls = acls.clone();
+            self.refresh_acls(&mailbox.acls, Some(current.inner.acls.as_slice()))
+                .await;
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::Mailbox)
+                .with_document(document_id)
+                .custom(
+                    ObjectIndexBuilder::new()
+                        .with_changes(mailbox)
+                        .with_current(current),
+                )
+                .caused_by(trc::location!())?;
+            self.commit_batch(batch).await.caused_by(trc::location!())?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn create_folders(
+    server: &Server,
+    account_id: u32,
+    folders: impl Iterator<Item = (SpecialUse, String, bool)>,
+) -> trc::Result<()> {
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(account_id)
+        .with_collection(Collection::Mailbox);
+
+    // Create mailboxes
+    let mut last_document_id = ARCHIVE_ID;
+    for (special_use, name, subscribe) in folders {
+        let document_id = match special_use {
+            SpecialUse::Inbox => INBOX_ID,
+            SpecialUse::Trash => TRASH_ID,
+            SpecialUse::Junk => JUNK_ID,
+            SpecialUse::Drafts => DRAFTS_ID,
+            SpecialUse::Sent => SENT_ID,
+            SpecialUse::Archive => ARCHIVE_ID,
+            SpecialUse::None
+            | SpecialUse::Important
+            | SpecialUse::Memos
+            | SpecialUse::Scheduled
+            | SpecialUse::Snoozed => {
+                last_document_id += 1;
+                last_document_id
+            }
+            SpecialUse::Shared => unreachable!(),
+        };
+
+        let mut object = Mailbox::new(name).with_role(special_use);
+        if subscribe {
+            object.add_subscriber(account_id);
+        }
+        batch
+            .with_document(document_id)
+            .custom(ObjectIndexBuilder::<(), _>::new().with_changes(object))
+            .caused_by(trc::location!())?;
+    }
+    server
+        .store()
+        .assign_document_ids(
+            account_id,
+            Collection::Mailbox,
+            (last_document_id + 1) as u64,
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+    server
+        .core
+        .storage
+        .data
+        .write(batch.build_all())
+        .await
+        .caused_by(trc::location!())?;
+
+    Ok(())
+}
+
+fn localized_name(locale: &Locale, special_use: SpecialUse) -> Option<&'static str> {
+    match special_use {
+        SpecialUse::Inbox => Some(locale.mailbox_inbox),
+        SpecialUse::Sent => Some(locale.mailbox_sent),
+        SpecialUse::Drafts => Some(locale.mailbox_drafts),
+        SpecialUse::Trash => Some(locale.mailbox_trash),
+        SpecialUse::Junk => Some(locale.mailbox_junk),
+        SpecialUse::Archive => Some(locale.mailbox_archive),
+        _ => None,
+    }
 }
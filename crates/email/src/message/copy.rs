@@ -188,6 +188,7 @@ impl EmailCopy for Server {
             .custom(
                 ObjectIndexBuilder::<(), _>::new()
                     .with_tenant_id(resource_token.tenant.map(|t| t.id))
+                    .with_domain_id(resource_token.domain_id)
                     .with_changes(MessageData {
                         mailboxes: mailbox_ids.into_boxed_slice(),
                         keywords: keywords.into_boxed_slice(),
@@ -26,6 +26,7 @@ pub trait EmailDeletion: Sync + Send {
         &self,
         account_id: u32,
         tenant_id: Option<u32>,
+        domain_id: Option<u32>,
         batch: &mut BatchBuilder,
         document_ids: RoaringBitmap,
     ) -> impl Future<Output = trc::Result<RoaringBitmap>> + Send;
@@ -52,6 +53,7 @@ impl EmailDeletion for Server {
         &self,
         account_id: u32,
         tenant_id: Option<u32>,
+        domain_id: Option<u32>,
         batch: &mut BatchBuilder,
         document_ids: RoaringBitmap,
     ) -> trc::Result<RoaringBitmap> {
@@ -79,6 +81,7 @@ impl EmailDeletion for Server {
                     .custom(
                         ObjectIndexBuilder::<_, ()>::new()
                             .with_tenant_id(tenant_id)
+                            .with_domain_id(domain_id)
                             .with_current(metadata),
                     )
                     .caused_by(trc::location!())?
@@ -263,7 +266,8 @@ impl EmailDeletion for Server {
             .await
             .caused_by(trc::location!())?
             .and_then(|p| p.tenant());
-        self.emails_delete(account_id, tenant_id, &mut batch, destroy_ids)
+        let domain_id = self.account_domain_id(account_id).await?;
+        self.emails_delete(account_id, tenant_id, domain_id, &mut batch, destroy_ids)
             .await?;
         self.commit_batch(batch).await?;
         self.notify_task_queue();
@@ -13,7 +13,7 @@ use common::{
 use directory::Permission;
 use mail_parser::MessageParser;
 use std::{borrow::Cow, future::Future};
-use store::ahash::AHashMap;
+use store::{ahash::AHashMap, write::now};
 use types::blob_hash::BlobHash;
 
 #[derive(Debug)]
@@ -53,6 +53,8 @@ pub struct AutogeneratedMessage {
     pub sender_address: String,
     pub recipients: Vec<String>,
     pub message: Vec<u8>,
+    /// Tenant of the account that redirected an incoming message.
+    pub forwarded_by: Option<u32>,
 }
 
 pub trait MailDelivery: Sync + Send {
@@ -65,13 +67,7 @@ pub trait MailDelivery: Sync + Send {
 impl MailDelivery for Server {
     async fn deliver_message(&self, message: IngestMessage) -> LocalDeliveryResult {
         // Read message
-        let raw_message = match self
-            .core
-            .storage
-            .blob
-            .get_blob(message.message_blob.as_slice(), 0..usize::MAX)
-            .await
-        {
+        let raw_message = match self.get_blob(&message.message_blob, 0..usize::MAX).await {
             Ok(Some(raw_message)) => raw_message,
             Ok(None) => {
                 trc::event!(
@@ -162,9 +158,16 @@ impl MailDelivery for Server {
                     .map(|_| token)
             }) {
                 Ok(access_token) => {
+                    // Scripts assigned by the tenant run before the account's own,
+                    // preceded by the forwarding rule set up by an administrator
+                    let tenant_scripts = self.assigned_sieve_scripts(&access_token);
+                    let forwarding = self
+                        .forwarding_rule(account_id)
+                        .filter(|rule| !rule.is_expired(now()));
+
                     // Check if there is an active sieve script
                     match self.sieve_script_get_active(account_id).await {
-                        Ok(None) => {
+                        Ok(None) if tenant_scripts.is_empty() && forwarding.is_none() => {
                             // Ingest message
                             self.email_ingest(IngestEmail {
                                 raw_message: &raw_message,
@@ -183,7 +186,7 @@ impl MailDelivery for Server {
                             })
                             .await
                         }
-                        Ok(Some(active_script)) => {
+                        Ok(active_script) => {
                             self.sieve_script_ingest(
                                 &access_token,
                                 &message.message_blob,
@@ -193,6 +196,8 @@ impl MailDelivery for Server {
                                 &rcpt,
                                 message.session_id,
                                 active_script,
+                                tenant_scripts,
+                                forwarding,
                                 &mut result.autogenerated,
                             )
                             .await
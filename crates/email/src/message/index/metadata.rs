@@ -101,6 +101,7 @@ impl IndexMessage for BatchBuilder {
     fn index_message<'x>(
         &mut self,
         tenant_id: Option<u32>,
+        domain_id: Option<u32>,
         mut message: mail_parser::Message<'x>,
         extra_headers: Vec<u8>,
         mut extra_headers_parsed: Vec<mail_parser::Header<'x>>,
@@ -236,6 +237,7 @@ impl IndexMessage for BatchBuilder {
         .custom(
             ObjectIndexBuilder::<(), _>::new()
                 .with_tenant_id(tenant_id)
+                .with_domain_id(domain_id)
                 .with_changes(data),
         )
         .caused_by(trc::location!())?
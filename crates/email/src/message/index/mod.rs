@@ -39,6 +39,7 @@ impl IndexableObject for MessageData {
                 },
             },
             IndexValue::Quota { used: self.size },
+            IndexValue::MessageCount,
             IndexValue::LogItem {
                 sync_collection: SyncCollection::Email,
                 prefix: self.thread_id.into(),
@@ -79,6 +80,7 @@ impl IndexableObject for &ArchivedMessageData {
             IndexValue::Quota {
                 used: self.size.to_native(),
             },
+            IndexValue::MessageCount,
             IndexValue::LogItem {
                 sync_collection: SyncCollection::Email,
                 prefix: self.thread_id.to_native().into(),
@@ -101,6 +103,7 @@ pub(super) trait IndexMessage {
     fn index_message<'x>(
         &mut self,
         tenant_id: Option<u32>,
+        domain_id: Option<u32>,
         message: mail_parser::Message<'x>,
         extra_headers: Vec<u8>,
         extra_headers_parsed: Vec<mail_parser::Header<'x>>,
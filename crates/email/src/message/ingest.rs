@@ -14,7 +14,7 @@ use crate::{
         metadata::{MessageData, MessageMetadata},
     },
 };
-use common::{Server, auth::AccessToken};
+use common::{Server, auth::AccessToken, telemetry::metrics::tenant::TenantMetric};
 use directory::Permission;
 use groupware::{
     calendar::itip::{ItipIngest, ItipIngestError},
@@ -552,8 +552,16 @@ impl EmailIngest for Server {
             false
         };
 
-        // Store blob
-        let (blob_hash, blob_hold) = if !is_encrypted && let Some(blob_hash) = params.blob_hash {
+        // Store blob, an existing blob is only reused when it is scoped to
+        // the same tenant as the account
+        let (blob_hash, blob_hold) = if !is_encrypted
+            && let Some(blob_hash) = params.blob_hash
+            && self
+                .account_blob_hash(account_id, raw_message.as_ref())
+                .await
+                .caused_by(trc::location!())?
+                == *blob_hash
+        {
             (blob_hash.clone(), None)
         } else {
             self.put_temporary_blob(account_id, raw_message.as_ref(), 60)
@@ -606,6 +614,7 @@ impl EmailIngest for Server {
             .with_document(document_id)
             .index_message(
                 tenant_id,
+                params.access_token.domain_id(account_id),
                 message,
                 extra_headers.into_bytes(),
                 extra_headers_parsed,
@@ -695,6 +704,11 @@ impl EmailIngest for Server {
             Elapsed = start_time.elapsed(),
         );
 
+        if matches!(params.source, IngestSource::Smtp { .. }) {
+            self.record_tenant_metric(tenant_id, TenantMetric::MessagesReceived, 1);
+            self.record_tenant_metric(tenant_id, TenantMetric::BytesReceived, raw_message_len);
+        }
+
         Ok(IngestedEmail {
             document_id,
             thread_id,
@@ -0,0 +1,110 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::cache::{MessageCacheFetch, email::MessageCacheAccess};
+use common::Server;
+use directory::{QueryParams, backend::internal::lookup::DirectoryStore};
+use std::future::Future;
+use store::{
+    IterateParams, SerializeInfallible, U32_LEN, ValueKey,
+    roaring::RoaringBitmap,
+    write::{
+        BatchBuilder, SearchIndex, TaskEpoch, TaskQueueClass, ValueClass, key::DeserializeBigEndian,
+    },
+};
+use trc::AddContext;
+use types::{collection::Collection, field::EmailField};
+
+// Messages expunged from an account on legal hold keep their metadata and blob
+// links until the hold is released, these messages form the account's hold area.
+pub trait EmailLegalHold: Sync + Send {
+    fn is_legal_hold(&self, account_id: u32) -> impl Future<Output = trc::Result<bool>> + Send;
+
+    fn held_messages(
+        &self,
+        account_id: u32,
+    ) -> impl Future<Output = trc::Result<RoaringBitmap>> + Send;
+
+    fn release_held_messages(
+        &self,
+        account_id: u32,
+    ) -> impl Future<Output = trc::Result<u64>> + Send;
+}
+
+impl EmailLegalHold for Server {
+    async fn is_legal_hold(&self, account_id: u32) -> trc::Result<bool> {
+        self.store()
+            .query(QueryParams::id(account_id).with_return_member_of(false))
+            .await
+            .caused_by(trc::location!())
+            .map(|principal| principal.is_some_and(|p| p.legal_hold().is_some()))
+    }
+
+    async fn held_messages(&self, account_id: u32) -> trc::Result<RoaringBitmap> {
+        let mut document_ids = RoaringBitmap::new();
+        self.store()
+            .iterate(
+                IterateParams::new(
+                    ValueKey::property(account_id, Collection::Email, 0, EmailField::Metadata),
+                    ValueKey::property(
+                        account_id,
+                        Collection::Email,
+                        u32::MAX,
+                        EmailField::Metadata,
+                    ),
+                )
+                .no_values(),
+                |key, _| {
+                    document_ids.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        // Messages without an archive have been expunged
+        if !document_ids.is_empty() {
+            document_ids -= self
+                .get_cached_messages(account_id)
+                .await
+                .caused_by(trc::location!())?
+                .email_document_ids();
+        }
+
+        Ok(document_ids)
+    }
+
+    async fn release_held_messages(&self, account_id: u32) -> trc::Result<u64> {
+        let document_ids = self.held_messages(account_id).await?;
+        if document_ids.is_empty() {
+            return Ok(0);
+        }
+
+        // Queue the pending metadata removals
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Email);
+        for document_id in &document_ids {
+            batch
+                .with_document(document_id)
+                .set(
+                    ValueClass::TaskQueue(TaskQueueClass::UpdateIndex {
+                        index: SearchIndex::Email,
+                        due: TaskEpoch::now(),
+                        is_insert: false,
+                    }),
+                    0u64.serialize(),
+                )
+                .commit_point();
+        }
+        self.commit_batch(batch).await.caused_by(trc::location!())?;
+        self.notify_task_queue();
+
+        Ok(document_ids.len())
+    }
+}
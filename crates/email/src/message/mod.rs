@@ -10,4 +10,6 @@ pub mod delete;
 pub mod delivery;
 pub mod index;
 pub mod ingest;
+pub mod legal_hold;
 pub mod metadata;
+pub mod retention;
@@ -0,0 +1,505 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    delete::EmailDeletion,
+    ingest::EmailIngest,
+    legal_hold::EmailLegalHold,
+    metadata::{MESSAGE_RECEIVED_MASK, MessageData, MessageMetadata},
+};
+use crate::{
+    cache::{MessageCacheFetch, mailbox::MailboxCacheAccess},
+    mailbox::UidMailbox,
+};
+use common::{
+    Server,
+    config::jmap::retention::{RetentionAction, RetentionPolicy, TenantRetention},
+    storage::index::ObjectIndexBuilder,
+};
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use std::{future::Future, time::Instant};
+use store::{
+    Deserialize, IterateParams, Serialize, U32_LEN, ValueKey,
+    ahash::AHashMap,
+    roaring::RoaringBitmap,
+    write::{
+        AlignedBytes, Archive, Archiver, BatchBuilder, ValueClass, key::DeserializeBigEndian, now,
+    },
+};
+use trc::AddContext;
+use types::{
+    collection::{Collection, VanishedCollection},
+    field::{EmailField, PrincipalField},
+    special_use::SpecialUse,
+};
+
+pub const MAX_RETENTION_REPORTS: usize = 30;
+const MAX_REPORT_ERRORS: usize = 100;
+const DAY: u64 = 86400;
+
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionReport {
+    pub id: u64,
+    pub started: u64,
+    pub finished: u64,
+    pub dry_run: bool,
+    /// Number of accounts the policies were applied to.
+    pub accounts: u64,
+    /// Number of accounts skipped because of a legal hold.
+    pub legal_hold: u64,
+    pub policies: Vec<RetentionPolicyReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<RetentionError>,
+}
+
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicyReport {
+    #[serde(flatten)]
+    pub policy: RetentionPolicy,
+    /// Number of accounts with expired messages.
+    pub affected_accounts: u64,
+    /// Number of expired messages.
+    pub affected_messages: u64,
+}
+
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, serde::Serialize)]
+pub struct RetentionError {
+    pub account: String,
+    pub details: String,
+}
+
+pub trait EmailRetention: Sync + Send {
+    fn apply_retention_policies(&self) -> impl Future<Output = ()> + Send;
+
+    fn apply_tenant_retention(
+        &self,
+        tenant_id: u32,
+        retention: &TenantRetention,
+    ) -> impl Future<Output = trc::Result<RetentionReport>> + Send;
+
+    fn retention_reports(
+        &self,
+        tenant_id: u32,
+    ) -> impl Future<Output = trc::Result<Vec<RetentionReport>>> + Send;
+}
+
+impl EmailRetention for Server {
+    async fn apply_retention_policies(&self) {
+        let tenant_ids = match self.store().principal_ids(Some(Type::Tenant), None).await {
+            Ok(tenant_ids) => tenant_ids,
+            Err(err) => {
+                trc::error!(err.details("Failed to obtain tenant ids."));
+                return;
+            }
+        };
+
+        for tenant_id in tenant_ids {
+            match self.tenant_retention(tenant_id).await {
+                Ok(Some(retention)) if !retention.policies.is_empty() => {
+                    if let Err(err) = self.apply_tenant_retention(tenant_id, &retention).await {
+                        trc::error!(
+                            err.details("Failed to apply retention policies.")
+                                .ctx(trc::Key::Id, tenant_id)
+                        );
+                    }
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    trc::error!(
+                        err.details("Failed to obtain retention policies.")
+                            .ctx(trc::Key::Id, tenant_id)
+                    );
+                }
+            }
+        }
+    }
+
+    async fn apply_tenant_retention(
+        &self,
+        tenant_id: u32,
+        retention: &TenantRetention,
+    ) -> trc::Result<RetentionReport> {
+        let start_time = Instant::now();
+        let mut report = RetentionReport {
+            id: self.inner.data.jmap_id_gen.generate(),
+            started: now(),
+            finished: 0,
+            dry_run: retention.dry_run,
+            accounts: 0,
+            legal_hold: 0,
+            policies: retention
+                .policies
+                .iter()
+                .map(|policy| RetentionPolicyReport {
+                    policy: policy.clone(),
+                    affected_accounts: 0,
+                    affected_messages: 0,
+                })
+                .collect(),
+            errors: Vec::new(),
+        };
+
+        // Resolve the principals on legal hold
+        let mut legal_hold = RoaringBitmap::new();
+        for name in &retention.legal_hold {
+            if let Some(principal_id) = self
+                .store()
+                .get_principal_id(name)
+                .await
+                .caused_by(trc::location!())?
+            {
+                legal_hold.insert(principal_id);
+            }
+        }
+
+        let mut account_ids = self
+            .store()
+            .principal_ids(Some(Type::Individual), Some(tenant_id))
+            .await
+            .caused_by(trc::location!())?;
+        account_ids |= self
+            .store()
+            .principal_ids(Some(Type::Group), Some(tenant_id))
+            .await
+            .caused_by(trc::location!())?;
+
+        for account_id in account_ids {
+            if legal_hold.contains(account_id) || self.is_legal_hold(account_id).await? {
+                report.legal_hold += 1;
+                continue;
+            }
+
+            report.accounts += 1;
+            match self
+                .apply_account_retention(account_id, tenant_id, retention)
+                .await
+            {
+                Ok(expired) => {
+                    for (policy, messages) in report.policies.iter_mut().zip(expired) {
+                        if messages > 0 {
+                            policy.affected_accounts += 1;
+                            policy.affected_messages += messages;
+                        }
+                    }
+                }
+                Err(err) => {
+                    if report.errors.len() < MAX_REPORT_ERRORS {
+                        report.errors.push(RetentionError {
+                            account: self
+                                .store()
+                                .get_principal_name(account_id)
+                                .await
+                                .ok()
+                                .flatten()
+                                .unwrap_or_else(|| account_id.to_string()),
+                            details: err
+                                .value_as_str(trc::Key::Details)
+                                .or_else(|| err.value_as_str(trc::Key::Reason))
+                                .map(|details| details.to_string())
+                                .unwrap_or_else(|| err.event_type().description().to_string()),
+                        });
+                    }
+
+                    trc::error!(
+                        err.details("Failed to apply retention policies.")
+                            .account_id(account_id)
+                    );
+                }
+            }
+        }
+        report.finished = now();
+
+        trc::event!(
+            Purge(trc::PurgeEvent::Retention),
+            Id = tenant_id,
+            Total = report
+                .policies
+                .iter()
+                .map(|policy| policy.affected_messages)
+                .sum::<u64>(),
+            Details = if report.dry_run { "dry-run" } else { "enforce" },
+            Elapsed = start_time.elapsed(),
+        );
+
+        // Keep the most recent reports
+        let mut reports = self.retention_reports(tenant_id).await?;
+        reports.insert(0, report.clone());
+        reports.truncate(MAX_RETENTION_REPORTS);
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(tenant_id)
+            .with_collection(Collection::Principal)
+            .with_document(0)
+            .set(
+                PrincipalField::RetentionReports,
+                Archiver::new(reports)
+                    .serialize()
+                    .caused_by(trc::location!())?,
+            );
+        self.store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(report)
+    }
+
+    async fn retention_reports(&self, tenant_id: u32) -> trc::Result<Vec<RetentionReport>> {
+        self.store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::property(
+                tenant_id,
+                Collection::Principal,
+                0,
+                PrincipalField::RetentionReports,
+            ))
+            .await?
+            .map(|archive| archive.deserialize::<Vec<RetentionReport>>())
+            .transpose()
+            .map(|reports| reports.unwrap_or_default())
+    }
+}
+
+trait AccountRetention: Sync + Send {
+    fn apply_account_retention(
+        &self,
+        account_id: u32,
+        tenant_id: u32,
+        retention: &TenantRetention,
+    ) -> impl Future<Output = trc::Result<Vec<u64>>> + Send;
+
+    fn received_dates(
+        &self,
+        account_id: u32,
+        document_ids: &RoaringBitmap,
+    ) -> impl Future<Output = trc::Result<AHashMap<u32, u64>>> + Send;
+}
+
+impl AccountRetention for Server {
+    /// Applies the retention policies to an account, returning the number
+    /// of expired messages for each policy.
+    async fn apply_account_retention(
+        &self,
+        account_id: u32,
+        tenant_id: u32,
+        retention: &TenantRetention,
+    ) -> trc::Result<Vec<u64>> {
+        let cache = self
+            .get_cached_messages(account_id)
+            .await
+            .caused_by(trc::location!())?;
+        let archive_id = cache
+            .mailbox_by_role(&SpecialUse::Archive)
+            .map(|mailbox| mailbox.document_id);
+
+        // Obtain the mailboxes each policy applies to
+        let policy_mailboxes = retention
+            .policies
+            .iter()
+            .map(|policy| {
+                cache
+                    .mailboxes
+                    .items
+                    .iter()
+                    .filter(|mailbox| {
+                        policy.matches(&mailbox.path, mailbox.role)
+                            && (policy.action != RetentionAction::Archive
+                                || Some(mailbox.document_id) != archive_id)
+                    })
+                    .map(|mailbox| mailbox.document_id)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut expired_count = vec![0u64; retention.policies.len()];
+
+        // Obtain the messages in those mailboxes
+        let mut candidates = RoaringBitmap::new();
+        for message in cache.emails.items.iter() {
+            if message.mailboxes.iter().any(|mailbox| {
+                policy_mailboxes
+                    .iter()
+                    .any(|mailboxes| mailboxes.contains(&mailbox.mailbox_id))
+            }) {
+                candidates.insert(message.document_id);
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(expired_count);
+        }
+
+        // Each message is handled by the first policy that expires it
+        let now = now();
+        let received_dates = self.received_dates(account_id, &candidates).await?;
+        let mut expired = Vec::new();
+        for message in cache.emails.items.iter() {
+            let Some(received_at) = received_dates.get(&message.document_id) else {
+                continue;
+            };
+            if let Some((policy_idx, (policy, mailboxes))) = retention
+                .policies
+                .iter()
+                .zip(policy_mailboxes.iter())
+                .enumerate()
+                .find(|(_, (policy, mailboxes))| {
+                    received_at.saturating_add(policy.days.saturating_mul(DAY)) <= now
+                        && message
+                            .mailboxes
+                            .iter()
+                            .any(|mailbox| mailboxes.contains(&mailbox.mailbox_id))
+                })
+            {
+                if policy.action == RetentionAction::Archive && archive_id.is_none() {
+                    return Err(trc::StoreEvent::NotFound
+                        .into_err()
+                        .details("No archive folder found"));
+                }
+
+                expired_count[policy_idx] += 1;
+                expired.push((message.document_id, policy.action, mailboxes));
+            }
+        }
+        if expired.is_empty() || retention.dry_run {
+            return Ok(expired_count);
+        }
+
+        // Remove messages from the expired mailboxes, destroying them when
+        // they do not belong to any other mailbox
+        let mut destroy_ids = RoaringBitmap::new();
+        let mut batch = BatchBuilder::new();
+        for (document_id, action, mailboxes) in expired {
+            let Some(data_) = self
+                .store()
+                .get_value::<Archive<AlignedBytes>>(ValueKey::archive(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                ))
+                .await
+                .caused_by(trc::location!())?
+            else {
+                continue;
+            };
+            let data = data_
+                .to_unarchived::<MessageData>()
+                .caused_by(trc::location!())?;
+            let mut new_data = data.inner.to_builder();
+            for mailbox_id in mailboxes {
+                new_data.remove_mailbox(*mailbox_id);
+            }
+            if let (RetentionAction::Archive, Some(archive_id)) = (action, archive_id) {
+                if !new_data
+                    .mailboxes
+                    .iter()
+                    .any(|mailbox| mailbox.mailbox_id == archive_id)
+                {
+                    let uid = self
+                        .assign_email_ids(account_id, [archive_id], false)
+                        .await
+                        .caused_by(trc::location!())?
+                        .next()
+                        .unwrap_or_default();
+                    new_data.add_mailbox(UidMailbox {
+                        mailbox_id: archive_id,
+                        uid,
+                    });
+                }
+            } else if new_data.mailboxes.is_empty() {
+                destroy_ids.insert(document_id);
+                continue;
+            }
+
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::Email)
+                .with_document(document_id);
+            for mailbox in data.inner.mailboxes.iter() {
+                if mailboxes.contains(&mailbox.mailbox_id.to_native()) {
+                    batch.log_vanished_item(
+                        VanishedCollection::Email,
+                        (mailbox.mailbox_id.to_native(), mailbox.uid.to_native()),
+                    );
+                }
+            }
+            batch
+                .custom(
+                    ObjectIndexBuilder::new()
+                        .with_current(data)
+                        .with_changes(new_data.seal()),
+                )
+                .caused_by(trc::location!())?
+                .commit_point();
+
+            if batch.is_large_batch() {
+                self.commit_batch(batch).await.caused_by(trc::location!())?;
+                batch = BatchBuilder::new();
+            }
+        }
+
+        if !destroy_ids.is_empty() {
+            let domain_id = self.account_domain_id(account_id).await?;
+            self.emails_delete(
+                account_id,
+                Some(tenant_id),
+                domain_id,
+                &mut batch,
+                destroy_ids,
+            )
+            .await?;
+        }
+        if !batch.is_empty() {
+            self.commit_batch(batch).await.caused_by(trc::location!())?;
+            self.notify_task_queue();
+        }
+
+        Ok(expired_count)
+    }
+
+    async fn received_dates(
+        &self,
+        account_id: u32,
+        document_ids: &RoaringBitmap,
+    ) -> trc::Result<AHashMap<u32, u64>> {
+        let mut received_dates = AHashMap::with_capacity(document_ids.len() as usize);
+        self.store()
+            .iterate(
+                IterateParams::new(
+                    ValueKey {
+                        account_id,
+                        collection: Collection::Email.into(),
+                        document_id: document_ids.min().unwrap_or_default(),
+                        class: ValueClass::Property(EmailField::Metadata.into()),
+                    },
+                    ValueKey {
+                        account_id,
+                        collection: Collection::Email.into(),
+                        document_id: document_ids.max().unwrap_or_default(),
+                        class: ValueClass::Property(EmailField::Metadata.into()),
+                    },
+                ),
+                |key, value| {
+                    let document_id = key.deserialize_be_u32(key.len() - U32_LEN)?;
+                    if document_ids.contains(document_id) {
+                        let metadata = <Archive<AlignedBytes> as Deserialize>::deserialize(value)?;
+                        received_dates.insert(
+                            document_id,
+                            metadata
+                                .unarchive::<MessageMetadata>()?
+                                .rcvd_attach
+                                .to_native()
+                                & MESSAGE_RECEIVED_MASK,
+                        );
+                    }
+
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(received_dates)
+    }
+}
@@ -4,7 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use super::{ActiveScript, SeenIdHash, SieveScript};
+use super::{ActiveScript, SeenIdHash, SieveScript, VacationResponse};
 use crate::{
     cache::{MessageCacheFetch, mailbox::MailboxCacheAccess},
     mailbox::{INBOX_ID, TRASH_ID, manage::MailboxFnc},
@@ -13,12 +13,20 @@ use crate::{
         ingest::{EmailIngest, IngestEmail, IngestSource, IngestedEmail},
     },
 };
-use common::{Server, auth::AccessToken, scripts::plugins::PluginContext};
+use common::{
+    Server,
+    auth::AccessToken,
+    config::{
+        overrides::TenantSetting,
+        scripts::{ForwardingRule, TenantSieveScript},
+    },
+    scripts::plugins::PluginContext,
+};
 use directory::QueryParams;
 use mail_parser::MessageParser;
-use sieve::{Envelope, Event, Input, Mailbox, Recipient, Sieve, SpamStatus};
+use sieve::{Compiler, Envelope, Event, Input, Mailbox, Recipient, Sieve, SpamStatus};
 use std::{borrow::Cow, sync::Arc};
-use std::{future::Future, str::FromStr};
+use std::{fmt::Write, future::Future, str::FromStr};
 use store::{
     Deserialize, Serialize, ValueKey,
     ahash::AHashMap,
@@ -38,6 +46,13 @@ use types::{
 };
 use utils::config::utils::ParseValue;
 
+/// Prefix of the names tenant scripts are included with.
+const TENANT_SCRIPT_PREFIX: &str = "tenant/";
+
+/// Name the forwarding rule of an account is included with. It can't clash
+/// with tenant scripts since their names are prefixed.
+const FORWARDING_SCRIPT_NAME: &str = "forwarding";
+
 struct SieveMessage<'x> {
     pub raw_message: Cow<'x, [u8]>,
     pub file_into: Vec<u32>,
@@ -56,7 +71,9 @@ pub trait SieveScriptIngest: Sync + Send {
         envelope_from_authenticated: bool,
         envelope_to: &IngestRecipient,
         session_id: u64,
-        active_script: ActiveScript,
+        active_script: Option<ActiveScript>,
+        tenant_scripts: Vec<Arc<TenantSieveScript>>,
+        forwarding: Option<Arc<ForwardingRule>>,
         autogenerated: &mut Vec<AutogeneratedMessage>,
     ) -> impl Future<Output = trc::Result<IngestedEmail>> + Send;
 
@@ -94,7 +111,9 @@ impl SieveScriptIngest for Server {
         envelope_from_authenticated: bool,
         envelope_to: &IngestRecipient,
         session_id: u64,
-        active_script: ActiveScript,
+        mut active_script: Option<ActiveScript>,
+        tenant_scripts: Vec<Arc<TenantSieveScript>>,
+        forwarding: Option<Arc<ForwardingRule>>,
         autogenerated: &mut Vec<AutogeneratedMessage>,
     ) -> trc::Result<IngestedEmail> {
         // Parse message
@@ -119,6 +138,7 @@ impl SieveScriptIngest for Server {
         let mut instance = self.core.sieve.untrusted_runtime.filter_parsed(message);
 
         // Set account name and email
+        let mut full_name = None;
         let mail_from = self
             .core
             .storage
@@ -127,7 +147,9 @@ impl SieveScriptIngest for Server {
             .await
             .caused_by(trc::location!())?
             .and_then(|p| {
-                instance.set_user_full_name(p.description().unwrap_or_else(|| p.name()));
+                let name = p.description().unwrap_or_else(|| p.name()).to_string();
+                instance.set_user_full_name(&name);
+                full_name = Some(name);
                 p.into_primary_email()
             });
 
@@ -144,10 +166,64 @@ impl SieveScriptIngest for Server {
             SpamStatus::Ham
         });
 
-        let mut input = Input::script(
-            active_script.script_name.to_string(),
-            active_script.script.clone(),
-        );
+        // Build the vacation response using the tenant's template
+        if let (Some(tenant), Some(active_script)) = (
+            &access_token.tenant,
+            active_script
+                .as_mut()
+                .filter(|script| script.vacation_response.is_some()),
+        ) {
+            match tenant_vacation_script(
+                self,
+                tenant.id,
+                active_script.vacation_response.as_ref().unwrap(),
+                full_name.as_deref().unwrap_or(mail_from.as_str()),
+            )
+            .await
+            {
+                Ok(Some(script)) => {
+                    active_script.script = Arc::new(script);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    trc::error!(
+                        err.span_id(session_id)
+                            .details("Failed to apply tenant vacation template")
+                    );
+                }
+            }
+        }
+
+        // Build the forwarding script, which runs before any other script
+        let forwarding_script = if let Some(forwarding) = &forwarding {
+            account_forwarding_script(self, access_token, forwarding, session_id)
+                .await
+                .caused_by(trc::location!())?
+                .map(Arc::new)
+        } else {
+            None
+        };
+
+        let mut input = match &active_script {
+            Some(active_script) if tenant_scripts.is_empty() && forwarding_script.is_none() => {
+                Input::script(
+                    active_script.script_name.to_string(),
+                    active_script.script.clone(),
+                )
+            }
+            _ => Input::script(
+                sieve::Script::Global(TENANT_SCRIPT_PREFIX.to_string()),
+                tenant_script_runner(
+                    &tenant_scripts,
+                    forwarding_script.is_some(),
+                    active_script.as_ref(),
+                )?,
+            ),
+        };
+        let script_hash = active_script
+            .as_ref()
+            .and_then(|active_script| active_script.version.hash())
+            .unwrap_or_default();
 
         let mut do_discard = false;
         let mut do_deliver = false;
@@ -174,7 +250,12 @@ impl SieveScriptIngest for Server {
                 Ok(event) => match event {
                     Event::IncludeScript { name, .. } => match &name {
                         sieve::Script::Personal(name_) => {
-                            if let Ok(Some(script)) =
+                            if let Some(active_script) = active_script
+                                .as_ref()
+                                .filter(|active_script| &active_script.script_name == name_)
+                            {
+                                input = Input::script(name, active_script.script.clone());
+                            } else if let Ok(Some(script)) =
                                 self.sieve_script_get_by_name(account_id, name_).await
                             {
                                 input = Input::script(name, script);
@@ -182,6 +263,23 @@ impl SieveScriptIngest for Server {
                                 input = false.into();
                             }
                         }
+                        sieve::Script::Global(name_) if name_ == FORWARDING_SCRIPT_NAME => {
+                            if let Some(script) = &forwarding_script {
+                                input = Input::script(name, script.clone());
+                            } else {
+                                input = false.into();
+                            }
+                        }
+                        sieve::Script::Global(name_) if name_.starts_with(TENANT_SCRIPT_PREFIX) => {
+                            if let Some(script) = tenant_scripts.iter().find(|script| {
+                                name_.strip_prefix(TENANT_SCRIPT_PREFIX)
+                                    == Some(script.name.as_str())
+                            }) {
+                                input = Input::script(name, script.script.clone());
+                            } else {
+                                input = false.into();
+                            }
+                        }
                         sieve::Script::Global(name_) => {
                             if let Some(script) =
                                 self.get_untrusted_sieve_script(&name_.to_lowercase(), session_id)
@@ -263,11 +361,7 @@ impl SieveScriptIngest for Server {
                         }
                     }
                     Event::DuplicateId { id, expiry, last } => {
-                        let id_hash = SeenIdHash::new(
-                            account_id,
-                            active_script.version.hash().unwrap_or_default(),
-                            &id,
-                        );
+                        let id_hash = SeenIdHash::new(account_id, script_hash, &id);
                         if let Some(result) = checked_ids.get(&id_hash) {
                             input = (*result).into();
                         } else {
@@ -405,7 +499,13 @@ impl SieveScriptIngest for Server {
                                 }
                             };
 
-                            if message.raw_message.len() <= self.core.jmap.mail_max_size {
+                            let mail_max_size = self
+                                .tenant_setting(
+                                    access_token.tenant.map(|tenant| tenant.id),
+                                    TenantSetting::EmailMaxSize,
+                                )
+                                .await as usize;
+                            if message.raw_message.len() <= mail_max_size {
                                 trc::event!(
                                     Sieve(SieveEvent::SendMessage),
                                     From = mail_from.clone(),
@@ -421,6 +521,11 @@ impl SieveScriptIngest for Server {
                                     sender_address: mail_from.clone(),
                                     recipients,
                                     message: message.raw_message.to_vec(),
+                                    forwarded_by: access_token
+                                        .tenant
+                                        .as_ref()
+                                        .filter(|_| message_id == 0)
+                                        .map(|tenant| tenant.id),
                                 });
                             } else {
                                 trc::event!(
@@ -431,7 +536,7 @@ impl SieveScriptIngest for Server {
                                         .map(|r| trc::Value::String(r.as_str().into()))
                                         .collect::<Vec<_>>(),
                                     Size = message.raw_message.len(),
-                                    Limit = self.core.jmap.mail_max_size,
+                                    Limit = mail_max_size,
                                     SpanId = session_id,
                                 );
                             }
@@ -599,6 +704,7 @@ impl SieveScriptIngest for Server {
                     script: Arc::new(script.script),
                     script_name: script.name,
                     version: script.version,
+                    vacation_response: script.vacation_response,
                 }))
             } else {
                 Ok(None)
@@ -658,13 +764,12 @@ impl SieveScriptIngest for Server {
             .unarchive::<SieveScript>()
             .caused_by(trc::location!())?;
         let script_offset = u32::from(unarchived_script.size) as usize;
+        let vacation_response: Option<VacationResponse> =
+            rkyv::deserialize(&unarchived_script.vacation_response).caused_by(trc::location!())?;
 
         // Obtain the sieve script blob
         let script_bytes = self
-            .core
-            .storage
-            .blob
-            .get_blob(unarchived_script.blob_hash.0.as_ref(), 0..usize::MAX)
+            .get_blob(&BlobHash::from(&unarchived_script.blob_hash), 0..usize::MAX)
             .await
             .caused_by(trc::location!())?
             .ok_or_else(|| {
@@ -685,6 +790,7 @@ impl SieveScriptIngest for Server {
                 script,
                 name: unarchived_script.name.as_str().into(),
                 version,
+                vacation_response,
             }))
         } else {
             // Deserialization failed, probably because the script compiler version changed
@@ -747,6 +853,7 @@ impl SieveScriptIngest for Server {
                         script: sieve.into_inner(),
                         name: new_archive.into_inner().name,
                         version,
+                        vacation_response,
                     }))
                 }
                 Err(error) => Err(trc::StoreEvent::UnexpectedError
@@ -758,8 +865,142 @@ impl SieveScriptIngest for Server {
     }
 }
 
+/// Rebuilds the vacation script of an account with the current template of
+/// its tenant, if the tenant has one.
+async fn tenant_vacation_script(
+    server: &Server,
+    tenant_id: u32,
+    vacation_response: &VacationResponse,
+    name: &str,
+) -> trc::Result<Option<Sieve>> {
+    let Some(template) = server
+        .tenant_vacation_template(tenant_id)
+        .await
+        .caused_by(trc::location!())?
+    else {
+        return Ok(None);
+    };
+
+    server
+        .core
+        .sieve
+        .untrusted_compiler
+        .compile(&vacation_response.build_script(Some(&template), name))
+        .map(Some)
+        .map_err(|err| {
+            trc::SieveEvent::UnexpectedError
+                .into_err()
+                .caused_by(trc::location!())
+                .reason(err)
+                .details("Vacation Sieve Script failed to compile.")
+        })
+}
+
+/// Builds the forwarding script of an account. When the tenant does not
+/// allow forwarding outside its domains, external addresses are skipped and
+/// the message is delivered normally if none are left.
+async fn account_forwarding_script(
+    server: &Server,
+    access_token: &AccessToken,
+    forwarding: &ForwardingRule,
+    session_id: u64,
+) -> trc::Result<Option<Sieve>> {
+    let mut addresses = forwarding
+        .addresses
+        .iter()
+        .map(|address| address.as_str())
+        .collect::<Vec<_>>();
+    if let Some(tenant) = &access_token.tenant
+        && !server
+            .tenant_forwarding_policy(tenant.id)
+            .await
+            .caused_by(trc::location!())?
+            .allow_external
+    {
+        let mut allowed = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            if !server
+                .is_external_address(tenant.id, address)
+                .await
+                .caused_by(trc::location!())?
+            {
+                allowed.push(address);
+            } else {
+                trc::event!(
+                    Sieve(SieveEvent::NotSupported),
+                    Details = "External forwarding is not allowed by the tenant",
+                    To = address.to_string(),
+                    SpanId = session_id,
+                );
+            }
+        }
+        addresses = allowed;
+    }
+    if addresses.is_empty() {
+        return Ok(None);
+    }
+
+    server
+        .core
+        .sieve
+        .untrusted_compiler
+        .compile(forwarding.build_script(&addresses).as_bytes())
+        .map(Some)
+        .map_err(|err| {
+            trc::SieveEvent::UnexpectedError
+                .into_err()
+                .caused_by(trc::location!())
+                .reason(err)
+                .details("Forwarding Sieve script failed to compile.")
+        })
+}
+
+/// Builds the script that runs the forwarding rule and the tenant scripts
+/// assigned to an account before its active script. Since they are executed
+/// as includes, all the scripts share the implicit keep and a `stop` in a
+/// tenant script also prevents the account's own script from running.
+fn tenant_script_runner(
+    tenant_scripts: &[Arc<TenantSieveScript>],
+    has_forwarding: bool,
+    active_script: Option<&ActiveScript>,
+) -> trc::Result<Sieve> {
+    let mut script = String::from("require \"include\";\n");
+    if has_forwarding {
+        let _ = writeln!(script, "include :global \"{FORWARDING_SCRIPT_NAME}\";");
+    }
+    for tenant_script in tenant_scripts {
+        let _ = writeln!(
+            script,
+            "include :global \"{TENANT_SCRIPT_PREFIX}{}\";",
+            tenant_script.name
+        );
+    }
+    if let Some(active_script) = active_script {
+        let _ = writeln!(
+            script,
+            "include :personal \"{}\";",
+            active_script
+                .script_name
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+        );
+    }
+
+    Compiler::new()
+        .with_max_includes(tenant_scripts.len() + 2)
+        .compile(script.as_bytes())
+        .map_err(|err| {
+            trc::SieveEvent::UnexpectedError
+                .into_err()
+                .caused_by(trc::location!())
+                .reason(err)
+                .details("Failed to compile tenant Sieve scripts")
+        })
+}
+
 pub struct CompiledScript {
     pub script: Sieve,
     pub name: String,
     pub version: ArchiveVersion,
+    pub vacation_response: Option<VacationResponse>,
 }
@@ -13,6 +13,7 @@ use types::blob_hash::BlobHash;
 pub mod delete;
 pub mod index;
 pub mod ingest;
+pub mod vacation;
 
 #[derive(Debug, Clone)]
 pub struct ActiveScript {
@@ -20,6 +21,7 @@ pub struct ActiveScript {
     pub version: ArchiveVersion,
     pub script_name: String,
     pub script: Arc<Sieve>,
+    pub vacation_response: Option<VacationResponse>,
 }
 
 #[derive(
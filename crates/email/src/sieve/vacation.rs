@@ -0,0 +1,132 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::VacationResponse;
+use common::config::scripts::{VacationReply, VacationTemplate, VacationVariables};
+use mail_builder::MessageBuilder;
+use mail_parser::{DateTime, decoders::html::html_to_text};
+use std::borrow::Cow;
+
+impl VacationResponse {
+    /// Builds the source of the Sieve script that sends the vacation response,
+    /// applying the tenant's template when provided. The `name` of the account
+    /// is used to fill the template's placeholders.
+    pub fn build_script(&self, template: Option<&VacationTemplate>, name: &str) -> Vec<u8> {
+        let mut script = Vec::with_capacity(1024);
+        script.extend_from_slice(b"require [\"vacation\", \"relational\", \"date\"];\r\n\r\n");
+        let mut num_blocks = 0;
+
+        // Add start date
+        if let Some(value) = self.from_date {
+            script.extend_from_slice(b"if currentdate :value \"ge\" \"iso8601\" \"");
+            script.extend_from_slice(
+                DateTime::from_timestamp(value as i64)
+                    .to_rfc3339()
+                    .as_bytes(),
+            );
+            script.extend_from_slice(b"\" {\r\n");
+            num_blocks += 1;
+        }
+
+        // Add end date
+        if let Some(value) = self.to_date {
+            script.extend_from_slice(b"if currentdate :value \"le\" \"iso8601\" \"");
+            script.extend_from_slice(
+                DateTime::from_timestamp(value as i64)
+                    .to_rfc3339()
+                    .as_bytes(),
+            );
+            script.extend_from_slice(b"\" {\r\n");
+            num_blocks += 1;
+        }
+
+        // Apply template
+        let reply = if let Some(template) = template {
+            let from = self.from_date.map(format_date);
+            let to = self.to_date.map(format_date);
+            template.apply(
+                self.subject.as_deref(),
+                self.text_body.as_deref(),
+                self.html_body.as_deref(),
+                &VacationVariables {
+                    name,
+                    from: from.as_deref(),
+                    to: to.as_deref(),
+                },
+            )
+        } else {
+            VacationReply {
+                subject: self.subject.clone(),
+                text_body: self.text_body.clone(),
+                html_body: self.html_body.clone(),
+            }
+        };
+
+        script.extend_from_slice(b"vacation :mime ");
+        if let Some(value) = &reply.subject {
+            script.extend_from_slice(b":subject \"");
+            for &ch in value.as_bytes().iter() {
+                match ch {
+                    b'\\' | b'\"' => {
+                        script.push(b'\\');
+                    }
+                    b'\r' | b'\n' => {
+                        continue;
+                    }
+                    _ => (),
+                }
+                script.push(ch);
+            }
+            script.extend_from_slice(b"\" ");
+        }
+
+        let mut text_body = reply.text_body.as_deref().map(Cow::from);
+        let html_body = reply.html_body.as_deref().map(Cow::from);
+        match (&html_body, &text_body) {
+            (Some(html_body), None) => {
+                text_body = Cow::from(html_to_text(html_body.as_ref())).into();
+            }
+            (None, None) => {
+                text_body = Cow::from("I am away.").into();
+            }
+            _ => (),
+        }
+
+        let mut builder = MessageBuilder::new();
+        let mut body_len = 0;
+        if let Some(html_body) = html_body {
+            body_len = html_body.len();
+            builder = builder.html_body(html_body);
+        }
+        if let Some(text_body) = text_body {
+            body_len += text_body.len();
+            builder = builder.text_body(text_body);
+        }
+        let mut message_body = Vec::with_capacity(body_len + 128);
+        builder.write_body(&mut message_body).ok();
+
+        script.push(b'\"');
+        for ch in message_body {
+            if [b'\\', b'\"'].contains(&ch) {
+                script.push(b'\\');
+            }
+            script.push(ch);
+        }
+        script.extend_from_slice(b"\";\r\n");
+
+        // Close blocks
+        for _ in 0..num_blocks {
+            script.extend_from_slice(b"}\r\n");
+        }
+
+        script
+    }
+}
+
+fn format_date(timestamp: u64) -> String {
+    let date = DateTime::from_timestamp(timestamp as i64);
+    format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)
+}
@@ -0,0 +1,538 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{
+    cache::GroupwareCache,
+    calendar::{CalendarEvent, CalendarEventData},
+    scheduling::{
+        ItipError, ItipMessage, ItipMessages, ItipSummary, event_update::itip_update,
+        snapshot::itip_snapshot,
+    },
+};
+use calcard::{
+    common::timezone::Tz,
+    icalendar::{
+        ArchivedICalendarComponentType, ArchivedICalendarStatus, ICalendar, ICalendarParameter,
+        ICalendarParameterName, ICalendarParameterValue, ICalendarParticipationStatus,
+        ICalendarProperty, ICalendarTransparency,
+    },
+};
+use common::{
+    Server,
+    auth::AccessToken,
+    config::groupware::{BookableResource, BookingPolicy},
+};
+use store::{
+    ValueKey,
+    write::{AlignedBytes, Archive, BatchBuilder, now, serialize::rkyv_deserialize},
+};
+use trc::AddContext;
+use types::{
+    TimeRange,
+    collection::{Collection, SyncCollection},
+    field::CalendarEventField,
+};
+
+/// Event in the calendar of a bookable resource.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Booking {
+    pub uid: String,
+    pub summary: Option<String>,
+    pub organizer: String,
+    pub start: i64,
+    pub end: i64,
+    pub part_stat: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookingAnswer {
+    Answered,
+    Conflict,
+    NotFound,
+}
+
+pub trait ResourceBooking: Sync + Send {
+    fn resource_booking_process(
+        &self,
+        access_token: &AccessToken,
+        resource: &BookableResource,
+        document_id: u32,
+        request: &ICalendar,
+    ) -> impl Future<Output = trc::Result<Option<ItipMessage<ICalendar>>>> + Send;
+
+    fn resource_booking_conflicts(
+        &self,
+        access_token: &AccessToken,
+        document_id: u32,
+    ) -> impl Future<Output = trc::Result<bool>> + Send;
+
+    fn resource_booking_respond(
+        &self,
+        access_token: &AccessToken,
+        document_id: u32,
+        part_stat: ICalendarParticipationStatus,
+        batch: &mut BatchBuilder,
+    ) -> impl Future<Output = trc::Result<Option<ItipMessage<ICalendar>>>> + Send;
+
+    fn resource_booking_answer(
+        &self,
+        access_token: &AccessToken,
+        uid: &str,
+        accept: bool,
+    ) -> impl Future<Output = trc::Result<BookingAnswer>> + Send;
+
+    fn resource_bookings(
+        &self,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<Vec<Booking>>> + Send;
+}
+
+impl ResourceBooking for Server {
+    // Bookings that conflict with an accepted one are declined right away,
+    // free slots are either accepted or left for the approver to answer.
+    async fn resource_booking_process(
+        &self,
+        access_token: &AccessToken,
+        resource: &BookableResource,
+        document_id: u32,
+        request: &ICalendar,
+    ) -> trc::Result<Option<ItipMessage<ICalendar>>> {
+        let part_stat = if self
+            .resource_booking_conflicts(access_token, document_id)
+            .await?
+        {
+            ICalendarParticipationStatus::Declined
+        } else if resource.policy == BookingPolicy::AutoAccept {
+            ICalendarParticipationStatus::Accepted
+        } else {
+            return booking_approval_request(self, access_token, resource, request).await;
+        };
+
+        let mut batch = BatchBuilder::new();
+        let message = self
+            .resource_booking_respond(access_token, document_id, part_stat, &mut batch)
+            .await?;
+        if !batch.is_empty() {
+            self.commit_batch(batch).await.caused_by(trc::location!())?;
+        }
+
+        Ok(message)
+    }
+
+    async fn resource_booking_conflicts(
+        &self,
+        access_token: &AccessToken,
+        document_id: u32,
+    ) -> trc::Result<bool> {
+        let account_id = access_token.primary_id;
+        let resources = self
+            .fetch_dav_resources(access_token, account_id, SyncCollection::Calendar)
+            .await
+            .caused_by(trc::location!())?;
+        let Some((start, end)) = resources
+            .resources
+            .iter()
+            .find(|resource| resource.document_id == document_id && !resource.is_container())
+            .and_then(|resource| resource.event_time_range())
+        else {
+            return Ok(false);
+        };
+        let Some(booking) = self
+            .store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::archive(
+                account_id,
+                Collection::CalendarEvent,
+                document_id,
+            ))
+            .await
+            .caused_by(trc::location!())?
+        else {
+            return Ok(false);
+        };
+        let booking = booking
+            .unarchive::<CalendarEvent>()
+            .caused_by(trc::location!())?;
+
+        for resource in resources
+            .resources
+            .iter()
+            .filter(|resource| resource.document_id != document_id && !resource.is_container())
+        {
+            let Some((other_start, other_end)) = resource
+                .event_time_range()
+                .filter(|(other_start, other_end)| *other_start < end && *other_end > start)
+            else {
+                continue;
+            };
+            let Some(archive) = self
+                .store()
+                .get_value::<Archive<AlignedBytes>>(ValueKey::archive(
+                    account_id,
+                    Collection::CalendarEvent,
+                    resource.document_id,
+                ))
+                .await
+                .caused_by(trc::location!())?
+            else {
+                continue;
+            };
+            let event = archive
+                .unarchive::<CalendarEvent>()
+                .caused_by(trc::location!())?;
+
+            // Events declined by the resource or still waiting for an answer
+            // do not block the slot
+            let ical =
+                rkyv_deserialize::<_, ICalendar>(&event.data.event).caused_by(trc::location!())?;
+            if resource_part_stat(&ical, &access_token.emails).is_some_and(|part_stat| {
+                !matches!(
+                    part_stat,
+                    ICalendarParticipationStatus::Accepted
+                        | ICalendarParticipationStatus::Tentative
+                )
+            }) {
+                continue;
+            }
+
+            // Only opaque events that are not cancelled take up time
+            let busy_ids = event
+                .data
+                .event
+                .components
+                .iter()
+                .enumerate()
+                .filter(|(_, comp)| {
+                    matches!(comp.component_type, ArchivedICalendarComponentType::VEvent)
+                        && comp
+                            .transparency()
+                            .is_none_or(|t| t == &ICalendarTransparency::Opaque)
+                        && !matches!(comp.status(), Some(ArchivedICalendarStatus::Cancelled))
+                })
+                .map(|(comp_id, _)| comp_id as u32)
+                .collect::<Vec<_>>();
+            if busy_ids.is_empty() {
+                continue;
+            }
+
+            let range = TimeRange {
+                start: start.max(other_start),
+                end: end.min(other_end),
+            };
+            let (Some(requested), Some(booked)) = (
+                booking.data.expand(Tz::UTC, range),
+                event.data.expand(Tz::UTC, range),
+            ) else {
+                continue;
+            };
+            if requested.iter().any(|requested| {
+                booked.iter().any(|booked| {
+                    busy_ids.contains(&booked.comp_id)
+                        && requested.start < booked.end
+                        && booked.start < requested.end
+                })
+            }) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn resource_booking_respond(
+        &self,
+        access_token: &AccessToken,
+        document_id: u32,
+        part_stat: ICalendarParticipationStatus,
+        batch: &mut BatchBuilder,
+    ) -> trc::Result<Option<ItipMessage<ICalendar>>> {
+        let account_id = access_token.primary_id;
+        let Some(archive) = self
+            .store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::archive(
+                account_id,
+                Collection::CalendarEvent,
+                document_id,
+            ))
+            .await
+            .caused_by(trc::location!())?
+        else {
+            return Ok(None);
+        };
+        let event = archive
+            .to_unarchived::<CalendarEvent>()
+            .caused_by(trc::location!())?;
+        let mut new_event = event
+            .deserialize::<CalendarEvent>()
+            .caused_by(trc::location!())?;
+        let old_ical = new_event.data.event.clone();
+
+        let mut did_change = false;
+        for component in &mut new_event.data.event.components {
+            if !component.component_type.is_scheduling_object() {
+                continue;
+            }
+            for entry in &mut component.entries {
+                if entry.name != ICalendarProperty::Attendee
+                    || !entry.calendar_address().is_some_and(|address| {
+                        access_token
+                            .emails
+                            .iter()
+                            .any(|email| email.eq_ignore_ascii_case(address))
+                    })
+                {
+                    continue;
+                }
+
+                let mut add_partstat = true;
+                for param in &mut entry.params {
+                    if let (
+                        ICalendarParameterName::Partstat,
+                        ICalendarParameterValue::Partstat(current),
+                    ) = (&param.name, &mut param.value)
+                    {
+                        if current != &part_stat {
+                            *current = part_stat.clone();
+                            did_change = true;
+                        }
+                        add_partstat = false;
+                    }
+                }
+                if add_partstat {
+                    entry
+                        .params
+                        .push(ICalendarParameter::partstat(part_stat.clone()));
+                    did_change = true;
+                }
+            }
+        }
+        if !did_change {
+            return Ok(None);
+        }
+
+        // Build the reply to the organizer
+        let message = match itip_update(
+            &mut new_event.data.event,
+            &old_ical,
+            access_token.emails.as_slice(),
+        ) {
+            Ok(messages) => messages.into_iter().next(),
+            Err(ItipError::NothingToSend) => None,
+            Err(err) => {
+                trc::event!(
+                    Calendar(trc::CalendarEvent::ItipMessageError),
+                    AccountId = account_id,
+                    DocumentId = document_id,
+                    Details = err.to_string(),
+                );
+                None
+            }
+        };
+
+        let mut next_email_alarm = None;
+        new_event.data = CalendarEventData::new(
+            new_event.data.event,
+            Tz::Floating,
+            self.core.groupware.max_ical_instances,
+            &mut next_email_alarm,
+        );
+        new_event.size = new_event.data.event.to_string().len() as u32;
+        new_event
+            .update(access_token, event, account_id, document_id, batch)
+            .caused_by(trc::location!())?;
+
+        Ok(message)
+    }
+
+    // Approvers answer pending bookings, and may also cancel accepted ones
+    async fn resource_booking_answer(
+        &self,
+        access_token: &AccessToken,
+        uid: &str,
+        accept: bool,
+    ) -> trc::Result<BookingAnswer> {
+        let Some(document_id) = self
+            .document_ids_matching(
+                access_token.primary_id,
+                Collection::CalendarEvent,
+                CalendarEventField::Uid,
+                uid.as_bytes(),
+            )
+            .await
+            .caused_by(trc::location!())?
+            .iter()
+            .next()
+        else {
+            return Ok(BookingAnswer::NotFound);
+        };
+
+        let part_stat = if !accept {
+            ICalendarParticipationStatus::Declined
+        } else if !self
+            .resource_booking_conflicts(access_token, document_id)
+            .await?
+        {
+            ICalendarParticipationStatus::Accepted
+        } else {
+            return Ok(BookingAnswer::Conflict);
+        };
+
+        let mut batch = BatchBuilder::new();
+        if let Some(message) = self
+            .resource_booking_respond(access_token, document_id, part_stat, &mut batch)
+            .await?
+        {
+            ItipMessages::new(vec![message])
+                .queue(&mut batch)
+                .caused_by(trc::location!())?;
+        }
+        if !batch.is_empty() {
+            self.commit_batch(batch).await.caused_by(trc::location!())?;
+            self.notify_task_queue();
+        }
+
+        Ok(BookingAnswer::Answered)
+    }
+
+    async fn resource_bookings(&self, access_token: &AccessToken) -> trc::Result<Vec<Booking>> {
+        let account_id = access_token.primary_id;
+        let resources = self
+            .fetch_dav_resources(access_token, account_id, SyncCollection::Calendar)
+            .await
+            .caused_by(trc::location!())?;
+        let now = now() as i64;
+        let mut bookings = Vec::new();
+
+        for resource in resources
+            .resources
+            .iter()
+            .filter(|resource| !resource.is_container())
+        {
+            let Some((start, end)) = resource.event_time_range().filter(|(_, end)| *end > now)
+            else {
+                continue;
+            };
+            let Some(archive) = self
+                .store()
+                .get_value::<Archive<AlignedBytes>>(ValueKey::archive(
+                    account_id,
+                    Collection::CalendarEvent,
+                    resource.document_id,
+                ))
+                .await
+                .caused_by(trc::location!())?
+            else {
+                continue;
+            };
+            let ical = rkyv_deserialize::<_, ICalendar>(
+                &archive
+                    .unarchive::<CalendarEvent>()
+                    .caused_by(trc::location!())?
+                    .data
+                    .event,
+            )
+            .caused_by(trc::location!())?;
+            let (Some(part_stat), Ok(snapshots)) = (
+                resource_part_stat(&ical, &access_token.emails),
+                itip_snapshot(&ical, access_token.emails.as_slice(), false),
+            ) else {
+                continue;
+            };
+
+            bookings.push(Booking {
+                uid: snapshots.uid.to_string(),
+                summary: ical
+                    .components
+                    .iter()
+                    .filter(|component| component.component_type.is_scheduling_object())
+                    .flat_map(|component| component.entries.iter())
+                    .find(|entry| entry.name == ICalendarProperty::Summary)
+                    .and_then(|entry| entry.values.first())
+                    .and_then(|value| value.as_text())
+                    .map(|summary| summary.to_string()),
+                organizer: snapshots.organizer.email.email.clone(),
+                start,
+                end,
+                part_stat: part_stat.as_str().to_string(),
+            });
+        }
+        bookings.sort_unstable_by_key(|booking| booking.start);
+
+        Ok(bookings)
+    }
+}
+
+/// Sends the request to the approver of the resource, the booking stays
+/// pending until the approver answers it.
+async fn booking_approval_request(
+    server: &Server,
+    access_token: &AccessToken,
+    resource: &BookableResource,
+    request: &ICalendar,
+) -> trc::Result<Option<ItipMessage<ICalendar>>> {
+    let (Some(approver_id), Some(from)) = (resource.approver_id, access_token.emails.first())
+    else {
+        return Ok(None);
+    };
+    let Some(to) = server
+        .get_access_token(approver_id)
+        .await
+        .caused_by(trc::location!())?
+        .emails
+        .first()
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    let Ok(snapshots) = itip_snapshot(request, access_token.emails.as_slice(), false) else {
+        return Ok(None);
+    };
+
+    Ok(Some(ItipMessage {
+        from: from.clone(),
+        from_organizer: false,
+        to: vec![to],
+        summary: ItipSummary::Invite(
+            snapshots
+                .main_instance_or_default()
+                .build_summary(Some(&snapshots.organizer), &[]),
+        ),
+        message: request.clone(),
+    }))
+}
+
+/// Returns the participation status of the resource in an event, if it was
+/// invited to it.
+pub fn resource_part_stat(
+    ical: &ICalendar,
+    emails: &[String],
+) -> Option<ICalendarParticipationStatus> {
+    ical.components
+        .iter()
+        .filter(|component| component.component_type.is_scheduling_object())
+        .flat_map(|component| component.entries.iter())
+        .find(|entry| {
+            entry.name == ICalendarProperty::Attendee
+                && entry.calendar_address().is_some_and(|address| {
+                    emails
+                        .iter()
+                        .any(|email| email.eq_ignore_ascii_case(address))
+                })
+        })
+        .map(|entry| {
+            entry
+                .params
+                .iter()
+                .find_map(|param| match (&param.name, &param.value) {
+                    (
+                        ICalendarParameterName::Partstat,
+                        ICalendarParameterValue::Partstat(part_stat),
+                    ) => Some(part_stat.clone()),
+                    _ => None,
+                })
+                .unwrap_or(ICalendarParticipationStatus::NeedsAction)
+        })
+}
@@ -10,6 +10,7 @@ use crate::{
     calendar::{
         CalendarEvent, CalendarEventData, CalendarEventNotification, ChangedBy,
         EVENT_NOTIFICATION_IS_CHANGE,
+        booking::{ResourceBooking, resource_part_stat},
     },
     scheduling::{
         ItipError, ItipMessage,
@@ -195,6 +196,16 @@ impl ItipIngest for Server {
                         // Merge changes
                         itip_merge_changes(&mut event.data.event, changes);
 
+                        // Rescheduled bookings of a resource are answered again
+                        let booking = self
+                            .bookable_resource(account_id)
+                            .filter(|_| {
+                                is_organizer_update
+                                    && resource_part_stat(&event.data.event, &access_token.emails)
+                                        == Some(ICalendarParticipationStatus::NeedsAction)
+                            })
+                            .map(|resource| (resource, itip.clone()));
+
                         // Calculate the new ical size
                         event.size = event.data.event.to_string().len() as u32;
                         if event.size > self.core.groupware.max_ical_size as u32 {
@@ -268,7 +279,18 @@ impl ItipIngest for Server {
                             .caused_by(trc::location!())?;
                         self.commit_batch(batch).await.caused_by(trc::location!())?;
 
-                        Ok(None)
+                        if let Some((resource, request)) = booking {
+                            self.resource_booking_process(
+                                access_token,
+                                &resource,
+                                document_id,
+                                &request,
+                            )
+                            .await
+                            .map_err(Into::into)
+                        } else {
+                            Ok(None)
+                        }
                     }
                     MergeResult::Message(itip_message) => Ok(Some(itip_message)),
                     MergeResult::None => Ok(None),
@@ -347,6 +369,9 @@ impl ItipIngest for Server {
                 .assign_document_ids(account_id, Collection::CalendarEventNotification, 1)
                 .await
                 .caused_by(trc::location!())?;
+            let booking = self
+                .bookable_resource(account_id)
+                .map(|resource| (resource, itip.clone()));
             let itip_message = CalendarEventNotification {
                 event: itip,
                 event_id: Some(document_id),
@@ -371,7 +396,14 @@ impl ItipIngest for Server {
                 .caused_by(trc::location!())?;
             self.commit_batch(batch).await.caused_by(trc::location!())?;
 
-            Ok(None)
+            // Rooms and equipment answer booking requests on their own
+            if let Some((resource, request)) = booking {
+                self.resource_booking_process(access_token, &resource, document_id, &request)
+                    .await
+                    .map_err(Into::into)
+            } else {
+                Ok(None)
+            }
         }
     }
 
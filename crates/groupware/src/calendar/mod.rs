@@ -5,6 +5,7 @@
  */
 
 pub mod alarm;
+pub mod booking;
 pub mod dates;
 pub mod expand;
 pub mod index;
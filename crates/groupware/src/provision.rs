@@ -0,0 +1,297 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{
+    cache::GroupwareCache,
+    calendar::{Calendar, CalendarPreferences},
+    contact::{AddressBook, AddressBookPreferences},
+};
+use common::{Server, auth::AccessToken, config::groupware::ProvisionCollection};
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use store::{
+    ValueKey,
+    ahash::AHashMap,
+    write::{AlignedBytes, Archive, BatchBuilder},
+};
+use trc::AddContext;
+use types::{
+    acl::{Acl, AclGrant},
+    collection::{Collection, SyncCollection},
+};
+use utils::map::bitmap::Bitmap;
+
+pub trait CollectionProvisioning: Sync + Send {
+    fn provision_collections(
+        &self,
+        account_id: u32,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+
+    fn provision_tenant_collections(
+        &self,
+        tenant_id: u32,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl CollectionProvisioning for Server {
+    async fn provision_collections(&self, account_id: u32) -> trc::Result<()> {
+        let Some(principal) = self
+            .store()
+            .get_principal(account_id)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|principal| principal.typ() == Type::Individual)
+        else {
+            return Ok(());
+        };
+        let access_token = self
+            .get_access_token(account_id)
+            .await
+            .caused_by(trc::location!())?;
+
+        // Building the caches creates the default calendar and address book
+        for collection in [SyncCollection::Calendar, SyncCollection::AddressBook] {
+            self.fetch_dav_resources(&access_token, account_id, collection)
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        let Some(tenant_id) = principal.tenant() else {
+            return Ok(());
+        };
+        let Some(collections) = self
+            .tenant_collections(tenant_id)
+            .await
+            .caused_by(trc::location!())?
+        else {
+            return Ok(());
+        };
+
+        for (collection, items) in [
+            (Collection::Calendar, &collections.calendars),
+            (Collection::AddressBook, &collections.address_books),
+        ] {
+            // Personal collections are created once, by name
+            let mut names = collection_names(self, account_id, collection).await?;
+            for item in items.iter().filter(|item| !item.shared) {
+                if !names.contains_key(&item.name) {
+                    let document_id =
+                        create_collection(self, &access_token, account_id, collection, item)
+                            .await?;
+                    names.insert(item.name.clone(), document_id);
+                }
+            }
+
+            // Shared collections belong to the tenant, which grants read access
+            for document_id in shared_collections(self, tenant_id, collection, items).await? {
+                share_collection(self, tenant_id, collection, document_id, account_id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn provision_tenant_collections(&self, tenant_id: u32) -> trc::Result<()> {
+        if let Some(collections) = self
+            .tenant_collections(tenant_id)
+            .await
+            .caused_by(trc::location!())?
+        {
+            shared_collections(
+                self,
+                tenant_id,
+                Collection::Calendar,
+                &collections.calendars,
+            )
+            .await?;
+            shared_collections(
+                self,
+                tenant_id,
+                Collection::AddressBook,
+                &collections.address_books,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the ids of the shared collections of a tenant, creating the
+/// ones that do not exist yet.
+async fn shared_collections(
+    server: &Server,
+    tenant_id: u32,
+    collection: Collection,
+    items: &[ProvisionCollection],
+) -> trc::Result<Vec<u32>> {
+    let mut document_ids = Vec::new();
+    if !items.iter().any(|item| item.shared) {
+        return Ok(document_ids);
+    }
+
+    let access_token = server
+        .get_access_token(tenant_id)
+        .await
+        .caused_by(trc::location!())?;
+    let names = collection_names(server, tenant_id, collection).await?;
+    for item in items.iter().filter(|item| item.shared) {
+        document_ids.push(if let Some(document_id) = names.get(&item.name) {
+            *document_id
+        } else {
+            create_collection(server, &access_token, tenant_id, collection, item).await?
+        });
+    }
+
+    Ok(document_ids)
+}
+
+async fn collection_names(
+    server: &Server,
+    account_id: u32,
+    collection: Collection,
+) -> trc::Result<AHashMap<String, u32>> {
+    let mut names = AHashMap::new();
+    server
+        .archives(account_id, collection, &(), |document_id, archive| {
+            let name = if collection == Collection::Calendar {
+                archive.unarchive::<Calendar>()?.name.to_string()
+            } else {
+                archive.unarchive::<AddressBook>()?.name.to_string()
+            };
+            names.insert(name, document_id);
+            Ok(true)
+        })
+        .await
+        .caused_by(trc::location!())?;
+
+    Ok(names)
+}
+
+async fn create_collection(
+    server: &Server,
+    access_token: &AccessToken,
+    account_id: u32,
+    collection: Collection,
+    item: &ProvisionCollection,
+) -> trc::Result<u32> {
+    let mut batch = BatchBuilder::new();
+    let document_id = server
+        .store()
+        .assign_document_ids(account_id, collection, 1)
+        .await
+        .caused_by(trc::location!())?;
+    if collection == Collection::Calendar {
+        Calendar {
+            name: item.name.clone(),
+            preferences: vec![CalendarPreferences {
+                account_id,
+                name: item.display_name().to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .insert(access_token, account_id, document_id, &mut batch)
+    } else {
+        AddressBook {
+            name: item.name.clone(),
+            preferences: vec![AddressBookPreferences {
+                account_id,
+                name: item.display_name().to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .insert(access_token, account_id, document_id, &mut batch)
+    }
+    .caused_by(trc::location!())?;
+    server
+        .commit_batch(batch)
+        .await
+        .caused_by(trc::location!())?;
+
+    Ok(document_id)
+}
+
+/// Grants an account read access to a shared collection of its tenant,
+/// leaving any existing grant of the account untouched.
+async fn share_collection(
+    server: &Server,
+    tenant_id: u32,
+    collection: Collection,
+    document_id: u32,
+    account_id: u32,
+) -> trc::Result<()> {
+    let Some(archive) = server
+        .store()
+        .get_value::<Archive<AlignedBytes>>(ValueKey::archive(tenant_id, collection, document_id))
+        .await
+        .caused_by(trc::location!())?
+    else {
+        return Ok(());
+    };
+    let access_token = server
+        .get_access_token(tenant_id)
+        .await
+        .caused_by(trc::location!())?;
+    let mut batch = BatchBuilder::new();
+
+    if collection == Collection::Calendar {
+        let current = archive
+            .to_unarchived::<Calendar>()
+            .caused_by(trc::location!())?;
+        if current
+            .inner
+            .acls
+            .iter()
+            .any(|grant| grant.account_id.to_native() == account_id)
+        {
+            return Ok(());
+        }
+        let mut calendar = current
+            .deserialize::<Calendar>()
+            .caused_by(trc::location!())?;
+        calendar.acls.push(AclGrant {
+            account_id,
+            grants: Bitmap::from_iter([Acl::Read, Acl::ReadItems, Acl::SchedulingReadFreeBusy]),
+        });
+        server
+            .refresh_archived_acls(&calendar.acls, current.inner.acls.as_slice())
+            .await;
+        calendar
+            .update(&access_token, current, tenant_id, document_id, &mut batch)
+            .caused_by(trc::location!())?;
+    } else {
+        let current = archive
+            .to_unarchived::<AddressBook>()
+            .caused_by(trc::location!())?;
+        if current
+            .inner
+            .acls
+            .iter()
+            .any(|grant| grant.account_id.to_native() == account_id)
+        {
+            return Ok(());
+        }
+        let mut book = current
+            .deserialize::<AddressBook>()
+            .caused_by(trc::location!())?;
+        book.acls.push(AclGrant {
+            account_id,
+            grants: Bitmap::from_iter([Acl::Read, Acl::ReadItems]),
+        });
+        server
+            .refresh_archived_acls(&book.acls, current.inner.acls.as_slice())
+            .await;
+        book.update(&access_token, current, tenant_id, document_id, &mut batch)
+            .caused_by(trc::location!())?;
+    }
+
+    server
+        .commit_batch(batch)
+        .await
+        .caused_by(trc::location!())
+        .map(|_| ())
+}
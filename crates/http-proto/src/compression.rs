@@ -0,0 +1,694 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    io::{self, Read, Write},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use common::config::jmap::settings::HttpCompression;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use http_body_util::{BodyExt, combinators::BoxBody};
+use hyper::{
+    body::{Body, Bytes, Frame},
+    header::{self, HeaderMap, HeaderValue},
+};
+use trc::AddContext;
+
+use crate::{HttpBodyError, HttpResponse, HttpResponseBody};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    Zstd,
+}
+
+pub struct CompressedBody {
+    inner: BoxBody<Bytes, HttpBodyError>,
+    encoder: Option<Encoder>,
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl ContentEncoding {
+    /// Picks the preferred encoding from an `Accept-Encoding` header,
+    /// favouring zstd over gzip when both carry the same weight.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut result: Option<(Self, f32)> = None;
+
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        value.trim().parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+
+            let encoding = if coding.eq_ignore_ascii_case("zstd") {
+                ContentEncoding::Zstd
+            } else if coding.eq_ignore_ascii_case("gzip")
+                || coding.eq_ignore_ascii_case("x-gzip")
+                || coding == "*"
+            {
+                ContentEncoding::Gzip
+            } else {
+                continue;
+            };
+
+            match result {
+                Some((current, current_q))
+                    if current_q > quality
+                        || (current_q == quality && current == ContentEncoding::Zstd) => {}
+                _ => {
+                    result = Some((encoding, quality));
+                }
+            }
+        }
+
+        result.map(|(encoding, _)| encoding)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentEncoding::Gzip)
+        } else if value.eq_ignore_ascii_case("zstd") {
+            Some(ContentEncoding::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+
+    fn encoder(&self) -> io::Result<Encoder> {
+        match self {
+            ContentEncoding::Gzip => Ok(Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                Compression::default(),
+            ))),
+            ContentEncoding::Zstd => {
+                zstd::stream::write::Encoder::new(Vec::new(), 0).map(Encoder::Zstd)
+            }
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = self.encoder()?;
+        let mut output = Vec::from(encoder.write(data)?);
+        output.extend_from_slice(&encoder.finish()?);
+        Ok(output)
+    }
+
+    /// Decompresses a request body, failing once the output grows beyond
+    /// `max_size` bytes.
+    pub fn decompress(&self, data: &[u8], max_size: usize) -> trc::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(std::cmp::min(data.len() * 4, max_size));
+        let limit = max_size as u64 + 1;
+        match self {
+            ContentEncoding::Gzip => GzDecoder::new(data).take(limit).read_to_end(&mut output),
+            ContentEncoding::Zstd => zstd::stream::read::Decoder::new(data)
+                .and_then(|decoder| decoder.take(limit).read_to_end(&mut output)),
+        }
+        .map_err(|err| {
+            trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Failed to decompress request body")
+                .reason(err)
+        })?;
+
+        if output.len() <= max_size {
+            Ok(output)
+        } else {
+            Err(trc::LimitEvent::SizeRequest
+                .into_err()
+                .ctx(trc::Key::Limit, max_size)
+                .ctx(trc::Key::Size, output.len()))
+        }
+    }
+}
+
+/// Decodes a request body according to its `Content-Encoding` header.
+pub fn decode_body(headers: &HeaderMap, body: Vec<u8>, max_size: usize) -> trc::Result<Vec<u8>> {
+    match request_encoding(headers)? {
+        Some(encoding) => encoding
+            .decompress(&body, max_size)
+            .caused_by(trc::location!()),
+        None => Ok(body),
+    }
+}
+
+/// Returns the encoding of a request body, or `None` for identity.
+pub fn request_encoding(headers: &HeaderMap) -> trc::Result<Option<ContentEncoding>> {
+    match headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim())
+    {
+        Some(value) if !value.is_empty() && !value.eq_ignore_ascii_case("identity") => {
+            ContentEncoding::parse(value).map(Some).ok_or_else(|| {
+                trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Unsupported Content-Encoding")
+                    .ctx(trc::Key::Value, value.to_string())
+            })
+        }
+        _ => Ok(None),
+    }
+}
+
+// Compressed input is fed in small slices so that the output can be
+// checked against the size limit before a zip bomb fills the memory.
+const DECODE_SLICE_LEN: usize = 256;
+
+/// Incremental decoder for compressed request bodies that are read frame
+/// by frame.
+pub struct StreamDecoder(Decoder);
+
+enum Decoder {
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl StreamDecoder {
+    pub fn new(encoding: ContentEncoding) -> trc::Result<Self> {
+        match encoding {
+            ContentEncoding::Gzip => Ok(Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new()))),
+            ContentEncoding::Zstd => zstd::stream::write::Decoder::new(Vec::new())
+                .map(Decoder::Zstd)
+                .map_err(decode_error),
+        }
+        .map(StreamDecoder)
+    }
+
+    /// Decodes a chunk of the body, passing the output to `sink` as it is
+    /// produced.
+    pub fn decode(
+        &mut self,
+        data: &[u8],
+        mut sink: impl FnMut(Vec<u8>) -> trc::Result<()>,
+    ) -> trc::Result<()> {
+        for data in data.chunks(DECODE_SLICE_LEN) {
+            let output = match &mut self.0 {
+                Decoder::Gzip(decoder) => decoder
+                    .write_all(data)
+                    .map(|_| std::mem::take(decoder.get_mut())),
+                Decoder::Zstd(decoder) => decoder
+                    .write_all(data)
+                    .map(|_| std::mem::take(decoder.get_mut())),
+            }
+            .map_err(decode_error)?;
+            if !output.is_empty() {
+                sink(output)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the output buffered by the decoder at the end of the body.
+    pub fn finish(&mut self) -> trc::Result<Vec<u8>> {
+        match &mut self.0 {
+            Decoder::Gzip(decoder) => decoder
+                .try_finish()
+                .map(|_| std::mem::take(decoder.get_mut())),
+            Decoder::Zstd(decoder) => decoder.flush().map(|_| std::mem::take(decoder.get_mut())),
+        }
+        .map_err(decode_error)
+    }
+}
+
+fn decode_error(err: io::Error) -> trc::Error {
+    trc::ResourceEvent::BadParameters
+        .into_err()
+        .details("Failed to decompress request body")
+        .reason(err)
+}
+
+impl HttpResponse {
+    /// Compresses the response body using the encoding negotiated from the
+    /// request's `Accept-Encoding` header. Bodies that are too small, already
+    /// encoded or of an already compressed media type are left untouched.
+    pub fn with_compression(mut self, accept_encoding: &str, config: &HttpCompression) -> Self {
+        if !config.enable {
+            return self;
+        }
+        let Some(encoding) = ContentEncoding::negotiate(accept_encoding) else {
+            return self;
+        };
+        if let Some(headers) = self.builder.headers_ref()
+            && (headers.contains_key(header::CONTENT_ENCODING)
+                || headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|content_type| {
+                        is_compressed_media_type(content_type) || is_event_stream(content_type)
+                    }))
+        {
+            return self;
+        }
+
+        self.body = match std::mem::replace(&mut self.body, HttpResponseBody::Empty) {
+            HttpResponseBody::Text(body) if body.len() >= config.min_size => {
+                match encoding.compress(body.as_bytes()) {
+                    Ok(compressed) => HttpResponseBody::Binary(compressed),
+                    Err(_) => {
+                        self.body = HttpResponseBody::Text(body);
+                        return self;
+                    }
+                }
+            }
+            HttpResponseBody::Binary(body) if body.len() >= config.min_size => {
+                match encoding.compress(&body) {
+                    Ok(compressed) => HttpResponseBody::Binary(compressed),
+                    Err(_) => {
+                        self.body = HttpResponseBody::Binary(body);
+                        return self;
+                    }
+                }
+            }
+            HttpResponseBody::Stream(stream) => match encoding.encoder() {
+                Ok(encoder) => HttpResponseBody::Stream(
+                    CompressedBody {
+                        inner: stream,
+                        encoder: Some(encoder),
+                    }
+                    .boxed(),
+                ),
+                Err(_) => {
+                    self.body = HttpResponseBody::Stream(stream);
+                    return self;
+                }
+            },
+            body => {
+                self.body = body;
+                return self;
+            }
+        };
+
+        if let Some(headers) = self.builder.headers_mut() {
+            match &self.body {
+                HttpResponseBody::Binary(body) => {
+                    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+                }
+                _ => {
+                    headers.remove(header::CONTENT_LENGTH);
+                }
+            }
+            headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        }
+
+        self
+    }
+}
+
+fn is_compressed_media_type(content_type: &str) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    (content_type.starts_with("image/") && content_type != "image/svg+xml")
+        || content_type.starts_with("video/")
+        || content_type.starts_with("audio/")
+        || matches!(
+            content_type.as_str(),
+            "application/zip"
+                | "application/gzip"
+                | "application/x-gzip"
+                | "application/zstd"
+                | "application/x-bzip2"
+                | "application/x-xz"
+                | "application/x-7z-compressed"
+                | "application/pdf"
+                | "font/woff"
+                | "font/woff2"
+        )
+}
+
+// Events are delivered as they happen, which compression would hold back
+fn is_event_stream(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .eq_ignore_ascii_case("text/event-stream")
+}
+
+impl Encoder {
+    // Flushes after every frame so streamed data is not held back until
+    // the encoder fills its internal buffer
+    fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(Bytes::from(std::mem::take(encoder.get_mut())))
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(Bytes::from(std::mem::take(encoder.get_mut())))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+        .map(Bytes::from)
+    }
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = HttpBodyError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => match encoder.write(&data) {
+                        Ok(chunk) if !chunk.is_empty() => {
+                            return Poll::Ready(Some(Ok(Frame::data(chunk))));
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            this.encoder = None;
+                            return Poll::Ready(Some(Err(err.into())));
+                        }
+                    },
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(err)) => {
+                    this.encoder = None;
+                    return Poll::Ready(Some(Err(err)));
+                }
+                None => {
+                    return Poll::Ready(match this.encoder.take().map(Encoder::finish) {
+                        Some(Ok(chunk)) if !chunk.is_empty() => Some(Ok(Frame::data(chunk))),
+                        Some(Err(err)) => Some(Err(err.into())),
+                        _ => None,
+                    });
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use common::config::jmap::settings::HttpCompression;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{
+        StatusCode,
+        body::{Bytes, Frame},
+        header::{self, HeaderMap, HeaderValue},
+    };
+
+    use super::{ContentEncoding, decode_body};
+    use crate::{HttpBodyError, HttpResponse};
+
+    // Yields each string as a separate data frame
+    struct TestBody(Vec<&'static str>);
+
+    impl hyper::body::Body for TestBody {
+        type Data = Bytes;
+        type Error = HttpBodyError;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(if !self.0.is_empty() {
+                Some(Ok(Frame::data(Bytes::from(self.0.remove(0)))))
+            } else {
+                None
+            })
+        }
+    }
+
+    const CONFIG: HttpCompression = HttpCompression {
+        enable: true,
+        min_size: 16,
+    };
+
+    fn decompress(encoding: ContentEncoding, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        match encoding {
+            ContentEncoding::Gzip => {
+                flate2::read::GzDecoder::new(data)
+                    .read_to_end(&mut output)
+                    .unwrap();
+            }
+            ContentEncoding::Zstd => {
+                zstd::stream::read::Decoder::new(data)
+                    .unwrap()
+                    .read_to_end(&mut output)
+                    .unwrap();
+            }
+        }
+        output
+    }
+
+    async fn collect(response: HttpResponse) -> (hyper::HeaderMap, Vec<u8>) {
+        let response = response.build();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (headers, body.to_vec())
+    }
+
+    #[test]
+    fn negotiate_encoding() {
+        for (header, expected) in [
+            ("gzip", Some(ContentEncoding::Gzip)),
+            ("gzip, deflate, br, zstd", Some(ContentEncoding::Zstd)),
+            ("zstd;q=0.5, gzip", Some(ContentEncoding::Gzip)),
+            ("gzip;q=0, zstd;q=0", None),
+            ("identity", None),
+            ("br", None),
+            ("*", Some(ContentEncoding::Gzip)),
+            ("", None),
+        ] {
+            assert_eq!(ContentEncoding::negotiate(header), expected, "{header}");
+        }
+    }
+
+    #[tokio::test]
+    async fn response_round_trip() {
+        let text = format!("{{\"data\":[{}null]}}", "\"user@example.org\",".repeat(500));
+
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+            // Buffered body
+            let (headers, body) = collect(
+                HttpResponse::new(StatusCode::OK)
+                    .with_content_type("application/json")
+                    .with_text_body(text.clone())
+                    .with_compression(encoding.as_str(), &CONFIG),
+            )
+            .await;
+            assert_eq!(headers[header::CONTENT_ENCODING], encoding.as_str());
+            assert_eq!(headers[header::CONTENT_LENGTH], body.len().to_string());
+            assert!(body.len() < text.len());
+            assert_eq!(decompress(encoding, &body), text.as_bytes());
+
+            // Streamed body
+            let (headers, body) = collect(
+                HttpResponse::new(StatusCode::OK)
+                    .with_content_type("text/csv")
+                    .with_stream_body(
+                        Full::new(Bytes::from(text.clone()))
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .with_compression(encoding.as_str(), &CONFIG),
+            )
+            .await;
+            assert_eq!(headers[header::CONTENT_ENCODING], encoding.as_str());
+            assert!(!headers.contains_key(header::CONTENT_LENGTH));
+            assert_eq!(decompress(encoding, &body), text.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn response_skipped() {
+        let data = vec![0u8; 4096];
+
+        // Already compressed media types
+        let (headers, body) = collect(
+            HttpResponse::new(StatusCode::OK)
+                .with_content_type("image/png")
+                .with_binary_body(data.clone())
+                .with_compression("gzip", &CONFIG),
+        )
+        .await;
+        assert!(!headers.contains_key(header::CONTENT_ENCODING));
+        assert_eq!(body, data);
+
+        // Small bodies
+        let (headers, body) = collect(
+            HttpResponse::new(StatusCode::OK)
+                .with_text_body("{}")
+                .with_compression("gzip", &CONFIG),
+        )
+        .await;
+        assert!(!headers.contains_key(header::CONTENT_ENCODING));
+        assert_eq!(body, b"{}");
+
+        // Disabled
+        let (headers, _) = collect(
+            HttpResponse::new(StatusCode::OK)
+                .with_binary_body(data.clone())
+                .with_compression(
+                    "gzip",
+                    &HttpCompression {
+                        enable: false,
+                        min_size: 0,
+                    },
+                ),
+        )
+        .await;
+        assert!(!headers.contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn response_stream_flush() {
+        let frames = ["data: first\n\n", "data: second\n\n"];
+
+        // Event streams are never compressed
+        let (headers, body) = collect(
+            HttpResponse::new(StatusCode::OK)
+                .with_content_type("text/event-stream")
+                .with_stream_body(TestBody(frames.to_vec()).boxed())
+                .with_compression("gzip", &CONFIG),
+        )
+        .await;
+        assert!(!headers.contains_key(header::CONTENT_ENCODING));
+        assert_eq!(body, frames.concat().as_bytes());
+
+        // Each frame can be decoded as soon as it is received
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+            let mut body = HttpResponse::new(StatusCode::OK)
+                .with_content_type("text/csv")
+                .with_stream_body(TestBody(frames.to_vec()).boxed())
+                .with_compression(encoding.as_str(), &CONFIG)
+                .build()
+                .into_body();
+            let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+            let decoded = match encoding {
+                ContentEncoding::Gzip => {
+                    let mut decoder = flate2::write::GzDecoder::new(Vec::new());
+                    decoder.write_all(&frame).unwrap();
+                    decoder.flush().unwrap();
+                    std::mem::take(decoder.get_mut())
+                }
+                ContentEncoding::Zstd => {
+                    let mut decoder = zstd::stream::write::Decoder::new(Vec::new()).unwrap();
+                    decoder.write_all(&frame).unwrap();
+                    decoder.flush().unwrap();
+                    std::mem::take(decoder.get_mut())
+                }
+            };
+            assert_eq!(decoded, frames[0].as_bytes());
+        }
+    }
+
+    #[test]
+    fn request_decoding() {
+        let data = b"name,email\njohn,john@example.org\n".repeat(100);
+        let mut headers = HeaderMap::new();
+
+        // Identity
+        assert_eq!(decode_body(&headers, data.clone(), 10).unwrap(), data);
+
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+            headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            let compressed = encoding.compress(&data).unwrap();
+            assert_eq!(
+                decode_body(&headers, compressed.clone(), data.len()).unwrap(),
+                data
+            );
+
+            // Output limit
+            let err = decode_body(&headers, compressed, data.len() - 1).unwrap_err();
+            assert!(err.matches(trc::EventType::Limit(trc::LimitEvent::SizeRequest)));
+
+            // Corrupted input
+            let err = decode_body(&headers, data.clone(), data.len()).unwrap_err();
+            assert!(err.matches(trc::EventType::Resource(trc::ResourceEvent::BadParameters)));
+        }
+
+        // Unsupported encoding
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("br"));
+        assert!(decode_body(&headers, data.clone(), data.len()).is_err());
+    }
+
+    #[test]
+    fn decompression_bomb() {
+        // 64 MiB of zeros compresses to a few kilobytes
+        let bomb = ContentEncoding::Gzip
+            .compress(&vec![0u8; 64 * 1024 * 1024])
+            .unwrap();
+        assert!(bomb.len() < 128 * 1024);
+
+        let err = ContentEncoding::Gzip
+            .decompress(&bomb, 1024 * 1024)
+            .unwrap_err();
+        assert!(err.matches(trc::EventType::Limit(trc::LimitEvent::SizeRequest)));
+        assert_eq!(
+            err.value(trc::Key::Limit).and_then(|v| v.to_uint()),
+            Some(1024 * 1024)
+        );
+    }
+}
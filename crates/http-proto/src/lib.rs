@@ -4,7 +4,10 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+pub mod compression;
 pub mod context;
+pub mod multipart;
+pub mod plain;
 pub mod request;
 pub mod response;
 
@@ -17,6 +20,10 @@ use hyper::StatusCode;
 
 pub type HttpRequest = hyper::Request<hyper::body::Incoming>;
 
+// Streamed bodies report errors so the connection is aborted instead of
+// the response ending early
+pub type HttpBodyError = Box<dyn std::error::Error + Send + Sync>;
+
 pub struct JsonResponse<T: serde::Serialize> {
     status: StatusCode,
     inner: T,
@@ -31,7 +38,7 @@ pub struct HtmlResponse {
 pub enum HttpResponseBody {
     Text(String),
     Binary(Vec<u8>),
-    Stream(http_body_util::combinators::BoxBody<hyper::body::Bytes, hyper::Error>),
+    Stream(http_body_util::combinators::BoxBody<hyper::body::Bytes, HttpBodyError>),
     WebsocketUpgrade(String),
     Empty,
 }
@@ -65,6 +72,23 @@ pub struct DownloadResponse {
 
 pub struct JsonProblemResponse(pub StatusCode);
 
+/// Column aligned table of a list, for clients that requested plain text.
+pub struct TextTable {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+/// `key: value` lines of a response, for clients that requested plain text.
+pub struct TextFields {
+    lines: Vec<(String, String)>,
+}
+
+/// Single line error message, for clients that requested plain text.
+pub struct TextError {
+    status: StatusCode,
+    message: String,
+}
+
 impl<T: serde::Serialize> JsonResponse<T> {
     pub fn new(inner: T) -> Self {
         JsonResponse {
@@ -0,0 +1,378 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use http_body_util::BodyExt;
+use hyper::{body::Bytes, header};
+
+use crate::{
+    compression::{StreamDecoder, request_encoding},
+    request::body_too_large,
+};
+
+// Data is returned once this much is buffered, or at the end of a field
+const MIN_CHUNK_LEN: usize = 64 * 1024;
+const MAX_HEADERS_LEN: usize = 16 * 1024;
+
+/// Reads a `multipart/form-data` upload as it arrives, so that routes with
+/// large body limits do not need to hold the whole request in memory.
+pub struct MultipartReader<'x, B> {
+    body: &'x mut B,
+    decoder: Option<StreamDecoder>,
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    received: usize,
+    max_size: usize,
+    eof: bool,
+    state: State,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartField {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Data,
+    Done,
+}
+
+impl<'x, B> MultipartReader<'x, B>
+where
+    B: hyper::body::Body<Data = Bytes> + Unpin,
+{
+    /// Starts reading the body of a multipart request. `max_size` limits the
+    /// decoded size of the body, zero disables the limit.
+    pub fn new(req: &'x mut hyper::Request<B>, max_size: usize) -> trc::Result<Self> {
+        let boundary = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(multipart_boundary)
+            .ok_or_else(|| {
+                trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Expected a multipart/form-data upload")
+            })?;
+        let decoder = request_encoding(req.headers())?
+            .map(StreamDecoder::new)
+            .transpose()?;
+
+        // The opening delimiter is not preceded by a line break
+        Ok(MultipartReader {
+            body: req.body_mut(),
+            decoder,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            buf: b"\r\n".to_vec(),
+            received: 0,
+            max_size,
+            eof: false,
+            state: State::Preamble,
+        })
+    }
+
+    /// Returns the headers of the next field, skipping any data left in
+    /// the current one.
+    pub async fn next_field(&mut self) -> trc::Result<Option<MultipartField>> {
+        while self.state == State::Data {
+            self.next_chunk().await?;
+        }
+        if self.state == State::Preamble {
+            let pos = self.find(&self.delimiter.clone()).await?;
+            self.buf.drain(..pos + self.delimiter.len());
+            self.state = State::Delimiter;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        // Either the closing delimiter or the headers of the next field
+        while self.buf.len() < 2 && self.fill().await? {}
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        let end = self.find(b"\r\n\r\n").await?;
+        let headers = std::str::from_utf8(&self.buf[..end])
+            .map_err(|_| invalid_upload("Invalid field headers"))?;
+        let mut field = MultipartField {
+            name: String::new(),
+            filename: None,
+            content_type: None,
+        };
+        for line in headers.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    if let Some((name, value)) = param.split_once('=') {
+                        let value = value.trim().trim_matches('"').to_string();
+                        match name.trim() {
+                            "name" => field.name = value,
+                            "filename" => field.filename = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                field.content_type = Some(value.trim().to_string());
+            }
+        }
+        self.buf.drain(..end + 4);
+        self.state = State::Data;
+
+        Ok(Some(field))
+    }
+
+    /// Returns the next chunk of data of the current field, or `None` once
+    /// the field has been read.
+    pub async fn next_chunk(&mut self) -> trc::Result<Option<Vec<u8>>> {
+        if self.state != State::Data {
+            return Ok(None);
+        }
+
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                let data = self.buf.drain(..pos).collect::<Vec<_>>();
+                self.buf.drain(..self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(Some(data).filter(|data| !data.is_empty()));
+            }
+
+            // Keep enough bytes to match a delimiter split across frames
+            let safe_len = self.buf.len().saturating_sub(self.delimiter.len());
+            if safe_len >= MIN_CHUNK_LEN {
+                return Ok(Some(self.buf.drain(..safe_len).collect()));
+            } else if !self.fill().await? {
+                return Err(invalid_upload("Truncated multipart upload"));
+            }
+        }
+    }
+
+    /// Reads the data of the current field into memory, failing if it is
+    /// larger than `max_size`.
+    pub async fn read_to_end(&mut self, max_size: usize) -> trc::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            if data.len() + chunk.len() > max_size {
+                return Err(body_too_large(max_size, data.len() + chunk.len()));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// Buffers data until `pattern` is found, returning its position.
+    async fn find(&mut self, pattern: &[u8]) -> trc::Result<usize> {
+        loop {
+            if let Some(pos) = find(&self.buf, pattern) {
+                return Ok(pos);
+            } else if self.state != State::Preamble && self.buf.len() > MAX_HEADERS_LEN {
+                return Err(invalid_upload("Field headers are too long"));
+            } else if !self.fill().await? {
+                return Err(invalid_upload("Truncated multipart upload"));
+            }
+        }
+    }
+
+    /// Appends the next frame of the body to the buffer, returning `false`
+    /// at the end of the body.
+    async fn fill(&mut self) -> trc::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        loop {
+            match self.body.frame().await {
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data()
+                        && !data.is_empty()
+                    {
+                        match &mut self.decoder {
+                            Some(decoder) => {
+                                let (buf, received, max_size) =
+                                    (&mut self.buf, &mut self.received, self.max_size);
+                                decoder.decode(&data, |output| {
+                                    append(buf, received, max_size, &output)
+                                })?;
+                            }
+                            None => {
+                                append(&mut self.buf, &mut self.received, self.max_size, &data)?
+                            }
+                        }
+                        return Ok(true);
+                    }
+                }
+                _ => {
+                    self.eof = true;
+                    if let Some(decoder) = &mut self.decoder {
+                        let output = decoder.finish()?;
+                        append(&mut self.buf, &mut self.received, self.max_size, &output)?;
+                        return Ok(!output.is_empty());
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+fn append(
+    buf: &mut Vec<u8>,
+    received: &mut usize,
+    max_size: usize,
+    data: &[u8],
+) -> trc::Result<()> {
+    *received += data.len();
+    if max_size != 0 && *received > max_size {
+        Err(body_too_large(max_size, *received))
+    } else {
+        buf.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("boundary") {
+            Some(value.trim().trim_matches('"').to_string()).filter(|b| !b.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+fn invalid_upload(details: &'static str) -> trc::Error {
+    trc::ResourceEvent::BadParameters
+        .into_err()
+        .details("Invalid multipart upload")
+        .reason(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultipartReader;
+    use hyper::body::{Bytes, Frame};
+    use std::{
+        collections::VecDeque,
+        convert::Infallible,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    struct TestBody(VecDeque<Bytes>);
+
+    impl hyper::body::Body for TestBody {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(self.0.pop_front().map(|data| Ok(Frame::data(data))))
+        }
+    }
+
+    // Splits the body in small frames to exercise delimiters across frames
+    fn request(body: &[u8], frame_len: usize) -> hyper::Request<TestBody> {
+        hyper::Request::builder()
+            .method("POST")
+            .uri("/api/test")
+            .header(
+                hyper::header::CONTENT_TYPE,
+                "multipart/form-data; boundary=\"boundary\"",
+            )
+            .body(TestBody(
+                body.chunks(frame_len)
+                    .map(|chunk| Bytes::from(chunk.to_vec()))
+                    .collect(),
+            ))
+            .unwrap()
+    }
+
+    const BODY: &str = concat!(
+        "preamble\r\n",
+        "--boundary\r\n",
+        "Content-Disposition: form-data; name=\"options\"\r\n",
+        "\r\n",
+        "{\"skipDuplicates\":true}\r\n",
+        "--boundary\r\n",
+        "Content-Disposition: form-data; name=\"file\"; filename=\"mail.mbox\"\r\n",
+        "Content-Type: application/mbox\r\n",
+        "\r\n",
+        "From jane@example.org\n\r\n--boundar\n",
+        "\r\n",
+        "--boundary--\r\n",
+    );
+
+    #[tokio::test]
+    async fn multipart_fields() {
+        for frame_len in [1, 7, 64, BODY.len()] {
+            let mut req = request(BODY.as_bytes(), frame_len);
+            let mut reader = MultipartReader::new(&mut req, 0).unwrap();
+
+            let field = reader.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name, "options");
+            assert_eq!(
+                reader.read_to_end(1024).await.unwrap(),
+                b"{\"skipDuplicates\":true}"
+            );
+            let field = reader.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name, "file");
+            assert_eq!(field.filename.as_deref(), Some("mail.mbox"));
+            assert_eq!(field.content_type.as_deref(), Some("application/mbox"));
+            let mut data = Vec::new();
+            while let Some(chunk) = reader.next_chunk().await.unwrap() {
+                data.extend(chunk);
+            }
+            assert_eq!(data, b"From jane@example.org\n\r\n--boundar\n");
+            assert_eq!(reader.next_field().await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn multipart_limits() {
+        // The body limit applies while streaming
+        let mut req = request(BODY.as_bytes(), 16);
+        let mut reader = MultipartReader::new(&mut req, 100).unwrap();
+        let err = loop {
+            match reader.next_field().await {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("limit not enforced"),
+                Err(err) => break err,
+            }
+        };
+        assert!(err.matches(trc::EventType::Limit(trc::LimitEvent::SizeRequest)));
+
+        // Truncated uploads are rejected
+        let mut req = request(&BODY.as_bytes()[..BODY.len() - 20], 16);
+        let mut reader = MultipartReader::new(&mut req, 0).unwrap();
+        reader.next_field().await.unwrap();
+        reader.next_field().await.unwrap();
+        assert!(reader.next_chunk().await.is_err());
+    }
+}
@@ -0,0 +1,288 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use hyper::{StatusCode, header};
+use serde_json::Value;
+
+use crate::{HttpRequest, HttpResponse, TextError, TextFields, TextTable, ToHttpResponse};
+
+const COLUMN_SEPARATOR: &str = "  ";
+
+/// Returns whether the client asked for plain text output, either with
+/// `Accept: text/plain` or with `?format=table`.
+pub fn is_plain_text_request(req: &HttpRequest) -> bool {
+    req.uri().query().is_some_and(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .any(|(key, value)| key == "format" && value == "table")
+    }) || req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/plain"))
+}
+
+impl TextTable {
+    pub fn new(columns: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        TextTable {
+            headers: columns
+                .into_iter()
+                .map(|column| column_header(column.as_ref()))
+                .collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Builds a table with one row per item and one column per field.
+    /// Nested fields are separated by dots and are read from every
+    /// element of the arrays along the way.
+    pub fn from_json<'x>(fields: &[&str], items: impl IntoIterator<Item = &'x Value>) -> Self {
+        let mut table = TextTable::new(fields);
+        for item in items {
+            table.rows.push(
+                fields
+                    .iter()
+                    .map(|field| cell_value(&field_value(item, field)))
+                    .collect(),
+            );
+        }
+        table
+    }
+
+    pub fn push_row<'x>(&mut self, values: impl IntoIterator<Item = &'x Value>) {
+        self.rows.push(values.into_iter().map(cell_value).collect());
+    }
+
+    pub fn render(&self) -> String {
+        let mut widths = self
+            .headers
+            .iter()
+            .map(|header| header.chars().count())
+            .collect::<Vec<_>>();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut output = String::new();
+        for row in std::iter::once(&self.headers).chain(&self.rows) {
+            let mut line = String::new();
+            for (pos, (cell, width)) in row.iter().zip(&widths).enumerate() {
+                if pos > 0 {
+                    line.push_str(COLUMN_SEPARATOR);
+                }
+                line.push_str(cell);
+                line.extend(std::iter::repeat_n(' ', width - cell.chars().count()));
+            }
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+        output
+    }
+}
+
+impl TextFields {
+    /// Flattens a JSON value into `key: value` lines, with the keys of
+    /// nested objects separated by dots and array elements by index.
+    pub fn from_json(value: &Value) -> Self {
+        let mut fields = TextFields { lines: Vec::new() };
+        fields.flatten(String::new(), value);
+        fields
+    }
+
+    fn flatten(&mut self, key: String, value: &Value) {
+        match value {
+            Value::Object(object) if !object.is_empty() => {
+                for (name, value) in object {
+                    self.flatten(
+                        if key.is_empty() {
+                            name.clone()
+                        } else {
+                            format!("{key}.{name}")
+                        },
+                        value,
+                    );
+                }
+            }
+            Value::Array(items) if items.iter().any(|item| item.is_object()) => {
+                for (pos, item) in items.iter().enumerate() {
+                    self.flatten(format!("{key}[{pos}]"), item);
+                }
+            }
+            value => {
+                self.lines.push((key, cell_value(value)));
+            }
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for (key, value) in &self.lines {
+            output.push_str(key);
+            output.push_str(": ");
+            output.push_str(value);
+            output.push('\n');
+        }
+        output
+    }
+}
+
+impl TextError {
+    pub fn new(status: StatusCode, message: impl AsRef<str>) -> Self {
+        TextError {
+            status,
+            message: single_line(message.as_ref()),
+        }
+    }
+}
+
+impl ToHttpResponse for TextTable {
+    fn into_http_response(self) -> HttpResponse {
+        plain_text_response(StatusCode::OK, self.render())
+    }
+}
+
+impl ToHttpResponse for TextFields {
+    fn into_http_response(self) -> HttpResponse {
+        plain_text_response(StatusCode::OK, self.render())
+    }
+}
+
+impl ToHttpResponse for TextError {
+    fn into_http_response(self) -> HttpResponse {
+        plain_text_response(self.status, format!("error: {}\n", self.message))
+    }
+}
+
+fn plain_text_response(status: StatusCode, body: String) -> HttpResponse {
+    HttpResponse::new(status)
+        .with_content_type("text/plain; charset=utf-8")
+        .with_no_store()
+        .with_text_body(body)
+}
+
+// Field names are camel case or snake case, headers are upper snake case
+fn column_header(field: &str) -> String {
+    let mut header = String::with_capacity(field.len() + 4);
+    for ch in field.chars() {
+        if ch.is_uppercase() && header.chars().last().is_some_and(|ch| ch.is_lowercase()) {
+            header.push('_');
+        }
+        header.extend(ch.to_uppercase());
+    }
+    header
+}
+
+fn field_value(value: &Value, field: &str) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| field_value(item, field))
+                .filter(|item| !item.is_null())
+                .collect(),
+        ),
+        Value::Object(object) => match field.split_once('.') {
+            Some((name, field)) => object
+                .get(name)
+                .map_or(Value::Null, |value| field_value(value, field)),
+            None => object.get(field).cloned().unwrap_or(Value::Null),
+        },
+        _ => Value::Null,
+    }
+}
+
+fn cell_value(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(value) if value.is_empty() => "-".to_string(),
+        Value::String(value) => single_line(value),
+        Value::Array(items) if items.is_empty() => "-".to_string(),
+        Value::Array(items) => items.iter().map(cell_value).collect::<Vec<_>>().join(","),
+        value => single_line(&value.to_string()),
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value
+        .chars()
+        .map(|ch| if ch.is_control() { ' ' } else { ch })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::StatusCode;
+    use serde_json::json;
+
+    use crate::{TextError, TextFields, TextTable};
+
+    #[test]
+    fn render_table() {
+        let items = [
+            json!({
+                "id": 1,
+                "name": "acme",
+                "usedQuota": 1024,
+                "recipients": [{"address": "a@acme.org"}, {"address": "b@acme.org"}],
+            }),
+            json!({
+                "id": 20,
+                "name": "Initech\nCorp",
+                "recipients": [],
+            }),
+        ];
+
+        assert_eq!(
+            TextTable::from_json(&["id", "name", "usedQuota", "recipients.address"], &items)
+                .render(),
+            concat!(
+                "ID  NAME          USED_QUOTA  RECIPIENTS.ADDRESS\n",
+                "1   acme          1024        a@acme.org,b@acme.org\n",
+                "20  Initech Corp  -           -\n",
+            )
+        );
+        assert_eq!(
+            TextTable::from_json(&["return_path"], []).render(),
+            "RETURN_PATH\n"
+        );
+    }
+
+    #[test]
+    fn render_fields() {
+        // Keys are only ordered when serde_json preserves the insertion order
+        let mut lines = TextFields::from_json(&json!({
+            "tenantId": 1,
+            "pendingVerification": false,
+            "dnsRecords": [{"type": "MX"}],
+            "warnings": ["a", "b"],
+            "limits": {"quota": null},
+        }))
+        .render()
+        .lines()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+        lines.sort();
+
+        assert_eq!(
+            lines,
+            vec![
+                "dnsRecords[0].type: MX",
+                "limits.quota: -",
+                "pendingVerification: false",
+                "tenantId: 1",
+                "warnings: a,b",
+            ]
+        );
+    }
+
+    #[test]
+    fn render_error() {
+        let error = TextError::new(StatusCode::NOT_FOUND, "principal\r\nnot found");
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+        assert_eq!(error.message, "principal  not found");
+    }
+}
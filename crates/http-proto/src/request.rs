@@ -8,6 +8,7 @@ use std::borrow::Cow;
 
 use compact_str::ToCompactString;
 use http_body_util::BodyExt;
+use hyper::{body::Bytes, header};
 
 use crate::HttpRequest;
 
@@ -23,31 +24,61 @@ pub async fn fetch_body(
     max_size: usize,
     session_id: u64,
 ) -> Option<Vec<u8>> {
+    fetch_body_with_limit(req, max_size, session_id).await.ok()
+}
+
+/// Reads the request body, aborting as soon as `max_size` is exceeded.
+///
+/// On overflow a `LimitEvent::SizeRequest` error is returned carrying the
+/// configured limit and the number of bytes received (or announced via
+/// `Content-Length`) at the time the read was aborted.
+pub async fn fetch_body_with_limit<B>(
+    req: &mut hyper::Request<B>,
+    max_size: usize,
+    session_id: u64,
+) -> trc::Result<Vec<u8>>
+where
+    B: hyper::body::Body<Data = Bytes> + Unpin,
+{
+    // Reject early if the announced length is already over the limit
+    if max_size != 0
+        && let Some(content_length) = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|len| *len > max_size)
+    {
+        trc::event!(
+            Http(trc::HttpEvent::RequestBody),
+            SpanId = session_id,
+            Details = request_headers(req),
+            Size = content_length,
+            Limit = max_size,
+        );
+
+        return Err(body_too_large(max_size, content_length));
+    }
+
     let mut bytes = Vec::with_capacity(1024);
     while let Some(Ok(frame)) = req.frame().await {
         if let Some(data) = frame.data_ref() {
             if bytes.len() + data.len() <= max_size || max_size == 0 {
                 bytes.extend_from_slice(data);
             } else {
+                let received = bytes.len() + data.len();
                 trc::event!(
                     Http(trc::HttpEvent::RequestBody),
                     SpanId = session_id,
-                    Details = req
-                        .headers()
-                        .iter()
-                        .map(|(k, v)| trc::Value::Array(vec![
-                            k.as_str().to_compact_string().into(),
-                            v.to_str().unwrap_or_default().to_compact_string().into()
-                        ]))
-                        .collect::<Vec<_>>(),
+                    Details = request_headers(req),
                     Contents = std::str::from_utf8(&bytes)
                         .unwrap_or("[binary data]")
                         .to_string(),
-                    Size = bytes.len(),
+                    Size = received,
                     Limit = max_size,
                 );
 
-                return None;
+                return Err(body_too_large(max_size, received));
             }
         }
     }
@@ -55,19 +86,87 @@ pub async fn fetch_body(
     trc::event!(
         Http(trc::HttpEvent::RequestBody),
         SpanId = session_id,
-        Details = req
-            .headers()
-            .iter()
-            .map(|(k, v)| trc::Value::Array(vec![
-                k.as_str().to_compact_string().into(),
-                v.to_str().unwrap_or_default().to_compact_string().into()
-            ]))
-            .collect::<Vec<_>>(),
+        Details = request_headers(req),
         Contents = std::str::from_utf8(&bytes)
             .unwrap_or("[binary data]")
             .to_string(),
         Size = bytes.len(),
     );
 
-    bytes.into()
+    Ok(bytes)
+}
+
+fn request_headers<B>(req: &hyper::Request<B>) -> Vec<trc::Value> {
+    req.headers()
+        .iter()
+        .map(|(k, v)| {
+            trc::Value::Array(vec![
+                k.as_str().to_compact_string().into(),
+                v.to_str().unwrap_or_default().to_compact_string().into(),
+            ])
+        })
+        .collect()
+}
+
+pub(crate) fn body_too_large(limit: usize, received: usize) -> trc::Error {
+    trc::LimitEvent::SizeRequest
+        .into_err()
+        .ctx(trc::Key::Limit, limit)
+        .ctx(trc::Key::Size, received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fetch_body_with_limit;
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+
+    fn request(body: &[u8], content_length: Option<usize>) -> hyper::Request<Full<Bytes>> {
+        let mut builder = hyper::Request::builder().method("POST").uri("/api/test");
+        if let Some(content_length) = content_length {
+            builder = builder.header(hyper::header::CONTENT_LENGTH, content_length);
+        }
+        builder.body(Full::new(Bytes::from(body.to_vec()))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn body_at_limit() {
+        let body = vec![b'a'; 128];
+        let mut req = request(&body, None);
+        assert_eq!(fetch_body_with_limit(&mut req, 128, 0).await.unwrap(), body);
+
+        let mut req = request(&body, Some(128));
+        assert_eq!(fetch_body_with_limit(&mut req, 128, 0).await.unwrap(), body);
+
+        // A zero limit disables the check
+        let mut req = request(&body, None);
+        assert_eq!(fetch_body_with_limit(&mut req, 0, 0).await.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn body_over_limit() {
+        let body = vec![b'a'; 129];
+
+        for content_length in [None, Some(129)] {
+            let mut req = request(&body, content_length);
+            let err = fetch_body_with_limit(&mut req, 128, 0).await.unwrap_err();
+            assert!(err.matches(trc::EventType::Limit(trc::LimitEvent::SizeRequest)));
+            assert_eq!(
+                err.value(trc::Key::Limit).and_then(|v| v.to_uint()),
+                Some(128)
+            );
+            assert_eq!(
+                err.value(trc::Key::Size).and_then(|v| v.to_uint()),
+                Some(129)
+            );
+        }
+
+        // Announced length is rejected before reading the body
+        let mut req = request(b"", Some(10_000_000));
+        let err = fetch_body_with_limit(&mut req, 128, 0).await.unwrap_err();
+        assert_eq!(
+            err.value(trc::Key::Size).and_then(|v| v.to_uint()),
+            Some(10_000_000)
+        );
+    }
 }
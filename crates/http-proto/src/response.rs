@@ -14,8 +14,8 @@ use hyper::{
 use serde_json::json;
 
 use crate::{
-    DownloadResponse, HtmlResponse, HttpResponse, HttpResponseBody, JsonProblemResponse,
-    JsonResponse, ToHttpResponse,
+    DownloadResponse, HtmlResponse, HttpBodyError, HttpResponse, HttpResponseBody,
+    JsonProblemResponse, JsonResponse, ToHttpResponse,
 };
 
 impl HttpResponse {
@@ -111,7 +111,7 @@ impl HttpResponse {
 
     pub fn with_stream_body(
         mut self,
-        stream: http_body_util::combinators::BoxBody<hyper::body::Bytes, hyper::Error>,
+        stream: http_body_util::combinators::BoxBody<hyper::body::Bytes, HttpBodyError>,
     ) -> Self {
         self.body = HttpResponseBody::Stream(stream);
         self
@@ -173,7 +173,7 @@ impl HttpResponse {
 
     pub fn build(
         self,
-    ) -> hyper::Response<http_body_util::combinators::BoxBody<hyper::body::Bytes, hyper::Error>>
+    ) -> hyper::Response<http_body_util::combinators::BoxBody<hyper::body::Bytes, HttpBodyError>>
     {
         match self.body {
             HttpResponseBody::Text(body) => self.builder.body(
@@ -5,7 +5,9 @@
  */
 
 use common::auth::AccessToken;
-use common::{HttpAuthCache, Server, auth::AuthRequest, listener::limiter::InFlight};
+use common::config::overrides::TenantProtocol;
+use common::{HttpAuthCache, HttpSession, Server, auth::AuthRequest, listener::limiter::InFlight};
+use directory::AuthProtocol;
 use http_proto::{HttpRequest, HttpSessionData};
 use hyper::header;
 use mail_parser::decoders::base64::base64_decode;
@@ -13,6 +15,7 @@ use mail_send::Credentials;
 use std::future::Future;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use store::write::now;
 
 pub trait Authenticator: Sync + Send {
     fn authenticate_headers(
@@ -21,6 +24,19 @@ pub trait Authenticator: Sync + Send {
         session: &HttpSessionData,
         allow_api_access: bool,
     ) -> impl Future<Output = trc::Result<(Option<InFlight>, Arc<AccessToken>)>> + Send;
+
+    /// Authenticates a request to a protocol endpoint that tenants can
+    /// disable, such as JMAP or WebDAV.
+    fn authenticate_protocol_headers(
+        &self,
+        req: &HttpRequest,
+        session: &HttpSessionData,
+        protocol: TenantProtocol,
+    ) -> impl Future<Output = trc::Result<(Option<InFlight>, Arc<AccessToken>)>> + Send;
+
+    /// Whether the session of an authenticated request was started by a
+    /// master user logged in as the account.
+    fn is_impersonated_session(&self, req: &HttpRequest) -> bool;
 }
 
 impl Authenticator for Server {
@@ -30,15 +46,100 @@ impl Authenticator for Server {
         session: &HttpSessionData,
         allow_api_access: bool,
     ) -> trc::Result<(Option<InFlight>, Arc<AccessToken>)> {
-        if let Some((mechanism, token)) = req.authorization() {
-            // Check if the credentials are cached
-            if let Some(http_cache) = self.inner.cache.http_auth.get(token) {
+        authenticate_headers(self, req, session, allow_api_access, None).await
+    }
+
+    async fn authenticate_protocol_headers(
+        &self,
+        req: &HttpRequest,
+        session: &HttpSessionData,
+        protocol: TenantProtocol,
+    ) -> trc::Result<(Option<InFlight>, Arc<AccessToken>)> {
+        authenticate_headers(self, req, session, false, Some(protocol)).await
+    }
+
+    fn is_impersonated_session(&self, req: &HttpRequest) -> bool {
+        req.authorization().is_some_and(|(_, token)| {
+            self.inner
+                .cache
+                .http_auth
+                .get(token)
+                .is_some_and(|http_cache| http_cache.session.impersonated)
+        })
+    }
+}
+
+async fn authenticate_headers(
+    server: &Server,
+    req: &HttpRequest,
+    session: &HttpSessionData,
+    allow_api_access: bool,
+    protocol: Option<TenantProtocol>,
+) -> trc::Result<(Option<InFlight>, Arc<AccessToken>)> {
+    if let Some((mechanism, token)) = req.authorization() {
+        let is_bearer = mechanism.eq_ignore_ascii_case("bearer");
+        let now = now();
+
+        // Check if the credentials are cached, credentials validated outside
+        // the management API may be app passwords and are not reused there
+        let mut http_session = None;
+        if let Some(mut http_cache) = server.inner.cache.http_auth.get(token)
+            && (http_cache.allow_api_access || !allow_api_access)
+        {
+            // Sessions end once they reach the maximum lifetime set for the
+            // tenant or after being idle for too long
+            server
+                .refresh_session_limits(&mut http_cache.session.limits)
+                .await;
+            if http_cache.session.ended
+                || http_cache.session.limits.is_expired(
+                    http_cache.session.created_at,
+                    http_cache.session.last_used,
+                    now,
+                )
+            {
+                if is_bearer {
+                    http_cache.session.ended = true;
+                    server
+                        .inner
+                        .cache
+                        .http_auth
+                        .insert(token.to_string(), http_cache);
+
+                    return Err(trc::AuthEvent::TokenExpired
+                        .into_err()
+                        .details("Session expired")
+                        .caused_by(trc::location!()));
+                }
+
+                // Basic auth credentials start a new session
+                server.inner.cache.http_auth.remove(token);
+            } else {
+                http_cache.session.last_used = now;
+
                 // Make sure the revision is still valid
                 if http_cache.expires <= Instant::now() {
-                    let access_token = self.get_access_token(http_cache.account_id).await?;
+                    let access_token = server.get_access_token(http_cache.account_id).await?;
                     if access_token.revision == http_cache.revision {
+                        // HTTP requests are stateless, so each one counts as a login
+                        server.assert_tenant_available(access_token.tenant.map(|t| t.id))?;
+                        if let Some(protocol) = protocol {
+                            server
+                                .assert_protocol_enabled(
+                                    access_token.tenant.map(|t| t.id),
+                                    protocol,
+                                )
+                                .await?;
+                            access_token.assert_protocol_allowed(protocol.as_str())?;
+                        }
+                        server
+                            .inner
+                            .cache
+                            .http_auth
+                            .insert(token.to_string(), http_cache);
+
                         // Enforce authenticated rate limit
-                        return self
+                        return server
                             .is_http_authenticated_request_allowed(&access_token)
                             .await
                             .map(|in_flight| (in_flight, access_token));
@@ -46,79 +147,105 @@ impl Authenticator for Server {
                 }
 
                 // If the revision is not valid, remove the cached credentials
-                self.inner.cache.http_auth.remove(token);
+                server.inner.cache.http_auth.remove(token);
+                http_session = Some(http_cache.session);
             }
+        }
 
-            let credentials = if mechanism.eq_ignore_ascii_case("basic") {
-                // Decode the base64 encoded credentials
-                decode_plain_auth(token).ok_or_else(|| {
-                    trc::AuthEvent::Error
-                        .into_err()
-                        .details("Failed to decode Basic auth request.")
-                        .id(token.to_string())
-                        .caused_by(trc::location!())
-                })?
-            } else if mechanism.eq_ignore_ascii_case("bearer") {
-                // Enforce anonymous rate limit
-                self.is_http_anonymous_request_allowed(&session.remote_ip)
-                    .await?;
-
-                decode_bearer_token(token, allow_api_access).ok_or_else(|| {
-                    trc::AuthEvent::Error
-                        .into_err()
-                        .details("Failed to decode Bearer token.")
-                        .id(token.to_string())
-                        .caused_by(trc::location!())
-                })?
-            } else {
-                // Enforce anonymous rate limit
-                self.is_http_anonymous_request_allowed(&session.remote_ip)
-                    .await?;
-
-                return Err(trc::AuthEvent::Error
+        let credentials = if mechanism.eq_ignore_ascii_case("basic") {
+            // Decode the base64 encoded credentials
+            decode_plain_auth(token).ok_or_else(|| {
+                trc::AuthEvent::Error
                     .into_err()
-                    .reason("Unsupported authentication mechanism.")
-                    .details(token.to_string())
-                    .caused_by(trc::location!()));
-            };
+                    .details("Failed to decode Basic auth request.")
+                    .id(token.to_string())
+                    .caused_by(trc::location!())
+            })?
+        } else if is_bearer {
+            // Enforce anonymous rate limit
+            server
+                .is_http_anonymous_request_allowed(&session.remote_ip)
+                .await?;
 
-            // Authenticate
-            let access_token = self
-                .authenticate(
-                    &AuthRequest::from_credentials(
-                        credentials,
-                        session.session_id,
-                        session.remote_ip,
-                    )
-                    .with_api_access(allow_api_access),
-                )
+            decode_bearer_token(token, allow_api_access).ok_or_else(|| {
+                trc::AuthEvent::Error
+                    .into_err()
+                    .details("Failed to decode Bearer token.")
+                    .id(token.to_string())
+                    .caused_by(trc::location!())
+            })?
+        } else {
+            // Enforce anonymous rate limit
+            server
+                .is_http_anonymous_request_allowed(&session.remote_ip)
                 .await?;
 
-            // Cache credentials
-            self.inner.cache.http_auth.insert(
+            return Err(trc::AuthEvent::Error
+                .into_err()
+                .reason("Unsupported authentication mechanism.")
+                .details(token.to_string())
+                .caused_by(trc::location!()));
+        };
+
+        // Accounts with an expired password may only change their password
+        let allow_expired_password =
+            allow_api_access && req.uri().path().trim_end_matches('/') == "/api/account/auth";
+
+        // Authenticate
+        let auth_request =
+            AuthRequest::from_credentials(credentials, session.session_id, session.remote_ip)
+                .with_api_access(allow_api_access)
+                .with_protocol(if allow_api_access {
+                    AuthProtocol::Management
+                } else {
+                    AuthProtocol::Http
+                })
+                .with_tenant_protocol(protocol)
+                .with_expired_password(allow_expired_password);
+        let access_token = server.authenticate(&auth_request).await?;
+
+        // Cache credentials, the session continues when they were cached before
+        if !allow_expired_password {
+            let session = match http_session {
+                Some(session) => session,
+                None => HttpSession {
+                    created_at: now,
+                    last_used: now,
+                    limits: server
+                        .session_limits(access_token.tenant.map(|t| t.id))
+                        .await,
+                    ended: false,
+                    impersonated: auth_request.is_impersonated(),
+                },
+            };
+            server.inner.cache.http_auth.insert(
                 token.to_string(),
                 HttpAuthCache {
                     account_id: access_token.primary_id(),
                     revision: access_token.revision,
                     expires: Instant::now()
-                        + Duration::from_secs(self.core.oauth.oauth_expiry_token),
+                        + Duration::from_secs(server.core.oauth.oauth_expiry_token),
+                    allow_api_access,
+                    session,
                 },
             );
+        }
 
-            // Enforce authenticated rate limit
-            self.is_http_authenticated_request_allowed(&access_token)
-                .await
-                .map(|in_flight| (in_flight, access_token))
-        } else {
-            // Enforce anonymous rate limit
-            self.is_http_anonymous_request_allowed(&session.remote_ip)
-                .await?;
+        // Enforce authenticated rate limit
+        server
+            .is_http_authenticated_request_allowed(&access_token)
+            .await
+            .map(|in_flight| (in_flight, access_token))
+    } else {
+        // Enforce anonymous rate limit
+        server
+            .is_http_anonymous_request_allowed(&session.remote_ip)
+            .await?;
 
-            Err(trc::AuthEvent::Failed
-                .into_err()
-                .details("Missing Authorization header.")
-                .caused_by(trc::location!()))
-        }
+        Err(trc::AuthEvent::Failed
+            .into_err()
+            .details("Missing Authorization header.")
+            .caused_by(trc::location!()))
     }
 }
 
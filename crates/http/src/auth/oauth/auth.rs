@@ -42,6 +42,7 @@ pub struct OAuthMetadata {
     pub device_authorization_endpoint: String,
     pub registration_endpoint: String,
     pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
     pub grant_types_supported: Vec<String>,
     pub response_types_supported: Vec<String>,
     pub scopes_supported: Vec<String>,
@@ -51,6 +52,7 @@ pub trait OAuthApiHandler: Sync + Send {
     fn handle_oauth_api_request(
         &self,
         access_token: Arc<AccessToken>,
+        impersonated: bool,
         body: Option<Vec<u8>>,
     ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
 
@@ -71,6 +73,7 @@ impl OAuthApiHandler for Server {
     async fn handle_oauth_api_request(
         &self,
         access_token: Arc<AccessToken>,
+        impersonated: bool,
         body: Option<Vec<u8>>,
     ) -> trc::Result<HttpResponse> {
         let request =
@@ -110,6 +113,7 @@ impl OAuthApiHandler for Server {
                 let value = Archiver::new(OAuthCode {
                     status: OAuthStatus::Authorized,
                     account_id: access_token.primary_id(),
+                    impersonated,
                     client_id,
                     nonce,
                     params: redirect_uri.unwrap_or_default(),
@@ -168,6 +172,7 @@ impl OAuthApiHandler for Server {
                         let new_oauth_code = OAuthCode {
                             status: OAuthStatus::Authorized,
                             account_id: access_token.primary_id(),
+                            impersonated,
                             client_id: oauth.client_id.to_string(),
                             nonce: oauth.nonce.as_ref().map(|s| s.to_string()),
                             params: Default::default(),
@@ -251,6 +256,7 @@ impl OAuthApiHandler for Server {
         let oauth_code = Archiver::new(OAuthCode {
             status: OAuthStatus::Pending,
             account_id: u32::MAX,
+            impersonated: false,
             client_id,
             nonce,
             params: device_code.clone(),
@@ -310,6 +316,7 @@ impl OAuthApiHandler for Server {
             token_endpoint: format!("{base_url}/auth/token"),
             device_authorization_endpoint: format!("{base_url}/auth/device"),
             introspection_endpoint: format!("{base_url}/auth/introspect"),
+            revocation_endpoint: format!("{base_url}/auth/revoke"),
             registration_endpoint: format!("{base_url}/auth/register"),
             grant_types_supported: vec![
                 "authorization_code".to_string(),
@@ -50,6 +50,7 @@ pub struct OAuth {
 pub struct OAuthCode {
     pub status: OAuthStatus,
     pub account_id: u32,
+    pub impersonated: bool,
     pub client_id: String,
     pub nonce: Option<String>,
     pub params: String,
@@ -12,17 +12,26 @@ use common::{
     KV_OAUTH, Server,
     auth::{
         AccessToken,
-        oauth::{GrantType, oidc::StandardClaims},
+        oauth::{GrantType, oidc::StandardClaims, token::TokenGrant},
     },
+    config::overrides::TenantSetting,
 };
 use http_proto::*;
 use hyper::StatusCode;
-use std::future::Future;
+use serde_json::json;
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
 use store::{
     dispatch::lookup::KeyValue,
     write::{AlignedBytes, Archive},
 };
-use trc::AddContext;
+use trc::{AddContext, AuthEvent, EventType};
+
+// Introspection and revocation responses are delayed to this minimum so that
+// their timing does not reveal whether a token was valid
+const TOKEN_CHECK_MIN_DURATION: Duration = Duration::from_millis(50);
 
 pub trait TokenHandler: Sync + Send {
     fn handle_token_request(
@@ -38,10 +47,17 @@ pub trait TokenHandler: Sync + Send {
         session_id: u64,
     ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
 
+    fn handle_token_revoke(
+        &self,
+        req: &mut HttpRequest,
+        session_id: u64,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
     fn issue_token(
         &self,
         account_id: u32,
         client_id: &str,
+        grant: TokenGrant,
         issuer: String,
         nonce: Option<String>,
         with_refresh_token: bool,
@@ -115,6 +131,7 @@ impl TokenHandler for Server {
                                 self.issue_token(
                                     oauth.account_id.into(),
                                     &oauth.client_id,
+                                    TokenGrant::new(oauth.impersonated),
                                     issuer,
                                     oauth.nonce.as_ref().map(|s| s.as_str().into()),
                                     true,
@@ -187,6 +204,7 @@ impl TokenHandler for Server {
                                     self.issue_token(
                                         oauth.account_id.into(),
                                         &oauth.client_id,
+                                        TokenGrant::new(oauth.impersonated),
                                         issuer,
                                         oauth.nonce.as_ref().map(|s| s.as_str().into()),
                                         true,
@@ -222,6 +240,7 @@ impl TokenHandler for Server {
                         .issue_token(
                             token_info.account_id,
                             &token_info.client_id,
+                            token_info.grant,
                             issuer,
                             None,
                             token_info.expires_in
@@ -267,6 +286,8 @@ impl TokenHandler for Server {
         access_token: &AccessToken,
         session_id: u64,
     ) -> trc::Result<HttpResponse> {
+        let started = Instant::now();
+
         // Parse token
         let token = FormData::from_request(req, 1024, session_id)
             .await?
@@ -274,40 +295,113 @@ impl TokenHandler for Server {
             .ok_or_else(|| {
                 trc::ResourceEvent::BadParameters
                     .into_err()
-                    .details("Client ID is missing.")
+                    .details("Token is missing.")
             })?;
 
-        self.introspect_access_token(&token, access_token)
-            .await
-            .map(|response| JsonResponse::new(response).no_cache().into_http_response())
+        let response = self.introspect_access_token(&token, access_token).await;
+        tokio::time::sleep(TOKEN_CHECK_MIN_DURATION.saturating_sub(started.elapsed())).await;
+
+        response.map(|response| JsonResponse::new(response).no_cache().into_http_response())
+    }
+
+    // Token revocation endpoint (RFC 7009)
+    async fn handle_token_revoke(
+        &self,
+        req: &mut HttpRequest,
+        session_id: u64,
+    ) -> trc::Result<HttpResponse> {
+        let started = Instant::now();
+
+        // Parse form, the token type is obtained from the token itself so
+        // the token_type_hint parameter is ignored
+        let mut params = FormData::from_request(req, MAX_POST_LEN, session_id).await?;
+        let token = params.remove("token").ok_or_else(|| {
+            trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Token is missing.")
+        })?;
+        let client_id = params.remove("client_id");
+
+        // Invalid tokens and tokens issued to other clients are not reported,
+        // the response is the same whether a token was revoked or not
+        let result = match self.validate_access_token(None, &token).await {
+            Ok(token_info)
+                if matches!(
+                    token_info.grant_type,
+                    GrantType::AccessToken | GrantType::RefreshToken
+                ) && client_id
+                    .as_deref()
+                    .is_none_or(|client_id| client_id == token_info.client_id) =>
+            {
+                self.revoke_token(&token, &token_info).await
+            }
+            Err(err)
+                if !matches!(
+                    err.event_type(),
+                    EventType::Auth(AuthEvent::Error) | EventType::Auth(AuthEvent::TokenExpired)
+                ) =>
+            {
+                Err(err)
+            }
+            _ => Ok(()),
+        };
+        tokio::time::sleep(TOKEN_CHECK_MIN_DURATION.saturating_sub(started.elapsed())).await;
+
+        result.map(|_| JsonResponse::new(json!({})).no_cache().into_http_response())
     }
 
     async fn issue_token(
         &self,
         account_id: u32,
         client_id: &str,
+        grant: TokenGrant,
         issuer: String,
         nonce: Option<String>,
         with_refresh_token: bool,
         with_id_token: bool,
     ) -> trc::Result<OAuthResponse> {
+        // Token lifetimes may be overridden by the tenant of the account and
+        // never exceed the maximum session lifetime
+        let access_token = self
+            .get_access_token(account_id)
+            .await
+            .caused_by(trc::location!())?;
+        let tenant_id = access_token.tenant.map(|tenant| tenant.id);
+        let limits = self.session_limits(tenant_id).await;
+        let max_lifetime = |expiry: u64| {
+            if limits.max_lifetime > 0 {
+                expiry.min(limits.max_lifetime)
+            } else {
+                expiry
+            }
+        };
+        let expiry_token = max_lifetime(
+            self.tenant_setting(tenant_id, TenantSetting::OAuthTokenExpiry)
+                .await,
+        );
+
         Ok(OAuthResponse {
             access_token: self
-                .encode_access_token(
+                .encode_granted_token(
                     GrantType::AccessToken,
                     account_id,
                     client_id,
-                    self.core.oauth.oauth_expiry_token,
+                    expiry_token,
+                    grant,
                 )
                 .await?,
             token_type: "bearer".to_string(),
-            expires_in: self.core.oauth.oauth_expiry_token,
+            expires_in: expiry_token,
             refresh_token: if with_refresh_token {
-                self.encode_access_token(
+                self.encode_granted_token(
                     GrantType::RefreshToken,
                     account_id,
                     client_id,
-                    self.core.oauth.oauth_expiry_refresh_token,
+                    max_lifetime(
+                        self.tenant_setting(tenant_id, TenantSetting::OAuthRefreshTokenExpiry)
+                            .await,
+                    ),
+                    grant,
                 )
                 .await?
                 .into()
@@ -315,12 +409,6 @@ impl TokenHandler for Server {
                 None
             },
             id_token: if with_id_token {
-                // Obtain access token
-                let access_token = self
-                    .get_access_token(account_id)
-                    .await
-                    .caused_by(trc::location!())?;
-
                 match self.issue_id_token(
                     account_id.to_string(),
                     issuer,
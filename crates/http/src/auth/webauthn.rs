@@ -0,0 +1,345 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::oauth::token::TokenHandler;
+use common::{
+    Server,
+    auth::{
+        AuthRequest,
+        oauth::token::TokenGrant,
+        webauthn::{
+            RelyingParty, WEBAUTHN_TIMEOUT, decode_base64url, encode_base64url, verify_assertion,
+        },
+    },
+    config::overrides::TenantSetting,
+};
+use directory::{
+    AuthProtocol, Permission, PrincipalData, Type,
+    backend::internal::{
+        lockout::AccountLockout,
+        manage::{self, ManageDirectory},
+        webauthn::WebAuthnCredentials,
+    },
+};
+use http_proto::{request::fetch_body, *};
+use hyper::header;
+use serde_json::{Value, json};
+use std::future::Future;
+use utils::url_params::UrlParams;
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct LoginStartRequest {
+    #[serde(default)]
+    username: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginFinishRequest {
+    challenge_id: String,
+    #[serde(default)]
+    password: Option<String>,
+    credential: LoginCredential,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LoginCredential {
+    id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    #[serde(default)]
+    user_handle: Option<String>,
+}
+
+pub trait WebAuthnLogin: Sync + Send {
+    fn handle_webauthn_login(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+        step: &str,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl WebAuthnLogin for Server {
+    // Passkeys replace the password when the tenant of the account allows it
+    // and the authenticator verified the user, otherwise they are a second
+    // factor and the password has to be sent along with the assertion.
+    async fn handle_webauthn_login(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+        step: &str,
+    ) -> trc::Result<HttpResponse> {
+        let rp = relying_party(self, req).await?;
+        let body = fetch_body(req, 16 * 1024, session.session_id).await;
+
+        match step {
+            "start" => {
+                let request = serde_json::from_slice::<LoginStartRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .unwrap_or_default();
+
+                // Unknown accounts get an empty list, as in a usernameless login
+                let mut account_id = None;
+                let mut allow = Vec::new();
+                if let Some(username) = request.username.filter(|name| !name.is_empty())
+                    && let Some(info) = self
+                        .store()
+                        .resolve_principal_info(&username, None)
+                        .await?
+                        .filter(|info| {
+                            info.typ == Type::Individual
+                                && (rp.tenant_id.is_none() || info.tenant == rp.tenant_id)
+                        })
+                {
+                    account_id = Some(info.id);
+                    allow = self
+                        .store()
+                        .list_webauthn_credentials(info.id)
+                        .await?
+                        .into_iter()
+                        .filter(|entry| entry.credential.rp_id == rp.id)
+                        .map(|entry| credential_descriptor(&entry.credential.id))
+                        .collect();
+                }
+                let (challenge_id, challenge) = self
+                    .create_webauthn_challenge(&rp, account_id, None, false)
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "challengeId": challenge_id,
+                        "expiresIn": WEBAUTHN_TIMEOUT,
+                        "publicKey": {
+                            "rpId": rp.id,
+                            "challenge": encode_base64url(&challenge.challenge),
+                            "timeout": WEBAUTHN_TIMEOUT * 1000,
+                            "allowCredentials": allow,
+                            "userVerification": "preferred",
+                        },
+                    },
+                }))
+                .no_cache()
+                .into_http_response())
+            }
+            "finish" => {
+                let request = serde_json::from_slice::<LoginFinishRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                let challenge = self
+                    .take_webauthn_challenge(&request.challenge_id)
+                    .await?
+                    .filter(|challenge| challenge.rp_id == rp.id)
+                    .ok_or_else(|| login_failed("Unknown or expired challenge"))?;
+                let response = &request.credential.response;
+
+                // The account is identified by the user handle of discoverable
+                // credentials or by the username the login was started with
+                let user_handle = response
+                    .user_handle
+                    .as_deref()
+                    .filter(|handle| !handle.is_empty())
+                    .map(|handle| {
+                        decode_base64url(handle)
+                            .and_then(|handle| handle.try_into().ok())
+                            .map(u32::from_be_bytes)
+                            .ok_or_else(|| login_failed("Invalid user handle"))
+                    })
+                    .transpose()?;
+                let account_id = match (user_handle, challenge.account_id) {
+                    (Some(a), Some(b)) if a != b => {
+                        return Err(login_failed("User handle mismatch"));
+                    }
+                    (Some(account_id), _) | (None, Some(account_id)) => account_id,
+                    (None, None) => return Err(login_failed("Missing user handle")),
+                };
+                let principal = self
+                    .store()
+                    .get_principal(account_id)
+                    .await?
+                    .filter(|principal| {
+                        principal.typ() == Type::Individual
+                            && (rp.tenant_id.is_none() || principal.tenant() == rp.tenant_id)
+                    })
+                    .ok_or_else(|| login_failed("Unknown account"))?;
+                let credential_id = decode_base64url(&request.credential.id)
+                    .ok_or_else(|| login_failed("Invalid credential id"))?;
+                let public_key = principal
+                    .data
+                    .iter()
+                    .find_map(|item| match item {
+                        PrincipalData::WebAuthnCredential {
+                            id,
+                            public_key,
+                            rp_id,
+                            ..
+                        } if *id == credential_id && *rp_id == rp.id => Some(public_key),
+                        _ => None,
+                    })
+                    .ok_or_else(|| login_failed("Unknown credential"))?;
+
+                let assertion = match (
+                    decode_base64url(&response.client_data_json),
+                    decode_base64url(&response.authenticator_data),
+                    decode_base64url(&response.signature),
+                ) {
+                    (Some(client_data_json), Some(authenticator_data), Some(signature)) => {
+                        verify_assertion(
+                            &client_data_json,
+                            &authenticator_data,
+                            &signature,
+                            &challenge.challenge,
+                            &rp.id,
+                            public_key,
+                        )
+                    }
+                    _ => Err("Invalid base64 encoding".to_string()),
+                }
+                .map_err(|err| login_failed(err).account_id(account_id))?;
+
+                // Verify the password, or that passkeys may replace it
+                if let Some(password) = request.password {
+                    match self
+                        .authenticate(
+                            &AuthRequest::from_plain(
+                                principal.name(),
+                                password,
+                                session.session_id,
+                                session.remote_ip,
+                            )
+                            .with_api_access(true)
+                            .with_protocol(AuthProtocol::Management),
+                        )
+                        .await
+                    {
+                        Ok(_) => {}
+                        // The passkey replaces the TOTP code
+                        Err(err)
+                            if err.matches(trc::EventType::Auth(trc::AuthEvent::MissingTotp)) => {}
+                        Err(err) => return Err(err),
+                    }
+                } else if !assertion.user_verified {
+                    return Err(
+                        login_failed("User verification is required").account_id(account_id)
+                    );
+                } else if self
+                    .tenant_setting(principal.tenant(), TenantSetting::WebAuthnPasswordless)
+                    .await
+                    == 0
+                {
+                    return Err(manage::error(
+                        "Password required",
+                        "Passkeys are a second factor for this account".into(),
+                    ));
+                }
+
+                // Passwordless logins skip the directory, so locked accounts
+                // are rejected here as well
+                let is_lockout_enabled = if let Some(tenant_id) = principal.tenant() {
+                    self.store()
+                        .get_principal(tenant_id)
+                        .await?
+                        .and_then(|tenant| tenant.lockout_policy())
+                        .is_some_and(|policy| policy.threshold > 0)
+                } else {
+                    false
+                };
+                if is_lockout_enabled
+                    && let Some(until) = self.store().account_locked_until(account_id).await?
+                {
+                    return Err(trc::AuthEvent::AccountLocked
+                        .into_err()
+                        .account_id(account_id)
+                        .ctx(trc::Key::Expires, trc::Value::Timestamp(until))
+                        .ctx(trc::Key::RemoteIp, session.remote_ip));
+                }
+
+                // Counters that don't increase indicate a cloned authenticator
+                if !self
+                    .store()
+                    .webauthn_credential_used(account_id, &credential_id, assertion.sign_count)
+                    .await?
+                {
+                    return Err(login_failed("Signature counter did not increase")
+                        .account_id(account_id)
+                        .id(request.credential.id));
+                }
+
+                let access_token = self.get_access_token(account_id).await?;
+                access_token.assert_has_permission(Permission::Authenticate)?;
+                self.assert_tenant_available(access_token.tenant.map(|t| t.id))?;
+                self.record_activity(account_id, AuthProtocol::Management.into())
+                    .await;
+
+                let issuer = HttpContext::new(session, req)
+                    .resolve_response_url(self)
+                    .await;
+                let response = self
+                    .issue_token(
+                        account_id,
+                        "webadmin",
+                        TokenGrant::new(false),
+                        issuer,
+                        None,
+                        true,
+                        false,
+                    )
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .no_cache()
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+/// Resolves the relying party from the `domain` parameter or else from the
+/// Host header, as the branding of the login page.
+pub(crate) async fn relying_party(server: &Server, req: &HttpRequest) -> trc::Result<RelyingParty> {
+    let params = UrlParams::new(req.uri().query());
+    let host = params
+        .get("domain")
+        .or_else(|| {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+        })
+        .unwrap_or_default();
+
+    server.webauthn_relying_party(host).await?.ok_or_else(|| {
+        manage::error(
+            "Invalid relying party",
+            format!("Passkeys are not available on {host:?}").into(),
+        )
+    })
+}
+
+pub(crate) fn credential_descriptor(id: &[u8]) -> Value {
+    json!({
+        "type": "public-key",
+        "id": encode_base64url(id),
+    })
+}
+
+fn login_failed(reason: impl Into<trc::Value>) -> trc::Error {
+    trc::AuthEvent::Failed.into_err().details(reason)
+}
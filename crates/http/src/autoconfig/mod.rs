@@ -5,15 +5,25 @@
  */
 
 use common::{Server, manager::webadmin::Resource};
-use directory::QueryParams;
+use directory::{QueryParams, Type, backend::internal::lookup::DirectoryStore};
 use http_proto::*;
 use quick_xml::Reader;
+use quick_xml::escape::escape;
 use quick_xml::events::Event;
 use std::fmt::Write;
 use std::future::Future;
 use trc::AddContext;
 use utils::url_params::UrlParams;
 
+/// Client settings served for an e-mail address.
+pub struct AutoconfigParameters<'x> {
+    pub account_name: String,
+    pub server_name: String,
+    pub domain: &'x str,
+    /// Brand name of the tenant owning the domain.
+    pub display_name: Option<String>,
+}
+
 pub trait Autoconfig: Sync + Send {
     fn handle_autoconfig_request(
         &self,
@@ -27,7 +37,7 @@ pub trait Autoconfig: Sync + Send {
         &'x self,
         emailaddress: &'x str,
         fail_if_invalid: bool,
-    ) -> impl Future<Output = trc::Result<(String, String, &'x str)>> + Send;
+    ) -> impl Future<Output = trc::Result<AutoconfigParameters<'x>>> + Send;
 }
 
 impl Autoconfig for Server {
@@ -38,73 +48,14 @@ impl Autoconfig for Server {
             .get("emailaddress")
             .unwrap_or_default()
             .to_lowercase();
-        let (account_name, server_name, domain) =
-            self.autoconfig_parameters(&emailaddress, false).await?;
+        let params = self.autoconfig_parameters(&emailaddress, false).await?;
         let services = self.core.storage.config.get_services().await?;
 
-        // Build XML response
-        let mut config = String::with_capacity(1024);
-        config.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
-        config.push_str("<clientConfig version=\"1.1\">\n");
-        let _ = writeln!(&mut config, "\t<emailProvider id=\"{domain}\">");
-        let _ = writeln!(&mut config, "\t\t<domain>{domain}</domain>");
-        let _ = writeln!(&mut config, "\t\t<displayName>{emailaddress}</displayName>");
-        let _ = writeln!(
-            &mut config,
-            "\t\t<displayShortName>{domain}</displayShortName>"
-        );
-        for (protocol, port, is_tls) in services {
-            let tag = match protocol.as_str() {
-                "imap" | "pop3" => "incomingServer",
-                "smtp" if port != 25 => "outgoingServer",
-                _ => continue,
-            };
-            let _ = writeln!(&mut config, "\t\t<{tag} type=\"{protocol}\">");
-            let _ = writeln!(&mut config, "\t\t\t<hostname>{server_name}</hostname>");
-            let _ = writeln!(&mut config, "\t\t\t<port>{port}</port>");
-            let _ = writeln!(
-                &mut config,
-                "\t\t\t<socketType>{}</socketType>",
-                if is_tls { "SSL" } else { "STARTTLS" }
-            );
-            let _ = writeln!(&mut config, "\t\t\t<username>{account_name}</username>");
-            let _ = writeln!(
-                &mut config,
-                "\t\t\t<authentication>password-cleartext</authentication>"
-            );
-            let _ = writeln!(&mut config, "\t\t</{tag}>");
-        }
-
-        config.push_str("\t</emailProvider>\n");
-
-        for (tag, protocol, url) in [
-            ("addressBook", "carddav", "card"),
-            ("calendar", "caldav", "cal"),
-            ("fileShare", "webdav", "file"),
-        ] {
-            let _ = writeln!(&mut config, "\t<{tag} type=\"{protocol}\">");
-            let _ = writeln!(&mut config, "\t\t<username>{account_name}</username>");
-            let _ = writeln!(
-                &mut config,
-                "\t\t<authentication>http-basic</authentication>"
-            );
-            let _ = writeln!(
-                &mut config,
-                "\t\t<serverURL>https://{server_name}/dav/{url}</serverURL>"
-            );
-            let _ = writeln!(&mut config, "\t</{tag}>");
-        }
-
-        let _ = writeln!(
-            &mut config,
-            "\t<clientConfigUpdate url=\"https://autoconfig.{domain}/mail/config-v1.1.xml\"></clientConfigUpdate>"
-        );
-        config.push_str("</clientConfig>\n");
-
-        Ok(
-            Resource::new("application/xml; charset=utf-8", config.into_bytes())
-                .into_http_response(),
+        Ok(Resource::new(
+            "application/xml; charset=utf-8",
+            build_autoconfig(&params, &emailaddress, &services).into_bytes(),
         )
+        .into_http_response())
     }
 
     async fn handle_autodiscover_request(
@@ -119,93 +70,30 @@ impl Autoconfig for Server {
                     .details("Failed to parse autodiscover request")
                     .ctx(trc::Key::Reason, err)
             })?;
-        let (account_name, server_name, _) =
-            self.autoconfig_parameters(&emailaddress, true).await?;
+        let params = self.autoconfig_parameters(&emailaddress, true).await?;
         let services = self.core.storage.config.get_services().await?;
 
-        // Build XML response
-        let mut config = String::with_capacity(1024);
-        let _ = writeln!(&mut config, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
-        let _ = writeln!(
-            &mut config,
-            "<Autodiscover xmlns=\"http://schemas.microsoft.com/exchange/autodiscover/responseschema/2006\">"
-        );
-        let _ = writeln!(
-            &mut config,
-            "\t<Response xmlns=\"http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a\">"
-        );
-        let _ = writeln!(&mut config, "\t\t<User>");
-        let _ = writeln!(
-            &mut config,
-            "\t\t\t<DisplayName>{emailaddress}</DisplayName>"
-        );
-        let _ = writeln!(
-            &mut config,
-            "\t\t\t<AutoDiscoverSMTPAddress>{emailaddress}</AutoDiscoverSMTPAddress>"
-        );
-        // DeploymentId is a required field of User but we are not a MS Exchange server so use a random value
-        let _ = writeln!(
-            &mut config,
-            "\t\t\t<DeploymentId>644560b8-a1ce-429c-8ace-23395843f701</DeploymentId>"
-        );
-        let _ = writeln!(&mut config, "\t\t</User>");
-        let _ = writeln!(&mut config, "\t\t<Account>");
-        let _ = writeln!(&mut config, "\t\t\t<AccountType>email</AccountType>");
-        let _ = writeln!(&mut config, "\t\t\t<Action>settings</Action>");
-        for (protocol, port, is_tls) in services {
-            match protocol.as_str() {
-                "imap" | "pop3" => (),
-                "smtp" if port != 25 => (),
-                _ => continue,
-            }
-
-            let _ = writeln!(&mut config, "\t\t\t<Protocol>");
-            let _ = writeln!(
-                &mut config,
-                "\t\t\t\t<Type>{}</Type>",
-                protocol.to_uppercase()
-            );
-            let _ = writeln!(&mut config, "\t\t\t\t<Server>{server_name}</Server>");
-            let _ = writeln!(&mut config, "\t\t\t\t<Port>{port}</Port>");
-            let _ = writeln!(&mut config, "\t\t\t\t<LoginName>{account_name}</LoginName>");
-            let _ = writeln!(&mut config, "\t\t\t\t<AuthRequired>on</AuthRequired>");
-            let _ = writeln!(&mut config, "\t\t\t\t<DirectoryPort>0</DirectoryPort>");
-            let _ = writeln!(&mut config, "\t\t\t\t<ReferralPort>0</ReferralPort>");
-            let _ = writeln!(
-                &mut config,
-                "\t\t\t\t<SSL>{}</SSL>",
-                if is_tls { "on" } else { "off" }
-            );
-            if is_tls {
-                let _ = writeln!(&mut config, "\t\t\t\t<Encryption>TLS</Encryption>");
-            }
-            let _ = writeln!(&mut config, "\t\t\t\t<SPA>off</SPA>");
-            let _ = writeln!(&mut config, "\t\t\t</Protocol>");
-        }
-
-        let _ = writeln!(&mut config, "\t\t</Account>");
-        let _ = writeln!(&mut config, "\t</Response>");
-        let _ = writeln!(&mut config, "</Autodiscover>");
-
-        Ok(
-            Resource::new("application/xml; charset=utf-8", config.into_bytes())
-                .into_http_response(),
+        Ok(Resource::new(
+            "application/xml; charset=utf-8",
+            build_autodiscover(&params, &emailaddress, &services).into_bytes(),
         )
+        .into_http_response())
     }
 
     async fn autoconfig_parameters<'x>(
         &'x self,
         emailaddress: &'x str,
         fail_if_invalid: bool,
-    ) -> trc::Result<(String, String, &'x str)> {
+    ) -> trc::Result<AutoconfigParameters<'x>> {
         // Return EMAILADDRESS
         let Some((_, domain)) = emailaddress.rsplit_once('@') else {
             return if !fail_if_invalid {
-                Ok((
-                    "%EMAILADDRESS%".to_string(),
-                    self.core.network.server_name.clone(),
-                    &self.core.network.report_domain,
-                ))
+                Ok(AutoconfigParameters {
+                    account_name: "%EMAILADDRESS%".to_string(),
+                    server_name: self.core.network.server_name.clone(),
+                    domain: &self.core.network.report_domain,
+                    display_name: None,
+                })
             } else {
                 Err(trc::ResourceEvent::BadParameters
                     .into_err()
@@ -213,6 +101,21 @@ impl Autoconfig for Server {
             };
         };
 
+        // Settings are only served for domains in the directory
+        if !self
+            .core
+            .storage
+            .directory
+            .is_local_domain(domain)
+            .await
+            .caused_by(trc::location!())?
+        {
+            return Err(trc::ResourceEvent::NotFound
+                .into_err()
+                .details("Unknown domain")
+                .ctx(trc::Key::Domain, domain.to_string()));
+        }
+
         // Find the account name by e-mail address
         let mut account_name = emailaddress.into();
         if let Some(id) = self
@@ -235,10 +138,180 @@ impl Autoconfig for Server {
             account_name = principal.name;
         }
 
-        Ok((account_name, self.core.network.server_name.clone(), domain))
+        // Use the brand name of the tenant owning the domain
+        let mut display_name = None;
+        if let Some(tenant_id) = self
+            .store()
+            .query(QueryParams::name(domain).with_return_member_of(false))
+            .await
+            .caused_by(trc::location!())?
+            .filter(|p| p.typ() == Type::Domain)
+            .and_then(|p| p.tenant())
+        {
+            display_name = self
+                .store()
+                .query(QueryParams::id(tenant_id).with_return_member_of(false))
+                .await
+                .caused_by(trc::location!())?
+                .and_then(|p| p.brand_name().map(|name| name.to_string()));
+        }
+
+        Ok(AutoconfigParameters {
+            account_name,
+            server_name: self.core.network.server_name.clone(),
+            domain,
+            display_name,
+        })
     }
 }
 
+/// Builds a Thunderbird autoconfig response (clientConfig version 1.1).
+pub fn build_autoconfig(
+    params: &AutoconfigParameters<'_>,
+    emailaddress: &str,
+    services: &[(String, u16, bool)],
+) -> String {
+    let domain = params.domain;
+    let server_name = &params.server_name;
+    let account_name = escape(&params.account_name);
+    let display_name = escape(params.display_name.as_deref().unwrap_or(emailaddress));
+    let short_name = escape(params.display_name.as_deref().unwrap_or(domain));
+
+    let mut config = String::with_capacity(1024);
+    config.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    config.push_str("<clientConfig version=\"1.1\">\n");
+    let _ = writeln!(&mut config, "\t<emailProvider id=\"{domain}\">");
+    let _ = writeln!(&mut config, "\t\t<domain>{domain}</domain>");
+    let _ = writeln!(&mut config, "\t\t<displayName>{display_name}</displayName>");
+    let _ = writeln!(
+        &mut config,
+        "\t\t<displayShortName>{short_name}</displayShortName>"
+    );
+    for (protocol, port, is_tls) in services {
+        let tag = match protocol.as_str() {
+            "imap" | "pop3" => "incomingServer",
+            "smtp" if *port != 25 => "outgoingServer",
+            _ => continue,
+        };
+        let _ = writeln!(&mut config, "\t\t<{tag} type=\"{protocol}\">");
+        let _ = writeln!(&mut config, "\t\t\t<hostname>{server_name}</hostname>");
+        let _ = writeln!(&mut config, "\t\t\t<port>{port}</port>");
+        let _ = writeln!(
+            &mut config,
+            "\t\t\t<socketType>{}</socketType>",
+            if *is_tls { "SSL" } else { "STARTTLS" }
+        );
+        let _ = writeln!(&mut config, "\t\t\t<username>{account_name}</username>");
+        let _ = writeln!(
+            &mut config,
+            "\t\t\t<authentication>password-cleartext</authentication>"
+        );
+        let _ = writeln!(&mut config, "\t\t</{tag}>");
+    }
+
+    config.push_str("\t</emailProvider>\n");
+
+    for (tag, protocol, url) in [
+        ("addressBook", "carddav", "card"),
+        ("calendar", "caldav", "cal"),
+        ("fileShare", "webdav", "file"),
+    ] {
+        let _ = writeln!(&mut config, "\t<{tag} type=\"{protocol}\">");
+        let _ = writeln!(&mut config, "\t\t<username>{account_name}</username>");
+        let _ = writeln!(
+            &mut config,
+            "\t\t<authentication>http-basic</authentication>"
+        );
+        let _ = writeln!(
+            &mut config,
+            "\t\t<serverURL>https://{server_name}/dav/{url}</serverURL>"
+        );
+        let _ = writeln!(&mut config, "\t</{tag}>");
+    }
+
+    let _ = writeln!(
+        &mut config,
+        "\t<clientConfigUpdate url=\"https://autoconfig.{domain}/mail/config-v1.1.xml\"></clientConfigUpdate>"
+    );
+    config.push_str("</clientConfig>\n");
+    config
+}
+
+/// Builds an Outlook autodiscover response (POX schema 2006a).
+pub fn build_autodiscover(
+    params: &AutoconfigParameters<'_>,
+    emailaddress: &str,
+    services: &[(String, u16, bool)],
+) -> String {
+    let account_name = escape(&params.account_name);
+    let server_name = &params.server_name;
+    let emailaddress = escape(emailaddress);
+
+    let mut config = String::with_capacity(1024);
+    let _ = writeln!(&mut config, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    let _ = writeln!(
+        &mut config,
+        "<Autodiscover xmlns=\"http://schemas.microsoft.com/exchange/autodiscover/responseschema/2006\">"
+    );
+    let _ = writeln!(
+        &mut config,
+        "\t<Response xmlns=\"http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a\">"
+    );
+    let _ = writeln!(&mut config, "\t\t<User>");
+    let _ = writeln!(
+        &mut config,
+        "\t\t\t<DisplayName>{emailaddress}</DisplayName>"
+    );
+    let _ = writeln!(
+        &mut config,
+        "\t\t\t<AutoDiscoverSMTPAddress>{emailaddress}</AutoDiscoverSMTPAddress>"
+    );
+    // DeploymentId is a required field of User but we are not a MS Exchange server so use a random value
+    let _ = writeln!(
+        &mut config,
+        "\t\t\t<DeploymentId>644560b8-a1ce-429c-8ace-23395843f701</DeploymentId>"
+    );
+    let _ = writeln!(&mut config, "\t\t</User>");
+    let _ = writeln!(&mut config, "\t\t<Account>");
+    let _ = writeln!(&mut config, "\t\t\t<AccountType>email</AccountType>");
+    let _ = writeln!(&mut config, "\t\t\t<Action>settings</Action>");
+    for (protocol, port, is_tls) in services {
+        match protocol.as_str() {
+            "imap" | "pop3" => (),
+            "smtp" if *port != 25 => (),
+            _ => continue,
+        }
+
+        let _ = writeln!(&mut config, "\t\t\t<Protocol>");
+        let _ = writeln!(
+            &mut config,
+            "\t\t\t\t<Type>{}</Type>",
+            protocol.to_uppercase()
+        );
+        let _ = writeln!(&mut config, "\t\t\t\t<Server>{server_name}</Server>");
+        let _ = writeln!(&mut config, "\t\t\t\t<Port>{port}</Port>");
+        let _ = writeln!(&mut config, "\t\t\t\t<LoginName>{account_name}</LoginName>");
+        let _ = writeln!(&mut config, "\t\t\t\t<AuthRequired>on</AuthRequired>");
+        let _ = writeln!(&mut config, "\t\t\t\t<DirectoryPort>0</DirectoryPort>");
+        let _ = writeln!(&mut config, "\t\t\t\t<ReferralPort>0</ReferralPort>");
+        let _ = writeln!(
+            &mut config,
+            "\t\t\t\t<SSL>{}</SSL>",
+            if *is_tls { "on" } else { "off" }
+        );
+        if *is_tls {
+            let _ = writeln!(&mut config, "\t\t\t\t<Encryption>TLS</Encryption>");
+        }
+        let _ = writeln!(&mut config, "\t\t\t\t<SPA>off</SPA>");
+        let _ = writeln!(&mut config, "\t\t\t</Protocol>");
+    }
+
+    let _ = writeln!(&mut config, "\t\t</Account>");
+    let _ = writeln!(&mut config, "\t</Response>");
+    let _ = writeln!(&mut config, "</Autodiscover>");
+    config
+}
+
 fn parse_autodiscover_request(bytes: &[u8]) -> Result<String, String> {
     if bytes.is_empty() {
         return Err("Empty request body".to_string());
@@ -325,6 +398,222 @@ fn parse_autodiscover_request(bytes: &[u8]) -> Result<String, String> {
 
 #[cfg(test)]
 mod tests {
+    use quick_xml::{Reader, escape::resolve_predefined_entity, events::Event};
+
+    use super::{AutoconfigParameters, build_autoconfig, build_autodiscover};
+
+    #[derive(Debug, Default)]
+    struct Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        text: String,
+        children: Vec<Element>,
+    }
+
+    impl Element {
+        fn parse(xml: &str) -> Element {
+            let mut reader = Reader::from_str(xml);
+            let mut stack = vec![Element::default()];
+            loop {
+                match reader.read_event().unwrap() {
+                    Event::Start(e) => stack.push(Element {
+                        name: String::from_utf8(e.local_name().as_ref().to_vec()).unwrap(),
+                        attributes: e
+                            .attributes()
+                            .map(|attr| {
+                                let attr = attr.unwrap();
+                                (
+                                    String::from_utf8(attr.key.as_ref().to_vec()).unwrap(),
+                                    attr.unescape_value().unwrap().to_string(),
+                                )
+                            })
+                            .collect(),
+                        ..Default::default()
+                    }),
+                    Event::End(_) => {
+                        let mut element = stack.pop().unwrap();
+                        element.text = element.text.trim().to_string();
+                        stack.last_mut().unwrap().children.push(element);
+                    }
+                    Event::Text(text) => {
+                        stack
+                            .last_mut()
+                            .unwrap()
+                            .text
+                            .push_str(&text.xml_content().unwrap());
+                    }
+                    Event::GeneralRef(entity) => {
+                        stack.last_mut().unwrap().text.push_str(
+                            resolve_predefined_entity(&entity.decode().unwrap()).unwrap(),
+                        );
+                    }
+                    Event::Eof => break,
+                    _ => (),
+                }
+            }
+            assert_eq!(stack.len(), 1);
+            stack.pop().unwrap().children.pop().unwrap()
+        }
+
+        fn attr(&self, name: &str) -> Option<&str> {
+            self.attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        }
+
+        fn child(&self, name: &str) -> Option<&Element> {
+            self.children.iter().find(|child| child.name == name)
+        }
+
+        fn children<'x>(&'x self, name: &'x str) -> impl Iterator<Item = &'x Element> {
+            self.children.iter().filter(move |child| child.name == name)
+        }
+
+        fn text_of(&self, name: &str) -> &str {
+            self.child(name)
+                .map(|child| child.text.as_str())
+                .unwrap_or_else(|| panic!("missing <{name}> in <{}>", self.name))
+        }
+    }
+
+    fn services() -> Vec<(String, u16, bool)> {
+        vec![
+            ("smtp".to_string(), 25, false),
+            ("smtp".to_string(), 465, true),
+            ("smtp".to_string(), 587, false),
+            ("imap".to_string(), 993, true),
+            ("pop3".to_string(), 995, true),
+            ("http".to_string(), 443, true),
+        ]
+    }
+
+    #[test]
+    fn thunderbird_autoconfig() {
+        let params = AutoconfigParameters {
+            account_name: "jane".to_string(),
+            server_name: "mail.example.org".to_string(),
+            domain: "example.org",
+            display_name: Some("Acme & Sons <Mail>".to_string()),
+        };
+        let config = Element::parse(&build_autoconfig(&params, "jane@example.org", &services()));
+
+        // Checks performed by Thunderbird's readFromXML
+        assert_eq!(config.name, "clientConfig");
+        assert_eq!(config.attr("version"), Some("1.1"));
+        let provider = config.child("emailProvider").unwrap();
+        assert_eq!(provider.attr("id"), Some("example.org"));
+        assert_eq!(provider.text_of("domain"), "example.org");
+        assert_eq!(provider.text_of("displayName"), "Acme & Sons <Mail>");
+        assert_eq!(provider.text_of("displayShortName"), "Acme & Sons <Mail>");
+
+        let mut incoming = Vec::new();
+        let mut outgoing = Vec::new();
+        for (tag, types, servers) in [
+            ("incomingServer", &["imap", "pop3"][..], &mut incoming),
+            ("outgoingServer", &["smtp"][..], &mut outgoing),
+        ] {
+            for server in provider.children(tag) {
+                let typ = server.attr("type").unwrap();
+                assert!(types.contains(&typ), "{typ}");
+                assert_eq!(server.text_of("hostname"), "mail.example.org");
+                let port = server.text_of("port").parse::<u16>().unwrap();
+                assert!(port > 0);
+                assert!(
+                    ["plain", "SSL", "STARTTLS"].contains(&server.text_of("socketType")),
+                    "{server:?}"
+                );
+                assert_eq!(server.text_of("username"), "jane");
+                assert!(
+                    [
+                        "password-cleartext",
+                        "password-encrypted",
+                        "OAuth2",
+                        "client-IP-address",
+                        "TLS-client-cert",
+                        "none"
+                    ]
+                    .contains(&server.text_of("authentication")),
+                    "{server:?}"
+                );
+                servers.push((typ.to_string(), port));
+            }
+        }
+        assert_eq!(
+            incoming,
+            [("imap".to_string(), 993), ("pop3".to_string(), 995)]
+        );
+        assert_eq!(
+            outgoing,
+            [("smtp".to_string(), 465), ("smtp".to_string(), 587)]
+        );
+
+        for (tag, url) in [
+            ("addressBook", "https://mail.example.org/dav/card"),
+            ("calendar", "https://mail.example.org/dav/cal"),
+        ] {
+            let service = config.child(tag).unwrap();
+            assert_eq!(service.text_of("serverURL"), url);
+            assert_eq!(service.text_of("username"), "jane");
+        }
+        assert_eq!(
+            config.child("clientConfigUpdate").unwrap().attr("url"),
+            Some("https://autoconfig.example.org/mail/config-v1.1.xml")
+        );
+
+        // Without a brand name the e-mail address is displayed
+        let params = AutoconfigParameters {
+            display_name: None,
+            ..params
+        };
+        let config = Element::parse(&build_autoconfig(&params, "jane@example.org", &services()));
+        let provider = config.child("emailProvider").unwrap();
+        assert_eq!(provider.text_of("displayName"), "jane@example.org");
+        assert_eq!(provider.text_of("displayShortName"), "example.org");
+    }
+
+    #[test]
+    fn outlook_autodiscover() {
+        let params = AutoconfigParameters {
+            account_name: "jane".to_string(),
+            server_name: "mail.example.org".to_string(),
+            domain: "example.org",
+            display_name: None,
+        };
+        let config = Element::parse(&build_autodiscover(
+            &params,
+            "jane@example.org",
+            &services(),
+        ));
+        assert_eq!(config.name, "Autodiscover");
+        let response = config.child("Response").unwrap();
+        assert_eq!(
+            response
+                .child("User")
+                .unwrap()
+                .text_of("AutoDiscoverSMTPAddress"),
+            "jane@example.org"
+        );
+        let account = response.child("Account").unwrap();
+        assert_eq!(account.text_of("AccountType"), "email");
+        assert_eq!(account.text_of("Action"), "settings");
+        assert_eq!(
+            account
+                .children("Protocol")
+                .map(|protocol| (
+                    protocol.text_of("Type"),
+                    protocol.text_of("Port"),
+                    protocol.text_of("SSL")
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("SMTP", "465", "on"),
+                ("SMTP", "587", "off"),
+                ("IMAP", "993", "on"),
+                ("POP3", "995", "on")
+            ]
+        );
+    }
 
     #[test]
     fn parse_autodiscover() {
@@ -0,0 +1,276 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::config::jmap::settings::HttpCors;
+use http_proto::{HttpResponse, JsonProblemResponse, ToHttpResponse};
+use hyper::{
+    HeaderMap, StatusCode,
+    header::{self, HeaderValue},
+};
+
+/// Builds the response to a CORS preflight request. Preflight requests are
+/// answered before authentication takes place.
+pub fn cors_preflight(cors: &HttpCors, headers: &HeaderMap) -> HttpResponse {
+    let Some(origin) = headers
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+    else {
+        return JsonProblemResponse(StatusCode::NO_CONTENT).into_http_response();
+    };
+
+    let is_allowed = cors.is_allowed_origin(origin)
+        && headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| method.to_str().ok())
+            .is_none_or(|method| cors.is_allowed_method(method))
+        && headers
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .all(|value| {
+                value.to_str().is_ok_and(|value| {
+                    value
+                        .split(',')
+                        .filter(|name| !name.trim().is_empty())
+                        .all(|name| cors.is_allowed_header(name))
+                })
+            });
+    if !is_allowed {
+        return JsonProblemResponse(StatusCode::FORBIDDEN)
+            .into_http_response()
+            .with_header(header::VARY, "Origin");
+    }
+
+    let mut response = HttpResponse::new(StatusCode::NO_CONTENT)
+        .with_header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+        .with_header(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            cors.methods
+                .iter()
+                .map(|method| method.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .with_header(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            cors.headers
+                .iter()
+                .map(|header| header.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .with_header(header::VARY, "Origin");
+    if cors.allow_credentials {
+        response = response.with_header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+    }
+    if let Some(max_age) = cors.max_age {
+        response = response.with_header(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs());
+    }
+    response
+}
+
+/// Adds CORS headers to the response of an actual (non-preflight) request.
+pub fn add_cors_headers(cors: &HttpCors, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+
+    if let Some(origin) =
+        origin.filter(|origin| origin.to_str().is_ok_and(|o| cors.is_allowed_origin(o)))
+    {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        if cors.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        } else {
+            headers.remove(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+        }
+    } else {
+        headers.remove(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+        headers.remove(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::config::jmap::settings::{CorsOrigin, HttpCors};
+    use hyper::{
+        HeaderMap, StatusCode,
+        header::{self, HeaderValue},
+    };
+    use utils::config::Config;
+
+    use super::{add_cors_headers, cors_preflight};
+
+    fn cors() -> HttpCors {
+        let mut config = Config::new(
+            r#"
+            [http.cors]
+            origins = ["https://admin.example.org", "https://*.example.com"]
+            methods = ["GET", "POST", "PATCH"]
+            allow-credentials = true
+            max-age = "10m"
+            listeners = ["https"]
+            "#,
+        )
+        .unwrap();
+        let cors = HttpCors::parse(&mut config);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        cors
+    }
+
+    fn preflight(origin: &str, method: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        headers.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_str(method).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn cors_origin_matching() {
+        let cors = cors();
+        assert!(cors.is_enabled_for("https"));
+        assert!(!cors.is_enabled_for("http"));
+
+        for (origin, expected) in [
+            ("https://admin.example.org", true),
+            ("https://ADMIN.example.org", true),
+            ("http://admin.example.org", false),
+            ("https://admin.example.org:8443", false),
+            ("https://app.example.com", true),
+            ("https://a.b.example.com", true),
+            ("https://example.com", false),
+            ("https://badexample.com", false),
+            ("https://example.com.evil.org", false),
+            ("http://app.example.com", false),
+            ("https://app.example.com:8443", false),
+            ("null", false),
+        ] {
+            assert_eq!(cors.is_allowed_origin(origin), expected, "{origin}");
+        }
+
+        assert_eq!(CorsOrigin::parse("*"), Some(CorsOrigin::Any));
+        assert_eq!(
+            CorsOrigin::parse("https://*.example.com:8443/"),
+            Some(CorsOrigin::Subdomain {
+                scheme: "https".into(),
+                domain: "example.com:8443".into()
+            })
+        );
+        assert!(
+            CorsOrigin::parse("https://*.example.com:8443")
+                .unwrap()
+                .matches("https://app.example.com:8443")
+        );
+        for invalid in [
+            "example.org",
+            "https://",
+            "https://a.*.org",
+            "https://*.",
+            "https://a/b",
+        ] {
+            assert_eq!(CorsOrigin::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn cors_preflight_request() {
+        let cors = cors();
+
+        // Allowed origin
+        let response = cors_preflight(&cors, &preflight("https://app.example.com", "PATCH"));
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers().unwrap();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, POST, PATCH"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "authorization, content-type, accept, x-requested-with"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[header::VARY], "Origin");
+
+        // Disallowed method
+        let response = cors_preflight(&cors, &preflight("https://app.example.com", "DELETE"));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Request headers
+        for (request_headers, expected) in [
+            ("Content-Type, Authorization", StatusCode::NO_CONTENT),
+            ("content-type,accept,", StatusCode::NO_CONTENT),
+            ("", StatusCode::NO_CONTENT),
+            ("content-type, x-api-key", StatusCode::FORBIDDEN),
+            ("cookie", StatusCode::FORBIDDEN),
+        ] {
+            let mut headers = preflight("https://app.example.com", "POST");
+            headers.insert(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                HeaderValue::from_static(request_headers),
+            );
+            assert_eq!(
+                cors_preflight(&cors, &headers).status(),
+                expected,
+                "{request_headers}"
+            );
+        }
+
+        // Not a CORS request
+        let response = cors_preflight(&cors, &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(
+            !response
+                .headers()
+                .unwrap()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[test]
+    fn cors_disallowed_origin() {
+        let cors = cors();
+
+        let response = cors_preflight(&cors, &preflight("https://evil.org", "GET"));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let headers = response.headers().unwrap();
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(headers[header::VARY], "Origin");
+
+        // Actual requests
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+        add_cors_headers(
+            &cors,
+            Some(&HeaderValue::from_static("https://evil.org")),
+            &mut headers,
+        );
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(headers[header::VARY], "Origin");
+
+        let mut headers = HeaderMap::new();
+        add_cors_headers(
+            &cors,
+            Some(&HeaderValue::from_static("https://admin.example.org")),
+            &mut headers,
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://admin.example.org"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+}
@@ -6,9 +6,11 @@
 
 pub mod auth;
 pub mod autoconfig;
+pub mod cors;
 pub mod form;
 pub mod management;
 pub mod request;
+pub mod scim;
 
 use std::sync::Arc;
 
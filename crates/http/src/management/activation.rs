@@ -0,0 +1,187 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    auth::verification::OrganizationActivationToken,
+    config::{
+        activation::TenantActivation,
+        branding::{BrandNotification, BrandVariant, Branding},
+    },
+};
+use directory::backend::internal::manage::ManageDirectory;
+use http_proto::{request::fetch_body_with_limit, *};
+use mail_builder::{MessageBuilder, headers::HeaderType};
+use mail_parser::DateTime;
+use serde_json::json;
+use smtp::reporting::SmtpReporting;
+use std::future::Future;
+use store::write::now;
+use utils::url_params::UrlParams;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ActivateRequest {
+    token: String,
+}
+
+pub trait OrganizationActivationManager: Sync + Send {
+    fn handle_organization_activation(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+        tenant_id: &str,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl OrganizationActivationManager for Server {
+    // Activating an organization that is already active succeeds, so the
+    // link can be used more than once until it expires.
+    async fn handle_organization_activation(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+        tenant_id: &str,
+    ) -> trc::Result<HttpResponse> {
+        let token = if let Some(token) = UrlParams::new(req.uri().query()).get("token") {
+            token.to_string()
+        } else {
+            let max_size = self
+                .core
+                .jmap
+                .http_body_limits
+                .limit(req.uri().path().strip_prefix("/api").unwrap_or_default());
+            let body = fetch_body_with_limit(req, max_size, session.session_id).await?;
+            serde_json::from_slice::<ActivateRequest>(&body)
+                .map(|request| request.token)
+                .unwrap_or_default()
+        };
+
+        let invalid_token = || {
+            trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Invalid or expired token")
+        };
+        let token = OrganizationActivationToken::verify(&self.core.oauth.oauth_key, &token)
+            .filter(|token| {
+                token.expires > now() && tenant_id.parse::<u32>() == Ok(token.tenant_id)
+            })
+            .ok_or_else(invalid_token)?;
+
+        match self.tenant_activation(token.tenant_id) {
+            Some(activation) if activation.nonce == token.nonce => {
+                self.update_tenant_activation(token.tenant_id, None).await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::OrganizationActivated),
+                    AccountName = activation.contact.clone(),
+                    TenantId = token.tenant_id,
+                    Id = token.tenant_id,
+                    RemoteIp = session.remote_ip,
+                );
+            }
+            None if self
+                .store()
+                .get_principal_name(token.tenant_id)
+                .await?
+                .is_some() => {}
+            _ => {
+                return Err(invalid_token());
+            }
+        }
+
+        Ok(JsonResponse::new(json!({
+            "data": {"id": token.tenant_id, "state": "active"},
+        }))
+        .into_http_response())
+    }
+}
+
+/// Marks a newly provisioned organization as pending activation and sends
+/// the activation link to its contact.
+pub(super) async fn request_organization_activation(
+    server: &Server,
+    tenant_id: u32,
+    tenant_name: &str,
+    domain: &str,
+    contact: &str,
+) -> trc::Result<()> {
+    let now = now();
+    let activation = TenantActivation {
+        requested_at: now,
+        expires_at: now + server.core.jmap.activation_expiry,
+        contact: contact.to_string(),
+        nonce: server.inner.data.jmap_id_gen.generate(),
+    };
+    let token = OrganizationActivationToken {
+        tenant_id,
+        nonce: activation.nonce,
+        expires: activation.expires_at,
+    };
+    server
+        .update_tenant_activation(tenant_id, Some(activation))
+        .await?;
+
+    let url = format!(
+        "{}/api/organization/{}/activate?token={}",
+        server.core.jmap.activation_url,
+        tenant_id,
+        token.sign(&server.core.oauth.oauth_key),
+    );
+    let expires = DateTime::from_timestamp(token.expires as i64).to_rfc3339();
+    let title = "Activate your organization";
+    let html_body = server
+        .store()
+        .get_principal(tenant_id)
+        .await?
+        .map(|tenant| {
+            Branding::from_principal(&tenant).render_email(
+                BrandVariant::Light,
+                &BrandNotification {
+                    title,
+                    message: &format!(
+                        concat!(
+                            "The organization {} has been created for the domain {}.\n\n",
+                            "Organizations that are not activated before {} are removed."
+                        ),
+                        tenant_name, domain, expires,
+                    ),
+                    action: Some(("Activate", &url)),
+                },
+            )
+        });
+
+    let from = server.core.jmap.activation_from.as_str();
+    let mut message = MessageBuilder::new()
+        .from(from)
+        .to(contact)
+        .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+        .subject(title)
+        .text_body(format!(
+            concat!(
+                "The organization {} has been created for the domain {}.\r\n\r\n",
+                "To activate it, open the following link:\r\n\r\n",
+                "{}\r\n\r\n",
+                "Organizations that are not activated before {} are removed.\r\n"
+            ),
+            tenant_name, domain, url, expires,
+        ));
+    if let Some(html_body) = html_body {
+        message = message.html_body(html_body);
+    }
+    let message = message.write_to_vec().map_err(|err| {
+        trc::ResourceEvent::Error
+            .caused_by(trc::location!())
+            .reason(err)
+            .details("Failed to build activation message")
+    })?;
+
+    server
+        .send_autogenerated(from, [contact].into_iter(), message, None, 0)
+        .await;
+
+    Ok(())
+}
@@ -0,0 +1,225 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, auth::AccessToken, config::overrides::TenantProtocol};
+use directory::{
+    AuthProtocol, Permission, Type,
+    backend::internal::{
+        PrincipalField,
+        app_password::AppPasswords,
+        manage::{self, ChangedPrincipals, ManageDirectory, not_found},
+    },
+};
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::json;
+use std::future::Future;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppPasswordRequest {
+    label: String,
+    #[serde(default)]
+    protocols: Vec<String>,
+}
+
+pub trait AppPasswordManager: Sync + Send {
+    fn handle_app_passwords(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl AppPasswordManager for Server {
+    // App passwords are managed by their owner or by an administrator, the
+    // generated secret is only returned once.
+    async fn handle_app_passwords(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+        let info = self
+            .store()
+            .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+            .await?
+            .filter(|p| {
+                p.has_tenant_access(access_token.tenant.map(|t| t.id)) && p.typ == Type::Individual
+            })
+            .ok_or_else(|| not_found(name.to_string()))?;
+        let is_owner = info.id == access_token.primary_id();
+
+        match (
+            path.get(3).map(|label| decode_path_element(label)),
+            req.method(),
+        ) {
+            (None, &Method::GET) => {
+                access_token.assert_has_permission(if is_owner {
+                    Permission::ManagePasswords
+                } else {
+                    Permission::IndividualGet
+                })?;
+
+                let app_passwords = self
+                    .store()
+                    .list_app_passwords(info.id)
+                    .await?
+                    .into_iter()
+                    .map(|entry| {
+                        json!({
+                            "label": entry.label,
+                            "createdAt": entry.created_at,
+                            "lastUsed": entry.last_used,
+                            "protocols": protocol_names(&entry.protocols),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                Ok(JsonResponse::new(json!({
+                    "data": app_passwords,
+                }))
+                .into_http_response())
+            }
+            (None, &Method::POST) => {
+                access_token.assert_has_permission(if is_owner {
+                    Permission::ManagePasswords
+                } else {
+                    Permission::IndividualUpdate
+                })?;
+
+                let request = serde_json::from_slice::<AppPasswordRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                let protocols = request
+                    .protocols
+                    .iter()
+                    .map(|protocol| {
+                        AuthProtocol::parse(protocol).ok_or_else(|| {
+                            manage::error(
+                                "Invalid app password protocol",
+                                format!("Unknown protocol {protocol:?}").into(),
+                            )
+                        })
+                    })
+                    .collect::<trc::Result<Vec<_>>>()?;
+
+                // App passwords can't grant protocols the account is not
+                // permitted to use
+                let principal = self
+                    .store()
+                    .get_principal(info.id)
+                    .await?
+                    .ok_or_else(|| not_found(name.to_string()))?;
+                let allowed = principal.allowed_protocols().collect::<Vec<_>>();
+                let is_allowed = |protocol: TenantProtocol| allowed.contains(&protocol.as_str());
+                if !allowed.is_empty()
+                    && let Some(protocol) = protocols.iter().find(|protocol| match protocol {
+                        AuthProtocol::Http => {
+                            !is_allowed(TenantProtocol::Jmap) && !is_allowed(TenantProtocol::Dav)
+                        }
+                        protocol => !TenantProtocol::from_auth(**protocol).is_some_and(is_allowed),
+                    })
+                {
+                    return Err(manage::error(
+                        "Invalid app password protocol",
+                        format!(
+                            "The account is not permitted to use {:?}",
+                            protocol.as_str()
+                        )
+                        .into(),
+                    ));
+                }
+
+                if !self.password_policy(info.tenant).allow_app_passwords {
+                    return Err(manage::error(
+                        "App passwords not allowed",
+                        "The password policy does not allow app passwords".into(),
+                    ));
+                }
+
+                let secret = self
+                    .store()
+                    .add_app_password(info.id, &request.label, &protocols)
+                    .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::AppPasswordCreated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = name.to_string(),
+                    Details = request.label.trim().to_string(),
+                );
+                self.notify_security_event(
+                    trc::EventType::Directory(trc::DirectoryEvent::AppPasswordCreated),
+                    info.id,
+                    format!("App password: {}", request.label.trim()).into(),
+                )
+                .await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "label": request.label.trim(),
+                        "secret": secret,
+                        "protocols": protocol_names(&protocols),
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(label), &Method::DELETE) => {
+                access_token.assert_has_permission(if is_owner {
+                    Permission::ManagePasswords
+                } else {
+                    Permission::IndividualUpdate
+                })?;
+
+                if !self
+                    .store()
+                    .remove_app_password(info.id, label.as_ref())
+                    .await?
+                {
+                    return Err(not_found(label.to_string()));
+                }
+
+                // Cached credentials and access tokens are invalidated so that
+                // sessions using the app password have to authenticate again
+                self.invalidate_principal_caches(ChangedPrincipals::from_change(
+                    info.id,
+                    Type::Individual,
+                    PrincipalField::Secrets,
+                ))
+                .await;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::AppPasswordRevoked),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = name.to_string(),
+                    Details = label.to_string(),
+                );
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+fn protocol_names(protocols: &[AuthProtocol]) -> Vec<&'static str> {
+    protocols.iter().map(|protocol| protocol.as_str()).collect()
+}
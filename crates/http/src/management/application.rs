@@ -0,0 +1,435 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    invitation::provision_invited_organization,
+    organization::{
+        OrganizationProvisionRequest, provision_organization_request, validate_provision_request,
+    },
+};
+use common::{
+    Server,
+    auth::{AccessToken, invitation::OrganizationInvitation},
+    config::abuse::ProvisioningVerdict,
+    manager::abuse::ProvisioningRequestInfo,
+};
+use directory::{
+    Permission,
+    backend::internal::{
+        PrincipalField,
+        application::{ApplicationStatus, OrganizationApplication, OrganizationApplications},
+        lookup::DirectoryStore,
+        manage::{self, ManageDirectory, err_exists, not_found},
+    },
+};
+use http_proto::*;
+use hyper::Method;
+use mail_builder::{MessageBuilder, headers::HeaderType};
+use serde_json::{Value, json};
+use smtp::reporting::SmtpReporting;
+use std::{collections::BTreeMap, future::Future, net::IpAddr};
+use store::write::now;
+use utils::url_params::UrlParams;
+
+const MAX_REASON_LEN: usize = 1024;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RejectRequest {
+    reason: String,
+    #[serde(default)]
+    notify: bool,
+}
+
+pub trait OrganizationApplicationManager: Sync + Send {
+    fn handle_organization_applications(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_organization_signup(
+        &self,
+        body: Option<Vec<u8>>,
+        remote_ip: IpAddr,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl OrganizationApplicationManager for Server {
+    // Applications are reviewed by principals that can provision
+    // organizations themselves, approving one provisions it on their behalf.
+    async fn handle_organization_applications(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(Permission::TenantCreate)?;
+        access_token.assert_has_permission(Permission::DomainCreate)?;
+        access_token.assert_has_permission(Permission::IndividualCreate)?;
+
+        let Some(id) = path.get(2).copied() else {
+            if req.method() != Method::GET {
+                return Err(trc::ResourceEvent::NotFound.into_err());
+            }
+
+            let params = UrlParams::new(req.uri().query());
+            let status = params.get("status");
+            let items = self
+                .store()
+                .list_applications()
+                .await?
+                .iter()
+                .filter(|application| {
+                    status.is_none_or(|status| status_name(application.status) == status)
+                })
+                .map(application_json)
+                .collect::<Vec<_>>();
+
+            return Ok(JsonResponse::new(json!({
+                "data": {
+                    "items": items,
+                    "total": items.len(),
+                },
+            }))
+            .into_http_response());
+        };
+        let mut application = self
+            .store()
+            .get_application(id.parse::<u64>().map_err(|_| not_found(id.to_string()))?)
+            .await?
+            .ok_or_else(|| not_found(id.to_string()))?;
+
+        match (path.get(3).copied(), req.method()) {
+            (None, &Method::GET) => Ok(JsonResponse::new(json!({
+                "data": application_json(&application),
+            }))
+            .into_http_response()),
+            (Some("approve"), &Method::POST) => {
+                assert_pending(&application)?;
+
+                // The stored password hash is accepted as the admin's secret
+                let request = OrganizationProvisionRequest {
+                    tenant_name: application.tenant_name.clone(),
+                    domain: application.domain.clone(),
+                    admin_name: application.admin_name.clone(),
+                    admin_password: application.admin_secret.clone(),
+                    admin_email: application.admin_email.clone(),
+                    brand_name: application.brand_name.clone(),
+                    brand_logo_url: application.brand_logo_url.clone(),
+                    brand_logo_dark_url: application.brand_logo_dark_url.clone(),
+                    brand_theme: application.brand_theme.clone(),
+                    description: application.description.clone(),
+                    collections: None,
+                    require_admin_email_verification: false,
+                    contact_email: None,
+                    trial_ends_at: None,
+                    trial_days: None,
+                    plan: None,
+                    referral_code: application.referral_code.clone(),
+                    attribution: application.attribution.clone(),
+                    protocols: BTreeMap::new(),
+                    contacts: Default::default(),
+                };
+                let response = if let Some(token) = &application.invitation {
+                    // The invitation was claimed when the redemption was held,
+                    // so only its signature is checked here
+                    let invitation =
+                        OrganizationInvitation::verify(&self.core.oauth.oauth_key, token)
+                            .ok_or_else(|| {
+                                trc::ResourceEvent::BadParameters
+                                    .into_err()
+                                    .details("Invalid invitation")
+                            })?;
+                    let remote_ip = application
+                        .remote_ip
+                        .as_deref()
+                        .and_then(|ip| ip.parse().ok())
+                        .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+                    provision_invited_organization(
+                        self,
+                        &invitation,
+                        request,
+                        access_token,
+                        remote_ip,
+                    )
+                    .await?
+                } else {
+                    provision_organization_request(self, request, vec![], access_token, true)
+                        .await?
+                };
+
+                application.status = ApplicationStatus::Approved;
+                application.reviewed_by = access_token.name.clone().into();
+                application.tenant_id = response.tenant_id.into();
+                self.store().review_application(&application).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some("reject"), &Method::POST) => {
+                assert_pending(&application)?;
+                let request =
+                    serde_json::from_slice::<RejectRequest>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+                let reason = request.reason.trim();
+                if reason.is_empty() {
+                    return Err(manage::err_missing("reason"));
+                } else if reason.len() > MAX_REASON_LEN {
+                    return Err(manage::error(
+                        "Invalid reason",
+                        format!("Reasons may not exceed {MAX_REASON_LEN} bytes").into(),
+                    ));
+                }
+
+                application.status = ApplicationStatus::Rejected;
+                application.reviewed_by = access_token.name.clone().into();
+                application.reason = reason.to_string().into();
+                self.store().review_application(&application).await?;
+
+                if request.notify {
+                    let from = self.core.jmap.signup_from.as_str();
+                    let message = MessageBuilder::new()
+                        .from(from)
+                        .to(application.admin_email.as_str())
+                        .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+                        .subject("Your organization request was not approved")
+                        .text_body(format!(
+                            concat!(
+                                "Your request to create the organization {} for the domain {} ",
+                                "was not approved for the following reason:\r\n\r\n",
+                                "{}\r\n"
+                            ),
+                            application.tenant_name, application.domain, reason
+                        ))
+                        .write_to_vec()
+                        .unwrap_or_default();
+
+                    self.send_autogenerated(
+                        from,
+                        [application.admin_email.as_str()].into_iter(),
+                        message,
+                        None,
+                        0,
+                    )
+                    .await;
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": application_json(&application),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+
+    // Signups only record the application, after checking that the names
+    // it requests are available.
+    async fn handle_organization_signup(
+        &self,
+        body: Option<Vec<u8>>,
+        remote_ip: IpAddr,
+    ) -> trc::Result<HttpResponse> {
+        if !self.core.jmap.signup_enabled {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        }
+
+        let request = serde_json::from_slice::<OrganizationProvisionRequest>(
+            body.as_deref().unwrap_or_default(),
+        )
+        .map_err(|err| {
+            trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+        })?;
+        validate_provision_request(&request)?;
+        if request.collections.is_some() {
+            return Err(manage::unsupported(
+                "Calendars and address books can't be requested at signup",
+            ));
+        } else if request.require_admin_email_verification {
+            return Err(manage::unsupported(
+                "Organizations requested at signup are activated on approval",
+            ));
+        } else if request.trial_ends_at.is_some() || request.trial_days.is_some() {
+            return Err(manage::unsupported(
+                "Trial periods can't be requested at signup",
+            ));
+        } else if request.plan.is_some() {
+            return Err(manage::unsupported("Plans can't be requested at signup"));
+        } else if !request.protocols.is_empty() {
+            return Err(manage::unsupported(
+                "Protocols can't be requested at signup",
+            ));
+        }
+        let domain = request.domain.trim().to_lowercase();
+        if !domain.contains('.') || domain.contains(|ch: char| ch.is_whitespace() || ch == '@') {
+            return Err(manage::error(
+                "Invalid domain",
+                format!("{domain:?} is not a valid domain name").into(),
+            ));
+        }
+
+        if request
+            .admin_email
+            .rsplit_once('@')
+            .is_none_or(|(_, email_domain)| !email_domain.eq_ignore_ascii_case(&domain))
+        {
+            return Err(manage::error(
+                "Invalid admin email",
+                "The administrator's address must belong to the requested domain".into(),
+            ));
+        }
+
+        let verdict = self
+            .check_provisioning(&ProvisioningRequestInfo {
+                source: "signup",
+                remote_ip,
+                domain: &domain,
+                tenant_name: &request.tenant_name,
+                admin_email: &request.admin_email,
+            })
+            .await;
+        if verdict == ProvisioningVerdict::Deny {
+            return Err(request_denied());
+        }
+
+        // Applications are queued for review whether or not the check allowed them
+        let application = queue_application(self, request, verdict, remote_ip, None).await?;
+
+        Ok(JsonResponse::new(json!({
+            "data": application,
+        }))
+        .into_http_response())
+    }
+}
+
+/// Records an application for review, after checking that the names it
+/// requests are available. Email verification is not requested for
+/// queued organizations, approving them stands in for it.
+pub(super) async fn queue_application(
+    server: &Server,
+    request: OrganizationProvisionRequest,
+    verdict: ProvisioningVerdict,
+    remote_ip: IpAddr,
+    invitation: Option<String>,
+) -> trc::Result<Value> {
+    let domain = request.domain.trim().to_lowercase();
+
+    // Names that are taken are reported the same way provisioning does
+    for name in [
+        request.tenant_name.as_str(),
+        domain.as_str(),
+        request.admin_name.as_str(),
+    ] {
+        if server.store().get_principal_info(name).await?.is_some() {
+            return Err(err_exists(PrincipalField::Name, name.to_string()));
+        }
+    }
+    if server
+        .store()
+        .email_to_id(&request.admin_email.to_lowercase())
+        .await?
+        .is_some()
+    {
+        return Err(err_exists(
+            PrincipalField::Emails,
+            request.admin_email.to_string(),
+        ));
+    }
+
+    // Only the hash is stored, so the password is checked right away
+    server.assert_password_policy(None, [request.admin_password.as_str()])?;
+
+    let now = now();
+    let mut application = OrganizationApplication {
+        id: server.inner.data.jmap_id_gen.generate(),
+        status: ApplicationStatus::Pending,
+        tenant_name: request.tenant_name,
+        domain,
+        admin_name: request.admin_name,
+        admin_email: request.admin_email,
+        admin_secret: String::new(),
+        description: request.description,
+        brand_name: request.brand_name,
+        brand_logo_url: request.brand_logo_url,
+        brand_logo_dark_url: request.brand_logo_dark_url,
+        brand_theme: request.brand_theme,
+        submitted_at: now,
+        expires_at: now + server.core.jmap.signup_expiry,
+        remote_ip: remote_ip.to_string().into(),
+        reviewed_by: None,
+        reason: None,
+        tenant_id: None,
+        verdict: verdict.as_str().to_string().into(),
+        invitation,
+        referral_code: request.referral_code,
+        attribution: request.attribution,
+    };
+    application.set_password(&request.admin_password)?;
+    if !server.store().add_application(&application).await? {
+        return Err(manage::error(
+            "Domain unavailable",
+            "Another organization has already been requested for this domain".into(),
+        ));
+    }
+
+    Ok(application_json(&application))
+}
+
+/// Error returned for requests denied by the abuse check, which does not
+/// disclose the reason.
+pub(super) fn request_denied() -> trc::Error {
+    manage::error(
+        "Request denied",
+        "The request could not be processed".into(),
+    )
+}
+
+fn assert_pending(application: &OrganizationApplication) -> trc::Result<()> {
+    if application.status == ApplicationStatus::Pending {
+        Ok(())
+    } else {
+        Err(manage::error(
+            "Application already reviewed",
+            format!(
+                "The application has already been {}",
+                status_name(application.status)
+            )
+            .into(),
+        ))
+    }
+}
+
+fn status_name(status: ApplicationStatus) -> &'static str {
+    match status {
+        ApplicationStatus::Pending => "pending",
+        ApplicationStatus::Approved => "approved",
+        ApplicationStatus::Rejected => "rejected",
+    }
+}
+
+// Applications are returned without the administrator's password hash or
+// the invitation token
+fn application_json(application: &OrganizationApplication) -> Value {
+    let mut value = serde_json::to_value(application).unwrap_or_default();
+    if let Some(object) = value.as_object_mut() {
+        object.remove("adminSecret");
+        object.insert(
+            "invitation".to_string(),
+            application.invitation.is_some().into(),
+        );
+        object.insert("id".to_string(), application.id.to_string().into());
+    }
+    value
+}
@@ -0,0 +1,138 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::principal::{get_permission, update_permission};
+use common::{Server, auth::AccessToken};
+use directory::{
+    Type,
+    backend::internal::manage::{ManageDirectory, not_found},
+};
+use http_proto::{request::decode_path_element, *};
+use hyper::{Method, StatusCode, header};
+use serde_json::json;
+use std::future::Future;
+
+// Clients revalidate using the ETag, so a replaced avatar shows up right away
+const AVATAR_CACHE_CONTROL: &str = "private, no-cache";
+
+pub trait AvatarManager: Sync + Send {
+    fn handle_avatar(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl AvatarManager for Server {
+    // Avatars are managed by their owner, unless the tenant forbids it, or by
+    // an administrator with access to the principal.
+    async fn handle_avatar(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+        let info = self
+            .store()
+            .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+            .await?
+            .filter(|p| {
+                p.has_tenant_access(access_token.tenant.map(|t| t.id))
+                    && matches!(p.typ, Type::Individual | Type::Group)
+            })
+            .ok_or_else(|| not_found(name.to_string()))?;
+        let is_owner = info.id == access_token.primary_id();
+
+        match *req.method() {
+            Method::GET => {
+                if !is_owner {
+                    access_token.assert_has_permission(get_permission(info.typ))?;
+                }
+
+                let avatar = self
+                    .store()
+                    .get_principal(info.id)
+                    .await?
+                    .and_then(|principal| principal.avatar())
+                    .ok_or_else(|| not_found(name.to_string()))?;
+                let etag = format!("\"{}\"", avatar.hash);
+                if req
+                    .headers()
+                    .get(header::IF_NONE_MATCH)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|value| {
+                        value
+                            .split(',')
+                            .any(|tag| tag.trim() == "*" || tag.trim() == etag)
+                    })
+                {
+                    return Ok(HttpResponse::new(StatusCode::NOT_MODIFIED)
+                        .with_etag(etag)
+                        .with_cache_control(AVATAR_CACHE_CONTROL));
+                }
+
+                let bytes = self
+                    .get_avatar(info.id)
+                    .await?
+                    .ok_or_else(|| not_found(name.to_string()))?;
+
+                Ok(HttpResponse::new(StatusCode::OK)
+                    .with_content_type(avatar.content_type)
+                    .with_etag(etag)
+                    .with_cache_control(AVATAR_CACHE_CONTROL)
+                    .with_binary_body(bytes))
+            }
+            Method::PUT => {
+                if !is_owner || !avatar_self_service(self, access_token).await? {
+                    access_token.assert_has_permission(update_permission(info.typ))?;
+                }
+
+                let avatar = self
+                    .put_avatar(info.id, body.as_deref().unwrap_or_default())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "hash": avatar.hash,
+                        "contentType": avatar.content_type,
+                    },
+                }))
+                .into_http_response())
+            }
+            Method::DELETE => {
+                if !is_owner || !avatar_self_service(self, access_token).await? {
+                    access_token.assert_has_permission(update_permission(info.typ))?;
+                }
+
+                if !self.delete_avatar(info.id).await? {
+                    return Err(not_found(name.to_string()));
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+async fn avatar_self_service(server: &Server, access_token: &AccessToken) -> trc::Result<bool> {
+    if let Some(tenant) = &access_token.tenant {
+        Ok(server
+            .store()
+            .get_principal(tenant.id)
+            .await?
+            .is_none_or(|tenant| tenant.avatar_self_service()))
+    } else {
+        Ok(true)
+    }
+}
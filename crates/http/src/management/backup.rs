@@ -0,0 +1,999 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{import::error_details, stores::destroy_account_data};
+use common::{
+    Server,
+    auth::AccessToken,
+    storage::backup::{
+        TenantBackup, TenantBackupJob, TenantBackupJobKind, backup_blob_key, backup_key,
+    },
+};
+use directory::{
+    Permission, QueryBy, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+        lookup::DirectoryStore,
+        manage::{self, ChangedPrincipals, ManageDirectory, UpdatePrincipal},
+    },
+};
+use email::{
+    cache::{MessageCacheFetch, email::MessageCacheAccess, mailbox::MailboxCacheAccess},
+    mailbox::manage::MailboxFnc,
+    message::{
+        ingest::{EmailIngest, IngestEmail, IngestSource},
+        metadata::{MESSAGE_RECEIVED_MASK, MessageMetadata},
+    },
+};
+use http_proto::*;
+use hyper::Method;
+use serde_json::{Value, json};
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Instant};
+use store::{
+    ValueKey,
+    ahash::{AHashMap, AHashSet},
+    write::{AlignedBytes, Archive, now},
+};
+use trc::AddContext;
+use types::{
+    blob_hash::BlobHash, collection::Collection, field::EmailField, keyword::Keyword,
+    special_use::SpecialUse,
+};
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Members are restored through the memberships of each principal
+const PRINCIPAL_FIELDS: &[PrincipalField] = &[
+    PrincipalField::Name,
+    PrincipalField::Quota,
+    PrincipalField::Description,
+    PrincipalField::Secrets,
+    PrincipalField::Emails,
+    PrincipalField::MemberOf,
+    PrincipalField::Roles,
+    PrincipalField::Lists,
+    PrincipalField::EnabledPermissions,
+    PrincipalField::DisabledPermissions,
+    PrincipalField::Picture,
+    PrincipalField::Urls,
+    PrincipalField::ExternalMembers,
+    PrincipalField::Locale,
+    PrincipalField::BrandName,
+    PrincipalField::BrandLogoUrl,
+    PrincipalField::BrandLogoDarkUrl,
+    PrincipalField::BrandTheme,
+    PrincipalField::ExternalId,
+    PrincipalField::Metadata,
+    PrincipalField::Tags,
+    PrincipalField::AllowedProtocols,
+    PrincipalField::PasswordHistoryLength,
+    PrincipalField::MinPasswordAgeDays,
+    PrincipalField::LockoutThreshold,
+    PrincipalField::LockoutWindow,
+    PrincipalField::LockoutDuration,
+    PrincipalField::LockoutPerProtocol,
+    PrincipalField::LockoutNotify,
+    PrincipalField::DisableAt,
+    PrincipalField::EnableAt,
+    PrincipalField::AvatarSelfService,
+    PrincipalField::SecurityNoticeOptOut,
+    PrincipalField::TrialEndsAt,
+    PrincipalField::Plan,
+    PrincipalField::BillingEmail,
+    PrincipalField::TechnicalContact,
+    PrincipalField::Phone,
+    PrincipalField::PostalAddress,
+    PrincipalField::TaxId,
+    PrincipalField::SenderAddress,
+    PrincipalField::SendRate,
+];
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenantBackupRequest {
+    /// Copies message blobs to the backup store, otherwise the backup
+    /// references the blobs in the tenant's blob store.
+    #[serde(default)]
+    pub copy_blobs: bool,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenantRestoreRequest {
+    /// Name of a new tenant to restore into, defaults to the backed up tenant.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Replaces the principals and settings that already exist in the
+    /// tenant, deleting the data of the replaced accounts.
+    #[serde(default)]
+    pub force: bool,
+    /// Leaves the principals and settings that already exist in the
+    /// tenant untouched.
+    #[serde(default)]
+    pub skip_existing: bool,
+}
+
+/// Contents of a tenant backup, written to the backup store as JSON.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TenantSnapshot {
+    version: u32,
+    tenant: PrincipalSet,
+    principals: Vec<PrincipalSet>,
+    settings: BTreeMap<String, BTreeMap<String, String>>,
+    accounts: Vec<AccountSnapshot>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountSnapshot {
+    name: String,
+    /// Change the account was captured at.
+    change_id: u64,
+    mailboxes: Vec<MailboxSnapshot>,
+    messages: Vec<MessageSnapshot>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MailboxSnapshot {
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageSnapshot {
+    blob_hash: BlobHash,
+    received_at: u64,
+    /// Paths of the mailboxes the message is in.
+    mailboxes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<String>,
+}
+
+/// Changes a restore makes to live data, validated before the restore starts.
+struct RestorePlan {
+    /// Existing tenant to restore into, or `None` to create one.
+    tenant_id: Option<u32>,
+    tenant_name: String,
+    /// Principals replaced by the ones in the backup.
+    replace: Vec<(u32, Type)>,
+    /// Principals left untouched.
+    skip: AHashSet<String>,
+    /// Settings to restore, an empty entry clears the settings of a prefix.
+    settings: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+pub trait TenantBackupManager: Sync + Send {
+    fn handle_tenant_backup(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl TenantBackupManager for Server {
+    // Each account is captured as of a single change, so messages are never
+    // partially backed up, while the directory entries and settings are read
+    // before any account.
+    async fn handle_tenant_backup(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (
+            path.get(2).copied(),
+            path.get(3).copied(),
+            path.get(4).copied(),
+            req.method(),
+        ) {
+            (Some("backup"), None, None, &Method::POST) => {
+                // Backups include the secrets of every principal in the tenant
+                access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+                let request = parse_request::<TenantBackupRequest>(body)?;
+                if self.backup_store().is_none() {
+                    return Err(manage::unsupported("No backup store is configured"));
+                }
+
+                let backup_id = self.inner.data.jmap_id_gen.generate();
+                let job = self
+                    .inner
+                    .data
+                    .backup_jobs
+                    .start(backup_id, tenant_id, TenantBackupJobKind::Backup, backup_id)
+                    .ok_or_else(job_in_progress)?;
+                let response = job.summary();
+
+                let server = self.clone();
+                tokio::spawn(async move {
+                    run_backup(server, job, request.copy_blobs).await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some("backup"), Some(job_id), None, &Method::GET) => {
+                assert_can_view(access_token)?;
+
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.backup_jobs.get(job_id))
+                    .filter(|job| job.tenant_id == tenant_id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": job.summary(),
+                }))
+                .into_http_response())
+            }
+            (Some("backups"), None, None, &Method::GET) => {
+                assert_can_view(access_token)?;
+
+                let backups = self.tenant_backups(tenant_id).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": backups.iter().map(backup_json).collect::<Vec<_>>(),
+                        "total": backups.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some("backups"), Some(backup_id), Some("restore"), &Method::POST) => {
+                access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+                let request = parse_request::<TenantRestoreRequest>(body)?;
+                if request.force && request.skip_existing {
+                    return Err(manage::error(
+                        "Invalid parameters",
+                        "The force and skipExisting options cannot be combined".into(),
+                    ));
+                }
+                let backup = match backup_id.parse::<u64>() {
+                    Ok(backup_id) => self
+                        .tenant_backups(tenant_id)
+                        .await?
+                        .into_iter()
+                        .find(|backup| backup.id == backup_id),
+                    Err(_) => None,
+                }
+                .ok_or_else(|| manage::not_found(backup_id.to_string()))?;
+                let snapshot = read_snapshot(self, tenant_id, &backup).await?;
+                let plan = plan_restore(self, &snapshot, &request, tenant_id, access_token).await?;
+
+                let job = self
+                    .inner
+                    .data
+                    .backup_jobs
+                    .start(
+                        self.inner.data.jmap_id_gen.generate(),
+                        tenant_id,
+                        TenantBackupJobKind::Restore,
+                        backup.id,
+                    )
+                    .ok_or_else(job_in_progress)?;
+                let response = job.summary();
+
+                let server = self.clone();
+                tokio::spawn(async move {
+                    run_restore(server, job, backup, snapshot, plan).await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+async fn run_backup(server: Server, job: Arc<TenantBackupJob>, copy_blobs: bool) {
+    let start_time = Instant::now();
+
+    match write_backup(&server, &job, copy_blobs).await {
+        Ok(()) => {
+            job.complete();
+
+            trc::event!(
+                TaskQueue(trc::TaskQueueEvent::BackupCompleted),
+                TenantId = job.tenant_id,
+                Id = job.backup_id.to_string(),
+                Total = job.summary().messages,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                TaskQueue(trc::TaskQueueEvent::BackupFailed),
+                TenantId = job.tenant_id,
+                Id = job.backup_id.to_string(),
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn write_backup(server: &Server, job: &TenantBackupJob, copy_blobs: bool) -> trc::Result<()> {
+    let backup_store = server
+        .backup_store()
+        .ok_or_else(|| manage::unsupported("No backup store is configured"))?;
+    let tenant_id = job.tenant_id;
+    let store = server.store();
+
+    // Directory entries and settings
+    let settings = server
+        .tenant_settings(tenant_id)
+        .await
+        .caused_by(trc::location!())?;
+    let tenant = store
+        .get_principal(tenant_id)
+        .await
+        .caused_by(trc::location!())?
+        .ok_or_else(|| manage::not_found(tenant_id))?;
+    let tenant = store
+        .map_principal(tenant, PRINCIPAL_FIELDS)
+        .await
+        .caused_by(trc::location!())?;
+    let mut principals = Vec::new();
+    let mut account_ids = Vec::new();
+    for principal in store
+        .list_principals(None, Some(tenant_id), &[], true, 0, 0)
+        .await
+        .caused_by(trc::location!())?
+        .items
+    {
+        match principal.typ() {
+            Type::Tenant => continue,
+            Type::Individual | Type::Group => {
+                account_ids.push((principal.id(), principal.name().to_string()));
+            }
+            _ => {}
+        }
+        principals.push(
+            store
+                .map_principal(principal, PRINCIPAL_FIELDS)
+                .await
+                .caused_by(trc::location!())?,
+        );
+        job.add_principal();
+    }
+    job.set_total_accounts(account_ids.len() as u32);
+
+    // Copies are encrypted when the tenant encrypts its blobs at rest
+    let encrypted = copy_blobs
+        && server
+            .tenant_encryption(tenant_id)
+            .is_some_and(|settings| settings.enabled);
+    let mut copied = AHashSet::new();
+    let mut size = 0;
+    let mut accounts = Vec::with_capacity(account_ids.len());
+    for (account_id, name) in account_ids {
+        let cache = server
+            .get_cached_messages(account_id)
+            .await
+            .caused_by(trc::location!())?;
+        let paths = cache
+            .mailboxes
+            .items
+            .iter()
+            .map(|mailbox| (mailbox.document_id, mailbox.path.as_str()))
+            .collect::<AHashMap<_, _>>();
+        let mut messages = Vec::with_capacity(cache.emails.items.len());
+        for item in cache.emails.items.iter() {
+            let Some(archive) = store
+                .get_value::<Archive<AlignedBytes>>(ValueKey::property(
+                    account_id,
+                    Collection::Email,
+                    item.document_id,
+                    EmailField::Metadata,
+                ))
+                .await
+                .caused_by(trc::location!())?
+            else {
+                continue;
+            };
+            let metadata = archive
+                .unarchive::<MessageMetadata>()
+                .caused_by(trc::location!())?;
+            let blob_hash = BlobHash::from(&metadata.blob_hash);
+            if copy_blobs && copied.insert(blob_hash.clone()) {
+                let Some(blob) = server
+                    .get_blob(&blob_hash, 0..usize::MAX)
+                    .await
+                    .caused_by(trc::location!())?
+                else {
+                    job.fail_message();
+                    continue;
+                };
+                let blob = if encrypted {
+                    server.encrypt_blob(tenant_id, &blob).await?
+                } else {
+                    blob
+                };
+                size += blob.len() as u64;
+                backup_store
+                    .put_blob(
+                        &backup_blob_key(tenant_id, job.backup_id, &blob_hash),
+                        &blob,
+                    )
+                    .await
+                    .caused_by(trc::location!())?;
+            }
+
+            messages.push(MessageSnapshot {
+                blob_hash,
+                received_at: metadata.rcvd_attach.to_native() & MESSAGE_RECEIVED_MASK,
+                mailboxes: item
+                    .mailboxes
+                    .iter()
+                    .filter_map(|mailbox| paths.get(&mailbox.mailbox_id))
+                    .map(|path| path.to_string())
+                    .collect(),
+                keywords: cache
+                    .expand_keywords(item)
+                    .map(|keyword| keyword.to_string())
+                    .collect(),
+            });
+            job.add_message();
+        }
+
+        accounts.push(AccountSnapshot {
+            name,
+            change_id: cache.last_change_id,
+            mailboxes: cache
+                .mailboxes
+                .items
+                .iter()
+                .map(|mailbox| MailboxSnapshot {
+                    path: mailbox.path.clone(),
+                    role: mailbox.role.as_str().map(|role| role.to_string()),
+                })
+                .collect(),
+            messages,
+        });
+        job.complete_account();
+    }
+
+    // The backup is only listed once its snapshot has been written
+    let backup = TenantBackup {
+        id: job.backup_id,
+        started: job.summary().started,
+        finished: 0,
+        copy_blobs,
+        encrypted,
+        principals: principals.len() as u64,
+        accounts: accounts.len() as u64,
+        messages: accounts
+            .iter()
+            .map(|account| account.messages.len() as u64)
+            .sum(),
+        size: 0,
+    };
+    let snapshot = serde_json::to_vec(&TenantSnapshot {
+        version: SNAPSHOT_VERSION,
+        tenant,
+        principals,
+        settings,
+        accounts,
+    })
+    .unwrap_or_default();
+    backup_store
+        .put_blob(&backup_key(tenant_id, job.backup_id), &snapshot)
+        .await
+        .caused_by(trc::location!())?;
+
+    server
+        .add_tenant_backup(
+            tenant_id,
+            TenantBackup {
+                finished: now(),
+                size: size + snapshot.len() as u64,
+                ..backup
+            },
+        )
+        .await
+}
+
+async fn read_snapshot(
+    server: &Server,
+    tenant_id: u32,
+    backup: &TenantBackup,
+) -> trc::Result<TenantSnapshot> {
+    let snapshot = server
+        .backup_store()
+        .ok_or_else(|| manage::unsupported("No backup store is configured"))?
+        .get_blob(&backup_key(tenant_id, backup.id), 0..usize::MAX)
+        .await
+        .caused_by(trc::location!())?
+        .ok_or_else(|| {
+            manage::error(
+                "Backup unavailable",
+                "The snapshot was not found in the backup store".into(),
+            )
+        })?;
+
+    serde_json::from_slice::<TenantSnapshot>(&snapshot)
+        .ok()
+        .filter(|snapshot| snapshot.version == SNAPSHOT_VERSION)
+        .ok_or_else(|| {
+            manage::error(
+                "Backup unavailable",
+                "The snapshot could not be parsed".into(),
+            )
+        })
+}
+
+/// Validates that a restore does not overwrite any live data, unless
+/// requested, before any change is made.
+async fn plan_restore(
+    server: &Server,
+    snapshot: &TenantSnapshot,
+    request: &TenantRestoreRequest,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<RestorePlan> {
+    let store = server.store();
+
+    // Backups are restored into the backed up tenant or a new one
+    let (target_id, tenant_name) = match request
+        .tenant
+        .as_deref()
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+    {
+        Some(name) => {
+            access_token.assert_has_permission(Permission::TenantCreate)?;
+            if store
+                .get_principal_id(&name)
+                .await
+                .caused_by(trc::location!())?
+                .is_some()
+            {
+                return Err(manage::err_exists(PrincipalField::Name, name));
+            }
+            (None, name)
+        }
+        None => (Some(tenant_id), snapshot.tenant.name().to_string()),
+    };
+
+    let mut conflicts = Vec::new();
+    let mut replace = Vec::new();
+    let mut skip = AHashSet::new();
+    for principal in &snapshot.principals {
+        let name = principal.name().to_lowercase();
+        let Some(info) = store
+            .get_principal_info(&name)
+            .await
+            .caused_by(trc::location!())?
+        else {
+            continue;
+        };
+
+        if target_id.is_none() || !info.has_tenant_access(target_id) {
+            conflicts.push(format!("{name} (in another tenant)"));
+        } else if request.skip_existing {
+            skip.insert(name);
+        } else if !request.force {
+            conflicts.push(name);
+        } else if store
+            .get_principal(info.id)
+            .await
+            .caused_by(trc::location!())?
+            .is_some_and(|principal| principal.legal_hold().is_some())
+        {
+            conflicts.push(format!("{name} (on legal hold)"));
+        } else {
+            replace.push((info.id, info.typ));
+        }
+    }
+
+    // Addresses must not be in use by principals that are kept
+    for principal in &snapshot.principals {
+        if skip.contains(&principal.name().to_lowercase()) {
+            continue;
+        }
+        for email in principal
+            .get_str_array(PrincipalField::Emails)
+            .unwrap_or_default()
+        {
+            if let Some(principal_id) =
+                store.email_to_id(email).await.caused_by(trc::location!())?
+                && !replace.iter().any(|(id, _)| *id == principal_id)
+            {
+                conflicts.push(format!("{email} (address in use)"));
+            }
+        }
+    }
+
+    // Settings are only replaced when forced
+    let current = if let Some(target_id) = target_id {
+        server
+            .tenant_settings(target_id)
+            .await
+            .caused_by(trc::location!())?
+    } else {
+        BTreeMap::new()
+    };
+    let mut settings = BTreeMap::new();
+    for (prefix, keys) in &snapshot.settings {
+        match current.get(prefix) {
+            Some(existing) if existing == keys => {}
+            Some(_) if request.skip_existing => {}
+            Some(_) if !request.force => {
+                conflicts.push(format!("{prefix} settings"));
+            }
+            _ => {
+                settings.insert(prefix.clone(), keys.clone());
+            }
+        }
+    }
+    if request.force {
+        for prefix in current.keys() {
+            if !snapshot.settings.contains_key(prefix) {
+                settings.insert(prefix.clone(), BTreeMap::new());
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(RestorePlan {
+            tenant_id: target_id,
+            tenant_name,
+            replace,
+            skip,
+            settings,
+        })
+    } else {
+        Err(manage::error(
+            "Restore conflict",
+            format!(
+                "The following already exist: {}. Use force to replace them or skipExisting to keep them.",
+                conflicts.join(", ")
+            )
+            .into(),
+        ))
+    }
+}
+
+async fn run_restore(
+    server: Server,
+    job: Arc<TenantBackupJob>,
+    backup: TenantBackup,
+    snapshot: TenantSnapshot,
+    plan: RestorePlan,
+) {
+    let start_time = Instant::now();
+
+    match restore_backup(&server, &job, &backup, snapshot, plan).await {
+        Ok(tenant_id) => {
+            job.complete();
+
+            trc::event!(
+                TaskQueue(trc::TaskQueueEvent::RestoreCompleted),
+                TenantId = tenant_id,
+                Id = backup.id.to_string(),
+                Total = job.summary().messages,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                TaskQueue(trc::TaskQueueEvent::RestoreFailed),
+                TenantId = job.tenant_id,
+                Id = backup.id.to_string(),
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn restore_backup(
+    server: &Server,
+    job: &TenantBackupJob,
+    backup: &TenantBackup,
+    snapshot: TenantSnapshot,
+    plan: RestorePlan,
+) -> trc::Result<u32> {
+    // Caches are invalidated once for all the restored principals, including
+    // the ones restored before a failure
+    let mut changed_principals = ChangedPrincipals::default();
+    let result = restore_principals(
+        server,
+        job,
+        snapshot.tenant,
+        snapshot.principals,
+        &plan,
+        &mut changed_principals,
+    )
+    .await;
+    server.invalidate_principal_caches(changed_principals).await;
+    let tenant_id = result?;
+
+    // Messages of the skipped accounts are not restored
+    let accounts = snapshot
+        .accounts
+        .into_iter()
+        .filter(|account| !plan.skip.contains(&account.name))
+        .collect::<Vec<_>>();
+    job.set_total_accounts(accounts.len() as u32);
+    let store = server.store();
+    for account in accounts {
+        let account_id = store
+            .get_principal_id(&account.name)
+            .await
+            .caused_by(trc::location!())?
+            .ok_or_else(|| manage::not_found(account.name.clone()))?;
+        restore_account(server, job, backup, account_id, account).await?;
+        job.complete_account();
+    }
+
+    Ok(tenant_id)
+}
+
+/// Restores the tenant and its principals, collecting the changed principals
+/// for a single cache invalidation.
+async fn restore_principals(
+    server: &Server,
+    job: &TenantBackupJob,
+    mut tenant: PrincipalSet,
+    mut principals: Vec<PrincipalSet>,
+    plan: &RestorePlan,
+    changed_principals: &mut ChangedPrincipals,
+) -> trc::Result<u32> {
+    let store = server.store();
+
+    // Replaced principals are deleted along with their data
+    for &(principal_id, typ) in &plan.replace {
+        changed_principals.merge(
+            store
+                .delete_principal(QueryBy::Id(principal_id))
+                .await
+                .caused_by(trc::location!())?,
+        );
+        destroy_account_data(
+            server,
+            principal_id,
+            matches!(typ, Type::Individual | Type::Group),
+        )
+        .await?;
+    }
+
+    let tenant_id = match plan.tenant_id {
+        Some(tenant_id) => tenant_id,
+        None => {
+            tenant.set(PrincipalField::Name, plan.tenant_name.clone());
+            let result = store
+                .create_principal(tenant, None, None)
+                .await
+                .caused_by(trc::location!())?;
+            changed_principals.merge(result.changed_principals);
+            result.id
+        }
+    };
+    if !plan.settings.is_empty() {
+        server
+            .restore_tenant_settings(tenant_id, &plan.settings)
+            .await?;
+    }
+
+    // Memberships are restored once all principals exist, as principals
+    // can be members of each other. Domains are created first, since the
+    // names of the other principals must include one.
+    principals.sort_by_key(|principal| principal.typ() != Type::Domain);
+    let mut memberships = Vec::new();
+    for mut principal in principals {
+        if plan.skip.contains(&principal.name().to_lowercase()) {
+            job.skip_principal();
+            continue;
+        }
+
+        let mut updates = Vec::new();
+        for field in [
+            PrincipalField::MemberOf,
+            PrincipalField::Lists,
+            PrincipalField::Roles,
+        ] {
+            if let Some(names) = principal.take_str_array(field) {
+                updates.push(PrincipalUpdate::set(
+                    field,
+                    PrincipalValue::StringList(names),
+                ));
+            }
+        }
+        let result = store
+            .create_principal(principal, Some(tenant_id), None)
+            .await
+            .caused_by(trc::location!())?;
+        changed_principals.merge(result.changed_principals);
+        if !updates.is_empty() {
+            memberships.push((result.id, updates));
+        }
+    }
+    for (principal_id, updates) in memberships {
+        changed_principals.merge(
+            store
+                .update_principal(
+                    UpdatePrincipal::by_id(principal_id)
+                        .with_tenant(Some(tenant_id))
+                        .with_updates(updates),
+                )
+                .await
+                .caused_by(trc::location!())?,
+        );
+    }
+
+    Ok(tenant_id)
+}
+
+async fn restore_account(
+    server: &Server,
+    job: &TenantBackupJob,
+    backup: &TenantBackup,
+    account_id: u32,
+    account: AccountSnapshot,
+) -> trc::Result<()> {
+    let access_token = server
+        .get_access_token(account_id)
+        .await
+        .caused_by(trc::location!())?;
+
+    // Special-use mailboxes are matched by role, as their names can differ
+    let cache = server
+        .get_cached_messages(account_id)
+        .await
+        .caused_by(trc::location!())?;
+    let mut mailbox_ids = AHashMap::with_capacity(account.mailboxes.len());
+    for mailbox in &account.mailboxes {
+        let mailbox_id = match mailbox
+            .role
+            .as_deref()
+            .and_then(SpecialUse::parse)
+            .and_then(|role| cache.mailbox_by_role(&role))
+        {
+            Some(existing) => Some(existing.document_id),
+            None => server
+                .mailbox_create_path(account_id, &mailbox.path)
+                .await
+                .caused_by(trc::location!())?,
+        };
+        if let Some(mailbox_id) = mailbox_id {
+            mailbox_ids.insert(mailbox.path.as_str(), mailbox_id);
+        }
+    }
+
+    for message in &account.messages {
+        let raw_message = if backup.copy_blobs {
+            match server
+                .backup_store()
+                .ok_or_else(|| manage::unsupported("No backup store is configured"))?
+                .get_blob(
+                    &backup_blob_key(job.tenant_id, backup.id, &message.blob_hash),
+                    0..usize::MAX,
+                )
+                .await
+                .caused_by(trc::location!())?
+            {
+                Some(blob) if backup.encrypted => {
+                    Some(server.decrypt_blob(job.tenant_id, &blob).await?)
+                }
+                blob => blob,
+            }
+        } else {
+            // Referenced blobs are gone once the message has been purged
+            server
+                .get_blob(&message.blob_hash, 0..usize::MAX)
+                .await
+                .caused_by(trc::location!())?
+        };
+        let mailbox_ids = message
+            .mailboxes
+            .iter()
+            .filter_map(|path| mailbox_ids.get(path.as_str()).copied())
+            .collect::<Vec<_>>();
+        let Some(raw_message) = raw_message.filter(|_| !mailbox_ids.is_empty()) else {
+            job.fail_message();
+            continue;
+        };
+
+        match server
+            .email_ingest(IngestEmail {
+                raw_message: &raw_message,
+                blob_hash: None,
+                message: None,
+                access_token: &access_token,
+                mailbox_ids,
+                keywords: message
+                    .keywords
+                    .iter()
+                    .map(|keyword| Keyword::parse(keyword))
+                    .collect(),
+                received_at: Some(message.received_at),
+                source: IngestSource::Jmap {
+                    train_classifier: false,
+                },
+                session_id: 0,
+            })
+            .await
+        {
+            Ok(_) => {
+                job.add_message();
+            }
+            Err(err) => {
+                trc::error!(
+                    err.account_id(account_id)
+                        .details("Failed to restore message")
+                        .caused_by(trc::location!())
+                );
+                job.fail_message();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn backup_json(backup: &TenantBackup) -> Value {
+    json!({
+        "id": backup.id.to_string(),
+        "started": backup.started,
+        "finished": backup.finished,
+        "copyBlobs": backup.copy_blobs,
+        "encrypted": backup.encrypted,
+        "principals": backup.principals,
+        "accounts": backup.accounts,
+        "messages": backup.messages,
+        "size": backup.size,
+    })
+}
+
+fn parse_request<T: serde::de::DeserializeOwned + Default>(
+    body: Option<Vec<u8>>,
+) -> trc::Result<T> {
+    if body.as_ref().is_some_and(|body| !body.is_empty()) {
+        serde_json::from_slice::<T>(body.as_deref().unwrap_or_default()).map_err(|err| {
+            trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+        })
+    } else {
+        Ok(T::default())
+    }
+}
+
+fn assert_can_view(access_token: &AccessToken) -> trc::Result<()> {
+    access_token
+        .assert_has_permission(if access_token.tenant.is_some() {
+            Permission::PrincipalGet
+        } else {
+            Permission::TenantGet
+        })
+        .map(|_| ())
+}
+
+fn job_in_progress() -> trc::Error {
+    manage::error(
+        "Backup in progress",
+        "A backup or restore is already running for this organization".into(),
+    )
+}
@@ -0,0 +1,400 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    auth::AccessToken,
+    config::smtp::bimi::{BIMI_LOGO, BIMI_VMC, DomainBimi},
+    manager::webadmin::Resource,
+};
+use directory::{Permission, backend::internal::manage};
+use http_proto::*;
+use hyper::Method;
+use quick_xml::{Reader, events::Event};
+use serde::Deserialize;
+use serde_json::json;
+use x509_parser::{extensions::GeneralName, pem::Pem};
+
+/// Largest logo accepted, as recommended by the BIMI group.
+const MAX_LOGO_SIZE: usize = 32 * 1024;
+
+/// Elements excluded from the SVG Tiny Portable/Secure profile, which does
+/// not allow scripting, animation, interactivity or embedded content.
+const FORBIDDEN_ELEMENTS: [&str; 13] = [
+    "script",
+    "image",
+    "foreignObject",
+    "animate",
+    "animateColor",
+    "animateMotion",
+    "animateTransform",
+    "set",
+    "video",
+    "audio",
+    "handler",
+    "listener",
+    "discard",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BimiRequest {
+    logo: String,
+    #[serde(default)]
+    vmc: Option<String>,
+}
+
+pub(super) async fn handle_bimi(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    domain: &str,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    match *req.method() {
+        Method::GET => {
+            // Validate the access token
+            access_token.assert_has_permission(Permission::DomainGet)?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server
+                    .domain_bimi(domain)
+                    .map(|bimi| bimi_response(server, domain, &bimi)),
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            // Validate the access token
+            access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+            let request =
+                serde_json::from_slice::<BimiRequest>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            validate_svg_tiny_ps(request.logo.as_bytes())
+                .map_err(|err| manage::error("Invalid SVG Tiny PS logo", err.into()))?;
+            let vmc = request
+                .vmc
+                .map(|vmc| vmc.trim().to_string())
+                .filter(|vmc| !vmc.is_empty());
+            if let Some(vmc) = &vmc {
+                validate_vmc(vmc.as_bytes(), domain)
+                    .map_err(|err| manage::error("Invalid VMC certificate", err.into()))?;
+            }
+
+            let bimi = server
+                .update_domain_bimi(
+                    domain,
+                    request.logo.as_bytes(),
+                    vmc.as_ref().map(|vmc| vmc.as_bytes()),
+                )
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": bimi_response(server, domain, &bimi),
+            }))
+            .into_http_response())
+        }
+        Method::DELETE => {
+            // Validate the access token
+            access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+            if !server.delete_domain_bimi(domain).await? {
+                return Err(manage::not_found(domain.to_string()));
+            }
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Serves the logo and certificate of a domain to mailbox providers, which
+/// fetch them anonymously from the URLs published in the BIMI record.
+pub async fn bimi_resource(server: &Server, domain: &str, file: &str) -> trc::Result<HttpResponse> {
+    let content_type = match file {
+        BIMI_LOGO => "image/svg+xml",
+        BIMI_VMC => "application/pem-certificate-chain",
+        _ => return Err(trc::ResourceEvent::NotFound.into_err()),
+    };
+
+    match server
+        .domain_bimi_file(&domain.to_lowercase(), file)
+        .await?
+    {
+        Some(contents) => Ok(Resource::new(content_type, contents).into_http_response()),
+        None => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+fn bimi_response(server: &Server, domain: &str, bimi: &DomainBimi) -> serde_json::Value {
+    let hostname = server.core.network.server_name.as_str();
+    json!({
+        "logo": bimi.logo,
+        "vmc": bimi.vmc,
+        "logoUrl": DomainBimi::logo_url(hostname, domain),
+        "vmcUrl": bimi.vmc_url(hostname, domain),
+        "record": {
+            "type": "TXT",
+            "name": format!("default._bimi.{domain}."),
+            "content": bimi.record(hostname, domain),
+        },
+    })
+}
+
+/// Checks a logo against the constraints of the SVG Tiny Portable/Secure
+/// profile required by BIMI, returning the first violation found.
+pub fn validate_svg_tiny_ps(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() > MAX_LOGO_SIZE {
+        return Err(format!(
+            "Logo exceeds the maximum size of {MAX_LOGO_SIZE} bytes"
+        ));
+    }
+    let svg = std::str::from_utf8(bytes).map_err(|_| "Logo is not valid UTF-8".to_string())?;
+
+    let mut reader = Reader::from_str(svg);
+    let mut depth = 0usize;
+    let mut has_root = false;
+    let mut has_title = false;
+    loop {
+        let (element, is_empty) = match reader.read_event() {
+            Ok(Event::Start(element)) => (element, false),
+            Ok(Event::Empty(element)) => (element, true),
+            Ok(Event::End(_)) => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            Ok(Event::Text(text)) if depth == 0 && !text.iter().all(u8::is_ascii_whitespace) => {
+                return Err("Text is not allowed outside the root element".to_string());
+            }
+            Ok(Event::DocType(_)) => {
+                return Err("Document type declarations are not allowed".to_string());
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => continue,
+            Err(err) => {
+                return Err(format!(
+                    "Invalid XML at position {}: {err}",
+                    reader.buffer_position()
+                ));
+            }
+        };
+        let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+
+        if depth == 0 {
+            if has_root {
+                return Err("Only one root element is allowed".to_string());
+            } else if name != "svg" {
+                return Err(format!("Root element must be <svg>, found <{name}>"));
+            }
+            has_root = true;
+        } else if depth == 1 && name == "title" {
+            has_title = true;
+        }
+        if FORBIDDEN_ELEMENTS.contains(&name.as_str()) {
+            return Err(format!("Element <{name}> is not allowed"));
+        }
+
+        let mut version = None;
+        let mut base_profile = None;
+        let mut view_box = None;
+        for attribute in element.attributes() {
+            let attribute = attribute.map_err(|err| format!("Invalid attribute: {err}"))?;
+            let key = String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned();
+            let value = attribute
+                .unescape_value()
+                .map_err(|err| format!("Invalid attribute {key}: {err}"))?;
+
+            if key.starts_with("on") {
+                return Err(format!("Event attribute {key} is not allowed"));
+            } else if key == "href" && !value.starts_with('#') {
+                return Err(format!("External reference {value:?} is not allowed"));
+            } else if depth == 0 {
+                match key.as_str() {
+                    "x" | "y" => {
+                        return Err(format!(
+                            "Attribute {key} is not allowed on the root element"
+                        ));
+                    }
+                    "version" => version = Some(value.into_owned()),
+                    "baseProfile" => base_profile = Some(value.into_owned()),
+                    "viewBox" => view_box = Some(value.into_owned()),
+                    _ => (),
+                }
+            }
+        }
+
+        if depth == 0 {
+            if base_profile.as_deref() != Some("tiny-ps") {
+                return Err("Root element must have baseProfile=\"tiny-ps\"".to_string());
+            } else if version.as_deref() != Some("1.2") {
+                return Err("Root element must have version=\"1.2\"".to_string());
+            }
+            let dimensions = view_box
+                .as_deref()
+                .map(|view_box| {
+                    view_box
+                        .split(|c: char| c.is_ascii_whitespace() || c == ',')
+                        .filter(|value| !value.is_empty())
+                        .map(|value| value.parse::<f64>().ok())
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| "Root element must have a viewBox".to_string())?;
+            match dimensions.as_deref() {
+                Some([_, _, width, height]) if width == height && *width > 0.0 => (),
+                Some([_, _, _, _]) => {
+                    return Err("Logo must have a square aspect ratio".to_string());
+                }
+                _ => return Err("Invalid viewBox".to_string()),
+            }
+        }
+
+        if !is_empty {
+            depth += 1;
+        }
+    }
+
+    if !has_root {
+        Err("Logo does not contain an <svg> element".to_string())
+    } else if !has_title {
+        Err("Logo must have a <title> element".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that a Verified Mark Certificate is a PEM encoded chain whose first
+/// certificate is currently valid and issued for the domain.
+fn validate_vmc(bytes: &[u8], domain: &str) -> Result<(), String> {
+    let pem = Pem::iter_from_buffer(bytes)
+        .next()
+        .ok_or_else(|| "No PEM certificate found".to_string())?
+        .map_err(|err| format!("Invalid PEM encoding: {err}"))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|err| format!("Failed to parse certificate: {err}"))?;
+
+    if !cert.validity().is_valid() {
+        return Err("Certificate is expired or not yet valid".to_string());
+    }
+    let has_domain = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .is_some_and(|san| {
+            san.value.general_names.iter().any(|name| {
+                matches!(name, GeneralName::DNSName(name) if name.eq_ignore_ascii_case(domain))
+            })
+        });
+    if !has_domain {
+        return Err(format!("Certificate is not issued for {domain}"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_svg_tiny_ps;
+
+    #[test]
+    fn svg_tiny_ps() {
+        let logo = |attributes: &str, contents: &str| {
+            format!(
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                    "<svg xmlns=\"http://www.w3.org/2000/svg\" {}>",
+                    "{}</svg>"
+                ),
+                attributes, contents
+            )
+        };
+        let valid = "version=\"1.2\" baseProfile=\"tiny-ps\" viewBox=\"0 0 100 100\"";
+
+        assert_eq!(
+            validate_svg_tiny_ps(
+                logo(
+                    valid,
+                    "<title>Acme</title><circle cx=\"50\" cy=\"50\" r=\"40\" fill=\"#f00\"/>"
+                )
+                .as_bytes()
+            ),
+            Ok(())
+        );
+
+        for (attributes, contents, expected) in [
+            (valid, "<circle r=\"40\"/>", "<title>"),
+            (
+                "version=\"1.2\" viewBox=\"0 0 100 100\"",
+                "<title>Acme</title>",
+                "baseProfile",
+            ),
+            (
+                "version=\"1.1\" baseProfile=\"tiny-ps\" viewBox=\"0 0 100 100\"",
+                "<title>Acme</title>",
+                "version",
+            ),
+            (
+                "version=\"1.2\" baseProfile=\"tiny-ps\" viewBox=\"0 0 100 50\"",
+                "<title>Acme</title>",
+                "square",
+            ),
+            (
+                "version=\"1.2\" baseProfile=\"tiny-ps\"",
+                "<title>Acme</title>",
+                "viewBox",
+            ),
+            (
+                "version=\"1.2\" baseProfile=\"tiny-ps\" viewBox=\"0 0 100 100\" x=\"0\"",
+                "<title>Acme</title>",
+                "Attribute x",
+            ),
+            (
+                valid,
+                "<title>Acme</title><script>alert(1)</script>",
+                "<script>",
+            ),
+            (
+                valid,
+                "<title>Acme</title><g><animate attributeName=\"r\"/></g>",
+                "<animate>",
+            ),
+            (
+                valid,
+                "<title>Acme</title><use xlink:href=\"https://example.org/logo.svg#a\"/>",
+                "External reference",
+            ),
+            (
+                valid,
+                "<title>Acme</title><rect onclick=\"steal()\"/>",
+                "Event attribute onclick",
+            ),
+            (valid, "<title>Acme</title><rect>", "Invalid XML"),
+        ] {
+            let result = validate_svg_tiny_ps(logo(attributes, contents).as_bytes());
+            assert!(
+                result.as_ref().is_err_and(|err| err.contains(expected)),
+                "{attributes} {contents}: {result:?}"
+            );
+        }
+
+        assert!(
+            validate_svg_tiny_ps(
+                b"<!DOCTYPE svg [<!ENTITY a \"b\">]><svg version=\"1.2\" baseProfile=\"tiny-ps\"/>"
+            )
+            .is_err_and(|err| err.contains("Document type"))
+        );
+        assert!(
+            validate_svg_tiny_ps(&vec![b' '; 40 * 1024])
+                .is_err_and(|err| err.contains("maximum size"))
+        );
+    }
+}
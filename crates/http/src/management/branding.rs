@@ -0,0 +1,114 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    config::branding::{BrandVariant, Branding, DEFAULT_FONT_FAMILY},
+};
+use directory::backend::internal::manage::{self, ManageDirectory};
+use http_proto::*;
+use hyper::header;
+use serde_json::{Value, json};
+use std::future::Future;
+use utils::url_params::UrlParams;
+
+pub trait BrandingManager: Sync + Send {
+    fn handle_public_branding(
+        &self,
+        req: &HttpRequest,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl BrandingManager for Server {
+    // The branding is served without credentials, so the login page can be
+    // rendered before the user signs in. The domain is taken from the
+    // `domain` parameter or else from the Host header.
+    async fn handle_public_branding(&self, req: &HttpRequest) -> trc::Result<HttpResponse> {
+        let path = req.uri().path().split('/').skip(2).collect::<Vec<_>>();
+        let params = UrlParams::new(req.uri().query());
+        let variant = brand_variant(req, &params)?;
+        let host = params
+            .get("domain")
+            .or_else(|| {
+                req.headers()
+                    .get(header::HOST)
+                    .and_then(|host| host.to_str().ok())
+            })
+            .unwrap_or_default();
+
+        let (domain, tenant_id) = self.branding_domain(host).await?.unzip();
+        let tenant_id = tenant_id.flatten();
+        let branding = match tenant_id {
+            Some(tenant_id) => self
+                .store()
+                .get_principal(tenant_id)
+                .await?
+                .map(|tenant| Branding::from_principal(&tenant)),
+            None => None,
+        }
+        .unwrap_or_else(|| Branding {
+            name: domain
+                .clone()
+                .unwrap_or_else(|| self.core.network.server_name.clone()),
+            ..Default::default()
+        });
+        let login_page = self.login_page(domain.as_deref(), tenant_id);
+
+        match path.get(1).copied().filter(|part| !part.is_empty()) {
+            None => Ok(JsonResponse::new(json!({
+                "data": {
+                    "domain": domain,
+                    "variant": variant.as_str(),
+                    "brand": resolved_brand_json(&branding, variant),
+                    "loginPage": login_page,
+                },
+            }))
+            .into_http_response()),
+            Some("login") => Ok(HtmlResponse::new(
+                branding.render_login(variant, login_page.as_deref()),
+            )
+            .into_http_response()),
+            // Shown on password change forms, denied passwords are not listed
+            Some("password-policy") => Ok(JsonResponse::new(json!({
+                "data": {
+                    "domain": domain,
+                    "passwordPolicy": self.password_policy(tenant_id),
+                },
+            }))
+            .into_http_response()),
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+/// The variant is selected with the `variant` parameter, or else with the
+/// color scheme preferred by the browser.
+pub(super) fn brand_variant(req: &HttpRequest, params: &UrlParams) -> trc::Result<BrandVariant> {
+    match params.get("variant") {
+        Some(variant) => BrandVariant::parse(variant).ok_or_else(|| {
+            manage::error(
+                "Invalid variant",
+                format!("Unknown variant {variant:?}, expected light or dark").into(),
+            )
+        }),
+        None => Ok(req
+            .headers()
+            .get("sec-ch-prefers-color-scheme")
+            .and_then(|value| value.to_str().ok())
+            .and_then(BrandVariant::parse)
+            .unwrap_or_default()),
+    }
+}
+
+/// Values used when rendering a variant, with the defaults filled in.
+pub(super) fn resolved_brand_json(branding: &Branding, variant: BrandVariant) -> Value {
+    json!({
+        "name": branding.name,
+        "logoUrl": branding.logo(variant),
+        "fontFamily": branding.theme.font_family.as_deref().unwrap_or(DEFAULT_FONT_FAMILY),
+        "colors": branding.theme.resolve(variant),
+    })
+}
@@ -0,0 +1,118 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, time::Duration};
+
+use common::{
+    Server,
+    auth::{AccessToken, changes::DirectoryChange},
+};
+use directory::{Permission, Type};
+use http_body_util::{StreamBody, combinators::BoxBody};
+use http_proto::*;
+use hyper::{
+    StatusCode,
+    body::{Bytes, Frame},
+};
+use tokio::sync::broadcast::error::RecvError;
+use utils::url_params::UrlParams;
+
+pub trait DirectoryChangesApi: Sync + Send {
+    fn handle_directory_changes(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl DirectoryChangesApi for Server {
+    async fn handle_directory_changes(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        // Validate the access token
+        access_token.assert_has_permission(Permission::PrincipalList)?;
+
+        // Resume from the sequence in the URL or the last event id sent by the client
+        let since = UrlParams::new(req.uri().query())
+            .parse::<u64>("since")
+            .or_else(|| {
+                req.headers()
+                    .get("last-event-id")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+            });
+
+        // Tenant administrators only receive changes from their own tenant,
+        // including those to the tenant principal itself
+        let tenant_id = access_token.tenant.map(|tenant| tenant.id);
+        let is_visible = move |change: &DirectoryChange| {
+            tenant_id.is_none()
+                || change.tenant_id == tenant_id
+                || (change.typ == Type::Tenant && Some(change.id) == tenant_id)
+        };
+
+        let subscription = self.inner.data.directory_changes.subscribe(since);
+        let mut rx = subscription.rx;
+        let mut pending = Vec::new();
+        if subscription.resync {
+            pending.push(Bytes::from(format!(
+                "event: resync\ndata: {{\"sequence\": {}}}\n\n",
+                subscription.last_seq
+            )));
+        }
+        for change in subscription.replay {
+            if is_visible(&change) {
+                pending.push(change_payload(&change));
+            }
+        }
+
+        let ping_interval = Duration::from_secs(30);
+        let ping_payload = Bytes::from(format!(
+            "event: ping\ndata: {{\"interval\": {}}}\n\n",
+            ping_interval.as_millis()
+        ));
+
+        Ok(HttpResponse::new(StatusCode::OK)
+            .with_content_type("text/event-stream")
+            .with_cache_control("no-store")
+            .with_stream_body(BoxBody::new(StreamBody::new(async_stream::stream! {
+                for payload in pending {
+                    yield Ok(Frame::data(payload));
+                }
+
+                loop {
+                    match tokio::time::timeout(ping_interval, rx.recv()).await {
+                        Ok(Ok(change)) => {
+                            if is_visible(&change) {
+                                yield Ok(Frame::data(change_payload(&change)));
+                            }
+                        }
+                        Ok(Err(RecvError::Lagged(_))) => {
+                            // The consumer fell behind, close the stream so it
+                            // reconnects and catches up from its last event id
+                            break;
+                        }
+                        Ok(Err(RecvError::Closed)) => {
+                            break;
+                        }
+                        Err(_) => {
+                            yield Ok(Frame::data(ping_payload.clone()));
+                        }
+                    }
+                }
+            }))))
+    }
+}
+
+fn change_payload(change: &DirectoryChange) -> Bytes {
+    Bytes::from(format!(
+        "id: {}\nevent: change\ndata: {}\n\n",
+        change.sequence,
+        serde_json::to_string(change).unwrap_or_default()
+    ))
+}
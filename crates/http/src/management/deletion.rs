@@ -0,0 +1,533 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{import::error_details, organization::csv_field, stores::destroy_account_data};
+use common::{
+    Server,
+    auth::AccessToken,
+    config::smtp::queue::DomainRouteAction,
+    storage::deletion::{
+        AccountContents, DeletionBlocker, DeletionReport, DeletionReportJob, SieveScriptRef,
+    },
+};
+use directory::{
+    Permission, QueryBy, Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
+use email::{cache::MessageCacheFetch, sieve::SieveScript};
+use http_proto::*;
+use hyper::{Method, StatusCode};
+use serde_json::{Value, json};
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Instant};
+use store::{ahash::AHashSet, write::now};
+use trc::AddContext;
+use types::{collection::Collection, field::Field};
+use utils::url_params::UrlParams;
+
+// Reports of organizations with more accounts are generated in the background
+const LARGE_TENANT_ACCOUNTS: u64 = 100;
+
+// Principals are removed before the domains their addresses belong to
+const DELETION_ORDER: &[Type] = &[
+    Type::Individual,
+    Type::Group,
+    Type::List,
+    Type::Resource,
+    Type::Location,
+    Type::Other,
+    Type::Role,
+    Type::ApiKey,
+    Type::OauthClient,
+    Type::Domain,
+];
+
+pub trait OrganizationDeletionManager: Sync + Send {
+    fn handle_deletion_report(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_organization_deletion(
+        &self,
+        req: &HttpRequest,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl OrganizationDeletionManager for Server {
+    // Reports list what deleting the organization removes. Webhooks are
+    // configured for the whole server, so they are never part of a report.
+    async fn handle_deletion_report(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(if access_token.tenant.is_some() {
+            Permission::PrincipalGet
+        } else {
+            Permission::TenantGet
+        })?;
+
+        match (path.get(3).copied(), req.method()) {
+            (None, &Method::GET) => {
+                let job = self
+                    .inner
+                    .data
+                    .deletion_reports
+                    .start(self.inner.data.jmap_id_gen.generate(), tenant_id)
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Report in progress",
+                            "A deletion report is already being generated for this organization"
+                                .into(),
+                        )
+                    })?;
+
+                if self
+                    .store()
+                    .count_tenant_principals(tenant_id, Type::Individual)
+                    .await?
+                    > LARGE_TENANT_ACCOUNTS
+                {
+                    let server = self.clone();
+                    let job = job.clone();
+                    tokio::spawn(async move {
+                        run_deletion_report(server, job).await;
+                    });
+                } else {
+                    run_deletion_report(self.clone(), job.clone()).await;
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": job.summary(),
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), &Method::GET) => {
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.deletion_reports.get(job_id))
+                    .filter(|job| job.tenant_id == tenant_id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                let format = UrlParams::new(req.uri().query())
+                    .get("format")
+                    .map(|format| format.to_string());
+                let Some(format) = format else {
+                    return Ok(JsonResponse::new(json!({
+                        "data": job.summary(),
+                    }))
+                    .into_http_response());
+                };
+                let report = job.report().ok_or_else(|| {
+                    manage::error(
+                        "Report not available",
+                        "The report is still being generated or has failed".into(),
+                    )
+                })?;
+
+                match format.as_str() {
+                    "csv" => Ok(HttpResponse::new(StatusCode::OK)
+                        .with_content_type("text/csv; charset=utf-8")
+                        .with_content_disposition(format!(
+                            "attachment; filename=\"deletion-report-{}.csv\"",
+                            job.id
+                        ))
+                        .with_no_store()
+                        .with_text_body(report_csv(&report))),
+                    "json" => Ok(HttpResponse::new(StatusCode::OK)
+                        .with_content_type("application/json")
+                        .with_content_disposition(format!(
+                            "attachment; filename=\"deletion-report-{}.json\"",
+                            job.id
+                        ))
+                        .with_no_store()
+                        .with_text_body(serde_json::to_string(&report).unwrap_or_default())),
+                    _ => Err(manage::error(
+                        "Invalid format",
+                        format!("Unsupported report format {format:?}").into(),
+                    )),
+                }
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+
+    // An empty organization can always be deleted. Otherwise a deletion
+    // report has to be referenced, and its contents are removed as long as
+    // nothing material changed since the report was generated.
+    async fn handle_organization_deletion(
+        &self,
+        req: &HttpRequest,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(Permission::TenantDelete)?;
+
+        // SPDX-SnippetBegin
+        // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+        // SPDX-License-Identifier: LicenseRef-SEL
+        #[cfg(feature = "enterprise")]
+        if !self.core.is_enterprise_edition() {
+            return Err(manage::enterprise());
+        }
+        // SPDX-SnippetEnd
+
+        let current = deletion_report(self, tenant_id).await?;
+        if !current.blockers.is_empty() {
+            return Err(manage::error(
+                "Organization cannot be deleted",
+                current
+                    .blockers
+                    .iter()
+                    .map(|blocker| format!("{}: {}", blocker.name, blocker.reason))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+                    .into(),
+            ));
+        }
+
+        match UrlParams::new(req.uri().query()).get("report") {
+            Some(report_id) => {
+                let report = report_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|report_id| self.inner.data.deletion_reports.get(report_id))
+                    .filter(|job| job.tenant_id == tenant_id)
+                    .and_then(|job| job.report())
+                    .ok_or_else(|| manage::not_found(report_id.to_string()))?;
+                let changes = report.changes(&current);
+                if !changes.is_empty() {
+                    return Err(manage::error(
+                        "Organization changed",
+                        format!(
+                            "Generate a new report, the organization changed since it was reviewed: {}",
+                            changes.join("; ")
+                        )
+                        .into(),
+                    ));
+                }
+            }
+            None if !current.is_empty() => {
+                return Err(manage::error(
+                    "Organization is not empty",
+                    "Reference a deletion report to delete the organization along with its contents"
+                        .into(),
+                ));
+            }
+            None => {}
+        }
+
+        let start_time = Instant::now();
+        let mut removed = BTreeMap::new();
+        for script in self.tenant_sieve_scripts(tenant_id) {
+            self.update_tenant_sieve_script(tenant_id, &script.name, None)
+                .await?;
+            *removed.entry("sieveScript").or_insert(0u64) += 1;
+        }
+        for mapping in self.tenant_hostnames(tenant_id) {
+            self.update_tenant_hostname(&mapping.hostname, None).await?;
+            *removed.entry("hostname").or_insert(0u64) += 1;
+        }
+        for &typ in DELETION_ORDER {
+            for principal_id in self
+                .store()
+                .principal_ids(Some(typ), Some(tenant_id))
+                .await?
+            {
+                delete_principal(self, principal_id, typ, access_token).await?;
+                *removed.entry(typ.as_str()).or_insert(0u64) += 1;
+            }
+        }
+        delete_principal(self, tenant_id, Type::Tenant, access_token).await?;
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::PrincipalDeleted),
+            AccountName = access_token.name.clone(),
+            AccountId = access_token.primary_id(),
+            Id = current.organization,
+            Type = Type::Tenant.as_str(),
+            Total = removed.values().sum::<u64>(),
+            Elapsed = start_time.elapsed(),
+        );
+
+        Ok(JsonResponse::new(json!({
+            "data": {
+                "removed": removed,
+            },
+        }))
+        .into_http_response())
+    }
+}
+
+async fn run_deletion_report(server: Server, job: Arc<DeletionReportJob>) {
+    match deletion_report(&server, job.tenant_id).await {
+        Ok(report) => {
+            job.complete(report);
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+            trc::error!(err.details("Failed to generate deletion report"));
+        }
+    }
+}
+
+/// Walks the organization and lists everything that would be removed along
+/// with it.
+async fn deletion_report(server: &Server, tenant_id: u32) -> trc::Result<DeletionReport> {
+    let store = server.store();
+    let mut report = DeletionReport {
+        organization: store
+            .get_principal_name(tenant_id)
+            .await?
+            .ok_or_else(|| manage::not_found(tenant_id))?,
+        generated_at: now(),
+        ..Default::default()
+    };
+
+    for principal in store
+        .list_principals(None, Some(tenant_id), &[Type::Individual], true, 0, 0)
+        .await?
+        .items
+    {
+        let account_id = principal.id();
+        let messages = server
+            .get_cached_messages(account_id)
+            .await
+            .caused_by(trc::location!())?;
+        report.accounts.push(AccountContents {
+            name: principal.name().to_string(),
+            messages: messages.emails.items.len() as u64,
+            size: messages
+                .emails
+                .items
+                .iter()
+                .map(|message| message.size as u64)
+                .sum(),
+        });
+
+        server
+            .all_archives(
+                account_id,
+                Collection::SieveScript,
+                Field::ARCHIVE.into(),
+                |_, script| {
+                    report.sieve_scripts.push(SieveScriptRef {
+                        account: principal.name().to_string().into(),
+                        name: script.unarchive::<SieveScript>()?.name.to_string(),
+                    });
+                    Ok(())
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        if let Some((set_by, _)) = principal.legal_hold() {
+            report.blockers.push(DeletionBlocker {
+                typ: "legalHold".to_string(),
+                name: principal.name().to_string(),
+                reason: format!("Account is on legal hold set by {set_by}"),
+            });
+        }
+    }
+    for script in server.tenant_sieve_scripts(tenant_id) {
+        report.sieve_scripts.push(SieveScriptRef {
+            account: None,
+            name: script.name.clone(),
+        });
+    }
+
+    for principal in store
+        .list_principals(
+            None,
+            Some(tenant_id),
+            &[
+                Type::Group,
+                Type::List,
+                Type::Resource,
+                Type::Location,
+                Type::Other,
+                Type::Role,
+                Type::ApiKey,
+                Type::OauthClient,
+                Type::Domain,
+            ],
+            false,
+            0,
+            0,
+        )
+        .await?
+        .items
+    {
+        let name = principal.name().to_string();
+        match principal.typ() {
+            Type::Group => report.groups.push(name),
+            Type::List => report.lists.push(name),
+            Type::Role => report.roles.push(name),
+            Type::ApiKey => report.api_keys.push(name),
+            Type::OauthClient => report.oauth_clients.push(name),
+            Type::Domain => report.domains.push(name),
+            _ => report.resources.push(name),
+        }
+    }
+
+    // Domains that other organizations route mail to can't be removed
+    let domains = report
+        .domains
+        .iter()
+        .map(|domain| domain.as_str())
+        .collect::<AHashSet<_>>();
+    let mut blockers = Vec::new();
+    for (&other_id, routes) in server.inner.data.tenant_routing.load().iter() {
+        if other_id == tenant_id {
+            continue;
+        }
+        for domain in routes.settings.domains.keys() {
+            if domains.contains(domain.as_str()) {
+                blockers.push((domain.clone(), format!("organization #{other_id}")));
+            }
+        }
+    }
+    for (route_domain, routes) in server.inner.data.domain_routes.load().iter() {
+        if domains.contains(route_domain.as_str()) {
+            continue;
+        }
+        for rule in &routes.rules {
+            if let DomainRouteAction::Forward { address } = &rule.action
+                && let Some((_, domain)) = address.rsplit_once('@')
+                && domains.contains(domain.to_lowercase().as_str())
+            {
+                blockers.push((domain.to_lowercase(), format!("domain {route_domain}")));
+            }
+        }
+    }
+    for (domain, referenced_by) in blockers {
+        report.blockers.push(DeletionBlocker {
+            typ: "routing".to_string(),
+            name: domain,
+            reason: format!("Domain is referenced by the routing of {referenced_by}"),
+        });
+    }
+
+    report.sieve_scripts.sort_unstable();
+    report
+        .blockers
+        .sort_unstable_by(|a, b| (&a.typ, &a.name).cmp(&(&b.typ, &b.name)));
+
+    Ok(report)
+}
+
+async fn delete_principal(
+    server: &Server,
+    principal_id: u32,
+    typ: Type,
+    access_token: &AccessToken,
+) -> trc::Result<()> {
+    let name = server
+        .store()
+        .get_principal_name(principal_id)
+        .await?
+        .unwrap_or_default();
+    let changed_principals = server
+        .store()
+        .delete_principal(QueryBy::Id(principal_id))
+        .await?;
+
+    trc::event!(
+        Directory(trc::DirectoryEvent::PrincipalDeleted),
+        AccountName = access_token.name.clone(),
+        AccountId = access_token.primary_id(),
+        TenantId = access_token.tenant.map(|t| t.id),
+        Id = name,
+        Type = typ.as_str(),
+    );
+
+    if let Err(err) = destroy_account_data(
+        server,
+        principal_id,
+        matches!(typ, Type::Individual | Type::Group),
+    )
+    .await
+    {
+        trc::error!(err.details("Failed to delete principal"));
+    }
+
+    server.invalidate_principal_caches(changed_principals).await;
+
+    Ok(())
+}
+
+fn report_csv(report: &DeletionReport) -> String {
+    let mut rows = vec![vec![
+        Value::from("type"),
+        Value::from("name"),
+        Value::from("account"),
+        Value::from("messages"),
+        Value::from("size"),
+        Value::from("reason"),
+    ]];
+    let row = |typ: &str, name: &str| {
+        vec![
+            Value::from(typ),
+            Value::from(name),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+        ]
+    };
+
+    for domain in &report.domains {
+        rows.push(row("domain", domain));
+    }
+    for account in &report.accounts {
+        rows.push(vec![
+            Value::from("account"),
+            Value::from(account.name.as_str()),
+            Value::Null,
+            Value::from(account.messages),
+            Value::from(account.size),
+            Value::Null,
+        ]);
+    }
+    for (typ, names) in [
+        ("group", &report.groups),
+        ("list", &report.lists),
+        ("role", &report.roles),
+        ("resource", &report.resources),
+        ("apiKey", &report.api_keys),
+        ("oauthClient", &report.oauth_clients),
+    ] {
+        for name in names {
+            rows.push(row(typ, name));
+        }
+    }
+    for script in &report.sieve_scripts {
+        let mut row = row("sieveScript", &script.name);
+        row[2] = script
+            .account
+            .as_deref()
+            .map(Value::from)
+            .unwrap_or_default();
+        rows.push(row);
+    }
+    for blocker in &report.blockers {
+        let mut row = row(&blocker.typ, &blocker.name);
+        row[5] = Value::from(blocker.reason.as_str());
+        rows.push(row);
+    }
+
+    rows.iter()
+        .map(|row| row.iter().map(csv_field).collect::<Vec<_>>().join(",") + "\r\n")
+        .collect()
+}
@@ -0,0 +1,545 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{Timestamp, organization::csv_field};
+use common::{
+    Server,
+    auth::AccessToken,
+    config::{smtp::queue::DeliveryLogConfig, spamfilter::IpResolver},
+    dns::{DnsCheckStatus, DnsCheckType},
+};
+use directory::{Permission, backend::internal::manage};
+use http_proto::{request::decode_path_element, *};
+use hyper::StatusCode;
+use mail_auth::common::resolver::{IntoFqdn, ToReverseName};
+use mail_parser::DateTime;
+use serde_json::{Map, Value, json};
+use smtp::queue::delivery_log::{
+    DeferralReason, DeliveryLog, DeliveryOutcome, destination_provider,
+};
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use store::{
+    Deserialize, IterateParams, ValueKey,
+    ahash::{AHashMap, AHashSet},
+    write::{AlignedBytes, Archive, QueueClass, ValueClass, now},
+};
+use trc::AddContext;
+use utils::{snowflake::SnowflakeIdGenerator, url_params::UrlParams};
+
+// Reports cover the last week unless a window is requested
+const DEFAULT_WINDOW: u64 = 7 * 86400;
+const MAX_FAILURES: usize = 25;
+
+const STATS_COLUMNS: [&str; 11] = [
+    "recipients",
+    "delivered",
+    "deferred",
+    "bounced",
+    "bounceRate",
+    "rateLimit",
+    "reputation",
+    "authentication",
+    "content",
+    "connection",
+    "other",
+];
+
+pub trait DeliverabilityReport: Sync + Send {
+    fn handle_deliverability(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+/// Recipient outcomes and the reasons of their deferred attempts.
+#[derive(Debug, Default)]
+struct DeliveryStats {
+    recipients: u64,
+    delivered: u64,
+    deferred: u64,
+    bounced: u64,
+    queued: u64,
+    deferrals: [u64; DeferralReason::ALL.len()],
+}
+
+impl DeliverabilityReport for Server {
+    async fn handle_deliverability(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(if access_token.tenant.is_some() {
+            Permission::PrincipalGet
+        } else {
+            Permission::TenantGet
+        })?;
+
+        let config = self
+            .core
+            .smtp
+            .queue
+            .delivery_log
+            .as_ref()
+            .ok_or_else(|| manage::unsupported("Delivery logging is not enabled"))?;
+
+        let params = UrlParams::new(req.uri().query());
+        let format = params.get("format").unwrap_or("json");
+        if !matches!(format, "json" | "csv") {
+            return Err(manage::error(
+                "Invalid format",
+                format!("{format:?} is not one of csv or json").into(),
+            ));
+        }
+        let to = params
+            .parse::<Timestamp>("before")
+            .map(|t| t.into_inner())
+            .unwrap_or_else(now);
+        let from = params
+            .parse::<Timestamp>("after")
+            .map(|t| t.into_inner())
+            .unwrap_or_else(|| to.saturating_sub(DEFAULT_WINDOW));
+        if from >= to {
+            return Err(manage::error(
+                "Invalid range",
+                "after must be earlier than before".into(),
+            ));
+        }
+        let after = SnowflakeIdGenerator::from_timestamp(from).unwrap_or(0);
+        let before = SnowflakeIdGenerator::from_timestamp(to).unwrap_or(u64::MAX);
+
+        // Drill down into a single provider, broken down by recipient domain
+        let provider = path
+            .get(3)
+            .map(|provider| decode_path_element(provider).to_lowercase());
+
+        let mut total = DeliveryStats::default();
+        let mut providers: AHashMap<&'static str, DeliveryStats> = AHashMap::new();
+        let mut domains: AHashMap<String, DeliveryStats> = AHashMap::new();
+        let mut senders: AHashMap<String, u64> = AHashMap::new();
+        let mut failures = Vec::new();
+        let mut messages = 0u64;
+
+        self.core
+            .storage
+            .data
+            .iterate(
+                IterateParams::new(
+                    ValueKey::from(ValueClass::Queue(QueueClass::DeliveryLog {
+                        tenant_id,
+                        queue_id: after,
+                    })),
+                    ValueKey::from(ValueClass::Queue(QueueClass::DeliveryLog {
+                        tenant_id,
+                        queue_id: before,
+                    })),
+                )
+                .descending(),
+                |key, value| {
+                    let log = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
+                        .and_then(|archive| archive.deserialize::<DeliveryLog>())
+                        .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+
+                    // Attempts are grouped by the provider of their recipient
+                    let mut included = AHashMap::new();
+                    for rcpt in &log.recipients {
+                        let rcpt_provider =
+                            destination_provider(&rcpt.address, rcpt.details.as_deref());
+                        if provider.as_ref().is_some_and(|p| p != rcpt_provider) {
+                            continue;
+                        }
+                        let domain = recipient_domain(&rcpt.address);
+
+                        total.add_recipient(rcpt.outcome);
+                        providers
+                            .entry(rcpt_provider)
+                            .or_default()
+                            .add_recipient(rcpt.outcome);
+                        if provider.is_some() {
+                            domains
+                                .entry(domain.clone())
+                                .or_default()
+                                .add_recipient(rcpt.outcome);
+
+                            if matches!(
+                                rcpt.outcome,
+                                DeliveryOutcome::Deferred | DeliveryOutcome::Bounced
+                            ) && failures.len() < MAX_FAILURES
+                            {
+                                failures.push(json!({
+                                    "id": log.id,
+                                    "recipient": rcpt.address,
+                                    "outcome": rcpt.outcome,
+                                    "reason": DeferralReason::classify(
+                                        rcpt.details.as_deref().unwrap_or_default()
+                                    ),
+                                    "details": rcpt.details,
+                                    "updated": DateTime::from_timestamp(rcpt.updated as i64)
+                                        .to_rfc3339(),
+                                }));
+                            }
+                        }
+                        included.insert(rcpt.address.as_str(), (rcpt_provider, domain));
+                    }
+
+                    if !included.is_empty() {
+                        messages += 1;
+                        if let Some((_, domain)) = log.return_path.rsplit_once('@') {
+                            *senders.entry(domain.to_lowercase()).or_default() += 1;
+                        }
+
+                        for attempt in &log.attempts {
+                            if attempt.outcome == DeliveryOutcome::Deferred
+                                && let Some((rcpt_provider, domain)) =
+                                    included.get(attempt.recipient.as_str())
+                            {
+                                let reason = DeferralReason::classify(
+                                    attempt.details.as_deref().unwrap_or_default(),
+                                );
+                                total.add_deferral(reason);
+                                providers
+                                    .entry(*rcpt_provider)
+                                    .or_default()
+                                    .add_deferral(reason);
+                                if provider.is_some() {
+                                    domains
+                                        .entry(domain.clone())
+                                        .or_default()
+                                        .add_deferral(reason);
+                                }
+                            }
+                        }
+                    }
+
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        if format == "csv" {
+            let (column, rows) = if provider.is_some() {
+                ("domain", sorted_stats(domains))
+            } else {
+                (
+                    "provider",
+                    sorted_stats(
+                        providers
+                            .into_iter()
+                            .map(|(provider, stats)| (provider.to_string(), stats))
+                            .collect(),
+                    ),
+                )
+            };
+            let mut csv = std::iter::once(column)
+                .chain(STATS_COLUMNS)
+                .collect::<Vec<_>>()
+                .join(",");
+            csv.push_str("\r\n");
+            for (name, stats) in rows {
+                csv.push_str(
+                    &std::iter::once(Value::String(name))
+                        .chain(stats.csv_row())
+                        .map(|value| csv_field(&value))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                );
+                csv.push_str("\r\n");
+            }
+
+            return Ok(HttpResponse::new(StatusCode::OK)
+                .with_content_type("text/csv; charset=utf-8")
+                .with_content_disposition("attachment; filename=\"deliverability.csv\"")
+                .with_no_store()
+                .with_text_body(csv));
+        }
+
+        let mut report = total.to_json();
+        report["from"] = DateTime::from_timestamp(from as i64).to_rfc3339().into();
+        report["to"] = DateTime::from_timestamp(to as i64).to_rfc3339().into();
+        report["messages"] = messages.into();
+        report["alignment"] = sender_alignment(self, senders).await?;
+
+        if let Some(provider) = provider {
+            report["provider"] = provider.into();
+            report["domains"] = sorted_stats(domains)
+                .into_iter()
+                .map(|(domain, stats)| {
+                    let mut item = stats.to_json();
+                    item["domain"] = domain.into();
+                    item
+                })
+                .collect::<Vec<_>>()
+                .into();
+            report["recentFailures"] = failures.into();
+        } else {
+            report["providers"] = sorted_stats(
+                providers
+                    .into_iter()
+                    .map(|(provider, stats)| (provider.to_string(), stats))
+                    .collect(),
+            )
+            .into_iter()
+            .map(|(provider, stats)| {
+                let mut item = stats.to_json();
+                item["provider"] = provider.into();
+                item
+            })
+            .collect::<Vec<_>>()
+            .into();
+            report["dnsbl"] = dnsbl_status(self, config).into();
+        }
+
+        Ok(JsonResponse::new(json!({
+            "data": report,
+        }))
+        .into_http_response())
+    }
+}
+
+impl DeliveryStats {
+    fn add_recipient(&mut self, outcome: DeliveryOutcome) {
+        self.recipients += 1;
+        match outcome {
+            DeliveryOutcome::Queued => self.queued += 1,
+            DeliveryOutcome::Delivered => self.delivered += 1,
+            DeliveryOutcome::Deferred => self.deferred += 1,
+            DeliveryOutcome::Bounced => self.bounced += 1,
+        }
+    }
+
+    fn add_deferral(&mut self, reason: DeferralReason) {
+        self.deferrals[reason as usize] += 1;
+    }
+
+    /// Bounces over the recipients with a final outcome.
+    fn bounce_rate(&self) -> f64 {
+        rate(self.bounced, self.delivered + self.bounced)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "recipients": self.recipients,
+            "delivered": self.delivered,
+            "deferred": self.deferred,
+            "bounced": self.bounced,
+            "queued": self.queued,
+            "bounceRate": self.bounce_rate(),
+            "deferralReasons": DeferralReason::ALL
+                .iter()
+                .map(|reason| (reason.as_str().to_string(), self.deferrals[*reason as usize].into()))
+                .collect::<Map<_, _>>(),
+        })
+    }
+
+    fn csv_row(&self) -> impl Iterator<Item = Value> {
+        [
+            self.recipients.into(),
+            self.delivered.into(),
+            self.deferred.into(),
+            self.bounced.into(),
+            self.bounce_rate().into(),
+        ]
+        .into_iter()
+        .chain(self.deferrals.map(Value::from))
+    }
+}
+
+/// Estimates the share of messages whose envelope domain signs with DKIM and
+/// publishes a valid SPF record. Delivery logs do not keep the From header,
+/// so alignment is measured against the return path.
+async fn sender_alignment(server: &Server, senders: AHashMap<String, u64>) -> trc::Result<Value> {
+    let mut signed = AHashSet::new();
+    for (key, value) in server.core.storage.config.list("signature.", true).await? {
+        if key.ends_with(".domain") {
+            signed.insert(value.to_lowercase());
+        }
+    }
+
+    let mut messages = 0;
+    let mut dkim_aligned = 0;
+    let mut spf_aligned = 0;
+    let mut domains = Vec::with_capacity(senders.len());
+    for (domain, count) in sorted_by_count(senders) {
+        let dkim = signed.contains(&domain);
+        let spf = match server.inner.data.dns_checks.get(
+            &domain,
+            DnsCheckType::Spf,
+            server.core.jmap.dns_check_ttl,
+        ) {
+            Some(check) => check,
+            None => server.run_dns_check(&domain, DnsCheckType::Spf).await,
+        }
+        .status
+            == DnsCheckStatus::Ok;
+
+        messages += count;
+        if dkim {
+            dkim_aligned += count;
+        }
+        if spf {
+            spf_aligned += count;
+        }
+        domains.push(json!({
+            "domain": domain,
+            "messages": count,
+            "dkim": dkim,
+            "spf": spf,
+        }));
+    }
+
+    Ok(json!({
+        "messages": messages,
+        "dkimRate": rate(dkim_aligned, messages),
+        "spfRate": rate(spf_aligned, messages),
+        "domains": domains,
+    }))
+}
+
+/// Reports the cached DNSBL listings of the sending IPs. Lookups missing from
+/// the cache run in the background and are reported as pending until then.
+fn dnsbl_status(server: &Server, config: &DeliveryLogConfig) -> Vec<Value> {
+    let ips = if !config.sending_ips.is_empty() {
+        config.sending_ips.iter().copied().collect::<BTreeSet<_>>()
+    } else {
+        server
+            .core
+            .smtp
+            .queue
+            .connection_strategy
+            .values()
+            .flat_map(|strategy| strategy.source_ipv4.iter().chain(&strategy.source_ipv6))
+            .map(|source| source.ip)
+            .collect()
+    };
+
+    let mut items = Vec::with_capacity(ips.len() * config.dnsbl_zones.len());
+    let mut pending = Vec::new();
+    for ip in ips {
+        let reverse = ip.to_reverse_name();
+        for zone in &config.dnsbl_zones {
+            let name = format!("{reverse}.{zone}");
+            let (status, result) = match server.inner.cache.dns_rbl.get(name.as_str()) {
+                Some(Some(result)) => {
+                    let result = result.ip();
+                    if matches!(result, IpAddr::V4(ip) if ip.octets()[..3] == [127, 255, 255]) {
+                        // Return codes used by list operators to refuse queries
+                        ("unavailable", Some(result))
+                    } else {
+                        ("listed", Some(result))
+                    }
+                }
+                Some(None) => ("clear", None),
+                None => {
+                    pending.push(name);
+                    ("pending", None)
+                }
+            };
+
+            items.push(json!({
+                "ip": ip,
+                "zone": zone,
+                "status": status,
+                "result": result,
+            }));
+        }
+    }
+
+    if !pending.is_empty() {
+        let server = server.clone();
+        tokio::spawn(async move {
+            for name in pending {
+                lookup_dnsbl(&server, name).await;
+            }
+        });
+    }
+
+    items
+}
+
+async fn lookup_dnsbl(server: &Server, name: String) {
+    match server
+        .core
+        .smtp
+        .resolvers
+        .dns
+        .ipv4_lookup_raw((&name).into_fqdn().as_ref())
+        .await
+    {
+        Ok(result) => {
+            let entry = Arc::new(IpResolver::new(
+                result
+                    .entry
+                    .iter()
+                    .copied()
+                    .next()
+                    .unwrap_or(Ipv4Addr::BROADCAST)
+                    .into(),
+            ));
+            server
+                .inner
+                .cache
+                .dns_rbl
+                .insert_with_expiry(name, Some(entry), result.expires);
+        }
+        Err(mail_auth::Error::DnsRecordNotFound(_)) => {
+            server
+                .inner
+                .cache
+                .dns_rbl
+                .insert(name, None, Duration::from_secs(86400));
+        }
+        Err(err) => {
+            trc::event!(
+                Resource(trc::ResourceEvent::Error),
+                Details = "Failed to lookup DNSBL",
+                Hostname = name,
+                CausedBy = err.to_string()
+            );
+        }
+    }
+}
+
+fn recipient_domain(address: &str) -> String {
+    address
+        .rsplit_once('@')
+        .map_or(address, |(_, domain)| domain)
+        .to_lowercase()
+}
+
+fn rate(count: u64, total: u64) -> f64 {
+    if total > 0 {
+        (count as f64 / total as f64 * 10000.0).round() / 10000.0
+    } else {
+        0.0
+    }
+}
+
+fn sorted_stats(stats: AHashMap<String, DeliveryStats>) -> Vec<(String, DeliveryStats)> {
+    let mut stats = stats.into_iter().collect::<Vec<_>>();
+    stats.sort_unstable_by(|(a_name, a), (b_name, b)| {
+        b.recipients
+            .cmp(&a.recipients)
+            .then_with(|| a_name.cmp(b_name))
+    });
+    stats
+}
+
+fn sorted_by_count(counts: AHashMap<String, u64>) -> Vec<(String, u64)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_unstable_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+    counts
+}
@@ -6,11 +6,12 @@
 
 use common::{Server, auth::AccessToken};
 use directory::{
-    Permission,
-    backend::internal::manage::{self},
+    Permission, Type,
+    backend::internal::manage::{self, ManageDirectory},
 };
 
 use hyper::Method;
+use mail_parser::DateTime;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha1::Digest;
@@ -21,6 +22,12 @@ use crate::management::dkim::{Algorithm, obtain_dkim_public_key};
 use http_proto::{request::decode_path_element, *};
 use std::future::Future;
 
+/// Default TTL of the records of exported zone files.
+pub const DEFAULT_ZONE_TTL: u32 = 3600;
+
+/// Longest character string of a TXT record.
+const MAX_TXT_STRING: usize = 255;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DnsRecord {
     #[serde(rename = "type")]
@@ -184,7 +191,7 @@ impl DnsManagement for Server {
                 }
                 ("http", _) if is_tls => {
                     has_https = true;
-                    for service in ["jmap", "caldavs", "carddavs"] {
+                    for service in ["jmap", "caldavs", "carddavs", "autodiscover"] {
                         records.push(DnsRecord {
                             typ: "SRV".to_string(),
                             name: format!("_{service}._tcp.{domain_name}.",),
@@ -231,6 +238,37 @@ impl DnsManagement for Server {
             content: format!("v=DMARC1; p=reject; rua=mailto:postmaster@{domain_name}; ruf=mailto:postmaster@{domain_name}",),
         });
 
+        // Add BIMI record
+        if let Some(bimi) = self.domain_bimi(domain_name) {
+            records.push(DnsRecord {
+                typ: "TXT".to_string(),
+                name: format!("default._bimi.{domain_name}."),
+                content: bimi.record(server_name, domain_name),
+            });
+        }
+
+        // Add the challenges of the hostnames pending verification, only
+        // those of the tenant that owns the domain
+        let domain_tenant_id = self
+            .store()
+            .get_principal_info(domain_name)
+            .await?
+            .filter(|info| info.typ == Type::Domain)
+            .and_then(|info| info.tenant);
+        for hostname in self.inner.data.tenant_hostnames.load().values() {
+            if !hostname.is_verified()
+                && Some(hostname.tenant_id) == domain_tenant_id
+                && (hostname.hostname == domain_name
+                    || hostname.hostname.ends_with(&format!(".{domain_name}")))
+            {
+                records.push(DnsRecord {
+                    typ: "TXT".to_string(),
+                    name: hostname.challenge_name(),
+                    content: hostname.challenge_value(),
+                });
+            }
+        }
+
         // Add TLS reporting record
         records.push(DnsRecord {
             typ: "TXT".to_string(),
@@ -288,3 +326,110 @@ impl DnsManagement for Server {
         Ok(records)
     }
 }
+
+impl DnsRecord {
+    /// Renders the record as a line of a zone file (RFC 1035, section 5.1).
+    pub fn to_zone_entry(&self, ttl: u32) -> String {
+        format!(
+            "{}\t{ttl}\tIN\t{}\t{}",
+            self.name,
+            self.typ,
+            if self.typ == "TXT" {
+                quote_txt(&self.content)
+            } else {
+                self.content.clone()
+            }
+        )
+    }
+}
+
+/// Renders the records of a domain as a zone file fragment, ready to be
+/// pasted in the zone of a DNS hosting provider.
+pub fn render_zone_file(
+    domain_name: &str,
+    server_name: &str,
+    records: &[DnsRecord],
+    ttl: u32,
+    generated_at: u64,
+) -> String {
+    let mut zone = format!(
+        "; DNS records of {domain_name}\n; Generated by {server_name} on {}\n$TTL {ttl}\n",
+        DateTime::from_timestamp(generated_at as i64).to_rfc3339()
+    );
+    for record in records {
+        zone.push_str(&record.to_zone_entry(ttl));
+        zone.push('\n');
+    }
+    zone
+}
+
+/// Quotes the contents of a TXT record, split in character strings of at
+/// most 255 bytes that resolvers join back together.
+fn quote_txt(content: &str) -> String {
+    let mut strings = Vec::new();
+    let mut rest = content;
+    loop {
+        let mut end = rest.len().min(MAX_TXT_STRING);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (string, next) = rest.split_at(end);
+        strings.push(format!(
+            "\"{}\"",
+            string.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+        rest = next;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    strings.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DnsRecord, quote_txt, render_zone_file};
+
+    #[test]
+    fn zone_file() {
+        assert_eq!(quote_txt(""), "\"\"");
+        assert_eq!(
+            quote_txt("v=spf1 \"a\" \\ -all"),
+            r#""v=spf1 \"a\" \\ -all""#
+        );
+        let key = "a".repeat(300);
+        assert_eq!(
+            quote_txt(&format!("v=DKIM1; k=rsa; p={key}")),
+            format!("\"v=DKIM1; k=rsa; p={}\" \"{}\"", &key[..237], &key[237..])
+        );
+
+        let zone = render_zone_file(
+            "example.org",
+            "mx.example.org",
+            &[
+                DnsRecord {
+                    typ: "MX".to_string(),
+                    name: "example.org.".to_string(),
+                    content: "10 mx.example.org.".to_string(),
+                },
+                DnsRecord {
+                    typ: "TXT".to_string(),
+                    name: "_dmarc.example.org.".to_string(),
+                    content: "v=DMARC1; p=reject".to_string(),
+                },
+            ],
+            300,
+            0,
+        );
+        assert_eq!(
+            zone,
+            concat!(
+                "; DNS records of example.org\n",
+                "; Generated by mx.example.org on 1970-01-01T00:00:00Z\n",
+                "$TTL 300\n",
+                "example.org.\t300\tIN\tMX\t10 mx.example.org.\n",
+                "_dmarc.example.org.\t300\tIN\tTXT\t\"v=DMARC1; p=reject\"\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,522 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    bimi::handle_bimi,
+    branding::brand_variant,
+    dns::{DEFAULT_ZONE_TTL, DnsManagement, render_zone_file},
+    organization::{assert_public_relay, parse_login_page, preview_brand, preview_disclaimer},
+};
+use common::{
+    Server,
+    auth::AccessToken,
+    config::smtp::{
+        disclaimer::DomainDisclaimer,
+        queue::{DomainRoute, DomainRouteAction},
+    },
+    dns::DnsCheck,
+    manager::spf::SpfMonitor,
+};
+use directory::{
+    Permission, Type,
+    backend::{
+        RcptType,
+        internal::manage::{self, ManageDirectory},
+    },
+};
+use http_proto::{request::decode_path_element, *};
+use hyper::{Method, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::{future::Future, sync::Arc};
+use store::write::now;
+use utils::{DomainPart, url_params::UrlParams};
+
+pub trait DomainManagement: Sync + Send {
+    fn handle_manage_domain(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl DomainManagement for Server {
+    async fn handle_manage_domain(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (
+            path.get(1),
+            path.get(2).copied(),
+            path.get(3).copied(),
+            req.method(),
+        ) {
+            (Some(domain), Some("certificates"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                Ok(JsonResponse::new(json!({
+                    "data": self.domain_certificates(&domain),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("certificates"), Some("renew"), &Method::POST) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                if self.core.acme.domains.is_none() {
+                    return Err(manage::unsupported(
+                        "No ACME provider is configured to issue domain certificates",
+                    ));
+                }
+
+                let domain = domain_name(self, domain, access_token).await?;
+                self.request_domain_certificates(&domain, true).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.domain_certificates(&domain),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("dns-check"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let fresh = UrlParams::new(req.uri().query()).has_key("fresh");
+                let checks = self.domain_dns_checks(&domain, fresh).await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": dns_checks_with_age(&checks),
+                        "total": checks.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("usage"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let domain_id = self
+                    .store()
+                    .get_principal_id(&domain)
+                    .await?
+                    .ok_or_else(|| manage::not_found(domain.clone()))?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.domain_usage(domain_id).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("routes"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let routes = self
+                    .domain_routes(&domain)
+                    .map(|routes| routes.rules.clone())
+                    .unwrap_or_default();
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": routes,
+                        "total": routes.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("routes"), None, &Method::POST) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let mut route =
+                    serde_json::from_slice::<DomainRoute>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+                if let DomainRouteAction::Forward { address } = &mut route.action {
+                    *address = address.trim().to_lowercase();
+                }
+                route
+                    .validate()
+                    .map_err(|err| manage::error(err, None::<u64>))?;
+                // Relay addresses are checked again on delivery, in case the
+                // host is rebound to an internal address later
+                if let DomainRouteAction::Relay(relay) = &route.action
+                    && access_token.tenant.is_some()
+                {
+                    assert_public_relay(relay).await?;
+                }
+                route.id = self.generate_snowflake_id().to_string();
+
+                let mut routes = self
+                    .domain_routes(&domain)
+                    .map(|routes| routes.rules.clone())
+                    .unwrap_or_default();
+                routes.push(route.clone());
+                self.update_domain_routes(&domain, routes).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": route,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("routes"), Some("trace"), &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let params = UrlParams::new(req.uri().query());
+                let address = params
+                    .get("address")
+                    .map(|address| address.trim().to_lowercase())
+                    .filter(|address| address.try_domain_part() == Some(domain.as_str()))
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Invalid address",
+                            format!("Address must belong to domain {domain}").into(),
+                        )
+                    })?;
+
+                // Nothing is delivered, the rules are only evaluated
+                let routes = self.domain_routes(&domain);
+                let route = routes.as_ref().and_then(|routes| {
+                    routes.resolve(address.try_local_part().unwrap_or_default())
+                });
+                let action = route
+                    .map(|route| route.action.clone())
+                    .unwrap_or(DomainRouteAction::Local);
+                let recipient = if action == DomainRouteAction::Local {
+                    Some(match self.core.storage.directory.rcpt(&address).await? {
+                        RcptType::Mailbox => "mailbox",
+                        RcptType::List(_) => "list",
+                        RcptType::Invalid => "invalid",
+                    })
+                } else {
+                    None
+                };
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "address": address,
+                        "route": route,
+                        "action": action,
+                        "recipient": recipient,
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("routes"), Some(route_id), &Method::DELETE) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let mut routes = self
+                    .domain_routes(&domain)
+                    .map(|routes| routes.rules.clone())
+                    .unwrap_or_default();
+                let total = routes.len();
+                routes.retain(|route| route.id != route_id);
+                if routes.len() == total {
+                    return Err(trc::ResourceEvent::NotFound.into_err());
+                }
+                self.update_domain_routes(&domain, routes).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("disclaimer"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.domain_disclaimer(&domain),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("disclaimer"), None, &Method::PUT) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let mut disclaimer =
+                    serde_json::from_slice::<DomainDisclaimer>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+                if let DomainDisclaimer::Custom(disclaimer) = &mut disclaimer {
+                    disclaimer.html = disclaimer
+                        .html
+                        .take()
+                        .map(|html| html.trim().to_string())
+                        .filter(|html| !html.is_empty());
+                }
+                disclaimer
+                    .validate()
+                    .map_err(|err| manage::error(err, None::<u64>))?;
+
+                self.update_domain_disclaimer(&domain, disclaimer.clone().into())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": disclaimer,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("disclaimer"), None, &Method::DELETE) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                self.update_domain_disclaimer(&domain, None).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("disclaimer"), Some("preview"), &Method::POST) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                // Messages sent from the domain use its disclaimer, falling
+                // back to the one of the domain's tenant
+                let domain = domain_name(self, domain, access_token).await?;
+                let tenant_id = self.domain_tenant(&domain).await?;
+                let domain_disclaimer = self.domain_disclaimer(&domain);
+                let tenant_disclaimer =
+                    tenant_id.and_then(|tenant_id| self.tenant_disclaimer(tenant_id));
+                let disclaimer = DomainDisclaimer::resolve(
+                    domain_disclaimer.as_deref(),
+                    tenant_disclaimer.as_deref(),
+                )
+                .cloned()
+                .map(Arc::new);
+
+                preview_disclaimer(self, body, disclaimer, tenant_id, access_token).await
+            }
+            (Some(domain), Some("login-page"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.domain_login_page(&domain),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("login-page"), None, &Method::PUT) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let page = parse_login_page(body)?;
+                self.update_domain_login_page(&domain, page.clone().into())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": page,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("login-page"), None, &Method::DELETE) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                self.update_domain_login_page(&domain, None).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("login-page"), Some("preview"), &Method::POST) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                // The login page of the domain is rendered with the branding
+                // of its tenant, falling back to the tenant's login page
+                let domain = domain_name(self, domain, access_token).await?;
+                let tenant_id = self.domain_tenant(&domain).await?;
+                let params = UrlParams::new(req.uri().query());
+
+                preview_brand(
+                    self,
+                    &params,
+                    brand_variant(req, &params)?,
+                    body,
+                    self.login_page(Some(&domain), tenant_id),
+                    tenant_id,
+                    Some(&domain),
+                )
+                .await
+            }
+            (Some(domain), Some("dns"), Some("zonefile"), &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let ttl = match UrlParams::new(req.uri().query()).get("ttl") {
+                    Some(ttl) => ttl
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|ttl| (1..=i32::MAX as u32).contains(ttl))
+                        .ok_or_else(|| {
+                            manage::error(
+                                "Invalid TTL",
+                                format!("{ttl:?} is not a number of seconds").into(),
+                            )
+                        })?,
+                    None => DEFAULT_ZONE_TTL,
+                };
+                let zone = render_zone_file(
+                    &domain,
+                    &self.core.network.server_name,
+                    &self.build_dns_records(&domain).await?,
+                    ttl,
+                    now(),
+                );
+
+                Ok(HttpResponse::new(StatusCode::OK)
+                    .with_content_type("text/dns; charset=utf-8")
+                    .with_content_disposition(format!("attachment; filename=\"{domain}.zone\""))
+                    .with_no_store()
+                    .with_text_body(zone))
+            }
+            (Some(domain), Some("spf"), Some("analyze"), &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.analyze_spf(&domain).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("spf"), Some("flatten"), &Method::POST) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                let request = match body.as_deref() {
+                    Some(body) if !body.is_empty() => {
+                        serde_json::from_slice::<SpfFlattenRequest>(body).map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?
+                    }
+                    _ => SpfFlattenRequest::default(),
+                };
+                let flattened = self.flatten_spf(&domain, request.record.as_deref()).await?;
+                if request.monitor {
+                    self.set_spf_monitor(
+                        &domain,
+                        Some(&SpfMonitor {
+                            source: flattened.source.clone(),
+                            networks: flattened.networks.clone(),
+                            changed_at: None,
+                        }),
+                    )
+                    .await?;
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": flattened,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("spf"), Some("monitor"), &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainGet)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.spf_monitor(&domain).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("spf"), Some("monitor"), &Method::DELETE) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::DomainUpdate)?;
+
+                let domain = domain_name(self, domain, access_token).await?;
+                self.set_spf_monitor(&domain, None).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some(domain), Some("bimi"), None, _) => {
+                let domain = domain_name(self, domain, access_token).await?;
+
+                handle_bimi(self, req, body, &domain, access_token).await
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpfFlattenRequest {
+    /// Record to flatten instead of the published one.
+    #[serde(default)]
+    record: Option<String>,
+    /// Whether to flatten the record again periodically and report changes.
+    #[serde(default)]
+    monitor: bool,
+}
+
+/// Resolves a domain by name, hiding those outside the caller's tenant.
+async fn domain_name(
+    server: &Server,
+    name: &str,
+    access_token: &AccessToken,
+) -> trc::Result<String> {
+    let name = decode_path_element(name).to_lowercase();
+    server
+        .store()
+        .get_principal_info(&name)
+        .await?
+        .filter(|p| p.typ == Type::Domain && p.has_tenant_access(access_token.tenant.map(|t| t.id)))
+        .map(|_| name.clone())
+        .ok_or_else(|| manage::not_found(name))
+}
+
+/// Adds the age in seconds of each check, so clients can show how long ago
+/// a record was last looked up.
+pub(super) fn dns_checks_with_age(checks: &[DnsCheck]) -> Vec<serde_json::Value> {
+    let now = now();
+    checks
+        .iter()
+        .map(|check| {
+            let mut value = json!(check);
+            value["age"] = check.age(now).into();
+            value
+        })
+        .collect()
+}
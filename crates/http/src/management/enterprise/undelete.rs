@@ -264,11 +264,7 @@ impl UndeleteApi for Server {
                 for request in requests {
                     match request.collection {
                         Collection::Email => {
-                            match self
-                                .blob_store()
-                                .get_blob(request.hash.as_slice(), 0..usize::MAX)
-                                .await?
-                            {
+                            match self.get_blob(&request.hash, 0..usize::MAX).await? {
                                 Some(bytes) => {
                                     match self
                                         .email_ingest(IngestEmail {
@@ -0,0 +1,297 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{import::error_details, stores::destroy_account_data};
+use common::{
+    Server,
+    auth::AccessToken,
+    storage::erasure::{ErasureJob, ErasureReport, RetainedData},
+    telemetry::tracers::audit::AuditStore,
+};
+use directory::{
+    Permission, QueryBy, Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
+use email::message::metadata::MessageMetadata;
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::json;
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Instant};
+use store::{ahash::AHashSet, write::now};
+use trc::AddContext;
+use types::{
+    blob_hash::BlobHash,
+    collection::Collection,
+    field::{EmailField, Field},
+};
+
+// Collections holding the personal data of an account
+const ACCOUNT_COLLECTIONS: &[Collection] = &[
+    Collection::Mailbox,
+    Collection::Identity,
+    Collection::SieveScript,
+    Collection::AddressBook,
+    Collection::ContactCard,
+    Collection::Calendar,
+    Collection::CalendarEvent,
+    Collection::FileNode,
+];
+
+pub trait PrincipalErasureManager: Sync + Send {
+    fn handle_principal_erasure(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl PrincipalErasureManager for Server {
+    // Erasure deletes the principal and everything stored in its account.
+    // Message blobs that are also linked to other accounts are kept for
+    // them, and audit records are kept with the identifying fields replaced
+    // by a tombstone ID.
+    async fn handle_principal_erasure(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(Permission::PrincipalErase)?;
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+
+        match (path.get(3).copied(), req.method()) {
+            (None, &Method::POST) => {
+                let info = self
+                    .store()
+                    .get_principal_info(name.as_ref())
+                    .await?
+                    .filter(|p| {
+                        p.has_tenant_access(access_token.tenant.map(|t| t.id))
+                            && p.typ == Type::Individual
+                    })
+                    .ok_or_else(|| manage::not_found(name.to_string()))?;
+                let principal = self
+                    .store()
+                    .get_principal(info.id)
+                    .await?
+                    .ok_or_else(|| manage::not_found(name.to_string()))?;
+                if principal.legal_hold().is_some() {
+                    return Err(manage::error(
+                        "Account is on legal hold",
+                        "Release the legal hold before erasing this account".into(),
+                    ));
+                }
+
+                let job = self
+                    .inner
+                    .data
+                    .erasure_jobs
+                    .start(
+                        self.inner.data.jmap_id_gen.generate(),
+                        info.id,
+                        info.tenant,
+                        principal.name().to_string(),
+                    )
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Erasure in progress",
+                            "This account is already being erased".into(),
+                        )
+                    })?;
+                let response = job.summary();
+
+                let server = self.clone();
+                let identifiers = [principal.name()]
+                    .into_iter()
+                    .chain(principal.email_addresses())
+                    .map(|identifier| identifier.to_lowercase())
+                    .collect::<AHashSet<_>>()
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                let requested_by = (access_token.name.clone(), access_token.primary_id());
+                tokio::spawn(async move {
+                    run_erasure(server, job, identifiers, requested_by).await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), &Method::GET) => {
+                // The principal no longer exists once erased
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.erasure_jobs.get(job_id))
+                    .filter(|job| {
+                        job.name.eq_ignore_ascii_case(name.as_ref())
+                            && access_token
+                                .tenant
+                                .is_none_or(|tenant| job.tenant_id == Some(tenant.id))
+                    })
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": job.summary(),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+async fn run_erasure(
+    server: Server,
+    job: Arc<ErasureJob>,
+    identifiers: Vec<String>,
+    requested_by: (String, u32),
+) {
+    let start_time = Instant::now();
+    let tombstone = format!("erased-{}", job.id);
+
+    // Events are recorded under the tombstone ID, as they are audited
+    match erase_principal(&server, &job, &identifiers, &tombstone, &requested_by.0).await {
+        Ok(report) => {
+            trc::event!(
+                Directory(trc::DirectoryEvent::PrincipalErased),
+                AccountName = requested_by.0,
+                AccountId = requested_by.1,
+                TenantId = job.tenant_id,
+                Id = tombstone,
+                Total = report.removed.values().sum::<u64>(),
+                Elapsed = start_time.elapsed(),
+            );
+
+            job.complete(report);
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                Directory(trc::DirectoryEvent::ErasureFailed),
+                AccountName = requested_by.0,
+                AccountId = requested_by.1,
+                TenantId = job.tenant_id,
+                Id = tombstone,
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn erase_principal(
+    server: &Server,
+    job: &ErasureJob,
+    identifiers: &[String],
+    tombstone: &str,
+    requested_by: &str,
+) -> trc::Result<ErasureReport> {
+    let account_id = job.account_id;
+    let mut removed = BTreeMap::new();
+    let mut retained = Vec::new();
+
+    // Take an inventory of the account before it is destroyed
+    let mut blob_hashes = Vec::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::Email,
+            EmailField::Metadata.into(),
+            |_, archive| {
+                blob_hashes.push(BlobHash::from(
+                    &archive.unarchive::<MessageMetadata>()?.blob_hash,
+                ));
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+    for collection in ACCOUNT_COLLECTIONS {
+        let mut count = 0;
+        server
+            .all_archives(account_id, *collection, Field::ARCHIVE.into(), |_, _| {
+                count += 1;
+                Ok(())
+            })
+            .await
+            .caused_by(trc::location!())?;
+        if count > 0 {
+            removed.insert(collection.as_str().to_string(), count);
+        }
+    }
+
+    // Messages that were also delivered to other accounts keep their content
+    let mut shared_messages = 0;
+    let mut removed_blobs = AHashSet::new();
+    for hash in &blob_hashes {
+        if server
+            .store()
+            .blob_is_shared(hash, account_id)
+            .await
+            .caused_by(trc::location!())?
+        {
+            shared_messages += 1;
+        } else {
+            removed_blobs.insert(hash);
+        }
+    }
+    removed.insert(
+        Collection::Email.as_str().to_string(),
+        blob_hashes.len() as u64,
+    );
+    removed.insert("blob".to_string(), removed_blobs.len() as u64);
+    if shared_messages > 0 {
+        retained.push(RetainedData {
+            typ: Collection::Email.as_str().to_string(),
+            count: shared_messages,
+            reason: "Message content is also held in other users' mailboxes".to_string(),
+        });
+    }
+
+    // Deleting the principal fails if a legal hold was placed in the meantime
+    let changed_principals = server
+        .store()
+        .delete_principal(QueryBy::Id(account_id))
+        .await
+        .caused_by(trc::location!())?;
+    removed.insert(Collection::Principal.as_str().to_string(), 1);
+    destroy_account_data(server, account_id, true).await?;
+
+    // Invalidating the cached credentials ends all sessions
+    server.invalidate_principal_caches(changed_principals).await;
+
+    if let Some(audit_log) = &server.core.storage.audit {
+        let scrubbed = audit_log
+            .store
+            .scrub_audit_events(identifiers, account_id, tombstone)
+            .await
+            .caused_by(trc::location!())?;
+        if scrubbed > 0 {
+            retained.push(RetainedData {
+                typ: "auditRecord".to_string(),
+                count: scrubbed,
+                reason: "Audit records are retained with identifying fields replaced by the tombstone ID"
+                    .to_string(),
+            });
+        }
+    }
+
+    Ok(ErasureReport {
+        id: job.id.to_string(),
+        principal: job.name.clone(),
+        tombstone: tombstone.to_string(),
+        requested_by: requested_by.to_string(),
+        erased_at: now(),
+        removed,
+        retained,
+        signature: String::new(),
+    }
+    .sign(&server.core.oauth.oauth_key))
+}
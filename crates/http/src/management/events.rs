@@ -0,0 +1,217 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    auth::AccessToken,
+    telemetry::tracers::audit::{AuditQuery, AuditRecord, AuditStore, dropped_audit_events},
+};
+use directory::{Permission, backend::internal::manage};
+use http_body_util::{StreamBody, combinators::BoxBody};
+use http_proto::*;
+use hyper::{
+    StatusCode,
+    body::{Bytes, Frame},
+};
+use mail_parser::DateTime;
+use serde_json::{Value, json};
+use std::future::Future;
+use store::Store;
+use tokio::sync::mpsc;
+use trc::EventType;
+use utils::url_params::UrlParams;
+
+use super::{Timestamp, organization::csv_field};
+
+// Exports are read from the store in pages of this size
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+pub trait AuditEventsApi: Sync + Send {
+    fn handle_audit_events(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_organization_audit(
+        &self,
+        req: &HttpRequest,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl AuditEventsApi for Server {
+    async fn handle_audit_events(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        // Validate the access token
+        access_token.assert_has_permission(Permission::AuditList)?;
+
+        let audit_log = self
+            .core
+            .storage
+            .audit
+            .as_ref()
+            .ok_or_else(|| manage::unsupported("Audit log is not enabled"))?;
+
+        let params = UrlParams::new(req.uri().query());
+        let query = AuditQuery {
+            // Tenant administrators can only view their own tenant's events
+            tenant_id: access_token
+                .tenant
+                .map(|t| t.id)
+                .or_else(|| params.parse::<u32>("tenant")),
+            typ: params.parse::<EventType>("type"),
+            ..audit_query(&params)
+        };
+
+        let page = audit_log.store.query_audit_events(query).await?;
+
+        Ok(JsonResponse::new(json!({
+            "data": {
+                "items": page.items,
+                "cursor": page.cursor.map(|cursor| cursor.to_string()),
+                "dropped": dropped_audit_events(),
+            },
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_organization_audit(
+        &self,
+        req: &HttpRequest,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        // Validate the access token
+        access_token.assert_has_permission(Permission::AuditList)?;
+
+        let audit_log = self
+            .core
+            .storage
+            .audit
+            .as_ref()
+            .ok_or_else(|| manage::unsupported("Audit log is not enabled"))?;
+
+        let params = UrlParams::new(req.uri().query());
+        let typ = params
+            .get("action")
+            .map(|action| {
+                action
+                    .parse::<EventType>()
+                    .map_err(|_| manage::error("Invalid action", action.to_string().into()))
+            })
+            .transpose()?;
+        let query = AuditQuery {
+            tenant_id: Some(tenant_id),
+            typ,
+            ..audit_query(&params)
+        };
+
+        if params.get("export") == Some("csv") {
+            // Records are read by a separate task as the store futures are not Sync
+            let (tx, mut rx) = mpsc::channel::<Bytes>(32);
+            tokio::spawn(export_audit_events(audit_log.store.clone(), query, tx));
+
+            Ok(HttpResponse::new(StatusCode::OK)
+                .with_content_type("text/csv; charset=utf-8")
+                .with_content_disposition("attachment; filename=\"audit.csv\"")
+                .with_no_store()
+                .with_stream_body(BoxBody::new(StreamBody::new(async_stream::stream! {
+                    while let Some(row) = rx.recv().await {
+                        yield Ok(Frame::data(row));
+                    }
+                }))))
+        } else {
+            let page = audit_log.store.query_audit_events(query).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "items": page.items,
+                    "cursor": page.cursor.map(|cursor| cursor.to_string()),
+                    "dropped": dropped_audit_events(),
+                },
+            }))
+            .into_http_response())
+        }
+    }
+}
+
+/// Filters shared by the audit endpoints. Actors are given by name or by
+/// account ID, erased actors are recorded under their tombstone ID.
+fn audit_query(params: &UrlParams<'_>) -> AuditQuery {
+    let actor = params.get("actor");
+    let actor_id = actor.and_then(|actor| actor.parse::<u32>().ok());
+
+    AuditQuery {
+        after: params.parse::<Timestamp>("after").map(|t| t.into_inner()),
+        before: params.parse::<Timestamp>("before").map(|t| t.into_inner()),
+        actor: actor
+            .filter(|_| actor_id.is_none())
+            .map(|actor| actor.to_string()),
+        actor_id,
+        target: params.get("target").map(|v| v.to_string()),
+        target_type: params.get("targetType").map(|v| v.to_string()),
+        cursor: params.parse::<u64>("cursor"),
+        limit: params.parse::<usize>("limit").unwrap_or(100).min(1000),
+        ..Default::default()
+    }
+}
+
+async fn export_audit_events(store: Store, mut query: AuditQuery, tx: mpsc::Sender<Bytes>) {
+    let header = "id,timestamp,type,actor,actorId,target,targetType,remoteIp,details\r\n";
+    if tx.send(Bytes::from(header)).await.is_err() {
+        return;
+    }
+
+    // The whole filtered history is exported, most recent events first
+    query.cursor = None;
+    query.limit = EXPORT_PAGE_SIZE;
+    loop {
+        let page = match store.query_audit_events(query.clone()).await {
+            Ok(page) => page,
+            Err(err) => {
+                // Headers are already sent, so the export is truncated
+                trc::error!(err.details("Failed to export audit events"));
+                return;
+            }
+        };
+
+        for record in &page.items {
+            if tx.send(Bytes::from(audit_csv_row(record))).await.is_err() {
+                return;
+            }
+        }
+
+        match page.cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => return,
+        }
+    }
+}
+
+fn audit_csv_row(record: &AuditRecord) -> String {
+    let row = [
+        Value::from(record.id.to_string()),
+        Value::from(DateTime::from_timestamp(record.timestamp as i64).to_rfc3339()),
+        Value::from(record.typ.as_str()),
+        record.actor.as_deref().into(),
+        record.actor_id.into(),
+        record.target.as_deref().into(),
+        record.target_type().into(),
+        record.remote_ip.as_deref().into(),
+        record.details.as_deref().into(),
+    ]
+    .iter()
+    .map(csv_field)
+    .collect::<Vec<_>>()
+    .join(",");
+
+    format!("{row}\r\n")
+}
@@ -0,0 +1,754 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::import::error_details;
+use common::{
+    Server,
+    auth::AccessToken,
+    storage::export::{
+        AccountExportArchive, AccountExportJob, AccountExportRejection, export_download_path,
+        verify_export_signature,
+    },
+};
+use directory::{
+    Permission, Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
+use email::{
+    cache::MessageCacheFetch,
+    identity::{ArchivedEmailAddress, Identity},
+    message::metadata::MessageMetadata,
+    sieve::{SieveScript, ingest::SieveScriptIngest},
+};
+use groupware::{
+    calendar::{Calendar, CalendarEvent},
+    contact::{AddressBook, ContactCard},
+};
+use http_body_util::{StreamBody, combinators::BoxBody};
+use http_proto::{request::decode_path_element, *};
+use hyper::{
+    Method, StatusCode,
+    body::{Bytes, Frame},
+};
+use rkyv::{option::ArchivedOption, vec::ArchivedVec};
+use serde_json::{Value, json};
+use std::{
+    fs::File,
+    future::Future,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
+use store::{
+    ValueKey,
+    ahash::{AHashMap, AHashSet},
+    write::{AlignedBytes, Archive, BlobLink, BlobOp, now},
+};
+use trc::AddContext;
+use types::{
+    blob::BlobClass,
+    blob_hash::BlobHash,
+    collection::Collection,
+    field::{EmailField, Field},
+};
+use utils::url_params::UrlParams;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+const MANIFEST_VERSION: u32 = 1;
+const ARCHIVE_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+pub trait AccountExportManager: Sync + Send {
+    fn handle_account_export(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_export_download(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl AccountExportManager for Server {
+    // Users may export their own account and tenant admins the accounts of
+    // their tenant, each of which can be disabled in the configuration.
+    async fn handle_account_export(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+        let info = self
+            .store()
+            .get_principal_info(name.as_ref())
+            .await?
+            .filter(|p| {
+                p.typ == Type::Individual
+                    && (p.id == access_token.primary_id()
+                        || p.has_tenant_access(access_token.tenant.map(|t| t.id)))
+            })
+            .ok_or_else(|| manage::not_found(name.to_string()))?;
+        if info.id == access_token.primary_id() {
+            if !self.core.jmap.export_allow_self {
+                return Err(trc::SecurityEvent::Unauthorized
+                    .into_err()
+                    .details("Account exports are disabled"));
+            }
+        } else if self.core.jmap.export_allow_admin {
+            access_token.assert_has_permission(Permission::PrincipalExport)?;
+        } else {
+            return Err(trc::SecurityEvent::Unauthorized
+                .into_err()
+                .details(Permission::PrincipalExport.name()));
+        }
+
+        match (path.get(3).copied(), req.method()) {
+            (None, &Method::POST) => {
+                let principal = self
+                    .store()
+                    .get_principal(info.id)
+                    .await?
+                    .ok_or_else(|| manage::not_found(name.to_string()))?;
+                let job = self
+                    .inner
+                    .data
+                    .export_jobs
+                    .start(
+                        self.inner.data.jmap_id_gen.generate(),
+                        info.id,
+                        info.tenant,
+                        principal.name().to_string(),
+                        self.core.jmap.export_max_concurrent,
+                    )
+                    .map_err(|err| match err {
+                        AccountExportRejection::InProgress => manage::error(
+                            "Export in progress",
+                            "This account is already being exported".into(),
+                        ),
+                        AccountExportRejection::TooManyExports => manage::error(
+                            "Too many exports",
+                            "The maximum number of concurrent exports has been reached".into(),
+                        ),
+                    })?;
+                let response = job.summary();
+
+                let server = self.clone();
+                let account = json!({
+                    "name": principal.name(),
+                    "description": principal.description(),
+                    "emails": principal.email_addresses().collect::<Vec<_>>(),
+                    "locale": principal.locale(),
+                    "quota": principal.quota(),
+                });
+                let requested_by = (access_token.name.clone(), access_token.primary_id());
+                tokio::spawn(async move {
+                    run_export(server, job, account, requested_by).await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), &Method::GET) => {
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.export_jobs.get(job_id))
+                    .filter(|job| job.account_id == info.id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": job.summary(),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+
+    // Download links are signed, so they work on any node until they expire
+    async fn handle_export_download(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+    ) -> trc::Result<HttpResponse> {
+        let params = UrlParams::new(req.uri().query());
+        let (Some(account_id), Some(hash), Some(expires), Some(signature)) = (
+            path.first().and_then(|id| id.parse::<u32>().ok()),
+            path.get(1).and_then(|hash| BlobHash::from_hex(hash)),
+            params.parse::<u64>("expires"),
+            params.get("signature"),
+        ) else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+        if expires <= now()
+            || !verify_export_signature(
+                &self.core.oauth.oauth_key,
+                account_id,
+                &hash,
+                expires,
+                signature,
+            )
+            || !self
+                .store()
+                .blob_has_access(
+                    &hash,
+                    BlobClass::Reserved {
+                        account_id,
+                        expires,
+                    },
+                )
+                .await?
+        {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        }
+
+        // The link points to the index of the archive chunks, which are
+        // sent one at a time
+        let index = self
+            .get_blob(&hash, 0..usize::MAX)
+            .await?
+            .and_then(|index| serde_json::from_slice::<ArchiveIndex>(&index).ok())
+            .ok_or_else(|| trc::ResourceEvent::NotFound.into_err())?;
+        let chunks = index
+            .chunks
+            .iter()
+            .map(|hash| BlobHash::from_hex(hash))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| trc::ResourceEvent::NotFound.into_err())?;
+
+        // A missing chunk ends the body short of its length, so that the
+        // connection is aborted instead of the download looking complete
+        let server = self.clone();
+        Ok(HttpResponse::new(StatusCode::OK)
+            .with_content_type("application/zip")
+            .with_content_disposition(format!("attachment; filename=\"export-{account_id}.zip\""))
+            .with_cache_control("private, immutable, max-age=31536000")
+            .with_content_length(index.size as usize)
+            .with_stream_body(BoxBody::new(StreamBody::new(async_stream::stream! {
+                for hash in chunks {
+                    match server.get_blob(&hash, 0..usize::MAX).await {
+                        Ok(Some(chunk)) => {
+                            yield Ok(Frame::data(Bytes::from(chunk)));
+                        }
+                        Ok(None) => {
+                            break;
+                        }
+                        Err(err) => {
+                            trc::error!(err.details("Failed to read export archive"));
+                            break;
+                        }
+                    }
+                }
+            }))))
+    }
+}
+
+async fn run_export(
+    server: Server,
+    job: Arc<AccountExportJob>,
+    account: Value,
+    requested_by: (String, u32),
+) {
+    let start_time = Instant::now();
+
+    match export_account(&server, &job, account).await {
+        Ok(archive) => {
+            trc::event!(
+                TaskQueue(trc::TaskQueueEvent::ExportCompleted),
+                AccountName = requested_by.0,
+                AccountId = requested_by.1,
+                TenantId = job.tenant_id,
+                Id = job.name.clone(),
+                Size = archive.size,
+                Expires = trc::Value::Timestamp(archive.expires),
+                Elapsed = start_time.elapsed(),
+            );
+
+            job.complete(archive);
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                TaskQueue(trc::TaskQueueEvent::ExportFailed),
+                AccountName = requested_by.0,
+                AccountId = requested_by.1,
+                TenantId = job.tenant_id,
+                Id = job.name.clone(),
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn export_account(
+    server: &Server,
+    job: &AccountExportJob,
+    account: Value,
+) -> trc::Result<AccountExportArchive> {
+    let account_id = job.account_id;
+    let mut archive = ExportArchive::new(job.id, server.core.jmap.export_max_size)?;
+
+    // Messages are written once for every mailbox they are in
+    let cache = server
+        .get_cached_messages(account_id)
+        .await
+        .caused_by(trc::location!())?;
+    let mut mailbox_paths = AHashMap::new();
+    let mut mailboxes = Vec::with_capacity(cache.mailboxes.items.len());
+    for mailbox in cache.mailboxes.items.iter() {
+        let path = mailbox
+            .path
+            .split('/')
+            .map(safe_file_name)
+            .collect::<Vec<_>>()
+            .join("/");
+        mailboxes.push(json!({
+            "path": mailbox.path,
+            "role": mailbox.role.as_str(),
+            "subscribed": mailbox.subscribers.contains(&account_id),
+            "folder": format!("mail/{path}"),
+        }));
+        mailbox_paths.insert(mailbox.document_id, path);
+    }
+    let mut messages = 0;
+    for item in cache.emails.items.iter() {
+        let Some(metadata) = server
+            .store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::property(
+                account_id,
+                Collection::Email,
+                item.document_id,
+                EmailField::Metadata,
+            ))
+            .await
+            .caused_by(trc::location!())?
+        else {
+            continue;
+        };
+        let blob_hash = BlobHash::from(
+            &metadata
+                .unarchive::<MessageMetadata>()
+                .caused_by(trc::location!())?
+                .blob_hash,
+        );
+        let Some(message) = server
+            .get_blob(&blob_hash, 0..usize::MAX)
+            .await
+            .caused_by(trc::location!())?
+        else {
+            continue;
+        };
+        for mailbox in item.mailboxes.iter() {
+            if let Some(path) = mailbox_paths.get(&mailbox.mailbox_id) {
+                archive.add(format!("mail/{path}/{}.eml", item.document_id), &message)?;
+            }
+        }
+        messages += 1;
+    }
+
+    // Contacts and events are written to a folder per address book or calendar
+    let mut address_books = AHashMap::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::AddressBook,
+            Field::ARCHIVE.into(),
+            |document_id, book| {
+                address_books.insert(
+                    document_id,
+                    book.unarchive::<AddressBook>()?.name.to_string(),
+                );
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+    let address_books = unique_folders(address_books);
+    let mut cards = Vec::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::ContactCard,
+            Field::ARCHIVE.into(),
+            |_, card| {
+                let card = card.unarchive::<ContactCard>()?;
+                if let Some(folder) = card
+                    .names
+                    .iter()
+                    .find_map(|name| address_books.get(&name.parent_id.to_native()))
+                {
+                    let name = card.names.first().map_or("", |name| name.name.as_str());
+                    cards.push((
+                        format!("{folder}/{}", safe_file_name(name)),
+                        card.card.to_string(),
+                    ));
+                }
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+    let contacts = cards.len() as u64;
+    for (path, card) in cards {
+        archive.add(format!("contacts/{path}"), card.as_bytes())?;
+    }
+
+    let mut calendars = AHashMap::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::Calendar,
+            Field::ARCHIVE.into(),
+            |document_id, calendar| {
+                calendars.insert(
+                    document_id,
+                    calendar.unarchive::<Calendar>()?.name.to_string(),
+                );
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+    let calendars = unique_folders(calendars);
+    let mut events = Vec::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::CalendarEvent,
+            Field::ARCHIVE.into(),
+            |_, event| {
+                let event = event.unarchive::<CalendarEvent>()?;
+                if let Some(folder) = event
+                    .names
+                    .iter()
+                    .find_map(|name| calendars.get(&name.parent_id.to_native()))
+                {
+                    let name = event.names.first().map_or("", |name| name.name.as_str());
+                    events.push((
+                        format!("{folder}/{}", safe_file_name(name)),
+                        event.data.event.to_string(),
+                    ));
+                }
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+    let event_count = events.len() as u64;
+    for (path, event) in events {
+        archive.add(format!("calendars/{path}"), event.as_bytes())?;
+    }
+
+    // Sieve scripts
+    let active_script_id = server
+        .sieve_script_get_active_id(account_id)
+        .await
+        .caused_by(trc::location!())?;
+    let mut scripts = Vec::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::SieveScript,
+            Field::ARCHIVE.into(),
+            |document_id, script| {
+                let script = script.unarchive::<SieveScript>()?;
+                scripts.push((
+                    document_id,
+                    script.name.to_string(),
+                    BlobHash::from(&script.blob_hash),
+                ));
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+    let mut sieve_scripts = Vec::with_capacity(scripts.len());
+    for (document_id, name, blob_hash) in scripts {
+        let Some(script) = server
+            .get_blob(&blob_hash, 0..usize::MAX)
+            .await
+            .caused_by(trc::location!())?
+        else {
+            continue;
+        };
+        let file = format!("sieve/{}-{}.sieve", document_id, safe_file_name(&name));
+        archive.add(file.clone(), &script)?;
+        sieve_scripts.push(json!({
+            "name": name,
+            "active": active_script_id == Some(document_id),
+            "file": file,
+        }));
+    }
+
+    let mut identities = Vec::new();
+    server
+        .all_archives(
+            account_id,
+            Collection::Identity,
+            Field::ARCHIVE.into(),
+            |_, identity| {
+                let identity = identity.unarchive::<Identity>()?;
+                identities.push(json!({
+                    "name": identity.name.as_str(),
+                    "email": identity.email.as_str(),
+                    "replyTo": archived_addresses(&identity.reply_to),
+                    "bcc": archived_addresses(&identity.bcc),
+                    "textSignature": identity.text_signature.as_str(),
+                    "htmlSignature": identity.html_signature.as_str(),
+                }));
+                Ok(())
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+    let manifest = json!({
+        "version": MANIFEST_VERSION,
+        "exportedAt": now(),
+        "account": account,
+        "mailboxes": mailboxes,
+        "identities": identities,
+        "sieveScripts": sieve_scripts,
+        "addressBooks": address_books.values().map(|folder| format!("contacts/{folder}")).collect::<Vec<_>>(),
+        "calendars": calendars.values().map(|folder| format!("calendars/{folder}")).collect::<Vec<_>>(),
+    });
+    archive.add(
+        "manifest.json".to_string(),
+        &serde_json::to_vec_pretty(&manifest).unwrap_or_default(),
+    )?;
+    let (_temp_file, mut archive_file, size) = archive.finish()?;
+
+    // The archive is uploaded in chunks so that it is never held in memory
+    // in full, they are removed by the blob purge once the link expires
+    let mut index = ArchiveIndex {
+        size,
+        chunks: Vec::with_capacity((size as usize / ARCHIVE_CHUNK_SIZE) + 1),
+    };
+    let mut chunk = Vec::with_capacity(ARCHIVE_CHUNK_SIZE);
+    loop {
+        chunk.clear();
+        (&mut archive_file)
+            .take(ARCHIVE_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)
+            .map_err(archive_error)?;
+        if chunk.is_empty() {
+            break;
+        }
+        let (hash, _) = server
+            .put_temporary_blob(account_id, &chunk, server.core.jmap.export_expiry)
+            .await
+            .caused_by(trc::location!())?;
+        index.chunks.push(hash.to_hex());
+    }
+    let (hash, link) = server
+        .put_temporary_blob(
+            account_id,
+            &serde_json::to_vec(&index).unwrap_or_default(),
+            server.core.jmap.export_expiry,
+        )
+        .await
+        .caused_by(trc::location!())?;
+    let expires = match link {
+        BlobOp::Link {
+            to: BlobLink::Temporary { until },
+            ..
+        } => until,
+        _ => now() + server.core.jmap.export_expiry,
+    };
+
+    Ok(AccountExportArchive {
+        size,
+        messages,
+        contacts,
+        events: event_count,
+        sieve_scripts: sieve_scripts.len() as u64,
+        download_url: export_download_path(
+            &server.core.oauth.oauth_key,
+            account_id,
+            &hash,
+            expires,
+        ),
+        expires,
+    })
+}
+
+/// Chunks of an export archive, stored as the blob the download link
+/// points to.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ArchiveIndex {
+    size: u64,
+    chunks: Vec<String>,
+}
+
+/// Archive written to a temporary file while the account is exported.
+struct ExportArchive {
+    zip: ZipWriter<BufWriter<File>>,
+    temp_file: TempFile,
+    files: AHashSet<String>,
+    size: u64,
+    max_size: u64,
+}
+
+/// Removes the temporary file once the export is done.
+struct TempFile(PathBuf);
+
+impl ExportArchive {
+    fn new(job_id: u64, max_size: u64) -> trc::Result<Self> {
+        let temp_file = TempFile(std::env::temp_dir().join(format!("export-{job_id}.zip")));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_file.0)
+            .map_err(archive_error)?;
+
+        Ok(ExportArchive {
+            zip: ZipWriter::new(BufWriter::new(file)),
+            temp_file,
+            files: AHashSet::new(),
+            size: 0,
+            max_size,
+        })
+    }
+
+    fn add(&mut self, mut path: String, contents: &[u8]) -> trc::Result<()> {
+        self.size += contents.len() as u64;
+        if self.size > self.max_size {
+            return Err(too_large(self.max_size));
+        }
+
+        // Files with the same name get a numeric suffix
+        if self.files.contains(&path) {
+            let (name, extension) = path
+                .rsplit_once('.')
+                .filter(|(name, _)| !name.ends_with('/'))
+                .map_or((path.as_str(), ""), |(name, ext)| (name, ext));
+            path = (1..)
+                .map(|n| {
+                    if extension.is_empty() {
+                        format!("{name}-{n}")
+                    } else {
+                        format!("{name}-{n}.{extension}")
+                    }
+                })
+                .find(|path| !self.files.contains(path))
+                .unwrap();
+        }
+
+        self.zip
+            .start_file(
+                path.as_str(),
+                SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .large_file(contents.len() as u64 >= u32::MAX as u64),
+            )
+            .and_then(|_| self.zip.write_all(contents).map_err(Into::into))
+            .map_err(archive_error)?;
+        self.files.insert(path);
+
+        Ok(())
+    }
+
+    /// Completes the archive, returning it positioned at its start along
+    /// with its size.
+    fn finish(self) -> trc::Result<(TempFile, File, u64)> {
+        let mut file = self
+            .zip
+            .finish()
+            .map_err(archive_error)?
+            .into_inner()
+            .map_err(|err| archive_error(err.into_error()))?;
+        let size = file.seek(SeekFrom::End(0)).map_err(archive_error)?;
+        if size > self.max_size {
+            return Err(too_large(self.max_size));
+        }
+        file.seek(SeekFrom::Start(0)).map_err(archive_error)?;
+
+        Ok((self.temp_file, file, size))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn too_large(max_size: u64) -> trc::Error {
+    manage::error(
+        "Export too large",
+        format!("The account data exceeds the maximum export size of {max_size} bytes").into(),
+    )
+}
+
+fn archive_error(err: impl std::fmt::Display) -> trc::Error {
+    trc::ResourceEvent::Error
+        .caused_by(trc::location!())
+        .reason(err)
+        .details("Failed to write export archive")
+}
+
+fn archived_addresses(
+    addresses: &ArchivedOption<ArchivedVec<ArchivedEmailAddress>>,
+) -> Option<Vec<Value>> {
+    addresses.as_ref().map(|addresses| {
+        addresses
+            .iter()
+            .map(|address| {
+                json!({
+                    "name": address.name.as_ref().map(|name| name.as_str()),
+                    "email": address.email.as_str(),
+                })
+            })
+            .collect()
+    })
+}
+
+// Assigns a distinct folder name to each address book or calendar
+fn unique_folders(names: AHashMap<u32, String>) -> AHashMap<u32, String> {
+    let mut names = names.into_iter().collect::<Vec<_>>();
+    names.sort_unstable_by_key(|(document_id, _)| *document_id);
+    let mut used = AHashSet::new();
+    names
+        .into_iter()
+        .map(|(document_id, name)| {
+            let mut folder = safe_file_name(&name);
+            if !used.insert(folder.clone()) {
+                folder = format!("{folder}-{document_id}");
+                used.insert(folder.clone());
+            }
+            (document_id, folder)
+        })
+        .collect()
+}
+
+fn safe_file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|ch| {
+            if matches!(ch, '/' | '\\' | ':') || ch.is_control() {
+                '_'
+            } else {
+                ch
+            }
+        })
+        .collect::<String>();
+    if name.is_empty() || name == "." || name == ".." {
+        "_".to_string()
+    } else {
+        name
+    }
+}
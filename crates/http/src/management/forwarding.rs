@@ -0,0 +1,132 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, auth::AccessToken, config::scripts::ForwardingRule};
+use directory::{
+    Permission, Type,
+    backend::internal::manage::{self, ManageDirectory, not_found},
+};
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::json;
+use std::future::Future;
+use store::write::now;
+
+pub trait AccountForwardingManager: Sync + Send {
+    fn handle_account_forwarding(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl AccountForwardingManager for Server {
+    // Forwarding rules are managed by administrators on behalf of an account
+    // and are not visible to its owner, who can't remove them.
+    async fn handle_account_forwarding(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+        let info = self
+            .store()
+            .get_principal_info(name.as_ref())
+            .await?
+            .filter(|p| {
+                p.has_tenant_access(access_token.tenant.map(|t| t.id)) && p.typ == Type::Individual
+            })
+            .ok_or_else(|| not_found(name.to_string()))?;
+
+        match *req.method() {
+            Method::GET => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": self.forwarding_rule(info.id),
+                }))
+                .into_http_response())
+            }
+            Method::PUT => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let mut rule =
+                    serde_json::from_slice::<ForwardingRule>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+                rule.validate(now(), self.core.sieve.untrusted_max_redirects)
+                    .map_err(|err| manage::error(err, None::<u64>))?;
+
+                // Data protection policies may restrict forwarding to the tenant's domains
+                if let Some(tenant_id) = info.tenant
+                    && !self
+                        .tenant_forwarding_policy(tenant_id)
+                        .await?
+                        .allow_external
+                {
+                    for address in &rule.addresses {
+                        if self.is_external_address(tenant_id, address).await? {
+                            return Err(manage::error(
+                                "External forwarding is not allowed",
+                                format!("The organization does not allow forwarding to {address}")
+                                    .into(),
+                            ));
+                        }
+                    }
+                }
+
+                self.update_forwarding_rule(info.id, rule.clone().into())
+                    .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::ForwardingUpdated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = name.to_string(),
+                    To = rule
+                        .addresses
+                        .iter()
+                        .map(|address| trc::Value::String(address.as_str().into()))
+                        .collect::<Vec<_>>(),
+                    Expires = rule.expires.map(trc::Value::Timestamp),
+                );
+
+                Ok(JsonResponse::new(json!({
+                    "data": rule,
+                }))
+                .into_http_response())
+            }
+            Method::DELETE => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                if self.forwarding_rule(info.id).is_some() {
+                    self.update_forwarding_rule(info.id, None).await?;
+
+                    trc::event!(
+                        Directory(trc::DirectoryEvent::ForwardingRemoved),
+                        AccountName = access_token.name.clone(),
+                        AccountId = access_token.primary_id(),
+                        TenantId = access_token.tenant.map(|t| t.id),
+                        Id = name.to_string(),
+                    );
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
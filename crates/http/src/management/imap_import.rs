@@ -0,0 +1,685 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    import::error_details,
+    message_import::{ImportMessage, MessageImporter},
+};
+use common::{
+    Server,
+    auth::AccessToken,
+    storage::import::{ImapImportJob, ImapImportSource, ImapImportState, MessageImportJob},
+};
+use directory::{
+    Permission, Type,
+    backend::{
+        imap::{
+            ImapClient, ImapError,
+            mailbox::{ImapFolder, ImapMessage},
+        },
+        internal::manage::{self, ManageDirectory, not_found},
+    },
+};
+use email::{
+    cache::{MessageCacheFetch, mailbox::MailboxCacheAccess},
+    mailbox::{INBOX_ID, manage::MailboxFnc},
+};
+use http_proto::*;
+use hyper::Method;
+use imap_proto::utf7::utf7_maybe_decode;
+use serde_json::json;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use store::{
+    Serialize, ValueKey,
+    ahash::AHashMap,
+    write::{AlignedBytes, Archive, Archiver, BatchBuilder},
+};
+use tokio::{io::AsyncRead, io::AsyncWrite, sync::Semaphore};
+use trc::AddContext;
+use types::{
+    collection::Collection, field::PrincipalField, keyword::Keyword, special_use::SpecialUse,
+};
+use utils::is_public_ip;
+
+const MAX_ACCOUNTS: usize = 1000;
+const MAX_CONCURRENCY: usize = 16;
+const DEFAULT_CONCURRENCY: usize = 4;
+const IMAP_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// Credentials are only kept in memory for the duration of the job,
+// so these types intentionally do not implement `Debug`.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ImapImportRequest {
+    pub host: String,
+    /// Defaults to 993 for implicit TLS and 143 for STARTTLS.
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: ImapImportTls,
+    #[serde(default)]
+    pub allow_invalid_certs: bool,
+    #[serde(default)]
+    pub skip_duplicates: bool,
+    /// Number of accounts imported at the same time.
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    pub accounts: Vec<ImapImportAccount>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImapImportTls {
+    #[default]
+    Implicit,
+    StartTls,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ImapImportAccount {
+    pub source_user: String,
+    pub source_password: String,
+    pub target_principal: String,
+}
+
+struct ImapSource {
+    host: String,
+    port: u16,
+    tls_implicit: bool,
+    allow_invalid_certs: bool,
+    allow_internal: bool,
+    skip_duplicates: bool,
+}
+
+impl ImapImportRequest {
+    fn source_port(&self) -> u16 {
+        self.port.unwrap_or(match self.tls {
+            ImapImportTls::Implicit => 993,
+            ImapImportTls::StartTls => 143,
+        })
+    }
+}
+
+pub trait ImapImportManager: Sync + Send {
+    fn handle_imap_import(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl ImapImportManager for Server {
+    async fn handle_imap_import(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (path.get(4).copied(), path.get(5).copied(), req.method()) {
+            (None, None, &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let request = serde_json::from_slice::<ImapImportRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                if request.host.is_empty() {
+                    return Err(manage::err_missing("host"));
+                }
+                if request.accounts.is_empty() {
+                    return Err(manage::err_missing("accounts"));
+                }
+                if request.accounts.len() > MAX_ACCOUNTS {
+                    return Err(manage::error(
+                        "Too many accounts",
+                        format!("At most {MAX_ACCOUNTS} accounts can be imported at once").into(),
+                    ));
+                }
+
+                // Tenants may only import from publicly routable hosts
+                let allow_internal = access_token.tenant.is_none();
+                resolve_source(&request.host, request.source_port(), allow_internal).await?;
+
+                // Resolve the target accounts before starting
+                let mut account_ids = Vec::with_capacity(request.accounts.len());
+                for account in &request.accounts {
+                    if account.source_user.is_empty() {
+                        return Err(manage::err_missing("sourceUser"));
+                    }
+                    account_ids.push(
+                        self.core
+                            .storage
+                            .data
+                            .get_principal_info(&account.target_principal)
+                            .await?
+                            .filter(|p| {
+                                p.has_tenant_access(Some(tenant_id))
+                                    && matches!(p.typ, Type::Individual | Type::Group)
+                            })
+                            .map(|p| p.id)
+                            .ok_or_else(|| not_found(account.target_principal.clone()))?,
+                    );
+                }
+
+                let job = self
+                    .inner
+                    .data
+                    .imap_import_jobs
+                    .start(
+                        self.inner.data.jmap_id_gen.generate(),
+                        tenant_id,
+                        request.host.clone(),
+                        request.accounts.iter().map(|account| {
+                            (
+                                account.source_user.clone(),
+                                account.target_principal.clone(),
+                            )
+                        }),
+                    )
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Import in progress",
+                            "An import is already running for this organization".into(),
+                        )
+                    })?;
+                let response = job.summary();
+
+                let server = self.clone();
+                tokio::spawn(async move {
+                    run_imap_import(server, job, request, account_ids, allow_internal).await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), report, &Method::GET) if matches!(report, None | Some("report")) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.imap_import_jobs.get(job_id))
+                    .filter(|job| job.tenant_id == tenant_id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                if report.is_none() {
+                    Ok(JsonResponse::new(json!({
+                        "data": job.summary(),
+                    }))
+                    .into_http_response())
+                } else {
+                    Ok(DownloadResponse {
+                        filename: format!("import-{job_id}.json"),
+                        content_type: "application/json".to_string(),
+                        blob: serde_json::to_vec(&json!({
+                            "summary": job.summary(),
+                            "accounts": job.results().into_iter().map(|(target, messages)| json!({
+                                "targetPrincipal": target,
+                                "messages": messages,
+                            })).collect::<Vec<_>>(),
+                        }))
+                        .unwrap_or_default(),
+                    }
+                    .into_http_response())
+                }
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+async fn run_imap_import(
+    server: Server,
+    job: Arc<ImapImportJob>,
+    request: ImapImportRequest,
+    account_ids: Vec<u32>,
+    allow_internal: bool,
+) {
+    let source = Arc::new(ImapSource {
+        port: request.source_port(),
+        host: request.host,
+        tls_implicit: request.tls == ImapImportTls::Implicit,
+        allow_invalid_certs: request.allow_invalid_certs,
+        allow_internal,
+        skip_duplicates: request.skip_duplicates,
+    });
+    let semaphore = Arc::new(Semaphore::new(
+        request
+            .concurrency
+            .unwrap_or(DEFAULT_CONCURRENCY)
+            .clamp(1, MAX_CONCURRENCY),
+    ));
+
+    let mut tasks = Vec::with_capacity(account_ids.len());
+    for (index, (account, account_id)) in request.accounts.into_iter().zip(account_ids).enumerate()
+    {
+        let server = server.clone();
+        let job = job.clone();
+        let source = source.clone();
+        let semaphore = semaphore.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Ok(_permit) = semaphore.acquire_owned().await {
+                import_account(&server, &job, index, &source, account, account_id).await;
+            }
+        }));
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    job.complete();
+}
+
+async fn import_account(
+    server: &Server,
+    job: &ImapImportJob,
+    index: usize,
+    source: &ImapSource,
+    account: ImapImportAccount,
+    account_id: u32,
+) {
+    let Some(account_job) = server
+        .inner
+        .data
+        .message_import_jobs
+        .start(server.inner.data.jmap_id_gen.generate(), account_id)
+    else {
+        job.fail_account(
+            index,
+            "An import is already running for this account".to_string(),
+        );
+        return;
+    };
+    job.set_account_job(index, account_job.clone());
+
+    let start_time = Instant::now();
+    match import_imap_account(server, &account_job, source, &account, account_id).await {
+        Ok(()) => {
+            account_job.complete();
+
+            let summary = account_job.summary();
+            trc::event!(
+                MessageIngest(trc::MessageIngestEvent::ImportCompleted),
+                AccountName = account.target_principal,
+                AccountId = account_id,
+                Id = summary.id,
+                Total = summary.imported,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+        Err(err) => {
+            account_job.fail(error_details(&err));
+
+            trc::event!(
+                MessageIngest(trc::MessageIngestEvent::ImportFailed),
+                AccountName = account.target_principal,
+                AccountId = account_id,
+                Id = account_job.id.to_string(),
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn import_imap_account(
+    server: &Server,
+    job: &MessageImportJob,
+    source: &ImapSource,
+    account: &ImapImportAccount,
+    account_id: u32,
+) -> trc::Result<()> {
+    let access_token = server.get_access_token(account_id).await?;
+    // Resolved again when connecting, as the records might have changed
+    // since the request was validated
+    let addrs = resolve_source(&source.host, source.port, source.allow_internal).await?;
+    let mut client = ImapClient::connect(
+        addrs.as_slice(),
+        IMAP_TIMEOUT,
+        if source.allow_invalid_certs {
+            &server.inner.data.smtp_connectors.dummy_verify
+        } else {
+            &server.inner.data.smtp_connectors.pki_verify
+        },
+        &source.host,
+        source.tls_implicit,
+    )
+    .await
+    .map_err(imap_error)?;
+    client
+        .login(&account.source_user, &account.source_password)
+        .await
+        .map_err(imap_error)?;
+    let folders = client.list_folders().await.map_err(imap_error)?;
+
+    let mut importer =
+        MessageImporter::new(server, job, &access_token, source.skip_duplicates).await?;
+    let mut mailboxes = ImapMailboxes {
+        account_id,
+        ids: AHashMap::new(),
+    };
+    let (mut state, state_archive) = imap_import_state(server, account_id).await?;
+    let mut state_archive = state_archive;
+
+    for folder in folders {
+        if folder.attributes.iter().any(|attribute| {
+            attribute.eq_ignore_ascii_case("\\Noselect")
+                || attribute.eq_ignore_ascii_case("\\NonExistent")
+        }) {
+            continue;
+        }
+
+        let result = import_folder(
+            server,
+            &mut client,
+            &mut importer,
+            &mut mailboxes,
+            state.source_mut(&source.host, &account.source_user),
+            &folder,
+        )
+        .await;
+
+        // Progress is stored after each folder, so that a failed import
+        // can be resumed
+        state_archive = store_imap_import_state(server, account_id, &state, state_archive).await?;
+        result?;
+    }
+
+    client.logout().await.ok();
+
+    Ok(())
+}
+
+/// Resolves the source host, rejecting hosts with any address that is not
+/// publicly routable unless `allow_internal` is set.
+async fn resolve_source(
+    host: &str,
+    port: u16,
+    allow_internal: bool,
+) -> trc::Result<Vec<SocketAddr>> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map(|addrs| addrs.collect::<Vec<_>>())
+        .unwrap_or_default();
+    if addrs.is_empty() {
+        return Err(manage::error(
+            "Invalid host",
+            format!("Failed to resolve {host:?}").into(),
+        ));
+    }
+    if !allow_internal && let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        return Err(manage::error(
+            "Host not allowed",
+            format!("{host:?} resolves to the non-public address {}", addr.ip()).into(),
+        ));
+    }
+
+    Ok(addrs)
+}
+
+async fn import_folder<T: AsyncRead + AsyncWrite + Unpin>(
+    server: &Server,
+    client: &mut ImapClient<T>,
+    importer: &mut MessageImporter<'_>,
+    mailboxes: &mut ImapMailboxes,
+    source: &mut ImapImportSource,
+    folder: &ImapFolder,
+) -> trc::Result<()> {
+    let uid_validity = client.examine(&folder.name).await.map_err(imap_error)?;
+    let state = source.folder_mut(&folder.name);
+    if state.uid_validity != uid_validity {
+        // UIDs are no longer valid, the folder has to be imported again
+        state.uid_validity = uid_validity;
+        state.last_uid = 0;
+    }
+
+    let uids = client
+        .uid_search(state.last_uid + 1)
+        .await
+        .map_err(imap_error)?;
+    if uids.is_empty() {
+        return Ok(());
+    }
+
+    let (path, mailbox_id) = mailboxes.resolve(server, folder).await?;
+    for uid in uids {
+        match client.uid_fetch(uid).await {
+            Ok(Some(ImapMessage {
+                flags,
+                internal_date,
+                contents,
+                ..
+            })) => {
+                importer
+                    .import(
+                        ImportMessage {
+                            folder: Some(path.clone()),
+                            contents: contents.into(),
+                            received_at: internal_date,
+                            keywords: flags
+                                .iter()
+                                .map(|flag| Keyword::parse(flag))
+                                .filter(|keyword| *keyword != Keyword::Recent)
+                                .collect(),
+                        },
+                        mailbox_id,
+                    )
+                    .await?;
+            }
+            Ok(None) => {
+                // Expunged since the search
+            }
+            Err(ImapError::CommandFailed(reason)) => {
+                importer.fail(
+                    Some(path.clone()),
+                    format!("Failed to fetch message with UID {uid}: {reason}"),
+                );
+            }
+            Err(err) => {
+                return Err(imap_error(err));
+            }
+        }
+        state.last_uid = uid;
+    }
+
+    Ok(())
+}
+
+struct ImapMailboxes {
+    account_id: u32,
+    ids: AHashMap<String, Option<u32>>,
+}
+
+impl ImapMailboxes {
+    /// Returns the path of the folder and the mailbox it is imported into.
+    /// Special-use folders are imported into the mailbox with the same role.
+    async fn resolve(
+        &mut self,
+        server: &Server,
+        folder: &ImapFolder,
+    ) -> trc::Result<(String, Option<u32>)> {
+        let path = folder_path(folder);
+        if let Some(mailbox_id) = self.ids.get(&path) {
+            return Ok((path, *mailbox_id));
+        }
+
+        let mailbox_id = if path.eq_ignore_ascii_case("INBOX") {
+            Some(INBOX_ID)
+        } else {
+            let cache = server
+                .get_cached_messages(self.account_id)
+                .await
+                .caused_by(trc::location!())?;
+            if let Some(mailbox) = folder
+                .attributes
+                .iter()
+                .filter_map(|attribute| attribute.strip_prefix('\\'))
+                .filter_map(SpecialUse::parse)
+                .find(|role| {
+                    matches!(
+                        role,
+                        SpecialUse::Sent
+                            | SpecialUse::Drafts
+                            | SpecialUse::Trash
+                            | SpecialUse::Junk
+                            | SpecialUse::Archive
+                    )
+                })
+                .and_then(|role| cache.mailbox_by_role(&role))
+            {
+                Some(mailbox.document_id)
+            } else {
+                server.mailbox_create_path(self.account_id, &path).await?
+            }
+        };
+        self.ids.insert(path.clone(), mailbox_id);
+
+        Ok((path, mailbox_id))
+    }
+}
+
+/// Converts an IMAP folder name to a mailbox path, moving folders nested
+/// under INBOX (as done by some servers) to the top level.
+fn folder_path(folder: &ImapFolder) -> String {
+    let name = utf7_maybe_decode(folder.name.clone(), false);
+    let Some(delimiter) = folder.delimiter else {
+        return name;
+    };
+    let name = name
+        .get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("INBOX"))
+        .and_then(|_| name[5..].strip_prefix(delimiter))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(&name);
+
+    name.split(delimiter)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+async fn imap_import_state(
+    server: &Server,
+    account_id: u32,
+) -> trc::Result<(ImapImportState, Option<Archive<AlignedBytes>>)> {
+    let archive = server
+        .store()
+        .get_value::<Archive<AlignedBytes>>(ValueKey::property(
+            account_id,
+            Collection::Principal,
+            0,
+            PrincipalField::ImapImport,
+        ))
+        .await
+        .caused_by(trc::location!())?;
+    let state = if let Some(archive) = &archive {
+        archive
+            .deserialize::<ImapImportState>()
+            .caused_by(trc::location!())?
+    } else {
+        ImapImportState::default()
+    };
+
+    Ok((state, archive))
+}
+
+async fn store_imap_import_state(
+    server: &Server,
+    account_id: u32,
+    state: &ImapImportState,
+    current: Option<Archive<AlignedBytes>>,
+) -> trc::Result<Option<Archive<AlignedBytes>>> {
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(account_id)
+        .with_collection(Collection::Principal)
+        .with_document(0);
+    if let Some(current) = current {
+        batch.assert_value(PrincipalField::ImapImport, current);
+    }
+    batch.set(
+        PrincipalField::ImapImport,
+        Archiver::new(state.clone())
+            .serialize()
+            .caused_by(trc::location!())?,
+    );
+    server
+        .store()
+        .write(batch.build_all())
+        .await
+        .caused_by(trc::location!())?;
+
+    imap_import_state(server, account_id)
+        .await
+        .map(|(_, archive)| archive)
+}
+
+fn imap_error(err: ImapError) -> trc::Error {
+    manage::error(format!("IMAP server error: {err}"), None::<u64>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imap_folder_paths() {
+        for (name, delimiter, expected) in [
+            ("INBOX", Some('/'), "INBOX"),
+            ("Sent Items", Some('/'), "Sent Items"),
+            ("Work/Projects", Some('/'), "Work/Projects"),
+            ("INBOX.Archive.2024", Some('.'), "Archive/2024"),
+            ("inbox/Lists", Some('/'), "Lists"),
+            ("Entw&APw-rfe", None, "Entwürfe"),
+        ] {
+            assert_eq!(
+                folder_path(&ImapFolder {
+                    name: name.to_string(),
+                    delimiter,
+                    attributes: vec![],
+                }),
+                expected,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn imap_source_addresses() {
+        for (ip, expected) in [
+            ("93.184.216.34", true),
+            ("2606:2800:220:1:248:1893:25c8:1946", true),
+            ("127.0.0.1", false),
+            ("10.1.2.3", false),
+            ("172.16.0.1", false),
+            ("192.168.1.1", false),
+            ("169.254.169.254", false),
+            ("100.64.0.1", false),
+            ("0.0.0.0", false),
+            ("::1", false),
+            ("fd00:ec2::254", false),
+            ("fe80::1", false),
+            ("::ffff:127.0.0.1", false),
+        ] {
+            assert_eq!(is_public_ip(ip.parse().unwrap()), expected, "{ip}");
+        }
+    }
+}
@@ -0,0 +1,482 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::principal::PrincipalManager;
+use common::{
+    Server,
+    auth::{
+        AccessToken,
+        import::{ImportEntryOutcome, ImportEntryResult, ImportJob},
+    },
+};
+use directory::{
+    Permission, Type,
+    backend::{
+        internal::{
+            PrincipalField, PrincipalSet, SpecialSecrets,
+            manage::{self, ChangedPrincipals, ManageDirectory},
+        },
+        ldap::import::{LdapImportEntry, LdapImportSource},
+    },
+    core::secret::is_supported_secret_hash,
+};
+use http_proto::*;
+use hyper::Method;
+use serde_json::json;
+use std::{future::Future, sync::Arc, time::Instant};
+use store::ahash::AHashMap;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdapImportRequest {
+    #[serde(flatten)]
+    pub source: LdapImportSource,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+pub trait DirectoryImportManager: Sync + Send {
+    fn handle_directory_import(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl DirectoryImportManager for Server {
+    async fn handle_directory_import(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (path.get(3).copied(), path.get(4).copied(), req.method()) {
+            (Some("ldap"), None, &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualCreate)?;
+                access_token.assert_has_permission(Permission::GroupCreate)?;
+
+                let request = serde_json::from_slice::<LdapImportRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                if request.source.url.is_empty() {
+                    return Err(manage::err_missing("url"));
+                }
+                if request.source.base_dn.is_empty() {
+                    return Err(manage::err_missing("baseDn"));
+                }
+                if !request.dry_run {
+                    self.assert_supported_directory(false)?;
+                }
+
+                // Only system administrators may import from internal hosts
+                let allow_internal = access_token.tenant.is_none();
+                request.source.resolve(allow_internal).await?;
+
+                // The import outlives the request, so it needs its own token
+                let access_token = self.get_access_token(access_token.primary_id()).await?;
+                let job = self
+                    .inner
+                    .data
+                    .import_jobs
+                    .start(
+                        self.inner.data.jmap_id_gen.generate(),
+                        tenant_id,
+                        request.dry_run,
+                    )
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Import in progress",
+                            "An import is already running for this organization".into(),
+                        )
+                    })?;
+                let response = job.summary();
+
+                let server = self.clone();
+                tokio::spawn(async move {
+                    run_ldap_import(
+                        server,
+                        job,
+                        request.source,
+                        tenant_id,
+                        access_token,
+                        allow_internal,
+                    )
+                    .await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), report, &Method::GET) if matches!(report, None | Some("report")) => {
+                access_token.assert_has_permission(Permission::IndividualCreate)?;
+
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.import_jobs.get(job_id))
+                    .filter(|job| job.tenant_id == tenant_id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                if report.is_none() {
+                    Ok(JsonResponse::new(json!({
+                        "data": job.summary(),
+                    }))
+                    .into_http_response())
+                } else {
+                    Ok(DownloadResponse {
+                        filename: format!("import-{job_id}.json"),
+                        content_type: "application/json".to_string(),
+                        blob: serde_json::to_vec(&json!({
+                            "summary": job.summary(),
+                            "entries": job.results(),
+                        }))
+                        .unwrap_or_default(),
+                    }
+                    .into_http_response())
+                }
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+struct ImportContext {
+    tenant: Option<String>,
+    domains: Vec<String>,
+    // Maps user DNs and uids to the name of the imported principal
+    names: AHashMap<String, String>,
+}
+
+async fn run_ldap_import(
+    server: Server,
+    job: Arc<ImportJob>,
+    source: LdapImportSource,
+    tenant_id: u32,
+    access_token: Arc<AccessToken>,
+    allow_internal: bool,
+) {
+    let start_time = Instant::now();
+    let result = async {
+        // Resolved again at connect time in case the host records changed
+        let entries = source.fetch(allow_internal).await?;
+        let store = server.store();
+        let tenant = if access_token.tenant.is_none() {
+            store.get_principal_name(tenant_id).await?
+        } else {
+            None
+        };
+        let domains = store
+            .list_principals(None, Some(tenant_id), &[Type::Domain], false, 0, 0)
+            .await?
+            .items
+            .into_iter()
+            .map(|domain| domain.name().to_string())
+            .collect::<Vec<_>>();
+        if domains.is_empty() {
+            return Err(manage::error(
+                "Invalid organization",
+                "The organization has no domains".into(),
+            ));
+        }
+
+        let mut context = ImportContext {
+            tenant,
+            domains,
+            names: AHashMap::new(),
+        };
+        for entry in &entries {
+            if let Some(name) = &entry.name
+                && entry.typ == Type::Individual
+            {
+                let principal_name = context.principal_name(entry, name);
+                context
+                    .names
+                    .insert(entry.dn.to_lowercase(), principal_name.clone());
+                context.names.insert(name.to_lowercase(), principal_name);
+            }
+        }
+
+        // Users come first so that group members exist when groups are created
+        job.set_total(entries.len());
+        let mut changed_principals = ChangedPrincipals::default();
+        for entry in entries {
+            let result = import_entry(
+                &server,
+                &job,
+                &context,
+                entry,
+                &access_token,
+                &mut changed_principals,
+            )
+            .await;
+            job.push_result(result);
+        }
+        server.invalidate_principal_caches(changed_principals).await;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            job.complete();
+
+            let summary = job.summary();
+            trc::event!(
+                Directory(trc::DirectoryEvent::ImportCompleted),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                TenantId = tenant_id,
+                Id = summary.id,
+                Total = summary.processed,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                Directory(trc::DirectoryEvent::ImportFailed),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                TenantId = tenant_id,
+                Id = job.id.to_string(),
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn import_entry(
+    server: &Server,
+    job: &ImportJob,
+    context: &ImportContext,
+    entry: LdapImportEntry,
+    access_token: &AccessToken,
+    changed_principals: &mut ChangedPrincipals,
+) -> ImportEntryResult {
+    let mut result = ImportEntryResult {
+        dn: entry.dn.clone(),
+        name: None,
+        typ: entry.typ,
+        result: ImportEntryOutcome::Failed,
+        id: None,
+        password_not_imported: false,
+        details: None,
+    };
+    let Some(name) = &entry.name else {
+        result.details = Some("Missing name attribute".to_string());
+        return result;
+    };
+    let name = context.principal_name(&entry, name);
+    result.name = Some(name.clone());
+
+    match server.store().get_principal_id(&name).await {
+        Ok(Some(id)) => {
+            result.result = ImportEntryOutcome::Exists;
+            result.id = Some(id);
+            return result;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            result.details = Some(error_details(&err));
+            return result;
+        }
+    }
+
+    let mut principal = PrincipalSet::new(0, entry.typ).with_field(PrincipalField::Name, name);
+    if let Some(tenant) = &context.tenant {
+        principal.set(PrincipalField::Tenant, tenant.clone());
+    }
+    if let Some(description) = entry.description {
+        principal.set(PrincipalField::Description, description);
+    }
+    if !entry.emails.is_empty() {
+        principal.set(PrincipalField::Emails, entry.emails);
+    }
+    if let Some(quota) = entry.quota {
+        principal.set(PrincipalField::Quota, quota);
+    }
+    if let Some(external_id) = entry.external_id {
+        principal.set(PrincipalField::ExternalId, external_id);
+    }
+
+    match entry.typ {
+        Type::Group => {
+            let mut members = Vec::new();
+            let mut missing = Vec::new();
+            for member in entry.members {
+                match context.names.get(&member.to_lowercase()) {
+                    Some(name) => members.push(name.clone()),
+                    None => missing.push(member),
+                }
+            }
+            if !members.is_empty() {
+                principal.set(PrincipalField::Members, members);
+            }
+            if !missing.is_empty() {
+                result.details = Some(format!("Unknown members: {}", missing.join(", ")));
+            }
+        }
+        _ => {
+            // Hashes that cannot be verified would lock the user out
+            let (secrets, unsupported) =
+                entry.secrets.into_iter().partition::<Vec<_>, _>(|secret| {
+                    secret.is_otp_secret()
+                        || secret.is_app_secret()
+                        || is_supported_secret_hash(secret)
+                });
+            if !unsupported.is_empty() {
+                result.password_not_imported = true;
+                result.details = Some(format!(
+                    "Unsupported password scheme: {}",
+                    unsupported
+                        .iter()
+                        .map(|secret| secret_scheme(secret))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            if !secrets.is_empty() {
+                principal.set(PrincipalField::Secrets, secrets);
+            }
+            principal.set(PrincipalField::Roles, vec!["user".to_string()]);
+        }
+    }
+
+    if job.dry_run {
+        result.result = ImportEntryOutcome::WouldCreate;
+        return result;
+    }
+
+    match server
+        .create_managed_principal_deferred(principal, access_token, false, changed_principals)
+        .await
+    {
+        Ok(id) => {
+            result.result = ImportEntryOutcome::Created;
+            result.id = Some(id);
+        }
+        Err(err) => {
+            result.details = Some(error_details(&err));
+        }
+    }
+
+    result
+}
+
+impl ImportContext {
+    /// Principals in a tenant have to be named after one of its domains, so
+    /// bare names are qualified with the domain of the entry's first address,
+    /// falling back to the first domain of the tenant.
+    fn principal_name(&self, entry: &LdapImportEntry, name: &str) -> String {
+        let name = name.trim().to_lowercase();
+        if name.contains('@') {
+            return name;
+        }
+
+        let domain = entry
+            .emails
+            .iter()
+            .filter_map(|email| email.rsplit_once('@').map(|(_, domain)| domain))
+            .find(|domain| self.domains.iter().any(|d| d == domain))
+            .unwrap_or(&self.domains[0]);
+        format!(
+            "{}@{domain}",
+            name.split_whitespace().collect::<Vec<_>>().join("-")
+        )
+    }
+}
+
+pub(super) fn error_details(err: &trc::Error) -> String {
+    err.value_as_str(trc::Key::Details)
+        .or_else(|| err.value_as_str(trc::Key::Reason))
+        .map(|details| details.to_string())
+        .unwrap_or_else(|| err.event_type().description().to_string())
+}
+
+fn secret_scheme(secret: &str) -> &str {
+    if let Some(scheme) = secret
+        .strip_prefix('{')
+        .and_then(|secret| secret.split_once('}'))
+        .map(|(scheme, _)| scheme)
+    {
+        scheme
+    } else if let Some(scheme) = secret
+        .strip_prefix('$')
+        .and_then(|secret| secret.split_once('$'))
+        .map(|(scheme, _)| scheme)
+    {
+        scheme
+    } else {
+        "unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportContext, secret_scheme};
+    use directory::{
+        Type, backend::ldap::import::LdapImportEntry, core::secret::is_supported_secret_hash,
+    };
+    use store::ahash::AHashMap;
+
+    #[test]
+    fn ldap_import_names_and_secrets() {
+        let context = ImportContext {
+            tenant: None,
+            domains: vec!["acme.org".to_string(), "acme.com".to_string()],
+            names: AHashMap::new(),
+        };
+        let mut entry = LdapImportEntry {
+            dn: "uid=jane,dc=acme,dc=org".to_string(),
+            typ: Type::Individual,
+            name: Some("Jane".to_string()),
+            description: None,
+            emails: vec!["jane@example.org".to_string()],
+            secrets: vec![],
+            quota: None,
+            external_id: None,
+            members: vec![],
+        };
+        assert_eq!(context.principal_name(&entry, "Jane"), "jane@acme.org");
+        entry.emails.push("jane@acme.com".to_string());
+        assert_eq!(context.principal_name(&entry, "Jane"), "jane@acme.com");
+        assert_eq!(
+            context.principal_name(&entry, "jane@acme.org"),
+            "jane@acme.org"
+        );
+        assert_eq!(
+            context.principal_name(&entry, "Sales Team"),
+            "sales-team@acme.com"
+        );
+
+        for (secret, is_supported, scheme) in [
+            ("{SSHA}aGVsbG8=", true, "SSHA"),
+            ("{CRYPT}$6$salt$hash", true, "CRYPT"),
+            ("{CRYPT}$y$j9T$salt$hash", false, "CRYPT"),
+            ("{ARGON2}$argon2id$v=19$m=65536", true, "ARGON2"),
+            ("$2y$10$hash", true, "2y"),
+            ("{SASL}jane@acme.org", false, "SASL"),
+            ("{SMD5}aGVsbG8=", false, "SMD5"),
+            ("plain-secret", true, "unknown"),
+        ] {
+            assert_eq!(is_supported_secret_hash(secret), is_supported, "{secret}");
+            assert_eq!(secret_scheme(secret), scheme, "{secret}");
+        }
+    }
+}
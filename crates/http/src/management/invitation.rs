@@ -0,0 +1,414 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    FutureTimestamp,
+    application::{queue_application, request_denied},
+    organization::{
+        OrganizationProvisionRequest, OrganizationProvisionResponse,
+        provision_organization_request, validate_provision_request,
+    },
+};
+use common::{
+    Server,
+    auth::{
+        AccessToken,
+        invitation::{InvitationRecord, InvitationStatus, OrganizationInvitation},
+    },
+    config::{abuse::ProvisioningVerdict, groupware::TenantCollections},
+    manager::abuse::ProvisioningRequestInfo,
+};
+use directory::{
+    Permission,
+    backend::internal::{
+        PrincipalField, PrincipalValue,
+        manage::{self, not_found},
+    },
+};
+use http_proto::*;
+use hyper::Method;
+use serde_json::json;
+use std::{future::Future, net::IpAddr, str::FromStr};
+use store::write::now;
+
+// Invitations that do not set an expiration are valid for a week
+const DEFAULT_INVITATION_TTL: u64 = 7 * 86400;
+const MAX_INVITATION_TTL: u64 = 30 * 86400;
+const MAX_PLAN_LEN: usize = 64;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+struct InvitationRequest {
+    domain_pattern: String,
+    #[serde(default)]
+    max_users: Option<u32>,
+    #[serde(default)]
+    quota: Option<u64>,
+    #[serde(default)]
+    plan: Option<String>,
+    #[serde(default)]
+    collections: Option<TenantCollections>,
+    #[serde(default)]
+    expires_at: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InvitedProvisionRequest {
+    token: String,
+    #[serde(flatten)]
+    organization: OrganizationProvisionRequest,
+}
+
+pub trait OrganizationInvitationManager: Sync + Send {
+    fn handle_organization_invitations(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_invited_provisioning(
+        &self,
+        body: Option<Vec<u8>>,
+        remote_ip: IpAddr,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl OrganizationInvitationManager for Server {
+    // The signed token carries the invitation's limits, the stored record
+    // tracks whether it was redeemed or revoked.
+    async fn handle_organization_invitations(
+        &self,
+        req: &HttpRequest,
+        path: &[&str],
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        // Only principals that can provision organizations may create invitations
+        access_token.assert_has_permission(Permission::TenantCreate)?;
+        access_token.assert_has_permission(Permission::DomainCreate)?;
+        access_token.assert_has_permission(Permission::IndividualCreate)?;
+
+        match (path.get(2).copied(), req.method()) {
+            (None, &Method::POST) => {
+                let request = serde_json::from_slice::<InvitationRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+
+                let domain_pattern = request.domain_pattern.trim().to_lowercase();
+                if !OrganizationInvitation::is_valid_pattern(&domain_pattern) {
+                    return Err(manage::error(
+                        "Invalid domain pattern",
+                        format!(
+                            "{domain_pattern:?} must be a domain name, '*.' followed by a domain name, or '*'"
+                        )
+                        .into(),
+                    ));
+                }
+                if request.max_users == Some(0) {
+                    return Err(manage::error(
+                        "Invalid user limit",
+                        "The organization's administrator counts towards the limit".into(),
+                    ));
+                }
+                let plan = request
+                    .plan
+                    .map(|plan| plan.trim().to_string())
+                    .filter(|plan| !plan.is_empty());
+                if let Some(plan) = &plan
+                    && (plan.len() > MAX_PLAN_LEN || plan.chars().any(|ch| ch.is_control()))
+                {
+                    return Err(manage::error(
+                        "Invalid plan",
+                        format!("Plans must be a single line of at most {MAX_PLAN_LEN} bytes")
+                            .into(),
+                    ));
+                }
+                if let Some(collections) = &request.collections {
+                    collections
+                        .validate()
+                        .map_err(|err| manage::error(err, None::<u64>))?;
+                }
+                let now = now();
+                let expires = match request.expires_at {
+                    Some(expires_at) => FutureTimestamp::from_str(&expires_at)
+                        .ok()
+                        .map(|expires| expires.into_inner())
+                        .filter(|expires| *expires <= now + MAX_INVITATION_TTL)
+                        .ok_or_else(|| {
+                            manage::error(
+                                "Invalid expiration",
+                                format!(
+                                    "{expires_at:?} must be an RFC 3339 timestamp within the next {} days",
+                                    MAX_INVITATION_TTL / 86400
+                                )
+                                .into(),
+                            )
+                        })?,
+                    None => now + DEFAULT_INVITATION_TTL,
+                };
+
+                let invitation = OrganizationInvitation {
+                    id: self.inner.data.jmap_id_gen.generate(),
+                    invited_by: access_token.primary_id(),
+                    domain_pattern,
+                    max_users: request.max_users,
+                    quota: request.quota.filter(|quota| *quota > 0),
+                    plan,
+                    collections: request.collections,
+                    expires,
+                };
+
+                self.add_invitation(&InvitationRecord {
+                    invitation: invitation.clone(),
+                    tenant_id: access_token.tenant.map(|t| t.id),
+                    status: InvitationStatus::Pending,
+                })
+                .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::InvitationCreated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = invitation.id.to_string(),
+                    Domain = invitation.domain_pattern.clone(),
+                    Expires = trc::Value::Timestamp(expires),
+                );
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "id": invitation.id.to_string(),
+                        "token": invitation.sign(&self.core.oauth.oauth_key),
+                        "expires": expires,
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(id), &Method::DELETE) => {
+                // Tenant administrators can only revoke their tenant's invitations
+                let record = match id.parse::<u64>() {
+                    Ok(id) => self.get_invitation(id).await?,
+                    Err(_) => None,
+                }
+                .filter(|record| {
+                    access_token
+                        .tenant
+                        .is_none_or(|tenant| record.tenant_id == Some(tenant.id))
+                })
+                .ok_or_else(|| not_found(id.to_string()))?;
+                let id = record.invitation.id;
+
+                // Revoking claims the invitation the same way redeeming it does
+                if !self.claim_invitation(&record).await? {
+                    return Err(manage::error(
+                        "Invitation unavailable",
+                        "The invitation has already been redeemed or revoked".into(),
+                    ));
+                }
+                self.set_invitation_status(record, InvitationStatus::Revoked)
+                    .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::InvitationRevoked),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = id.to_string(),
+                );
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+
+    // Redemption does not require credentials, the organization is
+    // provisioned with the current permissions of the inviting principal
+    // unless the abuse check holds it for review.
+    async fn handle_invited_provisioning(
+        &self,
+        body: Option<Vec<u8>>,
+        remote_ip: IpAddr,
+    ) -> trc::Result<HttpResponse> {
+        let request =
+            serde_json::from_slice::<InvitedProvisionRequest>(body.as_deref().unwrap_or_default())
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+        let invitation = OrganizationInvitation::verify(&self.core.oauth.oauth_key, &request.token)
+            .filter(|invitation| invitation.expires > now())
+            .ok_or_else(|| {
+                trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Invalid or expired invitation")
+            })?;
+
+        let organization = &request.organization;
+        if !invitation.allows_domain(&organization.domain) {
+            return Err(manage::error(
+                "Domain not allowed",
+                format!(
+                    "The invitation only allows domains matching {:?}",
+                    invitation.domain_pattern
+                )
+                .into(),
+            ));
+        }
+        if organization.collections.is_some() {
+            return Err(manage::error(
+                "Invalid request",
+                "Calendars and address books are set by the invitation".into(),
+            ));
+        }
+        let inviter = self.get_access_token(invitation.invited_by).await?;
+
+        let verdict = self
+            .check_provisioning(&ProvisioningRequestInfo {
+                source: "invitation",
+                remote_ip,
+                domain: &organization.domain,
+                tenant_name: &organization.tenant_name,
+                admin_email: &organization.admin_email,
+            })
+            .await;
+        if verdict == ProvisioningVerdict::Deny {
+            return Err(request_denied());
+        } else if verdict == ProvisioningVerdict::Review {
+            validate_provision_request(organization)?;
+        }
+
+        // Claim the invitation, only one concurrent redemption can succeed
+        let record = self
+            .get_invitation(invitation.id)
+            .await?
+            .filter(|record| record.invitation == invitation)
+            .ok_or_else(|| {
+                trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Invalid or expired invitation")
+            })?;
+        if !self.claim_invitation(&record).await? {
+            return Err(manage::error(
+                "Invitation unavailable",
+                "The invitation has already been redeemed or revoked".into(),
+            ));
+        }
+
+        let result = if verdict == ProvisioningVerdict::Review {
+            // Held redemptions are provisioned once their application is approved
+            queue_application(
+                self,
+                request.organization,
+                verdict,
+                remote_ip,
+                request.token.into(),
+            )
+            .await
+            .map(|application| {
+                JsonResponse::new(json!({
+                    "data": application,
+                }))
+                .into_http_response()
+            })
+        } else {
+            provision_invited_organization(
+                self,
+                &invitation,
+                request.organization,
+                &inviter,
+                remote_ip,
+            )
+            .await
+            .map(|response| {
+                JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response()
+            })
+        };
+
+        if result.is_ok() {
+            // Held redemptions use up the invitation as well
+            self.set_invitation_status(record, InvitationStatus::Redeemed)
+                .await?;
+        } else if let Err(err) = self.release_invitation(invitation.id).await {
+            // Failed attempts do not use up the invitation
+            trc::error!(err.details("Failed to release organization invitation"));
+        }
+
+        result
+    }
+}
+
+/// Provisions an organization with the limits of the invitation it was
+/// requested with, on behalf of `access_token`.
+pub(super) async fn provision_invited_organization(
+    server: &Server,
+    invitation: &OrganizationInvitation,
+    mut organization: OrganizationProvisionRequest,
+    access_token: &AccessToken,
+    remote_ip: IpAddr,
+) -> trc::Result<OrganizationProvisionResponse> {
+    organization.collections = invitation.collections.clone();
+    organization.trial_ends_at = None;
+    organization.trial_days = None;
+    organization.protocols.clear();
+    // Plans that are no longer offered are only kept as metadata
+    organization.plan = invitation
+        .plan
+        .clone()
+        .filter(|plan| server.core.jmap.plans.contains_key(plan));
+    let mut tenant_fields = vec![(
+        PrincipalField::Metadata,
+        PrincipalValue::StringList(
+            [
+                Some(format!("invitation.id={}", invitation.id)),
+                Some(format!("invitation.invitedBy={}", invitation.invited_by)),
+                invitation.plan.as_ref().map(|plan| format!("plan={plan}")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        ),
+    )];
+    if invitation.quota.is_some() || invitation.max_users.is_some() {
+        tenant_fields.push((
+            PrincipalField::Quota,
+            PrincipalValue::IntegerList(vec![
+                invitation.quota.unwrap_or_default(),
+                invitation.max_users.unwrap_or_default() as u64,
+            ]),
+        ));
+    }
+    let tenant_name = organization.tenant_name.clone();
+    let domain = organization.domain.clone();
+    let response =
+        provision_organization_request(server, organization, tenant_fields, access_token, true)
+            .await?;
+
+    trc::event!(
+        Directory(trc::DirectoryEvent::InvitationRedeemed),
+        AccountName = access_token.name.clone(),
+        AccountId = access_token.primary_id(),
+        TenantId = response.tenant_id,
+        Id = invitation.id.to_string(),
+        Domain = domain,
+        Details = tenant_name,
+        RemoteIp = remote_ip,
+    );
+
+    Ok(response)
+}
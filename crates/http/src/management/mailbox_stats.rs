@@ -0,0 +1,227 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, auth::AccessToken};
+use directory::{
+    Permission, Type,
+    backend::internal::manage::{self, ManageDirectory, not_found},
+};
+use email::{
+    cache::{MessageCacheFetch, email::MessageCacheAccess},
+    message::retention::AccountRetention,
+};
+use http_proto::{request::decode_path_element, *};
+use serde_json::json;
+use std::future::Future;
+use store::ahash::AHashMap;
+use trc::AddContext;
+use types::keyword::Keyword;
+use utils::url_params::UrlParams;
+
+/// Message totals of a mailbox, or of all the mailboxes of an account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageStats {
+    pub messages: u64,
+    pub unseen: u64,
+    pub size: u64,
+    pub largest: u64,
+    pub oldest_received_at: Option<u64>,
+    pub newest_received_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MailboxStats {
+    pub id: u32,
+    pub name: String,
+    pub path: String,
+    pub role: Option<&'static str>,
+    #[serde(flatten)]
+    pub stats: MessageStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxStatsSort {
+    Path,
+    Size,
+    Messages,
+}
+
+pub trait MailboxStatsManager: Sync + Send {
+    fn handle_mailbox_stats(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn mailbox_stats(
+        &self,
+        account_id: u32,
+        threshold: Option<u64>,
+        with_dates: bool,
+    ) -> impl Future<Output = trc::Result<(Vec<MailboxStats>, MessageStats)>> + Send;
+}
+
+impl MailboxStatsManager for Server {
+    async fn handle_mailbox_stats(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(Permission::MailboxStats)?;
+
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+        let account_id = self
+            .store()
+            .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+            .await?
+            .filter(|p| {
+                p.has_tenant_access(access_token.tenant.map(|t| t.id))
+                    && matches!(p.typ, Type::Individual | Type::Group)
+            })
+            .map(|p| p.id)
+            .ok_or_else(|| not_found(name.to_string()))?;
+
+        let params = UrlParams::new(req.uri().query());
+        let sort = match params.get("sort") {
+            None | Some("path") => MailboxStatsSort::Path,
+            Some("size") => MailboxStatsSort::Size,
+            Some("messages") => MailboxStatsSort::Messages,
+            Some(sort) => {
+                return Err(manage::error(
+                    "Invalid sort",
+                    format!("{sort:?} is not one of path, size or messages").into(),
+                ));
+            }
+        };
+        let threshold = params.parse::<u64>("threshold");
+
+        let (mut mailboxes, total) = self.mailbox_stats(account_id, threshold, true).await?;
+        sort_mailbox_stats(&mut mailboxes, sort);
+
+        Ok(JsonResponse::new(json!({
+            "data": {
+                "items": mailboxes,
+                "total": total,
+            },
+        }))
+        .into_http_response())
+    }
+
+    // Statistics are computed from the message cache and the stored metadata,
+    // message bodies are never loaded. Only mailboxes with more messages than
+    // the threshold are returned, and messages filed in several of them are
+    // counted once in the totals.
+    async fn mailbox_stats(
+        &self,
+        account_id: u32,
+        threshold: Option<u64>,
+        with_dates: bool,
+    ) -> trc::Result<(Vec<MailboxStats>, MessageStats)> {
+        let cache = self
+            .get_cached_messages(account_id)
+            .await
+            .caused_by(trc::location!())?;
+        let received_dates = if with_dates {
+            self.received_dates(account_id, &cache.email_document_ids())
+                .await?
+        } else {
+            AHashMap::new()
+        };
+
+        let mut mailboxes = cache
+            .mailboxes
+            .items
+            .iter()
+            .map(|mailbox| {
+                (
+                    mailbox.document_id,
+                    MailboxStats {
+                        id: mailbox.document_id,
+                        name: mailbox.name.clone(),
+                        path: mailbox.path.clone(),
+                        role: mailbox.role.as_str(),
+                        stats: MessageStats::default(),
+                    },
+                )
+            })
+            .collect::<AHashMap<_, _>>();
+        for message in cache.emails.items.iter() {
+            let is_seen = cache.has_keyword(message, &Keyword::Seen);
+            let received_at = received_dates.get(&message.document_id).copied();
+            for mailbox in message.mailboxes.iter() {
+                if let Some(mailbox) = mailboxes.get_mut(&mailbox.mailbox_id) {
+                    mailbox.stats.add(message.size, is_seen, received_at);
+                }
+            }
+        }
+        if let Some(threshold) = threshold {
+            mailboxes.retain(|_, mailbox| mailbox.stats.messages > threshold);
+        }
+
+        let mut total = MessageStats::default();
+        for message in cache.emails.items.iter().filter(|message| {
+            message
+                .mailboxes
+                .iter()
+                .any(|mailbox| mailboxes.contains_key(&mailbox.mailbox_id))
+        }) {
+            total.add(
+                message.size,
+                cache.has_keyword(message, &Keyword::Seen),
+                received_dates.get(&message.document_id).copied(),
+            );
+        }
+
+        let mut mailboxes = mailboxes.into_values().collect::<Vec<_>>();
+        sort_mailbox_stats(&mut mailboxes, MailboxStatsSort::Path);
+        Ok((mailboxes, total))
+    }
+}
+
+impl MessageStats {
+    pub fn add(&mut self, size: u32, is_seen: bool, received_at: Option<u64>) {
+        let size = size as u64;
+        self.messages += 1;
+        self.size += size;
+        self.largest = self.largest.max(size);
+        if !is_seen {
+            self.unseen += 1;
+        }
+        if let Some(received_at) = received_at {
+            self.oldest_received_at = Some(
+                self.oldest_received_at
+                    .map_or(received_at, |oldest| oldest.min(received_at)),
+            );
+            self.newest_received_at = Some(
+                self.newest_received_at
+                    .map_or(received_at, |newest| newest.max(received_at)),
+            );
+        }
+    }
+}
+
+/// Sorts by path, or by size or message count with the largest first.
+pub fn sort_mailbox_stats(mailboxes: &mut [MailboxStats], sort: MailboxStatsSort) {
+    match sort {
+        MailboxStatsSort::Path => mailboxes.sort_by(|a, b| a.path.cmp(&b.path)),
+        MailboxStatsSort::Size => mailboxes.sort_by(|a, b| {
+            b.stats
+                .size
+                .cmp(&a.stats.size)
+                .then_with(|| a.path.cmp(&b.path))
+        }),
+        MailboxStatsSort::Messages => mailboxes.sort_by(|a, b| {
+            b.stats
+                .messages
+                .cmp(&a.stats.messages)
+                .then_with(|| a.path.cmp(&b.path))
+        }),
+    }
+}
@@ -0,0 +1,1035 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::import::error_details;
+use common::{
+    Server,
+    auth::AccessToken,
+    storage::import::{MessageImportJob, MessageImportOutcome, MessageImportResult},
+};
+use directory::{Permission, backend::internal::manage};
+use email::{
+    cache::{MessageCacheFetch, mailbox::MailboxCacheAccess},
+    mailbox::{INBOX_ID, manage::MailboxFnc},
+    message::{
+        ingest::{EmailIngest, IngestEmail, IngestSource},
+        metadata::MessageMetadata,
+    },
+};
+use http_proto::multipart::MultipartReader;
+use http_proto::*;
+use hyper::{Method, body::Bytes};
+use mail_parser::{MessageParser, mailbox::mbox};
+use serde_json::json;
+use std::{borrow::Cow, future::Future, sync::Arc, time::Instant};
+use store::{
+    ValueKey,
+    ahash::{AHashMap, AHashSet},
+    write::{AlignedBytes, Archive},
+};
+use trc::AddContext;
+use types::{
+    blob_hash::BlobHash, collection::Collection, field::EmailField, keyword::Keyword,
+    special_use::SpecialUse,
+};
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MessageImportOptions {
+    /// Detected from the uploaded file when not set.
+    #[serde(default)]
+    pub format: Option<MessageImportFormat>,
+    /// Skips messages with the same Message-ID and date as an existing one.
+    #[serde(default)]
+    pub skip_duplicates: bool,
+    /// Mailbox for mbox messages and the top-level Maildir folder,
+    /// defaults to the inbox.
+    #[serde(default)]
+    pub mailbox: Option<String>,
+    /// Maps Maildir folder names to mailbox paths. Folders that are not
+    /// mapped are imported into the mailbox with the same path, if it
+    /// exists, or into the archive otherwise.
+    #[serde(default)]
+    pub folders: AHashMap<String, String>,
+}
+
+const MAX_OPTIONS_SIZE: usize = 64 * 1024;
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+const UPLOAD_HEAD_SIZE: usize = 512;
+const UPLOAD_EXPIRY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageImportFormat {
+    Mbox,
+    Maildir,
+}
+
+pub trait MessageImportManager: Sync + Send {
+    fn handle_message_import(
+        &self,
+        req: &mut HttpRequest,
+        path: Vec<&str>,
+        account_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl MessageImportManager for Server {
+    async fn handle_message_import(
+        &self,
+        req: &mut HttpRequest,
+        path: Vec<&str>,
+        account_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (path.get(4).copied(), path.get(5).copied(), req.method()) {
+            (None, None, &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let max_size = self
+                    .core
+                    .jmap
+                    .http_body_limits
+                    .limit(req.uri().path().strip_prefix("/api").unwrap_or_default());
+                let (upload, options) =
+                    read_upload(self, account_id, &mut MultipartReader::new(req, max_size)?)
+                        .await?;
+                let format = options
+                    .format
+                    .or_else(|| MessageImportFormat::detect(&upload.head))
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Unknown file format",
+                            "Expected an mbox file or a tar archive of a Maildir".into(),
+                        )
+                    })?;
+
+                // The import outlives the request, so it runs as the account
+                let account_token = self.get_access_token(account_id).await?;
+                let job = self
+                    .inner
+                    .data
+                    .message_import_jobs
+                    .start(self.inner.data.jmap_id_gen.generate(), account_id)
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Import in progress",
+                            "An import is already running for this account".into(),
+                        )
+                    })?;
+                let response = job.summary();
+
+                let server = self.clone();
+                tokio::spawn(async move {
+                    run_message_import(server, job, upload, format, options, account_token).await;
+                });
+
+                Ok(JsonResponse::new(json!({
+                    "data": response,
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), report, &Method::GET) if matches!(report, None | Some("report")) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.message_import_jobs.get(job_id))
+                    .filter(|job| job.account_id == account_id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                if report.is_none() {
+                    Ok(JsonResponse::new(json!({
+                        "data": job.summary(),
+                    }))
+                    .into_http_response())
+                } else {
+                    Ok(DownloadResponse {
+                        filename: format!("import-{job_id}.json"),
+                        content_type: "application/json".to_string(),
+                        blob: serde_json::to_vec(&json!({
+                            "summary": job.summary(),
+                            "messages": job.results(),
+                        }))
+                        .unwrap_or_default(),
+                    }
+                    .into_http_response())
+                }
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+/// Uploaded file, spooled to the blob store in chunks so that it is never
+/// held in memory in full.
+struct MessageUpload {
+    chunks: Vec<BlobHash>,
+    // Start of the file, used to detect its format
+    head: Vec<u8>,
+}
+
+/// Reads the `file` and `options` fields of a multipart upload.
+async fn read_upload<B>(
+    server: &Server,
+    account_id: u32,
+    upload: &mut MultipartReader<'_, B>,
+) -> trc::Result<(MessageUpload, MessageImportOptions)>
+where
+    B: hyper::body::Body<Data = Bytes> + Unpin,
+{
+    let mut file = MessageUpload {
+        chunks: Vec::new(),
+        head: Vec::new(),
+    };
+    let mut options = MessageImportOptions::default();
+    while let Some(field) = upload.next_field().await? {
+        match field.name.as_str() {
+            "file" => {
+                // The size is bounded by the route limit
+                let mut chunk = Vec::with_capacity(UPLOAD_CHUNK_SIZE);
+                loop {
+                    let data = upload.next_chunk().await?;
+                    if let Some(data) = &data {
+                        if file.head.len() < UPLOAD_HEAD_SIZE {
+                            let len = (UPLOAD_HEAD_SIZE - file.head.len()).min(data.len());
+                            file.head.extend_from_slice(&data[..len]);
+                        }
+                        chunk.extend_from_slice(data);
+                    }
+                    if chunk.len() >= UPLOAD_CHUNK_SIZE || (data.is_none() && !chunk.is_empty()) {
+                        // Chunks are removed by the blob purge once they expire
+                        let (hash, _) = server
+                            .put_temporary_blob(account_id, &chunk, UPLOAD_EXPIRY)
+                            .await
+                            .caused_by(trc::location!())?;
+                        file.chunks.push(hash);
+                        chunk.clear();
+                    }
+                    if data.is_none() {
+                        break;
+                    }
+                }
+            }
+            "options" => {
+                let contents = upload.read_to_end(MAX_OPTIONS_SIZE).await?;
+                options = serde_json::from_slice(&contents).map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+            }
+            _ => {}
+        }
+    }
+
+    if !file.chunks.is_empty() {
+        Ok((file, options))
+    } else {
+        Err(manage::err_missing("file"))
+    }
+}
+
+async fn run_message_import(
+    server: Server,
+    job: Arc<MessageImportJob>,
+    upload: MessageUpload,
+    format: MessageImportFormat,
+    options: MessageImportOptions,
+    access_token: Arc<AccessToken>,
+) {
+    let start_time = Instant::now();
+
+    match import_messages(&server, &job, &upload, format, &options, &access_token).await {
+        Ok(()) => {
+            job.complete();
+
+            let summary = job.summary();
+            trc::event!(
+                MessageIngest(trc::MessageIngestEvent::ImportCompleted),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                Id = summary.id,
+                Total = summary.imported,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                MessageIngest(trc::MessageIngestEvent::ImportFailed),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                Id = job.id.to_string(),
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn import_messages(
+    server: &Server,
+    job: &MessageImportJob,
+    upload: &MessageUpload,
+    format: MessageImportFormat,
+    options: &MessageImportOptions,
+    access_token: &AccessToken,
+) -> trc::Result<()> {
+    let mut mailboxes = MailboxResolver {
+        account_id: access_token.primary_id(),
+        options,
+        ids: AHashMap::new(),
+    };
+    let mut importer =
+        MessageImporter::new(server, job, access_token, options.skip_duplicates).await?;
+
+    // Chunks are read back one at a time as the messages in them are imported
+    let mut source = MessageSource::new(format);
+    let mut chunks = upload.chunks.iter();
+    let mut eof = false;
+    loop {
+        match source.next(eof) {
+            Some(message) => {
+                let message = message.map_err(|err| manage::error(err, None::<u64>))?;
+                let mailbox_id = mailboxes.resolve(server, message.folder.as_deref()).await?;
+                importer.import(message, mailbox_id).await?;
+            }
+            None if eof => break,
+            None => match chunks.next() {
+                Some(hash) => {
+                    let chunk = server
+                        .get_blob(hash, 0..usize::MAX)
+                        .await
+                        .caused_by(trc::location!())?
+                        .ok_or_else(|| {
+                            manage::error("Uploaded file is no longer available", None::<u64>)
+                        })?;
+                    source.push(&chunk);
+                }
+                None => eof = true,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Ingests messages into an account, recording the outcome of each one
+/// in the job.
+pub(super) struct MessageImporter<'x> {
+    server: &'x Server,
+    job: &'x MessageImportJob,
+    access_token: &'x AccessToken,
+    // Message-ID and date of the messages in the account
+    existing: Option<AHashSet<(String, i64)>>,
+    processed: usize,
+    imported: usize,
+}
+
+impl<'x> MessageImporter<'x> {
+    pub(super) async fn new(
+        server: &'x Server,
+        job: &'x MessageImportJob,
+        access_token: &'x AccessToken,
+        skip_duplicates: bool,
+    ) -> trc::Result<Self> {
+        Ok(MessageImporter {
+            server,
+            job,
+            access_token,
+            existing: if skip_duplicates {
+                Some(existing_messages(server, access_token.primary_id()).await?)
+            } else {
+                None
+            },
+            processed: 0,
+            imported: 0,
+        })
+    }
+
+    /// Reports a message that could not be retrieved from the source.
+    pub(super) fn fail(&mut self, folder: Option<String>, details: String) {
+        self.processed += 1;
+        self.job.push_result(MessageImportResult {
+            index: self.processed,
+            folder,
+            message_id: None,
+            result: MessageImportOutcome::Failed,
+            details: Some(details),
+        });
+    }
+
+    /// Imports a message into a mailbox, or reports it as failed when the
+    /// mailbox could not be resolved. Only errors that prevent any further
+    /// messages from being imported are returned.
+    pub(super) async fn import(
+        &mut self,
+        message: ImportMessage<'_>,
+        mailbox_id: Option<u32>,
+    ) -> trc::Result<()> {
+        self.processed += 1;
+        let mut result = MessageImportResult {
+            index: self.processed,
+            folder: message.folder.clone(),
+            message_id: None,
+            result: MessageImportOutcome::Failed,
+            details: None,
+        };
+
+        let Some(parsed) = MessageParser::new()
+            .parse(message.contents.as_ref())
+            .filter(|m| m.root_part().headers().iter().any(|h| !h.name.is_other()))
+        else {
+            result.details = Some("Failed to parse message".to_string());
+            self.job.push_result(result);
+            return Ok(());
+        };
+        let key = parsed.message_id().map(|message_id| {
+            (
+                message_id.to_string(),
+                parsed.date().map_or(0, |date| date.to_timestamp()),
+            )
+        });
+        result.message_id = key.as_ref().map(|(message_id, _)| message_id.clone());
+        if let (Some(existing), Some(key)) = (&self.existing, &key)
+            && existing.contains(key)
+        {
+            result.result = MessageImportOutcome::Skipped;
+            result.details = Some("Duplicate message".to_string());
+            self.job.push_result(result);
+            return Ok(());
+        }
+
+        let Some(mailbox_id) = mailbox_id else {
+            result.details = Some("Invalid mailbox name".to_string());
+            self.job.push_result(result);
+            return Ok(());
+        };
+
+        match self
+            .server
+            .email_ingest(IngestEmail {
+                raw_message: message.contents.as_ref(),
+                blob_hash: None,
+                message: Some(parsed),
+                access_token: self.access_token,
+                mailbox_ids: vec![mailbox_id],
+                keywords: message.keywords,
+                received_at: message.received_at,
+                source: IngestSource::Jmap {
+                    train_classifier: false,
+                },
+                session_id: 0,
+            })
+            .await
+        {
+            Ok(_) => {
+                self.imported += 1;
+                result.result = MessageImportOutcome::Imported;
+                if let (Some(existing), Some(key)) = (&mut self.existing, key) {
+                    existing.insert(key);
+                }
+            }
+            Err(err)
+                if matches!(
+                    err.event_type(),
+                    trc::EventType::Limit(trc::LimitEvent::Quota | trc::LimitEvent::TenantQuota)
+                ) =>
+            {
+                // Messages are not imported partially, so stop at the first
+                // one that does not fit
+                result.details = Some("Quota exceeded".to_string());
+                self.job.push_result(result);
+                return Err(manage::error(
+                    format!(
+                        "Quota exceeded after importing {} messages, the remaining messages were not imported",
+                        self.imported
+                    ),
+                    None::<u64>,
+                ));
+            }
+            Err(err) => {
+                result.details = Some(error_details(&err));
+            }
+        }
+        self.job.push_result(result);
+
+        Ok(())
+    }
+}
+
+/// Returns the Message-ID and date of the messages in the account.
+async fn existing_messages(
+    server: &Server,
+    account_id: u32,
+) -> trc::Result<AHashSet<(String, i64)>> {
+    let cache = server
+        .get_cached_messages(account_id)
+        .await
+        .caused_by(trc::location!())?;
+    let mut messages = AHashSet::with_capacity(cache.emails.items.len());
+    for item in cache.emails.items.iter() {
+        let Some(archive) = server
+            .store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::property(
+                account_id,
+                Collection::Email,
+                item.document_id,
+                EmailField::Metadata,
+            ))
+            .await
+            .caused_by(trc::location!())?
+        else {
+            continue;
+        };
+        let metadata = archive
+            .unarchive::<MessageMetadata>()
+            .caused_by(trc::location!())?;
+        if let Some(root_part) = metadata
+            .contents
+            .first()
+            .map(|contents| contents.root_part())
+            && let Some(message_id) = root_part.message_id()
+        {
+            messages.insert((
+                message_id.to_string(),
+                root_part.date().map_or(0, |date| date.to_timestamp()),
+            ));
+        }
+    }
+
+    Ok(messages)
+}
+
+struct MailboxResolver<'x> {
+    account_id: u32,
+    options: &'x MessageImportOptions,
+    ids: AHashMap<Option<String>, Option<u32>>,
+}
+
+impl MailboxResolver<'_> {
+    async fn resolve(&mut self, server: &Server, folder: Option<&str>) -> trc::Result<Option<u32>> {
+        let key = folder.map(|folder| folder.to_string());
+        if let Some(mailbox_id) = self.ids.get(&key) {
+            return Ok(*mailbox_id);
+        }
+
+        let path = match folder {
+            Some(folder) => self.options.folders.get(folder).map(|path| path.as_str()),
+            None => self.options.mailbox.as_deref(),
+        };
+        let mailbox_id = match (path, folder) {
+            (Some(path), _) => server.mailbox_create_path(self.account_id, path).await?,
+            (None, None) => Some(INBOX_ID),
+            (None, Some(folder)) => {
+                let cache = server
+                    .get_cached_messages(self.account_id)
+                    .await
+                    .caused_by(trc::location!())?;
+                if let Some(mailbox) = cache
+                    .mailbox_by_path(folder)
+                    .or_else(|| cache.mailbox_by_role(&SpecialUse::Archive))
+                {
+                    Some(mailbox.document_id)
+                } else {
+                    server
+                        .mailbox_create_path(self.account_id, "Archive")
+                        .await?
+                }
+            }
+        };
+        self.ids.insert(key, mailbox_id);
+
+        Ok(mailbox_id)
+    }
+}
+
+impl MessageImportFormat {
+    fn detect(contents: &[u8]) -> Option<Self> {
+        if contents.get(257..262) == Some(b"ustar") {
+            Some(MessageImportFormat::Maildir)
+        } else if contents.starts_with(b"From ") {
+            Some(MessageImportFormat::Mbox)
+        } else {
+            None
+        }
+    }
+}
+
+pub(super) struct ImportMessage<'x> {
+    // Source folder, or `None` for the top-level Maildir folder
+    pub folder: Option<String>,
+    pub contents: Cow<'x, [u8]>,
+    pub received_at: Option<u64>,
+    pub keywords: Vec<Keyword>,
+}
+
+/// Splits an uploaded file into messages as its data is pushed, keeping
+/// only the data of the messages that are not complete yet.
+enum MessageSource {
+    Mbox(MboxReader),
+    Maildir(TarReader),
+}
+
+#[derive(Default)]
+struct MboxReader {
+    buf: Vec<u8>,
+    pos: usize,
+    // Where to continue looking for the next "From " line
+    scan_pos: usize,
+}
+
+#[derive(Default)]
+struct TarReader {
+    buf: Vec<u8>,
+    pos: usize,
+    long_name: Option<String>,
+    done: bool,
+}
+
+impl MessageSource {
+    fn new(format: MessageImportFormat) -> Self {
+        match format {
+            MessageImportFormat::Mbox => MessageSource::Mbox(MboxReader::default()),
+            MessageImportFormat::Maildir => MessageSource::Maildir(TarReader::default()),
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let (buf, pos) = match self {
+            MessageSource::Mbox(reader) => {
+                reader.scan_pos = reader.scan_pos.saturating_sub(reader.pos);
+                (&mut reader.buf, &mut reader.pos)
+            }
+            MessageSource::Maildir(reader) if !reader.done => (&mut reader.buf, &mut reader.pos),
+            MessageSource::Maildir(_) => return,
+        };
+        buf.drain(..*pos);
+        *pos = 0;
+        buf.extend_from_slice(data);
+    }
+
+    /// Returns the next message, or `None` when more data is needed or,
+    /// once `eof` is set, when all messages have been read.
+    fn next(&mut self, eof: bool) -> Option<Result<ImportMessage<'static>, String>> {
+        match self {
+            MessageSource::Mbox(reader) => reader.next(eof),
+            MessageSource::Maildir(reader) => loop {
+                match reader.next(eof)? {
+                    Ok(entry) => {
+                        if let Some(message) = ImportMessage::from_maildir(entry) {
+                            return Some(Ok(message));
+                        }
+                    }
+                    Err(err) => return Some(Err(err)),
+                }
+            },
+        }
+    }
+}
+
+impl MboxReader {
+    fn next(&mut self, eof: bool) -> Option<Result<ImportMessage<'static>, String>> {
+        loop {
+            let start = self.scan_pos.max(self.pos);
+            let end = match self.buf[start..]
+                .windows(6)
+                .position(|window| window == b"\nFrom ")
+            {
+                Some(offset) => start + offset + 1,
+                None if eof && self.pos < self.buf.len() => self.buf.len(),
+                None => {
+                    // The separator might be split across chunks
+                    self.scan_pos = self.buf.len().saturating_sub(5);
+                    return None;
+                }
+            };
+            let contents = &self.buf[self.pos..end];
+            self.pos = end;
+            self.scan_pos = end;
+
+            // Data before the first "From " line is skipped
+            if let Some(message) = mbox::MessageIterator::new(contents).next() {
+                return Some(
+                    message
+                        .map(ImportMessage::from_mbox)
+                        .map_err(|err| err.to_string()),
+                );
+            }
+        }
+    }
+}
+
+impl TarReader {
+    /// Returns the next regular file of the archive.
+    fn next(&mut self, eof: bool) -> Option<Result<TarEntry, String>> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let data = &self.buf[self.pos..];
+            let header = data.get(..512)?;
+            if header.iter().all(|&ch| ch == 0) {
+                self.done = true;
+                return None;
+            }
+            let (Some(size), Some(mtime)) = (
+                parse_octal(&header[124..136]),
+                parse_octal(&header[136..148]),
+            ) else {
+                self.done = true;
+                return Some(Err("Invalid tar header".to_string()));
+            };
+            let size = size as usize;
+            let padded_size = 512 + size.div_ceil(512) * 512;
+            if data.len() < padded_size {
+                if !eof {
+                    return None;
+                } else if data.len() < 512 + size {
+                    self.done = true;
+                    return Some(Err("Truncated tar archive".to_string()));
+                }
+            }
+            let contents = &data[512..512 + size];
+            let typeflag = header[156];
+            let entry = match typeflag {
+                b'0' | 0 => {
+                    let path = self.long_name.take().unwrap_or_else(|| {
+                        let name = tar_string(&header[..100]);
+                        let prefix = if &header[257..262] == b"ustar" {
+                            tar_string(&header[345..500])
+                        } else {
+                            String::new()
+                        };
+                        if !prefix.is_empty() {
+                            format!("{prefix}/{name}")
+                        } else {
+                            name
+                        }
+                    });
+
+                    Some(TarEntry {
+                        path,
+                        mtime,
+                        contents: contents.to_vec(),
+                    })
+                }
+                b'L' => {
+                    // GNU long name
+                    self.long_name = Some(tar_string(contents));
+                    None
+                }
+                b'x' => {
+                    // PAX extended header, records are "<length> <key>=<value>\n"
+                    self.long_name = std::str::from_utf8(contents).ok().and_then(|records| {
+                        records.lines().find_map(|record| {
+                            record
+                                .split_once(' ')?
+                                .1
+                                .strip_prefix("path=")
+                                .map(|path| path.to_string())
+                        })
+                    });
+                    None
+                }
+                _ => {
+                    self.long_name = None;
+                    None
+                }
+            };
+            self.pos = (self.pos + padded_size).min(self.buf.len());
+
+            if let Some(entry) = entry {
+                return Some(Ok(entry));
+            }
+        }
+    }
+}
+
+impl<'x> ImportMessage<'x> {
+    fn from_mbox(message: mbox::Message) -> Self {
+        let received_at = Some(message.internal_date()).filter(|date| *date > 0);
+        let contents = message.unwrap_contents();
+
+        ImportMessage {
+            folder: None,
+            keywords: mbox_keywords(&contents),
+            contents: contents.into(),
+            received_at,
+        }
+    }
+
+    /// Maps a Maildir++ entry such as `.Work.Projects/cur/<name>:2,S` to a
+    /// message, skipping anything that is not in a `cur` or `new` directory.
+    fn from_maildir(entry: TarEntry) -> Option<Self> {
+        let mut components = entry
+            .path
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .rev();
+        let filename = components.next()?;
+        let subdir = components.next()?;
+        if !matches!(subdir, "cur" | "new") || filename.starts_with('.') {
+            return None;
+        }
+        let folder = components
+            .next()
+            .and_then(|folder| folder.strip_prefix('.'))
+            .filter(|folder| !folder.is_empty() && !folder.starts_with('.'))
+            .map(|folder| folder.replace('.', "/"));
+        let keywords = if subdir == "cur" {
+            maildir_keywords(filename)
+        } else {
+            Vec::new()
+        };
+
+        Some(ImportMessage {
+            folder,
+            contents: entry.contents.into(),
+            received_at: Some(entry.mtime).filter(|mtime| *mtime > 0),
+            keywords,
+        })
+    }
+}
+
+fn maildir_keywords(filename: &str) -> Vec<Keyword> {
+    let mut keywords = Vec::new();
+    if let Some((_, flags)) = filename.rsplit_once(":2,") {
+        for flag in flags.chars() {
+            let keyword = match flag {
+                'S' => Keyword::Seen,
+                'R' => Keyword::Answered,
+                'F' => Keyword::Flagged,
+                'T' => Keyword::Deleted,
+                'D' => Keyword::Draft,
+                'P' => Keyword::Forwarded,
+                _ => continue,
+            };
+            if !keywords.contains(&keyword) {
+                keywords.push(keyword);
+            }
+        }
+    }
+    keywords
+}
+
+/// Reads the flags from the `Status` and `X-Status` headers written by
+/// mbox clients.
+fn mbox_keywords(contents: &[u8]) -> Vec<Keyword> {
+    let mut keywords = Vec::new();
+    for line in contents.split(|&ch| ch == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = std::str::from_utf8(line)
+            .ok()
+            .and_then(|line| line.split_once(':'))
+        else {
+            continue;
+        };
+        let is_status = if name.eq_ignore_ascii_case("Status") {
+            true
+        } else if name.eq_ignore_ascii_case("X-Status") {
+            false
+        } else {
+            continue;
+        };
+        for flag in value.trim().chars() {
+            let keyword = match (is_status, flag) {
+                (true, 'R') => Keyword::Seen,
+                (false, 'A') => Keyword::Answered,
+                (false, 'F') => Keyword::Flagged,
+                (false, 'D') => Keyword::Deleted,
+                (false, 'T') => Keyword::Draft,
+                _ => continue,
+            };
+            if !keywords.contains(&keyword) {
+                keywords.push(keyword);
+            }
+        }
+    }
+    keywords
+}
+
+struct TarEntry {
+    path: String,
+    mtime: u64,
+    contents: Vec<u8>,
+}
+
+fn parse_octal(bytes: &[u8]) -> Option<u64> {
+    let value = std::str::from_utf8(bytes)
+        .ok()?
+        .trim_matches(|ch: char| ch == '\0' || ch == ' ');
+    if !value.is_empty() {
+        u64::from_str_radix(value, 8).ok()
+    } else {
+        Some(0)
+    }
+}
+
+fn tar_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&ch| ch == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_header(name: &str, size: usize, mtime: u64, typeflag: u8) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{size:011o}").as_bytes());
+        header[136..147].copy_from_slice(format!("{mtime:011o}").as_bytes());
+        header[156] = typeflag;
+        header[257..262].copy_from_slice(b"ustar");
+        header
+    }
+
+    // Pushes the data in small chunks so that messages span several of them
+    fn read_messages(
+        format: MessageImportFormat,
+        data: &[u8],
+    ) -> Vec<Result<ImportMessage<'static>, String>> {
+        let mut source = MessageSource::new(format);
+        let mut chunks = data.chunks(100);
+        let mut messages = Vec::new();
+        let mut eof = false;
+        loop {
+            match source.next(eof) {
+                Some(message) => messages.push(message),
+                None if eof => break,
+                None => match chunks.next() {
+                    Some(chunk) => source.push(chunk),
+                    None => eof = true,
+                },
+            }
+        }
+        messages
+    }
+
+    fn tar_file(archive: &mut Vec<u8>, name: &str, contents: &[u8], mtime: u64) {
+        archive.extend(tar_header(name, contents.len(), mtime, b'0'));
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+    }
+
+    #[test]
+    fn maildir_archive() {
+        let long_name = format!("Maildir/.Work.Projects/cur/{}:2,FS", "x".repeat(120));
+        let mut archive = tar_header("Maildir/", 0, 0, b'5');
+        tar_file(
+            &mut archive,
+            "Maildir/cur/1700000000.1.host:2,RS",
+            b"Subject: one\r\n\r\nbody\r\n",
+            1700000000,
+        );
+        tar_file(
+            &mut archive,
+            "Maildir/new/1700000001.2.host",
+            b"Subject: two\r\n\r\nbody\r\n",
+            1700000001,
+        );
+        tar_file(&mut archive, "Maildir/tmp/ignored", b"x", 0);
+        tar_file(&mut archive, "Maildir/dovecot-uidlist", b"x", 0);
+        archive.extend(tar_header("././@LongLink", long_name.len(), 0, b'L'));
+        archive.extend_from_slice(long_name.as_bytes());
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+        tar_file(
+            &mut archive,
+            "truncated",
+            b"Subject: three\r\n\r\nbody\r\n",
+            1700000002,
+        );
+        archive.extend([0u8; 1024]);
+
+        assert_eq!(
+            MessageImportFormat::detect(&archive),
+            Some(MessageImportFormat::Maildir)
+        );
+        let messages = read_messages(MessageImportFormat::Maildir, &archive)
+            .into_iter()
+            .map(|message| {
+                let message = message.unwrap();
+                (message.folder, message.received_at, message.keywords)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    None,
+                    Some(1700000000),
+                    vec![Keyword::Answered, Keyword::Seen]
+                ),
+                (None, Some(1700000001), vec![]),
+                (
+                    Some("Work/Projects".to_string()),
+                    Some(1700000002),
+                    vec![Keyword::Flagged, Keyword::Seen]
+                ),
+            ]
+        );
+
+        // Truncated archives are reported
+        let mut archive = Vec::new();
+        tar_file(
+            &mut archive,
+            "Maildir/cur/1:2,S",
+            b"Subject: one\r\n\r\n",
+            0,
+        );
+        archive.truncate(520);
+        assert!(matches!(
+            read_messages(MessageImportFormat::Maildir, &archive).first(),
+            Some(Err(_))
+        ));
+    }
+
+    #[test]
+    fn mbox_file() {
+        let mbox = concat!(
+            "From jane@example.org Mon Nov 13 22:13:20 2023\n",
+            "Subject: one\n",
+            "Status: RO\n",
+            "X-Status: AF\n",
+            "\n",
+            ">From the body\n",
+            "\n",
+            "From john@example.org Tue Nov 14 22:13:20 2023\n",
+            "Subject: two\n",
+            "\n",
+            "Status: R\n",
+        );
+
+        assert_eq!(
+            MessageImportFormat::detect(mbox.as_bytes()),
+            Some(MessageImportFormat::Mbox)
+        );
+        let messages = read_messages(MessageImportFormat::Mbox, mbox.as_bytes())
+            .into_iter()
+            .map(|message| {
+                let message = message.unwrap();
+                (message.received_at, message.keywords)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    Some(1699913600),
+                    vec![Keyword::Seen, Keyword::Answered, Keyword::Flagged]
+                ),
+                (Some(1700000000), vec![]),
+            ]
+        );
+        assert_eq!(MessageImportFormat::detect(b"Subject: test\r\n"), None);
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::organization::csv_field;
+use common::{
+    Server,
+    auth::AccessToken,
+    telemetry::metrics::metering::{MeteringDay, MeteringStore, daily_metering},
+};
+use directory::{
+    Permission, Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
+use http_proto::*;
+use hyper::StatusCode;
+use mail_parser::DateTime;
+use serde_json::{Map, Value, json};
+use std::future::Future;
+use store::{ahash::AHashMap, write::now};
+use utils::url_params::UrlParams;
+
+const METERING_COLUMNS: [&str; 12] = [
+    "date",
+    "tenantId",
+    "tenantName",
+    "activeUsers",
+    "storagePeak",
+    "storageAverage",
+    "messagesSent",
+    "messagesReceived",
+    "bandwidthIn",
+    "bandwidthOut",
+    "samples",
+    "estimated",
+];
+
+pub trait MeteringApi: Sync + Send {
+    fn handle_metering_export(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl MeteringApi for Server {
+    async fn handle_metering_export(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        // Metering covers all tenants, so it is not available to tenant administrators
+        access_token.assert_has_permission(Permission::MeteringExport)?;
+        if access_token.tenant.is_some() {
+            return Err(manage::unsupported(
+                "Metering data is only available to system administrators",
+            ));
+        }
+
+        let metering = self
+            .core
+            .storage
+            .metering
+            .as_ref()
+            .ok_or_else(|| manage::unsupported("Metering is not enabled"))?;
+
+        // Days are inclusive and default to the current month
+        let params = UrlParams::new(req.uri().query());
+        let to = params
+            .get("to")
+            .map(parse_day)
+            .transpose()?
+            .unwrap_or_else(|| now() - now() % 86400);
+        let from = match params.get("from") {
+            Some(from) => parse_day(from)?,
+            None => {
+                let to = DateTime::from_timestamp(to as i64);
+                parse_day(&format!("{:04}-{:02}-01", to.year, to.month))?
+            }
+        };
+        if from > to {
+            return Err(manage::error(
+                "Invalid range",
+                "from must not be later than to".into(),
+            ));
+        }
+        let format = params.get("format").unwrap_or("json");
+        if !matches!(format, "json" | "csv") {
+            return Err(manage::error(
+                "Invalid format",
+                format!("{format:?} is not one of csv or json").into(),
+            ));
+        }
+
+        let days = daily_metering(
+            &metering
+                .store
+                .query_metering_samples(from, to + 86399)
+                .await?,
+        );
+
+        // Samples are keyed by tenant ID, names are the current ones
+        let names = self
+            .store()
+            .list_principals(None, None, &[Type::Tenant], false, 0, 0)
+            .await?
+            .items
+            .into_iter()
+            .map(|tenant| (tenant.id(), tenant.name().to_string()))
+            .collect::<AHashMap<_, _>>();
+        let rows = days
+            .iter()
+            .map(|day| metering_row(day, names.get(&day.tenant_id)));
+
+        if format == "csv" {
+            let mut csv = METERING_COLUMNS.join(",");
+            csv.push_str("\r\n");
+            for row in rows {
+                csv.push_str(&row.iter().map(csv_field).collect::<Vec<_>>().join(","));
+                csv.push_str("\r\n");
+            }
+
+            Ok(HttpResponse::new(StatusCode::OK)
+                .with_content_type("text/csv; charset=utf-8")
+                .with_content_disposition("attachment; filename=\"metering.csv\"")
+                .with_no_store()
+                .with_text_body(csv))
+        } else {
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "items": rows
+                        .map(|row| {
+                            METERING_COLUMNS
+                                .iter()
+                                .map(|column| column.to_string())
+                                .zip(row)
+                                .collect::<Map<_, _>>()
+                        })
+                        .collect::<Vec<_>>(),
+                },
+            }))
+            .into_http_response())
+        }
+    }
+}
+
+fn metering_row(day: &MeteringDay, tenant_name: Option<&String>) -> [Value; 12] {
+    let date = DateTime::from_timestamp(day.date as i64);
+    [
+        format!("{:04}-{:02}-{:02}", date.year, date.month, date.day).into(),
+        day.tenant_id.into(),
+        tenant_name.cloned().into(),
+        day.active_users.into(),
+        day.storage_peak.into(),
+        day.storage_average.into(),
+        day.messages_sent.into(),
+        day.messages_received.into(),
+        day.bandwidth_in.into(),
+        day.bandwidth_out.into(),
+        day.samples.into(),
+        day.estimated.into(),
+    ]
+}
+
+/// Parses a `YYYY-MM-DD` date into the timestamp of its first second (UTC).
+fn parse_day(value: &str) -> trc::Result<u64> {
+    Some(value)
+        .filter(|value| value.len() == 10)
+        .and_then(|value| DateTime::parse_rfc3339(&format!("{value}T00:00:00Z")))
+        .filter(|date| date.is_valid())
+        .map(|date| date.to_timestamp().max(0) as u64)
+        .ok_or_else(|| {
+            manage::error(
+                "Invalid date",
+                format!("{value:?} is not a YYYY-MM-DD date").into(),
+            )
+        })
+}
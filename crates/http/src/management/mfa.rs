@@ -0,0 +1,246 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::webauthn::WebAuthnManager;
+use common::{
+    Server,
+    auth::{AccessToken, webauthn::encode_base64url},
+};
+use directory::{
+    Permission, Type,
+    backend::internal::{
+        PrincipalField,
+        manage::{self, ChangedPrincipals, ManageDirectory, not_found},
+        mfa::{TOTP_ENROLLMENT_EXPIRY, TotpFactors},
+        recovery::RecoveryCodes,
+        webauthn::WebAuthnCredentials,
+    },
+};
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::json;
+use std::future::Future;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpConfirmRequest {
+    code: String,
+}
+
+pub trait MfaManager: Sync + Send {
+    fn handle_mfa(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl MfaManager for Server {
+    // Factors are enrolled by their owner or by an administrator, in which
+    // case the enrollment has to be handed over to the owner to be confirmed
+    // with a code from their authenticator.
+    async fn handle_mfa(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let name = decode_path_element(path.get(1).copied().unwrap_or_default());
+        let info = self
+            .store()
+            .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+            .await?
+            .filter(|p| {
+                p.has_tenant_access(access_token.tenant.map(|t| t.id)) && p.typ == Type::Individual
+            })
+            .ok_or_else(|| not_found(name.to_string()))?;
+        let is_owner = info.id == access_token.primary_id();
+        access_token.assert_has_permission(match (is_owner, req.method()) {
+            (true, _) => Permission::ManagePasswords,
+            (false, &Method::GET) => Permission::IndividualGet,
+            (false, _) => Permission::IndividualUpdate,
+        })?;
+        let cipher = self.secret_cipher();
+
+        match (path.get(3).copied(), path.get(4).copied(), req.method()) {
+            (Some("webauthn"), _, _) => {
+                self.handle_webauthn_credentials(req, path, body, info.id, is_owner)
+                    .await
+            }
+            (None, None, &Method::GET) => {
+                let mut factors = self
+                    .store()
+                    .list_totp_factors(info.id, &cipher)
+                    .await?
+                    .into_iter()
+                    .map(|factor| {
+                        json!({
+                            "type": "totp",
+                            "issuer": factor.issuer,
+                            "accountName": factor.account_name,
+                            "encrypted": factor.encrypted,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                factors.extend(
+                    self.store()
+                        .list_webauthn_credentials(info.id)
+                        .await?
+                        .into_iter()
+                        .map(|entry| {
+                            json!({
+                                "type": "webauthn",
+                                "id": encode_base64url(&entry.credential.id),
+                                "nickname": entry.credential.nickname,
+                                "rpId": entry.credential.rp_id,
+                            })
+                        }),
+                );
+                let remaining = self
+                    .store()
+                    .get_principal(info.id)
+                    .await?
+                    .map_or(0, |principal| principal.recovery_code_count());
+                if remaining > 0 {
+                    factors.push(json!({
+                        "type": "recoveryCodes",
+                        "remaining": remaining,
+                    }));
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": factors,
+                }))
+                .into_http_response())
+            }
+            (Some("totp"), None, &Method::POST) => {
+                let enrollment = self
+                    .store()
+                    .start_totp_enrollment(
+                        info.id,
+                        &self.core.network.server_name,
+                        name.as_ref(),
+                        &cipher,
+                    )
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "uri": enrollment.uri,
+                        "secret": enrollment.secret,
+                        "qrPayload": enrollment.uri,
+                        "expiresIn": TOTP_ENROLLMENT_EXPIRY,
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some("totp"), Some("confirm"), &Method::POST) => {
+                let request = serde_json::from_slice::<TotpConfirmRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+
+                if !self
+                    .store()
+                    .confirm_totp_enrollment(info.id, &request.code, &cipher)
+                    .await?
+                {
+                    return Err(manage::error(
+                        "Invalid TOTP code",
+                        "The code does not match the enrolled secret".into(),
+                    ));
+                }
+
+                // Sessions authenticated without a code have to log in again
+                self.invalidate_principal_caches(ChangedPrincipals::from_change(
+                    info.id,
+                    Type::Individual,
+                    PrincipalField::Secrets,
+                ))
+                .await;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::MfaFactorAdded),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    Id = name.to_string(),
+                    Type = "totp",
+                );
+                self.notify_security_event(
+                    trc::EventType::Directory(trc::DirectoryEvent::MfaFactorAdded),
+                    info.id,
+                    "An authenticator app was added.".to_string().into(),
+                )
+                .await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some("totp"), None, &Method::DELETE) => {
+                if !self.store().remove_totp_factor(info.id).await? {
+                    return Err(not_found("totp"));
+                }
+
+                self.invalidate_principal_caches(ChangedPrincipals::from_change(
+                    info.id,
+                    Type::Individual,
+                    PrincipalField::Secrets,
+                ))
+                .await;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::MfaFactorRemoved),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    Id = name.to_string(),
+                    Type = "totp",
+                );
+                self.notify_security_event(
+                    trc::EventType::Directory(trc::DirectoryEvent::MfaFactorRemoved),
+                    info.id,
+                    "An authenticator app was removed.".to_string().into(),
+                )
+                .await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some("recovery-codes"), None, &Method::POST) => {
+                let codes = self.store().generate_recovery_codes(info.id).await?;
+
+                // Administrators may replace the codes of an account but only
+                // its owner gets to see them
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "remaining": codes.len(),
+                        "codes": is_owner.then_some(codes),
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some("recovery-codes"), None, &Method::DELETE) => {
+                if !self.store().remove_recovery_codes(info.id).await? {
+                    return Err(not_found("recovery-codes"));
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
@@ -4,19 +4,51 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+pub mod activation;
+pub mod app_password;
+pub mod application;
+pub mod avatar;
+pub mod backup;
+pub mod bimi;
+pub mod branding;
+pub mod changes;
 pub mod crypto;
+pub mod deletion;
+pub mod deliverability;
 pub mod dkim;
 pub mod dns;
+pub mod domain;
+pub mod erasure;
+pub mod events;
+pub mod export;
+pub mod forwarding;
+pub mod imap_import;
+pub mod import;
+pub mod invitation;
 pub mod log;
+pub mod mailbox_stats;
+pub mod message_import;
+pub mod metering;
+pub mod mfa;
 pub mod organization;
 pub mod principal;
+pub mod provision_lock;
 pub mod queue;
+pub mod reindex;
 pub mod reload;
 pub mod report;
+pub mod resource;
+pub mod secondary_email;
+pub mod sender;
+pub mod session;
 pub mod settings;
+pub mod shared_mailbox;
 pub mod spam;
 pub mod stores;
+pub mod trial;
 pub mod troubleshoot;
+pub mod upsert;
+pub mod webauthn;
 
 // SPDX-SnippetBegin
 // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
@@ -28,31 +60,45 @@ pub mod enterprise;
 use enterprise::telemetry::TelemetryApi;
 // SPDX-SnippetEnd
 
-use crate::auth::oauth::auth::OAuthApiHandler;
-use common::{Server, auth::AccessToken};
+use crate::auth::{authenticate::Authenticator, oauth::auth::OAuthApiHandler};
+use changes::DirectoryChangesApi;
+use common::{
+    Server,
+    auth::AccessToken,
+    telemetry::metrics::{management::ManagementRoute, tenant::TenantMetric},
+};
 use crypto::CryptoHandler;
 use directory::{Permission, backend::internal::manage};
 use dkim::DkimManagement;
 use dns::DnsManagement;
-use http_proto::{request::fetch_body, *};
+use domain::DomainManagement;
+use events::AuditEventsApi;
+use http_proto::{
+    compression::decode_body, plain::is_plain_text_request, request::fetch_body_with_limit, *,
+};
 use hyper::{Method, StatusCode, header};
 use jmap::api::{ToJmapHttpResponse, ToRequestError};
 use jmap_proto::error::request::RequestError;
 use log::LogManagement;
 use mail_parser::DateTime;
+use metering::MeteringApi;
 use organization::OrganizationManager;
 use principal::PrincipalManager;
 use queue::QueueManagement;
 use reload::ManageReload;
 use report::ManageReports;
+use secondary_email::SecondaryEmailManager;
 use serde::Serialize;
+use session::AccountSessionManager;
 use settings::ManageSettings;
 use spam::ManageSpamHandler;
 use std::future::Future;
+use std::time::Instant;
 use std::{str::FromStr, sync::Arc};
 use store::write::now;
 use stores::ManageStore;
 use troubleshoot::TroubleshootApi;
+use utils::url_params::UrlParams;
 
 #[derive(Serialize)]
 #[serde(tag = "error")]
@@ -61,6 +107,10 @@ pub enum ManagementApiError<'x> {
     FieldAlreadyExists {
         field: &'x str,
         value: &'x str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        holder: Option<&'x str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
     },
     FieldMissing {
         field: &'x str,
@@ -72,6 +122,17 @@ pub enum ManagementApiError<'x> {
         details: &'x str,
     },
     AssertFailed,
+    FieldReserved {
+        field: &'x str,
+        value: &'x str,
+    },
+    PasswordReused {
+        history: u64,
+    },
+    RequestTooLarge {
+        limit: u64,
+        received: u64,
+    },
     Other {
         details: &'x str,
         reason: Option<&'x str>,
@@ -95,10 +156,61 @@ impl ManagementApi for Server {
         access_token: Arc<AccessToken>,
         session: &HttpSessionData,
     ) -> trc::Result<HttpResponse> {
-        let body = fetch_body(req, 1024 * 1024, session.session_id).await;
-        let path = req.uri().path().split('/').skip(2).collect::<Vec<_>>();
+        let uri_path = req.uri().path().to_string();
+        let api_path = uri_path.strip_prefix("/api").unwrap_or_default();
+        let body_limits = &self.core.jmap.http_body_limits;
+        let plain_text = is_plain_text_request(req);
+        let body = if !body_limits.is_streaming(api_path) {
+            // Compressed bodies may not expand past the route limit either
+            let max_size = body_limits.limit(api_path);
+            let max_decompressed = if max_size != 0 {
+                max_size.min(body_limits.decompressed)
+            } else {
+                body_limits.decompressed
+            };
+            match fetch_body_with_limit(req, max_size, session.session_id)
+                .await
+                .and_then(|body| decode_body(req.headers(), body, max_decompressed))
+            {
+                Ok(body) => Some(body),
+                Err(err) if plain_text => {
+                    let response = (&err).into_text_response();
+                    trc::error!(err.span_id(session.session_id));
+                    return Ok(response);
+                }
+                Err(err) => return Err(err),
+            }
+        } else {
+            // Read by the handler as it arrives
+            None
+        };
+        let accept_encoding = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let path = uri_path.split('/').skip(2).collect::<Vec<_>>();
+
+        self.record_tenant_metric(
+            access_token.tenant.map(|tenant| tenant.id),
+            TenantMetric::ApiRequests,
+            1,
+        );
 
-        match path.first().copied().unwrap_or_default() {
+        let route = ManagementRoute::parse(path.first().copied().unwrap_or_default());
+        let caller = access_token.clone();
+        // Erased principals are audited under their tombstone ID by the erasure job
+        let audit_path = if path.first().copied() == Some("principal")
+            && path.get(2).copied() == Some("erase")
+        {
+            "/api/principal/erase".to_string()
+        } else {
+            req.uri().path().to_string()
+        };
+        let start_time = Instant::now();
+
+        let response = match path.first().copied().unwrap_or_default() {
             "queue" => self.handle_manage_queue(req, path, &access_token).await,
             "settings" => {
                 self.handle_manage_settings(req, path, body, &access_token)
@@ -114,6 +226,10 @@ impl ManagementApi for Server {
                     .await
             }
             "dns" => self.handle_manage_dns(req, path, &access_token).await,
+            "domain" => {
+                self.handle_manage_domain(req, path, body, &access_token)
+                    .await
+            }
             "store" => {
                 self.handle_manage_store(req, path, body, session, &access_token)
                     .await
@@ -127,6 +243,17 @@ impl ManagementApi for Server {
             "logs" if req.method() == Method::GET => {
                 self.handle_view_logs(req, &access_token).await
             }
+            "directory"
+                if path.get(1).copied() == Some("changes") && req.method() == Method::GET =>
+            {
+                self.handle_directory_changes(req, &access_token).await
+            }
+            "events" if req.method() == Method::GET => {
+                self.handle_audit_events(req, &access_token).await
+            }
+            "metering" if req.method() == Method::GET => {
+                self.handle_metering_export(req, &access_token).await
+            }
             "spam-filter" => {
                 self.handle_manage_spam(req, path, body, session, &access_token)
                     .await
@@ -141,7 +268,9 @@ impl ManagementApi for Server {
                 // Validate the access token
                 access_token.assert_has_permission(Permission::AuthenticateOauth)?;
 
-                self.handle_oauth_api_request(access_token, body).await
+                let impersonated = self.is_impersonated_session(req);
+                self.handle_oauth_api_request(access_token, impersonated, body)
+                    .await
             }
             "account" => match (path.get(1).copied().unwrap_or_default(), req.method()) {
                 ("crypto", &Method::POST) => {
@@ -156,6 +285,21 @@ impl ManagementApi for Server {
 
                     self.handle_crypto_get(access_token).await
                 }
+                ("vacation-template", &Method::GET) => {
+                    // Validate the access token
+                    access_token.assert_has_permission(Permission::JmapVacationResponseGet)?;
+
+                    let template = if let Some(tenant) = &access_token.tenant {
+                        self.tenant_vacation_template(tenant.id).await?
+                    } else {
+                        None
+                    };
+
+                    Ok(JsonResponse::new(serde_json::json!({
+                        "data": template,
+                    }))
+                    .into_http_response())
+                }
                 ("auth", &Method::GET) => {
                     // Validate the access token
                     access_token.assert_has_permission(Permission::ManagePasswords)?;
@@ -168,6 +312,19 @@ impl ManagementApi for Server {
 
                     self.handle_account_auth_post(req, access_token, body).await
                 }
+                ("sessions", &Method::GET) => {
+                    // Validate the access token
+                    access_token.assert_has_permission(Permission::Authenticate)?;
+
+                    self.handle_account_sessions(req, &access_token).await
+                }
+                ("emails", _) => {
+                    // Validate the access token
+                    access_token.assert_has_permission(Permission::ManageSecondaryEmails)?;
+
+                    self.handle_account_emails(req, path, body, &access_token, session)
+                        .await
+                }
                 _ => Err(trc::ResourceEvent::NotFound.into_err()),
             },
             "troubleshoot" => {
@@ -177,6 +334,21 @@ impl ManagementApi for Server {
                 self.handle_troubleshoot_api_request(req, path, &access_token, body)
                     .await
             }
+            "telemetry"
+                if path.get(1).copied() == Some("slow-requests") && req.method() == Method::GET =>
+            {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::MetricsList)?;
+
+                let limit = UrlParams::new(req.uri().query())
+                    .parse::<usize>("limit")
+                    .unwrap_or(self.core.jmap.http_slow_requests.max_entries);
+
+                Ok(JsonResponse::new(serde_json::json!({
+                    "data": self.inner.data.slow_requests.recent(limit),
+                }))
+                .into_http_response())
+            }
             // SPDX-SnippetBegin
             // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
             // SPDX-License-Identifier: LicenseRef-SEL
@@ -200,7 +372,34 @@ impl ManagementApi for Server {
             }
             // SPDX-SnippetEnd
             _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        };
+
+        if response.is_ok() && !matches!(*req.method(), Method::GET | Method::HEAD) {
+            trc::event!(
+                Http(trc::HttpEvent::ManagementWrite),
+                AccountName = caller.name.clone(),
+                AccountId = caller.primary_id(),
+                TenantId = caller.tenant.map(|t| t.id),
+                Path = audit_path,
+                Type = req.method().as_str().to_string(),
+            );
         }
+
+        self.record_management_request(route, req.method(), &caller, start_time.elapsed());
+
+        // Errors are rendered here when plain text was requested
+        let response = match response {
+            Err(err) if plain_text => {
+                let response = (&err).into_text_response();
+                trc::error!(err.span_id(session.session_id));
+                Ok(response)
+            }
+            response => response,
+        };
+
+        response.map(|response| {
+            response.with_compression(&accept_encoding, &self.core.jmap.http_compression)
+        })
     }
 }
 
@@ -248,67 +447,174 @@ impl Timestamp {
 
 pub trait ToManageHttpResponse {
     fn into_http_response(self) -> HttpResponse;
+
+    /// Renders the error as a single line of text, for clients that
+    /// requested plain text output.
+    fn into_text_response(self) -> HttpResponse;
 }
 
 impl ToManageHttpResponse for &trc::Error {
     fn into_http_response(self) -> HttpResponse {
-        match self.as_ref() {
-            trc::EventType::Manage(cause) => {
-                match cause {
-                    trc::ManageEvent::MissingParameter => ManagementApiError::FieldMissing {
-                        field: self.value_as_str(trc::Key::Key).unwrap_or_default(),
-                    },
-                    trc::ManageEvent::AlreadyExists => ManagementApiError::FieldAlreadyExists {
-                        field: self.value_as_str(trc::Key::Key).unwrap_or_default(),
-                        value: self.value_as_str(trc::Key::Value).unwrap_or_default(),
-                    },
-                    trc::ManageEvent::NotFound => ManagementApiError::NotFound {
-                        item: self.value_as_str(trc::Key::Key).unwrap_or_default(),
-                    },
-                    trc::ManageEvent::NotSupported => ManagementApiError::Unsupported {
-                        details: self
-                            .value(trc::Key::Details)
-                            .or_else(|| self.value(trc::Key::Reason))
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("Requested action is unsupported"),
-                    },
-                    trc::ManageEvent::AssertFailed => ManagementApiError::AssertFailed,
-                    trc::ManageEvent::Error => ManagementApiError::Other {
-                        reason: self.value_as_str(trc::Key::Reason),
-                        details: self
-                            .value_as_str(trc::Key::Details)
-                            .unwrap_or("Unknown error"),
-                    },
-                }
+        match ManagementApiError::from_error(self) {
+            Some(error @ ManagementApiError::RequestTooLarge { .. }) => {
+                JsonResponse::with_status(StatusCode::PAYLOAD_TOO_LARGE, error).into_http_response()
+            }
+            Some(error) => error.into_http_response(),
+            None if is_unauthorized(self) => HttpResponse::unauthorized(true),
+            None => self.to_request_error().into_http_response(),
+        }
+    }
+
+    fn into_text_response(self) -> HttpResponse {
+        match ManagementApiError::from_error(self) {
+            Some(error) => TextError::new(error.status(), error.message()).into_http_response(),
+            None if is_unauthorized(self) => {
+                TextError::new(StatusCode::UNAUTHORIZED, "authentication required")
+                    .into_http_response()
+                    .with_auth_challenge()
+            }
+            None => {
+                let error = self.to_request_error();
+                TextError::new(
+                    StatusCode::from_u16(error.status).unwrap_or(StatusCode::BAD_REQUEST),
+                    error.detail,
+                )
+                .into_http_response()
             }
-            .into_http_response(),
-            trc::EventType::Auth(
-                trc::AuthEvent::Failed | trc::AuthEvent::Error | trc::AuthEvent::TokenExpired,
-            ) => HttpResponse::unauthorized(true),
-            _ => self.to_request_error().into_http_response(),
         }
     }
 }
 
+fn is_unauthorized(err: &trc::Error) -> bool {
+    matches!(
+        err.as_ref(),
+        trc::EventType::Auth(
+            trc::AuthEvent::Failed | trc::AuthEvent::Error | trc::AuthEvent::TokenExpired,
+        )
+    )
+}
+
 pub trait UnauthorizedResponse {
     fn unauthorized(include_realms: bool) -> Self;
+
+    /// Adds the authentication schemes accepted by the server.
+    fn with_auth_challenge(self) -> Self;
 }
 
 impl UnauthorizedResponse for HttpResponse {
     fn unauthorized(include_realms: bool) -> Self {
         (if include_realms {
-            HttpResponse::new(StatusCode::UNAUTHORIZED)
-                .with_header(header::WWW_AUTHENTICATE, "Bearer realm=\"RMail Server\"")
-                .with_header(header::WWW_AUTHENTICATE, "Basic realm=\"RMail Server\"")
+            HttpResponse::new(StatusCode::UNAUTHORIZED).with_auth_challenge()
         } else {
             HttpResponse::new(StatusCode::UNAUTHORIZED)
         })
         .with_content_type("application/problem+json")
         .with_text_body(serde_json::to_string(&RequestError::unauthorized()).unwrap_or_default())
     }
+
+    fn with_auth_challenge(self) -> Self {
+        self.with_header(header::WWW_AUTHENTICATE, "Bearer realm=\"RMail Server\"")
+            .with_header(header::WWW_AUTHENTICATE, "Basic realm=\"RMail Server\"")
+    }
 }
 
-impl ManagementApiError<'_> {
+impl<'x> ManagementApiError<'x> {
+    fn from_error(err: &'x trc::Error) -> Option<Self> {
+        match err.as_ref() {
+            trc::EventType::Manage(cause) => Some(match cause {
+                trc::ManageEvent::MissingParameter => ManagementApiError::FieldMissing {
+                    field: err.value_as_str(trc::Key::Key).unwrap_or_default(),
+                },
+                trc::ManageEvent::AlreadyExists => ManagementApiError::FieldAlreadyExists {
+                    field: err.value_as_str(trc::Key::Key).unwrap_or_default(),
+                    value: err.value_as_str(trc::Key::Value).unwrap_or_default(),
+                    holder: err.value_as_str(trc::Key::AccountName),
+                    id: err.value_as_uint(trc::Key::Id),
+                },
+                trc::ManageEvent::NotFound => ManagementApiError::NotFound {
+                    item: err.value_as_str(trc::Key::Key).unwrap_or_default(),
+                },
+                trc::ManageEvent::NotSupported => ManagementApiError::Unsupported {
+                    details: err
+                        .value(trc::Key::Details)
+                        .or_else(|| err.value(trc::Key::Reason))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("Requested action is unsupported"),
+                },
+                trc::ManageEvent::AssertFailed => ManagementApiError::AssertFailed,
+                trc::ManageEvent::ReservedName => ManagementApiError::FieldReserved {
+                    field: err.value_as_str(trc::Key::Key).unwrap_or_default(),
+                    value: err.value_as_str(trc::Key::Value).unwrap_or_default(),
+                },
+                trc::ManageEvent::PasswordReused => ManagementApiError::PasswordReused {
+                    history: err
+                        .value(trc::Key::Limit)
+                        .and_then(|v| v.to_uint())
+                        .unwrap_or_default(),
+                },
+                trc::ManageEvent::Error => ManagementApiError::Other {
+                    reason: err.value_as_str(trc::Key::Reason),
+                    details: err
+                        .value_as_str(trc::Key::Details)
+                        .unwrap_or("Unknown error"),
+                },
+            }),
+            trc::EventType::Limit(trc::LimitEvent::SizeRequest) => {
+                Some(ManagementApiError::RequestTooLarge {
+                    limit: err
+                        .value(trc::Key::Limit)
+                        .and_then(|v| v.to_uint())
+                        .unwrap_or_default(),
+                    received: err
+                        .value(trc::Key::Size)
+                        .and_then(|v| v.to_uint())
+                        .unwrap_or_default(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ManagementApiError::FieldAlreadyExists { .. } => StatusCode::CONFLICT,
+            ManagementApiError::NotFound { .. } => StatusCode::NOT_FOUND,
+            ManagementApiError::Unsupported { .. } => StatusCode::NOT_IMPLEMENTED,
+            ManagementApiError::AssertFailed => StatusCode::PRECONDITION_FAILED,
+            ManagementApiError::RequestTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ManagementApiError::FieldMissing { .. }
+            | ManagementApiError::FieldReserved { .. }
+            | ManagementApiError::PasswordReused { .. }
+            | ManagementApiError::Other { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ManagementApiError::FieldAlreadyExists { field, value, .. } => {
+                format!("{field} {value:?} already exists")
+            }
+            ManagementApiError::FieldMissing { field } => format!("missing field {field:?}"),
+            ManagementApiError::NotFound { item } => format!("{item:?} not found"),
+            ManagementApiError::Unsupported { details } => details.to_string(),
+            ManagementApiError::AssertFailed => "assertion failed".to_string(),
+            ManagementApiError::FieldReserved { field, value } => {
+                format!("{field} {value:?} is reserved")
+            }
+            ManagementApiError::PasswordReused { history } => {
+                format!("password was used within the last {history} changes")
+            }
+            ManagementApiError::RequestTooLarge { limit, received } => {
+                format!("request of {received} bytes exceeds the limit of {limit} bytes")
+            }
+            ManagementApiError::Other {
+                details,
+                reason: Some(reason),
+            } => format!("{details}: {reason}"),
+            ManagementApiError::Other { details, .. } => details.to_string(),
+        }
+    }
+
     fn into_http_response(self) -> HttpResponse {
         JsonResponse::new(self).into_http_response()
     }
@@ -4,18 +4,116 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use common::{Server, auth::AccessToken};
+use super::{
+    FutureTimestamp, Timestamp,
+    activation::request_organization_activation,
+    application::OrganizationApplicationManager,
+    backup::TenantBackupManager,
+    branding::{brand_variant, resolved_brand_json},
+    deletion::OrganizationDeletionManager,
+    deliverability::DeliverabilityReport,
+    dns::{DnsManagement, DnsRecord},
+    domain::dns_checks_with_age,
+    events::AuditEventsApi,
+    imap_import::ImapImportManager,
+    import::DirectoryImportManager,
+    invitation::OrganizationInvitationManager,
+    mailbox_stats::MailboxStatsManager,
+    principal::list_order,
+    provision_lock::{ProvisionLock, with_existing_id},
+    reindex::ReindexManager,
+    resource::ResourceManager,
+    sender::SenderCredentialManager,
+    shared_mailbox::SharedMailboxManager,
+    spam::{ManageSpamHandler, SpamClassifyRequest},
+    trial::{handle_trial, trial_end, trial_json},
+};
+use common::{
+    Server,
+    auth::AccessToken,
+    config::{
+        branding::{
+            BrandComponent, BrandNotification, BrandTheme, BrandVariant, Branding,
+            is_valid_logo_url,
+        },
+        groupware::TenantCollections,
+        hostname::{TenantHostname, is_valid_hostname},
+        jmap::{retention::TenantRetention, settings::TenantFolders},
+        login_page::LoginPage,
+        maintenance::TenantMaintenance,
+        overrides::{SettingsValidation, TenantOverrides, TenantProtocol, TenantSetting},
+        password_policy::TenantPasswordPolicy,
+        scripts::{ForwardingPolicy, TenantSieveScript, VacationTemplate},
+        smtp::{
+            auth::TenantDkimPolicy,
+            disclaimer::{DisclaimerVariables, TenantDisclaimer},
+            journal::{JournalTarget, TenantJournaling},
+            queue::{TenantRelay, TenantRouting},
+        },
+        spamfilter::{SenderListEntry, SenderListType, TenantSpamSettings},
+    },
+    storage::{
+        encryption::{resolve_key, validate_key},
+        quota::TenantUsage,
+    },
+};
 use directory::{
-    Permission, Type,
+    Permission, Principal, Type,
     backend::internal::{
-        PrincipalField, PrincipalSet, PrincipalValue,
-        manage::{self, ManageDirectory},
+        MAX_METADATA_KEY_LEN, PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+        TENANT_CONTACT_FIELDS,
+        activity::PrincipalActivity,
+        is_valid_metadata_key,
+        manage::{
+            self, BatchPrincipal, BatchTenant, ManageDirectory, TENANT_PRINCIPAL_TYPES,
+            UpdatePrincipal,
+        },
+        sanitize_contact,
+        trial::trial_days_remaining,
     },
 };
-use http_proto::*;
-use hyper::Method;
-use serde_json::json;
-use std::future::Future;
+use email::{
+    mailbox::manage::MailboxFnc,
+    message::{legal_hold::EmailLegalHold, retention::EmailRetention},
+};
+use groupware::provision::CollectionProvisioning;
+use http_body_util::{StreamBody, combinators::BoxBody};
+use http_proto::{plain::is_plain_text_request, request::decode_path_element, *};
+use hyper::{
+    Method, StatusCode,
+    body::{Bytes, Frame},
+    header,
+};
+use serde_json::{Map, Value, json};
+use smtp::queue::delivery_log::{DeliveryLog, DeliveryOutcome, SmtpDeliveryLog};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+    sync::Arc,
+    time::Instant,
+};
+use store::{
+    Deserialize, IterateParams, ValueKey,
+    ahash::AHashMap,
+    rand::{Rng, distr::Alphanumeric, rng},
+    write::{AlignedBytes, Archive, QueueClass, ValueClass, now},
+};
+use tokio::sync::mpsc;
+use trc::AddContext;
+use utils::{is_public_ip, snowflake::SnowflakeIdGenerator, url_params::UrlParams};
+
+// Maintenance messages are sent back in SMTP and IMAP responses
+const MAX_MAINTENANCE_MESSAGE_LEN: usize = 256;
+
+// Signup attribution is stored in the tenant's metadata, as `referralCode`
+// and `attribution.<key>` entries
+const MAX_REFERRAL_CODE_LEN: usize = 64;
+const MAX_ATTRIBUTION_ENTRIES: usize = 16;
+const MAX_ATTRIBUTION_VALUE_LEN: usize = 256;
+const REFERRAL_CODE_KEY: &str = "referralCode";
+const ATTRIBUTION_PREFIX: &str = "attribution.";
 
 /// Request body for organization provisioning.
 /// Creates a tenant, domain, and admin user in a single API call.
@@ -37,11 +135,218 @@ pub struct OrganizationProvisionRequest {
     #[serde(default)]
     pub brand_logo_url: Option<String>,
     #[serde(default)]
+    pub brand_logo_dark_url: Option<String>,
+    // Either a JSON object or its serialized form
+    #[serde(default, deserialize_with = "deserialize_brand_theme")]
     pub brand_theme: Option<String>,
 
     // Optional org description
     #[serde(default)]
     pub description: Option<String>,
+
+    // Optional calendars and address books of new accounts
+    #[serde(default)]
+    pub collections: Option<TenantCollections>,
+
+    // Optional activation by an out-of-band contact
+    #[serde(default)]
+    pub require_admin_email_verification: bool,
+    #[serde(default)]
+    pub contact_email: Option<String>,
+
+    // Optional trial period, either as an end time or a number of days
+    #[serde(default)]
+    pub trial_ends_at: Option<String>,
+    #[serde(default)]
+    pub trial_days: Option<u32>,
+
+    // Optional billing plan from the plan registry
+    #[serde(default)]
+    pub plan: Option<String>,
+
+    // Optional campaign attribution
+    #[serde(default)]
+    pub referral_code: Option<String>,
+    #[serde(default)]
+    pub attribution: BTreeMap<String, String>,
+
+    // Optional protocols to enable or disable, the rest follow the plan
+    #[serde(default)]
+    pub protocols: BTreeMap<String, bool>,
+
+    // Optional billing and technical contacts
+    #[serde(default)]
+    pub contacts: TenantContacts,
+}
+
+/// Contact details of an organization, an empty or null value clears a
+/// field when updating them.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenantContacts {
+    #[serde(default)]
+    pub billing_email: Option<String>,
+    #[serde(default)]
+    pub technical_contact: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub postal_address: Option<String>,
+    #[serde(default)]
+    pub tax_id: Option<String>,
+}
+
+impl TenantContacts {
+    /// Provided fields, in the order of `TENANT_CONTACT_FIELDS`.
+    fn fields(&self) -> impl Iterator<Item = (PrincipalField, &str)> {
+        [
+            &self.billing_email,
+            &self.technical_contact,
+            &self.phone,
+            &self.postal_address,
+            &self.tax_id,
+        ]
+        .into_iter()
+        .zip(TENANT_CONTACT_FIELDS)
+        .filter_map(|(value, field)| value.as_deref().map(|value| (field, value)))
+    }
+
+    /// Normalizes the provided fields, failing on the first invalid one.
+    fn sanitize(&self) -> trc::Result<Vec<(PrincipalField, String)>> {
+        self.fields()
+            .map(|(field, value)| {
+                sanitize_contact(field, value)
+                    .map(|value| (field, value))
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Invalid contact",
+                            format!("Invalid value {value:?} for {}", field.as_str()).into(),
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Candidate branding of an organization, the values that are not provided
+/// are taken from the current branding.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BrandPreviewRequest {
+    #[serde(default)]
+    pub brand_name: Option<String>,
+    #[serde(default)]
+    pub brand_logo_url: Option<String>,
+    #[serde(default)]
+    pub brand_logo_dark_url: Option<String>,
+    #[serde(default)]
+    pub brand_theme: Option<BrandTheme>,
+    /// Fields that replace those of the current login page.
+    #[serde(default)]
+    pub login_page: Option<LoginPage>,
+}
+
+/// Hostname to map to an organization, users log in with the first domain
+/// of the organization when no default domain is provided.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HostnameRequest {
+    pub hostname: String,
+    #[serde(default)]
+    pub default_domain: Option<String>,
+}
+
+/// Contact details of a tenant as returned by the API, missing fields are null.
+fn contacts_json(tenant: &Principal) -> Value {
+    Value::Object(
+        TENANT_CONTACT_FIELDS
+            .iter()
+            .map(|field| (field.as_str().to_string(), tenant.contact(*field).into()))
+            .collect(),
+    )
+}
+
+/// Contact details as `field=value` entries, included in webhook events.
+fn contact_entries<'x>(fields: impl IntoIterator<Item = (PrincipalField, &'x str)>) -> Vec<String> {
+    fields
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(field, value)| format!("{}={value}", field.as_str()))
+        .collect()
+}
+
+impl OrganizationProvisionRequest {
+    /// Metadata entries recording the campaign the organization came from.
+    fn attribution_metadata(&self) -> Vec<String> {
+        self.referral_code
+            .iter()
+            .map(|code| format!("{REFERRAL_CODE_KEY}={}", code.trim()))
+            .chain(
+                self.attribution
+                    .iter()
+                    .map(|(key, value)| format!("{ATTRIBUTION_PREFIX}{key}={value}")),
+            )
+            .collect()
+    }
+}
+
+/// Accepts the brand theme either as a JSON object or as its serialized form.
+fn deserialize_brand_theme<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match <Option<Value> as serde::Deserialize>::deserialize(deserializer)? {
+        Some(Value::String(theme)) => Ok(Some(theme)),
+        Some(theme @ Value::Object(_)) => Ok(Some(theme.to_string())),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(serde::de::Error::custom("brandTheme must be an object")),
+    }
+}
+
+fn parse_brand_theme(theme: &str) -> trc::Result<BrandTheme> {
+    let theme = serde_json::from_str::<BrandTheme>(theme)
+        .map_err(|err| manage::error("Invalid theme", err.to_string().into()))?;
+    theme
+        .validate()
+        .map_err(|err| manage::error("Invalid theme", err.into()))?;
+    Ok(theme)
+}
+
+fn assert_logo_url(field: PrincipalField, logo_url: &str) -> trc::Result<()> {
+    if logo_url.is_empty() || is_valid_logo_url(logo_url) {
+        Ok(())
+    } else {
+        Err(manage::error(
+            "Invalid logo",
+            format!("{} must be an HTTP or HTTPS URL", field.as_str()).into(),
+        ))
+    }
+}
+
+/// Stored branding of a tenant along with the values used for a variant.
+fn brand_json(tenant: &Principal, variant: BrandVariant) -> Value {
+    json!({
+        "brandName": tenant.brand_name(),
+        "brandLogoUrl": tenant.brand_logo_url(),
+        "brandLogoDarkUrl": tenant.brand_logo_dark_url(),
+        "brandTheme": tenant
+            .brand_theme()
+            .and_then(|theme| serde_json::from_str::<Value>(theme).ok()),
+        "variant": variant.as_str(),
+        "resolved": resolved_brand_json(&Branding::from_principal(tenant), variant),
+    })
+}
+
+/// Request body for configuring an organization's encryption key.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationEncryptionRequest {
+    /// Reference to the key, either `env:<name>` or `file:<path>`.
+    pub key_reference: String,
+    /// RSA public key to wrap the data key with, in which case the
+    /// referenced key is the matching private key.
+    #[serde(default)]
+    pub public_key: Option<String>,
 }
 
 /// Response for organization provisioning
@@ -51,6 +356,15 @@ pub struct OrganizationProvisionResponse {
     pub tenant_id: u32,
     pub domain_id: u32,
     pub admin_id: u32,
+    /// Records to publish for the new domain, including client auto-setup.
+    pub dns_records: Vec<DnsRecord>,
+    /// Whether the organization remains inactive until its contact confirms
+    /// the activation.
+    pub pending_verification: bool,
+    /// Problems that did not prevent provisioning, such as an unknown
+    /// referral code.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
 }
 
 pub trait OrganizationManager: Sync + Send {
@@ -72,7 +386,213 @@ impl OrganizationManager for Server {
         access_token: &AccessToken,
     ) -> trc::Result<HttpResponse> {
         match (path.get(1).copied(), req.method()) {
-            (Some("provision"), &Method::POST) => {
+            (None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::TenantList)?;
+
+                // SPDX-SnippetBegin
+                // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+                // SPDX-License-Identifier: LicenseRef-SEL
+                #[cfg(feature = "enterprise")]
+                if !self.core.is_enterprise_edition() {
+                    return Err(manage::enterprise());
+                }
+                // SPDX-SnippetEnd
+
+                let params = UrlParams::new(req.uri().query());
+                let page: usize = params.parse("page").unwrap_or(0);
+                let limit: usize = params.parse("limit").unwrap_or(0);
+                let mut order = list_order(&params)?;
+                order.created_after = params
+                    .parse::<Timestamp>("createdAfter")
+                    .map(|t| t.into_inner());
+                order.created_before = params
+                    .parse::<Timestamp>("createdBefore")
+                    .map(|t| t.into_inner());
+                let is_csv = params.get("format") == Some("csv")
+                    || req
+                        .headers()
+                        .get(header::ACCEPT)
+                        .and_then(|value| value.to_str().ok())
+                        .is_some_and(|value| value.contains("text/csv"));
+
+                // Parse columns
+                let mut columns = Vec::new();
+                for column in params.get("fields").unwrap_or_default().split(',') {
+                    if let Some(column) = OrganizationColumn::parse(column)
+                        && !columns.contains(&column)
+                    {
+                        columns.push(column);
+                    }
+                }
+                if columns.is_empty() {
+                    columns = OrganizationColumn::ALL.to_vec();
+                }
+
+                let store = self.store();
+                let tenants = if order.is_default() {
+                    store
+                        .list_principals(
+                            params.get("filter"),
+                            access_token.tenant.map(|t| t.id),
+                            &[Type::Tenant],
+                            columns.iter().any(|c| {
+                                matches!(
+                                    c,
+                                    OrganizationColumn::Description
+                                        | OrganizationColumn::Quota
+                                        | OrganizationColumn::CreatedAt
+                                        | OrganizationColumn::TrialStatus
+                                        | OrganizationColumn::TrialDaysRemaining
+                                        | OrganizationColumn::Plan
+                                        | OrganizationColumn::OverLimit
+                                        | OrganizationColumn::ReferralCode
+                                        | OrganizationColumn::Attribution
+                                        | OrganizationColumn::Contact(_)
+                                )
+                            }),
+                            page,
+                            limit,
+                        )
+                        .await?
+                } else {
+                    let mut tenants = store
+                        .list_principals(
+                            params.get("filter"),
+                            access_token.tenant.map(|t| t.id),
+                            &[Type::Tenant],
+                            true,
+                            0,
+                            0,
+                        )
+                        .await?;
+                    if let Some(since) = order.inactive_since {
+                        tenants = tenants.retain_inactive(store, since).await?;
+                    }
+                    tenants.ordered(&order, page, limit)
+                };
+
+                // Dormant accounts are counted for all organizations at once
+                let dormant_users = if columns.contains(&OrganizationColumn::DormantUsers) {
+                    store
+                        .count_inactive_by_tenant(
+                            now().saturating_sub(self.core.jmap.dormant_after),
+                        )
+                        .await?
+                } else {
+                    AHashMap::new()
+                };
+
+                // Usage breakdowns are added up for all organizations at once
+                let usage = if columns.iter().any(|column| {
+                    matches!(
+                        column,
+                        OrganizationColumn::PersonalUsedQuota | OrganizationColumn::SharedUsedQuota
+                    )
+                }) {
+                    self.tenants_usage(&tenants.items.iter().map(|tenant| tenant.id()).collect())
+                        .await?
+                } else {
+                    AHashMap::new()
+                };
+
+                if is_csv {
+                    // Rows are produced by a separate task as the store futures are not Sync
+                    let (tx, mut rx) = mpsc::channel::<Bytes>(32);
+                    let server = self.clone();
+                    tokio::spawn(async move {
+                        let header = columns
+                            .iter()
+                            .map(|column| column.as_str())
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        if tx.send(Bytes::from(format!("{header}\r\n"))).await.is_err() {
+                            return;
+                        }
+
+                        for tenant in tenants.items {
+                            match organization_row(
+                                &server,
+                                &tenant,
+                                &columns,
+                                &dormant_users,
+                                &usage,
+                            )
+                            .await
+                            {
+                                Ok(row) => {
+                                    let row =
+                                        row.iter().map(csv_field).collect::<Vec<_>>().join(",");
+                                    if tx.send(Bytes::from(format!("{row}\r\n"))).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                Err(err) => {
+                                    // Headers are already sent, so the export is truncated
+                                    trc::error!(err.details("Failed to export organization"));
+                                    break;
+                                }
+                            }
+                        }
+                    });
+
+                    Ok(HttpResponse::new(StatusCode::OK)
+                        .with_content_type("text/csv; charset=utf-8")
+                        .with_content_disposition("attachment; filename=\"organizations.csv\"")
+                        .with_no_store()
+                        .with_stream_body(BoxBody::new(StreamBody::new(async_stream::stream! {
+                            while let Some(row) = rx.recv().await {
+                                yield Ok(Frame::data(row));
+                            }
+                        }))))
+                } else if is_plain_text_request(req) {
+                    let mut table = TextTable::new(columns.iter().map(|column| column.as_str()));
+                    for tenant in &tenants.items {
+                        table.push_row(
+                            &organization_row(self, tenant, &columns, &dormant_users, &usage)
+                                .await?,
+                        );
+                    }
+
+                    Ok(table.into_http_response())
+                } else {
+                    let mut items = Vec::with_capacity(tenants.items.len());
+                    for tenant in &tenants.items {
+                        items.push(Value::Object(
+                            columns
+                                .iter()
+                                .map(|column| column.as_str().to_string())
+                                .zip(
+                                    organization_row(
+                                        self,
+                                        tenant,
+                                        &columns,
+                                        &dormant_users,
+                                        &usage,
+                                    )
+                                    .await?,
+                                )
+                                .collect::<Map<_, _>>(),
+                        ));
+                    }
+
+                    Ok(JsonResponse::new(json!({
+                        "data": {
+                            "items": items,
+                            "total": tenants.total,
+                        },
+                    }))
+                    .into_http_response())
+                }
+            }
+            (Some("applications"), _) => {
+                self.handle_organization_applications(req, &path, body, access_token)
+                    .await
+            }
+            (Some("invitations"), _) => {
+                self.handle_organization_invitations(req, &path, body, access_token)
+                    .await
+            }
+            (Some("provision"), &Method::POST) if path.get(2).is_none() => {
                 // Require TenantCreate, DomainCreate, and IndividualCreate permissions
                 access_token.assert_has_permission(Permission::TenantCreate)?;
                 access_token.assert_has_permission(Permission::DomainCreate)?;
@@ -83,129 +603,3714 @@ impl OrganizationManager for Server {
                     body.as_deref().unwrap_or_default(),
                 )
                 .map_err(|err| {
-                    trc::EventType::Resource(trc::ResourceEvent::BadParameters)
-                        .from_json_error(err)
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
                 })?;
 
-                // Validate required fields
-                if request.tenant_name.is_empty() {
-                    return Err(manage::err_missing("tenantName"));
+                let response =
+                    provision_organization_request(self, request, vec![], access_token, false)
+                        .await?;
+                if is_plain_text_request(req) {
+                    Ok(TextFields::from_json(&json!(response)).into_http_response())
+                } else {
+                    Ok(JsonResponse::new(json!({
+                        "data": response,
+                    }))
+                    .into_http_response())
                 }
-                if request.domain.is_empty() {
-                    return Err(manage::err_missing("domain"));
-                }
-                if request.admin_name.is_empty() {
-                    return Err(manage::err_missing("adminName"));
-                }
-                if request.admin_password.is_empty() {
-                    return Err(manage::err_missing("adminPassword"));
-                }
-                if request.admin_email.is_empty() {
-                    return Err(manage::err_missing("adminEmail"));
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("metrics") => {
+                // Tenant admins may only view their own tenant's metrics
+                let tenant_id = organization_id(self, name, access_token).await?;
+                access_token.assert_has_permission(if access_token.tenant.is_some() {
+                    Permission::PrincipalGet
+                } else {
+                    Permission::TenantGet
+                })?;
+
+                if !self.core.metrics.tenants {
+                    return Err(manage::unsupported("Per-tenant metrics are disabled"));
                 }
 
-                let tenant_id = access_token.tenant.map(|t| t.id);
+                let snapshot = self.tenant_metrics_snapshot(tenant_id).await?;
+                if is_plain_text_request(req) {
+                    let mut table = TextTable::new(["metric", "value"]);
+                    for (metric, value) in snapshot.as_object().into_iter().flatten() {
+                        table.push_row([&Value::from(metric.as_str()), value]);
+                    }
 
-                // Step 1: Create the tenant
-                let mut tenant = PrincipalSet::default();
-                tenant.typ = Type::Tenant;
-                tenant
-                    .fields
-                    .insert(PrincipalField::Name, PrincipalValue::String(request.tenant_name));
-                if let Some(description) = &request.description {
-                    tenant.fields.insert(
-                        PrincipalField::Description,
-                        PrincipalValue::String(description.clone()),
-                    );
+                    Ok(table.into_http_response())
+                } else {
+                    Ok(JsonResponse::new(json!({
+                        "data": snapshot,
+                    }))
+                    .into_http_response())
                 }
-                if let Some(brand_name) = &request.brand_name {
-                    tenant.fields.insert(
-                        PrincipalField::BrandName,
-                        PrincipalValue::String(brand_name.clone()),
-                    );
-                }
-                if let Some(brand_logo_url) = &request.brand_logo_url {
-                    tenant.fields.insert(
-                        PrincipalField::BrandLogoUrl,
-                        PrincipalValue::String(brand_logo_url.clone()),
-                    );
-                }
-                if let Some(brand_theme) = &request.brand_theme {
-                    tenant.fields.insert(
-                        PrincipalField::BrandTheme,
-                        PrincipalValue::String(brand_theme.clone()),
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("counts") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+                access_token.assert_has_permission(if access_token.tenant.is_some() {
+                    Permission::PrincipalGet
+                } else {
+                    Permission::TenantGet
+                })?;
+
+                let mut counts = Map::new();
+                for typ in TENANT_PRINCIPAL_TYPES {
+                    counts.insert(
+                        typ.as_str().to_string(),
+                        self.store()
+                            .count_tenant_principals(tenant_id, typ)
+                            .await?
+                            .into(),
                     );
                 }
 
-                let tenant_result = self
-                    .core
-                    .storage
-                    .data
-                    .create_principal(tenant, tenant_id, Some(&access_token.permissions))
-                    .await?;
-                let new_tenant_id = tenant_result.id;
-
-                self.invalidate_principal_caches(tenant_result.changed_principals)
-                    .await;
-
-                // Step 2: Create the domain under this tenant
-                let mut domain = PrincipalSet::default();
-                domain.typ = Type::Domain;
-                domain
-                    .fields
-                    .insert(PrincipalField::Name, PrincipalValue::String(request.domain));
-
-                let domain_result = self
-                    .core
-                    .storage
-                    .data
-                    .create_principal(domain, Some(new_tenant_id), Some(&access_token.permissions))
-                    .await?;
-                let new_domain_id = domain_result.id;
-
-                self.invalidate_principal_caches(domain_result.changed_principals)
-                    .await;
-
-                // Step 3: Create admin user under this tenant with tenant-admin role
-                let mut admin = PrincipalSet::default();
-                admin.typ = Type::Individual;
-                admin
-                    .fields
-                    .insert(PrincipalField::Name, PrincipalValue::String(request.admin_name));
-                admin.fields.insert(
-                    PrincipalField::Secrets,
-                    PrincipalValue::StringList(vec![request.admin_password]),
-                );
-                admin.fields.insert(
-                    PrincipalField::Emails,
-                    PrincipalValue::StringList(vec![request.admin_email]),
-                );
-                admin.fields.insert(
-                    PrincipalField::Roles,
-                    PrincipalValue::StringList(vec!["tenant-admin".to_string()]),
-                );
+                Ok(JsonResponse::new(json!({
+                    "data": counts,
+                }))
+                .into_http_response())
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("usage") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+                access_token.assert_has_permission(if access_token.tenant.is_some() {
+                    Permission::PrincipalGet
+                } else {
+                    Permission::TenantGet
+                })?;
+                access_token.assert_has_permission(Permission::MailboxStats)?;
 
-                let admin_result = self
-                    .core
-                    .storage
-                    .data
-                    .create_principal(admin, Some(new_tenant_id), Some(&access_token.permissions))
-                    .await?;
-                let new_admin_id = admin_result.id;
+                let limit = UrlParams::new(req.uri().query())
+                    .parse::<usize>("limit")
+                    .filter(|limit| *limit > 0)
+                    .unwrap_or(10);
+                let usage = self.tenant_usage(tenant_id).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "personal": usage.personal,
+                        "shared": usage.shared(),
+                        "sharedMailboxes": usage.shared_mailboxes,
+                        "groups": usage.groups,
+                        "domains": domain_usage(self, tenant_id).await?,
+                        "topFolders": top_folders(self, tenant_id, limit).await?,
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("health") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+                access_token.assert_has_permission(if access_token.tenant.is_some() {
+                    Permission::PrincipalGet
+                } else {
+                    Permission::TenantGet
+                })?;
+
+                // Certificate and DNS issues are reported for every domain of the organization
+                let fresh = UrlParams::new(req.uri().query()).has_key("fresh");
+                let mut healthy = true;
+                let mut domains = Vec::new();
+                for domain in self
+                    .store()
+                    .list_principals(None, Some(tenant_id), &[Type::Domain], false, 0, 0)
+                    .await?
+                    .items
+                {
+                    let certificates = self.domain_certificates(domain.name());
+                    let dns = self.domain_dns_checks(domain.name(), fresh).await;
+                    healthy &= certificates.iter().all(|cert| cert.is_healthy())
+                        && dns.iter().all(|check| check.is_healthy());
+                    domains.push(json!({
+                        "name": domain.name(),
+                        "certificates": certificates,
+                        "dns": dns_checks_with_age(&dns),
+                    }));
+                }
+
+                // Data encrypted with a key that can no longer be obtained is unreadable
+                let encryption = encryption_status(self, tenant_id).await;
+                healthy &= encryption["keyAvailable"].as_bool().unwrap_or(true);
 
-                self.invalidate_principal_caches(admin_result.changed_principals)
-                    .await;
+                // Organizations are not usable until they are activated
+                let activation = self.tenant_activation(tenant_id);
+                healthy &= activation.is_none();
 
                 Ok(JsonResponse::new(json!({
                     "data": {
-                        "tenantId": new_tenant_id,
-                        "domainId": new_domain_id,
-                        "adminId": new_admin_id,
-                    }
+                        "healthy": healthy,
+                        "domains": domains,
+                        "encryption": encryption,
+                        "pendingVerification": activation,
+                    },
                 }))
                 .into_http_response())
             }
+            (Some(name), _) if path.get(2).copied() == Some("trial") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_trial(self, req, &path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("spam-settings") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_spam_settings(self, req, &path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("senders") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                // Allow and block lists share the path with send-only credentials
+                if path
+                    .get(3)
+                    .is_some_and(|list| SenderListType::parse(list).is_some())
+                {
+                    handle_sender_lists(self, req, &path, body, tenant_id, access_token).await
+                } else {
+                    self.handle_senders(req, path, body, tenant_id, access_token)
+                        .await
+                }
+            }
+            (Some(name), _) if path.get(2).copied() == Some("sieve") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_sieve_scripts(self, req, &path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("routing") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_routing(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("dkim-policy") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_dkim_policy(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("folders") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_folders(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("collections") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_collections(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("encryption") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_encryption(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("vacation") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_vacation(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("brand") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_brand(self, req, path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("login-page") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_login_page(self, req, path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("hostnames") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_hostnames(self, req, path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("contacts") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_contacts(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("disclaimer") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_disclaimer(self, req, &path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("journaling") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_journaling(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("forwarding") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_forwarding_policy(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("settings") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_setting_overrides(self, req, &path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("maintenance") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_maintenance(self, req, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("retention") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_retention(self, req, &path, body, tenant_id, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("shared-mailboxes") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_shared_mailboxes(req, path, body, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("resources") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_resources(req, path, body, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("tags") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_tags(self, tenant_id, access_token).await
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("legal-holds") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_legal_holds(self, tenant_id, access_token).await
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("audit") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_organization_audit(req, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("deliveries") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_deliveries(self, req, &path, tenant_id, access_token).await
+            }
+            (Some(name), &Method::GET) if path.get(2).copied() == Some("deliverability") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_deliverability(req, &path, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), _)
+                if path.get(2).copied() == Some("import")
+                    && path.get(3).copied() == Some("imap") =>
+            {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_imap_import(req, path, body, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("import") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_directory_import(req, path, body, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("reindex") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_reindex(req, path, body, tenant_id, Type::Tenant, access_token)
+                    .await
+            }
+            (Some(name), _) if matches!(path.get(2).copied(), Some("backup" | "backups")) => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_tenant_backup(req, path, body, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("deletion-report") => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_deletion_report(req, &path, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), &Method::DELETE) if path.get(2).is_none() => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                self.handle_organization_deletion(req, tenant_id, access_token)
+                    .await
+            }
+            (Some(name), &Method::GET | &Method::PATCH) if path.get(2).is_none() => {
+                let tenant_id = organization_id(self, name, access_token).await?;
+
+                handle_organization(self, req, body, tenant_id, access_token).await
+            }
             _ => Err(trc::ResourceEvent::NotFound.into_err()),
         }
     }
 }
+
+async fn handle_organization(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    if req.method() == Method::PATCH {
+        access_token.assert_has_permission(if access_token.tenant.is_some() {
+            Permission::PrincipalUpdate
+        } else {
+            Permission::TenantUpdate
+        })?;
+
+        let patch =
+            serde_json::from_slice::<Map<String, Value>>(body.as_deref().unwrap_or_default())
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+        let mut blob_store = None;
+        let mut migrate_blobs = false;
+        let mut arc_seal = None;
+        let mut plan = None;
+        for (key, value) in patch {
+            // Blob stores and plans are assigned by system administrators only
+            if matches!(key.as_str(), "blobStore" | "migrateBlobs" | "plan") {
+                access_token.assert_has_permission(Permission::TenantUpdate)?;
+            }
+
+            match (key.as_str(), value) {
+                ("arcSeal", Value::Null) => {
+                    arc_seal = Some(None);
+                }
+                ("arcSeal", Value::String(signature)) => {
+                    // Forwarded messages are sealed with a key of one of the tenant's domains
+                    let is_tenant_domain = match server.get_signature_domain(&signature) {
+                        Some(domain) => server
+                            .store()
+                            .get_principal_info(&domain)
+                            .await?
+                            .is_some_and(|info| {
+                                info.typ == Type::Domain && info.tenant == Some(tenant_id)
+                            }),
+                        None => false,
+                    };
+                    if !is_tenant_domain {
+                        return Err(manage::error(
+                            "Invalid signature",
+                            format!(
+                                "Signature {signature:?} does not sign for a domain of this organization"
+                            )
+                            .into(),
+                        ));
+                    }
+                    arc_seal = Some(Some(signature));
+                }
+                ("blobStore", Value::Null) => {
+                    blob_store = Some(None);
+                }
+                ("blobStore", Value::String(store_id)) => {
+                    if !server.core.storage.blobs.contains_key(&store_id) {
+                        return Err(manage::error(
+                            "Invalid blob store",
+                            format!("Blob store {store_id:?} is not configured").into(),
+                        ));
+                    }
+                    blob_store = Some(Some(store_id));
+                }
+                ("migrateBlobs", Value::Bool(value)) => {
+                    migrate_blobs = value;
+                }
+                ("plan", Value::Null) => {
+                    plan = Some(String::new());
+                }
+                ("plan", Value::String(name)) => {
+                    server.assert_plan_exists(&name)?;
+                    plan = Some(name);
+                }
+                (key, _) => {
+                    return Err(manage::error(
+                        "Invalid parameter",
+                        format!("Invalid value for {key:?}").into(),
+                    ));
+                }
+            }
+        }
+
+        // Only new blobs are written to the assigned store, existing blobs are
+        // moved to it in the background when requested
+        if let Some(blob_store) = blob_store {
+            server
+                .update_tenant_blob_store(tenant_id, blob_store)
+                .await?;
+        }
+        if let Some(arc_seal) = arc_seal {
+            server.update_tenant_arc_sealer(tenant_id, arc_seal).await?;
+        }
+        // Moving to a smaller plan is allowed, the organization is then
+        // reported as over its limits until usage is reduced
+        if let Some(plan) = plan {
+            let changed_principals = server
+                .store()
+                .update_principal(UpdatePrincipal::by_id(tenant_id).with_updates(vec![
+                    PrincipalUpdate::set(PrincipalField::Plan, PrincipalValue::String(plan)),
+                ]))
+                .await?;
+            server.invalidate_principal_caches(changed_principals).await;
+        }
+        if migrate_blobs && server.start_blob_migration(tenant_id).is_none() {
+            return Err(manage::error(
+                "Migration in progress",
+                "A blob migration is already running for this organization".into(),
+            ));
+        }
+    } else {
+        access_token.assert_has_permission(if access_token.tenant.is_some() {
+            Permission::PrincipalGet
+        } else {
+            Permission::TenantGet
+        })?;
+    }
+
+    let tenant = server
+        .store()
+        .get_principal(tenant_id)
+        .await?
+        .ok_or_else(|| manage::not_found(tenant_id))?;
+
+    // Shared mailboxes and groups are accounted for separately from the
+    // personal accounts of the tenant's users
+    let shared_mailboxes = server.tenant_shared_mailboxes(tenant_id).await?;
+    let usage = server.tenant_usage(tenant_id).await?;
+    let limits = server.tenant_limits(&tenant);
+
+    Ok(JsonResponse::new(json!({
+        "data": {
+            "id": tenant_id,
+            "name": tenant.name(),
+            "description": tenant.description(),
+            "contacts": contacts_json(&tenant),
+            "hostnames": server
+                .tenant_hostnames(tenant_id)
+                .iter()
+                .map(|mapping| hostname_json(server, mapping))
+                .collect::<Vec<_>>(),
+            "quota": tenant.quota(),
+            "usedQuota": server.get_used_quota(tenant_id).await?.max(0),
+            "blobStore": server.tenant_blob_store(tenant_id),
+            "arcSeal": server.tenant_arc_sealer(tenant_id),
+            "maintenance": server.tenant_maintenance(tenant_id),
+            "pendingVerification": server.tenant_activation(tenant_id),
+            "trial": trial_json(tenant.trial_ends_at(), now()),
+            "plan": server.principal_plan(&tenant).map(|plan| json!({
+                "name": plan.name,
+                "features": plan.features,
+            })),
+            "limits": {
+                "quota": limits.quota,
+                "maxUsers": limits.max_users,
+                "maxDomains": limits.max_domains,
+            },
+            "overLimit": server.tenant_exceeded_limits(&tenant).await?,
+            "sharedMailboxes": {
+                "count": shared_mailboxes.len(),
+                "usedQuota": usage.shared_mailboxes,
+            },
+            "usage": {
+                "personal": usage.personal,
+                "shared": usage.shared(),
+                "sharedMailboxes": usage.shared_mailboxes,
+                "groups": usage.groups,
+            },
+            "domains": domain_usage(server, tenant_id).await?,
+            "blobMigration": server
+                .inner
+                .data
+                .blob_migrations
+                .get(tenant_id)
+                .map(|migration| migration.summary()),
+        },
+    }))
+    .into_http_response())
+}
+
+async fn handle_brand(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+    let params = UrlParams::new(req.uri().query());
+    let variant = brand_variant(req, &params)?;
+
+    match (path.get(3).copied(), req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+        }
+        (None, &Method::PATCH) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            // Only the provided fields are changed, null clears a field
+            let patch =
+                serde_json::from_slice::<Map<String, Value>>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+            let mut updates = Vec::with_capacity(patch.len());
+            for (key, value) in patch {
+                let field = PrincipalField::try_parse(&key)
+                    .filter(|field| {
+                        matches!(
+                            field,
+                            PrincipalField::BrandName
+                                | PrincipalField::BrandLogoUrl
+                                | PrincipalField::BrandLogoDarkUrl
+                                | PrincipalField::BrandTheme
+                        )
+                    })
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Invalid parameter",
+                            format!("Invalid value for {key:?}").into(),
+                        )
+                    })?;
+                let value = match (field, value) {
+                    (_, Value::Null) => String::new(),
+                    (PrincipalField::BrandTheme, theme @ Value::Object(_)) => {
+                        let theme = theme.to_string();
+                        parse_brand_theme(&theme)?;
+                        theme
+                    }
+                    (PrincipalField::BrandTheme, Value::String(theme)) => {
+                        if !theme.is_empty() {
+                            parse_brand_theme(&theme)?;
+                        }
+                        theme
+                    }
+                    (PrincipalField::BrandName, Value::String(name)) => name.trim().to_string(),
+                    (_, Value::String(logo_url)) => {
+                        let logo_url = logo_url.trim().to_string();
+                        assert_logo_url(field, &logo_url)?;
+                        logo_url
+                    }
+                    _ => {
+                        return Err(manage::error(
+                            "Invalid parameter",
+                            format!("Invalid value for {key:?}").into(),
+                        ));
+                    }
+                };
+                updates.push(PrincipalUpdate::set(field, PrincipalValue::String(value)));
+            }
+
+            if !updates.is_empty() {
+                let details = updates
+                    .iter()
+                    .map(|update| update.field.as_str())
+                    .collect::<Vec<_>>();
+                server
+                    .store()
+                    .update_principal(UpdatePrincipal::by_id(tenant_id).with_updates(updates))
+                    .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::PrincipalUpdated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = tenant_id,
+                    Type = Type::Tenant.as_str(),
+                    Details = details,
+                );
+            }
+        }
+        (Some("preview"), &Method::POST) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            return preview_brand(
+                server,
+                &params,
+                variant,
+                body,
+                server.tenant_login_page(tenant_id),
+                tenant_id.into(),
+                None,
+            )
+            .await;
+        }
+        _ => return Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+
+    let tenant = server
+        .store()
+        .get_principal(tenant_id)
+        .await?
+        .ok_or_else(|| manage::not_found(tenant_id))?;
+
+    Ok(JsonResponse::new(json!({
+        "data": brand_json(&tenant, variant),
+    }))
+    .into_http_response())
+}
+
+/// Renders a component with the candidate branding merged over the current
+/// one, the branding of the tenant is used for its domains and the domain
+/// name for the domains without a tenant.
+pub(super) async fn preview_brand(
+    server: &Server,
+    params: &UrlParams<'_>,
+    variant: BrandVariant,
+    body: Option<Vec<u8>>,
+    login_page: Option<Arc<LoginPage>>,
+    tenant_id: Option<u32>,
+    domain: Option<&str>,
+) -> trc::Result<HttpResponse> {
+    let component = match params.get("component") {
+        Some(component) => BrandComponent::parse(component).ok_or_else(|| {
+            manage::error(
+                "Invalid component",
+                format!("Unknown component {component:?}, expected login or email").into(),
+            )
+        })?,
+        None => BrandComponent::Login,
+    };
+    let request = if body.as_deref().is_some_and(|body| !body.is_empty()) {
+        serde_json::from_slice::<BrandPreviewRequest>(body.as_deref().unwrap_or_default()).map_err(
+            |err| trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err),
+        )?
+    } else {
+        BrandPreviewRequest::default()
+    };
+
+    // Merge the candidate over the current branding, nothing is stored
+    let mut branding = if let Some(tenant_id) = tenant_id {
+        Branding::from_principal(
+            &server
+                .store()
+                .get_principal(tenant_id)
+                .await?
+                .ok_or_else(|| manage::not_found(tenant_id))?,
+        )
+    } else {
+        Branding {
+            name: domain.unwrap_or_default().to_string(),
+            ..Default::default()
+        }
+    };
+    if let Some(brand_name) = request
+        .brand_name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+    {
+        branding.name = brand_name;
+    }
+    for (field, candidate, logo_url) in [
+        (
+            PrincipalField::BrandLogoUrl,
+            request.brand_logo_url,
+            &mut branding.logo_url,
+        ),
+        (
+            PrincipalField::BrandLogoDarkUrl,
+            request.brand_logo_dark_url,
+            &mut branding.logo_dark_url,
+        ),
+    ] {
+        if let Some(candidate) = candidate.map(|url| url.trim().to_string()) {
+            assert_logo_url(field, &candidate)?;
+            *logo_url = Some(candidate).filter(|url| !url.is_empty());
+        }
+    }
+    if let Some(theme) = request.brand_theme {
+        theme
+            .validate()
+            .map_err(|err| manage::error("Invalid theme", err.into()))?;
+        branding.theme.merge(theme);
+    }
+    let login_page = match request.login_page {
+        Some(mut candidate) => {
+            candidate
+                .sanitize()
+                .map_err(|err| manage::error("Invalid login page", err.into()))?;
+            let mut page = login_page.as_deref().cloned().unwrap_or_default();
+            page.merge(candidate);
+            Some(Arc::new(page))
+        }
+        None => login_page,
+    };
+
+    Ok(HtmlResponse::new(match component {
+        BrandComponent::Login => branding.render_login(variant, login_page.as_deref()),
+        BrandComponent::Email => branding.render_email(
+            variant,
+            &BrandNotification {
+                title: "Welcome to your new mailbox",
+                message: BRAND_SAMPLE_MESSAGE,
+                action: Some(("Sign in", "#")),
+            },
+        ),
+    })
+    .into_http_response())
+}
+
+async fn handle_login_page(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match (path.get(3).copied(), req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_login_page(tenant_id),
+            }))
+            .into_http_response())
+        }
+        (None, &Method::PUT) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let page = parse_login_page(body)?;
+            server
+                .update_tenant_login_page(tenant_id, page.clone().into())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": page,
+            }))
+            .into_http_response())
+        }
+        (None, &Method::DELETE) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            server.update_tenant_login_page(tenant_id, None).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        (Some("preview"), &Method::POST) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            let params = UrlParams::new(req.uri().query());
+            preview_brand(
+                server,
+                &params,
+                brand_variant(req, &params)?,
+                body,
+                server.tenant_login_page(tenant_id),
+                tenant_id.into(),
+                None,
+            )
+            .await
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Parses a login page, the custom styles are rewritten keeping only the
+/// allowed rules.
+pub(super) fn parse_login_page(body: Option<Vec<u8>>) -> trc::Result<LoginPage> {
+    let mut page = serde_json::from_slice::<LoginPage>(body.as_deref().unwrap_or_default())
+        .map_err(|err| {
+            trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+        })?;
+    page.sanitize()
+        .map_err(|err| manage::error("Invalid login page", err.into()))?;
+    Ok(page)
+}
+
+async fn handle_hostnames(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+    access_token.assert_has_permission(match (req.method(), is_tenant_admin) {
+        (&Method::GET, true) => Permission::PrincipalGet,
+        (&Method::GET, false) => Permission::TenantGet,
+        (_, true) => Permission::PrincipalUpdate,
+        (_, false) => Permission::TenantUpdate,
+    })?;
+
+    let hostname = path
+        .get(3)
+        .map(|hostname| decode_path_element(hostname).to_lowercase());
+    let mapping = match &hostname {
+        Some(hostname) => Some(
+            server
+                .tenant_hostname(hostname)
+                .filter(|mapping| mapping.tenant_id == tenant_id)
+                .ok_or_else(|| trc::ResourceEvent::NotFound.into_err())?,
+        ),
+        None => None,
+    };
+
+    match (mapping, path.get(4).copied(), req.method()) {
+        (None, None, &Method::GET) => Ok(JsonResponse::new(json!({
+            "data": server
+                .tenant_hostnames(tenant_id)
+                .iter()
+                .map(|mapping| hostname_json(server, mapping))
+                .collect::<Vec<_>>(),
+        }))
+        .into_http_response()),
+        (None, None, &Method::POST) => {
+            let request =
+                serde_json::from_slice::<HostnameRequest>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            let hostname = request.hostname.trim().trim_end_matches('.').to_lowercase();
+            if !is_valid_hostname(&hostname) {
+                return Err(manage::error(
+                    "Invalid hostname",
+                    format!("{hostname:?} is not a valid hostname").into(),
+                ));
+            } else if hostname == server.core.network.server_name
+                || server.tenant_hostname(&hostname).is_some()
+                || is_foreign_domain(server, &hostname, tenant_id).await?
+            {
+                // Domains of other organizations and their subdomains cannot
+                // be claimed
+                return Err(manage::error(
+                    "Hostname already mapped",
+                    format!("{hostname:?} is already in use").into(),
+                ));
+            }
+
+            // Users log in with one of the domains of the organization
+            let default_domain = match request.default_domain {
+                Some(domain) => {
+                    let domain = domain.trim().to_lowercase();
+                    if !server.is_tenant_domain(&domain, Some(tenant_id)).await? {
+                        return Err(manage::error(
+                            "Invalid default domain",
+                            format!("{domain:?} is not a domain of this organization").into(),
+                        ));
+                    }
+                    domain
+                }
+                None => server
+                    .store()
+                    .list_principals(None, Some(tenant_id), &[Type::Domain], false, 0, 0)
+                    .await?
+                    .items
+                    .into_iter()
+                    .map(|domain| domain.name().to_string())
+                    .min()
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Invalid default domain",
+                            "The organization has no domains".into(),
+                        )
+                    })?,
+            };
+
+            let mapping = TenantHostname {
+                hostname: hostname.clone(),
+                tenant_id,
+                default_domain,
+                token: rng()
+                    .sample_iter(Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect(),
+                created_at: now(),
+                verified_at: None,
+            };
+            server
+                .update_tenant_hostname(&hostname, mapping.clone().into())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": hostname_json(server, &mapping),
+            }))
+            .into_http_response())
+        }
+        (Some(mapping), None, &Method::GET) => Ok(JsonResponse::new(json!({
+            "data": hostname_json(server, &mapping),
+        }))
+        .into_http_response()),
+        (Some(mapping), None, &Method::DELETE) => {
+            server
+                .update_tenant_hostname(&mapping.hostname, None)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        (Some(mapping), Some("verify"), &Method::POST) => {
+            // Only the global administrator can skip the DNS check
+            let force = UrlParams::new(req.uri().query()).get("force") == Some("true");
+            if force && is_tenant_admin {
+                return Err(manage::unsupported(
+                    "Only administrators can verify hostnames without a DNS record",
+                ));
+            } else if !force && !server.has_hostname_challenge(&mapping).await? {
+                return Err(manage::error(
+                    "Verification failed",
+                    format!(
+                        "TXT record {} does not contain {:?}",
+                        mapping.challenge_name(),
+                        mapping.challenge_value()
+                    )
+                    .into(),
+                ));
+            }
+
+            let mut mapping = mapping.as_ref().clone();
+            if mapping.verified_at.is_none() {
+                mapping.verified_at = now().into();
+                server
+                    .update_tenant_hostname(&mapping.hostname, mapping.clone().into())
+                    .await?;
+            }
+            server
+                .request_domain_certificates(&mapping.hostname, false)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": hostname_json(server, &mapping),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Whether the hostname is, or is a subdomain of, a domain that does not
+/// belong to the tenant.
+async fn is_foreign_domain(server: &Server, hostname: &str, tenant_id: u32) -> trc::Result<bool> {
+    let mut domain = hostname;
+    loop {
+        if server
+            .store()
+            .get_principal_info(domain)
+            .await?
+            .is_some_and(|info| info.typ == Type::Domain && info.tenant != Some(tenant_id))
+        {
+            return Ok(true);
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => domain = parent,
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// Mapping of a hostname, pending mappings include the DNS record to publish.
+fn hostname_json(server: &Server, mapping: &TenantHostname) -> Value {
+    let mut value = json!(mapping);
+    value["status"] = if mapping.is_verified() {
+        "verified"
+    } else {
+        "pending"
+    }
+    .into();
+    value["challenge"] = if mapping.is_verified() {
+        Value::Null
+    } else {
+        json!({
+            "type": "TXT",
+            "name": mapping.challenge_name(),
+            "value": mapping.challenge_value(),
+        })
+    };
+    value["certificate"] = json!(
+        server
+            .domain_certificates(&mapping.hostname)
+            .into_iter()
+            .next()
+    );
+    value
+}
+
+async fn handle_contacts(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+        }
+        Method::PATCH => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            // Only the provided fields are changed
+            let contacts =
+                serde_json::from_slice::<TenantContacts>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            let mut null_fields = Vec::new();
+            if let Ok(Value::Object(patch)) =
+                serde_json::from_slice::<Value>(body.as_deref().unwrap_or_default())
+            {
+                for (key, value) in patch {
+                    if value.is_null()
+                        && let Some(field) = PrincipalField::try_parse(&key)
+                            .filter(|field| TENANT_CONTACT_FIELDS.contains(field))
+                    {
+                        null_fields.push((field, String::new()));
+                    }
+                }
+            }
+            let changes = contacts
+                .sanitize()?
+                .into_iter()
+                .chain(null_fields)
+                .collect::<Vec<_>>();
+
+            if !changes.is_empty() {
+                let updates = changes
+                    .iter()
+                    .map(|(field, value)| {
+                        PrincipalUpdate::set(*field, PrincipalValue::String(value.clone()))
+                    })
+                    .collect();
+                server
+                    .store()
+                    .update_principal(UpdatePrincipal::by_id(tenant_id).with_updates(updates))
+                    .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::PrincipalUpdated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = tenant_id,
+                    Type = Type::Tenant.as_str(),
+                    Details = contact_entries(
+                        changes
+                            .iter()
+                            .map(|(field, value)| (*field, value.as_str()))
+                    ),
+                );
+            }
+        }
+        _ => return Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+
+    let tenant = server
+        .store()
+        .get_principal(tenant_id)
+        .await?
+        .ok_or_else(|| manage::not_found(tenant_id))?;
+
+    Ok(JsonResponse::new(json!({
+        "data": contacts_json(&tenant),
+    }))
+    .into_http_response())
+}
+
+async fn handle_spam_settings(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match (path.get(3).copied(), req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server
+                    .tenant_spam_settings(tenant_id)
+                    .map(|settings| settings.as_ref().clone())
+                    .unwrap_or_default(),
+            }))
+            .into_http_response())
+        }
+        (None, &Method::PATCH) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            // Settings are merged with the current ones, null values reset them
+            let patch =
+                serde_json::from_slice::<Map<String, Value>>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+            let mut settings = match serde_json::to_value(
+                server
+                    .tenant_spam_settings(tenant_id)
+                    .map(|settings| settings.as_ref().clone())
+                    .unwrap_or_default(),
+            ) {
+                Ok(Value::Object(settings)) => settings,
+                _ => Map::new(),
+            };
+            for (key, value) in patch {
+                if value.is_null() {
+                    settings.remove(&key);
+                } else {
+                    settings.insert(key, value);
+                }
+            }
+            let mut settings = serde_json::from_value::<TenantSpamSettings>(Value::Object(
+                settings,
+            ))
+            .map_err(|err| {
+                trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+            })?;
+            for list in [&mut settings.trusted_domains, &mut settings.languages] {
+                for value in list.iter_mut() {
+                    *value = value.trim().to_lowercase();
+                }
+                list.sort_unstable();
+                list.dedup();
+            }
+            settings
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            server
+                .update_tenant_spam_settings(tenant_id, settings.clone())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": settings,
+            }))
+            .into_http_response())
+        }
+        (Some("test"), &Method::POST) => {
+            access_token.assert_has_permission(Permission::SpamFilterTest)?;
+
+            // The envelope of the raw message is optionally provided as parameters
+            let params = UrlParams::new(req.uri().query());
+            let request = SpamClassifyRequest {
+                message: String::from_utf8_lossy(body.as_deref().unwrap_or_default()).into_owned(),
+                remote_ip: params
+                    .parse("remote-ip")
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                ehlo_domain: params.get("ehlo").unwrap_or_default().to_string(),
+                authenticated_as: None,
+                is_tls: true,
+                env_from: params.get("from").unwrap_or_default().to_string(),
+                env_from_flags: 0,
+                env_rcpt_to: params
+                    .get("to")
+                    .map(|rcpt| vec![rcpt.to_lowercase()])
+                    .unwrap_or_default(),
+            };
+
+            Ok(JsonResponse::new(json!({
+                "data": server
+                    .spam_classify_request(
+                        request,
+                        Some(tenant_id),
+                        server.inner.data.span_id_gen.generate(),
+                    )
+                    .await?,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_sender_lists(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+    let list_type = path
+        .get(3)
+        .copied()
+        .and_then(SenderListType::parse)
+        .ok_or_else(|| trc::ResourceEvent::NotFound.into_err())?;
+    let mut entries = server
+        .tenant_sender_lists(tenant_id)
+        .map(|lists| lists.list(list_type).entries.clone())
+        .unwrap_or_default();
+    let now = now();
+    entries.retain(|entry| !entry.is_expired(now));
+
+    match (path.get(4).copied(), req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "items": entries,
+                    "total": entries.len(),
+                },
+            }))
+            .into_http_response())
+        }
+        (None, &Method::POST) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut entry =
+                serde_json::from_slice::<SenderListEntry>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            entry
+                .normalize()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+            if entry.is_expired(now) {
+                return Err(manage::error(
+                    "Invalid expiration",
+                    "Expiration must be in the future".into(),
+                ));
+            }
+
+            // Adding an existing value replaces its expiration and comment
+            entry.id = server.generate_snowflake_id().to_string();
+            entries.retain(|item| item.value != entry.value);
+            entries.push(entry.clone());
+
+            server
+                .update_tenant_sender_list(tenant_id, list_type, entries)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": entry,
+            }))
+            .into_http_response())
+        }
+        (Some(entry_id), &Method::DELETE) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let total = entries.len();
+            entries.retain(|entry| entry.id != entry_id);
+            if entries.len() == total {
+                return Err(trc::ResourceEvent::NotFound.into_err());
+            }
+
+            server
+                .update_tenant_sender_list(tenant_id, list_type, entries)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_routing(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server
+                    .tenant_routing(tenant_id)
+                    .map(|routes| routes.settings.clone())
+                    .unwrap_or_default(),
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut routing =
+                serde_json::from_slice::<TenantRouting>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            routing.domains = std::mem::take(&mut routing.domains)
+                .into_iter()
+                .map(|(domain, relay)| (domain.trim().to_lowercase(), relay))
+                .collect();
+
+            // Secrets are write-only, keep the current ones when they are omitted
+            if let Some(current) = server.tenant_routing(tenant_id) {
+                let current = &current.settings;
+                if let (Some(relay), Some(current)) = (&mut routing.relay, &current.relay) {
+                    keep_relay_secret(relay, current)?;
+                }
+                for (domain, relay) in routing.domains.iter_mut() {
+                    if let Some(current) = current.domains.get(domain) {
+                        keep_relay_secret(relay, current)?;
+                    }
+                }
+            }
+            routing
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+            if is_tenant_admin {
+                for relay in routing.relay.iter().chain(routing.domains.values()) {
+                    assert_public_relay(relay).await?;
+                }
+            }
+
+            server
+                .update_tenant_routing(tenant_id, routing.clone())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": routing,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_dkim_policy(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server
+                    .tenant_dkim_policy(tenant_id)
+                    .map(|policy| policy.settings.clone())
+                    .unwrap_or_default(),
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut policy =
+                serde_json::from_slice::<TenantDkimPolicy>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            policy.domains = std::mem::take(&mut policy.domains)
+                .into_iter()
+                .map(|(domain, signing)| (domain.trim().to_lowercase(), signing))
+                .collect();
+            for signing in policy.policy.iter_mut().chain(policy.domains.values_mut()) {
+                for header in signing.headers.iter_mut() {
+                    *header = header.trim().to_string();
+                }
+            }
+            policy
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            server
+                .update_tenant_dkim_policy(tenant_id, policy.clone())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": policy,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_folders(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            let folders =
+                server
+                    .tenant_folders(tenant_id)
+                    .await?
+                    .unwrap_or_else(|| TenantFolders {
+                        folders: server.core.jmap.provision_folders.clone(),
+                    });
+
+            Ok(JsonResponse::new(json!({
+                "data": folders,
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut folders =
+                serde_json::from_slice::<TenantFolders>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            for folder in &mut folders.folders {
+                folder.role = folder.role.take().map(|role| role.trim().to_lowercase());
+                folder.name = folder.name.take().map(|name| name.trim().to_string());
+            }
+            folders
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            server
+                .update_tenant_folders(tenant_id, folders.clone())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": folders,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_collections(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_collections(tenant_id).await?.unwrap_or_default(),
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut collections =
+                serde_json::from_slice::<TenantCollections>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            for collection in collections
+                .calendars
+                .iter_mut()
+                .chain(collections.address_books.iter_mut())
+            {
+                collection.name = collection.name.trim().to_string();
+                collection.display_name = collection
+                    .display_name
+                    .take()
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty());
+            }
+            collections
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            server
+                .update_tenant_collections(tenant_id, collections.clone())
+                .await?;
+
+            // Shared collections exist before the first account is created
+            server.provision_tenant_collections(tenant_id).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": collections,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_encryption(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if access_token.tenant.is_some() {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+        }
+        Method::PUT => {
+            // Key references point to the server's environment, so only system
+            // administrators may configure them
+            access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+            let request = serde_json::from_slice::<OrganizationEncryptionRequest>(
+                body.as_deref().unwrap_or_default(),
+            )
+            .map_err(|err| {
+                trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+            })?;
+            if request.key_reference.is_empty() {
+                return Err(manage::err_missing("keyReference"));
+            }
+            let public_key = request.public_key.filter(|key| !key.trim().is_empty());
+            let key = resolve_key(&request.key_reference)
+                .await
+                .and_then(|key| validate_key(&key, public_key.as_deref()).map(|_| key))
+                .map_err(|reason| manage::error("Invalid encryption key", reason.into()))?;
+
+            // Rotating the key requires the current key to unwrap the data key
+            if server.tenant_encryption(tenant_id).is_some() {
+                server
+                    .verify_tenant_key(tenant_id)
+                    .await
+                    .map_err(|reason| manage::error("Encryption key unavailable", reason.into()))?;
+            }
+
+            server
+                .update_tenant_encryption(tenant_id, request.key_reference, &key, public_key)
+                .await?;
+        }
+        Method::DELETE => {
+            access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+            if !server.disable_tenant_encryption(tenant_id).await? {
+                return Err(manage::not_found("encryption"));
+            }
+        }
+        _ => return Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+
+    Ok(JsonResponse::new(json!({
+        "data": encryption_status(server, tenant_id).await,
+    }))
+    .into_http_response())
+}
+
+async fn encryption_status(server: &Server, tenant_id: u32) -> Value {
+    if let Some(settings) = server.tenant_encryption(tenant_id) {
+        let key_error = server.verify_tenant_key(tenant_id).await.err();
+        json!({
+            "enabled": settings.enabled,
+            "keyReference": settings.key,
+            "publicKey": settings.public_key,
+            "keyAvailable": key_error.is_none(),
+            "error": key_error,
+        })
+    } else {
+        json!({
+            "enabled": false,
+            "keyReference": null,
+            "publicKey": null,
+            "keyAvailable": true,
+            "error": null,
+        })
+    }
+}
+
+async fn handle_vacation(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_vacation_template(tenant_id).await?,
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut template =
+                serde_json::from_slice::<VacationTemplate>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            template.subject = template
+                .subject
+                .map(|subject| subject.trim().to_string())
+                .filter(|subject| !subject.is_empty());
+            template
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            server
+                .update_tenant_vacation_template(tenant_id, template.clone().into())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": template,
+            }))
+            .into_http_response())
+        }
+        Method::DELETE => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            server
+                .update_tenant_vacation_template(tenant_id, None)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Sample message or disclaimer to preview, the stored disclaimer and a
+/// built-in message are used when omitted.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct DisclaimerPreviewRequest {
+    #[serde(default)]
+    disclaimer: Option<TenantDisclaimer>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+const BRAND_SAMPLE_MESSAGE: &str = concat!(
+    "Your account is ready. Sign in to read your mail, ",
+    "manage your calendars and share files with your team."
+);
+
+const DISCLAIMER_SAMPLE_MESSAGE: &str = concat!(
+    "From: sender@example.org\r\n",
+    "To: recipient@example.com\r\n",
+    "Subject: Disclaimer preview\r\n",
+    "MIME-Version: 1.0\r\n",
+    "Content-Type: multipart/alternative; boundary=\"preview\"\r\n\r\n",
+    "--preview\r\n",
+    "Content-Type: text/plain; charset=utf-8\r\n\r\n",
+    "This is a sample message.\r\n",
+    "--preview\r\n",
+    "Content-Type: text/html; charset=utf-8\r\n\r\n",
+    "<html><body><p>This is a sample message.</p></body></html>\r\n",
+    "--preview--\r\n",
+);
+
+async fn handle_disclaimer(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match (path.get(3).copied(), req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_disclaimer(tenant_id),
+            }))
+            .into_http_response())
+        }
+        (None, &Method::PUT) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut disclaimer =
+                serde_json::from_slice::<TenantDisclaimer>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            disclaimer.html = disclaimer
+                .html
+                .map(|html| html.trim().to_string())
+                .filter(|html| !html.is_empty());
+            disclaimer
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            server
+                .update_tenant_disclaimer(tenant_id, disclaimer.clone().into())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": disclaimer,
+            }))
+            .into_http_response())
+        }
+        (None, &Method::DELETE) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            server.update_tenant_disclaimer(tenant_id, None).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        (Some("preview"), &Method::POST) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            preview_disclaimer(
+                server,
+                body,
+                server.tenant_disclaimer(tenant_id),
+                tenant_id.into(),
+                access_token,
+            )
+            .await
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Applies a disclaimer to a sample message, the built-in message and the
+/// provided disclaimer are used when omitted from the request.
+pub(super) async fn preview_disclaimer(
+    server: &Server,
+    body: Option<Vec<u8>>,
+    disclaimer: Option<Arc<TenantDisclaimer>>,
+    tenant_id: Option<u32>,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let request = match body.as_deref() {
+        Some(body) if !body.is_empty() => serde_json::from_slice::<DisclaimerPreviewRequest>(body)
+            .map_err(|err| {
+                trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+            })?,
+        _ => DisclaimerPreviewRequest::default(),
+    };
+    let disclaimer = match request.disclaimer {
+        Some(disclaimer) => {
+            disclaimer
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+            Some(Arc::new(disclaimer))
+        }
+        None => disclaimer,
+    };
+    let message = request
+        .message
+        .unwrap_or_else(|| DISCLAIMER_SAMPLE_MESSAGE.to_string());
+    let brand = match tenant_id {
+        Some(tenant_id) => server.tenant_brand_name(tenant_id).await?,
+        None => String::new(),
+    };
+    let preview = disclaimer.and_then(|disclaimer| {
+        disclaimer.apply(
+            message.as_bytes(),
+            &DisclaimerVariables {
+                name: access_token
+                    .description
+                    .as_deref()
+                    .unwrap_or(&access_token.name),
+                email: access_token
+                    .emails
+                    .first()
+                    .map_or("", |email| email.as_str()),
+                brand: &brand,
+            },
+        )
+    });
+
+    Ok(JsonResponse::new(json!({
+        "data": {
+            "applied": preview.is_some(),
+            "message": preview
+                .map(|message| String::from_utf8_lossy(&message).into_owned())
+                .unwrap_or(message),
+        },
+    }))
+    .into_http_response())
+}
+
+async fn handle_journaling(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            let journaling = server.tenant_journaling(tenant_id);
+            let backlog = server.journal_backlog(tenant_id).await?;
+            let (last_delivered_at, last_failure) = server.journal_status(tenant_id).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "settings": journaling,
+                    "status": {
+                        "enabled": journaling.is_some(),
+                        "backlog": backlog,
+                        "lastDeliveredAt": last_delivered_at,
+                        "lastFailure": last_failure,
+                    },
+                },
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut journaling =
+                serde_json::from_slice::<TenantJournaling>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            match &mut journaling.target {
+                JournalTarget::Smtp { address } => {
+                    *address = address.trim().to_lowercase();
+                }
+                JournalTarget::Bucket { store, key_prefix } => {
+                    *store = store.trim().to_string();
+                    *key_prefix = key_prefix
+                        .take()
+                        .map(|prefix| prefix.trim().trim_end_matches('/').to_string())
+                        .filter(|prefix| !prefix.is_empty());
+                }
+            }
+            journaling
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+            if let JournalTarget::Bucket { store, .. } = &journaling.target
+                && (!server.core.jmap.journal_blob_stores.contains(store)
+                    || !server.core.storage.blobs.contains_key(store))
+            {
+                return Err(manage::error(
+                    "Invalid blob store",
+                    format!("Blob store {store:?} is not available for journaling").into(),
+                ));
+            }
+
+            server
+                .update_tenant_journaling(tenant_id, journaling.clone().into())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": journaling,
+            }))
+            .into_http_response())
+        }
+        Method::DELETE => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            server.update_tenant_journaling(tenant_id, None).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+async fn handle_forwarding_policy(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_forwarding_policy(tenant_id).await?,
+            }))
+            .into_http_response())
+        }
+        Method::PUT => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let policy =
+                serde_json::from_slice::<ForwardingPolicy>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            server
+                .update_tenant_forwarding_policy(tenant_id, &policy)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": policy,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MaintenanceRequest {
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    ends_at: Option<String>,
+}
+
+/// Maintenance mode of the tenant. Only system administrators may start or
+/// end it, since tenant administrators are locked out while it is active.
+async fn handle_maintenance(
+    server: &Server,
+    req: &HttpRequest,
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    match *req.method() {
+        Method::GET => {
+            access_token.assert_has_permission(if access_token.tenant.is_some() {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_maintenance(tenant_id),
+            }))
+            .into_http_response())
+        }
+        Method::POST => {
+            access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+            let request =
+                serde_json::from_slice::<MaintenanceRequest>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+            let ends_at = match request.ends_at {
+                Some(ends_at) => Some(
+                    FutureTimestamp::from_str(&ends_at)
+                        .map_err(|_| {
+                            manage::error(
+                                "Invalid end time",
+                                format!("{ends_at:?} is not a future RFC 3339 timestamp").into(),
+                            )
+                        })?
+                        .into_inner(),
+                ),
+                None => None,
+            };
+            let message = request
+                .message
+                .map(|message| message.trim().to_string())
+                .filter(|message| !message.is_empty());
+            if let Some(message) = &message
+                && (message.len() > MAX_MAINTENANCE_MESSAGE_LEN
+                    || message.chars().any(|ch| ch.is_control()))
+            {
+                return Err(manage::error(
+                    "Invalid message",
+                    format!(
+                        "Messages must be a single line of at most {MAX_MAINTENANCE_MESSAGE_LEN} bytes"
+                    )
+                    .into(),
+                ));
+            }
+
+            server
+                .update_tenant_maintenance(
+                    tenant_id,
+                    TenantMaintenance {
+                        started_at: now(),
+                        ends_at,
+                        message,
+                        trial_expired: false,
+                    }
+                    .into(),
+                )
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_maintenance(tenant_id),
+            }))
+            .into_http_response())
+        }
+        Method::DELETE => {
+            access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+            server.update_tenant_maintenance(tenant_id, None).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Overrides of global settings for the tenant. Patches set the given keys
+/// using the syntax of the global setting and remove those set to null, only
+/// the keys of `TenantSetting` are accepted. Patches are checked by the same
+/// validator as the validation endpoint and are refused when it reports errors.
+async fn handle_setting_overrides(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match (path.get(3).copied(), req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": setting_overrides(server, tenant_id),
+            }))
+            .into_http_response())
+        }
+        (None, &Method::PATCH) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let (overrides, validation) =
+                validate_setting_overrides(server, tenant_id, body.as_deref(), false).await?;
+            if validation.has_errors() {
+                return Err(manage::error(
+                    "Invalid settings",
+                    validation
+                        .errors
+                        .iter()
+                        .map(|issue| format!("{}: {}", issue.key, issue.message))
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                        .into(),
+                ));
+            }
+
+            server.update_tenant_overrides(tenant_id, overrides).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": setting_overrides(server, tenant_id),
+            }))
+            .into_http_response())
+        }
+        (Some("validate"), &Method::POST) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let live = UrlParams::new(req.uri().query()).has_key("live");
+            let (_, validation) =
+                validate_setting_overrides(server, tenant_id, body.as_deref(), live).await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "valid": !validation.has_errors(),
+                    "errors": validation.errors,
+                    "warnings": validation.warnings,
+                },
+            }))
+            .into_http_response())
+        }
+        (Some("password-policy"), &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "policy": server.tenant_password_policy(tenant_id),
+                    "effective": server.password_policy(Some(tenant_id)),
+                },
+            }))
+            .into_http_response())
+        }
+        (Some("password-policy"), &Method::PUT) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut policy =
+                serde_json::from_slice::<TenantPasswordPolicy>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            policy
+                .normalize()
+                .map_err(|err| manage::error("Invalid password policy", err.into()))?;
+            server
+                .update_tenant_password_policy(tenant_id, policy.into())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "policy": server.tenant_password_policy(tenant_id),
+                    "effective": server.password_policy(Some(tenant_id)),
+                },
+            }))
+            .into_http_response())
+        }
+        (Some("password-policy"), &Method::DELETE) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            server
+                .update_tenant_password_policy(tenant_id, None)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Applies a patch to the current overrides of the tenant and validates the
+/// result without saving it. Live checks compare the limits with the current
+/// state of the tenant.
+async fn validate_setting_overrides(
+    server: &Server,
+    tenant_id: u32,
+    body: Option<&[u8]>,
+    live: bool,
+) -> trc::Result<(TenantOverrides, SettingsValidation)> {
+    let changes =
+        serde_json::from_slice::<BTreeMap<String, Option<Value>>>(body.unwrap_or_default())
+            .map_err(|err| {
+                trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+            })?;
+    let mut overrides = server
+        .tenant_overrides(tenant_id)
+        .map(|overrides| overrides.as_ref().clone())
+        .unwrap_or_default();
+    let mut validation = SettingsValidation::default();
+
+    for (key, value) in changes {
+        let Some(setting) = TenantSetting::parse(&key) else {
+            validation.error(&key, "Setting cannot be overridden per organization");
+            continue;
+        };
+        let value = match value {
+            Some(Value::String(value)) => value,
+            Some(Value::Number(value)) => value.to_string(),
+            Some(Value::Bool(value)) if setting.is_flag() => value.to_string(),
+            Some(_) => {
+                validation.error(setting, "Values must be strings or numbers");
+                continue;
+            }
+            None => {
+                overrides.values.remove(&setting);
+                continue;
+            }
+        };
+        match setting.parse_value(&value) {
+            Ok(value) => {
+                overrides.values.insert(setting, value);
+            }
+            Err(err) => {
+                validation.error(setting, err);
+            }
+        }
+    }
+
+    let checks = overrides.validate(|setting| server.global_setting(setting));
+    validation.errors.extend(checks.errors);
+    validation.warnings.extend(checks.warnings);
+
+    if live
+        && let Some(quota) = server
+            .store()
+            .get_principal(tenant_id)
+            .await?
+            .and_then(|tenant| tenant.quota())
+    {
+        for setting in [TenantSetting::UploadMaxSize, TenantSetting::EmailMaxSize] {
+            let value = overrides
+                .get(setting)
+                .unwrap_or_else(|| server.global_setting(setting));
+            if value > quota {
+                validation.warning(
+                    setting,
+                    format!("Limit exceeds the organization's quota of {quota} bytes"),
+                );
+            }
+        }
+    }
+
+    Ok((overrides, validation))
+}
+
+fn setting_overrides(server: &Server, tenant_id: u32) -> BTreeMap<&'static str, String> {
+    server
+        .tenant_overrides(tenant_id)
+        .map(|overrides| {
+            overrides
+                .values
+                .iter()
+                .map(|(setting, value)| (setting.key(), setting.config_value(*value)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn handle_retention(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+
+    match (path.get(3).copied(), path.get(4).copied(), req.method()) {
+        (None, None, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.tenant_retention(tenant_id).await?.unwrap_or_default(),
+            }))
+            .into_http_response())
+        }
+        (None, None, &Method::PUT) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let mut retention =
+                serde_json::from_slice::<TenantRetention>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                        trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                            .from_json_error(err)
+                    })?;
+            for policy in &mut retention.policies {
+                policy.role = policy.role.take().map(|role| role.trim().to_lowercase());
+                policy.folder = policy.folder.take().map(|folder| folder.trim().to_string());
+            }
+            for name in &mut retention.legal_hold {
+                *name = name.trim().to_lowercase();
+            }
+            retention
+                .validate()
+                .map_err(|err| manage::error(err, None::<u64>))?;
+
+            // Policies have to be tested with a dry run before messages are modified
+            if !retention.dry_run
+                && !retention.policies.is_empty()
+                && !server
+                    .retention_reports(tenant_id)
+                    .await?
+                    .iter()
+                    .any(|report| {
+                        report.policies.len() == retention.policies.len()
+                            && report
+                                .policies
+                                .iter()
+                                .zip(&retention.policies)
+                                .all(|(report, policy)| &report.policy == policy)
+                    })
+            {
+                return Err(manage::error(
+                    "Dry run required",
+                    "Run these retention policies in dry-run mode before enforcing them".into(),
+                ));
+            }
+
+            server
+                .update_tenant_retention(tenant_id, retention.clone())
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": retention,
+            }))
+            .into_http_response())
+        }
+        (Some("run"), None, &Method::POST) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalUpdate
+            } else {
+                Permission::TenantUpdate
+            })?;
+
+            let retention = server
+                .tenant_retention(tenant_id)
+                .await?
+                .filter(|retention| !retention.policies.is_empty())
+                .ok_or_else(|| manage::error("No retention policies", None::<u64>))?;
+
+            Ok(JsonResponse::new(json!({
+                "data": server.apply_tenant_retention(tenant_id, &retention).await?,
+            }))
+            .into_http_response())
+        }
+        (Some("reports"), report_id, &Method::GET) => {
+            access_token.assert_has_permission(if is_tenant_admin {
+                Permission::PrincipalGet
+            } else {
+                Permission::TenantGet
+            })?;
+
+            let reports = server.retention_reports(tenant_id).await?;
+            if let Some(report_id) = report_id {
+                reports
+                    .into_iter()
+                    .find(|report| report.id.to_string() == report_id)
+                    .map(|report| {
+                        JsonResponse::new(json!({
+                            "data": report,
+                        }))
+                        .into_http_response()
+                    })
+                    .ok_or_else(|| trc::ResourceEvent::NotFound.into_err())
+            } else {
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": reports,
+                        "total": reports.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Returns the tags in use by the principals of a tenant with their counts.
+async fn handle_tags(
+    server: &Server,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    access_token.assert_has_permission(if access_token.tenant.is_some() {
+        Permission::PrincipalGet
+    } else {
+        Permission::TenantGet
+    })?;
+
+    let mut counts = BTreeMap::<String, u64>::new();
+    for principal in server
+        .store()
+        .list_principals(None, Some(tenant_id), &[], true, 0, 0)
+        .await?
+        .items
+    {
+        for tag in principal.tags() {
+            *counts.entry(tag.to_string()).or_default() += 1;
+        }
+    }
+
+    Ok(JsonResponse::new(json!({
+        "data": {
+            "total": counts.len(),
+            "items": counts
+                .into_iter()
+                .map(|(tag, count)| json!({"tag": tag, "count": count}))
+                .collect::<Vec<_>>(),
+        },
+    }))
+    .into_http_response())
+}
+
+async fn handle_legal_holds(
+    server: &Server,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    access_token.assert_has_permission(Permission::PrincipalLegalHold)?;
+
+    let mut items = Vec::new();
+    for principal in server
+        .store()
+        .list_principals(None, Some(tenant_id), &[Type::Individual], true, 0, 0)
+        .await?
+        .items
+    {
+        if let Some((set_by, set_at)) = principal.legal_hold() {
+            items.push(json!({
+                "id": principal.id(),
+                "name": principal.name(),
+                "setBy": set_by,
+                "setAt": set_at,
+                "heldMessages": server.held_messages(principal.id()).await?.len(),
+            }));
+        }
+    }
+
+    Ok(JsonResponse::new(json!({
+        "data": {
+            "total": items.len(),
+            "items": items,
+        },
+    }))
+    .into_http_response())
+}
+
+async fn handle_deliveries(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    access_token.assert_has_permission(if access_token.tenant.is_some() {
+        Permission::PrincipalGet
+    } else {
+        Permission::TenantGet
+    })?;
+
+    if let Some(id) = path.get(3) {
+        // Obtain the full delivery history of a message
+        let queue_id = id
+            .parse::<u64>()
+            .map_err(|_| trc::ResourceEvent::NotFound.into_err())?;
+
+        return server
+            .read_delivery_log(tenant_id, queue_id)
+            .await?
+            .map(|log| {
+                JsonResponse::new(json!({
+                    "data": log,
+                }))
+                .into_http_response()
+            })
+            .ok_or_else(|| trc::ResourceEvent::NotFound.into_err());
+    }
+
+    let params = UrlParams::new(req.uri().query());
+    let sender = params.get("sender").map(|s| s.to_lowercase());
+    let domain = params
+        .get("domain")
+        .map(|d| format!("@{}", d.to_lowercase()));
+    let outcome = params
+        .get("outcome")
+        .map(|outcome| {
+            serde_json::from_value::<DeliveryOutcome>(Value::String(outcome.to_string()))
+                .map_err(|_| manage::error("Invalid outcome", outcome.to_string().into()))
+        })
+        .transpose()?;
+    let after = params
+        .parse::<Timestamp>("after")
+        .and_then(|t| SnowflakeIdGenerator::from_timestamp(t.into_inner()))
+        .unwrap_or(0);
+    let before = params
+        .parse::<Timestamp>("before")
+        .and_then(|t| SnowflakeIdGenerator::from_timestamp(t.into_inner()))
+        .unwrap_or(u64::MAX);
+    let page = params.parse::<usize>("page").unwrap_or_default();
+    let limit = params.parse::<usize>("limit").unwrap_or_default();
+
+    let mut offset = page.saturating_sub(1) * limit;
+    let mut total = 0;
+    let mut items = Vec::new();
+
+    // Most recent deliveries first
+    server
+        .core
+        .storage
+        .data
+        .iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Queue(QueueClass::DeliveryLog {
+                    tenant_id,
+                    queue_id: after,
+                })),
+                ValueKey::from(ValueClass::Queue(QueueClass::DeliveryLog {
+                    tenant_id,
+                    queue_id: before,
+                })),
+            )
+            .descending(),
+            |key, value| {
+                let mut log = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
+                    .and_then(|archive| archive.deserialize::<DeliveryLog>())
+                    .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+
+                let matches = sender
+                    .as_ref()
+                    .is_none_or(|sender| log.return_path.to_lowercase().contains(sender))
+                    && log.recipients.iter().any(|rcpt| {
+                        domain
+                            .as_ref()
+                            .is_none_or(|domain| rcpt.address.to_lowercase().ends_with(domain))
+                            && outcome.is_none_or(|outcome| rcpt.outcome == outcome)
+                    });
+
+                if matches {
+                    if offset == 0 {
+                        if limit == 0 || items.len() < limit {
+                            log.attempts.clear();
+                            items.push(log);
+                        }
+                    } else {
+                        offset -= 1;
+                    }
+                    total += 1;
+                }
+
+                Ok(true)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+    Ok(JsonResponse::new(json!({
+        "data": {
+            "items": items,
+            "total": total,
+        },
+    }))
+    .into_http_response())
+}
+
+/// Rejects relays of tenants whose host does not resolve or resolves to an
+/// address that is not publicly routable. Delivery checks the resolved
+/// addresses again before connecting.
+pub(super) async fn assert_public_relay(relay: &TenantRelay) -> trc::Result<()> {
+    let addrs = tokio::net::lookup_host((relay.host.as_str(), relay.port))
+        .await
+        .map(|addrs| addrs.collect::<Vec<_>>())
+        .unwrap_or_default();
+    if addrs.is_empty() {
+        Err(manage::error(
+            "Invalid host",
+            format!("Failed to resolve {:?}", relay.host).into(),
+        ))
+    } else if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        Err(manage::error(
+            "Host not allowed",
+            format!(
+                "{:?} resolves to the non-public address {}",
+                relay.host,
+                addr.ip()
+            )
+            .into(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn keep_relay_secret(relay: &mut TenantRelay, current: &TenantRelay) -> trc::Result<()> {
+    if relay.secret.is_none() && relay.username.is_some() {
+        if relay.username == current.username {
+            relay.secret = current.secret.clone();
+        } else if current.secret.is_some() {
+            return Err(manage::error(
+                "Relay secret required",
+                format!(
+                    "The secret of relay {:?} has to be re-entered when its username changes",
+                    relay.host
+                )
+                .into(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Request body for creating or replacing a tenant Sieve script.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SieveScriptRequest {
+    contents: String,
+}
+
+/// Accounts a tenant Sieve script is installed for, in addition to the
+/// existing assignment.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SieveAssignRequest {
+    #[serde(default)]
+    all: bool,
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    accounts: Vec<String>,
+}
+
+async fn handle_sieve_scripts(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    let is_tenant_admin = access_token.tenant.is_some();
+    access_token.assert_has_permission(match (req.method(), is_tenant_admin) {
+        (&Method::GET, true) => Permission::PrincipalGet,
+        (&Method::GET, false) => Permission::TenantGet,
+        (_, true) => Permission::PrincipalUpdate,
+        (_, false) => Permission::TenantUpdate,
+    })?;
+    let script_name = path.get(3).map(|name| decode_path_element(name));
+    let current = script_name.as_ref().and_then(|name| {
+        server
+            .tenant_sieve_scripts(tenant_id)
+            .into_iter()
+            .find(|script| &script.name == name)
+    });
+
+    match (script_name, path.get(4).copied(), req.method()) {
+        (None, None, &Method::GET) => Ok(JsonResponse::new(json!({
+            "data": server
+                .tenant_sieve_scripts(tenant_id)
+                .iter()
+                .map(|script| json!({
+                    "name": script.name,
+                    "assignment": script.assignment,
+                }))
+                .collect::<Vec<_>>(),
+        }))
+        .into_http_response()),
+        (Some(name), None, &Method::GET) => {
+            let script = current.ok_or_else(|| manage::not_found(name.to_string()))?;
+
+            Ok(JsonResponse::new(json!({
+                "data": {
+                    "name": script.name,
+                    "contents": script.contents,
+                    "assignment": script.assignment,
+                },
+            }))
+            .into_http_response())
+        }
+        (Some(name), None, &Method::PUT) => {
+            TenantSieveScript::validate_name(&name)
+                .map_err(|err| manage::error(err, None::<u64>))?;
+            let request =
+                serde_json::from_slice::<SieveScriptRequest>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+
+            // Scripts are validated by the same compiler used for user scripts
+            let script = server
+                .core
+                .sieve
+                .untrusted_compiler
+                .compile(request.contents.as_bytes())
+                .map_err(|err| {
+                    manage::error(format!("Invalid Sieve script: {err}"), None::<u64>)
+                })?;
+
+            server
+                .update_tenant_sieve_script(
+                    tenant_id,
+                    &name,
+                    Some(TenantSieveScript {
+                        name: name.to_string(),
+                        contents: request.contents,
+                        script: script.into(),
+                        assignment: current
+                            .map(|script| script.assignment.clone())
+                            .unwrap_or_default(),
+                    }),
+                )
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        (Some(name), None, &Method::DELETE) => {
+            if current.is_none() {
+                return Err(manage::not_found(name.to_string()));
+            }
+            server
+                .update_tenant_sieve_script(tenant_id, &name, None)
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        }
+        (Some(name), Some("assign"), &Method::POST | &Method::DELETE) => {
+            let mut script = current
+                .ok_or_else(|| manage::not_found(name.to_string()))?
+                .as_ref()
+                .clone();
+
+            if req.method() == Method::POST {
+                let request = serde_json::from_slice::<SieveAssignRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                let assignment = &mut script.assignment;
+                assignment.all |= request.all;
+                if let Some(group) = &request.group {
+                    assignment
+                        .groups
+                        .push(tenant_principal_id(server, group, Type::Group, tenant_id).await?);
+                }
+                for account in &request.accounts {
+                    assignment.accounts.push(
+                        tenant_principal_id(server, account, Type::Individual, tenant_id).await?,
+                    );
+                }
+                for list in [&mut assignment.groups, &mut assignment.accounts] {
+                    list.sort_unstable();
+                    list.dedup();
+                }
+            } else {
+                script.assignment = Default::default();
+            }
+
+            server
+                .update_tenant_sieve_script(tenant_id, &name, Some(script.clone()))
+                .await?;
+
+            Ok(JsonResponse::new(json!({
+                "data": script.assignment,
+            }))
+            .into_http_response())
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+/// Resolves a principal by name, hiding those outside the tenant.
+async fn tenant_principal_id(
+    server: &Server,
+    name: &str,
+    typ: Type,
+    tenant_id: u32,
+) -> trc::Result<u32> {
+    server
+        .store()
+        .get_principal_info(name)
+        .await?
+        .filter(|p| p.typ == typ && p.tenant == Some(tenant_id))
+        .map(|p| p.id)
+        .ok_or_else(|| manage::not_found(name.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrganizationColumn {
+    Name,
+    Description,
+    Domains,
+    Users,
+    UsedQuota,
+    Quota,
+    CreatedAt,
+    DormantUsers,
+    PersonalUsedQuota,
+    SharedUsedQuota,
+    PendingVerification,
+    TrialStatus,
+    TrialDaysRemaining,
+    Plan,
+    OverLimit,
+    ReferralCode,
+    Attribution,
+    DomainUsage,
+    Contact(PrincipalField),
+}
+
+impl OrganizationColumn {
+    const ALL: [OrganizationColumn; 7] = [
+        OrganizationColumn::Name,
+        OrganizationColumn::Description,
+        OrganizationColumn::Domains,
+        OrganizationColumn::Users,
+        OrganizationColumn::UsedQuota,
+        OrganizationColumn::Quota,
+        OrganizationColumn::PendingVerification,
+    ];
+
+    fn parse(value: &str) -> Option<Self> {
+        OrganizationColumn::ALL
+            .into_iter()
+            .chain([
+                OrganizationColumn::CreatedAt,
+                OrganizationColumn::DormantUsers,
+                OrganizationColumn::PersonalUsedQuota,
+                OrganizationColumn::SharedUsedQuota,
+                OrganizationColumn::TrialStatus,
+                OrganizationColumn::TrialDaysRemaining,
+                OrganizationColumn::Plan,
+                OrganizationColumn::OverLimit,
+                OrganizationColumn::ReferralCode,
+                OrganizationColumn::Attribution,
+                OrganizationColumn::DomainUsage,
+            ])
+            .chain(TENANT_CONTACT_FIELDS.map(OrganizationColumn::Contact))
+            .find(|column| column.as_str() == value.trim())
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            OrganizationColumn::Name => "name",
+            OrganizationColumn::Description => "description",
+            OrganizationColumn::Domains => "domains",
+            OrganizationColumn::Users => "users",
+            OrganizationColumn::UsedQuota => "usedQuota",
+            OrganizationColumn::Quota => "quota",
+            OrganizationColumn::CreatedAt => "createdAt",
+            OrganizationColumn::DormantUsers => "dormantUsers",
+            OrganizationColumn::PersonalUsedQuota => "personalUsedQuota",
+            OrganizationColumn::SharedUsedQuota => "sharedUsedQuota",
+            OrganizationColumn::PendingVerification => "pendingVerification",
+            OrganizationColumn::TrialStatus => "trialStatus",
+            OrganizationColumn::TrialDaysRemaining => "trialDaysRemaining",
+            OrganizationColumn::Plan => "plan",
+            OrganizationColumn::OverLimit => "overLimit",
+            OrganizationColumn::ReferralCode => "referralCode",
+            OrganizationColumn::Attribution => "attribution",
+            OrganizationColumn::DomainUsage => "domainUsage",
+            OrganizationColumn::Contact(field) => field.as_str(),
+        }
+    }
+}
+
+/// Storage usage and principal totals are read from the tenant's counters,
+/// which are kept up to date as messages and principals are added and removed.
+/// The usage of each domain is read from the rollup of the domain. Dormant
+/// accounts and the personal and shared breakdown are computed once per
+/// report, in `dormant_users` and `usage`.
+async fn organization_row(
+    server: &Server,
+    tenant: &Principal,
+    columns: &[OrganizationColumn],
+    dormant_users: &AHashMap<u32, u64>,
+    usage: &AHashMap<u32, TenantUsage>,
+) -> trc::Result<Vec<Value>> {
+    let mut row = Vec::with_capacity(columns.len());
+    for column in columns {
+        row.push(match column {
+            OrganizationColumn::Name => tenant.name().into(),
+            OrganizationColumn::Description => tenant.description().into(),
+            OrganizationColumn::Domains => server
+                .store()
+                .count_tenant_principals(tenant.id(), Type::Domain)
+                .await?
+                .into(),
+            OrganizationColumn::Users => server
+                .store()
+                .count_tenant_principals(tenant.id(), Type::Individual)
+                .await?
+                .into(),
+            OrganizationColumn::UsedQuota => {
+                server.get_used_quota(tenant.id()).await?.max(0).into()
+            }
+            OrganizationColumn::Quota => tenant.quota().into(),
+            OrganizationColumn::CreatedAt => tenant.created_at().into(),
+            OrganizationColumn::DormantUsers => dormant_users
+                .get(&tenant.id())
+                .copied()
+                .unwrap_or_default()
+                .into(),
+            OrganizationColumn::PendingVerification => {
+                server.tenant_activation(tenant.id()).is_some().into()
+            }
+            OrganizationColumn::TrialStatus => tenant.trial_status(now()).as_str().into(),
+            OrganizationColumn::TrialDaysRemaining => tenant
+                .trial_ends_at()
+                .map(|ends_at| trial_days_remaining(ends_at, now()))
+                .into(),
+            OrganizationColumn::Plan => tenant.plan().into(),
+            OrganizationColumn::Contact(field) => tenant.contact(*field).into(),
+            OrganizationColumn::ReferralCode => tenant.metadata_value(REFERRAL_CODE_KEY).into(),
+            OrganizationColumn::Attribution => tenant
+                .metadata()
+                .filter_map(|(key, value)| {
+                    key.strip_prefix(ATTRIBUTION_PREFIX)
+                        .map(|key| (key.to_string(), Value::from(value)))
+                })
+                .collect::<Map<_, _>>()
+                .into(),
+            OrganizationColumn::OverLimit => {
+                (!server.tenant_exceeded_limits(tenant).await?.is_empty()).into()
+            }
+            OrganizationColumn::DomainUsage => domain_usage(server, tenant.id()).await?.into(),
+            OrganizationColumn::PersonalUsedQuota | OrganizationColumn::SharedUsedQuota => {
+                let usage = usage.get(&tenant.id()).copied().unwrap_or_default();
+                if *column == OrganizationColumn::PersonalUsedQuota {
+                    usage.personal.into()
+                } else {
+                    usage.shared().into()
+                }
+            }
+        });
+    }
+
+    Ok(row)
+}
+
+/// Messages and storage of each domain of an organization, read from the
+/// rollups kept for every domain.
+async fn domain_usage(server: &Server, tenant_id: u32) -> trc::Result<Vec<Value>> {
+    let mut domains = Vec::new();
+    for domain in server
+        .store()
+        .list_principals(None, Some(tenant_id), &[Type::Domain], false, 0, 0)
+        .await?
+        .items
+    {
+        let usage = server.domain_usage(domain.id()).await?;
+        domains.push(json!({
+            "name": domain.name(),
+            "messages": usage.messages,
+            "bytes": usage.bytes,
+        }));
+    }
+
+    Ok(domains)
+}
+
+/// Largest mailboxes across the accounts and groups of an organization,
+/// computed from the message cache of each account.
+async fn top_folders(server: &Server, tenant_id: u32, limit: usize) -> trc::Result<Vec<Value>> {
+    let mut folders = Vec::new();
+    for account in server
+        .store()
+        .list_principals(
+            None,
+            Some(tenant_id),
+            &[Type::Individual, Type::Group],
+            false,
+            0,
+            0,
+        )
+        .await?
+        .items
+    {
+        let (mailboxes, _) = server.mailbox_stats(account.id(), None, false).await?;
+        folders.extend(
+            mailboxes
+                .into_iter()
+                .filter(|mailbox| mailbox.stats.messages > 0)
+                .map(|mailbox| (account.name().to_string(), mailbox)),
+        );
+    }
+    folders.sort_by(|(a_account, a), (b_account, b)| {
+        b.stats
+            .size
+            .cmp(&a.stats.size)
+            .then_with(|| a_account.cmp(b_account))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    Ok(folders
+        .into_iter()
+        .take(limit)
+        .map(|(account, mailbox)| {
+            json!({
+                "account": account,
+                "path": mailbox.path,
+                "messages": mailbox.stats.messages,
+                "size": mailbox.stats.size,
+            })
+        })
+        .collect())
+}
+
+pub(super) fn csv_field(value: &Value) -> String {
+    let value = match value {
+        Value::String(value) => value.clone(),
+        Value::Null => return String::new(),
+        value => value.to_string(),
+    };
+
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Resolves an organization by name, hiding those outside the caller's tenant.
+async fn organization_id(
+    server: &Server,
+    name: &str,
+    access_token: &AccessToken,
+) -> trc::Result<u32> {
+    let name = decode_path_element(name);
+    server
+        .store()
+        .get_principal_info(name.as_ref())
+        .await?
+        .filter(|p| p.typ == Type::Tenant && p.has_tenant_access(access_token.tenant.map(|t| t.id)))
+        .map(|p| p.id)
+        .ok_or_else(|| manage::not_found(name.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProvisionStep {
+    Tenant,
+    Domain,
+    Admin,
+}
+
+impl ProvisionStep {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ProvisionStep::Tenant => "tenant",
+            ProvisionStep::Domain => "domain",
+            ProvisionStep::Admin => "admin",
+        }
+    }
+}
+
+pub(super) fn validate_provision_request(
+    request: &OrganizationProvisionRequest,
+) -> trc::Result<()> {
+    // Validate required fields
+    if request.tenant_name.is_empty() {
+        return Err(manage::err_missing("tenantName"));
+    }
+    if request.domain.is_empty() {
+        return Err(manage::err_missing("domain"));
+    }
+    if request.admin_name.is_empty() {
+        return Err(manage::err_missing("adminName"));
+    }
+    if request.admin_password.is_empty() {
+        return Err(manage::err_missing("adminPassword"));
+    }
+    if request.admin_email.is_empty() {
+        return Err(manage::err_missing("adminEmail"));
+    }
+    if request.require_admin_email_verification {
+        // The contact has to be reachable while the domain is not accepting mail
+        let contact = request.contact_email.as_deref().unwrap_or_default().trim();
+        if contact.is_empty() {
+            return Err(manage::err_missing("contactEmail"));
+        } else if contact.rsplit_once('@').is_none_or(|(local, domain)| {
+            local.is_empty()
+                || !domain.contains('.')
+                || domain.eq_ignore_ascii_case(request.domain.trim())
+                || contact.contains(|ch: char| ch.is_whitespace() || ch.is_control())
+        }) || contact.eq_ignore_ascii_case(request.admin_email.trim())
+        {
+            return Err(manage::error(
+                "Invalid contact email",
+                "The contact address must be a valid address outside the organization's domain"
+                    .into(),
+            ));
+        }
+    }
+    trial_end(request.trial_ends_at.as_deref(), request.trial_days, now())?;
+    for (field, logo_url) in [
+        (PrincipalField::BrandLogoUrl, &request.brand_logo_url),
+        (
+            PrincipalField::BrandLogoDarkUrl,
+            &request.brand_logo_dark_url,
+        ),
+    ] {
+        if let Some(logo_url) = logo_url {
+            assert_logo_url(field, logo_url)?;
+        }
+    }
+    if let Some(theme) = &request.brand_theme {
+        parse_brand_theme(theme)?;
+    }
+    if let Some(code) = &request.referral_code {
+        let code = code.trim();
+        if code.is_empty()
+            || code.len() > MAX_REFERRAL_CODE_LEN
+            || !code
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b'-' | b'_'))
+        {
+            return Err(manage::error(
+                "Invalid referral code",
+                format!(
+                    "Referral codes must be 1 to {MAX_REFERRAL_CODE_LEN} characters long and contain only letters, digits, '-' or '_'"
+                )
+                .into(),
+            ));
+        }
+    }
+    if request.attribution.len() > MAX_ATTRIBUTION_ENTRIES {
+        return Err(manage::error(
+            "Invalid attribution",
+            format!("Attribution can have at most {MAX_ATTRIBUTION_ENTRIES} entries").into(),
+        ));
+    }
+    for (key, value) in &request.attribution {
+        if !is_valid_metadata_key(&format!("{ATTRIBUTION_PREFIX}{key}")) {
+            return Err(manage::error(
+                "Invalid attribution",
+                format!(
+                    "Key {key:?} must be 1 to {} characters long and contain only letters, digits, '.', '-', '_' or ':'",
+                    MAX_METADATA_KEY_LEN - ATTRIBUTION_PREFIX.len()
+                )
+                .into(),
+            ));
+        } else if value.len() > MAX_ATTRIBUTION_VALUE_LEN || value.contains(char::is_control) {
+            return Err(manage::error(
+                "Invalid attribution",
+                format!(
+                    "Value of {key:?} must be at most {MAX_ATTRIBUTION_VALUE_LEN} bytes long without control characters"
+                )
+                .into(),
+            ));
+        }
+    }
+    if let Some(protocol) = request
+        .protocols
+        .keys()
+        .find(|protocol| TenantProtocol::parse(protocol).is_none())
+    {
+        return Err(manage::error(
+            "Invalid protocol",
+            format!(
+                "Unknown protocol {protocol:?}, expected one of {}",
+                TenantProtocol::ALL
+                    .iter()
+                    .map(|protocol| protocol.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Validates a provisioning request and provisions the organization, with
+/// `tenant_fields` added to the new tenant. Names of `self_service` requests
+/// were chosen by the requester, so reserved names are enforced regardless of
+/// the permissions of `access_token`.
+pub(super) async fn provision_organization_request(
+    server: &Server,
+    request: OrganizationProvisionRequest,
+    tenant_fields: Vec<(PrincipalField, PrincipalValue)>,
+    access_token: &AccessToken,
+    self_service: bool,
+) -> trc::Result<OrganizationProvisionResponse> {
+    validate_provision_request(&request)?;
+    if let Some(plan) = &request.plan {
+        server.assert_plan_exists(plan)?;
+    }
+
+    let tenant_name = request.tenant_name.clone();
+    let attribution = request.attribution_metadata();
+    let start_time = Instant::now();
+
+    // Codes outside the configured list are kept, the campaign may have
+    // been added to the list after the code was handed out
+    let mut warnings = vec![];
+    if let Some(code) = &request.referral_code
+        && !server.core.jmap.referral_codes.is_empty()
+        && !server
+            .core
+            .jmap
+            .referral_codes
+            .contains(&code.trim().to_lowercase())
+    {
+        warnings.push(format!("Unknown referral code {:?}", code.trim()));
+    }
+
+    // Concurrent requests for the same names wait for the first one to
+    // complete, and then fail with a conflict once its principals exist
+    let lock = match ProvisionLock::acquire(
+        server,
+        [request.tenant_name.as_str(), request.domain.as_str()],
+    )
+    .await
+    {
+        Ok(lock) => lock,
+        Err(err) => return Err(with_existing_id(server, err, access_token).await),
+    };
+
+    trc::event!(
+        Provision(trc::ProvisionEvent::Started),
+        AccountName = tenant_name.clone(),
+        Domain = request.domain.clone(),
+        AccountId = access_token.primary_id(),
+    );
+
+    let result =
+        provision_organization(server, request, tenant_fields, access_token, self_service).await;
+    lock.release().await;
+
+    match result {
+        Ok(mut response) => {
+            trc::event!(
+                Provision(trc::ProvisionEvent::Completed),
+                AccountName = tenant_name,
+                Id = response.tenant_id,
+                Details = attribution,
+                Elapsed = start_time.elapsed(),
+            );
+            response.warnings = warnings;
+
+            Ok(response)
+        }
+        Err((step, err)) => {
+            trc::event!(
+                Provision(trc::ProvisionEvent::Failed),
+                AccountName = tenant_name,
+                Details = step.as_str(),
+                CausedBy = err.clone(),
+                Elapsed = start_time.elapsed(),
+            );
+
+            Err(with_existing_id(server, err, access_token).await)
+        }
+    }
+}
+
+async fn provision_organization(
+    server: &Server,
+    request: OrganizationProvisionRequest,
+    tenant_fields: Vec<(PrincipalField, PrincipalValue)>,
+    access_token: &AccessToken,
+    self_service: bool,
+) -> Result<OrganizationProvisionResponse, (ProvisionStep, trc::Error)> {
+    let tenant_id = access_token.tenant.map(|t| t.id);
+    if let Some(collections) = &request.collections {
+        collections
+            .validate()
+            .map_err(|err| (ProvisionStep::Tenant, manage::error(err, None::<u64>)))?;
+    }
+    let contacts = request
+        .contacts
+        .sanitize()
+        .map_err(|err| (ProvisionStep::Tenant, err))?;
+
+    // Step 1: Build the tenant
+    let mut tenant = PrincipalSet::default();
+    tenant.typ = Type::Tenant;
+    tenant.fields.insert(
+        PrincipalField::Name,
+        PrincipalValue::String(request.tenant_name.clone()),
+    );
+    if let Some(description) = &request.description {
+        tenant.fields.insert(
+            PrincipalField::Description,
+            PrincipalValue::String(description.clone()),
+        );
+    }
+    if let Some(brand_name) = &request.brand_name {
+        tenant.fields.insert(
+            PrincipalField::BrandName,
+            PrincipalValue::String(brand_name.clone()),
+        );
+    }
+    if let Some(brand_logo_url) = &request.brand_logo_url {
+        tenant.fields.insert(
+            PrincipalField::BrandLogoUrl,
+            PrincipalValue::String(brand_logo_url.clone()),
+        );
+    }
+    if let Some(brand_logo_dark_url) = &request.brand_logo_dark_url {
+        tenant.fields.insert(
+            PrincipalField::BrandLogoDarkUrl,
+            PrincipalValue::String(brand_logo_dark_url.clone()),
+        );
+    }
+    if let Some(brand_theme) = &request.brand_theme {
+        tenant.fields.insert(
+            PrincipalField::BrandTheme,
+            PrincipalValue::String(brand_theme.clone()),
+        );
+    }
+    if let Some(ends_at) = trial_end(request.trial_ends_at.as_deref(), request.trial_days, now())
+        .map_err(|err| (ProvisionStep::Tenant, err))?
+    {
+        tenant.fields.insert(
+            PrincipalField::TrialEndsAt,
+            PrincipalValue::Integer(ends_at),
+        );
+    }
+    if let Some(plan) = &request.plan {
+        tenant
+            .fields
+            .insert(PrincipalField::Plan, PrincipalValue::String(plan.clone()));
+    }
+    for (field, value) in &contacts {
+        if !value.is_empty() {
+            tenant
+                .fields
+                .insert(*field, PrincipalValue::String(value.clone()));
+        }
+    }
+    tenant.fields.extend(tenant_fields);
+    for entry in request.attribution_metadata() {
+        tenant.append_str(PrincipalField::Metadata, entry);
+    }
+
+    // Step 2: Build the domain under this tenant
+    let mut domain = PrincipalSet::default();
+    domain.typ = Type::Domain;
+    domain.fields.insert(
+        PrincipalField::Name,
+        PrincipalValue::String(request.domain.clone()),
+    );
+
+    // Step 3: Build the admin user under this tenant with tenant-admin role
+    // The tenant has no password policy of its own yet
+    server
+        .assert_password_policy(None, [request.admin_password.as_str()])
+        .map_err(|err| (ProvisionStep::Admin, err))?;
+    let mut admin = PrincipalSet::default();
+    admin.typ = Type::Individual;
+    admin.fields.insert(
+        PrincipalField::Name,
+        PrincipalValue::String(request.admin_name.clone()),
+    );
+    admin.fields.insert(
+        PrincipalField::Secrets,
+        PrincipalValue::StringList(vec![request.admin_password]),
+    );
+    admin.fields.insert(
+        PrincipalField::Emails,
+        PrincipalValue::StringList(vec![request.admin_email]),
+    );
+    admin.fields.insert(
+        PrincipalField::Roles,
+        PrincipalValue::StringList(vec!["tenant-admin".to_string()]),
+    );
+
+    // Create all principals at once, nothing is written if any of them fails
+    let mut permissions = access_token.permissions.clone();
+    if self_service {
+        permissions.clear(Permission::PrincipalReservedName as usize);
+    }
+    let result = server
+        .core
+        .storage
+        .data
+        .create_principals_batch(
+            vec![
+                BatchPrincipal {
+                    principal: tenant,
+                    tenant: tenant_id.map(BatchTenant::Existing),
+                },
+                BatchPrincipal {
+                    principal: domain,
+                    tenant: BatchTenant::Created(0).into(),
+                },
+                BatchPrincipal {
+                    principal: admin,
+                    tenant: BatchTenant::Created(0).into(),
+                },
+            ],
+            Some(&permissions),
+            &server.core.jmap.directory_names,
+        )
+        .await
+        .map_err(|err| {
+            let step = match err.value_as_str(trc::Key::Type) {
+                Some("tenant") => ProvisionStep::Tenant,
+                Some("domain") => ProvisionStep::Domain,
+                _ => ProvisionStep::Admin,
+            };
+            (step, err)
+        })?;
+    let (new_tenant_id, new_domain_id, new_admin_id) =
+        (result.ids[0], result.ids[1], result.ids[2]);
+
+    server
+        .invalidate_principal_caches(result.changed_principals)
+        .await;
+
+    // Keep the organization inactive until its contact confirms it
+    if request.require_admin_email_verification {
+        request_organization_activation(
+            server,
+            new_tenant_id,
+            &request.tenant_name,
+            &request.domain,
+            request.contact_email.as_deref().unwrap_or_default().trim(),
+        )
+        .await
+        .map_err(|err| (ProvisionStep::Tenant, err))?;
+    }
+
+    trc::event!(
+        Provision(trc::ProvisionEvent::TenantCreated),
+        AccountName = request.tenant_name,
+        Id = new_tenant_id,
+        Details = contact_entries(
+            contacts
+                .iter()
+                .map(|(field, value)| (*field, value.as_str()))
+        ),
+    );
+
+    // Store the protocol flags before any account can log in
+    if !request.protocols.is_empty() {
+        let mut overrides = TenantOverrides::default();
+        for (protocol, enabled) in &request.protocols {
+            if let Some(protocol) = TenantProtocol::parse(protocol) {
+                overrides
+                    .values
+                    .insert(TenantSetting::Protocol(protocol), u64::from(*enabled));
+            }
+        }
+        server
+            .update_tenant_overrides(new_tenant_id, overrides)
+            .await
+            .map_err(|err| (ProvisionStep::Tenant, err))?;
+    }
+
+    // Create the shared calendars and address books of the tenant
+    if let Some(collections) = request.collections {
+        server
+            .update_tenant_collections(new_tenant_id, collections)
+            .await
+            .map_err(|err| (ProvisionStep::Tenant, err))?;
+        server
+            .provision_tenant_collections(new_tenant_id)
+            .await
+            .map_err(|err| (ProvisionStep::Tenant, err))?;
+    }
+
+    trc::event!(
+        Provision(trc::ProvisionEvent::DomainCreated),
+        Domain = request.domain.clone(),
+        Id = new_domain_id,
+        AccountId = new_tenant_id,
+    );
+
+    if let Err(err) = server
+        .request_domain_certificates(&request.domain.to_lowercase(), false)
+        .await
+    {
+        trc::error!(err.details("Failed to request domain certificates"));
+    }
+
+    if let Err(err) = server.provision_folders(new_admin_id).await {
+        trc::error!(err.details("Failed to provision folders"));
+    }
+    if let Err(err) = server.provision_collections(new_admin_id).await {
+        trc::error!(err.details("Failed to provision collections"));
+    }
+
+    trc::event!(
+        Provision(trc::ProvisionEvent::AdminCreated),
+        AccountName = request.admin_name,
+        Id = new_admin_id,
+        AccountId = new_tenant_id,
+    );
+
+    let dns_records = server
+        .build_dns_records(&request.domain.to_lowercase())
+        .await
+        .unwrap_or_else(|err| {
+            trc::error!(err.details("Failed to build DNS records"));
+            vec![]
+        });
+
+    Ok(OrganizationProvisionResponse {
+        tenant_id: new_tenant_id,
+        domain_id: new_domain_id,
+        admin_id: new_admin_id,
+        dns_records,
+        pending_verification: request.require_admin_email_verification,
+        warnings: vec![],
+    })
+}
@@ -4,19 +4,43 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use crate::management::stores::destroy_account_data;
-use common::{Server, auth::AccessToken};
+use crate::management::{
+    Timestamp,
+    app_password::AppPasswordManager,
+    avatar::AvatarManager,
+    erasure::PrincipalErasureManager,
+    export::AccountExportManager,
+    forwarding::AccountForwardingManager,
+    mailbox_stats::MailboxStatsManager,
+    message_import::MessageImportManager,
+    mfa::MfaManager,
+    provision_lock::{ProvisionLock, with_existing_id},
+    reindex::ReindexManager,
+    stores::destroy_account_data,
+    upsert::PrincipalUpsertApi,
+};
+use common::{
+    Server,
+    auth::{AccessToken, notice::is_password_change},
+    config::overrides::TenantProtocol,
+};
 use directory::{
     DirectoryInner, Permission, PrincipalData, QueryBy, QueryParams, Type,
     backend::internal::{
         PrincipalAction, PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+        external_id::ExternalIdLookup,
+        lockout::AccountLockout,
         lookup::DirectoryStore,
         manage::{
-            self, ChangedPrincipals, ManageDirectory, PrincipalList, UpdatePrincipal, not_found,
+            self, ChangedPrincipals, ManageDirectory, PrincipalList, PrincipalListOrder,
+            UpdatePrincipal, not_found,
         },
+        trial::TrialStatus,
     },
 };
-use http_proto::{request::decode_path_element, *};
+use email::{mailbox::manage::MailboxFnc, message::legal_hold::EmailLegalHold};
+use groupware::provision::CollectionProvisioning;
+use http_proto::{plain::is_plain_text_request, request::decode_path_element, *};
 use hyper::{Method, header};
 use serde_json::json;
 use std::future::Future;
@@ -24,6 +48,9 @@ use std::sync::Arc;
 use trc::AddContext;
 use utils::url_params::UrlParams;
 
+// Principal fields listed in plain text when no fields are requested
+const PRINCIPAL_TABLE_COLUMNS: &[&str] = &["id", "type", "name", "emails", "quota", "description"];
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "camelCase")]
@@ -46,7 +73,7 @@ pub struct AccountAuthResponse {
 pub trait PrincipalManager: Sync + Send {
     fn handle_manage_principal(
         &self,
-        req: &HttpRequest,
+        req: &mut HttpRequest,
         path: Vec<&str>,
         body: Option<Vec<u8>>,
         access_token: &AccessToken,
@@ -64,13 +91,39 @@ pub trait PrincipalManager: Sync + Send {
         body: Option<Vec<u8>>,
     ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
 
+    fn create_managed_principal(
+        &self,
+        principal: PrincipalSet,
+        access_token: &AccessToken,
+        override_directory: bool,
+    ) -> impl Future<Output = trc::Result<u32>> + Send;
+
+    /// Creates a principal leaving the cache invalidation to the caller, which
+    /// flushes the changes of a bulk operation at once.
+    fn create_managed_principal_deferred(
+        &self,
+        principal: PrincipalSet,
+        access_token: &AccessToken,
+        override_directory: bool,
+        changed_principals: &mut ChangedPrincipals,
+    ) -> impl Future<Output = trc::Result<u32>> + Send;
+
+    fn update_managed_principal(
+        &self,
+        account_id: u32,
+        name: &str,
+        typ: Type,
+        changes: Vec<PrincipalUpdate>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+
     fn assert_supported_directory(&self, override_: bool) -> trc::Result<()>;
 }
 
 impl PrincipalManager for Server {
     async fn handle_manage_principal(
         &self,
-        req: &HttpRequest,
+        req: &mut HttpRequest,
         path: Vec<&str>,
         body: Option<Vec<u8>>,
         access_token: &AccessToken,
@@ -85,122 +138,16 @@ impl PrincipalManager for Server {
                                 .from_json_error(err)
                         })?;
 
-                // Validate the access token
-                access_token.assert_has_permission(match principal.typ() {
-                    Type::Individual => Permission::IndividualCreate,
-                    Type::Group => Permission::GroupCreate,
-                    Type::List => Permission::MailingListCreate,
-                    Type::Domain => Permission::DomainCreate,
-                    Type::Tenant => Permission::TenantCreate,
-                    Type::Role => Permission::RoleCreate,
-                    Type::ApiKey => Permission::ApiKeyCreate,
-                    Type::OauthClient => Permission::OauthClientCreate,
-                    Type::Resource | Type::Location | Type::Other => Permission::PrincipalCreate,
-                })?;
-
-                // SPDX-SnippetBegin
-                // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
-                // SPDX-License-Identifier: LicenseRef-SEL
-
-                #[cfg(feature = "enterprise")]
-                {
-                    if (matches!(principal.typ(), Type::Tenant)
-                        || principal.has_field(PrincipalField::Tenant))
-                        && !self.core.is_enterprise_edition()
-                    {
-                        return Err(manage::enterprise());
-                    }
-
-                    if matches!(principal.typ(), Type::Individual)
-                        && self.core.is_enterprise_edition()
-                        && !self.can_create_account().await?
-                    {
-                        return Err(manage::error(
-                            "License account limit reached",
-                            format!(
-                                "Enterprise licensed account limit reached: {} accounts licensed.",
-                                self.licensed_accounts()
-                            )
-                            .into(),
-                        ));
-                    }
-                }
-
-                // SPDX-SnippetEnd
-
-                // Make sure the current directory supports updates
-                if matches!(principal.typ(), Type::Individual) {
-                    self.assert_supported_directory(path.get(1).copied() == Some("deploy"))?;
-                }
-
-                // Validate roles
-                let tenant_id = access_token.tenant.map(|t| t.id);
-                for name in principal
-                    .get_str_array(PrincipalField::Roles)
-                    .unwrap_or_default()
-                {
-                    if let Some(pinfo) = self
-                        .store()
-                        .get_principal_info(name)
-                        .await
-                        .caused_by(trc::location!())?
-                        .filter(|v| v.typ == Type::Role && v.has_tenant_access(tenant_id))
-                        .or_else(|| PrincipalField::Roles.map_internal_roles(name))
-                    {
-                        let role_permissions =
-                            self.get_role_permissions(pinfo.id).await?.finalize_as_ref();
-                        let mut allowed_permissions = role_permissions.clone();
-                        allowed_permissions.intersection(&access_token.permissions);
-                        if allowed_permissions != role_permissions {
-                            return Err(manage::error(
-                                "Invalid role",
-                                format!("Your account cannot grant the {name:?} role").into(),
-                            ));
-                        }
-                    }
-                }
-
-                // Set default report domain if missing
-                let report_domain = if principal.typ() == Type::Domain
-                    && self
-                        .core
-                        .storage
-                        .config
-                        .get("report.domain")
-                        .await
-                        .is_ok_and(|v| v.is_none())
-                {
-                    principal.name().to_lowercase().into()
-                } else {
-                    None
-                };
-
-                // Create principal
-                let result = self
-                    .core
-                    .storage
-                    .data
-                    .create_principal(principal, tenant_id, Some(&access_token.permissions))
+                let account_id = self
+                    .create_managed_principal(
+                        principal,
+                        access_token,
+                        path.get(1).copied() == Some("deploy"),
+                    )
                     .await?;
 
-                // Set report domain
-                if let Some(report_domain) = report_domain
-                    && let Err(err) = self
-                        .core
-                        .storage
-                        .config
-                        .set([("report.domain", report_domain)], true)
-                        .await
-                {
-                    trc::error!(err.details("Failed to set report domain"));
-                }
-
-                // Increment revision
-                self.invalidate_principal_caches(result.changed_principals)
-                    .await;
-
                 Ok(JsonResponse::new(json!({
-                    "data": result.id,
+                    "data": account_id,
                 }))
                 .into_http_response())
             }
@@ -211,6 +158,7 @@ impl PrincipalManager for Server {
                 let page: usize = params.parse("page").unwrap_or(0);
                 let limit: usize = params.parse("limit").unwrap_or(0);
                 let count = params.get("count").is_some();
+                let order = list_order(&params)?;
 
                 // Parse types
                 let mut types = Vec::new();
@@ -294,18 +242,28 @@ impl PrincipalManager for Server {
                 }
                 // SPDX-SnippetEnd
 
-                let principals = self
-                    .store()
-                    .list_principals(
-                        filter,
-                        tenant,
-                        &types,
-                        fields.len() != 1
-                            || fields.first().is_none_or(|v| v != &PrincipalField::Name),
-                        page,
-                        limit,
-                    )
-                    .await?;
+                let principals = if order.is_default() {
+                    self.store()
+                        .list_principals(
+                            filter,
+                            tenant,
+                            &types,
+                            fields.len() != 1
+                                || fields.first().is_none_or(|v| v != &PrincipalField::Name),
+                            page,
+                            limit,
+                        )
+                        .await?
+                } else {
+                    let mut principals = self
+                        .store()
+                        .list_principals(filter, tenant, &types, true, 0, 0)
+                        .await?;
+                    if let Some(since) = order.inactive_since {
+                        principals = principals.retain_inactive(self.store(), since).await?;
+                    }
+                    principals.ordered(&order, page, limit)
+                };
 
                 let principals: PrincipalList<PrincipalSet> = if !count {
                     let mut expanded = PrincipalList {
@@ -327,10 +285,24 @@ impl PrincipalManager for Server {
                     }
                 };
 
-                Ok(JsonResponse::new(json!({
-                        "data": principals,
-                }))
-                .into_http_response())
+                if is_plain_text_request(req) {
+                    let items = serde_json::to_value(&principals.items).unwrap_or_default();
+                    let columns = if !fields.is_empty() {
+                        fields.iter().map(|field| field.as_str()).collect()
+                    } else {
+                        PRINCIPAL_TABLE_COLUMNS.to_vec()
+                    };
+
+                    Ok(
+                        TextTable::from_json(&columns, items.as_array().into_iter().flatten())
+                            .into_http_response(),
+                    )
+                } else {
+                    Ok(JsonResponse::new(json!({
+                            "data": principals,
+                    }))
+                    .into_http_response())
+                }
             }
             (None, &Method::DELETE) => {
                 // List principal ids
@@ -395,6 +367,9 @@ impl PrincipalManager for Server {
                 let found = !principals.items.is_empty();
                 if found {
                     let server = self.clone();
+                    let actor_name = access_token.name.clone();
+                    let actor_id = access_token.primary_id();
+                    let actor_tenant_id = access_token.tenant.map(|t| t.id);
                     tokio::spawn(async move {
                         for principal in principals.items {
                             // Delete account
@@ -404,6 +379,15 @@ impl PrincipalManager for Server {
                                 .await
                             {
                                 Ok(changed_principals) => {
+                                    trc::event!(
+                                        Directory(trc::DirectoryEvent::PrincipalDeleted),
+                                        AccountName = actor_name.clone(),
+                                        AccountId = actor_id,
+                                        TenantId = actor_tenant_id,
+                                        Id = principal.name().to_string(),
+                                        Type = typ.as_str(),
+                                    );
+
                                     // Increment revision
                                     server.invalidate_principal_caches(changed_principals).await;
                                 }
@@ -431,6 +415,187 @@ impl PrincipalManager for Server {
                 }))
                 .into_http_response())
             }
+            (Some(name), &Method::PUT)
+                if UrlParams::new(req.uri().query())
+                    .get("upsert")
+                    .is_some_and(|v| v == "1" || v == "true") =>
+            {
+                // Create or update principal
+                self.handle_upsert_principal(
+                    req,
+                    decode_path_element(name).as_ref(),
+                    body,
+                    access_token,
+                )
+                .await
+            }
+            (Some(name), _)
+                if path.get(2).copied() == Some("import")
+                    && path.get(3).copied() == Some("messages") =>
+            {
+                // Import messages into an account
+                let name = decode_path_element(name);
+                let account_id = self
+                    .core
+                    .storage
+                    .data
+                    .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+                    .await?
+                    .filter(|p| {
+                        p.has_tenant_access(access_token.tenant.map(|t| t.id))
+                            && matches!(p.typ, Type::Individual | Type::Group)
+                    })
+                    .map(|p| p.id)
+                    .ok_or_else(|| not_found(name.to_string()))?;
+
+                self.handle_message_import(req, path, account_id, access_token)
+                    .await
+            }
+            (Some("by-external-id"), &Method::GET) if path.get(2).is_some() => {
+                // Fetch a principal by the identifier of an external system
+                let external_id = decode_path_element(path[2]);
+                let mut tenant = access_token.tenant.map(|t| t.id);
+
+                // SPDX-SnippetBegin
+                // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+                // SPDX-License-Identifier: LicenseRef-SEL
+                #[cfg(feature = "enterprise")]
+                {
+                    if self.core.is_enterprise_edition() && tenant.is_none() {
+                        // Look up the external ID within a tenant
+                        if let Some(tenant_name) = UrlParams::new(req.uri().query()).get("tenant") {
+                            tenant = self
+                                .core
+                                .storage
+                                .data
+                                .get_principal_info(tenant_name)
+                                .await?
+                                .filter(|p| p.typ == Type::Tenant)
+                                .map(|p| p.id)
+                                .ok_or_else(|| not_found(tenant_name.to_string()))?
+                                .into();
+                        }
+                    }
+                }
+                // SPDX-SnippetEnd
+
+                let (account_id, typ) = self
+                    .core
+                    .storage
+                    .data
+                    .resolve_external_id(external_id.as_ref(), tenant)
+                    .await?
+                    .filter(|p| p.has_tenant_access(access_token.tenant.map(|t| t.id)))
+                    .map(|p| (p.id, p.typ))
+                    .ok_or_else(|| not_found(external_id.to_string()))?;
+
+                // Validate the access token
+                access_token.assert_has_permission(get_permission(typ))?;
+
+                let principal = self
+                    .store()
+                    .query(QueryParams::id(account_id).with_return_member_of(true))
+                    .await?
+                    .ok_or_else(|| trc::ManageEvent::NotFound.into_err())?;
+
+                // Map fields
+                let principal = self
+                    .core
+                    .storage
+                    .data
+                    .map_principal(principal, &[])
+                    .await
+                    .caused_by(trc::location!())?;
+
+                Ok(JsonResponse::new(json!({
+                        "data": principal,
+                }))
+                .into_http_response())
+            }
+            (Some(_), _) if path.get(2).copied() == Some("erase") => {
+                // Erase an account and its personal data
+                self.handle_principal_erasure(req, path, access_token).await
+            }
+            (Some(_), _) if path.get(2).copied() == Some("export") => {
+                // Export the data of an account
+                self.handle_account_export(req, path, access_token).await
+            }
+            (Some(_), _) if path.get(2).copied() == Some("avatar") => {
+                // Manage the avatar of a principal
+                self.handle_avatar(req, path, body, access_token).await
+            }
+            (Some(_), _) if path.get(2).copied() == Some("app-passwords") => {
+                // Manage the app passwords of an account
+                self.handle_app_passwords(req, path, body, access_token)
+                    .await
+            }
+            (Some(_), _) if path.get(2).copied() == Some("mfa") => {
+                // Enroll, list and remove the second factors of an account
+                self.handle_mfa(req, path, body, access_token).await
+            }
+            (Some(name), &Method::POST) if path.get(2).copied() == Some("unlock") => {
+                // Unlock an account locked after repeated authentication failures
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let name = decode_path_element(name);
+                let account_id = self
+                    .core
+                    .storage
+                    .data
+                    .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+                    .await?
+                    .filter(|p| {
+                        p.has_tenant_access(access_token.tenant.map(|t| t.id))
+                            && p.typ == Type::Individual
+                    })
+                    .map(|p| p.id)
+                    .ok_or_else(|| not_found(name.to_string()))?;
+
+                let was_locked = self.core.storage.data.unlock_account(account_id).await?;
+                if was_locked {
+                    trc::event!(
+                        Directory(trc::DirectoryEvent::AccountUnlocked),
+                        AccountName = access_token.name.clone(),
+                        AccountId = access_token.primary_id(),
+                        TenantId = access_token.tenant.map(|t| t.id),
+                        Id = name.to_string(),
+                    );
+                    self.notify_account_lockout(account_id, None).await;
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": was_locked,
+                }))
+                .into_http_response())
+            }
+            (Some(_), _) if path.get(2).copied() == Some("forwarding") => {
+                // Manage the forwarding rule of an account
+                self.handle_account_forwarding(req, path, body, access_token)
+                    .await
+            }
+            (Some(_), &Method::GET) if path.get(2).copied() == Some("mailbox-stats") => {
+                // Per-mailbox message statistics of an account
+                self.handle_mailbox_stats(req, path, access_token).await
+            }
+            (Some(name), _) if path.get(2).copied() == Some("reindex") => {
+                // Reindex the messages of an account
+                let name = decode_path_element(name);
+                let (account_id, typ) = self
+                    .core
+                    .storage
+                    .data
+                    .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
+                    .await?
+                    .filter(|p| {
+                        p.has_tenant_access(access_token.tenant.map(|t| t.id))
+                            && matches!(p.typ, Type::Individual | Type::Group)
+                    })
+                    .map(|p| (p.id, p.typ))
+                    .ok_or_else(|| not_found(name.to_string()))?;
+
+                self.handle_reindex(req, path, body, account_id, typ, access_token)
+                    .await
+            }
             (Some(name), method) => {
                 // Fetch, update or delete principal
                 let name = decode_path_element(name);
@@ -438,7 +603,7 @@ impl PrincipalManager for Server {
                     .core
                     .storage
                     .data
-                    .get_principal_info(name.as_ref())
+                    .resolve_principal_info(name.as_ref(), access_token.tenant.map(|t| t.id))
                     .await?
                     .filter(|p| p.has_tenant_access(access_token.tenant.map(|t| t.id)))
                     .map(|p| (p.id, p.typ))
@@ -460,19 +625,7 @@ impl PrincipalManager for Server {
                 match *method {
                     Method::GET => {
                         // Validate the access token
-                        access_token.assert_has_permission(match typ {
-                            Type::Individual => Permission::IndividualGet,
-                            Type::Group => Permission::GroupGet,
-                            Type::List => Permission::MailingListGet,
-                            Type::Domain => Permission::DomainGet,
-                            Type::Tenant => Permission::TenantGet,
-                            Type::Role => Permission::RoleGet,
-                            Type::ApiKey => Permission::ApiKeyGet,
-                            Type::OauthClient => Permission::OauthClientGet,
-                            Type::Resource | Type::Location | Type::Other => {
-                                Permission::PrincipalGet
-                            }
-                        })?;
+                        access_token.assert_has_permission(get_permission(typ))?;
 
                         let principal = self
                             .store()
@@ -516,6 +669,15 @@ impl PrincipalManager for Server {
                             .delete_principal(QueryBy::Id(account_id))
                             .await?;
 
+                        trc::event!(
+                            Directory(trc::DirectoryEvent::PrincipalDeleted),
+                            AccountName = access_token.name.clone(),
+                            AccountId = access_token.primary_id(),
+                            TenantId = access_token.tenant.map(|t| t.id),
+                            Id = name.to_string(),
+                            Type = typ.as_str(),
+                        );
+
                         if let Err(err) = destroy_account_data(
                             self,
                             account_id,
@@ -536,20 +698,7 @@ impl PrincipalManager for Server {
                     }
                     Method::PATCH => {
                         // Validate the access token
-                        let permission_needed = match typ {
-                            Type::Individual => Permission::IndividualUpdate,
-                            Type::Group => Permission::GroupUpdate,
-                            Type::List => Permission::MailingListUpdate,
-                            Type::Domain => Permission::DomainUpdate,
-                            Type::Tenant => Permission::TenantUpdate,
-                            Type::Role => Permission::RoleUpdate,
-                            Type::ApiKey => Permission::ApiKeyUpdate,
-                            Type::OauthClient => Permission::OauthClientUpdate,
-                            Type::Resource | Type::Location | Type::Other => {
-                                Permission::PrincipalUpdate
-                            }
-                        };
-                        access_token.assert_has_permission(permission_needed)?;
+                        access_token.assert_has_permission(update_permission(typ))?;
 
                         let changes = serde_json::from_slice::<Vec<PrincipalUpdate>>(
                             body.as_deref().unwrap_or_default(),
@@ -559,117 +708,14 @@ impl PrincipalManager for Server {
                                 .from_json_error(err)
                         })?;
 
-                        // Validate changes
-                        let mut invalidate_logo_cache = false;
-                        for change in &changes {
-                            match change.field {
-                                PrincipalField::Secrets
-                                | PrincipalField::Name
-                                | PrincipalField::Emails
-                                | PrincipalField::Quota
-                                | PrincipalField::UsedQuota
-                                | PrincipalField::Description
-                                | PrincipalField::Type
-                                | PrincipalField::MemberOf
-                                | PrincipalField::Members
-                                | PrincipalField::Lists
-                                | PrincipalField::Urls
-                                | PrincipalField::ExternalMembers
-                                | PrincipalField::Locale
-                                | PrincipalField::BrandName
-                                | PrincipalField::BrandLogoUrl
-                                | PrincipalField::BrandTheme => (),
-                                PrincipalField::Picture => {
-                                    invalidate_logo_cache |=
-                                        matches!(typ, Type::Domain | Type::Tenant);
-                                }
-                                PrincipalField::Tenant => {
-                                    // Tenants are not allowed to change their tenantId
-                                    if access_token.tenant.is_some() {
-                                        trc::bail!(
-                                            trc::SecurityEvent::Unauthorized
-                                                .into_err()
-                                                .details(permission_needed.name())
-                                                .ctx(
-                                                    trc::Key::Reason,
-                                                    "Tenants cannot change their tenantId"
-                                                )
-                                        );
-                                    }
-                                }
-                                PrincipalField::Roles
-                                | PrincipalField::EnabledPermissions
-                                | PrincipalField::DisabledPermissions => {
-                                    if change.field == PrincipalField::Roles
-                                        && matches!(
-                                            change.action,
-                                            PrincipalAction::AddItem | PrincipalAction::Set
-                                        )
-                                    {
-                                        let roles = match &change.value {
-                                            PrincipalValue::String(v) => std::slice::from_ref(v),
-                                            PrincipalValue::StringList(vec) => vec,
-                                            PrincipalValue::Integer(_)
-                                            | PrincipalValue::IntegerList(_) => continue,
-                                        };
-
-                                        // Validate roles
-                                        let tenant_id = access_token.tenant.map(|t| t.id);
-                                        for name in roles {
-                                            if let Some(pinfo) = self
-                                                .store()
-                                                .get_principal_info(name)
-                                                .await
-                                                .caused_by(trc::location!())?
-                                                .filter(|v| {
-                                                    v.typ == Type::Role
-                                                        && v.has_tenant_access(tenant_id)
-                                                })
-                                                .or_else(|| {
-                                                    PrincipalField::Roles.map_internal_roles(name)
-                                                })
-                                            {
-                                                let role_permissions = self
-                                                    .get_role_permissions(pinfo.id)
-                                                    .await?
-                                                    .finalize_as_ref();
-                                                let mut allowed_permissions =
-                                                    role_permissions.clone();
-                                                allowed_permissions
-                                                    .intersection(&access_token.permissions);
-                                                if allowed_permissions != role_permissions {
-                                                    return Err(manage::error(
-                                                        "Invalid role",
-                                                        format!("Your account cannot grant the {name:?} role").into(),
-                                                    ));
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        // Update principal
-                        let changed_principals = self
-                            .core
-                            .storage
-                            .data
-                            .update_principal(
-                                UpdatePrincipal::by_id(account_id)
-                                    .with_updates(changes)
-                                    .with_tenant(access_token.tenant.map(|t| t.id))
-                                    .with_allowed_permissions(&access_token.permissions),
-                            )
-                            .await?;
-
-                        // Increment revision
-                        self.invalidate_principal_caches(changed_principals).await;
-
-                        // Invalidate logo cache if needed
-                        if invalidate_logo_cache {
-                            self.inner.data.logos.lock().clear();
-                        }
+                        self.update_managed_principal(
+                            account_id,
+                            name.as_ref(),
+                            typ,
+                            changes,
+                            access_token,
+                        )
+                        .await?;
 
                         Ok(JsonResponse::new(json!({
                             "data": (),
@@ -811,7 +857,22 @@ impl PrincipalManager for Server {
 
         // Build actions
         let mut actions = Vec::with_capacity(requests.len());
+        let mut events = Vec::new();
         for request in requests {
+            let event = match &request {
+                AccountAuthRequest::SetPassword { .. } => trc::DirectoryEvent::PasswordChanged,
+                AccountAuthRequest::EnableOtpAuth { .. } => trc::DirectoryEvent::MfaFactorAdded,
+                AccountAuthRequest::DisableOtpAuth { .. } => trc::DirectoryEvent::MfaFactorRemoved,
+                AccountAuthRequest::AddAppPassword { .. } => {
+                    trc::DirectoryEvent::AppPasswordCreated
+                }
+                AccountAuthRequest::RemoveAppPassword { .. } => {
+                    trc::DirectoryEvent::AppPasswordRevoked
+                }
+            };
+            if !events.contains(&event) {
+                events.push(event);
+            }
             let (action, secret) = match request {
                 AccountAuthRequest::SetPassword { password } => {
                     actions.push(PrincipalUpdate {
@@ -843,6 +904,9 @@ impl PrincipalManager for Server {
             });
         }
 
+        // New passwords must meet the password policy of the tenant
+        self.assert_password_changes(access_token.tenant.map(|t| t.id), &actions)?;
+
         // Update password
         let changed_principals = self
             .core
@@ -851,19 +915,535 @@ impl PrincipalManager for Server {
             .update_principal(
                 UpdatePrincipal::by_id(access_token.primary_id())
                     .with_updates(actions)
-                    .with_tenant(access_token.tenant.map(|t| t.id)),
+                    .with_tenant(access_token.tenant.map(|t| t.id))
+                    .self_service(),
             )
             .await?;
 
         // Increment revision
         self.invalidate_principal_caches(changed_principals).await;
 
+        for event in events {
+            trc::event!(
+                Directory(event),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                TenantId = access_token.tenant.map(|t| t.id),
+            );
+            self.notify_security_event(
+                trc::EventType::Directory(event),
+                access_token.primary_id(),
+                None,
+            )
+            .await;
+        }
+
         Ok(JsonResponse::new(json!({
             "data": (),
         }))
         .into_http_response())
     }
 
+    async fn create_managed_principal(
+        &self,
+        principal: PrincipalSet,
+        access_token: &AccessToken,
+        override_directory: bool,
+    ) -> trc::Result<u32> {
+        let mut changed_principals = ChangedPrincipals::default();
+        let account_id = self
+            .create_managed_principal_deferred(
+                principal,
+                access_token,
+                override_directory,
+                &mut changed_principals,
+            )
+            .await?;
+        self.invalidate_principal_caches(changed_principals).await;
+
+        Ok(account_id)
+    }
+
+    async fn create_managed_principal_deferred(
+        &self,
+        principal: PrincipalSet,
+        access_token: &AccessToken,
+        override_directory: bool,
+        changed_principals: &mut ChangedPrincipals,
+    ) -> trc::Result<u32> {
+        // Validate the access token
+        access_token.assert_has_permission(match principal.typ() {
+            Type::Individual => Permission::IndividualCreate,
+            Type::Group => Permission::GroupCreate,
+            Type::List => Permission::MailingListCreate,
+            Type::Domain => Permission::DomainCreate,
+            Type::Tenant => Permission::TenantCreate,
+            Type::Role => Permission::RoleCreate,
+            Type::ApiKey => Permission::ApiKeyCreate,
+            Type::OauthClient => Permission::OauthClientCreate,
+            Type::Resource | Type::Location | Type::Other => Permission::PrincipalCreate,
+        })?;
+
+        // SPDX-SnippetBegin
+        // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+        // SPDX-License-Identifier: LicenseRef-SEL
+
+        #[cfg(feature = "enterprise")]
+        {
+            if (matches!(principal.typ(), Type::Tenant)
+                || principal.has_field(PrincipalField::Tenant))
+                && !self.core.is_enterprise_edition()
+            {
+                return Err(manage::enterprise());
+            }
+
+            if matches!(principal.typ(), Type::Individual)
+                && self.core.is_enterprise_edition()
+                && !self.can_create_account().await?
+            {
+                return Err(manage::error(
+                    "License account limit reached",
+                    format!(
+                        "Enterprise licensed account limit reached: {} accounts licensed.",
+                        self.licensed_accounts()
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        // SPDX-SnippetEnd
+
+        // Make sure the current directory supports updates
+        if matches!(principal.typ(), Type::Individual) {
+            self.assert_supported_directory(override_directory)?;
+        }
+
+        // Validate roles
+        let tenant_id = access_token.tenant.map(|t| t.id);
+        for name in principal
+            .get_str_array(PrincipalField::Roles)
+            .unwrap_or_default()
+        {
+            if let Some(pinfo) = self
+                .store()
+                .get_principal_info(name)
+                .await
+                .caused_by(trc::location!())?
+                .filter(|v| v.typ == Type::Role && v.has_tenant_access(tenant_id))
+                .or_else(|| PrincipalField::Roles.map_internal_roles(name))
+            {
+                let role_permissions = self.get_role_permissions(pinfo.id).await?.finalize_as_ref();
+                let mut allowed_permissions = role_permissions.clone();
+                allowed_permissions.intersection(&access_token.permissions);
+                if allowed_permissions != role_permissions {
+                    return Err(manage::error(
+                        "Invalid role",
+                        format!("Your account cannot grant the {name:?} role").into(),
+                    ));
+                }
+            }
+        }
+
+        if let Some(protocols) = principal.get_str_array(PrincipalField::AllowedProtocols) {
+            assert_allowed_protocols(principal.typ(), protocols.iter().cloned())?;
+        }
+
+        // Enforce the limits of the tenant's plan
+        let plan_tenant_id = match (tenant_id, principal.get_str(PrincipalField::Tenant)) {
+            (Some(tenant_id), _) => Some(tenant_id),
+            (None, Some(name)) => self
+                .store()
+                .get_principal_id(name)
+                .await
+                .caused_by(trc::location!())?,
+            (None, None) => None,
+        };
+        if let Some(plan_tenant_id) = plan_tenant_id {
+            self.assert_plan_limit(plan_tenant_id, principal.typ())
+                .await?;
+        }
+
+        // New passwords must meet the password policy of the tenant
+        if principal.typ() == Type::Individual {
+            self.assert_password_policy(
+                plan_tenant_id,
+                principal
+                    .get_str_array(PrincipalField::Secrets)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|secret| secret.as_str()),
+            )?;
+        }
+
+        // Set default report domain if missing
+        let report_domain = if principal.typ() == Type::Domain
+            && self
+                .core
+                .storage
+                .config
+                .get("report.domain")
+                .await
+                .is_ok_and(|v| v.is_none())
+        {
+            principal.name().to_lowercase().into()
+        } else {
+            None
+        };
+
+        // Create principal, tenants and domains are locked against concurrent
+        // creation as they may also be created by organization provisioning
+        let principal_name = principal.name().to_lowercase();
+        let principal_typ = principal.typ();
+        let lock = if matches!(principal_typ, Type::Tenant | Type::Domain) {
+            Some(
+                match ProvisionLock::acquire(self, [principal_name.as_str()]).await {
+                    Ok(lock) => lock,
+                    Err(err) => return Err(with_existing_id(self, err, access_token).await),
+                },
+            )
+        } else {
+            None
+        };
+        let result = self
+            .core
+            .storage
+            .data
+            .create_principal_with_policy(
+                principal,
+                tenant_id,
+                Some(&access_token.permissions),
+                &self.core.jmap.directory_names,
+            )
+            .await;
+        if let Some(lock) = lock {
+            lock.release().await;
+        }
+        let result = match result {
+            Ok(result) => result,
+            Err(err) => return Err(with_existing_id(self, err, access_token).await),
+        };
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::PrincipalCreated),
+            AccountName = access_token.name.clone(),
+            AccountId = access_token.primary_id(),
+            TenantId = tenant_id,
+            Id = principal_name.clone(),
+            Type = principal_typ.as_str(),
+        );
+
+        // Set report domain
+        if let Some(report_domain) = report_domain
+            && let Err(err) = self
+                .core
+                .storage
+                .config
+                .set([("report.domain", report_domain)], true)
+                .await
+        {
+            trc::error!(err.details("Failed to set report domain"));
+        }
+
+        changed_principals.merge(result.changed_principals);
+
+        // Create the default folders of tenant accounts
+        if principal_typ == Type::Individual
+            && let Err(err) = self.provision_folders(result.id).await
+        {
+            trc::error!(err.details("Failed to provision folders"));
+        }
+
+        // Create the default calendars and address books before the first login
+        if principal_typ == Type::Individual
+            && let Err(err) = self.provision_collections(result.id).await
+        {
+            trc::error!(err.details("Failed to provision collections"));
+        }
+
+        // The technical contact is told about new administrators
+        if principal_typ == Type::Individual {
+            self.notify_security_event(
+                trc::EventType::Directory(trc::DirectoryEvent::PrincipalCreated),
+                result.id,
+                format!("Created by: {}", access_token.name).into(),
+            )
+            .await;
+        }
+
+        // Request certificates for the domain hostnames
+        if principal_typ == Type::Domain
+            && let Err(err) = self
+                .request_domain_certificates(&principal_name, false)
+                .await
+        {
+            trc::error!(err.details("Failed to request domain certificates"));
+        }
+
+        Ok(result.id)
+    }
+
+    async fn update_managed_principal(
+        &self,
+        account_id: u32,
+        name: &str,
+        typ: Type,
+        mut changes: Vec<PrincipalUpdate>,
+        access_token: &AccessToken,
+    ) -> trc::Result<()> {
+        // Validate changes
+        let mut invalidate_logo_cache = false;
+        let mut legal_hold = None;
+        for change in &mut changes {
+            match change.field {
+                PrincipalField::Secrets
+                | PrincipalField::Name
+                | PrincipalField::Emails
+                | PrincipalField::Quota
+                | PrincipalField::UsedQuota
+                | PrincipalField::Description
+                | PrincipalField::Type
+                | PrincipalField::MemberOf
+                | PrincipalField::Members
+                | PrincipalField::Lists
+                | PrincipalField::Urls
+                | PrincipalField::ExternalMembers
+                | PrincipalField::Locale
+                | PrincipalField::BrandName
+                | PrincipalField::BrandLogoUrl
+                | PrincipalField::BrandLogoDarkUrl
+                | PrincipalField::BrandTheme
+                | PrincipalField::ExternalId
+                | PrincipalField::Metadata
+                | PrincipalField::Tags
+                | PrincipalField::PasswordHistoryLength
+                | PrincipalField::MinPasswordAgeDays
+                | PrincipalField::LockoutThreshold
+                | PrincipalField::LockoutWindow
+                | PrincipalField::LockoutDuration
+                | PrincipalField::LockoutPerProtocol
+                | PrincipalField::LockoutNotify
+                | PrincipalField::DisableAt
+                | PrincipalField::EnableAt
+                | PrincipalField::AvatarSelfService
+                | PrincipalField::SecurityNoticeOptOut
+                | PrincipalField::TrialEndsAt
+                | PrincipalField::BillingEmail
+                | PrincipalField::TechnicalContact
+                | PrincipalField::Phone
+                | PrincipalField::PostalAddress
+                | PrincipalField::TaxId
+                | PrincipalField::SenderAddress
+                | PrincipalField::SendRate => (),
+                PrincipalField::AllowedProtocols => {
+                    assert_allowed_protocols(typ, change.value.clone().into_str_array())?;
+                }
+                PrincipalField::Plan => {
+                    if let PrincipalValue::String(plan) = &change.value
+                        && !plan.is_empty()
+                    {
+                        self.assert_plan_exists(plan)?;
+                    }
+                }
+                PrincipalField::Picture => {
+                    invalidate_logo_cache |= matches!(typ, Type::Domain | Type::Tenant);
+                }
+                PrincipalField::LegalHold => {
+                    access_token.assert_has_permission(Permission::PrincipalLegalHold)?;
+
+                    let is_set = match (&change.action, &change.value) {
+                        (PrincipalAction::Set, PrincipalValue::Integer(value)) if *value <= 1 => {
+                            *value == 1
+                        }
+                        _ => {
+                            return Err(manage::error(
+                                "Invalid legalHold value",
+                                "Expected a boolean value".into(),
+                            ));
+                        }
+                    };
+                    if typ != Type::Individual {
+                        return Err(manage::error(
+                            "Invalid principal type",
+                            "Legal holds can only be placed on individual accounts".into(),
+                        ));
+                    }
+
+                    // Record who placed the hold
+                    change.value = PrincipalValue::String(if is_set {
+                        access_token.name.clone()
+                    } else {
+                        String::new()
+                    });
+                    legal_hold = Some(is_set);
+                }
+                PrincipalField::LegalHoldBy
+                | PrincipalField::LegalHoldAt
+                | PrincipalField::CreatedAt
+                | PrincipalField::ModifiedAt
+                | PrincipalField::SecondaryEmails
+                | PrincipalField::PendingEmails
+                | PrincipalField::LastActivity
+                | PrincipalField::Disabled
+                | PrincipalField::Avatar => {
+                    return Err(manage::error(
+                        "Read-only field",
+                        format!("{} cannot be modified", change.field.as_str()).into(),
+                    ));
+                }
+                PrincipalField::Tenant => {
+                    // Tenants are not allowed to change their tenantId
+                    if access_token.tenant.is_some() {
+                        trc::bail!(
+                            trc::SecurityEvent::Unauthorized
+                                .into_err()
+                                .details(update_permission(typ).name())
+                                .ctx(trc::Key::Reason, "Tenants cannot change their tenantId")
+                        );
+                    }
+                }
+                PrincipalField::Roles
+                | PrincipalField::EnabledPermissions
+                | PrincipalField::DisabledPermissions => {
+                    if change.field == PrincipalField::Roles
+                        && matches!(
+                            change.action,
+                            PrincipalAction::AddItem | PrincipalAction::Set
+                        )
+                    {
+                        let roles = match &change.value {
+                            PrincipalValue::String(v) => std::slice::from_ref(v),
+                            PrincipalValue::StringList(vec) => vec,
+                            PrincipalValue::Integer(_) | PrincipalValue::IntegerList(_) => continue,
+                        };
+
+                        // Validate roles
+                        let tenant_id = access_token.tenant.map(|t| t.id);
+                        for name in roles {
+                            if let Some(pinfo) = self
+                                .store()
+                                .get_principal_info(name)
+                                .await
+                                .caused_by(trc::location!())?
+                                .filter(|v| v.typ == Type::Role && v.has_tenant_access(tenant_id))
+                                .or_else(|| PrincipalField::Roles.map_internal_roles(name))
+                            {
+                                let role_permissions =
+                                    self.get_role_permissions(pinfo.id).await?.finalize_as_ref();
+                                let mut allowed_permissions = role_permissions.clone();
+                                allowed_permissions.intersection(&access_token.permissions);
+                                if allowed_permissions != role_permissions {
+                                    return Err(manage::error(
+                                        "Invalid role",
+                                        format!("Your account cannot grant the {name:?} role")
+                                            .into(),
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // New passwords must meet the password policy of the account's tenant
+        if typ == Type::Individual
+            && changes
+                .iter()
+                .any(|change| change.field == PrincipalField::Secrets)
+        {
+            let tenant_id = match access_token.tenant {
+                Some(tenant) => Some(tenant.id),
+                None => self
+                    .store()
+                    .get_principal_info(name)
+                    .await
+                    .caused_by(trc::location!())?
+                    .and_then(|info| info.tenant),
+            };
+            self.assert_password_changes(tenant_id, &changes)?;
+        }
+
+        // Update principal
+        let password_changed = typ == Type::Individual && is_password_change(&changes);
+        let changed_principals = self
+            .core
+            .storage
+            .data
+            .update_principal(
+                UpdatePrincipal::by_id(account_id)
+                    .with_updates(changes)
+                    .with_tenant(access_token.tenant.map(|t| t.id))
+                    .with_allowed_permissions(&access_token.permissions)
+                    .with_name_policy(&self.core.jmap.directory_names),
+            )
+            .await?;
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::PrincipalUpdated),
+            AccountName = access_token.name.clone(),
+            AccountId = access_token.primary_id(),
+            TenantId = access_token.tenant.map(|t| t.id),
+            Id = name.to_string(),
+            Type = typ.as_str(),
+        );
+
+        if password_changed {
+            trc::event!(
+                Directory(trc::DirectoryEvent::PasswordChanged),
+                AccountName = access_token.name.clone(),
+                AccountId = access_token.primary_id(),
+                TenantId = access_token.tenant.map(|t| t.id),
+                Id = name.to_string(),
+            );
+            self.notify_security_event(
+                trc::EventType::Directory(trc::DirectoryEvent::PasswordChanged),
+                account_id,
+                format!("Changed by: {}", access_token.name).into(),
+            )
+            .await;
+        }
+
+        match legal_hold {
+            Some(true) => {
+                trc::event!(
+                    Directory(trc::DirectoryEvent::LegalHoldSet),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = name.to_string(),
+                );
+            }
+            Some(false) => {
+                // Purge the messages expunged while the hold was in place
+                let held_messages = self
+                    .release_held_messages(account_id)
+                    .await
+                    .caused_by(trc::location!())?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::LegalHoldReleased),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = access_token.tenant.map(|t| t.id),
+                    Id = name.to_string(),
+                    Total = held_messages,
+                );
+            }
+            None => (),
+        }
+
+        // Increment revision
+        self.invalidate_principal_caches(changed_principals).await;
+
+        // Invalidate logo cache if needed
+        if invalidate_logo_cache {
+            self.inner.data.logos.lock().clear();
+        }
+
+        Ok(())
+    }
+
     fn assert_supported_directory(&self, override_: bool) -> trc::Result<()> {
         let class = match &self.core.storage.directory.store {
             DirectoryInner::Internal(_) => return Ok(()),
@@ -889,3 +1469,124 @@ impl PrincipalManager for Server {
         }
     }
 }
+
+pub(crate) fn get_permission(typ: Type) -> Permission {
+    match typ {
+        Type::Individual => Permission::IndividualGet,
+        Type::Group => Permission::GroupGet,
+        Type::List => Permission::MailingListGet,
+        Type::Domain => Permission::DomainGet,
+        Type::Tenant => Permission::TenantGet,
+        Type::Role => Permission::RoleGet,
+        Type::ApiKey => Permission::ApiKeyGet,
+        Type::OauthClient => Permission::OauthClientGet,
+        Type::Resource | Type::Location | Type::Other => Permission::PrincipalGet,
+    }
+}
+
+pub(crate) fn update_permission(typ: Type) -> Permission {
+    match typ {
+        Type::Individual => Permission::IndividualUpdate,
+        Type::Group => Permission::GroupUpdate,
+        Type::List => Permission::MailingListUpdate,
+        Type::Domain => Permission::DomainUpdate,
+        Type::Tenant => Permission::TenantUpdate,
+        Type::Role => Permission::RoleUpdate,
+        Type::ApiKey => Permission::ApiKeyUpdate,
+        Type::OauthClient => Permission::OauthClientUpdate,
+        Type::Resource | Type::Location | Type::Other => Permission::PrincipalUpdate,
+    }
+}
+
+/// Parses the sort field, direction, metadata, tag, activity and trial filters
+/// of a principal listing.
+pub(crate) fn list_order(params: &UrlParams<'_>) -> trc::Result<PrincipalListOrder> {
+    let sort_by = params
+        .get("sort")
+        .map(|sort| {
+            PrincipalField::try_parse(sort)
+                .filter(|field| {
+                    matches!(
+                        field,
+                        PrincipalField::Name
+                            | PrincipalField::CreatedAt
+                            | PrincipalField::ModifiedAt
+                    )
+                })
+                .ok_or_else(|| manage::error("Invalid sort field", sort.to_string().into()))
+        })
+        .transpose()?;
+    let descending = match params.get("order") {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => {
+            return Err(manage::error(
+                "Invalid sort order",
+                order.to_string().into(),
+            ));
+        }
+    };
+
+    // Metadata filters, as in `metadata.billing.customerId=42`
+    let metadata = params
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix("metadata.")
+                .map(|key| (key.to_string(), value.to_string()))
+        })
+        .collect();
+
+    // Tag filters, as in `tag=pilot,vip`, must all match
+    let tags = params
+        .get("tag")
+        .unwrap_or_default()
+        .split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    let trial = params
+        .get("trial")
+        .map(|trial| {
+            TrialStatus::parse(trial)
+                .ok_or_else(|| manage::error("Invalid trial status", trial.to_string().into()))
+        })
+        .transpose()?;
+
+    Ok(PrincipalListOrder {
+        sort_by,
+        descending,
+        metadata,
+        tags,
+        inactive_since: params
+            .parse::<Timestamp>("inactive_since")
+            .map(|t| t.into_inner()),
+        trial,
+        ..Default::default()
+    })
+}
+
+// Protocol restrictions apply to accounts only, an empty list clears them
+fn assert_allowed_protocols(
+    typ: Type,
+    protocols: impl IntoIterator<Item = String>,
+) -> trc::Result<()> {
+    for protocol in protocols {
+        let protocol = protocol.trim().to_lowercase();
+        if protocol.is_empty() {
+            continue;
+        } else if typ != Type::Individual {
+            return Err(manage::error(
+                "Invalid field",
+                "Protocols can only be restricted for individual accounts".into(),
+            ));
+        } else if TenantProtocol::parse(&protocol).is_none() {
+            return Err(manage::error(
+                "Invalid protocol",
+                format!("Unknown protocol {protocol:?}").into(),
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{KV_LOCK_PROVISION, Server, auth::AccessToken};
+use directory::{
+    Permission, Type,
+    backend::internal::{
+        PrincipalField,
+        manage::{self, ManageDirectory},
+    },
+};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+// Locks are refreshed while the creation is in progress and released as
+// soon as it completes, the expiry only matters when a node stops while
+// holding one
+const LOCK_EXPIRY: u64 = 60;
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Advisory lock on the names of the tenants and domains being created, so
+/// that concurrent requests cannot both pass the existence checks before
+/// either one is written. Locks are kept in the in-memory store, which is
+/// shared by all nodes of the cluster.
+///
+/// Dropping the lock without calling `release`, for example when the request
+/// is cancelled, removes the locks from a background task.
+pub struct ProvisionLock {
+    server: Server,
+    keys: Vec<Vec<u8>>,
+    refresh: Option<JoinHandle<()>>,
+}
+
+impl ProvisionLock {
+    /// Locks the names, waiting briefly for other requests holding any of
+    /// them. Tenants and domains share the same namespace, so names are
+    /// locked regardless of their type.
+    pub async fn acquire<'x>(
+        server: &Server,
+        names: impl IntoIterator<Item = &'x str>,
+    ) -> trc::Result<Self> {
+        Self::acquire_with_expiry(server, names, LOCK_EXPIRY).await
+    }
+
+    pub async fn acquire_with_expiry<'x>(
+        server: &Server,
+        names: impl IntoIterator<Item = &'x str>,
+        expiry: u64,
+    ) -> trc::Result<Self> {
+        // Names are locked in the same order by every request to avoid deadlocks
+        let mut names = names
+            .into_iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+
+        let mut lock = ProvisionLock {
+            server: server.clone(),
+            keys: Vec::with_capacity(names.len()),
+            refresh: None,
+        };
+        for name in names {
+            let key = name.into_bytes();
+            let started = Instant::now();
+            loop {
+                match server
+                    .in_memory_store()
+                    .try_lock(KV_LOCK_PROVISION, &key, expiry)
+                    .await
+                {
+                    Ok(true) => {
+                        lock.keys.push(key);
+                        break;
+                    }
+                    Ok(false) if started.elapsed() < LOCK_WAIT => {
+                        tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                    }
+                    result => {
+                        lock.release().await;
+
+                        return Err(match result {
+                            Err(err) => err.caused_by(trc::location!()),
+                            _ => manage::err_exists(
+                                PrincipalField::Name,
+                                String::from_utf8(key).unwrap_or_default(),
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        // Keep the locks alive for as long as the creation runs
+        let server = server.clone();
+        let keys = lock.keys.clone();
+        lock.refresh = Some(tokio::spawn(async move {
+            let interval = Duration::from_secs((expiry / 3).max(1));
+            loop {
+                tokio::time::sleep(interval).await;
+                for key in &keys {
+                    if let Err(err) = server
+                        .in_memory_store()
+                        .refresh_lock(KV_LOCK_PROVISION, key, expiry)
+                        .await
+                    {
+                        trc::error!(
+                            err.details("Failed to refresh provisioning lock")
+                                .ctx(trc::Key::Key, key.clone())
+                                .caused_by(trc::location!())
+                        );
+                    }
+                }
+            }
+        }));
+
+        Ok(lock)
+    }
+
+    pub async fn release(mut self) {
+        if let Some(refresh) = self.refresh.take() {
+            refresh.abort();
+        }
+        remove_locks(&self.server, std::mem::take(&mut self.keys)).await;
+    }
+}
+
+impl Drop for ProvisionLock {
+    fn drop(&mut self) {
+        if let Some(refresh) = self.refresh.take() {
+            refresh.abort();
+        }
+        if !self.keys.is_empty()
+            && let Ok(handle) = tokio::runtime::Handle::try_current()
+        {
+            let server = self.server.clone();
+            let keys = std::mem::take(&mut self.keys);
+            handle.spawn(async move {
+                remove_locks(&server, keys).await;
+            });
+        }
+    }
+}
+
+async fn remove_locks(server: &Server, keys: Vec<Vec<u8>>) {
+    for key in keys {
+        if let Err(err) = server
+            .in_memory_store()
+            .remove_lock(KV_LOCK_PROVISION, &key)
+            .await
+        {
+            trc::error!(
+                err.details("Failed to release provisioning lock")
+                    .ctx(trc::Key::Key, key)
+                    .caused_by(trc::location!())
+            );
+        }
+    }
+}
+
+/// Adds the ID of the existing tenant or domain to a name conflict, when
+/// the caller is allowed to see it.
+pub(super) async fn with_existing_id(
+    server: &Server,
+    err: trc::Error,
+    access_token: &AccessToken,
+) -> trc::Error {
+    if !err.matches(trc::EventType::Manage(trc::ManageEvent::AlreadyExists))
+        || err.value_as_str(trc::Key::Key) != Some(PrincipalField::Name.as_str())
+        || access_token.tenant.is_some()
+    {
+        return err;
+    }
+    let Some(name) = err
+        .value_as_str(trc::Key::Value)
+        .map(|name| name.to_string())
+    else {
+        return err;
+    };
+
+    match server.store().get_principal_info(&name).await {
+        Ok(Some(info))
+            if match info.typ {
+                Type::Tenant => access_token.has_permission(Permission::TenantGet),
+                Type::Domain => access_token.has_permission(Permission::DomainGet),
+                _ => false,
+            } =>
+        {
+            err.ctx(trc::Key::Id, info.id)
+        }
+        _ => err,
+    }
+}
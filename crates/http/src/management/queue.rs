@@ -13,7 +13,7 @@ use common::{
     ipc::QueueEvent,
 };
 use directory::{Permission, Type, backend::internal::manage::ManageDirectory};
-use http_proto::{request::decode_path_element, *};
+use http_proto::{plain::is_plain_text_request, request::decode_path_element, *};
 use hyper::Method;
 use mail_auth::{
     dmarc::URI,
@@ -31,7 +31,7 @@ use smtp::{
 };
 use std::{future::Future, sync::atomic::Ordering};
 use store::{
-    Deserialize, IterateParams, ValueKey,
+    Deserialize, IterateParams, U32_LEN, ValueKey,
     write::{
         AlignedBytes, Archive, QueueClass, ReportEvent, ValueClass, key::DeserializeBigEndian, now,
     },
@@ -39,6 +39,10 @@ use store::{
 use trc::AddContext;
 use utils::url_params::UrlParams;
 
+// Message fields listed in plain text
+const QUEUE_TABLE_COLUMNS: &[&str] =
+    &["id", "return_path", "recipients.address", "created", "size"];
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
 pub struct Message {
     pub id: QueueId,
@@ -137,16 +141,18 @@ impl QueueManagement for Server {
     ) -> trc::Result<HttpResponse> {
         let params = UrlParams::new(req.uri().query());
         let mut tenant_domains: Option<Vec<String>> = None;
+        let mut tenant_id: Option<u32> = None;
 
         // SPDX-SnippetBegin
         // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
         // SPDX-License-Identifier: LicenseRef-SEL
 
-        // Limit to tenant domains
+        // Limit to tenant messages and domains
         #[cfg(feature = "enterprise")]
         if self.core.is_enterprise_edition()
             && let Some(tenant) = access_token.tenant
         {
+            tenant_id = tenant.id.into();
             tenant_domains = self
                 .core
                 .storage
@@ -175,7 +181,23 @@ impl QueueManagement for Server {
                 // Validate the access token
                 access_token.assert_has_permission(Permission::MessageQueueList)?;
 
-                let result = fetch_queued_messages(self, &params, &tenant_domains).await?;
+                let is_plain_text = is_plain_text_request(req);
+                let result = fetch_queued_messages(
+                    self,
+                    &params,
+                    tenant_id,
+                    is_plain_text || params.has_key("values"),
+                )
+                .await?;
+
+                if is_plain_text {
+                    let items = serde_json::to_value(&result.values).unwrap_or_default();
+                    return Ok(TextTable::from_json(
+                        QUEUE_TABLE_COLUMNS,
+                        items.as_array().into_iter().flatten(),
+                    )
+                    .into_http_response());
+                }
 
                 let queue_status = self.inner.data.queue_status.load(Ordering::Relaxed);
 
@@ -203,14 +225,14 @@ impl QueueManagement for Server {
                 access_token.assert_has_permission(Permission::MessageQueueGet)?;
 
                 let queue_id = queue_id.parse().unwrap_or_default();
-                if let Some(message_) = self.read_message_archive(queue_id).await? {
+                if is_tenant_message(self, tenant_id, queue_id).await?
+                    && let Some(message_) = self.read_message_archive(queue_id).await?
+                {
                     let message = message_.unarchive::<queue::Message>()?;
-                    if message.is_tenant_domain(&tenant_domains) {
-                        return Ok(JsonResponse::new(json!({
-                                "data": Message::from_archive(queue_id, message),
-                        }))
-                        .into_http_response());
-                    }
+                    return Ok(JsonResponse::new(json!({
+                            "data": Message::from_archive(queue_id, message),
+                    }))
+                    .into_http_response());
                 }
                 Err(trc::ResourceEvent::NotFound.into_err())
             }
@@ -222,7 +244,9 @@ impl QueueManagement for Server {
                     .parse::<FutureTimestamp>("at")
                     .map(|t| t.into_inner())
                     .unwrap_or_else(now);
-                let result = fetch_queued_messages(self, &params, &tenant_domains).await?;
+                let result =
+                    fetch_queued_messages(self, &params, tenant_id, params.has_key("values"))
+                        .await?;
 
                 let found = !result.ids.is_empty();
                 if found {
@@ -276,14 +300,10 @@ impl QueueManagement for Server {
                     .unwrap_or_else(now);
                 let item = params.get("filter");
 
-                if let Some(mut message) = self
-                    .read_message(queue_id.parse().unwrap_or_default(), QueueName::default())
-                    .await
-                    .filter(|message| {
-                        tenant_domains
-                            .as_ref()
-                            .is_none_or(|domains| message.has_domain(domains))
-                    })
+                let queue_id = queue_id.parse().unwrap_or_default();
+                if is_tenant_message(self, tenant_id, queue_id).await?
+                    && let Some(mut message) =
+                        self.read_message(queue_id, QueueName::default()).await
                 {
                     let mut found = false;
 
@@ -324,7 +344,9 @@ impl QueueManagement for Server {
                 // Validate the access token
                 access_token.assert_has_permission(Permission::MessageQueueDelete)?;
 
-                let result = fetch_queued_messages(self, &params, &tenant_domains).await?;
+                let result =
+                    fetch_queued_messages(self, &params, tenant_id, params.has_key("values"))
+                        .await?;
 
                 let found = !result.ids.is_empty();
                 if found {
@@ -369,14 +391,10 @@ impl QueueManagement for Server {
                 // Validate the access token
                 access_token.assert_has_permission(Permission::MessageQueueDelete)?;
 
-                if let Some(mut message) = self
-                    .read_message(queue_id.parse().unwrap_or_default(), QueueName::default())
-                    .await
-                    .filter(|message| {
-                        tenant_domains
-                            .as_ref()
-                            .is_none_or(|domains| message.has_domain(domains))
-                    })
+                let queue_id = queue_id.parse().unwrap_or_default();
+                if is_tenant_message(self, tenant_id, queue_id).await?
+                    && let Some(mut message) =
+                        self.read_message(queue_id, QueueName::default()).await
                 {
                     let mut found = false;
                     if let Some(item) = params.get("filter") {
@@ -633,7 +651,8 @@ struct QueuedMessages {
 async fn fetch_queued_messages(
     server: &Server,
     params: &UrlParams<'_>,
-    tenant_domains: &Option<Vec<String>>,
+    tenant_id: Option<u32>,
+    values: bool,
 ) -> trc::Result<QueuedMessages> {
     let queue = params.get("queue").and_then(QueueName::new);
     let text = params.get("text");
@@ -647,7 +666,6 @@ async fn fetch_queued_messages(
         .map(|t| t.into_inner());
     let page = params.parse::<usize>("page").unwrap_or_default();
     let limit = params.parse::<usize>("limit").unwrap_or_default();
-    let values = params.has_key("values");
 
     let range_start = params.parse::<u64>("range-start").unwrap_or_default();
     let range_end = params.parse::<u64>("range-end").unwrap_or(u64::MAX);
@@ -658,8 +676,6 @@ async fn fetch_queued_messages(
         values: Vec::new(),
         total: 0,
     };
-    let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(range_start)));
-    let to_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(range_end)));
     let has_filters = text.is_some()
         || from.is_some()
         || to.is_some()
@@ -669,76 +685,137 @@ async fn fetch_queued_messages(
     let mut offset = page.saturating_sub(1) * limit;
     let mut total_returned = 0;
 
-    server
-        .core
-        .storage
-        .data
-        .iterate(
-            IterateParams::new(from_key, to_key).ascending(),
-            |key, value| {
-                let message_ = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
-                    .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
-                let message = message_
-                    .unarchive::<queue::Message>()
-                    .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
-                let matches = tenant_domains
+    let mut add_message = |queue_id: QueueId, message: &ArchivedMessage| {
+        let matches = !has_filters
+            || (text
+                .as_ref()
+                .map(|text| {
+                    message.return_path.contains(text)
+                        || message
+                            .recipients
+                            .iter()
+                            .any(|r| r.address().contains(text))
+                })
+                .unwrap_or_else(|| {
+                    from.as_ref()
+                        .is_none_or(|from| message.return_path.contains(from))
+                        && to.as_ref().is_none_or(|to| {
+                            message.recipients.iter().any(|r| r.address().contains(to))
+                        })
+                })
+                && before.as_ref().is_none_or(|before| {
+                    message
+                        .next_delivery_event(queue)
+                        .is_some_and(|next| next < *before)
+                })
+                && after.as_ref().is_none_or(|after| {
+                    message
+                        .next_delivery_event(queue)
+                        .is_some_and(|next| next > *after)
+                })
+                && queue
                     .as_ref()
-                    .is_none_or(|domains| message.has_domain(domains))
-                    && (!has_filters
-                        || (text
-                            .as_ref()
-                            .map(|text| {
-                                message.return_path.contains(text)
-                                    || message
-                                        .recipients
-                                        .iter()
-                                        .any(|r| r.address().contains(text))
-                            })
-                            .unwrap_or_else(|| {
-                                from.as_ref()
-                                    .is_none_or(|from| message.return_path.contains(from))
-                                    && to.as_ref().is_none_or(|to| {
-                                        message.recipients.iter().any(|r| r.address().contains(to))
-                                    })
-                            })
-                            && before.as_ref().is_none_or(|before| {
-                                message
-                                    .next_delivery_event(queue)
-                                    .is_some_and(|next| next < *before)
-                            })
-                            && after.as_ref().is_none_or(|after| {
-                                message
-                                    .next_delivery_event(queue)
-                                    .is_some_and(|next| next > *after)
-                            })
-                            && queue
-                                .as_ref()
-                                .is_none_or(|q| message.recipients.iter().any(|r| &r.queue == q))));
-
-                if matches {
-                    if offset == 0 {
-                        if limit == 0 || total_returned < limit {
-                            let queue_id = key.deserialize_be_u64(0)?;
-                            if values {
-                                result.values.push(Message::from_archive(queue_id, message));
-                            } else {
-                                result.ids.push(queue_id);
-                            }
-                            total_returned += 1;
-                        }
+                    .is_none_or(|q| message.recipients.iter().any(|r| &r.queue == q)));
+
+        if matches {
+            if offset == 0 {
+                if limit == 0 || total_returned < limit {
+                    if values {
+                        result.values.push(Message::from_archive(queue_id, message));
                     } else {
-                        offset -= 1;
+                        result.ids.push(queue_id);
                     }
-
-                    result.total += 1;
+                    total_returned += 1;
                 }
+            } else {
+                offset -= 1;
+            }
 
-                Ok(max_total == 0 || result.total < max_total)
-            },
-        )
-        .await
-        .caused_by(trc::location!())
-        .map(|_| result)
+            result.total += 1;
+        }
+
+        max_total == 0 || result.total < max_total
+    };
+
+    if let Some(tenant_id) = tenant_id {
+        // Obtain the messages submitted by the tenant
+        let mut queue_ids = Vec::new();
+        server
+            .core
+            .storage
+            .data
+            .iterate(
+                IterateParams::new(
+                    ValueKey::from(ValueClass::Queue(QueueClass::TenantIndex {
+                        tenant_id,
+                        queue_id: range_start,
+                    })),
+                    ValueKey::from(ValueClass::Queue(QueueClass::TenantIndex {
+                        tenant_id,
+                        queue_id: range_end,
+                    })),
+                )
+                .ascending()
+                .no_values(),
+                |key, _| {
+                    queue_ids.push(key.deserialize_be_u64(U32_LEN + 1)?);
+
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        for queue_id in queue_ids {
+            if let Some(message_) = server.read_message_archive(queue_id).await?
+                && !add_message(queue_id, message_.unarchive::<queue::Message>()?)
+            {
+                break;
+            }
+        }
+    } else {
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(range_start)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(range_end)));
+
+        server
+            .core
+            .storage
+            .data
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |key, value| {
+                    let message_ = <Archive<AlignedBytes> as Deserialize>::deserialize(value)
+                        .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+                    let message = message_
+                        .unarchive::<queue::Message>()
+                        .add_context(|ctx| ctx.ctx(trc::Key::Key, key))?;
+
+                    Ok(add_message(key.deserialize_be_u64(0)?, message))
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+    }
+
+    Ok(result)
+}
+
+/// Returns whether a queued message was submitted by the tenant, all messages
+/// are visible when no tenant is specified.
+async fn is_tenant_message(
+    server: &Server,
+    tenant_id: Option<u32>,
+    queue_id: QueueId,
+) -> trc::Result<bool> {
+    if let Some(tenant_id) = tenant_id {
+        server
+            .read_message_tenant(queue_id)
+            .await
+            .map(|message_tenant_id| message_tenant_id == Some(tenant_id))
+            .caused_by(trc::location!())
+    } else {
+        Ok(true)
+    }
 }
 
 struct QueuedReports {
@@ -937,14 +1014,3 @@ where
 fn is_zero(num: &i16) -> bool {
     *num == 0
 }
-
-trait IsTenantDomain {
-    fn is_tenant_domain(&self, tenant_domains: &Option<Vec<String>>) -> bool;
-}
-impl IsTenantDomain for ArchivedMessage {
-    fn is_tenant_domain(&self, tenant_domains: &Option<Vec<String>>) -> bool {
-        tenant_domains
-            .as_ref()
-            .is_none_or(|domains| self.has_domain(domains))
-    }
-}
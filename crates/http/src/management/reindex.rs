@@ -0,0 +1,119 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, auth::AccessToken};
+use directory::{
+    Permission, Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
+use http_proto::*;
+use hyper::Method;
+use serde_json::json;
+use services::task_manager::reindex::{DEFAULT_REINDEX_CONCURRENCY, ReindexJobTask};
+use std::future::Future;
+use store::roaring::RoaringBitmap;
+use trc::AddContext;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ReindexRequest {
+    /// Number of accounts reindexed in parallel.
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+pub trait ReindexManager: Sync + Send {
+    fn handle_reindex(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        owner_id: u32,
+        typ: Type,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl ReindexManager for Server {
+    // Messages are reindexed in place: searches keep returning results from
+    // the existing index while a job runs, and each message is replaced as it
+    // is reindexed. Messages whose document cannot be built keep their previous
+    // index entries and are reported as failed.
+    async fn handle_reindex(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        owner_id: u32,
+        typ: Type,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        access_token.assert_has_permission(Permission::FtsReindex)?;
+
+        match (path.get(3).copied(), req.method()) {
+            (None, &Method::POST) => {
+                let request = if body.as_ref().is_some_and(|body| !body.is_empty()) {
+                    serde_json::from_slice::<ReindexRequest>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?
+                } else {
+                    ReindexRequest::default()
+                };
+
+                let account_ids = if typ == Type::Tenant {
+                    let mut account_ids = self
+                        .store()
+                        .principal_ids(Some(Type::Individual), Some(owner_id))
+                        .await
+                        .caused_by(trc::location!())?;
+                    account_ids |= self
+                        .store()
+                        .principal_ids(Some(Type::Group), Some(owner_id))
+                        .await
+                        .caused_by(trc::location!())?;
+                    account_ids
+                } else {
+                    RoaringBitmap::from_iter([owner_id])
+                };
+
+                let job = self
+                    .start_reindex_job(
+                        owner_id,
+                        account_ids,
+                        request.concurrency.unwrap_or(DEFAULT_REINDEX_CONCURRENCY),
+                    )
+                    .await?
+                    .ok_or_else(|| {
+                        manage::error(
+                            "Reindex in progress",
+                            "A reindex job is already running for this principal".into(),
+                        )
+                    })?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": job.summary(),
+                }))
+                .into_http_response())
+            }
+            (Some(job_id), &Method::GET) => {
+                let job = job_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|job_id| self.inner.data.reindex_jobs.get(job_id))
+                    .filter(|job| job.owner_id == owner_id)
+                    .ok_or_else(|| manage::not_found(job_id.to_string()))?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": job.summary(),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::principal::PrincipalManager;
+use common::{
+    Server,
+    auth::AccessToken,
+    config::groupware::{BookableResource, BookingPolicy, ResourceKind},
+};
+use directory::{
+    Permission, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet,
+        manage::{self, ManageDirectory},
+    },
+};
+use groupware::calendar::booking::{BookingAnswer, ResourceBooking};
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::{Value, json};
+use std::future::Future;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ResourceRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub emails: Vec<String>,
+    pub kind: ResourceKind,
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    #[serde(default)]
+    pub location: Option<String>,
+    pub booking_policy: BookingPolicy,
+    #[serde(default)]
+    pub approver: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ResourceSettings {
+    pub kind: ResourceKind,
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    #[serde(default)]
+    pub location: Option<String>,
+    pub booking_policy: BookingPolicy,
+    #[serde(default)]
+    pub approver: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct BookingAnswerRequest {
+    pub accept: bool,
+}
+
+pub trait ResourceManager: Sync + Send {
+    fn handle_resources(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl ResourceManager for Server {
+    async fn handle_resources(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (
+            path.get(3).map(|name| decode_path_element(name)),
+            path.get(4).copied(),
+            req.method(),
+        ) {
+            (None, None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let mut items = Vec::new();
+                for (account_id, resource) in self.tenant_bookable_resources(tenant_id) {
+                    if let Some(item) = resource_details(self, account_id, &resource).await? {
+                        items.push(item);
+                    }
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": items,
+                        "total": items.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (None, None, &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualCreate)?;
+
+                let request =
+                    serde_json::from_slice::<ResourceRequest>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+                if request.name.is_empty() {
+                    return Err(manage::err_missing("name"));
+                }
+                let resource = build_resource(
+                    self,
+                    tenant_id,
+                    None,
+                    ResourceSettings {
+                        kind: request.kind,
+                        capacity: request.capacity,
+                        location: request.location,
+                        booking_policy: request.booking_policy,
+                        approver: request.approver,
+                    },
+                )
+                .await?;
+
+                // Resources have no secrets, their calendar is managed by the server
+                let name = request.name.trim().to_lowercase();
+                let emails = if request.emails.is_empty() && name.contains('@') {
+                    vec![name.clone()]
+                } else {
+                    request.emails
+                };
+                if emails.is_empty() {
+                    return Err(manage::err_missing("emails"));
+                }
+                let mut principal = PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, name)
+                    .with_field(PrincipalField::Emails, emails)
+                    .with_field(PrincipalField::Roles, vec!["user".to_string()]);
+                if let Some(description) = request.description {
+                    principal.set(PrincipalField::Description, description);
+                }
+                if access_token.tenant.is_none()
+                    && let Some(tenant) = self.store().get_principal_name(tenant_id).await?
+                {
+                    principal.set(PrincipalField::Tenant, tenant);
+                }
+                let account_id = self
+                    .create_managed_principal(principal, access_token, false)
+                    .await?;
+
+                self.update_bookable_resource(account_id, resource.into())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": account_id,
+                }))
+                .into_http_response())
+            }
+            (Some(name), None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let (account_id, resource) =
+                    resource_by_name(self, tenant_id, name.as_ref()).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": resource_details(self, account_id, &resource).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(name), None, &Method::PUT) => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let (account_id, _) = resource_by_name(self, tenant_id, name.as_ref()).await?;
+                let settings =
+                    serde_json::from_slice::<ResourceSettings>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+                let resource = build_resource(self, tenant_id, Some(account_id), settings).await?;
+                self.update_bookable_resource(account_id, resource.clone().into())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": resource_details(self, account_id, &resource).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(name), Some("bookings"), method) => {
+                let (account_id, resource) =
+                    resource_by_name(self, tenant_id, name.as_ref()).await?;
+
+                // Bookings are answered by the approver of the resource
+                if resource.approver_id != Some(access_token.primary_id()) {
+                    access_token.assert_has_permission(if *method == Method::GET {
+                        Permission::IndividualGet
+                    } else {
+                        Permission::IndividualUpdate
+                    })?;
+                }
+                let resource_token = self.get_access_token(account_id).await?;
+
+                match (path.get(5).map(|uid| decode_path_element(uid)), method) {
+                    (None, &Method::GET) => {
+                        let items = self.resource_bookings(&resource_token).await?;
+
+                        Ok(JsonResponse::new(json!({
+                            "data": {
+                                "total": items.len(),
+                                "items": items,
+                            },
+                        }))
+                        .into_http_response())
+                    }
+                    (Some(uid), &Method::POST) => {
+                        let request = serde_json::from_slice::<BookingAnswerRequest>(
+                            body.as_deref().unwrap_or_default(),
+                        )
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+
+                        match self
+                            .resource_booking_answer(&resource_token, uid.as_ref(), request.accept)
+                            .await?
+                        {
+                            BookingAnswer::Answered => Ok(JsonResponse::new(json!({
+                                "data": (),
+                            }))
+                            .into_http_response()),
+                            BookingAnswer::Conflict => Err(manage::error(
+                                "Booking conflict",
+                                "The resource is already booked at that time".into(),
+                            )),
+                            BookingAnswer::NotFound => Err(manage::not_found(uid.to_string())),
+                        }
+                    }
+                    _ => Err(trc::ResourceEvent::NotFound.into_err()),
+                }
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+async fn resource_by_name(
+    server: &Server,
+    tenant_id: u32,
+    name: &str,
+) -> trc::Result<(u32, BookableResource)> {
+    if let Some(info) = server.store().get_principal_info(name).await?
+        && let Some(resource) = server
+            .bookable_resource(info.id)
+            .filter(|resource| resource.tenant_id == tenant_id)
+    {
+        Ok((info.id, resource.as_ref().clone()))
+    } else {
+        Err(manage::not_found(name.to_string()))
+    }
+}
+
+/// Validates the settings of a resource, the approver has to be an
+/// Individual of the tenant other than the resource itself.
+async fn build_resource(
+    server: &Server,
+    tenant_id: u32,
+    account_id: Option<u32>,
+    settings: ResourceSettings,
+) -> trc::Result<BookableResource> {
+    let approver_id = if let Some(approver) = settings.approver {
+        server
+            .store()
+            .get_principal_info(&approver)
+            .await?
+            .filter(|info| {
+                info.typ == Type::Individual
+                    && info.tenant == Some(tenant_id)
+                    && Some(info.id) != account_id
+                    && server.bookable_resource(info.id).is_none()
+            })
+            .ok_or_else(|| {
+                manage::error(
+                    "Invalid approver",
+                    format!("{approver:?} is not an account of this organization").into(),
+                )
+            })?
+            .id
+            .into()
+    } else {
+        None
+    };
+
+    let resource = BookableResource {
+        tenant_id,
+        kind: settings.kind,
+        capacity: settings.capacity,
+        location: settings.location.filter(|location| !location.is_empty()),
+        policy: settings.booking_policy,
+        approver_id,
+    };
+    resource
+        .validate()
+        .map_err(|err| manage::error(err, None::<u64>))?;
+
+    Ok(resource)
+}
+
+async fn resource_details(
+    server: &Server,
+    account_id: u32,
+    resource: &BookableResource,
+) -> trc::Result<Option<Value>> {
+    let store = server.store();
+    let Some(principal) = store.get_principal(account_id).await? else {
+        return Ok(None);
+    };
+    let approver = if let Some(approver_id) = resource.approver_id {
+        store.get_principal_name(approver_id).await?
+    } else {
+        None
+    };
+
+    Ok(Some(json!({
+        "id": account_id,
+        "name": principal.name(),
+        "description": principal.description(),
+        "emails": principal.email_addresses().collect::<Vec<_>>(),
+        "kind": resource.kind,
+        "capacity": resource.capacity,
+        "location": resource.location,
+        "bookingPolicy": resource.policy,
+        "approver": approver,
+    })))
+}
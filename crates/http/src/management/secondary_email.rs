@@ -0,0 +1,264 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    auth::{AccessToken, verification::EmailVerificationToken},
+};
+use directory::{
+    Type,
+    backend::internal::{
+        manage::{ManageDirectory, not_found},
+        secondary::SecondaryEmails,
+    },
+};
+use http_proto::{
+    request::{decode_path_element, fetch_body},
+    *,
+};
+use hyper::Method;
+use mail_builder::{MessageBuilder, headers::HeaderType};
+use serde_json::json;
+use smtp::reporting::SmtpReporting;
+use std::future::Future;
+use store::write::now;
+use utils::url_params::UrlParams;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecondaryEmailRequest {
+    address: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+pub trait SecondaryEmailManager: Sync + Send {
+    fn handle_account_emails(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+        session: &HttpSessionData,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_verify_email(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn send_email_verification(
+        &self,
+        req: &HttpRequest,
+        session: &HttpSessionData,
+        account_name: &str,
+        token: &EmailVerificationToken,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl SecondaryEmailManager for Server {
+    // Secondary addresses are added by the account owner and only become
+    // verified once the confirmation link sent to them is used.
+    async fn handle_account_emails(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+        session: &HttpSessionData,
+    ) -> trc::Result<HttpResponse> {
+        let account_id = access_token.primary_id();
+        let address = path.get(2).map(|address| decode_path_element(address));
+
+        match (address, path.get(3).copied(), req.method()) {
+            (None, None, &Method::GET) => {
+                let principal = self
+                    .store()
+                    .get_principal(account_id)
+                    .await?
+                    .ok_or_else(|| not_found(account_id))?;
+                let now = now();
+                let emails = principal
+                    .secondary_emails()
+                    .map(|address| json!({"address": address, "state": "verified"}))
+                    .chain(
+                        principal
+                            .pending_emails()
+                            .filter(|(_, _, expires)| *expires > now)
+                            .map(|(address, _, expires)| {
+                                json!({"address": address, "state": "pending", "expires": expires})
+                            }),
+                    )
+                    .collect::<Vec<_>>();
+
+                Ok(JsonResponse::new(json!({
+                    "data": emails,
+                }))
+                .into_http_response())
+            }
+            (None, None, &Method::POST) => {
+                let request = serde_json::from_slice::<SecondaryEmailRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                let principal = self
+                    .store()
+                    .get_principal(account_id)
+                    .await?
+                    .filter(|p| p.typ == Type::Individual)
+                    .ok_or_else(|| not_found(account_id))?;
+
+                let nonce = self.inner.data.jmap_id_gen.generate();
+                let expires = now() + self.core.jmap.email_verification_expiry;
+                let address = self
+                    .store()
+                    .add_pending_email(account_id, &request.address, nonce, expires)
+                    .await?;
+                let token = EmailVerificationToken {
+                    account_id,
+                    address,
+                    nonce,
+                    expires,
+                };
+                self.send_email_verification(req, session, principal.name(), &token)
+                    .await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {"address": token.address, "state": "pending", "expires": expires},
+                }))
+                .into_http_response())
+            }
+            (Some(address), Some("resend"), &Method::POST) => {
+                let principal = self
+                    .store()
+                    .get_principal(account_id)
+                    .await?
+                    .ok_or_else(|| not_found(account_id))?;
+
+                let nonce = self.inner.data.jmap_id_gen.generate();
+                let expires = now() + self.core.jmap.email_verification_expiry;
+                self.store()
+                    .renew_pending_email(account_id, address.as_ref(), nonce, expires)
+                    .await?;
+                let token = EmailVerificationToken {
+                    account_id,
+                    address: address.to_string(),
+                    nonce,
+                    expires,
+                };
+                self.send_email_verification(req, session, principal.name(), &token)
+                    .await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {"address": token.address, "state": "pending", "expires": expires},
+                }))
+                .into_http_response())
+            }
+            (Some(address), None, &Method::DELETE) => {
+                if self
+                    .store()
+                    .remove_secondary_email(account_id, address.as_ref())
+                    .await?
+                {
+                    Ok(JsonResponse::new(json!({
+                        "data": (),
+                    }))
+                    .into_http_response())
+                } else {
+                    Err(not_found(address.to_string()))
+                }
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+
+    // Tokens are accepted from the query string of the confirmation link or
+    // a JSON body, only on POST so that link scanners can't consume them.
+    async fn handle_verify_email(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> trc::Result<HttpResponse> {
+        let token = if let Some(token) = UrlParams::new(req.uri().query()).get("token") {
+            token.to_string()
+        } else {
+            fetch_body(req, 1024 * 1024, session.session_id)
+                .await
+                .and_then(|body| serde_json::from_slice::<VerifyEmailRequest>(&body).ok())
+                .map(|request| request.token)
+                .unwrap_or_default()
+        };
+
+        let invalid_token = || {
+            trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Invalid or expired token")
+        };
+        let token = EmailVerificationToken::verify(&self.core.oauth.oauth_key, &token)
+            .filter(|token| token.expires > now())
+            .ok_or_else(invalid_token)?;
+        if self
+            .store()
+            .verify_pending_email(token.account_id, &token.address, token.nonce)
+            .await?
+        {
+            Ok(JsonResponse::new(json!({
+                "data": {"address": token.address, "state": "verified"},
+            }))
+            .into_http_response())
+        } else {
+            Err(invalid_token())
+        }
+    }
+
+    async fn send_email_verification(
+        &self,
+        req: &HttpRequest,
+        session: &HttpSessionData,
+        account_name: &str,
+        token: &EmailVerificationToken,
+    ) {
+        let base_url = HttpContext::new(session, req)
+            .resolve_response_url(self)
+            .await;
+        let from = self.core.jmap.email_verification_from.as_str();
+        let message = MessageBuilder::new()
+            .from(from)
+            .to(token.address.as_str())
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .subject("Confirm your email address")
+            .text_body(format!(
+                concat!(
+                    "The account {} has requested to add {} as a secondary address.\r\n\r\n",
+                    "To confirm the request, open the following link:\r\n\r\n",
+                    "{}/auth/verify-email?token={}\r\n\r\n",
+                    "If you did not make this request, you can ignore this message.\r\n"
+                ),
+                account_name,
+                token.address,
+                base_url,
+                token.sign(&self.core.oauth.oauth_key)
+            ))
+            .write_to_vec()
+            .unwrap_or_default();
+
+        self.send_autogenerated(
+            from,
+            [token.address.as_str()].into_iter(),
+            message,
+            None,
+            session.session_id,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,252 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::principal::PrincipalManager;
+use common::{Server, auth::AccessToken, config::spamfilter::SenderListType};
+use directory::{
+    Permission, Principal, QueryBy, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet,
+        activity::{ActivityType, PrincipalActivity},
+        manage::{self, ChangedPrincipals, ManageDirectory},
+        sender::SenderCredentials,
+    },
+};
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::{Value, json};
+use std::future::Future;
+use trc::AddContext;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SenderRequest {
+    pub name: String,
+    pub sender_address: String,
+    #[serde(default)]
+    pub send_rate: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+pub trait SenderCredentialManager: Sync + Send {
+    fn handle_senders(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl SenderCredentialManager for Server {
+    async fn handle_senders(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (
+            path.get(3).map(|name| decode_path_element(name)),
+            path.get(4).copied(),
+            req.method(),
+        ) {
+            (None, None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let accounts = self
+                    .store()
+                    .list_principals(None, Some(tenant_id), &[Type::Individual], false, 0, 0)
+                    .await?;
+                let mut items = Vec::new();
+                for account in accounts.items {
+                    if let Some(principal) = self.store().get_principal(account.id).await?
+                        && principal.sender_address().is_some()
+                    {
+                        items.push(sender_details(self, &principal).await?);
+                    }
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": items,
+                        "total": items.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (None, None, &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualCreate)?;
+
+                let request =
+                    serde_json::from_slice::<SenderRequest>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+                let name = request.name.trim().to_lowercase();
+                if name.is_empty() {
+                    return Err(manage::err_missing("name"));
+                } else if SenderListType::parse(&name).is_some() {
+                    return Err(manage::error(
+                        "Invalid name",
+                        format!("{name:?} is reserved for sender lists").into(),
+                    ));
+                }
+                if request.sender_address.trim().is_empty() {
+                    return Err(manage::err_missing("senderAddress"));
+                }
+
+                // Send-only credentials have no addresses, and therefore no
+                // mailbox, and can only authenticate over SMTP
+                let mut principal = PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, name.clone())
+                    .with_field(PrincipalField::Roles, vec!["user".to_string()])
+                    .with_field(
+                        PrincipalField::AllowedProtocols,
+                        vec!["submission".to_string()],
+                    )
+                    .with_field(PrincipalField::SenderAddress, request.sender_address);
+                if let Some(send_rate) = request.send_rate {
+                    principal.set(PrincipalField::SendRate, send_rate);
+                }
+                if let Some(description) = request.description {
+                    principal.set(PrincipalField::Description, description);
+                }
+                if access_token.tenant.is_none()
+                    && let Some(tenant) = self.store().get_principal_name(tenant_id).await?
+                {
+                    principal.set(PrincipalField::Tenant, tenant);
+                }
+                let account_id = self
+                    .create_managed_principal(principal, access_token, false)
+                    .await?;
+                let secret = self.store().rotate_sender_secret(account_id).await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::SenderCreated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = tenant_id,
+                    Id = name.clone(),
+                );
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "id": account_id,
+                        "name": name,
+                        "secret": secret,
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(name), None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let principal = sender_by_name(self, tenant_id, name.as_ref()).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": sender_details(self, &principal).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(name), Some("rotate"), &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let principal = sender_by_name(self, tenant_id, name.as_ref()).await?;
+                let secret = self.store().rotate_sender_secret(principal.id).await?;
+
+                // Sessions authenticated with the previous secret are dropped
+                self.invalidate_principal_caches(ChangedPrincipals::from_change(
+                    principal.id,
+                    Type::Individual,
+                    PrincipalField::Secrets,
+                ))
+                .await;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::SenderSecretRotated),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = tenant_id,
+                    Id = principal.name,
+                );
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "id": principal.id,
+                        "secret": secret,
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some(name), None, &Method::DELETE) => {
+                access_token.assert_has_permission(Permission::IndividualDelete)?;
+
+                let principal = sender_by_name(self, tenant_id, name.as_ref()).await?;
+                let changed_principals = self
+                    .store()
+                    .delete_principal(QueryBy::Id(principal.id))
+                    .await?;
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::PrincipalDeleted),
+                    AccountName = access_token.name.clone(),
+                    AccountId = access_token.primary_id(),
+                    TenantId = tenant_id,
+                    Id = principal.name,
+                    Type = Type::Individual.as_str(),
+                );
+
+                self.invalidate_principal_caches(changed_principals).await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+/// Only send-only credentials of the tenant are resolved, other accounts are
+/// managed through the principal endpoints.
+async fn sender_by_name(server: &Server, tenant_id: u32, name: &str) -> trc::Result<Principal> {
+    if let Some(info) = server.store().get_principal_info(name).await?
+        && info.typ == Type::Individual
+        && info.tenant == Some(tenant_id)
+        && let Some(principal) = server.store().get_principal(info.id).await?
+        && principal.sender_address().is_some()
+    {
+        Ok(principal)
+    } else {
+        Err(manage::not_found(name.to_string()))
+    }
+}
+
+async fn sender_details(server: &Server, principal: &Principal) -> trc::Result<Value> {
+    let last_used = server
+        .store()
+        .last_activity(principal.id)
+        .await
+        .caused_by(trc::location!())?
+        .into_iter()
+        .find_map(|(typ, timestamp)| (typ == ActivityType::Smtp).then_some(timestamp));
+
+    Ok(json!({
+        "id": principal.id,
+        "name": principal.name(),
+        "description": principal.description(),
+        "senderAddress": principal.sender_address(),
+        "sendRate": principal.send_rate(),
+        "lastUsed": last_used,
+    }))
+}
@@ -0,0 +1,60 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::auth::authenticate::HttpHeaders;
+use common::{Server, auth::AccessToken};
+use http_proto::*;
+use serde_json::json;
+use std::{cell::RefCell, future::Future};
+
+pub trait AccountSessionManager: Sync + Send {
+    fn handle_account_sessions(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl AccountSessionManager for Server {
+    // Lists the HTTP sessions of the account on this node along with the
+    // limits that apply to new sessions.
+    async fn handle_account_sessions(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let account_id = access_token.primary_id();
+        let current = req.authorization().map(|(_, token)| token);
+        let sessions = RefCell::new(Vec::new());
+        self.inner.cache.http_auth.retain(|token, entry| {
+            if entry.account_id == account_id && !entry.session.ended {
+                let session = &entry.session;
+                sessions.borrow_mut().push((
+                    session.created_at,
+                    json!({
+                        "createdAt": session.created_at,
+                        "lastUsed": session.last_used,
+                        "expiresAt": session.limits.expires_at(session.created_at, session.last_used),
+                        "maxLifetime": session.limits.max_lifetime,
+                        "idleTimeout": session.limits.idle_timeout,
+                        "current": current == Some(token.as_str()),
+                    }),
+                ));
+            }
+            true
+        });
+        let mut sessions = sessions.into_inner();
+        sessions.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+        Ok(JsonResponse::new(json!({
+            "data": {
+                "limits": self.session_limits(access_token.tenant.map(|t| t.id)).await,
+                "sessions": sessions.into_iter().map(|(_, session)| session).collect::<Vec<_>>(),
+            },
+        }))
+        .into_http_response())
+    }
+}
@@ -4,11 +4,17 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use common::{Server, auth::AccessToken};
-use directory::Permission;
+use common::{
+    KV_RATE_LIMIT_WEBHOOK_TEST, Server,
+    auth::AccessToken,
+    config::telemetry::WebhookTracer,
+    telemetry::webhooks::{test_webhook, webhook_deliveries},
+};
+use directory::{Permission, backend::internal::manage::not_found};
 use hyper::Method;
 use serde_json::json;
 use store::ahash::AHashMap;
+use trc::AddContext;
 use utils::{config::ConfigKey, map::vec_map::VecMap, url_params::UrlParams};
 
 use http_proto::{request::decode_path_element, *};
@@ -255,6 +261,56 @@ impl ManageSettings for Server {
                 }))
                 .into_http_response())
             }
+            (Some("webhooks"), &Method::POST) if path.get(3).copied() == Some("test") => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::SettingsUpdate)?;
+
+                // Test deliveries are limited so the server can't be used as a request proxy
+                let settings = webhook_settings(self, path.get(2).copied()).await?;
+                if let Some(rate) = &self.core.jmap.rate_webhook_test
+                    && self
+                        .core
+                        .storage
+                        .lookup
+                        .is_rate_allowed(
+                            KV_RATE_LIMIT_WEBHOOK_TEST,
+                            settings.id.as_bytes(),
+                            rate,
+                            false,
+                        )
+                        .await
+                        .caused_by(trc::location!())?
+                        .is_some()
+                {
+                    return Err(trc::LimitEvent::TooManyRequests.into_err());
+                }
+
+                let result = test_webhook(&settings).await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "success": result.as_ref().is_ok_and(|response| response.is_success()),
+                        "response": result.as_ref().ok(),
+                        "error": result.as_ref().err(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (Some("webhooks"), &Method::GET) if path.get(3).copied() == Some("deliveries") => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::SettingsList)?;
+
+                let settings = webhook_settings(self, path.get(2).copied()).await?;
+                let deliveries = webhook_deliveries(&settings.id);
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": deliveries,
+                        "total": deliveries.len(),
+                    },
+                }))
+                .into_http_response())
+            }
             (Some(prefix), &Method::DELETE) if !prefix.is_empty() => {
                 // Validate the access token
                 access_token.assert_has_permission(Permission::SettingsDelete)?;
@@ -353,3 +409,14 @@ impl ManageSettings for Server {
         }
     }
 }
+
+async fn webhook_settings(server: &Server, id: Option<&str>) -> trc::Result<WebhookTracer> {
+    let id = decode_path_element(id.unwrap_or_default());
+    let mut config = server
+        .core
+        .storage
+        .config
+        .build_config(&format!("webhook.{id}."))
+        .await?;
+    WebhookTracer::parse(&mut config, id.as_ref()).ok_or_else(|| not_found(id.to_string()))
+}
@@ -0,0 +1,276 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::principal::PrincipalManager;
+use common::{
+    Server,
+    auth::AccessToken,
+    config::jmap::shared::{SharedMailbox, SharedMailboxAccess, SharedMailboxRights},
+};
+use directory::{
+    Permission, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet,
+        manage::{self, ManageDirectory},
+    },
+};
+use email::mailbox::manage::MailboxFnc;
+use http_proto::{request::decode_path_element, *};
+use hyper::Method;
+use serde_json::{Value, json};
+use std::future::Future;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SharedMailboxRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub emails: Vec<String>,
+    #[serde(default)]
+    pub quota: Option<u64>,
+    #[serde(default)]
+    pub access: Vec<SharedMailboxMember>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SharedMailboxMember {
+    pub name: String,
+    pub rights: SharedMailboxRights,
+}
+
+pub trait SharedMailboxManager: Sync + Send {
+    fn handle_shared_mailboxes(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl SharedMailboxManager for Server {
+    async fn handle_shared_mailboxes(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        tenant_id: u32,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        match (
+            path.get(3).map(|name| decode_path_element(name)),
+            path.get(4).copied(),
+            req.method(),
+        ) {
+            (None, None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let mut items = Vec::new();
+                for mailbox in self.tenant_shared_mailboxes(tenant_id).await? {
+                    if let Some(item) = shared_mailbox_details(self, &mailbox).await? {
+                        items.push(item);
+                    }
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "items": items,
+                        "total": items.len(),
+                    },
+                }))
+                .into_http_response())
+            }
+            (None, None, &Method::POST) => {
+                access_token.assert_has_permission(Permission::IndividualCreate)?;
+
+                let request = serde_json::from_slice::<SharedMailboxRequest>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                if request.name.is_empty() {
+                    return Err(manage::err_missing("name"));
+                }
+                let access = shared_mailbox_access(self, tenant_id, None, request.access).await?;
+
+                // Shared mailboxes have no secrets, so nobody can log in as them
+                let name = request.name.trim().to_lowercase();
+                let emails = if request.emails.is_empty() && name.contains('@') {
+                    vec![name.clone()]
+                } else {
+                    request.emails
+                };
+                let mut principal = PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, name)
+                    .with_field(PrincipalField::Emails, emails)
+                    .with_field(PrincipalField::Roles, vec!["user".to_string()]);
+                if let Some(description) = request.description {
+                    principal.set(PrincipalField::Description, description);
+                }
+                if let Some(quota) = request.quota {
+                    principal.set(PrincipalField::Quota, quota);
+                }
+                if access_token.tenant.is_none()
+                    && let Some(tenant) = self.store().get_principal_name(tenant_id).await?
+                {
+                    principal.set(PrincipalField::Tenant, tenant);
+                }
+                let account_id = self
+                    .create_managed_principal(principal, access_token, false)
+                    .await?;
+
+                let mailbox = SharedMailbox { account_id, access };
+                self.update_shared_mailbox(tenant_id, &mailbox).await?;
+                self.mailbox_share_all(account_id, mailbox.acl_grants())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": account_id,
+                }))
+                .into_http_response())
+            }
+            (Some(name), None, &Method::GET) => {
+                access_token.assert_has_permission(Permission::IndividualGet)?;
+
+                let mailbox = shared_mailbox_by_name(self, tenant_id, name.as_ref()).await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": shared_mailbox_details(self, &mailbox).await?,
+                }))
+                .into_http_response())
+            }
+            (Some(name), Some("access"), &Method::PUT) => {
+                access_token.assert_has_permission(Permission::IndividualUpdate)?;
+
+                let mut mailbox = shared_mailbox_by_name(self, tenant_id, name.as_ref()).await?;
+                let members = serde_json::from_slice::<Vec<SharedMailboxMember>>(
+                    body.as_deref().unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+                mailbox.access =
+                    shared_mailbox_access(self, tenant_id, Some(mailbox.account_id), members)
+                        .await?;
+
+                self.update_shared_mailbox(tenant_id, &mailbox).await?;
+
+                // Folders created since the last change are shared as well
+                self.mailbox_share_all(mailbox.account_id, mailbox.acl_grants())
+                    .await?;
+
+                Ok(JsonResponse::new(json!({
+                    "data": shared_mailbox_details(self, &mailbox).await?,
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+async fn shared_mailbox_by_name(
+    server: &Server,
+    tenant_id: u32,
+    name: &str,
+) -> trc::Result<SharedMailbox> {
+    if let Some(info) = server.store().get_principal_info(name).await?
+        && let Some(mailbox) = server.shared_mailbox(tenant_id, info.id).await?
+    {
+        Ok(mailbox)
+    } else {
+        Err(manage::not_found(name.to_string()))
+    }
+}
+
+/// Resolves the members of an access list, which have to be Individuals of
+/// the tenant other than the shared mailbox itself.
+async fn shared_mailbox_access(
+    server: &Server,
+    tenant_id: u32,
+    account_id: Option<u32>,
+    members: Vec<SharedMailboxMember>,
+) -> trc::Result<Vec<SharedMailboxAccess>> {
+    let mut access: Vec<SharedMailboxAccess> = Vec::with_capacity(members.len());
+    for member in members {
+        let member_id = server
+            .store()
+            .get_principal_info(&member.name)
+            .await?
+            .filter(|info| {
+                info.typ == Type::Individual
+                    && info.tenant == Some(tenant_id)
+                    && Some(info.id) != account_id
+            })
+            .ok_or_else(|| {
+                manage::error(
+                    "Invalid member",
+                    format!("{:?} is not an account of this organization", member.name).into(),
+                )
+            })?
+            .id;
+
+        if let Some(item) = access.iter_mut().find(|item| item.account_id == member_id) {
+            item.rights = member.rights;
+        } else {
+            access.push(SharedMailboxAccess {
+                account_id: member_id,
+                rights: member.rights,
+            });
+        }
+    }
+
+    if access.len() > server.core.groupware.max_shares_per_item {
+        return Err(manage::error(
+            "Too many members",
+            format!(
+                "Shared mailboxes can have at most {} members",
+                server.core.groupware.max_shares_per_item
+            )
+            .into(),
+        ));
+    }
+
+    Ok(access)
+}
+
+async fn shared_mailbox_details(
+    server: &Server,
+    mailbox: &SharedMailbox,
+) -> trc::Result<Option<Value>> {
+    let store = server.store();
+    let Some(principal) = store.get_principal(mailbox.account_id).await? else {
+        return Ok(None);
+    };
+
+    // Members deleted since the last change are left out
+    let mut access = Vec::with_capacity(mailbox.access.len());
+    for item in &mailbox.access {
+        if let Some(name) = store.get_principal_name(item.account_id).await? {
+            access.push(SharedMailboxMember {
+                name,
+                rights: item.rights,
+            });
+        }
+    }
+
+    Ok(Some(json!({
+        "id": mailbox.account_id,
+        "name": principal.name(),
+        "description": principal.description(),
+        "emails": principal.email_addresses().collect::<Vec<_>>(),
+        "quota": principal.quota(),
+        "usedQuota": server.get_used_quota(mailbox.account_id).await?.max(0),
+        "access": access,
+    })))
+}
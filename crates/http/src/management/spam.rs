@@ -26,7 +26,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use spam_filter::{
     SpamFilterInput,
-    analysis::{init::SpamFilterInit, score::SpamFilterAnalyzeScore},
+    analysis::{
+        init::SpamFilterInit,
+        score::{SpamFilterAnalyzeScore, tag_action},
+    },
     modules::classifier::SpamClassifier,
 };
 use std::future::Future;
@@ -42,6 +45,13 @@ pub trait ManageSpamHandler: Sync + Send {
         session: &HttpSessionData,
         access_token: &AccessToken,
     ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn spam_classify_request(
+        &self,
+        request: SpamClassifyRequest,
+        tenant_id: Option<u32>,
+        span_id: u64,
+    ) -> impl Future<Output = trc::Result<SpamClassifyResponse>> + Send;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,6 +80,8 @@ pub struct SpamClassifyRequest {
 #[serde(rename_all = "camelCase")]
 pub struct SpamClassifyResponse {
     pub score: f32,
+    #[serde(default)]
+    pub spam: bool,
     pub tags: AHashMap<String, SpamFilterDisposition<f32>>,
     pub disposition: SpamFilterDisposition<String>,
 }
@@ -201,172 +213,190 @@ impl ManageSpamHandler for Server {
                     trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
                 })?;
 
-                // Built spam filter input
-                let message = MessageParser::new()
-                    .parse(request.message.as_bytes())
-                    .filter(|m| m.root_part().headers().iter().any(|h| !h.name.is_other()))
-                    .ok_or_else(|| manage::error("Failed to parse message.", None::<u64>))?;
-
-                let remote_ip = request.remote_ip;
-                let ehlo_domain = request.ehlo_domain.to_lowercase();
-                let mail_from = request.env_from.to_lowercase();
-                let mail_from_domain = mail_from.rsplit_once('@').map(|(_, domain)| domain);
-                let local_host = &self.core.network.server_name;
-
-                let spf_ehlo_result =
-                    self.core
-                        .smtp
-                        .resolvers
-                        .dns
-                        .verify_spf(self.inner.cache.build_auth_parameters(
-                            SpfParameters::verify_ehlo(remote_ip, &ehlo_domain, local_host),
-                        ))
-                        .await;
-
-                let iprev_result = self
-                    .core
-                    .smtp
-                    .resolvers
-                    .dns
-                    .verify_iprev(self.inner.cache.build_auth_parameters(remote_ip))
-                    .await;
-
-                let spf_mail_from_result = if let Some(mail_from_domain) = mail_from_domain {
-                    self.core
-                        .smtp
-                        .resolvers
-                        .dns
-                        .check_host(self.inner.cache.build_auth_parameters(SpfParameters::new(
-                            remote_ip,
-                            mail_from_domain,
-                            &ehlo_domain,
-                            local_host,
-                            &mail_from,
-                        )))
-                        .await
-                } else {
-                    self.core
-                        .smtp
-                        .resolvers
-                        .dns
-                        .check_host(self.inner.cache.build_auth_parameters(SpfParameters::new(
-                            remote_ip,
-                            &ehlo_domain,
-                            &ehlo_domain,
-                            local_host,
-                            &format!("postmaster@{ehlo_domain}"),
-                        )))
-                        .await
-                };
-
-                let auth_message = AuthenticatedMessage::from_parsed(&message, true);
-
-                let dkim_output = self
-                    .core
-                    .smtp
-                    .resolvers
-                    .dns
-                    .verify_dkim(self.inner.cache.build_auth_parameters(&auth_message))
-                    .await;
-
-                let arc_output = self
-                    .core
-                    .smtp
-                    .resolvers
-                    .dns
-                    .verify_arc(self.inner.cache.build_auth_parameters(&auth_message))
-                    .await;
-
-                let dmarc_output = self
-                    .core
-                    .smtp
-                    .resolvers
-                    .dns
-                    .verify_dmarc(self.inner.cache.build_auth_parameters(DmarcParameters {
-                        message: &auth_message,
-                        dkim_output: &dkim_output,
-                        rfc5321_mail_from_domain: mail_from_domain.unwrap_or(ehlo_domain.as_str()),
-                        spf_output: &spf_mail_from_result,
-                        domain_suffix_fn: |domain| psl::domain_str(domain).unwrap_or(domain),
-                    }))
-                    .await;
-                let dmarc_pass = matches!(dmarc_output.spf_result(), DmarcResult::Pass)
-                    || matches!(dmarc_output.dkim_result(), DmarcResult::Pass);
-                let dmarc_result = if dmarc_pass {
-                    DmarcResult::Pass
-                } else if dmarc_output.spf_result() != &DmarcResult::None {
-                    dmarc_output.spf_result().clone()
-                } else if dmarc_output.dkim_result() != &DmarcResult::None {
-                    dmarc_output.dkim_result().clone()
-                } else {
-                    DmarcResult::None
-                };
-                let dmarc_policy = dmarc_output.policy();
-
-                let asn_geo = self.lookup_asn_country(remote_ip).await;
-
-                let input = SpamFilterInput {
-                    message: &message,
-                    span_id: session.session_id,
-                    arc_result: Some(&arc_output),
-                    spf_ehlo_result: Some(&spf_ehlo_result),
-                    spf_mail_from_result: Some(&spf_mail_from_result),
-                    dkim_result: dkim_output.as_slice(),
-                    dmarc_result: Some(&dmarc_result),
-                    dmarc_policy: Some(&dmarc_policy),
-                    iprev_result: Some(&iprev_result),
-                    remote_ip: request.remote_ip,
-                    ehlo_domain: Some(ehlo_domain.as_str()),
-                    authenticated_as: request.authenticated_as.as_deref(),
-                    asn: asn_geo.asn.as_ref().map(|a| a.id),
-                    country: asn_geo.country.as_ref().map(|c| c.as_str()),
-                    is_tls: request.is_tls,
-                    env_from: &request.env_from,
-                    env_from_flags: request.env_from_flags,
-                    env_rcpt_to: request.env_rcpt_to.iter().map(String::as_str).collect(),
-                    is_test: true,
-                    is_train: false,
-                };
-
-                // Classify
-                let mut ctx = self.spam_filter_init(input);
-                let result = self.spam_filter_classify(&mut ctx).await;
-
-                // Build response
-                let mut response = SpamClassifyResponse {
-                    score: ctx.result.score,
-                    tags: AHashMap::with_capacity(ctx.result.tags.len()),
-                    disposition: match result {
-                        SpamFilterAction::Allow(value) => SpamFilterDisposition::Allow {
-                            value: value.headers,
-                        },
-                        SpamFilterAction::Discard => SpamFilterDisposition::Discard,
-                        SpamFilterAction::Reject => SpamFilterDisposition::Reject,
-                        SpamFilterAction::Disabled => SpamFilterDisposition::Allow {
-                            value: String::new(),
-                        },
-                    },
-                };
-                for tag in ctx.result.tags {
-                    let disposition = match self.core.spam.lists.scores.get(&tag) {
-                        Some(SpamFilterAction::Allow(score)) => {
-                            SpamFilterDisposition::Allow { value: *score }
-                        }
-                        Some(SpamFilterAction::Discard) => SpamFilterDisposition::Discard,
-                        Some(SpamFilterAction::Reject) => SpamFilterDisposition::Reject,
-                        Some(SpamFilterAction::Disabled) | None => {
-                            SpamFilterDisposition::Allow { value: 0.0 }
-                        }
-                    };
-                    response.tags.insert(tag, disposition);
-                }
-
                 Ok(JsonResponse::new(json!({
-                    "data": response,
+                    "data": self.spam_classify_request(request, None, session.session_id).await?,
                 }))
                 .into_http_response())
             }
             _ => Err(trc::ResourceEvent::NotFound.into_err()),
         }
     }
+
+    async fn spam_classify_request(
+        &self,
+        request: SpamClassifyRequest,
+        tenant_id: Option<u32>,
+        span_id: u64,
+    ) -> trc::Result<SpamClassifyResponse> {
+        // Built spam filter input
+        let message = MessageParser::new()
+            .parse(request.message.as_bytes())
+            .filter(|m| m.root_part().headers().iter().any(|h| !h.name.is_other()))
+            .ok_or_else(|| manage::error("Failed to parse message.", None::<u64>))?;
+
+        let remote_ip = request.remote_ip;
+        let ehlo_domain = request.ehlo_domain.to_lowercase();
+        let mail_from = request.env_from.to_lowercase();
+        let mail_from_domain = mail_from.rsplit_once('@').map(|(_, domain)| domain);
+        let local_host = &self.core.network.server_name;
+
+        let spf_ehlo_result = self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .verify_spf(
+                self.inner
+                    .cache
+                    .build_auth_parameters(SpfParameters::verify_ehlo(
+                        remote_ip,
+                        &ehlo_domain,
+                        local_host,
+                    )),
+            )
+            .await;
+
+        let iprev_result = self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .verify_iprev(self.inner.cache.build_auth_parameters(remote_ip))
+            .await;
+
+        let spf_mail_from_result = if let Some(mail_from_domain) = mail_from_domain {
+            self.core
+                .smtp
+                .resolvers
+                .dns
+                .check_host(self.inner.cache.build_auth_parameters(SpfParameters::new(
+                    remote_ip,
+                    mail_from_domain,
+                    &ehlo_domain,
+                    local_host,
+                    &mail_from,
+                )))
+                .await
+        } else {
+            self.core
+                .smtp
+                .resolvers
+                .dns
+                .check_host(self.inner.cache.build_auth_parameters(SpfParameters::new(
+                    remote_ip,
+                    &ehlo_domain,
+                    &ehlo_domain,
+                    local_host,
+                    &format!("postmaster@{ehlo_domain}"),
+                )))
+                .await
+        };
+
+        let auth_message = AuthenticatedMessage::from_parsed(&message, true);
+
+        let dkim_output = self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .verify_dkim(self.inner.cache.build_auth_parameters(&auth_message))
+            .await;
+
+        let arc_output = self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .verify_arc(self.inner.cache.build_auth_parameters(&auth_message))
+            .await;
+
+        let dmarc_output = self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .verify_dmarc(self.inner.cache.build_auth_parameters(DmarcParameters {
+                message: &auth_message,
+                dkim_output: &dkim_output,
+                rfc5321_mail_from_domain: mail_from_domain.unwrap_or(ehlo_domain.as_str()),
+                spf_output: &spf_mail_from_result,
+                domain_suffix_fn: |domain| psl::domain_str(domain).unwrap_or(domain),
+            }))
+            .await;
+        let dmarc_pass = matches!(dmarc_output.spf_result(), DmarcResult::Pass)
+            || matches!(dmarc_output.dkim_result(), DmarcResult::Pass);
+        let dmarc_result = if dmarc_pass {
+            DmarcResult::Pass
+        } else if dmarc_output.spf_result() != &DmarcResult::None {
+            dmarc_output.spf_result().clone()
+        } else if dmarc_output.dkim_result() != &DmarcResult::None {
+            dmarc_output.dkim_result().clone()
+        } else {
+            DmarcResult::None
+        };
+        let dmarc_policy = dmarc_output.policy();
+
+        let asn_geo = self.lookup_asn_country(remote_ip).await;
+
+        let input = SpamFilterInput {
+            message: &message,
+            span_id,
+            arc_result: Some(&arc_output),
+            spf_ehlo_result: Some(&spf_ehlo_result),
+            spf_mail_from_result: Some(&spf_mail_from_result),
+            dkim_result: dkim_output.as_slice(),
+            dmarc_result: Some(&dmarc_result),
+            dmarc_policy: Some(&dmarc_policy),
+            iprev_result: Some(&iprev_result),
+            remote_ip: request.remote_ip,
+            ehlo_domain: Some(ehlo_domain.as_str()),
+            authenticated_as: request.authenticated_as.as_deref(),
+            asn: asn_geo.asn.as_ref().map(|a| a.id),
+            country: asn_geo.country.as_ref().map(|c| c.as_str()),
+            is_tls: request.is_tls,
+            env_from: &request.env_from,
+            env_from_flags: request.env_from_flags,
+            env_rcpt_to: request.env_rcpt_to.iter().map(String::as_str).collect(),
+            tenant: tenant_id.and_then(|tenant_id| self.tenant_spam_settings(tenant_id)),
+            senders: tenant_id.and_then(|tenant_id| self.tenant_sender_lists(tenant_id)),
+            is_test: true,
+            is_train: false,
+        };
+
+        // Classify
+        let mut ctx = self.spam_filter_init(input);
+        let result = self.spam_filter_classify(&mut ctx).await;
+
+        // Build response
+        let mut response = SpamClassifyResponse {
+            score: ctx.result.score,
+            spam: matches!(&result, SpamFilterAction::Allow(value) if value.is_spam),
+            tags: AHashMap::with_capacity(ctx.result.tags.len()),
+            disposition: match result {
+                SpamFilterAction::Allow(value) => SpamFilterDisposition::Allow {
+                    value: value.headers,
+                },
+                SpamFilterAction::Discard => SpamFilterDisposition::Discard,
+                SpamFilterAction::Reject => SpamFilterDisposition::Reject,
+                SpamFilterAction::Disabled => SpamFilterDisposition::Allow {
+                    value: String::new(),
+                },
+            },
+        };
+        for tag in ctx.result.tags {
+            let disposition = match tag_action(self, ctx.input.tenant.as_deref(), &tag) {
+                Some(SpamFilterAction::Allow(score)) => {
+                    SpamFilterDisposition::Allow { value: score }
+                }
+                Some(SpamFilterAction::Discard) => SpamFilterDisposition::Discard,
+                Some(SpamFilterAction::Reject) => SpamFilterDisposition::Reject,
+                Some(SpamFilterAction::Disabled) | None => {
+                    SpamFilterDisposition::Allow { value: 0.0 }
+                }
+            };
+            response.tags.insert(tag, disposition);
+        }
+
+        Ok(response)
+    }
 }
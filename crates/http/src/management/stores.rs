@@ -13,8 +13,11 @@ use common::{
     *,
 };
 use directory::{
-    Permission,
-    backend::internal::manage::{self, ManageDirectory},
+    Permission, Type,
+    backend::internal::{
+        avatar::avatar_key,
+        manage::{self, ManageDirectory},
+    },
 };
 use email::{
     cache::MessageCacheFetch,
@@ -98,13 +101,14 @@ impl ManageStore for Server {
                         trc::EventType::Resource(trc::ResourceEvent::BadParameters)
                             .from_base64_error(err)
                     })?;
-                let contents = self
-                    .core
-                    .storage
-                    .blob
-                    .get_blob(&blob_hash, 0..usize::MAX)
-                    .await?
-                    .ok_or_else(|| trc::ManageEvent::NotFound.into_err())?;
+                let contents = if let Ok(blob_hash) = BlobHash::try_from_hash_slice(&blob_hash) {
+                    self.get_blob(&blob_hash, 0..usize::MAX).await?
+                } else {
+                    self.blob_store()
+                        .get_blob(&blob_hash, 0..usize::MAX)
+                        .await?
+                }
+                .ok_or_else(|| trc::ManageEvent::NotFound.into_err())?;
                 let params = UrlParams::new(req.uri().query());
                 let offset = params.parse("offset").unwrap_or(0);
                 let limit = params.parse("limit").unwrap_or(usize::MAX);
@@ -174,12 +178,15 @@ impl ManageStore for Server {
                     }
                     Some("rate-http-anonymous") => vec![KV_RATE_LIMIT_HTTP_ANONYMOUS].into(),
                     Some("rate-imap") => vec![KV_RATE_LIMIT_IMAP].into(),
+                    Some("rate-sender") => vec![KV_RATE_LIMIT_SENDER].into(),
                     Some("greylist") => vec![KV_GREYLIST].into(),
+                    Some("greylist-tenant") => vec![KV_GREYLIST_TENANT].into(),
                     Some("lock-purge-account") => vec![KV_LOCK_PURGE_ACCOUNT].into(),
                     Some("lock-queue-message") => vec![KV_LOCK_QUEUE_MESSAGE].into(),
                     Some("lock-queue-report") => vec![KV_LOCK_QUEUE_REPORT].into(),
                     Some("lock-email-task") => vec![KV_LOCK_TASK].into(),
                     Some("lock-housekeeper") => vec![KV_LOCK_HOUSEKEEPER].into(),
+                    Some("lock-provision") => vec![KV_LOCK_PROVISION].into(),
                     _ => None,
                 };
 
@@ -211,6 +218,13 @@ impl ManageStore for Server {
                 }))
                 .await
             }
+            (Some("purge"), Some("principal-counts"), None, &Method::GET) => {
+                // Validate the access token
+                access_token.assert_has_permission(Permission::PurgeDataStore)?;
+
+                self.housekeeper_request(HousekeeperEvent::Purge(PurgeType::PrincipalCounts))
+                    .await
+            }
             (Some("reindex"), Some(index), id, &Method::GET) => {
                 // Validate the access token
                 access_token.assert_has_permission(Permission::FtsReindex)?;
@@ -327,8 +341,52 @@ impl ManageStore for Server {
     }
 }
 
+/// Recounts the storage used by an account, or the aggregate of a tenant or
+/// domain from the counters of its accounts. Correcting the counters of an
+/// account also corrects the aggregates of its tenant and domain by the same
+/// amount.
 pub async fn recalculate_quota(server: &Server, account_id: u32) -> trc::Result<()> {
+    let principal = server
+        .store()
+        .get_principal(account_id)
+        .await
+        .caused_by(trc::location!())?;
+    if principal.as_ref().is_some_and(|p| p.typ() == Type::Domain) {
+        let usage = server.recount_domain_usage(account_id).await?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .add(
+                DirectoryClass::UsedQuota(account_id),
+                usage.bytes - server.get_used_quota(account_id).await?,
+            )
+            .add(
+                DirectoryClass::MessageCount(account_id),
+                usage.messages - server.get_message_count(account_id).await?,
+            );
+        return server
+            .store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ());
+    }
+    if principal.as_ref().is_some_and(|p| p.typ() == Type::Tenant) {
+        let usage = server.tenant_usage(account_id).await?;
+        let mut batch = BatchBuilder::new();
+        batch.add(
+            DirectoryClass::UsedQuota(account_id),
+            usage.total() - server.get_used_quota(account_id).await?,
+        );
+        return server
+            .store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ());
+    }
+
     let mut quota = 0;
+    let mut messages = 0;
 
     for collection in [
         Collection::Email,
@@ -344,6 +402,7 @@ pub async fn recalculate_quota(server: &Server, account_id: u32) -> trc::Result<
                 match collection {
                     Collection::Email => {
                         quota += archive.unarchive::<MessageData>()?.size.to_native() as i64;
+                        messages += 1;
                     }
                     Collection::Calendar => {
                         quota += archive.unarchive::<Calendar>()?.size() as i64;
@@ -371,10 +430,25 @@ pub async fn recalculate_quota(server: &Server, account_id: u32) -> trc::Result<
             .caused_by(trc::location!())?;
     }
 
+    // Counters are corrected by the same delta in a single batch, so that
+    // changes committed while recounting are kept and the aggregates of the
+    // tenant and domain stay consistent with the account
+    let quota_delta = quota - server.get_used_quota(account_id).await?;
+    let messages_delta = messages - server.get_message_count(account_id).await?;
     let mut batch = BatchBuilder::new();
     batch
-        .clear(DirectoryClass::UsedQuota(account_id))
-        .add(DirectoryClass::UsedQuota(account_id), quota);
+        .add(DirectoryClass::UsedQuota(account_id), quota_delta)
+        .add(DirectoryClass::MessageCount(account_id), messages_delta);
+    if let Some(tenant_id) = principal.as_ref().and_then(|p| p.tenant()) {
+        batch.add(DirectoryClass::UsedQuota(tenant_id), quota_delta);
+    }
+    if let Some(email) = principal.as_ref().and_then(|p| p.primary_email())
+        && let Some(domain_id) = server.store().get_domain_id(email).await?
+    {
+        batch
+            .add(DirectoryClass::UsedQuota(domain_id), quota_delta)
+            .add(DirectoryClass::MessageCount(domain_id), messages_delta);
+    }
     server
         .store()
         .write(batch.build_all())
@@ -476,6 +550,36 @@ pub async fn destroy_account_data(
         .await
         .caused_by(trc::location!())?;
 
+    // Drop per-tenant metrics (no-op unless the principal is a tenant)
+    server.inner.data.tenant_metrics.remove(account_id);
+
+    // Remove the avatar of the principal
+    server
+        .blob_store()
+        .delete_blob(&avatar_key(account_id))
+        .await
+        .caused_by(trc::location!())?;
+
+    // Remove the forwarding rule of the account
+    if server.forwarding_rule(account_id).is_some() {
+        server
+            .update_forwarding_rule(account_id, None)
+            .await
+            .caused_by(trc::location!())?;
+    }
+
+    // Remove the account from shared mailboxes
+    server
+        .remove_shared_mailbox_account(account_id)
+        .await
+        .caused_by(trc::location!())?;
+
+    // Remove the account from bookable resources
+    server
+        .remove_bookable_resource_account(account_id)
+        .await
+        .caused_by(trc::location!())?;
+
     if has_data {
         // Remove search index
         for index in [
@@ -0,0 +1,167 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::FutureTimestamp;
+use common::{Server, auth::AccessToken};
+use directory::{
+    Permission,
+    backend::internal::{
+        PrincipalField, PrincipalUpdate, PrincipalValue,
+        manage::{self, ManageDirectory, UpdatePrincipal},
+        trial::{TrialStatus, trial_days_remaining},
+    },
+};
+use http_proto::*;
+use hyper::Method;
+use mail_parser::DateTime;
+use serde_json::{Value, json};
+use std::str::FromStr;
+use store::write::now;
+
+const MAX_TRIAL_DAYS: u32 = 365;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrialExtendRequest {
+    #[serde(default)]
+    days: Option<u32>,
+    #[serde(default)]
+    trial_ends_at: Option<String>,
+}
+
+/// Resolves the end of a trial requested either as an RFC 3339 timestamp or
+/// as a number of days counted from `from`.
+pub(super) fn trial_end(
+    ends_at: Option<&str>,
+    days: Option<u32>,
+    from: u64,
+) -> trc::Result<Option<u64>> {
+    match (ends_at, days) {
+        (Some(_), Some(_)) => Err(manage::error(
+            "Invalid trial",
+            "Only one of trialEndsAt and trialDays can be set".into(),
+        )),
+        (Some(ends_at), None) => FutureTimestamp::from_str(ends_at)
+            .map(|ends_at| Some(ends_at.into_inner()))
+            .map_err(|_| {
+                manage::error(
+                    "Invalid trial end",
+                    format!("{ends_at:?} is not a future RFC 3339 timestamp").into(),
+                )
+            }),
+        (None, Some(days)) if (1..=MAX_TRIAL_DAYS).contains(&days) => {
+            Ok(Some(from + days as u64 * 86400))
+        }
+        (None, Some(_)) => Err(manage::error(
+            "Invalid trial length",
+            format!("Trials last between 1 and {MAX_TRIAL_DAYS} days").into(),
+        )),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Trial period of a tenant as shown in the organization overview.
+pub(super) fn trial_json(ends_at: Option<u64>, now: u64) -> Value {
+    match ends_at {
+        Some(ends_at) => json!({
+            "endsAt": DateTime::from_timestamp(ends_at as i64).to_rfc3339(),
+            "status": TrialStatus::at(Some(ends_at), now).as_str(),
+            "daysRemaining": trial_days_remaining(ends_at, now),
+        }),
+        None => Value::Null,
+    }
+}
+
+/// Extends or converts the trial of a tenant. Both lift the suspension of a
+/// trial that already lapsed, and converting clears the trial altogether.
+pub(super) async fn handle_trial(
+    server: &Server,
+    req: &HttpRequest,
+    path: &[&str],
+    body: Option<Vec<u8>>,
+    tenant_id: u32,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    if req.method() != Method::POST {
+        return Err(trc::ResourceEvent::NotFound.into_err());
+    }
+    access_token.assert_has_permission(Permission::TenantUpdate)?;
+
+    let tenant = server
+        .store()
+        .get_principal(tenant_id)
+        .await?
+        .ok_or_else(|| manage::not_found(tenant_id))?;
+    let Some(current) = tenant.trial_ends_at() else {
+        return Err(manage::error(
+            "No trial",
+            "The organization is not on a trial".into(),
+        ));
+    };
+    let now = now();
+
+    let ends_at = match path.get(3).copied() {
+        Some("extend") => {
+            let request =
+                serde_json::from_slice::<TrialExtendRequest>(body.as_deref().unwrap_or_default())
+                    .map_err(|err| {
+                    trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+                })?;
+
+            // Days are added to the trial, or to the current time if it lapsed
+            trial_end(
+                request.trial_ends_at.as_deref(),
+                request.days,
+                current.max(now),
+            )?
+            .ok_or_else(|| manage::err_missing("days"))?
+        }
+        Some("convert") => 0,
+        _ => return Err(trc::ResourceEvent::NotFound.into_err()),
+    };
+
+    let changed_principals = server
+        .store()
+        .update_principal(UpdatePrincipal::by_id(tenant_id).with_updates(vec![
+            PrincipalUpdate::set(
+                PrincipalField::TrialEndsAt,
+                PrincipalValue::Integer(ends_at),
+            ),
+        ]))
+        .await?;
+    server.invalidate_principal_caches(changed_principals).await;
+
+    if server
+        .tenant_maintenance(tenant_id)
+        .is_some_and(|maintenance| maintenance.trial_expired)
+    {
+        server.update_tenant_maintenance(tenant_id, None).await?;
+    }
+
+    if ends_at > 0 {
+        trc::event!(
+            Directory(trc::DirectoryEvent::TrialExtended),
+            AccountName = access_token.name.clone(),
+            AccountId = access_token.primary_id(),
+            TenantId = tenant_id,
+            Id = tenant.name().to_string(),
+            Expires = trc::Value::Timestamp(ends_at),
+        );
+    } else {
+        trc::event!(
+            Directory(trc::DirectoryEvent::TrialConverted),
+            AccountName = access_token.name.clone(),
+            AccountId = access_token.primary_id(),
+            TenantId = tenant_id,
+            Id = tenant.name().to_string(),
+        );
+    }
+
+    Ok(JsonResponse::new(json!({
+        "data": trial_json(Some(ends_at).filter(|at| *at > 0), now),
+    }))
+    .into_http_response())
+}
@@ -13,13 +13,19 @@ use std::{
 use common::{
     Server,
     auth::{AccessToken, oauth::GrantType},
-    config::smtp::{
-        queue::MxConfig,
-        resolver::{Policy, Tlsa},
+    config::{
+        overrides::TenantSetting,
+        smtp::{
+            queue::MxConfig,
+            resolver::{Policy, Tlsa},
+        },
     },
     psl,
 };
-use directory::backend::internal::manage;
+use directory::{
+    Type,
+    backend::internal::manage::{self, ManageDirectory},
+};
 use http_body_util::{StreamBody, combinators::BoxBody};
 use hyper::{
     Method, StatusCode,
@@ -102,6 +108,44 @@ impl TroubleshootApi for Server {
                         yield Ok(DeliveryStage::Completed.to_frame());
                     }))))
             }
+            ("settings", None, &Method::GET) => {
+                // Resolve the tenant the same way request handling does, from
+                // the principal or from the tenant of the recipient's domain
+                let tenant_id = if let Some(domain) = params.get("domain") {
+                    self.domain_tenant(domain).await?
+                } else if let Some(name) = params.get("account").or_else(|| params.get("tenant")) {
+                    let info = self
+                        .store()
+                        .get_principal_info(name)
+                        .await?
+                        .ok_or_else(|| manage::not_found(name.to_string()))?;
+                    if info.typ == Type::Tenant {
+                        Some(info.id)
+                    } else {
+                        info.tenant
+                    }
+                } else {
+                    None
+                };
+
+                let mut settings = Vec::with_capacity(TenantSetting::ALL.len());
+                for setting in TenantSetting::ALL {
+                    let (value, source) = self.effective_setting(tenant_id, setting).await;
+                    settings.push(json!({
+                        "key": setting.key(),
+                        "value": setting.config_value(value),
+                        "source": source,
+                    }));
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": {
+                        "tenantId": tenant_id,
+                        "settings": settings,
+                    },
+                }))
+                .into_http_response())
+            }
             ("dmarc", None, &Method::POST) => {
                 let request = serde_json::from_slice::<DmarcTroubleshootRequest>(
                     body.as_deref().unwrap_or_default(),
@@ -0,0 +1,482 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::principal::{PrincipalManager, update_permission};
+use common::{Server, auth::AccessToken};
+use directory::{
+    PrincipalData, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue, SpecialSecrets,
+        manage::{self, ManageDirectory, not_found},
+    },
+    core::secret::verify_secret_hash,
+};
+use http_proto::*;
+use serde_json::json;
+use std::future::Future;
+use trc::AddContext;
+use utils::url_params::UrlParams;
+
+pub trait PrincipalUpsertApi: Sync + Send {
+    fn handle_upsert_principal(
+        &self,
+        req: &HttpRequest,
+        name: &str,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UpsertResult {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl PrincipalUpsertApi for Server {
+    async fn handle_upsert_principal(
+        &self,
+        req: &HttpRequest,
+        name: &str,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> trc::Result<HttpResponse> {
+        let prune = UrlParams::new(req.uri().query())
+            .get("prune")
+            .is_some_and(|v| v == "1" || v == "true");
+        let mut principal = serde_json::from_slice::<PrincipalSet>(
+            body.as_deref().unwrap_or_default(),
+        )
+        .map_err(|err| {
+            trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+        })?;
+
+        // The principal is identified by the name in the URL
+        if principal
+            .get_str(PrincipalField::Name)
+            .is_some_and(|body_name| !body_name.eq_ignore_ascii_case(name))
+        {
+            return Err(manage::error(
+                "Invalid principal name",
+                "The principal name does not match the one in the URL".into(),
+            ));
+        }
+        principal.set(PrincipalField::Name, name);
+
+        let tenant_id = access_token.tenant.map(|t| t.id);
+        let Some(info) = self
+            .store()
+            .get_principal_info(&name.to_lowercase())
+            .await
+            .caused_by(trc::location!())?
+            .filter(|p| p.has_tenant_access(tenant_id))
+        else {
+            // Principal does not exist, create it
+            let mut changes = principal
+                .fields
+                .iter()
+                .filter(|(field, _)| **field != PrincipalField::Name)
+                .map(|(field, value)| PrincipalUpdate::set(*field, value.clone()))
+                .collect::<Vec<_>>();
+            changes.sort_unstable_by_key(|change| change.field);
+            let account_id = self
+                .create_managed_principal(principal, access_token, false)
+                .await?;
+
+            return Ok(upsert_response(account_id, UpsertResult::Created, changes));
+        };
+
+        if info.typ != principal.typ() {
+            return Err(manage::error(
+                "Invalid principal type",
+                format!(
+                    "Principal {name:?} is of type {:?} and cannot be changed",
+                    info.typ.as_str()
+                )
+                .into(),
+            ));
+        }
+
+        // SPDX-SnippetBegin
+        // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+        // SPDX-License-Identifier: LicenseRef-SEL
+
+        #[cfg(feature = "enterprise")]
+        {
+            if matches!(info.typ, Type::Tenant) && !self.core.is_enterprise_edition() {
+                return Err(manage::enterprise());
+            }
+        }
+
+        // SPDX-SnippetEnd
+
+        // Validate the access token
+        access_token.assert_has_permission(update_permission(info.typ))?;
+
+        // Compare the requested fields with the stored ones
+        let current = self
+            .store()
+            .get_principal(info.id)
+            .await
+            .caused_by(trc::location!())?
+            .ok_or_else(|| not_found(name.to_string()))?;
+        let stored_secrets = current
+            .data
+            .iter()
+            .filter_map(|data| match data {
+                PrincipalData::Password(secret)
+                | PrincipalData::AppPassword(secret)
+                | PrincipalData::OtpAuth(secret) => Some(secret.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let current = self
+            .store()
+            .map_principal(current, &[])
+            .await
+            .caused_by(trc::location!())?;
+        let mut changes = principal_diff(&current, &principal, prune);
+        changes.extend(
+            secret_changes(
+                &stored_secrets,
+                principal
+                    .take_str_array(PrincipalField::Secrets)
+                    .unwrap_or_default(),
+            )
+            .await,
+        );
+
+        if changes.is_empty() {
+            return Ok(upsert_response(info.id, UpsertResult::Unchanged, changes));
+        }
+
+        self.update_managed_principal(info.id, name, info.typ, changes.clone(), access_token)
+            .await?;
+
+        Ok(upsert_response(info.id, UpsertResult::Updated, changes))
+    }
+}
+
+/// Computes the changes needed to turn `current` into `requested`. Fields
+/// absent from the request are left untouched unless `prune` is set.
+/// Secrets are compared separately, see [`secret_changes`].
+pub fn principal_diff(
+    current: &PrincipalSet,
+    requested: &PrincipalSet,
+    prune: bool,
+) -> Vec<PrincipalUpdate> {
+    let mut fields = requested
+        .fields
+        .keys()
+        .copied()
+        .filter(|field| !is_read_only(*field))
+        .collect::<Vec<_>>();
+    if prune {
+        fields.extend(current.fields.keys().copied().filter(|field| {
+            !is_read_only(*field)
+                && *field != PrincipalField::Tenant
+                && !requested.fields.contains_key(field)
+        }));
+    }
+    fields.sort_unstable();
+
+    let mut changes = Vec::new();
+    for field in fields {
+        let current_value = current.fields.get(&field);
+
+        // Member counts of domains and tenants cannot be changed
+        if matches!(current_value, Some(PrincipalValue::Integer(_)))
+            && field == PrincipalField::Members
+        {
+            continue;
+        }
+
+        let Some(value) = requested.fields.get(&field) else {
+            if let Some(value) = current_value.filter(|value| !is_empty_value(value)) {
+                changes.push(PrincipalUpdate::set(field, empty_value(value)));
+            }
+            continue;
+        };
+
+        if is_list(field) {
+            let current_items = current_value
+                .cloned()
+                .map(PrincipalValue::into_str_array)
+                .unwrap_or_default();
+            let items = value.clone().into_str_array();
+            let ignore_case = !matches!(
+                field,
+                PrincipalField::Urls | PrincipalField::ExternalMembers
+            );
+            let contains = |list: &[String], item: &str| {
+                list.iter().any(|v| {
+                    if ignore_case {
+                        v.eq_ignore_ascii_case(item)
+                    } else {
+                        v == item
+                    }
+                })
+            };
+
+            if field == PrincipalField::Emails {
+                // The first address is the primary one, so order matters
+                if current_items.len() != items.len()
+                    || current_items
+                        .iter()
+                        .zip(&items)
+                        .any(|(a, b)| !a.eq_ignore_ascii_case(b))
+                {
+                    changes.push(PrincipalUpdate::set(
+                        field,
+                        PrincipalValue::StringList(items),
+                    ));
+                }
+                continue;
+            }
+
+            for item in &current_items {
+                if !contains(&items, item) {
+                    changes.push(PrincipalUpdate::remove_item(
+                        field,
+                        PrincipalValue::String(item.clone()),
+                    ));
+                }
+            }
+            for item in &items {
+                if !contains(&current_items, item) {
+                    changes.push(PrincipalUpdate::add_item(
+                        field,
+                        PrincipalValue::String(item.clone()),
+                    ));
+                }
+            }
+        } else {
+            let is_equal = match (current_value, value) {
+                (None, value) => is_empty_value(value),
+                (Some(PrincipalValue::String(a)), PrincipalValue::String(b))
+                    if field == PrincipalField::Tenant =>
+                {
+                    a.eq_ignore_ascii_case(b)
+                }
+                (Some(current_value), value) => current_value == value,
+            };
+
+            if !is_equal {
+                changes.push(PrincipalUpdate::set(field, value.clone()));
+            }
+        }
+    }
+
+    changes
+}
+
+/// Secrets are never removed by an upsert. A plaintext password is only
+/// replaced when it does not verify against the stored hash.
+async fn secret_changes(stored: &[String], requested: Vec<String>) -> Vec<PrincipalUpdate> {
+    let mut changes = Vec::new();
+
+    'outer: for secret in requested {
+        if stored.contains(&secret) {
+            continue;
+        }
+
+        if !secret.is_otp_secret() && !secret.is_app_secret() {
+            for hash in stored {
+                if !hash.is_otp_secret()
+                    && !hash.is_app_secret()
+                    && verify_secret_hash(hash, &secret).await.unwrap_or(false)
+                {
+                    continue 'outer;
+                }
+            }
+        }
+
+        changes.push(PrincipalUpdate::add_item(
+            PrincipalField::Secrets,
+            PrincipalValue::String(secret),
+        ));
+    }
+
+    changes
+}
+
+fn upsert_response(
+    account_id: u32,
+    result: UpsertResult,
+    changes: Vec<PrincipalUpdate>,
+) -> HttpResponse {
+    // Never echo secrets back
+    let changes = changes
+        .into_iter()
+        .map(|mut change| {
+            if change.field == PrincipalField::Secrets {
+                change.value = match change.value {
+                    PrincipalValue::StringList(list) => {
+                        PrincipalValue::StringList(vec!["********".to_string(); list.len()])
+                    }
+                    _ => PrincipalValue::String("********".to_string()),
+                };
+            }
+            change
+        })
+        .collect::<Vec<_>>();
+
+    JsonResponse::new(json!({
+        "data": {
+            "id": account_id,
+            "result": result,
+            "changes": changes,
+        },
+    }))
+    .into_http_response()
+}
+
+fn is_read_only(field: PrincipalField) -> bool {
+    matches!(
+        field,
+        PrincipalField::Name
+            | PrincipalField::Type
+            | PrincipalField::Secrets
+            | PrincipalField::UsedQuota
+    )
+}
+
+fn is_list(field: PrincipalField) -> bool {
+    matches!(
+        field,
+        PrincipalField::Emails
+            | PrincipalField::MemberOf
+            | PrincipalField::Members
+            | PrincipalField::Roles
+            | PrincipalField::Lists
+            | PrincipalField::EnabledPermissions
+            | PrincipalField::DisabledPermissions
+            | PrincipalField::Urls
+            | PrincipalField::ExternalMembers
+    )
+}
+
+fn is_empty_value(value: &PrincipalValue) -> bool {
+    match value {
+        PrincipalValue::String(v) => v.is_empty(),
+        PrincipalValue::StringList(v) => v.is_empty(),
+        PrincipalValue::Integer(v) => *v == 0,
+        PrincipalValue::IntegerList(v) => v.iter().all(|v| *v == 0),
+    }
+}
+
+fn empty_value(value: &PrincipalValue) -> PrincipalValue {
+    match value {
+        PrincipalValue::String(_) => PrincipalValue::String(String::new()),
+        PrincipalValue::StringList(_) => PrincipalValue::StringList(vec![]),
+        PrincipalValue::Integer(_) => PrincipalValue::Integer(0),
+        PrincipalValue::IntegerList(_) => PrincipalValue::IntegerList(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use directory::{
+        Type,
+        backend::internal::{PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue},
+    };
+
+    use super::principal_diff;
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn upsert_principal_diff() {
+        let current = PrincipalSet::new(1, Type::Individual)
+            .with_field(PrincipalField::Name, "jdoe")
+            .with_field(PrincipalField::Description, "John Doe")
+            .with_field(PrincipalField::Quota, 1024u64)
+            .with_field(PrincipalField::UsedQuota, 512u64)
+            .with_field(
+                PrincipalField::Emails,
+                list(&["jdoe@acme.org", "john@acme.org"]),
+            )
+            .with_field(PrincipalField::MemberOf, list(&["sales", "staff"]))
+            .with_field(PrincipalField::Roles, list(&["user"]))
+            .with_field(PrincipalField::Locale, "en");
+
+        // Identical request
+        let requested = current
+            .clone()
+            .with_field(PrincipalField::Secrets, "secret");
+        assert_eq!(principal_diff(&current, &requested, true), vec![]);
+
+        // Fields absent from the request are left untouched
+        let requested = PrincipalSet::new(0, Type::Individual)
+            .with_field(PrincipalField::Name, "JDoe")
+            .with_field(PrincipalField::Description, "Johnny Doe")
+            .with_field(
+                PrincipalField::Emails,
+                list(&["JDOE@acme.org", "john@acme.org"]),
+            )
+            .with_field(PrincipalField::MemberOf, list(&["staff", "support"]))
+            .with_field(PrincipalField::Picture, "");
+        assert_eq!(
+            principal_diff(&current, &requested, false),
+            vec![
+                PrincipalUpdate::set(
+                    PrincipalField::Description,
+                    PrincipalValue::String("Johnny Doe".to_string())
+                ),
+                PrincipalUpdate::remove_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("sales".to_string())
+                ),
+                PrincipalUpdate::add_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("support".to_string())
+                ),
+            ]
+        );
+
+        // Pruning clears them, except for read-only fields
+        assert_eq!(
+            principal_diff(&current, &requested, true),
+            vec![
+                PrincipalUpdate::set(PrincipalField::Quota, PrincipalValue::Integer(0)),
+                PrincipalUpdate::set(
+                    PrincipalField::Description,
+                    PrincipalValue::String("Johnny Doe".to_string())
+                ),
+                PrincipalUpdate::remove_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("sales".to_string())
+                ),
+                PrincipalUpdate::add_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("support".to_string())
+                ),
+                PrincipalUpdate::set(PrincipalField::Roles, PrincipalValue::StringList(vec![])),
+                PrincipalUpdate::set(
+                    PrincipalField::Locale,
+                    PrincipalValue::String(String::new())
+                ),
+            ]
+        );
+
+        // Changing the primary address replaces the whole list
+        let requested = PrincipalSet::new(0, Type::Individual).with_field(
+            PrincipalField::Emails,
+            list(&["john@acme.org", "jdoe@acme.org"]),
+        );
+        assert_eq!(
+            principal_diff(&current, &requested, false),
+            vec![PrincipalUpdate::set(
+                PrincipalField::Emails,
+                PrincipalValue::StringList(list(&["john@acme.org", "jdoe@acme.org"]))
+            )]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::auth::webauthn::{credential_descriptor, relying_party};
+use common::{
+    Server,
+    auth::webauthn::{
+        COSE_ALGORITHMS, WEBAUTHN_TIMEOUT, decode_base64url, encode_base64url, verify_registration,
+    },
+};
+use directory::backend::internal::{
+    manage::{self, ManageDirectory, not_found},
+    webauthn::{WebAuthnCredential, WebAuthnCredentials},
+};
+use http_proto::*;
+use hyper::Method;
+use serde_json::json;
+use std::future::Future;
+use store::write::now;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistrationStartRequest {
+    nickname: String,
+    #[serde(default)]
+    resident_key: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistrationFinishRequest {
+    challenge_id: String,
+    credential: RegistrationCredential,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RegistrationCredential {
+    id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RenameRequest {
+    nickname: String,
+}
+
+pub trait WebAuthnManager: Sync + Send {
+    fn handle_webauthn_credentials(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        account_id: u32,
+        is_owner: bool,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl WebAuthnManager for Server {
+    // Credentials are registered by their owner from the browser holding the
+    // authenticator, administrators may only list and revoke them.
+    async fn handle_webauthn_credentials(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+        body: Option<Vec<u8>>,
+        account_id: u32,
+        is_owner: bool,
+    ) -> trc::Result<HttpResponse> {
+        match (path.get(4).copied(), path.get(5).copied(), req.method()) {
+            (None, None, &Method::GET) => {
+                let credentials = self
+                    .store()
+                    .list_webauthn_credentials(account_id)
+                    .await?
+                    .into_iter()
+                    .map(|entry| {
+                        json!({
+                            "id": encode_base64url(&entry.credential.id),
+                            "nickname": entry.credential.nickname,
+                            "rpId": entry.credential.rp_id,
+                            "residentKey": entry.credential.resident,
+                            "createdAt": entry.credential.created_at,
+                            "lastUsed": entry.last_used,
+                            "signCount": entry.sign_count,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                Ok(JsonResponse::new(json!({
+                    "data": credentials,
+                }))
+                .into_http_response())
+            }
+            (Some("register"), Some(step), &Method::POST) => {
+                if !is_owner {
+                    return Err(trc::SecurityEvent::Unauthorized
+                        .into_err()
+                        .details("Credentials can only be registered by their owner"));
+                }
+                let body = body.as_deref().unwrap_or_default();
+
+                match step {
+                    "start" => {
+                        let request = serde_json::from_slice::<RegistrationStartRequest>(body)
+                            .map_err(|err| {
+                                trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                    .from_json_error(err)
+                            })?;
+                        let rp = relying_party(self, req).await?;
+                        let principal = self
+                            .store()
+                            .get_principal(account_id)
+                            .await?
+                            .ok_or_else(|| not_found(account_id))?;
+                        let exclude = self
+                            .store()
+                            .list_webauthn_credentials(account_id)
+                            .await?
+                            .into_iter()
+                            .filter(|entry| entry.credential.rp_id == rp.id)
+                            .map(|entry| credential_descriptor(&entry.credential.id))
+                            .collect::<Vec<_>>();
+                        let (challenge_id, challenge) = self
+                            .create_webauthn_challenge(
+                                &rp,
+                                account_id.into(),
+                                request.nickname.into(),
+                                request.resident_key,
+                            )
+                            .await?;
+                        let algorithms = COSE_ALGORITHMS
+                            .iter()
+                            .map(|alg| json!({"type": "public-key", "alg": alg}))
+                            .collect::<Vec<_>>();
+                        let resident_key = if challenge.resident {
+                            "required"
+                        } else {
+                            "discouraged"
+                        };
+
+                        Ok(JsonResponse::new(json!({
+                            "data": {
+                                "challengeId": challenge_id,
+                                "expiresIn": WEBAUTHN_TIMEOUT,
+                                "publicKey": {
+                                    "rp": {
+                                        "id": rp.id,
+                                        "name": rp.id,
+                                    },
+                                    "user": {
+                                        "id": encode_base64url(&account_id.to_be_bytes()),
+                                        "name": principal.name(),
+                                        "displayName": principal
+                                            .description()
+                                            .unwrap_or(principal.name()),
+                                    },
+                                    "challenge": encode_base64url(&challenge.challenge),
+                                    "pubKeyCredParams": algorithms,
+                                    "timeout": WEBAUTHN_TIMEOUT * 1000,
+                                    "excludeCredentials": exclude,
+                                    "authenticatorSelection": {
+                                        "residentKey": resident_key,
+                                        "requireResidentKey": challenge.resident,
+                                        "userVerification": "preferred",
+                                    },
+                                    "attestation": "none",
+                                },
+                            },
+                        }))
+                        .into_http_response())
+                    }
+                    "finish" => {
+                        let request = serde_json::from_slice::<RegistrationFinishRequest>(body)
+                            .map_err(|err| {
+                                trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                    .from_json_error(err)
+                            })?;
+                        let challenge = self
+                            .take_webauthn_challenge(&request.challenge_id)
+                            .await?
+                            .filter(|challenge| challenge.account_id == Some(account_id))
+                            .ok_or_else(|| {
+                                manage::error(
+                                    "Invalid challenge",
+                                    "The registration expired or was already completed".into(),
+                                )
+                            })?;
+                        let response = &request.credential.response;
+                        let credential = match (
+                            decode_base64url(&response.client_data_json),
+                            decode_base64url(&response.attestation_object),
+                        ) {
+                            (Some(client_data_json), Some(attestation_object)) => {
+                                verify_registration(
+                                    &client_data_json,
+                                    &attestation_object,
+                                    &challenge.challenge,
+                                    &challenge.rp_id,
+                                )
+                            }
+                            _ => Err("Invalid base64 encoding".to_string()),
+                        }
+                        .and_then(|credential| {
+                            if decode_base64url(&request.credential.id).as_ref()
+                                == Some(&credential.id)
+                            {
+                                Ok(credential)
+                            } else {
+                                Err("Credential id mismatch".to_string())
+                            }
+                        })
+                        .map_err(|err| manage::error("Invalid credential", err.into()))?;
+
+                        let nickname = challenge.nickname.unwrap_or_default();
+                        self.store()
+                            .add_webauthn_credential(
+                                account_id,
+                                WebAuthnCredential {
+                                    id: credential.id.clone(),
+                                    public_key: credential.public_key,
+                                    rp_id: challenge.rp_id,
+                                    nickname: nickname.clone(),
+                                    created_at: now(),
+                                    resident: challenge.resident,
+                                },
+                                credential.sign_count,
+                            )
+                            .await?;
+
+                        trc::event!(
+                            Directory(trc::DirectoryEvent::MfaFactorAdded),
+                            AccountId = account_id,
+                            Type = "webauthn",
+                            Details = nickname.clone(),
+                        );
+                        self.notify_security_event(
+                            trc::EventType::Directory(trc::DirectoryEvent::MfaFactorAdded),
+                            account_id,
+                            format!("The passkey {nickname:?} was added.").into(),
+                        )
+                        .await;
+
+                        Ok(JsonResponse::new(json!({
+                            "data": {
+                                "id": encode_base64url(&credential.id),
+                            },
+                        }))
+                        .into_http_response())
+                    }
+                    _ => Err(trc::ResourceEvent::NotFound.into_err()),
+                }
+            }
+            (Some(id), None, &Method::PATCH) => {
+                let id = decode_base64url(id).ok_or_else(|| not_found(id.to_string()))?;
+                let request =
+                    serde_json::from_slice::<RenameRequest>(body.as_deref().unwrap_or_default())
+                        .map_err(|err| {
+                            trc::EventType::Resource(trc::ResourceEvent::BadParameters)
+                                .from_json_error(err)
+                        })?;
+
+                if !self
+                    .store()
+                    .rename_webauthn_credential(account_id, &id, &request.nickname)
+                    .await?
+                {
+                    return Err(not_found("webauthn"));
+                }
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            (Some(id), None, &Method::DELETE) => {
+                let id = decode_base64url(id).ok_or_else(|| not_found(id.to_string()))?;
+
+                if !self
+                    .store()
+                    .remove_webauthn_credential(account_id, &id)
+                    .await?
+                {
+                    return Err(not_found("webauthn"));
+                }
+
+                trc::event!(
+                    Directory(trc::DirectoryEvent::MfaFactorRemoved),
+                    AccountId = account_id,
+                    Type = "webauthn",
+                    Id = path.get(4).copied().unwrap_or_default().to_string(),
+                );
+                self.notify_security_event(
+                    trc::EventType::Directory(trc::DirectoryEvent::MfaFactorRemoved),
+                    account_id,
+                    "A passkey was removed.".to_string().into(),
+                )
+                .await;
+
+                Ok(JsonResponse::new(json!({
+                    "data": (),
+                }))
+                .into_http_response())
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
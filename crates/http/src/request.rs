@@ -12,16 +12,24 @@ use crate::{
             FormData, auth::OAuthApiHandler, openid::OpenIdHandler,
             registration::ClientRegistrationHandler, token::TokenHandler,
         },
+        webauthn::WebAuthnLogin,
     },
     autoconfig::Autoconfig,
+    cors::{add_cors_headers, cors_preflight},
     form::FormHandler,
     management::{
-        ManagementApi, ToManageHttpResponse, UnauthorizedResponse, troubleshoot::TroubleshootApi,
+        ManagementApi, ToManageHttpResponse, UnauthorizedResponse,
+        activation::OrganizationActivationManager, application::OrganizationApplicationManager,
+        bimi::bimi_resource, branding::BrandingManager, export::AccountExportManager,
+        invitation::OrganizationInvitationManager, secondary_email::SecondaryEmailManager,
+        troubleshoot::TroubleshootApi,
     },
+    scim::ScimApi,
 };
 use common::{
     Inner, KV_ACME, Server,
     auth::{AccessToken, oauth::GrantType},
+    config::overrides::TenantProtocol,
     core::BuildServer,
     ipc::PushEvent,
     listener::{SessionData, SessionManager, SessionStream},
@@ -32,7 +40,8 @@ use directory::Permission;
 use groupware::{DavResourceName, calendar::itip::ItipIngest};
 use http_proto::{
     DownloadResponse, HtmlResponse, HttpContext, HttpRequest, HttpResponse, HttpResponseBody,
-    HttpSessionData, JsonProblemResponse, ToHttpResponse, form_urlencoded, request::fetch_body,
+    HttpSessionData, JsonProblemResponse, ToHttpResponse, form_urlencoded,
+    request::{decode_path_element, fetch_body, fetch_body_with_limit},
 };
 use hyper::{
     Method, StatusCode, body,
@@ -90,8 +99,9 @@ impl ParseHttp for Server {
                 match (path.next().unwrap_or_default(), req.method()) {
                     ("", &Method::POST) => {
                         // Authenticate request
-                        let (_in_flight, access_token) =
-                            self.authenticate_headers(&req, &session, false).await?;
+                        let (_in_flight, access_token) = self
+                            .authenticate_protocol_headers(&req, &session, TenantProtocol::Jmap)
+                            .await?;
 
                         let bytes = fetch_body(
                             &mut req,
@@ -120,8 +130,9 @@ impl ParseHttp for Server {
                     }
                     ("download", &Method::GET) => {
                         // Authenticate request
-                        let (_in_flight, access_token) =
-                            self.authenticate_headers(&req, &session, false).await?;
+                        let (_in_flight, access_token) = self
+                            .authenticate_protocol_headers(&req, &session, TenantProtocol::Jmap)
+                            .await?;
 
                         if let (Some(_), Some(blob_id), Some(name)) = (
                             path.next().and_then(|p| Id::from_str(p).ok()),
@@ -149,8 +160,9 @@ impl ParseHttp for Server {
                     }
                     ("upload", &Method::POST) => {
                         // Authenticate request
-                        let (_in_flight, access_token) =
-                            self.authenticate_headers(&req, &session, false).await?;
+                        let (_in_flight, access_token) = self
+                            .authenticate_protocol_headers(&req, &session, TenantProtocol::Jmap)
+                            .await?;
 
                         if let Some(account_id) = path.next().and_then(|p| Id::from_str(p).ok()) {
                             return match fetch_body(
@@ -182,15 +194,17 @@ impl ParseHttp for Server {
                     }
                     ("eventsource", &Method::GET) => {
                         // Authenticate request
-                        let (_in_flight, access_token) =
-                            self.authenticate_headers(&req, &session, false).await?;
+                        let (_in_flight, access_token) = self
+                            .authenticate_protocol_headers(&req, &session, TenantProtocol::Jmap)
+                            .await?;
 
                         return self.handle_event_source(req, access_token).await;
                     }
                     ("ws", &Method::GET) => {
                         // Authenticate request
-                        let (_in_flight, access_token) =
-                            self.authenticate_headers(&req, &session, false).await?;
+                        let (_in_flight, access_token) = self
+                            .authenticate_protocol_headers(&req, &session, TenantProtocol::Jmap)
+                            .await?;
 
                         return self
                             .upgrade_websocket_connection(req, access_token, session)
@@ -199,8 +213,9 @@ impl ParseHttp for Server {
                     ("session", &Method::GET) => {
                         return if req.headers().contains_key(header::AUTHORIZATION) {
                             // Authenticate request
-                            let (_in_flight, access_token) =
-                                self.authenticate_headers(&req, &session, false).await?;
+                            let (_in_flight, access_token) = self
+                                .authenticate_protocol_headers(&req, &session, TenantProtocol::Jmap)
+                                .await?;
 
                             self.handle_session_resource(
                                 ctx.resolve_response_url(self).await,
@@ -244,8 +259,9 @@ impl ParseHttp for Server {
                         ),
                     (Some(resource), Some(method)) => {
                         // Authenticate request
-                        let (_in_flight, access_token) =
-                            self.authenticate_headers(&req, &session, false).await?;
+                        let (_in_flight, access_token) = self
+                            .authenticate_protocol_headers(&req, &session, TenantProtocol::Dav)
+                            .await?;
 
                         self.handle_dav_request(req, access_token, &session, resource, method)
                             .await
@@ -358,6 +374,12 @@ impl ParseHttp for Server {
                         .handle_token_introspect(&mut req, &access_token, session.session_id)
                         .await;
                 }
+                ("revoke", &Method::POST) => {
+                    self.is_http_anonymous_request_allowed(&session.remote_ip)
+                        .await?;
+
+                    return self.handle_token_revoke(&mut req, session.session_id).await;
+                }
                 ("userinfo", &Method::GET) => {
                     // Authenticate request
                     let (_in_flight, access_token) =
@@ -365,6 +387,19 @@ impl ParseHttp for Server {
 
                     return self.handle_userinfo_request(&access_token).await;
                 }
+                ("verify-email", &Method::POST) => {
+                    self.is_http_anonymous_request_allowed(&session.remote_ip)
+                        .await?;
+
+                    return self.handle_verify_email(&mut req, &session).await;
+                }
+                ("webauthn", &Method::POST) => {
+                    self.is_http_anonymous_request_allowed(&session.remote_ip)
+                        .await?;
+
+                    let step = path.next().unwrap_or_default().to_string();
+                    return self.handle_webauthn_login(&mut req, &session, &step).await;
+                }
                 ("register", &Method::POST) => {
                     return self
                         .handle_oauth_registration_request(&mut req, session)
@@ -382,10 +417,91 @@ impl ParseHttp for Server {
                 }
                 _ => (),
             },
+            "scim" => {
+                return self.handle_scim_request(&mut req, &session).await;
+            }
             "api" => {
                 // Allow CORS preflight requests
                 if req.method() == Method::OPTIONS {
-                    return Ok(JsonProblemResponse(StatusCode::NO_CONTENT).into_http_response());
+                    let cors = &self.core.jmap.http_cors;
+                    return Ok(if cors.is_enabled_for(&session.instance.id) {
+                        cors_preflight(cors, req.headers())
+                    } else {
+                        JsonProblemResponse(StatusCode::NO_CONTENT).into_http_response()
+                    });
+                }
+
+                // The branding, login page and password policy of a domain are public
+                if req.method() == Method::GET
+                    && matches!(
+                        req.uri().path().trim_end_matches('/'),
+                        "/api/branding" | "/api/branding/login" | "/api/branding/password-policy"
+                    )
+                {
+                    // Limit anonymous requests
+                    self.is_http_anonymous_request_allowed(&session.remote_ip)
+                        .await?;
+
+                    return self.handle_public_branding(&req).await;
+                }
+
+                // Organizations are requested, provisioned from invitations and
+                // activated without credentials
+                if req.method() == Method::POST {
+                    match req.uri().path().trim_end_matches('/') {
+                        "/api/organization/provision/invited" => {
+                            // Limit anonymous requests
+                            self.is_http_anonymous_request_allowed(&session.remote_ip)
+                                .await?;
+
+                            let max_size = self
+                                .core
+                                .jmap
+                                .http_body_limits
+                                .limit("/organization/provision/invited");
+                            let body =
+                                fetch_body_with_limit(&mut req, max_size, session.session_id)
+                                    .await?;
+                            return self
+                                .handle_invited_provisioning(Some(body), session.remote_ip)
+                                .await;
+                        }
+                        "/api/organization/signup" => {
+                            // Limit anonymous requests
+                            self.is_http_anonymous_request_allowed(&session.remote_ip)
+                                .await?;
+
+                            let max_size = self
+                                .core
+                                .jmap
+                                .http_body_limits
+                                .limit("/organization/signup");
+                            let body =
+                                fetch_body_with_limit(&mut req, max_size, session.session_id)
+                                    .await?;
+                            return self
+                                .handle_organization_signup(Some(body), session.remote_ip)
+                                .await;
+                        }
+                        path if path.starts_with("/api/organization/")
+                            && path.ends_with("/activate") =>
+                        {
+                            let tenant_id = path
+                                .strip_prefix("/api/organization/")
+                                .and_then(|path| path.strip_suffix("/activate"))
+                                .unwrap_or_default()
+                                .to_string();
+
+                            // Limit anonymous requests
+                            self.is_http_anonymous_request_allowed(&session.remote_ip)
+                                .await?;
+
+                            return self
+                                .handle_organization_activation(&mut req, &session, &tenant_id)
+                                .await;
+                        }
+                        _ => (),
+                    }
                 }
 
                 // Authenticate user
@@ -475,6 +591,15 @@ impl ParseHttp for Server {
                     return self.handle_autoconfig_request(&req).await;
                 }
             }
+            "export" => {
+                if req.method() == Method::GET {
+                    // Limit anonymous requests
+                    self.is_http_anonymous_request_allowed(&session.remote_ip)
+                        .await?;
+
+                    return self.handle_export_download(&req, path.collect()).await;
+                }
+            }
             "calendar" => {
                 // Limit anonymous requests
                 self.is_http_anonymous_request_allowed(&session.remote_ip)
@@ -570,7 +695,7 @@ impl ParseHttp for Server {
 
                         return Ok(Resource::new(
                             "text/plain; version=0.0.4",
-                            self.export_prometheus_metrics().await?.into_bytes(),
+                            self.export_prometheus_metrics()?.into_bytes(),
                         )
                         .into_http_response());
                     }
@@ -580,6 +705,17 @@ impl ParseHttp for Server {
                 }
                 _ => (),
             },
+            "bimi" => {
+                if let (Some(domain), Some(file), None, &Method::GET) =
+                    (path.next(), path.next(), path.next(), req.method())
+                {
+                    // Limit anonymous requests
+                    self.is_http_anonymous_request_allowed(&session.remote_ip)
+                        .await?;
+
+                    return bimi_resource(self, &decode_path_element(domain), file).await;
+                }
+            }
             // SPDX-SnippetBegin
             // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
             // SPDX-License-Identifier: LicenseRef-SEL
@@ -756,6 +892,12 @@ async fn handle_session<T: SessionStream>(inner: Arc<Inner>, session: SessionDat
                         session.remote_ip
                     };
 
+                    // Obtain CORS origin for management API requests
+                    let cors_origin = (server.core.jmap.http_cors.is_enabled_for(&instance.id)
+                        && req.method() != Method::OPTIONS
+                        && (req.uri().path() == "/api" || req.uri().path().starts_with("/api/")))
+                    .then(|| req.headers().get(header::ORIGIN).cloned());
+
                     // Parse HTTP request
                     let response = match Box::pin(server.parse_http_request(
                         req,
@@ -806,6 +948,15 @@ async fn handle_session<T: SessionStream>(inner: Arc<Inner>, session: SessionDat
                         }
                     }
 
+                    // Add CORS headers
+                    if let Some(origin) = cors_origin {
+                        add_cors_headers(
+                            &server.core.jmap.http_cors,
+                            origin.as_ref(),
+                            response.headers_mut(),
+                        );
+                    }
+
                     Ok::<_, hyper::Error>(response)
                 }
             }),
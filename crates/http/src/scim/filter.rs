@@ -0,0 +1,220 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+// Subset of the RFC 7644 filter grammar sent by the major identity providers:
+// equality comparisons, optionally joined with "and".
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    pub attribute: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchPath {
+    pub attribute: String,
+    pub filter: Vec<Comparison>,
+    pub sub_attribute: Option<String>,
+}
+
+/// Parses a filter such as `userName eq "bjensen"` into a list of
+/// comparisons that must all match. Attribute names are lowercased and
+/// stripped of any schema URN prefix.
+pub fn parse_filter(filter: &str) -> Result<Vec<Comparison>, String> {
+    let tokens = tokenize(filter)?;
+    let mut comparisons = Vec::new();
+    let mut tokens = tokens.into_iter();
+
+    loop {
+        let (Some(Token::Word(attribute)), Some(Token::Word(op)), Some(value)) =
+            (tokens.next(), tokens.next(), tokens.next())
+        else {
+            return Err(format!("Invalid filter {filter:?}"));
+        };
+
+        if !op.eq_ignore_ascii_case("eq") {
+            return Err(format!("Unsupported filter operator {op:?}"));
+        }
+
+        comparisons.push(Comparison {
+            attribute: attribute_name(&attribute),
+            value: match value {
+                Token::Word(value) | Token::Quoted(value) => value,
+            },
+        });
+
+        match tokens.next() {
+            None => return Ok(comparisons),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("and") => (),
+            Some(_) => return Err(format!("Unsupported filter expression {filter:?}")),
+        }
+    }
+}
+
+/// Parses a PATCH operation path such as `members[value eq "2819c223"]` or
+/// `emails[type eq "work"].value`.
+pub fn parse_path(path: &str) -> Result<PatchPath, String> {
+    let path = path.trim();
+    let (attribute, filter, rest) = if let Some((attribute, rest)) = path.split_once('[') {
+        let (filter, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("Invalid path {path:?}"))?;
+        (attribute, parse_filter(filter)?, rest)
+    } else {
+        (path, vec![], "")
+    };
+    let attribute = attribute_name(attribute);
+    if attribute.is_empty() {
+        return Err(format!("Invalid path {path:?}"));
+    }
+
+    let (attribute, sub_attribute) = if let Some(sub_attribute) = rest.strip_prefix('.') {
+        (attribute, Some(sub_attribute.to_ascii_lowercase()))
+    } else if !rest.is_empty() {
+        return Err(format!("Invalid path {path:?}"));
+    } else if let Some((attribute, sub_attribute)) = attribute.split_once('.') {
+        (attribute.to_string(), Some(sub_attribute.to_string()))
+    } else {
+        (attribute, None)
+    };
+
+    Ok(PatchPath {
+        attribute,
+        filter,
+        sub_attribute,
+    })
+}
+
+fn attribute_name(name: &str) -> String {
+    // Remove schema URN prefixes, e.g. "urn:ietf:params:scim:schemas:core:2.0:User:userName"
+    name.rsplit_once(':')
+        .map_or(name, |(_, name)| name)
+        .to_ascii_lowercase()
+}
+
+enum Token {
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(ch) => value.push(ch),
+                            None => return Err("Unterminated string in filter".to_string()),
+                        },
+                        Some(ch) => value.push(ch),
+                        None => return Err("Unterminated string in filter".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            '(' | ')' | '[' | ']' => {
+                return Err("Grouping is not supported in filters".to_string());
+            }
+            ch if ch.is_whitespace() => (),
+            ch => {
+                let mut word = String::from(ch);
+                while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace() && *ch != '"') {
+                    word.push(ch);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparison, PatchPath, parse_filter, parse_path};
+
+    fn eq(attribute: &str, value: &str) -> Comparison {
+        Comparison {
+            attribute: attribute.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_scim_filter() {
+        assert_eq!(
+            parse_filter(r#"userName eq "jdoe@acme.org""#).unwrap(),
+            vec![eq("username", "jdoe@acme.org")]
+        );
+        assert_eq!(
+            parse_filter(r#"externalId EQ "00u1a2b3\"c""#).unwrap(),
+            vec![eq("externalid", "00u1a2b3\"c")]
+        );
+        assert_eq!(
+            parse_filter(
+                r#"urn:ietf:params:scim:schemas:core:2.0:Group:displayName eq "Sales" and members eq "12""#
+            )
+            .unwrap(),
+            vec![eq("displayname", "Sales"), eq("members", "12")]
+        );
+        assert_eq!(parse_filter("id eq 42").unwrap(), vec![eq("id", "42")]);
+
+        for invalid in [
+            r#"userName sw "j""#,
+            r#"userName eq "jdoe" or userName eq "jane""#,
+            r#"(userName eq "jdoe")"#,
+            r#"userName eq "jdoe"#,
+            "userName eq",
+            "",
+        ] {
+            assert!(parse_filter(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parse_scim_path() {
+        assert_eq!(
+            parse_path("active").unwrap(),
+            PatchPath {
+                attribute: "active".to_string(),
+                filter: vec![],
+                sub_attribute: None,
+            }
+        );
+        assert_eq!(
+            parse_path("name.givenName").unwrap(),
+            PatchPath {
+                attribute: "name".to_string(),
+                filter: vec![],
+                sub_attribute: Some("givenname".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_path(r#"members[value eq "2819c223"]"#).unwrap(),
+            PatchPath {
+                attribute: "members".to_string(),
+                filter: vec![eq("value", "2819c223")],
+                sub_attribute: None,
+            }
+        );
+        assert_eq!(
+            parse_path(r#"emails[type eq "work"].value"#).unwrap(),
+            PatchPath {
+                attribute: "emails".to_string(),
+                filter: vec![eq("type", "work")],
+                sub_attribute: Some("value".to_string()),
+            }
+        );
+        assert!(parse_path(r#"members[value eq "1"]x"#).is_err());
+        assert!(parse_path("").is_err());
+    }
+}
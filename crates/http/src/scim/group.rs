@@ -0,0 +1,229 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    AttributeChange, PatchOp, SCHEMA_GROUP, ScimContext, external_id, get_attribute, insert_opt,
+    invalid_value, location, meta, multi_values,
+};
+use directory::{
+    Principal, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+        manage::{ManageDirectory, err_missing},
+    },
+};
+use serde_json::{Map, Value, json};
+use trc::AddContext;
+
+const MEMBER_TYPES: &[Type] = &[Type::Individual, Type::Group];
+
+impl ScimContext<'_> {
+    pub(super) async fn group_resource(
+        &self,
+        principal: Principal,
+        exclude_members: bool,
+    ) -> trc::Result<Value> {
+        let id = principal.id();
+
+        let mut resource = Map::new();
+        resource.insert("schemas".into(), json!([SCHEMA_GROUP]));
+        resource.insert("id".into(), id.to_string().into());
+        insert_opt(&mut resource, "externalId", external_id(&principal));
+        resource.insert(
+            "displayName".into(),
+            principal.description().unwrap_or(principal.name()).into(),
+        );
+
+        if !exclude_members {
+            let mut members = Vec::new();
+            for member_id in self
+                .server
+                .store()
+                .get_members(id)
+                .await
+                .caused_by(trc::location!())?
+            {
+                if let Some(member) = self
+                    .server
+                    .store()
+                    .get_principal(member_id)
+                    .await
+                    .caused_by(trc::location!())?
+                    .filter(|member| MEMBER_TYPES.contains(&member.typ()))
+                {
+                    members.push(json!({
+                        "value": member_id.to_string(),
+                        "display": member.description().unwrap_or(member.name()),
+                        "$ref": location(member.typ(), member_id),
+                    }));
+                }
+            }
+            resource.insert("members".into(), members.into());
+        }
+        resource.insert("meta".into(), meta(Type::Group, id));
+
+        Ok(resource.into())
+    }
+
+    pub(super) async fn group_principal(&self, body: &Value) -> trc::Result<PrincipalSet> {
+        let display_name = get_attribute(body, "displayName")
+            .and_then(|name| name.as_str())
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| err_missing("displayName"))?;
+        let mut principal = PrincipalSet::new(0, Type::Group)
+            .with_field(
+                PrincipalField::Name,
+                self.new_group_name(display_name).await?,
+            )
+            .with_field(PrincipalField::Description, display_name);
+
+        if let Some(external_id) = get_attribute(body, "externalId").and_then(|v| v.as_str()) {
+            principal.set(PrincipalField::ExternalId, external_id);
+        }
+
+        let mut members = Vec::new();
+        for member_id in get_attribute(body, "members")
+            .map(multi_values)
+            .unwrap_or_default()
+        {
+            members.push(self.member_name(&member_id).await?);
+        }
+        if !members.is_empty() {
+            principal.set(PrincipalField::Members, members);
+        }
+
+        Ok(principal)
+    }
+
+    pub(super) async fn group_updates(
+        &self,
+        changes: Vec<AttributeChange>,
+    ) -> trc::Result<Vec<PrincipalUpdate>> {
+        let mut updates = Vec::with_capacity(changes.len());
+
+        for change in changes {
+            let is_remove = change.op == PatchOp::Remove || change.value.is_null();
+
+            match (
+                change.path.attribute.as_str(),
+                change.path.sub_attribute.as_deref(),
+            ) {
+                ("displayname", None) if !is_remove => {
+                    let display_name = change
+                        .value
+                        .as_str()
+                        .map(|name| name.trim())
+                        .filter(|name| !name.is_empty())
+                        .ok_or_else(|| invalid_value("Invalid displayName"))?;
+                    updates.push(PrincipalUpdate::set(
+                        PrincipalField::Description,
+                        PrincipalValue::String(display_name.to_string()),
+                    ));
+                }
+                ("externalid", None) => {
+                    updates.push(PrincipalUpdate::set(
+                        PrincipalField::ExternalId,
+                        PrincipalValue::String(
+                            change.value.as_str().unwrap_or_default().to_string(),
+                        ),
+                    ));
+                }
+                ("members", None) => {
+                    let filtered = change
+                        .path
+                        .filter
+                        .iter()
+                        .filter(|c| c.attribute == "value")
+                        .map(|c| c.value.clone())
+                        .collect::<Vec<_>>();
+
+                    if is_remove {
+                        if !filtered.is_empty() || !change.value.is_null() {
+                            for member_id in filtered.into_iter().chain(multi_values(&change.value))
+                            {
+                                // Deleted principals are no longer members
+                                if let Some(name) =
+                                    self.principal_name(&member_id, MEMBER_TYPES).await?
+                                {
+                                    updates.push(PrincipalUpdate::remove_item(
+                                        PrincipalField::Members,
+                                        PrincipalValue::String(name),
+                                    ));
+                                }
+                            }
+                        } else {
+                            updates.push(PrincipalUpdate::set(
+                                PrincipalField::Members,
+                                PrincipalValue::StringList(vec![]),
+                            ));
+                        }
+                    } else {
+                        let mut names = Vec::new();
+                        for member_id in multi_values(&change.value) {
+                            names.push(self.member_name(&member_id).await?);
+                        }
+
+                        if change.op == PatchOp::Add {
+                            updates.extend(names.into_iter().map(|name| {
+                                PrincipalUpdate::add_item(
+                                    PrincipalField::Members,
+                                    PrincipalValue::String(name),
+                                )
+                            }));
+                        } else {
+                            updates.push(PrincipalUpdate::set(
+                                PrincipalField::Members,
+                                PrincipalValue::StringList(names),
+                            ));
+                        }
+                    }
+                }
+                // Attributes without a directory counterpart are ignored
+                _ => {}
+            }
+        }
+
+        Ok(updates)
+    }
+
+    /// Tenant principal names have to include one of the tenant's domains,
+    /// so groups created by a tenant are named after its first domain.
+    async fn new_group_name(&self, display_name: &str) -> trc::Result<String> {
+        let name = display_name
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase();
+        let Some(tenant_id) = self.tenant_id.filter(|_| !name.contains('@')) else {
+            return Ok(name);
+        };
+
+        let store = self.server.store();
+        let domain_id = store
+            .list_principals(None, Some(tenant_id), &[Type::Domain], false, 0, 0)
+            .await
+            .caused_by(trc::location!())?
+            .items
+            .into_iter()
+            .map(|domain| domain.id())
+            .min()
+            .ok_or_else(|| invalid_value("The tenant has no domains"))?;
+        let domain = store
+            .get_principal_name(domain_id)
+            .await
+            .caused_by(trc::location!())?
+            .ok_or_else(|| invalid_value("The tenant has no domains"))?;
+
+        Ok(format!("{name}@{domain}"))
+    }
+
+    async fn member_name(&self, id: &str) -> trc::Result<String> {
+        self.principal_name(id, MEMBER_TYPES)
+            .await?
+            .ok_or_else(|| invalid_value(format!("Member {id:?} does not exist")))
+    }
+}
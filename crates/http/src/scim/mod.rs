@@ -0,0 +1,896 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+pub mod filter;
+pub mod group;
+pub mod user;
+
+use crate::{
+    auth::authenticate::Authenticator,
+    management::{principal::PrincipalManager, stores::destroy_account_data},
+};
+use common::{
+    Server,
+    auth::{AccessToken, notice::is_password_change},
+};
+use directory::{
+    Permission, Principal, QueryBy, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet, PrincipalUpdate,
+        external_id::ExternalIdLookup,
+        manage::{self, ManageDirectory, UpdatePrincipal, not_found},
+    },
+};
+use email::mailbox::manage::MailboxFnc;
+use filter::{Comparison, PatchPath, parse_filter, parse_path};
+use http_proto::{request::fetch_body_with_limit, *};
+use hyper::{Method, StatusCode, header};
+use serde_json::{Map, Value, json};
+use std::future::Future;
+use trc::AddContext;
+use utils::url_params::UrlParams;
+
+pub const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const SCHEMA_GROUP: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const SCHEMA_LIST_RESPONSE: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const SCHEMA_PATCH_OP: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+// Metadata key read when a principal has no externalId of its own
+pub const METADATA_EXTERNAL_ID: &str = "scim.externalId";
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+pub trait ScimApi: Sync + Send {
+    fn handle_scim_request(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchOp {
+    Add,
+    Replace,
+    Remove,
+}
+
+/// A single attribute change, either from a PATCH operation or derived from
+/// the attributes of a POST or PUT body.
+#[derive(Debug)]
+pub struct AttributeChange {
+    pub op: PatchOp,
+    pub path: PatchPath,
+    pub value: Value,
+}
+
+pub(crate) struct ScimContext<'x> {
+    pub server: &'x Server,
+    pub access_token: &'x AccessToken,
+    pub tenant_id: Option<u32>,
+}
+
+impl ScimApi for Server {
+    async fn handle_scim_request(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> trc::Result<HttpResponse> {
+        let result = match self.authenticate_headers(req, session, true).await {
+            Ok((_in_flight, access_token)) => {
+                handle_request(self, req, session, &access_token).await
+            }
+            Err(err) => Err(err),
+        };
+
+        // SCIM clients expect errors in the SCIM message format
+        Ok(result.unwrap_or_else(|err| {
+            let response = error_response(&err);
+            trc::error!(err.span_id(session.session_id));
+            response
+        }))
+    }
+}
+
+async fn handle_request(
+    server: &Server,
+    req: &mut HttpRequest,
+    session: &HttpSessionData,
+    access_token: &AccessToken,
+) -> trc::Result<HttpResponse> {
+    // Validate the access token
+    access_token.assert_has_permission(Permission::ScimProvision)?;
+
+    // Make sure the current directory supports updates
+    server.assert_supported_directory(false)?;
+
+    let path = req.uri().path().to_string();
+    let path = path.split('/').skip(2).collect::<Vec<_>>();
+    if path.first().copied() != Some("v2") {
+        return Err(trc::ResourceEvent::NotFound.into_err());
+    }
+    let typ = match path.get(1).copied().unwrap_or_default() {
+        resource if resource.eq_ignore_ascii_case("Users") => Type::Individual,
+        resource if resource.eq_ignore_ascii_case("Groups") => Type::Group,
+        _ => return Err(trc::ResourceEvent::NotFound.into_err()),
+    };
+    let id = path.get(2).copied().filter(|id| !id.is_empty());
+    let body = if matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH) {
+        let body = fetch_body_with_limit(
+            req,
+            server.core.jmap.http_body_limits.default,
+            session.session_id,
+        )
+        .await?;
+        serde_json::from_slice::<Value>(&body).map_err(|err| {
+            trc::EventType::Resource(trc::ResourceEvent::BadParameters).from_json_error(err)
+        })?
+    } else {
+        Value::Null
+    };
+
+    let ctx = ScimContext {
+        server,
+        access_token,
+        tenant_id: access_token.tenant.map(|tenant| tenant.id),
+    };
+
+    match (id, req.method()) {
+        (None, &Method::GET) => {
+            access_token.assert_has_permission(match typ {
+                Type::Individual => Permission::IndividualList,
+                _ => Permission::GroupList,
+            })?;
+
+            let params = UrlParams::new(req.uri().query());
+            let filter = params
+                .get("filter")
+                .filter(|filter| !filter.trim().is_empty())
+                .map(parse_filter)
+                .transpose()
+                .map_err(invalid_filter)?
+                .unwrap_or_default();
+            let start_index = params.parse::<usize>("startIndex").unwrap_or(1).max(1);
+            let count = params
+                .parse::<usize>("count")
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .min(MAX_PAGE_SIZE);
+            let exclude_members = params
+                .get("excludedAttributes")
+                .is_some_and(|attrs| attrs.to_ascii_lowercase().contains("members"));
+
+            let (total, principals) = ctx.list(typ, &filter, start_index, count).await?;
+            let mut resources = Vec::with_capacity(principals.len());
+            for principal in principals {
+                resources.push(ctx.resource(principal, exclude_members).await?);
+            }
+
+            Ok(scim_response(
+                StatusCode::OK,
+                json!({
+                    "schemas": [SCHEMA_LIST_RESPONSE],
+                    "totalResults": total,
+                    "startIndex": start_index,
+                    "itemsPerPage": resources.len(),
+                    "Resources": resources,
+                }),
+            ))
+        }
+        (None, &Method::POST) => {
+            access_token.assert_has_permission(match typ {
+                Type::Individual => Permission::IndividualCreate,
+                _ => Permission::GroupCreate,
+            })?;
+
+            let principal = match typ {
+                Type::Individual => ctx.user_principal(&body).await?,
+                _ => ctx.group_principal(&body).await?,
+            };
+            let id = ctx.create(principal).await?;
+            let resource = ctx.resource(ctx.principal(id, typ).await?, false).await?;
+
+            Ok(scim_response(StatusCode::CREATED, resource)
+                .with_header(header::LOCATION, location(typ, id)))
+        }
+        (Some(id), method) => {
+            let principal = ctx.principal(parse_id(id)?, typ).await?;
+
+            match *method {
+                Method::GET => {
+                    access_token.assert_has_permission(match typ {
+                        Type::Individual => Permission::IndividualGet,
+                        _ => Permission::GroupGet,
+                    })?;
+
+                    let exclude_members = UrlParams::new(req.uri().query())
+                        .get("excludedAttributes")
+                        .is_some_and(|attrs| attrs.to_ascii_lowercase().contains("members"));
+
+                    Ok(scim_response(
+                        StatusCode::OK,
+                        ctx.resource(principal, exclude_members).await?,
+                    ))
+                }
+                Method::PUT | Method::PATCH => {
+                    access_token.assert_has_permission(match typ {
+                        Type::Individual => Permission::IndividualUpdate,
+                        _ => Permission::GroupUpdate,
+                    })?;
+
+                    let changes = if *method == Method::PUT {
+                        replace_changes(&body, typ)?
+                    } else {
+                        patch_changes(&body)?
+                    };
+                    let updates = match typ {
+                        Type::Individual => ctx.user_updates(&principal, changes).await?,
+                        _ => ctx.group_updates(changes).await?,
+                    };
+                    ctx.update(&principal, updates).await?;
+
+                    Ok(scim_response(
+                        StatusCode::OK,
+                        ctx.resource(ctx.principal(principal.id(), typ).await?, false)
+                            .await?,
+                    ))
+                }
+                Method::DELETE => {
+                    access_token.assert_has_permission(match typ {
+                        Type::Individual => Permission::IndividualDelete,
+                        _ => Permission::GroupDelete,
+                    })?;
+
+                    ctx.delete(&principal).await?;
+
+                    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+                }
+                _ => Err(trc::ResourceEvent::NotFound.into_err()),
+            }
+        }
+        _ => Err(trc::ResourceEvent::NotFound.into_err()),
+    }
+}
+
+impl ScimContext<'_> {
+    /// Fetches a principal, making sure it is of the expected type and
+    /// visible to the caller's tenant.
+    pub async fn principal(&self, id: u32, typ: Type) -> trc::Result<Principal> {
+        self.server
+            .store()
+            .get_principal(id)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|principal| principal.typ() == typ && self.has_access(principal))
+            .ok_or_else(|| not_found(id.to_string()))
+    }
+
+    /// Maps the id of a member or group to its principal name.
+    pub async fn principal_name(&self, id: &str, types: &[Type]) -> trc::Result<Option<String>> {
+        let Ok(id) = id.parse::<u32>() else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .server
+            .store()
+            .get_principal(id)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|principal| types.contains(&principal.typ()) && self.has_access(principal))
+            .map(|principal| principal.name))
+    }
+
+    fn has_access(&self, principal: &Principal) -> bool {
+        self.tenant_id
+            .is_none_or(|tenant_id| principal.tenant() == Some(tenant_id))
+    }
+
+    async fn list(
+        &self,
+        typ: Type,
+        filter: &[Comparison],
+        start_index: usize,
+        count: usize,
+    ) -> trc::Result<(usize, Vec<Principal>)> {
+        let store = self.server.store();
+
+        for comparison in filter {
+            let is_supported = match typ {
+                Type::Individual => {
+                    matches!(
+                        comparison.attribute.as_str(),
+                        "id" | "username" | "externalid"
+                    )
+                }
+                _ => matches!(
+                    comparison.attribute.as_str(),
+                    "id" | "displayname" | "externalid" | "members" | "members.value"
+                ),
+            };
+            if !is_supported {
+                return Err(invalid_filter(format!(
+                    "Filtering by {:?} is not supported",
+                    comparison.attribute
+                )));
+            }
+        }
+
+        // Narrow down the candidates using the most selective comparison
+        let mut ids = Vec::new();
+        if let Some(comparison) = filter.iter().find(|c| c.attribute == "id") {
+            ids.extend(comparison.value.parse::<u32>().ok());
+        } else if let Some(comparison) = filter.iter().find(|c| c.attribute == "username") {
+            ids.extend(
+                store
+                    .get_principal_id(&comparison.value.to_lowercase())
+                    .await
+                    .caused_by(trc::location!())?,
+            );
+        } else if let Some(comparison) = filter.iter().find(|c| c.attribute == "externalid")
+            && let Some(info) = store
+                .resolve_external_id(&comparison.value, self.tenant_id)
+                .await
+                .caused_by(trc::location!())?
+        {
+            ids.push(info.id);
+        } else {
+            // External IDs only kept in the metadata are not indexed
+            ids.extend(
+                store
+                    .list_principals(None, self.tenant_id, &[typ], false, 0, 0)
+                    .await
+                    .caused_by(trc::location!())?
+                    .items
+                    .into_iter()
+                    .map(|principal| principal.id()),
+            );
+        }
+
+        let skip = start_index - 1;
+        if filter.is_empty() {
+            let total = ids.len();
+            let mut principals = Vec::with_capacity(count.min(total));
+            for id in ids.into_iter().skip(skip).take(count) {
+                if let Some(principal) =
+                    store.get_principal(id).await.caused_by(trc::location!())?
+                {
+                    principals.push(principal);
+                }
+            }
+            return Ok((total, principals));
+        }
+
+        let mut matches = Vec::new();
+        for id in ids {
+            if let Some(principal) = store
+                .get_principal(id)
+                .await
+                .caused_by(trc::location!())?
+                .filter(|principal| principal.typ() == typ && self.has_access(principal))
+                && self.matches_filter(&principal, filter).await?
+            {
+                matches.push(principal);
+            }
+        }
+        let total = matches.len();
+
+        Ok((total, matches.into_iter().skip(skip).take(count).collect()))
+    }
+
+    async fn matches_filter(
+        &self,
+        principal: &Principal,
+        filter: &[Comparison],
+    ) -> trc::Result<bool> {
+        for comparison in filter {
+            let value = comparison.value.as_str();
+            let is_match = match (comparison.attribute.as_str(), principal.typ()) {
+                ("id", _) => principal.id().to_string() == value,
+                ("username", Type::Individual) => principal.name().eq_ignore_ascii_case(value),
+                ("displayname", Type::Group) => {
+                    principal.name().eq_ignore_ascii_case(value)
+                        || principal.description() == Some(value)
+                }
+                ("externalid", _) => external_id(principal) == Some(value),
+                ("members" | "members.value", Type::Group) => self
+                    .server
+                    .store()
+                    .get_members(principal.id())
+                    .await
+                    .caused_by(trc::location!())?
+                    .iter()
+                    .any(|id| id.to_string() == value),
+                _ => false,
+            };
+
+            if !is_match {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn resource(&self, principal: Principal, exclude_members: bool) -> trc::Result<Value> {
+        match principal.typ() {
+            Type::Individual => self.user_resource(principal).await,
+            _ => self.group_resource(principal, exclude_members).await,
+        }
+    }
+
+    async fn create(&self, principal: PrincipalSet) -> trc::Result<u32> {
+        // SPDX-SnippetBegin
+        // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+        // SPDX-License-Identifier: LicenseRef-SEL
+
+        #[cfg(feature = "enterprise")]
+        if matches!(principal.typ(), Type::Individual)
+            && self.server.core.is_enterprise_edition()
+            && !self.server.can_create_account().await?
+        {
+            return Err(manage::error(
+                "License account limit reached",
+                format!(
+                    "Enterprise licensed account limit reached: {} accounts licensed.",
+                    self.server.licensed_accounts()
+                )
+                .into(),
+            ));
+        }
+
+        // SPDX-SnippetEnd
+
+        if let Some(tenant_id) = self.tenant_id {
+            self.server
+                .assert_plan_limit(tenant_id, principal.typ())
+                .await?;
+        }
+        if principal.typ() == Type::Individual {
+            self.server.assert_password_policy(
+                self.tenant_id,
+                principal
+                    .get_str_array(PrincipalField::Secrets)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|secret| secret.as_str()),
+            )?;
+        }
+
+        let principal_name = principal.name().to_lowercase();
+        let principal_typ = principal.typ();
+        let result = self
+            .server
+            .store()
+            .create_principal_with_policy(
+                principal,
+                self.tenant_id,
+                Some(&self.access_token.permissions),
+                &self.server.core.jmap.directory_names,
+            )
+            .await?;
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::PrincipalCreated),
+            AccountName = self.access_token.name.clone(),
+            AccountId = self.access_token.primary_id(),
+            TenantId = self.tenant_id,
+            Id = principal_name,
+            Type = principal_typ.as_str(),
+        );
+
+        self.server
+            .invalidate_principal_caches(result.changed_principals)
+            .await;
+
+        // Create the default folders of tenant accounts
+        if principal_typ == Type::Individual
+            && let Err(err) = self.server.provision_folders(result.id).await
+        {
+            trc::error!(err.details("Failed to provision folders"));
+        }
+
+        if principal_typ == Type::Individual {
+            self.server
+                .notify_security_event(
+                    trc::EventType::Directory(trc::DirectoryEvent::PrincipalCreated),
+                    result.id,
+                    format!("Created by: {}", self.access_token.name).into(),
+                )
+                .await;
+        }
+
+        Ok(result.id)
+    }
+
+    async fn update(
+        &self,
+        principal: &Principal,
+        updates: Vec<PrincipalUpdate>,
+    ) -> trc::Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        self.server
+            .assert_password_changes(principal.tenant().or(self.tenant_id), &updates)?;
+        let password_changed = is_password_change(&updates);
+
+        let changed_principals = self
+            .server
+            .store()
+            .update_principal(
+                UpdatePrincipal::by_id(principal.id())
+                    .with_updates(updates)
+                    .with_tenant(self.tenant_id)
+                    .with_allowed_permissions(&self.access_token.permissions)
+                    .with_name_policy(&self.server.core.jmap.directory_names),
+            )
+            .await?;
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::PrincipalUpdated),
+            AccountName = self.access_token.name.clone(),
+            AccountId = self.access_token.primary_id(),
+            TenantId = self.tenant_id,
+            Id = principal.name().to_string(),
+            Type = principal.typ().as_str(),
+        );
+
+        if password_changed {
+            trc::event!(
+                Directory(trc::DirectoryEvent::PasswordChanged),
+                AccountName = self.access_token.name.clone(),
+                AccountId = self.access_token.primary_id(),
+                TenantId = self.tenant_id,
+                Id = principal.name().to_string(),
+            );
+            self.server
+                .notify_security_event(
+                    trc::EventType::Directory(trc::DirectoryEvent::PasswordChanged),
+                    principal.id(),
+                    format!("Changed by: {}", self.access_token.name).into(),
+                )
+                .await;
+        }
+
+        self.server
+            .invalidate_principal_caches(changed_principals)
+            .await;
+
+        Ok(())
+    }
+
+    async fn delete(&self, principal: &Principal) -> trc::Result<()> {
+        let changed_principals = self
+            .server
+            .store()
+            .delete_principal(QueryBy::Id(principal.id()))
+            .await?;
+
+        trc::event!(
+            Directory(trc::DirectoryEvent::PrincipalDeleted),
+            AccountName = self.access_token.name.clone(),
+            AccountId = self.access_token.primary_id(),
+            TenantId = self.tenant_id,
+            Id = principal.name().to_string(),
+            Type = principal.typ().as_str(),
+        );
+
+        if let Err(err) = destroy_account_data(self.server, principal.id(), true).await {
+            trc::error!(err.details("Failed to delete principal"));
+        }
+
+        self.server
+            .invalidate_principal_caches(changed_principals)
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Converts a PUT body into replace operations. Attributes missing from the
+/// body are cleared.
+fn replace_changes(body: &Value, typ: Type) -> trc::Result<Vec<AttributeChange>> {
+    let replacements = object_changes(PatchOp::Replace, body)?;
+    let clearable: &[&str] = match typ {
+        Type::Individual => &["displayname", "externalid", "emails"],
+        _ => &["externalid", "members"],
+    };
+
+    // Clear first, so that attributes derived from others (such as the
+    // display name from the name components) are not overwritten
+    let mut changes = Vec::with_capacity(replacements.len() + clearable.len());
+    for attribute in clearable {
+        if !replacements.iter().any(|c| c.path.attribute == *attribute) {
+            changes.push(AttributeChange {
+                op: PatchOp::Remove,
+                path: PatchPath {
+                    attribute: attribute.to_string(),
+                    filter: vec![],
+                    sub_attribute: None,
+                },
+                value: Value::Null,
+            });
+        }
+    }
+    changes.extend(replacements);
+
+    Ok(changes)
+}
+
+/// Converts the operations of a PATCH request.
+fn patch_changes(body: &Value) -> trc::Result<Vec<AttributeChange>> {
+    let operations = get_attribute(body, "Operations")
+        .and_then(|operations| operations.as_array())
+        .ok_or_else(|| invalid_syntax("Missing Operations"))?;
+    let mut changes = Vec::with_capacity(operations.len());
+
+    for operation in operations {
+        let op = match get_attribute(operation, "op")
+            .and_then(|op| op.as_str())
+            .map(|op| op.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("add") => PatchOp::Add,
+            Some("replace") => PatchOp::Replace,
+            Some("remove") => PatchOp::Remove,
+            _ => return Err(invalid_syntax("Invalid or missing op")),
+        };
+        let value = get_attribute(operation, "value")
+            .cloned()
+            .unwrap_or_default();
+
+        match get_attribute(operation, "path").and_then(|path| path.as_str()) {
+            Some(path) => {
+                changes.push(AttributeChange {
+                    op,
+                    path: parse_path(path).map_err(invalid_path)?,
+                    value,
+                });
+            }
+            None if op != PatchOp::Remove => {
+                changes.extend(object_changes(op, &value)?);
+            }
+            None => return Err(no_target("Remove operations require a path")),
+        }
+    }
+
+    Ok(changes)
+}
+
+fn object_changes(op: PatchOp, value: &Value) -> trc::Result<Vec<AttributeChange>> {
+    let object = value
+        .as_object()
+        .ok_or_else(|| invalid_syntax("Expected a JSON object"))?;
+    let mut changes = Vec::with_capacity(object.len());
+    for (attribute, value) in object {
+        changes.push(AttributeChange {
+            op,
+            path: parse_path(attribute).map_err(invalid_path)?,
+            value: value.clone(),
+        });
+    }
+    Ok(changes)
+}
+
+/// Attribute names are case insensitive.
+pub fn get_attribute<'x>(value: &'x Value, name: &str) -> Option<&'x Value> {
+    value
+        .as_object()?
+        .iter()
+        .find_map(|(key, value)| key.eq_ignore_ascii_case(name).then_some(value))
+}
+
+/// Some identity providers send booleans as strings.
+pub fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::String(value) if value.eq_ignore_ascii_case("true") => Some(true),
+        Value::String(value) if value.eq_ignore_ascii_case("false") => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the `value` of each item in a multi-valued attribute.
+pub fn multi_values(value: &Value) -> Vec<String> {
+    fn item_value(item: &Value) -> Option<String> {
+        match item {
+            Value::String(value) => Some(value.clone()),
+            Value::Number(value) => Some(value.to_string()),
+            Value::Object(_) => get_attribute(item, "value").and_then(item_value),
+            _ => None,
+        }
+    }
+
+    match value {
+        Value::Array(items) => {
+            // Primary values go first
+            let mut items = items.iter().collect::<Vec<_>>();
+            items.sort_by_key(|item| {
+                !get_attribute(item, "primary")
+                    .and_then(as_bool)
+                    .unwrap_or_default()
+            });
+            items.into_iter().filter_map(item_value).collect()
+        }
+        value => item_value(value).into_iter().collect(),
+    }
+}
+
+pub fn location(typ: Type, id: u32) -> String {
+    match typ {
+        Type::Individual => format!("/scim/v2/Users/{id}"),
+        _ => format!("/scim/v2/Groups/{id}"),
+    }
+}
+
+pub fn meta(typ: Type, id: u32) -> Value {
+    json!({
+        "resourceType": if typ == Type::Individual { "User" } else { "Group" },
+        "location": location(typ, id),
+    })
+}
+
+pub fn insert_opt(resource: &mut Map<String, Value>, key: &str, value: Option<&str>) {
+    if let Some(value) = value.filter(|value| !value.is_empty()) {
+        resource.insert(key.to_string(), value.into());
+    }
+}
+
+pub fn external_id(principal: &Principal) -> Option<&str> {
+    principal
+        .external_id()
+        .or_else(|| principal.metadata_value(METADATA_EXTERNAL_ID))
+}
+
+fn parse_id(id: &str) -> trc::Result<u32> {
+    id.parse::<u32>().map_err(|_| not_found(id.to_string()))
+}
+
+fn scim_response(status: StatusCode, body: Value) -> HttpResponse {
+    HttpResponse::new(status)
+        .with_content_type("application/scim+json")
+        .with_text_body(body.to_string())
+}
+
+fn scim_error(err: trc::Error, scim_type: &'static str) -> trc::Error {
+    err.ctx(trc::Key::Type, scim_type)
+}
+
+pub fn invalid_syntax(details: impl Into<trc::Value>) -> trc::Error {
+    scim_error(
+        trc::ResourceEvent::BadParameters
+            .into_err()
+            .details(details),
+        "invalidSyntax",
+    )
+}
+
+pub fn invalid_value(details: impl Into<trc::Value>) -> trc::Error {
+    scim_error(
+        trc::ResourceEvent::BadParameters
+            .into_err()
+            .details(details),
+        "invalidValue",
+    )
+}
+
+fn invalid_filter(details: impl Into<trc::Value>) -> trc::Error {
+    scim_error(
+        trc::ResourceEvent::BadParameters
+            .into_err()
+            .details(details),
+        "invalidFilter",
+    )
+}
+
+fn invalid_path(details: impl Into<trc::Value>) -> trc::Error {
+    scim_error(
+        trc::ResourceEvent::BadParameters
+            .into_err()
+            .details(details),
+        "invalidPath",
+    )
+}
+
+fn no_target(details: impl Into<trc::Value>) -> trc::Error {
+    scim_error(
+        trc::ResourceEvent::BadParameters
+            .into_err()
+            .details(details),
+        "noTarget",
+    )
+}
+
+fn error_response(err: &trc::Error) -> HttpResponse {
+    let mut detail = err
+        .value_as_str(trc::Key::Details)
+        .or_else(|| err.value_as_str(trc::Key::Reason))
+        .map(|detail| detail.to_string());
+    let (status, scim_type) = match err.as_ref() {
+        trc::EventType::Manage(trc::ManageEvent::NotFound)
+        | trc::EventType::Resource(trc::ResourceEvent::NotFound) => {
+            detail = Some("Resource not found".to_string());
+            (StatusCode::NOT_FOUND, None)
+        }
+        trc::EventType::Manage(trc::ManageEvent::AlreadyExists) => {
+            detail = Some(format!(
+                "A resource with {} {:?} already exists",
+                err.value_as_str(trc::Key::Key).unwrap_or_default(),
+                err.value_as_str(trc::Key::Value).unwrap_or_default()
+            ));
+            (StatusCode::CONFLICT, Some("uniqueness"))
+        }
+        trc::EventType::Manage(trc::ManageEvent::MissingParameter) => {
+            detail = Some(format!(
+                "Missing required attribute {}",
+                err.value_as_str(trc::Key::Key).unwrap_or_default()
+            ));
+            (StatusCode::BAD_REQUEST, Some("invalidValue"))
+        }
+        trc::EventType::Manage(trc::ManageEvent::Error | trc::ManageEvent::AssertFailed) => {
+            (StatusCode::BAD_REQUEST, Some("invalidValue"))
+        }
+        trc::EventType::Manage(trc::ManageEvent::ReservedName) => {
+            detail = Some(format!(
+                "The {} {:?} is reserved",
+                err.value_as_str(trc::Key::Key).unwrap_or_default(),
+                err.value_as_str(trc::Key::Value).unwrap_or_default()
+            ));
+            (StatusCode::BAD_REQUEST, Some("invalidValue"))
+        }
+        trc::EventType::Manage(trc::ManageEvent::PasswordReused) => {
+            detail = Some(format!(
+                "The password matches one of the last {} passwords",
+                err.value(trc::Key::Limit)
+                    .and_then(|v| v.to_uint())
+                    .unwrap_or_default()
+            ));
+            (StatusCode::BAD_REQUEST, Some("invalidValue"))
+        }
+        trc::EventType::Manage(trc::ManageEvent::NotSupported) => {
+            (StatusCode::NOT_IMPLEMENTED, None)
+        }
+        trc::EventType::Resource(trc::ResourceEvent::BadParameters) => (
+            StatusCode::BAD_REQUEST,
+            Some(err.value_as_str(trc::Key::Type).unwrap_or("invalidSyntax")),
+        ),
+        trc::EventType::Security(trc::SecurityEvent::Unauthorized) => (StatusCode::FORBIDDEN, None),
+        trc::EventType::Auth(
+            trc::AuthEvent::Failed | trc::AuthEvent::Error | trc::AuthEvent::TokenExpired,
+        ) => {
+            detail = Some("Invalid or missing credentials".to_string());
+            (StatusCode::UNAUTHORIZED, None)
+        }
+        trc::EventType::Limit(trc::LimitEvent::SizeRequest) => {
+            (StatusCode::PAYLOAD_TOO_LARGE, None)
+        }
+        trc::EventType::Limit(_) | trc::EventType::Security(_) => {
+            (StatusCode::TOO_MANY_REQUESTS, None)
+        }
+        _ => {
+            detail = None;
+            (StatusCode::INTERNAL_SERVER_ERROR, None)
+        }
+    };
+
+    let mut body = json!({
+        "schemas": [SCHEMA_ERROR],
+        "status": status.as_u16().to_string(),
+        "detail": detail.unwrap_or_else(|| status.canonical_reason().unwrap_or_default().to_string()),
+    });
+    if let Some(scim_type) = scim_type {
+        body["scimType"] = scim_type.into();
+    }
+
+    let response = scim_response(status, body);
+    if status == StatusCode::UNAUTHORIZED {
+        response.with_header(header::WWW_AUTHENTICATE, "Bearer realm=\"RMail Server\"")
+    } else {
+        response
+    }
+}
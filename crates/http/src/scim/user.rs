@@ -0,0 +1,352 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{
+    AttributeChange, PatchOp, SCHEMA_USER, ScimContext, as_bool, external_id, get_attribute,
+    insert_opt, invalid_value, location, meta, multi_values,
+};
+use directory::{
+    Permission, Principal, Type,
+    backend::internal::{
+        PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+        manage::{ManageDirectory, err_missing},
+    },
+};
+use serde_json::{Map, Value, json};
+use trc::AddContext;
+
+impl ScimContext<'_> {
+    pub(super) async fn user_resource(&self, principal: Principal) -> trc::Result<Value> {
+        let id = principal.id();
+        let is_active = !principal
+            .permissions()
+            .any(|p| p.permission == Permission::Authenticate && !p.grant);
+
+        let mut resource = Map::new();
+        resource.insert("schemas".into(), json!([SCHEMA_USER]));
+        resource.insert("id".into(), id.to_string().into());
+        insert_opt(&mut resource, "externalId", external_id(&principal));
+        resource.insert("userName".into(), principal.name().into());
+        if let Some(description) = principal.description() {
+            resource.insert("displayName".into(), description.into());
+            resource.insert("name".into(), json!({ "formatted": description }));
+        }
+        resource.insert("active".into(), is_active.into());
+        resource.insert(
+            "emails".into(),
+            principal
+                .email_addresses()
+                .enumerate()
+                .map(|(idx, email)| {
+                    json!({
+                        "value": email,
+                        "type": "work",
+                        "primary": idx == 0,
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        );
+
+        let mut groups = Vec::new();
+        for member_of in self
+            .server
+            .store()
+            .get_member_of(id)
+            .await
+            .caused_by(trc::location!())?
+        {
+            if member_of.typ == Type::Group
+                && let Some(group) = self
+                    .server
+                    .store()
+                    .get_principal(member_of.principal_id)
+                    .await
+                    .caused_by(trc::location!())?
+            {
+                groups.push(json!({
+                    "value": group.id().to_string(),
+                    "display": group.description().unwrap_or(group.name()),
+                    "$ref": location(Type::Group, group.id()),
+                }));
+            }
+        }
+        resource.insert("groups".into(), groups.into());
+        resource.insert("meta".into(), meta(Type::Individual, id));
+
+        Ok(resource.into())
+    }
+
+    pub(super) async fn user_principal(&self, body: &Value) -> trc::Result<PrincipalSet> {
+        let name = get_attribute(body, "userName")
+            .and_then(|name| name.as_str())
+            .filter(|name| !name.trim().is_empty())
+            .ok_or_else(|| err_missing("userName"))?;
+        let mut principal = PrincipalSet::new(0, Type::Individual)
+            .with_field(PrincipalField::Name, name.trim())
+            .with_field(PrincipalField::Roles, vec!["user".to_string()]);
+
+        if let Some(description) = display_name(body) {
+            principal.set(PrincipalField::Description, description);
+        }
+
+        let mut emails = get_attribute(body, "emails")
+            .map(multi_values)
+            .unwrap_or_default();
+        if emails.is_empty() && name.contains('@') {
+            emails.push(name.trim().to_string());
+        }
+        if !emails.is_empty() {
+            principal.set(PrincipalField::Emails, emails);
+        }
+
+        if let Some(password) = get_attribute(body, "password").and_then(|v| v.as_str()) {
+            principal.set(PrincipalField::Secrets, vec![password.to_string()]);
+        }
+        if let Some(external_id) = get_attribute(body, "externalId").and_then(|v| v.as_str()) {
+            principal.set(PrincipalField::ExternalId, external_id);
+        }
+        if get_attribute(body, "active").and_then(as_bool) == Some(false) {
+            principal.set(
+                PrincipalField::DisabledPermissions,
+                vec![Permission::Authenticate.name().to_string()],
+            );
+        }
+
+        let mut member_of = Vec::new();
+        for group_id in get_attribute(body, "groups")
+            .map(multi_values)
+            .unwrap_or_default()
+        {
+            member_of.push(self.group_name(&group_id).await?);
+        }
+        if !member_of.is_empty() {
+            principal.set(PrincipalField::MemberOf, member_of);
+        }
+
+        Ok(principal)
+    }
+
+    pub(super) async fn user_updates(
+        &self,
+        principal: &Principal,
+        changes: Vec<AttributeChange>,
+    ) -> trc::Result<Vec<PrincipalUpdate>> {
+        let mut updates = Vec::with_capacity(changes.len());
+
+        for change in changes {
+            let is_remove = change.op == PatchOp::Remove || change.value.is_null();
+
+            match (
+                change.path.attribute.as_str(),
+                change.path.sub_attribute.as_deref(),
+            ) {
+                ("username", None) if !is_remove => {
+                    let name = change
+                        .value
+                        .as_str()
+                        .filter(|name| !name.trim().is_empty())
+                        .ok_or_else(|| invalid_value("Invalid userName"))?;
+                    updates.push(PrincipalUpdate::set(
+                        PrincipalField::Name,
+                        PrincipalValue::String(name.trim().to_string()),
+                    ));
+                }
+                ("externalid", None) => {
+                    updates.push(PrincipalUpdate::set(
+                        PrincipalField::ExternalId,
+                        PrincipalValue::String(
+                            change.value.as_str().unwrap_or_default().to_string(),
+                        ),
+                    ));
+                }
+                ("displayname", None) | ("name", Some("formatted")) => {
+                    updates.push(PrincipalUpdate::set(
+                        PrincipalField::Description,
+                        PrincipalValue::String(
+                            change.value.as_str().unwrap_or_default().to_string(),
+                        ),
+                    ));
+                }
+                ("name", None) => {
+                    updates.push(PrincipalUpdate::set(
+                        PrincipalField::Description,
+                        PrincipalValue::String(
+                            display_name(&json!({ "name": change.value })).unwrap_or_default(),
+                        ),
+                    ));
+                }
+                ("active", None) => {
+                    let is_active = is_remove || as_bool(&change.value).unwrap_or(true);
+                    let value = PrincipalValue::String(Permission::Authenticate.name().to_string());
+                    updates.push(if is_active {
+                        PrincipalUpdate::remove_item(PrincipalField::DisabledPermissions, value)
+                    } else {
+                        PrincipalUpdate::add_item(PrincipalField::DisabledPermissions, value)
+                    });
+                }
+                ("password", None) if !is_remove => {
+                    let password = change
+                        .value
+                        .as_str()
+                        .ok_or_else(|| invalid_value("Invalid password"))?;
+                    updates.push(PrincipalUpdate::remove_item(
+                        PrincipalField::Secrets,
+                        PrincipalValue::String(String::new()),
+                    ));
+                    updates.push(PrincipalUpdate::add_item(
+                        PrincipalField::Secrets,
+                        PrincipalValue::String(password.to_string()),
+                    ));
+                }
+                ("emails", sub_attribute) => {
+                    let filtered = change
+                        .path
+                        .filter
+                        .iter()
+                        .filter(|c| c.attribute == "value")
+                        .map(|c| c.value.clone())
+                        .collect::<Vec<_>>();
+
+                    if is_remove {
+                        if !filtered.is_empty() || !change.value.is_null() {
+                            for email in filtered.into_iter().chain(multi_values(&change.value)) {
+                                updates.push(PrincipalUpdate::remove_item(
+                                    PrincipalField::Emails,
+                                    PrincipalValue::String(email),
+                                ));
+                            }
+                        } else {
+                            updates.push(PrincipalUpdate::set(
+                                PrincipalField::Emails,
+                                PrincipalValue::StringList(vec![]),
+                            ));
+                        }
+                    } else if sub_attribute == Some("value") || !change.path.filter.is_empty() {
+                        // Replace the primary address, keeping any aliases
+                        let Some(primary) = multi_values(&change.value).into_iter().next() else {
+                            return Err(invalid_value("Invalid email address"));
+                        };
+                        let emails = std::iter::once(primary.clone())
+                            .chain(
+                                principal
+                                    .email_addresses()
+                                    .skip(1)
+                                    .filter(|email| *email != primary)
+                                    .map(|email| email.to_string()),
+                            )
+                            .collect::<Vec<_>>();
+                        updates.push(PrincipalUpdate::set(
+                            PrincipalField::Emails,
+                            PrincipalValue::StringList(emails),
+                        ));
+                    } else if change.op == PatchOp::Add {
+                        for email in multi_values(&change.value) {
+                            updates.push(PrincipalUpdate::add_item(
+                                PrincipalField::Emails,
+                                PrincipalValue::String(email),
+                            ));
+                        }
+                    } else {
+                        updates.push(PrincipalUpdate::set(
+                            PrincipalField::Emails,
+                            PrincipalValue::StringList(multi_values(&change.value)),
+                        ));
+                    }
+                }
+                ("groups", None) => {
+                    let filtered = change
+                        .path
+                        .filter
+                        .iter()
+                        .filter(|c| c.attribute == "value")
+                        .map(|c| c.value.clone())
+                        .collect::<Vec<_>>();
+
+                    if is_remove {
+                        if !filtered.is_empty() || !change.value.is_null() {
+                            for group_id in filtered.into_iter().chain(multi_values(&change.value))
+                            {
+                                // Groups that no longer exist are not members anyway
+                                if let Some(name) =
+                                    self.principal_name(&group_id, &[Type::Group]).await?
+                                {
+                                    updates.push(PrincipalUpdate::remove_item(
+                                        PrincipalField::MemberOf,
+                                        PrincipalValue::String(name),
+                                    ));
+                                }
+                            }
+                        } else {
+                            updates.push(PrincipalUpdate::set(
+                                PrincipalField::MemberOf,
+                                PrincipalValue::StringList(vec![]),
+                            ));
+                        }
+                    } else {
+                        let mut names = Vec::new();
+                        for group_id in multi_values(&change.value) {
+                            names.push(self.group_name(&group_id).await?);
+                        }
+
+                        if change.op == PatchOp::Add {
+                            updates.extend(names.into_iter().map(|name| {
+                                PrincipalUpdate::add_item(
+                                    PrincipalField::MemberOf,
+                                    PrincipalValue::String(name),
+                                )
+                            }));
+                        } else {
+                            updates.push(PrincipalUpdate::set(
+                                PrincipalField::MemberOf,
+                                PrincipalValue::StringList(names),
+                            ));
+                        }
+                    }
+                }
+                // Attributes without a directory counterpart are ignored
+                _ => {}
+            }
+        }
+
+        Ok(updates)
+    }
+
+    async fn group_name(&self, id: &str) -> trc::Result<String> {
+        self.principal_name(id, &[Type::Group])
+            .await?
+            .ok_or_else(|| invalid_value(format!("Group {id:?} does not exist")))
+    }
+}
+
+/// The display name is taken from `displayName`, falling back to the
+/// components of the `name` attribute.
+fn display_name(body: &Value) -> Option<String> {
+    if let Some(display_name) = get_attribute(body, "displayName")
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.trim().is_empty())
+    {
+        return Some(display_name.trim().to_string());
+    }
+
+    let name = get_attribute(body, "name")?;
+    if let Some(formatted) = get_attribute(name, "formatted")
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.trim().is_empty())
+    {
+        return Some(formatted.trim().to_string());
+    }
+
+    let full_name = ["givenName", "familyName"]
+        .into_iter()
+        .filter_map(|part| get_attribute(name, part).and_then(|v| v.as_str()))
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| part.trim())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!full_name.is_empty()).then_some(full_name)
+}
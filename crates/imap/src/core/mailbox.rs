@@ -36,6 +36,12 @@ impl<T: SessionStream> SessionData<T> {
         access_token: Arc<AccessToken>,
         in_flight: Option<InFlight>,
     ) -> trc::Result<Self> {
+        // Session limits are resolved once and refreshed when the tenant
+        // changes its settings
+        let limits = session
+            .server
+            .session_limits(access_token.tenant.map(|t| t.id))
+            .await;
         let mut session = SessionData {
             stream_tx: session.stream_tx.clone(),
             server: session.server.clone(),
@@ -45,6 +51,7 @@ impl<T: SessionStream> SessionData<T> {
             state: access_token.state().into(),
             access_token,
             in_flight,
+            limits: Mutex::new(limits),
         };
         let access_token = session.access_token.clone();
 
@@ -14,6 +14,7 @@ use ahash::AHashMap;
 use common::{
     Inner, Server,
     auth::AccessToken,
+    config::overrides::SessionLimits,
     listener::{ServerInstance, SessionStream, limiter::InFlight},
 };
 
@@ -70,6 +71,7 @@ pub struct SessionData<T: SessionStream> {
     pub stream_tx: Arc<tokio::sync::Mutex<WriteHalf<T>>>,
     pub state: AtomicU32,
     pub in_flight: Option<InFlight>,
+    pub limits: parking_lot::Mutex<SessionLimits>,
 }
 
 pub struct SelectedMailbox {
@@ -218,6 +220,7 @@ impl<T: SessionStream> SessionData<T> {
             state: self.state,
             in_flight: self.in_flight,
             access_token: self.access_token,
+            limits: self.limits,
         }
     }
 }
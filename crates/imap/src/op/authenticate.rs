@@ -12,7 +12,7 @@ use common::{
     listener::{SessionStream, limiter::LimiterResult},
 };
 
-use directory::Permission;
+use directory::{AuthProtocol, Permission};
 use imap_proto::{
     Command, ResponseCode, StatusResponse,
     protocol::{authenticate::Mechanism, capability::Capability},
@@ -79,11 +79,10 @@ impl<T: SessionStream> Session<T> {
         // Authenticate
         let access_token = self
             .server
-            .authenticate(&AuthRequest::from_credentials(
-                credentials,
-                self.session_id,
-                self.remote_addr,
-            ))
+            .authenticate(
+                &AuthRequest::from_credentials(credentials, self.session_id, self.remote_addr)
+                    .with_protocol(AuthProtocol::Imap),
+            )
             .await
             .map_err(|err| {
                 if err.matches(trc::EventType::Auth(trc::AuthEvent::Failed)) {
@@ -95,6 +94,8 @@ impl<T: SessionStream> Session<T> {
                     } else {
                         return trc::AuthEvent::TooManyAttempts.into_err().caused_by(err);
                     }
+                } else if err.matches(trc::EventType::Auth(trc::AuthEvent::Maintenance)) {
+                    return err.id(tag.clone()).code(ResponseCode::Unavailable);
                 }
 
                 err.id(tag.clone())
@@ -45,6 +45,7 @@ use store::{
 };
 use types::{
     acl::Acl,
+    blob_hash::BlobHash,
     collection::{Collection, SyncCollection, VanishedCollection},
     field::EmailField,
     id::Id,
@@ -361,8 +362,7 @@ impl<T: SessionStream> SessionData<T> {
                 // Retrieve raw message if needed
                 raw_body = self
                     .server
-                    .blob_store()
-                    .get_blob(metadata.blob_hash.0.as_slice(), 0..usize::MAX)
+                    .get_blob(&BlobHash::from(&metadata.blob_hash), 0..usize::MAX)
                     .await
                     .imap_ctx(&arguments.tag, trc::location!())?;
 
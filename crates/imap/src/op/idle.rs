@@ -20,7 +20,10 @@ use imap_proto::{
     },
     receiver::Request,
 };
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use store::query::log::Query;
 use tokio::io::AsyncReadExt;
 use trc::AddContext;
@@ -55,6 +58,12 @@ impl<T: SessionStream> Session<T> {
             .await
             .imap_ctx(&request.tag, trc::location!())?;
 
+        // The idle timeout may be set by the tenant of the account
+        let mut limits = data.limits.lock().clone();
+        self.server.refresh_session_limits(&mut limits).await;
+        let timeout_idle = Duration::from_secs(limits.imap_idle_timeout);
+        *data.limits.lock() = limits;
+
         // Send continuation response
         self.write_bytes(b"+ Idling, send 'DONE' to stop.\r\n".to_vec())
             .await?;
@@ -69,7 +78,7 @@ impl<T: SessionStream> Session<T> {
         let mut buf = vec![0; 4];
         loop {
             tokio::select! {
-                result = tokio::time::timeout(self.server.core.imap.timeout_idle, self.stream_rx.read_exact(&mut buf)) => {
+                result = tokio::time::timeout(timeout_idle, self.stream_rx.read_exact(&mut buf)) => {
                     match result {
                         Ok(Ok(bytes_read)) => {
                             if bytes_read > 0 {
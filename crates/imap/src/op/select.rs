@@ -7,7 +7,7 @@
 use super::{ImapContext, ToModSeq};
 use crate::core::{SavedSearch, SelectedMailbox, Session, State};
 use common::listener::SessionStream;
-use directory::Permission;
+use directory::{Permission, backend::internal::activity::ActivityType};
 use imap_proto::{
     Command, ResponseCode, StatusResponse,
     protocol::{
@@ -105,6 +105,10 @@ impl<T: SessionStream> Session<T> {
                 }
             }
 
+            data.server
+                .record_activity(data.account_id, ActivityType::Mailbox)
+                .await;
+
             trc::event!(
                 Imap(trc::ImapEvent::Select),
                 SpanId = self.session_id,
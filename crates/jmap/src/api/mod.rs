@@ -101,6 +101,26 @@ impl ToRequestError for trc::Error {
                     RequestError::blank(402, "TOTP code required", cause.message())
                 }
                 trc::AuthEvent::TooManyAttempts => RequestError::too_many_auth_attempts(),
+                trc::AuthEvent::AccountLocked => {
+                    RequestError::blank(403, "Account locked", cause.message())
+                }
+                trc::AuthEvent::Maintenance => RequestError::blank(
+                    StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+                    "Temporarily unavailable",
+                    details,
+                ),
+                trc::AuthEvent::PendingActivation => {
+                    RequestError::blank(403, "Organization pending activation", cause.message())
+                }
+                trc::AuthEvent::ProtocolDisabled => {
+                    RequestError::blank(403, "Protocol not enabled", details)
+                }
+                trc::AuthEvent::ProtocolNotAllowed => {
+                    RequestError::blank(403, "Protocol not permitted", details)
+                }
+                trc::AuthEvent::PasswordExpired => {
+                    RequestError::blank(403, "Password expired", cause.message())
+                }
                 _ => RequestError::unauthorized(),
             },
             trc::EventType::Security(cause) => match cause {
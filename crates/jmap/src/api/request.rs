@@ -44,7 +44,8 @@ use crate::{
     thread::get::ThreadGet,
     vacation::{get::VacationResponseGet, set::VacationResponseSet},
 };
-use common::{Server, auth::AccessToken};
+use common::{Server, auth::AccessToken, config::overrides::TenantProtocol};
+use directory::backend::internal::activity::ActivityType;
 use http_proto::HttpSessionData;
 use jmap_proto::{
     request::{
@@ -84,6 +85,9 @@ impl RequestHandler for Server {
         access_token: Arc<AccessToken>,
         session: &HttpSessionData,
     ) -> Response<'x> {
+        self.record_activity(access_token.primary_id(), ActivityType::Mailbox)
+            .await;
+
         let add_created_ids = request.created_ids.is_some();
         let mut response = Response::new(
             access_token.state(),
@@ -434,6 +438,21 @@ impl RequestHandler for Server {
                 SetRequestMethod::EmailSubmission(mut req) => {
                     set_account_id_if_missing(&mut req.account_id, access_token);
                     access_token.assert_is_member(req.account_id)?;
+                    if !self
+                        .is_protocol_enabled(
+                            access_token.tenant.map(|t| t.id),
+                            TenantProtocol::Submission,
+                        )
+                        .await
+                    {
+                        return Err(trc::JmapEvent::Forbidden
+                            .into_err()
+                            .details("Protocol not enabled for your organization"));
+                    } else if !access_token.is_protocol_allowed(TenantProtocol::Submission) {
+                        return Err(trc::JmapEvent::Forbidden
+                            .into_err()
+                            .details("Protocol not permitted for this account"));
+                    }
 
                     self.email_submission_set(req, &session.instance, next_call)
                         .await?
@@ -4,7 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use common::{Server, auth::AccessToken};
+use common::{Server, auth::AccessToken, config::overrides::TenantProtocol};
 use directory::Permission;
 use jmap_proto::request::capability::{
     Account, Capabilities, Capability, EmptyCapabilities, Session,
@@ -32,6 +32,18 @@ impl SessionHandler for Server {
         session.set_state(access_token.state());
         let account_capabilities = &self.core.jmap.capabilities.account;
 
+        // Hide submission when the tenant disabled it or the account can't use it
+        let submission_enabled = access_token.is_protocol_allowed(TenantProtocol::Submission)
+            && self
+                .is_protocol_enabled(
+                    access_token.tenant.map(|t| t.id),
+                    TenantProtocol::Submission,
+                )
+                .await;
+        if !submission_enabled {
+            session.capabilities.remove(&Capability::Submission);
+        }
+
         // Set primary account
         session.username = access_token.name.to_string();
         let account_id = Id::from(access_token.primary_id());
@@ -41,7 +53,10 @@ impl SessionHandler for Server {
             is_read_only: false,
             account_capabilities: VecMap::with_capacity(account_capabilities.len()),
         };
-        for capability in access_token.account_capabilities() {
+        for capability in access_token
+            .account_capabilities()
+            .filter(|capability| submission_enabled || *capability != Capability::Submission)
+        {
             session.primary_accounts.append(capability, account_id);
             account.account_capabilities.append(
                 capability,
@@ -74,7 +89,10 @@ impl SessionHandler for Server {
                 is_read_only: false,
                 account_capabilities: VecMap::with_capacity(account_capabilities.len()),
             };
-            for capability in access_token.account_capabilities() {
+            for capability in access_token
+                .account_capabilities()
+                .filter(|capability| submission_enabled || *capability != Capability::Submission)
+            {
                 account.account_capabilities.append(
                     capability,
                     account_capabilities
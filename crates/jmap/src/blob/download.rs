@@ -9,9 +9,9 @@ use email::cache::MessageCacheFetch;
 use email::cache::email::MessageCacheAccess;
 use email::message::metadata::MessageMetadata;
 use groupware::cache::GroupwareCache;
+use std::future::Future;
 use store::ValueKey;
 use store::write::{AlignedBytes, Archive};
-use std::future::Future;
 use trc::AddContext;
 use types::acl::Acl;
 use types::blob::{BlobClass, BlobId};
@@ -47,8 +47,7 @@ impl BlobDownload for Server {
                     .caused_by(trc::location!())
             } else {
                 let blob = self
-                    .blob_store()
-                    .get_blob(blob_id.hash.as_slice(), 0..usize::MAX)
+                    .get_blob(&blob_id.hash, 0..usize::MAX)
                     .await
                     .caused_by(trc::location!());
                 match (&blob_id.class, blob) {
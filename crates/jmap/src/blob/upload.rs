@@ -7,7 +7,7 @@
 use std::sync::Arc;
 
 use super::{UploadResponse, download::BlobDownload};
-use common::{Server, auth::AccessToken};
+use common::{Server, auth::AccessToken, config::overrides::TenantSetting};
 use directory::Permission;
 use jmap_proto::{
     error::set::SetError,
@@ -52,6 +52,12 @@ impl BlobUpload for Server {
             not_created: Default::default(),
         };
         let account_id = request.account_id.document_id();
+        let upload_max_size = self
+            .tenant_setting(
+                access_token.tenant.map(|tenant| tenant.id),
+                TenantSetting::UploadMaxSize,
+            )
+            .await as usize;
 
         if request.create.len() > self.core.jmap.set_max_objects {
             return Err(trc::JmapEvent::RequestTooLarge.into_err());
@@ -116,9 +122,7 @@ impl BlobUpload for Server {
                                     }
                                 })
                         } else {
-                            self.blob_store()
-                                .get_blob(id.hash.as_slice(), offset..length)
-                                .await?
+                            self.get_blob(&id.hash, offset..length).await?
                         };
                         if let Some(bytes) = bytes {
                             bytes
@@ -142,14 +146,13 @@ impl BlobUpload for Server {
                     }
                 };
 
-                if bytes.len() + data.len() < self.core.jmap.upload_max_size {
+                if bytes.len() + data.len() < upload_max_size {
                     data.extend(bytes);
                 } else {
                     response.not_created.append(
                         create_id,
                         SetError::too_large().with_description(format!(
-                            "Upload size exceeds maximum of {} bytes.",
-                            self.core.jmap.upload_max_size
+                            "Upload size exceeds maximum of {upload_max_size} bytes."
                         )),
                     );
                     continue 'outer;
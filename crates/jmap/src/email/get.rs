@@ -190,10 +190,7 @@ impl EmailGet for Server {
             let raw_body;
             let mut raw_message = ChainedBytes::new(metadata.raw_headers.as_ref());
             if needs_body {
-                raw_body = self
-                    .blob_store()
-                    .get_blob(blob_hash.as_slice(), 0..usize::MAX)
-                    .await?;
+                raw_body = self.get_blob(&blob_hash, 0..usize::MAX).await?;
 
                 if let Some(raw_body) = &raw_body {
                     raw_message.append(
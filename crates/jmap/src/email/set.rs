@@ -11,7 +11,8 @@ use crate::{
     email::{PatchResult, handle_email_patch, ingested_into_object},
 };
 use common::{
-    Server, auth::AccessToken, ipc::PushNotification, storage::index::ObjectIndexBuilder,
+    Server, auth::AccessToken, config::overrides::TenantSetting, ipc::PushNotification,
+    storage::index::ObjectIndexBuilder,
 };
 use email::{
     cache::{MessageCacheFetch, email::MessageCacheAccess, mailbox::MailboxCacheAccess},
@@ -79,6 +80,12 @@ impl EmailSet for Server {
         let cache = self.get_cached_messages(account_id).await?;
         let mut response = SetResponse::from_request(&request, self.core.jmap.set_max_objects)?
             .with_state(cache.assert_state(false, &request.if_in_state)?);
+        let attachments_max_size = self
+            .tenant_setting(
+                access_token.tenant.map(|tenant| tenant.id),
+                TenantSetting::AttachmentMaxSize,
+            )
+            .await as usize;
 
         // Obtain mailboxIds
         let (can_add_mailbox_ids, can_delete_mailbox_ids, can_modify_mailbox_ids) =
@@ -613,17 +620,15 @@ impl EmailSet for Server {
                                 // Check attachment sizes
                                 if !is_multipart {
                                     size_attachments += parts.last().unwrap().size();
-                                    if self.core.jmap.mail_attachments_max_size > 0
-                                        && size_attachments
-                                            > self.core.jmap.mail_attachments_max_size
+                                    if attachments_max_size > 0
+                                        && size_attachments > attachments_max_size
                                     {
                                         response.not_created.append(
                                             id,
                                             SetError::invalid_properties()
                                                 .with_property(property)
                                                 .with_description(format!(
-                                                    "Message exceeds maximum size of {} bytes.",
-                                                    self.core.jmap.mail_attachments_max_size
+                                                    "Message exceeds maximum size of {attachments_max_size} bytes."
                                                 )),
                                         );
                                         continue 'create;
@@ -1145,6 +1150,7 @@ impl EmailSet for Server {
                     .emails_delete(
                         account_id,
                         access_token.tenant_id(),
+                        access_token.domain_id(account_id),
                         &mut batch,
                         destroy_ids,
                     )
@@ -28,7 +28,7 @@ use store::{
     write::{AlignedBytes, Archive},
 };
 use trc::AddContext;
-use types::{acl::Acl, collection::Collection, field::EmailField};
+use types::{acl::Acl, blob_hash::BlobHash, collection::Collection, field::EmailField};
 use utils::chained_bytes::ChainedBytes;
 
 pub trait EmailSearchSnippet: Sync + Send {
@@ -162,8 +162,7 @@ impl EmailSearchSnippet for Server {
 
             // Download message
             let raw_body = if let Some(raw_body) = self
-                .blob_store()
-                .get_blob(metadata.blob_hash.0.as_slice(), 0..usize::MAX)
+                .get_blob(&BlobHash::from(&metadata.blob_hash), 0..usize::MAX)
                 .await?
             {
                 raw_body
@@ -76,10 +76,8 @@ impl FileNodeSet for Server {
                                 )),
                             );
                             continue;
-                        } else if let Some(blob_contents) = self
-                            .blob_store()
-                            .get_blob(blob_id.hash.as_slice(), 0..usize::MAX)
-                            .await?
+                        } else if let Some(blob_contents) =
+                            self.get_blob(&blob_id.hash, 0..usize::MAX).await?
                         {
                             file_details.size = blob_contents.len() as u32;
                         } else {
@@ -222,10 +220,8 @@ impl FileNodeSet for Server {
                                 )),
                             );
                             continue;
-                        } else if let Some(blob_contents) = self
-                            .blob_store()
-                            .get_blob(blob_id.hash.as_slice(), 0..usize::MAX)
-                            .await?
+                        } else if let Some(blob_contents) =
+                            self.get_blob(&blob_id.hash, 0..usize::MAX).await?
                         {
                             file_details.size = blob_contents.len() as u32;
                         } else {
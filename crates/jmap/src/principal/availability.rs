@@ -30,7 +30,11 @@ use jmap_proto::{
 };
 use jmap_tools::{Key, Map, Value};
 use std::{collections::hash_map::Entry, future::Future};
-use store::{ValueKey, ahash::AHashMap, write::{AlignedBytes, Archive}};
+use store::{
+    ValueKey,
+    ahash::AHashMap,
+    write::{AlignedBytes, Archive},
+};
 use trc::AddContext;
 use types::{
     TimeRange,
@@ -101,7 +105,9 @@ impl PrincipalGetAvailability for Server {
 
             // Obtain shared ids
             let is_account_owner = principal_id == account_id;
-            let shared_ids = if !access_token.is_member(account_id) {
+            let shared_ids = if !access_token.is_member(account_id)
+                && !self.is_tenant_resource(access_token, account_id)
+            {
                 // Condition: The user has the "mayReadFreeBusy" permission for the calendar.
                 let shared_ids = resources.shared_items(
                     access_token,
@@ -5,6 +5,7 @@
  */
 
 use common::{Server, auth::AccessToken};
+use directory::backend::internal::manage::ManageDirectory;
 use jmap_proto::{
     method::get::{GetRequest, GetResponse},
     object::quota::{Quota, QuotaProperty, QuotaValue},
@@ -15,6 +16,9 @@ use std::{future::Future, sync::Arc};
 use trc::AddContext;
 use types::{id::Id, type_state::DataType};
 
+pub const ACCOUNT_QUOTA_ID: u32 = 0;
+pub const TENANT_QUOTA_ID: u32 = 1;
+
 pub trait QuotaGet: Sync + Send {
     fn quota_get(
         &self,
@@ -43,11 +47,26 @@ impl QuotaGet for Server {
             QuotaProperty::Types,
         ]);
         let account_id = request.account_id.document_id();
-        let quota_ids = if access_token.quota > 0 {
-            vec![0u32]
+        let access_token = if account_id == access_token.primary_id() {
+            AccessTokenRef::Borrowed(access_token)
         } else {
-            vec![]
+            AccessTokenRef::Owned(
+                self.get_access_token(account_id)
+                    .await
+                    .caused_by(trc::location!())?,
+            )
         };
+        let tenant = access_token
+            .as_ref()
+            .tenant
+            .filter(|tenant| tenant.quota > 0);
+        let mut quota_ids = Vec::with_capacity(2);
+        if access_token.as_ref().quota > 0 {
+            quota_ids.push(ACCOUNT_QUOTA_ID);
+        }
+        if tenant.is_some() {
+            quota_ids.push(TENANT_QUOTA_ID);
+        }
         let ids = if let Some(ids) = ids {
             ids
         } else {
@@ -55,37 +74,62 @@ impl QuotaGet for Server {
         };
         let mut response = GetResponse {
             account_id: request.account_id.into(),
-            state: State::Initial.into(),
-            list: Vec::with_capacity(ids.len()),
-            not_found: vec![],
-        };
-
-        let access_token = if account_id == access_token.primary_id() {
-            AccessTokenRef::Borrowed(access_token)
-        } else {
-            AccessTokenRef::Owned(
-                self.get_access_token(account_id)
+            state: State::Exact(
+                self.quota_state(access_token.as_ref())
                     .await
                     .caused_by(trc::location!())?,
             )
+            .into(),
+            list: Vec::with_capacity(ids.len()),
+            not_found: vec![],
         };
 
         for id in ids {
-            // Obtain the sieve script object
             let document_id = id.document_id();
             if !quota_ids.contains(&document_id) {
                 response.not_found.push(id);
                 continue;
             }
 
+            // The tenant quota is shared by all the accounts of the organization
+            let (quota_id, hard_limit, scope) = match tenant {
+                Some(tenant) if document_id == TENANT_QUOTA_ID => {
+                    (tenant.id, tenant.quota, "domain")
+                }
+                _ => (account_id, access_token.as_ref().quota, "account"),
+            };
+            let tenant_principal = if quota_id != account_id
+                && properties
+                    .iter()
+                    .any(|p| matches!(p, QuotaProperty::Name | QuotaProperty::Description))
+            {
+                self.store()
+                    .get_principal(quota_id)
+                    .await
+                    .caused_by(trc::location!())?
+            } else {
+                None
+            };
+
             let mut result = Map::with_capacity(properties.len());
             for property in &properties {
                 let value = match property {
                     QuotaProperty::Id => Value::Element(id.into()),
                     QuotaProperty::ResourceType => "octets".to_string().into(),
-                    QuotaProperty::Used => (self.get_used_quota(account_id).await? as u64).into(),
-                    QuotaProperty::HardLimit => access_token.as_ref().quota.into(),
-                    QuotaProperty::Scope => "account".to_string().into(),
+                    QuotaProperty::Used => {
+                        (self.get_used_quota(quota_id).await?.max(0) as u64).into()
+                    }
+                    QuotaProperty::HardLimit => hard_limit.into(),
+                    QuotaProperty::Scope => scope.to_string().into(),
+                    QuotaProperty::Name if quota_id != account_id => tenant_principal
+                        .as_ref()
+                        .map(|p| p.name().to_string())
+                        .into(),
+                    QuotaProperty::Description if quota_id != account_id => tenant_principal
+                        .as_ref()
+                        .and_then(|p| p.description())
+                        .map(|s| s.to_string())
+                        .into(),
                     QuotaProperty::Name => access_token.as_ref().name.to_string().into(),
                     QuotaProperty::Description => access_token
                         .as_ref()
@@ -13,6 +13,8 @@ use jmap_proto::{
 use std::future::Future;
 use types::id::Id;
 
+use super::get::{ACCOUNT_QUOTA_ID, TENANT_QUOTA_ID};
+
 pub trait QuotaQuery: Sync + Send {
     fn quota_query(
         &self,
@@ -27,17 +29,21 @@ impl QuotaQuery for Server {
         request: QueryRequest<Quota>,
         access_token: &AccessToken,
     ) -> trc::Result<QueryResponse> {
+        let mut ids = Vec::with_capacity(2);
+        if access_token.quota > 0 {
+            ids.push(Id::from(ACCOUNT_QUOTA_ID));
+        }
+        if access_token.tenant.is_some_and(|tenant| tenant.quota > 0) {
+            ids.push(Id::from(TENANT_QUOTA_ID));
+        }
+
         Ok(QueryResponse {
             account_id: request.account_id,
             query_state: State::Initial,
             can_calculate_changes: false,
             position: 0,
-            ids: if access_token.quota > 0 {
-                vec![Id::new(0)]
-            } else {
-                vec![]
-            },
-            total: Some(1),
+            total: Some(ids.len()),
+            ids,
             limit: None,
         })
     }
@@ -6,7 +6,7 @@
 
 use common::{
     Server,
-    config::smtp::queue::QueueName,
+    config::{overrides::TenantSetting, smtp::queue::QueueName},
     listener::{ServerInstance, stream::NullIo},
     storage::index::ObjectIndexBuilder,
 };
@@ -40,7 +40,7 @@ use store::{
     write::{AlignedBytes, Archive, BatchBuilder, now},
 };
 use trc::AddContext;
-use types::{collection::Collection, field::EmailField, id::Id};
+use types::{blob_hash::BlobHash, collection::Collection, field::EmailField, id::Id};
 use utils::{map::vec_map::VecMap, sanitize_email};
 
 pub trait EmailSubmissionSet: Sync + Send {
@@ -610,16 +610,24 @@ impl EmailSubmissionSet for Server {
         };
 
         // Obtain raw message
+        let access_token = self
+            .get_access_token(account_id)
+            .await
+            .caused_by(trc::location!())?;
+        let mail_max_size = self
+            .tenant_setting(
+                access_token.tenant.map(|tenant| tenant.id),
+                TenantSetting::EmailMaxSize,
+            )
+            .await as usize;
         let mut message = if let Some(message) = self
-            .blob_store()
-            .get_blob(metadata.blob_hash.0.as_slice(), 0..usize::MAX)
+            .get_blob(&BlobHash::from(&metadata.blob_hash), 0..usize::MAX)
             .await?
         {
-            if message.len() > self.core.jmap.mail_max_size {
+            if message.len() > mail_max_size {
                 return Ok(Err(SetError::new(SetErrorType::InvalidEmail)
                     .with_description(format!(
-                        "Message exceeds maximum size of {} bytes.",
-                        self.core.jmap.mail_max_size
+                        "Message exceeds maximum size of {mail_max_size} bytes."
                     ))));
             }
 
@@ -643,15 +651,7 @@ impl EmailSubmissionSet for Server {
         let mut session = Session::<NullIo>::local(
             self.clone(),
             instance.clone(),
-            SessionData::local(
-                self.get_access_token(account_id)
-                    .await
-                    .caused_by(trc::location!())?,
-                None,
-                vec![],
-                vec![],
-                0,
-            ),
+            SessionData::local(access_token, None, vec![], vec![], 0),
         );
 
         // Spawn SMTP session to avoid overflowing the stack
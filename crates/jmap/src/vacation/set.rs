@@ -16,12 +16,8 @@ use jmap_proto::{
     object::vacation_response::{self, VacationResponseProperty, VacationResponseValue},
     references::resolve::ResolveCreatedReference,
     request::IntoValid,
-    types::date::UTCDate,
 };
 use jmap_tools::{Key, Map, Value};
-use mail_builder::MessageBuilder;
-use mail_parser::decoders::html::html_to_text;
-use std::borrow::Cow;
 use std::future::Future;
 use store::{
     Serialize, SerializeInfallible, ValueKey,
@@ -367,102 +363,11 @@ impl VacationResponseSet for Server {
 
     fn build_script(&self, obj: &mut SieveScript) -> trc::Result<Vec<u8>> {
         // Build Sieve script
-        let mut script = Vec::with_capacity(1024);
-        script.extend_from_slice(b"require [\"vacation\", \"relational\", \"date\"];\r\n\r\n");
-        let mut num_blocks = 0;
-
-        // Add start date
-        if let Some(value) = obj.vacation_response.as_ref().and_then(|v| v.from_date) {
-            script.extend_from_slice(b"if currentdate :value \"ge\" \"iso8601\" \"");
-            script.extend_from_slice(UTCDate::from(value).to_string().as_bytes());
-            script.extend_from_slice(b"\" {\r\n");
-            num_blocks += 1;
-        }
-
-        // Add end date
-        if let Some(value) = obj.vacation_response.as_ref().and_then(|v| v.to_date) {
-            script.extend_from_slice(b"if currentdate :value \"le\" \"iso8601\" \"");
-            script.extend_from_slice(UTCDate::from(value).to_string().as_bytes());
-            script.extend_from_slice(b"\" {\r\n");
-            num_blocks += 1;
-        }
-
-        script.extend_from_slice(b"vacation :mime ");
-        if let Some(value) = obj
-            .vacation_response
-            .as_ref()
-            .and_then(|v| v.subject.as_ref())
-        {
-            script.extend_from_slice(b":subject \"");
-            for &ch in value.as_bytes().iter() {
-                match ch {
-                    b'\\' | b'\"' => {
-                        script.push(b'\\');
-                    }
-                    b'\r' | b'\n' => {
-                        continue;
-                    }
-                    _ => (),
-                }
-                script.push(ch);
-            }
-            script.extend_from_slice(b"\" ");
-        }
-
-        let mut text_body = if let Some(value) = obj
+        let mut script = obj
             .vacation_response
             .as_ref()
-            .and_then(|v| v.text_body.as_ref())
-        {
-            Cow::from(value.as_str()).into()
-        } else {
-            None
-        };
-        let html_body = if let Some(value) = obj
-            .vacation_response
-            .as_ref()
-            .and_then(|v| v.html_body.as_ref())
-        {
-            Cow::from(value.as_str()).into()
-        } else {
-            None
-        };
-        match (&html_body, &text_body) {
-            (Some(html_body), None) => {
-                text_body = Cow::from(html_to_text(html_body.as_ref())).into();
-            }
-            (None, None) => {
-                text_body = Cow::from("I am away.").into();
-            }
-            _ => (),
-        }
-
-        let mut builder = MessageBuilder::new();
-        let mut body_len = 0;
-        if let Some(html_body) = html_body {
-            body_len = html_body.len();
-            builder = builder.html_body(html_body);
-        }
-        if let Some(text_body) = text_body {
-            body_len += text_body.len();
-            builder = builder.text_body(text_body);
-        }
-        let mut message_body = Vec::with_capacity(body_len + 128);
-        builder.write_body(&mut message_body).ok();
-
-        script.push(b'\"');
-        for ch in message_body {
-            if [b'\\', b'\"'].contains(&ch) {
-                script.push(b'\\');
-            }
-            script.push(ch);
-        }
-        script.extend_from_slice(b"\";\r\n");
-
-        // Close blocks
-        for _ in 0..num_blocks {
-            script.extend_from_slice(b"}\r\n");
-        }
+            .map(|vacation| vacation.build_script(None, ""))
+            .unwrap_or_else(|| VacationResponse::default().build_script(None, ""));
 
         match self.core.sieve.untrusted_compiler.compile(&script) {
             Ok(compiled_script) => {
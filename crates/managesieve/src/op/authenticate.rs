@@ -12,7 +12,7 @@ use common::{
     listener::{SessionStream, limiter::LimiterResult},
 };
 
-use directory::Permission;
+use directory::{AuthProtocol, Permission};
 use imap_proto::{
     protocol::authenticate::Mechanism,
     receiver::{self, Request},
@@ -72,11 +72,10 @@ impl<T: SessionStream> Session<T> {
         // Authenticate
         let access_token = self
             .server
-            .authenticate(&AuthRequest::from_credentials(
-                credentials,
-                self.session_id,
-                self.remote_addr,
-            ))
+            .authenticate(
+                &AuthRequest::from_credentials(credentials, self.session_id, self.remote_addr)
+                    .with_protocol(AuthProtocol::ManageSieve),
+            )
             .await
             .map_err(|err| {
                 if err.matches(trc::EventType::Auth(trc::AuthEvent::Failed)) {
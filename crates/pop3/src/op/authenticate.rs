@@ -11,7 +11,7 @@ use common::{
     },
     listener::{SessionStream, limiter::LimiterResult},
 };
-use directory::Permission;
+use directory::{AuthProtocol, Permission, backend::internal::activity::ActivityType};
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 
@@ -68,11 +68,10 @@ impl<T: SessionStream> Session<T> {
         // Authenticate
         let access_token = self
             .server
-            .authenticate(&AuthRequest::from_credentials(
-                credentials,
-                self.session_id,
-                self.remote_addr,
-            ))
+            .authenticate(
+                &AuthRequest::from_credentials(credentials, self.session_id, self.remote_addr)
+                    .with_protocol(AuthProtocol::Pop3),
+            )
             .await
             .map_err(|err| {
                 if err.matches(trc::EventType::Auth(trc::AuthEvent::Failed)) {
@@ -111,6 +110,9 @@ impl<T: SessionStream> Session<T> {
 
         // Fetch mailbox
         let mailbox = self.fetch_mailbox(access_token.primary_id()).await?;
+        self.server
+            .record_activity(access_token.primary_id(), ActivityType::Mailbox)
+            .await;
 
         // Create session
         self.state = State::Authenticated {
@@ -92,6 +92,7 @@ impl<T: SessionStream> Session<T> {
                     .emails_delete(
                         mailbox.account_id,
                         self.state.access_token().tenant_id(),
+                        self.state.access_token().domain_id(mailbox.account_id),
                         &mut batch,
                         deleted,
                     )
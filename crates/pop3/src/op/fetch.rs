@@ -14,7 +14,7 @@ use store::{
     write::{AlignedBytes, Archive},
 };
 use trc::AddContext;
-use types::{collection::Collection, field::EmailField};
+use types::{blob_hash::BlobHash, collection::Collection, field::EmailField};
 use utils::chained_bytes::ChainedBytes;
 
 impl<T: SessionStream> Session<T> {
@@ -44,8 +44,7 @@ impl<T: SessionStream> Session<T> {
                     .caused_by(trc::location!())?;
                 if let Some(bytes) = self
                     .server
-                    .blob_store()
-                    .get_blob(metadata.blob_hash.0.as_slice(), 0..usize::MAX)
+                    .get_blob(&BlobHash::from(&metadata.blob_hash), 0..usize::MAX)
                     .await
                     .caused_by(trc::location!())?
                 {
@@ -93,6 +93,67 @@ impl BroadcastBatch<Vec<BroadcastEvent>> {
                 BroadcastEvent::ReloadSpamFilter => {
                     serialized.push(8u8);
                 }
+                BroadcastEvent::ReloadTenantSpamSettings => {
+                    serialized.push(9u8);
+                }
+                BroadcastEvent::ReloadTenantSieveScripts => {
+                    serialized.push(10u8);
+                }
+                BroadcastEvent::ReloadTenantRouting => {
+                    serialized.push(11u8);
+                }
+                BroadcastEvent::ReloadTenantBlobStores => {
+                    serialized.push(12u8);
+                }
+                BroadcastEvent::ReloadTenantDkimPolicies => {
+                    serialized.push(13u8);
+                }
+                BroadcastEvent::ReloadTenantArcSealers => {
+                    serialized.push(14u8);
+                }
+                BroadcastEvent::ReloadTenantSenderLists => {
+                    serialized.push(15u8);
+                }
+                BroadcastEvent::ReloadDomainRoutes => {
+                    serialized.push(16u8);
+                }
+                BroadcastEvent::ReloadForwardingRules => {
+                    serialized.push(17u8);
+                }
+                BroadcastEvent::ReloadBookableResources => {
+                    serialized.push(18u8);
+                }
+                BroadcastEvent::InvalidateTenantAccessTokens(tenant_id) => {
+                    serialized.push(19u8);
+                    let _ = serialized.write_leb128(tenant_id.map_or(0, |id| id as u64 + 1));
+                }
+                BroadcastEvent::ReloadTenantOverrides => {
+                    serialized.push(20u8);
+                }
+                BroadcastEvent::ReloadTenantMaintenance => {
+                    serialized.push(21u8);
+                }
+                BroadcastEvent::ReloadTenantActivation => {
+                    serialized.push(22u8);
+                }
+                BroadcastEvent::ReloadDisclaimers => {
+                    serialized.push(23u8);
+                }
+                BroadcastEvent::ReloadLoginPages => {
+                    serialized.push(24u8);
+                }
+                BroadcastEvent::ReloadTenantHostnames => {
+                    serialized.push(25u8);
+                }
+                BroadcastEvent::ReloadPasswordPolicies => {
+                    serialized.push(26u8);
+                }
+                BroadcastEvent::ReloadTenantJournaling => {
+                    serialized.push(27u8);
+                }
+                BroadcastEvent::ReloadDomainBimi => {
+                    serialized.push(28u8);
+                }
             }
         }
         serialized
@@ -191,6 +252,43 @@ where
 
                 8 => Ok(Some(BroadcastEvent::ReloadSpamFilter)),
 
+                9 => Ok(Some(BroadcastEvent::ReloadTenantSpamSettings)),
+
+                10 => Ok(Some(BroadcastEvent::ReloadTenantSieveScripts)),
+
+                11 => Ok(Some(BroadcastEvent::ReloadTenantRouting)),
+
+                12 => Ok(Some(BroadcastEvent::ReloadTenantBlobStores)),
+
+                13 => Ok(Some(BroadcastEvent::ReloadTenantDkimPolicies)),
+
+                14 => Ok(Some(BroadcastEvent::ReloadTenantArcSealers)),
+
+                15 => Ok(Some(BroadcastEvent::ReloadTenantSenderLists)),
+
+                16 => Ok(Some(BroadcastEvent::ReloadDomainRoutes)),
+
+                17 => Ok(Some(BroadcastEvent::ReloadForwardingRules)),
+
+                18 => Ok(Some(BroadcastEvent::ReloadBookableResources)),
+
+                19 => {
+                    let tenant_id = self.messages.next_leb128::<u64>().ok_or(())?;
+                    Ok(Some(BroadcastEvent::InvalidateTenantAccessTokens(
+                        tenant_id.checked_sub(1).map(|id| id as u32),
+                    )))
+                }
+
+                20 => Ok(Some(BroadcastEvent::ReloadTenantOverrides)),
+                21 => Ok(Some(BroadcastEvent::ReloadTenantMaintenance)),
+                22 => Ok(Some(BroadcastEvent::ReloadTenantActivation)),
+                23 => Ok(Some(BroadcastEvent::ReloadDisclaimers)),
+                24 => Ok(Some(BroadcastEvent::ReloadLoginPages)),
+                25 => Ok(Some(BroadcastEvent::ReloadTenantHostnames)),
+                26 => Ok(Some(BroadcastEvent::ReloadPasswordPolicies)),
+                27 => Ok(Some(BroadcastEvent::ReloadTenantJournaling)),
+                28 => Ok(Some(BroadcastEvent::ReloadDomainBimi)),
+
                 _ => Err(()),
             }
         } else {
@@ -143,6 +143,17 @@ pub fn spawn_broadcast_subscriber(inner: Arc<Inner>, mut shutdown_rx: watch::Rec
                                                     inner.cache.access_tokens.remove(id);
                                                 }
                                             }
+                                            BroadcastEvent::InvalidateTenantAccessTokens(tenant_id) => {
+                                                inner.cache.permissions.clear();
+                                                if let Some(tenant_id) = tenant_id {
+                                                    inner.cache.access_tokens.retain(|id, token| {
+                                                        *id != tenant_id
+                                                            && token.tenant.is_none_or(|t| t.id != tenant_id)
+                                                    });
+                                                } else {
+                                                    inner.cache.access_tokens.clear();
+                                                }
+                                            }
                                             BroadcastEvent::InvalidateGroupwareCache(ids) => {
                                                 for id in &ids {
                                                     inner.cache.files.remove(id);
@@ -197,6 +208,158 @@ pub fn spawn_broadcast_subscriber(inner: Arc<Inner>, mut shutdown_rx: watch::Rec
                                                     );
                                                 }
                                             }
+                                            BroadcastEvent::ReloadTenantSpamSettings => {
+                                                if let Err(err) = inner.build_server().reload_tenant_spam_settings().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant spam filter settings")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantSieveScripts => {
+                                                if let Err(err) = inner.build_server().reload_tenant_sieve_scripts().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant Sieve scripts")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantRouting => {
+                                                if let Err(err) = inner.build_server().reload_tenant_routing().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant outbound routing")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantBlobStores => {
+                                                if let Err(err) = inner.build_server().reload_tenant_blob_stores().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant blob stores")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantDkimPolicies => {
+                                                if let Err(err) = inner.build_server().reload_tenant_dkim_policies().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant DKIM policies")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantArcSealers => {
+                                                if let Err(err) = inner.build_server().reload_tenant_arc_sealers().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant ARC sealers")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantSenderLists => {
+                                                if let Err(err) = inner.build_server().reload_tenant_sender_lists().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant sender lists")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadDomainRoutes => {
+                                                if let Err(err) = inner.build_server().reload_domain_routes().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload domain routes")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadForwardingRules => {
+                                                if let Err(err) = inner.build_server().reload_forwarding_rules().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload forwarding rules")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadBookableResources => {
+                                                if let Err(err) = inner.build_server().reload_bookable_resources().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload bookable resources")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantOverrides => {
+                                                if let Err(err) = inner.build_server().reload_tenant_overrides().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant setting overrides")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantMaintenance => {
+                                                if let Err(err) = inner.build_server().reload_tenant_maintenance().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant maintenance")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantActivation => {
+                                                if let Err(err) = inner.build_server().reload_tenant_activation().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant activation")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadDisclaimers => {
+                                                if let Err(err) = inner.build_server().reload_disclaimers().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant disclaimers")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadLoginPages => {
+                                                if let Err(err) = inner.build_server().reload_login_pages().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload login pages")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantHostnames => {
+                                                if let Err(err) = inner.build_server().reload_tenant_hostnames().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant hostnames")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadPasswordPolicies => {
+                                                if let Err(err) = inner.build_server().reload_password_policies().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload password policies")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadTenantJournaling => {
+                                                if let Err(err) = inner.build_server().reload_tenant_journaling().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload tenant journaling")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
+                                            BroadcastEvent::ReloadDomainBimi => {
+                                                if let Err(err) = inner.build_server().reload_domain_bimi().await {
+                                                    trc::error!(
+                                                        err.details("Failed to reload domain BIMI settings")
+                                                            .caused_by(trc::location!())
+                                                    );
+                                                }
+                                            }
                                         }
                                     }
                                     Ok(None) => break,
@@ -261,6 +424,10 @@ fn log_event(event: &BroadcastEvent) -> trc::Value {
             }
             trc::Value::Array(array)
         }
+        BroadcastEvent::InvalidateTenantAccessTokens(tenant_id) => trc::Value::Array(vec![
+            "InvalidateTenantAccessTokens".into(),
+            (*tenant_id).into(),
+        ]),
         BroadcastEvent::InvalidateGroupwareCache(items) => {
             let mut array = Vec::with_capacity(items.len() + 1);
             array.push("InvalidateGroupwareCache".into());
@@ -273,5 +440,54 @@ fn log_event(event: &BroadcastEvent) -> trc::Value {
             trc::Value::Array(vec!["ReloadPushServers".into(), (*account_id).into()])
         }
         BroadcastEvent::ReloadSpamFilter => CompactString::const_new("ReloadSpamFilter").into(),
+        BroadcastEvent::ReloadTenantSpamSettings => {
+            CompactString::const_new("ReloadTenantSpamSettings").into()
+        }
+        BroadcastEvent::ReloadTenantSieveScripts => {
+            CompactString::const_new("ReloadTenantSieveScripts").into()
+        }
+        BroadcastEvent::ReloadTenantRouting => {
+            CompactString::const_new("ReloadTenantRouting").into()
+        }
+        BroadcastEvent::ReloadTenantBlobStores => {
+            CompactString::const_new("ReloadTenantBlobStores").into()
+        }
+        BroadcastEvent::ReloadTenantDkimPolicies => {
+            CompactString::const_new("ReloadTenantDkimPolicies").into()
+        }
+        BroadcastEvent::ReloadTenantArcSealers => {
+            CompactString::const_new("ReloadTenantArcSealers").into()
+        }
+        BroadcastEvent::ReloadTenantSenderLists => {
+            CompactString::const_new("ReloadTenantSenderLists").into()
+        }
+        BroadcastEvent::ReloadDomainRoutes => CompactString::const_new("ReloadDomainRoutes").into(),
+        BroadcastEvent::ReloadForwardingRules => {
+            CompactString::const_new("ReloadForwardingRules").into()
+        }
+        BroadcastEvent::ReloadBookableResources => {
+            CompactString::const_new("ReloadBookableResources").into()
+        }
+        BroadcastEvent::ReloadTenantOverrides => {
+            CompactString::const_new("ReloadTenantOverrides").into()
+        }
+        BroadcastEvent::ReloadTenantMaintenance => {
+            CompactString::const_new("ReloadTenantMaintenance").into()
+        }
+        BroadcastEvent::ReloadTenantActivation => {
+            CompactString::const_new("ReloadTenantActivation").into()
+        }
+        BroadcastEvent::ReloadDisclaimers => CompactString::const_new("ReloadDisclaimers").into(),
+        BroadcastEvent::ReloadLoginPages => CompactString::const_new("ReloadLoginPages").into(),
+        BroadcastEvent::ReloadTenantHostnames => {
+            CompactString::const_new("ReloadTenantHostnames").into()
+        }
+        BroadcastEvent::ReloadPasswordPolicies => {
+            CompactString::const_new("ReloadPasswordPolicies").into()
+        }
+        BroadcastEvent::ReloadTenantJournaling => {
+            CompactString::const_new("ReloadTenantJournaling").into()
+        }
+        BroadcastEvent::ReloadDomainBimi => CompactString::const_new("ReloadDomainBimi").into(),
     }
 }
@@ -0,0 +1,104 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::Server;
+use directory::{
+    QueryBy, Type,
+    backend::internal::manage::{ManageDirectory, TENANT_PRINCIPAL_TYPES},
+};
+use std::future::Future;
+use store::write::now;
+use trc::AddContext;
+
+pub trait OrganizationActivationPurge: Sync + Send {
+    fn purge_unverified_organizations(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl OrganizationActivationPurge for Server {
+    // Unverified organizations were never usable, so they are removed along
+    // with their principals without generating a deletion report.
+    async fn purge_unverified_organizations(&self) {
+        let now = now();
+        let role = &self.core.network.roles.purge_accounts;
+        let expired = self
+            .inner
+            .data
+            .tenant_activation
+            .load()
+            .iter()
+            .filter(|(tenant_id, activation)| {
+                activation.is_expired(now) && role.is_enabled_for_integer(**tenant_id)
+            })
+            .map(|(tenant_id, activation)| (*tenant_id, activation.contact.clone()))
+            .collect::<Vec<_>>();
+
+        for (tenant_id, contact) in expired {
+            match purge_organization(self, tenant_id).await {
+                Ok(name) => {
+                    trc::event!(
+                        Directory(trc::DirectoryEvent::OrganizationPurged),
+                        AccountName = contact,
+                        TenantId = tenant_id,
+                        Id = name,
+                    );
+                }
+                Err(err) => {
+                    trc::error!(
+                        err.details("Failed to purge unverified organization")
+                            .ctx(trc::Key::TenantId, tenant_id)
+                    );
+                }
+            }
+        }
+    }
+}
+
+async fn purge_organization(server: &Server, tenant_id: u32) -> trc::Result<Option<String>> {
+    let store = server.store();
+    let name = store
+        .get_principal_name(tenant_id)
+        .await
+        .caused_by(trc::location!())?;
+
+    if name.is_some() {
+        // Domains are removed last, once no principal uses their addresses
+        for typ in TENANT_PRINCIPAL_TYPES
+            .iter()
+            .copied()
+            .filter(|typ| *typ != Type::Domain)
+            .chain([Type::Domain])
+        {
+            for principal_id in store
+                .principal_ids(Some(typ), Some(tenant_id))
+                .await
+                .caused_by(trc::location!())?
+            {
+                destroy_principal(server, principal_id).await?;
+            }
+        }
+        destroy_principal(server, tenant_id).await?;
+    }
+
+    server.update_tenant_activation(tenant_id, None).await?;
+
+    Ok(name)
+}
+
+async fn destroy_principal(server: &Server, principal_id: u32) -> trc::Result<()> {
+    let changed_principals = server
+        .store()
+        .delete_principal(QueryBy::Id(principal_id))
+        .await
+        .caused_by(trc::location!())?;
+    server
+        .store()
+        .danger_destroy_account(principal_id)
+        .await
+        .caused_by(trc::location!())?;
+    server.invalidate_principal_caches(changed_principals).await;
+
+    Ok(())
+}
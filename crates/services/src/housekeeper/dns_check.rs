@@ -0,0 +1,85 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::Server;
+use directory::{Type, backend::internal::manage::ManageDirectory};
+use std::future::Future;
+use store::ahash::AHashSet;
+
+pub trait DomainDnsRevalidation: Sync + Send {
+    fn revalidate_domain_dns(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl DomainDnsRevalidation for Server {
+    // The results are cached on each node, so every node refreshes its own
+    // cache rather than only the one holding a housekeeping role.
+    async fn revalidate_domain_dns(&self) {
+        let domains = match self
+            .store()
+            .list_principals(None, None, &[Type::Domain], false, 0, 0)
+            .await
+        {
+            Ok(domains) => domains
+                .items
+                .into_iter()
+                .map(|domain| domain.name().to_string())
+                .collect::<AHashSet<_>>(),
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to list domains")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        };
+
+        // Forget the results of deleted domains
+        self.inner
+            .data
+            .dns_checks
+            .retain(|domain| domains.contains(domain));
+
+        // Flattened SPF records are refreshed by a single node, before the
+        // checks that report the changes
+        match self.spf_monitored_domains().await {
+            Ok(monitored) => {
+                for domain in monitored {
+                    // Monitors of deleted domains are removed
+                    let result = if !domains.contains(&domain) {
+                        self.set_spf_monitor(&domain, None).await
+                    } else if self
+                        .core
+                        .network
+                        .roles
+                        .spf_monitoring
+                        .is_enabled_for_hash(&domain)
+                    {
+                        self.refresh_spf_monitor(&domain).await.map(|_| ())
+                    } else {
+                        continue;
+                    };
+                    if let Err(err) = result {
+                        trc::error!(
+                            err.details("Failed to refresh flattened SPF record")
+                                .ctx(trc::Key::Domain, domain)
+                                .caused_by(trc::location!())
+                        );
+                    }
+                }
+            }
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to list monitored SPF records")
+                        .caused_by(trc::location!())
+                );
+            }
+        }
+
+        for domain in domains {
+            self.domain_dns_checks(&domain, true).await;
+        }
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::Server;
+use directory::backend::internal::manage::ManageDirectory;
+use mail_builder::{MessageBuilder, headers::HeaderType};
+use mail_parser::DateTime;
+use smtp::reporting::SmtpReporting;
+use std::future::Future;
+
+pub trait LockoutNotification: Sync + Send {
+    fn notify_lockout(
+        &self,
+        account_id: u32,
+        locked_until: Option<u64>,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl LockoutNotification for Server {
+    // Owners are only notified at their verified secondary addresses, as the
+    // primary mailbox may be the one under attack.
+    async fn notify_lockout(&self, account_id: u32, locked_until: Option<u64>) {
+        let principal = match self.store().get_principal(account_id).await {
+            Ok(Some(principal)) => principal,
+            Ok(None) => return,
+            Err(err) => {
+                trc::error!(
+                    err.account_id(account_id)
+                        .details("Failed to obtain locked account")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        };
+        let notify = match principal.tenant() {
+            Some(tenant_id) => self
+                .store()
+                .get_principal(tenant_id)
+                .await
+                .unwrap_or_default()
+                .and_then(|tenant| tenant.lockout_policy())
+                .is_some_and(|policy| policy.notify),
+            None => false,
+        };
+        let recipients = principal.secondary_emails().collect::<Vec<_>>();
+        if !notify || recipients.is_empty() {
+            return;
+        }
+
+        let (subject, body) = match locked_until {
+            Some(until) => (
+                "Your account has been locked",
+                format!(
+                    concat!(
+                        "The account {} has been locked after repeated failed login attempts.\r\n\r\n",
+                        "It will be unlocked automatically on {}, or earlier by an administrator.\r\n\r\n",
+                        "If these attempts were not made by you, consider changing your password.\r\n"
+                    ),
+                    principal.name(),
+                    DateTime::from_timestamp(until as i64).to_rfc822(),
+                ),
+            ),
+            None => (
+                "Your account has been unlocked",
+                format!(
+                    "The account {} has been unlocked by an administrator.\r\n",
+                    principal.name(),
+                ),
+            ),
+        };
+        let from = self.core.jmap.email_verification_from.as_str();
+        let message = MessageBuilder::new()
+            .from(from)
+            .to(recipients.clone())
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .subject(subject)
+            .text_body(body)
+            .write_to_vec()
+            .unwrap_or_default();
+
+        self.send_autogenerated(from, recipients.into_iter(), message, None, 0)
+            .await;
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    config::telemetry::Metering,
+    telemetry::metrics::{
+        metering::{MeteringReading, MeteringStore, SharedMeteringHistory},
+        tenant::TenantMetric,
+    },
+};
+use directory::{
+    Type,
+    backend::internal::{activity::PrincipalActivity, manage::ManageDirectory},
+};
+use std::{future::Future, time::Duration};
+use store::write::now;
+use trc::AddContext;
+
+pub trait TenantMetering: Sync + Send {
+    fn sample_tenant_metering(
+        &self,
+        metering: &Metering,
+        history: SharedMeteringHistory,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+
+    fn tenant_metering_reading(
+        &self,
+        tenant_id: u32,
+        day_start: u64,
+    ) -> impl Future<Output = trc::Result<MeteringReading>> + Send;
+}
+
+impl TenantMetering for Server {
+    // Every node samples its own counters, samples are aligned to the
+    // interval so the readings of all nodes can be combined on export.
+    async fn sample_tenant_metering(
+        &self,
+        metering: &Metering,
+        history: SharedMeteringHistory,
+    ) -> trc::Result<()> {
+        let node_id = self.core.network.node_id;
+        let interval = metering.interval.as_secs();
+        let now = now();
+        let timestamp = now - now % interval;
+
+        // After a restart, the gap since the last sample written by this node is filled
+        let mut last_sample = history.lock().last_sample;
+        if last_sample.is_none() {
+            last_sample = metering
+                .store
+                .metering_cursor(node_id)
+                .await
+                .caused_by(trc::location!())?;
+        }
+        if last_sample.is_some_and(|last_sample| last_sample >= timestamp) {
+            return Ok(());
+        }
+
+        let tenants = self
+            .store()
+            .list_principals(None, None, &[Type::Tenant], false, 0, 0)
+            .await
+            .caused_by(trc::location!())?;
+        let day_start = now - now % 86400;
+        let mut readings = Vec::with_capacity(tenants.items.len());
+        for tenant in tenants.items {
+            readings.push((
+                tenant.id(),
+                self.tenant_metering_reading(tenant.id(), day_start).await?,
+            ));
+        }
+
+        let samples = {
+            let mut history = history.lock();
+            if history.last_sample.is_none() {
+                history.last_sample = last_sample;
+            }
+            history.build_samples(node_id, interval, timestamp, readings)
+        };
+
+        metering
+            .store
+            .write_metering_samples(samples)
+            .await
+            .caused_by(trc::location!())
+    }
+
+    async fn tenant_metering_reading(
+        &self,
+        tenant_id: u32,
+        day_start: u64,
+    ) -> trc::Result<MeteringReading> {
+        let store = self.store();
+
+        // Users are active on a day once they authenticate or use a protocol
+        let mut active_users = 0;
+        for user in store
+            .list_principals(None, Some(tenant_id), &[Type::Individual], false, 0, 0)
+            .await
+            .caused_by(trc::location!())?
+            .items
+        {
+            if store
+                .last_active_at(user.id())
+                .await
+                .caused_by(trc::location!())?
+                .is_some_and(|timestamp| timestamp >= day_start)
+            {
+                active_users += 1;
+            }
+        }
+
+        let counters = self.inner.data.tenant_metrics.get(tenant_id);
+        let counter = |metric| counters.as_ref().map_or(0, |c| c.get(metric));
+
+        Ok(MeteringReading {
+            active_users,
+            storage_bytes: self.get_used_quota(tenant_id).await?.max(0) as u64,
+            messages_sent: counter(TenantMetric::MessagesSent),
+            messages_received: counter(TenantMetric::MessagesReceived),
+            bytes_sent: counter(TenantMetric::BytesSent),
+            bytes_received: counter(TenantMetric::BytesReceived),
+        })
+    }
+}
+
+/// Time left until the next sample, which is taken just after the start of
+/// each interval.
+pub fn metering_due(interval: Duration) -> Duration {
+    let interval = interval.as_secs().max(1);
+    Duration::from_secs(interval - now() % interval + 1)
+}
@@ -4,14 +4,26 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use activation::OrganizationActivationPurge;
 use common::{
     Inner, KV_LOCK_HOUSEKEEPER, LONG_1D_SLUMBER, Server,
     config::{spamfilter, telemetry::OtelMetrics},
     core::BuildServer,
     ipc::{BroadcastEvent, HousekeeperEvent, PurgeType},
+    telemetry::{
+        metrics::metering::{MeteringStore, SharedMeteringHistory},
+        tracers::audit::AuditStore,
+    },
 };
-use email::message::delete::EmailDeletion;
-use smtp::reporting::SmtpReporting;
+use directory::backend::internal::manage::ManageDirectory;
+use dns_check::DomainDnsRevalidation;
+use email::message::{delete::EmailDeletion, retention::EmailRetention};
+use lockout::LockoutNotification;
+use metering::{TenantMetering, metering_due};
+use recovery::RecoveryCodeNotification;
+use schedule::AccountScheduling;
+use security::SecurityNotification;
+use smtp::{queue::delivery_log::SmtpDeliveryLog, reporting::SmtpReporting};
 use spam_filter::modules::classifier::SpamClassifier;
 use std::{
     collections::BinaryHeap,
@@ -22,6 +34,16 @@ use std::{
 use store::{PurgeStore, write::now};
 use tokio::sync::mpsc;
 use trc::{Collector, MetricType, PurgeEvent};
+use trial::TenantTrials;
+
+pub mod activation;
+pub mod dns_check;
+pub mod lockout;
+pub mod metering;
+pub mod recovery;
+pub mod schedule;
+pub mod security;
+pub mod trial;
 
 // SPDX-SnippetBegin
 // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
@@ -42,10 +64,15 @@ struct Action {
 #[derive(PartialEq, Eq, Debug)]
 enum ActionClass {
     Account,
+    AccountSchedule,
+    Retention,
     Store(usize),
     Acme(String),
+    DomainCertificates(String),
+    DnsCheck,
     OtelMetrics,
     CalculateMetrics,
+    TenantMetering,
     // SPDX-SnippetBegin
     // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
     // SPDX-License-Identifier: LicenseRef-SEL
@@ -88,8 +115,16 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                     Instant::now() + server.core.jmap.account_purge_frequency.time_to_next(),
                     ActionClass::Account,
                 );
+                queue.schedule(
+                    Instant::now() + server.core.jmap.retention_frequency.time_to_next(),
+                    ActionClass::Retention,
+                );
+                queue.schedule(Instant::now(), ActionClass::AccountSchedule);
             }
 
+            // Domain DNS checks
+            queue.schedule(Instant::now(), ActionClass::DnsCheck);
+
             // Store purges
             if roles.purge_stores.is_enabled_or_sharded() {
                 for (idx, schedule) in server.core.storage.purge_schedules.iter().enumerate() {
@@ -136,6 +171,14 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
             // Calculate expensive metrics
             queue.schedule(Instant::now(), ActionClass::CalculateMetrics);
 
+            // Tenant metering samples
+            if let Some(metering) = &server.core.storage.metering {
+                queue.schedule(
+                    Instant::now() + metering_due(metering.interval),
+                    ActionClass::TenantMetering,
+                );
+            }
+
             // Add all ACME renewals to heap
             for provider in server.core.acme.providers.values() {
                 if roles.renew_acme.is_enabled_for_hash(&provider.id) {
@@ -155,6 +198,23 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                 }
             }
 
+            // Add all domain certificate renewals to heap
+            match server.init_all_domain_certificates().await {
+                Ok(domains) => {
+                    for (domain, due) in domains {
+                        if roles.renew_acme.is_enabled_for_hash(&domain) {
+                            queue.schedule(
+                                Instant::now() + due,
+                                ActionClass::DomainCertificates(domain),
+                            );
+                        }
+                    }
+                }
+                Err(err) => {
+                    trc::error!(err.details("Failed to initialize domain certificates."));
+                }
+            }
+
             // SPDX-SnippetBegin
             // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
             // SPDX-License-Identifier: LicenseRef-SEL
@@ -193,6 +253,7 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
         let metrics_history = SharedMetricHistory::default();
         // SPDX-SnippetEnd
 
+        let metering_history = SharedMeteringHistory::default();
         let mut next_metric_update = Instant::now();
 
         loop {
@@ -215,6 +276,16 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                 _ => {}
                             }
 
+                            // Schedule tenant metering
+                            if let Some(metering) = &server.core.storage.metering
+                                && !queue.has_action(&ActionClass::TenantMetering)
+                            {
+                                queue.schedule(
+                                    Instant::now() + metering_due(metering.interval),
+                                    ActionClass::TenantMetering,
+                                );
+                            }
+
                             // SPDX-SnippetBegin
                             // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
                             // SPDX-License-Identifier: LicenseRef-SEL
@@ -276,6 +347,37 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                         }
                                     };
                                 }
+
+                                // Reload domain certificates
+                                match server.init_all_domain_certificates().await {
+                                    Ok(domains) => {
+                                        for (domain, due) in domains {
+                                            if server
+                                                .core
+                                                .network
+                                                .roles
+                                                .renew_acme
+                                                .is_enabled_for_hash(&domain)
+                                            {
+                                                server
+                                                    .inner
+                                                    .ipc
+                                                    .housekeeper_tx
+                                                    .send(HousekeeperEvent::DomainCertificates {
+                                                        domain,
+                                                        due: Instant::now() + due,
+                                                    })
+                                                    .await
+                                                    .ok();
+                                            }
+                                        }
+                                    }
+                                    Err(err) => {
+                                        trc::error!(
+                                            err.details("Failed to reload domain certificates.")
+                                        );
+                                    }
+                                }
                             });
                         }
                         HousekeeperEvent::AcmeReschedule {
@@ -286,12 +388,49 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                             queue.remove_action(&action);
                             queue.schedule(renew_at, action);
                         }
+                        HousekeeperEvent::DomainCertificates { domain, due } => {
+                            let action = ActionClass::DomainCertificates(domain);
+                            queue.remove_action(&action);
+                            queue.schedule(due, action);
+                        }
                         HousekeeperEvent::Purge(purge) => {
                             let server = inner.build_server();
                             tokio::spawn(async move {
                                 server.purge(purge, 0).await;
                             });
                         }
+                        HousekeeperEvent::AccountLockout {
+                            account_id,
+                            locked_until,
+                        } => {
+                            let server = inner.build_server();
+                            tokio::spawn(async move {
+                                server.notify_lockout(account_id, locked_until).await;
+                            });
+                        }
+                        HousekeeperEvent::RecoveryCodeUsed {
+                            account_id,
+                            remaining,
+                        } => {
+                            let server = inner.build_server();
+                            tokio::spawn(async move {
+                                server
+                                    .send_recovery_code_notice(account_id, remaining)
+                                    .await;
+                            });
+                        }
+                        HousekeeperEvent::SecurityNotice {
+                            notice,
+                            account_id,
+                            details,
+                        } => {
+                            let server = inner.build_server();
+                            tokio::spawn(async move {
+                                server
+                                    .send_security_notice(notice, account_id, details)
+                                    .await;
+                            });
+                        }
                         HousekeeperEvent::Exit => {
                             trc::event!(
                                 Housekeeper(trc::HousekeeperEvent::Stop),
@@ -364,6 +503,40 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                     }
                                 });
                             }
+                            ActionClass::DomainCertificates(domain) => {
+                                trc::event!(
+                                    Housekeeper(trc::HousekeeperEvent::Run),
+                                    Type = "domain_certificates",
+                                    Domain = domain.clone()
+                                );
+
+                                let server = server.clone();
+                                tokio::spawn(async move {
+                                    let due = match server.renew_domain_certificates(&domain).await
+                                    {
+                                        Ok(Some(due)) => due,
+                                        Ok(None) => return,
+                                        Err(err) => {
+                                            trc::error!(
+                                                err.details("Failed to renew domain certificates.")
+                                            );
+
+                                            Duration::from_secs(3600)
+                                        }
+                                    };
+
+                                    server
+                                        .inner
+                                        .ipc
+                                        .housekeeper_tx
+                                        .send(HousekeeperEvent::DomainCertificates {
+                                            domain,
+                                            due: Instant::now() + due,
+                                        })
+                                        .await
+                                        .ok();
+                                });
+                            }
                             ActionClass::Account => {
                                 trc::event!(
                                     Housekeeper(trc::HousekeeperEvent::Run),
@@ -386,6 +559,55 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                             0,
                                         )
                                         .await;
+                                    server.purge(PurgeType::PrincipalCounts, 0).await;
+                                });
+                            }
+                            ActionClass::AccountSchedule => {
+                                trc::event!(
+                                    Housekeeper(trc::HousekeeperEvent::Run),
+                                    Type = "account_schedule"
+                                );
+
+                                let server = server.clone();
+                                queue.schedule(
+                                    Instant::now() + server.core.jmap.schedule_interval,
+                                    ActionClass::AccountSchedule,
+                                );
+                                tokio::spawn(async move {
+                                    server.apply_account_schedules().await;
+                                    server.purge_unverified_organizations().await;
+                                    server.apply_tenant_trials().await;
+                                });
+                            }
+                            ActionClass::DnsCheck => {
+                                trc::event!(
+                                    Housekeeper(trc::HousekeeperEvent::Run),
+                                    Type = "dns_check"
+                                );
+
+                                let server = server.clone();
+                                queue.schedule(
+                                    Instant::now() + server.core.jmap.dns_check_interval,
+                                    ActionClass::DnsCheck,
+                                );
+                                tokio::spawn(async move {
+                                    server.revalidate_domain_dns().await;
+                                });
+                            }
+                            ActionClass::Retention => {
+                                trc::event!(
+                                    Housekeeper(trc::HousekeeperEvent::Run),
+                                    Type = "retention"
+                                );
+
+                                let server = server.clone();
+                                queue.schedule(
+                                    Instant::now()
+                                        + server.core.jmap.retention_frequency.time_to_next(),
+                                    ActionClass::Retention,
+                                );
+                                tokio::spawn(async move {
+                                    server.purge(PurgeType::Retention, 0).await;
                                 });
                             }
                             ActionClass::Store(idx) => {
@@ -456,6 +678,32 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                     });
                                 }
                             }
+                            ActionClass::TenantMetering => {
+                                if let Some(metering) = server.core.storage.metering.clone() {
+                                    trc::event!(
+                                        Housekeeper(trc::HousekeeperEvent::Run),
+                                        Type = "tenant_metering"
+                                    );
+
+                                    queue.schedule(
+                                        Instant::now() + metering_due(metering.interval),
+                                        ActionClass::TenantMetering,
+                                    );
+
+                                    let server = server.clone();
+                                    let metering_history = metering_history.clone();
+                                    tokio::spawn(async move {
+                                        if let Err(err) = server
+                                            .sample_tenant_metering(&metering, metering_history)
+                                            .await
+                                        {
+                                            trc::error!(
+                                                err.details("Failed to write metering samples")
+                                            );
+                                        }
+                                    });
+                                }
+                            }
                             ActionClass::CalculateMetrics => {
                                 trc::event!(
                                     Housekeeper(trc::HousekeeperEvent::Run),
@@ -542,6 +790,14 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                         }
                                     }
 
+                                    // Tenant usage is cached for scrapes on every node
+                                    if server.core.metrics.tenants
+                                        && let Err(err) =
+                                            server.calculate_tenant_bytes_stored().await
+                                    {
+                                        trc::error!(err.details("Failed to obtain tenant usage"));
+                                    }
+
                                     match tokio::task::spawn_blocking(memory_stats::memory_stats)
                                         .await
                                     {
@@ -749,6 +1005,8 @@ impl Purge for Server {
             ),
             PurgeType::Lookup { .. } => ("in-memory-prefix", None),
             PurgeType::Account { .. } => ("account", None),
+            PurgeType::Retention => ("retention", vec![3u8].into()),
+            PurgeType::PrincipalCounts => ("principal-counts", vec![4u8].into()),
         };
         if let Some(lock_name) = &lock_name {
             match self
@@ -798,6 +1056,27 @@ impl Purge for Server {
                     trc::error!(err.details("Failed to purge data store"));
                 }
 
+                if let Some(audit_log) = &self.core.storage.audit
+                    && let Some(retention) = audit_log.retention
+                    && let Err(err) = audit_log.store.purge_audit_events(retention).await
+                {
+                    trc::error!(err.details("Failed to purge audit events"));
+                }
+
+                if let Some(metering) = &self.core.storage.metering
+                    && let Some(retention) = metering.retention
+                    && let Err(err) = metering.store.purge_metering_samples(retention).await
+                {
+                    trc::error!(err.details("Failed to purge metering samples"));
+                }
+
+                if let Some(delivery_log) = &self.core.smtp.queue.delivery_log
+                    && let Some(retention) = delivery_log.retention
+                    && let Err(err) = self.purge_delivery_logs(retention).await
+                {
+                    trc::error!(err.details("Failed to purge delivery logs"));
+                }
+
                 // SPDX-SnippetBegin
                 // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
                 // SPDX-License-Identifier: LicenseRef-SEL
@@ -830,7 +1109,10 @@ impl Purge for Server {
                 // SPDX-SnippetEnd
             }
             PurgeType::Blobs { store, blob_store } => {
-                if let Err(err) = store.purge_blobs(blob_store).await {
+                if let Err(err) = store
+                    .purge_blobs(blob_store, &self.core.storage.blobs)
+                    .await
+                {
                     trc::error!(err.details("Failed to purge blob store"));
                 }
             }
@@ -856,6 +1138,18 @@ impl Purge for Server {
                     self.purge_accounts(use_roles).await;
                 }
             }
+            PurgeType::Retention => {
+                self.apply_retention_policies().await;
+
+                if let Err(err) = self.purge_expired_forwarding_rules().await {
+                    trc::error!(err.details("Failed to purge expired forwarding rules"));
+                }
+            }
+            PurgeType::PrincipalCounts => {
+                if let Err(err) = self.store().repair_principal_counts().await {
+                    trc::error!(err.details("Failed to repair principal counters"));
+                }
+            }
         }
 
         trc::event!(
@@ -0,0 +1,67 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::Server;
+use directory::backend::internal::manage::ManageDirectory;
+use mail_builder::{MessageBuilder, headers::HeaderType};
+use smtp::reporting::SmtpReporting;
+use std::future::Future;
+
+pub trait RecoveryCodeNotification: Sync + Send {
+    fn send_recovery_code_notice(
+        &self,
+        account_id: u32,
+        remaining: usize,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl RecoveryCodeNotification for Server {
+    // As with lockouts, only the verified secondary addresses are notified
+    // since whoever used the code may also control the primary mailbox.
+    async fn send_recovery_code_notice(&self, account_id: u32, remaining: usize) {
+        if !self.core.oauth.recovery_code_notify {
+            return;
+        }
+        let principal = match self.store().get_principal(account_id).await {
+            Ok(Some(principal)) => principal,
+            Ok(None) => return,
+            Err(err) => {
+                trc::error!(
+                    err.account_id(account_id)
+                        .details("Failed to obtain account")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        };
+        let recipients = principal.secondary_emails().collect::<Vec<_>>();
+        if recipients.is_empty() {
+            return;
+        }
+
+        let body = format!(
+            concat!(
+                "A recovery code was used to log in to the account {}.\r\n\r\n",
+                "{} recovery codes are left. If this login was not made by you, ",
+                "change your password and generate new recovery codes.\r\n"
+            ),
+            principal.name(),
+            remaining,
+        );
+        let from = self.core.jmap.email_verification_from.as_str();
+        let message = MessageBuilder::new()
+            .from(from)
+            .to(recipients.clone())
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .subject("A recovery code was used")
+            .text_body(body)
+            .write_to_vec()
+            .unwrap_or_default();
+
+        self.send_autogenerated(from, recipients.into_iter(), message, None, 0)
+            .await;
+    }
+}
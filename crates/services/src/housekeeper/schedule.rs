@@ -0,0 +1,129 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::Server;
+use directory::{
+    Type,
+    backend::internal::{
+        PrincipalField, PrincipalUpdate, PrincipalValue,
+        manage::{ManageDirectory, UpdatePrincipal},
+        schedule::ScheduleNotification,
+    },
+};
+use std::future::Future;
+use store::write::now;
+use trc::AddContext;
+
+pub trait AccountScheduling: Sync + Send {
+    fn apply_account_schedules(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl AccountScheduling for Server {
+    // Scheduled events are already enforced when logging in, applying them
+    // here keeps the disabled permissions, and any listing filtering on them,
+    // in line with the effective state.
+    async fn apply_account_schedules(&self) {
+        let principals = match self
+            .store()
+            .list_principals(None, None, &[Type::Individual], true, 0, 0)
+            .await
+        {
+            Ok(principals) => principals,
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to list principals")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        };
+        let role = &self.core.network.roles.purge_accounts;
+
+        for principal in principals.items {
+            let schedule = principal.schedule();
+            if schedule.is_empty() || !role.is_enabled_for_integer(principal.id()) {
+                continue;
+            }
+            let now = now();
+
+            if let Some(disabled) = schedule.is_disabled_at(now) {
+                // Consume the events that are due
+                let changes = [
+                    (PrincipalField::DisableAt, schedule.disable_at),
+                    (PrincipalField::EnableAt, schedule.enable_at),
+                ]
+                .into_iter()
+                .filter(|(_, at)| at.is_some_and(|at| at <= now))
+                .map(|(field, _)| PrincipalUpdate::set(field, PrincipalValue::Integer(0)))
+                .collect();
+
+                match self
+                    .store()
+                    .update_principal(UpdatePrincipal::by_id(principal.id()).with_updates(changes))
+                    .await
+                    .caused_by(trc::location!())
+                {
+                    Ok(changed_principals) => {
+                        self.invalidate_principal_caches(changed_principals).await;
+
+                        if disabled {
+                            trc::event!(
+                                Directory(trc::DirectoryEvent::AccountDisabled),
+                                AccountName = principal.name().to_string(),
+                                AccountId = principal.id(),
+                                TenantId = principal.tenant(),
+                            );
+                        } else {
+                            trc::event!(
+                                Directory(trc::DirectoryEvent::AccountEnabled),
+                                AccountName = principal.name().to_string(),
+                                AccountId = principal.id(),
+                                TenantId = principal.tenant(),
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        trc::error!(
+                            err.account_id(principal.id())
+                                .details("Failed to apply account schedule")
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            // Give administrators notice of upcoming disables
+            if let Some(disable_at) = schedule
+                .disable_at
+                .filter(|at| *at > now && *at <= now + self.core.jmap.schedule_notify_before)
+            {
+                match self
+                    .store()
+                    .mark_disable_notified(principal.id(), disable_at)
+                    .await
+                {
+                    Ok(true) => {
+                        trc::event!(
+                            Directory(trc::DirectoryEvent::AccountDisablePending),
+                            AccountName = principal.name().to_string(),
+                            AccountId = principal.id(),
+                            TenantId = principal.tenant(),
+                            Expires = trc::Value::Timestamp(disable_at),
+                        );
+                    }
+                    Ok(false) => (),
+                    Err(err) => {
+                        trc::error!(
+                            err.account_id(principal.id())
+                                .details("Failed to record disable notice")
+                                .caused_by(trc::location!())
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
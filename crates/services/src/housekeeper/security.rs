@@ -0,0 +1,126 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, auth::notice::SecurityNotice, config::overrides::TenantSetting};
+use directory::{
+    ROLE_ADMIN, ROLE_TENANT_ADMIN, Type,
+    backend::internal::{manage::ManageDirectory, security::SecurityNotices},
+};
+use mail_builder::{MessageBuilder, headers::HeaderType};
+use smtp::reporting::SmtpReporting;
+use std::future::Future;
+
+pub trait SecurityNotification: Sync + Send {
+    fn send_security_notice(
+        &self,
+        notice: SecurityNotice,
+        account_id: u32,
+        details: Option<String>,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl SecurityNotification for Server {
+    // Notices are skipped when the tenant did not enable the class, when the
+    // account opted out or when the same notice was sent within the burst
+    // period, so that a script changing settings sends a single message.
+    async fn send_security_notice(
+        &self,
+        notice: SecurityNotice,
+        account_id: u32,
+        details: Option<String>,
+    ) {
+        let principal = match self.store().get_principal(account_id).await {
+            Ok(Some(principal)) => principal,
+            Ok(None) => return,
+            Err(err) => {
+                trc::error!(
+                    err.account_id(account_id)
+                        .details("Failed to obtain account")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        };
+        if principal.typ() != Type::Individual
+            || principal.security_notice_opt_out()
+            || self
+                .tenant_setting(principal.tenant(), TenantSetting::SecurityNotice(notice))
+                .await
+                == 0
+        {
+            return;
+        }
+
+        // Only accounts created with an administrator role are notified
+        if notice == SecurityNotice::AdminCreated {
+            match self.store().get_member_of(account_id).await {
+                Ok(member_of) => {
+                    if !member_of.iter().any(|member| {
+                        member.typ == Type::Role
+                            && matches!(member.principal_id, ROLE_ADMIN | ROLE_TENANT_ADMIN)
+                    }) {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    trc::error!(
+                        err.account_id(account_id)
+                            .details("Failed to obtain roles")
+                            .caused_by(trc::location!())
+                    );
+                    return;
+                }
+            }
+        }
+
+        let tenant = match principal.tenant() {
+            Some(tenant_id) => self
+                .store()
+                .get_principal(tenant_id)
+                .await
+                .unwrap_or_default(),
+            None => None,
+        };
+        let recipients = notice.recipients(&principal, tenant.as_ref());
+        if recipients.is_empty() {
+            return;
+        }
+
+        match self
+            .store()
+            .mark_security_notice(
+                account_id,
+                notice.id(),
+                self.core.oauth.security_notice_burst,
+            )
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(err) => {
+                trc::error!(
+                    err.account_id(account_id)
+                        .details("Failed to record security notice")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        }
+
+        let from = self.core.jmap.email_verification_from.as_str();
+        let message = MessageBuilder::new()
+            .from(from)
+            .to(recipients.clone())
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .subject(notice.subject())
+            .text_body(notice.body(principal.name(), details.as_deref()))
+            .write_to_vec()
+            .unwrap_or_default();
+
+        self.send_autogenerated(from, recipients.into_iter(), message, None, 0)
+            .await;
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, config::maintenance::TenantMaintenance};
+use directory::{
+    Type,
+    backend::internal::{
+        manage::ManageDirectory,
+        trial::{TrialNotification, trial_days_remaining},
+    },
+};
+use std::future::Future;
+use store::write::now;
+use trc::AddContext;
+
+pub trait TenantTrials: Sync + Send {
+    fn apply_tenant_trials(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl TenantTrials for Server {
+    // Lapsed trials are suspended with a maintenance window that has no end,
+    // which blocks logins and defers mail until the trial is extended or
+    // converted.
+    async fn apply_tenant_trials(&self) {
+        let tenants = match self
+            .store()
+            .list_principals(None, None, &[Type::Tenant], true, 0, 0)
+            .await
+        {
+            Ok(tenants) => tenants,
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to list tenants")
+                        .caused_by(trc::location!())
+                );
+                return;
+            }
+        };
+        let role = &self.core.network.roles.purge_accounts;
+
+        for tenant in tenants.items {
+            let Some(ends_at) = tenant.trial_ends_at() else {
+                continue;
+            };
+            if !role.is_enabled_for_integer(tenant.id()) {
+                continue;
+            }
+            let now = now();
+
+            if ends_at <= now {
+                if self
+                    .tenant_maintenance(tenant.id())
+                    .is_some_and(|maintenance| maintenance.trial_expired)
+                {
+                    continue;
+                }
+
+                match self
+                    .update_tenant_maintenance(
+                        tenant.id(),
+                        TenantMaintenance {
+                            started_at: now,
+                            ends_at: None,
+                            message: None,
+                            trial_expired: true,
+                        }
+                        .into(),
+                    )
+                    .await
+                {
+                    Ok(_) => {
+                        trc::event!(
+                            Directory(trc::DirectoryEvent::TrialExpired),
+                            TenantId = tenant.id(),
+                            Id = tenant.name().to_string(),
+                            To = tenant.alert_recipient().map(|to| to.to_string()),
+                            Expires = trc::Value::Timestamp(ends_at),
+                        );
+                    }
+                    Err(err) => {
+                        trc::error!(
+                            err.details("Failed to suspend tenant")
+                                .ctx(trc::Key::TenantId, tenant.id())
+                        );
+                    }
+                }
+                continue;
+            }
+
+            // Send the closest notice that is due, each one only once
+            let Some(notice) = self
+                .core
+                .jmap
+                .trial_notify_before
+                .iter()
+                .copied()
+                .find(|notice| ends_at <= now + notice)
+            else {
+                continue;
+            };
+            match self
+                .store()
+                .mark_trial_notified(tenant.id(), ends_at, notice)
+                .await
+            {
+                Ok(true) => {
+                    trc::event!(
+                        Directory(trc::DirectoryEvent::TrialExpiring),
+                        TenantId = tenant.id(),
+                        Id = tenant.name().to_string(),
+                        Details = format!("{} days remaining", trial_days_remaining(ends_at, now)),
+                        To = tenant.alert_recipient().map(|to| to.to_string()),
+                        Expires = trc::Value::Timestamp(ends_at),
+                    );
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    trc::error!(
+                        err.details("Failed to record trial notice")
+                            .ctx(trc::Key::TenantId, tenant.id())
+                            .caused_by(trc::location!())
+                    );
+                }
+            }
+        }
+    }
+}
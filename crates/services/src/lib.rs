@@ -7,12 +7,13 @@
 use broadcast::publisher::spawn_broadcast_publisher;
 use common::{
     Inner,
+    core::BuildServer,
     manager::boot::{BootManager, IpcReceivers},
 };
 use housekeeper::spawn_housekeeper;
 use state_manager::manager::spawn_push_router;
 use std::sync::Arc;
-use task_manager::spawn_task_manager;
+use task_manager::{reindex::ReindexJobTask, spawn_task_manager};
 
 pub mod broadcast;
 pub mod housekeeper;
@@ -62,6 +63,14 @@ impl SpawnServices for IpcReceivers {
             spawn_broadcast_publisher(inner.clone(), event_rx);
         }
 
+        // Resume interrupted reindex jobs
+        let server = inner.build_server();
+        tokio::spawn(async move {
+            if let Err(err) = server.resume_reindex_jobs().await {
+                trc::error!(err.details("Failed to resume reindex jobs"));
+            }
+        });
+
         // Spawn task manager
         spawn_task_manager(inner);
     }
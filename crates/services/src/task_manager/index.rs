@@ -7,7 +7,10 @@
 use crate::task_manager::{IndexAction, Task};
 use common::{Server, auth::AccessToken};
 use directory::{Type, backend::internal::manage::ManageDirectory};
-use email::{cache::MessageCacheFetch, message::metadata::MessageMetadata};
+use email::{
+    cache::MessageCacheFetch,
+    message::{legal_hold::EmailLegalHold, metadata::MessageMetadata},
+};
 use groupware::{cache::GroupwareCache, calendar::CalendarEvent, contact::ContactCard};
 use std::cmp::Ordering;
 use store::{
@@ -72,6 +75,7 @@ impl SearchIndexTask for Server {
         let mut document_insertions = Vec::new();
         let mut document_deletions: [AHashMap<u32, Vec<u32>>; NUM_INDEXES] =
             std::array::from_fn(|_| AHashMap::new());
+        let mut legal_holds = AHashMap::new();
 
         for task in tasks {
             if task.action.is_insert {
@@ -133,6 +137,7 @@ impl SearchIndexTask for Server {
                         if let Err(err) = delete_email_metadata(
                             self,
                             &mut batch,
+                            &mut legal_holds,
                             task.account_id,
                             task.document_id,
                         )
@@ -448,7 +453,7 @@ impl ReindexIndexTask for Server {
     }
 }
 
-async fn build_email_document(
+pub(crate) async fn build_email_document(
     server: &Server,
     account_id: u32,
     document_id: u32,
@@ -473,8 +478,7 @@ async fn build_email_document(
                 .caused_by(trc::location!())?;
 
             let raw_message = server
-                .blob_store()
-                .get_blob(metadata.blob_hash.0.as_slice(), 0..usize::MAX)
+                .get_blob(&BlobHash::from(&metadata.blob_hash), 0..usize::MAX)
                 .await
                 .caused_by(trc::location!())?
                 .ok_or_else(|| {
@@ -609,9 +613,30 @@ async fn build_tracing_span_document(
 async fn delete_email_metadata(
     server: &Server,
     batch: &mut BatchBuilder,
+    legal_holds: &mut AHashMap<u32, bool>,
     account_id: u32,
     document_id: u32,
 ) -> trc::Result<()> {
+    // Keep the metadata of accounts on legal hold until the hold is released
+    let is_legal_hold = match legal_holds.get(&account_id) {
+        Some(is_legal_hold) => *is_legal_hold,
+        None => {
+            let is_legal_hold = server.is_legal_hold(account_id).await?;
+            legal_holds.insert(account_id, is_legal_hold);
+            is_legal_hold
+        }
+    };
+    if is_legal_hold {
+        trc::event!(
+            TaskQueue(TaskQueueEvent::TaskIgnored),
+            Collection = SearchIndex::Email.name(),
+            Reason = "Account is on legal hold",
+            AccountId = account_id,
+            DocumentId = document_id,
+        );
+        return Ok(());
+    }
+
     match server
         .store()
         .get_value::<Archive<AlignedBytes>>(ValueKey::property(
@@ -0,0 +1,152 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    config::smtp::journal::{JournalEntry, JournalTarget},
+};
+use smtp::queue::{MessageSource, spool::SmtpSpool};
+use std::future::Future;
+use store::{
+    ValueKey,
+    write::{AlignedBytes, Archive, TaskEpoch, TaskQueueClass, ValueClass, now},
+};
+use trc::AddContext;
+
+pub trait JournalTask: Sync + Send {
+    fn deliver_journal_entry(
+        &self,
+        tenant_id: u32,
+        document_id: u32,
+        due: TaskEpoch,
+    ) -> impl Future<Output = bool> + Send;
+}
+
+impl JournalTask for Server {
+    async fn deliver_journal_entry(
+        &self,
+        tenant_id: u32,
+        document_id: u32,
+        due: TaskEpoch,
+    ) -> bool {
+        match deliver_journal_entry(self, tenant_id, document_id, due).await {
+            Ok(Some(target)) => {
+                trc::event!(
+                    TaskQueue(trc::TaskQueueEvent::JournalDelivered),
+                    TenantId = tenant_id,
+                    DocumentId = document_id,
+                    Details = target,
+                );
+                self.set_journal_delivered(tenant_id).await;
+                true
+            }
+            Ok(None) => true,
+            Err(err) => {
+                let reason = err
+                    .value_as_str(trc::Key::Details)
+                    .unwrap_or("Journal delivery failed")
+                    .to_string();
+                trc::event!(
+                    TaskQueue(trc::TaskQueueEvent::JournalFailed),
+                    TenantId = tenant_id,
+                    DocumentId = document_id,
+                    Reason = reason.clone(),
+                    CausedBy = err,
+                );
+                self.set_journal_failure(tenant_id, reason).await;
+                false
+            }
+        }
+    }
+}
+
+/// Delivers a journal entry to the target of its tenant, returning the
+/// target it was delivered to or `None` when there was nothing to deliver.
+async fn deliver_journal_entry(
+    server: &Server,
+    tenant_id: u32,
+    document_id: u32,
+    due: TaskEpoch,
+) -> trc::Result<Option<String>> {
+    let Some(archive) = server
+        .store()
+        .get_value::<Archive<AlignedBytes>>(ValueKey {
+            account_id: tenant_id,
+            collection: 0,
+            document_id,
+            class: ValueClass::TaskQueue(TaskQueueClass::Journal {
+                due,
+                is_payload: true,
+            }),
+        })
+        .await
+        .caused_by(trc::location!())?
+    else {
+        return Ok(None);
+    };
+
+    // Entries queued before journaling was disabled are discarded
+    let Some(journaling) = server.tenant_journaling(tenant_id) else {
+        return Ok(None);
+    };
+
+    let entry = archive
+        .deserialize::<JournalEntry>()
+        .caused_by(trc::location!())?;
+    let hostname = &server.core.network.server_name;
+    let from = format!("postmaster@{hostname}");
+
+    match &journaling.target {
+        JournalTarget::Smtp { address } => {
+            let envelope = entry.envelope(&from, address, hostname, now());
+
+            // Journal reports are sent with a null sender so that they are
+            // never bounced back, nor journaled again
+            let mut message = server.new_message("", 0);
+            message.add_recipient(address.as_str(), server).await;
+            if message
+                .queue(None, &envelope, 0, server, MessageSource::Autogenerated)
+                .await
+            {
+                Ok(Some(address.clone()))
+            } else {
+                Err(trc::StoreEvent::UnexpectedError
+                    .into_err()
+                    .details("Failed to queue journal report")
+                    .caused_by(trc::location!()))
+            }
+        }
+        JournalTarget::Bucket { store, key_prefix } => {
+            // Stores removed from the allowed list stop receiving entries
+            if !server.core.jmap.journal_blob_stores.contains(store) {
+                return Err(trc::StoreEvent::UnexpectedError
+                    .into_err()
+                    .details("Blob store is not available for journaling")
+                    .ctx(trc::Key::Id, store.clone())
+                    .caused_by(trc::location!()));
+            }
+
+            // Entries are kept under the tenant's root, whatever the prefix
+            let envelope = entry.envelope(&from, &from, hostname, now());
+            let name = format!(
+                "{}-{:x}-{document_id:x}.eml",
+                entry.received_at,
+                due.inner()
+            );
+            let key = match key_prefix {
+                Some(prefix) => format!("journal/{tenant_id}/{prefix}/{name}"),
+                None => format!("journal/{tenant_id}/{name}"),
+            };
+            server
+                .blob_store_by_id(store)?
+                .put_blob(key.as_bytes(), &envelope)
+                .await
+                .caused_by(trc::location!())?;
+
+            Ok(Some(key))
+        }
+    }
+}
@@ -206,6 +206,43 @@ impl TaskLock for Task<MergeThreadIds<AHashSet<u32>>> {
     }
 }
 
+impl TaskLock for Task<JournalAction> {
+    fn account_id(&self) -> u32 {
+        self.account_id
+    }
+
+    fn document_id(&self) -> u32 {
+        self.document_id
+    }
+
+    fn lock_key(&self) -> Vec<u8> {
+        KeySerializer::new((U32_LEN * 2) + U64_LEN + 1)
+            .write(5u8)
+            .write(self.due.inner())
+            .write_leb128(self.account_id)
+            .write_leb128(self.document_id)
+            .finalize()
+    }
+
+    fn lock_expiry(&self) -> u64 {
+        ALARM_EXPIRY
+    }
+
+    fn value_classes(&self) -> impl Iterator<Item = ValueClass> {
+        [
+            ValueClass::TaskQueue(TaskQueueClass::Journal {
+                due: self.due,
+                is_payload: false,
+            }),
+            ValueClass::TaskQueue(TaskQueueClass::Journal {
+                due: self.due,
+                is_payload: true,
+            }),
+        ]
+        .into_iter()
+    }
+}
+
 impl Task<TaskAction> {
     pub(crate) fn lock_expiry(&self) -> u64 {
         match &self.action {
@@ -260,6 +297,7 @@ impl Task<TaskAction> {
                     })
                 }
                 Some(4) => TaskAction::SendImip,
+                Some(10) => TaskAction::Journal,
                 Some(9) => {
                     TaskAction::MergeThreads(MergeThreadIds::deserialize(value).ok_or_else(
                         || trc::Error::corrupted_key(key, value.into(), trc::location!()),
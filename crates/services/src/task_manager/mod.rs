@@ -6,6 +6,7 @@
 
 use crate::task_manager::imip::SendImipTask;
 use crate::task_manager::index::SearchIndexTask;
+use crate::task_manager::journal::JournalTask;
 use crate::task_manager::lock::{TaskLock, TaskLockManager};
 use crate::task_manager::merge_threads::MergeThreadsTask;
 use alarm::SendAlarmTask;
@@ -40,8 +41,10 @@ use utils::snowflake::SnowflakeIdGenerator;
 pub mod alarm;
 pub mod imip;
 pub mod index;
+pub mod journal;
 pub mod lock;
 pub mod merge_threads;
+pub mod reindex;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Task<T> {
@@ -57,6 +60,7 @@ pub enum TaskAction {
     SendAlarm(CalendarAlarm),
     SendImip,
     MergeThreads(MergeThreadIds<AHashSet<u32>>),
+    Journal,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -68,6 +72,9 @@ pub struct IndexAction {
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub(crate) struct ImipAction;
 
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) struct JournalAction;
+
 const INDEX_EXPIRY: u64 = 60 * 5; // 5 minutes
 const ALARM_EXPIRY: u64 = 60 * 2; // 2 minutes
 const QUEUE_REFRESH_INTERVAL: u64 = 60 * 5; // 5 minutes
@@ -77,6 +84,7 @@ pub(crate) struct TaskManagerIpc {
     tx_alarm: mpsc::Sender<Task<CalendarAlarm>>,
     tx_imip: mpsc::Sender<Task<ImipAction>>,
     tx_threads: mpsc::Sender<Task<MergeThreadIds<AHashSet<u32>>>>,
+    tx_journal: mpsc::Sender<Task<JournalAction>>,
     locked: AHashMap<Vec<u8>, Locked>,
     revision: u64,
 }
@@ -93,6 +101,7 @@ pub fn spawn_task_manager(inner: Arc<Inner>) {
     let (tx_index_3, mut rx_index_3) = mpsc::channel::<Task<ImipAction>>(IPC_CHANNEL_BUFFER);
     let (tx_index_4, mut rx_index_4) =
         mpsc::channel::<Task<MergeThreadIds<AHashSet<u32>>>>(IPC_CHANNEL_BUFFER);
+    let (tx_index_5, mut rx_index_5) = mpsc::channel::<Task<JournalAction>>(IPC_CHANNEL_BUFFER);
 
     // Create dummy server instance for alarms
     let server_instance = Arc::new(ServerInstance {
@@ -294,12 +303,44 @@ pub fn spawn_task_manager(inner: Arc<Inner>) {
         });
     }
 
+    // Journal worker
+    {
+        let inner = inner.clone();
+        tokio::spawn(async move {
+            while let Some(task) = rx_index_5.recv().await {
+                let server = inner.build_server();
+
+                // Lock task
+                if server
+                    .try_lock_task(
+                        task.account_id,
+                        task.document_id,
+                        task.lock_key(),
+                        task.lock_expiry(),
+                    )
+                    .await
+                {
+                    let success = server
+                        .deliver_journal_entry(task.account_id, task.document_id, task.due)
+                        .await;
+
+                    // Remove entry from queue, failed entries are retried once
+                    // the lock expires
+                    if success {
+                        delete_tasks(&server, &[task]).await;
+                    }
+                }
+            }
+        });
+    }
+
     tokio::spawn(async move {
         let mut ipc = TaskManagerIpc {
             tx_fts: tx_index_1,
             tx_alarm: tx_index_2,
             tx_imip: tx_index_3,
             tx_threads: tx_index_4,
+            tx_journal: tx_index_5,
             locked: Default::default(),
             revision: 0,
         };
@@ -492,6 +533,25 @@ impl TaskQueueManager for Server {
                         );
                     }
                 }
+                TaskAction::Journal if roles.journaling.is_enabled_for_hash(&event) => {
+                    if ipc
+                        .tx_journal
+                        .send(Task {
+                            account_id: event.account_id,
+                            document_id: event.document_id,
+                            due: event.due,
+                            action: JournalAction,
+                        })
+                        .await
+                        .is_err()
+                    {
+                        trc::event!(
+                            Server(trc::ServerEvent::ThreadError),
+                            Details = "Error sending task.",
+                            CausedBy = trc::location!()
+                        );
+                    }
+                }
                 _ => {
                     trc::event!(
                         TaskQueue(TaskQueueEvent::TaskIgnored),
@@ -544,6 +604,7 @@ impl TaskAction {
             TaskAction::SendAlarm(_) => "SendAlarm",
             TaskAction::SendImip => "SendImip",
             TaskAction::MergeThreads(_) => "MergeThreads",
+            TaskAction::Journal => "Journal",
         }
     }
 }
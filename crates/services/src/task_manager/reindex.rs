@@ -0,0 +1,299 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::index::build_email_document;
+use common::{
+    Server,
+    storage::reindex::{ReindexAccount, ReindexCheckpoint, ReindexJob},
+};
+use email::cache::{MessageCacheFetch, email::MessageCacheAccess};
+use std::{future::Future, sync::Arc, time::Instant};
+use store::{
+    Deserialize, IterateParams, Serialize, ValueKey,
+    roaring::RoaringBitmap,
+    write::{AlignedBytes, Archive, Archiver, BatchBuilder, now},
+};
+use tokio::sync::{Mutex, Semaphore};
+use trc::{AddContext, TaskQueueEvent};
+use types::{collection::Collection, field::PrincipalField};
+
+pub const DEFAULT_REINDEX_CONCURRENCY: usize = 2;
+pub const MAX_REINDEX_CONCURRENCY: usize = 8;
+
+// Checkpoints are stored under the directory account using the job owner as
+// document id, so that interrupted jobs can be found on startup.
+const CHECKPOINT_ACCOUNT_ID: u32 = u32::MAX;
+
+pub trait ReindexJobTask: Sync + Send {
+    fn start_reindex_job(
+        &self,
+        owner_id: u32,
+        account_ids: RoaringBitmap,
+        concurrency: usize,
+    ) -> impl Future<Output = trc::Result<Option<Arc<ReindexJob>>>> + Send;
+
+    fn resume_reindex_jobs(&self) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl ReindexJobTask for Server {
+    async fn start_reindex_job(
+        &self,
+        owner_id: u32,
+        account_ids: RoaringBitmap,
+        concurrency: usize,
+    ) -> trc::Result<Option<Arc<ReindexJob>>> {
+        let checkpoint = ReindexCheckpoint {
+            id: self.inner.data.jmap_id_gen.generate(),
+            owner_id,
+            started: now(),
+            concurrency: concurrency.clamp(1, MAX_REINDEX_CONCURRENCY) as u32,
+            total_accounts: account_ids.len() as u32,
+            pending: account_ids
+                .iter()
+                .map(|account_id| ReindexAccount {
+                    account_id,
+                    next_document_id: 0,
+                })
+                .collect(),
+            indexed: 0,
+            failed: 0,
+        };
+        let Some(job) = self.inner.data.reindex_jobs.start(checkpoint.clone()) else {
+            return Ok(None);
+        };
+
+        if let Err(err) = store_checkpoint(self, &checkpoint).await {
+            job.fail(error_details(&err));
+            return Err(err);
+        }
+
+        let server = self.clone();
+        let job_ = job.clone();
+        tokio::spawn(async move {
+            run_reindex_job(server, job_).await;
+        });
+
+        Ok(Some(job))
+    }
+
+    async fn resume_reindex_jobs(&self) -> trc::Result<()> {
+        let mut checkpoints = Vec::new();
+        self.store()
+            .iterate(
+                IterateParams::new(
+                    ValueKey::property(
+                        CHECKPOINT_ACCOUNT_ID,
+                        Collection::Principal,
+                        0,
+                        PrincipalField::Reindex,
+                    ),
+                    ValueKey::property(
+                        CHECKPOINT_ACCOUNT_ID,
+                        Collection::Principal,
+                        u32::MAX,
+                        PrincipalField::Reindex,
+                    ),
+                ),
+                |_, value| {
+                    checkpoints.push(
+                        <Archive<AlignedBytes> as Deserialize>::deserialize(value)?
+                            .deserialize::<ReindexCheckpoint>()?,
+                    );
+
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        for checkpoint in checkpoints {
+            if let Some(job) = self.inner.data.reindex_jobs.start(checkpoint) {
+                let server = self.clone();
+                tokio::spawn(async move {
+                    run_reindex_job(server, job).await;
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn run_reindex_job(server: Server, job: Arc<ReindexJob>) {
+    let checkpoint = job.checkpoint();
+    let semaphore = Arc::new(Semaphore::new(checkpoint.concurrency.max(1) as usize));
+    let persist_lock = Arc::new(Mutex::new(()));
+    let start_time = Instant::now();
+
+    let mut tasks = Vec::with_capacity(checkpoint.pending.len());
+    for account in checkpoint.pending {
+        let server = server.clone();
+        let job = job.clone();
+        let semaphore = semaphore.clone();
+        let persist_lock = persist_lock.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Ok(_permit) = semaphore.acquire_owned().await {
+                reindex_account(&server, &job, &persist_lock, account).await
+            } else {
+                Ok(())
+            }
+        }));
+    }
+
+    let mut result = Ok(());
+    for task in tasks {
+        if let Ok(Err(err)) = task.await
+            && result.is_ok()
+        {
+            result = Err(err);
+        }
+    }
+
+    // Remove the checkpoint once every account has been reindexed
+    if result.is_ok() {
+        result = clear_checkpoint(&server, job.owner_id).await;
+    }
+
+    let summary = job.summary();
+    match result {
+        Ok(()) => {
+            job.complete();
+
+            trc::event!(
+                TaskQueue(TaskQueueEvent::ReindexCompleted),
+                AccountId = job.owner_id,
+                Id = summary.id,
+                Total = summary.indexed,
+                Details = summary.failed,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+        Err(err) => {
+            job.fail(error_details(&err));
+
+            trc::event!(
+                TaskQueue(TaskQueueEvent::ReindexFailed),
+                AccountId = job.owner_id,
+                Id = summary.id,
+                CausedBy = err,
+                Elapsed = start_time.elapsed(),
+            );
+        }
+    }
+}
+
+async fn reindex_account(
+    server: &Server,
+    job: &ReindexJob,
+    persist_lock: &Mutex<()>,
+    account: ReindexAccount,
+) -> trc::Result<()> {
+    let account_id = account.account_id;
+    let document_ids = server
+        .get_cached_messages(account_id)
+        .await
+        .caused_by(trc::location!())?
+        .email_document_ids();
+    let batch_size = server.core.jmap.index_batch_size.max(1);
+
+    let mut document_ids = document_ids
+        .into_iter()
+        .filter(|document_id| *document_id >= account.next_document_id)
+        .peekable();
+    while document_ids.peek().is_some() {
+        let mut documents = Vec::with_capacity(batch_size);
+        let mut failed = 0;
+        let mut next_document_id = account.next_document_id;
+        for document_id in document_ids.by_ref().take(batch_size) {
+            next_document_id = document_id + 1;
+            match build_email_document(server, account_id, document_id).await {
+                Ok(Some(document)) => documents.push(document),
+                Ok(None) => (),
+                Err(err) => {
+                    trc::error!(
+                        err.account_id(account_id)
+                            .document_id(document_id)
+                            .caused_by(trc::location!())
+                            .details("Failed to build document for reindexing")
+                    );
+                    failed += 1;
+                }
+            }
+        }
+
+        // Documents are replaced in place, so searches keep using the existing
+        // index entries until each message has been reindexed
+        let mut indexed = documents.len() as u64;
+        if !documents.is_empty()
+            && let Err(err) = server.search_store().index(documents).await
+        {
+            trc::error!(
+                err.account_id(account_id)
+                    .caused_by(trc::location!())
+                    .details("Failed to reindex documents")
+            );
+            failed += indexed;
+            indexed = 0;
+        }
+
+        job.record_batch(account_id, next_document_id, indexed, failed);
+        persist_checkpoint(server, job, persist_lock).await?;
+    }
+
+    job.complete_account(account_id);
+    persist_checkpoint(server, job, persist_lock).await
+}
+
+async fn persist_checkpoint(
+    server: &Server,
+    job: &ReindexJob,
+    persist_lock: &Mutex<()>,
+) -> trc::Result<()> {
+    let _lock = persist_lock.lock().await;
+    store_checkpoint(server, &job.checkpoint()).await
+}
+
+async fn store_checkpoint(server: &Server, checkpoint: &ReindexCheckpoint) -> trc::Result<()> {
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(CHECKPOINT_ACCOUNT_ID)
+        .with_collection(Collection::Principal)
+        .with_document(checkpoint.owner_id)
+        .set(
+            PrincipalField::Reindex,
+            Archiver::new(checkpoint.clone())
+                .serialize()
+                .caused_by(trc::location!())?,
+        );
+    server
+        .store()
+        .write(batch.build_all())
+        .await
+        .caused_by(trc::location!())
+        .map(|_| ())
+}
+
+async fn clear_checkpoint(server: &Server, owner_id: u32) -> trc::Result<()> {
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(CHECKPOINT_ACCOUNT_ID)
+        .with_collection(Collection::Principal)
+        .with_document(owner_id)
+        .clear(PrincipalField::Reindex);
+    server
+        .store()
+        .write(batch.build_all())
+        .await
+        .caused_by(trc::location!())
+        .map(|_| ())
+}
+
+fn error_details(err: &trc::Error) -> String {
+    err.value_as_str(trc::Key::Details)
+        .or_else(|| err.value_as_str(trc::Key::Reason))
+        .map(|details| details.to_string())
+        .unwrap_or_else(|| err.event_type().description().to_string())
+}
@@ -5,7 +5,7 @@
  */
 
 use common::{
-    KV_RATE_LIMIT_SMTP, ThrottleKey,
+    KV_RATE_LIMIT_SENDER, KV_RATE_LIMIT_SMTP, ThrottleKey,
     config::smtp::*,
     expr::{functions::ResolveVariable, *},
     listener::SessionStream,
@@ -209,6 +209,50 @@ impl<T: SessionStream> Session<T> {
         true
     }
 
+    /// Applies the message rate of a send-only credential, other accounts
+    /// are only subject to the configured throttles.
+    pub async fn is_send_rate_allowed(&self) -> bool {
+        let Some((account_id, rate)) = self.data.authenticated_as.as_ref().and_then(|token| {
+            token
+                .send_rate
+                .as_ref()
+                .map(|rate| (token.primary_id(), rate))
+        }) else {
+            return true;
+        };
+
+        match self
+            .server
+            .core
+            .storage
+            .lookup
+            .is_rate_allowed(KV_RATE_LIMIT_SENDER, &account_id.to_be_bytes(), rate, false)
+            .await
+        {
+            Ok(None) => true,
+            Ok(Some(_)) => {
+                trc::event!(
+                    Smtp(SmtpEvent::RateLimitExceeded),
+                    SpanId = self.data.session_id,
+                    AccountId = account_id,
+                    Limit = vec![
+                        trc::Value::from(rate.requests),
+                        trc::Value::from(rate.period)
+                    ],
+                );
+
+                false
+            }
+            Err(err) => {
+                trc::error!(
+                    err.span_id(self.data.session_id)
+                        .caused_by(trc::location!())
+                );
+                true
+            }
+        }
+    }
+
     pub async fn throttle_rcpt(&self, rcpt: &str, rate: &Rate, ctx: &str) -> bool {
         let mut hasher = blake3::Hasher::new();
         hasher.update(rcpt.as_bytes());
@@ -12,7 +12,7 @@ use common::{
     listener::SessionStream,
 };
 
-use directory::Permission;
+use directory::{AuthProtocol, Permission};
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use smtp_proto::{AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_XOAUTH2, IntoString};
@@ -109,7 +109,8 @@ impl<T: SessionStream> Session<T> {
                         self.data.session_id,
                         self.data.remote_ip,
                     )
-                    .with_directory(directory),
+                    .with_directory(directory)
+                    .with_protocol(AuthProtocol::Smtp),
                 )
                 .await
                 .and_then(|access_token| {
@@ -128,6 +129,7 @@ impl<T: SessionStream> Session<T> {
                 }
                 Err(err) => {
                     let reason = *err.as_ref();
+                    let details = err.value_as_str(trc::Key::Details).map(|d| d.to_string());
 
                     trc::error!(err.span_id(self.data.session_id));
 
@@ -137,6 +139,50 @@ impl<T: SessionStream> Session<T> {
                                 .auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
                                 .await;
                         }
+                        trc::EventType::Auth(trc::AuthEvent::AccountLocked) => {
+                            return self
+                                .auth_error(b"535 5.7.8 Account temporarily locked.\r\n")
+                                .await;
+                        }
+                        trc::EventType::Auth(trc::AuthEvent::Maintenance) => {
+                            return self
+                                .auth_error(
+                                    format!(
+                                        "454 4.7.0 {}\r\n",
+                                        details
+                                            .as_deref()
+                                            .unwrap_or(trc::AuthEvent::Maintenance.message())
+                                    )
+                                    .as_bytes(),
+                                )
+                                .await;
+                        }
+                        trc::EventType::Auth(trc::AuthEvent::PendingActivation) => {
+                            return self
+                                .auth_error(b"535 5.7.8 Organization pending activation.\r\n")
+                                .await;
+                        }
+                        trc::EventType::Auth(trc::AuthEvent::ProtocolDisabled) => {
+                            return self
+                                .auth_error(
+                                    b"535 5.7.8 Protocol not enabled for your organization.\r\n",
+                                )
+                                .await;
+                        }
+                        trc::EventType::Auth(trc::AuthEvent::PasswordExpired) => {
+                            return self
+                                .auth_error(
+                                    b"535 5.7.8 Password expired, please change your password.\r\n",
+                                )
+                                .await;
+                        }
+                        trc::EventType::Auth(trc::AuthEvent::ProtocolNotAllowed) => {
+                            return self
+                                .auth_error(
+                                    b"535 5.7.8 Protocol not permitted for this account.\r\n",
+                                )
+                                .await;
+                        }
                         trc::EventType::Auth(trc::AuthEvent::TokenExpired) => {
                             return self.auth_error(b"535 5.7.8 OAuth token expired.\r\n").await;
                         }
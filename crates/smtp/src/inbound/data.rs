@@ -15,10 +15,13 @@ use crate::{
     reporting::analysis::AnalyzeReport,
     scripts::ScriptResult,
 };
+use ahash::AHashMap;
 use common::{
     config::{
         smtp::{
             auth::VerifyStrategy,
+            disclaimer::{DisclaimerVariables, DomainDisclaimer},
+            journal::{JournalDirection, JournalEntry},
             queue::{QueueExpiry, QueueName},
             session::Stage,
         },
@@ -28,6 +31,7 @@ use common::{
     psl,
     scripts::ScriptModification,
 };
+use directory::Type;
 use mail_auth::{
     AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcResult, ReceivedSpf,
     common::{headers::HeaderWriter, verify::VerifySignature},
@@ -43,6 +47,7 @@ use std::{
     borrow::Cow,
     time::{Instant, SystemTime},
 };
+use store::write::now;
 use trc::SmtpEvent;
 use utils::{DomainPart, config::Rate};
 
@@ -65,6 +70,29 @@ impl<T: SessionStream> Session<T> {
             }
         };
 
+        // Send-only credentials can't use another address in the From header
+        if let Some(token) = self
+            .data
+            .authenticated_as
+            .as_ref()
+            .filter(|token| token.sender_address.is_some())
+            && !parsed_message.from().is_some_and(|from| {
+                from.iter().all(|addr| {
+                    addr.address()
+                        .is_some_and(|address| token.is_sender_allowed(&address.to_lowercase()))
+                })
+            })
+        {
+            trc::event!(
+                Smtp(SmtpEvent::MailFromUnauthorized),
+                SpanId = self.data.session_id,
+                AccountId = token.primary_id(),
+                Details = token.sender_address.clone(),
+            );
+
+            return (&b"550 5.7.1 From header not allowed for this credential.\r\n"[..]).into();
+        }
+
         // Authenticate message
         let auth_message = AuthenticatedMessage::from_parsed(
             &parsed_message,
@@ -602,6 +630,14 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Add tenant disclaimer
+        if let Some(message) = self
+            .add_disclaimer(edited_message.as_deref().unwrap_or(&raw_message))
+            .await
+        {
+            edited_message = message.into();
+        }
+
         // Build message
         let mail_from = self.data.mail_from.clone().unwrap();
         let rcpt_to = std::mem::take(&mut self.data.rcpt_to);
@@ -647,13 +683,22 @@ impl<T: SessionStream> Session<T> {
 
         // DKIM sign
         let raw_message = edited_message.as_deref().unwrap_or(raw_message.as_slice());
+        let tenant_id = self
+            .data
+            .authenticated_as
+            .as_ref()
+            .and_then(|token| token.tenant.as_ref())
+            .map(|tenant| tenant.id);
         for signer in self
             .server
             .eval_if::<Vec<String>, _>(&ac.dkim.sign, self, self.data.session_id)
             .await
             .unwrap_or_default()
         {
-            if let Some(signer) = self.server.get_dkim_signer(&signer, self.data.session_id) {
+            if let Some(signer) =
+                self.server
+                    .get_tenant_dkim_signer(&signer, tenant_id, self.data.session_id)
+            {
                 match signer.sign_chained(&[headers.as_ref(), raw_message]) {
                     Ok(signature) => {
                         signature.write_header(&mut headers);
@@ -697,8 +742,27 @@ impl<T: SessionStream> Session<T> {
                     }
                 }
             } else {
-                MessageSource::Authenticated
+                MessageSource::Authenticated {
+                    tenant_id: self
+                        .data
+                        .authenticated_as
+                        .as_ref()
+                        .and_then(|token| token.tenant.as_ref())
+                        .map(|tenant| tenant.id),
+                }
             };
+            let journal_envelope = (!self.server.inner.data.tenant_journaling.load().is_empty())
+                .then(|| {
+                    (
+                        message.message.return_path.to_string(),
+                        message
+                            .message
+                            .recipients
+                            .iter()
+                            .map(|rcpt| rcpt.address.to_string())
+                            .collect::<Vec<_>>(),
+                    )
+                });
             if message
                 .queue(
                     Some(&headers),
@@ -709,6 +773,10 @@ impl<T: SessionStream> Session<T> {
                 )
                 .await
             {
+                if let Some((return_path, recipients)) = journal_envelope {
+                    self.journal_message(return_path, recipients, &headers, raw_message)
+                        .await;
+                }
                 self.state = State::Accepted(queue_id);
                 self.data.messages_sent += 1;
                 format!("250 2.0.0 Message queued with id {queue_id:x}.\r\n")
@@ -893,6 +961,178 @@ impl<T: SessionStream> Session<T> {
         }
     }
 
+    /// Appends the disclaimer of the sending domain or, when the domain has
+    /// none, the one of the sender's tenant. Messages addressed only to the
+    /// tenant's own domains are skipped when so configured.
+    async fn add_disclaimer(&self, raw_message: &[u8]) -> Option<Vec<u8>> {
+        let token = self.data.authenticated_as.as_ref()?;
+        let tenant_id = token.tenant.as_ref().map(|tenant| tenant.id);
+        let sender = self.data.mail_from.as_ref()?;
+        let tenant_disclaimer =
+            tenant_id.and_then(|tenant_id| self.server.tenant_disclaimer(tenant_id));
+        let domain_disclaimer = match self
+            .server
+            .sender_domain_disclaimer(&sender.domain, tenant_id)
+            .await
+        {
+            Ok(disclaimer) => disclaimer,
+            Err(err) => {
+                trc::error!(
+                    err.span_id(self.data.session_id)
+                        .caused_by(trc::location!())
+                        .details("Failed to obtain domain disclaimer")
+                );
+                None
+            }
+        };
+        let disclaimer =
+            DomainDisclaimer::resolve(domain_disclaimer.as_deref(), tenant_disclaimer.as_deref())?;
+
+        if disclaimer.exclude_internal {
+            let mut is_internal = true;
+            for rcpt in &self.data.rcpt_to {
+                match self.server.is_tenant_domain(&rcpt.domain, tenant_id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        is_internal = false;
+                        break;
+                    }
+                    Err(err) => {
+                        trc::error!(
+                            err.span_id(self.data.session_id)
+                                .caused_by(trc::location!())
+                                .details("Failed to obtain recipient tenant")
+                        );
+                        is_internal = false;
+                        break;
+                    }
+                }
+            }
+            if is_internal {
+                return None;
+            }
+        }
+
+        let brand = match tenant_id {
+            Some(tenant_id) => self.server.tenant_brand_name(tenant_id).await,
+            None => Ok(String::new()),
+        };
+        let brand = match brand {
+            Ok(brand) => brand,
+            Err(err) => {
+                trc::error!(
+                    err.span_id(self.data.session_id)
+                        .caused_by(trc::location!())
+                        .details("Failed to obtain tenant brand name")
+                );
+                String::new()
+            }
+        };
+        disclaimer.apply(
+            raw_message,
+            &DisclaimerVariables {
+                name: token.description.as_deref().unwrap_or(&token.name),
+                email: &sender.address,
+                brand: &brand,
+            },
+        )
+    }
+
+    /// Adds a copy of an accepted message to the journal queue of the tenants
+    /// journaling it. Messages from a tenant's users are journaled as internal
+    /// when all recipients are in the tenant's domains and as outbound
+    /// otherwise, while the tenants of any other recipient journal them as
+    /// inbound. Failures are logged and never affect the delivery.
+    async fn journal_message(
+        &self,
+        return_path: String,
+        recipients: Vec<String>,
+        headers: &[u8],
+        raw_message: &[u8],
+    ) {
+        let journaling = self.server.inner.data.tenant_journaling.load();
+        let sender_tenant_id = self
+            .data
+            .authenticated_as
+            .as_ref()
+            .and_then(|token| token.tenant.as_ref())
+            .map(|tenant| tenant.id);
+
+        // Group recipients by the tenant of their domain
+        let mut domains: AHashMap<String, Option<u32>> = AHashMap::new();
+        let mut tenant_rcpts: AHashMap<Option<u32>, Vec<String>> = AHashMap::new();
+        for rcpt in &recipients {
+            let domain = rcpt.domain_part().to_lowercase();
+            let tenant_id = match domains.get(&domain) {
+                Some(tenant_id) => *tenant_id,
+                None => {
+                    let tenant_id = match self.server.store().get_principal_info(&domain).await {
+                        Ok(info) => info
+                            .filter(|info| info.typ == Type::Domain)
+                            .and_then(|info| info.tenant),
+                        Err(err) => {
+                            trc::error!(
+                                err.span_id(self.data.session_id)
+                                    .caused_by(trc::location!())
+                                    .details("Failed to obtain recipient tenant")
+                            );
+                            None
+                        }
+                    };
+                    domains.insert(domain, tenant_id);
+                    tenant_id
+                }
+            };
+            tenant_rcpts
+                .entry(tenant_id)
+                .or_default()
+                .push(rcpt.clone());
+        }
+
+        let mut entries = Vec::new();
+        if let Some(sender_tenant_id) = sender_tenant_id {
+            let direction = if tenant_rcpts
+                .keys()
+                .all(|tenant_id| *tenant_id == Some(sender_tenant_id))
+            {
+                JournalDirection::Internal
+            } else {
+                JournalDirection::Outbound
+            };
+            entries.push((sender_tenant_id, direction, recipients));
+        }
+        for (tenant_id, rcpts) in tenant_rcpts {
+            if let Some(tenant_id) = tenant_id
+                && Some(tenant_id) != sender_tenant_id
+            {
+                entries.push((tenant_id, JournalDirection::Inbound, rcpts));
+            }
+        }
+
+        for (tenant_id, direction, recipients) in entries {
+            if journaling
+                .get(&tenant_id)
+                .is_some_and(|journaling| journaling.journals(direction))
+            {
+                let entry = JournalEntry {
+                    direction,
+                    return_path: return_path.clone(),
+                    recipients,
+                    received_at: now(),
+                    message: [headers, raw_message].concat(),
+                };
+                if let Err(err) = self.server.schedule_journal_entry(tenant_id, entry).await {
+                    trc::error!(
+                        err.span_id(self.data.session_id)
+                            .ctx(trc::Key::TenantId, tenant_id)
+                            .caused_by(trc::location!())
+                            .details("Failed to schedule journal entry")
+                    );
+                }
+            }
+        }
+    }
+
     fn write_received(&self, headers: &mut Vec<u8>, id: u64) {
         headers.extend_from_slice(b"Received: from ");
         headers.extend_from_slice(self.data.helo_domain.as_bytes());
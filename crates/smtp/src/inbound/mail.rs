@@ -231,6 +231,37 @@ impl<T: SessionStream> Session<T> {
 
         // Make sure that the authenticated user is allowed to send from this address
         match self.authenticated_as() {
+            // Send-only credentials are locked to their sender address whatever
+            // the configuration says
+            Some(authenticated_as)
+                if self
+                    .data
+                    .authenticated_as
+                    .as_ref()
+                    .is_some_and(|token| token.sender_address.is_some()) =>
+            {
+                let token = self.data.authenticated_as.as_ref().unwrap();
+                let address_lcase = self.data.mail_from.as_ref().unwrap().address_lcase.as_str();
+                if !token.is_sender_allowed(address_lcase) {
+                    trc::event!(
+                        Smtp(SmtpEvent::MailFromUnauthorized),
+                        SpanId = self.data.session_id,
+                        From = address_lcase.to_string(),
+                        Details = [
+                            trc::Value::String(authenticated_as.into()),
+                            trc::Value::String(
+                                token.sender_address.as_deref().unwrap_or_default().into()
+                            )
+                        ]
+                        .into_iter()
+                        .collect::<Vec<_>>()
+                    );
+                    self.data.mail_from = None;
+                    return self
+                        .write(b"550 5.7.1 Sender address not allowed for this credential.\r\n")
+                        .await;
+                }
+            }
             Some(authenticated_as)
                 if self
                     .server
@@ -436,7 +467,7 @@ impl<T: SessionStream> Session<T> {
                 .await;
         }
 
-        if self.is_allowed().await {
+        if self.is_allowed().await && self.is_send_rate_allowed().await {
             // Verify SPF
             if self.params.spf_mail_from.verify() {
                 let time = Instant::now();
@@ -9,14 +9,21 @@ use crate::{
     scripts::ScriptResult,
 };
 use common::{
-    KV_GREYLIST, config::smtp::session::Stage, listener::SessionStream, scripts::ScriptModification,
+    KV_GREYLIST,
+    config::{
+        smtp::{queue::DomainRouteAction, session::Stage},
+        spamfilter::TenantSpamSettings,
+    },
+    listener::SessionStream,
+    manager::spam::greylist_tenant_prefix,
+    scripts::ScriptModification,
 };
 use directory::backend::RcptType;
 use smtp_proto::{
     RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS, RcptTo,
 };
 use std::borrow::Cow;
-use store::dispatch::lookup::KeyValue;
+use store::{dispatch::lookup::KeyValue, write::now};
 use trc::{SecurityEvent, SmtpEvent};
 use utils::DomainPart;
 
@@ -188,161 +195,195 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Inbound routing rules of the recipient domain
+        let rcpt = self.data.rcpt_to.last().unwrap();
+        let mut is_routed = false;
+        if let Some(routes) = self.server.domain_routes(&rcpt.domain)
+            && let Some(route) = rcpt
+                .address_lcase
+                .try_local_part()
+                .and_then(|local_part| routes.resolve(local_part))
+        {
+            trc::event!(
+                Smtp(SmtpEvent::RcptToRouted),
+                SpanId = self.data.session_id,
+                To = rcpt.address_lcase.clone(),
+                Id = route.id.clone(),
+                Details = route.action.as_str(),
+            );
+
+            match &route.action {
+                DomainRouteAction::Local => (),
+                DomainRouteAction::Forward { address } => {
+                    let rcpt = self.data.rcpt_to.last_mut().unwrap();
+                    rcpt.address_lcase = address.clone();
+                    rcpt.domain = address.domain_part().into();
+                    rcpt.address = address.clone();
+                    is_routed = true;
+                }
+                DomainRouteAction::Relay(_) => {
+                    is_routed = true;
+                }
+                DomainRouteAction::Reject { message } => {
+                    let response = format!("550 5.7.1 {message}\r\n");
+                    self.data.rcpt_to.pop();
+                    return self.write(response.as_bytes()).await;
+                }
+            }
+        }
+
         // Verify address
         let rcpt = self.data.rcpt_to.last().unwrap();
         let mut rcpt_members = None;
-        if let Some(directory) = self
-            .server
-            .eval_if::<String, _>(&rcpt_config.directory, self, self.data.session_id)
-            .await
-            .and_then(|name| self.server.get_directory(&name))
-        {
-            match directory.is_local_domain(&rcpt.domain).await {
-                Ok(true) => {
-                    match self
-                        .server
-                        .rcpt(directory, &rcpt.address_lcase, self.data.session_id)
-                        .await
-                    {
-                        Ok(RcptType::Mailbox) => {}
-                        Ok(RcptType::List(members)) => {
-                            rcpt_members = Some(members);
-                        }
-                        Ok(RcptType::Invalid) => {
+        if !is_routed {
+            if let Some(directory) = self
+                .server
+                .eval_if::<String, _>(&rcpt_config.directory, self, self.data.session_id)
+                .await
+                .and_then(|name| self.server.get_directory(&name))
+            {
+                match directory.is_local_domain(&rcpt.domain).await {
+                    Ok(true) => {
+                        // Mail for tenants under maintenance is retried by the sender
+                        if let Some(maintenance) =
+                            self.server.domain_maintenance(&rcpt.domain).await
+                        {
                             trc::event!(
-                                Smtp(SmtpEvent::MailboxDoesNotExist),
+                                Smtp(SmtpEvent::RcptToMaintenance),
                                 SpanId = self.data.session_id,
                                 To = rcpt.address_lcase.clone(),
                             );
 
-                            let rcpt_to = self.data.rcpt_to.pop().unwrap().address_lcase;
+                            self.data.rcpt_to.pop();
                             return self
-                                .rcpt_error(b"550 5.1.2 Mailbox does not exist.\r\n", rcpt_to)
+                                .write(format!("451 4.3.2 {}\r\n", maintenance.reason()).as_bytes())
                                 .await;
                         }
-                        Err(err) => {
-                            trc::error!(
-                                err.span_id(self.data.session_id)
-                                    .caused_by(trc::location!())
-                                    .details("Failed to verify address.")
+
+                        // Domains of organizations that were not activated yet
+                        if self.server.domain_pending_activation(&rcpt.domain).await {
+                            trc::event!(
+                                Smtp(SmtpEvent::RcptToPendingActivation),
+                                SpanId = self.data.session_id,
+                                To = rcpt.address_lcase.clone(),
                             );
 
                             self.data.rcpt_to.pop();
                             return self
-                                .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
+                                .write(b"450 4.2.1 Recipient organization pending activation.\r\n")
                                 .await;
                         }
-                    }
-                }
-                Ok(false) => {
-                    if !self
-                        .server
-                        .eval_if(&rcpt_config.relay, self, self.data.session_id)
-                        .await
-                        .unwrap_or(false)
-                    {
-                        trc::event!(
-                            Smtp(SmtpEvent::RelayNotAllowed),
-                            SpanId = self.data.session_id,
-                            To = rcpt.address_lcase.clone(),
-                        );
 
-                        let rcpt_to = self.data.rcpt_to.pop().unwrap().address_lcase;
-                        return self
-                            .rcpt_error(b"550 5.1.2 Relay not allowed.\r\n", rcpt_to)
-                            .await;
-                    }
-                }
-                Err(err) => {
-                    trc::error!(
-                        err.span_id(self.data.session_id)
-                            .caused_by(trc::location!())
-                            .details("Failed to verify address.")
-                    );
-
-                    self.data.rcpt_to.pop();
-                    return self
-                        .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
-                        .await;
-                }
-            }
-        } else if !self
-            .server
-            .eval_if(&rcpt_config.relay, self, self.data.session_id)
-            .await
-            .unwrap_or(false)
-        {
-            trc::event!(
-                Smtp(SmtpEvent::RelayNotAllowed),
-                SpanId = self.data.session_id,
-                To = rcpt.address_lcase.clone(),
-            );
-
-            let rcpt_to = self.data.rcpt_to.pop().unwrap().address_lcase;
-            return self
-                .rcpt_error(b"550 5.1.2 Relay not allowed.\r\n", rcpt_to)
-                .await;
-        }
-
-        if self.is_allowed().await {
-            // Greylist
-            if let Some(greylist_duration) = self
-                .server
-                .core
-                .spam
-                .grey_list_expiry
-                .filter(|_| self.data.authenticated_as.is_none())
-            {
-                let from_addr = self
-                    .data
-                    .mail_from
-                    .as_ref()
-                    .unwrap()
-                    .address_lcase
-                    .as_bytes();
-                let to_addr = self.data.rcpt_to.last().unwrap().address_lcase.as_bytes();
-                let mut key = Vec::with_capacity(from_addr.len() + to_addr.len() + 1);
-                key.push(KV_GREYLIST);
-                key.extend_from_slice(from_addr);
-                key.extend_from_slice(to_addr);
-
-                match self.server.in_memory_store().key_exists(key.clone()).await {
-                    Ok(true) => (),
-                    Ok(false) => {
                         match self
                             .server
-                            .in_memory_store()
-                            .key_set(KeyValue::new(key, vec![]).expires(greylist_duration))
+                            .rcpt(directory, &rcpt.address_lcase, self.data.session_id)
                             .await
                         {
-                            Ok(_) => {
-                                let rcpt = self.data.rcpt_to.pop().unwrap();
-
+                            Ok(RcptType::Mailbox) => {}
+                            Ok(RcptType::List(members)) => {
+                                rcpt_members = Some(members);
+                            }
+                            Ok(RcptType::Invalid) => {
                                 trc::event!(
-                                    Smtp(SmtpEvent::RcptToGreylisted),
+                                    Smtp(SmtpEvent::MailboxDoesNotExist),
                                     SpanId = self.data.session_id,
-                                    To = rcpt.address_lcase,
+                                    To = rcpt.address_lcase.clone(),
                                 );
 
+                                let rcpt_to = self.data.rcpt_to.pop().unwrap().address_lcase;
                                 return self
-                                    .write(
-                                        concat!(
-                                            "452 4.2.2 Greylisted, please try ",
-                                            "again in a few moments.\r\n"
-                                        )
-                                        .as_bytes(),
-                                    )
+                                    .rcpt_error(b"550 5.1.2 Mailbox does not exist.\r\n", rcpt_to)
                                     .await;
                             }
                             Err(err) => {
                                 trc::error!(
                                     err.span_id(self.data.session_id)
                                         .caused_by(trc::location!())
-                                        .details("Failed to set greylist.")
+                                        .details("Failed to verify address.")
                                 );
+
+                                self.data.rcpt_to.pop();
+                                return self
+                                    .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
+                                    .await;
                             }
                         }
                     }
+                    Ok(false) => {
+                        if !self
+                            .server
+                            .eval_if(&rcpt_config.relay, self, self.data.session_id)
+                            .await
+                            .unwrap_or(false)
+                        {
+                            trc::event!(
+                                Smtp(SmtpEvent::RelayNotAllowed),
+                                SpanId = self.data.session_id,
+                                To = rcpt.address_lcase.clone(),
+                            );
+
+                            let rcpt_to = self.data.rcpt_to.pop().unwrap().address_lcase;
+                            return self
+                                .rcpt_error(b"550 5.1.2 Relay not allowed.\r\n", rcpt_to)
+                                .await;
+                        }
+                    }
+                    Err(err) => {
+                        trc::error!(
+                            err.span_id(self.data.session_id)
+                                .caused_by(trc::location!())
+                                .details("Failed to verify address.")
+                        );
+
+                        self.data.rcpt_to.pop();
+                        return self
+                            .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
+                            .await;
+                    }
+                }
+            } else if !self
+                .server
+                .eval_if(&rcpt_config.relay, self, self.data.session_id)
+                .await
+                .unwrap_or(false)
+            {
+                trc::event!(
+                    Smtp(SmtpEvent::RelayNotAllowed),
+                    SpanId = self.data.session_id,
+                    To = rcpt.address_lcase.clone(),
+                );
+
+                let rcpt_to = self.data.rcpt_to.pop().unwrap().address_lcase;
+                return self
+                    .rcpt_error(b"550 5.1.2 Relay not allowed.\r\n", rcpt_to)
+                    .await;
+            }
+        }
+
+        if self.is_allowed().await {
+            // Greylist
+            if let Some(greylist) = self.greylist_params().await {
+                match self.is_greylisted(&greylist).await {
+                    Ok(true) => {
+                        let rcpt = self.data.rcpt_to.pop().unwrap();
+
+                        trc::event!(
+                            Smtp(SmtpEvent::RcptToGreylisted),
+                            SpanId = self.data.session_id,
+                            To = rcpt.address_lcase,
+                        );
+
+                        return self
+                            .write(
+                                concat!(
+                                    "452 4.2.2 Greylisted, please try ",
+                                    "again in a few moments.\r\n"
+                                )
+                                .as_bytes(),
+                            )
+                            .await;
+                    }
+                    Ok(false) => (),
                     Err(err) => {
                         trc::error!(
                             err.span_id(self.data.session_id)
@@ -436,4 +477,111 @@ impl<T: SessionStream> Session<T> {
             Err(())
         }
     }
+
+    /// Resolves the greylisting settings that apply to the last recipient,
+    /// tenants may override the global settings for their domains.
+    async fn greylist_params(&self) -> Option<Greylist> {
+        if self.data.authenticated_as.is_some() {
+            return None;
+        }
+
+        let expiry = self.server.core.spam.grey_list_expiry;
+        let rcpt = self.data.rcpt_to.last()?.address_lcase.as_str();
+        let Some((tenant_id, settings)) =
+            self.server
+                .recipients_tenant(&[rcpt])
+                .await
+                .and_then(|tenant_id| {
+                    self.server
+                        .tenant_spam_settings(tenant_id)
+                        .map(|settings| (tenant_id, settings))
+                })
+        else {
+            return expiry.map(|expiry| Greylist {
+                tenant_id: None,
+                expiry,
+                delay: 0,
+                whitelist_after: 0,
+            });
+        };
+
+        Some(Greylist {
+            tenant_id: Some(tenant_id),
+            expiry: match settings.greylist {
+                Some(true) => expiry.unwrap_or(TenantSpamSettings::DEFAULT_GREYLIST_EXPIRY),
+                Some(false) => return None,
+                None => expiry?,
+            },
+            delay: settings.greylist_delay.unwrap_or_default(),
+            whitelist_after: settings.greylist_whitelist_after.unwrap_or_default(),
+        })
+    }
+
+    async fn is_greylisted(&self, greylist: &Greylist) -> trc::Result<bool> {
+        let from_addr = self
+            .data
+            .mail_from
+            .as_ref()
+            .unwrap()
+            .address_lcase
+            .as_bytes();
+        let to_addr = self.data.rcpt_to.last().unwrap().address_lcase.as_bytes();
+        let mut key = if let Some(tenant_id) = greylist.tenant_id {
+            greylist_tenant_prefix(tenant_id)
+        } else {
+            vec![KV_GREYLIST]
+        };
+        let store = self.server.in_memory_store();
+        let now = now();
+
+        // Senders that passed greylisting enough times are no longer delayed
+        let whitelist_key = if greylist.whitelist_after > 0 {
+            let mut whitelist_key = key.clone();
+            whitelist_key.push(GREYLIST_WHITELIST);
+            whitelist_key.extend_from_slice(from_addr);
+            if store.counter_get(whitelist_key.clone()).await? >= greylist.whitelist_after as i64 {
+                return Ok(false);
+            }
+            Some(whitelist_key)
+        } else {
+            None
+        };
+
+        if greylist.tenant_id.is_some() {
+            key.push(GREYLIST_TRIPLET);
+        }
+        key.extend_from_slice(from_addr);
+        key.extend_from_slice(to_addr);
+
+        match store.key_get::<String>(key.clone()).await? {
+            Some(first_seen) => {
+                if first_seen.parse::<u64>().unwrap_or_default() + greylist.delay > now {
+                    return Ok(true);
+                }
+                if let Some(whitelist_key) = whitelist_key {
+                    store
+                        .counter_incr(
+                            KeyValue::new(whitelist_key, 1).expires(greylist.expiry),
+                            false,
+                        )
+                        .await?;
+                }
+                Ok(false)
+            }
+            None => store
+                .key_set(KeyValue::new(key, now.to_string().into_bytes()).expires(greylist.expiry))
+                .await
+                .map(|_| true),
+        }
+    }
+}
+
+const GREYLIST_TRIPLET: u8 = 0;
+const GREYLIST_WHITELIST: u8 = 1;
+
+struct Greylist {
+    tenant_id: Option<u32>,
+    expiry: u64,
+    delay: u64,
+    whitelist_after: u32,
 }
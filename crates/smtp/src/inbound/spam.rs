@@ -27,16 +27,18 @@ impl<T: SessionStream> Session<T> {
         dmarc_policy: Option<&'x Policy>,
     ) -> SpamFilterAction<SpamFilterScore> {
         let server = &self.server;
-        let mut ctx = server.spam_filter_init(self.build_spam_input(
-            message,
-            dkim_result,
-            arc_result,
-            dmarc_result,
-            dmarc_policy,
-        ));
+        let mut input =
+            self.build_spam_input(message, dkim_result, arc_result, dmarc_result, dmarc_policy);
 
         if !self.is_authenticated() {
+            // Apply the settings of the recipients' tenant
+            if let Some(tenant_id) = server.recipients_tenant(&input.env_rcpt_to).await {
+                input.tenant = server.tenant_spam_settings(tenant_id);
+                input.senders = server.tenant_sender_lists(tenant_id);
+            }
+
             // Spam classification
+            let mut ctx = server.spam_filter_init(input);
             server.spam_filter_classify(&mut ctx).await
         } else {
             // Do not classify authenticated sessions
@@ -86,6 +88,8 @@ impl<T: SessionStream> Session<T> {
                 .iter()
                 .map(|r| r.address_lcase.as_str())
                 .collect(),
+            tenant: None,
+            senders: None,
             is_test: false,
             is_train: false,
         }
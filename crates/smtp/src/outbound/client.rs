@@ -178,8 +178,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> SmtpClient<T> {
     ) -> Result<(), Status<HostResponse<Box<str>>, ErrorDetails>> {
         match params
             .server
-            .blob_store()
-            .get_blob(message.message.blob_hash.as_slice(), 0..usize::MAX)
+            .get_blob(&message.message.blob_hash, 0..usize::MAX)
             .await
         {
             Ok(Some(raw_message)) => {
@@ -27,6 +27,7 @@ use common::Server;
 use common::config::smtp::queue::RoutingStrategy;
 use common::config::{server::ServerProtocol, smtp::report::AggregateFrequency};
 use common::ipc::{PolicyType, QueueEvent, QueueEventStatus, TlsEvent};
+use common::telemetry::metrics::tenant::TenantMetric;
 use compact_str::ToCompactString;
 use mail_auth::{
     mta_sts::TlsRpt,
@@ -40,6 +41,7 @@ use std::{
 };
 use store::write::{BatchBuilder, QueueClass, ValueClass, now};
 use trc::{DaneEvent, DeliveryEvent, MtaStsEvent, ServerEvent, TlsRptEvent};
+use utils::DomainPart;
 
 impl QueuedMessage {
     pub fn try_deliver(self, server: Server) {
@@ -167,6 +169,13 @@ impl QueuedMessage {
                     Elapsed = trc::Value::Duration((now() - message.message.created) * 1000)
                 );
 
+                // Record the expired recipients in the delivery log
+                if server.core.smtp.queue.delivery_log.is_some()
+                    && let Some(tenant_id) = message.tenant_id(&server).await
+                {
+                    message.log_delivery(&server, tenant_id, []).await;
+                }
+
                 // All message recipients expired, do not re-queue. (DSN has been already sent)
                 message.remove(&server, self.due.into()).await;
 
@@ -214,6 +223,19 @@ impl QueuedMessage {
             }
         }
 
+        // Messages submitted by tenant users are relayed through the tenant's hosts
+        // and recorded in the tenant's delivery log
+        let is_delivery_log = server.core.smtp.queue.delivery_log.is_some();
+        let tenant_id = if is_delivery_log || !server.inner.data.tenant_routing.load().is_empty() {
+            message.tenant_id(&server).await
+        } else {
+            None
+        };
+        let tenant_routes = tenant_id.and_then(|tenant_id| server.tenant_routing(tenant_id));
+
+        // Recipients of local domains may be relayed by the domain's routing rules
+        let domain_routes = server.inner.data.domain_routes.load_full();
+
         // Group recipients by route
         let queue_config = &server.core.smtp.queue;
         let now_ = now();
@@ -226,13 +248,26 @@ impl QueuedMessage {
                 && rcpt.queue == message.queue_name
             {
                 let envelope = QueueEnvelope::new(&message.message, rcpt);
-                let route = server.get_route_or_default(
+                let mut route = server.get_route_or_default(
                     &server
                         .eval_if::<String, _>(&queue_config.route, &envelope, message.span_id)
                         .await
                         .unwrap_or_else(|| "default".to_string()),
                     message.span_id,
                 );
+                if matches!(route, RoutingStrategy::Mx(_))
+                    && let Some(tenant_route) = tenant_routes
+                        .as_ref()
+                        .and_then(|routes| routes.route(rcpt.domain_part()))
+                {
+                    route = tenant_route;
+                }
+                if let Some(domain_route) = domain_routes
+                    .get(rcpt.domain_part())
+                    .and_then(|routes| routes.relay(rcpt.address().try_local_part()?))
+                {
+                    route = domain_route;
+                }
 
                 routes
                     .entry((rcpt.domain_part(), route))
@@ -1247,6 +1282,7 @@ impl QueuedMessage {
         }
 
         // Apply status changes
+        let mut attempted_idxs = Vec::new();
         for delivery_result in delivery_results {
             match delivery_result {
                 DeliveryResult::Domain { status, rcpt_idxs } => {
@@ -1254,10 +1290,12 @@ impl QueuedMessage {
                         message
                             .set_rcpt_status(status.clone(), rcpt_idx, &server)
                             .await;
+                        attempted_idxs.push(rcpt_idx);
                     }
                 }
                 DeliveryResult::Account { status, rcpt_idx } => {
                     message.set_rcpt_status(status, rcpt_idx, &server).await;
+                    attempted_idxs.push(rcpt_idx);
                 }
                 DeliveryResult::RateLimited {
                     rcpt_idxs,
@@ -1265,11 +1303,19 @@ impl QueuedMessage {
                 } => {
                     for rcpt_idx in rcpt_idxs {
                         message.set_rcpt_rate_limit(rcpt_idx, retry_at);
+                        attempted_idxs.push(rcpt_idx);
                     }
                 }
             }
         }
 
+        // Update delivery log
+        if is_delivery_log && let Some(tenant_id) = tenant_id {
+            message
+                .log_delivery(&server, tenant_id, attempted_idxs)
+                .await;
+        }
+
         // Send Delivery Status Notifications
         server.send_dsn(&mut message).await;
 
@@ -1297,6 +1343,33 @@ impl QueuedMessage {
                 Elapsed = trc::Value::Duration((now() - message.message.created) * 1000)
             );
 
+            // Update tenant metrics
+            if server.core.metrics.tenants && !message.message.return_path.is_empty() {
+                let tenant_id = server
+                    .tenant_id_for_name(&message.message.return_path)
+                    .await;
+                let (delivered, bounced) =
+                    message
+                        .message
+                        .recipients
+                        .iter()
+                        .fold((0, 0), |(delivered, bounced), rcpt| match rcpt.status {
+                            Status::Completed(_) => (delivered + 1, bounced),
+                            Status::PermanentFailure(_) => (delivered, bounced + 1),
+                            _ => (delivered, bounced),
+                        });
+                server.record_tenant_metric(tenant_id, TenantMetric::MessagesDelivered, delivered);
+                server.record_tenant_metric(tenant_id, TenantMetric::MessagesBounced, bounced);
+                if delivered > 0 {
+                    server.record_tenant_metric(tenant_id, TenantMetric::MessagesSent, 1);
+                    server.record_tenant_metric(
+                        tenant_id,
+                        TenantMetric::BytesSent,
+                        message.message.size,
+                    );
+                }
+            }
+
             // Delete message from queue
             message.remove(&server, self.due.into()).await;
 
@@ -5,6 +5,7 @@
  */
 
 use crate::{
+    inbound::ArcSeal,
     outbound::DeliveryResult,
     queue::{
         Error, ErrorDetails, FROM_AUTHENTICATED, FROM_UNAUTHENTICATED_DMARC, HostResponse,
@@ -15,8 +16,10 @@ use crate::{
 };
 use common::Server;
 use email::message::delivery::{IngestMessage, IngestRecipient, LocalDeliveryStatus, MailDelivery};
+use mail_auth::{AuthenticatedMessage, AuthenticationResults, common::headers::HeaderWriter};
+use mail_parser::MessageParser;
 use smtp_proto::Response;
-use trc::SieveEvent;
+use trc::{ArcEvent, SieveEvent};
 
 impl MessageWrapper {
     pub(super) async fn deliver_local(
@@ -102,22 +105,36 @@ impl MessageWrapper {
                 message.add_recipient(rcpt, server).await;
             }
 
+            // ARC seal messages redirected by accounts of tenants that require it
+            let mut headers = match autogenerated
+                .forwarded_by
+                .and_then(|tenant_id| server.tenant_arc_sealer(tenant_id))
+            {
+                Some(sealer) => {
+                    arc_seal(server, &sealer, &autogenerated.message, self.span_id).await
+                }
+                None => None,
+            };
+
             // Sign message
-            let signature = server
+            if let Some(signature) = server
                 .sign_message(
                     &mut message,
                     &server.core.sieve.sign,
                     &autogenerated.message,
                 )
-                .await;
+                .await
+            {
+                headers.get_or_insert_default().extend(signature);
+            }
 
             // Queue Message
             message.message.size =
-                (autogenerated.message.len() + signature.as_ref().map_or(0, |s| s.len())) as u64;
+                (autogenerated.message.len() + headers.as_ref().map_or(0, |s| s.len())) as u64;
             if server.has_quota(&mut message).await {
                 message
                     .queue(
-                        signature.as_deref(),
+                        headers.as_deref(),
                         &autogenerated.message,
                         self.span_id,
                         server,
@@ -140,3 +157,63 @@ impl MessageWrapper {
         }
     }
 }
+
+async fn arc_seal(
+    server: &Server,
+    name: &str,
+    raw_message: &[u8],
+    span_id: u64,
+) -> Option<Vec<u8>> {
+    let sealer = server.get_arc_sealer(name, span_id)?;
+    let Some(parsed_message) = MessageParser::new().parse(raw_message) else {
+        trc::event!(
+            Arc(ArcEvent::SealFailed),
+            SpanId = span_id,
+            Id = name.to_string(),
+            Reason = "Failed to parse message",
+        );
+        return None;
+    };
+    let auth_message =
+        AuthenticatedMessage::from_parsed(&parsed_message, server.core.smtp.mail_auth.dkim.strict);
+
+    // Record the results of verifying the message as received
+    let resolver = &server.core.smtp.resolvers.dns;
+    let dkim_output = resolver
+        .verify_dkim(server.inner.cache.build_auth_parameters(&auth_message))
+        .await;
+    let arc_output = resolver
+        .verify_arc(server.inner.cache.build_auth_parameters(&auth_message))
+        .await;
+    if !arc_output.can_be_sealed() {
+        trc::event!(
+            Arc(ArcEvent::SealFailed),
+            SpanId = span_id,
+            Id = name.to_string(),
+            Result = trc::Error::from(arc_output.result()),
+            Reason = "ARC chain cannot be sealed",
+        );
+        return None;
+    }
+    let mut auth_results = AuthenticationResults::new(&server.core.network.server_name);
+    if !dkim_output.is_empty() {
+        auth_results = auth_results.with_dkim_results(&dkim_output, auth_message.from());
+    }
+
+    match sealer.seal(&auth_message, &auth_results, &arc_output) {
+        Ok(set) => {
+            let mut headers = Vec::with_capacity(256);
+            set.write_header(&mut headers);
+            Some(headers)
+        }
+        Err(err) => {
+            trc::event!(
+                Arc(ArcEvent::SealFailed),
+                SpanId = span_id,
+                Id = name.to_string(),
+                Reason = err.to_string(),
+            );
+            None
+        }
+    }
+}
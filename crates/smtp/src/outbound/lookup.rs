@@ -14,6 +14,7 @@ use common::{
 use mail_auth::{IpLookupStrategy, MX};
 use rand::{Rng, seq::SliceRandom};
 use std::{future::Future, net::IpAddr, sync::Arc};
+use utils::is_public_ip;
 
 pub struct IpLookupResult {
     pub remote_ips: Vec<IpAddr>,
@@ -147,6 +148,21 @@ impl DnsLookup for Server {
             })?;
 
         if !remote_ips.is_empty() {
+            // Tenant relays are checked again on the addresses that are
+            // connected to, as their records may have changed since they
+            // were configured
+            if let NextHop::Relay(relay) = remote_host
+                && relay.public_only
+                && let Some(ip) = remote_ips.iter().find(|ip| !is_public_ip(**ip))
+            {
+                return Err(Status::PermanentFailure(ErrorDetails {
+                    entity: remote_host.hostname().into(),
+                    details: Error::ConnectionError(
+                        format!("relay resolves to non-public address {ip}").into_boxed_str(),
+                    ),
+                }));
+            }
+
             #[cfg(not(feature = "test_mode"))]
             if remote_ips.iter().any(|ip| ip.is_loopback()) {
                 remote_ips.retain(|ip| !ip.is_loopback());
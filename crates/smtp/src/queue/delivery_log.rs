@@ -0,0 +1,512 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use super::{ErrorDetails, HostResponse, MessageWrapper, Status, spool::SmtpSpool};
+use common::Server;
+use std::{future::Future, time::Duration};
+use store::{
+    IterateParams, Serialize, U32_LEN, ValueKey,
+    write::{
+        AlignedBytes, Archive, Archiver, BatchBuilder, QueueClass, ValueClass,
+        key::DeserializeBigEndian, now,
+    },
+};
+use trc::AddContext;
+use utils::snowflake::SnowflakeIdGenerator;
+
+const MAX_ATTEMPTS: usize = 50;
+
+#[derive(
+    rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, PartialEq, Eq, serde::Serialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryLog {
+    pub id: u64,
+    pub created: u64,
+    pub return_path: String,
+    pub recipients: Vec<DeliveryLogRecipient>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<DeliveryLogAttempt>,
+}
+
+#[derive(
+    rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, PartialEq, Eq, serde::Serialize,
+)]
+pub struct DeliveryLogRecipient {
+    pub address: String,
+    pub outcome: DeliveryOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    pub updated: u64,
+}
+
+#[derive(
+    rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, PartialEq, Eq, serde::Serialize,
+)]
+pub struct DeliveryLogAttempt {
+    pub timestamp: u64,
+    pub recipient: String,
+    pub outcome: DeliveryOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    rkyv::Archive,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryOutcome {
+    Queued,
+    Delivered,
+    Deferred,
+    Bounced,
+}
+
+pub trait SmtpDeliveryLog: Sync + Send {
+    fn read_delivery_log(
+        &self,
+        tenant_id: u32,
+        queue_id: u64,
+    ) -> impl Future<Output = trc::Result<Option<DeliveryLog>>> + Send;
+
+    fn purge_delivery_logs(&self, period: Duration)
+    -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl SmtpDeliveryLog for Server {
+    async fn read_delivery_log(
+        &self,
+        tenant_id: u32,
+        queue_id: u64,
+    ) -> trc::Result<Option<DeliveryLog>> {
+        self.store()
+            .get_value::<Archive<AlignedBytes>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::DeliveryLog {
+                    tenant_id,
+                    queue_id,
+                },
+            )))
+            .await?
+            .map(|archive| archive.deserialize::<DeliveryLog>())
+            .transpose()
+    }
+
+    async fn purge_delivery_logs(&self, period: Duration) -> trc::Result<()> {
+        let until_id = SnowflakeIdGenerator::from_duration(period).ok_or_else(|| {
+            trc::StoreEvent::UnexpectedError
+                .caused_by(trc::location!())
+                .ctx(trc::Key::Reason, "Failed to generate reference queue id.")
+        })?;
+
+        // Obtain expired records of all tenants
+        let mut expired = Vec::new();
+        self.store()
+            .iterate(
+                IterateParams::new(
+                    ValueKey::from(ValueClass::Queue(QueueClass::DeliveryLog {
+                        tenant_id: 0,
+                        queue_id: 0,
+                    })),
+                    ValueKey::from(ValueClass::Queue(QueueClass::DeliveryLog {
+                        tenant_id: u32::MAX,
+                        queue_id: u64::MAX,
+                    })),
+                )
+                .ascending()
+                .no_values(),
+                |key, _| {
+                    let queue_id = key.deserialize_be_u64(U32_LEN + 1)?;
+                    if queue_id < until_id {
+                        expired.push((key.deserialize_be_u32(1)?, queue_id));
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        let mut batch = BatchBuilder::new();
+        for (tenant_id, queue_id) in expired {
+            batch.clear(ValueClass::Queue(QueueClass::DeliveryLog {
+                tenant_id,
+                queue_id,
+            }));
+
+            if batch.is_large_batch() {
+                self.store()
+                    .write(batch.build_all())
+                    .await
+                    .caused_by(trc::location!())?;
+                batch = BatchBuilder::new();
+            }
+        }
+        if !batch.is_empty() {
+            self.store()
+                .write(batch.build_all())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DeliveryLog {
+    pub fn new(message: &MessageWrapper, now: u64) -> Self {
+        DeliveryLog {
+            id: message.queue_id,
+            created: message.message.created,
+            return_path: message.message.return_path.to_string(),
+            recipients: message
+                .message
+                .recipients
+                .iter()
+                .map(|rcpt| {
+                    let (outcome, details) = rcpt.status.delivery_outcome();
+                    DeliveryLogRecipient {
+                        address: rcpt.address.to_string(),
+                        outcome,
+                        details,
+                        updated: now,
+                    }
+                })
+                .collect(),
+            attempts: Vec::new(),
+        }
+    }
+}
+
+impl MessageWrapper {
+    /// Returns the tenant that submitted the message, if any.
+    pub async fn tenant_id(&self, server: &Server) -> Option<u32> {
+        match server.read_message_tenant(self.queue_id).await {
+            Ok(tenant_id) => tenant_id,
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to read message tenant.")
+                        .span_id(self.span_id)
+                        .caused_by(trc::location!())
+                );
+                None
+            }
+        }
+    }
+
+    /// Records the outcome of the recipients in the delivery log of the tenant,
+    /// adding an attempt for each of the recipients in `rcpt_idxs`.
+    pub async fn log_delivery(
+        &self,
+        server: &Server,
+        tenant_id: u32,
+        rcpt_idxs: impl IntoIterator<Item = usize>,
+    ) {
+        if let Err(err) = self.write_delivery_log(server, tenant_id, rcpt_idxs).await {
+            trc::error!(
+                err.details("Failed to write delivery log.")
+                    .span_id(self.span_id)
+                    .caused_by(trc::location!())
+            );
+        }
+    }
+
+    async fn write_delivery_log(
+        &self,
+        server: &Server,
+        tenant_id: u32,
+        rcpt_idxs: impl IntoIterator<Item = usize>,
+    ) -> trc::Result<()> {
+        let now = now();
+        let mut log = server
+            .read_delivery_log(tenant_id, self.queue_id)
+            .await?
+            .unwrap_or_else(|| DeliveryLog::new(self, now));
+
+        // Update the current outcome of each recipient
+        for rcpt in &self.message.recipients {
+            let (outcome, details) = rcpt.status.delivery_outcome();
+            match log
+                .recipients
+                .iter_mut()
+                .find(|entry| entry.address == rcpt.address.as_ref())
+            {
+                Some(entry) => {
+                    if entry.outcome != outcome || entry.details != details {
+                        entry.outcome = outcome;
+                        entry.details = details;
+                        entry.updated = now;
+                    }
+                }
+                None => log.recipients.push(DeliveryLogRecipient {
+                    address: rcpt.address.to_string(),
+                    outcome,
+                    details,
+                    updated: now,
+                }),
+            }
+        }
+
+        // Add delivery attempts
+        for rcpt_idx in rcpt_idxs {
+            if let Some(rcpt) = self.message.recipients.get(rcpt_idx) {
+                let (outcome, details) = rcpt.status.delivery_outcome();
+                log.attempts.push(DeliveryLogAttempt {
+                    timestamp: now,
+                    recipient: rcpt.address.to_string(),
+                    outcome,
+                    details,
+                });
+            }
+        }
+        if log.attempts.len() > MAX_ATTEMPTS {
+            log.attempts.drain(..log.attempts.len() - MAX_ATTEMPTS);
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::DeliveryLog {
+                tenant_id,
+                queue_id: self.queue_id,
+            }),
+            Archiver::new(log).serialize().caused_by(trc::location!())?,
+        );
+        server
+            .store()
+            .write(batch.build_all())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ())
+    }
+}
+
+impl Status<HostResponse<Box<str>>, ErrorDetails> {
+    pub fn delivery_outcome(&self) -> (DeliveryOutcome, Option<String>) {
+        match self {
+            Status::Scheduled => (DeliveryOutcome::Queued, None),
+            Status::Completed(response) => (
+                DeliveryOutcome::Delivered,
+                format!("{}: {}", response.hostname, response.response).into(),
+            ),
+            Status::TemporaryFailure(err) => (
+                DeliveryOutcome::Deferred,
+                format!("{}: {}", err.entity, err.details).into(),
+            ),
+            Status::PermanentFailure(err) => (
+                DeliveryOutcome::Bounced,
+                format!("{}: {}", err.entity, err.details).into(),
+            ),
+        }
+    }
+}
+
+/// Cause of a failed delivery attempt, inferred from the remote SMTP response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeferralReason {
+    RateLimit,
+    Reputation,
+    Authentication,
+    Content,
+    Connection,
+    Other,
+}
+
+impl DeferralReason {
+    pub const ALL: [DeferralReason; 6] = [
+        DeferralReason::RateLimit,
+        DeferralReason::Reputation,
+        DeferralReason::Authentication,
+        DeferralReason::Content,
+        DeferralReason::Connection,
+        DeferralReason::Other,
+    ];
+
+    /// Buckets the details of an attempt, patterns are checked in order so
+    /// that block lists mentioning spam are not reported as content issues.
+    pub fn classify(details: &str) -> Self {
+        // Skip the host that answered, its name could match any pattern
+        let details = details
+            .split_once(": ")
+            .map_or(details, |(_, response)| response)
+            .to_lowercase();
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| details.contains(p));
+
+        if matches(&[
+            "rate limit",
+            "ratelimit",
+            "rate-limit",
+            "too many",
+            "throttl",
+            "unexpected volume",
+            "temporarily deferred",
+            "4.7.28",
+        ]) {
+            DeferralReason::RateLimit
+        } else if matches(&[
+            "reputation",
+            "blocklist",
+            "blacklist",
+            "block list",
+            "black list",
+            "dnsbl",
+            "spamhaus",
+            "spamcop",
+            "listed",
+            "4.7.650",
+            "5.7.606",
+        ]) {
+            DeferralReason::Reputation
+        } else if matches(&[
+            "dmarc",
+            "spf",
+            "dkim",
+            "unauthenticated",
+            "not authenticated",
+            "5.7.26",
+            "4.7.26",
+            "5.7.25",
+        ]) {
+            DeferralReason::Authentication
+        } else if matches(&["spam", "content", "virus", "malware", "phish", "suspicious"]) {
+            DeferralReason::Content
+        } else if matches(&[
+            "connection failed",
+            "connection refused",
+            "tls error",
+            "dns lookup failed",
+            "timed out",
+            "timeout",
+            "dane",
+            "mta-sts",
+        ]) {
+            DeferralReason::Connection
+        } else {
+            DeferralReason::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeferralReason::RateLimit => "rateLimit",
+            DeferralReason::Reputation => "reputation",
+            DeferralReason::Authentication => "authentication",
+            DeferralReason::Content => "content",
+            DeferralReason::Connection => "connection",
+            DeferralReason::Other => "other",
+        }
+    }
+}
+
+/// Groups a recipient into the mailbox provider hosting its domain, using the
+/// remote host that answered the delivery when the domain is not well known.
+pub fn destination_provider(recipient: &str, details: Option<&str>) -> &'static str {
+    const DOMAINS: &[(&str, &str)] = &[
+        ("gmail.com", "google"),
+        ("googlemail.com", "google"),
+        ("outlook.com", "microsoft"),
+        ("hotmail.com", "microsoft"),
+        ("live.com", "microsoft"),
+        ("msn.com", "microsoft"),
+        ("yahoo.com", "yahoo"),
+        ("ymail.com", "yahoo"),
+        ("aol.com", "yahoo"),
+        ("icloud.com", "apple"),
+        ("me.com", "apple"),
+        ("mac.com", "apple"),
+    ];
+    const HOSTS: &[(&str, &str)] = &[
+        ("google.com", "google"),
+        ("googlemail.com", "google"),
+        ("outlook.com", "microsoft"),
+        ("hotmail.com", "microsoft"),
+        ("yahoodns.net", "yahoo"),
+        ("icloud.com", "apple"),
+        ("pphosted.com", "proofpoint"),
+        ("mimecast.com", "mimecast"),
+    ];
+
+    let domain = recipient
+        .rsplit_once('@')
+        .map_or(recipient, |(_, domain)| domain)
+        .to_lowercase();
+    if let Some((_, provider)) = DOMAINS.iter().find(|(name, _)| *name == domain) {
+        return provider;
+    }
+
+    // Details start with the host that answered the delivery
+    details
+        .and_then(|details| details.split_once(':'))
+        .map(|(host, _)| host.trim().trim_end_matches('.').to_lowercase())
+        .and_then(|host| {
+            HOSTS.iter().find(|(suffix, _)| {
+                host.strip_suffix(suffix)
+                    .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+            })
+        })
+        .map_or("other", |(_, provider)| provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeferralReason, destination_provider};
+
+    #[test]
+    fn classify_deferrals() {
+        for (details, expected) in [
+            (
+                "gmail-smtp-in.l.google.com: Code: 421, Enhanced code: 4.7.28, Message: Our system has detected an unusual rate of unsolicited mail",
+                DeferralReason::RateLimit,
+            ),
+            (
+                "mx.example.org: Code: 554, Enhanced code: 5.7.1, Message: Service unavailable; Client host blocked using zen.spamhaus.org",
+                DeferralReason::Reputation,
+            ),
+            (
+                "mx.example.org: Code: 550, Enhanced code: 5.7.26, Message: Unauthenticated email is not accepted",
+                DeferralReason::Authentication,
+            ),
+            (
+                "mx.example.org: Code: 550, Enhanced code: 5.7.1, Message: Message rejected as spam",
+                DeferralReason::Content,
+            ),
+            (
+                "example.org: Connection failed: Connection refused",
+                DeferralReason::Connection,
+            ),
+            (
+                "mx.example.org: Code: 452, Enhanced code: 4.2.2, Message: Mailbox full",
+                DeferralReason::Other,
+            ),
+        ] {
+            assert_eq!(DeferralReason::classify(details), expected, "{details}");
+        }
+    }
+
+    #[test]
+    fn group_providers() {
+        assert_eq!(destination_provider("jane@GMail.com", None), "google");
+        assert_eq!(
+            destination_provider(
+                "john@example.org",
+                Some("example-org.mail.protection.outlook.com: Code: 250, Message: OK")
+            ),
+            "microsoft"
+        );
+        assert_eq!(
+            destination_provider("john@example.org", Some("mx.notoutlook.com: Code: 250")),
+            "other"
+        );
+        assert_eq!(destination_provider("john@example.org", None), "other");
+    }
+}
@@ -314,8 +314,7 @@ impl MessageWrapper {
 
         // Fetch up to MAX_HEADER_SIZE bytes of message headers
         let headers = match server
-            .blob_store()
-            .get_blob(self.message.blob_hash.as_slice(), 0..MAX_HEADER_SIZE)
+            .get_blob(&self.message.blob_hash, 0..MAX_HEADER_SIZE)
             .await
         {
             Ok(Some(mut buf)) => {
@@ -19,6 +19,7 @@ use store::write::now;
 use types::blob_hash::BlobHash;
 use utils::DomainPart;
 
+pub mod delivery_log;
 pub mod dsn;
 pub mod manager;
 pub mod quota;
@@ -42,7 +43,9 @@ pub struct QueuedMessage {
 
 #[derive(Debug, Clone, Copy)]
 pub enum MessageSource {
-    Authenticated,
+    Authenticated {
+        tenant_id: Option<u32>,
+    },
     Unauthenticated {
         dmarc_pass: bool,
         train_spam: Option<bool>,
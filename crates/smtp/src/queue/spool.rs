@@ -8,6 +8,7 @@ use super::{
     ArchivedMessage, ArchivedStatus, Message, MessageSource, QueueEnvelope, QueueId, QueuedMessage,
     QuotaKey, Recipient, Schedule, Status,
 };
+use crate::queue::delivery_log::DeliveryLog;
 use crate::queue::manager::{LockedMessage, Queue};
 use crate::queue::{
     FROM_AUTHENTICATED, FROM_AUTOGENERATED, FROM_DSN, FROM_REPORT, FROM_UNAUTHENTICATED,
@@ -68,6 +69,11 @@ pub trait SmtpSpool: Sync + Send {
         &self,
         id: QueueId,
     ) -> impl Future<Output = trc::Result<Option<Archive<AlignedBytes>>>> + Send;
+
+    fn read_message_tenant(
+        &self,
+        id: QueueId,
+    ) -> impl Future<Output = trc::Result<Option<u32>>> + Send;
 }
 
 impl SmtpSpool for Server {
@@ -283,6 +289,14 @@ impl SmtpSpool for Server {
             )))
             .await
     }
+
+    async fn read_message_tenant(&self, id: QueueId) -> trc::Result<Option<u32>> {
+        self.store()
+            .get_value::<u32>(ValueKey::from(ValueClass::Queue(
+                QueueClass::MessageTenant(id),
+            )))
+            .await
+    }
 }
 
 fn lock_id(queue_id: QueueId, queue_name: QueueName) -> [u8; 16] {
@@ -302,12 +316,18 @@ impl MessageWrapper {
         source: MessageSource,
     ) -> bool {
         // Set flags
+        let mut tenant_id = None;
         let (flags, event, train_spam) = match source {
-            MessageSource::Authenticated => (
-                FROM_AUTHENTICATED,
-                trc::QueueEvent::QueueMessageAuthenticated,
-                None,
-            ),
+            MessageSource::Authenticated {
+                tenant_id: sender_tenant_id,
+            } => {
+                tenant_id = sender_tenant_id;
+                (
+                    FROM_AUTHENTICATED,
+                    trc::QueueEvent::QueueMessageAuthenticated,
+                    None,
+                )
+            }
             MessageSource::Unauthenticated {
                 dmarc_pass: true,
                 train_spam,
@@ -372,10 +392,24 @@ impl MessageWrapper {
 
             return false;
         }
-        if let Err(err) = server
-            .blob_store()
-            .put_blob(self.message.blob_hash.as_slice(), message.as_ref())
-            .await
+        // Blobs already moved to a tenant's blob store or encrypted keep their location
+        let location = match server.store().blob_location(&self.message.blob_hash).await {
+            Ok(location) => location.filter(|location| !location.is_default()),
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to read blob location.")
+                        .span_id(session_id)
+                        .caused_by(trc::location!())
+                );
+
+                return false;
+            }
+        };
+        if location.is_none()
+            && let Err(err) = server
+                .blob_store()
+                .put_blob(self.message.blob_hash.as_slice(), message.as_ref())
+                .await
         {
             trc::error!(
                 err.details("Failed to write blob.")
@@ -439,6 +473,42 @@ impl MessageWrapper {
             );
         }
 
+        // Stamp the tenant of the sender
+        if let Some(tenant_id) = tenant_id {
+            batch
+                .set(
+                    ValueClass::Queue(QueueClass::TenantIndex {
+                        tenant_id,
+                        queue_id: self.queue_id,
+                    }),
+                    Vec::new(),
+                )
+                .set(
+                    ValueClass::Queue(QueueClass::MessageTenant(self.queue_id)),
+                    tenant_id.to_be_bytes().to_vec(),
+                );
+
+            if server.core.smtp.queue.delivery_log.is_some() {
+                batch.set(
+                    ValueClass::Queue(QueueClass::DeliveryLog {
+                        tenant_id,
+                        queue_id: self.queue_id,
+                    }),
+                    match Archiver::new(DeliveryLog::new(&self, now)).serialize() {
+                        Ok(data) => data,
+                        Err(err) => {
+                            trc::error!(
+                                err.details("Failed to serialize delivery log.")
+                                    .span_id(session_id)
+                                    .caused_by(trc::location!())
+                            );
+                            return false;
+                        }
+                    },
+                );
+            }
+        }
+
         if let Some(is_spam) = train_spam
             && let Some(config) = &server.core.spam.classifier
         {
@@ -486,7 +556,9 @@ impl MessageWrapper {
                 BlobOp::Commit {
                     hash: self.message.blob_hash.clone(),
                 },
-                vec![],
+                location
+                    .map(|location| location.serialize())
+                    .unwrap_or_default(),
             )
             .set(
                 ValueClass::Queue(QueueClass::Message(self.queue_id)),
@@ -689,6 +761,26 @@ impl MessageWrapper {
             }
         }
 
+        // Remove the tenant stamp
+        match server.read_message_tenant(self.queue_id).await {
+            Ok(Some(tenant_id)) => {
+                batch
+                    .clear(ValueClass::Queue(QueueClass::TenantIndex {
+                        tenant_id,
+                        queue_id: self.queue_id,
+                    }))
+                    .clear(ValueClass::Queue(QueueClass::MessageTenant(self.queue_id)));
+            }
+            Ok(None) => {}
+            Err(err) => {
+                trc::error!(
+                    err.details("Failed to read message tenant.")
+                        .span_id(self.span_id)
+                        .caused_by(trc::location!())
+                );
+            }
+        }
+
         // Release all quotas
         for quota_key in self.message.quota_keys {
             match quota_key {
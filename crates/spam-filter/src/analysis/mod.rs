@@ -33,6 +33,7 @@ pub mod replyto;
 pub mod rules;
 pub mod score;
 pub mod subject;
+pub mod tenant;
 pub mod url;
 
 // SPDX-SnippetBegin
@@ -7,18 +7,34 @@
 use crate::{
     SpamFilterContext,
     analysis::{
-        classifier::SpamFilterAnalyzeClassify, date::SpamFilterAnalyzeDate,
-        dmarc::SpamFilterAnalyzeDmarc, domain::SpamFilterAnalyzeDomain,
-        ehlo::SpamFilterAnalyzeEhlo, from::SpamFilterAnalyzeFrom,
-        headers::SpamFilterAnalyzeHeaders, html::SpamFilterAnalyzeHtml, ip::SpamFilterAnalyzeIp,
-        messageid::SpamFilterAnalyzeMid, mime::SpamFilterAnalyzeMime,
-        pyzor::SpamFilterAnalyzePyzor, received::SpamFilterAnalyzeReceived,
-        recipient::SpamFilterAnalyzeRecipient, replyto::SpamFilterAnalyzeReplyTo,
-        rules::SpamFilterAnalyzeRules, subject::SpamFilterAnalyzeSubject,
+        classifier::SpamFilterAnalyzeClassify,
+        date::SpamFilterAnalyzeDate,
+        dmarc::SpamFilterAnalyzeDmarc,
+        domain::SpamFilterAnalyzeDomain,
+        ehlo::SpamFilterAnalyzeEhlo,
+        from::SpamFilterAnalyzeFrom,
+        headers::SpamFilterAnalyzeHeaders,
+        html::SpamFilterAnalyzeHtml,
+        ip::SpamFilterAnalyzeIp,
+        messageid::SpamFilterAnalyzeMid,
+        mime::SpamFilterAnalyzeMime,
+        pyzor::SpamFilterAnalyzePyzor,
+        received::SpamFilterAnalyzeReceived,
+        recipient::SpamFilterAnalyzeRecipient,
+        replyto::SpamFilterAnalyzeReplyTo,
+        rules::SpamFilterAnalyzeRules,
+        subject::SpamFilterAnalyzeSubject,
+        tenant::{
+            SpamFilterAnalyzeTenant, TAG_ALLOWED_SENDER, TAG_BLOCKED_SENDER, TAG_FOREIGN_LANGUAGE,
+            TAG_TRUSTED_SENDER,
+        },
         url::SpamFilterAnalyzeUrl,
     },
 };
-use common::{Server, config::spamfilter::SpamFilterAction};
+use common::{
+    Server,
+    config::spamfilter::{BlockedSenderAction, SpamFilterAction, TenantSpamSettings},
+};
 use std::{fmt::Write, future::Future, vec};
 
 // SPDX-SnippetBegin
@@ -46,6 +62,7 @@ pub struct SpamFilterScore {
     pub headers: String,
     pub train_spam: Option<bool>,
     pub score: f32,
+    pub is_spam: bool,
 }
 
 impl SpamFilterAnalyzeScore for Server {
@@ -58,10 +75,20 @@ impl SpamFilterAnalyzeScore for Server {
         let mut header_len = 60;
         let mut is_spam_trap = false;
         let mut rbl_count = 0;
+        let tenant = ctx.input.tenant.as_deref();
+        let spam_threshold = tenant
+            .and_then(|tenant| tenant.spam_threshold)
+            .unwrap_or(self.core.spam.scores.spam_threshold);
+        let reject_threshold = tenant
+            .and_then(|tenant| tenant.reject_threshold)
+            .unwrap_or(self.core.spam.scores.reject_threshold);
+        let is_trusted =
+            ctx.result.has_tag(TAG_TRUSTED_SENDER) || ctx.result.has_tag(TAG_ALLOWED_SENDER);
+        let is_blocked = ctx.result.has_tag(TAG_BLOCKED_SENDER);
 
         for tag in &ctx.result.tags {
-            let score = match self.core.spam.lists.scores.get(tag) {
-                Some(SpamFilterAction::Allow(score)) => *score,
+            let score = match tag_action(self, tenant, tag) {
+                Some(SpamFilterAction::Allow(score)) => score,
                 Some(SpamFilterAction::Discard) => {
                     return SpamFilterAction::Discard;
                 }
@@ -82,13 +109,21 @@ impl SpamFilterAnalyzeScore for Server {
             }
         }
 
+        // Blocked senders are rejected unless the tenant quarantines them
+        if is_blocked
+            && tenant
+                .and_then(|tenant| tenant.blocked_sender_action)
+                .unwrap_or_default()
+                == BlockedSenderAction::Reject
+        {
+            return SpamFilterAction::Reject;
+        }
+
         let mut final_score = ctx.result.score;
         let mut avg_confidence: f32 = 0.0;
         let mut total_results = 0;
-        let mut user_results = vec![
-            ctx.result.score >= self.core.spam.scores.spam_threshold;
-            ctx.input.env_rcpt_to.len()
-        ];
+        let mut user_results =
+            vec![!is_trusted && ctx.result.score >= spam_threshold; ctx.input.env_rcpt_to.len()];
         if !ctx.result.classifier_confidence.is_empty() {
             for (idx, &confidence) in ctx.result.classifier_confidence.iter().enumerate() {
                 if let Some(confidence) = confidence {
@@ -106,7 +141,7 @@ impl SpamFilterAnalyzeScore for Server {
                         .unwrap_or_default();
 
                     user_results[idx] =
-                        ctx.result.score + user_score >= self.core.spam.scores.spam_threshold;
+                        !is_trusted && ctx.result.score + user_score >= spam_threshold;
                 }
             }
 
@@ -128,11 +163,10 @@ impl SpamFilterAnalyzeScore for Server {
             }
         }
 
-        if self.core.spam.scores.reject_threshold > 0.0
-            && final_score >= self.core.spam.scores.reject_threshold
-        {
+        if !is_trusted && reject_threshold > 0.0 && final_score >= reject_threshold {
             SpamFilterAction::Reject
-        } else if self.core.spam.scores.discard_threshold > 0.0
+        } else if !is_trusted
+            && self.core.spam.scores.discard_threshold > 0.0
             && final_score >= self.core.spam.scores.discard_threshold
         {
             SpamFilterAction::Discard
@@ -152,7 +186,10 @@ impl SpamFilterAnalyzeScore for Server {
                 let _ = write!(&mut headers, "X-Spam-LLM: {category} ({explanation})\r\n",);
             }
 
-            let is_spam = final_score >= self.core.spam.scores.spam_threshold;
+            let is_spam = is_blocked || (!is_trusted && final_score >= spam_threshold);
+            if is_blocked {
+                user_results.fill(true);
+            }
             let class = if is_spam { "spam" } else { "ham" };
 
             if avg_confidence != 0.0 {
@@ -179,11 +216,17 @@ impl SpamFilterAnalyzeScore for Server {
                 train_spam = Some(true);
             }
 
+            // Tenants may opt out of the X-Spam headers
+            if tenant.and_then(|tenant| tenant.add_headers) == Some(false) {
+                headers.clear();
+            }
+
             SpamFilterAction::Allow(SpamFilterScore {
                 results: user_results,
                 headers,
                 train_spam,
                 score: final_score,
+                is_spam,
             })
         }
     }
@@ -259,11 +302,30 @@ impl SpamFilterAnalyzeScore for Server {
         // User-defined rules
         self.spam_filter_analyze_rules(ctx).await;
 
+        // Tenant trusted senders and language preferences
+        self.spam_filter_analyze_tenant(ctx).await;
+
         // Final score calculation
         self.spam_filter_finalize(ctx).await
     }
 }
 
+/// Returns the action of a tag, applying the overrides of the tenant.
+pub fn tag_action(
+    server: &Server,
+    tenant: Option<&TenantSpamSettings>,
+    tag: &str,
+) -> Option<SpamFilterAction<f32>> {
+    match tenant {
+        Some(tenant) if tag == TAG_FOREIGN_LANGUAGE => Some(SpamFilterAction::Allow(
+            tenant
+                .language_score
+                .unwrap_or(TenantSpamSettings::DEFAULT_LANGUAGE_SCORE),
+        )),
+        _ => server.core.spam.lists.scores.get(tag).cloned(),
+    }
+}
+
 pub trait ConfidenceStore {
     fn spam_tag(&self) -> &'static str;
 }
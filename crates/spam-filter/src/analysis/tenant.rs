@@ -0,0 +1,68 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::Server;
+use mail_auth::DmarcResult;
+use nlp::language::detect::LanguageDetector;
+use store::write::now;
+
+use crate::SpamFilterContext;
+
+pub const TAG_TRUSTED_SENDER: &str = "TENANT_TRUSTED_SENDER";
+pub const TAG_FOREIGN_LANGUAGE: &str = "TENANT_FOREIGN_LANGUAGE";
+pub const TAG_ALLOWED_SENDER: &str = "TENANT_ALLOWED_SENDER";
+pub const TAG_BLOCKED_SENDER: &str = "TENANT_BLOCKED_SENDER";
+
+const MIN_LANGUAGE_CONFIDENCE: f64 = 0.5;
+
+pub trait SpamFilterAnalyzeTenant: Sync + Send {
+    fn spam_filter_analyze_tenant(
+        &self,
+        ctx: &mut SpamFilterContext<'_>,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl SpamFilterAnalyzeTenant for Server {
+    async fn spam_filter_analyze_tenant(&self, ctx: &mut SpamFilterContext<'_>) {
+        // Sender lists are matched against both the envelope and header sender,
+        // entries in the block list take precedence
+        if let Some(senders) = ctx.input.senders.clone() {
+            let from = ctx.output.from.email.address.to_lowercase();
+            let addresses = [ctx.input.env_from, from.as_str()];
+            let addresses = addresses.iter().copied().filter(|addr| !addr.is_empty());
+            let remote_ip = Some(ctx.input.remote_ip);
+            let now = now();
+
+            if senders.block.matches(addresses.clone(), remote_ip, now) {
+                ctx.result.add_tag(TAG_BLOCKED_SENDER);
+            } else if senders.allow.matches(addresses, remote_ip, now) {
+                ctx.result.add_tag(TAG_ALLOWED_SENDER);
+            }
+        }
+
+        let Some(tenant) = ctx.input.tenant.clone() else {
+            return;
+        };
+
+        // Trusted senders have to pass DMARC to prevent spoofing
+        if matches!(ctx.input.dmarc_result, Some(DmarcResult::Pass))
+            && tenant.is_trusted_domain(&ctx.output.from.email.domain_part.fqdn)
+        {
+            ctx.result.add_tag(TAG_TRUSTED_SENDER);
+        }
+
+        if !tenant.languages.is_empty()
+            && let Some((language, confidence)) =
+                ctx.text_body().and_then(LanguageDetector::detect_single)
+            && confidence >= MIN_LANGUAGE_CONFIDENCE
+            && tenant.is_foreign_language(language)
+        {
+            ctx.result.add_tag(TAG_FOREIGN_LANGUAGE);
+        }
+    }
+}
@@ -9,6 +9,7 @@ pub mod modules;
 
 use analysis::ElementLocation;
 use analysis::url::UrlParts;
+use common::config::spamfilter::{TenantSenderLists, TenantSpamSettings};
 use mail_auth::{ArcOutput, DkimOutput, DmarcResult, IprevOutput, SpfOutput, dmarc::Policy};
 use mail_parser::Message;
 use modules::html::HtmlToken;
@@ -17,6 +18,7 @@ use std::borrow::Cow;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use store::ahash::AHashSet;
 
 pub struct SpamFilterInput<'x> {
@@ -47,6 +49,10 @@ pub struct SpamFilterInput<'x> {
     pub env_from_flags: u64,
     pub env_rcpt_to: Vec<&'x str>,
 
+    // Settings of the tenant the recipients belong to
+    pub tenant: Option<Arc<TenantSpamSettings>>,
+    pub senders: Option<Arc<TenantSenderLists>>,
+
     pub is_train: bool,
     pub is_test: bool,
 }
@@ -155,6 +161,8 @@ impl<'x> SpamFilterInput<'x> {
             env_from: "",
             env_from_flags: 0,
             env_rcpt_to: vec![],
+            tenant: None,
+            senders: None,
             is_test: false,
             is_train: false,
         }
@@ -341,8 +341,7 @@ impl SpamClassifier for Server {
                     None
                 };
                 let Some(raw_message) = self
-                    .blob_store()
-                    .get_blob(sample.sample.hash.as_slice(), 0..usize::MAX)
+                    .get_blob(&sample.sample.hash, 0..usize::MAX)
                     .await
                     .caused_by(trc::location!())?
                 else {
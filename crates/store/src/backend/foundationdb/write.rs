@@ -190,6 +190,10 @@ impl FdbStore {
                                             is_payload: true,
                                             ..
                                         })
+                                        | ValueClass::TaskQueue(TaskQueueClass::Journal {
+                                            is_payload: true,
+                                            ..
+                                        })
                                         | ValueClass::InMemory(_)
                                 ) {
                                     trx.clear_range(
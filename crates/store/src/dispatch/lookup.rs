@@ -390,6 +390,41 @@ impl InMemoryStore {
         }
     }
 
+    /// Extends the expiration of a lock obtained with `try_lock`, for locks
+    /// held by long running operations.
+    pub async fn refresh_lock(&self, prefix: u8, key: &[u8], duration: u64) -> trc::Result<()> {
+        match self {
+            InMemoryStore::Store(store) => {
+                let mut batch = BatchBuilder::new();
+                batch.set(
+                    ValueClass::InMemory(InMemoryClass::Key(KeyValue::<()>::build_key(
+                        prefix, key,
+                    ))),
+                    (now() + duration).serialize(),
+                );
+                store.write(batch.build_all()).await.map(|_| ())
+            }
+            #[cfg(feature = "redis")]
+            InMemoryStore::Redis(store) => store
+                .key_incr(&KeyValue::<()>::build_key(prefix, key), 0, duration.into())
+                .await
+                .map(|_| ()),
+            // SPDX-SnippetBegin
+            // SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+            // SPDX-License-Identifier: LicenseRef-SEL
+            #[cfg(feature = "enterprise")]
+            InMemoryStore::Sharded(store) => store
+                .counter_incr(KeyValue::with_prefix(prefix, key, 0).expires(duration))
+                .await
+                .map(|_| ()),
+            // SPDX-SnippetEnd
+            InMemoryStore::Static(_) | InMemoryStore::Http(_) => {
+                Err(trc::StoreEvent::NotSupported.into_err())
+            }
+        }
+        .caused_by(trc::location!())
+    }
+
     pub async fn remove_lock(&self, prefix: u8, key: &[u8]) -> trc::Result<()> {
         self.key_delete(KeyValue::<()>::build_key(prefix, key))
             .await
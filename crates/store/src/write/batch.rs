@@ -5,8 +5,8 @@
  */
 
 use super::{
-    Batch, BatchBuilder, ChangedCollection, IntoOperations, Operation, ValueClass, ValueOp,
-    assert::ToAssertValue, log::VanishedItem,
+    Batch, BatchBuilder, ChangedCollection, DirectoryClass, IntoOperations, Operation, ValueClass,
+    ValueOp, assert::ToAssertValue, log::VanishedItem,
 };
 use crate::{
     SerializeInfallible, U32_LEN,
@@ -452,6 +452,17 @@ impl BatchBuilder {
         self.ops.as_slice()
     }
 
+    /// Returns the principals whose used quota is updated by the batch.
+    pub fn changed_quotas(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ops.iter().filter_map(|op| match op {
+            Operation::Value {
+                class: ValueClass::Directory(DirectoryClass::UsedQuota(id)),
+                op: ValueOp::AtomicAdd(_),
+            } => Some(*id),
+            _ => None,
+        })
+    }
+
     pub fn len(&self) -> usize {
         self.batch_size
     }
@@ -8,9 +8,11 @@ use std::time::Instant;
 
 use super::{BlobOp, Operation, ValueClass, ValueOp, key::DeserializeBigEndian, now};
 use crate::{
-    BlobStore, IterateParams, Store, U32_LEN, U64_LEN, ValueKey,
+    BlobStore, Deserialize, IterateParams, Store, U32_LEN, U64_LEN, ValueKey,
     write::{BatchBuilder, BlobLink},
 };
+use ahash::AHashMap;
+use roaring::RoaringBitmap;
 use trc::{AddContext, PurgeEvent};
 use types::{
     blob::BlobClass,
@@ -23,6 +25,51 @@ pub struct BlobQuota {
     pub count: usize,
 }
 
+/// Location of a committed blob, stored as the value of its commit key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlobLocation {
+    /// Blob store the blob was written to, empty for the default blob store.
+    pub store_id: String,
+    /// Tenant whose data key the blob contents are encrypted with.
+    pub encryption_key: Option<u32>,
+}
+
+impl BlobLocation {
+    const ENCRYPTED_MARKER: u8 = 0;
+
+    pub fn is_default(&self) -> bool {
+        self.store_id.is_empty() && self.encryption_key.is_none()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        if let Some(tenant_id) = self.encryption_key {
+            let mut bytes = Vec::with_capacity(1 + U32_LEN + self.store_id.len());
+            bytes.push(Self::ENCRYPTED_MARKER);
+            bytes.extend_from_slice(&tenant_id.to_be_bytes());
+            bytes.extend_from_slice(self.store_id.as_bytes());
+            bytes
+        } else {
+            self.store_id.as_bytes().to_vec()
+        }
+    }
+}
+
+impl Deserialize for BlobLocation {
+    fn deserialize(bytes: &[u8]) -> trc::Result<Self> {
+        match bytes.split_first() {
+            Some((&Self::ENCRYPTED_MARKER, bytes)) => Ok(BlobLocation {
+                encryption_key: Some(bytes.deserialize_be_u32(0)?),
+                store_id: String::from_utf8_lossy(bytes.get(U32_LEN..).unwrap_or_default())
+                    .into_owned(),
+            }),
+            _ => Ok(BlobLocation {
+                store_id: String::from_utf8_lossy(bytes).into_owned(),
+                encryption_key: None,
+            }),
+        }
+    }
+}
+
 impl Store {
     pub async fn blob_exists(&self, hash: impl AsRef<BlobHash> + Sync + Send) -> trc::Result<bool> {
         self.get_value::<()>(ValueKey {
@@ -38,6 +85,23 @@ impl Store {
         .caused_by(trc::location!())
     }
 
+    /// Returns the location of a committed blob.
+    pub async fn blob_location(
+        &self,
+        hash: impl AsRef<BlobHash> + Sync + Send,
+    ) -> trc::Result<Option<BlobLocation>> {
+        self.get_value::<BlobLocation>(ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Blob(BlobOp::Commit {
+                hash: hash.as_ref().clone(),
+            }),
+        })
+        .await
+        .caused_by(trc::location!())
+    }
+
     pub async fn blob_quota(&self, account_id: u32) -> trc::Result<BlobQuota> {
         let from_key = ValueKey {
             account_id,
@@ -118,7 +182,110 @@ impl Store {
         self.get_value::<()>(key).await.map(|v| v.is_some())
     }
 
-    pub async fn purge_blobs(&self, blob_store: BlobStore) -> trc::Result<()> {
+    /// Returns whether a blob is linked to documents of other accounts or
+    /// to a non-document object, such as a queued message.
+    pub async fn blob_is_shared(
+        &self,
+        hash: impl AsRef<BlobHash> + Sync + Send,
+        account_id: u32,
+    ) -> trc::Result<bool> {
+        const DOC_LINK: usize = BLOB_HASH_LEN + U64_LEN + 1;
+        const ID_LINK: usize = BLOB_HASH_LEN + U64_LEN;
+
+        let mut is_shared = false;
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id: 0,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::Blob(BlobOp::Commit {
+                        hash: hash.as_ref().clone(),
+                    }),
+                },
+                ValueKey {
+                    account_id: u32::MAX,
+                    collection: u8::MAX,
+                    document_id: u32::MAX,
+                    class: ValueClass::Blob(BlobOp::Link {
+                        hash: hash.as_ref().clone(),
+                        to: BlobLink::Document,
+                    }),
+                },
+            )
+            .ascending()
+            .no_values(),
+            |key, _| {
+                is_shared = match key.len() {
+                    DOC_LINK => key.deserialize_be_u32(BLOB_HASH_LEN)? != account_id,
+                    ID_LINK => true,
+                    _ => false,
+                };
+                Ok(!is_shared)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        Ok(is_shared)
+    }
+
+    /// Returns whether a blob is linked to accounts other than `account_ids`
+    /// or to a non-account object, such as a queued message.
+    pub async fn blob_is_linked_outside(
+        &self,
+        hash: impl AsRef<BlobHash> + Sync + Send,
+        account_ids: &RoaringBitmap,
+    ) -> trc::Result<bool> {
+        const ID_LINK: usize = BLOB_HASH_LEN + U64_LEN;
+        const DOC_LINK: usize = BLOB_HASH_LEN + U64_LEN + 1;
+        const TEMP_LINK: usize = BLOB_HASH_LEN + U32_LEN + U64_LEN;
+
+        let mut is_outside = false;
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id: 0,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::Blob(BlobOp::Commit {
+                        hash: hash.as_ref().clone(),
+                    }),
+                },
+                ValueKey {
+                    account_id: u32::MAX,
+                    collection: u8::MAX,
+                    document_id: u32::MAX,
+                    class: ValueClass::Blob(BlobOp::Link {
+                        hash: hash.as_ref().clone(),
+                        to: BlobLink::Temporary { until: u64::MAX },
+                    }),
+                },
+            )
+            .ascending()
+            .no_values(),
+            |key, _| {
+                is_outside = match key.len() {
+                    DOC_LINK | TEMP_LINK => {
+                        !account_ids.contains(key.deserialize_be_u32(BLOB_HASH_LEN)?)
+                    }
+                    ID_LINK => true,
+                    _ => false,
+                };
+                Ok(!is_outside)
+            },
+        )
+        .await
+        .caused_by(trc::location!())?;
+
+        Ok(is_outside)
+    }
+
+    pub async fn purge_blobs(
+        &self,
+        blob_store: BlobStore,
+        blob_stores: &AHashMap<String, BlobStore>,
+    ) -> trc::Result<()> {
         let mut total_active = 0;
         let mut total_deleted = 0;
         let started = Instant::now();
@@ -169,7 +336,12 @@ impl Store {
             // Delete expired or unlinked blobs
             for (_, op) in &state.delete_keys {
                 if let BlobOp::Commit { hash } = op {
-                    blob_store
+                    // Blobs are deleted from the store they were written to
+                    state
+                        .locations
+                        .get(hash)
+                        .and_then(|location| blob_stores.get(location))
+                        .unwrap_or(&blob_store)
                         .delete_blob(hash.as_ref())
                         .await
                         .caused_by(trc::location!())?;
@@ -220,6 +392,8 @@ struct BlobPurgeState {
     last_hash: BlobHash,
     last_hash_is_linked: bool,
     delete_keys: Vec<(Option<u32>, BlobOp)>,
+    last_location: Option<String>,
+    locations: AHashMap<BlobHash, String>,
     spam_train_samples: Vec<(u32, u64)>,
     now: u64,
     total_deleted: u64,
@@ -232,6 +406,8 @@ impl BlobPurgeState {
             last_hash: BlobHash::default(),
             last_hash_is_linked: true, // Avoid deleting non-existing last_hash on first iteration
             delete_keys: Vec::new(),
+            last_location: None,
+            locations: AHashMap::new(),
             spam_train_samples: Vec::new(),
             now: now(),
             total_deleted: 0,
@@ -243,12 +419,16 @@ impl BlobPurgeState {
         if self.last_hash != hash {
             self.finalize(hash);
             self.last_hash_is_linked = false;
+            self.last_location = None;
         }
     }
 
     pub fn finalize(&mut self, new_hash: BlobHash) {
         if !self.last_hash_is_linked {
             self.total_deleted += 1;
+            if let Some(location) = self.last_location.take() {
+                self.locations.insert(self.last_hash.clone(), location);
+            }
             self.delete_keys.push((
                 None,
                 BlobOp::Commit {
@@ -304,7 +484,10 @@ impl BlobPurgeState {
 
         match key.len() {
             BLOB_HASH_LEN => {
-                // Main blob entry
+                // Main blob entry, its value is the store the blob was written to
+                if !value.is_empty() {
+                    self.last_location = Some(BlobLocation::deserialize(value)?.store_id);
+                }
                 Ok(())
             }
             TEMP_LINK => {
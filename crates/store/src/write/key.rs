@@ -337,6 +337,22 @@ impl ValueClass {
                     .write(account_id)
                     .write(9u8)
                     .write(document_id),
+                TaskQueueClass::Journal { due, is_payload } => {
+                    if !*is_payload {
+                        serializer
+                            .write(due.inner())
+                            .write(account_id)
+                            .write(10u8)
+                            .write(document_id)
+                    } else {
+                        serializer
+                            .write(u64::MAX)
+                            .write(account_id)
+                            .write(11u8)
+                            .write(document_id)
+                            .write(due.inner())
+                    }
+                }
             },
             ValueClass::Blob(op) => match op {
                 BlobOp::Commit { hash } => serializer.write::<&[u8]>(hash.as_ref()),
@@ -376,8 +392,13 @@ impl ValueClass {
             ValueClass::Directory(directory) => match directory {
                 DirectoryClass::NameToId(name) => serializer.write(0u8).write(name.as_slice()),
                 DirectoryClass::EmailToId(email) => serializer.write(1u8).write(email.as_slice()),
+                DirectoryClass::ExternalIdToId(id) => serializer.write(9u8).write(id.as_slice()),
                 DirectoryClass::Principal(uid) => serializer.write(2u8).write_leb128(*uid),
                 DirectoryClass::UsedQuota(uid) => serializer.write(4u8).write_leb128(*uid),
+                DirectoryClass::MessageCount(uid) => serializer.write(10u8).write_leb128(*uid),
+                DirectoryClass::PrincipalCount { tenant_id, typ } => {
+                    serializer.write(8u8).write(*tenant_id).write(*typ)
+                }
                 DirectoryClass::MemberOf {
                     principal_id,
                     member_of,
@@ -428,6 +449,15 @@ impl ValueClass {
                     .write(event.seq_id),
                 QueueClass::QuotaCount(key) => serializer.write(0u8).write(key.as_slice()),
                 QueueClass::QuotaSize(key) => serializer.write(1u8).write(key.as_slice()),
+                QueueClass::TenantIndex {
+                    tenant_id,
+                    queue_id,
+                } => serializer.write(3u8).write(*tenant_id).write(*queue_id),
+                QueueClass::MessageTenant(queue_id) => serializer.write(4u8).write(*queue_id),
+                QueueClass::DeliveryLog {
+                    tenant_id,
+                    queue_id,
+                } => serializer.write(5u8).write(*tenant_id).write(*queue_id),
             },
             ValueClass::Report(report) => match report {
                 ReportClass::Tls { id, expires } => {
@@ -450,6 +480,36 @@ impl ValueClass {
                     .write(*timestamp)
                     .write_leb128(*metric_id)
                     .write_leb128(*node_id),
+                TelemetryClass::AuditEvent { event_id } => {
+                    serializer.write(u64::MAX).write(0u8).write(*event_id)
+                }
+                TelemetryClass::AuditIndex {
+                    field,
+                    value,
+                    event_id,
+                } => {
+                    let value = &value[..value.len().min(u8::MAX as usize)];
+                    serializer
+                        .write(u64::MAX)
+                        .write(1u8)
+                        .write(*field)
+                        .write(value.len() as u8)
+                        .write(value)
+                        .write(*event_id)
+                }
+                TelemetryClass::MeteringSample {
+                    timestamp,
+                    tenant_id,
+                    node_id,
+                } => serializer
+                    .write(u64::MAX)
+                    .write(2u8)
+                    .write(*timestamp)
+                    .write(*tenant_id)
+                    .write(*node_id),
+                TelemetryClass::MeteringCursor { node_id } => {
+                    serializer.write(u64::MAX).write(3u8).write(*node_id)
+                }
             },
             ValueClass::DocumentId => serializer.write(account_id).write(collection),
             ValueClass::ChangeId => serializer.write(account_id),
@@ -580,8 +640,13 @@ impl ValueClass {
             ValueClass::InMemory(InMemoryClass::Counter(v) | InMemoryClass::Key(v))
             | ValueClass::Config(v) => v.len(),
             ValueClass::Directory(d) => match d {
-                DirectoryClass::NameToId(v) | DirectoryClass::EmailToId(v) => v.len(),
-                DirectoryClass::Principal(_) | DirectoryClass::UsedQuota(_) => U32_LEN,
+                DirectoryClass::NameToId(v)
+                | DirectoryClass::EmailToId(v)
+                | DirectoryClass::ExternalIdToId(v) => v.len(),
+                DirectoryClass::Principal(_)
+                | DirectoryClass::UsedQuota(_)
+                | DirectoryClass::MessageCount(_) => U32_LEN,
+                DirectoryClass::PrincipalCount { .. } => U32_LEN + 1,
                 DirectoryClass::Members { .. } | DirectoryClass::MemberOf { .. } => U32_LEN * 2,
                 DirectoryClass::Index { word, .. } => word.len() + U32_LEN,
             },
@@ -605,7 +670,8 @@ impl ValueClass {
                 TaskQueueClass::SendAlarm { .. } | TaskQueueClass::MergeThreads { .. } => {
                     U64_LEN + (U32_LEN * 3) + 1
                 }
-                TaskQueueClass::SendImip { is_payload, .. } => {
+                TaskQueueClass::SendImip { is_payload, .. }
+                | TaskQueueClass::Journal { is_payload, .. } => {
                     if *is_payload {
                         (U64_LEN * 2) + (U32_LEN * 2) + 1
                     } else {
@@ -623,11 +689,19 @@ impl ValueClass {
                     event.domain.len() + (U64_LEN * 3) + 1
                 }
                 QueueClass::QuotaCount(v) | QueueClass::QuotaSize(v) => v.len(),
+                QueueClass::TenantIndex { .. } | QueueClass::DeliveryLog { .. } => {
+                    U32_LEN + U64_LEN + 1
+                }
+                QueueClass::MessageTenant(_) => U64_LEN + 1,
             },
             ValueClass::Report(_) => U64_LEN * 2 + 1,
             ValueClass::Telemetry(telemetry) => match telemetry {
                 TelemetryClass::Span { .. } => U64_LEN + 1,
                 TelemetryClass::Metric { .. } => U64_LEN * 2 + 1,
+                TelemetryClass::AuditEvent { .. } => U64_LEN * 2 + 1,
+                TelemetryClass::AuditIndex { value, .. } => U64_LEN * 2 + value.len() + 3,
+                TelemetryClass::MeteringSample { .. } => U64_LEN * 3 + U32_LEN + 1,
+                TelemetryClass::MeteringCursor { .. } => U64_LEN * 2 + 1,
             },
             ValueClass::DocumentId => U32_LEN + 1,
             ValueClass::ChangeId => U32_LEN,
@@ -668,7 +742,9 @@ impl ValueClass {
                 InMemoryClass::Counter(_) => SUBSPACE_IN_MEMORY_COUNTER,
             },
             ValueClass::Directory(directory) => match directory {
-                DirectoryClass::UsedQuota(_) => SUBSPACE_QUOTA,
+                DirectoryClass::UsedQuota(_)
+                | DirectoryClass::MessageCount(_)
+                | DirectoryClass::PrincipalCount { .. } => SUBSPACE_QUOTA,
                 _ => SUBSPACE_DIRECTORY,
             },
             ValueClass::Queue(queue) => match queue {
@@ -677,12 +753,19 @@ impl ValueClass {
                 QueueClass::DmarcReportHeader(_)
                 | QueueClass::TlsReportHeader(_)
                 | QueueClass::DmarcReportEvent(_)
-                | QueueClass::TlsReportEvent(_) => SUBSPACE_REPORT_OUT,
+                | QueueClass::TlsReportEvent(_)
+                | QueueClass::TenantIndex { .. }
+                | QueueClass::MessageTenant(_)
+                | QueueClass::DeliveryLog { .. } => SUBSPACE_REPORT_OUT,
                 QueueClass::QuotaCount(_) | QueueClass::QuotaSize(_) => SUBSPACE_QUOTA,
             },
             ValueClass::Report(_) => SUBSPACE_REPORT_IN,
             ValueClass::Telemetry(telemetry) => match telemetry {
-                TelemetryClass::Span { .. } => SUBSPACE_TELEMETRY_SPAN,
+                TelemetryClass::Span { .. }
+                | TelemetryClass::AuditEvent { .. }
+                | TelemetryClass::AuditIndex { .. }
+                | TelemetryClass::MeteringSample { .. }
+                | TelemetryClass::MeteringCursor { .. } => SUBSPACE_TELEMETRY_SPAN,
                 TelemetryClass::Metric { .. } => SUBSPACE_TELEMETRY_METRIC,
             },
             ValueClass::DocumentId | ValueClass::ChangeId => SUBSPACE_COUNTER,
@@ -694,7 +777,11 @@ impl ValueClass {
 
     pub fn is_counter(&self, collection: u8) -> bool {
         match self {
-            ValueClass::Directory(DirectoryClass::UsedQuota(_))
+            ValueClass::Directory(
+                DirectoryClass::UsedQuota(_)
+                | DirectoryClass::MessageCount(_)
+                | DirectoryClass::PrincipalCount { .. },
+            )
             | ValueClass::InMemory(InMemoryClass::Counter(_))
             | ValueClass::Queue(QueueClass::QuotaCount(_) | QueueClass::QuotaSize(_))
             | ValueClass::DocumentId
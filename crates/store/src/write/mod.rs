@@ -239,6 +239,12 @@ pub enum TaskQueueClass {
     MergeThreads {
         due: TaskEpoch,
     },
+    /// Copy of a message kept for a tenant's journal, the account id is the
+    /// tenant id.
+    Journal {
+        due: TaskEpoch,
+        is_payload: bool,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
@@ -271,11 +277,14 @@ pub enum InMemoryClass {
 pub enum DirectoryClass {
     NameToId(Vec<u8>),
     EmailToId(Vec<u8>),
+    ExternalIdToId(Vec<u8>),
     Index { word: Vec<u8>, principal_id: u32 },
     MemberOf { principal_id: u32, member_of: u32 },
     Members { principal_id: u32, has_member: u32 },
     Principal(u32),
     UsedQuota(u32),
+    MessageCount(u32),
+    PrincipalCount { tenant_id: u32, typ: u8 },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -288,6 +297,9 @@ pub enum QueueClass {
     TlsReportEvent(ReportEvent),
     QuotaCount(Vec<u8>),
     QuotaSize(Vec<u8>),
+    TenantIndex { tenant_id: u32, queue_id: u64 },
+    MessageTenant(u64),
+    DeliveryLog { tenant_id: u32, queue_id: u64 },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -307,6 +319,25 @@ pub enum TelemetryClass {
         metric_id: u64,
         node_id: u64,
     },
+    // Audit keys share the span subspace and are prefixed with u64::MAX
+    // so they sort after every span id.
+    AuditEvent {
+        event_id: u64,
+    },
+    AuditIndex {
+        field: u8,
+        value: Vec<u8>,
+        event_id: u64,
+    },
+    // Metering samples are kept apart from metrics so they have their own retention
+    MeteringSample {
+        timestamp: u64,
+        tenant_id: u32,
+        node_id: u64,
+    },
+    MeteringCursor {
+        node_id: u64,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -54,6 +54,8 @@ impl EventType {
             EventType::Ai(event) => event.description(),
             EventType::WebDav(event) => event.description(),
             EventType::Calendar(event) => event.description(),
+            EventType::Provision(event) => event.description(),
+            EventType::Directory(event) => event.description(),
         }
     }
 
@@ -104,6 +106,8 @@ impl EventType {
             EventType::Ai(event) => event.explain(),
             EventType::WebDav(event) => event.explain(),
             EventType::Calendar(event) => event.explain(),
+            EventType::Provision(event) => event.explain(),
+            EventType::Directory(event) => event.explain(),
         }
     }
 }
@@ -116,6 +120,8 @@ impl HttpEvent {
             HttpEvent::RequestBody => "HTTP request body",
             HttpEvent::ResponseBody => "HTTP response body",
             HttpEvent::XForwardedMissing => "X-Forwarded-For header is missing",
+            HttpEvent::SlowRequest => "Slow HTTP request",
+            HttpEvent::ManagementWrite => "Management API write request",
             HttpEvent::ConnectionStart => "HTTP connection started",
             HttpEvent::ConnectionEnd => "HTTP connection ended",
         }
@@ -128,6 +134,10 @@ impl HttpEvent {
             HttpEvent::RequestBody => "The body of an HTTP request",
             HttpEvent::ResponseBody => "The body of an HTTP response",
             HttpEvent::XForwardedMissing => "The X-Forwarded-For header is missing",
+            HttpEvent::SlowRequest => {
+                "A management API request took longer than the configured threshold"
+            }
+            HttpEvent::ManagementWrite => "A management API request modified server state",
             HttpEvent::ConnectionStart => "An HTTP connection was started",
             HttpEvent::ConnectionEnd => "An HTTP connection was ended",
         }
@@ -197,6 +207,18 @@ impl TaskQueueEvent {
             TaskQueueEvent::MetadataNotFound => "Metadata not found for task",
             TaskQueueEvent::TaskIgnored => "Task ignored based on current server roles",
             TaskQueueEvent::TaskFailed => "Task failed during processing",
+            TaskQueueEvent::ReindexCompleted => "Full-text reindex completed",
+            TaskQueueEvent::ReindexFailed => "Full-text reindex failed",
+            TaskQueueEvent::BlobMigrationCompleted => "Blob migration completed",
+            TaskQueueEvent::BlobMigrationFailed => "Blob migration failed",
+            TaskQueueEvent::BackupCompleted => "Tenant backup completed",
+            TaskQueueEvent::BackupFailed => "Tenant backup failed",
+            TaskQueueEvent::RestoreCompleted => "Tenant restore completed",
+            TaskQueueEvent::RestoreFailed => "Tenant restore failed",
+            TaskQueueEvent::ExportCompleted => "Account export completed",
+            TaskQueueEvent::ExportFailed => "Account export failed",
+            TaskQueueEvent::JournalDelivered => "Journal copy delivered",
+            TaskQueueEvent::JournalFailed => "Journal copy delivery failed",
         }
     }
 
@@ -208,6 +230,36 @@ impl TaskQueueEvent {
             TaskQueueEvent::MetadataNotFound => "The metadata was not found for task",
             TaskQueueEvent::TaskIgnored => "The task was ignored based on the current server roles",
             TaskQueueEvent::TaskFailed => "The task failed during processing",
+            TaskQueueEvent::ReindexCompleted => {
+                "The messages of an account or tenant have been reindexed"
+            }
+            TaskQueueEvent::ReindexFailed => {
+                "A full-text reindex job was interrupted and will resume on restart"
+            }
+            TaskQueueEvent::BlobMigrationCompleted => {
+                "The blobs of a tenant have been moved to its assigned blob store"
+            }
+            TaskQueueEvent::BlobMigrationFailed => {
+                "The blobs of a tenant could not be moved to its assigned blob store"
+            }
+            TaskQueueEvent::BackupCompleted => {
+                "A snapshot of a tenant has been written to the backup store"
+            }
+            TaskQueueEvent::BackupFailed => "A snapshot of a tenant could not be written",
+            TaskQueueEvent::RestoreCompleted => "A tenant has been restored from a backup",
+            TaskQueueEvent::RestoreFailed => "A tenant could not be restored from a backup",
+            TaskQueueEvent::ExportCompleted => {
+                "An archive of an account's data is ready for download"
+            }
+            TaskQueueEvent::ExportFailed => {
+                "An archive of an account's data could not be generated"
+            }
+            TaskQueueEvent::JournalDelivered => {
+                "A copy of a message has been delivered to a tenant's journal target"
+            }
+            TaskQueueEvent::JournalFailed => {
+                "A copy of a message could not be delivered to a tenant's journal target and will be retried"
+            }
         }
     }
 }
@@ -439,8 +491,11 @@ impl SmtpEvent {
             SmtpEvent::RcptTo => "SMTP RCPT TO command",
             SmtpEvent::RcptToDuplicate => "Duplicate RCPT TO",
             SmtpEvent::RcptToRewritten => "RCPT TO address rewritten",
+            SmtpEvent::RcptToRouted => "RCPT TO matched a domain route",
             SmtpEvent::RcptToMissing => "RCPT TO address missing",
             SmtpEvent::RcptToGreylisted => "RCPT TO greylisted",
+            SmtpEvent::RcptToMaintenance => "RCPT TO deferred for maintenance",
+            SmtpEvent::RcptToPendingActivation => "RCPT TO deferred pending activation",
             SmtpEvent::TooManyRecipients => "Too many recipients",
             SmtpEvent::TooManyInvalidRcpt => "Too many invalid recipients",
             SmtpEvent::RawInput => "Raw SMTP input received",
@@ -546,8 +601,17 @@ impl SmtpEvent {
                 "The remote client already sent an RCPT TO command for this recipient"
             }
             SmtpEvent::RcptToRewritten => "The envelope recipient address was rewritten",
+            SmtpEvent::RcptToRouted => {
+                "The envelope recipient matched an inbound routing rule of its domain"
+            }
             SmtpEvent::RcptToMissing => "The remote client issued a DATA command before RCPT TO",
             SmtpEvent::RcptToGreylisted => "The recipient was greylisted",
+            SmtpEvent::RcptToMaintenance => {
+                "The recipient belongs to an organization that is under maintenance"
+            }
+            SmtpEvent::RcptToPendingActivation => {
+                "The recipient belongs to an organization that has not been activated yet"
+            }
             SmtpEvent::TooManyRecipients => {
                 "The remote client exceeded the number of recipients allowed"
             }
@@ -1280,6 +1344,7 @@ impl PurgeEvent {
             PurgeEvent::InProgress => "Active purge in progress",
             PurgeEvent::AutoExpunge => "Auto-expunge executed",
             PurgeEvent::BlobCleanup => "Blob storage cleanup completed",
+            PurgeEvent::Retention => "Retention policies applied",
         }
     }
 
@@ -1292,6 +1357,7 @@ impl PurgeEvent {
             PurgeEvent::InProgress => "An active purge is in progress",
             PurgeEvent::AutoExpunge => "Auto-expunge has been executed",
             PurgeEvent::BlobCleanup => "Blob storage cleanup has completed",
+            PurgeEvent::Retention => "Message retention policies have been applied to a tenant",
         }
     }
 }
@@ -1363,6 +1429,7 @@ impl ArcEvent {
             ArcEvent::HasHeaderTag => "ARC has header tag",
             ArcEvent::BrokenChain => "Broken ARC chain",
             ArcEvent::SealerNotFound => "ARC sealer not found",
+            ArcEvent::SealFailed => "ARC sealing failed",
         }
     }
 
@@ -1374,6 +1441,7 @@ impl ArcEvent {
             ArcEvent::HasHeaderTag => "The ARC has a header tag",
             ArcEvent::BrokenChain => "The ARC chain is broken",
             ArcEvent::SealerNotFound => "The ARC sealer was not found",
+            ArcEvent::SealFailed => "A forwarded message could not be ARC sealed",
         }
     }
 }
@@ -1552,6 +1620,7 @@ impl StoreEvent {
             StoreEvent::NotSupported => "Operation not supported by store",
             StoreEvent::UnexpectedError => "Unexpected store error",
             StoreEvent::CryptoError => "Store crypto error",
+            StoreEvent::EncryptionKeyUnavailable => "Encryption key unavailable",
             StoreEvent::BlobMissingMarker => "Blob missing marker",
             StoreEvent::SqlQuery => "SQL query executed",
             StoreEvent::LdapQuery => "LDAP query executed",
@@ -1594,6 +1663,9 @@ impl StoreEvent {
             StoreEvent::NotSupported => "The operation is not supported by the store",
             StoreEvent::UnexpectedError => "An unexpected store error occurred",
             StoreEvent::CryptoError => "A store crypto error occurred",
+            StoreEvent::EncryptionKeyUnavailable => {
+                "The key protecting an organization's data could not be obtained"
+            }
             StoreEvent::BlobMissingMarker => "The blob is missing a marker",
             StoreEvent::SqlQuery => "An SQL query was executed",
             StoreEvent::LdapQuery => "An LDAP query was executed",
@@ -1624,6 +1696,8 @@ impl MessageIngestEvent {
             MessageIngestEvent::Duplicate => "Skipping duplicate message",
             MessageIngestEvent::Error => "Message ingestion error",
             MessageIngestEvent::FtsIndex => "Full-text search index updated",
+            MessageIngestEvent::ImportCompleted => "Message import completed",
+            MessageIngestEvent::ImportFailed => "Message import failed",
         }
     }
 
@@ -1636,6 +1710,12 @@ impl MessageIngestEvent {
             MessageIngestEvent::Duplicate => "The message is a duplicate and has been skipped",
             MessageIngestEvent::Error => "An error occurred while ingesting the message",
             MessageIngestEvent::FtsIndex => "The full-text search index has been updated",
+            MessageIngestEvent::ImportCompleted => {
+                "Messages from an mbox file or Maildir archive were imported into an account"
+            }
+            MessageIngestEvent::ImportFailed => {
+                "An error occurred while importing messages into an account"
+            }
         }
     }
 }
@@ -1738,6 +1818,8 @@ impl ManageEvent {
             ManageEvent::AssertFailed => "Assertion failed",
             ManageEvent::NotFound => "Resource not found",
             ManageEvent::NotSupported => "Management operation not supported",
+            ManageEvent::ReservedName => "Reserved name",
+            ManageEvent::PasswordReused => "Password reused",
             ManageEvent::Error => "Management error",
         }
     }
@@ -1749,6 +1831,8 @@ impl ManageEvent {
             ManageEvent::AssertFailed => "A management assertion has failed",
             ManageEvent::NotFound => "The managed resource was not found",
             ManageEvent::NotSupported => "The management operation is not supported",
+            ManageEvent::ReservedName => "The requested name is reserved",
+            ManageEvent::PasswordReused => "The new password matches a previous password",
             ManageEvent::Error => "A management error occurred",
         }
     }
@@ -1761,6 +1845,15 @@ impl AuthEvent {
             AuthEvent::Failed => "Authentication failed",
             AuthEvent::MissingTotp => "Missing TOTP for authentication",
             AuthEvent::TooManyAttempts => "Too many authentication attempts",
+            AuthEvent::AccountLocked => "Account locked",
+            AuthEvent::Maintenance => "Organization under maintenance",
+            AuthEvent::PendingActivation => "Organization pending activation",
+            AuthEvent::ProtocolDisabled => "Protocol not enabled",
+            AuthEvent::ProtocolNotAllowed => "Protocol not permitted",
+            AuthEvent::PasswordExpired => "Password expired",
+            AuthEvent::RecoveryCodeUsed => "Recovery code used",
+            AuthEvent::NewCountry => "Login from a new country",
+            AuthEvent::TokenRevoked => "OAuth token revoked",
             AuthEvent::Error => "Authentication error",
             AuthEvent::TokenExpired => "OAuth token expired",
             AuthEvent::ClientRegistration => "OAuth Client registration",
@@ -1773,6 +1866,29 @@ impl AuthEvent {
             AuthEvent::Failed => "Failed authentication",
             AuthEvent::MissingTotp => "TOTP is missing for authentication",
             AuthEvent::TooManyAttempts => "Too many authentication attempts have been made",
+            AuthEvent::AccountLocked => {
+                "The account is temporarily locked after repeated authentication failures"
+            }
+            AuthEvent::Maintenance => {
+                "The account belongs to an organization that is under maintenance"
+            }
+            AuthEvent::PendingActivation => {
+                "The account belongs to an organization that has not been activated yet"
+            }
+            AuthEvent::ProtocolDisabled => {
+                "The account belongs to an organization that disabled the protocol"
+            }
+            AuthEvent::ProtocolNotAllowed => "The account is not permitted to use the protocol",
+            AuthEvent::PasswordExpired => {
+                "The password of the account is older than the maximum password age"
+            }
+            AuthEvent::RecoveryCodeUsed => {
+                "A single-use recovery code was used in place of the second factor"
+            }
+            AuthEvent::NewCountry => {
+                "An account logged in from a country it was not seen in before"
+            }
+            AuthEvent::TokenRevoked => "An OAuth token was revoked before its expiration",
             AuthEvent::Error => "An error occurred with authentication",
             AuthEvent::TokenExpired => "OAuth authentication token has expired",
             AuthEvent::ClientRegistration => "OAuth client successfully registered",
@@ -1928,3 +2044,169 @@ impl CalendarEvent {
         }
     }
 }
+
+impl ProvisionEvent {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ProvisionEvent::Started => "Organization provisioning started",
+            ProvisionEvent::TenantCreated => "Organization tenant created",
+            ProvisionEvent::DomainCreated => "Organization domain created",
+            ProvisionEvent::AdminCreated => "Organization administrator created",
+            ProvisionEvent::Completed => "Organization provisioning completed",
+            ProvisionEvent::Failed => "Organization provisioning failed",
+        }
+    }
+
+    pub fn explain(&self) -> &'static str {
+        match self {
+            ProvisionEvent::Started => "A request to provision a new organization was received",
+            ProvisionEvent::TenantCreated => "The tenant for a new organization has been created",
+            ProvisionEvent::DomainCreated => "The domain for a new organization has been created",
+            ProvisionEvent::AdminCreated => {
+                "The administrator account for a new organization has been created"
+            }
+            ProvisionEvent::Completed => "A new organization has been provisioned successfully",
+            ProvisionEvent::Failed => "An error occurred while provisioning a new organization",
+        }
+    }
+}
+
+impl DirectoryEvent {
+    pub fn description(&self) -> &'static str {
+        match self {
+            DirectoryEvent::PrincipalCreated => "Principal created",
+            DirectoryEvent::PrincipalUpdated => "Principal updated",
+            DirectoryEvent::PrincipalDeleted => "Principal deleted",
+            DirectoryEvent::ImportCompleted => "Directory import completed",
+            DirectoryEvent::ImportFailed => "Directory import failed",
+            DirectoryEvent::LegalHoldSet => "Legal hold placed",
+            DirectoryEvent::LegalHoldReleased => "Legal hold released",
+            DirectoryEvent::PrincipalErased => "Principal erased",
+            DirectoryEvent::ErasureFailed => "Principal erasure failed",
+            DirectoryEvent::ForwardingUpdated => "Forwarding rule updated",
+            DirectoryEvent::ForwardingRemoved => "Forwarding rule removed",
+            DirectoryEvent::CounterDrift => "Principal counter drift",
+            DirectoryEvent::AppPasswordCreated => "App password created",
+            DirectoryEvent::AppPasswordRevoked => "App password revoked",
+            DirectoryEvent::AccountLocked => "Account locked",
+            DirectoryEvent::AccountUnlocked => "Account unlocked",
+            DirectoryEvent::AccountDisablePending => "Account disable pending",
+            DirectoryEvent::AccountDisabled => "Account disabled",
+            DirectoryEvent::AccountEnabled => "Account enabled",
+            DirectoryEvent::DomainDnsMissing => "Domain DNS record missing",
+            DirectoryEvent::DomainSpfChanged => "Domain SPF networks changed",
+            DirectoryEvent::InvitationCreated => "Organization invitation created",
+            DirectoryEvent::InvitationRevoked => "Organization invitation revoked",
+            DirectoryEvent::InvitationRedeemed => "Organization invitation redeemed",
+            DirectoryEvent::OrganizationActivated => "Organization activated",
+            DirectoryEvent::OrganizationPurged => "Unverified organization purged",
+            DirectoryEvent::ProvisioningChecked => "Organization provisioning checked",
+            DirectoryEvent::TrialExpiring => "Organization trial expiring",
+            DirectoryEvent::TrialExpired => "Organization trial expired",
+            DirectoryEvent::TrialExtended => "Organization trial extended",
+            DirectoryEvent::TrialConverted => "Organization trial converted",
+            DirectoryEvent::PasswordChanged => "Password changed",
+            DirectoryEvent::MfaFactorAdded => "MFA factor added",
+            DirectoryEvent::MfaFactorRemoved => "MFA factor removed",
+            DirectoryEvent::SenderCreated => "Send-only credential created",
+            DirectoryEvent::SenderSecretRotated => "Send-only credential secret rotated",
+        }
+    }
+
+    pub fn explain(&self) -> &'static str {
+        match self {
+            DirectoryEvent::PrincipalCreated => "A principal was created in the internal directory",
+            DirectoryEvent::PrincipalUpdated => "A principal was updated in the internal directory",
+            DirectoryEvent::PrincipalDeleted => {
+                "A principal was deleted from the internal directory"
+            }
+            DirectoryEvent::ImportCompleted => {
+                "Entries from an external directory were imported into a tenant"
+            }
+            DirectoryEvent::ImportFailed => {
+                "An error occurred while importing entries from an external directory"
+            }
+            DirectoryEvent::LegalHoldSet => {
+                "An account was placed on legal hold and its messages can no longer be purged"
+            }
+            DirectoryEvent::LegalHoldReleased => {
+                "The legal hold on an account was released and held messages were purged"
+            }
+            DirectoryEvent::PrincipalErased => {
+                "An account and its personal data were erased, and audit records were scrubbed"
+            }
+            DirectoryEvent::ErasureFailed => {
+                "An error occurred while erasing an account and its personal data"
+            }
+            DirectoryEvent::ForwardingUpdated => {
+                "An administrator set up forwarding of an account's messages"
+            }
+            DirectoryEvent::ForwardingRemoved => {
+                "The forwarding rule of an account was removed or expired"
+            }
+            DirectoryEvent::CounterDrift => {
+                "The number of principals of a tenant did not match its counter and was corrected"
+            }
+            DirectoryEvent::AppPasswordCreated => "An app password was generated for an account",
+            DirectoryEvent::AppPasswordRevoked => {
+                "An app password was revoked and its sessions invalidated"
+            }
+            DirectoryEvent::AccountLocked => {
+                "An account was locked after repeated authentication failures"
+            }
+            DirectoryEvent::AccountUnlocked => "A locked account was unlocked by an administrator",
+            DirectoryEvent::AccountDisablePending => {
+                "An account is scheduled to be disabled shortly"
+            }
+            DirectoryEvent::AccountDisabled => "A scheduled disable of an account took effect",
+            DirectoryEvent::AccountEnabled => "A scheduled enable of an account took effect",
+            DirectoryEvent::DomainDnsMissing => {
+                "A DNS record of a domain that was previously found is now missing"
+            }
+            DirectoryEvent::DomainSpfChanged => {
+                "The networks authorized by the SPF includes of a flattened record have changed"
+            }
+            DirectoryEvent::InvitationCreated => {
+                "An invitation to provision an organization has been created"
+            }
+            DirectoryEvent::InvitationRevoked => {
+                "An invitation to provision an organization has been revoked"
+            }
+            DirectoryEvent::InvitationRedeemed => {
+                "An organization has been provisioned from an invitation"
+            }
+            DirectoryEvent::OrganizationActivated => {
+                "The contact of an organization has confirmed its activation"
+            }
+            DirectoryEvent::OrganizationPurged => {
+                "An organization that was not activated in time has been removed"
+            }
+            DirectoryEvent::ProvisioningChecked => {
+                "A public organization provisioning request was checked for abuse"
+            }
+            DirectoryEvent::TrialExpiring => "The trial period of an organization ends soon",
+            DirectoryEvent::TrialExpired => {
+                "An organization has been suspended because its trial period ended"
+            }
+            DirectoryEvent::TrialExtended => {
+                "The trial period of an organization has been extended"
+            }
+            DirectoryEvent::TrialConverted => {
+                "The trial of an organization has been converted to a regular subscription"
+            }
+            DirectoryEvent::PasswordChanged => "The password of an account was changed",
+            DirectoryEvent::MfaFactorAdded => {
+                "A second authentication factor was added to an account"
+            }
+            DirectoryEvent::MfaFactorRemoved => {
+                "A second authentication factor was removed from an account"
+            }
+            DirectoryEvent::SenderCreated => {
+                "A send-only credential was created for SMTP submission"
+            }
+            DirectoryEvent::SenderSecretRotated => {
+                "The secret of a send-only credential was replaced and its sessions invalidated"
+            }
+        }
+    }
+}
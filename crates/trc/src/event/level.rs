@@ -46,7 +46,8 @@ impl EventType {
                 | StoreEvent::NotConfigured
                 | StoreEvent::NotSupported
                 | StoreEvent::UnexpectedError
-                | StoreEvent::CryptoError => Level::Error,
+                | StoreEvent::CryptoError
+                | StoreEvent::EncryptionKeyUnavailable => Level::Error,
                 StoreEvent::BlobMissingMarker | StoreEvent::HttpStoreError => Level::Warn,
             },
             EventType::Jmap(_) => Level::Debug,
@@ -137,6 +138,7 @@ impl EventType {
                 | SmtpEvent::MailFromNotAllowed
                 | SmtpEvent::RcptToDuplicate
                 | SmtpEvent::RcptToRewritten
+                | SmtpEvent::RcptToRouted
                 | SmtpEvent::RcptToMissing
                 | SmtpEvent::RequireTlsDisabled
                 | SmtpEvent::DeliverByDisabled
@@ -192,6 +194,8 @@ impl EventType {
                 | SmtpEvent::RelayNotAllowed
                 | SmtpEvent::RcptTo
                 | SmtpEvent::RcptToGreylisted
+                | SmtpEvent::RcptToMaintenance
+                | SmtpEvent::RcptToPendingActivation
                 | SmtpEvent::TooManyInvalidRcpt
                 | SmtpEvent::Vrfy
                 | SmtpEvent::VrfyNotFound
@@ -234,7 +238,16 @@ impl EventType {
             EventType::Auth(cause) => match cause {
                 AuthEvent::Failed | AuthEvent::TokenExpired => Level::Debug,
                 AuthEvent::MissingTotp => Level::Trace,
-                AuthEvent::TooManyAttempts => Level::Warn,
+                AuthEvent::TooManyAttempts
+                | AuthEvent::AccountLocked
+                | AuthEvent::RecoveryCodeUsed
+                | AuthEvent::NewCountry => Level::Warn,
+                AuthEvent::Maintenance
+                | AuthEvent::PendingActivation
+                | AuthEvent::ProtocolDisabled
+                | AuthEvent::ProtocolNotAllowed
+                | AuthEvent::PasswordExpired
+                | AuthEvent::TokenRevoked => Level::Info,
                 AuthEvent::Error => Level::Error,
                 AuthEvent::Success | AuthEvent::ClientRegistration => Level::Info,
             },
@@ -262,7 +275,7 @@ impl EventType {
                 | ArcEvent::InvalidCv
                 | ArcEvent::HasHeaderTag
                 | ArcEvent::BrokenChain => Level::Debug,
-                ArcEvent::SealerNotFound => Level::Warn,
+                ArcEvent::SealerNotFound | ArcEvent::SealFailed => Level::Warn,
             },
             EventType::Dkim(event) => match event {
                 DkimEvent::SignerNotFound => Level::Warn,
@@ -274,7 +287,7 @@ impl EventType {
                 PurgeEvent::Finished => Level::Debug,
                 PurgeEvent::Running => Level::Info,
                 PurgeEvent::Error => Level::Error,
-                PurgeEvent::BlobCleanup => Level::Info,
+                PurgeEvent::BlobCleanup | PurgeEvent::Retention => Level::Info,
                 PurgeEvent::InProgress | PurgeEvent::AutoExpunge => Level::Debug,
             },
             EventType::Eval(event) => match event {
@@ -355,8 +368,9 @@ impl EventType {
             },
             EventType::Http(event) => match event {
                 HttpEvent::ConnectionStart | HttpEvent::ConnectionEnd => Level::Debug,
-                HttpEvent::XForwardedMissing => Level::Warn,
+                HttpEvent::XForwardedMissing | HttpEvent::SlowRequest => Level::Warn,
                 HttpEvent::Error | HttpEvent::RequestUrl => Level::Debug,
+                HttpEvent::ManagementWrite => Level::Info,
                 HttpEvent::RequestBody | HttpEvent::ResponseBody => Level::Trace,
             },
             EventType::PushSubscription(event) => match event {
@@ -384,7 +398,19 @@ impl EventType {
                 | TaskQueueEvent::TaskLocked
                 | TaskQueueEvent::TaskIgnored
                 | TaskQueueEvent::MetadataNotFound => Level::Debug,
-                TaskQueueEvent::TaskFailed => Level::Warn,
+                TaskQueueEvent::ReindexCompleted
+                | TaskQueueEvent::BlobMigrationCompleted
+                | TaskQueueEvent::BackupCompleted
+                | TaskQueueEvent::RestoreCompleted
+                | TaskQueueEvent::ExportCompleted
+                | TaskQueueEvent::JournalDelivered => Level::Info,
+                TaskQueueEvent::TaskFailed
+                | TaskQueueEvent::ReindexFailed
+                | TaskQueueEvent::BlobMigrationFailed
+                | TaskQueueEvent::BackupFailed
+                | TaskQueueEvent::RestoreFailed
+                | TaskQueueEvent::ExportFailed
+                | TaskQueueEvent::JournalFailed => Level::Warn,
             },
             EventType::Dmarc(_) => Level::Debug,
             EventType::Spf(_) => Level::Debug,
@@ -531,7 +557,9 @@ impl EventType {
                 | MessageIngestEvent::ImapAppend
                 | MessageIngestEvent::JmapAppend
                 | MessageIngestEvent::Duplicate
-                | MessageIngestEvent::FtsIndex => Level::Info,
+                | MessageIngestEvent::FtsIndex
+                | MessageIngestEvent::ImportCompleted => Level::Info,
+                MessageIngestEvent::ImportFailed => Level::Warn,
                 MessageIngestEvent::Error => Level::Error,
             },
             EventType::Security(_) => Level::Info,
@@ -550,6 +578,52 @@ impl EventType {
                 | CalendarEvent::AlarmRecipientOverride
                 | CalendarEvent::ItipMessageError => Level::Debug,
             },
+            EventType::Provision(event) => match event {
+                ProvisionEvent::Started
+                | ProvisionEvent::TenantCreated
+                | ProvisionEvent::DomainCreated
+                | ProvisionEvent::AdminCreated => Level::Debug,
+                ProvisionEvent::Completed => Level::Info,
+                ProvisionEvent::Failed => Level::Warn,
+            },
+            EventType::Directory(event) => match event {
+                DirectoryEvent::PrincipalCreated
+                | DirectoryEvent::PrincipalUpdated
+                | DirectoryEvent::PrincipalDeleted
+                | DirectoryEvent::ImportCompleted
+                | DirectoryEvent::LegalHoldSet
+                | DirectoryEvent::LegalHoldReleased
+                | DirectoryEvent::PrincipalErased
+                | DirectoryEvent::ForwardingUpdated
+                | DirectoryEvent::ForwardingRemoved
+                | DirectoryEvent::AppPasswordCreated
+                | DirectoryEvent::AppPasswordRevoked
+                | DirectoryEvent::AccountUnlocked
+                | DirectoryEvent::AccountDisablePending
+                | DirectoryEvent::AccountDisabled
+                | DirectoryEvent::AccountEnabled
+                | DirectoryEvent::InvitationCreated
+                | DirectoryEvent::InvitationRevoked
+                | DirectoryEvent::InvitationRedeemed
+                | DirectoryEvent::OrganizationActivated
+                | DirectoryEvent::OrganizationPurged
+                | DirectoryEvent::ProvisioningChecked
+                | DirectoryEvent::TrialExpiring
+                | DirectoryEvent::TrialExpired
+                | DirectoryEvent::TrialExtended
+                | DirectoryEvent::TrialConverted
+                | DirectoryEvent::PasswordChanged
+                | DirectoryEvent::MfaFactorAdded
+                | DirectoryEvent::MfaFactorRemoved
+                | DirectoryEvent::SenderCreated
+                | DirectoryEvent::SenderSecretRotated => Level::Info,
+                DirectoryEvent::AccountLocked => Level::Warn,
+                DirectoryEvent::ImportFailed
+                | DirectoryEvent::ErasureFailed
+                | DirectoryEvent::CounterDrift
+                | DirectoryEvent::DomainDnsMissing
+                | DirectoryEvent::DomainSpfChanged => Level::Warn,
+            },
         }
     }
 }
@@ -405,6 +405,11 @@ impl AuthEvent {
                 "Try authenticating again using 'secret$totp_token'."
             ),
             Self::TooManyAttempts => "Too many authentication attempts",
+            Self::AccountLocked => concat!(
+                "This account is temporarily locked after repeated ",
+                "authentication failures."
+            ),
+            Self::Maintenance => "Temporarily unavailable, try later.",
             _ => "Authentication error",
         }
     }
@@ -457,7 +457,8 @@ impl EventType {
                 | StoreEvent::BlobRead
                 | StoreEvent::BlobWrite
                 | StoreEvent::BlobDelete
-                | StoreEvent::HttpStoreError,
+                | StoreEvent::HttpStoreError
+                | StoreEvent::EncryptionKeyUnavailable,
             ) => true,
             EventType::MessageIngest(_) => true,
             EventType::Jmap(
@@ -525,7 +526,8 @@ impl EventType {
                 HttpEvent::Error
                 | HttpEvent::RequestBody
                 | HttpEvent::ResponseBody
-                | HttpEvent::XForwardedMissing,
+                | HttpEvent::XForwardedMissing
+                | HttpEvent::SlowRequest,
             ) => true,
             EventType::Network(NetworkEvent::Timeout) => true,
             EventType::Security(_) => true,
@@ -546,7 +548,8 @@ impl EventType {
                 | ArcEvent::InvalidInstance
                 | ArcEvent::InvalidCv
                 | ArcEvent::HasHeaderTag
-                | ArcEvent::BrokenChain,
+                | ArcEvent::BrokenChain
+                | ArcEvent::SealFailed,
             ) => true,
             EventType::Dkim(_) => true,
             EventType::Dmarc(_) => true,
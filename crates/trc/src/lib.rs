@@ -140,6 +140,7 @@ pub enum Key {
     Value,
     Version,
     QueueName,
+    TenantId,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -190,6 +191,8 @@ pub enum EventType {
     Ai(AiEvent),
     WebDav(WebDavEvent),
     Calendar(CalendarEvent),
+    Provision(ProvisionEvent),
+    Directory(DirectoryEvent),
 }
 
 #[event_type]
@@ -201,6 +204,8 @@ pub enum HttpEvent {
     RequestBody,
     ResponseBody,
     XForwardedMissing,
+    SlowRequest,
+    ManagementWrite,
 }
 
 #[event_type]
@@ -243,6 +248,18 @@ pub enum TaskQueueEvent {
     TaskFailed,
     BlobNotFound,
     MetadataNotFound,
+    ReindexCompleted,
+    ReindexFailed,
+    BlobMigrationCompleted,
+    BlobMigrationFailed,
+    BackupCompleted,
+    BackupFailed,
+    RestoreCompleted,
+    RestoreFailed,
+    ExportCompleted,
+    ExportFailed,
+    JournalDelivered,
+    JournalFailed,
 }
 
 #[event_type]
@@ -393,8 +410,11 @@ pub enum SmtpEvent {
     RcptTo,
     RcptToDuplicate,
     RcptToRewritten,
+    RcptToRouted,
     RcptToMissing,
     RcptToGreylisted,
+    RcptToMaintenance,
+    RcptToPendingActivation,
     TooManyRecipients,
     TooManyInvalidRcpt,
     RawInput,
@@ -718,6 +738,7 @@ pub enum PurgeEvent {
     InProgress,
     AutoExpunge,
     BlobCleanup,
+    Retention,
 }
 
 #[event_type]
@@ -752,6 +773,7 @@ pub enum ArcEvent {
     HasHeaderTag,
     BrokenChain,
     SealerNotFound,
+    SealFailed,
 }
 
 #[event_type]
@@ -845,6 +867,7 @@ pub enum StoreEvent {
     UnexpectedError,
     CryptoError,
     HttpStoreError,
+    EncryptionKeyUnavailable,
 
     // Caching
     CacheMiss,
@@ -877,6 +900,8 @@ pub enum MessageIngestEvent {
     Duplicate,
     Error,
     FtsIndex,
+    ImportCompleted,
+    ImportFailed,
 }
 
 #[event_type]
@@ -933,6 +958,8 @@ pub enum ManageEvent {
     AssertFailed,
     NotFound,
     NotSupported,
+    ReservedName,
+    PasswordReused,
     Error,
 }
 
@@ -944,6 +971,15 @@ pub enum AuthEvent {
     MissingTotp,
     TooManyAttempts,
     ClientRegistration,
+    AccountLocked,
+    Maintenance,
+    PendingActivation,
+    ProtocolDisabled,
+    ProtocolNotAllowed,
+    PasswordExpired,
+    RecoveryCodeUsed,
+    NewCountry,
+    TokenRevoked,
     Error,
 }
 
@@ -999,6 +1035,56 @@ pub enum CalendarEvent {
     ItipMessageError,
 }
 
+#[event_type]
+pub enum ProvisionEvent {
+    Started,
+    TenantCreated,
+    DomainCreated,
+    AdminCreated,
+    Completed,
+    Failed,
+}
+
+#[event_type]
+pub enum DirectoryEvent {
+    PrincipalCreated,
+    PrincipalUpdated,
+    PrincipalDeleted,
+    ImportCompleted,
+    ImportFailed,
+    LegalHoldSet,
+    LegalHoldReleased,
+    PrincipalErased,
+    ErasureFailed,
+    ForwardingUpdated,
+    ForwardingRemoved,
+    CounterDrift,
+    AppPasswordCreated,
+    AppPasswordRevoked,
+    AccountLocked,
+    AccountUnlocked,
+    AccountDisablePending,
+    AccountDisabled,
+    AccountEnabled,
+    DomainDnsMissing,
+    DomainSpfChanged,
+    InvitationCreated,
+    InvitationRevoked,
+    InvitationRedeemed,
+    OrganizationActivated,
+    OrganizationPurged,
+    ProvisioningChecked,
+    TrialExpiring,
+    TrialExpired,
+    TrialExtended,
+    TrialConverted,
+    PasswordChanged,
+    MfaFactorAdded,
+    MfaFactorRemoved,
+    SenderCreated,
+    SenderSecretRotated,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MetricType {
     ServerMemory,
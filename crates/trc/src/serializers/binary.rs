@@ -459,6 +459,8 @@ impl EventType {
             EventType::Http(HttpEvent::RequestUrl) => 156,
             EventType::Http(HttpEvent::ResponseBody) => 157,
             EventType::Http(HttpEvent::XForwardedMissing) => 158,
+            EventType::Http(HttpEvent::SlowRequest) => 597,
+            EventType::Http(HttpEvent::ManagementWrite) => 598,
             EventType::Imap(ImapEvent::Append) => 159,
             EventType::Imap(ImapEvent::Capabilities) => 160,
             EventType::Imap(ImapEvent::Close) => 161,
@@ -898,6 +900,79 @@ impl EventType {
             EventType::Spam(SpamEvent::TrainStarted) => 588,
             EventType::Spam(SpamEvent::ModelLoaded) => 589,
             EventType::Store(StoreEvent::MeilisearchError) => 590,
+            EventType::Provision(ProvisionEvent::Started) => 591,
+            EventType::Provision(ProvisionEvent::TenantCreated) => 592,
+            EventType::Provision(ProvisionEvent::DomainCreated) => 593,
+            EventType::Provision(ProvisionEvent::AdminCreated) => 594,
+            EventType::Provision(ProvisionEvent::Completed) => 595,
+            EventType::Provision(ProvisionEvent::Failed) => 596,
+            EventType::Directory(DirectoryEvent::PrincipalCreated) => 599,
+            EventType::Directory(DirectoryEvent::PrincipalUpdated) => 600,
+            EventType::Directory(DirectoryEvent::PrincipalDeleted) => 601,
+            EventType::Directory(DirectoryEvent::ImportCompleted) => 602,
+            EventType::Directory(DirectoryEvent::ImportFailed) => 603,
+            EventType::MessageIngest(MessageIngestEvent::ImportCompleted) => 604,
+            EventType::MessageIngest(MessageIngestEvent::ImportFailed) => 605,
+            EventType::Purge(PurgeEvent::Retention) => 606,
+            EventType::Directory(DirectoryEvent::LegalHoldSet) => 607,
+            EventType::Directory(DirectoryEvent::LegalHoldReleased) => 608,
+            EventType::TaskQueue(TaskQueueEvent::ReindexCompleted) => 609,
+            EventType::TaskQueue(TaskQueueEvent::ReindexFailed) => 610,
+            EventType::TaskQueue(TaskQueueEvent::BlobMigrationCompleted) => 611,
+            EventType::TaskQueue(TaskQueueEvent::BlobMigrationFailed) => 612,
+            EventType::Store(StoreEvent::EncryptionKeyUnavailable) => 613,
+            EventType::TaskQueue(TaskQueueEvent::BackupCompleted) => 614,
+            EventType::TaskQueue(TaskQueueEvent::BackupFailed) => 615,
+            EventType::TaskQueue(TaskQueueEvent::RestoreCompleted) => 616,
+            EventType::TaskQueue(TaskQueueEvent::RestoreFailed) => 617,
+            EventType::Directory(DirectoryEvent::PrincipalErased) => 618,
+            EventType::Directory(DirectoryEvent::ErasureFailed) => 619,
+            EventType::TaskQueue(TaskQueueEvent::ExportCompleted) => 620,
+            EventType::TaskQueue(TaskQueueEvent::ExportFailed) => 621,
+            EventType::Arc(ArcEvent::SealFailed) => 622,
+            EventType::Smtp(SmtpEvent::RcptToRouted) => 623,
+            EventType::Directory(DirectoryEvent::ForwardingUpdated) => 624,
+            EventType::Directory(DirectoryEvent::ForwardingRemoved) => 625,
+            EventType::Directory(DirectoryEvent::CounterDrift) => 626,
+            EventType::Manage(ManageEvent::ReservedName) => 627,
+            EventType::Directory(DirectoryEvent::AppPasswordCreated) => 628,
+            EventType::Directory(DirectoryEvent::AppPasswordRevoked) => 629,
+            EventType::Manage(ManageEvent::PasswordReused) => 630,
+            EventType::Auth(AuthEvent::AccountLocked) => 631,
+            EventType::Directory(DirectoryEvent::AccountLocked) => 632,
+            EventType::Directory(DirectoryEvent::AccountUnlocked) => 633,
+            EventType::Directory(DirectoryEvent::AccountDisablePending) => 634,
+            EventType::Directory(DirectoryEvent::AccountDisabled) => 635,
+            EventType::Directory(DirectoryEvent::AccountEnabled) => 636,
+            EventType::Directory(DirectoryEvent::DomainDnsMissing) => 637,
+            EventType::Auth(AuthEvent::Maintenance) => 638,
+            EventType::Smtp(SmtpEvent::RcptToMaintenance) => 639,
+            EventType::Directory(DirectoryEvent::InvitationCreated) => 640,
+            EventType::Directory(DirectoryEvent::InvitationRevoked) => 641,
+            EventType::Directory(DirectoryEvent::InvitationRedeemed) => 642,
+            EventType::Auth(AuthEvent::PendingActivation) => 643,
+            EventType::Smtp(SmtpEvent::RcptToPendingActivation) => 644,
+            EventType::Directory(DirectoryEvent::OrganizationActivated) => 645,
+            EventType::Directory(DirectoryEvent::OrganizationPurged) => 646,
+            EventType::Directory(DirectoryEvent::ProvisioningChecked) => 647,
+            EventType::Directory(DirectoryEvent::TrialExpiring) => 648,
+            EventType::Directory(DirectoryEvent::TrialExpired) => 649,
+            EventType::Directory(DirectoryEvent::TrialExtended) => 650,
+            EventType::Directory(DirectoryEvent::TrialConverted) => 651,
+            EventType::Auth(AuthEvent::ProtocolDisabled) => 652,
+            EventType::Auth(AuthEvent::ProtocolNotAllowed) => 653,
+            EventType::Auth(AuthEvent::PasswordExpired) => 654,
+            EventType::Auth(AuthEvent::RecoveryCodeUsed) => 655,
+            EventType::Directory(DirectoryEvent::PasswordChanged) => 656,
+            EventType::Directory(DirectoryEvent::MfaFactorAdded) => 657,
+            EventType::Directory(DirectoryEvent::MfaFactorRemoved) => 658,
+            EventType::Auth(AuthEvent::NewCountry) => 659,
+            EventType::Auth(AuthEvent::TokenRevoked) => 660,
+            EventType::Directory(DirectoryEvent::SenderCreated) => 661,
+            EventType::Directory(DirectoryEvent::SenderSecretRotated) => 662,
+            EventType::TaskQueue(TaskQueueEvent::JournalDelivered) => 663,
+            EventType::TaskQueue(TaskQueueEvent::JournalFailed) => 664,
+            EventType::Directory(DirectoryEvent::DomainSpfChanged) => 665,
         }
     }
 
@@ -1055,6 +1130,8 @@ impl EventType {
             156 => Some(EventType::Http(HttpEvent::RequestUrl)),
             157 => Some(EventType::Http(HttpEvent::ResponseBody)),
             158 => Some(EventType::Http(HttpEvent::XForwardedMissing)),
+            597 => Some(EventType::Http(HttpEvent::SlowRequest)),
+            598 => Some(EventType::Http(HttpEvent::ManagementWrite)),
             159 => Some(EventType::Imap(ImapEvent::Append)),
             160 => Some(EventType::Imap(ImapEvent::Capabilities)),
             161 => Some(EventType::Imap(ImapEvent::Close)),
@@ -1534,6 +1611,81 @@ impl EventType {
             588 => Some(EventType::Spam(SpamEvent::TrainStarted)),
             589 => Some(EventType::Spam(SpamEvent::ModelLoaded)),
             590 => Some(EventType::Store(StoreEvent::MeilisearchError)),
+            591 => Some(EventType::Provision(ProvisionEvent::Started)),
+            592 => Some(EventType::Provision(ProvisionEvent::TenantCreated)),
+            593 => Some(EventType::Provision(ProvisionEvent::DomainCreated)),
+            594 => Some(EventType::Provision(ProvisionEvent::AdminCreated)),
+            595 => Some(EventType::Provision(ProvisionEvent::Completed)),
+            596 => Some(EventType::Provision(ProvisionEvent::Failed)),
+            599 => Some(EventType::Directory(DirectoryEvent::PrincipalCreated)),
+            600 => Some(EventType::Directory(DirectoryEvent::PrincipalUpdated)),
+            601 => Some(EventType::Directory(DirectoryEvent::PrincipalDeleted)),
+            602 => Some(EventType::Directory(DirectoryEvent::ImportCompleted)),
+            603 => Some(EventType::Directory(DirectoryEvent::ImportFailed)),
+            604 => Some(EventType::MessageIngest(
+                MessageIngestEvent::ImportCompleted,
+            )),
+            605 => Some(EventType::MessageIngest(MessageIngestEvent::ImportFailed)),
+            606 => Some(EventType::Purge(PurgeEvent::Retention)),
+            607 => Some(EventType::Directory(DirectoryEvent::LegalHoldSet)),
+            608 => Some(EventType::Directory(DirectoryEvent::LegalHoldReleased)),
+            609 => Some(EventType::TaskQueue(TaskQueueEvent::ReindexCompleted)),
+            610 => Some(EventType::TaskQueue(TaskQueueEvent::ReindexFailed)),
+            611 => Some(EventType::TaskQueue(TaskQueueEvent::BlobMigrationCompleted)),
+            612 => Some(EventType::TaskQueue(TaskQueueEvent::BlobMigrationFailed)),
+            613 => Some(EventType::Store(StoreEvent::EncryptionKeyUnavailable)),
+            614 => Some(EventType::TaskQueue(TaskQueueEvent::BackupCompleted)),
+            615 => Some(EventType::TaskQueue(TaskQueueEvent::BackupFailed)),
+            616 => Some(EventType::TaskQueue(TaskQueueEvent::RestoreCompleted)),
+            617 => Some(EventType::TaskQueue(TaskQueueEvent::RestoreFailed)),
+            618 => Some(EventType::Directory(DirectoryEvent::PrincipalErased)),
+            619 => Some(EventType::Directory(DirectoryEvent::ErasureFailed)),
+            620 => Some(EventType::TaskQueue(TaskQueueEvent::ExportCompleted)),
+            621 => Some(EventType::TaskQueue(TaskQueueEvent::ExportFailed)),
+            622 => Some(EventType::Arc(ArcEvent::SealFailed)),
+            623 => Some(EventType::Smtp(SmtpEvent::RcptToRouted)),
+            624 => Some(EventType::Directory(DirectoryEvent::ForwardingUpdated)),
+            625 => Some(EventType::Directory(DirectoryEvent::ForwardingRemoved)),
+            626 => Some(EventType::Directory(DirectoryEvent::CounterDrift)),
+            627 => Some(EventType::Manage(ManageEvent::ReservedName)),
+            628 => Some(EventType::Directory(DirectoryEvent::AppPasswordCreated)),
+            629 => Some(EventType::Directory(DirectoryEvent::AppPasswordRevoked)),
+            630 => Some(EventType::Manage(ManageEvent::PasswordReused)),
+            631 => Some(EventType::Auth(AuthEvent::AccountLocked)),
+            632 => Some(EventType::Directory(DirectoryEvent::AccountLocked)),
+            633 => Some(EventType::Directory(DirectoryEvent::AccountUnlocked)),
+            634 => Some(EventType::Directory(DirectoryEvent::AccountDisablePending)),
+            635 => Some(EventType::Directory(DirectoryEvent::AccountDisabled)),
+            636 => Some(EventType::Directory(DirectoryEvent::AccountEnabled)),
+            637 => Some(EventType::Directory(DirectoryEvent::DomainDnsMissing)),
+            638 => Some(EventType::Auth(AuthEvent::Maintenance)),
+            639 => Some(EventType::Smtp(SmtpEvent::RcptToMaintenance)),
+            640 => Some(EventType::Directory(DirectoryEvent::InvitationCreated)),
+            641 => Some(EventType::Directory(DirectoryEvent::InvitationRevoked)),
+            642 => Some(EventType::Directory(DirectoryEvent::InvitationRedeemed)),
+            643 => Some(EventType::Auth(AuthEvent::PendingActivation)),
+            644 => Some(EventType::Smtp(SmtpEvent::RcptToPendingActivation)),
+            645 => Some(EventType::Directory(DirectoryEvent::OrganizationActivated)),
+            646 => Some(EventType::Directory(DirectoryEvent::OrganizationPurged)),
+            647 => Some(EventType::Directory(DirectoryEvent::ProvisioningChecked)),
+            648 => Some(EventType::Directory(DirectoryEvent::TrialExpiring)),
+            649 => Some(EventType::Directory(DirectoryEvent::TrialExpired)),
+            650 => Some(EventType::Directory(DirectoryEvent::TrialExtended)),
+            651 => Some(EventType::Directory(DirectoryEvent::TrialConverted)),
+            652 => Some(EventType::Auth(AuthEvent::ProtocolDisabled)),
+            653 => Some(EventType::Auth(AuthEvent::ProtocolNotAllowed)),
+            654 => Some(EventType::Auth(AuthEvent::PasswordExpired)),
+            655 => Some(EventType::Auth(AuthEvent::RecoveryCodeUsed)),
+            656 => Some(EventType::Directory(DirectoryEvent::PasswordChanged)),
+            657 => Some(EventType::Directory(DirectoryEvent::MfaFactorAdded)),
+            658 => Some(EventType::Directory(DirectoryEvent::MfaFactorRemoved)),
+            659 => Some(EventType::Auth(AuthEvent::NewCountry)),
+            660 => Some(EventType::Auth(AuthEvent::TokenRevoked)),
+            661 => Some(EventType::Directory(DirectoryEvent::SenderCreated)),
+            662 => Some(EventType::Directory(DirectoryEvent::SenderSecretRotated)),
+            663 => Some(EventType::TaskQueue(TaskQueueEvent::JournalDelivered)),
+            664 => Some(EventType::TaskQueue(TaskQueueEvent::JournalFailed)),
+            665 => Some(EventType::Directory(DirectoryEvent::DomainSpfChanged)),
             _ => None,
         }
     }
@@ -1608,6 +1760,7 @@ impl Key {
             Key::Value => 63,
             Key::Version => 64,
             Key::QueueName => 65,
+            Key::TenantId => 66,
         }
     }
 
@@ -1679,6 +1832,7 @@ impl Key {
             63 => Some(Key::Value),
             64 => Some(Key::Version),
             65 => Some(Key::QueueName),
+            66 => Some(Key::TenantId),
             _ => None,
         }
     }
@@ -34,6 +34,14 @@ impl BlobHash {
         BlobHash(blake3::hash(value.as_ref()).into())
     }
 
+    /// Hashes a value within a scope, so that equal values in different
+    /// scopes are stored as different blobs.
+    pub fn generate_scoped(scope: u32, value: impl AsRef<[u8]>) -> Self {
+        let mut key = [0u8; 32];
+        key[..4].copy_from_slice(&scope.to_be_bytes());
+        BlobHash(blake3::keyed_hash(&key, value.as_ref()).into())
+    }
+
     pub fn try_from_hash_slice(value: &[u8]) -> Result<BlobHash, std::array::TryFromSliceError> {
         value.try_into().map(BlobHash)
     }
@@ -50,6 +58,17 @@ impl BlobHash {
         hex
     }
 
+    pub fn from_hex(value: &str) -> Option<Self> {
+        if value.len() != BLOB_HASH_LEN * 2 || !value.is_ascii() {
+            return None;
+        }
+        let mut hash = [0u8; BLOB_HASH_LEN];
+        for (byte, hex) in hash.iter_mut().zip(value.as_bytes().chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+        }
+        Some(BlobHash(hash))
+    }
+
     pub fn is_empty(&self) -> bool {
         self.0 == [0; BLOB_HASH_LEN]
     }
@@ -83,6 +83,10 @@ pub enum PrincipalField {
     DefaultAddressBookId,
     ActiveScriptId,
     PushSubscriptions,
+    ImapImport,
+    RetentionReports,
+    Reindex,
+    Backups,
 }
 
 impl From<ContactField> for u8 {
@@ -162,6 +166,10 @@ impl From<PrincipalField> for u8 {
             PrincipalField::DefaultAddressBookId => 48,
             PrincipalField::ActiveScriptId => 49,
             PrincipalField::PushSubscriptions => 44,
+            PrincipalField::ImapImport => 52,
+            PrincipalField::RetentionReports => 53,
+            PrincipalField::Reindex => 54,
+            PrincipalField::Backups => 55,
             PrincipalField::Archive => ARCHIVE_FIELD,
         }
     }
@@ -96,6 +96,11 @@ impl<K: Eq + Hash + CacheItemWeight, V: Clone + CacheItemWeight> Cache<K, V> {
     pub fn clear(&self) {
         self.0.clear();
     }
+
+    #[inline(always)]
+    pub fn retain(&self, f: impl Fn(&K, &V) -> bool) {
+        self.0.retain(f);
+    }
 }
 
 impl<K: Eq + Hash + CacheItemWeight, V: Clone + CacheItemWeight> CacheWithTtl<K, V> {
@@ -25,7 +25,7 @@ use rustls::{
     client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
 };
 use rustls_pki_types::TrustAnchor;
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 pub trait HttpLimitResponse: Sync + Send {
     fn bytes_with_limit(
@@ -262,6 +262,38 @@ impl ServerCertVerifier for DummyVerifier {
     }
 }
 
+/// Whether the address is publicly routable, which excludes loopback,
+/// private, link-local, shared and other special-purpose ranges.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Shared address space (RFC 6598)
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let segment = ip.segments()[0];
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    // Unique local and link-local
+                    || (segment & 0xfe00) == 0xfc00
+                    || (segment & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
 // Basic email sanitizer
 pub fn sanitize_email(email: &str) -> Option<String> {
     let mut result = String::with_capacity(email.len());
@@ -39,6 +39,10 @@ impl<'x> UrlParams<'x> {
         self.get(key).and_then(|v| v.parse().ok())
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_ref(), v.as_ref()))
+    }
+
     pub fn into_inner(self) -> HashMap<Cow<'x, str>, Cow<'x, str>> {
         self.params
     }
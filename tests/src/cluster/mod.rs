@@ -185,7 +185,7 @@ async fn build_server(mut config: Config, stores: Stores) -> (Server, watch::Sen
     let core = Core::parse(&mut config, stores, config_manager)
         .await
         .enable_enterprise();
-    let data = Data::parse(&mut config);
+    let data = Data::parse(&mut config, &core);
     let cache = Caches::parse(&mut config);
     let (ipc, mut ipc_rxs) = build_ipc(true);
     let inner = Arc::new(Inner {
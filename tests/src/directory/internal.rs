@@ -4,22 +4,46 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::sync::Arc;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
 
 use crate::{
     directory::{DirectoryTest, IntoTestPrincipal, TestPrincipal},
     store::cleanup::{store_assert_is_empty, store_destroy},
 };
 use ahash::AHashSet;
-use common::{Core, Inner, Server, config::storage::Storage};
+use common::{
+    Core, Inner, Ipc, Server,
+    auth::oauth::crypto::SymmetricEncrypt,
+    config::{jmap::settings::JmapConfig, storage::Storage},
+    ipc::BroadcastEvent,
+};
 use directory::{
-    Permission, QueryBy, QueryParams, Type,
+    AuthProtocol, Permission, Permissions, PrincipalData, QueryBy, QueryParams, Type,
     backend::{
         RcptType,
         internal::{
-            PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+            DEFAULT_RESERVED_NAMES, NamePolicy, NameScope, PrincipalField, PrincipalSet,
+            PrincipalUpdate, PrincipalValue,
+            activity::{ActivityType, PrincipalActivity},
+            app_password::AppPasswords,
+            avatar::{Avatar, PrincipalAvatars},
+            external_id::ExternalIdLookup,
+            lockout::AccountLockout,
             lookup::DirectoryStore,
-            manage::{self, ChangedPrincipals, ManageDirectory, UpdatePrincipal},
+            manage::{
+                self, BatchPrincipal, BatchTenant, ChangedPrincipals, ManageDirectory,
+                UpdatePrincipal,
+            },
+            mfa::{TotpFactor, TotpFactors},
+            recovery::RecoveryCodes,
+            schedule::{AccountSchedule, ScheduleNotification},
+            security::SecurityNotices,
         },
     },
 };
@@ -27,8 +51,10 @@ use http::management::stores::destroy_account_data;
 use mail_send::Credentials;
 use store::{
     IterateParams, Store, ValueKey,
-    write::{BatchBuilder, ValueClass},
+    write::{BatchBuilder, DirectoryClass, ValueClass, now},
 };
+use tokio::sync::mpsc;
+use totp_rs::TOTP;
 use types::collection::Collection;
 
 #[tokio::test]
@@ -776,6 +802,1997 @@ async fn internal_directory() {
     }
 }
 
+#[tokio::test]
+async fn internal_directory_batch() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing batch principal creation with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        // Later entries are assigned to the tenant created by the first one
+        let created = store
+            .create_principals_batch(organization_batch("acme"), None, &NamePolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(created.ids.len(), 7);
+        assert_eq!(created.changed_principals.changes().len(), 7);
+        let tenant_id = created.ids[0];
+        for (name, id) in organization_names("acme").iter().zip(&created.ids) {
+            let info = store.get_principal_info(name).await.unwrap().unwrap();
+            assert_eq!(info.id, *id, "{name}");
+            if info.id != tenant_id {
+                assert_eq!(info.tenant, Some(tenant_id), "{name}");
+            }
+        }
+        assert_eq!(
+            store.email_to_id("admin4@acme.org").await.unwrap(),
+            Some(created.ids[6])
+        );
+
+        // Nothing is written when any of the entries fails
+        let mut batch = organization_batch("globex");
+        batch[6].principal.set(
+            PrincipalField::Emails,
+            vec!["admin0@globex.org".to_string()],
+        );
+        assert_eq!(
+            store
+                .create_principals_batch(batch, None, &NamePolicy::default())
+                .await,
+            Err(
+                manage::err_exists(PrincipalField::Emails, "admin0@globex.org")
+                    .ctx(trc::Key::Type, "individual")
+            )
+        );
+        let mut batch = organization_batch("globex");
+        batch[2].tenant = Some(BatchTenant::Created(1));
+        assert!(
+            store
+                .create_principals_batch(batch, None, &NamePolicy::default())
+                .await
+                .is_err()
+        );
+        for name in organization_names("globex") {
+            assert_eq!(store.get_principal_id(&name).await.unwrap(), None);
+        }
+
+        // Compare against creating the same principals one at a time
+        let time = Instant::now();
+        let mut tenant_id = None;
+        for item in organization_batch("initech") {
+            let id = store
+                .create_principal(item.principal, item.tenant.and(tenant_id), None)
+                .await
+                .unwrap()
+                .id;
+            tenant_id.get_or_insert(id);
+        }
+        let sequential = time.elapsed();
+        let time = Instant::now();
+        store
+            .create_principals_batch(organization_batch("umbrella"), None, &NamePolicy::default())
+            .await
+            .unwrap();
+        println!(
+            "Created a tenant, a domain and 5 admins in {:?} as a batch, {:?} one at a time",
+            time.elapsed(),
+            sequential
+        );
+
+        // Clean up
+        for organization in ["acme", "initech", "umbrella"] {
+            for name in organization_names(organization).into_iter().rev() {
+                store.delete_principal(QueryBy::Name(&name)).await.unwrap();
+            }
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_counters() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing principal counters with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let tenant_id = store
+            .create_principals_batch(organization_batch("acme"), None, &NamePolicy::default())
+            .await
+            .unwrap()
+            .ids[0];
+        assert_eq!(
+            store
+                .count_tenant_principals(tenant_id, Type::Individual)
+                .await
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            store
+                .count_tenant_principals(tenant_id, Type::Domain)
+                .await
+                .unwrap(),
+            1
+        );
+
+        // Create and delete principals concurrently
+        let mut tasks = Vec::new();
+        for task_id in 0..8 {
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                for idx in 0..10 {
+                    let name = format!("user{task_id}.{idx}@acme.org");
+                    store
+                        .create_principal(
+                            PrincipalSet::new(0, Type::Individual)
+                                .with_field(PrincipalField::Name, name.clone())
+                                .with_field(PrincipalField::Emails, vec![name.clone()]),
+                            Some(tenant_id),
+                            None,
+                        )
+                        .await
+                        .unwrap();
+                    if idx % 2 == 0 {
+                        store.delete_principal(QueryBy::Name(&name)).await.unwrap();
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(
+            store
+                .count_tenant_principals(tenant_id, Type::Individual)
+                .await
+                .unwrap(),
+            45
+        );
+        assert_eq!(
+            store
+                .count_principals(None, Type::Individual.into(), tenant_id.into())
+                .await
+                .unwrap(),
+            45
+        );
+        assert_eq!(store.repair_principal_counts().await.unwrap(), 0);
+
+        // Drift is detected and corrected
+        let mut batch = BatchBuilder::new();
+        batch.add(
+            DirectoryClass::PrincipalCount {
+                tenant_id,
+                typ: Type::Individual as u8,
+            },
+            3,
+        );
+        store.write(batch.build_all()).await.unwrap();
+        assert_eq!(
+            store
+                .count_tenant_principals(tenant_id, Type::Individual)
+                .await
+                .unwrap(),
+            48
+        );
+        assert_eq!(store.repair_principal_counts().await.unwrap(), 1);
+        assert_eq!(
+            store
+                .count_tenant_principals(tenant_id, Type::Individual)
+                .await
+                .unwrap(),
+            45
+        );
+
+        // Clean up
+        for task_id in 0..8 {
+            for idx in (1..10).step_by(2) {
+                store
+                    .delete_principal(QueryBy::Name(&format!("user{task_id}.{idx}@acme.org")))
+                    .await
+                    .unwrap();
+            }
+        }
+        assert_eq!(
+            store
+                .count_tenant_principals(tenant_id, Type::Individual)
+                .await
+                .unwrap(),
+            5
+        );
+        for name in organization_names("acme").into_iter().rev() {
+            store.delete_principal(QueryBy::Name(&name)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_invalidations() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing cache invalidations with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let (broadcast_tx, mut broadcast_rx) = mpsc::channel(1024);
+        let server = Server {
+            inner: Arc::new(Inner {
+                ipc: Ipc {
+                    broadcast_tx: Some(broadcast_tx),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            core: Arc::new(Core {
+                storage: Storage {
+                    data: store.clone(),
+                    blob: store.clone().into(),
+                    fts: store.clone().into(),
+                    ..Default::default()
+                },
+                jmap: JmapConfig {
+                    directory_invalidation_threshold: 100,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        };
+        let tenant_id = store
+            .create_principals_batch(organization_batch("acme"), None, &NamePolicy::default())
+            .await
+            .unwrap()
+            .ids[0];
+
+        // Importing 1k users and a group with all of them is flushed as a
+        // single tenant invalidation
+        let mut changed_principals = ChangedPrincipals::default();
+        let mut names = Vec::new();
+        for idx in 0..1000 {
+            let name = format!("user{idx}@acme.org");
+            changed_principals.merge(
+                store
+                    .create_principal(
+                        PrincipalSet::new(0, Type::Individual)
+                            .with_field(PrincipalField::Name, name.clone())
+                            .with_field(PrincipalField::Emails, vec![name.clone()]),
+                        Some(tenant_id),
+                        None,
+                    )
+                    .await
+                    .unwrap()
+                    .changed_principals,
+            );
+            names.push(name);
+        }
+        changed_principals.merge(
+            store
+                .create_principal(
+                    PrincipalSet::new(0, Type::Group)
+                        .with_field(PrincipalField::Name, "staff@acme.org")
+                        .with_field(PrincipalField::Members, names.clone()),
+                    Some(tenant_id),
+                    None,
+                )
+                .await
+                .unwrap()
+                .changed_principals,
+        );
+        assert_eq!(changed_principals.changes().len(), 1001);
+        assert_eq!(changed_principals.tenant_id(), Some(tenant_id));
+        server.invalidate_principal_caches(changed_principals).await;
+        let mut broadcasts = Vec::new();
+        while let Ok(event) = broadcast_rx.try_recv() {
+            broadcasts.push(event);
+        }
+        assert_eq!(broadcasts.len(), 1);
+        assert!(matches!(
+            broadcasts[0],
+            BroadcastEvent::InvalidateTenantAccessTokens(Some(id)) if id == tenant_id
+        ));
+
+        // Small batches list the deduplicated principal ids
+        let mut changed_principals = ChangedPrincipals::default();
+        for name in ["user1@acme.org", "user2@acme.org", "user1@acme.org"] {
+            changed_principals.merge(
+                store
+                    .update_principal(UpdatePrincipal::by_name(name).with_updates(vec![
+                        PrincipalUpdate::set(PrincipalField::Quota, PrincipalValue::Integer(1024)),
+                    ]))
+                    .await
+                    .unwrap(),
+            );
+        }
+        server.invalidate_principal_caches(changed_principals).await;
+        let mut broadcasts = Vec::new();
+        while let Ok(event) = broadcast_rx.try_recv() {
+            broadcasts.push(event);
+        }
+        assert_eq!(broadcasts.len(), 1);
+        assert!(matches!(
+            &broadcasts[0],
+            BroadcastEvent::InvalidateAccessTokens(ids) if ids.len() == 2
+        ));
+
+        // Clean up
+        store
+            .delete_principal(QueryBy::Name("staff@acme.org"))
+            .await
+            .unwrap();
+        for name in names {
+            store.delete_principal(QueryBy::Name(&name)).await.unwrap();
+        }
+        for name in organization_names("acme").into_iter().rev() {
+            store.delete_principal(QueryBy::Name(&name)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_scoped_names() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing tenant-scoped names with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let acme_id = store
+            .create_principals_batch(organization_batch("acme"), None, &NamePolicy::default())
+            .await
+            .unwrap()
+            .ids[0];
+        let globex_id = store
+            .create_principals_batch(organization_batch("globex"), None, &NamePolicy::default())
+            .await
+            .unwrap()
+            .ids[0];
+
+        // Both tenants can have a user named "info"
+        let scoped_names = NamePolicy {
+            scope: NameScope::Tenant,
+            ..Default::default()
+        };
+        let mut ids = Vec::new();
+        for (tenant_id, secret) in [(acme_id, "acme-secret"), (globex_id, "globex-secret")] {
+            ids.push(
+                store
+                    .create_principal_with_policy(
+                        PrincipalSet::new(0, Type::Individual)
+                            .with_field(PrincipalField::Name, "info")
+                            .with_field(PrincipalField::Secrets, secret),
+                        Some(tenant_id),
+                        None,
+                        &scoped_names,
+                    )
+                    .await
+                    .unwrap()
+                    .id,
+            );
+        }
+        assert_ne!(ids[0], ids[1]);
+
+        // Names remain unique within a tenant, and global names sharing a
+        // login with a tenant-scoped name are rejected
+        for (name, scope) in [
+            ("info", NameScope::Tenant),
+            ("info@acme.org", NameScope::Tenant),
+            ("info@acme.org", NameScope::Global),
+        ] {
+            assert_eq!(
+                store
+                    .create_principal_with_policy(
+                        PrincipalSet::new(0, Type::Individual)
+                            .with_field(PrincipalField::Name, name),
+                        Some(acme_id),
+                        None,
+                        &NamePolicy {
+                            scope,
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .map(|created| created.id),
+                Err(manage::err_exists(PrincipalField::Name, name)),
+            );
+        }
+
+        // The global mode still requires a domain
+        assert!(
+            store
+                .create_principal(
+                    PrincipalSet::new(0, Type::Individual)
+                        .with_field(PrincipalField::Name, "sales"),
+                    Some(acme_id),
+                    None,
+                )
+                .await
+                .is_err()
+        );
+
+        // Logins are resolved through the tenant of the domain, bare names
+        // are ambiguous and never match a tenant-scoped principal
+        for (name, tenant_id, expected) in [
+            ("info@acme.org", None, Some(ids[0])),
+            ("info@globex.org", None, Some(ids[1])),
+            ("info", Some(acme_id), Some(ids[0])),
+            ("info", Some(globex_id), Some(ids[1])),
+            ("info", None, None),
+            ("info@example.org", None, None),
+        ] {
+            assert_eq!(
+                store
+                    .resolve_principal_info(name, tenant_id)
+                    .await
+                    .unwrap()
+                    .map(|info| info.id),
+                expected,
+                "{name}"
+            );
+        }
+        for (username, secret, expected) in [
+            ("info@acme.org", "acme-secret", Some(ids[0])),
+            ("info@globex.org", "globex-secret", Some(ids[1])),
+            ("info@acme.org", "globex-secret", None),
+            ("info", "acme-secret", None),
+        ] {
+            assert_eq!(
+                store
+                    .query(QueryParams::credentials(&Credentials::new(
+                        username.into(),
+                        secret.into()
+                    )))
+                    .await
+                    .unwrap()
+                    .map(|principal| principal.id),
+                expected,
+                "{username}"
+            );
+        }
+
+        // Listings show the bare name
+        assert_eq!(
+            store
+                .list_principals(
+                    Some("info"),
+                    Some(acme_id),
+                    &[Type::Individual],
+                    false,
+                    0,
+                    0
+                )
+                .await
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|principal| principal.name)
+                .collect::<Vec<_>>(),
+            vec!["info".to_string()]
+        );
+
+        // Renames stay within the tenant
+        store
+            .update_principal(UpdatePrincipal::by_id(ids[0]).with_updates(vec![
+                PrincipalUpdate::set(PrincipalField::Name, PrincipalValue::String("sales".into())),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .resolve_principal_info("sales@acme.org", None)
+                .await
+                .unwrap()
+                .map(|info| info.id),
+            Some(ids[0])
+        );
+        assert_eq!(
+            store
+                .resolve_principal_info("info@acme.org", None)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            store
+                .update_principal(UpdatePrincipal::by_id(ids[0]).with_updates(vec![
+                    PrincipalUpdate::set(
+                        PrincipalField::Name,
+                        PrincipalValue::String("info@acme.org".into()),
+                    )
+                ]))
+                .await
+                .is_err()
+        );
+
+        // Clean up
+        store
+            .delete_principal(QueryBy::Name("sales@acme.org"))
+            .await
+            .unwrap();
+        store
+            .delete_principal(QueryBy::Name("info@globex.org"))
+            .await
+            .unwrap();
+        for name in organization_names("acme")
+            .into_iter()
+            .rev()
+            .chain(organization_names("globex").into_iter().rev())
+        {
+            store.delete_principal(QueryBy::Name(&name)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_reserved_names() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing reserved names with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let policy = NamePolicy {
+            reserved: DEFAULT_RESERVED_NAMES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            ..Default::default()
+        };
+        let permissions = Permissions::new();
+        let mut bypass_permissions = Permissions::new();
+        bypass_permissions.set(Permission::PrincipalReservedName as usize);
+
+        store
+            .create_principal(
+                PrincipalSet::new(0, Type::Domain).with_field(PrincipalField::Name, "example.org"),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        // Reserved names and local parts are rejected, regardless of case
+        for (principal, field, value) in [
+            (
+                PrincipalSet::new(0, Type::Individual).with_field(PrincipalField::Name, "Admin"),
+                PrincipalField::Name,
+                "admin",
+            ),
+            (
+                PrincipalSet::new(0, Type::Group).with_field(PrincipalField::Name, "postmaster"),
+                PrincipalField::Name,
+                "postmaster",
+            ),
+            (
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(
+                        PrincipalField::Emails,
+                        vec!["Mailer-Daemon@example.org".to_string()],
+                    ),
+                PrincipalField::Emails,
+                "mailer-daemon@example.org",
+            ),
+        ] {
+            assert_eq!(
+                store
+                    .create_principal_with_policy(principal, None, Some(&permissions), &policy)
+                    .await
+                    .map(|created| created.id),
+                Err(manage::err_reserved(field, value))
+            );
+        }
+
+        // Batches check the name of every principal
+        let mut batch = organization_batch("initech");
+        batch[2].principal = PrincipalSet::new(0, Type::Individual)
+            .with_field(PrincipalField::Name, "webmaster")
+            .with_field(
+                PrincipalField::Emails,
+                vec!["webmaster@initech.org".to_string()],
+            );
+        assert_eq!(
+            store
+                .create_principals_batch(batch, Some(&permissions), &policy)
+                .await
+                .map(|created| created.ids),
+            Err(manage::err_reserved(PrincipalField::Name, "webmaster")
+                .ctx(trc::Key::Type, "individual"))
+        );
+        assert_eq!(store.get_principal_id("initech").await.unwrap(), None);
+
+        // Other principal types are not affected
+        let list_id = store
+            .create_principal_with_policy(
+                PrincipalSet::new(0, Type::List).with_field(PrincipalField::Name, "support"),
+                None,
+                Some(&permissions),
+                &policy,
+            )
+            .await
+            .unwrap()
+            .id;
+
+        // Aliases and renames are checked on updates
+        let john_id = store
+            .create_principal_with_policy(
+                PrincipalSet::new(0, Type::Individual).with_field(PrincipalField::Name, "john"),
+                None,
+                Some(&permissions),
+                &policy,
+            )
+            .await
+            .unwrap()
+            .id;
+        for (update, field, value) in [
+            (
+                PrincipalUpdate::add_item(
+                    PrincipalField::Emails,
+                    PrincipalValue::String("Abuse@example.org".into()),
+                ),
+                PrincipalField::Emails,
+                "abuse@example.org",
+            ),
+            (
+                PrincipalUpdate::set(PrincipalField::Name, PrincipalValue::String("root".into())),
+                PrincipalField::Name,
+                "root",
+            ),
+        ] {
+            assert_eq!(
+                store
+                    .update_principal(
+                        UpdatePrincipal::by_id(john_id)
+                            .with_updates(vec![update])
+                            .with_allowed_permissions(&permissions)
+                            .with_name_policy(&policy),
+                    )
+                    .await,
+                Err(manage::err_reserved(field, value))
+            );
+        }
+
+        // The bypass permission and internal callers are exempt
+        let admin_id = store
+            .create_principal_with_policy(
+                PrincipalSet::new(0, Type::Individual).with_field(PrincipalField::Name, "admin"),
+                None,
+                Some(&bypass_permissions),
+                &policy,
+            )
+            .await
+            .unwrap()
+            .id;
+        store
+            .update_principal(
+                UpdatePrincipal::by_id(john_id)
+                    .with_updates(vec![PrincipalUpdate::add_item(
+                        PrincipalField::Emails,
+                        PrincipalValue::String("abuse@example.org".into()),
+                    )])
+                    .with_allowed_permissions(&bypass_permissions)
+                    .with_name_policy(&policy),
+            )
+            .await
+            .unwrap();
+        let postmaster_id = store
+            .create_principal_with_policy(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "postmaster"),
+                None,
+                None,
+                &policy,
+            )
+            .await
+            .unwrap()
+            .id;
+
+        // Clean up
+        for id in [list_id, john_id, admin_id, postmaster_id] {
+            store.delete_principal(QueryBy::Id(id)).await.unwrap();
+        }
+        store
+            .delete_principal(QueryBy::Name("example.org"))
+            .await
+            .unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_email_normalization() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing email normalization with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        // Domains created before normalization may use their Unicode form
+        for domain in ["example.org", "münchen.de"] {
+            store
+                .create_principal(
+                    PrincipalSet::new(0, Type::Domain).with_field(PrincipalField::Name, domain),
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        assert!(store.is_local_domain("xn--mnchen-3ya.de").await.unwrap());
+        assert!(store.is_local_domain("MÜNCHEN.DE").await.unwrap());
+
+        // Domains are stored in their ASCII form and local parts are folded
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(
+                        PrincipalField::Emails,
+                        vec![
+                            "John.Doe@Example.ORG".to_string(),
+                            "jd@MÜNCHEN.de".to_string(),
+                        ],
+                    ),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        assert_eq!(
+            store
+                .get_principal(john_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .email_addresses()
+                .collect::<Vec<_>>(),
+            vec!["john.doe@example.org", "jd@xn--mnchen-3ya.de"]
+        );
+
+        // Recipients and logins are resolved in any form
+        for address in [
+            "john.doe@example.org",
+            "JOHN.DOE@EXAMPLE.ORG",
+            "jd@münchen.de",
+            "JD@xn--mnchen-3ya.de",
+        ] {
+            assert_eq!(
+                store.email_to_id(address).await.unwrap(),
+                Some(john_id),
+                "{address}"
+            );
+            assert_eq!(store.rcpt(address).await.unwrap(), RcptType::Mailbox);
+        }
+
+        // Duplicates are detected using the normalized form
+        assert_eq!(
+            store
+                .create_principal(
+                    PrincipalSet::new(0, Type::Individual)
+                        .with_field(PrincipalField::Name, "jane")
+                        .with_field(
+                            PrincipalField::Emails,
+                            vec!["JOHN.doe@example.ORG".to_string()],
+                        ),
+                    None,
+                    None,
+                )
+                .await
+                .map(|created| created.id),
+            Err(manage::err_exists(
+                PrincipalField::Emails,
+                "john.doe@example.org"
+            ))
+        );
+
+        // Local parts keep their case when folding is disabled
+        let policy = NamePolicy {
+            case_sensitive_local_part: true,
+            ..Default::default()
+        };
+        let jane_id = store
+            .create_principal_with_policy(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "jane")
+                    .with_field(
+                        PrincipalField::Emails,
+                        vec!["Jane.Roe@Example.org".to_string()],
+                    ),
+                None,
+                None,
+                &policy,
+            )
+            .await
+            .unwrap()
+            .id;
+        assert_eq!(
+            store.email_to_id("Jane.Roe@example.org").await.unwrap(),
+            Some(jane_id)
+        );
+        assert_eq!(
+            store
+                .update_principal(
+                    UpdatePrincipal::by_id(jane_id)
+                        .with_updates(vec![PrincipalUpdate::add_item(
+                            PrincipalField::Emails,
+                            PrincipalValue::String("jd@München.DE".into()),
+                        )])
+                        .with_name_policy(&policy),
+                )
+                .await,
+            Err(manage::err_exists(
+                PrincipalField::Emails,
+                "jd@xn--mnchen-3ya.de"
+            ))
+        );
+
+        // Aliases are removed using any of their forms
+        store
+            .update_principal(UpdatePrincipal::by_id(john_id).with_updates(vec![
+                PrincipalUpdate::remove_item(
+                    PrincipalField::Emails,
+                    PrincipalValue::String("JD@münchen.de".into()),
+                ),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.rcpt("jd@xn--mnchen-3ya.de").await.unwrap(),
+            RcptType::Invalid
+        );
+
+        // Clean up
+        for id in [john_id, jane_id] {
+            store.delete_principal(QueryBy::Id(id)).await.unwrap();
+        }
+        for domain in ["example.org", "münchen.de"] {
+            store.delete_principal(QueryBy::Name(domain)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_app_passwords() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing app passwords with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        // Accounts with TOTP enabled can't use their password alone
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(
+                        PrincipalField::Secrets,
+                        vec![
+                            "12345".to_string(),
+                            "otpauth://totp/Stalwart:john?secret=JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP&issuer=Stalwart".to_string(),
+                        ],
+                    ),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let login = |secret: &str, protocol: Option<AuthProtocol>| {
+            let store = store.clone();
+            let credentials = Credentials::Plain {
+                username: "john".to_string(),
+                secret: secret.to_string(),
+            };
+            async move {
+                store
+                    .query(QueryParams::credentials(&credentials).with_protocol(protocol))
+                    .await
+                    .map(|principal| principal.map(|p| p.id))
+            }
+        };
+        assert!(
+            login("12345", Some(AuthProtocol::Imap))
+                .await
+                .unwrap_err()
+                .matches(trc::EventType::Auth(trc::AuthEvent::MissingTotp))
+        );
+
+        // App passwords are pre-authorized and may be restricted to some protocols
+        let imap_pass = store
+            .add_app_password(john_id, "Phone", &[AuthProtocol::Imap])
+            .await
+            .unwrap();
+        let any_pass = store
+            .add_app_password(john_id, "Laptop", &[])
+            .await
+            .unwrap();
+        assert_ne!(imap_pass, any_pass);
+        assert_eq!(
+            store.add_app_password(john_id, "Phone", &[]).await,
+            Err(manage::err_exists(PrincipalField::Secrets, "Phone"))
+        );
+        assert!(
+            store
+                .add_app_password(john_id, "Bad$Label", &[])
+                .await
+                .is_err()
+        );
+        assert!(
+            store
+                .add_app_password(john_id, "Admin", &[AuthProtocol::Management])
+                .await
+                .is_err()
+        );
+        for (secret, protocol, expected) in [
+            (&imap_pass, Some(AuthProtocol::Imap), Some(john_id)),
+            (&imap_pass, Some(AuthProtocol::Smtp), None),
+            (&imap_pass, Some(AuthProtocol::Management), None),
+            (&any_pass, Some(AuthProtocol::Smtp), Some(john_id)),
+            (&any_pass, Some(AuthProtocol::Http), Some(john_id)),
+            (&any_pass, Some(AuthProtocol::Management), None),
+        ] {
+            assert_eq!(
+                login(secret, protocol).await.unwrap(),
+                expected,
+                "{protocol:?}"
+            );
+        }
+
+        // Listing shows metadata and usage, never the secret
+        let entries = store.list_app_passwords(john_id).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        let laptop = entries.iter().find(|e| e.label == "Laptop").unwrap();
+        let phone = entries.iter().find(|e| e.label == "Phone").unwrap();
+        assert!(laptop.created_at.is_some());
+        assert!(laptop.last_used.is_some());
+        assert!(laptop.protocols.is_empty());
+        assert_eq!(phone.protocols, vec![AuthProtocol::Imap]);
+
+        // Revoked app passwords can no longer be used
+        assert!(store.remove_app_password(john_id, "Laptop").await.unwrap());
+        assert!(!store.remove_app_password(john_id, "Laptop").await.unwrap());
+        assert_eq!(
+            login(&any_pass, Some(AuthProtocol::Smtp)).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            login(&imap_pass, Some(AuthProtocol::Imap)).await.unwrap(),
+            Some(john_id)
+        );
+        assert_eq!(
+            store
+                .list_app_passwords(john_id)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| e.label)
+                .collect::<Vec<_>>(),
+            vec!["Phone".to_string()]
+        );
+
+        // Usage records are removed with the account
+        store.delete_principal(QueryBy::Id(john_id)).await.unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_totp() {
+    let config = DirectoryTest::new(None).await;
+    let cipher = SymmetricEncrypt::new(b"oauth key", "totp secret");
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing TOTP factors with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::Secrets, "12345"),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let login = |secret: String| {
+            let store = store.clone();
+            let cipher = &cipher;
+            let credentials = Credentials::Plain {
+                username: "john".to_string(),
+                secret,
+            };
+            async move {
+                store
+                    .query(
+                        QueryParams::credentials(&credentials)
+                            .with_protocol(Some(AuthProtocol::Imap))
+                            .with_secret_cipher(Some(cipher)),
+                    )
+                    .await
+                    .map(|principal| principal.map(|p| p.id))
+            }
+        };
+
+        // Factors are only active once confirmed with a valid code
+        let enrollment = store
+            .start_totp_enrollment(john_id, "mail.example.org", "john", &cipher)
+            .await
+            .unwrap();
+        assert!(enrollment.uri.starts_with("otpauth://totp/"));
+        assert!(enrollment.uri.contains(&enrollment.secret));
+        let totp = TOTP::from_url(&enrollment.uri).unwrap();
+        assert_eq!(totp.skew, 1);
+        assert_eq!(login("12345".to_string()).await.unwrap(), Some(john_id));
+        assert!(
+            store
+                .list_totp_factors(john_id, &cipher)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let code = totp.generate_current().unwrap();
+        let wrong_code = format!(
+            "{:06}",
+            (code.parse::<u32>().unwrap() + 500_000) % 1_000_000
+        );
+        assert!(
+            !store
+                .confirm_totp_enrollment(john_id, &wrong_code, &cipher)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .confirm_totp_enrollment(john_id, &code, &cipher)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .confirm_totp_enrollment(john_id, &code, &cipher)
+                .await
+                .is_err()
+        );
+
+        // The secret is stored encrypted
+        let principal = store.get_principal(john_id).await.unwrap().unwrap();
+        assert!(principal.data.iter().any(|item| matches!(
+            item,
+            PrincipalData::OtpAuth(secret) if secret.starts_with("$otp$")
+                && !secret.contains(&enrollment.secret)
+        )));
+        assert_eq!(
+            store.list_totp_factors(john_id, &cipher).await.unwrap(),
+            vec![TotpFactor {
+                issuer: Some("mail.example.org".to_string()),
+                account_name: Some("john".to_string()),
+                encrypted: true,
+            }]
+        );
+
+        // Logins require the code once the factor is active
+        assert!(
+            login("12345".to_string())
+                .await
+                .unwrap_err()
+                .matches(trc::EventType::Auth(trc::AuthEvent::MissingTotp))
+        );
+        assert_eq!(login(format!("12345${code}")).await.unwrap(), Some(john_id));
+        assert_eq!(login(format!("54321${code}")).await.unwrap(), None);
+
+        // Code attempts are rate limited per account
+        for _ in 0..4 {
+            assert_eq!(login(format!("12345${wrong_code}")).await.unwrap(), None);
+        }
+        assert!(
+            login(format!("12345${code}"))
+                .await
+                .unwrap_err()
+                .matches(trc::EventType::Auth(trc::AuthEvent::TooManyAttempts))
+        );
+        store.reset_totp_failures(john_id).await.unwrap();
+        assert_eq!(login(format!("12345${code}")).await.unwrap(), Some(john_id));
+
+        // Removing the factor restores password logins
+        assert!(store.remove_totp_factor(john_id).await.unwrap());
+        assert!(!store.remove_totp_factor(john_id).await.unwrap());
+        assert_eq!(login("12345".to_string()).await.unwrap(), Some(john_id));
+
+        store.delete_principal(QueryBy::Id(john_id)).await.unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_recovery_codes() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing recovery codes with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::Secrets, "12345"),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let login = |secret: String| {
+            let store = store.clone();
+            let credentials = Credentials::Plain {
+                username: "john".to_string(),
+                secret,
+            };
+            async move {
+                let used = AtomicBool::new(false);
+                store
+                    .query(
+                        QueryParams::credentials(&credentials)
+                            .with_protocol(Some(AuthProtocol::Imap))
+                            .with_recovery_code_used(Some(&used)),
+                    )
+                    .await
+                    .map(|principal| principal.map(|p| (p.id, used.load(Ordering::Relaxed))))
+            }
+        };
+
+        // Codes are accepted once, following the password
+        let codes = store.generate_recovery_codes(john_id).await.unwrap();
+        assert_eq!(codes.len(), 10);
+        assert_eq!(
+            login(format!("12345${}", codes[0])).await.unwrap(),
+            Some((john_id, true))
+        );
+        assert_eq!(login(format!("12345${}", codes[0])).await.unwrap(), None);
+        assert_eq!(login(format!("54321${}", codes[1])).await.unwrap(), None);
+        assert_eq!(
+            login("12345".to_string()).await.unwrap(),
+            Some((john_id, false))
+        );
+
+        // Concurrent logins with the same code succeed only once
+        let (a, b) = tokio::join!(
+            login(format!("12345${}", codes[1])),
+            login(format!("12345${}", codes[1]))
+        );
+        assert_eq!(
+            [a.unwrap(), b.unwrap()]
+                .into_iter()
+                .filter(|result| result.is_some())
+                .count(),
+            1
+        );
+        assert_eq!(
+            store
+                .get_principal(john_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .recovery_code_count(),
+            8
+        );
+
+        // Generating new codes invalidates the previous ones
+        let new_codes = store.generate_recovery_codes(john_id).await.unwrap();
+        assert!(new_codes.iter().all(|code| !codes.contains(code)));
+        assert_eq!(login(format!("12345${}", codes[2])).await.unwrap(), None);
+        assert!(store.remove_recovery_codes(john_id).await.unwrap());
+        assert!(!store.remove_recovery_codes(john_id).await.unwrap());
+        assert_eq!(
+            login(format!("12345${}", new_codes[0])).await.unwrap(),
+            None
+        );
+
+        store.delete_principal(QueryBy::Id(john_id)).await.unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_security_notices() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing security notices with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::SecurityNoticeOptOut, 1u64),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let principal = store.get_principal(john_id).await.unwrap().unwrap();
+        assert!(principal.security_notice_opt_out());
+        store
+            .update_principal(UpdatePrincipal::by_id(john_id).with_updates(vec![
+                PrincipalUpdate::set(
+                    PrincipalField::SecurityNoticeOptOut,
+                    PrincipalValue::Integer(0),
+                ),
+            ]))
+            .await
+            .unwrap();
+        let principal = store.get_principal(john_id).await.unwrap().unwrap();
+        assert!(!principal.security_notice_opt_out());
+
+        // The first country is not new, countries are compared ignoring case
+        assert!(!store.record_login_country(john_id, "ES").await.unwrap());
+        assert!(!store.record_login_country(john_id, "es").await.unwrap());
+        assert!(store.record_login_country(john_id, "FR").await.unwrap());
+        assert!(!store.record_login_country(john_id, "fr").await.unwrap());
+
+        // Notices are sent once per period and class
+        assert!(store.mark_security_notice(john_id, 1, 60).await.unwrap());
+        assert!(!store.mark_security_notice(john_id, 1, 60).await.unwrap());
+        assert!(store.mark_security_notice(john_id, 2, 60).await.unwrap());
+        assert!(store.mark_security_notice(john_id, 3, 0).await.unwrap());
+        assert!(store.mark_security_notice(john_id, 3, 0).await.unwrap());
+
+        store.delete_principal(QueryBy::Id(john_id)).await.unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_password_history() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing password history with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        // Tenants remember the last 3 passwords and require a day between changes
+        let acme_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Tenant)
+                    .with_field(PrincipalField::Name, "acme")
+                    .with_field(PrincipalField::PasswordHistoryLength, 3u64)
+                    .with_field(PrincipalField::MinPasswordAgeDays, 1u64),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let tenant = store
+            .map_principal(store.get_principal(acme_id).await.unwrap().unwrap(), &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            tenant.get_int(PrincipalField::PasswordHistoryLength),
+            Some(3)
+        );
+        assert_eq!(tenant.get_int(PrincipalField::MinPasswordAgeDays), Some(1));
+        assert!(
+            store
+                .update_principal(UpdatePrincipal::by_id(acme_id).with_updates(vec![
+                    PrincipalUpdate::set(
+                        PrincipalField::PasswordHistoryLength,
+                        PrincipalValue::Integer(1000),
+                    )
+                ]))
+                .await
+                .is_err()
+        );
+
+        // Passwords hashed with older schemes are detected when reused
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::Secrets, "{MD5}Ruk0tBS/B7i5UU4R6PJhZA=="),
+                Some(acme_id),
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let set_password = |secret: &str, self_service: bool| {
+            let store = store.clone();
+            let update = UpdatePrincipal::by_id(john_id).with_updates(vec![
+                PrincipalUpdate::remove_item(
+                    PrincipalField::Secrets,
+                    PrincipalValue::String(String::new()),
+                ),
+                PrincipalUpdate::add_item(
+                    PrincipalField::Secrets,
+                    PrincipalValue::String(secret.to_string()),
+                ),
+            ]);
+            let update = if self_service {
+                update.self_service()
+            } else {
+                update
+            };
+            async move { store.update_principal(update).await.map(|_| ()) }
+        };
+        let reused = manage::err_password_reused(3);
+        set_password("{SHA}iXX0fdkxxqv6YISNpgr8bDl2Kds=", false)
+            .await
+            .unwrap();
+        assert_eq!(set_password("pass-one", false).await, Err(reused.clone()));
+        assert_eq!(set_password("pass-two", false).await, Err(reused.clone()));
+
+        // The minimum age only applies to changes made by the account owner
+        assert!(
+            set_password("pass-three", true)
+                .await
+                .unwrap_err()
+                .matches(trc::EventType::Manage(trc::ManageEvent::Error))
+        );
+        set_password(
+            "{SSHA256}7KswX9YUL/K34XOM1ZMYkDrWOd37JZnXrbKlH8Se2d9zYWx0c2FsdA==",
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(set_password("pass-two", false).await, Err(reused.clone()));
+        assert_eq!(set_password("pass-three", false).await, Err(reused.clone()));
+
+        // Pruned passwords can be used again, history is not exposed
+        set_password("pass-four", false).await.unwrap();
+        let principal = store.get_principal(john_id).await.unwrap().unwrap();
+        assert_eq!(
+            principal.password_history(),
+            &[
+                "{SSHA256}7KswX9YUL/K34XOM1ZMYkDrWOd37JZnXrbKlH8Se2d9zYWx0c2FsdA==".to_string(),
+                "{SHA}iXX0fdkxxqv6YISNpgr8bDl2Kds=".to_string(),
+            ]
+        );
+        let principal = store.map_principal(principal, &[]).await.unwrap();
+        assert_eq!(
+            principal.get_str_array(PrincipalField::Secrets),
+            Some(&["pass-four".to_string()][..])
+        );
+        set_password("pass-one", false).await.unwrap();
+
+        // Disabling the history clears it on the next change
+        store
+            .update_principal(UpdatePrincipal::by_id(acme_id).with_updates(vec![
+                PrincipalUpdate::set(
+                    PrincipalField::PasswordHistoryLength,
+                    PrincipalValue::Integer(0),
+                ),
+            ]))
+            .await
+            .unwrap();
+        set_password("pass-four", false).await.unwrap();
+        assert!(
+            store
+                .get_principal(john_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .password_history()
+                .is_empty()
+        );
+
+        store.delete_principal(QueryBy::Id(john_id)).await.unwrap();
+        store.delete_principal(QueryBy::Id(acme_id)).await.unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_account_lockout() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing account lockout with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        // Tenants lock accounts after 3 failures, counted per protocol
+        let acme_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Tenant)
+                    .with_field(PrincipalField::Name, "acme")
+                    .with_field(PrincipalField::LockoutThreshold, 3u64)
+                    .with_field(PrincipalField::LockoutDuration, 3600u64)
+                    .with_field(PrincipalField::LockoutPerProtocol, 1u64),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let tenant = store
+            .map_principal(store.get_principal(acme_id).await.unwrap().unwrap(), &[])
+            .await
+            .unwrap();
+        assert_eq!(tenant.get_int(PrincipalField::LockoutThreshold), Some(3));
+        assert_eq!(tenant.get_int(PrincipalField::LockoutWindow), Some(900));
+        assert_eq!(tenant.get_int(PrincipalField::LockoutDuration), Some(3600));
+        assert_eq!(tenant.get_int(PrincipalField::LockoutPerProtocol), Some(1));
+        assert_eq!(tenant.get_int(PrincipalField::LockoutNotify), Some(0));
+        assert!(
+            store
+                .update_principal(UpdatePrincipal::by_id(acme_id).with_updates(vec![
+                    PrincipalUpdate::set(PrincipalField::LockoutWindow, PrincipalValue::Integer(0))
+                ]))
+                .await
+                .is_err()
+        );
+
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::Secrets, "secret"),
+                Some(acme_id),
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let login = |secret: &str, protocol: AuthProtocol| {
+            let store = store.clone();
+            let credentials = Credentials::new("john".to_string(), secret.to_string());
+            async move {
+                store
+                    .query(QueryParams::credentials(&credentials).with_protocol(protocol))
+                    .await
+                    .map(|principal| principal.map(|principal| principal.id))
+            }
+        };
+
+        // A successful login resets the failure counter
+        for _ in 0..2 {
+            assert_eq!(login("wrong", AuthProtocol::Imap).await, Ok(None));
+        }
+        assert_eq!(login("secret", AuthProtocol::Imap).await, Ok(Some(john_id)));
+        for _ in 0..2 {
+            assert_eq!(login("wrong", AuthProtocol::Imap).await, Ok(None));
+        }
+
+        // Failures over other protocols are counted separately
+        assert_eq!(login("wrong", AuthProtocol::Smtp).await, Ok(None));
+        assert_eq!(login("secret", AuthProtocol::Smtp).await, Ok(Some(john_id)));
+
+        // Reaching the threshold locks the account for all protocols
+        assert!(
+            login("wrong", AuthProtocol::Imap)
+                .await
+                .unwrap_err()
+                .matches(trc::EventType::Directory(
+                    trc::DirectoryEvent::AccountLocked
+                ))
+        );
+        assert!(store.account_locked_until(john_id).await.unwrap().is_some());
+        for protocol in [AuthProtocol::Imap, AuthProtocol::Smtp] {
+            assert!(
+                login("secret", protocol)
+                    .await
+                    .unwrap_err()
+                    .matches(trc::EventType::Auth(trc::AuthEvent::AccountLocked))
+            );
+        }
+
+        // Administrators can unlock accounts before the lock expires
+        assert!(store.unlock_account(john_id).await.unwrap());
+        assert!(!store.unlock_account(john_id).await.unwrap());
+        assert_eq!(login("secret", AuthProtocol::Imap).await, Ok(Some(john_id)));
+
+        // Disabling the policy stops tracking failures
+        store
+            .update_principal(UpdatePrincipal::by_id(acme_id).with_updates(vec![
+                PrincipalUpdate::set(PrincipalField::LockoutThreshold, PrincipalValue::Integer(0)),
+            ]))
+            .await
+            .unwrap();
+        for _ in 0..5 {
+            assert_eq!(login("wrong", AuthProtocol::Imap).await, Ok(None));
+        }
+        assert_eq!(login("secret", AuthProtocol::Imap).await, Ok(Some(john_id)));
+
+        store.delete_principal(QueryBy::Id(john_id)).await.unwrap();
+        store.delete_principal(QueryBy::Id(acme_id)).await.unwrap();
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_activity() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing principal activity with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let mut ids = Vec::new();
+        for name in ["john", "jane", "bill"] {
+            ids.push(
+                store
+                    .create_principal(
+                        PrincipalSet::new(0, Type::Individual)
+                            .with_field(PrincipalField::Name, name),
+                        None,
+                        None,
+                    )
+                    .await
+                    .unwrap()
+                    .id,
+            );
+        }
+        let (john_id, jane_id) = (ids[0], ids[1]);
+        let modified_at = store
+            .get_principal(john_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .modified_at();
+
+        // Activity is recorded per type without modifying the principal
+        store
+            .set_last_activity(john_id, ActivityType::Imap, 1000)
+            .await
+            .unwrap();
+        store
+            .set_last_activity(john_id, ActivityType::Mailbox, 3000)
+            .await
+            .unwrap();
+        store
+            .set_last_activity(jane_id, ActivityType::Smtp, 5000)
+            .await
+            .unwrap();
+        let principal = store.get_principal(john_id).await.unwrap().unwrap();
+        assert_eq!(principal.modified_at(), modified_at);
+        assert_eq!(
+            store.last_activity(john_id).await.unwrap(),
+            vec![(ActivityType::Imap, 1000), (ActivityType::Mailbox, 3000)]
+        );
+        assert_eq!(store.last_active_at(john_id).await.unwrap(), Some(3000));
+        let principal = store
+            .map_principal(principal, &[PrincipalField::LastActivity])
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&principal).unwrap()["lastActivity"],
+            serde_json::json!({"imap": 1000, "mailbox": 3000})
+        );
+
+        // Principals never active or inactive since a given time are listed
+        let inactive = store
+            .list_principals(None, None, &[Type::Individual], false, 0, 0)
+            .await
+            .unwrap()
+            .retain_inactive(&store, 4000)
+            .await
+            .unwrap();
+        assert_eq!(inactive.total, 2);
+        assert_eq!(
+            inactive
+                .items
+                .iter()
+                .map(|principal| principal.name())
+                .collect::<AHashSet<_>>(),
+            AHashSet::from_iter(["john", "bill"])
+        );
+
+        for id in ids {
+            store.delete_principal(QueryBy::Id(id)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_account_schedule() {
+    // Events take effect from the UTC second they are due
+    let schedule = AccountSchedule {
+        disable_at: Some(1000),
+        enable_at: None,
+    };
+    assert_eq!(schedule.is_disabled_at(999), None);
+    assert_eq!(schedule.is_disabled_at(1000), Some(true));
+    assert_eq!(schedule.next_change(999), Some(1000));
+    assert_eq!(schedule.next_change(1000), None);
+    let schedule = AccountSchedule {
+        disable_at: Some(1000),
+        enable_at: Some(2000),
+    };
+    assert_eq!(schedule.is_disabled_at(1999), Some(true));
+    assert_eq!(schedule.is_disabled_at(2000), Some(false));
+    assert_eq!(schedule.next_change(1000), Some(2000));
+
+    // A disable wins over an enable due at the same instant
+    let schedule = AccountSchedule {
+        disable_at: Some(1000),
+        enable_at: Some(1000),
+    };
+    assert_eq!(schedule.is_disabled_at(999), None);
+    assert_eq!(schedule.is_disabled_at(1000), Some(true));
+
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing account schedules with store {:?}", store_id);
+        store_destroy(&store).await;
+        let now = now();
+
+        // Disabling in the past is the same as disabling now
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::DisableAt, now - 3600),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let john = store.get_principal(john_id).await.unwrap().unwrap();
+        assert!(john.schedule().is_empty());
+        assert!(john.is_disabled_at(now));
+        let john = store
+            .map_principal(
+                john,
+                &[
+                    PrincipalField::DisabledPermissions,
+                    PrincipalField::Disabled,
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(john.get_int(PrincipalField::Disabled), Some(1));
+        assert_eq!(
+            john.get_str_array(PrincipalField::DisabledPermissions),
+            Some(&["authenticate".to_string()][..])
+        );
+
+        // Scheduled events are kept until they are due
+        let jane_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual).with_field(PrincipalField::Name, "jane"),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        store
+            .update_principal(UpdatePrincipal::by_id(jane_id).with_updates(vec![
+                PrincipalUpdate::set(
+                    PrincipalField::DisableAt,
+                    PrincipalValue::Integer(now + 3600),
+                ),
+                PrincipalUpdate::set(
+                    PrincipalField::EnableAt,
+                    PrincipalValue::Integer(now + 7200),
+                ),
+            ]))
+            .await
+            .unwrap();
+        let jane = store.get_principal(jane_id).await.unwrap().unwrap();
+        assert_eq!(
+            jane.schedule(),
+            AccountSchedule {
+                disable_at: Some(now + 3600),
+                enable_at: Some(now + 7200),
+            }
+        );
+        assert!(!jane.is_disabled_at(now + 3599));
+        assert!(jane.is_disabled_at(now + 3600));
+        assert!(jane.is_disabled_at(now + 7199));
+        assert!(!jane.is_disabled_at(now + 7200));
+        let jane = store
+            .map_principal(
+                jane,
+                &[
+                    PrincipalField::DisableAt,
+                    PrincipalField::EnableAt,
+                    PrincipalField::Disabled,
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(jane.get_int(PrincipalField::DisableAt), Some(now + 3600));
+        assert_eq!(jane.get_int(PrincipalField::EnableAt), Some(now + 7200));
+        assert_eq!(jane.get_int(PrincipalField::Disabled), None);
+
+        // Disables are only notified once
+        assert!(
+            store
+                .mark_disable_notified(jane_id, now + 3600)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .mark_disable_notified(jane_id, now + 3600)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .mark_disable_notified(jane_id, now + 4000)
+                .await
+                .unwrap()
+        );
+
+        // Scheduled enable of a disabled account, zero clears it
+        for (enable_at, is_disabled) in [(now + 60, true), (0, true), (now - 1, false)] {
+            store
+                .update_principal(UpdatePrincipal::by_id(john_id).with_updates(vec![
+                    PrincipalUpdate::set(
+                        PrincipalField::EnableAt,
+                        PrincipalValue::Integer(enable_at),
+                    ),
+                ]))
+                .await
+                .unwrap();
+            let john = store.get_principal(john_id).await.unwrap().unwrap();
+            assert_eq!(john.is_disabled_at(now), is_disabled, "{enable_at}");
+            if enable_at > now {
+                assert!(!john.is_disabled_at(enable_at));
+                assert!(john.is_disabled_at(enable_at - 1));
+            } else {
+                assert!(john.schedule().is_empty());
+            }
+        }
+
+        for id in [john_id, jane_id] {
+            store.delete_principal(QueryBy::Id(id)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_external_id() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing external ids with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "john")
+                    .with_field(PrincipalField::ExternalId, "hr-1"),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        assert_eq!(
+            store
+                .resolve_external_id("hr-1", None)
+                .await
+                .unwrap()
+                .map(|info| info.id),
+            Some(john_id)
+        );
+
+        // External IDs are unique and conflicts name the current holder
+        let err = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "jane")
+                    .with_field(PrincipalField::ExternalId, "hr-1"),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.matches(trc::EventType::Manage(trc::ManageEvent::AlreadyExists)));
+        assert_eq!(err.value_as_str(trc::Key::AccountName), Some("john"));
+
+        // The same external ID can be used in another tenant
+        let tenant_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Tenant).with_field(PrincipalField::Name, "acme"),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let jane_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, "jane")
+                    .with_field(PrincipalField::ExternalId, "hr-1"),
+                Some(tenant_id),
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        assert_eq!(
+            store
+                .resolve_external_id("hr-1", Some(tenant_id))
+                .await
+                .unwrap()
+                .map(|info| info.id),
+            Some(jane_id)
+        );
+
+        // Updates move the index entry, clearing the value removes it
+        for (value, previous) in [("hr-2", "hr-1"), ("", "hr-2")] {
+            store
+                .update_principal(UpdatePrincipal::by_id(john_id).with_updates(vec![
+                    PrincipalUpdate::set(
+                        PrincipalField::ExternalId,
+                        PrincipalValue::String(value.to_string()),
+                    ),
+                ]))
+                .await
+                .unwrap();
+            assert!(
+                store
+                    .resolve_external_id(previous, None)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert_eq!(
+                store
+                    .get_principal(john_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .external_id(),
+                Some(value).filter(|v| !v.is_empty())
+            );
+        }
+        assert_eq!(
+            store
+                .resolve_external_id("hr-1", Some(tenant_id))
+                .await
+                .unwrap()
+                .map(|info| info.id),
+            Some(jane_id)
+        );
+
+        for id in [john_id, jane_id, tenant_id] {
+            store.delete_principal(QueryBy::Id(id)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+#[tokio::test]
+async fn internal_directory_avatar() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing avatars with store {:?}", store_id);
+        store_destroy(&store).await;
+
+        let tenant_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Tenant)
+                    .with_field(PrincipalField::Name, "acme")
+                    .with_field(PrincipalField::AvatarSelfService, 0u64),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        let john_id = store
+            .create_principal(
+                PrincipalSet::new(0, Type::Individual).with_field(PrincipalField::Name, "john"),
+                Some(tenant_id),
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+
+        // Self-service is allowed unless the tenant forbids it
+        let tenant = store.get_principal(tenant_id).await.unwrap().unwrap();
+        assert!(!tenant.avatar_self_service());
+        store
+            .update_principal(UpdatePrincipal::by_id(tenant_id).with_updates(vec![
+                PrincipalUpdate::set(
+                    PrincipalField::AvatarSelfService,
+                    PrincipalValue::Integer(1),
+                ),
+            ]))
+            .await
+            .unwrap();
+        let tenant = store.get_principal(tenant_id).await.unwrap().unwrap();
+        assert!(tenant.avatar_self_service());
+
+        // Replacing an avatar returns the previous one
+        let avatar = |hash: &str| Avatar {
+            hash: hash.to_string(),
+            content_type: "image/png".to_string(),
+        };
+        assert_eq!(
+            store.set_avatar(john_id, Some(avatar("a1"))).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            store.set_avatar(john_id, Some(avatar("b2"))).await.unwrap(),
+            Some(avatar("a1"))
+        );
+
+        // Listings include the hash of the avatar
+        let john = store
+            .map_principal(
+                store.get_principal(john_id).await.unwrap().unwrap(),
+                &[PrincipalField::Avatar],
+            )
+            .await
+            .unwrap();
+        assert_eq!(john.get_str(PrincipalField::Avatar), Some("b2"));
+        assert_eq!(
+            store.set_avatar(john_id, None).await.unwrap(),
+            Some(avatar("b2"))
+        );
+        assert_eq!(
+            store
+                .get_principal(john_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .avatar(),
+            None
+        );
+
+        for id in [john_id, tenant_id] {
+            store.delete_principal(QueryBy::Id(id)).await.unwrap();
+        }
+        store_assert_is_empty(&store, store.clone().into(), true).await;
+    }
+}
+
+fn organization_names(name: &str) -> Vec<String> {
+    [name.to_string(), format!("{name}.org")]
+        .into_iter()
+        .chain((0..5).map(|idx| format!("admin{idx}@{name}.org")))
+        .collect()
+}
+
+fn organization_batch(name: &str) -> Vec<BatchPrincipal> {
+    organization_names(name)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| match idx {
+            0 => BatchPrincipal {
+                principal: PrincipalSet::new(0, Type::Tenant)
+                    .with_field(PrincipalField::Name, name),
+                tenant: None,
+            },
+            1 => BatchPrincipal {
+                principal: PrincipalSet::new(0, Type::Domain)
+                    .with_field(PrincipalField::Name, name),
+                tenant: Some(BatchTenant::Created(0)),
+            },
+            _ => BatchPrincipal {
+                principal: PrincipalSet::new(0, Type::Individual)
+                    .with_field(PrincipalField::Name, name.clone())
+                    .with_field(PrincipalField::Emails, vec![name])
+                    .with_field(PrincipalField::Roles, vec!["tenant-admin".to_string()]),
+                tenant: Some(BatchTenant::Created(0)),
+            },
+        })
+        .collect()
+}
+
 #[allow(async_fn_in_trait)]
 pub trait TestInternalDirectory {
     async fn create_test_user(&self, login: &str, secret: &str, name: &str, emails: &[&str])
@@ -168,7 +168,7 @@ async fn init_imap_tests(delete_if_exists: bool) -> IMAPTest {
     // Parse core
     let tracers = Telemetry::parse(&mut config, &stores);
     let core = Core::parse(&mut config, stores, Default::default()).await;
-    let data = Data::parse(&mut config);
+    let data = Data::parse(&mut config, &core);
     let cache = Caches::parse(&mut config);
 
     let store = core.storage.data.clone();
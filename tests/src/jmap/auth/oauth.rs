@@ -15,10 +15,13 @@ use base64::{Engine, engine::general_purpose};
 use biscuit::{JWT, SingleOrMultiple, jwk::JWKSet};
 use bytes::Bytes;
 use common::auth::oauth::{
+    GrantType,
     introspect::OAuthIntrospect,
     oidc::StandardClaims,
     registration::{ClientRegistrationRequest, ClientRegistrationResponse},
+    token::TokenGrant,
 };
+use directory::Permission;
 use http::auth::oauth::{
     DeviceAuthResponse, ErrorType, OAuthCodeRequest, TokenResponse, auth::OAuthMetadata,
     openid::OpenIdMetadata,
@@ -218,6 +221,212 @@ pub async fn test(params: &mut JMAPTest) {
         .await;
     pop3.assert_read(pop::ResponseType::Ok).await;
 
+    // ------------------------
+    // Introspection and revocation
+    // ------------------------
+
+    // Long lived tokens are issued directly, as the configured ones expire in a second
+    let john_id = account.id().document_id();
+    let admin_id = params.account("admin").id().document_id();
+    let jane_id = params.account("jane.smith@example.com").id().document_id();
+    let issue = |grant_type: GrantType, account_id: u32, expiry_in: u64| {
+        let server = server.clone();
+        let client_id = client_id.clone();
+        async move {
+            server
+                .encode_access_token(grant_type, account_id, &client_id, expiry_in)
+                .await
+                .unwrap()
+        }
+    };
+    let access_token = issue(GrantType::AccessToken, john_id, 3600).await;
+    let refresh_token = issue(GrantType::RefreshToken, john_id, 3600).await;
+    let admin_token = issue(GrantType::AccessToken, admin_id, 3600).await;
+    let jane_token = issue(GrantType::AccessToken, jane_id, 3600).await;
+    let introspect = |auth_token: String, token: String| {
+        let url = metadata.introspection_endpoint.clone();
+        async move {
+            post_with_auth::<OAuthIntrospect>(
+                &url,
+                auth_token.as_str().into(),
+                &AHashMap::from_iter([("token".to_string(), token)]),
+            )
+            .await
+        }
+    };
+
+    // Accounts can introspect their own tokens
+    let started = Instant::now();
+    let response = introspect(access_token.clone(), access_token.clone()).await;
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert!(response.active);
+    assert_eq!(response.username.unwrap(), "jdoe@example.com");
+    assert_eq!(response.sub.unwrap(), john_id.to_string());
+    assert_eq!(response.client_id.unwrap(), client_id);
+    assert_eq!(response.tenant, None);
+    assert!(!response.impersonated);
+    assert!(
+        response
+            .permissions
+            .iter()
+            .any(|permission| permission == Permission::Authenticate.name())
+    );
+    assert!(
+        introspect(access_token.clone(), refresh_token.clone())
+            .await
+            .active
+    );
+
+    // Tokens of other accounts require the introspection permission
+    assert_eq!(
+        introspect(jane_token.clone(), access_token.clone()).await,
+        OAuthIntrospect::default()
+    );
+    let response = introspect(admin_token.clone(), access_token.clone()).await;
+    assert!(response.active);
+    assert_eq!(response.username.unwrap(), "jdoe@example.com");
+
+    // Malformed and expired tokens are inactive and take as long to check
+    for token in [
+        "invalid_token".to_string(),
+        access_token[..access_token.len() - 4].to_string(),
+        issue(GrantType::AccessToken, john_id, 0).await,
+        issue(GrantType::LiveTracing, john_id, 3600).await,
+    ] {
+        let started = Instant::now();
+        assert_eq!(
+            introspect(admin_token.clone(), token).await,
+            OAuthIntrospect::default()
+        );
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    // Authenticate with the access token so its session is cached
+    let john_client = Client::new()
+        .credentials(Credentials::bearer(&access_token))
+        .accept_invalid_certs(true)
+        .follow_redirects(["127.0.0.1"])
+        .connect("https://127.0.0.1:8899")
+        .await
+        .unwrap();
+    john_client
+        .mailbox_query(None::<Filter>, None::<Vec<_>>)
+        .await
+        .unwrap();
+
+    // Revoking with another client id or a malformed token does nothing
+    for (token, client_id) in [
+        (access_token.clone(), "other_client".to_string()),
+        ("invalid_token".to_string(), client_id.clone()),
+    ] {
+        assert_eq!(
+            post::<serde_json::Value>(
+                &metadata.revocation_endpoint,
+                &AHashMap::from_iter([
+                    ("token".to_string(), token),
+                    ("client_id".to_string(), client_id),
+                ]),
+            )
+            .await,
+            serde_json::json!({})
+        );
+    }
+    assert!(
+        introspect(admin_token.clone(), access_token.clone())
+            .await
+            .active
+    );
+
+    // Revoked access tokens are rejected immediately
+    for token in [access_token.clone(), refresh_token.clone()] {
+        assert_eq!(
+            post::<serde_json::Value>(
+                &metadata.revocation_endpoint,
+                &AHashMap::from_iter([
+                    ("token".to_string(), token),
+                    ("client_id".to_string(), client_id.to_string()),
+                    ("token_type_hint".to_string(), "access_token".to_string()),
+                ]),
+            )
+            .await,
+            serde_json::json!({})
+        );
+    }
+    assert!(
+        john_client
+            .mailbox_query(None::<Filter>, None::<Vec<_>>)
+            .await
+            .is_err()
+    );
+    assert_unauthorized("https://127.0.0.1:8899", &access_token).await;
+    for token in [access_token.clone(), refresh_token.clone()] {
+        assert_eq!(
+            introspect(admin_token.clone(), token).await,
+            OAuthIntrospect::default()
+        );
+    }
+    assert_eq!(
+        post::<TokenResponse>(
+            &metadata.token_endpoint,
+            &AHashMap::from_iter([
+                ("client_id".to_string(), client_id.to_string()),
+                ("grant_type".to_string(), "refresh_token".to_string()),
+                ("refresh_token".to_string(), refresh_token),
+            ]),
+        )
+        .await,
+        TokenResponse::Error {
+            error: ErrorType::InvalidGrant
+        }
+    );
+
+    // Tokens issued to a master user logged in as the account are impersonated,
+    // and revoking a refresh token revokes the tokens issued under its grant
+    let grant = TokenGrant::new(true);
+    let issue_granted = |grant_type: GrantType| {
+        let server = server.clone();
+        let client_id = client_id.clone();
+        async move {
+            server
+                .encode_granted_token(grant_type, john_id, &client_id, 3600, grant)
+                .await
+                .unwrap()
+        }
+    };
+    let access_token = issue_granted(GrantType::AccessToken).await;
+    let refresh_token = issue_granted(GrantType::RefreshToken).await;
+    let other_token = issue(GrantType::AccessToken, john_id, 3600).await;
+    let response = introspect(admin_token.clone(), access_token.clone()).await;
+    assert!(response.active);
+    assert!(response.impersonated);
+    assert!(
+        introspect(admin_token.clone(), refresh_token.clone())
+            .await
+            .impersonated
+    );
+    assert!(
+        !introspect(admin_token.clone(), other_token.clone())
+            .await
+            .impersonated
+    );
+    assert_eq!(
+        post::<serde_json::Value>(
+            &metadata.revocation_endpoint,
+            &AHashMap::from_iter([
+                ("token".to_string(), refresh_token),
+                ("client_id".to_string(), client_id.to_string()),
+            ]),
+        )
+        .await,
+        serde_json::json!({})
+    );
+    assert_eq!(
+        introspect(admin_token.clone(), access_token.clone()).await,
+        OAuthIntrospect::default()
+    );
+    assert_unauthorized("https://127.0.0.1:8899", &access_token).await;
+    assert!(introspect(admin_token.clone(), other_token).await.active);
+
     // ------------------------
     // Device code flow
     // ------------------------
@@ -448,7 +657,7 @@ async fn post_json<D: DeserializeOwned>(
 async fn post<T: DeserializeOwned>(url: &str, params: &AHashMap<String, String>) -> T {
     post_with_auth(url, None, params).await
 }
-async fn post_with_auth<T: DeserializeOwned>(
+pub(crate) async fn post_with_auth<T: DeserializeOwned>(
     url: &str,
     auth_token: Option<&str>,
     params: &AHashMap<String, String>,
@@ -6,10 +6,13 @@
 
 use crate::{
     directory::internal::TestInternalDirectory,
-    jmap::{JMAPTest, ManagementApi, server::List},
+    jmap::{JMAPTest, ManagementApi, auth::oauth::post_with_auth, server::List},
+};
+use ahash::{AHashMap, AHashSet};
+use common::auth::{
+    AccessToken, TenantInfo,
+    oauth::{GrantType, introspect::OAuthIntrospect},
 };
-use ahash::AHashSet;
-use common::auth::{AccessToken, TenantInfo};
 use directory::{
     Permission, Type,
     backend::internal::{PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue},
@@ -462,6 +465,38 @@ pub async fn test(params: &JMAPTest) {
         )
         .validate_tenant(tenant_id, TENANT_QUOTA);
 
+    // Tenant admins can only introspect tokens issued within their tenant
+    let tenant_admin_token = server
+        .encode_access_token(GrantType::AccessToken, tenant_admin_id, "test", 3600)
+        .await
+        .unwrap();
+    let tenant_user_token = server
+        .encode_access_token(GrantType::AccessToken, tenant_user_id, "test", 3600)
+        .await
+        .unwrap();
+    let outside_token = server
+        .encode_access_token(GrantType::AccessToken, account_id, "test", 3600)
+        .await
+        .unwrap();
+    let response = post_with_auth::<OAuthIntrospect>(
+        "https://127.0.0.1:8899/auth/introspect",
+        tenant_admin_token.as_str().into(),
+        &AHashMap::from_iter([("token".to_string(), tenant_user_token)]),
+    )
+    .await;
+    assert!(response.active);
+    assert_eq!(response.username.unwrap(), "john@foobar.org");
+    assert_eq!(response.tenant.unwrap(), "foobar");
+    assert_eq!(
+        post_with_auth::<OAuthIntrospect>(
+            "https://127.0.0.1:8899/auth/introspect",
+            tenant_admin_token.as_str().into(),
+            &AHashMap::from_iter([("token".to_string(), outside_token)]),
+        )
+        .await,
+        OAuthIntrospect::default()
+    );
+
     // Create a second account should be limited by quota
     tenant_api
         .post::<u32>(
@@ -10,7 +10,7 @@ use crate::{
     smtp::queue::QueuedEvents,
     store::cleanup::store_blob_expire_all,
 };
-use common::config::smtp::queue::QueueName;
+use common::{config::smtp::queue::QueueName, ipc::PushNotification};
 use email::{cache::MessageCacheFetch, mailbox::INBOX_ID};
 use http::management::stores::recalculate_quota;
 use jmap::blob::upload::DISABLE_UPLOAD_QUOTA;
@@ -20,7 +20,9 @@ use jmap_client::{
 };
 use serde_json::json;
 use smtp::queue::spool::SmtpSpool;
-use types::id::Id;
+use std::time::Duration;
+use types::{id::Id, type_state::DataType};
+use utils::map::bitmap::Bitmap;
 
 pub async fn test(params: &mut JMAPTest) {
     println!("Running quota tests...");
@@ -111,6 +113,16 @@ pub async fn test(params: &mut JMAPTest) {
 
     // Test Email/import quota
     let inbox_id = Id::new(INBOX_ID as u64).to_string();
+    let mut push_rx = server
+        .subscribe_push_manager(
+            &server
+                .get_access_token(account.id().document_id())
+                .await
+                .unwrap(),
+            Bitmap::from_iter([DataType::Quota]),
+        )
+        .await
+        .unwrap();
     let mut message_ids = Vec::new();
     for i in 0..2 {
         message_ids.push(
@@ -132,6 +144,15 @@ pub async fn test(params: &mut JMAPTest) {
         );
     }
 
+    // A Quota state change is pushed once usage crosses a step
+    match tokio::time::timeout(Duration::from_secs(1), push_rx.recv()).await {
+        Ok(Some(PushNotification::StateChange(state_change))) => {
+            assert_eq!(state_change.account_id, account.id().document_id());
+            assert!(state_change.types.contains(DataType::Quota));
+        }
+        result => panic!("Expected a Quota state change, got {result:?}"),
+    }
+
     assert_over_quota(
         client
             .email_import(
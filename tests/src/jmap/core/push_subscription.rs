@@ -75,11 +75,10 @@ pub async fn test(params: &mut JMAPTest) {
     // Start mock push server
     let mut settings = Config::new(add_test_certs(SERVER)).unwrap();
     settings.resolve_all_macros().await;
+    let core = Core::parse(&mut settings, Default::default(), Default::default()).await;
     let mock_inner = Arc::new(Inner {
-        shared_core: Core::parse(&mut settings, Default::default(), Default::default())
-            .await
-            .into_shared(),
-        data: Data::parse(&mut settings),
+        data: Data::parse(&mut settings, &core),
+        shared_core: core.into_shared(),
         cache: Caches::parse(&mut settings),
         ..Default::default()
     });
@@ -127,7 +127,10 @@ pub async fn test(params: &mut JMAPTest) {
     params
         .server
         .store()
-        .purge_blobs(params.server.blob_store().clone())
+        .purge_blobs(
+            params.server.blob_store().clone(),
+            &params.server.core.storage.blobs,
+        )
         .await
         .unwrap();
     let samples = spam_training_samples(&params.server).await;
@@ -162,7 +165,10 @@ pub async fn test(params: &mut JMAPTest) {
     params
         .server
         .store()
-        .purge_blobs(params.server.blob_store().clone())
+        .purge_blobs(
+            params.server.blob_store().clone(),
+            &params.server.core.storage.blobs,
+        )
         .await
         .unwrap();
     let samples = spam_training_samples(&params.server).await;
@@ -5,7 +5,10 @@
  */
 
 pub mod enterprise;
+pub mod organization;
 pub mod purge;
+pub mod scim;
+pub mod upsert;
 pub mod webhooks;
 
 #[derive(serde::Deserialize, Debug)]
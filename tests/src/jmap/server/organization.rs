@@ -0,0 +1,8764 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    imap::{ImapConnection, Type, pop::Pop3Connection},
+    jmap::{
+        JMAPTest, ManagementApi, Response,
+        mail::{
+            delivery::{AssertResult, SmtpConnection},
+            submission::{MockMessage, expect_message_delivery, spawn_mock_smtp_server},
+        },
+    },
+    smtp::DnsCache,
+    webdav::DummyWebDavClient,
+};
+use ::email::{
+    cache::{MessageCacheFetch, mailbox::MailboxCacheAccess},
+    mailbox::Mailbox,
+};
+use ahash::AHashMap;
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use calcard::icalendar::{ICalendar, ICalendarParticipationStatus};
+use chrono::{SecondsFormat, TimeDelta, Utc};
+use common::{
+    KV_LOCK_PROVISION, Server,
+    auth::{AccessToken, invitation::InvitationStatus},
+    config::{activation::TenantActivation, scripts::ForwardingRule},
+    storage::{blob::BlobMove, erasure::ErasureReport},
+    telemetry::{
+        metrics::metering::{MeteringSample, MeteringStore, SharedMeteringHistory},
+        tracers::audit::{AuditQuery, AuditRecord, AuditStore},
+    },
+};
+use directory::backend::internal::{lookup::DirectoryStore, manage::ManageDirectory};
+use groupware::{
+    calendar::itip::{ItipIngest, ItipIngestError},
+    scheduling::{ItipMessage, ItipSummary},
+};
+use http::management::{
+    provision_lock::ProvisionLock,
+    spam::{ManageSpamHandler, SpamClassifyRequest, SpamFilterDisposition},
+};
+use hyper::{Method, StatusCode};
+use jmap_client::{
+    client::{Client, Credentials},
+    email,
+    mailbox::{self, Role},
+};
+use mail_auth::{
+    AuthenticatedMessage, DkimResult, MX,
+    common::{parse::TxtRecordParser, verify::DomainKey},
+    dmarc::Dmarc,
+    mta_sts::TlsRpt,
+    spf::Spf,
+};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde_json::json;
+use services::housekeeper::{
+    activation::OrganizationActivationPurge, metering::TenantMetering, trial::TenantTrials,
+};
+use smtp::queue::delivery_log::{
+    DeliveryLog, DeliveryLogAttempt, DeliveryLogRecipient, DeliveryOutcome,
+};
+use store::{
+    Serialize,
+    write::{Archiver, BatchBuilder, QueueClass, ValueClass, blob::BlobLocation, now},
+};
+use tokio::sync::mpsc;
+use trc::{
+    EventType, Key, ProvisionEvent, StoreEvent, Value,
+    ipc::subscriber::{EventBatch, SubscriberBuilder},
+};
+use types::{
+    blob::BlobId, blob_hash::BlobHash, collection::Collection, id::Id, special_use::SpecialUse,
+};
+use utils::snowflake::SnowflakeIdGenerator;
+
+#[derive(Debug, serde::Deserialize)]
+struct AuditEvents {
+    items: Vec<AuditEvent>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AuditEvent {
+    actor: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    details: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct OrganizationList {
+    items: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProvisionResponse {
+    tenant_id: u32,
+    domain_id: u32,
+    admin_id: u32,
+    dns_records: Vec<serde_json::Value>,
+}
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running organization provisioning tests...");
+
+    let (_tx, mut rx) = SubscriberBuilder::new("provision-test".to_string())
+        .set_interests(
+            ProvisionEvent::variants()
+                .iter()
+                .map(|event| EventType::Provision(*event)),
+        )
+        .with_lossy(false)
+        .register();
+    let api = ManagementApi::new(8899, "admin", "secret");
+
+    provisioning(params, &api, &mut rx).await;
+    tenant_spam_settings(params).await;
+    domain_dns_records(params, &api).await;
+    organization_listings(&api).await;
+    outbound_delivery(params).await;
+    tenant_mail_processing(params, &api).await;
+    tenant_branding(&api).await;
+    tenant_policies(params, &api).await;
+    multi_factor_authentication(params).await;
+    account_forwarding(params, &api).await;
+    shared_mailboxes(&api).await;
+    send_only_credentials().await;
+    resources_and_collections(params).await;
+    message_retention().await;
+    blob_storage(params, &api).await;
+    organization_backup(params, &api).await;
+    audit_log(params, &api).await;
+    usage_metering(params, &api).await;
+    account_data(params, &api).await;
+    deletion_reports(params, &api).await;
+    invitations(params, &api).await;
+    public_signup(params, &api).await;
+    abuse_checks(params, &api).await;
+    trials(params, &api).await;
+    plans(&api).await;
+    attribution(&api).await;
+    message_import(params, &api).await;
+    imap_import(params, &api).await;
+
+    trc::Collector::remove_subscriber("provision-test".to_string());
+}
+
+async fn provisioning(params: &JMAPTest, api: &ManagementApi, rx: &mut mpsc::Receiver<EventBatch>) {
+    // Successful provisioning
+    let response = api
+        .post::<ProvisionResponse>(
+            "/api/organization/provision",
+            &json!({
+                "tenantName": "acme",
+                "domain": "acme.org",
+                "adminName": "acme-admin",
+                "adminPassword": "acme-secret",
+                "adminEmail": "admin@acme.org",
+                "brandName": "Acme & Sons",
+                "collections": {
+                    "calendars": [{
+                        "name": "holidays",
+                        "displayName": "Company holidays",
+                        "shared": true,
+                    }],
+                },
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let events = collect_events(rx, 5).await;
+    assert_eq!(
+        events.iter().map(|(event, _)| *event).collect::<Vec<_>>(),
+        vec![
+            ProvisionEvent::Started,
+            ProvisionEvent::TenantCreated,
+            ProvisionEvent::DomainCreated,
+            ProvisionEvent::AdminCreated,
+            ProvisionEvent::Completed,
+        ]
+    );
+    assert_eq!(events[1].1.id, Some(response.tenant_id as u64));
+    assert_eq!(events[2].1.id, Some(response.domain_id as u64));
+    assert_eq!(events[3].1.id, Some(response.admin_id as u64));
+    assert_eq!(events[4].1.id, Some(response.tenant_id as u64));
+    assert!(events[4].1.elapsed);
+
+    // The admin's folders are created during provisioning
+    assert_eq!(
+        mailbox_roles(&params.server, response.admin_id).await,
+        vec![
+            ("Inbox".to_string(), SpecialUse::Inbox),
+            ("Deleted Items".to_string(), SpecialUse::Trash),
+            ("Junk Mail".to_string(), SpecialUse::Junk),
+            ("Drafts".to_string(), SpecialUse::Drafts),
+            ("Sent Items".to_string(), SpecialUse::Sent),
+            ("Archive".to_string(), SpecialUse::Archive),
+        ]
+    );
+
+    // Shared calendars of the tenant are created during provisioning
+    let hrefs = DummyWebDavClient::new(u32::MAX, "acme-admin", "acme-secret", "admin@acme.org")
+        .request_with_headers("PROPFIND", "/dav/cal/acme/", [("depth", "1")], "")
+        .await
+        .with_status(StatusCode::MULTI_STATUS)
+        .hrefs()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    assert!(
+        hrefs.contains(&"/dav/cal/acme/holidays/".to_string()),
+        "{hrefs:?}"
+    );
+    for (typ, name) in [
+        ("MX", "acme.org."),
+        ("CNAME", "autoconfig.acme.org."),
+        ("CNAME", "autodiscover.acme.org."),
+        ("SRV", "_autodiscover._tcp.acme.org."),
+    ] {
+        assert!(
+            response
+                .dns_records
+                .iter()
+                .any(|record| record["type"] == typ && record["name"] == name),
+            "{typ} {name}: {:?}",
+            response.dns_records
+        );
+    }
+
+    // Client auto-setup is served for provisioned domains only
+    let config = api
+        .get_raw("/.well-known/autoconfig/mail/config-v1.1.xml?emailaddress=admin@acme.org")
+        .await
+        .unwrap();
+    assert!(
+        config.contains("<displayName>Acme &amp; Sons</displayName>"),
+        "{config}"
+    );
+    assert!(config.contains("<domain>acme.org</domain>"), "{config}");
+    let config = api
+        .get_raw("/.well-known/autoconfig/mail/config-v1.1.xml?emailaddress=jdoe@unknown.org")
+        .await
+        .unwrap();
+    assert!(!config.contains("<clientConfig"), "{config}");
+
+    // Provisioning a tenant that already exists fails at the first step
+    api.post::<ProvisionResponse>(
+        "/api/organization/provision",
+        &json!({
+            "tenantName": "acme",
+            "domain": "acme-corp.org",
+            "adminName": "acme-admin2",
+            "adminPassword": "acme-secret",
+            "adminEmail": "admin@acme-corp.org",
+        }),
+    )
+    .await
+    .unwrap()
+    .expect_error("fieldAlreadyExists");
+    let events = collect_events(rx, 2).await;
+    assert_eq!(
+        events.iter().map(|(event, _)| *event).collect::<Vec<_>>(),
+        vec![ProvisionEvent::Started, ProvisionEvent::Failed]
+    );
+    assert_eq!(events[1].1.details.as_deref(), Some("tenant"));
+    assert!(events[1].1.caused_by);
+
+    // Reusing a domain fails without creating the tenant
+    api.post::<ProvisionResponse>(
+        "/api/organization/provision",
+        &json!({
+            "tenantName": "acme-corp",
+            "domain": "acme.org",
+            "adminName": "acme-corp-admin",
+            "adminPassword": "acme-secret",
+            "adminEmail": "admin@acme.org",
+        }),
+    )
+    .await
+    .unwrap()
+    .expect_error("fieldAlreadyExists");
+    let events = collect_events(rx, 2).await;
+    assert_eq!(
+        events.iter().map(|(event, _)| *event).collect::<Vec<_>>(),
+        vec![ProvisionEvent::Started, ProvisionEvent::Failed]
+    );
+    assert_eq!(events[1].1.details.as_deref(), Some("domain"));
+    api.get::<serde_json::Value>("/api/principal/acme-corp")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Concurrent provisioning of the same organization creates a single tenant,
+    // the other requests fail with the ID of the tenant that was created
+    let responses = futures::future::join_all((0..4).map(|_| {
+        api.post::<ProvisionResponse>(
+            "/api/organization/provision",
+            &json!({
+                "tenantName": "vandelay",
+                "domain": "vandelay.org",
+                "adminName": "vandelay-admin",
+                "adminPassword": "vandelay-secret",
+                "adminEmail": "admin@vandelay.org",
+            }),
+        )
+    }))
+    .await;
+    let mut tenant_ids = vec![];
+    let mut conflicts = vec![];
+    for response in responses {
+        match response.unwrap() {
+            Response::Data { data } => tenant_ids.push(data.tenant_id as u64),
+            Response::Error { error, id, .. } => conflicts.push((error, id)),
+            Response::RequestError(err) => panic!("Unexpected error {err:?}"),
+        }
+    }
+    assert_eq!(tenant_ids.len(), 1, "{conflicts:?}");
+    assert_eq!(
+        conflicts,
+        vec![("fieldAlreadyExists".to_string(), Some(tenant_ids[0])); 3]
+    );
+    let tenants = api
+        .get::<serde_json::Value>("/api/principal?types=tenant&filter=vandelay")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(tenants["total"], 1, "{tenants}");
+
+    // Standalone domain creation waits for the provisioning lock as well
+    api.post::<u32>(
+        "/api/principal",
+        &json!({"type": "domain", "name": "Vandelay.org"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("fieldAlreadyExists");
+    api.post::<u32>(
+        "/api/principal",
+        &json!({"type": "tenant", "name": "acme-corp"}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+
+    // Provisioning locks are refreshed while held, released when the request
+    // is cancelled and expire when the node holding them stops
+    let server = params.server.clone();
+    let lock = ProvisionLock::acquire_with_expiry(&server, ["Initech.example"], 2)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    assert!(
+        ProvisionLock::acquire(&server, ["initech.example"])
+            .await
+            .is_err()
+    );
+    lock.release().await;
+    ProvisionLock::acquire(&server, ["initech.example"])
+        .await
+        .unwrap()
+        .release()
+        .await;
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), async {
+            let _lock = ProvisionLock::acquire(&server, ["initech.example"])
+                .await
+                .unwrap();
+            std::future::pending::<()>().await;
+        })
+        .await
+        .is_err()
+    );
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let started = Instant::now();
+    ProvisionLock::acquire(&server, ["initech.example"])
+        .await
+        .unwrap()
+        .release()
+        .await;
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(
+        server
+            .in_memory_store()
+            .try_lock(KV_LOCK_PROVISION, b"initech.example", 1)
+            .await
+            .unwrap()
+    );
+    ProvisionLock::acquire(&server, ["initech.example"])
+        .await
+        .unwrap()
+        .release()
+        .await;
+}
+
+async fn tenant_spam_settings(params: &JMAPTest) {
+    // Tenant admins can view their own tenant's metrics
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let metrics = tenant_api
+        .get::<AHashMap<String, u64>>("/api/organization/acme/metrics")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(metrics["api-requests"] >= 1, "{metrics:?}");
+    assert!(metrics["auth-success"] >= 1, "{metrics:?}");
+    assert_eq!(metrics["messages-received"], 0);
+    assert_eq!(metrics["bytes-stored"], 0);
+    tenant_api
+        .get::<AHashMap<String, u64>>("/api/organization/acme-corp/metrics")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Tenants override the global spam filter settings
+    let settings = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/spam-settings")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(settings["spamThreshold"], serde_json::Value::Null);
+    assert_eq!(settings["trustedDomains"], json!([]));
+    let settings = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({
+                "spamThreshold": 8.0,
+                "addHeaders": false,
+                "trustedDomains": ["News.Example.org", "news.example.org"],
+                "languages": ["en"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(settings["spamThreshold"], json!(8.0));
+    assert_eq!(settings["addHeaders"], json!(false));
+    assert_eq!(settings["trustedDomains"], json!(["news.example.org"]));
+    let settings = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({"addHeaders": null, "rejectThreshold": 15.0}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(settings["spamThreshold"], json!(8.0));
+    assert_eq!(settings["rejectThreshold"], json!(15.0));
+    assert_eq!(settings["addHeaders"], serde_json::Value::Null);
+    assert_eq!(settings["languages"], json!(["en"]));
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({"languages": ["klingon"]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid language code");
+    let settings = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({"greylist": false, "greylistDelay": 300, "greylistWhitelistAfter": 3}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(settings["greylist"], json!(false));
+    assert_eq!(settings["greylistDelay"], json!(300));
+    assert_eq!(settings["greylistWhitelistAfter"], json!(3));
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({"greylistDelay": 172800}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Greylist delay cannot exceed");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/spam-settings")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Tenants maintain sender allow and block lists
+    let allowed = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders/allow",
+            &json!({"value": "Partner.NET", "comment": "Supplier"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(allowed["value"], json!("partner.net"));
+    assert_eq!(allowed["comment"], json!("Supplier"));
+    let blocked = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders/block",
+            &json!({"value": "spammer@partner.net", "expires": store::write::now() + 3600}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders/block",
+            &json!({"value": "10.0.0.0/8"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let senders = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/senders/block")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(senders["total"], json!(2));
+    assert_eq!(senders["items"][0], blocked);
+    for (value, error) in [
+        (json!({"value": "not a sender"}), "Invalid sender"),
+        (
+            json!({"value": "user@acme.org", "expires": 1}),
+            "Invalid expiration",
+        ),
+    ] {
+        tenant_api
+            .post::<serde_json::Value>("/api/organization/acme/senders/block", &value)
+            .await
+            .unwrap()
+            .expect_error(error);
+    }
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/senders/other")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/senders/allow")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Blocked senders take precedence over allowed domains
+    let acme_id = params
+        .server
+        .store()
+        .get_principal_id("acme")
+        .await
+        .unwrap()
+        .unwrap();
+    let classify = |env_from: &str, remote_ip: &str| SpamClassifyRequest {
+        message: concat!(
+            "From: sender@partner.net\r\n",
+            "To: jane@acme.org\r\n",
+            "Subject: Order\r\n\r\n",
+            "Please find the order attached.\r\n"
+        )
+        .to_string(),
+        remote_ip: remote_ip.parse().unwrap(),
+        ehlo_domain: "mx.partner.net".to_string(),
+        authenticated_as: None,
+        is_tls: true,
+        env_from: env_from.to_string(),
+        env_from_flags: 0,
+        env_rcpt_to: vec!["jane@acme.org".to_string()],
+    };
+    let result = params
+        .server
+        .spam_classify_request(
+            classify("sender@partner.net", "192.0.2.1"),
+            Some(acme_id),
+            0,
+        )
+        .await
+        .unwrap();
+    assert!(result.tags.contains_key("TENANT_ALLOWED_SENDER"));
+    assert!(!result.spam);
+    for (env_from, remote_ip) in [
+        ("spammer@partner.net", "192.0.2.1"),
+        ("sender@partner.net", "10.1.2.3"),
+    ] {
+        let result = params
+            .server
+            .spam_classify_request(classify(env_from, remote_ip), Some(acme_id), 0)
+            .await
+            .unwrap();
+        assert!(result.tags.contains_key("TENANT_BLOCKED_SENDER"));
+        assert!(matches!(result.disposition, SpamFilterDisposition::Reject));
+    }
+
+    // Blocked senders can be quarantined instead of rejected
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({"blockedSenderAction": "quarantine"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let result = params
+        .server
+        .spam_classify_request(
+            classify("spammer@partner.net", "192.0.2.1"),
+            Some(acme_id),
+            0,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        result.disposition,
+        SpamFilterDisposition::Allow { .. }
+    ));
+    assert!(result.spam);
+
+    // Removing entries invalidates the matcher
+    tenant_api
+        .delete::<()>(&format!(
+            "/api/organization/acme/senders/block/{}",
+            blocked["id"].as_str().unwrap()
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/organization/acme/senders/block/12345")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    let result = params
+        .server
+        .spam_classify_request(
+            classify("spammer@partner.net", "192.0.2.1"),
+            Some(acme_id),
+            0,
+        )
+        .await
+        .unwrap();
+    assert!(!result.tags.contains_key("TENANT_BLOCKED_SENDER"));
+    assert!(!result.spam);
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/spam-settings",
+            &json!({"blockedSenderAction": null}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+}
+
+async fn domain_dns_records(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Without domain certificates configured, organizations with the expected
+    // DNS records are healthy
+    params.server.mx_add(
+        "acme.org",
+        vec![MX {
+            exchanges: vec![params.server.core.network.server_name.clone()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    params.server.txt_add(
+        "acme.org",
+        Spf::parse(b"v=spf1 mx -all").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    params.server.txt_add(
+        "_dmarc.acme.org",
+        Dmarc::parse(b"v=DMARC1; p=reject").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    params.server.txt_add(
+        "_smtp._tls.acme.org",
+        TlsRpt::parse(b"v=TLSRPTv1; rua=mailto:tls@acme.org").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    let mut health = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/health?fresh=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let dns = health["domains"][0]
+        .as_object_mut()
+        .unwrap()
+        .remove("dns")
+        .unwrap();
+    assert_eq!(
+        dns.as_array()
+            .unwrap()
+            .iter()
+            .map(|check| (
+                check["type"].as_str().unwrap(),
+                check["status"].as_str().unwrap()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            ("mx", "ok"),
+            ("spf", "ok"),
+            ("dmarc", "ok"),
+            ("tlsRpt", "ok")
+        ],
+        "{dns}"
+    );
+    assert!(dns[0]["age"].as_u64().is_some_and(|age| age < 5), "{dns}");
+    assert_eq!(
+        health,
+        json!({
+            "healthy": true,
+            "domains": [{"name": "acme.org", "certificates": []}],
+            "encryption": {
+                "enabled": false,
+                "keyReference": null,
+                "publicKey": null,
+                "keyAvailable": true,
+                "error": null,
+            },
+        })
+    );
+    tenant_api
+        .get::<serde_json::Value>("/api/domain/acme-corp.org/certificates")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // DNS checks are cached until refreshed on demand, a record that no
+    // longer points to this server makes the organization unhealthy
+    params.server.mx_add(
+        "acme.org",
+        vec![MX {
+            exchanges: vec!["mx.elsewhere.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    let checks = tenant_api
+        .get::<serde_json::Value>("/api/domain/acme.org/dns-check")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(checks["items"][0]["status"], "ok", "{checks}");
+    let checks = tenant_api
+        .get::<serde_json::Value>("/api/domain/acme.org/dns-check?fresh=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(checks["items"][0]["status"], "invalid", "{checks}");
+    let health = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/health")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(health["healthy"], false, "{health}");
+    tenant_api
+        .get::<serde_json::Value>("/api/domain/acme-corp.org/dns-check")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    api.post::<serde_json::Value>("/api/domain/acme.org/certificates/renew", &json!({}))
+        .await
+        .unwrap()
+        .expect_error("No ACME provider");
+
+    // Domains route local parts before resolving recipients
+    let route = api
+        .post::<serde_json::Value>(
+            "/api/domain/acme.org/routes",
+            &json!({
+                "priority": 1,
+                "match": {"type": "exact", "value": "invoices"},
+                "action": {"type": "forward", "address": "Tickets@Helpdesk.example.net"},
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        route["action"]["address"],
+        json!("tickets@helpdesk.example.net")
+    );
+    for (rule, error) in [
+        (
+            json!({"match": {"type": "regex", "value": "inv("}, "action": {"type": "local"}}),
+            "Invalid regular expression",
+        ),
+        (
+            json!({"match": {"type": "exact", "value": "billing"},
+                   "action": {"type": "forward", "address": "billing"}}),
+            "Invalid forward address",
+        ),
+        (
+            json!({"match": {"type": "prefix", "value": "crm"},
+                   "action": {"type": "relay", "host": "10.0.0.1"}}),
+            "is not a public address",
+        ),
+    ] {
+        api.post::<serde_json::Value>("/api/domain/acme.org/routes", &rule)
+            .await
+            .unwrap()
+            .expect_error(error);
+    }
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/domain/acme.org/routes",
+            &json!({"match": {"type": "prefix", "value": "crm"},
+                    "action": {"type": "relay", "host": "crm.acme.invalid"}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Failed to resolve");
+    let routes = api
+        .get::<serde_json::Value>("/api/domain/acme.org/routes")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(routes["items"], json!([route.clone()]));
+    let trace = api
+        .get::<serde_json::Value>("/api/domain/acme.org/routes/trace?address=invoices@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(trace["route"]["id"], route["id"]);
+    assert_eq!(trace["action"]["type"], json!("forward"));
+    let trace = api
+        .get::<serde_json::Value>("/api/domain/acme.org/routes/trace?address=admin@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(trace["route"], serde_json::Value::Null);
+    assert_eq!(trace["action"], json!({"type": "local"}));
+    assert_eq!(trace["recipient"], json!("mailbox"));
+    api.get::<serde_json::Value>("/api/domain/acme.org/routes/trace?address=admin@other.org")
+        .await
+        .unwrap()
+        .expect_error("Invalid address");
+    api.delete::<()>(&format!(
+        "/api/domain/acme.org/routes/{}",
+        route["id"].as_str().unwrap()
+    ))
+    .await
+    .unwrap()
+    .unwrap_data();
+    api.delete::<()>("/api/domain/acme.org/routes/12345")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Domains publish BIMI logos, which must follow the SVG Tiny PS profile
+    let logo = concat!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.2\" ",
+        "baseProfile=\"tiny-ps\" viewBox=\"0 0 64 64\">",
+        "<title>Acme</title><circle cx=\"32\" cy=\"32\" r=\"30\" fill=\"#c00\"/></svg>"
+    );
+    api.put::<serde_json::Value>(
+        "/api/domain/acme.org/bimi",
+        &json!({"logo": logo.replace(" baseProfile=\"tiny-ps\"", "")}),
+    )
+    .await
+    .unwrap()
+    .expect_error("baseProfile");
+    api.put::<serde_json::Value>(
+        "/api/domain/acme.org/bimi",
+        &json!({"logo": logo.replace("</svg>", "<script>alert(1)</script></svg>")}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Element <script> is not allowed");
+    api.put::<serde_json::Value>(
+        "/api/domain/acme.org/bimi",
+        &json!({"logo": logo, "vmc": "not a certificate"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid VMC certificate");
+    let server_name = params.server.core.network.server_name.clone();
+    let bimi = api
+        .put::<serde_json::Value>("/api/domain/acme.org/bimi", &json!({"logo": logo}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let record = format!("v=BIMI1; l=https://{server_name}/bimi/acme.org/logo.svg; a=");
+    assert_eq!(
+        bimi["logoUrl"],
+        format!("https://{server_name}/bimi/acme.org/logo.svg"),
+        "{bimi}"
+    );
+    assert_eq!(bimi["record"]["name"], "default._bimi.acme.org.", "{bimi}");
+    assert_eq!(bimi["record"]["content"], record, "{bimi}");
+    assert_eq!(
+        tenant_api
+            .get::<serde_json::Value>("/api/domain/acme.org/bimi")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        bimi
+    );
+    let records = api
+        .get::<serde_json::Value>("/api/dns/records/acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        records
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|r| r["name"] == "default._bimi.acme.org." && r["content"] == record),
+        "{records}"
+    );
+
+    // Logos are served anonymously at the published URL
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let response = http_client
+        .get("https://127.0.0.1:8899/bimi/acme.org/logo.svg")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "image/svg+xml"
+    );
+    assert_eq!(response.text().await.unwrap(), logo);
+    assert_eq!(
+        http_client
+            .get("https://127.0.0.1:8899/bimi/acme.org/vmc.pem")
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        404
+    );
+
+    // Logos are only displayed for domains with a DMARC policy at enforcement
+    params.server.txt_add(
+        "_dmarc.acme.org",
+        Dmarc::parse(b"v=DMARC1; p=none").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    let checks = tenant_api
+        .get::<serde_json::Value>("/api/domain/acme.org/dns-check?fresh=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let check = checks["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|check| check["type"] == "bimi")
+        .unwrap();
+    assert_eq!(check["status"], "warning", "{checks}");
+    assert_eq!(check["name"], "default._bimi.acme.org.", "{checks}");
+    assert!(
+        check["reason"].as_str().unwrap().contains("DMARC"),
+        "{checks}"
+    );
+
+    api.delete::<()>("/api/domain/acme.org/bimi")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        api.get::<serde_json::Value>("/api/domain/acme.org/bimi")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        serde_json::Value::Null
+    );
+    assert_eq!(
+        http_client
+            .get("https://127.0.0.1:8899/bimi/acme.org/logo.svg")
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        404
+    );
+    api.delete::<()>("/api/domain/acme.org/bimi")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // SPF records needing more than 10 lookups are reported with a fix
+    let valid_until = Instant::now() + Duration::from_secs(10);
+    for (name, records) in [
+        (
+            "acme.org".to_string(),
+            vec![
+                "google-site-verification=abc".to_string(),
+                "v=spf1 mx include:_spf.mail.example ptr include:_spf.crm.example -all".to_string(),
+            ],
+        ),
+        (
+            "_spf.mail.example".to_string(),
+            vec![
+                "v=spf1 ip4:192.0.2.0/24 ip6:2001:db8::/48 include:_spf2.mail.example ~all"
+                    .to_string(),
+            ],
+        ),
+        (
+            "_spf2.mail.example".to_string(),
+            vec!["v=spf1 ip4:198.51.100.7 -ip4:203.0.113.99 ~all".to_string()],
+        ),
+        (
+            "_spf.crm.example".to_string(),
+            vec![format!(
+                "v=spf1 {} -all",
+                (1..=8)
+                    .map(|n| format!("include:n{n}.crm.example"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )],
+        ),
+        (
+            "_spf.dyn.example".to_string(),
+            vec!["v=spf1 exists:%{i}._ip.dyn.example -all".to_string()],
+        ),
+    ]
+    .into_iter()
+    .chain((1..=8).map(|n| {
+        (
+            format!("n{n}.crm.example"),
+            vec![format!("v=spf1 ip4:203.0.113.{n} -all")],
+        )
+    })) {
+        params.server.txt_records_add(name, records, valid_until);
+    }
+    params.server.txt_add(
+        "acme.org",
+        Spf::parse(b"v=spf1 mx -all").unwrap(),
+        valid_until,
+    );
+    let analysis = tenant_api
+        .get::<serde_json::Value>("/api/domain/acme.org/spf/analyze")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(analysis["lookups"], 13, "{analysis}");
+    assert_eq!(analysis["lookupLimit"], 10, "{analysis}");
+    assert_eq!(analysis["overLimit"], true, "{analysis}");
+    assert_eq!(
+        analysis["includes"].as_array().unwrap().len(),
+        11,
+        "{analysis}"
+    );
+    assert_eq!(
+        analysis["suggestedRecord"],
+        "v=spf1 mx include:_spf.mail.example include:_spf.crm.example -all",
+        "{analysis}"
+    );
+    assert!(
+        analysis["remediation"]
+            .as_str()
+            .unwrap()
+            .contains("flattening"),
+        "{analysis}"
+    );
+    let checks = tenant_api
+        .get::<serde_json::Value>("/api/domain/acme.org/dns-check?fresh=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let check = checks["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|check| check["type"] == "spf")
+        .unwrap();
+    assert_eq!(check["status"], "invalid", "{checks}");
+    assert!(
+        check["reason"].as_str().unwrap().contains("13 DNS lookups"),
+        "{checks}"
+    );
+    assert_eq!(check["remediation"], analysis["remediation"], "{checks}");
+
+    // Flattening replaces includes with the networks they authorize
+    let flattened = api
+        .post::<serde_json::Value>(
+            "/api/domain/acme.org/spf/flatten",
+            &json!({"monitor": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let record = flattened["record"].as_str().unwrap();
+    assert!(
+        record.starts_with(concat!(
+            "v=spf1 mx ip4:192.0.2.0/24 ip6:2001:db8::/48 ",
+            "ip4:198.51.100.7 ptr ip4:203.0.113."
+        )),
+        "{flattened}"
+    );
+    assert!(
+        record.ends_with(" -all") && !record.contains("include:"),
+        "{flattened}"
+    );
+    assert_eq!(flattened["lookups"], 2, "{flattened}");
+    assert_eq!(
+        flattened["networks"].as_array().unwrap().len(),
+        11,
+        "{flattened}"
+    );
+    assert_eq!(
+        flattened["strings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|string| string.as_str().unwrap())
+            .collect::<String>(),
+        record
+    );
+    assert!(
+        flattened["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|warning| warning.as_str().unwrap().contains("refreshed periodically")),
+        "{flattened}"
+    );
+    let flattened = api
+        .post::<serde_json::Value>(
+            "/api/domain/acme.org/spf/flatten",
+            &json!({"record": "v=spf1 include:_spf.dyn.example include:_spf.mail.example -all"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        flattened["record"]
+            .as_str()
+            .unwrap()
+            .starts_with("v=spf1 include:_spf.dyn.example ip4:192.0.2.0/24 "),
+        "{flattened}"
+    );
+    assert!(
+        flattened["warnings"][0]
+            .as_str()
+            .unwrap()
+            .contains("exists mechanism"),
+        "{flattened}"
+    );
+    api.post::<serde_json::Value>(
+        "/api/domain/acme.org/spf/flatten",
+        &json!({"record": "v=spf1 include: -all"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid SPF record");
+
+    // Monitored records raise an event when the upstream networks change
+    assert!(!params.server.refresh_spf_monitor("acme.org").await.unwrap());
+    params.server.txt_records_add(
+        "_spf2.mail.example",
+        vec!["v=spf1 ip4:198.51.100.8 ~all".to_string()],
+        valid_until,
+    );
+    assert!(params.server.refresh_spf_monitor("acme.org").await.unwrap());
+    let monitor = api
+        .get::<serde_json::Value>("/api/domain/acme.org/spf/monitor")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(monitor["changedAt"].as_u64().is_some(), "{monitor}");
+    assert!(
+        monitor["networks"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("ip4:198.51.100.8")),
+        "{monitor}"
+    );
+    let checks = tenant_api
+        .get::<serde_json::Value>("/api/domain/acme.org/dns-check?fresh=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let check = checks["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|check| check["type"] == "spf")
+        .unwrap();
+    assert_eq!(check["status"], "invalid", "{checks}");
+    assert!(
+        check["remediation"]
+            .as_str()
+            .unwrap()
+            .contains("Flatten the SPF record again"),
+        "{checks}"
+    );
+    api.delete::<()>("/api/domain/acme.org/spf/monitor")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        api.get::<serde_json::Value>("/api/domain/acme.org/spf/monitor")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        serde_json::Value::Null
+    );
+
+    // Zone files list the same records as the JSON endpoint
+    let records = api
+        .get::<serde_json::Value>("/api/dns/records/acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let zone = tenant_api
+        .get_raw("/api/domain/acme.org/dns/zonefile?ttl=300")
+        .await
+        .unwrap();
+    let mut lines = zone.lines();
+    assert!(
+        lines
+            .next()
+            .is_some_and(|line| line == "; DNS records of acme.org"),
+        "{zone}"
+    );
+    assert!(
+        lines
+            .next()
+            .is_some_and(|line| line.starts_with("; Generated by ")),
+        "{zone}"
+    );
+    assert_eq!(lines.next(), Some("$TTL 300"), "{zone}");
+    let entries = lines
+        .map(|line| {
+            let fields = line.splitn(5, '\t').collect::<Vec<_>>();
+            assert_eq!(fields.len(), 5, "{line}");
+            assert_eq!((fields[1], fields[2]), ("300", "IN"), "{line}");
+
+            // TXT records are quoted and split in strings of up to 255 bytes
+            let content = if fields[3] == "TXT" {
+                let mut content = String::new();
+                let mut in_string = false;
+                let mut escaped = false;
+                let mut string_len = 0;
+                for ch in fields[4].chars() {
+                    if !in_string {
+                        assert!(ch == '"' || ch == ' ', "{line}");
+                        in_string = ch == '"';
+                        string_len = 0;
+                    } else if !escaped && ch == '\\' {
+                        escaped = true;
+                    } else if !escaped && ch == '"' {
+                        in_string = false;
+                        assert!(string_len <= 255, "{line}");
+                    } else {
+                        content.push(ch);
+                        escaped = false;
+                        string_len += ch.len_utf8();
+                    }
+                }
+                assert!(!in_string, "{line}");
+                content
+            } else {
+                fields[4].to_string()
+            };
+
+            json!({"type": fields[3], "name": fields[0], "content": content})
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(json!(entries), records, "{zone}");
+    for ttl in ["0", "abc"] {
+        tenant_api
+            .get::<serde_json::Value>(&format!("/api/domain/acme.org/dns/zonefile?ttl={ttl}"))
+            .await
+            .unwrap()
+            .expect_error("Invalid TTL");
+    }
+    tenant_api
+        .get::<serde_json::Value>("/api/domain/acme-corp.org/dns/zonefile")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+}
+
+async fn organization_listings(api: &ManagementApi) {
+    // Organizations can be exported as CSV with usage columns
+    api.patch::<()>(
+        "/api/principal/acme-corp",
+        &json!([{
+            "action": "set",
+            "field": "description",
+            "value": "Acme, \"Corp\""
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let organizations = api
+        .get::<OrganizationList>("/api/organization?filter=acme&fields=name,domains,users")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations
+            .items
+            .iter()
+            .find(|org| org["name"] == "acme")
+            .unwrap(),
+        &json!({"name": "acme", "domains": 1, "users": 1})
+    );
+    let csv = api
+        .get_raw("/api/organization?filter=acme&format=csv")
+        .await
+        .unwrap();
+    let mut lines = csv.split("\r\n");
+    assert_eq!(
+        lines.next(),
+        Some("name,description,domains,users,usedQuota,quota,pendingVerification")
+    );
+    let lines = lines.collect::<Vec<_>>();
+    assert!(lines.contains(&"acme,,1,1,0,,false"), "{csv}");
+    assert!(
+        lines.contains(&"acme-corp,\"Acme, \"\"Corp\"\"\",0,0,0,,false"),
+        "{csv}"
+    );
+    assert_eq!(lines.last(), Some(&""));
+
+    // Lists are rendered as aligned tables for shell users
+    let table = api
+        .get_raw("/api/organization?filter=acme&format=table")
+        .await
+        .unwrap();
+    let mut lines = table.lines();
+    let header = lines.next().unwrap();
+    assert_eq!(
+        header.split_whitespace().collect::<Vec<_>>(),
+        vec![
+            "NAME",
+            "DESCRIPTION",
+            "DOMAINS",
+            "USERS",
+            "USED_QUOTA",
+            "QUOTA",
+            "PENDING_VERIFICATION"
+        ]
+    );
+    let row = lines
+        .find(|line| line.starts_with("acme "))
+        .unwrap_or_else(|| panic!("{table}"));
+    assert_eq!(
+        row.split_whitespace().collect::<Vec<_>>(),
+        vec!["acme", "-", "1", "1", "0", "-", "false"]
+    );
+    assert_eq!(header.find("DOMAINS"), row.find(" 1 ").map(|pos| pos + 2));
+    let (status, table) = api
+        .request_text(Method::GET, "/api/principal?filter=acme-admin", None)
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+    let mut lines = table.lines();
+    assert_eq!(
+        lines.next().unwrap().split_whitespace().collect::<Vec<_>>(),
+        vec!["ID", "TYPE", "NAME", "EMAILS", "QUOTA", "DESCRIPTION"]
+    );
+    assert!(
+        lines.any(|line| line.contains(" individual  acme-admin ")),
+        "{table}"
+    );
+    let table = api
+        .get_raw("/api/queue/messages?format=table")
+        .await
+        .unwrap();
+    assert_eq!(
+        table.lines().next(),
+        Some("ID  RETURN_PATH  RECIPIENTS.ADDRESS  CREATED  SIZE"),
+        "{table}"
+    );
+    let (status, table) = api
+        .request_text(Method::GET, "/api/organization/acme/metrics", None)
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+    assert!(table.starts_with("METRIC"), "{table}");
+    assert!(
+        table.lines().any(|line| line.starts_with("bytes-stored ")),
+        "{table}"
+    );
+
+    // Errors are single lines with the matching status code
+    for (method, query, body, expected_status, expected) in [
+        (
+            Method::GET,
+            "/api/organization/unknown-org/metrics",
+            None,
+            404,
+            "error: \"unknown-org\" not found\n",
+        ),
+        (
+            Method::POST,
+            "/api/organization/provision",
+            Some(json!({
+                "tenantName": "acme",
+                "domain": "acme-corp.org",
+                "adminName": "acme-admin2",
+                "adminPassword": "acme-secret",
+                "adminEmail": "admin@acme-corp.org",
+            })),
+            409,
+            "error: name \"acme\" already exists\n",
+        ),
+        (Method::GET, "/api/unknown-endpoint", None, 404, "error: "),
+    ] {
+        let (status, response) = api
+            .request_text(method, query, body.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(status, expected_status, "{query}: {response}");
+        assert!(response.starts_with(expected), "{query}: {response}");
+        assert_eq!(response.lines().count(), 1, "{query}: {response}");
+    }
+
+    // Principals record when they were created and last modified
+    let principal = api
+        .get::<serde_json::Value>("/api/principal/acme-corp")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let created_at = principal["createdAt"].as_u64().unwrap();
+    assert!(
+        principal["modifiedAt"].as_u64().unwrap() >= created_at,
+        "{principal}"
+    );
+    assert!(
+        created_at <= store::write::now() && created_at > store::write::now() - 3600,
+        "{principal}"
+    );
+    for field in ["createdAt", "modifiedAt"] {
+        api.patch::<()>(
+            "/api/principal/acme-corp",
+            &json!([{"action": "set", "field": field, "value": 0}]),
+        )
+        .await
+        .unwrap()
+        .expect_error("Read-only field");
+    }
+    let principals = api
+        .get::<serde_json::Value>(
+            "/api/principal?types=tenant&filter=acme&sort=createdAt&order=desc&fields=name,createdAt",
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(principals["items"][0]["name"], "acme-corp", "{principals}");
+    assert!(
+        principals["items"][0]["createdAt"].as_u64()
+            >= principals["items"][1]["createdAt"].as_u64(),
+        "{principals}"
+    );
+    api.get::<serde_json::Value>("/api/principal?sort=quota")
+        .await
+        .unwrap()
+        .expect_error("Invalid sort field");
+
+    // Organizations can be filtered by creation time
+    let rfc3339 = |timestamp: u64| {
+        chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .unwrap()
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    };
+    let organizations = api
+        .get::<OrganizationList>(&format!(
+            "/api/organization?filter=acme&fields=name,createdAt&createdAfter={}",
+            rfc3339(created_at)
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        organizations
+            .items
+            .contains(&json!({"name": "acme-corp", "createdAt": created_at})),
+        "{:?}",
+        organizations.items
+    );
+    for filter in [
+        format!("createdBefore={}", rfc3339(created_at)),
+        format!("createdAfter={}", rfc3339(created_at + 1)),
+    ] {
+        let organizations = api
+            .get::<OrganizationList>(&format!(
+                "/api/organization?filter=acme&fields=name&{filter}"
+            ))
+            .await
+            .unwrap()
+            .unwrap_data();
+        assert!(
+            organizations
+                .items
+                .iter()
+                .all(|org| org["name"] != "acme-corp"),
+            "{:?}",
+            organizations.items
+        );
+    }
+
+    // Integrators can attach metadata to principals
+    api.patch::<()>(
+        "/api/principal/acme-corp",
+        &json!([{
+            "action": "set",
+            "field": "metadata",
+            "value": {"billing.customerId": "cus_42", "billing.costCenter": "eu-1"}
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    api.patch::<()>(
+        "/api/principal/acme-corp",
+        &json!([{
+            "action": "set",
+            "field": "metadata",
+            "value": {"billing.costCenter": null, "hr:employeeNumber": "1001"}
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let principal = api
+        .get::<serde_json::Value>("/api/principal/acme-corp")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        principal["metadata"],
+        json!({"billing.customerId": "cus_42", "hr:employeeNumber": "1001"}),
+        "{principal}"
+    );
+    for key in ["", "billing customer", "billing/customer"] {
+        api.patch::<()>(
+            "/api/principal/acme-corp",
+            &json!([{"action": "set", "field": "metadata", "value": {key: "1"}}]),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid metadata key");
+    }
+    let organizations = api
+        .get::<OrganizationList>("/api/organization?fields=name&metadata.billing.customerId=cus_42")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organizations.items, vec![json!({"name": "acme-corp"})]);
+    let principals = api
+        .get::<serde_json::Value>("/api/principal?types=tenant&metadata.billing.customerId=cus_41")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(principals["total"], 0, "{principals}");
+    api.patch::<()>(
+        "/api/principal/acme-corp",
+        &json!([{"action": "removeItem", "field": "metadata", "value": "hr:employeeNumber"}]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+
+    // Principals can be labelled with tags, compared case-insensitively
+    for (name, tags) in [
+        ("acme-corp", json!(["Pilot", "vip"])),
+        ("acme", json!(["pilot"])),
+    ] {
+        api.patch::<()>(
+            &format!("/api/principal/{name}"),
+            &json!([{"action": "set", "field": "tags", "value": tags}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    }
+    api.patch::<()>(
+        "/api/principal/acme-corp",
+        &json!([{"action": "addItem", "field": "tags", "value": "bad tag"}]),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid tag");
+    for (filter, expected) in [("pilot", 2), ("PILOT,vip", 1), ("pilot,migrating", 0)] {
+        let principals = api
+            .get::<serde_json::Value>(&format!("/api/principal?types=tenant&tag={filter}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+        assert_eq!(principals["total"], expected, "{filter}: {principals}");
+    }
+    let tags = api
+        .get::<serde_json::Value>("/api/organization/acme-corp/tags")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        tags["items"],
+        json!([{"tag": "pilot", "count": 1}, {"tag": "vip", "count": 1}]),
+        "{tags}"
+    );
+    api.patch::<()>(
+        "/api/principal/acme-corp",
+        &json!([{"action": "removeItem", "field": "tags", "value": "VIP"}]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let principal = api
+        .get::<serde_json::Value>("/api/principal/acme-corp")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(principal["tags"], json!(["pilot"]), "{principal}");
+    api.patch::<()>(
+        "/api/principal/acme",
+        &json!([{"action": "set", "field": "tags", "value": []}]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+}
+
+async fn outbound_delivery(params: &JMAPTest) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let acme_id = params
+        .server
+        .store()
+        .get_principal_id("acme")
+        .await
+        .unwrap()
+        .unwrap();
+    // Relay credentials of a tenant are write-only
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/routing",
+            &json!({"relay": {"host": "relay.acme.org", "username": "acme"}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Both username and secret are required");
+    for relay in [
+        json!({"relay": {"host": "127.0.0.1"}}),
+        json!({"relay": {"host": "Localhost"}}),
+        json!({"domains": {"partner.org": {"host": "[::1]"}}}),
+        json!({"domains": {"partner.org": {"host": "169.254.169.254", "port": 80}}}),
+    ] {
+        tenant_api
+            .put::<serde_json::Value>("/api/organization/acme/routing", &relay)
+            .await
+            .unwrap()
+            .expect_error("is not a public address");
+    }
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/routing",
+            &json!({"relay": {"host": "relay.acme.invalid"}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Failed to resolve");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/routing",
+            &json!({
+                "relay": {
+                    "host": "1.1.1.1",
+                    "port": 587,
+                    "tls": "starttls",
+                    "username": "acme",
+                    "secret": "relay-secret",
+                },
+                "domains": {
+                    "Partner.org": {"host": "8.8.8.8"},
+                },
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/routing",
+            &json!({
+                "relay": {
+                    "host": "1.1.1.1",
+                    "port": 587,
+                    "tls": "starttls",
+                    "username": "acme",
+                },
+                "domains": {
+                    "partner.org": {"host": "8.8.8.8"},
+                },
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let routing = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/routing")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(routing["relay"]["username"], "acme");
+    assert_eq!(routing["relay"]["tls"], "starttls");
+    assert!(routing["relay"].get("secret").is_none(), "{routing}");
+    assert_eq!(routing["domains"]["partner.org"]["port"], 465);
+
+    // Stored secrets are kept while the username is unchanged
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/routing",
+            &json!({"relay": {"host": "1.0.0.1", "username": "acme"}}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/routing",
+            &json!({"relay": {"host": "1.0.0.1", "username": "acme-relay"}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("has to be re-entered when its username changes");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/routing")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .put::<serde_json::Value>("/api/organization/acme/routing", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // DKIM signing policies must always sign the From header
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/dkim-policy",
+            &json!({"policy": {"headers": ["To", "Subject"]}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("The From header must be signed");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/dkim-policy",
+            &json!({
+                "policy": {"headers": ["From", "To"], "oversign": true},
+                "domains": {
+                    "Acme.org": {
+                        "headers": ["from", "date"],
+                        "headerCanonicalization": "simple",
+                        "bodyLength": true,
+                    },
+                },
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let policy = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/dkim-policy")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(policy["policy"]["oversign"], true, "{policy}");
+    assert_eq!(policy["policy"]["bodyCanonicalization"], "relaxed");
+    assert_eq!(
+        policy["domains"]["acme.org"]["headerCanonicalization"],
+        "simple"
+    );
+    assert_eq!(policy["domains"]["acme.org"]["bodyLength"], true);
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/dkim-policy")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .put::<serde_json::Value>("/api/organization/acme/dkim-policy", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Tenant admins can only access the delivery log of their organization
+    let deliveries = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/deliveries?outcome=bounced")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(deliveries["total"], 0, "{deliveries}");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/deliveries/1")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/deliveries")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Deliverability is summarized by destination provider
+    let created = now() - 30 * 86400;
+    let queue_id = SnowflakeIdGenerator::from_timestamp(created).unwrap();
+    let mut batch = BatchBuilder::new();
+    for (queue_id, log) in [
+        DeliveryLog {
+            id: queue_id,
+            created,
+            return_path: "jane@acme.org".to_string(),
+            recipients: vec![
+                DeliveryLogRecipient {
+                    address: "alice@gmail.com".to_string(),
+                    outcome: DeliveryOutcome::Delivered,
+                    details: Some("gmail-smtp-in.l.google.com: Code: 250, Message: OK".into()),
+                    updated: created,
+                },
+                DeliveryLogRecipient {
+                    address: "bob@example.net".to_string(),
+                    outcome: DeliveryOutcome::Bounced,
+                    details: Some(
+                        "mx.example.net: Code: 550, Enhanced code: 5.1.1, Message: User unknown"
+                            .into(),
+                    ),
+                    updated: created,
+                },
+            ],
+            attempts: vec![DeliveryLogAttempt {
+                timestamp: created,
+                recipient: "alice@gmail.com".to_string(),
+                outcome: DeliveryOutcome::Deferred,
+                details: Some(
+                    "gmail-smtp-in.l.google.com: Code: 421, Enhanced code: 4.7.28, Message: Try again later"
+                        .into(),
+                ),
+            }],
+        },
+        DeliveryLog {
+            id: queue_id + 1,
+            created,
+            return_path: "john@acme.org".to_string(),
+            recipients: vec![DeliveryLogRecipient {
+                address: "carol@corp.example".to_string(),
+                outcome: DeliveryOutcome::Deferred,
+                details: Some(
+                    "corp-example.mail.protection.outlook.com: Code: 451, Enhanced code: 4.7.650, Message: Poor reputation"
+                        .into(),
+                ),
+                updated: created,
+            }],
+            attempts: vec![DeliveryLogAttempt {
+                timestamp: created,
+                recipient: "carol@corp.example".to_string(),
+                outcome: DeliveryOutcome::Deferred,
+                details: Some(
+                    "corp-example.mail.protection.outlook.com: Code: 451, Enhanced code: 4.7.650, Message: Poor reputation"
+                        .into(),
+                ),
+            }],
+        },
+    ]
+    .into_iter()
+    .map(|log| (log.id, log))
+    {
+        batch.set(
+            ValueClass::Queue(QueueClass::DeliveryLog {
+                tenant_id: acme_id,
+                queue_id,
+            }),
+            Archiver::new(log).serialize().unwrap(),
+        );
+    }
+    params
+        .server
+        .store()
+        .write(batch.build_all())
+        .await
+        .unwrap();
+    params.server.dnsbl_add(
+        "1.2.0.192.bl.test.org",
+        vec!["127.0.0.2".parse().unwrap()],
+        Instant::now() + Duration::from_secs(60),
+    );
+    let window = format!(
+        "after={}&before={}",
+        (Utc::now() - TimeDelta::days(31)).to_rfc3339_opts(SecondsFormat::Secs, true),
+        (Utc::now() - TimeDelta::days(29)).to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    let report = tenant_api
+        .get::<serde_json::Value>(&format!("/api/organization/acme/deliverability?{window}"))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(report["messages"], 2, "{report}");
+    assert_eq!(report["recipients"], 3, "{report}");
+    assert_eq!(report["bounceRate"], 0.5, "{report}");
+    assert_eq!(report["alignment"]["messages"], 2, "{report}");
+    assert_eq!(report["alignment"]["domains"][0]["domain"], "acme.org");
+    let providers = report["providers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|provider| (provider["provider"].as_str().unwrap(), provider))
+        .collect::<AHashMap<_, _>>();
+    assert_eq!(providers.len(), 3, "{report}");
+    assert_eq!(providers["google"]["delivered"], 1);
+    assert_eq!(providers["google"]["deferralReasons"]["rateLimit"], 1);
+    assert_eq!(providers["microsoft"]["deferred"], 1);
+    assert_eq!(providers["microsoft"]["deferralReasons"]["reputation"], 1);
+    assert_eq!(providers["other"]["bounceRate"], 1.0);
+    assert_eq!(
+        report["dnsbl"],
+        json!([
+            {"ip": "192.0.2.1", "zone": "bl.test.org", "status": "listed", "result": "127.0.0.2"},
+            {"ip": "192.0.2.1", "zone": "bl.pending.org", "status": "pending", "result": null},
+        ])
+    );
+    let report = tenant_api
+        .get::<serde_json::Value>(&format!(
+            "/api/organization/acme/deliverability/microsoft?{window}"
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(report["recipients"], 1, "{report}");
+    assert_eq!(report["domains"][0]["domain"], "corp.example", "{report}");
+    assert_eq!(report["recentFailures"][0]["reason"], "reputation");
+    let csv = tenant_api
+        .get_raw(&format!(
+            "/api/organization/acme/deliverability?{window}&format=csv"
+        ))
+        .await
+        .unwrap();
+    assert!(
+        csv.starts_with("provider,recipients,delivered,deferred,bounced,bounceRate,rateLimit,"),
+        "{csv}"
+    );
+    assert!(
+        csv.contains("\r\nother,1,0,0,1,1.0,0,0,0,0,0,0\r\n"),
+        "{csv}"
+    );
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/deliverability?format=xml")
+        .await
+        .unwrap()
+        .expect_error("Invalid format");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/deliverability")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+}
+
+async fn tenant_mail_processing(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Tenant folders are localized to the tenant's locale
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/folders",
+            &json!({"folders": [{"role": "inbox"}, {"role": "Inbox"}]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Duplicate folder role");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/folders",
+            &json!({"folders": [{"name": "Projects"}, {"role": "snoozed"}]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Folder name is required");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/folders",
+            &json!({"folders": [
+                {"role": "inbox"},
+                {"role": "sent", "name": "Outbox"},
+                {"name": "Projects", "subscribe": false},
+            ]}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let folders = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/folders")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(folders["folders"][1]["name"], "Outbox", "{folders}");
+    assert_eq!(folders["folders"][2]["subscribe"], false, "{folders}");
+    api.patch::<()>(
+        "/api/principal/acme",
+        &json!([{
+            "action": "set",
+            "field": "locale",
+            "value": "es_ES"
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+
+    // Tenant Sieve scripts run before the user's own active script
+    let jane_id = tenant_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "jane@acme.org",
+                "secrets": ["jane-secret"],
+                "emails": ["jane@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        mailbox_roles(&params.server, jane_id).await,
+        vec![
+            ("Bandeja de entrada".to_string(), SpecialUse::Inbox),
+            ("Elementos eliminados".to_string(), SpecialUse::Trash),
+            ("Correo no deseado".to_string(), SpecialUse::Junk),
+            ("Outbox".to_string(), SpecialUse::Sent),
+            ("Projects".to_string(), SpecialUse::None),
+        ]
+    );
+    tenant_api
+        .put::<serde_json::Value>("/api/organization/acme/folders", &json!({"folders": []}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .put::<()>(
+            "/api/organization/acme/sieve/compliance",
+            &json!({"contents": "require \"imap4flags\"; addflag \"$compliance\""}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid Sieve script");
+    tenant_api
+        .put::<()>(
+            "/api/organization/acme/sieve/compliance",
+            &json!({"contents": "require \"imap4flags\"; addflag \"$compliance\";"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/sieve/compliance/assign",
+            &json!({"accounts": ["jdoe@example.com"]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    let assignment = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/sieve/compliance/assign",
+            &json!({"accounts": ["jane@acme.org"]}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(assignment["all"], json!(false));
+    assert_eq!(assignment["accounts"].as_array().unwrap().len(), 1);
+    let scripts = tenant_api
+        .get::<Vec<serde_json::Value>>("/api/organization/acme/sieve")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(scripts.len(), 1);
+    assert_eq!(scripts[0]["name"], "compliance");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme-corp/sieve")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    let client = jane_client().await;
+    client
+        .sieve_script_create(
+            "own",
+            b"require \"imap4flags\"; addflag \"$personal\";".to_vec(),
+            true,
+        )
+        .await
+        .unwrap();
+    SmtpConnection::connect()
+        .await
+        .ingest(
+            "bill@remote.org",
+            &["jane@acme.org"],
+            concat!(
+                "From: bill@remote.org\r\n",
+                "To: jane@acme.org\r\n",
+                "Subject: TPS Report\r\n",
+                "\r\n",
+                "I'm going to need those TPS reports ASAP."
+            ),
+        )
+        .await;
+    let message_ids = client
+        .email_query(None::<email::query::Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids();
+    assert_eq!(message_ids.len(), 1, "{message_ids:?}");
+    let email = client
+        .email_get(&message_ids[0], [email::Property::Keywords].into())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut keywords = email.keywords();
+    keywords.sort_unstable();
+    assert_eq!(keywords, vec!["$compliance", "$personal"]);
+
+    // Vacation responses without custom text use the tenant's template
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    params.server.ipv4_add(
+        "localhost",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/vacation",
+            &json!({"body": "Back on {{return_date}}"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Unknown placeholder");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/vacation",
+            &json!({
+                "subject": "Out of office: {{name}}",
+                "body": "{{name}} is away since {{from}}."
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let template = ManagementApi::new(8899, "jane@acme.org", "jane-secret")
+        .get::<serde_json::Value>("/api/account/vacation-template")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(template["subject"], "Out of office: {{name}}", "{template}");
+    let from_date = Utc::now() - TimeDelta::try_days(1).unwrap_or_default();
+    client
+        .vacation_response_set_dates(from_date.timestamp().into(), None)
+        .await
+        .unwrap();
+    SmtpConnection::connect()
+        .await
+        .ingest(
+            "bill@remote.org",
+            &["jane@acme.org"],
+            concat!(
+                "From: bill@remote.org\r\n",
+                "To: jane@acme.org\r\n",
+                "Subject: TPS Report -- friendly reminder\r\n",
+                "\r\n",
+                "Listen, are you gonna have those TPS reports for us this afternoon?",
+            ),
+        )
+        .await;
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(message.rcpt_to, vec!["<bill@remote.org>".to_string()]);
+    assert!(
+        message
+            .message
+            .contains("Subject: Out of office: jane@acme.org"),
+        "{}",
+        message.message
+    );
+    assert!(
+        message.message.contains(&format!(
+            "jane@acme.org is away since {}.",
+            from_date.format("%Y-%m-%d")
+        )),
+        "{}",
+        message.message
+    );
+
+    // Mandatory footers are appended to custom vacation text
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/vacation",
+            &json!({
+                "body": "For urgent matters contact help@acme.org.",
+                "mandatoryFooter": true
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    client
+        .vacation_response_enable("Gone fishing", Some("Back next week."), None::<String>)
+        .await
+        .unwrap();
+    smtp_settings.lock().do_stop = true;
+    SmtpConnection::connect()
+        .await
+        .ingest(
+            "jane_smith@remote.org",
+            &["jane@acme.org"],
+            concat!(
+                "From: jane_smith@remote.org\r\n",
+                "To: jane@acme.org\r\n",
+                "Subject: When were you going on holidays?\r\n",
+                "\r\n",
+                "I'm asking because Bill really wants those TPS reports.",
+            ),
+        )
+        .await;
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert!(
+        message.message.contains("Subject: Gone fishing"),
+        "{}",
+        message.message
+    );
+    for text in [
+        "Back next week.",
+        "For urgent matters contact help@acme.org.",
+    ] {
+        assert!(message.message.contains(text), "{}", message.message);
+    }
+    tenant_api
+        .delete::<()>("/api/organization/acme/vacation")
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Outbound disclaimers are validated and can be previewed
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/disclaimer",
+            &json!({"text": "Sent by {{sender}}"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Unknown placeholder");
+    let disclaimer = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/disclaimer",
+            &json!({
+                "text": "Sent by {{name}} on behalf of {{brand}}.",
+                "html": " ",
+                "excludeReplies": true
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(disclaimer["html"], serde_json::Value::Null, "{disclaimer}");
+    let disclaimer = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/disclaimer")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(disclaimer["excludeReplies"], true, "{disclaimer}");
+    let preview = tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/disclaimer/preview", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(preview["applied"], true, "{preview}");
+    let message = mail_parser::MessageParser::new()
+        .parse(preview["message"].as_str().unwrap())
+        .unwrap();
+    assert!(
+        message
+            .body_text(0)
+            .unwrap()
+            .contains("on behalf of Acme & Sons."),
+        "{preview}"
+    );
+    let preview = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/disclaimer/preview",
+            &json!({"message": "In-Reply-To: <1@acme.org>\r\n\r\nThanks."}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(preview["applied"], false, "{preview}");
+
+    // Domain disclaimers replace the organization disclaimer, or suppress it
+    let preview_text = |preview: serde_json::Value| {
+        assert_eq!(preview["applied"], true, "{preview}");
+        mail_parser::MessageParser::new()
+            .parse(preview["message"].as_str().unwrap())
+            .unwrap()
+            .body_text(0)
+            .unwrap()
+            .into_owned()
+    };
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/domain/acme-corp.org/disclaimer",
+            &json!({"disabled": true}),
+        )
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/domain/acme.org/disclaimer",
+            &json!({"disabled": false}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Remove the domain disclaimer");
+    assert!(
+        preview_text(
+            tenant_api
+                .post::<serde_json::Value>("/api/domain/acme.org/disclaimer/preview", &json!({}))
+                .await
+                .unwrap()
+                .unwrap_data()
+        )
+        .contains("on behalf of Acme & Sons.")
+    );
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/domain/acme.org/disclaimer",
+            &json!({"text": "Marketing by {{brand}}."}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let text = preview_text(
+        tenant_api
+            .post::<serde_json::Value>("/api/domain/acme.org/disclaimer/preview", &json!({}))
+            .await
+            .unwrap()
+            .unwrap_data(),
+    );
+    assert!(text.contains("Marketing by Acme & Sons."), "{text}");
+    assert!(!text.contains("on behalf of"), "{text}");
+    let disclaimer = tenant_api
+        .put::<serde_json::Value>(
+            "/api/domain/acme.org/disclaimer",
+            &json!({"disabled": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(disclaimer, json!({"disabled": true}));
+    let preview = tenant_api
+        .post::<serde_json::Value>("/api/domain/acme.org/disclaimer/preview", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(preview["applied"], false, "{preview}");
+    tenant_api
+        .delete::<()>("/api/domain/acme.org/disclaimer")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        tenant_api
+            .get::<serde_json::Value>("/api/domain/acme.org/disclaimer")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        serde_json::Value::Null
+    );
+    tenant_api
+        .delete::<()>("/api/organization/acme/disclaimer")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        tenant_api
+            .get::<serde_json::Value>("/api/organization/acme/disclaimer")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        serde_json::Value::Null
+    );
+}
+
+async fn tenant_branding(api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Contact details are validated and normalized
+    api.post::<serde_json::Value>(
+        "/api/organization/provision",
+        &json!({
+            "tenantName": "contactless",
+            "domain": "contactless.example",
+            "adminName": "contactless-admin",
+            "adminPassword": "contactless-secret",
+            "adminEmail": "admin@contactless.example",
+            "contacts": {"billingEmail": "not an email"},
+        }),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid contact");
+    for contacts in [
+        json!({"technicalContact": "ops@"}),
+        json!({"phone": "+1 12"}),
+        json!({"phone": "555+1234567"}),
+        json!({"taxId": "DE 123_456"}),
+    ] {
+        tenant_api
+            .patch::<serde_json::Value>("/api/organization/acme/contacts", &contacts)
+            .await
+            .unwrap()
+            .expect_error("Invalid contact");
+    }
+    tenant_api
+        .patch::<serde_json::Value>("/api/organization/acme/contacts", &json!({"fax": "1"}))
+        .await
+        .unwrap()
+        .expect_error("fax");
+    let contacts = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/contacts",
+            &json!({
+                "billingEmail": "Billing@Acme.org",
+                "technicalContact": "ops@acme.org",
+                "phone": "+1 (555) 010-9999",
+                "postalAddress": "1 Road Runner Way\r\nDesert, AZ",
+                "taxId": "us 12-3456789",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        contacts,
+        json!({
+            "billingEmail": "billing@acme.org",
+            "technicalContact": "ops@acme.org",
+            "phone": "+1 (555) 010-9999",
+            "postalAddress": "1 Road Runner Way\nDesert, AZ",
+            "taxId": "US 12-3456789",
+        })
+    );
+
+    // Only the provided fields are changed, null clears a field
+    let contacts = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/contacts",
+            &json!({"phone": null, "taxId": ""}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(contacts["billingEmail"], "billing@acme.org", "{contacts}");
+    assert_eq!(contacts["phone"], serde_json::Value::Null, "{contacts}");
+    assert_eq!(contacts["taxId"], serde_json::Value::Null, "{contacts}");
+    let organization = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organization["contacts"]["technicalContact"], "ops@acme.org",
+        "{organization}"
+    );
+    let organizations = api
+        .get::<OrganizationList>(
+            "/api/organization?filter=acme&fields=name,billingEmail,technicalContact",
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations
+            .items
+            .iter()
+            .find(|org| org["name"] == "acme")
+            .unwrap(),
+        &json!({
+            "name": "acme",
+            "billingEmail": "billing@acme.org",
+            "technicalContact": "ops@acme.org"
+        })
+    );
+
+    // Branding can be previewed before it is saved
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/brand/preview",
+            &json!({"brandTheme": {"primaryColor": "red;}</style><script>"}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid theme");
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/brand/preview?component=sms",
+            &json!({}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid component");
+    let login = tenant_api
+        .post_raw(
+            "/api/organization/acme/brand/preview",
+            &json!({
+                "brandLogoUrl": "https://acme.org/logo.png",
+                "brandTheme": {"primaryColor": "#ff5733", "fontFamily": "Inter, sans-serif"}
+            }),
+        )
+        .await
+        .unwrap();
+    assert!(login.contains("Acme &amp; Sons"), "{login}");
+    assert!(login.contains("background-color: #ff5733"), "{login}");
+    assert!(login.contains("font-family: Inter, sans-serif"), "{login}");
+    assert!(
+        login.contains("src=\"https://acme.org/logo.png\""),
+        "{login}"
+    );
+    let email = tenant_api
+        .post_raw(
+            "/api/organization/acme/brand/preview?component=email",
+            &json!({"brandName": "<b>Acme</b>"}),
+        )
+        .await
+        .unwrap();
+    assert!(email.contains("&lt;b&gt;Acme&lt;/b&gt;"), "{email}");
+    assert!(!email.contains("#ff5733"), "{email}");
+
+    // Branding has light and dark variants, the dark one is derived when missing
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/brand",
+            &json!({"brandLogoDarkUrl": "javascript:alert(1)"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid logo");
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/brand",
+            &json!({"brandTheme": {"dark": {"textColor": "white"}}}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid theme");
+    let brand = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/brand?variant=dark",
+            &json!({
+                "brandLogoUrl": "https://acme.org/logo.png",
+                "brandTheme": {"primaryColor": "#ff5733", "textColor": "#111111"}
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(brand["variant"], "dark", "{brand}");
+    assert_eq!(
+        brand["resolved"]["logoUrl"], "https://acme.org/logo.png",
+        "{brand}"
+    );
+    assert_eq!(
+        brand["resolved"]["colors"]["primaryColor"], "#ff5733",
+        "{brand}"
+    );
+    assert_eq!(
+        brand["resolved"]["colors"]["backgroundColor"], "#111111",
+        "{brand}"
+    );
+    let brand = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/brand?variant=dark",
+            &json!({
+                "brandLogoDarkUrl": "https://acme.org/logo-dark.png",
+                "brandTheme": {
+                    "primaryColor": "#ff5733",
+                    "dark": {"primaryColor": "#ffaa88", "backgroundColor": "#000000"}
+                }
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        brand["resolved"]["logoUrl"], "https://acme.org/logo-dark.png",
+        "{brand}"
+    );
+    assert_eq!(
+        brand["resolved"]["colors"]["primaryColor"], "#ffaa88",
+        "{brand}"
+    );
+    let brand = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/brand?variant=light")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        brand["resolved"]["logoUrl"], "https://acme.org/logo.png",
+        "{brand}"
+    );
+    assert_eq!(
+        brand["resolved"]["colors"]["primaryColor"], "#ff5733",
+        "{brand}"
+    );
+    assert_eq!(
+        brand["brandTheme"]["dark"]["backgroundColor"], "#000000",
+        "{brand}"
+    );
+    let login = tenant_api
+        .post_raw(
+            "/api/organization/acme/brand/preview?variant=dark",
+            &json!({}),
+        )
+        .await
+        .unwrap();
+    assert!(login.contains("logo-dark.png"), "{login}");
+    assert!(login.contains("color-scheme: dark"), "{login}");
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/brand",
+            &json!({"brandLogoUrl": null, "brandLogoDarkUrl": null, "brandTheme": null}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    api.post::<serde_json::Value>(
+        "/api/organization/provision",
+        &json!({
+            "tenantName": "darkmode",
+            "domain": "darkmode.example",
+            "adminName": "darkmode-admin",
+            "adminPassword": "darkmode-secret",
+            "adminEmail": "admin@darkmode.example",
+            "brandLogoDarkUrl": "ftp://darkmode.example/logo.png",
+        }),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid logo");
+
+    // Login pages are customized per tenant and per domain
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/domain/acme.org/login-page",
+            &json!({"css": "body { background: url(https://evil.org/x.png) }"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid login page");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/login-page",
+            &json!({"termsUrl": "javascript:alert(1)"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid login page");
+    let page = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/login-page",
+            &json!({
+                "subtitle": "Webmail for Acme staff",
+                "supportUrl": "mailto:help@acme.org",
+                "privacyUrl": "https://acme.org/privacy",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(page["supportUrl"], "mailto:help@acme.org", "{page}");
+    let page = tenant_api
+        .put::<serde_json::Value>(
+            "/api/domain/acme.org/login-page",
+            &json!({
+                "title": "Acme <Webmail>",
+                "termsUrl": "https://acme.org/terms",
+                "css": "/* brand */ h1 {\n  COLOR: #ff0000 }",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(page["css"], "h1 { color: #ff0000; }\n", "{page}");
+    assert_eq!(
+        tenant_api
+            .get::<serde_json::Value>("/api/domain/acme.org/login-page")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        page
+    );
+
+    // The login page is public and resolved by the host or the domain hint,
+    // the domain page takes precedence over the tenant page
+    let login = api
+        .get_raw("/api/branding/login?domain=mail.acme.org")
+        .await
+        .unwrap();
+    assert!(login.contains("<h1>Acme &lt;Webmail&gt;</h1>"), "{login}");
+    assert!(login.contains("h1 { color: #ff0000; }"), "{login}");
+    assert!(login.contains("https://acme.org/terms"), "{login}");
+    assert!(!login.contains("Webmail for Acme staff"), "{login}");
+    let branding = api
+        .get::<serde_json::Value>("/api/branding?domain=acme.org&variant=dark")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(branding["domain"], "acme.org", "{branding}");
+    assert_eq!(branding["brand"]["name"], "Acme & Sons", "{branding}");
+    assert_eq!(
+        branding["loginPage"]["title"], "Acme <Webmail>",
+        "{branding}"
+    );
+    let preview = tenant_api
+        .post_raw(
+            "/api/domain/acme.org/login-page/preview",
+            &json!({"loginPage": {"subtitle": "Preview only"}}),
+        )
+        .await
+        .unwrap();
+    assert!(preview.contains("Preview only"), "{preview}");
+    assert!(preview.contains("Acme &lt;Webmail&gt;"), "{preview}");
+    tenant_api
+        .delete::<()>("/api/domain/acme.org/login-page")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let login = api
+        .get_raw("/api/branding/login?domain=acme.org")
+        .await
+        .unwrap();
+    assert!(login.contains("<h1>Acme &amp; Sons</h1>"), "{login}");
+    assert!(login.contains("Webmail for Acme staff"), "{login}");
+    assert!(login.contains("mailto:help@acme.org"), "{login}");
+    assert!(!login.contains("https://acme.org/terms"), "{login}");
+    tenant_api
+        .delete::<()>("/api/organization/acme/login-page")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let branding = api
+        .get::<serde_json::Value>("/api/branding?domain=acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(branding["loginPage"], serde_json::Value::Null, "{branding}");
+
+    // Tenants map their own hostnames to the web interface, the mapping is
+    // only routed once the ownership of the hostname is verified
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/hostnames",
+            &json!({"hostname": "localhost"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid hostname");
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/hostnames",
+            &json!({"hostname": "mail.acme-portal.com", "defaultDomain": "example.org"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid default domain");
+    let mapping = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/hostnames",
+            &json!({"hostname": "Mail.Acme-Portal.com."}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(mapping["hostname"], "mail.acme-portal.com", "{mapping}");
+    assert_eq!(mapping["defaultDomain"], "acme.org", "{mapping}");
+    assert_eq!(mapping["status"], "pending", "{mapping}");
+    assert_eq!(
+        mapping["challenge"]["name"], "_stalwart-challenge.mail.acme-portal.com.",
+        "{mapping}"
+    );
+    assert!(
+        mapping["challenge"]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("stalwart-verification="),
+        "{mapping}"
+    );
+    api.post::<serde_json::Value>(
+        "/api/organization/acme/hostnames",
+        &json!({"hostname": "mail.acme-portal.com"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Hostname already mapped");
+    for hostname in ["vandelay.org", "mail.Vandelay.org", "a.b.vandelay.org"] {
+        tenant_api
+            .post::<serde_json::Value>(
+                "/api/organization/acme/hostnames",
+                &json!({"hostname": hostname}),
+            )
+            .await
+            .unwrap()
+            .expect_error("Hostname already mapped");
+    }
+    let branding = api
+        .get::<serde_json::Value>("/api/branding?domain=mail.acme-portal.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(branding["domain"], serde_json::Value::Null, "{branding}");
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/hostnames/mail.acme-portal.com/verify?force=true",
+            &json!({}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Only administrators");
+    let mapping = api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/hostnames/mail.acme-portal.com/verify?force=true",
+            &json!({}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(mapping["status"], "verified", "{mapping}");
+    assert_eq!(mapping["challenge"], serde_json::Value::Null, "{mapping}");
+    let branding = api
+        .get::<serde_json::Value>("/api/branding?domain=mail.acme-portal.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(branding["domain"], "acme.org", "{branding}");
+    assert_eq!(branding["brand"]["name"], "Acme & Sons", "{branding}");
+    let overview = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        overview["hostnames"][0]["hostname"], "mail.acme-portal.com",
+        "{overview}"
+    );
+    assert_eq!(overview["hostnames"][0]["status"], "verified", "{overview}");
+
+    // Removing the mapping stops serving the tenant branding right away
+    tenant_api
+        .delete::<()>("/api/organization/acme/hostnames/mail.acme-portal.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let branding = api
+        .get::<serde_json::Value>("/api/branding?domain=mail.acme-portal.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(branding["domain"], serde_json::Value::Null, "{branding}");
+    assert_eq!(
+        tenant_api
+            .get::<serde_json::Value>("/api/organization/acme/hostnames")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        json!([])
+    );
+
+    // Pending challenges are only published in the zone of the tenant that
+    // owns the domain
+    for hostname in ["portal.acme.org", "mail.later.example"] {
+        tenant_api
+            .post::<serde_json::Value>(
+                "/api/organization/acme/hostnames",
+                &json!({"hostname": hostname}),
+            )
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    api.post::<u32>(
+        "/api/principal",
+        &json!({"type": "domain", "name": "later.example", "tenant": "acme-corp"}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    for (domain, challenge, expected) in [
+        ("acme.org", "_stalwart-challenge.portal.acme.org.", true),
+        (
+            "later.example",
+            "_stalwart-challenge.mail.later.example.",
+            false,
+        ),
+    ] {
+        let records = api
+            .get::<serde_json::Value>(&format!("/api/dns/records/{domain}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+        assert_eq!(
+            records
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|record| record["name"] == challenge),
+            expected,
+            "{records}"
+        );
+    }
+    for hostname in ["portal.acme.org", "mail.later.example"] {
+        tenant_api
+            .delete::<()>(&format!("/api/organization/acme/hostnames/{hostname}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    api.delete::<()>("/api/principal/later.example")
+        .await
+        .unwrap()
+        .unwrap_data();
+}
+
+async fn tenant_policies(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let client = jane_client().await;
+    // Tenants apply a stricter password policy to their accounts
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/settings/password-policy",
+            &json!({"minLength": 1000}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid password policy");
+    let policy = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/settings/password-policy",
+            &json!({
+                "minLength": 12,
+                "requiredClasses": ["digit", "symbol"],
+                "denyList": ["AcmeRocks!2024"],
+                "allowAppPasswords": false,
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(policy["effective"]["minLength"], 12, "{policy}");
+    assert_eq!(
+        policy["effective"]["requiredClasses"],
+        json!(["digit", "symbol"]),
+        "{policy}"
+    );
+    assert_eq!(policy["policy"]["denyList"], json!(["acmerocks!2024"]));
+    let branding = api
+        .get::<serde_json::Value>("/api/branding/password-policy?domain=acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(branding["passwordPolicy"]["minLength"], 12, "{branding}");
+    assert_eq!(
+        branding["passwordPolicy"]["allowAppPasswords"], false,
+        "{branding}"
+    );
+    assert_eq!(
+        branding["passwordPolicy"]["denyList"],
+        serde_json::Value::Null,
+        "{branding}"
+    );
+    for weak in ["short1!", "no-digits-here!", "acmerocks!2024"] {
+        tenant_api
+            .post::<u32>(
+                "/api/principal",
+                &json!({
+                    "type": "individual",
+                    "name": "policy-user",
+                    "secrets": [weak],
+                    "emails": ["policy-user@acme.org"],
+                }),
+            )
+            .await
+            .unwrap()
+            .expect_error("Invalid password");
+    }
+    tenant_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "policy-user",
+                "secrets": ["correct-horse-42!"],
+                "emails": ["policy-user@acme.org"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .patch::<()>(
+            "/api/principal/policy-user",
+            &json!([{"action": "set", "field": "secrets", "value": "password"}]),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid password");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/policy-user",
+            &json!([{"action": "addItem", "field": "secrets", "value": "$app$phone$secret"}]),
+        )
+        .await
+        .unwrap()
+        .expect_error("App passwords not allowed");
+
+    // Looser policies are accepted as well, removing the policy restores the
+    // global one
+    let policy = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/settings/password-policy",
+            &json!({"minLength": 4, "requiredClasses": []}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(policy["effective"]["minLength"], 4, "{policy}");
+    assert_eq!(
+        policy["effective"]["requiredClasses"],
+        json!([]),
+        "{policy}"
+    );
+    tenant_api
+        .patch::<()>(
+            "/api/principal/policy-user",
+            &json!([{"action": "set", "field": "secrets", "value": "abcd"}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/organization/acme/settings/password-policy")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let policy = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/settings/password-policy")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(policy["policy"], serde_json::Value::Null, "{policy}");
+    assert_eq!(policy["effective"]["minLength"], 0, "{policy}");
+    tenant_api
+        .delete::<()>("/api/principal/policy-user")
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Messages redirected by tenant accounts are ARC sealed with a key of the tenant
+    tenant_api
+        .patch::<serde_json::Value>("/api/organization/acme", &json!({"arcSeal": "unknown"}))
+        .await
+        .unwrap()
+        .expect_error("Invalid signature");
+    let organization = tenant_api
+        .patch::<serde_json::Value>("/api/organization/acme", &json!({"arcSeal": "acme"}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["arcSeal"], "acme", "{organization}");
+    params.server.txt_add(
+        "arc._domainkey.acme.org",
+        DomainKey::parse(b"v=DKIM1; k=ed25519; p=qgmCKM1i01iLwa3o4KFoCYBx3cIKW1kvigiYw0WDuD8=")
+            .unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    client
+        .sieve_script_create("forward", b"redirect \"bill@remote.org\";".to_vec(), true)
+        .await
+        .unwrap();
+    smtp_settings.lock().do_stop = true;
+    SmtpConnection::connect()
+        .await
+        .ingest(
+            "jane_smith@remote.org",
+            &["jane@acme.org"],
+            concat!(
+                "From: jane_smith@remote.org\r\n",
+                "To: jane@acme.org\r\n",
+                "Subject: TPS reports\r\n",
+                "\r\n",
+                "Did you get the memo about the new cover sheets?",
+            ),
+        )
+        .await;
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(message.rcpt_to, vec!["<bill@remote.org>".to_string()]);
+    assert!(
+        message
+            .message
+            .contains("ARC-Seal: i=1; a=ed25519-sha256; s=arc; d=acme.org; cv=none;"),
+        "{}",
+        message.message
+    );
+    assert!(
+        message.message.contains("ARC-Authentication-Results: i=1;"),
+        "{}",
+        message.message
+    );
+    let auth_message = AuthenticatedMessage::parse(message.message.as_bytes()).unwrap();
+    let arc_output = params
+        .server
+        .core
+        .smtp
+        .resolvers
+        .dns
+        .verify_arc(
+            params
+                .server
+                .inner
+                .cache
+                .build_auth_parameters(&auth_message),
+        )
+        .await;
+    assert_eq!(
+        arc_output.result(),
+        &DkimResult::Pass,
+        "{}",
+        message.message
+    );
+    client.sieve_script_deactivate().await.unwrap();
+    tenant_api
+        .patch::<serde_json::Value>("/api/organization/acme", &json!({"arcSeal": null}))
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Only whitelisted settings can be overridden per organization
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"http.use-x-forwarded": "true"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("cannot be overridden per organization");
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"oauth.expiry.token": "soon"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid duration value");
+    let validation = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/settings/validate",
+            &json!({
+                "oauth.expiry.token": "2h",
+                "oauth.expiry.refresh-token": "1h",
+                "jmap.email.max-size": [1],
+                "smtp.relay": "mx.acme.org",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(validation["valid"], json!(false));
+    let mut errors = validation["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|issue| issue["key"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    errors.sort_unstable();
+    assert_eq!(
+        errors,
+        [
+            "jmap.email.max-size",
+            "oauth.expiry.refresh-token",
+            "smtp.relay"
+        ]
+    );
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"oauth.expiry.token": "2h", "oauth.expiry.refresh-token": "1h"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Refresh tokens must not expire before access tokens");
+    assert_eq!(
+        tenant_api
+            .get::<serde_json::Value>("/api/organization/acme/settings")
+            .await
+            .unwrap()
+            .unwrap_data(),
+        json!({})
+    );
+    let validation = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/settings/validate",
+            &json!({"oauth.expiry.token": "2s", "jmap.email.max-attachment-size": 1000}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(validation["valid"], json!(true), "{validation}");
+    let overrides = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"oauth.expiry.token": "2s", "jmap.email.max-attachment-size": 1000}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        overrides,
+        json!({"oauth.expiry.token": "2s", "jmap.email.max-attachment-size": "1000"})
+    );
+
+    // Overrides apply to the tenant's accounts and domains, others use the global value
+    for (query, expected) in [
+        ("account=jane@acme.org", ("2s", "tenant")),
+        ("domain=acme.org", ("2s", "tenant")),
+        ("account=jdoe@example.com", ("1s", "global")),
+    ] {
+        let effective = api
+            .get::<serde_json::Value>(&format!("/api/troubleshoot/settings?{query}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+        let setting = effective["settings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|setting| setting["key"] == "oauth.expiry.token")
+            .unwrap();
+        assert_eq!(
+            (
+                setting["value"].as_str().unwrap(),
+                setting["source"].as_str().unwrap()
+            ),
+            expected,
+            "{query}"
+        );
+    }
+    let overrides = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"oauth.expiry.token": null, "jmap.email.max-attachment-size": null}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(overrides, json!({}));
+
+    // Session limits set by the organization apply to existing sessions at
+    // their next request
+    let sessions = tenant_api
+        .get::<serde_json::Value>("/api/account/sessions")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(sessions["limits"]["idleTimeout"], 0, "{sessions}");
+    let current = sessions["sessions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|session| session["current"] == true)
+        .unwrap_or_else(|| panic!("{sessions}"));
+    assert_eq!(current["expiresAt"], serde_json::Value::Null, "{sessions}");
+    let validation = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/settings/validate",
+            &json!({"authentication.session.idle-timeout": "8h", "authentication.session.max-lifetime": "1h"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        validation["warnings"][0]["key"], "authentication.session.idle-timeout",
+        "{validation}"
+    );
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"authentication.session.idle-timeout": "15m", "authentication.session.max-lifetime": "8h"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let sessions = tenant_api
+        .get::<serde_json::Value>("/api/account/sessions")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(sessions["limits"]["idleTimeout"], 900, "{sessions}");
+    assert_eq!(sessions["limits"]["maxLifetime"], 8 * 3600, "{sessions}");
+    let current = sessions["sessions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|session| session["current"] == true)
+        .unwrap_or_else(|| panic!("{sessions}"));
+    assert_eq!(current["idleTimeout"], 900, "{sessions}");
+    assert!(
+        current["expiresAt"].as_u64().unwrap() <= current["lastUsed"].as_u64().unwrap() + 900,
+        "{sessions}"
+    );
+    let sessions = api
+        .get::<serde_json::Value>("/api/account/sessions")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(sessions["limits"]["idleTimeout"], 0, "{sessions}");
+    let overrides = tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"authentication.session.idle-timeout": null, "authentication.session.max-lifetime": null}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(overrides, json!({}));
+
+    // Protocols disabled for the organization refuse logins, even before the
+    // password is checked, and are accepted again once re-enabled
+    for protocol in ["imap", "pop3", "submission", "jmap", "dav"] {
+        assert_eq!(
+            protocol_login(protocol, "jane@acme.org", "jane-secret").await,
+            Ok(()),
+            "{protocol}"
+        );
+        let key = format!("protocol.{protocol}.enable");
+        let overrides = tenant_api
+            .patch::<serde_json::Value>("/api/organization/acme/settings", &json!({&key: false}))
+            .await
+            .unwrap()
+            .unwrap_data();
+        assert_eq!(overrides, json!({&key: "false"}));
+        for secret in ["jane-secret", "wrong-secret"] {
+            let err = protocol_login(protocol, "jane@acme.org", secret)
+                .await
+                .unwrap_err();
+            assert!(err.contains("Protocol not enabled"), "{protocol}: {err}");
+        }
+        assert_eq!(
+            protocol_login(protocol, "jdoe@example.com", "12345").await,
+            Ok(()),
+            "{protocol}"
+        );
+        tenant_api
+            .patch::<serde_json::Value>("/api/organization/acme/settings", &json!({&key: true}))
+            .await
+            .unwrap()
+            .unwrap_data();
+        assert_eq!(
+            protocol_login(protocol, "jane@acme.org", "jane-secret").await,
+            Ok(()),
+            "{protocol}"
+        );
+        tenant_api
+            .patch::<serde_json::Value>("/api/organization/acme/settings", &json!({&key: null}))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+
+    // Disabling submission hides it from the JMAP session
+    let has_submission = |name: &'static str, secret: &'static str| async move {
+        let session = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .get("https://127.0.0.1:8899/jmap/session")
+            .basic_auth(name, Some(secret))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        session["capabilities"]
+            .get("urn:ietf:params:jmap:submission")
+            .is_some()
+    };
+    assert!(has_submission("jane@acme.org", "jane-secret").await);
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"protocol.submission.enable": false}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(!has_submission("jane@acme.org", "jane-secret").await);
+    assert!(has_submission("jdoe@example.com", "12345").await);
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"protocol.submission.enable": null, "protocol.gopher.enable": false}),
+        )
+        .await
+        .unwrap()
+        .expect_error("cannot be overridden per organization");
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"protocol.submission.enable": null}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(has_submission("jane@acme.org", "jane-secret").await);
+
+    // Accounts restricted to some protocols are refused the others, but only
+    // once the password was verified
+    tenant_api
+        .patch::<()>(
+            "/api/principal/jane@acme.org",
+            &json!([{"action": "set", "field": "allowedProtocols", "value": ["imap", "gopher"]}]),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid protocol");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/jane@acme.org",
+            &json!([{"action": "set", "field": "allowedProtocols", "value": ["IMAP", "jmap"]}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let principal = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        principal["allowedProtocols"],
+        json!(["imap", "jmap"]),
+        "{principal}"
+    );
+    for protocol in ["imap", "jmap"] {
+        assert_eq!(
+            protocol_login(protocol, "jane@acme.org", "jane-secret").await,
+            Ok(()),
+            "{protocol}"
+        );
+    }
+    for protocol in ["pop3", "submission", "dav"] {
+        let err = protocol_login(protocol, "jane@acme.org", "jane-secret")
+            .await
+            .unwrap_err();
+        assert!(err.contains("Protocol not permitted"), "{protocol}: {err}");
+        let err = protocol_login(protocol, "jane@acme.org", "wrong-secret")
+            .await
+            .unwrap_err();
+        assert!(!err.contains("Protocol not permitted"), "{protocol}: {err}");
+    }
+    assert!(!has_submission("jane@acme.org", "jane-secret").await);
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/app-passwords",
+            &json!({"label": "phone", "protocols": ["imap", "smtp"]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid app password protocol");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/jane@acme.org",
+            &json!([{"action": "set", "field": "allowedProtocols", "value": []}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        protocol_login("pop3", "jane@acme.org", "jane-secret").await,
+        Ok(())
+    );
+    assert!(has_submission("jane@acme.org", "jane-secret").await);
+}
+
+async fn multi_factor_authentication(params: &JMAPTest) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Users enroll TOTP factors themselves, confirming them with a code
+    let jane_api = ManagementApi::new(8899, "jane@acme.org", "jane-secret");
+    let enrollment = jane_api
+        .post::<serde_json::Value>("/api/principal/jane@acme.org/mfa/totp", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let uri = enrollment["uri"].as_str().unwrap();
+    assert_eq!(enrollment["qrPayload"], uri, "{enrollment}");
+    let code = totp_rs::TOTP::from_url(uri)
+        .unwrap()
+        .generate_current()
+        .unwrap();
+    jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/totp/confirm",
+            &json!({"code": "abcdef"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid TOTP code");
+    jane_api
+        .post::<()>(
+            "/api/principal/jane@acme.org/mfa/totp/confirm",
+            &json!({"code": code}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        protocol_login("imap", "jane@acme.org", "jane-secret")
+            .await
+            .is_err()
+    );
+    assert_eq!(
+        protocol_login("imap", "jane@acme.org", &format!("jane-secret${code}")).await,
+        Ok(())
+    );
+
+    // Administrators list and remove the factors of their users
+    let factors = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org/mfa")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(factors.as_array().map(|f| f.len()), Some(1), "{factors}");
+    assert_eq!(factors[0]["type"], "totp", "{factors}");
+    assert_eq!(factors[0]["accountName"], "jane@acme.org", "{factors}");
+    assert_eq!(factors[0]["encrypted"], true, "{factors}");
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org/mfa/totp")
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org/mfa/totp")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    assert_eq!(
+        protocol_login("imap", "jane@acme.org", "jane-secret").await,
+        Ok(())
+    );
+
+    // Passkeys are registered by their owner for the host of the web interface
+    let mut authenticator = TestAuthenticator::new();
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/start?domain=acme.org",
+            &json!({"nickname": "Laptop"}),
+        )
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/start?domain=unknown.org",
+            &json!({"nickname": "Laptop"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid relying party");
+    let registration = jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/start?domain=acme.org",
+            &json!({"nickname": "Laptop", "residentKey": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        registration["publicKey"]["rp"]["id"], "acme.org",
+        "{registration}"
+    );
+    assert_eq!(
+        registration["publicKey"]["attestation"], "none",
+        "{registration}"
+    );
+    jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/finish",
+            &json!({
+                "challengeId": registration["challengeId"],
+                "credential": authenticator.attestation(&registration, "https://evil.org"),
+            }),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid credential");
+    let registration = jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/start?domain=acme.org",
+            &json!({"nickname": "Laptop", "residentKey": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let credential = jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/finish",
+            &json!({
+                "challengeId": registration["challengeId"],
+                "credential": authenticator.attestation(&registration, "https://acme.org"),
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let credential_id = credential["id"].as_str().unwrap().to_string();
+    jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/webauthn/register/finish",
+            &json!({
+                "challengeId": registration["challengeId"],
+                "credential": authenticator.attestation(&registration, "https://acme.org"),
+            }),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid challenge");
+    let credentials = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org/mfa/webauthn")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(credentials[0]["id"], credential_id, "{credentials}");
+    assert_eq!(credentials[0]["nickname"], "Laptop", "{credentials}");
+    assert_eq!(credentials[0]["rpId"], "acme.org", "{credentials}");
+    assert_eq!(credentials[0]["residentKey"], true, "{credentials}");
+    let factors = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org/mfa")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(factors[0]["type"], "webauthn", "{factors}");
+
+    // Passkeys are a second factor unless the organization allows passwordless logins
+    let anonymous_api = ManagementApi::new(8899, "anonymous", "");
+    let jane_id = params
+        .server
+        .store()
+        .get_principal_id("jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap();
+    let options = anonymous_api
+        .post::<serde_json::Value>(
+            "/auth/webauthn/start?domain=acme.org",
+            &json!({"username": "jane@acme.org"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        options["publicKey"]["allowCredentials"][0]["id"], credential_id,
+        "{options}"
+    );
+    authenticator.sign_count = 1;
+    webauthn_login(&authenticator, jane_id, Some("jane@acme.org"), None)
+        .await
+        .expect_error("Password required");
+    webauthn_login(
+        &authenticator,
+        jane_id,
+        Some("jane@acme.org"),
+        Some("wrong"),
+    )
+    .await
+    .expect_request_error("Unauthorized");
+    let token = webauthn_login(
+        &authenticator,
+        jane_id,
+        Some("jane@acme.org"),
+        Some("jane-secret"),
+    )
+    .await
+    .unwrap_data();
+    assert_eq!(token["token_type"], "bearer", "{token}");
+    assert!(token["access_token"].as_str().is_some(), "{token}");
+
+    // Signature counters that don't increase indicate a cloned authenticator
+    webauthn_login(
+        &authenticator,
+        jane_id,
+        Some("jane@acme.org"),
+        Some("jane-secret"),
+    )
+    .await
+    .expect_request_error("Unauthorized");
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"authentication.webauthn.passwordless": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    authenticator.sign_count = 2;
+    let token = webauthn_login(&authenticator, jane_id, None, None)
+        .await
+        .unwrap_data();
+    assert!(token["access_token"].as_str().is_some(), "{token}");
+    let credentials = jane_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org/mfa/webauthn")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(credentials[0]["signCount"], 2, "{credentials}");
+    assert!(
+        credentials[0]["lastUsed"].as_u64().is_some(),
+        "{credentials}"
+    );
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({"authentication.webauthn.passwordless": null}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Credentials are renamed by their owner and revoked by administrators
+    jane_api
+        .patch::<()>(
+            &format!("/api/principal/jane@acme.org/mfa/webauthn/{credential_id}"),
+            &json!({"nickname": "Work laptop"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>(&format!(
+            "/api/principal/jane@acme.org/mfa/webauthn/{credential_id}"
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>(&format!(
+            "/api/principal/jane@acme.org/mfa/webauthn/{credential_id}"
+        ))
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    authenticator.sign_count = 3;
+    webauthn_login(
+        &authenticator,
+        jane_id,
+        Some("jane@acme.org"),
+        Some("jane-secret"),
+    )
+    .await
+    .expect_request_error("Unauthorized");
+
+    // Recovery codes are shown once to their owner and can be used only once
+    let codes = jane_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/recovery-codes",
+            &json!({}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let codes = codes["codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(codes.len(), 10);
+    assert_eq!(
+        protocol_login(
+            "imap",
+            "jane@acme.org",
+            &format!("jane-secret${}", codes[0])
+        )
+        .await,
+        Ok(())
+    );
+    assert!(
+        protocol_login(
+            "imap",
+            "jane@acme.org",
+            &format!("jane-secret${}", codes[0])
+        )
+        .await
+        .is_err()
+    );
+    assert!(
+        protocol_login(
+            "imap",
+            "jane@acme.org",
+            &format!("wrong-secret${}", codes[1])
+        )
+        .await
+        .is_err()
+    );
+    assert_eq!(
+        protocol_login(
+            "pop3",
+            "jane@acme.org",
+            &format!("jane-secret${}", codes[1].replace('-', "").to_uppercase())
+        )
+        .await,
+        Ok(())
+    );
+    let factors = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org/mfa")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(factors[0]["type"], "recoveryCodes", "{factors}");
+    assert_eq!(factors[0]["remaining"], 8, "{factors}");
+
+    // Administrators may replace the codes without seeing them
+    let regenerated = tenant_api
+        .post::<serde_json::Value>(
+            "/api/principal/jane@acme.org/mfa/recovery-codes",
+            &json!({}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        regenerated["codes"],
+        serde_json::Value::Null,
+        "{regenerated}"
+    );
+    assert_eq!(regenerated["remaining"], 10, "{regenerated}");
+    assert!(
+        protocol_login(
+            "imap",
+            "jane@acme.org",
+            &format!("jane-secret${}", codes[2])
+        )
+        .await
+        .is_err()
+    );
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org/mfa/recovery-codes")
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org/mfa/recovery-codes")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+}
+
+async fn account_forwarding(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let jane_id = params
+        .server
+        .store()
+        .get_principal_id("jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap();
+    let client = jane_client().await;
+    // Maintenance mode turns away the organization's users and defers its mail
+    tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/maintenance", &json!({}))
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    api.post::<serde_json::Value>(
+        "/api/organization/acme/maintenance",
+        &json!({"endsAt": "2000-01-01T00:00:00Z"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid end time");
+    api.post::<serde_json::Value>(
+        "/api/organization/acme/maintenance",
+        &json!({"message": "Moving\r\n250 OK"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid message");
+    let maintenance = api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/maintenance",
+            &json!({"message": "Migrating mailboxes", "endsAt": "2100-01-01T00:00:00Z"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        maintenance["message"], "Migrating mailboxes",
+        "{maintenance}"
+    );
+    assert_eq!(maintenance["endsAt"], 4102444800u64, "{maintenance}");
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["maintenance"], maintenance, "{organization}");
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .expect_request_error("Migrating mailboxes");
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.mail_from("bill@remote.org", 2).await;
+    let response = lmtp.rcpt_to("jane@acme.org", 4).await;
+    assert!(
+        response
+            .iter()
+            .any(|line| line.contains("Migrating mailboxes")),
+        "{response:?}"
+    );
+    lmtp.rcpt_to("jdoe@example.com", 2).await;
+    lmtp.quit().await;
+    api.delete::<serde_json::Value>("/api/organization/acme/maintenance")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let maintenance = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/maintenance")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(maintenance, serde_json::Value::Null);
+
+    // Administrators can forward the messages of an account on its behalf
+    tenant_api
+        .get::<serde_json::Value>("/api/principal/jdoe@example.com/forwarding")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/principal/jane@acme.org/forwarding",
+            &json!({"addresses": ["bill@remote.org"], "expires": 1}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Expiry date must be in the future");
+    let policy = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/forwarding",
+            &json!({"allowExternal": false}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(policy["allowExternal"], json!(false));
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/principal/jane@acme.org/forwarding",
+            &json!({"addresses": ["bill@remote.org"]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("External forwarding is not allowed");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/forwarding",
+            &json!({"allowExternal": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let rule = tenant_api
+        .put::<serde_json::Value>(
+            "/api/principal/jane@acme.org/forwarding",
+            &json!({"addresses": [" Bill@Remote.org "]}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(rule["addresses"], json!(["bill@remote.org"]));
+    assert_eq!(rule["keepCopy"], json!(false));
+    let rule = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org/forwarding")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(rule["addresses"], json!(["bill@remote.org"]));
+
+    // Forwarded messages are not delivered to the account unless a copy is kept
+    let num_messages = client
+        .email_query(None::<email::query::Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids()
+        .len();
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    smtp_settings.lock().do_stop = true;
+    SmtpConnection::connect()
+        .await
+        .ingest(
+            "jane_smith@remote.org",
+            &["jane@acme.org"],
+            concat!(
+                "From: jane_smith@remote.org\r\n",
+                "To: jane@acme.org\r\n",
+                "Subject: Cover sheets\r\n",
+                "\r\n",
+                "Please remember the new cover sheets.",
+            ),
+        )
+        .await;
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(message.rcpt_to, vec!["<bill@remote.org>".to_string()]);
+    assert_eq!(
+        client
+            .email_query(None::<email::query::Filter>, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .take_ids()
+            .len(),
+        num_messages
+    );
+
+    // Expired rules are removed by the housekeeper
+    params
+        .server
+        .update_forwarding_rule(
+            jane_id,
+            ForwardingRule {
+                addresses: vec!["bill@remote.org".to_string()],
+                keep_copy: true,
+                expires: Some(1),
+            }
+            .into(),
+        )
+        .await
+        .unwrap();
+    params
+        .server
+        .purge_expired_forwarding_rules()
+        .await
+        .unwrap();
+    assert!(params.server.forwarding_rule(jane_id).is_none());
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/principal/jane@acme.org/forwarding",
+            &json!({"addresses": ["bill@remote.org"], "keepCopy": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org/forwarding")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        tenant_api
+            .get::<serde_json::Value>("/api/principal/jane@acme.org/forwarding")
+            .await
+            .unwrap()
+            .unwrap_data()
+            .is_null()
+    );
+}
+
+async fn shared_mailboxes(api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let jane_api = ManagementApi::new(8899, "jane@acme.org", "jane-secret");
+    // Shared mailboxes are opened by the members of their access list
+    tenant_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "mary@acme.org",
+                "secrets": ["mary-secret"],
+                "emails": ["mary@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .post::<u32>(
+            "/api/organization/acme/shared-mailboxes",
+            &json!({
+                "name": "support@acme.org",
+                "access": [{"name": "bill@remote.org", "rights": "full"}],
+            }),
+        )
+        .await
+        .unwrap()
+        .expect_error("not an account of this organization");
+    let support_id = tenant_api
+        .post::<u32>(
+            "/api/organization/acme/shared-mailboxes",
+            &json!({
+                "name": "support@acme.org",
+                "quota": 1024 * 1024,
+                "access": [{"name": "jane@acme.org", "rights": "full"}],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let shared = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/shared-mailboxes/support@acme.org/access",
+            &json!([
+                {"name": "jane@acme.org", "rights": "full"},
+                {"name": "mary@acme.org", "rights": "read-write"}
+            ]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(shared["emails"], json!(["support@acme.org"]), "{shared}");
+    assert_eq!(shared["access"][1]["rights"], "read-write", "{shared}");
+    assert!(
+        Client::new()
+            .credentials(Credentials::basic("support@acme.org", ""))
+            .accept_invalid_certs(true)
+            .follow_redirects(["127.0.0.1"])
+            .connect("https://127.0.0.1:8899")
+            .await
+            .is_err()
+    );
+
+    // Flag changes made by one member are seen by the others
+    let support_account_id = Id::new(support_id as u64).to_string();
+    let mut shared_clients = Vec::with_capacity(2);
+    for (name, secret) in [
+        ("jane@acme.org", "jane-secret"),
+        ("mary@acme.org", "mary-secret"),
+    ] {
+        let mut shared_client = Client::new()
+            .credentials(Credentials::basic(name, secret))
+            .timeout(Duration::from_secs(3600))
+            .accept_invalid_certs(true)
+            .follow_redirects(["127.0.0.1"])
+            .connect("https://127.0.0.1:8899")
+            .await
+            .unwrap();
+        shared_client.set_default_account_id(&support_account_id);
+        shared_clients.push(shared_client);
+    }
+    let (jane_shared, mary_shared) = (&shared_clients[0], &shared_clients[1]);
+    let support_inbox_id = jane_shared
+        .mailbox_query(
+            mailbox::query::Filter::role(Role::Inbox).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+    let shared_message_id = jane_shared
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Printer on fire\r\n\r\nPlease help.".to_vec(),
+            [&support_inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    jane_shared
+        .email_set_keyword(&shared_message_id, "$flagged", true)
+        .await
+        .unwrap();
+    let email = mary_shared
+        .email_get(&shared_message_id, [email::Property::Keywords].into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email.keywords(), vec!["$flagged"]);
+    mary_shared
+        .email_set_keyword(&shared_message_id, "$flagged", false)
+        .await
+        .unwrap();
+    mary_shared
+        .email_set_keyword(&shared_message_id, "$seen", true)
+        .await
+        .unwrap();
+    let email = jane_shared
+        .email_get(&shared_message_id, [email::Property::Keywords].into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email.keywords(), vec!["$seen"]);
+
+    // Only members with full rights can manage the folders
+    assert!(
+        mary_shared
+            .mailbox_create("Escalations", Some(&support_inbox_id), Role::None)
+            .await
+            .is_err()
+    );
+    jane_shared
+        .mailbox_create("Escalations", Some(&support_inbox_id), Role::None)
+        .await
+        .unwrap();
+
+    // Messages count towards the quota of the shared mailbox
+    let shared = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/shared-mailboxes/support@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(shared["usedQuota"].as_u64().unwrap() > 0, "{shared}");
+    let organization = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organization["sharedMailboxes"]["count"], 1,
+        "{organization}"
+    );
+    assert_eq!(
+        organization["sharedMailboxes"]["usedQuota"], shared["usedQuota"],
+        "{organization}"
+    );
+    let usage = &organization["usage"];
+    assert_eq!(
+        usage["sharedMailboxes"], shared["usedQuota"],
+        "{organization}"
+    );
+    assert_eq!(
+        usage["shared"].as_u64().unwrap(),
+        usage["sharedMailboxes"].as_u64().unwrap() + usage["groups"].as_u64().unwrap(),
+        "{organization}"
+    );
+    assert!(usage["personal"].as_u64().is_some(), "{organization}");
+    let organizations = tenant_api
+        .get::<OrganizationList>("/api/organization?fields=name,personalUsedQuota,sharedUsedQuota")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        [json!({
+            "name": "acme",
+            "personalUsedQuota": usage["personal"],
+            "sharedUsedQuota": usage["shared"],
+        })]
+    );
+
+    // Messages and storage are rolled up into the domain of the primary address
+    let domain_usage = |domain: &'static str| {
+        let tenant_api = &tenant_api;
+        async move {
+            tenant_api
+                .get::<serde_json::Value>(&format!("/api/domain/{domain}/usage"))
+                .await
+                .unwrap()
+                .unwrap_data()
+        }
+    };
+    let acme_usage = domain_usage("acme.org").await;
+    assert!(
+        acme_usage["messages"].as_u64().unwrap() >= 1,
+        "{acme_usage}"
+    );
+    assert!(
+        acme_usage["bytes"].as_u64().unwrap() >= shared["usedQuota"].as_u64().unwrap(),
+        "{acme_usage}"
+    );
+    assert_eq!(
+        organization["domains"],
+        json!([{
+            "name": "acme.org",
+            "messages": acme_usage["messages"],
+            "bytes": acme_usage["bytes"],
+        }]),
+        "{organization}"
+    );
+    jane_shared
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Still on fire\r\n\r\nPlease hurry.".to_vec(),
+            [&support_inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+    let shared_used = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/shared-mailboxes/support@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data()["usedQuota"]
+        .as_i64()
+        .unwrap();
+    let shared_messages = jane_shared
+        .email_query(None::<email::query::Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids()
+        .len() as i64;
+    assert_eq!(shared_messages, 2);
+    let updated_usage = domain_usage("acme.org").await;
+    assert_eq!(
+        updated_usage["messages"].as_i64().unwrap(),
+        acme_usage["messages"].as_i64().unwrap() + 1,
+        "{updated_usage}"
+    );
+    assert_eq!(
+        updated_usage["bytes"].as_i64().unwrap() - acme_usage["bytes"].as_i64().unwrap(),
+        shared_used - shared["usedQuota"].as_i64().unwrap(),
+        "{updated_usage}"
+    );
+    tenant_api
+        .get::<serde_json::Value>("/api/domain/acme-corp.org/usage")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Appends and copies are checked against the quota of the shared mailbox
+    // before the aggregate of the organization
+    tenant_api
+        .patch::<()>(
+            "/api/principal/support@acme.org",
+            &json!([{"action": "set", "field": "quota", "value": shared_used + 16}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let message = "From: bill@remote.org\r\nSubject: Fire spreading\r\n\r\nThe floor is on fire.";
+    let mut imap = ImapConnection::connect(b"_x ").await;
+    imap.send("LOGIN \"jane@acme.org\" \"jane-secret\"").await;
+    let line = imap.read(Type::Tagged).await.pop().unwrap();
+    assert!(line.starts_with("_x OK"), "{line}");
+    let mut uid = String::new();
+    for (mailbox, result) in [
+        ("Shared Folders/support@acme.org/INBOX", "_x NO [OVERQUOTA]"),
+        ("INBOX", "_x OK [APPENDUID "),
+    ] {
+        imap.send(&format!(
+            "APPEND \"{mailbox}\" {{{}+}}\r\n{message}",
+            message.len()
+        ))
+        .await;
+        let line = imap.read(Type::Tagged).await.pop().unwrap();
+        assert!(line.starts_with(result), "{line}");
+        uid = line
+            .split([' ', ']'])
+            .nth(4)
+            .unwrap_or_default()
+            .to_string();
+    }
+    imap.send("SELECT INBOX").await;
+    let line = imap.read(Type::Tagged).await.pop().unwrap();
+    assert!(line.starts_with("_x OK"), "{line}");
+    imap.send(&format!(
+        "UID COPY {uid} \"Shared Folders/support@acme.org/INBOX\""
+    ))
+    .await;
+    let line = imap.read(Type::Tagged).await.pop().unwrap();
+    assert!(line.starts_with("_x NO [OVERQUOTA]"), "{line}");
+    imap.send(&format!("UID STORE {uid} +FLAGS (\\Deleted)"))
+        .await;
+    imap.read(Type::Tagged).await;
+    imap.send(&format!("UID EXPUNGE {uid}")).await;
+    let line = imap.read(Type::Tagged).await.pop().unwrap();
+    assert!(line.starts_with("_x OK"), "{line}");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/support@acme.org",
+            &json!([{"action": "set", "field": "quota", "value": 1024 * 1024}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Usage follows the account when its primary address moves to another domain
+    api.post::<u32>(
+        "/api/principal",
+        &json!({"type": "domain", "name": "acme-help.org", "tenant": "acme"}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    tenant_api
+        .patch::<()>(
+            "/api/principal/support@acme.org",
+            &json!([{
+                "action": "set",
+                "field": "emails",
+                "value": ["support@acme-help.org"],
+            }]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        domain_usage("acme-help.org").await,
+        json!({"messages": shared_messages, "bytes": shared_used})
+    );
+    assert_eq!(
+        domain_usage("acme.org").await,
+        json!({
+            "messages": updated_usage["messages"].as_i64().unwrap() - shared_messages,
+            "bytes": updated_usage["bytes"].as_i64().unwrap() - shared_used,
+        })
+    );
+
+    // Per-domain numbers are included in the exports
+    let organizations = tenant_api
+        .get::<OrganizationList>("/api/organization?fields=name,domainUsage")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items[0]["domainUsage"],
+        json!([
+            {
+                "name": "acme-help.org",
+                "messages": shared_messages,
+                "bytes": shared_used,
+            },
+            {
+                "name": "acme.org",
+                "messages": updated_usage["messages"].as_i64().unwrap() - shared_messages,
+                "bytes": updated_usage["bytes"].as_i64().unwrap() - shared_used,
+            },
+        ]),
+        "{:?}",
+        organizations.items
+    );
+    let csv = tenant_api
+        .get_raw("/api/organization?fields=name,domainUsage&format=csv")
+        .await
+        .unwrap();
+    let mut lines = csv.split("\r\n");
+    assert_eq!(lines.next(), Some("name,domainUsage"));
+    let row = lines.next().unwrap();
+    assert!(row.starts_with("acme,\"[{"), "{csv}");
+    assert!(row.contains("\"\"acme-help.org\"\""), "{csv}");
+    assert!(
+        row.contains(&format!("\"\"bytes\"\":{shared_used}")),
+        "{csv}"
+    );
+
+    // Per-mailbox statistics are available to tenant admins
+    let stats = tenant_api
+        .get::<serde_json::Value>("/api/principal/support@acme.org/mailbox-stats?sort=size")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let inbox = &stats["items"][0];
+    assert_eq!(inbox["role"], "inbox", "{stats}");
+    assert_eq!(inbox["messages"], 2, "{stats}");
+    assert_eq!(inbox["unseen"], 1, "{stats}");
+    assert_eq!(inbox["size"], shared_used, "{stats}");
+    assert!(inbox["largest"].as_i64().unwrap() < shared_used, "{stats}");
+    assert!(
+        inbox["oldestReceivedAt"].as_u64().unwrap() <= inbox["newestReceivedAt"].as_u64().unwrap(),
+        "{stats}"
+    );
+    assert!(
+        stats["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|mailbox| mailbox["name"] == "Escalations" && mailbox["messages"] == 0),
+        "{stats}"
+    );
+    assert_eq!(stats["total"]["messages"], 2, "{stats}");
+    assert_eq!(stats["total"]["size"], shared_used, "{stats}");
+    let stats = tenant_api
+        .get::<serde_json::Value>("/api/principal/support@acme.org/mailbox-stats?threshold=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(stats["items"].as_array().unwrap().len(), 1, "{stats}");
+    assert_eq!(stats["items"][0]["role"], "inbox", "{stats}");
+    assert_eq!(stats["total"]["messages"], 2, "{stats}");
+    let stats = tenant_api
+        .get::<serde_json::Value>("/api/principal/support@acme.org/mailbox-stats?threshold=2")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        stats,
+        json!({
+            "items": [],
+            "total": {
+                "messages": 0,
+                "unseen": 0,
+                "size": 0,
+                "largest": 0,
+                "oldestReceivedAt": null,
+                "newestReceivedAt": null,
+            },
+        })
+    );
+    tenant_api
+        .get::<serde_json::Value>("/api/principal/support@acme.org/mailbox-stats?sort=name")
+        .await
+        .unwrap()
+        .expect_error("Invalid sort");
+    jane_api
+        .get::<serde_json::Value>("/api/principal/support@acme.org/mailbox-stats")
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+
+    // The largest folders of the organization are listed in its usage
+    let usage = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/usage?limit=1")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        usage["domains"], organizations.items[0]["domainUsage"],
+        "{usage}"
+    );
+    let top_folders = usage["topFolders"].as_array().unwrap();
+    assert_eq!(top_folders.len(), 1, "{usage}");
+    assert!(
+        top_folders[0]["size"].as_i64().unwrap() >= shared_used,
+        "{usage}"
+    );
+    let usage = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/usage")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        usage["topFolders"].as_array().unwrap().contains(&json!({
+            "account": "support@acme.org",
+            "path": "INBOX",
+            "messages": 2,
+            "size": shared_used,
+        })),
+        "{usage}"
+    );
+
+    // Removing a member revokes its access
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/shared-mailboxes/support@acme.org/access",
+            &json!([{"name": "jane@acme.org", "rights": "full"}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        mary_shared
+            .email_get(&shared_message_id, None::<Vec<_>>)
+            .await
+            .is_err()
+    );
+    for name in ["support@acme.org", "mary@acme.org"] {
+        tenant_api
+            .delete::<()>(&format!("/api/principal/{name}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    assert_eq!(
+        domain_usage("acme-help.org").await,
+        json!({"messages": 0, "bytes": 0})
+    );
+    api.delete::<()>("/api/principal/acme-help.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        tenant_api
+            .get::<serde_json::Value>("/api/organization/acme/shared-mailboxes")
+            .await
+            .unwrap()
+            .unwrap_data()["items"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+}
+
+async fn send_only_credentials() {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Send-only credentials are locked to domains of the organization
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders",
+            &json!({"name": "ci-builds", "senderAddress": "@example.com"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders",
+            &json!({"name": "ci-builds", "senderAddress": "@acme.org", "sendRate": "0/1h"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid sender restriction");
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders",
+            &json!({"name": "block", "senderAddress": "@acme.org"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid name");
+    let sender = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders",
+            &json!({
+                "name": "ci-builds",
+                "senderAddress": "@acme.org",
+                "sendRate": "3/1h",
+                "description": "Build notifications",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let secret = sender["secret"].as_str().unwrap().to_string();
+    assert_eq!(secret.len(), 32, "{sender}");
+
+    // They can only submit mail, and have no mailbox
+    for protocol in ["imap", "pop3", "jmap", "dav"] {
+        assert!(
+            protocol_login(protocol, "ci-builds", &secret)
+                .await
+                .is_err(),
+            "{protocol}"
+        );
+    }
+    let mut smtp = SmtpConnection::connect().await;
+    smtp.send(&format!(
+        "AUTH PLAIN {}",
+        STANDARD.encode(format!("\0ci-builds\0{secret}"))
+    ))
+    .await;
+    smtp.read(1, 2).await;
+
+    // Envelope and header senders outside the pattern are rejected
+    smtp.mail_from("ceo@example.com", 5)
+        .await
+        .assert_contains("5.7.1");
+    smtp.mail_from("ci@acme.org", 2).await;
+    smtp.rcpt_to("jane@acme.org", 2).await;
+    smtp.data(3).await;
+    smtp.data_bytes(
+        "From: ceo@example.com\r\nTo: jane@acme.org\r\nSubject: build\r\n\r\nok\r\n",
+        1,
+        5,
+    )
+    .await
+    .assert_contains("From header not allowed");
+
+    // Each credential has its own message rate
+    for sender in ["builds@acme.org", "ci@acme.org"] {
+        smtp.mail_from(sender, 2).await;
+        smtp.rset().await;
+    }
+    smtp.mail_from("ci@acme.org", 4).await;
+    smtp.quit().await;
+
+    let senders = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/senders")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(senders["total"], 1, "{senders}");
+    let item = &senders["items"][0];
+    assert_eq!(item["name"], "ci-builds", "{senders}");
+    assert_eq!(item["senderAddress"], "@acme.org", "{senders}");
+    assert_eq!(item["sendRate"], "3/1h", "{senders}");
+    assert!(item["lastUsed"].as_u64().is_some(), "{senders}");
+
+    // Rotating the secret invalidates the previous one
+    let rotated = tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/acme/senders/ci-builds/rotate",
+            &json!({}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let new_secret = rotated["secret"].as_str().unwrap();
+    assert_ne!(new_secret, secret);
+    assert!(
+        protocol_login("submission", "ci-builds", &secret)
+            .await
+            .is_err()
+    );
+    assert_eq!(
+        protocol_login("submission", "ci-builds", new_secret).await,
+        Ok(())
+    );
+
+    // Other accounts are not listed or managed as senders
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/senders/jane@acme.org")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Revoking a sender deletes it
+    tenant_api
+        .delete::<()>("/api/organization/acme/senders/ci-builds")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        protocol_login("submission", "ci-builds", new_secret)
+            .await
+            .is_err()
+    );
+    assert!(
+        tenant_api
+            .get::<serde_json::Value>("/api/organization/acme/senders")
+            .await
+            .unwrap()
+            .unwrap_data()["items"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+
+    // Journaling targets and directions are validated
+    for (settings, error) in [
+        (
+            json!({"target": {"type": "smtp", "address": "journal"}}),
+            "Invalid journal address",
+        ),
+        (
+            json!({"target": {"type": "bucket", "store": "unknown"}}),
+            "Invalid blob store",
+        ),
+        (
+            json!({"target": {"type": "bucket", "store": "tenant-blobs"}}),
+            "Invalid blob store",
+        ),
+        (
+            json!({"target": {"type": "bucket", "store": "tenant-blobs", "keyPrefix": "a/../b"}}),
+            "Invalid journal key prefix",
+        ),
+        (
+            json!({"target": {"type": "bucket", "store": "tenant-blobs", "keyPrefix": "/etc"}}),
+            "Invalid journal key prefix",
+        ),
+        (
+            json!({
+                "target": {"type": "smtp", "address": "journal@remote.org"},
+                "inbound": false,
+                "outbound": false,
+                "internal": false,
+            }),
+            "At least one direction",
+        ),
+    ] {
+        tenant_api
+            .put::<serde_json::Value>("/api/organization/acme/journaling", &settings)
+            .await
+            .unwrap()
+            .expect_error(error);
+    }
+    let settings = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/journaling",
+            &json!({
+                "target": {"type": "smtp", "address": "Journal@Remote.org"},
+                "outbound": false,
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        settings["target"]["address"], "journal@remote.org",
+        "{settings}"
+    );
+    assert_eq!(settings["inbound"], true, "{settings}");
+
+    // Inbound messages are sent to the journal wrapped in an envelope
+    tenant_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "dwight@acme.org",
+                "secrets": ["dwight-secret"],
+                "emails": ["dwight@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    smtp_settings.lock().do_stop = true;
+    SmtpConnection::connect()
+        .await
+        .ingest(
+            "bill@remote.org",
+            &["dwight@acme.org"],
+            concat!(
+                "From: bill@remote.org\r\n",
+                "To: dwight@acme.org\r\n",
+                "Subject: Beet farm\r\n",
+                "\r\n",
+                "Bears. Beets. Battlestar Galactica.",
+            ),
+        )
+        .await;
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(message.mail_from, "<>");
+    assert_eq!(message.rcpt_to, vec!["<journal@remote.org>".to_string()]);
+    let envelope = mail_parser::MessageParser::new()
+        .parse(message.message.as_bytes())
+        .unwrap();
+    let report = envelope.body_text(0).unwrap();
+    for text in [
+        "Sender: <bill@remote.org>",
+        "Direction: inbound",
+        "Recipient: <dwight@acme.org>",
+    ] {
+        assert!(report.contains(text), "{report}");
+    }
+    assert_eq!(
+        envelope
+            .attachment(0)
+            .and_then(|part| part.message())
+            .and_then(|message| message.subject()),
+        Some("Beet farm")
+    );
+
+    // Delivery status and backlog are reported with the settings
+    let journaling = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/journaling")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let status = &journaling["status"];
+    assert_eq!(status["enabled"], true, "{journaling}");
+    assert_eq!(status["backlog"]["messages"], 0, "{journaling}");
+    assert!(status["lastDeliveredAt"].is_u64(), "{journaling}");
+    assert!(status["lastFailure"].is_null(), "{journaling}");
+    tenant_api
+        .delete::<()>("/api/organization/acme/journaling")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let journaling = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/journaling")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(journaling["settings"].is_null(), "{journaling}");
+    assert_eq!(journaling["status"]["enabled"], false, "{journaling}");
+    tenant_api
+        .delete::<()>("/api/principal/dwight@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+}
+
+async fn resources_and_collections(params: &JMAPTest) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Rooms answer booking requests on their own
+    tenant_api
+        .post::<u32>(
+            "/api/organization/acme/resources",
+            &json!({
+                "name": "projector@acme.org",
+                "kind": "equipment",
+                "bookingPolicy": "require-approval",
+            }),
+        )
+        .await
+        .unwrap()
+        .expect_error("need an approver");
+    let room_id = tenant_api
+        .post::<u32>(
+            "/api/organization/acme/resources",
+            &json!({
+                "name": "boardroom@acme.org",
+                "kind": "room",
+                "capacity": 12,
+                "location": "Building A, 2nd floor",
+                "bookingPolicy": "auto-accept",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let projector_id = tenant_api
+        .post::<u32>(
+            "/api/organization/acme/resources",
+            &json!({
+                "name": "projector@acme.org",
+                "kind": "equipment",
+                "bookingPolicy": "require-approval",
+                "approver": "jane@acme.org",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let resources = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/resources")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(resources["total"], 2, "{resources}");
+    assert_eq!(resources["items"][0]["capacity"], 12, "{resources}");
+    assert_eq!(
+        resources["items"][1]["approver"], "jane@acme.org",
+        "{resources}"
+    );
+    assert!(params.server.bookable_resource(room_id).is_some());
+
+    // Free slots are accepted, overlapping bookings are declined
+    let room = params.server.get_access_token(room_id).await.unwrap();
+    for (uid, start, end, part_stat) in [
+        (
+            "standup",
+            "20300101T090000Z",
+            "20300101T100000Z",
+            ICalendarParticipationStatus::Accepted,
+        ),
+        (
+            "review",
+            "20300101T093000Z",
+            "20300101T103000Z",
+            ICalendarParticipationStatus::Declined,
+        ),
+        (
+            "planning",
+            "20300101T100000Z",
+            "20300101T110000Z",
+            ICalendarParticipationStatus::Accepted,
+        ),
+    ] {
+        let reply = book_resource(&params.server, &room, "boardroom@acme.org", uid, start, end)
+            .await
+            .unwrap_or_else(|| panic!("No reply for {uid}"));
+        assert_eq!(reply.from, "boardroom@acme.org");
+        assert_eq!(reply.to, vec!["jane@acme.org".to_string()]);
+        match reply.summary {
+            ItipSummary::Rsvp {
+                part_stat: reply, ..
+            } => assert_eq!(reply, part_stat, "{uid}"),
+            summary => panic!("Unexpected summary for {uid}: {summary:?}"),
+        }
+    }
+
+    // Resources that require approval forward requests to the approver
+    let projector = params.server.get_access_token(projector_id).await.unwrap();
+    for uid in ["demo", "training"] {
+        let request = book_resource(
+            &params.server,
+            &projector,
+            "projector@acme.org",
+            uid,
+            "20300102T090000Z",
+            "20300102T100000Z",
+        )
+        .await
+        .unwrap();
+        assert_eq!(request.from, "projector@acme.org");
+        assert_eq!(request.to, vec!["jane@acme.org".to_string()]);
+        assert!(matches!(request.summary, ItipSummary::Invite(_)));
+    }
+    let bookings = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/resources/projector@acme.org/bookings")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(bookings["total"], 2, "{bookings}");
+    assert_eq!(
+        bookings["items"][0]["partStat"], "NEEDS-ACTION",
+        "{bookings}"
+    );
+    tenant_api
+        .post::<()>(
+            "/api/organization/acme/resources/projector@acme.org/bookings/demo",
+            &json!({"accept": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .post::<()>(
+            "/api/organization/acme/resources/projector@acme.org/bookings/training",
+            &json!({"accept": true}),
+        )
+        .await
+        .unwrap()
+        .expect_error("already booked");
+    tenant_api
+        .post::<()>(
+            "/api/organization/acme/resources/projector@acme.org/bookings/training",
+            &json!({"accept": false}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let bookings = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/resources/projector@acme.org/bookings")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let part_stats = bookings["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|booking| {
+            (
+                booking["uid"].as_str().unwrap().to_string(),
+                booking["partStat"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect::<AHashMap<_, _>>();
+    assert_eq!(part_stats["demo"], "ACCEPTED");
+    assert_eq!(part_stats["training"], "DECLINED");
+
+    // Conflicts are declined without asking the approver
+    let reply = book_resource(
+        &params.server,
+        &projector,
+        "projector@acme.org",
+        "workshop",
+        "20300102T093000Z",
+        "20300102T110000Z",
+    )
+    .await
+    .unwrap();
+    assert_eq!(reply.to, vec!["jane@acme.org".to_string()]);
+    assert!(matches!(
+        reply.summary,
+        ItipSummary::Rsvp {
+            part_stat: ICalendarParticipationStatus::Declined,
+            ..
+        }
+    ));
+
+    // Resources are edited and removed along with their account
+    let resource = tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/resources/projector@acme.org",
+            &json!({
+                "kind": "equipment",
+                "location": "Storage room",
+                "bookingPolicy": "auto-accept",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(resource["location"], "Storage room", "{resource}");
+    assert!(resource["approver"].is_null(), "{resource}");
+    for name in ["boardroom@acme.org", "projector@acme.org"] {
+        tenant_api
+            .delete::<()>(&format!("/api/principal/{name}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    assert!(params.server.bookable_resource(room_id).is_none());
+    assert!(params.server.bookable_resource(projector_id).is_none());
+
+    // New accounts start with the calendars and address books of the tenant
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/collections",
+            &json!({"calendars": [{"name": "work"}, {"name": "work", "shared": true}]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Duplicate collection name");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/collections",
+            &json!({"addressBooks": [{"name": "staff/all"}]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid collection name");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/collections",
+            &json!({
+                "calendars": [
+                    {"name": "work", "displayName": "Work"},
+                    {"name": "holidays", "displayName": "Company holidays", "shared": true},
+                ],
+                "addressBooks": [
+                    {"name": "staff", "displayName": "Staff directory", "shared": true},
+                ],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let collections = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/collections")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(collections["calendars"][1]["shared"], true, "{collections}");
+    assert_eq!(
+        collections["addressBooks"][0]["name"], "staff",
+        "{collections}"
+    );
+    let mike_id = tenant_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "mike@acme.org",
+                "secrets": ["mike-secret"],
+                "emails": ["mike@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let acme_id = params
+        .server
+        .store()
+        .get_principal_id("acme")
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Personal and shared collections are visible over DAV on first login
+    let mike_dav =
+        DummyWebDavClient::new(u32::MAX, "mike@acme.org", "mike-secret", "mike@acme.org");
+    for (path, expected) in [
+        (
+            "/dav/cal/mike%40acme.org/",
+            &[
+                "/dav/cal/mike%40acme.org/default/",
+                "/dav/cal/mike%40acme.org/work/",
+            ][..],
+        ),
+        (
+            "/dav/card/mike%40acme.org/",
+            &["/dav/card/mike%40acme.org/default/"][..],
+        ),
+        ("/dav/cal/acme/", &["/dav/cal/acme/holidays/"][..]),
+        ("/dav/card/acme/", &["/dav/card/acme/staff/"][..]),
+    ] {
+        let response = mike_dav
+            .request_with_headers("PROPFIND", path, [("depth", "1")], "")
+            .await
+            .with_status(StatusCode::MULTI_STATUS);
+        let hrefs = response.hrefs();
+        for href in expected {
+            assert!(hrefs.contains(href), "{path}: {hrefs:?}");
+        }
+    }
+
+    // Shared collections are read-only
+    mike_dav
+        .request(
+            "PUT",
+            "/dav/cal/acme/holidays/party.ics",
+            concat!(
+                "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Acme//EN\r\n",
+                "BEGIN:VEVENT\r\nUID:party\r\nDTSTAMP:20300101T000000Z\r\n",
+                "DTSTART:20301224T180000Z\r\nDTEND:20301224T220000Z\r\n",
+                "SUMMARY:Party\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
+            ),
+        )
+        .await
+        .with_status(StatusCode::FORBIDDEN);
+
+    // The same collections are returned over JMAP
+    for (method, account_id, expected) in [
+        ("Calendar/get", mike_id, "Work"),
+        ("Calendar/get", acme_id, "Company holidays"),
+        (
+            "AddressBook/get",
+            mike_id,
+            "Stalwart Address Book (mike@acme.org)",
+        ),
+        ("AddressBook/get", acme_id, "Staff directory"),
+    ] {
+        let names = jmap_collection_names("mike@acme.org", "mike-secret", method, account_id).await;
+        assert!(
+            names.iter().any(|name| name == expected),
+            "{method} {account_id}: {names:?}"
+        );
+    }
+    tenant_api
+        .put::<serde_json::Value>("/api/organization/acme/collections", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/principal/mike@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+}
+
+async fn message_retention() {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let client = jane_client().await;
+    // Retention policies require a dry run before they are enforced
+    let inbox_id = client
+        .mailbox_query(
+            mailbox::query::Filter::role(Role::Inbox).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+    let old_message_id = client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Old TPS Report\r\n\r\nFrom last year.".to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            Some((Utc::now() - TimeDelta::try_days(400).unwrap_or_default()).timestamp()),
+        )
+        .await
+        .unwrap()
+        .take_id();
+    let policies = json!([
+        {"role": "Trash", "days": 30, "action": "delete"},
+        {"folder": "*", "days": 365, "action": "delete"}
+    ]);
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/retention",
+            &json!({"policies": [{"role": "archive", "days": 30, "action": "archive"}]}),
+        )
+        .await
+        .unwrap()
+        .expect_error("cannot be archived");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/retention",
+            &json!({"policies": policies, "dryRun": false}),
+        )
+        .await
+        .unwrap()
+        .expect_error("Dry run required");
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/retention",
+            &json!({"policies": policies}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let report = tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/retention/run", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(report["dryRun"], true, "{report}");
+    assert_eq!(report["policies"][0]["role"], "trash", "{report}");
+    assert_eq!(report["policies"][0]["affectedMessages"], 0, "{report}");
+    assert_eq!(report["policies"][1]["affectedMessages"], 1, "{report}");
+    assert_eq!(report["policies"][1]["affectedAccounts"], 1, "{report}");
+    assert!(
+        client
+            .email_get(&old_message_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    // Principals on legal hold are skipped
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/retention",
+            &json!({"policies": policies, "legalHold": ["jane@acme.org"], "dryRun": false}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let report = tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/retention/run", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(report["dryRun"], false, "{report}");
+    assert_eq!(report["legalHold"], 1, "{report}");
+    assert_eq!(report["policies"][1]["affectedMessages"], 0, "{report}");
+    assert!(
+        client
+            .email_get(&old_message_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    // Expired messages are deleted once the policies are enforced
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/retention",
+            &json!({"policies": policies, "dryRun": false}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let report = tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/retention/run", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(report["policies"][1]["affectedMessages"], 1, "{report}");
+    assert!(
+        client
+            .email_get(&old_message_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let reports = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/retention/reports")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(reports["total"], 3, "{reports}");
+    let report_id = reports["items"][2]["id"].as_u64().unwrap();
+    let report = tenant_api
+        .get::<serde_json::Value>(&format!(
+            "/api/organization/acme/retention/reports/{report_id}"
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(report["dryRun"], true, "{report}");
+    tenant_api
+        .put::<serde_json::Value>("/api/organization/acme/retention", &json!({"policies": []}))
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Accounts on legal hold retain expunged messages and cannot be deleted
+    tenant_api
+        .patch::<()>(
+            "/api/principal/jane@acme.org",
+            &json!([{"action": "set", "field": "legalHold", "value": true}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let principal = tenant_api
+        .get::<serde_json::Value>("/api/principal/jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(principal["legalHold"], 1, "{principal}");
+    assert_eq!(principal["legalHoldBy"], "acme-admin", "{principal}");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/jane@acme.org",
+            &json!([{"action": "set", "field": "legalHoldBy", "value": "jane@acme.org"}]),
+        )
+        .await
+        .unwrap()
+        .expect_error("Read-only field");
+    let held_message_id = client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Evidence\r\n\r\nKeep this.".to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    client.email_destroy(&held_message_id).await.unwrap();
+    assert!(
+        client
+            .email_get(&held_message_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let holds = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/legal-holds")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(holds["total"], 1, "{holds}");
+    assert_eq!(holds["items"][0]["name"], "jane@acme.org", "{holds}");
+    assert_eq!(holds["items"][0]["setBy"], "acme-admin", "{holds}");
+    assert_eq!(holds["items"][0]["heldMessages"], 1, "{holds}");
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org")
+        .await
+        .unwrap()
+        .expect_error("legal hold");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/jane@acme.org",
+            &json!([{"action": "set", "field": "legalHold", "value": false}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let holds = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/legal-holds")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(holds["total"], 0, "{holds}");
+}
+
+async fn blob_storage(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let acme_id = params
+        .server
+        .store()
+        .get_principal_id("acme")
+        .await
+        .unwrap()
+        .unwrap();
+    let jane_id = params
+        .server
+        .store()
+        .get_principal_id("jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap();
+    let client = jane_client().await;
+    let inbox_id = client
+        .mailbox_query(
+            mailbox::query::Filter::role(Role::Inbox).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+    // Messages can be reindexed per account or per organization
+    let old_email = client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Quarterly TPS Report\r\n\r\nAttached.".to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+    tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/reindex", &json!({}))
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    for path in [
+        "/api/principal/jane@acme.org/reindex",
+        "/api/organization/acme/reindex",
+    ] {
+        let job = api
+            .post::<serde_json::Value>(path, &json!({"concurrency": 2}))
+            .await
+            .unwrap()
+            .unwrap_data();
+        let job_id = job["id"].as_str().unwrap().to_string();
+        let mut job = job;
+        for _ in 0..50 {
+            if job["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            job = api
+                .get::<serde_json::Value>(&format!("{path}/{job_id}"))
+                .await
+                .unwrap()
+                .unwrap_data();
+        }
+        assert_eq!(job["status"], "completed", "{job}");
+        assert_eq!(job["failed"], 0, "{job}");
+        assert_eq!(job["completedAccounts"], job["totalAccounts"], "{job}");
+        assert!(job["indexed"].as_u64().unwrap() >= 1, "{job}");
+    }
+
+    // New blobs are written to the blob store assigned to the organization
+    let org = api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(org["blobStore"], serde_json::Value::Null, "{org}");
+    assert_eq!(org["blobMigration"], serde_json::Value::Null, "{org}");
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme",
+            &json!({"blobStore": "tenant-blobs"}),
+        )
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    api.patch::<serde_json::Value>("/api/organization/acme", &json!({"blobStore": "unknown"}))
+        .await
+        .unwrap()
+        .expect_error("Invalid blob store");
+    let org = api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme",
+            &json!({"blobStore": "tenant-blobs"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(org["blobStore"], "tenant-blobs", "{org}");
+    let new_email = client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: EU TPS Report\r\n\r\nStored in the EU.".to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+    let old_blob = BlobId::from_str(old_email.blob_id().unwrap()).unwrap().hash;
+    let new_blob = BlobId::from_str(new_email.blob_id().unwrap()).unwrap().hash;
+    assert_eq!(blob_location(&params.server, &old_blob).await, "");
+    assert_eq!(
+        blob_location(&params.server, &new_blob).await,
+        "tenant-blobs"
+    );
+
+    // Blobs are only deduplicated within the organization
+    let raw_message = params
+        .server
+        .get_blob(&new_blob, 0..usize::MAX)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_ne!(new_blob, BlobHash::generate(&raw_message));
+    assert_eq!(
+        params
+            .server
+            .account_blob_hash(jane_id, &raw_message)
+            .await
+            .unwrap(),
+        new_blob
+    );
+    assert!(
+        params
+            .server
+            .get_blob(&new_blob, 0..usize::MAX)
+            .await
+            .unwrap()
+            .is_some_and(|blob| blob.ends_with(b"Stored in the EU."))
+    );
+
+    // Existing blobs are moved by the migration job, in both directions
+    for (patch, location) in [
+        (json!({"migrateBlobs": true}), "tenant-blobs"),
+        (json!({"blobStore": null, "migrateBlobs": true}), ""),
+    ] {
+        let mut org = api
+            .patch::<serde_json::Value>("/api/organization/acme", &patch)
+            .await
+            .unwrap()
+            .unwrap_data();
+        for _ in 0..50 {
+            if org["blobMigration"]["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            org = api
+                .get::<serde_json::Value>("/api/organization/acme")
+                .await
+                .unwrap()
+                .unwrap_data();
+        }
+        assert_eq!(org["blobMigration"]["status"], "completed", "{org}");
+        assert_eq!(org["blobMigration"]["failed"], 0, "{org}");
+        assert_eq!(org["blobMigration"]["shared"], 0, "{org}");
+        assert!(
+            org["blobMigration"]["moved"].as_u64().unwrap() >= 1,
+            "{org}"
+        );
+        for blob in [&old_blob, &new_blob] {
+            assert_eq!(blob_location(&params.server, blob).await, location);
+            assert!(
+                params
+                    .server
+                    .get_blob(blob, 0..usize::MAX)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+    }
+    let org = api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(org["blobStore"], serde_json::Value::Null, "{org}");
+
+    // Blobs referenced outside the organization are not moved
+    let acme_ids = params
+        .server
+        .store()
+        .principal_ids(
+            None,
+            params
+                .server
+                .get_access_token(jane_id)
+                .await
+                .unwrap()
+                .tenant
+                .map(|tenant| tenant.id),
+        )
+        .await
+        .unwrap();
+    let (shared_blob, _) = params
+        .server
+        .put_temporary_blob(u32::MAX, b"Shared with the server", 3600)
+        .await
+        .unwrap();
+    let tenant_location = BlobLocation {
+        store_id: "tenant-blobs".to_string(),
+        encryption_key: None,
+    };
+    assert_eq!(
+        params
+            .server
+            .move_blob(
+                &shared_blob,
+                &BlobLocation::default(),
+                &tenant_location,
+                &acme_ids,
+                None
+            )
+            .await
+            .unwrap(),
+        BlobMove::Shared
+    );
+    assert_eq!(blob_location(&params.server, &shared_blob).await, "");
+    for (from, to, location) in [
+        (&BlobLocation::default(), &tenant_location, "tenant-blobs"),
+        (&tenant_location, &BlobLocation::default(), ""),
+    ] {
+        assert_eq!(
+            params
+                .server
+                .move_blob(&new_blob, from, to, &acme_ids, None)
+                .await
+                .unwrap(),
+            BlobMove::Moved
+        );
+        assert_eq!(blob_location(&params.server, &new_blob).await, location);
+    }
+
+    // New blobs are encrypted with the organization's data key
+    let key_dir = std::env::temp_dir().join("acme-encryption-keys");
+    let _ = std::fs::remove_dir_all(&key_dir);
+    std::fs::create_dir_all(&key_dir).unwrap();
+    let key_paths = ["key1", "key2"].map(|name| key_dir.join(name));
+    for (num, path) in key_paths.iter().enumerate() {
+        std::fs::write(path, format!("acme customer key {num}\n")).unwrap();
+    }
+    let key_references = key_paths
+        .each_ref()
+        .map(|path| format!("file:{}", path.display()));
+    tenant_api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/encryption",
+            &json!({"keyReference": key_references[0]}),
+        )
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    api.put::<serde_json::Value>(
+        "/api/organization/acme/encryption",
+        &json!({"keyReference": format!("file:{}", key_dir.join("missing").display())}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid encryption key");
+    let encryption = api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/encryption",
+            &json!({"keyReference": key_references[0]}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(encryption["enabled"], true, "{encryption}");
+    assert_eq!(encryption["keyAvailable"], true, "{encryption}");
+    let secret_email = client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Merger\r\n\r\nTop secret plans.".to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+    let secret_blob_id = secret_email.blob_id().unwrap().to_string();
+    let secret_blob = BlobId::from_str(&secret_blob_id).unwrap().hash;
+    assert_eq!(
+        blob_encryption_key(&params.server, &secret_blob).await,
+        Some(acme_id)
+    );
+    let encrypted_blob = params
+        .server
+        .blob_store()
+        .get_blob(secret_blob.as_slice(), 0..usize::MAX)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        !encrypted_blob
+            .windows(b"Top secret plans.".len())
+            .any(|window| window == b"Top secret plans."),
+    );
+    assert!(
+        client
+            .download(&secret_blob_id)
+            .await
+            .unwrap()
+            .ends_with(b"Top secret plans.")
+    );
+    assert_eq!(
+        params
+            .server
+            .get_blob(&secret_blob, 0..4)
+            .await
+            .unwrap()
+            .unwrap(),
+        b"From"
+    );
+
+    // Rotating the key re-wraps the data key without re-encrypting blobs
+    let encryption = api
+        .put::<serde_json::Value>(
+            "/api/organization/acme/encryption",
+            &json!({"keyReference": key_references[1]}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        encryption["keyReference"], key_references[1],
+        "{encryption}"
+    );
+    assert_eq!(
+        params
+            .server
+            .blob_store()
+            .get_blob(secret_blob.as_slice(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .unwrap(),
+        encrypted_blob
+    );
+    assert!(
+        client
+            .download(&secret_blob_id)
+            .await
+            .unwrap()
+            .ends_with(b"Top secret plans.")
+    );
+
+    // Losing the key fails reads and is reported by the health endpoint
+    std::fs::remove_file(&key_paths[1]).unwrap();
+    params.server.inner.data.data_keys.remove(acme_id);
+    let err = params
+        .server
+        .get_blob(&secret_blob, 0..usize::MAX)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.event_type(),
+        EventType::Store(StoreEvent::EncryptionKeyUnavailable),
+        "{err:?}"
+    );
+    assert!(client.download(&secret_blob_id).await.is_err());
+    let health = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/health")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(health["healthy"], false, "{health}");
+    assert_eq!(health["encryption"]["keyAvailable"], false, "{health}");
+    api.put::<serde_json::Value>(
+        "/api/organization/acme/encryption",
+        &json!({"keyReference": key_references[0]}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Encryption key unavailable");
+    std::fs::write(&key_paths[1], "acme customer key 1\n").unwrap();
+
+    // Disabling encryption keeps existing blobs readable
+    let encryption = api
+        .delete::<serde_json::Value>("/api/organization/acme/encryption")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(encryption["enabled"], false, "{encryption}");
+    assert_eq!(encryption["keyAvailable"], true, "{encryption}");
+    api.delete::<serde_json::Value>("/api/organization/acme/encryption")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    let plain_email = client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Lunch\r\n\r\nPizza on Friday.".to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+    let plain_blob = BlobId::from_str(plain_email.blob_id().unwrap())
+        .unwrap()
+        .hash;
+    assert_eq!(blob_encryption_key(&params.server, &plain_blob).await, None);
+    assert!(
+        client
+            .download(&secret_blob_id)
+            .await
+            .unwrap()
+            .ends_with(b"Top secret plans.")
+    );
+}
+
+async fn organization_backup(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let key_dir = std::env::temp_dir().join("acme-encryption-keys");
+    // Organizations can be backed up and restored
+    let jane_id = params
+        .server
+        .store()
+        .get_principal_id("jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap();
+    let jane_messages = params
+        .server
+        .get_cached_messages(jane_id)
+        .await
+        .unwrap()
+        .emails
+        .items
+        .len();
+    tenant_api
+        .post::<serde_json::Value>("/api/organization/acme/backup", &json!({}))
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    let mut job = api
+        .post::<serde_json::Value>("/api/organization/acme/backup", &json!({"copyBlobs": true}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let job_id = job["id"].as_str().unwrap().to_string();
+    for _ in 0..50 {
+        if job["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        job = api
+            .get::<serde_json::Value>(&format!("/api/organization/acme/backup/{job_id}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    assert_eq!(job["status"], "completed", "{job}");
+    assert_eq!(job["failed"], 0, "{job}");
+    let backup_id = job["backupId"].as_str().unwrap().to_string();
+    let backups = tenant_api
+        .get::<serde_json::Value>("/api/organization/acme/backups")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(backups["total"], 1, "{backups}");
+    assert_eq!(backups["items"][0]["id"], backup_id, "{backups}");
+    assert_eq!(backups["items"][0]["copyBlobs"], true, "{backups}");
+    let messages = backups["items"][0]["messages"].as_u64().unwrap();
+    assert!(messages >= 1, "{backups}");
+    let restore_path = format!("/api/organization/acme/backups/{backup_id}/restore");
+    api.post::<serde_json::Value>(&restore_path, &json!({}))
+        .await
+        .unwrap()
+        .expect_error("Restore conflict");
+    for (request, skipped) in [
+        (json!({"skipExisting": true}), true),
+        (json!({"force": true}), false),
+    ] {
+        let mut job = api
+            .post::<serde_json::Value>(&restore_path, &request)
+            .await
+            .unwrap()
+            .unwrap_data();
+        let job_id = job["id"].as_str().unwrap().to_string();
+        for _ in 0..50 {
+            if job["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            job = api
+                .get::<serde_json::Value>(&format!("/api/organization/acme/backup/{job_id}"))
+                .await
+                .unwrap()
+                .unwrap_data();
+        }
+        assert_eq!(job["status"], "completed", "{job}");
+        assert_eq!(job["failed"], 0, "{job}");
+        assert_eq!(job["skipped"].as_u64().unwrap() > 0, skipped, "{job}");
+        assert_eq!(
+            job["messages"].as_u64().unwrap() == messages,
+            !skipped,
+            "{job}"
+        );
+    }
+    let jane_id = params
+        .server
+        .store()
+        .get_principal_id("jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        params
+            .server
+            .get_cached_messages(jane_id)
+            .await
+            .unwrap()
+            .emails
+            .items
+            .len(),
+        jane_messages
+    );
+
+    std::fs::remove_dir_all(&key_dir).unwrap();
+
+    tenant_api
+        .delete::<()>("/api/organization/acme/sieve/compliance")
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .delete::<()>("/api/principal/jane@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+}
+
+async fn audit_log(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let acme_id = params
+        .server
+        .store()
+        .get_principal_id("acme")
+        .await
+        .unwrap()
+        .unwrap();
+    // Successful management API writes are recorded in the audit log
+    let mut audit_events = None;
+    for _ in 0..50 {
+        let events = api
+            .get::<AuditEvents>(
+                "/api/events?type=http.management-write&target=/api/organization/provision",
+            )
+            .await
+            .unwrap()
+            .unwrap_data();
+        if !events.items.is_empty() {
+            audit_events = Some(events);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let audit_events = audit_events.expect("Audit event was not recorded");
+    assert_eq!(audit_events.items.len(), 1, "{audit_events:?}");
+    assert_eq!(audit_events.items[0].actor.as_deref(), Some("admin"));
+    assert_eq!(
+        audit_events.items[0].target.as_deref(),
+        Some("/api/organization/provision")
+    );
+    assert!(audit_events.cursor.is_none());
+
+    // Tenant administrators only see events from their own tenant
+    let audit_events = tenant_api
+        .get::<AuditEvents>("/api/events?actor=admin")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(audit_events.items.is_empty(), "{audit_events:?}");
+
+    // Organization audit events are filtered by actor, action and target type
+    let audit_store = params
+        .server
+        .core
+        .storage
+        .audit
+        .as_ref()
+        .unwrap()
+        .store
+        .clone();
+    let id_gen = SnowflakeIdGenerator::new();
+    let mut records = Vec::new();
+    for i in 0..30_000u32 {
+        let (typ, target, details) = if i % 2 == 0 {
+            (
+                "directory.principal-updated",
+                format!("user{}@acme.org", i % 100),
+                "individual",
+            )
+        } else {
+            (
+                "http.management-write",
+                "/api/domain/acme.org".to_string(),
+                "PATCH",
+            )
+        };
+        records.push(AuditRecord {
+            id: id_gen.generate(),
+            timestamp: now(),
+            typ: typ.to_string(),
+            actor: Some(format!("helpdesk{}@acme.org", i % 50)),
+            actor_id: Some(900_000 + (i % 50)),
+            tenant_id: Some(if i % 3 == 0 { u32::MAX } else { acme_id }),
+            target: Some(target),
+            remote_ip: None,
+            details: Some(details.to_string()),
+        });
+    }
+    for i in 0..24u32 {
+        records.push(AuditRecord {
+            id: id_gen.generate(),
+            timestamp: now(),
+            typ: if i % 4 == 0 {
+                "directory.principal-deleted"
+            } else {
+                "directory.principal-updated"
+            }
+            .to_string(),
+            actor: Some("auditee@acme.org".to_string()),
+            actor_id: Some(950_000),
+            // Events of other tenants are not returned
+            tenant_id: Some(if i < 20 { acme_id } else { u32::MAX }),
+            target: Some(format!("user{i}@acme.org")),
+            remote_ip: Some("10.0.0.1".to_string()),
+            details: Some(if i % 2 == 0 { "individual" } else { "group" }.to_string()),
+        });
+    }
+    audit_store.write_audit_records(records).await.unwrap();
+
+    for (query, expected) in [
+        ("actor=auditee@acme.org", 20),
+        ("actor=AUDITEE@acme.org&limit=1000", 20),
+        ("actor=950000", 20),
+        (
+            "actor=auditee@acme.org&action=directory.principal-deleted",
+            5,
+        ),
+        ("actor=950000&targetType=group", 10),
+        (
+            "actor=auditee@acme.org&action=directory.principal-updated&targetType=individual",
+            5,
+        ),
+        ("actor=helpdesk7@acme.org&targetType=domain", 400),
+        ("actor=nobody@acme.org", 0),
+    ] {
+        let mut total = 0;
+        let mut cursor = None::<String>;
+        loop {
+            let audit_events = api
+                .get::<AuditEvents>(&format!(
+                    "/api/organization/acme/audit?{query}&limit=100{}",
+                    cursor
+                        .as_deref()
+                        .map(|cursor| format!("&cursor={cursor}"))
+                        .unwrap_or_default()
+                ))
+                .await
+                .unwrap()
+                .unwrap_data();
+            total += audit_events.items.len();
+            cursor = audit_events.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(total, expected, "{query}");
+    }
+    tenant_api
+        .get::<AuditEvents>("/api/organization/acme/audit?action=invalid")
+        .await
+        .unwrap()
+        .expect_error("Invalid action");
+
+    // Filters are resolved with the indexes, reading only matching records
+    for query in [
+        AuditQuery {
+            actor: Some("auditee@acme.org".to_string()),
+            ..Default::default()
+        },
+        AuditQuery {
+            actor_id: Some(950_000),
+            target_type: Some("individual".to_string()),
+            ..Default::default()
+        },
+        AuditQuery {
+            typ: Some(EventType::Directory(trc::DirectoryEvent::PrincipalDeleted)),
+            ..Default::default()
+        },
+        AuditQuery {
+            target_type: Some("domain".to_string()),
+            limit: 50,
+            ..Default::default()
+        },
+    ] {
+        let page = audit_store
+            .query_audit_events(AuditQuery {
+                tenant_id: Some(acme_id),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert!(!page.items.is_empty(), "{query:?}");
+        assert_eq!(page.scanned, page.items.len(), "{query:?}");
+        assert!(
+            page.items
+                .iter()
+                .all(|record| record.tenant_id == Some(acme_id)),
+            "{query:?}"
+        );
+    }
+
+    // Erased actors remain filterable by their tombstone ID
+    assert_eq!(
+        audit_store
+            .scrub_audit_events(&["auditee@acme.org".to_string()], 950_000, "erased-audit")
+            .await
+            .unwrap(),
+        24
+    );
+    for (query, expected) in [
+        ("actor=erased-audit", 20),
+        ("actor=erased-audit&action=directory.principal-deleted", 5),
+        ("actor=auditee@acme.org", 0),
+        ("actor=950000", 0),
+    ] {
+        let audit_events = tenant_api
+            .get::<AuditEvents>(&format!("/api/organization/acme/audit?{query}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+        assert_eq!(audit_events.items.len(), expected, "{query}");
+        assert!(
+            audit_events
+                .items
+                .iter()
+                .all(|event| event.actor.as_deref() == Some("erased-audit")),
+            "{audit_events:?}"
+        );
+    }
+    let page = audit_store
+        .query_audit_events(AuditQuery {
+            tenant_id: Some(acme_id),
+            actor: Some("erased-audit".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(page.items.len(), 20);
+    assert_eq!(page.scanned, 20);
+
+    // Filtered results are exported as CSV
+    let csv = tenant_api
+        .get_raw("/api/organization/acme/audit?actor=erased-audit&targetType=group&export=csv")
+        .await
+        .unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("id,timestamp,type,actor,actorId,target,targetType,remoteIp,details")
+    );
+    let rows = lines.collect::<Vec<_>>();
+    assert_eq!(rows.len(), 10, "{csv}");
+    assert!(
+        rows.iter()
+            .all(|row| row.contains(",erased-audit,,") && row.ends_with(",group,,group")),
+        "{csv}"
+    );
+    let csv = api
+        .get_raw("/api/organization/acme/audit?actor=helpdesk1@acme.org&export=csv")
+        .await
+        .unwrap();
+    assert_eq!(csv.lines().count(), 401);
+    tenant_api
+        .get::<AuditEvents>("/api/organization/initrode/audit")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+}
+
+async fn usage_metering(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Missed metering runs are filled with estimated samples
+    let metering = params.server.core.storage.metering.clone().unwrap();
+    let metered_id = api
+        .post::<u32>(
+            "/api/principal",
+            &json!({"type": "tenant", "name": "metered-org"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let interval = metering.interval.as_secs();
+    let sampled_at = now() - now() % interval;
+    let history = SharedMeteringHistory::default();
+    history.lock().last_sample = Some(sampled_at - 3 * interval);
+    params
+        .server
+        .sample_tenant_metering(&metering, history.clone())
+        .await
+        .unwrap();
+    let samples = metering
+        .store
+        .query_metering_samples(sampled_at - 3 * interval, sampled_at)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|sample| sample.tenant_id == metered_id)
+        .map(|sample| (sample.timestamp, sample.estimated))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        samples,
+        vec![
+            (sampled_at - 2 * interval, true),
+            (sampled_at - interval, true),
+            (sampled_at, true)
+        ]
+    );
+
+    // Runs within the same interval do not add samples
+    params
+        .server
+        .sample_tenant_metering(&metering, history)
+        .await
+        .unwrap();
+    assert_eq!(
+        metering
+            .store
+            .query_metering_samples(sampled_at, sampled_at)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|sample| sample.tenant_id == metered_id)
+            .count(),
+        1
+    );
+
+    // Samples of all nodes are combined into one row per tenant and day
+    let day = 1709251200;
+    let sample = |timestamp: u64, node_id: u64, storage_bytes: u64| MeteringSample {
+        timestamp,
+        tenant_id: metered_id,
+        node_id,
+        estimated: false,
+        active_users: node_id - 99,
+        storage_bytes,
+        messages_sent: 2,
+        messages_received: 3,
+        bytes_sent: 2000,
+        bytes_received: 3000,
+    };
+    metering
+        .store
+        .write_metering_samples(vec![
+            sample(day + 3600, 100, 1000),
+            sample(day + 3600, 101, 1000),
+            sample(day + 7200, 100, 3000),
+            MeteringSample {
+                estimated: true,
+                ..sample(day + 86400 + 3600, 100, 500)
+            },
+        ])
+        .await
+        .unwrap();
+
+    // Rows are keyed by tenant ID and show the current name
+    api.patch::<()>(
+        "/api/principal/metered-org",
+        &json!([{"action": "set", "field": "name", "value": "metered-renamed"}]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let rows = api
+        .get::<serde_json::Value>("/api/metering?from=2024-03-01&to=2024-03-02")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        rows["items"],
+        json!([
+            {
+                "date": "2024-03-01",
+                "tenantId": metered_id,
+                "tenantName": "metered-renamed",
+                "activeUsers": 2,
+                "storagePeak": 3000,
+                "storageAverage": 2000,
+                "messagesSent": 6,
+                "messagesReceived": 9,
+                "bandwidthIn": 9000,
+                "bandwidthOut": 6000,
+                "samples": 2,
+                "estimated": false,
+            },
+            {
+                "date": "2024-03-02",
+                "tenantId": metered_id,
+                "tenantName": "metered-renamed",
+                "activeUsers": 1,
+                "storagePeak": 500,
+                "storageAverage": 500,
+                "messagesSent": 2,
+                "messagesReceived": 3,
+                "bandwidthIn": 3000,
+                "bandwidthOut": 2000,
+                "samples": 1,
+                "estimated": true,
+            },
+        ])
+    );
+
+    // Deleted tenants are still exported, without a name
+    api.delete::<()>("/api/principal/metered-renamed")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let csv = api
+        .get_raw("/api/metering?from=2024-03-02&to=2024-03-02&format=csv")
+        .await
+        .unwrap();
+    assert_eq!(
+        csv,
+        format!(
+            concat!(
+                "date,tenantId,tenantName,activeUsers,storagePeak,storageAverage,",
+                "messagesSent,messagesReceived,bandwidthIn,bandwidthOut,samples,estimated\r\n",
+                "2024-03-02,{},,1,500,500,2,3,3000,2000,1,true\r\n"
+            ),
+            metered_id
+        )
+    );
+    api.get::<serde_json::Value>("/api/metering?from=2024-03-02&to=2024-03-01")
+        .await
+        .unwrap()
+        .expect_error("Invalid range");
+    api.get::<serde_json::Value>("/api/metering?from=2024-13-01")
+        .await
+        .unwrap()
+        .expect_error("Invalid date");
+    tenant_api
+        .get::<serde_json::Value>("/api/metering")
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+
+    // Raw samples older than the retention period are purged
+    metering
+        .store
+        .purge_metering_samples(Duration::from_secs(30 * 86400))
+        .await
+        .unwrap();
+    assert!(
+        metering
+            .store
+            .query_metering_samples(day, day + 2 * 86400)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        !metering
+            .store
+            .query_metering_samples(sampled_at, sampled_at)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+async fn account_data(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Accounts can be erased along with their personal data
+    tenant_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "john@acme.org",
+                "secrets": ["john-secret"],
+                "emails": ["john@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let john_client = Client::new()
+        .credentials(Credentials::basic("john@acme.org", "john-secret"))
+        .timeout(Duration::from_secs(3600))
+        .accept_invalid_certs(true)
+        .follow_redirects(["127.0.0.1"])
+        .connect("https://127.0.0.1:8899")
+        .await
+        .unwrap();
+    let john_inbox_id = john_client
+        .mailbox_query(
+            mailbox::query::Filter::role(Role::Inbox).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+    john_client
+        .email_import(
+            b"From: bill@remote.org\r\nSubject: Medical records\r\n\r\nPrivate.".to_vec(),
+            [&john_inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Users can export their own data
+    let john_api = ManagementApi::new(8899, "john@acme.org", "john-secret");
+    let mut job = john_api
+        .post::<serde_json::Value>("/api/principal/john@acme.org/export", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let job_id = job["id"].as_str().unwrap().to_string();
+    for _ in 0..50 {
+        if job["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        job = john_api
+            .get::<serde_json::Value>(&format!("/api/principal/john@acme.org/export/{job_id}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    assert_eq!(job["status"], "completed", "{job}");
+    assert_eq!(job["archive"]["messages"], 1, "{job}");
+    let download_url = job["archive"]["downloadUrl"].as_str().unwrap().to_string();
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let response = http_client
+        .get(format!("https://127.0.0.1:8899{download_url}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(
+        response.bytes().await.unwrap().to_vec(),
+    ))
+    .unwrap();
+    let message_file = archive
+        .file_names()
+        .find(|name| name.starts_with("mail/Inbox/") && name.ends_with(".eml"))
+        .unwrap()
+        .to_string();
+    let mut message = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name(&message_file).unwrap(), &mut message)
+        .unwrap();
+    assert!(message.contains("Subject: Medical records"), "{message}");
+    let mut manifest = String::new();
+    std::io::Read::read_to_string(
+        &mut archive.by_name("manifest.json").unwrap(),
+        &mut manifest,
+    )
+    .unwrap();
+    let manifest = serde_json::from_str::<serde_json::Value>(&manifest).unwrap();
+    assert_eq!(manifest["account"]["name"], "john@acme.org", "{manifest}");
+    assert_eq!(
+        http_client
+            .get(format!(
+                "https://127.0.0.1:8899{}",
+                download_url.replace("/export/", "/export/9")
+            ))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        404
+    );
+
+    // Tenant administrators can export the accounts of their tenant
+    let job = tenant_api
+        .post::<serde_json::Value>("/api/principal/john@acme.org/export", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(job["principal"], "john@acme.org", "{job}");
+    john_api
+        .post::<serde_json::Value>("/api/principal/acme-admin/export", &json!({}))
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    for _ in 0..50 {
+        let job = tenant_api
+            .get::<serde_json::Value>(&format!(
+                "/api/principal/john@acme.org/export/{}",
+                job["id"].as_str().unwrap()
+            ))
+            .await
+            .unwrap()
+            .unwrap_data();
+        if job["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    // Secondary addresses are pending until confirmed by their owner
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    let pending = john_api
+        .post::<serde_json::Value>(
+            "/api/account/emails",
+            &json!({"address": "John@Remote.org"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(pending["address"], "john@remote.org", "{pending}");
+    assert_eq!(pending["state"], "pending", "{pending}");
+    john_api
+        .post::<serde_json::Value>(
+            "/api/account/emails",
+            &json!({"address": "john@remote.org"}),
+        )
+        .await
+        .unwrap()
+        .expect_error("fieldAlreadyExists");
+    let first_token = verification_token(&mut smtp_rx, "<john@remote.org>").await;
+    let principal = tenant_api
+        .get::<serde_json::Value>("/api/principal/john@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        principal["pendingEmails"],
+        json!("john@remote.org"),
+        "{principal}"
+    );
+    assert!(principal.get("secondaryEmails").is_none(), "{principal}");
+
+    // Resending replaces the previous token
+    smtp_settings.lock().do_stop = true;
+    john_api
+        .post::<serde_json::Value>("/api/account/emails/john@remote.org/resend", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let token = verification_token(&mut smtp_rx, "<john@remote.org>").await;
+    assert_ne!(token, first_token);
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    for (token, status) in [
+        (first_token.as_str(), 400),
+        ("invalid.token", 400),
+        (token.as_str(), 200),
+        (token.as_str(), 400),
+    ] {
+        assert_eq!(
+            http_client
+                .post("https://127.0.0.1:8899/auth/verify-email")
+                .body(json!({"token": token}).to_string())
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            status,
+            "{token}"
+        );
+    }
+    let emails = john_api
+        .get::<serde_json::Value>("/api/account/emails")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        emails,
+        json!([{"address": "john@remote.org", "state": "verified"}])
+    );
+    let principal = tenant_api
+        .get::<serde_json::Value>("/api/principal/john@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        principal["secondaryEmails"],
+        json!("john@remote.org"),
+        "{principal}"
+    );
+    assert!(principal.get("pendingEmails").is_none(), "{principal}");
+    assert_eq!(
+        params
+            .server
+            .store()
+            .email_to_id("john@remote.org")
+            .await
+            .unwrap(),
+        None
+    );
+
+    // Security notices are sent to the verified secondary addresses, once per
+    // burst of events and only to accounts that did not opt out
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({
+                "authentication.security-notice.app-password-created": true,
+                "authentication.security-notice.password-changed": true,
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    for label in ["laptop", "tablet"] {
+        john_api
+            .post::<serde_json::Value>(
+                "/api/principal/john@acme.org/app-passwords",
+                &json!({"label": label, "protocols": ["imap"]}),
+            )
+            .await
+            .unwrap()
+            .unwrap_data();
+        if label == "laptop" {
+            let message = expect_message_delivery(&mut smtp_rx).await;
+            assert_eq!(message.rcpt_to, vec!["<john@remote.org>".to_string()]);
+            assert!(
+                message.message.contains("A new app password was created"),
+                "{}",
+                message.message
+            );
+            assert!(
+                message.message.contains("App password: laptop"),
+                "{}",
+                message.message
+            );
+        }
+    }
+    for label in ["laptop", "tablet"] {
+        john_api
+            .delete::<()>(&format!(
+                "/api/principal/john@acme.org/app-passwords/{label}"
+            ))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    for opt_out in [true, false] {
+        tenant_api
+            .patch::<()>(
+                "/api/principal/john@acme.org",
+                &json!([
+                    {"action": "set", "field": "securityNoticeOptOut", "value": opt_out},
+                    {"action": "set", "field": "secrets", "value": "john-secret"},
+                ]),
+            )
+            .await
+            .unwrap()
+            .unwrap_data();
+        if !opt_out {
+            let message = expect_message_delivery(&mut smtp_rx).await;
+            assert_eq!(message.rcpt_to, vec!["<john@remote.org>".to_string()]);
+            assert!(
+                message.message.contains("Your password was changed"),
+                "{}",
+                message.message
+            );
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(smtp_rx.try_recv().is_err());
+    tenant_api
+        .patch::<serde_json::Value>(
+            "/api/organization/acme/settings",
+            &json!({
+                "authentication.security-notice.app-password-created": null,
+                "authentication.security-notice.password-changed": null,
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    john_api
+        .delete::<()>("/api/account/emails/john@remote.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    john_api
+        .delete::<()>("/api/account/emails/john@remote.org")
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    tenant_api
+        .patch::<()>(
+            "/api/principal/john@acme.org",
+            &json!([{"action": "set", "field": "legalHold", "value": true}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    tenant_api
+        .post::<serde_json::Value>("/api/principal/john@acme.org/erase", &json!({}))
+        .await
+        .unwrap()
+        .expect_error("legal hold");
+    tenant_api
+        .patch::<()>(
+            "/api/principal/john@acme.org",
+            &json!([{"action": "set", "field": "legalHold", "value": false}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    for _ in 0..50 {
+        let events = api
+            .get::<AuditEvents>("/api/events?target=/api/principal/john@acme.org")
+            .await
+            .unwrap()
+            .unwrap_data();
+        if events.items.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let mut job = tenant_api
+        .post::<serde_json::Value>("/api/principal/john@acme.org/erase", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let job_id = job["id"].as_str().unwrap().to_string();
+    for _ in 0..50 {
+        if job["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        job = tenant_api
+            .get::<serde_json::Value>(&format!("/api/principal/john@acme.org/erase/{job_id}"))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    assert_eq!(job["status"], "completed", "{job}");
+    let report = serde_json::from_value::<ErasureReport>(job["report"].clone()).unwrap();
+    assert_eq!(report.principal, "john@acme.org");
+    assert_eq!(report.requested_by, "acme-admin");
+    assert_eq!(report.removed.get("principal"), Some(&1), "{report:?}");
+    assert_eq!(report.removed.get("email"), Some(&1), "{report:?}");
+    assert_eq!(report.removed.get("blob"), Some(&1), "{report:?}");
+    assert!(report.removed.contains_key("mailbox"), "{report:?}");
+    assert_eq!(
+        report.signature,
+        report.signature(&params.server.core.oauth.oauth_key)
+    );
+    assert!(
+        params
+            .server
+            .store()
+            .get_principal_id("john@acme.org")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let audit_events = api
+        .get::<AuditEvents>("/api/events?target=/api/principal/john@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(audit_events.items.is_empty(), "{audit_events:?}");
+    let audit_events = api
+        .get::<AuditEvents>(&format!(
+            "/api/events?target=/api/principal/{}",
+            report.tombstone
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(audit_events.items.len(), 2, "{audit_events:?}");
+}
+
+async fn deletion_reports(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Empty organizations are deleted without a report
+    api.post::<u32>(
+        "/api/principal",
+        &json!({"type": "tenant", "name": "hooli"}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    api.delete::<serde_json::Value>("/api/organization/hooli")
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Deletion reports list what is removed and what blocks the deletion
+    api.post::<ProvisionResponse>(
+        "/api/organization/provision",
+        &json!({
+            "tenantName": "initech",
+            "domain": "initech.org",
+            "adminName": "initech-admin",
+            "adminPassword": "initech-secret",
+            "adminEmail": "admin@initech.org",
+        }),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    api.put::<serde_json::Value>(
+        "/api/organization/acme/routing",
+        &json!({"domains": {"initech.org": {"host": "relay.acme.org"}}}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let job = api
+        .get::<serde_json::Value>("/api/organization/initech/deletion-report")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(job["status"], "completed", "{job}");
+    let report = &job["report"];
+    assert_eq!(report["organization"], "initech", "{report}");
+    assert_eq!(report["domains"], json!(["initech.org"]), "{report}");
+    assert_eq!(report["accounts"][0]["name"], "initech-admin", "{report}");
+    assert_eq!(report["accounts"][0]["messages"], 0, "{report}");
+    assert_eq!(report["blockers"][0]["type"], "routing", "{report}");
+    assert_eq!(report["blockers"][0]["name"], "initech.org", "{report}");
+    api.delete::<serde_json::Value>(&format!(
+        "/api/organization/initech?report={}",
+        job["id"].as_str().unwrap()
+    ))
+    .await
+    .unwrap()
+    .expect_error("Organization cannot be deleted");
+    api.put::<serde_json::Value>("/api/organization/acme/routing", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    let job = api
+        .get::<serde_json::Value>("/api/organization/initech/deletion-report")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let report_id = job["id"].as_str().unwrap().to_string();
+    assert_eq!(job["report"]["blockers"], json!([]), "{job}");
+    let csv = api
+        .get_raw(&format!(
+            "/api/organization/initech/deletion-report/{report_id}?format=csv"
+        ))
+        .await
+        .unwrap();
+    assert!(
+        csv.starts_with("type,name,account,messages,size,reason\r\n"),
+        "{csv}"
+    );
+    assert!(csv.contains("domain,initech.org,,,,\r\n"), "{csv}");
+    assert!(csv.contains("account,initech-admin,,0,0,\r\n"), "{csv}");
+    tenant_api
+        .get::<serde_json::Value>(&format!(
+            "/api/organization/initech/deletion-report/{report_id}"
+        ))
+        .await
+        .unwrap()
+        .expect_error("notFound");
+    api.delete::<serde_json::Value>("/api/organization/initech")
+        .await
+        .unwrap()
+        .expect_error("Organization is not empty");
+
+    // Reports no longer apply once the organization changes
+    ManagementApi::new(8899, "initech-admin", "initech-secret")
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "milton@initech.org",
+                "secrets": ["milton-secret"],
+                "emails": ["milton@initech.org"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    api.delete::<serde_json::Value>(&format!("/api/organization/initech?report={report_id}"))
+        .await
+        .unwrap()
+        .expect_error("accounts added: milton@initech.org");
+    let job = api
+        .get::<serde_json::Value>("/api/organization/initech/deletion-report")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let removed = api
+        .delete::<serde_json::Value>(&format!(
+            "/api/organization/initech?report={}",
+            job["id"].as_str().unwrap()
+        ))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(removed["removed"]["individual"], 2, "{removed}");
+    assert_eq!(removed["removed"]["domain"], 1, "{removed}");
+    for name in ["initech", "initech.org", "milton@initech.org"] {
+        assert!(
+            params
+                .server
+                .store()
+                .get_principal_id(name)
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}"
+        );
+    }
+}
+
+async fn invitations(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    // Organizations can be provisioned from single-use invitations
+    tenant_api
+        .post::<serde_json::Value>(
+            "/api/organization/invitations",
+            &json!({"domainPattern": "*.example.net"}),
+        )
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    for (request, error) in [
+        (
+            json!({"domainPattern": "*example.net"}),
+            "Invalid domain pattern",
+        ),
+        (
+            json!({"domainPattern": "*.example.net", "maxUsers": 0}),
+            "Invalid user limit",
+        ),
+        (
+            json!({"domainPattern": "*.example.net", "expiresAt": "2099-01-01T00:00:00Z"}),
+            "Invalid expiration",
+        ),
+    ] {
+        api.post::<serde_json::Value>("/api/organization/invitations", &request)
+            .await
+            .unwrap()
+            .expect_error(error);
+    }
+    let invitation = api
+        .post::<serde_json::Value>(
+            "/api/organization/invitations",
+            &json!({
+                "domainPattern": "*.example.net",
+                "maxUsers": 2,
+                "plan": "starter",
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let invitation_id = invitation["id"].as_str().unwrap().to_string();
+    let token = invitation["token"].as_str().unwrap().to_string();
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let redeem = |token: String, domain: &'static str| {
+        let http_client = http_client.clone();
+        async move {
+            http_client
+                .post("https://127.0.0.1:8899/api/organization/provision/invited")
+                .body(
+                    json!({
+                        "token": token,
+                        "tenantName": "globex",
+                        "domain": domain,
+                        "adminName": "globex-admin",
+                        "adminPassword": "globex-secret",
+                        "adminEmail": format!("owner@{domain}"),
+                    })
+                    .to_string(),
+                )
+                .send()
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+    let response = redeem(format!("{token}x"), "globex.example.net").await;
+    assert_eq!(response["status"], 400, "{response}");
+    let response = redeem(token.clone(), "globex.org").await;
+    assert_eq!(response["error"], "other", "{response}");
+    assert_eq!(response["details"], "Domain not allowed", "{response}");
+
+    // Only one of several concurrent redemptions succeeds
+    let responses =
+        futures::future::join_all((0..4).map(|_| redeem(token.clone(), "globex.example.net")))
+            .await;
+    assert_eq!(
+        responses
+            .iter()
+            .filter(|response| response.get("data").is_some())
+            .count(),
+        1,
+        "{responses:?}"
+    );
+    assert!(
+        responses
+            .iter()
+            .filter(|response| response.get("data").is_none())
+            .all(|response| response["details"] == "Invitation unavailable"),
+        "{responses:?}"
+    );
+    let response = redeem(token.clone(), "globex.example.net").await;
+    assert_eq!(response["details"], "Invitation unavailable", "{response}");
+    assert_eq!(
+        params
+            .server
+            .get_invitation(invitation_id.parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap()
+            .status,
+        InvitationStatus::Redeemed
+    );
+
+    // The invitation limits apply to the new organization
+    let tenant = api
+        .get::<serde_json::Value>("/api/principal/globex")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(tenant["quota"][1], 2, "{tenant}");
+    let metadata = tenant["metadata"].to_string();
+    for entry in [
+        format!("invitation.id={invitation_id}"),
+        "plan=starter".to_string(),
+    ] {
+        assert!(metadata.contains(&entry), "{metadata}");
+    }
+    let globex_api = ManagementApi::new(8899, "globex-admin", "globex-secret");
+    globex_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "hank@globex.example.net",
+                "secrets": ["hank-secret"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    globex_api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "frank@globex.example.net",
+                "secrets": ["frank-secret"],
+            }),
+        )
+        .await
+        .unwrap()
+        .expect_request_error("Tenant quota exceeded");
+
+    // Redemptions are audited along with the inviting principal
+    let mut audit_events = None;
+    for _ in 0..50 {
+        let events = api
+            .get::<AuditEvents>(&format!(
+                "/api/events?type=directory.invitation-redeemed&target={invitation_id}"
+            ))
+            .await
+            .unwrap()
+            .unwrap_data();
+        if !events.items.is_empty() {
+            audit_events = Some(events);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let audit_events = audit_events.expect("Audit event was not recorded");
+    assert_eq!(audit_events.items.len(), 1, "{audit_events:?}");
+    assert_eq!(audit_events.items[0].actor.as_deref(), Some("admin"));
+
+    // Revoked invitations cannot be redeemed
+    let invitation = api
+        .post::<serde_json::Value>(
+            "/api/organization/invitations",
+            &json!({"domainPattern": "initrode.org"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let invitation_path = format!(
+        "/api/organization/invitations/{}",
+        invitation["id"].as_str().unwrap()
+    );
+    api.delete::<serde_json::Value>(&invitation_path)
+        .await
+        .unwrap()
+        .unwrap_data();
+    api.delete::<serde_json::Value>(&invitation_path)
+        .await
+        .unwrap()
+        .expect_error("Invitation unavailable");
+    assert_eq!(
+        params
+            .server
+            .get_invitation(invitation["id"].as_str().unwrap().parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap()
+            .status,
+        InvitationStatus::Revoked
+    );
+    let response = redeem(
+        invitation["token"].as_str().unwrap().to_string(),
+        "initrode.org",
+    )
+    .await;
+    assert_eq!(response["details"], "Invitation unavailable", "{response}");
+    assert!(
+        params
+            .server
+            .store()
+            .get_principal_id("initrode.org")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn public_signup(params: &JMAPTest, api: &ManagementApi) {
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    // Organizations requested through the public signup are held for review
+    let signup = |tenant: &'static str, domain: &'static str, email: &'static str| {
+        let http_client = http_client.clone();
+        async move {
+            http_client
+                .post("https://127.0.0.1:8899/api/organization/signup")
+                .body(
+                    json!({
+                        "tenantName": tenant,
+                        "domain": domain,
+                        "adminName": format!("{tenant}-admin"),
+                        "adminPassword": format!("{tenant}-secret"),
+                        "adminEmail": email,
+                    })
+                    .to_string(),
+                )
+                .send()
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+    let application = signup("vandelay", "vandelay.example", "art@vandelay.example").await;
+    let application = &application["data"];
+    assert_eq!(application["status"], "pending", "{application}");
+    assert!(application.get("adminSecret").is_none(), "{application}");
+    let application_id = application["id"].as_str().unwrap().to_string();
+    let response = signup("pennypacker", "Vandelay.example", "hep@vandelay.example").await;
+    assert_eq!(response["details"], "Domain unavailable", "{response}");
+    let response = signup("acme", "acme.example", "admin@acme.example").await;
+    assert_eq!(response["error"], "fieldAlreadyExists", "{response}");
+    let response = signup("vandelay", "vandelay.example", "art@remote.org").await;
+    assert_eq!(response["details"], "Invalid admin email", "{response}");
+    assert!(
+        params
+            .server
+            .store()
+            .get_principal_id("vandelay")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Applications are reviewed by administrators that can provision them
+    tenant_api
+        .get::<serde_json::Value>("/api/organization/applications")
+        .await
+        .unwrap()
+        .expect_request_error("Forbidden");
+    let applications = api
+        .get::<serde_json::Value>("/api/organization/applications?status=pending")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(applications["total"], 1, "{applications}");
+    assert_eq!(
+        applications["items"][0]["tenantName"], "vandelay",
+        "{applications}"
+    );
+    api.post::<ProvisionResponse>(
+        &format!("/api/organization/applications/{application_id}/approve"),
+        &json!({}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    api.post::<serde_json::Value>(
+        &format!("/api/organization/applications/{application_id}/approve"),
+        &json!({}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Application already reviewed");
+    ManagementApi::new(8899, "vandelay-admin", "vandelay-secret")
+        .get::<serde_json::Value>("/api/principal/vandelay-admin")
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Rejected applicants are told why and their domain is released
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    let application = signup("kramerica", "other_domain.com", "cosmo@other_domain.com").await;
+    let application_id = application["data"]["id"].as_str().unwrap().to_string();
+    api.post::<serde_json::Value>(
+        &format!("/api/organization/applications/{application_id}/reject"),
+        &json!({"reason": ""}),
+    )
+    .await
+    .unwrap()
+    .expect_error("reason");
+    smtp_settings.lock().do_stop = true;
+    let application = api
+        .post::<serde_json::Value>(
+            &format!("/api/organization/applications/{application_id}/reject"),
+            &json!({"reason": "Incomplete business details", "notify": true}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(application["status"], "rejected", "{application}");
+    assert_eq!(
+        application["reason"], "Incomplete business details",
+        "{application}"
+    );
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(
+        message.rcpt_to,
+        vec!["<cosmo@other_domain.com>".to_string()]
+    );
+    assert!(
+        message.message.contains("Incomplete business details"),
+        "{}",
+        message.message
+    );
+    let application = signup("kramerica", "other_domain.com", "cosmo@other_domain.com").await;
+    assert_eq!(application["data"]["status"], "pending", "{application}");
+
+    // Organizations can require their contact to activate them first
+    let provision = |tenant: &'static str, domain: &'static str, contact: Option<&'static str>| {
+        json!({
+            "tenantName": tenant,
+            "domain": domain,
+            "adminName": format!("{tenant}-admin"),
+            "adminPassword": format!("{tenant}-secret"),
+            "adminEmail": format!("admin@{domain}"),
+            "requireAdminEmailVerification": true,
+            "contactEmail": contact,
+        })
+    };
+    api.post::<serde_json::Value>(
+        "/api/organization/provision",
+        &provision("pendant", "pendant.example", None),
+    )
+    .await
+    .unwrap()
+    .expect_error("contactEmail");
+    for contact in ["admin@pendant.example", "elaine@Pendant.example", "elaine"] {
+        api.post::<serde_json::Value>(
+            "/api/organization/provision",
+            &provision("pendant", "pendant.example", Some(contact)),
+        )
+        .await
+        .unwrap()
+        .expect_error("Invalid contact email");
+    }
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    smtp_settings.lock().do_stop = true;
+    let response = api
+        .post::<serde_json::Value>(
+            "/api/organization/provision",
+            &provision("pendant", "pendant.example", Some("elaine@remote.org")),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(response["pendingVerification"], true, "{response}");
+    let tenant_id = response["tenantId"].as_u64().unwrap();
+    let token = verification_token(&mut smtp_rx, "<elaine@remote.org>").await;
+
+    // Pending organizations can't be used and are reported as such
+    ManagementApi::new(8899, "pendant-admin", "pendant-secret")
+        .get::<serde_json::Value>("/api/principal/pendant-admin")
+        .await
+        .unwrap()
+        .expect_request_error("Organization pending activation");
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.mail_from("bill@remote.org", 2).await;
+    let response = lmtp.rcpt_to("admin@pendant.example", 4).await;
+    assert!(
+        response
+            .iter()
+            .any(|line| line.contains("pending activation")),
+        "{response:?}"
+    );
+    lmtp.quit().await;
+    let organizations = api
+        .get::<OrganizationList>("/api/organization?filter=pendant&fields=name,pendingVerification")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        vec![json!({"name": "pendant", "pendingVerification": true})]
+    );
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/pendant")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organization["pendingVerification"]["contact"], "elaine@remote.org",
+        "{organization}"
+    );
+    assert!(
+        organization["pendingVerification"].get("nonce").is_none(),
+        "{organization}"
+    );
+    let health = api
+        .get::<serde_json::Value>("/api/organization/pendant/health")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(health["healthy"], false, "{health}");
+    assert!(health["pendingVerification"].is_object(), "{health}");
+
+    // Activation requires a token issued for the organization, and can be repeated
+    let activate = |tenant_id: u64, token: &str| {
+        let http_client = http_client.clone();
+        let url =
+            format!("https://127.0.0.1:8899/api/organization/{tenant_id}/activate?token={token}");
+        async move {
+            http_client
+                .post(url)
+                .send()
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+    let response = activate(tenant_id + 1, &token).await;
+    assert_eq!(response["status"], 400, "{response}");
+    let response = activate(tenant_id, &token.replace('.', "")).await;
+    assert_eq!(response["status"], 400, "{response}");
+    let response = http_client
+        .post(format!(
+            "https://127.0.0.1:8899/api/organization/{tenant_id}/activate"
+        ))
+        .body(json!({"token": "x".repeat(65 * 1024)}).to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 413);
+    for _ in 0..2 {
+        let response = activate(tenant_id, &token).await;
+        assert_eq!(response["data"]["state"], "active", "{response}");
+    }
+    ManagementApi::new(8899, "pendant-admin", "pendant-secret")
+        .get::<serde_json::Value>("/api/principal/pendant-admin")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let health = api
+        .get::<serde_json::Value>("/api/organization/pendant/health")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(health["pendingVerification"], serde_json::Value::Null);
+
+    // Organizations that are not activated in time are purged
+    let response = api
+        .post::<serde_json::Value>(
+            "/api/organization/provision",
+            &provision("pendleton", "pendleton.example", Some("elaine@remote.org")),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let tenant_id = response["tenantId"].as_u64().unwrap() as u32;
+    let token = verification_token(&mut smtp_rx, "<elaine@remote.org>").await;
+    let activation = params.server.tenant_activation(tenant_id).unwrap();
+    params.server.purge_unverified_organizations().await;
+    assert!(
+        params
+            .server
+            .store()
+            .get_principal_id("pendleton")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    params
+        .server
+        .update_tenant_activation(
+            tenant_id,
+            Some(TenantActivation {
+                expires_at: now(),
+                ..activation.as_ref().clone()
+            }),
+        )
+        .await
+        .unwrap();
+    params.server.purge_unverified_organizations().await;
+    for name in ["pendleton", "pendleton.example", "pendleton-admin"] {
+        assert!(
+            params
+                .server
+                .store()
+                .get_principal_id(name)
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}"
+        );
+    }
+    assert!(params.server.tenant_activation(tenant_id).is_none());
+    let response = activate(tenant_id as u64, &token).await;
+    assert_eq!(response["status"], 400, "{response}");
+
+    // Public requests are checked for abuse, denials don't reveal why
+    let response = signup("spammer", "deals.spam", "admin@deals.spam").await;
+    assert_eq!(response["details"], "Request denied", "{response}");
+    let applications = api
+        .get::<serde_json::Value>("/api/organization/applications")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        !applications.to_string().contains("deals.spam"),
+        "{applications}"
+    );
+    let application = signup("seinfeld", "seinfeld.example", "jerry@seinfeld.example").await;
+    assert_eq!(application["data"]["verdict"], "allow", "{application}");
+    assert_eq!(application["data"]["status"], "pending", "{application}");
+}
+
+async fn abuse_checks(params: &JMAPTest, api: &ManagementApi) {
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    // Invitation redemptions can be denied or held for review
+    let redeem_as = |token: String, tenant: &'static str, domain: &'static str| {
+        let http_client = http_client.clone();
+        async move {
+            http_client
+                .post("https://127.0.0.1:8899/api/organization/provision/invited")
+                .body(
+                    json!({
+                        "token": token,
+                        "tenantName": tenant,
+                        "domain": domain,
+                        "adminName": format!("{tenant}-admin"),
+                        "adminPassword": format!("{tenant}-secret"),
+                        "adminEmail": format!("owner@{domain}"),
+                    })
+                    .to_string(),
+                )
+                .send()
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+    let invite = |domain_pattern: &'static str| async move {
+        api.post::<serde_json::Value>(
+            "/api/organization/invitations",
+            &json!({"domainPattern": domain_pattern, "plan": "checked"}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data()
+    };
+    let invitation = invite("*").await;
+    let token = invitation["token"].as_str().unwrap().to_string();
+    let response = redeem_as(token.clone(), "eggs", "eggs.spam").await;
+    assert_eq!(response["details"], "Request denied", "{response}");
+    let response = redeem_as(token.clone(), "review-soup", "soup.example").await;
+    let application = &response["data"];
+    assert_eq!(application["status"], "pending", "{response}");
+    assert_eq!(application["verdict"], "review", "{response}");
+    assert_eq!(application["invitation"], true, "{response}");
+    assert!(!response.to_string().contains(&token), "{response}");
+    let application_id = application["id"].as_str().unwrap().to_string();
+    assert!(
+        params
+            .server
+            .store()
+            .get_principal_id("review-soup")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let response = redeem_as(token.clone(), "kenny", "kenny.example").await;
+    assert_eq!(response["details"], "Invitation unavailable", "{response}");
+
+    // Approving a held redemption applies the invitation's limits
+    api.post::<ProvisionResponse>(
+        &format!("/api/organization/applications/{application_id}/approve"),
+        &json!({}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let tenant = api
+        .get::<serde_json::Value>("/api/principal/review-soup")
+        .await
+        .unwrap()
+        .unwrap_data();
+    let metadata = tenant["metadata"].to_string();
+    for entry in [
+        format!("invitation.id={}", invitation["id"].as_str().unwrap()),
+        "plan=checked".to_string(),
+    ] {
+        assert!(metadata.contains(&entry), "{metadata}");
+    }
+
+    // Verdicts that can't be used fall back to the default decision
+    let invitation = invite("undecided.example").await;
+    let response = redeem_as(
+        invitation["token"].as_str().unwrap().to_string(),
+        "undecided",
+        "undecided.example",
+    )
+    .await;
+    assert_eq!(response["data"]["status"], "pending", "{response}");
+    assert_eq!(response["data"]["verdict"], "review", "{response}");
+
+    // Verdicts are audited with the check that produced them
+    for (domain, expected) in [
+        ("deals.spam", "deny by expression"),
+        ("eggs.spam", "deny by expression"),
+        ("seinfeld.example", "allow by expression"),
+        ("soup.example", "review by expression"),
+        ("undecided.example", "review by default"),
+    ] {
+        let mut audit_events = None;
+        for _ in 0..50 {
+            let events = api
+                .get::<AuditEvents>(&format!(
+                    "/api/events?type=directory.provisioning-checked&target={domain}"
+                ))
+                .await
+                .unwrap()
+                .unwrap_data();
+            if !events.items.is_empty() {
+                audit_events = Some(events);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let audit_events = audit_events.expect("Audit event was not recorded");
+        assert_eq!(audit_events.items.len(), 1, "{audit_events:?}");
+        let details = audit_events.items[0].details.as_deref().unwrap_or_default();
+        assert!(details.starts_with(expected), "{details}");
+        assert!(details.contains("ms"), "{details}");
+    }
+}
+
+async fn trials(params: &JMAPTest, api: &ManagementApi) {
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    // Organizations can be provisioned as trials
+    let trial_request = |trial: serde_json::Value| {
+        let mut request = json!({
+            "tenantName": "trialist",
+            "domain": "trialist.example",
+            "adminName": "trialist-admin",
+            "adminPassword": "trialist-secret",
+            "adminEmail": "admin@trialist.example",
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(trial.as_object().unwrap().clone());
+        request
+    };
+    for (trial, expected) in [
+        (json!({"trialDays": 0}), "Invalid trial length"),
+        (json!({"trialDays": 366}), "Invalid trial length"),
+        (
+            json!({"trialDays": 30, "trialEndsAt": "2100-01-01T00:00:00Z"}),
+            "Only one of",
+        ),
+        (
+            json!({"trialEndsAt": "2000-01-01T00:00:00Z"}),
+            "Invalid trial end",
+        ),
+    ] {
+        api.post::<serde_json::Value>("/api/organization/provision", &trial_request(trial))
+            .await
+            .unwrap()
+            .expect_error(expected);
+    }
+    let response = http_client
+        .post("https://127.0.0.1:8899/api/organization/signup")
+        .body(trial_request(json!({"trialDays": 30})).to_string())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(
+        response["details"], "Trial periods can't be requested at signup",
+        "{response}"
+    );
+    api.post::<ProvisionResponse>(
+        "/api/organization/provision",
+        &trial_request(json!({"trialDays": 30})),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/trialist")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["trial"]["status"], "active", "{organization}");
+    assert_eq!(organization["trial"]["daysRemaining"], 30, "{organization}");
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/acme")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["trial"], serde_json::Value::Null);
+
+    // Listings can be filtered by trial status
+    let organizations = api
+        .get::<OrganizationList>(
+            "/api/organization?fields=name,trialStatus,trialDaysRemaining&trial=active",
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        vec![json!({"name": "trialist", "trialStatus": "active", "trialDaysRemaining": 30})]
+    );
+    let organizations = api
+        .get::<OrganizationList>("/api/organization?filter=trialist&fields=name&trial=none")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organizations.items, Vec::<serde_json::Value>::new());
+    api.get::<OrganizationList>("/api/organization?trial=lapsed")
+        .await
+        .unwrap()
+        .expect_error("Invalid trial status");
+
+    // Notices are sent once as each configured point before expiry is reached
+    let set_trial_end = |ends_at: u64| async move {
+        api.patch::<()>(
+            "/api/principal/trialist",
+            &json!([{"action": "set", "field": "trialEndsAt", "value": ends_at}]),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    };
+    set_trial_end(now() + 3600).await;
+    for _ in 0..2 {
+        params.server.apply_tenant_trials().await;
+    }
+    let mut audit_events = None;
+    for _ in 0..50 {
+        let events = api
+            .get::<AuditEvents>("/api/events?type=directory.trial-expiring&target=trialist")
+            .await
+            .unwrap()
+            .unwrap_data();
+        if !events.items.is_empty() {
+            audit_events = Some(events);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let audit_events = audit_events.expect("Audit event was not recorded");
+    assert_eq!(audit_events.items.len(), 1, "{audit_events:?}");
+    assert_eq!(
+        audit_events.items[0].details.as_deref(),
+        Some("1 days remaining")
+    );
+
+    // Lapsed trials suspend the organization
+    let trial_admin = ManagementApi::new(8899, "trialist-admin", "trialist-secret");
+    trial_admin
+        .get::<serde_json::Value>("/api/principal/trialist-admin")
+        .await
+        .unwrap()
+        .unwrap_data();
+    set_trial_end(now()).await;
+    params.server.apply_tenant_trials().await;
+    let tenant_id = params
+        .server
+        .store()
+        .get_principal_id("trialist")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        params
+            .server
+            .tenant_maintenance(tenant_id)
+            .is_some_and(|maintenance| maintenance.trial_expired)
+    );
+    trial_admin
+        .get::<serde_json::Value>("/api/principal/trialist-admin")
+        .await
+        .unwrap()
+        .expect_request_error("Trial period ended");
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/trialist")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["trial"]["status"], "expired", "{organization}");
+    assert_eq!(organization["trial"]["daysRemaining"], 0, "{organization}");
+
+    // Extending the trial lifts the suspension
+    api.post::<serde_json::Value>("/api/organization/trialist/trial/extend", &json!({}))
+        .await
+        .unwrap()
+        .expect_error("days");
+    let trial = api
+        .post::<serde_json::Value>(
+            "/api/organization/trialist/trial/extend",
+            &json!({"days": 10}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(trial["status"], "active", "{trial}");
+    assert_eq!(trial["daysRemaining"], 10, "{trial}");
+    assert!(params.server.tenant_maintenance(tenant_id).is_none());
+    trial_admin
+        .get::<serde_json::Value>("/api/principal/trialist-admin")
+        .await
+        .unwrap()
+        .unwrap_data();
+
+    // Converting the trial clears its expiry
+    let trial = api
+        .post::<serde_json::Value>("/api/organization/trialist/trial/convert", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(trial, serde_json::Value::Null);
+    let organizations = api
+        .get::<OrganizationList>(
+            "/api/organization?filter=trialist&fields=name,trialStatus,trialDaysRemaining&trial=none",
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        vec![json!({"name": "trialist", "trialStatus": "none", "trialDaysRemaining": null})]
+    );
+    api.post::<serde_json::Value>("/api/organization/trialist/trial/convert", &json!({}))
+        .await
+        .unwrap()
+        .expect_error("No trial");
+    for event in ["trial-expired", "trial-extended", "trial-converted"] {
+        let mut found = false;
+        for _ in 0..50 {
+            found = !api
+                .get::<AuditEvents>(&format!(
+                    "/api/events?type=directory.{event}&target=trialist"
+                ))
+                .await
+                .unwrap()
+                .unwrap_data()
+                .items
+                .is_empty();
+            if found {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(found, "{event} was not audited");
+    }
+}
+
+async fn plans(api: &ManagementApi) {
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    // Organizations can be provisioned on a plan of the registry
+    let plan_request = |plan: &str| {
+        json!({
+            "tenantName": "planned",
+            "domain": "planned.example",
+            "adminName": "planned-admin",
+            "adminPassword": "planned-secret",
+            "adminEmail": "admin@planned.example",
+            "plan": plan,
+        })
+    };
+    api.post::<serde_json::Value>("/api/organization/provision", &plan_request("platinum"))
+        .await
+        .unwrap()
+        .expect_error("Invalid plan");
+    let response = http_client
+        .post("https://127.0.0.1:8899/api/organization/signup")
+        .body(plan_request("starter").to_string())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(
+        response["details"], "Plans can't be requested at signup",
+        "{response}"
+    );
+    let mut request = plan_request("starter");
+    request["protocols"] = json!({"gopher": false});
+    api.post::<serde_json::Value>("/api/organization/provision", &request)
+        .await
+        .unwrap()
+        .expect_error("Invalid protocol");
+    request["protocols"] = json!({"imap": false});
+    api.post::<ProvisionResponse>("/api/organization/provision", &request)
+        .await
+        .unwrap()
+        .unwrap_data();
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/planned")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["plan"]["name"], "starter", "{organization}");
+    assert_eq!(organization["plan"]["features"], json!(["basic"]));
+    assert_eq!(
+        organization["limits"],
+        json!({"quota": null, "maxUsers": 2, "maxDomains": 1})
+    );
+    assert_eq!(organization["overLimit"], json!([]));
+
+    // The plan's user limit counts the organization's admin
+    let create_user = |name: &'static str| async move {
+        api.post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": name,
+                "secrets": ["planned-user-secret"],
+                "emails": [name],
+                "tenant": "planned",
+            }),
+        )
+        .await
+        .unwrap()
+    };
+    create_user("jane@planned.example").await.unwrap_data();
+    create_user("john@planned.example")
+        .await
+        .expect_request_error("Tenant quota exceeded");
+
+    // Protocols follow the plan unless set at provisioning
+    for protocol in ["pop3", "imap"] {
+        assert!(
+            protocol_login(protocol, "jane@planned.example", "planned-user-secret")
+                .await
+                .unwrap_err()
+                .contains("Protocol not enabled"),
+            "{protocol}"
+        );
+    }
+    assert_eq!(
+        protocol_login("jmap", "jane@planned.example", "planned-user-secret").await,
+        Ok(())
+    );
+
+    // Plan settings apply unless the organization overrides them
+    api.patch::<serde_json::Value>(
+        "/api/organization/planned/settings",
+        &json!({"jmap.email.max-attachment-size": 1000}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let effective = api
+        .get::<serde_json::Value>("/api/troubleshoot/settings?domain=planned.example")
+        .await
+        .unwrap()
+        .unwrap_data();
+    for (key, expected) in [
+        ("jmap.email.max-size", ("5000000", "plan")),
+        ("jmap.email.max-attachment-size", ("1000", "tenant")),
+        ("oauth.expiry.token", ("1s", "global")),
+        ("protocol.pop3.enable", ("false", "plan")),
+        ("protocol.imap.enable", ("false", "tenant")),
+        ("protocol.jmap.enable", ("true", "global")),
+    ] {
+        let setting = effective["settings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|setting| setting["key"] == key)
+            .unwrap();
+        assert_eq!(
+            (
+                setting["value"].as_str().unwrap(),
+                setting["source"].as_str().unwrap()
+            ),
+            expected,
+            "{key}"
+        );
+    }
+
+    // Upgrading lifts the limits right away
+    api.patch::<serde_json::Value>("/api/organization/planned", &json!({"plan": "business"}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    create_user("john@planned.example").await.unwrap_data();
+    create_user("mary@planned.example").await.unwrap_data();
+
+    // Downgrading is allowed and flags the organization as over its limits
+    api.patch::<serde_json::Value>("/api/organization/planned", &json!({"plan": "platinum"}))
+        .await
+        .unwrap()
+        .expect_error("Invalid plan");
+    let organization = api
+        .patch::<serde_json::Value>("/api/organization/planned", &json!({"plan": "starter"}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["plan"]["name"], "starter", "{organization}");
+    assert_eq!(organization["overLimit"], json!(["users"]));
+    let organizations = api
+        .get::<OrganizationList>("/api/organization?filter=planned&fields=name,plan,overLimit")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        vec![json!({"name": "planned", "plan": "starter", "overLimit": true})]
+    );
+    create_user("peter@planned.example")
+        .await
+        .expect_request_error("Tenant quota exceeded");
+
+    // Limits set on the organization take precedence over its plan
+    api.patch::<()>(
+        "/api/principal/planned",
+        &json!([{"action": "set", "field": "quota", "value": [0, 10]}]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let organization = api
+        .get::<serde_json::Value>("/api/organization/planned")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(organization["limits"]["maxUsers"], 10, "{organization}");
+    assert_eq!(organization["overLimit"], json!([]));
+    create_user("peter@planned.example").await.unwrap_data();
+}
+
+async fn attribution(api: &ManagementApi) {
+    let http_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    // Campaign attribution is kept in the organization's metadata
+    let referral_request = |name: &str, attribution: serde_json::Value| {
+        let mut request = json!({
+            "tenantName": name,
+            "domain": format!("{name}.example"),
+            "adminName": format!("{name}-admin"),
+            "adminPassword": format!("{name}-secret"),
+            "adminEmail": format!("admin@{name}.example"),
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(attribution.as_object().unwrap().clone());
+        request
+    };
+    let too_many = (0..17)
+        .map(|i| (format!("key{i}"), json!("value")))
+        .collect::<serde_json::Map<_, _>>();
+    for (attribution, expected) in [
+        (
+            json!({"referralCode": "spring 24"}),
+            "Invalid referral code",
+        ),
+        (
+            json!({"referralCode": "x".repeat(65)}),
+            "Invalid referral code",
+        ),
+        (
+            json!({"attribution": {"utm source": "ads"}}),
+            "Invalid attribution",
+        ),
+        (
+            json!({"attribution": {"source": "x".repeat(257)}}),
+            "Invalid attribution",
+        ),
+        (json!({"attribution": too_many}), "Invalid attribution"),
+    ] {
+        api.post::<serde_json::Value>(
+            "/api/organization/provision",
+            &referral_request("referred", attribution),
+        )
+        .await
+        .unwrap()
+        .expect_error(expected);
+    }
+    let response = api
+        .post::<serde_json::Value>(
+            "/api/organization/provision",
+            &referral_request(
+                "referred",
+                json!({
+                    "referralCode": "spring24",
+                    "attribution": {"utm_source": "newsletter", "utm_campaign": "spring"},
+                }),
+            ),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(response.get("warnings").is_none(), "{response}");
+
+    // Unknown codes are kept and reported as a warning
+    let response = api
+        .post::<serde_json::Value>(
+            "/api/organization/provision",
+            &referral_request("misreferred", json!({"referralCode": "WINTER99"})),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        response["warnings"],
+        json!(["Unknown referral code \"WINTER99\""])
+    );
+
+    // Organizations can be filtered and exported by attribution
+    let organizations = api
+        .get::<OrganizationList>(
+            "/api/organization?metadata.referralCode=spring24&fields=name,referralCode,attribution",
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        vec![json!({
+            "name": "referred",
+            "referralCode": "spring24",
+            "attribution": {"utm_campaign": "spring", "utm_source": "newsletter"},
+        })]
+    );
+    let csv = api
+        .get_raw(
+            "/api/organization?filter=referred&fields=name,referralCode,attribution&format=csv",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        csv,
+        concat!(
+            "name,referralCode,attribution\r\n",
+            "referred,spring24,\"{\"\"utm_campaign\"\":\"\"spring\"\",\"\"utm_source\"\":\"\"newsletter\"\"}\"\r\n"
+        )
+    );
+
+    // Attribution captured at signup is kept when the application is approved
+    let application = http_client
+        .post("https://127.0.0.1:8899/api/organization/signup")
+        .body(
+            referral_request(
+                "signedup",
+                json!({"referralCode": "partner-42", "attribution": {"source": "partner"}}),
+            )
+            .to_string(),
+        )
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let application = &application["data"];
+    assert_eq!(application["referralCode"], "partner-42", "{application}");
+    api.post::<ProvisionResponse>(
+        &format!(
+            "/api/organization/applications/{}/approve",
+            application["id"].as_str().unwrap()
+        ),
+        &json!({}),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let organizations = api
+        .get::<OrganizationList>(
+            "/api/organization?metadata.attribution.source=partner&fields=name,referralCode",
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        organizations.items,
+        vec![json!({"name": "signedup", "referralCode": "partner-42"})]
+    );
+}
+
+async fn jane_client() -> Client {
+    Client::new()
+        .credentials(Credentials::basic("jane@acme.org", "jane-secret"))
+        .timeout(Duration::from_secs(3600))
+        .accept_invalid_certs(true)
+        .follow_redirects(["127.0.0.1"])
+        .connect("https://127.0.0.1:8899")
+        .await
+        .unwrap()
+}
+
+async fn message_import(params: &JMAPTest, api: &ManagementApi) {
+    let account_id = api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "importer@acme.org",
+                "secrets": ["importer-secret"],
+                "emails": ["importer@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let mbox = (0..3)
+        .map(|i| {
+            format!(
+                concat!(
+                    "From jane@example.org Mon Nov 13 22:13:2{i} 2023\n",
+                    "Message-ID: <import-{i}@example.org>\n",
+                    "Subject: import {i}\n",
+                    "\n",
+                    "Message {i}\n",
+                    "\n",
+                ),
+                i = i
+            )
+        })
+        .collect::<String>();
+    let upload = |options: serde_json::Value| {
+        reqwest::multipart::Form::new()
+            .text("options", options.to_string())
+            .part(
+                "file",
+                reqwest::multipart::Part::bytes(mbox.clone().into_bytes()).file_name("mail.mbox"),
+            )
+    };
+
+    // Messages are imported from the uploaded mbox file
+    let summary = import_job(
+        api,
+        upload(json!({"mailbox": "Imported"})),
+        "/api/principal/importer@acme.org/import/messages",
+    )
+    .await;
+    assert_eq!(summary["status"], "completed", "{summary}");
+    assert_eq!(summary["imported"], 3, "{summary}");
+    let cache = params.server.get_cached_messages(account_id).await.unwrap();
+    assert!(cache.mailbox_by_path("Imported").is_some());
+    assert_eq!(cache.emails.items.len(), 3);
+
+    // Duplicates are skipped
+    let summary = import_job(
+        api,
+        upload(json!({"mailbox": "Imported", "skipDuplicates": true})),
+        "/api/principal/importer@acme.org/import/messages",
+    )
+    .await;
+    assert_eq!(summary["skipped"], 3, "{summary}");
+    assert_eq!(summary["imported"], 0, "{summary}");
+
+    // Uploads without a file or with an unknown format are rejected
+    api.post_multipart::<serde_json::Value>(
+        "/api/principal/importer@acme.org/import/messages",
+        reqwest::multipart::Form::new().text("options", "{}"),
+    )
+    .await
+    .unwrap()
+    .expect_error("fieldMissing");
+    api.post_multipart::<serde_json::Value>(
+        "/api/principal/importer@acme.org/import/messages",
+        reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(b"Subject: test\r\n\r\n".to_vec()),
+        ),
+    )
+    .await
+    .unwrap()
+    .expect_error("Unknown file format");
+}
+
+async fn imap_import(params: &JMAPTest, api: &ManagementApi) {
+    let account_id = api
+        .post::<u32>(
+            "/api/principal",
+            &json!({
+                "type": "individual",
+                "name": "imap-importer@acme.org",
+                "secrets": ["imap-importer-secret"],
+                "emails": ["imap-importer@acme.org"],
+                "roles": ["user"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    let request = |host: &str| {
+        json!({
+            "host": host,
+            "port": 9991,
+            "tls": "startTls",
+            "allowInvalidCerts": true,
+            "accounts": [{
+                "sourceUser": "importer@acme.org",
+                "sourcePassword": "importer-secret",
+                "targetPrincipal": "imap-importer@acme.org",
+            }],
+        })
+    };
+
+    // Tenants cannot reach internal addresses, including after resolution
+    let tenant_api = ManagementApi::new(8899, "acme-admin", "acme-secret");
+    for host in [
+        "127.0.0.1",
+        "localhost",
+        "169.254.169.254",
+        "::ffff:10.0.0.1",
+    ] {
+        tenant_api
+            .post::<serde_json::Value>("/api/organization/acme/import/imap", &request(host))
+            .await
+            .unwrap()
+            .expect_error("Host not allowed");
+    }
+
+    // Server administrators can import from internal hosts
+    let job = api
+        .post::<serde_json::Value>("/api/organization/acme/import/imap", &request("127.0.0.1"))
+        .await
+        .unwrap()
+        .unwrap_data();
+    let job_path = format!(
+        "/api/organization/acme/import/imap/{}",
+        job["id"].as_str().unwrap()
+    );
+    let mut summary = serde_json::Value::Null;
+    for _ in 0..50 {
+        summary = api
+            .get::<serde_json::Value>(&job_path)
+            .await
+            .unwrap()
+            .unwrap_data();
+        if summary["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(summary["completed"], 1, "{summary}");
+    assert_eq!(
+        summary["accounts"][0]["progress"]["imported"], 3,
+        "{summary}"
+    );
+    let cache = params.server.get_cached_messages(account_id).await.unwrap();
+    assert!(cache.mailbox_by_path("Imported").is_some());
+    assert_eq!(cache.emails.items.len(), 3);
+}
+
+async fn import_job(
+    api: &ManagementApi,
+    form: reqwest::multipart::Form,
+    path: &str,
+) -> serde_json::Value {
+    let job = api
+        .post_multipart::<serde_json::Value>(path, form)
+        .await
+        .unwrap()
+        .unwrap_data();
+    let job_path = format!("{path}/{}", job["id"].as_str().unwrap());
+    for _ in 0..50 {
+        let summary = api
+            .get::<serde_json::Value>(&job_path)
+            .await
+            .unwrap()
+            .unwrap_data();
+        if summary["status"] != "running" {
+            return summary;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Import job did not finish");
+}
+
+async fn verification_token(smtp_rx: &mut mpsc::Receiver<MockMessage>, rcpt: &str) -> String {
+    let message = expect_message_delivery(smtp_rx).await;
+    assert_eq!(message.rcpt_to, vec![rcpt.to_string()]);
+    let body = mail_parser::MessageParser::new()
+        .parse(message.message.as_bytes())
+        .unwrap()
+        .body_text(0)
+        .unwrap()
+        .into_owned();
+    body.split_once("token=")
+        .and_then(|(_, token)| token.split_whitespace().next())
+        .unwrap_or_else(|| panic!("No token found in {body}"))
+        .to_string()
+}
+
+#[derive(Debug, Default)]
+struct EventKeys {
+    id: Option<u64>,
+    details: Option<String>,
+    elapsed: bool,
+    caused_by: bool,
+}
+
+async fn collect_events(
+    rx: &mut mpsc::Receiver<EventBatch>,
+    expected: usize,
+) -> Vec<(ProvisionEvent, EventKeys)> {
+    let mut events = Vec::new();
+
+    while events.len() < expected {
+        let batch = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("Timed out waiting for provisioning events")
+            .expect("Subscriber channel closed");
+
+        for event in batch {
+            if let EventType::Provision(typ) = event.inner.typ {
+                events.push((
+                    typ,
+                    EventKeys {
+                        id: event.value_as_uint(Key::Id),
+                        details: event
+                            .value(Key::Details)
+                            .and_then(|v| v.as_str())
+                            .map(|v| v.to_string()),
+                        elapsed: matches!(event.value(Key::Elapsed), Some(Value::Duration(_))),
+                        caused_by: event.value(Key::CausedBy).is_some(),
+                    },
+                ));
+            }
+        }
+    }
+
+    assert_eq!(events.len(), expected, "{events:?}");
+    events
+}
+
+async fn mailbox_roles(server: &Server, account_id: u32) -> Vec<(String, SpecialUse)> {
+    let mut mailboxes = Vec::new();
+    server
+        .archives(
+            account_id,
+            Collection::Mailbox,
+            &(),
+            |document_id, archive| {
+                let mailbox = archive.unarchive::<Mailbox>()?;
+                mailboxes.push((
+                    document_id,
+                    mailbox.name.to_string(),
+                    SpecialUse::from(&mailbox.role),
+                ));
+                Ok(true)
+            },
+        )
+        .await
+        .unwrap();
+    mailboxes.sort_unstable_by_key(|(document_id, _, _)| *document_id);
+    mailboxes
+        .into_iter()
+        .map(|(_, name, role)| (name, role))
+        .collect()
+}
+
+async fn blob_location(server: &Server, hash: &BlobHash) -> String {
+    server
+        .store()
+        .blob_location(hash)
+        .await
+        .unwrap()
+        .map(|location| location.store_id)
+        .unwrap_or_default()
+}
+
+async fn blob_encryption_key(server: &Server, hash: &BlobHash) -> Option<u32> {
+    server
+        .store()
+        .blob_location(hash)
+        .await
+        .unwrap()
+        .and_then(|location| location.encryption_key)
+}
+
+/// Delivers a scheduling request from jane@acme.org to a resource and
+/// returns the message the resource sends back, if any.
+async fn book_resource(
+    server: &Server,
+    access_token: &AccessToken,
+    resource: &str,
+    uid: &str,
+    start: &str,
+    end: &str,
+) -> Option<ItipMessage<ICalendar>> {
+    let request = format!(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\nMETHOD:REQUEST\r\n\
+         BEGIN:VEVENT\r\nUID:{uid}\r\nDTSTAMP:20260101T000000Z\r\n\
+         DTSTART:{start}\r\nDTEND:{end}\r\nSEQUENCE:0\r\nSUMMARY:{uid}\r\n\
+         ORGANIZER:mailto:jane@acme.org\r\n\
+         ATTENDEE;PARTSTAT=ACCEPTED:mailto:jane@acme.org\r\n\
+         ATTENDEE;PARTSTAT=NEEDS-ACTION;RSVP=TRUE;CUTYPE=RESOURCE:mailto:{resource}\r\n\
+         END:VEVENT\r\nEND:VCALENDAR\r\n"
+    );
+
+    match server
+        .itip_ingest(
+            access_token,
+            &access_token.as_resource_token(),
+            "jane@acme.org",
+            resource,
+            &request,
+        )
+        .await
+    {
+        Ok(message) => message,
+        Err(ItipIngestError::Message(err)) => panic!("Failed to book {uid}: {err}"),
+        Err(ItipIngestError::Internal(err)) => panic!("Failed to book {uid}: {err:?}"),
+    }
+}
+
+async fn jmap_collection_names(
+    name: &str,
+    secret: &str,
+    method: &str,
+    account_id: u32,
+) -> Vec<String> {
+    let response = serde_json::from_slice::<serde_json::Value>(
+        &reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .post("https://127.0.0.1:8899/jmap")
+            .basic_auth(name, Some(secret))
+            .body(
+                json!({
+                    "using": [
+                        "urn:ietf:params:jmap:core",
+                        "urn:ietf:params:jmap:calendars",
+                        "urn:ietf:params:jmap:contacts",
+                    ],
+                    "methodCalls": [[
+                        method,
+                        {"accountId": Id::from(account_id).to_string()},
+                        "0",
+                    ]],
+                })
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+
+    response["methodResponses"][0][1]["list"]
+        .as_array()
+        .unwrap_or_else(|| panic!("Unexpected response: {response}"))
+        .iter()
+        .filter_map(|item| item["name"].as_str().map(String::from))
+        .collect()
+}
+
+/// Logs in over a protocol that can be disabled per organization, returning
+/// the server's error when the login is refused.
+async fn webauthn_login(
+    authenticator: &TestAuthenticator,
+    account_id: u32,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Response<serde_json::Value> {
+    let api = ManagementApi::new(8899, "anonymous", "");
+    let options = api
+        .post::<serde_json::Value>(
+            "/auth/webauthn/start?domain=acme.org",
+            &json!({"username": username}),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    api.post::<serde_json::Value>(
+        "/auth/webauthn/finish?domain=acme.org",
+        &json!({
+            "challengeId": options["challengeId"],
+            "password": password,
+            "credential": authenticator.assertion(&options, account_id),
+        }),
+    )
+    .await
+    .unwrap()
+}
+
+async fn protocol_login(protocol: &str, name: &str, secret: &str) -> Result<(), String> {
+    let plain = STANDARD.encode(format!("\0{name}\0{secret}"));
+
+    match protocol {
+        "imap" => {
+            let mut imap = ImapConnection::connect(b"_x ").await;
+            imap.send(&format!("LOGIN \"{name}\" \"{secret}\"")).await;
+            let line = imap.read(Type::Tagged).await.pop().unwrap();
+            if line.starts_with("_x OK") {
+                Ok(())
+            } else {
+                Err(line)
+            }
+        }
+        "pop3" => {
+            let mut pop3 = Pop3Connection::connect().await;
+            pop3.read(false).await;
+            pop3.send(&format!("AUTH PLAIN {plain}")).await;
+            let line = pop3.read(false).await.pop().unwrap();
+            if line.starts_with("+OK") {
+                Ok(())
+            } else {
+                Err(line)
+            }
+        }
+        "submission" => {
+            let mut smtp = SmtpConnection::connect().await;
+            smtp.send(&format!("AUTH PLAIN {plain}")).await;
+            let line = smtp.read(1, u8::MAX).await.pop().unwrap();
+            if line.starts_with("235") {
+                Ok(())
+            } else {
+                Err(line)
+            }
+        }
+        "jmap" | "dav" => {
+            let client = reqwest::Client::builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .unwrap();
+            let request = if protocol == "jmap" {
+                client.get("https://127.0.0.1:8899/jmap/session")
+            } else {
+                client.request(
+                    reqwest::Method::from_bytes(b"PROPFIND").unwrap(),
+                    format!("https://127.0.0.1:8899/dav/card/{name}/"),
+                )
+            };
+            let response = request.basic_auth(name, Some(secret)).send().await.unwrap();
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(response.text().await.unwrap())
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Authenticator with an ES256 credential that signs with a "none" attestation.
+struct TestAuthenticator {
+    key_pair: EcdsaKeyPair,
+    id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl TestAuthenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        TestAuthenticator {
+            key_pair: EcdsaKeyPair::from_pkcs8(
+                &ECDSA_P256_SHA256_ASN1_SIGNING,
+                pkcs8.as_ref(),
+                &rng,
+            )
+            .unwrap(),
+            id: b"test-credential".to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    fn attestation(&self, options: &serde_json::Value, origin: &str) -> serde_json::Value {
+        let point = self.key_pair.public_key().as_ref();
+        let mut auth_data =
+            self.auth_data(options["publicKey"]["rp"]["id"].as_str().unwrap(), 0x45);
+        auth_data.extend([0u8; 16]);
+        auth_data.extend((self.id.len() as u16).to_be_bytes());
+        auth_data.extend(&self.id);
+        // COSE key {1: 2, 3: -7, -1: 1, -2: x, -3: y}
+        auth_data.extend([0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21, 0x58, 0x20]);
+        auth_data.extend(&point[1..33]);
+        auth_data.extend([0x22, 0x58, 0x20]);
+        auth_data.extend(&point[33..65]);
+
+        // {"fmt": "none", "attStmt": {}, "authData": auth_data}
+        let mut attestation = vec![0xa3, 0x63];
+        attestation.extend(b"fmt");
+        attestation.push(0x64);
+        attestation.extend(b"none");
+        attestation.push(0x67);
+        attestation.extend(b"attStmt");
+        attestation.extend([0xa0, 0x68]);
+        attestation.extend(b"authData");
+        attestation.push(0x59);
+        attestation.extend((auth_data.len() as u16).to_be_bytes());
+        attestation.extend(auth_data);
+
+        json!({
+            "id": URL_SAFE_NO_PAD.encode(&self.id),
+            "response": {
+                "clientDataJSON": self.client_data("webauthn.create", options, origin),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation),
+            },
+        })
+    }
+
+    fn assertion(&self, options: &serde_json::Value, account_id: u32) -> serde_json::Value {
+        let rp_id = options["publicKey"]["rpId"].as_str().unwrap();
+        let auth_data = self.auth_data(rp_id, 0x05);
+        let client_data = self.client_data("webauthn.get", options, &format!("https://{rp_id}"));
+        let mut message = auth_data.clone();
+        message.extend(
+            digest::digest(
+                &digest::SHA256,
+                &URL_SAFE_NO_PAD.decode(&client_data).unwrap(),
+            )
+            .as_ref(),
+        );
+        let signature = self.key_pair.sign(&SystemRandom::new(), &message).unwrap();
+
+        json!({
+            "id": URL_SAFE_NO_PAD.encode(&self.id),
+            "response": {
+                "clientDataJSON": client_data,
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                "userHandle": URL_SAFE_NO_PAD.encode(account_id.to_be_bytes()),
+            },
+        })
+    }
+
+    fn auth_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+        let mut auth_data = digest::digest(&digest::SHA256, rp_id.as_bytes())
+            .as_ref()
+            .to_vec();
+        auth_data.push(flags);
+        auth_data.extend(self.sign_count.to_be_bytes());
+        auth_data
+    }
+
+    fn client_data(&self, typ: &str, options: &serde_json::Value, origin: &str) -> String {
+        URL_SAFE_NO_PAD.encode(
+            json!({
+                "type": typ,
+                "challenge": options["publicKey"]["challenge"],
+                "origin": origin,
+            })
+            .to_string(),
+        )
+    }
+}
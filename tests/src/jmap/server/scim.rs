@@ -0,0 +1,404 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use crate::jmap::{JMAPTest, ManagementApi};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hyper::{
+    Method,
+    header::{AUTHORIZATION, CONTENT_TYPE, LOCATION},
+};
+use serde_json::{Value, json};
+
+const SCIM_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const SCIM_GROUP: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const SCIM_PATCH: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+const SCIM_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+struct ScimClient {
+    token: Option<String>,
+}
+
+struct ScimResponse {
+    status: u16,
+    location: Option<String>,
+    body: Value,
+}
+
+pub async fn test(_params: &mut JMAPTest) {
+    println!("Running SCIM provisioning tests...");
+
+    // Create a SCIM token for the acme tenant
+    let api = ManagementApi::new(8899, "admin", "secret");
+    api.post::<u32>(
+        "/api/principal",
+        &json!({
+            "type": "apiKey",
+            "name": "scim-okta@acme.org",
+            "secrets": ["scim-secret"],
+            "tenant": "acme",
+            "roles": ["tenant-admin"],
+        }),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let scim = ScimClient::new("scim-okta@acme.org", "scim-secret");
+
+    // Requests without credentials are rejected
+    let response = ScimClient { token: None }
+        .request(Method::GET, "/scim/v2/Users", None)
+        .await;
+    response.assert_error(401, None);
+
+    // The following sequence replays the requests sent by Okta when
+    // assigning, updating, pushing groups and unassigning a user
+
+    // Okta checks whether the user exists before creating it
+    let response = scim
+        .request(
+            Method::GET,
+            "/scim/v2/Users?filter=userName%20eq%20%22jdoe%40acme.org%22&startIndex=1&count=100",
+            None,
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["totalResults"], 0);
+    assert_eq!(response.body["Resources"], json!([]));
+
+    // Create user
+    let user = json!({
+        "schemas": [SCIM_USER],
+        "userName": "jdoe@acme.org",
+        "name": {
+            "givenName": "John",
+            "familyName": "Doe"
+        },
+        "emails": [{
+            "primary": true,
+            "value": "jdoe@acme.org",
+            "type": "work"
+        }],
+        "displayName": "John Doe",
+        "locale": "en-US",
+        "externalId": "00u1a2b3c4DEF5g6h7",
+        "groups": [],
+        "password": "Okta-s3cret!",
+        "active": true
+    });
+    let response = scim
+        .request(Method::POST, "/scim/v2/Users", Some(&user))
+        .await;
+    assert_eq!(response.status, 201, "{}", response.body);
+    let user_id = response.body["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        response.location.as_deref(),
+        Some(format!("/scim/v2/Users/{user_id}").as_str())
+    );
+    assert_eq!(response.body["userName"], "jdoe@acme.org");
+    assert_eq!(response.body["displayName"], "John Doe");
+    assert_eq!(response.body["externalId"], "00u1a2b3c4DEF5g6h7");
+    assert_eq!(response.body["active"], true);
+    assert_eq!(response.body["emails"][0]["value"], "jdoe@acme.org");
+    assert_eq!(response.body["emails"][0]["primary"], true);
+    assert_eq!(response.body["meta"]["resourceType"], "User");
+
+    // Creating the same user again fails
+    scim.request(Method::POST, "/scim/v2/Users", Some(&user))
+        .await
+        .assert_error(409, Some("uniqueness"));
+
+    // Users can be found by userName and externalId
+    for filter in [
+        "userName%20eq%20%22jdoe%40acme.org%22",
+        "externalId%20eq%20%2200u1a2b3c4DEF5g6h7%22",
+    ] {
+        let response = scim
+            .request(
+                Method::GET,
+                &format!("/scim/v2/Users?filter={filter}"),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(response.body["totalResults"], 1);
+        assert_eq!(response.body["Resources"][0]["id"], user_id.as_str());
+    }
+    let response = scim
+        .request(Method::GET, &format!("/scim/v2/Users/{user_id}"), None)
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["userName"], "jdoe@acme.org");
+
+    // Deactivate user
+    let response = scim
+        .request(
+            Method::PATCH,
+            &format!("/scim/v2/Users/{user_id}"),
+            Some(&json!({
+                "schemas": [SCIM_PATCH],
+                "Operations": [{
+                    "op": "replace",
+                    "value": {
+                        "active": false
+                    }
+                }]
+            })),
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["active"], false);
+
+    // Profile push replaces the whole user
+    let response = scim
+        .request(
+            Method::PUT,
+            &format!("/scim/v2/Users/{user_id}"),
+            Some(&json!({
+                "schemas": [SCIM_USER],
+                "id": user_id,
+                "userName": "jdoe@acme.org",
+                "name": {
+                    "givenName": "Johnny",
+                    "familyName": "Doe"
+                },
+                "emails": [{
+                    "primary": true,
+                    "value": "jdoe@acme.org",
+                    "type": "work"
+                }, {
+                    "value": "johnny@acme.org",
+                    "type": "work"
+                }],
+                "active": true
+            })),
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["displayName"], "Johnny Doe");
+    assert_eq!(response.body["active"], true);
+    assert!(
+        response.body.get("externalId").is_none(),
+        "{}",
+        response.body
+    );
+    assert_eq!(
+        response.body["emails"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|email| email["value"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        vec!["jdoe@acme.org", "johnny@acme.org"]
+    );
+
+    // The externalId falls back to the principal's metadata
+    api.patch::<()>(
+        "/api/principal/jdoe@acme.org",
+        &json!([{
+            "action": "set",
+            "field": "metadata",
+            "value": {"scim.externalId": "00u9z8y7x6WVU5t4s3"}
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let response = scim
+        .request(
+            Method::GET,
+            "/scim/v2/Users?filter=externalId%20eq%20%2200u9z8y7x6WVU5t4s3%22",
+            None,
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["totalResults"], 1);
+    assert_eq!(
+        response.body["Resources"][0]["externalId"],
+        "00u9z8y7x6WVU5t4s3"
+    );
+
+    // Push group
+    let response = scim
+        .request(
+            Method::POST,
+            "/scim/v2/Groups",
+            Some(&json!({
+                "schemas": [SCIM_GROUP],
+                "displayName": "Sales",
+                "members": []
+            })),
+        )
+        .await;
+    assert_eq!(response.status, 201, "{}", response.body);
+    let group_id = response.body["id"].as_str().unwrap().to_string();
+    assert_eq!(response.body["displayName"], "Sales");
+    assert_eq!(response.body["members"], json!([]));
+
+    // Add group members
+    let response = scim
+        .request(
+            Method::PATCH,
+            &format!("/scim/v2/Groups/{group_id}"),
+            Some(&json!({
+                "schemas": [SCIM_PATCH],
+                "Operations": [{
+                    "op": "add",
+                    "path": "members",
+                    "value": [{
+                        "value": user_id,
+                        "display": "jdoe@acme.org"
+                    }]
+                }]
+            })),
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["members"][0]["value"], user_id.as_str());
+    let response = scim
+        .request(Method::GET, &format!("/scim/v2/Users/{user_id}"), None)
+        .await;
+    assert_eq!(response.body["groups"][0]["value"], group_id.as_str());
+    assert_eq!(response.body["groups"][0]["display"], "Sales");
+
+    // Groups can be found by displayName and member
+    let response = scim
+        .request(
+            Method::GET,
+            &format!(
+                "/scim/v2/Groups?filter=displayName%20eq%20%22Sales%22%20and%20members%20eq%20%22{user_id}%22&excludedAttributes=members"
+            ),
+            None,
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["totalResults"], 1);
+    assert_eq!(response.body["Resources"][0]["id"], group_id.as_str());
+    assert!(response.body["Resources"][0].get("members").is_none());
+
+    // Remove group members
+    let response = scim
+        .request(
+            Method::PATCH,
+            &format!("/scim/v2/Groups/{group_id}"),
+            Some(&json!({
+                "schemas": [SCIM_PATCH],
+                "Operations": [{
+                    "op": "remove",
+                    "path": format!("members[value eq \"{user_id}\"]")
+                }]
+            })),
+        )
+        .await;
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body["members"], json!([]));
+
+    // Unsupported filters and missing resources return SCIM errors
+    scim.request(
+        Method::GET,
+        "/scim/v2/Users?filter=userName%20sw%20%22j%22",
+        None,
+    )
+    .await
+    .assert_error(400, Some("invalidFilter"));
+    scim.request(Method::GET, "/scim/v2/Users/4294967295", None)
+        .await
+        .assert_error(404, None);
+    scim.request(Method::GET, &format!("/scim/v2/Users/{group_id}"), None)
+        .await
+        .assert_error(404, None);
+
+    // Principals outside the tenant are not visible
+    let response = scim
+        .request(
+            Method::GET,
+            "/scim/v2/Users?filter=userName%20eq%20%22admin%22",
+            None,
+        )
+        .await;
+    assert_eq!(response.body["totalResults"], 0);
+
+    // Unassign user and delete group
+    for path in [
+        format!("/scim/v2/Groups/{group_id}"),
+        format!("/scim/v2/Users/{user_id}"),
+    ] {
+        let response = scim.request(Method::DELETE, &path, None).await;
+        assert_eq!(response.status, 204, "{}", response.body);
+        scim.request(Method::GET, &path, None)
+            .await
+            .assert_error(404, None);
+    }
+
+    api.delete::<()>("/api/principal/scim-okta@acme.org")
+        .await
+        .unwrap()
+        .unwrap_data();
+}
+
+impl ScimClient {
+    fn new(name: &str, secret: &str) -> Self {
+        Self {
+            token: Some(format!(
+                "api_{}",
+                STANDARD.encode(format!("{name}:{secret}").as_bytes())
+            )),
+        }
+    }
+
+    async fn request(&self, method: Method, path: &str, body: Option<&Value>) -> ScimResponse {
+        let mut request = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .request(method, format!("https://127.0.0.1:8899{path}"));
+
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, "application/scim+json")
+                .body(body.to_string());
+        }
+
+        let response = request.send().await.unwrap();
+        let status = response.status().as_u16();
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let bytes = response.bytes().await.unwrap();
+
+        ScimResponse {
+            status,
+            location,
+            body: if bytes.is_empty() {
+                Value::Null
+            } else {
+                serde_json::from_slice(&bytes)
+                    .unwrap_or_else(|err| panic!("{err}: {}", String::from_utf8_lossy(&bytes)))
+            },
+        }
+    }
+}
+
+impl ScimResponse {
+    fn assert_error(&self, status: u16, scim_type: Option<&str>) {
+        assert_eq!(self.status, status, "{}", self.body);
+        assert_eq!(self.body["schemas"], json!([SCIM_ERROR]), "{}", self.body);
+        assert_eq!(self.body["status"], status.to_string(), "{}", self.body);
+        assert_eq!(
+            self.body.get("scimType").and_then(|v| v.as_str()),
+            scim_type,
+            "{}",
+            self.body
+        );
+    }
+}
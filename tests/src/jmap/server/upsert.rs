@@ -0,0 +1,185 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::jmap::{JMAPTest, ManagementApi};
+use directory::backend::internal::{
+    PrincipalAction, PrincipalField, PrincipalSet, PrincipalUpdate, PrincipalValue,
+};
+use serde_json::json;
+
+#[derive(Debug, serde::Deserialize)]
+struct UpsertResponse {
+    id: u32,
+    result: String,
+    changes: Vec<PrincipalUpdate>,
+}
+
+pub async fn test(_params: &mut JMAPTest) {
+    println!("Running principal upsert tests...");
+
+    let api = ManagementApi::new(8899, "admin", "secret");
+    let url = "/api/principal/upsert@example.com?upsert=1";
+
+    // Create principal
+    let principal = json!({
+        "type": "individual",
+        "secrets": ["upsert-secret"],
+        "emails": ["upsert@example.com"],
+        "description": "Upsert User",
+        "memberOf": ["sales@example.com"],
+    });
+    let response = api
+        .put::<UpsertResponse>(url, &principal)
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(response.result, "created");
+    let account_id = response.id;
+    assert!(
+        response
+            .changes
+            .iter()
+            .any(|change| change.field == PrincipalField::Secrets),
+        "{response:?}"
+    );
+    assert!(
+        !format!("{:?}", response.changes).contains("upsert-secret"),
+        "{response:?}"
+    );
+
+    // Applying the same state again is a no-op
+    let response = api
+        .put::<UpsertResponse>(url, &principal)
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(response.result, "unchanged", "{response:?}");
+    assert_eq!(response.id, account_id);
+    assert!(response.changes.is_empty(), "{response:?}");
+
+    // Only the changed fields are updated, absent fields are left untouched
+    let response = api
+        .put::<UpsertResponse>(
+            url,
+            &json!({
+                "type": "individual",
+                "name": "upsert@example.com",
+                "description": "Upserted User",
+                "secrets": ["upsert-secret"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(response.result, "updated");
+    assert_eq!(
+        response.changes,
+        vec![PrincipalUpdate::set(
+            PrincipalField::Description,
+            PrincipalValue::String("Upserted User".to_string())
+        )]
+    );
+    let principal = api
+        .get::<PrincipalSet>("/api/principal/upsert@example.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(
+        principal.get_str(PrincipalField::Description),
+        Some("Upserted User")
+    );
+    assert_eq!(
+        principal.get_str_array(PrincipalField::MemberOf),
+        Some(&["sales@example.com".to_string()][..])
+    );
+    let secret = principal
+        .get_str_array(PrincipalField::Secrets)
+        .unwrap()
+        .to_vec();
+
+    // A new password replaces the stored one
+    let response = api
+        .put::<UpsertResponse>(
+            url,
+            &json!({
+                "type": "individual",
+                "secrets": ["new-upsert-secret"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(response.result, "updated");
+    assert_eq!(
+        response.changes,
+        vec![PrincipalUpdate {
+            action: PrincipalAction::AddItem,
+            field: PrincipalField::Secrets,
+            value: PrincipalValue::String("********".to_string()),
+        }]
+    );
+
+    // Pruning removes absent fields but never the stored secrets
+    let response = api
+        .put::<UpsertResponse>(
+            &format!("{url}&prune=1"),
+            &json!({
+                "type": "individual",
+                "emails": ["upsert@example.com"],
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(response.result, "updated");
+    assert!(
+        response.changes.contains(&PrincipalUpdate::set(
+            PrincipalField::Description,
+            PrincipalValue::String(String::new())
+        )),
+        "{response:?}"
+    );
+    assert!(
+        response
+            .changes
+            .iter()
+            .all(|change| change.field != PrincipalField::Secrets),
+        "{response:?}"
+    );
+    let principal = api
+        .get::<PrincipalSet>("/api/principal/upsert@example.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(principal.get_str(PrincipalField::Description), None);
+    assert_eq!(principal.get_str_array(PrincipalField::MemberOf), None);
+    let new_secret = principal
+        .get_str_array(PrincipalField::Secrets)
+        .unwrap()
+        .to_vec();
+    assert_eq!(new_secret.len(), 1);
+    assert_ne!(new_secret, secret);
+
+    // The principal type cannot be changed
+    api.put::<UpsertResponse>(url, &json!({"type": "group"}))
+        .await
+        .unwrap()
+        .expect_error("Invalid principal type");
+
+    // The name in the body has to match the URL
+    api.put::<UpsertResponse>(
+        url,
+        &json!({"type": "individual", "name": "other@example.com"}),
+    )
+    .await
+    .unwrap()
+    .expect_error("Invalid principal name");
+
+    api.delete::<()>("/api/principal/upsert@example.com")
+        .await
+        .unwrap()
+        .unwrap_data();
+}
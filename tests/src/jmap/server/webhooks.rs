@@ -12,7 +12,7 @@ use std::{
     time::Duration,
 };
 
-use crate::jmap::JMAPTest;
+use crate::jmap::{JMAPTest, ManagementApi};
 use base64::{Engine, engine::general_purpose::STANDARD};
 use common::manager::webadmin::Resource;
 use http_proto::{ToHttpResponse, request::fetch_body};
@@ -21,6 +21,7 @@ use hyper_util::rt::TokioIo;
 use jmap::api::ToJmapHttpResponse;
 use jmap_proto::error::request::RequestError;
 use ring::hmac;
+use serde_json::json;
 use store::parking_lot::Mutex;
 use tokio::{net::TcpListener, sync::watch};
 
@@ -43,6 +44,82 @@ pub async fn test(params: &mut JMAPTest) {
 
     // Check for events
     params.webhook.assert_contains(&["auth.success"]);
+
+    // Test deliveries are signed like real events and reported back
+    let api = ManagementApi::new(8899, "admin", "secret");
+    let result = api
+        .post::<serde_json::Value>("/api/settings/webhooks/test/test", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(result["success"], true, "{result}");
+    assert_eq!(result["response"]["status"], 200, "{result}");
+    assert_eq!(result["response"]["body"], "[]", "{result}");
+    params.webhook.assert_contains(&["\"type\": \"test\""]);
+
+    // The test delivery is recorded and flagged as such
+    let deliveries = api
+        .get::<serde_json::Value>("/api/settings/webhooks/test/deliveries")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(deliveries["items"][0]["test"], true, "{deliveries}");
+    assert_eq!(deliveries["items"][0]["status"], 200, "{deliveries}");
+    assert!(
+        deliveries["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|delivery| delivery["test"] == false),
+        "{deliveries}"
+    );
+
+    // Connection failures are diagnosed
+    api.post::<serde_json::Value>(
+        "/api/settings",
+        &json!([{
+            "type": "insert",
+            "prefix": "webhook.unreachable",
+            "values": [["url", "http://127.0.0.1:1/hook"], ["timeout", "1s"]],
+            "assert_empty": true,
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
+    let result = api
+        .post::<serde_json::Value>("/api/settings/webhooks/unreachable/test", &json!({}))
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert_eq!(result["success"], false, "{result}");
+    assert_eq!(result["error"]["kind"], "connect", "{result}");
+    api.post::<serde_json::Value>("/api/settings/webhooks/missing/test", &json!({}))
+        .await
+        .unwrap()
+        .expect_error("notFound");
+
+    // Test deliveries are rate limited per webhook
+    for _ in 0..4 {
+        api.post::<serde_json::Value>("/api/settings/webhooks/unreachable/test", &json!({}))
+            .await
+            .unwrap()
+            .unwrap_data();
+    }
+    api.post::<serde_json::Value>("/api/settings/webhooks/unreachable/test", &json!({}))
+        .await
+        .unwrap()
+        .expect_request_error("Too Many Requests");
+    api.post::<serde_json::Value>(
+        "/api/settings",
+        &json!([{
+            "type": "clear",
+            "prefix": "webhook.unreachable.",
+        }]),
+    )
+    .await
+    .unwrap()
+    .unwrap_data();
 }
 
 impl MockWebhookEndpoint {
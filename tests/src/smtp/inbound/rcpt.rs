@@ -6,7 +6,20 @@
 
 use std::time::Duration;
 
-use common::Core;
+use common::{
+    Core,
+    config::{
+        smtp::queue::{
+            DomainRoute, DomainRouteAction, LocalPartMatch, RoutingStrategy, TenantRelay,
+            TenantRelayTls,
+        },
+        spamfilter::TenantSpamSettings,
+    },
+};
+use directory::{
+    Type,
+    backend::internal::{PrincipalField, PrincipalSet, manage::ManageDirectory},
+};
 
 use smtp_proto::{RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS};
 use store::Stores;
@@ -163,3 +176,279 @@ async fn rcpt() {
     assert!((rcpt.flags & (RCPT_NOTIFY_DELAY | RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_FAILURE)) != 0);
     assert_eq!(rcpt.dsn_info.as_ref().unwrap(), "Jane.Doe@Foobar.org");
 }
+
+const GREYLIST_CONFIG: &str = r#"
+[storage]
+data = "rocksdb"
+lookup = "rocksdb"
+blob = "rocksdb"
+fts = "rocksdb"
+
+[store."rocksdb"]
+type = "rocksdb"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "john"
+secret = "secret"
+email = "john@acme.org"
+
+[[directory."local".principals]]
+name = "mike"
+secret = "secret"
+email = "mike@acme.org"
+
+[[directory."local".principals]]
+name = "jane"
+secret = "secret"
+email = "jane@globex.org"
+
+[session.rcpt]
+directory = "'local'"
+
+[spam-filter.grey-list]
+duration = "1d"
+"#;
+
+#[tokio::test]
+async fn rcpt_tenant_greylist() {
+    // Enable logging
+    crate::enable_logging();
+
+    let tmp_dir = TempDir::new("smtp_rcpt_greylist_test", true);
+    let mut config = Config::new(tmp_dir.update_config(GREYLIST_CONFIG)).unwrap();
+    let stores = Stores::parse_all(&mut config, false).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let server = TestSMTP::from_core(core).server;
+
+    // Create two tenants owning one domain each
+    let mut tenant_ids = Vec::new();
+    for (tenant, domain) in [("acme", "acme.org"), ("globex", "globex.org")] {
+        let tenant_id = server
+            .store()
+            .create_principal(
+                PrincipalSet::new(0, Type::Tenant).with_field(PrincipalField::Name, tenant),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .id;
+        server
+            .store()
+            .create_principal(
+                PrincipalSet::new(0, Type::Domain).with_field(PrincipalField::Name, domain),
+                Some(tenant_id),
+                None,
+            )
+            .await
+            .unwrap();
+        tenant_ids.push(tenant_id);
+    }
+
+    // Acme delays unknown senders, Globex opts out of greylisting
+    server
+        .update_tenant_spam_settings(
+            tenant_ids[0],
+            TenantSpamSettings {
+                greylist: Some(true),
+                greylist_delay: Some(1),
+                greylist_whitelist_after: Some(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    server
+        .update_tenant_spam_settings(
+            tenant_ids[1],
+            TenantSpamSettings {
+                greylist: Some(false),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let mut session = Session::test(server.clone());
+    session.data.remote_ip_str = "10.0.0.5".into();
+    session.eval_session_params().await;
+    session.ehlo("mx.remote.org").await;
+    session.mail_from("bill@remote.org", "250").await;
+    session.rcpt_to("john@acme.org", "452 4.2.2").await;
+    session.rcpt_to("jane@globex.org", "250").await;
+
+    // Retries are accepted once the initial delay has passed
+    session.rcpt_to("john@acme.org", "452 4.2.2").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    session.rcpt_to("john@acme.org", "250").await;
+
+    // The sender is whitelisted after passing greylisting once
+    session.rcpt_to("mike@acme.org", "250").await;
+
+    // Toggling greylisting only resets the trackers of that tenant
+    session.rset().await;
+    session.mail_from("tom@remote.org", "250").await;
+    session.rcpt_to("john@acme.org", "452 4.2.2").await;
+    server
+        .update_tenant_spam_settings(
+            tenant_ids[1],
+            TenantSpamSettings {
+                greylist: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    session.rcpt_to("jane@globex.org", "452 4.2.2").await;
+    session.rcpt_to("jane@globex.org", "250").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    session.rcpt_to("john@acme.org", "250").await;
+}
+
+#[tokio::test]
+async fn rcpt_domain_routes() {
+    // Enable logging
+    crate::enable_logging();
+
+    let tmp_dir = TempDir::new("smtp_rcpt_routes_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG)).unwrap();
+    let stores = Stores::parse_all(&mut config, false).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let server = TestSMTP::from_core(core).server;
+
+    let route = |id: &str, priority: u32, matcher: LocalPartMatch, action: DomainRouteAction| {
+        let route = DomainRoute {
+            id: id.to_string(),
+            priority,
+            matcher,
+            action,
+        };
+        assert_eq!(route.validate(), Ok(()));
+        route
+    };
+    server
+        .update_domain_routes(
+            "foobar.org",
+            vec![
+                route(
+                    "1",
+                    10,
+                    LocalPartMatch::Prefix {
+                        value: "inv".to_string(),
+                    },
+                    DomainRouteAction::Reject {
+                        message: "Not accepted here".to_string(),
+                    },
+                ),
+                route(
+                    "2",
+                    1,
+                    LocalPartMatch::Exact {
+                        value: "invoices".to_string(),
+                    },
+                    DomainRouteAction::Forward {
+                        address: "tickets@helpdesk.example.net".to_string(),
+                    },
+                ),
+                route(
+                    "3",
+                    5,
+                    LocalPartMatch::Regex {
+                        value: "sales-[a-z]+".to_string(),
+                    },
+                    DomainRouteAction::Relay(TenantRelay {
+                        host: "crm.example.net".to_string(),
+                        port: 25,
+                        tls: TenantRelayTls::StartTls,
+                        allow_invalid_certs: false,
+                        username: None,
+                        secret: None,
+                    }),
+                ),
+                route(
+                    "4",
+                    0,
+                    LocalPartMatch::Exact {
+                        value: "jane".to_string(),
+                    },
+                    DomainRouteAction::Local,
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+
+    // Invalid rules are rejected
+    for (matcher, action) in [
+        (
+            LocalPartMatch::Regex {
+                value: "sales-(".to_string(),
+            },
+            DomainRouteAction::Local,
+        ),
+        (
+            LocalPartMatch::Exact {
+                value: "user@foobar.org".to_string(),
+            },
+            DomainRouteAction::Local,
+        ),
+        (
+            LocalPartMatch::Exact {
+                value: "billing".to_string(),
+            },
+            DomainRouteAction::Forward {
+                address: "not an address".to_string(),
+            },
+        ),
+    ] {
+        let route = DomainRoute {
+            id: String::new(),
+            priority: 0,
+            matcher,
+            action,
+        };
+        assert!(route.validate().is_err(), "{route:?}");
+    }
+
+    // Relayed recipients are routed to the rule's host on delivery, which
+    // may only resolve to public addresses
+    let routes = server.domain_routes("foobar.org").unwrap();
+    assert!(matches!(
+        routes.relay("sales-eu"),
+        Some(RoutingStrategy::Relay(relay))
+            if relay.address == "crm.example.net" && relay.public_only
+    ));
+    assert!(routes.relay("sales-").is_none());
+    assert!(routes.relay("invoices").is_none());
+
+    let mut session = Session::test(server.clone());
+    session.data.remote_ip_str = "10.0.0.2".into();
+    session.eval_session_params().await;
+    session.ehlo("mx.remote.org").await;
+    session.mail_from("bill@remote.org", "250").await;
+
+    // Rules are evaluated in priority order before resolving the recipient
+    session.rcpt_to("Invoices@foobar.org", "250").await;
+    assert_eq!(
+        session.data.rcpt_to.last().unwrap().address_lcase,
+        "tickets@helpdesk.example.net"
+    );
+    session
+        .rcpt_to("invalid@foobar.org", "550 5.7.1 Not accepted here")
+        .await;
+    session.rcpt_to("sales-eu@foobar.org", "250").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+    session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+
+    // Removing the rules restores the directory lookup
+    server
+        .update_domain_routes("foobar.org", vec![])
+        .await
+        .unwrap();
+    assert!(server.domain_routes("foobar.org").is_none());
+    session.rcpt_to("sales-us@foobar.org", "550 5.1.2").await;
+}
@@ -4,14 +4,21 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use common::Core;
+use common::{
+    Core, Data,
+    auth::{AccessToken, TenantInfo},
+};
 
 use mail_auth::{
     common::{parse::TxtRecordParser, verify::DomainKey},
     spf::Spf,
 };
+use mail_parser::MessageParser;
 use store::Stores;
 use utils::config::Config;
 
@@ -219,3 +226,229 @@ async fn sign_and_seal() {
             "ARC-Message-Signature: i=1; a=ed25519-sha256; s=ed; d=example.com; c=relaxed/simple;",
         );
 }
+
+const TENANT_POLICIES: &str = r#"
+[session.auth]
+must-match-sender = false
+
+[auth.dkim.tenant.1.policy]
+headers = "From:To:Subject"
+oversign = true
+canonicalization.header = "relaxed"
+canonicalization.body = "relaxed"
+
+[auth.dkim.tenant.2.policy]
+headers = "From:To:Subject"
+
+[auth.dkim.tenant.2.domain."example.com"]
+headers = "From:Date"
+canonicalization.header = "simple"
+canonicalization.body = "simple"
+body-length = true
+"#;
+
+#[tokio::test]
+async fn tenant_signing_policy() {
+    // Enable logging
+    crate::enable_logging();
+
+    let tmp_dir = TempDir::new("smtp_tenant_sign_test", true);
+    let mut config =
+        Config::new(tmp_dir.update_config(CONFIG.to_string() + SIGNATURES + TENANT_POLICIES))
+            .unwrap();
+    let stores = Stores::parse_all(&mut config, false).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let data = Data::parse(&mut config, &core);
+    let test = TestSMTP::from_core_and_data(core, data);
+    test.server.txt_add(
+        "example.com",
+        Spf::parse(b"v=spf1 ip4:10.0.0.1 -all").unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+
+    let mut qr = test.queue_receiver;
+    let mut session = Session::test(test.server);
+    session.data.remote_ip_str = "10.0.0.1".into();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+
+    // Tenants without a policy use the signature settings
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john".to_string(),
+        tenant: Some(TenantInfo { id: 3, quota: 0 }),
+        ..Default::default()
+    }));
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = unfold(qr.expect_message().await.read_message(&qr).await);
+    assert!(
+        message.contains("d=example.com; c=simple/relaxed;"),
+        "{message}"
+    );
+    assert!(
+        message.contains("h=From:To:Date:Subject:Message-ID;"),
+        "{message}"
+    );
+
+    // The tenant policy replaces the signed headers and canonicalization
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john".to_string(),
+        tenant: Some(TenantInfo { id: 1, quota: 0 }),
+        ..Default::default()
+    }));
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = unfold(qr.expect_message().await.read_message(&qr).await);
+    assert!(
+        message.contains("d=example.com; c=relaxed/relaxed;"),
+        "{message}"
+    );
+    assert!(
+        message.contains("h=From:From:To:To:Subject:Subject;"),
+        "{message}"
+    );
+
+    // Domain policies take precedence over the tenant policy
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john".to_string(),
+        tenant: Some(TenantInfo { id: 2, quota: 0 }),
+        ..Default::default()
+    }));
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = unfold(qr.expect_message().await.read_message(&qr).await);
+    assert!(
+        message.contains("d=example.com; c=simple/simple;"),
+        "{message}"
+    );
+    assert!(message.contains("h=From:Date;"), "{message}");
+}
+
+const TENANT_DISCLAIMER: &str = r#"
+[session.disclaimer.tenant.1]
+text = "Sent by {{name}} <{{email}}>."
+html = "<p>Sent by <b>{{name}}</b>.</p>"
+exclude-replies = true
+"#;
+
+#[tokio::test]
+async fn tenant_disclaimer() {
+    // Enable logging
+    crate::enable_logging();
+
+    let tmp_dir = TempDir::new("smtp_tenant_disclaimer_test", true);
+    let mut config =
+        Config::new(tmp_dir.update_config(CONFIG.to_string() + SIGNATURES + TENANT_DISCLAIMER))
+            .unwrap();
+    let stores = Stores::parse_all(&mut config, false).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let data = Data::parse(&mut config, &core);
+    let test = TestSMTP::from_core_and_data(core, data);
+    test.server.txt_add(
+        "example.com",
+        Spf::parse(b"v=spf1 ip4:10.0.0.1 -all").unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+
+    let mut qr = test.queue_receiver;
+    let mut session = Session::test(test.server);
+    session.data.remote_ip_str = "10.0.0.1".into();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john".to_string(),
+        description: Some("John Doe".to_string()),
+        tenant: Some(TenantInfo { id: 1, quota: 0 }),
+        ..Default::default()
+    }));
+
+    // The disclaimer is added before the message is signed
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            concat!(
+                "From: bill@foobar.org\r\n",
+                "To: jdoe@example.com\r\n",
+                "Subject: Lunch\r\n",
+                "Content-Type: multipart/alternative; boundary=\"b\"\r\n\r\n",
+                "--b\r\n",
+                "Content-Type: text/plain\r\n\r\n",
+                "Lunch at noon?\r\n",
+                "--b\r\n",
+                "Content-Type: text/html\r\n\r\n",
+                "<html><body><p>Lunch at noon?</p></body></html>\r\n",
+                "--b--\r\n",
+            ),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(message.contains("DKIM-Signature: "), "{message}");
+    let parsed = MessageParser::new().parse(&message).unwrap();
+    assert_eq!(
+        parsed.body_text(0).unwrap(),
+        "Lunch at noon?\r\n\r\nSent by John Doe <bill@foobar.org>.\r\n"
+    );
+    assert_eq!(
+        parsed.body_html(0).unwrap(),
+        "<html><body><p>Lunch at noon?</p><p>Sent by <b>John Doe</b>.</p></body></html>"
+    );
+
+    // Replies are left untouched
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            concat!(
+                "From: bill@foobar.org\r\n",
+                "To: jdoe@example.com\r\n",
+                "Subject: Re: Lunch\r\n",
+                "In-Reply-To: <lunch@example.com>\r\n\r\n",
+                "Sure.\r\n",
+            ),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(!message.contains("Sent by"), "{message}");
+
+    // Accounts without a tenant disclaimer are not affected
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john".to_string(),
+        tenant: Some(TenantInfo { id: 2, quota: 0 }),
+        ..Default::default()
+    }));
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(!message.contains("Sent by"), "{message}");
+}
+
+fn unfold(message: String) -> String {
+    message.replace("\r\n\t", "")
+}
@@ -172,6 +172,10 @@ impl TestSMTP {
         Self::from_core_and_tempdir(core, Default::default(), None)
     }
 
+    pub fn from_core_and_data(core: Core, data: Data) -> Self {
+        Self::from_core_and_tempdir(core, data, None)
+    }
+
     pub fn inner_with_rxs(&self) -> (Arc<Inner>, IpcReceivers) {
         let (ipc, ipc_rxs) = build_ipc(false);
 
@@ -235,7 +239,7 @@ impl TestSMTP {
         config.resolve_all_macros().await;
         let stores = Stores::parse_all(&mut config, false).await;
         let core = Core::parse(&mut config, stores, Default::default()).await;
-        let data = Data::parse(&mut config);
+        let data = Data::parse(&mut config, &core);
         store_destroy(&core.storage.data).await;
 
         Self::from_core_and_tempdir(core, data, Some(temp_dir))
@@ -295,6 +299,12 @@ pub trait DnsCache {
         value: impl Into<Txt>,
         valid_until: std::time::Instant,
     );
+    fn txt_records_add<'x>(
+        &self,
+        name: impl IntoFqdn<'x>,
+        value: Vec<String>,
+        valid_until: std::time::Instant,
+    );
     fn ipv4_add<'x>(
         &self,
         name: impl IntoFqdn<'x>,
@@ -332,6 +342,19 @@ impl DnsCache for Server {
         );
     }
 
+    fn txt_records_add<'x>(
+        &self,
+        name: impl IntoFqdn<'x>,
+        value: Vec<String>,
+        valid_until: std::time::Instant,
+    ) {
+        self.inner.cache.dns_txt_records.insert_with_expiry(
+            name.into_fqdn().into_owned(),
+            Arc::new(value),
+            valid_until,
+        );
+    }
+
     fn ipv4_add<'x>(
         &self,
         name: impl IntoFqdn<'x>,
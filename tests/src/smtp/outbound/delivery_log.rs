@@ -0,0 +1,140 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{
+    auth::{AccessToken, TenantInfo},
+    config::server::ServerProtocol,
+    ipc::QueueEvent,
+};
+use mail_auth::MX;
+use smtp::queue::delivery_log::{DeliveryOutcome, SmtpDeliveryLog};
+
+use crate::smtp::{DnsCache, TestSMTP, session::TestSession};
+
+const LOCAL: &str = r#"
+[session.rcpt]
+relay = true
+max-recipients = 100
+
+[session.auth]
+must-match-sender = false
+
+[queue.delivery-log]
+retention = "1d"
+
+[spam-filter]
+enable = false
+"#;
+
+const REMOTE: &str = r#"
+[session.rcpt]
+relay = true
+
+[session.ehlo]
+reject-non-fqdn = false
+
+[spam-filter]
+enable = false
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn delivery_log() {
+    // Enable logging
+    crate::enable_logging();
+
+    // Start test server
+    let remote = TestSMTP::new("smtp_delivery_log_remote", REMOTE).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let mut local = TestSMTP::new("smtp_delivery_log_local", LOCAL).await;
+
+    // Add mock DNS entries
+    let core = local.build_smtp();
+    core.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Messages submitted by tenant users are recorded in the tenant's delivery log
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".into();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john@test.org".to_string(),
+        tenant: Some(TenantInfo { id: 1, quota: 0 }),
+        ..Default::default()
+    }));
+    session
+        .send_message(
+            "john@test.org",
+            &["ok@foobar.org", "delay@foobar.org", "fail@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = local.queue_receiver.expect_message().await;
+    let queue_id = message.queue_id;
+    let log = core.read_delivery_log(1, queue_id).await.unwrap().unwrap();
+    assert_eq!(log.return_path, "john@test.org");
+    assert_eq!(log.recipients.len(), 3);
+    assert!(
+        log.recipients
+            .iter()
+            .all(|rcpt| rcpt.outcome == DeliveryOutcome::Queued)
+    );
+    assert!(log.attempts.is_empty());
+    assert_eq!(core.read_delivery_log(2, queue_id).await.unwrap(), None);
+
+    // Record the outcome of the delivery attempt
+    local
+        .queue_receiver
+        .delivery_attempt(queue_id)
+        .await
+        .try_deliver(core.clone());
+    loop {
+        if let QueueEvent::WorkerDone { .. } = local.queue_receiver.read_event().await {
+            break;
+        }
+    }
+    let log = core.read_delivery_log(1, queue_id).await.unwrap().unwrap();
+    for (address, outcome) in [
+        ("ok@foobar.org", DeliveryOutcome::Delivered),
+        ("delay@foobar.org", DeliveryOutcome::Deferred),
+        ("fail@foobar.org", DeliveryOutcome::Bounced),
+    ] {
+        let rcpt = log
+            .recipients
+            .iter()
+            .find(|rcpt| rcpt.address == address)
+            .unwrap();
+        assert_eq!(rcpt.outcome, outcome, "{rcpt:?}");
+        assert!(rcpt.details.is_some(), "{rcpt:?}");
+    }
+    assert_eq!(log.attempts.len(), 3);
+
+    // Records are removed after the retention period
+    core.purge_delivery_logs(Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert!(core.read_delivery_log(1, queue_id).await.unwrap().is_some());
+    core.purge_delivery_logs(Duration::ZERO).await.unwrap();
+    assert_eq!(core.read_delivery_log(1, queue_id).await.unwrap(), None);
+}
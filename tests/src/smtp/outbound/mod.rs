@@ -5,11 +5,13 @@
  */
 
 pub mod dane;
+pub mod delivery_log;
 pub mod extensions;
 pub mod fallback_relay;
 pub mod ip_lookup;
 pub mod lmtp;
 pub mod mta_sts;
 pub mod smtp;
+pub mod tenant_relay;
 pub mod throttle;
 pub mod tls;
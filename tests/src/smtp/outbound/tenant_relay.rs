@@ -0,0 +1,114 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{
+    auth::{AccessToken, TenantInfo},
+    config::server::ServerProtocol,
+};
+use mail_auth::MX;
+use smtp::queue::FROM_AUTHENTICATED;
+
+use crate::smtp::{DnsCache, TestSMTP, session::TestSession};
+
+const LOCAL: &str = r#"
+[session.rcpt]
+relay = true
+max-recipients = 100
+
+[session.auth]
+must-match-sender = false
+
+[queue.tenant.1.relay]
+host = "relay.foobar.net"
+port = 9925
+tls = "starttls"
+allow-invalid-certs = true
+
+[queue.tenant.1.relay.auth]
+username = "relay-user"
+secret = "relay-secret"
+"#;
+
+const REMOTE: &str = r#"
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "relay-user"
+description = "Relay account"
+secret = "relay-secret"
+email = "relay-user@foobar.net"
+
+[session.rcpt]
+relay = true
+
+[session.ehlo]
+reject-non-fqdn = false
+
+[session.auth]
+require = true
+mechanisms = "[plain, login]"
+directory = "'local'"
+must-match-sender = false
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn tenant_relay() {
+    // Enable logging
+    crate::enable_logging();
+
+    // Start test server
+    let mut remote = TestSMTP::new("smtp_tenant_relay_remote", REMOTE).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let mut local = TestSMTP::new("smtp_tenant_relay_local", LOCAL).await;
+
+    // MX delivery to the destination is not possible
+    let core = local.build_smtp();
+    core.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["_dns_error.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.ipv4_add(
+        "relay.foobar.net",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Messages submitted by tenant users are delivered through the tenant's relay
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".into();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session.data.authenticated_as = Some(Arc::new(AccessToken {
+        name: "john@test.org".to_string(),
+        tenant: Some(TenantInfo { id: 1, quota: 0 }),
+        ..Default::default()
+    }));
+    session
+        .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    local
+        .queue_receiver
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone());
+
+    // The relay only accepts authenticated sessions
+    let message = remote.queue_receiver.expect_message().await;
+    assert_ne!(message.message.flags & FROM_AUTHENTICATED, 0);
+    assert_eq!(message.message.recipients[0].address(), "bill@foobar.org");
+    local.queue_receiver.assert_no_events();
+}
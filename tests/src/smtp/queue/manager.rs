@@ -10,13 +10,17 @@ use crate::smtp::{
 };
 use common::config::smtp::queue::QueueName;
 use smtp::queue::{
-    Error, ErrorDetails, Message, MessageWrapper, Recipient, Status, spool::SmtpSpool,
+    Error, ErrorDetails, Message, MessageSource, MessageWrapper, Recipient, Status,
+    spool::SmtpSpool,
 };
 use std::{
     net::{IpAddr, Ipv4Addr},
     time::Duration,
 };
-use store::write::now;
+use store::{
+    ValueKey,
+    write::{QueueClass, ValueClass, now},
+};
 
 const CONFIG: &str = r#"
 [session.ehlo]
@@ -72,6 +76,81 @@ async fn queue_due() {
     qr.assert_queue_is_empty().await;
 }
 
+#[tokio::test]
+async fn queue_tenant() {
+    // Enable logging
+    crate::enable_logging();
+
+    let local = TestSMTP::new("smtp_queue_tenant_test", CONFIG).await;
+    let core = local.build_smtp();
+    let qr = &local.queue_receiver;
+
+    // Messages submitted by tenant users are stamped with the tenant id
+    let mut message = new_message(0);
+    message.message.recipients.push(build_rcpt("a", 1, 2, 3));
+    assert!(
+        message
+            .queue(
+                None,
+                b"Subject: test\r\n\r\ntest",
+                0,
+                &core,
+                MessageSource::Authenticated {
+                    tenant_id: 7.into()
+                },
+            )
+            .await
+    );
+    let store = core.store();
+    assert_eq!(
+        store
+            .get_value::<u32>(ValueKey::from(ValueClass::Queue(
+                QueueClass::MessageTenant(0)
+            )))
+            .await
+            .unwrap(),
+        Some(7)
+    );
+    assert!(
+        store
+            .get_value::<()>(ValueKey::from(ValueClass::Queue(QueueClass::TenantIndex {
+                tenant_id: 7,
+                queue_id: 0,
+            })))
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    // Removing the message also removes the stamp
+    core.read_message(0, QueueName::default())
+        .await
+        .unwrap()
+        .remove(&core, None)
+        .await;
+    assert_eq!(
+        store
+            .get_value::<u32>(ValueKey::from(ValueClass::Queue(
+                QueueClass::MessageTenant(0)
+            )))
+            .await
+            .unwrap(),
+        None
+    );
+    assert!(
+        store
+            .get_value::<()>(ValueKey::from(ValueClass::Queue(QueueClass::TenantIndex {
+                tenant_id: 7,
+                queue_id: 0,
+            })))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    qr.assert_queue_is_empty().await;
+}
+
 #[test]
 fn delivery_events() {
     let mut message = new_message(0).message;
@@ -133,7 +133,10 @@ pub async fn blob_tests() {
         );
 
         // Purge expired blobs
-        store.purge_blobs(blob_store.clone()).await.unwrap();
+        store
+            .purge_blobs(blob_store.clone(), &Default::default())
+            .await
+            .unwrap();
 
         // Blob hash should no longer exist
         assert!(!store.blob_exists(&hash).await.unwrap());
@@ -247,7 +250,10 @@ pub async fn blob_tests() {
         );
 
         // Purge expired blobs and make sure nothing else is deleted
-        store.purge_blobs(blob_store.clone()).await.unwrap();
+        store
+            .purge_blobs(blob_store.clone(), &Default::default())
+            .await
+            .unwrap();
         for (pos, (blob, blob_class)) in [
             (
                 b"abc",
@@ -344,7 +350,10 @@ pub async fn blob_tests() {
             .unwrap();
 
         // Purge and make sure blob is deleted
-        store.purge_blobs(blob_store.clone()).await.unwrap();
+        store
+            .purge_blobs(blob_store.clone(), &Default::default())
+            .await
+            .unwrap();
         for (pos, (blob, blob_class)) in [
             (
                 b"789",
@@ -404,7 +413,10 @@ pub async fn blob_tests() {
 
         // Unlink all blobs from accountId 1 and purge
         destroy_account_blobs(&server, 1).await.unwrap();
-        store.purge_blobs(blob_store.clone()).await.unwrap();
+        store
+            .purge_blobs(blob_store.clone(), &Default::default())
+            .await
+            .unwrap();
 
         // Make sure only accountId 0's blobs are left
         for (pos, (blob, blob_class)) in [
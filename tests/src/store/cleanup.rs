@@ -242,7 +242,10 @@ pub async fn store_lookup_expire_all(store: &Store) {
 pub async fn store_assert_is_empty(store: &Store, blob_store: BlobStore, include_directory: bool) {
     store_blob_expire_all(store).await;
     store_lookup_expire_all(store).await;
-    store.purge_blobs(blob_store).await.unwrap();
+    store
+        .purge_blobs(blob_store, &Default::default())
+        .await
+        .unwrap();
     store.purge_store().await.unwrap();
 
     let store = store.clone();
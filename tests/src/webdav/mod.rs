@@ -153,7 +153,7 @@ async fn init_webdav_tests(assisted_discovery: bool, delete_if_exists: bool) ->
     // Parse core
     let tracers = Telemetry::parse(&mut config, &stores);
     let core = Core::parse(&mut config, stores, Default::default()).await;
-    let data = Data::parse(&mut config);
+    let data = Data::parse(&mut config, &core);
     let cache = Caches::parse(&mut config);
 
     let store = core.storage.data.clone();